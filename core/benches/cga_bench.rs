@@ -33,14 +33,14 @@
 use marty_core::{devices::cga::CGACard, tracelogger::TraceLogger};
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use marty_core::videocard::ClockingMode;
+use marty_core::device_traits::videocard::{ClockingMode, VideoCardSubType};
 
 pub fn cga_tick_bench(c: &mut Criterion) {
     // One-time setup code goes here
 
     c.bench_function("cga_bench_tick", |b| {
         // Per-sample (note that a sample can be many iterations) setup goes here
-        let mut cga = CGACard::new(TraceLogger::None, ClockingMode::Dynamic, false);
+        let mut cga = CGACard::new(VideoCardSubType::None, TraceLogger::None, ClockingMode::Dynamic, false);
 
         b.iter(|| {
             // Measured code goes here
@@ -51,7 +51,7 @@ pub fn cga_tick_bench(c: &mut Criterion) {
     c.bench_function("cga_bench_tick_char", |b| {
         // Per-sample (note that a sample can be many iterations) setup goes here
 
-        let mut cga = CGACard::new(TraceLogger::None, ClockingMode::Dynamic, false);
+        let mut cga = CGACard::new(VideoCardSubType::None, TraceLogger::None, ClockingMode::Dynamic, false);
 
         b.iter(|| {
             // Measured code goes here
@@ -62,7 +62,7 @@ pub fn cga_tick_bench(c: &mut Criterion) {
     c.bench_function("cga_bench_frame_by_pixel_ticks", |b| {
         // Per-sample (note that a sample can be many iterations) setup goes here
 
-        let mut cga = CGACard::new(TraceLogger::None, ClockingMode::Dynamic, false);
+        let mut cga = CGACard::new(VideoCardSubType::None, TraceLogger::None, ClockingMode::Dynamic, false);
 
         b.iter(|| {
             // Measured code goes here
@@ -75,7 +75,7 @@ pub fn cga_tick_bench(c: &mut Criterion) {
     c.bench_function("cga_bench_frame_by_char_ticks", |b| {
         // Per-sample (note that a sample can be many iterations) setup goes here
 
-        let mut cga = CGACard::new(TraceLogger::None, ClockingMode::Dynamic, false);
+        let mut cga = CGACard::new(VideoCardSubType::None, TraceLogger::None, ClockingMode::Dynamic, false);
 
         b.iter(|| {
             // Measured code goes here
@@ -88,7 +88,7 @@ pub fn cga_tick_bench(c: &mut Criterion) {
     c.bench_function("cga_bench_draw_textmode_char", |b| {
         // Per-sample (note that a sample can be many iterations) setup goes here
 
-        let mut cga = CGACard::new(TraceLogger::None, ClockingMode::Dynamic, false);
+        let mut cga = CGACard::new(VideoCardSubType::None, TraceLogger::None, ClockingMode::Dynamic, false);
 
         b.iter(|| {
             // Measured code goes here
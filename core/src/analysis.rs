@@ -0,0 +1,317 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    analysis.rs
+
+    Implements a collection pass for static analysis export. While enabled,
+    executed instructions, direct data references, call targets and interrupt
+    handler entry points are collected. The result can be written out as a
+    Ghidra Python script or an IDA IDC script that marks up a disassembly
+    database with everything observed during emulation.
+
+*/
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Error;
+
+use crate::{
+    control_flow::{ControlFlowEdgeKind, ControlFlowNode, ControlFlowRecorder},
+    cpu_common::{calc_linear_address, operands::OperandSize, AddressingMode, Displacement, Instruction, OperandType},
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum AnalysisFormat {
+    #[default]
+    Ghidra,
+    Idc,
+}
+
+impl AnalysisFormat {
+    /// Select an export format from a filename's extension. Unrecognized extensions use the Ghidra script format.
+    pub fn from_path(path: &Path) -> AnalysisFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("idc") => AnalysisFormat::Idc,
+            _ => AnalysisFormat::Ghidra,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CodeEntry {
+    pub cs:   u16,
+    pub ip:   u16,
+    pub size: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DataEntry {
+    pub seg:  u16,
+    pub ofs:  u16,
+    pub size: u8,
+}
+
+/// Collects code and data boundaries, call targets, interrupt handlers and symbols observed during execution.
+#[derive(Default)]
+pub struct AnalysisCollector {
+    code: BTreeMap<u32, CodeEntry>,
+    data: BTreeMap<u32, DataEntry>,
+    symbols: BTreeMap<u32, String>,
+    control_flow: ControlFlowRecorder,
+}
+
+impl AnalysisCollector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.code.clear();
+        self.data.clear();
+        self.symbols.clear();
+        self.control_flow.clear();
+    }
+
+    pub fn code_ct(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn data_ct(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Record an executed instruction. `data_seg` should be the value of the segment register that the
+    /// instruction's memory operand (if any) is relative to at the time of execution.
+    pub fn record_instruction(&mut self, cs: u16, ip: u16, i: &Instruction, data_seg: u16) {
+        let flat = calc_linear_address(cs, ip);
+        self.code.entry(flat).or_insert(CodeEntry {
+            cs,
+            ip,
+            size: i.size as u8,
+        });
+
+        for (operand, size) in [(i.operand1_type, i.operand1_size), (i.operand2_type, i.operand2_size)] {
+            let ofs = match operand {
+                OperandType::Offset8(ofs) | OperandType::Offset16(ofs) => ofs,
+                OperandType::AddressingMode(AddressingMode::Disp16(Displacement::Disp16(disp))) => disp as u16,
+                _ => continue,
+            };
+            let size = match size {
+                OperandSize::Operand16 => 2,
+                _ => 1,
+            };
+            let data_flat = calc_linear_address(data_seg, ofs);
+            self.data.entry(data_flat).or_insert(DataEntry {
+                seg: data_seg,
+                ofs,
+                size,
+            });
+        }
+    }
+
+    /// Record a control flow edge. Call and interrupt targets become function entry points.
+    pub fn record_edge(&mut self, from: ControlFlowNode, to: ControlFlowNode, kind: ControlFlowEdgeKind) {
+        self.control_flow.record(from, to, kind);
+    }
+
+    pub fn record_interrupt(&mut self, from: ControlFlowNode, to: ControlFlowNode, vector: Option<u8>, hw: bool) {
+        self.control_flow.record_interrupt(from, to, vector, hw);
+    }
+
+    /// Attach a named symbol to a flat address, such as a ROM checkpoint.
+    pub fn add_symbol(&mut self, flat: u32, name: &str) {
+        self.symbols.insert(flat & 0xFFFFF, name.to_string());
+    }
+
+    /// Return the generated name for a function entry point.
+    fn function_name(&self, node: &ControlFlowNode) -> String {
+        match self.control_flow.interrupt_targets().get(node) {
+            Some(vectors) if !vectors.is_empty() => {
+                let vecs = vectors.iter().map(|v| format!("{:02X}", v)).collect::<Vec<_>>().join("_");
+                format!("int_{}h_{:04X}_{:04X}", vecs, node.cs, node.ip)
+            }
+            Some(_) => format!("isr_{:04X}_{:04X}", node.cs, node.ip),
+            None => format!("sub_{:04X}_{:04X}", node.cs, node.ip),
+        }
+    }
+
+    /// Return a list of all function entry points (call and interrupt targets) with generated names.
+    fn functions(&self) -> Vec<(ControlFlowNode, String)> {
+        let mut entries: BTreeMap<ControlFlowNode, String> = BTreeMap::new();
+        for node in self.control_flow.call_targets() {
+            entries.insert(*node, self.function_name(node));
+        }
+        for node in self.control_flow.interrupt_targets().keys() {
+            entries.insert(*node, self.function_name(node));
+        }
+        entries.into_iter().collect()
+    }
+
+    pub fn export(&self, path: &Path, format: AnalysisFormat) -> Result<(), Error> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        match format {
+            AnalysisFormat::Ghidra => self.write_ghidra(&mut writer)?,
+            AnalysisFormat::Idc => self.write_idc(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write a Ghidra Python script. Addresses are emitted in segmented form, which Ghidra's x86 Real Mode
+    /// language resolves directly.
+    pub fn write_ghidra<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        writeln!(w, "# Analysis export generated by MartyPC")?;
+        writeln!(w, "# Run from the Ghidra Script Manager on a program using the x86:LE:16:Real Mode language.")?;
+        writeln!(w, "#@category MartyPC")?;
+        writeln!(w)?;
+        writeln!(w, "from ghidra.program.model.symbol import SourceType")?;
+        writeln!(w)?;
+        writeln!(w, "def a(s):")?;
+        writeln!(w, "    return toAddr(s)")?;
+        writeln!(w)?;
+        writeln!(w, "def code(s):")?;
+        writeln!(w, "    if getInstructionAt(a(s)) is None:")?;
+        writeln!(w, "        disassemble(a(s))")?;
+        writeln!(w)?;
+        writeln!(w, "def func(s, name):")?;
+        writeln!(w, "    code(s)")?;
+        writeln!(w, "    f = getFunctionAt(a(s))")?;
+        writeln!(w, "    if f is None:")?;
+        writeln!(w, "        f = createFunction(a(s), name)")?;
+        writeln!(w, "    if f is not None:")?;
+        writeln!(w, "        f.setName(name, SourceType.USER_DEFINED)")?;
+        writeln!(w)?;
+        writeln!(w, "def data(s, size):")?;
+        writeln!(w, "    try:")?;
+        writeln!(w, "        if size == 2:")?;
+        writeln!(w, "            createWord(a(s))")?;
+        writeln!(w, "        else:")?;
+        writeln!(w, "            createByte(a(s))")?;
+        writeln!(w, "    except:")?;
+        writeln!(w, "        pass")?;
+        writeln!(w)?;
+        writeln!(w, "def label(s, name):")?;
+        writeln!(w, "    createLabel(a(s), name, True, SourceType.USER_DEFINED)")?;
+        writeln!(w)?;
+
+        writeln!(w, "# Code ({} instructions)", self.code.len())?;
+        for entry in self.code.values() {
+            writeln!(w, "code(\"{:04X}:{:04X}\")", entry.cs, entry.ip)?;
+        }
+        writeln!(w)?;
+
+        let functions = self.functions();
+        writeln!(w, "# Functions and interrupt handlers ({})", functions.len())?;
+        for (node, name) in functions.iter() {
+            writeln!(w, "func(\"{:04X}:{:04X}\", \"{}\")", node.cs, node.ip, name)?;
+        }
+        writeln!(w)?;
+
+        writeln!(w, "# Data references ({})", self.data.len())?;
+        for entry in self.data.values() {
+            let prefix = if entry.size == 2 { "word" } else { "byte" };
+            writeln!(w, "data(\"{:04X}:{:04X}\", {})", entry.seg, entry.ofs, entry.size)?;
+            writeln!(
+                w,
+                "label(\"{:04X}:{:04X}\", \"{}_{:05X}\")",
+                entry.seg,
+                entry.ofs,
+                prefix,
+                calc_linear_address(entry.seg, entry.ofs)
+            )?;
+        }
+        writeln!(w)?;
+
+        writeln!(w, "# Symbols ({})", self.symbols.len())?;
+        for (flat, name) in self.symbols.iter() {
+            writeln!(
+                w,
+                "setEOLComment(a(\"{:04X}:{:04X}\"), \"{}\")",
+                (flat & 0xF0000) >> 4,
+                flat & 0xFFFF,
+                escape(name)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write an IDA IDC script. IDA databases for real mode binaries are addressed linearly, so flat
+    /// addresses are emitted.
+    pub fn write_idc<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        writeln!(w, "// Analysis export generated by MartyPC")?;
+        writeln!(w, "#include <idc.idc>")?;
+        writeln!(w)?;
+        writeln!(w, "static main() {{")?;
+
+        writeln!(w, "    // Code ({} instructions)", self.code.len())?;
+        for flat in self.code.keys() {
+            writeln!(w, "    create_insn(0x{:05X});", flat)?;
+        }
+        writeln!(w)?;
+
+        let functions = self.functions();
+        writeln!(w, "    // Functions and interrupt handlers ({})", functions.len())?;
+        for (node, name) in functions.iter() {
+            writeln!(w, "    add_func(0x{:05X}, BADADDR);", node.flat())?;
+            writeln!(w, "    set_name(0x{:05X}, \"{}\", SN_NOWARN);", node.flat(), name)?;
+        }
+        writeln!(w)?;
+
+        writeln!(w, "    // Data references ({})", self.data.len())?;
+        for (flat, entry) in self.data.iter() {
+            match entry.size {
+                2 => {
+                    writeln!(w, "    create_word(0x{:05X});", flat)?;
+                    writeln!(w, "    set_name(0x{:05X}, \"word_{:05X}\", SN_NOWARN);", flat, flat)?;
+                }
+                _ => {
+                    writeln!(w, "    create_byte(0x{:05X});", flat)?;
+                    writeln!(w, "    set_name(0x{:05X}, \"byte_{:05X}\", SN_NOWARN);", flat, flat)?;
+                }
+            }
+        }
+        writeln!(w)?;
+
+        writeln!(w, "    // Symbols ({})", self.symbols.len())?;
+        for (flat, name) in self.symbols.iter() {
+            writeln!(w, "    set_cmt(0x{:05X}, \"{}\", 0);", flat, escape(name))?;
+        }
+
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+/// Escape a string for inclusion in a double-quoted script string literal.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -30,18 +30,21 @@
 
 */
 
+use crate::device_traits::videocard::VideoBreakpoint;
+
 #[allow(dead_code)]
 pub enum BreakPointType {
-    StepOver(u32),       // Breakpoint on next decoded instruction
-    Execute(u16, u16),   // Breakpoint on CS:IP
-    ExecuteOffset(u16),  // Breakpoint on *::IP
-    ExecuteFlat(u32),    // Breakpoint on CS<<4+IP
-    MemAccess(u16, u16), // Breakpoint on memory access, seg::offset
-    MemAccessFlat(u32),  // Breakpoint on memory access, seg<<4+offset
-    Interrupt(u8),       // Breakpoint on interrupt #
-    IoAccess(u16),       // Breakpoint on I/O port access
-    StartWatch(u32),     // Start stopwatch at address
-    StopWatch(u32),      // Stop stopwatch at address
+    StepOver(u32),                  // Breakpoint on next decoded instruction
+    Execute(u16, u16),              // Breakpoint on CS:IP
+    ExecuteOffset(u16),             // Breakpoint on *::IP
+    ExecuteFlat(u32),               // Breakpoint on CS<<4+IP
+    MemAccess(u16, u16),            // Breakpoint on memory access, seg::offset
+    MemAccessFlat(u32),             // Breakpoint on memory access, seg<<4+offset
+    Interrupt(u8),                  // Breakpoint on interrupt #
+    IoAccess(u16),                  // Breakpoint on I/O port access
+    StartWatch(u32),                // Start stopwatch at address
+    StopWatch(u32),                 // Stop stopwatch at address
+    VideoRegister(VideoBreakpoint), // Breakpoint on video mode or CRTC register write
 }
 
 pub enum StopWatchType {
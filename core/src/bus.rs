@@ -36,13 +36,13 @@
 
 #![allow(dead_code)]
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 
 use fxhash::FxHashMap;
 use std::{collections::VecDeque, fmt, io::Write, path::Path};
 
 #[cfg(feature = "sound")]
-use crate::device_traits::sounddevice::SoundDevice;
+use crate::device_traits::sounddevice::{SoundDevice, SoundDispatch};
 #[cfg(feature = "sound")]
 use crate::devices::pit::SPEAKER_SAMPLE_RATE;
 #[cfg(feature = "sound")]
@@ -59,33 +59,58 @@ use crate::{
         VideoType,
     },
     devices::{
+        cassette::Cassette,
         cga::CGACard,
         dma::*,
         fdc::FloppyController,
         hdc::*,
+        inport_mouse::InPortMouse,
+        kbc::*,
         keyboard::{KeyboardType, *},
         mda::MDACard,
+        modem::HayesModem,
         mouse::*,
+        mpu401::{Mpu401, MPU_DEFAULT_IRQ},
         pic::*,
         pit::Pit,
         ppi::*,
+        ps2_mouse::Ps2Mouse,
         serial::*,
     },
     machine::{KeybufferEntry, MachineCheckpoint, MachinePatch},
-    machine_config::{normalize_conventional_memory, MachineConfiguration, MachineDescriptor},
-    machine_types::{HardDiskControllerType, SerialControllerType, SerialMouseType},
+    machine_config::{
+        normalize_conventional_memory,
+        DmaType,
+        KbControllerType,
+        MachineConfiguration,
+        MachineDescriptor,
+        MemoryRegionConfig,
+        PicType,
+    },
+    machine_types::{BusMouseType, HardDiskControllerType, MemoryRegionType, SerialControllerType},
     memerror::MemError,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
     tracelogger::TraceLogger,
 };
 
 #[cfg(feature = "opl")]
 use crate::devices::adlib::AdLibCard;
+#[cfg(feature = "sound")]
+use crate::devices::atapi_cdrom::{CDROM_AUDIO_CHANNELS, CDROM_AUDIO_SAMPLE_RATE};
 #[cfg(feature = "ega")]
 use crate::devices::ega::EGACard;
 #[cfg(feature = "vga")]
 use crate::devices::vga::VGACard;
 #[cfg(feature = "sound")]
+use crate::devices::{
+    sn76489::{Sn76489, SN_DEFAULT_IO_BASE},
+    sound_blaster::{SoundBlaster, SB_DEFAULT_DMA, SB_DEFAULT_IRQ},
+    speaker_filter::SpeakerFilter,
+};
+#[cfg(feature = "serial")]
+use crate::machine_types::SerialBridgeType;
+#[cfg(feature = "sound")]
 use crate::machine_types::SoundType;
 #[cfg(feature = "sound")]
 use crate::sound::{SoundOutputConfig, SoundSourceDescriptor};
@@ -94,14 +119,27 @@ use crate::{
     cpu_common::CpuType,
     device_traits::videocard::VideoCardSubType,
     devices::{
-        a0::A0Register,
+        a0::{A0Register, A0Type},
         cartridge_slots::CartridgeSlot,
+        eems::EemsBoard,
         game_port::GamePort,
         lotech_ems::LotechEmsCard,
-        lpt_card::ParallelController,
-        tga::TGACard,
+        lpt_card::{ParallelController, LPT_DEFAULT_IO_BASE},
+        lpt_dac::{ParallelDac, ParallelDacModel},
+        net::{
+            ne2000::{Ne2000, NicModel},
+            parse_mac,
+            slirp::SlirpBackend,
+            NetworkBackend,
+        },
+        post_card::PostCard,
+        printer::Printer,
+        rtc::Rtc,
+        seagate_st01::SeagateSt01,
+        tga::{TGACard, TGA_JR_SHARED_MEM_SIZE},
+        xtide::XtIdeController,
     },
-    machine_types::{EmsType, FdcType, MachineType},
+    machine_types::{EmsType, FdcType, MachineType, NetworkBackendType, NetworkCardType, ParallelDeviceType, RtcType},
     syntax_token::SyntaxFormatType,
 };
 
@@ -110,6 +148,8 @@ pub const OPEN_BUS_BYTE: u8 = 0xFF; // This is the byte read from an unmapped me
 
 const ADDRESS_SPACE: usize = 0x10_0000;
 const DEFAULT_WAIT_STATES: u32 = 0;
+const WAIT_MAP_SHIFT: usize = 4; // Wait states are tracked per paragraph.
+const WAIT_MAP_LEN: usize = ADDRESS_SPACE >> WAIT_MAP_SHIFT;
 
 const MMIO_MAP_SIZE: usize = 0x2000;
 const MMIO_MAP_SHIFT: usize = 13;
@@ -122,6 +162,7 @@ pub const MEM_BPA_BIT: u8 = 0b0001_0000; // Bit to signify that this address is
 pub const MEM_CP_BIT: u8 = 0b0000_1000; // Bit to signify that this address is a ROM checkpoint
 pub const MEM_MMIO_BIT: u8 = 0b0000_0100; // Bit to signify that this address is MMIO mapped
 pub const MEM_SW_BIT: u8 = 0b0000_0010; // Bit to signify that this address is in a stopwatch
+pub const MEM_RAM_BIT: u8 = 0b0000_0001; // Bit to signify that this address is RAM outside of conventional memory
 
 pub const KB_UPDATE_RATE: f64 = 5000.0; // Keyboard device update rate in microseconds
 
@@ -236,6 +277,8 @@ pub enum DeviceEvent {
     DramRefreshUpdate(u16, u16, u32, bool),
     DramRefreshEnable(bool),
     TurboToggled(bool),
+    CpuReset,
+    Notification(String),
 }
 
 pub trait MemoryMappedDevice {
@@ -294,6 +337,7 @@ impl MemRangeDescriptor {
 pub enum IoDeviceType {
     A0Register,
     Ppi,
+    KeyboardController,
     Pit,
     DmaPrimary,
     DmaSecondary,
@@ -303,11 +347,17 @@ pub enum IoDeviceType {
     Parallel,
     FloppyController,
     HardDiskController,
+    XtIde,
     Mouse,
     Ems,
+    Eems,
     GamePort,
+    Rtc,
+    PostCard,
+    Network,
     Video(VideoCardId),
-    Sound,
+    Sound(usize),
+    Mpu401,
 }
 
 pub enum IoDeviceDispatch {
@@ -379,7 +429,9 @@ pub enum MmioDeviceType {
     Vga,
     Rom,
     Ems,
+    Eems,
     Cart,
+    Scsi,
 }
 
 // Main bus struct.
@@ -396,9 +448,12 @@ pub struct BusInterface {
     machine_desc: Option<MachineDescriptor>,
     keyboard_type: KeyboardType,
     keyboard: Option<Keyboard>,
+    kbc: Option<KeyboardController>,
     conventional_size: usize,
     memory: Vec<u8>,
     memory_mask: Vec<u8>,
+    memory_regions: Vec<MemoryRegionConfig>,
+    wait_map: Vec<u8>,
     open_bus_byte: u8,
     desc_vec: Vec<MemRangeDescriptor>,
     mmio_map: Vec<(MemRangeDescriptor, MmioDeviceType)>,
@@ -425,15 +480,28 @@ pub struct BusInterface {
     parallel: Option<ParallelController>,
     fdc: Option<FloppyController>,
     hdc: Option<HardDiskController>,
+    xtide: Option<XtIdeController>,
+    st01: Option<SeagateSt01>,
     mouse: Option<Mouse>,
+    modem: Option<HayesModem>,
+    cassette: Option<Cassette>,
+    bus_mouse: Option<InPortMouse>,
+    ps2_mouse: Option<Ps2Mouse>,
     ems: Option<LotechEmsCard>,
+    eems: Option<EemsBoard>,
     cart_slot: Option<CartridgeSlot>,
     game_port: Option<GamePort>,
-    #[cfg(feature = "opl")]
-    adlib: Option<AdLibCard>,
+    rtc: Option<Rtc>,
+    post_card: Option<PostCard>,
+    nic: Option<Ne2000>,
+    #[cfg(feature = "sound")]
+    sound_devices: Vec<SoundDispatch>,
+    mpu401: Option<Mpu401>,
 
     videocards:    FxHashMap<VideoCardId, VideoCardDispatch>,
     videocard_ids: Vec<VideoCardId>,
+    shared_vram:   Option<VideoCardId>, // Video card that shares conventional memory with the CPU (PCjr)
+    primary_card:  Option<VideoCardId>,
 
     cycles_to_ticks:   [u32; 256], // TODO: Benchmarks don't show any faster than raw multiplication. It's not slower either though.
     pit_ticks_advance: u32, // We can schedule extra PIT ticks to add when run() occurs. This is generally used for PIT phase offset adjustment.
@@ -448,6 +516,8 @@ pub struct BusInterface {
     refresh_active: bool,
 
     terminal_port: Option<u16>,
+
+    notifications: Vec<String>,
 }
 
 #[macro_export]
@@ -575,9 +645,12 @@ impl Default for BusInterface {
             machine_desc: None,
             keyboard_type: KeyboardType::ModelF,
             keyboard: None,
+            kbc: None,
             conventional_size: ADDRESS_SPACE,
             memory: vec![0; ADDRESS_SPACE],
             memory_mask: vec![0; ADDRESS_SPACE],
+            memory_regions: Vec::new(),
+            wait_map: vec![DEFAULT_WAIT_STATES as u8; WAIT_MAP_LEN],
             open_bus_byte: 0xFF,
             desc_vec: Vec::new(),
             mmio_map: Vec::new(),
@@ -604,14 +677,27 @@ impl Default for BusInterface {
             parallel: None,
             fdc: None,
             hdc: None,
+            xtide: None,
+            st01: None,
             mouse: None,
+            modem: None,
+            cassette: None,
+            bus_mouse: None,
+            ps2_mouse: None,
             ems: None,
+            eems: None,
             cart_slot: None,
             game_port: None,
+            rtc: None,
+            post_card: None,
+            nic: None,
             #[cfg(feature = "sound")]
-            adlib: None,
+            sound_devices: Vec::new(),
+            mpu401: None,
             videocards: FxHashMap::default(),
             videocard_ids: Vec::new(),
+            shared_vram: None,
+            primary_card: None,
 
             cycles_to_ticks:   [0; 256],
             pit_ticks_advance: 0,
@@ -626,6 +712,8 @@ impl Default for BusInterface {
             refresh_active: false,
 
             terminal_port: None,
+
+            notifications: Vec::new(),
         }
     }
 }
@@ -648,6 +736,17 @@ impl BusInterface {
         self.do_title_hacks = do_timing_hacks;
     }
 
+    /// Post a short message for the frontend to show the user, such as in an on-screen display.
+    /// Notifications are delivered as DeviceEvent::Notification from Machine::frame_update().
+    pub fn post_notification(&mut self, message: impl Into<String>) {
+        self.notifications.push(message.into());
+    }
+
+    /// Take all notifications posted since the last call.
+    pub fn take_notifications(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notifications)
+    }
+
     /// Update the bus timing table.
     /// The bus keeps a timing table which is a lookup table of system ticks and microseconds for each possible CPU
     /// instruction cycle count from 0 to TIMING_TABLE_LEN. This table needs to be updated whenever the clock divisor
@@ -683,6 +782,14 @@ impl BusInterface {
         context.delta_us = self.timing_table[cycles as usize].us;
     }
 
+    /// Return the elapsed time for the specified number of CPU cycles, for devices that catch up to
+    /// the CPU on IO access.
+    #[inline]
+    pub fn cycles_to_us_delta(&self, cycles: u32) -> DeviceRunTimeUnit {
+        let cycles = (cycles as usize).min(TIMING_TABLE_LEN - 1);
+        DeviceRunTimeUnit::Microseconds(self.timing_table[cycles].us)
+    }
+
     /// Set the checkpoint bit in memory flags for all checkpoints provided.
     pub fn install_checkpoints(&mut self, checkpoints: &Vec<MachineCheckpoint>) {
         for checkpoint in checkpoints.iter() {
@@ -731,6 +838,148 @@ impl BusInterface {
         self.conventional_size
     }
 
+    fn install_a0_register(&mut self, a0_type: A0Type) {
+        let a0 = A0Register::new(a0_type);
+
+        log::debug!("Creating A0 register...");
+        add_io_device!(self, a0, IoDeviceType::A0Register);
+        self.a0 = Some(a0);
+    }
+
+    /// Create the primary PIC, and the secondary PIC if `pic_type` is Chained. The secondary PIC decodes
+    /// port A0h, which the A0 register occupies on the PC, XT, PCjr and Tandy. On the PC and XT the A0
+    /// register is removed in favor of the secondary PIC, as on the AT. The PCjr and Tandy's A0 register
+    /// also controls their keyboard NMI, so a secondary PIC can't be installed on those machines.
+    fn install_pics(&mut self, mut pic_type: PicType) {
+        // Create PIC. One PIC will always exist.
+        let pic1 = Pic::new();
+        // Add PIC ports to io_map
+        add_io_device!(self, pic1, IoDeviceType::PicPrimary);
+        self.pic1 = Some(pic1);
+
+        if let (PicType::Chained, Some(a0)) = (pic_type, &self.a0) {
+            match a0.a0_type() {
+                A0Type::PCXT => {
+                    log::warn!("Secondary PIC replaces the NMI mask register at port A0h. NMI can't be masked.");
+                    for (_, port) in a0.port_list() {
+                        self.io_map.remove(&port);
+                        self.io_desc_map.remove(&port);
+                    }
+                    self.a0 = None;
+                }
+                a0_type => {
+                    log::error!(
+                        "Can't install a secondary PIC: port A0h is used by the {:?} A0 register. Using a single PIC.",
+                        a0_type
+                    );
+                    pic_type = PicType::Single;
+                }
+            }
+        }
+
+        // Create the slave PIC if the machine has chained PICs.
+        if let PicType::Chained = pic_type {
+            log::debug!("Installing secondary PIC cascaded on IRQ{}", PIC_CASCADE_IRQ);
+            let pic2 = Pic::new_secondary();
+            add_io_device!(self, pic2, IoDeviceType::PicSecondary);
+            self.pic2 = Some(pic2);
+        }
+    }
+
+    /// Validate and install the custom memory regions from the machine configuration. RAM regions are made
+    /// writable and wait states are set for each region. Region contents are populated by apply_memory_regions().
+    pub fn install_memory_regions(&mut self, regions: &[MemoryRegionConfig]) -> Result<(), Error> {
+        for region in regions {
+            let start = region.address as usize;
+            let end = start + region.size as usize;
+            if region.size == 0 || end > ADDRESS_SPACE {
+                return Err(anyhow!(
+                    "Invalid memory region: {:?} at {:05X} size {:X}",
+                    region.region_type,
+                    start,
+                    region.size
+                ));
+            }
+
+            match region.region_type {
+                MemoryRegionType::Ram => {
+                    for byte_ref in &mut self.memory_mask[start..end] {
+                        *byte_ref |= MEM_RAM_BIT;
+                    }
+                }
+                MemoryRegionType::RomMirror => match region.source {
+                    Some(source) if source as usize + region.size as usize <= ADDRESS_SPACE => {}
+                    _ => {
+                        return Err(anyhow!(
+                            "ROM mirror at {:05X} requires a valid source address",
+                            region.address
+                        ));
+                    }
+                },
+                MemoryRegionType::Hole => {}
+            }
+
+            if let Some(wait_states) = region.wait_states {
+                for entry in &mut self.wait_map[(start >> WAIT_MAP_SHIFT)..=((end - 1) >> WAIT_MAP_SHIFT)] {
+                    *entry = wait_states.min(u8::MAX as u32) as u8;
+                }
+            }
+
+            log::debug!(
+                "Installed memory region: {:?} at {:05X}-{:05X}",
+                region.region_type,
+                start,
+                end - 1
+            );
+        }
+
+        self.memory_regions = regions.to_vec();
+        Ok(())
+    }
+
+    /// Populate the contents of custom memory regions. This should be called after memory is cleared and
+    /// ROMs are installed, so that ROM mirrors can copy their source.
+    pub fn apply_memory_regions(&mut self) {
+        let regions = std::mem::take(&mut self.memory_regions);
+
+        for region in regions.iter() {
+            let start = region.address as usize;
+            let end = start + region.size as usize;
+
+            match region.region_type {
+                MemoryRegionType::Ram => {
+                    for byte_ref in &mut self.memory[start..end] {
+                        *byte_ref = 0;
+                    }
+                }
+                MemoryRegionType::RomMirror => {
+                    if let Some(source) = region.source {
+                        let source = source as usize;
+                        let src = self.memory[source..source + region.size as usize].to_vec();
+                        _ = self.copy_from(&src, start, 0, true);
+                    }
+                }
+                MemoryRegionType::Hole => {
+                    // Holes are marked as ROM so that writes are ignored.
+                    for byte_ref in &mut self.memory[start..end] {
+                        *byte_ref = self.open_bus_byte;
+                    }
+                    for byte_ref in &mut self.memory_mask[start..end] {
+                        *byte_ref |= MEM_ROM_BIT;
+                    }
+                }
+            }
+        }
+
+        self.memory_regions = regions;
+    }
+
+    /// Return true if the specified address is writable RAM.
+    #[inline]
+    fn is_ram(&self, address: usize) -> bool {
+        address < self.conventional_size || self.memory_mask[address] & MEM_RAM_BIT != 0
+    }
+
     pub fn size(&self) -> usize {
         self.memory.len()
     }
@@ -763,6 +1012,47 @@ impl BusInterface {
         self.mmio_map.push((mem_descriptor, device));
     }
 
+    /// Unregister a memory-mapped device from the range specified within MemRangeDescriptor.
+    ///
+    /// Accesses to the range will return to conventional memory. This allows devices such as EEMS boards
+    /// to page their memory in and out of the address space at runtime.
+    pub fn unregister_map(&mut self, device: MmioDeviceType, mem_descriptor: MemRangeDescriptor) {
+        assert_eq!(mem_descriptor.size % MMIO_MAP_SIZE, 0);
+
+        for i in mem_descriptor.address..(mem_descriptor.address + mem_descriptor.size) {
+            self.memory_mask[i] &= !MEM_MMIO_BIT;
+        }
+
+        let map_segs = mem_descriptor.size / MMIO_MAP_SIZE;
+        for i in 0..map_segs {
+            let seg = (mem_descriptor.address >> MMIO_MAP_SHIFT) + i;
+            if self.mmio_map_fast[seg] == device {
+                self.mmio_map_fast[seg] = MmioDeviceType::Memory;
+            }
+        }
+
+        self.mmio_map.retain(|(desc, map_device)| {
+            !(*map_device == device && desc.address == mem_descriptor.address && desc.size == mem_descriptor.size)
+        });
+    }
+
+    /// Apply any window mapping changes made by the EEMS board since the last call.
+    fn update_eems_mapping(&mut self) {
+        let changes = match &mut self.eems {
+            Some(eems) => eems.take_map_changes(),
+            None => return,
+        };
+
+        for (desc, mapped) in changes {
+            if mapped {
+                self.register_map(MmioDeviceType::Eems, desc);
+            }
+            else {
+                self.unregister_map(MmioDeviceType::Eems, desc);
+            }
+        }
+    }
+
     pub fn copy_from(&mut self, src: &[u8], location: usize, cycle_cost: u32, read_only: bool) -> Result<(), bool> {
         let src_size = src.len();
         if location + src_size > self.memory.len() {
@@ -949,11 +1239,30 @@ impl BusInterface {
         }
     }
 
+    /// Return the wait states for a CPU access to conventional memory shared with a video card,
+    /// or None if the address is not in shared memory.
+    fn get_shared_vram_wait(&mut self, address: usize, cycles: u32) -> Option<u32> {
+        if address >= TGA_JR_SHARED_MEM_SIZE {
+            return None;
+        }
+        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
+        match self.shared_vram.and_then(|vid| self.videocards.get(&vid)) {
+            Some(VideoCardDispatch::Tga(tga)) => {
+                let syswait = tga.get_shared_mem_wait(system_ticks);
+                Some(self.system_ticks_to_cpu_cycles(syswait))
+            }
+            _ => None,
+        }
+    }
+
     pub fn get_read_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES);
+                if let Some(waits) = self.get_shared_vram_wait(address, cycles) {
+                    return Ok(waits);
+                }
+                return Ok(self.wait_map[address >> WAIT_MAP_SHIFT] as u32);
             }
             else {
                 // Handle memory-mapped devices
@@ -1005,7 +1314,10 @@ impl BusInterface {
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES);
+                if let Some(waits) = self.get_shared_vram_wait(address, cycles) {
+                    return Ok(waits);
+                }
+                return Ok(self.wait_map[address >> WAIT_MAP_SHIFT] as u32);
             }
             else {
                 // Handle memory-mapped devices
@@ -1110,6 +1422,12 @@ impl BusInterface {
                             return Ok((data, 0));
                         }
                     }
+                    MmioDeviceType::Eems => {
+                        if let Some(eems) = &mut self.eems {
+                            let (data, _waits) = MemoryMappedDevice::mmio_read_u8(eems, address, system_ticks, None);
+                            return Ok((data, 0));
+                        }
+                    }
                     MmioDeviceType::Cart => {
                         if let Some(cart_slot) = &mut self.cart_slot {
                             let (data, _waits) =
@@ -1117,6 +1435,13 @@ impl BusInterface {
                             return Ok((data, 0));
                         }
                     }
+                    MmioDeviceType::Scsi => {
+                        if let Some(st01) = &mut self.st01 {
+                            let (data, _waits) =
+                                MemoryMappedDevice::mmio_read_u8(st01, address, system_ticks, Some(&self.memory));
+                            return Ok((data, 0));
+                        }
+                    }
                     _ => {}
                 }
                 return Err(MemError::MmioError);
@@ -1126,7 +1451,7 @@ impl BusInterface {
     }
 
     pub fn peek_range(&self, address: usize, len: usize) -> Result<&[u8], MemError> {
-        if address + len < self.memory.len() {
+        if address + len <= self.memory.len() {
             Ok(&self.memory[address..address + len])
         }
         else {
@@ -1179,12 +1504,24 @@ impl BusInterface {
                             return Ok(data);
                         }
                     }
+                    MmioDeviceType::Eems => {
+                        if let Some(eems) = &self.eems {
+                            let data = MemoryMappedDevice::mmio_peek_u8(eems, address, None);
+                            return Ok(data);
+                        }
+                    }
                     MmioDeviceType::Cart => {
                         if let Some(cart_slot) = &self.cart_slot {
                             let data = MemoryMappedDevice::mmio_peek_u8(cart_slot, address, None);
                             return Ok(data);
                         }
                     }
+                    MmioDeviceType::Scsi => {
+                        if let Some(st01) = &self.st01 {
+                            let data = MemoryMappedDevice::mmio_peek_u8(st01, address, Some(&self.memory));
+                            return Ok(data);
+                        }
+                    }
                     _ => {}
                 }
                 return Err(MemError::MmioError);
@@ -1244,6 +1581,19 @@ impl BusInterface {
                             return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                         }
                     }
+                    MmioDeviceType::Eems => {
+                        if let Some(eems) = &mut self.eems {
+                            let (data, syswait) = MemoryMappedDevice::mmio_read_u16(eems, address, 0, None);
+                            return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
+                        }
+                    }
+                    MmioDeviceType::Scsi => {
+                        if let Some(st01) = &mut self.st01 {
+                            let (data, _syswait) =
+                                MemoryMappedDevice::mmio_read_u16(st01, address, 0, Some(&self.memory));
+                            return Ok((data, 0));
+                        }
+                    }
                     _ => {}
                 }
                 return Ok((0xFFFF, 0));
@@ -1256,8 +1606,8 @@ impl BusInterface {
     pub fn write_u8(&mut self, address: usize, data: u8, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() {
             if self.memory_mask[address] & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
-                // Address is not mapped and not ROM, write to it if it is within conventional memory.
-                if address < self.conventional_size {
+                // Address is not mapped and not ROM, write to it if it is RAM.
+                if self.is_ram(address) {
                     self.memory[address] = data;
                 }
                 return Ok(DEFAULT_WAIT_STATES);
@@ -1306,6 +1656,16 @@ impl BusInterface {
                             MemoryMappedDevice::mmio_write_u8(ems, address, data, 0, None);
                         }
                     }
+                    MmioDeviceType::Eems => {
+                        if let Some(eems) = &mut self.eems {
+                            MemoryMappedDevice::mmio_write_u8(eems, address, data, 0, None);
+                        }
+                    }
+                    MmioDeviceType::Scsi => {
+                        if let Some(st01) = &mut self.st01 {
+                            MemoryMappedDevice::mmio_write_u8(st01, address, data, 0, None);
+                        }
+                    }
                     _ => {}
                 }
                 return Ok(DEFAULT_WAIT_STATES);
@@ -1317,13 +1677,12 @@ impl BusInterface {
     pub fn write_u16(&mut self, address: usize, data: u16, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() - 1 {
             if self.memory_mask[address] & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
-                // Address is not mapped. Write to memory if within conventional memory size.
-                if address < self.conventional_size - 1 {
+                // Address is not mapped. Write to memory if it is RAM.
+                if self.is_ram(address) {
                     self.memory[address] = (data & 0xFF) as u8;
-                    self.memory[address + 1] = (data >> 8) as u8;
                 }
-                else if address < self.conventional_size {
-                    self.memory[address] = (data & 0xFF) as u8;
+                if self.is_ram(address + 1) {
+                    self.memory[address + 1] = (data >> 8) as u8;
                 }
                 return Ok(DEFAULT_WAIT_STATES);
             }
@@ -1408,6 +1767,16 @@ impl BusInterface {
                             MemoryMappedDevice::mmio_write_u16(ems, address, data, 0, None);
                         }
                     }
+                    MmioDeviceType::Eems => {
+                        if let Some(eems) = &mut self.eems {
+                            MemoryMappedDevice::mmio_write_u16(eems, address, data, 0, None);
+                        }
+                    }
+                    MmioDeviceType::Scsi => {
+                        if let Some(st01) = &mut self.st01 {
+                            MemoryMappedDevice::mmio_write_u16(st01, address, data, 0, None);
+                        }
+                    }
                     _ => {}
                 }
                 return Ok(0);
@@ -1814,12 +2183,17 @@ impl BusInterface {
         // First we need to initialize the PPI. The PPI is used to read the system's DIP switches, so the PPI must be
         // given several parameters from the machine configuration.
 
-        // Create vector of video types for PPI initialization.
-        let video_types = machine_config
+        // Create vector of video types for PPI initialization. The primary card, if designated, comes first.
+        let mut video_types = machine_config
             .video
             .iter()
             .map(|vcd| vcd.video_type)
             .collect::<Vec<VideoType>>();
+        if let Some(primary_idx) = machine_config.video.iter().position(|vcd| vcd.primary) {
+            let primary_type = video_types.remove(primary_idx);
+            video_types.insert(0, primary_type);
+        }
+        let have_mda = video_types.iter().any(|vt| matches!(vt, VideoType::MDA));
 
         // Get the number of floppies.
         let num_floppies = machine_config
@@ -1833,14 +2207,13 @@ impl BusInterface {
         self.set_conventional_size(conventional_memory as usize);
         self.open_bus_byte = machine_desc.open_bus_byte;
 
+        // Install any custom memory regions (UMB RAM, ROM mirrors and holes).
+        self.install_memory_regions(&machine_config.memory.regions)?;
+
         // Create the A0 register if specified.
         // TODO: Wrap this up in a motherboard device type?
         if let Some(a0_type) = machine_desc.a0 {
-            let a0 = A0Register::new(a0_type);
-
-            log::debug!("Creating A0 register...");
-            add_io_device!(self, a0, IoDeviceType::A0Register);
-            self.a0 = Some(a0);
+            self.install_a0_register(a0_type);
         }
 
         // Set the expansion rom flag for DIP if there is anything besides a video card
@@ -1885,7 +2258,7 @@ impl BusInterface {
         // Pick the device type from MachineDesc.
         // Provide the timer with its base crystal and divisor.
         let mut pit = Pit::new(
-            machine_desc.pit_type,
+            machine_config.pit_type.unwrap_or(machine_desc.pit_type),
             if let Some(crystal) = machine_desc.timer_crystal {
                 crystal
             }
@@ -1896,6 +2269,13 @@ impl BusInterface {
             pit_sample_sender,
         );
 
+        #[cfg(feature = "sound")]
+        if machine_config.speaker {
+            let filter_config = machine_config.speaker_filter.clone().unwrap_or_default();
+            log::debug!("PC speaker filter preset: {:?}", filter_config.preset);
+            pit.set_speaker_filter(SpeakerFilter::new(filter_config.params(), SPEAKER_SAMPLE_RATE));
+        }
+
         // Add PIT ports to io_map
         add_io_device!(self, pit, IoDeviceType::Pit);
 
@@ -1912,11 +2292,17 @@ impl BusInterface {
         add_io_device!(self, dma1, IoDeviceType::DmaPrimary);
         self.dma1 = Some(dma1);
 
-        // Create PIC. One PIC will always exist.
-        let pic1 = Pic::new();
-        // Add PIC ports to io_map
-        add_io_device!(self, pic1, IoDeviceType::PicPrimary);
-        self.pic1 = Some(pic1);
+        // Create secondary DMA controller if the machine has chained DMA controllers. The machine
+        // configuration may override the machine type's default.
+        if let Some(DmaType::Chained) = machine_config.dma_type.or(machine_desc.dma_type) {
+            let dma2 = DMAController::new_secondary();
+            add_io_device!(self, dma2, IoDeviceType::DmaSecondary);
+            self.dma2 = Some(dma2);
+        }
+
+        // Create the PICs. The machine configuration may override the machine type's default, to support
+        // XT option cards that provide IRQs 8-15.
+        self.install_pics(machine_config.pic_type.unwrap_or(machine_desc.pic_type));
 
         // Create keyboard if specified.
         if let Some(kb_config) = &machine_config.keyboard {
@@ -1931,6 +2317,32 @@ impl BusInterface {
             self.keyboard = Some(keyboard);
         }
 
+        // Create an 8042 keyboard controller if this machine uses one. The KBC claims port 60h; if a PPI is also
+        // present it keeps ports 61h-63h for speaker and DIP switch functions.
+        let kb_controller = machine_config.kb_controller.unwrap_or(machine_desc.kb_controller);
+        if let KbControllerType::At = kb_controller {
+            log::debug!("Creating 8042 keyboard controller...");
+            let kbc = KeyboardController::new(machine_config.ps2_mouse.is_some(), have_mda);
+            add_io_device!(self, kbc, IoDeviceType::KeyboardController);
+            self.kbc = Some(kbc);
+
+            // The 8042 talks to an AT-style keyboard, which natively sends scancode set 2.
+            if let Some(keyboard) = &mut self.keyboard {
+                keyboard.set_scancode_set(ScancodeSet::Set2);
+            }
+        }
+
+        // Create a PS/2 mouse if specified. It attaches to the auxiliary port of the 8042.
+        if let Some(ps2_mouse_config) = &machine_config.ps2_mouse {
+            if self.kbc.is_some() {
+                log::debug!("Creating PS/2 mouse...");
+                self.ps2_mouse = Some(Ps2Mouse::new(ps2_mouse_config.scale));
+            }
+            else {
+                log::warn!("PS/2 mouse requires an 8042 keyboard controller. Mouse not installed.");
+            }
+        }
+
         // Create FDC if specified.
         if let Some(fdc_config) = &machine_config.fdc {
             //let floppy_ct = fdc_config.drive.len();
@@ -1953,17 +2365,80 @@ impl BusInterface {
                 HardDiskControllerType::IbmXebec => {
                     // TODO: Get the correct drive type from the specified VHD...?
                     let hdc = HardDiskController::new(2, DRIVE_TYPE2_DIP);
+                    if hdc_config.cdrom.is_some() {
+                        log::warn!("CD-ROM drive is not supported by the IBM/Xebec controller.");
+                    }
                     // Add HDC ports to io_map
                     add_io_device!(self, hdc, IoDeviceType::HardDiskController);
                     self.hdc = Some(hdc);
                 }
+                HardDiskControllerType::XtIde => {
+                    #[allow(unused_mut)]
+                    let mut xtide = XtIdeController::new(hdc_config.io_base, hdc_config.cdrom);
+
+                    // Route the CD-ROM drive's audio output to the sound output.
+                    #[cfg(feature = "sound")]
+                    if let Some(cdrom) = xtide.cdrom_mut() {
+                        let (s, r) = unbounded();
+                        installed_devices.sound_sources.push(SoundSourceDescriptor::new(
+                            "CD-ROM Audio",
+                            CDROM_AUDIO_SAMPLE_RATE,
+                            CDROM_AUDIO_CHANNELS,
+                            r,
+                        ));
+                        cdrom.set_audio_sender(s);
+                    }
+
+                    add_io_device!(self, xtide, IoDeviceType::XtIde);
+                    self.xtide = Some(xtide);
+                }
+                HardDiskControllerType::SeagateSt01 => {
+                    if hdc_config.cdrom.is_some() {
+                        log::warn!("CD-ROM drive is not supported by the ST-01 controller.");
+                    }
+                    let st01 = SeagateSt01::new(hdc_config.window);
+                    add_mmio_device!(self, st01, MmioDeviceType::Scsi);
+                    self.st01 = Some(st01);
+                }
             }
         }
 
-        // Create an onboard parallel port if specified
-        if let Some(port_base) = machine_desc.onboard_parallel {
-            log::debug!("Creating on-board parallel port...");
-            let parallel = ParallelController::new(Some(port_base));
+        // Create an onboard parallel port if specified, or a parallel card if configured.
+        let parallel_config = machine_config.parallel.as_ref();
+        let parallel_base = machine_desc
+            .onboard_parallel
+            .or_else(|| parallel_config.map(|config| config.io_base.unwrap_or(LPT_DEFAULT_IO_BASE)));
+        if let Some(port_base) = parallel_base {
+            log::debug!("Creating parallel port at {:04X}...", port_base);
+            let mut parallel = ParallelController::new(Some(port_base), parallel_config.and_then(|config| config.irq));
+            // Attach a device if the parallel port was configured.
+            if let Some(config) = parallel_config {
+                let dac_model = match config.device {
+                    ParallelDeviceType::Printer => {
+                        parallel.attach_printer(Printer::new(config.command.clone(), config.job_timeout));
+                        None
+                    }
+                    ParallelDeviceType::Covox => Some(ParallelDacModel::Covox),
+                    ParallelDeviceType::DisneySoundSource => Some(ParallelDacModel::DisneySoundSource),
+                };
+                if let Some(model) = dac_model {
+                    #[allow(unused_mut)]
+                    let mut dac = ParallelDac::new(model);
+                    log::debug!("Attaching {} to parallel port", dac.name());
+                    #[cfg(feature = "sound")]
+                    {
+                        let (s, r) = unbounded();
+                        installed_devices.sound_sources.push(SoundSourceDescriptor::new(
+                            dac.name(),
+                            sound_config.sample_rate,
+                            1,
+                            r,
+                        ));
+                        dac.set_audio_sender(s, sound_config.sample_rate);
+                    }
+                    parallel.attach_dac(dac);
+                }
+            }
             // Add Parallel Port ports to io_map
             add_io_device!(self, parallel, IoDeviceType::Parallel);
             self.parallel = Some(parallel);
@@ -1977,7 +2452,23 @@ impl BusInterface {
             }
             match serial_config.sc_type {
                 SerialControllerType::IbmAsync => {
-                    let serial = SerialPortController::new(out2_suppresses_int);
+                    let mut serial = SerialPortController::new(out2_suppresses_int);
+                    // Bridge any ports configured with a host socket or pseudo-terminal.
+                    for (i, port_config) in serial_config.port.iter().enumerate().take(2) {
+                        if let Some(bridge_config) = &port_config.bridge {
+                            let result = match bridge_config.bridge_type {
+                                #[cfg(feature = "serial")]
+                                SerialBridgeType::Host => match &bridge_config.address {
+                                    Some(host_port) => serial.bridge_host_port(i, host_port).map(|_| ()),
+                                    None => Err(anyhow!("A host serial bridge requires an address.")),
+                                },
+                                _ => serial.bridge_socket(i, bridge_config).map(|_| ()),
+                            };
+                            if let Err(e) = result {
+                                log::error!("Failed to bridge serial port {}: {}", i, e);
+                            }
+                        }
+                    }
                     // Add Serial Controller ports to io_map
                     add_io_device!(self, serial, IoDeviceType::Serial);
                     self.serial = Some(serial);
@@ -1989,27 +2480,91 @@ impl BusInterface {
         if let Some(serial_mouse_config) = &machine_config.serial_mouse {
             // Only create mouse if we have as serial card to plug it into!
             if self.serial.is_some() {
-                match serial_mouse_config.mouse_type {
-                    SerialMouseType::Microsoft => {
-                        let mouse = Mouse::new(serial_mouse_config.port as usize);
-                        self.mouse = Some(mouse);
+                log::debug!(
+                    "Creating {:?} serial mouse on port {}",
+                    serial_mouse_config.mouse_type,
+                    serial_mouse_config.port
+                );
+                let mouse = Mouse::new(serial_mouse_config.port as usize, serial_mouse_config.mouse_type);
+                self.mouse = Some(mouse);
+            }
+        }
+
+        // Create a Hayes modem if specified. Like the mouse, it needs a serial port to attach to.
+        if let Some(modem_config) = &machine_config.modem {
+            if let Some(serial) = &mut self.serial {
+                log::debug!("Creating Hayes modem on port {}", modem_config.port);
+                serial.attach_device(modem_config.port as usize);
+                let modem = HayesModem::new(
+                    modem_config.port as usize,
+                    modem_config.listen_port,
+                    modem_config.phonebook.clone().unwrap_or_default(),
+                    modem_config.telnet.unwrap_or(true),
+                );
+                self.modem = Some(modem);
+            }
+            else {
+                log::warn!("Hayes modem requires a serial port. Modem not installed.");
+            }
+        }
+
+        // Create a cassette deck if specified. Only the 5150 and PCjr have a cassette port.
+        if let Some(cassette_config) = &machine_config.cassette {
+            match machine_desc.machine_type {
+                MachineType::Ibm5150v64K | MachineType::Ibm5150v256K | MachineType::IbmPCJr => {
+                    log::debug!("Creating cassette deck");
+                    let mut cassette = Cassette::new();
+                    if let Some(image) = &cassette_config.image {
+                        if let Err(e) = cassette.load(Path::new(image)) {
+                            log::error!("Failed to load cassette image {}: {}", image, e);
+                        }
                     }
+                    self.cassette = Some(cassette);
+                }
+                _ => {
+                    log::warn!("Machine type has no cassette port. Cassette deck not installed.");
+                }
+            }
+        }
+
+        // Create a bus mouse adapter if specified
+        if let Some(bus_mouse_config) = &machine_config.bus_mouse {
+            match bus_mouse_config.mouse_type {
+                BusMouseType::InPort => {
+                    let bus_mouse =
+                        InPortMouse::new(bus_mouse_config.io_base, bus_mouse_config.irq, bus_mouse_config.scale);
+                    log::debug!("Installing InPort bus mouse on IRQ{}", bus_mouse.irq());
+                    add_io_device!(self, bus_mouse, IoDeviceType::Mouse);
+                    self.bus_mouse = Some(bus_mouse);
                 }
             }
         }
 
         // Create an EMS board if specified
         if let Some(ems_config) = &machine_config.ems {
-            #[allow(irrefutable_let_patterns)]
-            if let EmsType::LoTech2MB = ems_config.ems_type {
-                // Add EMS ports to io_map
-                let ems = LotechEmsCard::new(Some(ems_config.io_base), Some(ems_config.window as usize));
-                add_io_device!(self, ems, IoDeviceType::Ems);
-                add_mmio_device!(self, ems, MmioDeviceType::Ems);
-                self.ems = Some(ems);
-            }
-            else {
-                log::error!("Bad EMS type {:?}", ems_config.ems_type);
+            match ems_config.ems_type {
+                EmsType::LoTech2MB => {
+                    // Add EMS ports to io_map
+                    let ems = LotechEmsCard::new(
+                        Some(ems_config.io_base),
+                        Some(ems_config.window as usize),
+                        ems_config.size,
+                    );
+                    add_io_device!(self, ems, IoDeviceType::Ems);
+                    add_mmio_device!(self, ems, MmioDeviceType::Ems);
+                    self.ems = Some(ems);
+                }
+                EmsType::Eems => {
+                    let eems = EemsBoard::new(
+                        Some(ems_config.io_base),
+                        Some(ems_config.window as usize),
+                        ems_config.size,
+                        ems_config.backfill.map(|seg| seg as usize),
+                    );
+                    add_io_device!(self, eems, IoDeviceType::Eems);
+                    add_mmio_device!(self, eems, MmioDeviceType::Eems);
+                    self.eems = Some(eems);
+                }
             }
         }
 
@@ -2032,34 +2587,134 @@ impl BusInterface {
         }
         // Either way, install it if present
         if let Some(game_port_addr) = game_port_addr {
-            let game_port = GamePort::new(Some(game_port_addr));
+            let layout = machine_config.game_port.as_ref().and_then(|gp| gp.layout);
+            let game_port = GamePort::new(Some(game_port_addr), layout);
             add_io_device!(self, game_port, IoDeviceType::GamePort);
             self.game_port = Some(game_port);
         }
 
+        // Create a real time clock if specified.
+        if let Some(rtc_config) = &machine_config.rtc {
+            let rtc = match rtc_config.rtc_type {
+                RtcType::At => Rtc::new(rtc_config.io_base, rtc_config.irq, true),
+                RtcType::XtClockCard => Rtc::new(rtc_config.io_base, rtc_config.irq, false),
+            };
+            log::debug!("Installing {:?} RTC on IRQ{}", rtc_config.rtc_type, rtc.irq());
+            add_io_device!(self, rtc, IoDeviceType::Rtc);
+            self.rtc = Some(rtc);
+        }
+
+        // Create a POST card if specified. On the AT this shadows the unused DMA page register at 80h.
+        if let Some(post_card_config) = &machine_config.post_card {
+            let post_card = PostCard::new(post_card_config.io_base);
+            log::debug!("Installing POST card on port {:04X}", post_card.port());
+            add_io_device!(self, post_card, IoDeviceType::PostCard);
+            self.post_card = Some(post_card);
+        }
+
+        // Create a network card if specified.
+        if let Some(nic_config) = &machine_config.network {
+            let model = match nic_config.nic_type {
+                NetworkCardType::Ne1000 => NicModel::Ne1000,
+                NetworkCardType::Ne2000 => NicModel::Ne2000,
+            };
+            let mac = match &nic_config.mac {
+                Some(mac_str) => {
+                    let mac = parse_mac(mac_str);
+                    if mac.is_none() {
+                        log::warn!(
+                            "Invalid MAC address '{}' in network card config. Using default.",
+                            mac_str
+                        );
+                    }
+                    mac
+                }
+                None => None,
+            };
+            let backend: Box<dyn NetworkBackend> = match nic_config.backend {
+                NetworkBackendType::Slirp => Box::new(SlirpBackend::new()),
+            };
+            let nic = Ne2000::new(model, nic_config.io_base, nic_config.irq, mac, backend);
+            log::debug!("Installing {} on IRQ{}", nic.model_name(), nic.irq());
+            add_io_device!(self, nic, IoDeviceType::Network);
+            self.nic = Some(nic);
+        }
+
         // Create sound cards
         #[cfg(feature = "sound")]
+        let mut sn_io_base = match machine_desc.machine_type {
+            MachineType::Tandy1000 | MachineType::IbmPCJr => Some(SN_DEFAULT_IO_BASE),
+            _ => None,
+        };
+        #[cfg(feature = "sound")]
         for (_i, card) in machine_config.sound.iter().enumerate() {
-            #[allow(irrefutable_let_patterns)]
             if let SoundType::AdLib = card.sound_type {
                 // Create an AdLib card.
-
-                let (s, r) = unbounded();
-                installed_devices.sound_sources.push(SoundSourceDescriptor::new(
-                    "AdLib Music Synthesizer",
+                #[cfg(feature = "opl")]
+                self.install_sound_device(
+                    AdLibCard::new(card.io_base).into(),
                     sound_config.sample_rate,
-                    2,
-                    r,
-                ));
-
-                let adlib = AdLibCard::new(card.io_base, 48000, s);
-                println!(">>> TESTING ADLIB <<<");
-
-                add_io_device!(self, adlib, IoDeviceType::Sound);
-                self.adlib = Some(adlib);
+                    &mut installed_devices,
+                );
+                #[cfg(not(feature = "opl"))]
+                log::warn!("AdLib requires the 'opl' feature.");
+            }
+            else if let SoundType::SoundBlaster = card.sound_type {
+                // Create a Sound Blaster. It plays digitized audio through the primary DMA controller.
+                let irq = card.irq.unwrap_or(SB_DEFAULT_IRQ);
+                let dma = card.dma.unwrap_or(SB_DEFAULT_DMA);
+                log::debug!(
+                    "Installing Sound Blaster at {:03X}h on IRQ{}, DMA{}",
+                    card.io_base,
+                    irq,
+                    dma
+                );
+                self.install_sound_device(
+                    SoundBlaster::new(card.io_base, irq, dma).into(),
+                    sound_config.sample_rate,
+                    &mut installed_devices,
+                );
+            }
+            else if let SoundType::Sn76489 = card.sound_type {
+                sn_io_base = Some(card.io_base);
+            }
+            else if let SoundType::Mpu401 = card.sound_type {
+                // Create an MPU-401 MIDI interface and connect it to a host MIDI port.
+                let irq = card.irq.unwrap_or(MPU_DEFAULT_IRQ);
+                #[allow(unused_mut)]
+                let mut mpu = Mpu401::new(card.io_base, irq);
+                #[cfg(feature = "midi")]
+                match mpu.connect_output(card.midi_output.as_deref()) {
+                    Ok(name) => log::info!("MPU-401 connected to MIDI output: {}", name),
+                    Err(e) => log::warn!("MPU-401 could not connect to a MIDI output: {}", e),
+                }
+                #[cfg(feature = "midi")]
+                if let Some(input) = &card.midi_input {
+                    match mpu.connect_input(Some(input)) {
+                        Ok(name) => log::info!("MPU-401 connected to MIDI input: {}", name),
+                        Err(e) => log::warn!("MPU-401 could not connect to a MIDI input: {}", e),
+                    }
+                }
+                #[cfg(not(feature = "midi"))]
+                log::warn!("MPU-401 requires the 'midi' feature for MIDI output.");
+                log::debug!("Installing MPU-401 at {:03X}h on IRQ{}", card.io_base, irq);
+                add_io_device!(self, mpu, IoDeviceType::Mpu401);
+                self.mpu401 = Some(mpu);
             }
         }
 
+        // Create the SN76489 sound generator. The PCjr and Tandy 1000 have one onboard; other machines
+        // can have one on a Tandy sound card.
+        #[cfg(feature = "sound")]
+        if let Some(io_base) = sn_io_base {
+            log::debug!("Installing SN76489 at {:03X}h", io_base);
+            self.install_sound_device(
+                Sn76489::new(io_base).into(),
+                sound_config.sample_rate,
+                &mut installed_devices,
+            );
+        }
+
         // Create video cards
         for (i, card) in machine_config.video.iter().enumerate() {
             let video_dispatch;
@@ -2069,6 +2724,10 @@ impl BusInterface {
             };
 
             log::debug!("Creating video card of type: {:?}", card.video_type);
+            if card.primary && self.primary_card.is_none() {
+                log::debug!("Video card {:?} is the primary display", video_id);
+                self.primary_card = Some(video_id);
+            }
             match card.video_type {
                 VideoType::MDA => {
                     let mda = MDACard::new(
@@ -2083,7 +2742,13 @@ impl BusInterface {
                     video_dispatch = VideoCardDispatch::Mda(mda)
                 }
                 VideoType::CGA => {
-                    let cga = CGACard::new(TraceLogger::None, clock_mode, video_frame_debug);
+                    let cga = CGACard::new(
+                        card.video_subtype.unwrap_or(VideoCardSubType::None),
+                        card.standard,
+                        TraceLogger::None,
+                        clock_mode,
+                        video_frame_debug,
+                    );
                     add_io_device!(self, cga, IoDeviceType::Video(video_id));
                     add_mmio_device!(self, cga, MmioDeviceType::Video(video_id));
                     video_dispatch = VideoCardDispatch::Cga(cga)
@@ -2092,6 +2757,10 @@ impl BusInterface {
                     // Subtype can be Tandy1000 or PCJr
                     let subtype = card.video_subtype.unwrap_or(VideoCardSubType::Tandy1000);
                     let tga = TGACard::new(subtype, TraceLogger::None, clock_mode, video_frame_debug);
+                    if let VideoCardSubType::IbmPCJr = subtype {
+                        // The PCjr's video gate array arbitrates all access to the first 128K of RAM.
+                        self.shared_vram = Some(video_id);
+                    }
                     add_io_device!(self, tga, IoDeviceType::Video(video_id));
                     add_mmio_device!(self, tga, MmioDeviceType::Video(video_id));
                     video_dispatch = VideoCardDispatch::Tga(tga)
@@ -2105,7 +2774,13 @@ impl BusInterface {
                 }
                 #[cfg(feature = "vga")]
                 VideoType::VGA => {
-                    let vga = VGACard::new(TraceLogger::None, clock_mode, video_frame_debug, card.dip_switch);
+                    let vga = VGACard::new(
+                        card.video_subtype.unwrap_or(VideoCardSubType::None),
+                        TraceLogger::None,
+                        clock_mode,
+                        video_frame_debug,
+                        card.dip_switch,
+                    );
                     add_io_device!(self, vga, IoDeviceType::Video(video_id));
                     add_mmio_device!(self, vga, MmioDeviceType::Video(video_id));
                     video_dispatch = VideoCardDispatch::Vga(vga)
@@ -2127,9 +2802,38 @@ impl BusInterface {
         Ok(installed_devices)
     }
 
+    /// Install a sound device, giving it a sound source of its own and mapping its I/O ports.
+    #[cfg(feature = "sound")]
+    fn install_sound_device(
+        &mut self,
+        mut device: SoundDispatch,
+        sample_rate: u32,
+        installed_devices: &mut InstalledDevicesResult,
+    ) {
+        let (s, r) = unbounded();
+        installed_devices.sound_sources.push(SoundSourceDescriptor::new(
+            device.name(),
+            sample_rate,
+            device.channels(),
+            r,
+        ));
+        device.set_audio_sender(s, sample_rate);
+
+        let idx = self.sound_devices.len();
+        add_io_device!(self, device, IoDeviceType::Sound(idx));
+        self.sound_devices.push(device);
+    }
+
     /// Return whether NMI is enabled.
     /// On the 5150 & 5160, NMI generation can be disabled via the PPI.
+    /// An AT-style RTC can also mask NMI via bit 7 of its index register.
     pub fn nmi_enabled(&self) -> bool {
+        if let Some(rtc) = &self.rtc {
+            if rtc.nmi_masked() {
+                return false;
+            }
+        }
+
         match self.machine_desc.unwrap().machine_type {
             // TODO: Add other types?
             MachineType::Ibm5150v64K | MachineType::Ibm5150v256K | MachineType::Ibm5160 => {
@@ -2160,6 +2864,34 @@ impl BusInterface {
     }
 
     pub fn process_keyboard_input(&mut self) {
+        // If we have an 8042, the keyboard talks to it instead of the PPI.
+        if let Some(kbc) = &mut self.kbc {
+            if let Some(keyboard) = &mut self.keyboard {
+                // Forward any byte the host wrote to the keyboard.
+                if let Some(kb_cmd) = kbc.take_keyboard_tx() {
+                    keyboard.command(kb_cmd);
+                }
+                // Only clock in a new byte once the controller can accept one.
+                if kbc.keyboard_ready() {
+                    if let Some(kb_byte) = keyboard.recv_scancode() {
+                        kbc.receive_keyboard(kb_byte);
+                    }
+                }
+            }
+            // The PS/2 mouse on the auxiliary port works the same way.
+            if let Some(ps2_mouse) = &mut self.ps2_mouse {
+                if let Some(aux_cmd) = kbc.take_aux_tx() {
+                    ps2_mouse.command(aux_cmd);
+                }
+                if kbc.aux_ready() {
+                    if let Some(aux_byte) = ps2_mouse.recv() {
+                        kbc.receive_aux(aux_byte);
+                    }
+                }
+            }
+            return;
+        }
+
         if let Some(keyboard) = &mut self.keyboard {
             // Read a byte from the keyboard
             if let Some(kb_byte) = keyboard.recv_scancode() {
@@ -2222,15 +2954,53 @@ impl BusInterface {
             }
         }
 
-        if process_keyboard {
+        // Run the PS/2 mouse, if present, so it can generate packets at its sample rate.
+        if let Some(ps2_mouse) = &mut self.ps2_mouse {
+            ps2_mouse.run(us);
+        }
+
+        // The 8042 is polled every device update, as it may be waiting to transfer command bytes or responses.
+        if process_keyboard || self.kbc.is_some() {
             self.process_keyboard_input();
         }
 
+        // Run the 8042 if present. It raises IRQ1 for keyboard data and IRQ12 for auxiliary device data, and can
+        // pulse the CPU reset line via its output port.
+        if let Some(kbc) = &mut self.kbc {
+            let (kb_irq, aux_irq) = kbc.run(us);
+            let cpu_reset = kbc.take_reset();
+            match kb_irq {
+                Some(true) => self.request_irq(KBC_KEYBOARD_IRQ),
+                Some(false) => self.clear_irq(KBC_KEYBOARD_IRQ),
+                None => {}
+            }
+            match aux_irq {
+                Some(true) => self.request_irq(KBC_AUX_IRQ),
+                Some(false) => self.clear_irq(KBC_AUX_IRQ),
+                None => {}
+            }
+            if cpu_reset {
+                event = Some(DeviceEvent::CpuReset);
+            }
+        }
+
         // There will always be a PIC, so safe to unwrap.
         let pic = self.pic1.as_mut().unwrap();
 
         pic.run(sys_ticks);
 
+        // Run the slave PIC if present. Its INT output drives the cascade IR line of the master.
+        if let Some(pic2) = &mut self.pic2 {
+            pic2.run(sys_ticks);
+            let slave_intr = pic2.query_interrupt_line();
+            if slave_intr != pic.ir_line(PIC_CASCADE_IRQ) {
+                match slave_intr {
+                    true => pic.request_interrupt(PIC_CASCADE_IRQ),
+                    false => pic.clear_interrupt(PIC_CASCADE_IRQ),
+                }
+            }
+        }
+
         // There will always be a PIT, so safe to unwrap.
         let mut pit = self.pit.take().unwrap();
 
@@ -2300,6 +3070,13 @@ impl BusInterface {
         // Put the PIT back.
         self.pit = Some(pit);
 
+        // Run the cassette deck. Its data output is driven by PIT channel 2 through the PPI, and its data
+        // input is read back through the PPI when the motor relay is closed.
+        if let (Some(cassette), Some(ppi)) = (&mut self.cassette, &mut self.ppi) {
+            let data_in = cassette.run(us, ppi.cassette_motor_on(), ppi.get_pit_output_bit());
+            ppi.set_cassette_bit(data_in);
+        }
+
         let mut dma1 = self.dma1.take().unwrap();
 
         // Run the FDC, passing it DMA controller while DMA is still unattached.
@@ -2314,9 +3091,36 @@ impl BusInterface {
             self.hdc = Some(hdc);
         }
 
+        // Run sound devices. Devices that use DMA, such as the Sound Blaster DSP, pace their transfers
+        // by their sample rate.
+        #[cfg(feature = "sound")]
+        {
+            let mut sound_devices = std::mem::take(&mut self.sound_devices);
+            for device in sound_devices.iter_mut() {
+                let irq_state = device.run(&mut dma1, self, us);
+                if let (Some(irq), Some(state)) = (device.irq(), irq_state) {
+                    if state {
+                        self.request_irq(irq);
+                    }
+                    else {
+                        self.clear_irq(irq);
+                    }
+                }
+            }
+            self.sound_devices = sound_devices;
+        }
+
         // Run the DMA controller.
         dma1.run(self);
 
+        // Run the secondary DMA controller, if present. The primary controller's HRQ output drives
+        // DREQ on the secondary controller's cascade channel.
+        if let Some(mut dma2) = self.dma2.take() {
+            dma2.set_cascade_request(dma1.hrq());
+            dma2.run(self);
+            self.dma2 = Some(dma2);
+        }
+
         // Replace the DMA controller.
         self.dma1 = Some(dma1);
 
@@ -2327,6 +3131,20 @@ impl BusInterface {
             if let Some(mouse) = &mut self.mouse {
                 mouse.run(serial, us);
             }
+
+            if let Some(modem) = &mut self.modem {
+                modem.run(serial, us);
+            }
+        }
+
+        // Run the bus mouse adapter.
+        if let Some(bus_mouse) = &mut self.bus_mouse {
+            let irq = bus_mouse.irq();
+            match bus_mouse.run(us) {
+                Some(true) => self.request_irq(irq),
+                Some(false) => self.clear_irq(irq),
+                None => {}
+            }
         }
 
         // Run the game port {
@@ -2334,10 +3152,54 @@ impl BusInterface {
             game_port.run(us);
         }
 
-        // Run the adlib card {
-        #[cfg(feature = "opl")]
-        if let Some(adlib) = &mut self.adlib {
-            adlib.run(us);
+        // Run the RTC. Its IRQ may be routed to either PIC.
+        if let Some(rtc) = &mut self.rtc {
+            let irq = rtc.irq();
+            match rtc.run(us) {
+                Some(true) => self.request_irq(irq),
+                Some(false) => self.clear_irq(irq),
+                None => {}
+            }
+        }
+
+        // Run the POST card
+        if let Some(post_card) = &mut self.post_card {
+            post_card.run(us);
+        }
+
+        // Run the parallel port
+        if let Some(parallel) = &mut self.parallel {
+            let irq = parallel.irq() as u8;
+            match parallel.run(us) {
+                Some(true) => self.request_irq(irq),
+                Some(false) => self.clear_irq(irq),
+                None => {}
+            }
+        }
+
+        // Run the network card
+        if let Some(nic) = &mut self.nic {
+            let irq = nic.irq();
+            match nic.run(us) {
+                Some(true) => self.request_irq(irq),
+                Some(false) => self.clear_irq(irq),
+                None => {}
+            }
+        }
+
+        // Run the MPU-401. It holds its IRQ line while data is waiting to be read.
+        if let Some(mpu) = &mut self.mpu401 {
+            let irq = mpu.irq();
+            match mpu.run(us) {
+                Some(true) => self.request_irq(irq),
+                Some(false) => self.clear_irq(irq),
+                None => {}
+            }
+        }
+
+        // Run the XT-IDE controller, which plays CD audio.
+        if let Some(xtide) = &mut self.xtide {
+            xtide.run(us);
         }
 
         let mut do_area5150_hack = false;
@@ -2503,26 +3365,109 @@ impl BusInterface {
     //noinspection RsBorrowChecker
     /// Call the reset methods for all devices on the bus
     pub fn reset_devices(&mut self) {
+        // Apply any DIP switch changes made since the last reset
+        if let Some(ppi) = self.ppi.as_mut() {
+            ppi.apply_pending_dip_switches();
+        }
+
         // Reset PIT
         if let Some(pit) = self.pit.as_mut() {
             pit.reset();
         }
 
+        // Reset XT-IDE controller
+        if let Some(xtide) = self.xtide.as_mut() {
+            xtide.reset();
+        }
+
+        // Reset SCSI adapter
+        if let Some(st01) = self.st01.as_mut() {
+            st01.reset();
+        }
+
+        // Reset EEMS board, returning any backfill windows to conventional memory
+        if let Some(eems) = self.eems.as_mut() {
+            eems.reset();
+        }
+        self.update_eems_mapping();
+
         // Reset PIC
         if let Some(pic1) = self.pic1.as_mut() {
             pic1.reset();
         }
+        if let Some(pic2) = self.pic2.as_mut() {
+            pic2.reset();
+        }
 
         // Reset DMA
         if let Some(dma1) = self.dma1.as_mut() {
             dma1.reset();
         }
+        if let Some(dma2) = self.dma2.as_mut() {
+            dma2.reset();
+        }
+
+        // Reset keyboard controller
+        if let Some(kbc) = self.kbc.as_mut() {
+            kbc.reset();
+        }
+
+        // Reset bus mouse
+        if let Some(bus_mouse) = self.bus_mouse.as_mut() {
+            bus_mouse.reset();
+        }
+
+        // Reset PS/2 mouse
+        if let Some(ps2_mouse) = self.ps2_mouse.as_mut() {
+            ps2_mouse.reset();
+        }
 
         // Reset Serial controller
         if let Some(serial) = self.serial.as_mut() {
             serial.reset();
         }
 
+        // Reset modem
+        if let Some(modem) = self.modem.as_mut() {
+            modem.reset();
+        }
+
+        // Reset sound devices
+        #[cfg(feature = "sound")]
+        for device in self.sound_devices.iter_mut() {
+            device.reset();
+        }
+
+        // Reset MPU-401
+        if let Some(mpu) = self.mpu401.as_mut() {
+            mpu.reset();
+        }
+
+        // Reset cassette deck. The tape stays in the deck.
+        if let Some(cassette) = self.cassette.as_mut() {
+            cassette.reset();
+        }
+
+        // Reset RTC. The time and CMOS contents are battery-backed and preserved.
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.reset();
+        }
+
+        // Reset POST card
+        if let Some(post_card) = self.post_card.as_mut() {
+            post_card.reset();
+        }
+
+        // Reset network card
+        if let Some(nic) = self.nic.as_mut() {
+            nic.reset();
+        }
+
+        // Reset parallel port
+        if let Some(parallel) = self.parallel.as_mut() {
+            parallel.reset();
+        }
+
         // Reset fdc
         if let Some(fdc) = self.fdc.as_mut() {
             fdc.reset();
@@ -2605,6 +3550,11 @@ impl BusInterface {
                         byte = Some(hdc.read_u8(port, nul_delta));
                     }
                 }
+                IoDeviceType::XtIde => {
+                    if let Some(xtide) = &mut self.xtide {
+                        byte = Some(xtide.read_u8(port, nul_delta));
+                    }
+                }
                 IoDeviceType::Serial => {
                     if let Some(serial) = &mut self.serial {
                         // Serial port write does not need bus.
@@ -2621,9 +3571,40 @@ impl BusInterface {
                         byte = Some(ems.read_u8(port, nul_delta));
                     }
                 }
+                IoDeviceType::Eems => {
+                    if let Some(eems) = &mut self.eems {
+                        byte = Some(eems.read_u8(port, nul_delta));
+                    }
+                }
                 IoDeviceType::GamePort => {
+                    let delta = self.cycles_to_us_delta(cycles);
                     if let Some(game_port) = &mut self.game_port {
-                        byte = Some(game_port.read_u8(port, nul_delta));
+                        byte = Some(game_port.read_u8(port, delta));
+                    }
+                }
+                IoDeviceType::Rtc => {
+                    if let Some(rtc) = &mut self.rtc {
+                        byte = Some(rtc.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::PostCard => {
+                    if let Some(post_card) = &mut self.post_card {
+                        byte = Some(post_card.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::Network => {
+                    if let Some(nic) = &mut self.nic {
+                        byte = Some(nic.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::KeyboardController => {
+                    if let Some(kbc) = &mut self.kbc {
+                        byte = Some(kbc.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::Mouse => {
+                    if let Some(bus_mouse) = &mut self.bus_mouse {
+                        byte = Some(bus_mouse.read_u8(port, nul_delta));
                     }
                 }
                 IoDeviceType::Video(vid) => {
@@ -2646,11 +3627,16 @@ impl BusInterface {
                         }
                     }
                 }
-                IoDeviceType::Sound =>
+                IoDeviceType::Sound(idx) =>
                 {
-                    #[cfg(feature = "opl")]
-                    if let Some(adlib) = &mut self.adlib {
-                        byte = Some(adlib.read_u8(port, nul_delta));
+                    #[cfg(feature = "sound")]
+                    if let Some(device) = self.sound_devices.get_mut(*idx) {
+                        byte = Some(device.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::Mpu401 => {
+                    if let Some(mpu) = &mut self.mpu401 {
+                        byte = Some(mpu.read_u8(port, nul_delta));
                     }
                 }
                 _ => {}
@@ -2764,6 +3750,12 @@ impl BusInterface {
                         self.hdc = Some(hdc);
                     }
                 }
+                IoDeviceType::XtIde => {
+                    if let Some(xtide) = &mut self.xtide {
+                        xtide.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
                 IoDeviceType::Serial => {
                     if let Some(serial) = &mut self.serial {
                         // Serial port write does not need bus.
@@ -2783,9 +3775,48 @@ impl BusInterface {
                         resolved = true;
                     }
                 }
+                IoDeviceType::Eems => {
+                    if let Some(eems) = &mut self.eems {
+                        eems.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                    self.update_eems_mapping();
+                }
                 IoDeviceType::GamePort => {
+                    let delta = self.cycles_to_us_delta(cycles);
                     if let Some(game_port) = &mut self.game_port {
-                        game_port.write_u8(port, data, None, nul_delta);
+                        game_port.write_u8(port, data, None, delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Rtc => {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::PostCard => {
+                    let delta = self.cycles_to_us_delta(cycles);
+                    if let Some(post_card) = &mut self.post_card {
+                        post_card.write_u8(port, data, None, delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Network => {
+                    if let Some(nic) = &mut self.nic {
+                        nic.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::KeyboardController => {
+                    if let Some(kbc) = &mut self.kbc {
+                        kbc.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Mouse => {
+                    if let Some(bus_mouse) = &mut self.bus_mouse {
+                        bus_mouse.write_u8(port, data, None, nul_delta);
                         resolved = true;
                     }
                 }
@@ -2818,11 +3849,18 @@ impl BusInterface {
                         }
                     }
                 }
-                IoDeviceType::Sound =>
+                IoDeviceType::Sound(idx) =>
                 {
-                    #[cfg(feature = "opl")]
-                    if let Some(adlib) = &mut self.adlib {
-                        IoDevice::write_u8(adlib, port, data, None, nul_delta);
+                    #[cfg(feature = "sound")]
+                    if let Some(device) = self.sound_devices.get_mut(*idx) {
+                        IoDevice::write_u8(device, port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Mpu401 => {
+                    if let Some(mpu) = &mut self.mpu401 {
+                        mpu.write_u8(port, data, None, nul_delta);
+                        resolved = true;
                     }
                 }
                 _ => {}
@@ -2854,6 +3892,10 @@ impl BusInterface {
         &self.fdc
     }
 
+    pub fn rtc(&self) -> &Option<Rtc> {
+        &self.rtc
+    }
+
     pub fn pit_mut(&mut self) -> &mut Option<Pit> {
         &mut self.pit
     }
@@ -2870,6 +3912,87 @@ impl BusInterface {
         &mut self.dma1
     }
 
+    pub fn dma2_mut(&mut self) -> &mut Option<DMAController> {
+        &mut self.dma2
+    }
+
+    pub fn pic2_mut(&mut self) -> &mut Option<Pic> {
+        &mut self.pic2
+    }
+
+    /// Return the state of the INTR line to the CPU.
+    pub fn query_interrupt_line(&self) -> bool {
+        self.pic1.as_ref().map_or(false, |pic| pic.query_interrupt_line())
+    }
+
+    /// Perform the interrupt acknowledge sequence and return the interrupt vector placed on the bus.
+    /// If the master PIC acknowledges a request on a cascade line, the slave PIC supplies the vector.
+    pub fn get_interrupt_vector(&mut self) -> Option<u8> {
+        let pic1 = self.pic1.as_mut()?;
+        let vector = pic1.get_interrupt_vector();
+        if !pic1.cascade_acknowledged() {
+            return vector;
+        }
+
+        match &mut self.pic2 {
+            Some(pic2) if pic2.query_interrupt_line() => pic2.get_interrupt_vector(),
+            Some(pic2) => Some(pic2.get_spurious_vector()),
+            None => {
+                log::warn!("Cascade interrupt acknowledged with no secondary PIC installed");
+                vector
+            }
+        }
+    }
+
+    /// Request an interrupt on the specified IRQ line. IRQs 8-15 are routed to the secondary PIC.
+    pub fn request_irq(&mut self, irq: u8) {
+        match irq {
+            0..=7 => {
+                if let Some(pic1) = &mut self.pic1 {
+                    pic1.request_interrupt(irq);
+                }
+            }
+            8..=15 => match &mut self.pic2 {
+                Some(pic2) => pic2.request_interrupt(irq - 8),
+                None => log::warn!("IRQ{} requested with no secondary PIC installed", irq),
+            },
+            _ => log::error!("IRQ{} out of range", irq),
+        }
+    }
+
+    /// Withdraw an interrupt request on the specified IRQ line. IRQs 8-15 are routed to the secondary PIC.
+    pub fn clear_irq(&mut self, irq: u8) {
+        match irq {
+            0..=7 => {
+                if let Some(pic1) = &mut self.pic1 {
+                    pic1.clear_interrupt(irq);
+                }
+            }
+            8..=15 => {
+                if let Some(pic2) = &mut self.pic2 {
+                    pic2.clear_interrupt(irq - 8);
+                }
+            }
+            _ => log::error!("IRQ{} out of range", irq),
+        }
+    }
+
+    /// Pulse the specified IRQ line. IRQs 8-15 are routed to the secondary PIC.
+    pub fn pulse_irq(&mut self, irq: u8) {
+        match irq {
+            0..=7 => {
+                if let Some(pic1) = &mut self.pic1 {
+                    pic1.pulse_interrupt(irq);
+                }
+            }
+            8..=15 => match &mut self.pic2 {
+                Some(pic2) => pic2.pulse_interrupt(irq - 8),
+                None => log::warn!("IRQ{} pulsed with no secondary PIC installed", irq),
+            },
+            _ => log::error!("IRQ{} out of range", irq),
+        }
+    }
+
     pub fn serial_mut(&mut self) -> &mut Option<SerialPortController> {
         &mut self.serial
     }
@@ -2882,6 +4005,14 @@ impl BusInterface {
         &mut self.hdc
     }
 
+    pub fn xtide_mut(&mut self) -> &mut Option<XtIdeController> {
+        &mut self.xtide
+    }
+
+    pub fn st01_mut(&mut self) -> &mut Option<SeagateSt01> {
+        &mut self.st01
+    }
+
     pub fn cart_slot_mut(&mut self) -> &mut Option<CartridgeSlot> {
         &mut self.cart_slot
     }
@@ -2890,26 +4021,84 @@ impl BusInterface {
         &mut self.game_port
     }
 
+    pub fn rtc_mut(&mut self) -> &mut Option<Rtc> {
+        &mut self.rtc
+    }
+
+    pub fn post_card_mut(&mut self) -> &mut Option<PostCard> {
+        &mut self.post_card
+    }
+
+    pub fn parallel_mut(&mut self) -> &mut Option<ParallelController> {
+        &mut self.parallel
+    }
+
+    pub fn cassette_mut(&mut self) -> &mut Option<Cassette> {
+        &mut self.cassette
+    }
+
+    pub fn nic_mut(&mut self) -> &mut Option<Ne2000> {
+        &mut self.nic
+    }
+
+    pub fn kbc_mut(&mut self) -> &mut Option<KeyboardController> {
+        &mut self.kbc
+    }
+
     pub fn mouse_mut(&mut self) -> &mut Option<Mouse> {
         &mut self.mouse
     }
 
-    pub fn primary_video(&self) -> Option<Box<&dyn VideoCard>> {
-        if self.videocard_ids.len() > 0 {
-            self.video(&self.videocard_ids[0])
+    pub fn bus_mouse_mut(&mut self) -> &mut Option<InPortMouse> {
+        &mut self.bus_mouse
+    }
+
+    pub fn ps2_mouse_mut(&mut self) -> &mut Option<Ps2Mouse> {
+        &mut self.ps2_mouse
+    }
+
+    /// Return whether any mouse device that accepts host mouse input is installed.
+    pub fn have_mouse(&self) -> bool {
+        self.mouse.is_some() || self.bus_mouse.is_some() || self.ps2_mouse.is_some()
+    }
+
+    /// Send a host mouse update to every installed mouse device.
+    pub fn mouse_update(
+        &mut self,
+        l_button_pressed: bool,
+        r_button_pressed: bool,
+        m_button_pressed: bool,
+        delta_x: f64,
+        delta_y: f64,
+    ) {
+        if let Some(mouse) = &mut self.mouse {
+            mouse.update(l_button_pressed, r_button_pressed, m_button_pressed, delta_x, delta_y);
         }
-        else {
-            None
+        if let Some(bus_mouse) = &mut self.bus_mouse {
+            bus_mouse.update(l_button_pressed, r_button_pressed, m_button_pressed, delta_x, delta_y);
+        }
+        if let Some(ps2_mouse) = &mut self.ps2_mouse {
+            ps2_mouse.update(l_button_pressed, r_button_pressed, m_button_pressed, delta_x, delta_y);
         }
     }
 
-    pub fn primary_video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
-        if self.videocard_ids.len() > 0 {
-            let vid = self.videocard_ids[0];
-            self.video_mut(&vid)
+    /// Return the id of the primary video card. This is the card designated as primary in the
+    /// machine configuration, or the first card installed if none was designated.
+    pub fn primary_video_id(&self) -> Option<VideoCardId> {
+        self.primary_card.or_else(|| self.videocard_ids.first().copied())
+    }
+
+    pub fn primary_video(&self) -> Option<Box<&dyn VideoCard>> {
+        match self.primary_video_id() {
+            Some(vid) => self.video(&vid),
+            None => None,
         }
-        else {
-            None
+    }
+
+    pub fn primary_video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
+        match self.primary_video_id() {
+            Some(vid) => self.video_mut(&vid),
+            None => None,
         }
     }
 
@@ -3008,6 +4197,12 @@ impl BusInterface {
         if let Some(hdc) = &self.hdc {
             hdc.drive_ct()
         }
+        else if let Some(xtide) = &self.xtide {
+            xtide.drive_ct()
+        }
+        else if let Some(st01) = &self.st01 {
+            st01.drive_ct()
+        }
         else {
             0
         }
@@ -3085,4 +4280,195 @@ impl BusInterface {
             stats.1.writes_dirty = false;
         }
     }
+
+    /// Write the contents of memory and the state of all installed devices. The POST card is a
+    /// diagnostic aid and is not saved.
+    pub fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        w.write_section(b"MEM ", |w| w.write_bytes(&self.memory));
+        w.write_section(b"BUS ", |w| {
+            w.write_u8(self.open_bus_byte);
+            w.write_bool(self.intr_imminent);
+            w.write_u8(self.a0_data);
+            w.write_bool(self.nmi_latch);
+            w.write_u16(self.dma_counter);
+            w.write_u32(self.pit_ticks_advance);
+            w.write_bool(self.timer_trigger1_armed);
+            w.write_bool(self.timer_trigger2_armed);
+            w.write_u32(self.cga_tick_accum);
+            w.write_u32(self.tga_tick_accum);
+            w.write_f64(self.kb_us_accum);
+            w.write_bool(self.refresh_active);
+        });
+
+        fn write_device<T: SaveState>(w: &mut StateWriter, tag: &[u8; 4], device: &Option<T>) {
+            if let Some(device) = device {
+                w.write_section(tag, |w| device.save_state(w));
+            }
+        }
+        write_device(w, b"PPI ", &self.ppi);
+        write_device(w, b"A0  ", &self.a0);
+        write_device(w, b"PIT ", &self.pit);
+        write_device(w, b"DMA1", &self.dma1);
+        write_device(w, b"DMA2", &self.dma2);
+        write_device(w, b"PIC1", &self.pic1);
+        write_device(w, b"PIC2", &self.pic2);
+        write_device(w, b"KBD ", &self.keyboard);
+        write_device(w, b"FDC ", &self.fdc);
+        write_device(w, b"SER ", &self.serial);
+        write_device(w, b"LPT ", &self.parallel);
+        write_device(w, b"MOUS", &self.mouse);
+        write_device(w, b"GAME", &self.game_port);
+        write_device(w, b"KBC ", &self.kbc);
+        write_device(w, b"HDC ", &self.hdc);
+        write_device(w, b"XIDE", &self.xtide);
+        write_device(w, b"ST01", &self.st01);
+        write_device(w, b"MODM", &self.modem);
+        write_device(w, b"CASS", &self.cassette);
+        write_device(w, b"BMOU", &self.bus_mouse);
+        write_device(w, b"PS2M", &self.ps2_mouse);
+        write_device(w, b"EMS ", &self.ems);
+        write_device(w, b"EEMS", &self.eems);
+        write_device(w, b"CART", &self.cart_slot);
+        write_device(w, b"RTC ", &self.rtc);
+        write_device(w, b"NIC ", &self.nic);
+        write_device(w, b"MPU ", &self.mpu401);
+        #[cfg(feature = "sound")]
+        for device in self.sound_devices.iter() {
+            w.write_section(b"SND ", |w| device.save_state(w));
+        }
+
+        for vid in self.videocard_ids.iter() {
+            if let Some(card) = self.video(vid) {
+                let mut result = Ok(());
+                w.write_section(b"VID ", |w| result = card.save_state(w));
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore memory and device state written by save_state(). The set of installed devices
+    /// must match the machine that the state was saved from.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.read_section(b"MEM ", |r| r.read_bytes_into(&mut self.memory, "memory size"))?;
+        r.read_section(b"BUS ", |r| {
+            self.open_bus_byte = r.read_u8()?;
+            self.intr_imminent = r.read_bool()?;
+            self.a0_data = r.read_u8()?;
+            self.nmi_latch = r.read_bool()?;
+            self.dma_counter = r.read_u16()?;
+            self.pit_ticks_advance = r.read_u32()?;
+            self.timer_trigger1_armed = r.read_bool()?;
+            self.timer_trigger2_armed = r.read_bool()?;
+            self.cga_tick_accum = r.read_u32()?;
+            self.tga_tick_accum = r.read_u32()?;
+            self.kb_us_accum = r.read_f64()?;
+            self.refresh_active = r.read_bool()?;
+            Ok(())
+        })?;
+
+        fn read_device<T: SaveState>(
+            r: &mut StateReader,
+            tag: &[u8; 4],
+            device: &mut Option<T>,
+        ) -> Result<(), SaveStateError> {
+            match device {
+                Some(device) => r.read_section(tag, |r| device.load_state(r)),
+                None => Ok(()),
+            }
+        }
+        read_device(r, b"PPI ", &mut self.ppi)?;
+        read_device(r, b"A0  ", &mut self.a0)?;
+        read_device(r, b"PIT ", &mut self.pit)?;
+        read_device(r, b"DMA1", &mut self.dma1)?;
+        read_device(r, b"DMA2", &mut self.dma2)?;
+        read_device(r, b"PIC1", &mut self.pic1)?;
+        read_device(r, b"PIC2", &mut self.pic2)?;
+        read_device(r, b"KBD ", &mut self.keyboard)?;
+        read_device(r, b"FDC ", &mut self.fdc)?;
+        read_device(r, b"SER ", &mut self.serial)?;
+        read_device(r, b"LPT ", &mut self.parallel)?;
+        read_device(r, b"MOUS", &mut self.mouse)?;
+        read_device(r, b"GAME", &mut self.game_port)?;
+        read_device(r, b"KBC ", &mut self.kbc)?;
+        read_device(r, b"HDC ", &mut self.hdc)?;
+        read_device(r, b"XIDE", &mut self.xtide)?;
+        read_device(r, b"ST01", &mut self.st01)?;
+        read_device(r, b"MODM", &mut self.modem)?;
+        read_device(r, b"CASS", &mut self.cassette)?;
+        read_device(r, b"BMOU", &mut self.bus_mouse)?;
+        read_device(r, b"PS2M", &mut self.ps2_mouse)?;
+        read_device(r, b"EMS ", &mut self.ems)?;
+        read_device(r, b"EEMS", &mut self.eems)?;
+        read_device(r, b"CART", &mut self.cart_slot)?;
+        read_device(r, b"RTC ", &mut self.rtc)?;
+        read_device(r, b"NIC ", &mut self.nic)?;
+        read_device(r, b"MPU ", &mut self.mpu401)?;
+        #[cfg(feature = "sound")]
+        for device in self.sound_devices.iter_mut() {
+            r.read_section(b"SND ", |r| device.load_state(r))?;
+        }
+        // The EEMS board's windows may have moved.
+        self.update_eems_mapping();
+
+        for vid in self.videocard_ids.clone() {
+            if let Some(mut card) = self.video_mut(&vid) {
+                r.read_section(b"VID ", |r| card.load_state(r))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{devices::rtc::RTC_AT_PORT, machine_config::get_machine_descriptor};
+
+    #[test]
+    fn test_rtc_nmi_mask() {
+        let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
+        let mut bus = BusInterface::default();
+        bus.machine_desc = get_machine_descriptor(MachineType::Ibm5160).copied();
+        bus.rtc = Some(Rtc::new(None, None, true));
+        assert!(bus.nmi_enabled());
+
+        // Setting bit 7 of the AT RTC's index register masks NMI.
+        bus.rtc.as_mut().unwrap().write_u8(RTC_AT_PORT, 0x8D, None, nul_delta);
+        assert!(!bus.nmi_enabled());
+        bus.rtc.as_mut().unwrap().write_u8(RTC_AT_PORT, 0x0D, None, nul_delta);
+        assert!(bus.nmi_enabled());
+
+        // An XT clock card has no NMI mask.
+        bus.rtc = Some(Rtc::new(Some(RTC_AT_PORT), None, false));
+        bus.rtc.as_mut().unwrap().write_u8(RTC_AT_PORT, 0x8D, None, nul_delta);
+        assert!(bus.nmi_enabled());
+    }
+
+    #[test]
+    fn test_port_a0_owner() {
+        // With a single PIC, the XT's NMI mask register decodes port A0h.
+        let mut bus = BusInterface::default();
+        bus.install_a0_register(A0Type::PCXT);
+        bus.install_pics(PicType::Single);
+        assert!(matches!(bus.io_map.get(&0xA0), Some(IoDeviceType::A0Register)));
+        assert!(bus.pic2.is_none());
+
+        // A secondary PIC replaces the XT's NMI mask register.
+        let mut bus = BusInterface::default();
+        bus.install_a0_register(A0Type::PCXT);
+        bus.install_pics(PicType::Chained);
+        assert!(matches!(bus.io_map.get(&0xA0), Some(IoDeviceType::PicSecondary)));
+        assert!(matches!(bus.io_map.get(&0xA1), Some(IoDeviceType::PicSecondary)));
+        assert!(bus.a0.is_none());
+        assert!(bus.pic2.is_some());
+
+        // The PCjr's A0 register also controls its keyboard NMI, so it keeps port A0h.
+        let mut bus = BusInterface::default();
+        bus.install_a0_register(A0Type::PCJr);
+        bus.install_pics(PicType::Chained);
+        assert!(matches!(bus.io_map.get(&0xA0), Some(IoDeviceType::A0Register)));
+        assert!(bus.a0.is_some());
+        assert!(bus.pic2.is_none());
+    }
 }
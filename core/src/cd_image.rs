@@ -0,0 +1,471 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    cd_image.rs
+
+    Implements read-only CD-ROM images. Plain .ISO images are treated as a
+    single MODE1/2048 data track. .CUE sheets may describe any number of
+    data and audio tracks spread across one or more BINARY files.
+
+    Addresses are logical block addresses (LBAs) relative to the start of
+    track 1's INDEX 01. MSF addresses include the standard 2 second lead-in.
+
+*/
+
+use core::fmt::Display;
+use std::{
+    error::Error,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+pub const CD_FRAMES_PER_SECOND: u32 = 75;
+pub const CD_LEAD_IN_FRAMES: u32 = 150;
+pub const CD_DATA_SECTOR_SIZE: usize = 2048;
+pub const CD_RAW_SECTOR_SIZE: usize = 2352;
+pub const CD_LEADOUT_TRACK: u8 = 0xAA;
+
+#[derive(Debug)]
+pub enum CdImageError {
+    InvalidLength,
+    InvalidCueSheet(String),
+    UnsupportedFileType(String),
+    UnsupportedTrackType(String),
+    NoTracks,
+    NotDataTrack,
+    InvalidAddress,
+}
+impl Error for CdImageError {}
+impl Display for CdImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CdImageError::InvalidLength => write!(f, "The image file is not a multiple of the sector size."),
+            CdImageError::InvalidCueSheet(line) => write!(f, "Invalid cue sheet entry: {}", line),
+            CdImageError::UnsupportedFileType(t) => write!(f, "Unsupported cue sheet file type: {}", t),
+            CdImageError::UnsupportedTrackType(t) => write!(f, "Unsupported cue sheet track type: {}", t),
+            CdImageError::NoTracks => write!(f, "The image contains no tracks."),
+            CdImageError::NotDataTrack => write!(f, "The requested sector is not in a data track."),
+            CdImageError::InvalidAddress => write!(f, "The requested sector is outside of the image."),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CdTrackType {
+    Mode1,
+    Mode1Raw,
+    Mode2Raw,
+    Audio,
+}
+
+impl CdTrackType {
+    pub fn sector_size(&self) -> usize {
+        match self {
+            CdTrackType::Mode1 => CD_DATA_SECTOR_SIZE,
+            _ => CD_RAW_SECTOR_SIZE,
+        }
+    }
+
+    /// Offset of the user data within a sector. Mode 2 sectors are assumed to be XA Form 1.
+    fn data_offset(&self) -> usize {
+        match self {
+            CdTrackType::Mode1 | CdTrackType::Audio => 0,
+            CdTrackType::Mode1Raw => 16,
+            CdTrackType::Mode2Raw => 24,
+        }
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(self, CdTrackType::Audio)
+    }
+
+    /// The control nibble reported in the TOC and Q sub-channel for this track type.
+    pub fn control(&self) -> u8 {
+        match self {
+            CdTrackType::Audio => 0x00,
+            _ => 0x04,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CdTrack {
+    pub number: u8,
+    pub track_type: CdTrackType,
+    /// LBA of INDEX 01
+    pub start: u32,
+    /// Length in frames, not including any pregap
+    pub length: u32,
+    file: usize,
+    file_offset: u64,
+}
+
+impl CdTrack {
+    pub fn contains(&self, lba: u32) -> bool {
+        lba >= self.start && lba < self.start + self.length
+    }
+}
+
+pub struct CdImage {
+    path:    PathBuf,
+    files:   Vec<File>,
+    tracks:  Vec<CdTrack>,
+    leadout: u32,
+}
+
+/// Convert an LBA to a (minute, second, frame) address, including the lead-in.
+pub fn lba_to_msf(lba: u32) -> (u8, u8, u8) {
+    let frames = lba + CD_LEAD_IN_FRAMES;
+    (
+        (frames / (CD_FRAMES_PER_SECOND * 60)) as u8,
+        ((frames / CD_FRAMES_PER_SECOND) % 60) as u8,
+        (frames % CD_FRAMES_PER_SECOND) as u8,
+    )
+}
+
+/// Convert a (minute, second, frame) address to an LBA. Addresses within the lead-in return None.
+pub fn msf_to_lba(m: u8, s: u8, f: u8) -> Option<u32> {
+    let frames = (m as u32 * 60 + s as u32) * CD_FRAMES_PER_SECOND + f as u32;
+    frames.checked_sub(CD_LEAD_IN_FRAMES)
+}
+
+/// Parse a cue sheet mm:ss:ff timestamp into a frame count. Cue sheet timestamps have no lead-in.
+fn parse_cue_time(s: &str) -> Option<u32> {
+    let mut parts = s.split(':').map(|p| p.parse::<u32>().ok());
+    let m = parts.next()??;
+    let sec = parts.next()??;
+    let f = parts.next()??;
+    if parts.next().is_some() || sec >= 60 || f >= CD_FRAMES_PER_SECOND {
+        return None;
+    }
+    Some((m * 60 + sec) * CD_FRAMES_PER_SECOND + f)
+}
+
+/// Split a cue sheet line into tokens, keeping quoted strings intact.
+fn tokenize_cue_line(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+// A track as described by the cue sheet, before being resolved to absolute addresses.
+struct CueTrack {
+    number: u8,
+    track_type: CdTrackType,
+    file: usize,
+    index1: Option<u32>,
+    pregap: u32,
+    postgap: u32,
+}
+
+impl CdImage {
+    /// Open a CD image, selecting the image format by file extension.
+    pub fn open(path: &Path) -> Result<CdImage> {
+        let is_cue = path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("cue"))
+            .unwrap_or(false);
+
+        if is_cue {
+            let cue = std::fs::read_to_string(path).with_context(|| format!("Reading cue sheet {:?}", path))?;
+            let base_dir = path.parent().unwrap_or(Path::new("."));
+            CdImage::from_cue(path, &cue, base_dir)
+        }
+        else {
+            CdImage::from_iso(path)
+        }
+    }
+
+    pub fn from_iso(path: &Path) -> Result<CdImage> {
+        let file = File::open(path).with_context(|| format!("Opening ISO image {:?}", path))?;
+        let len = file.metadata()?.len();
+        if len == 0 || len % CD_DATA_SECTOR_SIZE as u64 != 0 {
+            bail!(CdImageError::InvalidLength);
+        }
+
+        let length = (len / CD_DATA_SECTOR_SIZE as u64) as u32;
+        Ok(CdImage {
+            path:    path.to_path_buf(),
+            files:   vec![file],
+            tracks:  vec![CdTrack {
+                number: 1,
+                track_type: CdTrackType::Mode1,
+                start: 0,
+                length,
+                file: 0,
+                file_offset: 0,
+            }],
+            leadout: length,
+        })
+    }
+
+    /// Build an image from the text of a cue sheet. FILE entries are resolved relative to `base_dir`.
+    pub fn from_cue(path: &Path, cue: &str, base_dir: &Path) -> Result<CdImage> {
+        let mut files: Vec<File> = Vec::new();
+        let mut cue_tracks: Vec<CueTrack> = Vec::new();
+
+        for line in cue.lines() {
+            let tokens = tokenize_cue_line(line);
+            if tokens.is_empty() {
+                continue;
+            }
+            let bad_line = || CdImageError::InvalidCueSheet(line.trim().to_string());
+
+            match tokens[0].to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    if tokens.len() < 3 {
+                        bail!(bad_line());
+                    }
+                    if !tokens[2].eq_ignore_ascii_case("BINARY") {
+                        bail!(CdImageError::UnsupportedFileType(tokens[2].clone()));
+                    }
+                    let file_path = base_dir.join(&tokens[1]);
+                    let file = File::open(&file_path).with_context(|| format!("Opening track file {:?}", file_path))?;
+                    files.push(file);
+                }
+                "TRACK" => {
+                    if tokens.len() < 3 || files.is_empty() {
+                        bail!(bad_line());
+                    }
+                    let number = tokens[1].parse::<u8>().map_err(|_| bad_line())?;
+                    let track_type = match tokens[2].to_ascii_uppercase().as_str() {
+                        "MODE1/2048" => CdTrackType::Mode1,
+                        "MODE1/2352" => CdTrackType::Mode1Raw,
+                        "MODE2/2352" => CdTrackType::Mode2Raw,
+                        "AUDIO" => CdTrackType::Audio,
+                        _ => bail!(CdImageError::UnsupportedTrackType(tokens[2].clone())),
+                    };
+                    cue_tracks.push(CueTrack {
+                        number,
+                        track_type,
+                        file: files.len() - 1,
+                        index1: None,
+                        pregap: 0,
+                        postgap: 0,
+                    });
+                }
+                "INDEX" => {
+                    if tokens.len() < 3 {
+                        bail!(bad_line());
+                    }
+                    let track = cue_tracks.last_mut().ok_or_else(bad_line)?;
+                    let time = parse_cue_time(&tokens[2]).ok_or_else(bad_line)?;
+                    // INDEX 00 marks a pregap stored in the file, which belongs to the preceding track's data.
+                    if tokens[1].parse::<u8>().map_err(|_| bad_line())? == 1 {
+                        track.index1 = Some(time);
+                    }
+                }
+                "PREGAP" | "POSTGAP" => {
+                    if tokens.len() < 2 {
+                        bail!(bad_line());
+                    }
+                    let is_pregap = tokens[0].eq_ignore_ascii_case("PREGAP");
+                    let track = cue_tracks.last_mut().ok_or_else(bad_line)?;
+                    let time = parse_cue_time(&tokens[1]).ok_or_else(bad_line)?;
+                    if is_pregap {
+                        track.pregap = time;
+                    }
+                    else {
+                        track.postgap = time;
+                    }
+                }
+                _ => {
+                    // CATALOG, TITLE, PERFORMER, REM, FLAGS, etc. don't affect the layout.
+                }
+            }
+        }
+
+        if cue_tracks.is_empty() {
+            bail!(CdImageError::NoTracks);
+        }
+
+        let mut tracks: Vec<CdTrack> = Vec::with_capacity(cue_tracks.len());
+        // Absolute LBA of the start of the current file, not counting gaps.
+        let mut file_lba = 0;
+        // Accumulated gaps that aren't stored in any file.
+        let mut gap_frames = 0;
+
+        for (i, cue_track) in cue_tracks.iter().enumerate() {
+            let index1 = cue_track
+                .index1
+                .ok_or_else(|| CdImageError::InvalidCueSheet(format!("TRACK {} has no INDEX 01", cue_track.number)))?;
+
+            let file_offset = if i > 0 && cue_tracks[i - 1].file == cue_track.file {
+                let prev = &cue_tracks[i - 1];
+                let prev_index1 = prev.index1.unwrap();
+                if index1 < prev_index1 {
+                    bail!(CdImageError::InvalidCueSheet(format!(
+                        "TRACK {} starts before the previous track",
+                        cue_track.number
+                    )));
+                }
+                // The previous track ends where this one begins, including any stored INDEX 00 gap.
+                tracks[i - 1].length = index1 - prev_index1;
+                tracks[i - 1].file_offset + (index1 - prev_index1) as u64 * prev.track_type.sector_size() as u64
+            }
+            else {
+                if i > 0 {
+                    file_lba += cue_tracks[i - 1].index1.unwrap() + tracks[i - 1].length;
+                }
+                index1 as u64 * cue_track.track_type.sector_size() as u64
+            };
+
+            gap_frames += cue_track.pregap;
+            tracks.push(CdTrack {
+                number: cue_track.number,
+                track_type: cue_track.track_type,
+                start: file_lba + gap_frames + index1,
+                length: 0,
+                file: cue_track.file,
+                file_offset,
+            });
+            gap_frames += cue_track.postgap;
+
+            if i + 1 == cue_tracks.len() || cue_tracks[i + 1].file != cue_track.file {
+                // The last track in a file extends to the end of the file.
+                let file_len = files[cue_track.file].metadata()?.len();
+                let track = tracks.last_mut().unwrap();
+                if file_len < track.file_offset {
+                    bail!(CdImageError::InvalidLength);
+                }
+                track.length = ((file_len - track.file_offset) / track.track_type.sector_size() as u64) as u32;
+            }
+        }
+
+        let last = tracks.last().unwrap();
+        let leadout = last.start + last.length + cue_tracks.last().unwrap().postgap;
+
+        Ok(CdImage {
+            path: path.to_path_buf(),
+            files,
+            tracks,
+            leadout,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return a description that identifies the disc: its file name and layout.
+    pub fn identity(&self) -> String {
+        format!(
+            "{} tracks:{} leadout:{}",
+            self.path.file_name().unwrap_or_default().to_string_lossy(),
+            self.tracks.len(),
+            self.leadout
+        )
+    }
+
+    pub fn tracks(&self) -> &[CdTrack] {
+        &self.tracks
+    }
+
+    /// The LBA of the lead-out, which is also the total number of addressable frames.
+    pub fn leadout(&self) -> u32 {
+        self.leadout
+    }
+
+    pub fn track_at(&self, lba: u32) -> Option<&CdTrack> {
+        self.tracks.iter().find(|t| t.contains(lba))
+    }
+
+    /// Return the last track starting at or before the specified LBA. Unlike track_at(), this
+    /// resolves addresses within a pregap to the preceding track.
+    pub fn track_for(&self, lba: u32) -> Option<&CdTrack> {
+        self.tracks
+            .iter()
+            .rev()
+            .find(|t| t.start <= lba)
+            .or(self.tracks.first())
+    }
+
+    fn read_at(&mut self, track_idx: usize, lba: u32, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let track = &self.tracks[track_idx];
+        let pos =
+            track.file_offset + (lba - track.start) as u64 * track.track_type.sector_size() as u64 + offset as u64;
+        let file = &mut self.files[track.file];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+
+    /// Read the 2048 bytes of user data of the sector at the specified LBA.
+    pub fn read_data_sector(&mut self, buf: &mut [u8], lba: u32) -> Result<()> {
+        let track_idx = match self.tracks.iter().position(|t| t.contains(lba)) {
+            Some(idx) => idx,
+            None => bail!(CdImageError::InvalidAddress),
+        };
+        let track_type = self.tracks[track_idx].track_type;
+        if track_type.is_audio() {
+            bail!(CdImageError::NotDataTrack);
+        }
+        self.read_at(
+            track_idx,
+            lba,
+            track_type.data_offset(),
+            &mut buf[..CD_DATA_SECTOR_SIZE],
+        )
+    }
+
+    /// Read a 2352 byte frame of 16-bit stereo audio at the specified LBA. Frames within gaps
+    /// that are not stored in the image read as silence.
+    pub fn read_audio_frame(&mut self, buf: &mut [u8], lba: u32) -> Result<()> {
+        let buf = &mut buf[..CD_RAW_SECTOR_SIZE];
+        match self.tracks.iter().position(|t| t.contains(lba)) {
+            Some(track_idx) => {
+                if !self.tracks[track_idx].track_type.is_audio() {
+                    bail!(CdImageError::NotDataTrack);
+                }
+                self.read_at(track_idx, lba, 0, buf)
+            }
+            None if lba < self.leadout => {
+                buf.fill(0);
+                Ok(())
+            }
+            None => bail!(CdImageError::InvalidAddress),
+        }
+    }
+}
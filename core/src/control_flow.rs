@@ -0,0 +1,361 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    control_flow.rs
+
+    Implements a recorder for dynamic control flow. Each branch, call,
+    return and interrupt observed during execution is recorded as an edge
+    between two segmented addresses. The resulting graph can be exported as
+    Graphviz DOT or JSON for use in external tools.
+
+*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Error;
+
+use crate::cpu_common::{calc_linear_address, Mnemonic};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ControlFlowEdgeKind {
+    Jump,
+    Branch,
+    Fallthrough,
+    Call,
+    Return,
+    Interrupt,
+    InterruptReturn,
+    HwInterrupt,
+}
+
+impl ControlFlowEdgeKind {
+    /// Classify an executed instruction into an edge kind. `taken` should be true if execution did not
+    /// continue at the next sequential instruction.
+    pub fn from_mnemonic(mnemonic: Mnemonic, taken: bool) -> Option<ControlFlowEdgeKind> {
+        match mnemonic {
+            Mnemonic::JMP | Mnemonic::JMPF => Some(ControlFlowEdgeKind::Jump),
+            Mnemonic::JB
+            | Mnemonic::JBE
+            | Mnemonic::JCXZ
+            | Mnemonic::JL
+            | Mnemonic::JLE
+            | Mnemonic::JNB
+            | Mnemonic::JNBE
+            | Mnemonic::JNL
+            | Mnemonic::JNLE
+            | Mnemonic::JNO
+            | Mnemonic::JNP
+            | Mnemonic::JNS
+            | Mnemonic::JNZ
+            | Mnemonic::JO
+            | Mnemonic::JP
+            | Mnemonic::JS
+            | Mnemonic::JZ
+            | Mnemonic::LOOP
+            | Mnemonic::LOOPNE
+            | Mnemonic::LOOPE => match taken {
+                true => Some(ControlFlowEdgeKind::Branch),
+                false => Some(ControlFlowEdgeKind::Fallthrough),
+            },
+            Mnemonic::CALL | Mnemonic::CALLF => Some(ControlFlowEdgeKind::Call),
+            Mnemonic::RETN | Mnemonic::RETF => Some(ControlFlowEdgeKind::Return),
+            Mnemonic::INT | Mnemonic::INT3 | Mnemonic::INTO if taken => Some(ControlFlowEdgeKind::Interrupt),
+            Mnemonic::IRET => Some(ControlFlowEdgeKind::InterruptReturn),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ControlFlowEdgeKind::Jump => "jump",
+            ControlFlowEdgeKind::Branch => "branch",
+            ControlFlowEdgeKind::Fallthrough => "fallthrough",
+            ControlFlowEdgeKind::Call => "call",
+            ControlFlowEdgeKind::Return => "return",
+            ControlFlowEdgeKind::Interrupt => "interrupt",
+            ControlFlowEdgeKind::InterruptReturn => "iret",
+            ControlFlowEdgeKind::HwInterrupt => "hw_interrupt",
+        }
+    }
+
+    fn dot_style(&self) -> &'static str {
+        match self {
+            ControlFlowEdgeKind::Jump => "color=black",
+            ControlFlowEdgeKind::Branch => "color=darkgreen",
+            ControlFlowEdgeKind::Fallthrough => "color=gray50, style=dashed",
+            ControlFlowEdgeKind::Call => "color=blue, penwidth=2",
+            ControlFlowEdgeKind::Return => "color=blue, style=dotted",
+            ControlFlowEdgeKind::Interrupt => "color=red, penwidth=2",
+            ControlFlowEdgeKind::InterruptReturn => "color=red, style=dotted",
+            ControlFlowEdgeKind::HwInterrupt => "color=orange, style=dashed",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ControlFlowNode {
+    pub cs: u16,
+    pub ip: u16,
+}
+
+impl ControlFlowNode {
+    pub fn new(cs: u16, ip: u16) -> Self {
+        Self { cs, ip }
+    }
+
+    pub fn flat(&self) -> u32 {
+        calc_linear_address(self.cs, self.ip)
+    }
+
+    fn dot_id(&self) -> String {
+        format!("n{:04X}_{:04X}", self.cs, self.ip)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ControlFlowEdge {
+    pub from: ControlFlowNode,
+    pub to:   ControlFlowNode,
+    pub kind: ControlFlowEdgeKind,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ControlFlowFormat {
+    #[default]
+    Dot,
+    Json,
+}
+
+impl ControlFlowFormat {
+    /// Select an export format from a filename's extension. Unrecognized extensions use DOT.
+    pub fn from_path(path: &Path) -> ControlFlowFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ControlFlowFormat::Json,
+            _ => ControlFlowFormat::Dot,
+        }
+    }
+}
+
+/// Records dynamic control flow edges and the targets of calls and interrupts.
+#[derive(Default)]
+pub struct ControlFlowRecorder {
+    edges: BTreeMap<ControlFlowEdge, u64>,
+    call_targets: BTreeSet<ControlFlowNode>,
+    interrupt_targets: BTreeMap<ControlFlowNode, BTreeSet<u8>>,
+}
+
+impl ControlFlowRecorder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.edges.clear();
+        self.call_targets.clear();
+        self.interrupt_targets.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    pub fn edge_ct(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Record a single control flow edge. Repeated edges increment a visit count.
+    pub fn record(&mut self, from: ControlFlowNode, to: ControlFlowNode, kind: ControlFlowEdgeKind) {
+        if let ControlFlowEdgeKind::Call = kind {
+            self.call_targets.insert(to);
+        }
+        *self.edges.entry(ControlFlowEdge { from, to, kind }).or_insert(0) += 1;
+    }
+
+    /// Record an interrupt entry, remembering the vector number that led to the handler if known.
+    pub fn record_interrupt(&mut self, from: ControlFlowNode, to: ControlFlowNode, vector: Option<u8>, hw: bool) {
+        let vectors = self.interrupt_targets.entry(to).or_default();
+        if let Some(vector) = vector {
+            vectors.insert(vector);
+        }
+        let kind = match hw {
+            true => ControlFlowEdgeKind::HwInterrupt,
+            false => ControlFlowEdgeKind::Interrupt,
+        };
+        *self.edges.entry(ControlFlowEdge { from, to, kind }).or_insert(0) += 1;
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (&ControlFlowEdge, &u64)> {
+        self.edges.iter()
+    }
+
+    pub fn call_targets(&self) -> &BTreeSet<ControlFlowNode> {
+        &self.call_targets
+    }
+
+    pub fn interrupt_targets(&self) -> &BTreeMap<ControlFlowNode, BTreeSet<u8>> {
+        &self.interrupt_targets
+    }
+
+    /// Return the set of all nodes that appear as an edge source or destination.
+    pub fn nodes(&self) -> BTreeSet<ControlFlowNode> {
+        let mut nodes = BTreeSet::new();
+        for edge in self.edges.keys() {
+            nodes.insert(edge.from);
+            nodes.insert(edge.to);
+        }
+        nodes
+    }
+
+    /// Export the recorded graph to the specified file, using the given format.
+    pub fn export(&self, path: &Path, format: ControlFlowFormat) -> Result<(), Error> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        match format {
+            ControlFlowFormat::Dot => self.write_dot(&mut writer)?,
+            ControlFlowFormat::Json => self.write_json(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_dot<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        writeln!(w, "digraph control_flow {{")?;
+        writeln!(w, "    node [shape=box, fontname=\"Courier\"];")?;
+
+        for node in self.nodes() {
+            let mut attrs = String::new();
+            if self.call_targets.contains(&node) {
+                attrs.push_str(", style=filled, fillcolor=lightblue");
+            }
+            else if self.interrupt_targets.contains_key(&node) {
+                attrs.push_str(", style=filled, fillcolor=lightpink");
+            }
+            let mut label = format!("{:04X}:{:04X}", node.cs, node.ip);
+            if let Some(vectors) = self.interrupt_targets.get(&node) {
+                for v in vectors {
+                    label.push_str(&format!("\\nINT {:02X}h", v));
+                }
+            }
+            writeln!(w, "    {} [label=\"{}\"{}];", node.dot_id(), label, attrs)?;
+        }
+
+        for (edge, count) in self.edges.iter() {
+            writeln!(
+                w,
+                "    {} -> {} [{}, label=\"{}\"];",
+                edge.from.dot_id(),
+                edge.to.dot_id(),
+                edge.kind.dot_style(),
+                count
+            )?;
+        }
+
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        writeln!(w, "{{")?;
+
+        writeln!(w, "  \"nodes\": [")?;
+        let nodes = self.nodes();
+        for (i, node) in nodes.iter().enumerate() {
+            let vectors = self
+                .interrupt_targets
+                .get(node)
+                .map(|v| v.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "))
+                .unwrap_or_default();
+            writeln!(
+                w,
+                "    {{ \"cs\": {}, \"ip\": {}, \"flat\": {}, \"call_target\": {}, \"interrupt_vectors\": [{}] }}{}",
+                node.cs,
+                node.ip,
+                node.flat(),
+                self.call_targets.contains(node),
+                vectors,
+                if i + 1 < nodes.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(w, "  ],")?;
+
+        writeln!(w, "  \"edges\": [")?;
+        let edge_ct = self.edges.len();
+        for (i, (edge, count)) in self.edges.iter().enumerate() {
+            writeln!(
+                w,
+                "    {{ \"from\": \"{:04X}:{:04X}\", \"to\": \"{:04X}:{:04X}\", \"kind\": \"{}\", \"count\": {} }}{}",
+                edge.from.cs,
+                edge.from.ip,
+                edge.to.cs,
+                edge.to.ip,
+                edge.kind.as_str(),
+                count,
+                if i + 1 < edge_ct { "," } else { "" }
+            )?;
+        }
+        writeln!(w, "  ]")?;
+
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_record_and_export() {
+        let mut cfg = ControlFlowRecorder::new();
+        let a = ControlFlowNode::new(0xF000, 0xE05B);
+        let b = ControlFlowNode::new(0xF000, 0x1000);
+
+        cfg.record(a, b, ControlFlowEdgeKind::Call);
+        cfg.record(a, b, ControlFlowEdgeKind::Call);
+        cfg.record_interrupt(b, a, Some(0x10), false);
+
+        assert_eq!(cfg.edge_ct(), 2);
+        assert!(cfg.call_targets().contains(&b));
+        assert!(cfg.interrupt_targets()[&a].contains(&0x10));
+
+        let mut dot = Vec::new();
+        cfg.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("nF000_E05B -> nF000_1000"));
+        assert!(dot.contains("label=\"2\""));
+
+        assert_eq!(
+            ControlFlowEdgeKind::from_mnemonic(Mnemonic::JNZ, false),
+            Some(ControlFlowEdgeKind::Fallthrough)
+        );
+        assert_eq!(ControlFlowEdgeKind::from_mnemonic(Mnemonic::MOV, true), None);
+    }
+}
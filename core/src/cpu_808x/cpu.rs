@@ -35,6 +35,7 @@ use crate::{
     bus::BusInterface,
     cpu_808x::{Intel808x, Register16},
     cpu_common::{Cpu, CpuAddress, CpuError, CpuOption, CpuStringState, CpuType, ServiceEvent, StepResult},
+    savestate::{SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
 };
 
@@ -208,6 +209,14 @@ impl Cpu for Intel808x {
         self.get_string_state()
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
+
     fn eval_address(&self, expr: &str) -> Option<CpuAddress> {
         self.eval_address(expr)
     }
@@ -299,6 +308,10 @@ impl Cpu for Intel808x {
                 log::debug!("Setting EnableServiceInterrupt to: {:?}", state);
                 self.enable_service_interrupt = state;
             }
+            CpuOption::DmaCascade(state) => {
+                log::debug!("Setting DmaCascade to: {:?}", state);
+                self.dma_cascade = state;
+            }
         }
     }
 
@@ -313,6 +326,7 @@ impl Cpu for Intel808x {
             CpuOption::EnableWaitStates(_) => self.enable_wait_states,
             CpuOption::TraceLoggingEnabled(_) => self.trace_enabled,
             CpuOption::EnableServiceInterrupt(_) => self.enable_service_interrupt,
+            CpuOption::DmaCascade(_) => self.dma_cascade,
         }
     }
 
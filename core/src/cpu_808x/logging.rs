@@ -328,6 +328,7 @@ impl Intel808x {
             DmaState::Idle => dma_count_str,
             DmaState::Dreq => "DREQ",
             DmaState::Hrq => "HRQ ",
+            DmaState::Cascade => "CASC",
             DmaState::HoldA => "HLDA",
             DmaState::Operating(n) => match n {
                 0 => "S1",
@@ -571,6 +572,7 @@ impl Intel808x {
             DmaState::Idle => dma_count_str,
             DmaState::Dreq => "DREQ",
             DmaState::Hrq => "HRQ ",
+            DmaState::Cascade => "CASC",
             DmaState::HoldA => "HLDA",
             DmaState::Operating(n) => match n {
                 4 => "S1",
@@ -71,6 +71,7 @@ pub mod mnemonic;
 mod modrm;
 mod muldiv;
 mod queue;
+mod savestate;
 mod stack;
 mod step;
 mod string;
@@ -353,6 +354,7 @@ pub enum DmaState {
     Idle,
     Dreq,
     Hrq,
+    Cascade,
     HoldA,
     Operating(u8),
     End,
@@ -644,6 +646,7 @@ pub struct Intel808x {
     dram_refresh_adjust: u32,
     dram_refresh_tc: bool,
     dram_refresh_retrigger: bool,
+    dma_cascade: bool,
     dma_aen: bool,
     dma_holda: bool,
     dma_req: bool,
@@ -884,6 +887,7 @@ impl Intel808x {
             // Save options
             instruction_history_on: self.instruction_history_on,
             dram_refresh_simulation: self.dram_refresh_simulation,
+            dma_cascade: self.dma_cascade,
             halt_resume_delay: self.halt_resume_delay,
             off_rails_detection: self.off_rails_detection,
             enable_wait_states: self.enable_wait_states,
@@ -348,12 +348,11 @@ impl Intel808x {
                 // This is a bit artificial as we don't actually read the IV during the 2nd
                 // INTA cycle like the CPU does, instead we save the value now and simulate it later.
                 // TODO: Think about changing this to query during INTA
-                if let Some(pic) = self.bus.pic_mut().as_mut() {
-                    // Is INTR active? TODO: Could combine these calls (return Option<iv>) on query?
-                    if pic.query_interrupt_line() {
-                        if let Some(iv) = pic.get_interrupt_vector() {
-                            irq = iv;
-                        }
+                // The bus handles cascade sequencing if a secondary PIC is present.
+                // Is INTR active? TODO: Could combine these calls (return Option<iv>) on query?
+                if self.bus.query_interrupt_line() {
+                    if let Some(iv) = self.bus.get_interrupt_vector() {
+                        irq = iv;
                     }
                 }
 
@@ -57,6 +57,7 @@ use crate::{
     bytequeue::ByteQueue,
     cpu_808x::Intel808x,
     cpu_vx0::NecVx0,
+    savestate::{SaveStateError, StateReader, StateWriter},
     syntax_token::{SyntaxToken, SyntaxTokenize},
 };
 
@@ -284,6 +285,7 @@ pub enum CpuOption {
     EnableWaitStates(bool),
     TraceLoggingEnabled(bool),
     EnableServiceInterrupt(bool),
+    DmaCascade(bool),
 }
 
 #[derive(Debug)]
@@ -423,6 +425,10 @@ pub trait Cpu {
 
     fn get_string_state(&self) -> CpuStringState;
 
+    // Save states
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError>;
+
     // Eval
     fn eval_address(&self, expr: &str) -> Option<CpuAddress>;
 
@@ -58,6 +58,7 @@ use crate::{
         TaCycle,
         CPU_FLAGS_RESERVED_ON,
     },
+    savestate::{SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
 };
 
@@ -84,6 +85,7 @@ impl Cpu for NecVx0 {
             // Save options
             instruction_history_on: self.instruction_history_on,
             dram_refresh_simulation: self.dram_refresh_simulation,
+            dma_cascade: self.dma_cascade,
             halt_resume_delay: self.halt_resume_delay,
             off_rails_detection: self.off_rails_detection,
             enable_wait_states: self.enable_wait_states,
@@ -380,6 +382,14 @@ impl Cpu for NecVx0 {
         self.get_string_state()
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
+
     fn eval_address(&self, expr: &str) -> Option<CpuAddress> {
         self.eval_address(expr)
     }
@@ -471,6 +481,10 @@ impl Cpu for NecVx0 {
                 log::debug!("Setting EnableServiceInterrupt to: {:?}", state);
                 self.enable_service_interrupt = state;
             }
+            CpuOption::DmaCascade(state) => {
+                log::debug!("Setting DmaCascade to: {:?}", state);
+                self.dma_cascade = state;
+            }
         }
     }
 
@@ -485,6 +499,7 @@ impl Cpu for NecVx0 {
             CpuOption::EnableWaitStates(_) => self.enable_wait_states,
             CpuOption::TraceLoggingEnabled(_) => self.trace_enabled,
             CpuOption::EnableServiceInterrupt(_) => self.enable_service_interrupt,
+            CpuOption::DmaCascade(_) => self.dma_cascade,
         }
     }
 
@@ -406,6 +406,18 @@ impl NecVx0 {
             DmaState::Dreq => {
                 // DMA request triggered on DMA controller. Next cycle, DMA controller
                 // will assert HRQ (Hold Request)
+                if self.dma_cascade {
+                    // With a cascaded DMA controller, HRQ from the first controller drives DREQ of
+                    // the second controller's cascade channel. The second controller then asserts
+                    // HRQ to the CPU on the following cycle.
+                    self.dma_state = DmaState::Cascade;
+                }
+                else {
+                    self.dma_state = DmaState::Hrq;
+                }
+            }
+            DmaState::Cascade => {
+                // Cascade channel has received HRQ from the first controller.
                 self.dma_state = DmaState::Hrq;
             }
             DmaState::Hrq => {
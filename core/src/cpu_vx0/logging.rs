@@ -309,6 +309,7 @@ impl NecVx0 {
             DmaState::Idle => dma_count_str,
             DmaState::Dreq => "DREQ",
             DmaState::Hrq => "HRQ ",
+            DmaState::Cascade => "CASC",
             DmaState::HoldA => "HLDA",
             DmaState::Operating(n) => match n {
                 0 => "S1",
@@ -531,6 +532,7 @@ impl NecVx0 {
             DmaState::Idle => dma_count_str,
             DmaState::Dreq => "DREQ",
             DmaState::Hrq => "HRQ ",
+            DmaState::Cascade => "CASC",
             DmaState::HoldA => "HLDA",
             DmaState::Operating(n) => match n {
                 4 => "S1",
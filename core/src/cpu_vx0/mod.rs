@@ -55,6 +55,7 @@ pub mod mnemonic;
 mod modrm;
 mod muldiv;
 mod queue;
+mod savestate;
 mod stack;
 mod step;
 mod string;
@@ -350,6 +351,7 @@ pub enum DmaState {
     Idle,
     Dreq,
     Hrq,
+    Cascade,
     HoldA,
     Operating(u8),
     End,
@@ -641,6 +643,7 @@ pub struct NecVx0 {
     dram_refresh_adjust: u32,
     dram_refresh_tc: bool,
     dram_refresh_retrigger: bool,
+    dma_cascade: bool,
     dma_aen: bool,
     dma_holda: bool,
     dma_req: bool,
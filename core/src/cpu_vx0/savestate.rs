@@ -0,0 +1,181 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_vx0::savestate.rs
+
+    Implements save state support for the NEC V20 and V30 CPUs.
+
+    States are taken on instruction boundaries. The instruction queue is not
+    saved; it is flushed on restore and refilled from memory, the same as
+    after a jump. A REP string instruction that is still repeating is
+    restarted from its first prefix, as it would be after an interrupt.
+
+*/
+
+use crate::{
+    cpu_vx0::*,
+    cpu_common::QueueOp,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
+
+impl NecVx0 {
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let ip = match self.in_rep {
+            true => self.disassembly_ip(),
+            false => self.ip(),
+        };
+
+        w.write_u16(self.a.x());
+        w.write_u16(self.b.x());
+        w.write_u16(self.c.x());
+        w.write_u16(self.d.x());
+        w.write_u16(self.sp);
+        w.write_u16(self.bp);
+        w.write_u16(self.si);
+        w.write_u16(self.di);
+        w.write_u16(self.cs);
+        w.write_u16(self.ds);
+        w.write_u16(self.ss);
+        w.write_u16(self.es);
+        w.write_u16(ip);
+        w.write_u16(self.flags);
+
+        w.write_bool(self.halted);
+        w.write_bool(self.intr);
+        w.write_bool(self.intr_pending && !self.in_rep);
+        w.write_bool(self.interrupt_inhibit);
+        w.write_bool(self.nmi);
+        w.write_bool(self.nmi_triggered);
+        w.write_u32(self.trap_enable_delay);
+        w.write_u32(self.trap_disable_delay);
+        w.write_bool(self.trap_suppressed);
+        w.write_u32(self.opcode0_counter);
+
+        w.write_u64(self.cycle_num);
+        w.write_u64(self.halt_cycles);
+        w.write_u64(self.instruction_count);
+        w.write_u64(self.int_count);
+        w.write_u64(self.iret_count);
+
+        w.write_bool(self.dram_refresh_simulation);
+        w.write_u32(self.dram_refresh_cycle_period);
+        w.write_u32(self.dram_refresh_cycle_num);
+        w.write_u32(self.dram_refresh_adjust);
+        w.write_bool(self.dram_refresh_tc);
+        w.write_bool(self.dram_refresh_retrigger);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.a.set_x(r.read_u16()?);
+        self.b.set_x(r.read_u16()?);
+        self.c.set_x(r.read_u16()?);
+        self.d.set_x(r.read_u16()?);
+        self.sp = r.read_u16()?;
+        self.bp = r.read_u16()?;
+        self.si = r.read_u16()?;
+        self.di = r.read_u16()?;
+        self.cs = r.read_u16()?;
+        self.ds = r.read_u16()?;
+        self.ss = r.read_u16()?;
+        self.es = r.read_u16()?;
+        self.pc = r.read_u16()?;
+        self.flags = r.read_u16()?;
+
+        self.halted = r.read_bool()?;
+        self.intr = r.read_bool()?;
+        self.intr_pending = r.read_bool()?;
+        self.interrupt_inhibit = r.read_bool()?;
+        self.nmi = r.read_bool()?;
+        self.nmi_triggered = r.read_bool()?;
+        self.trap_enable_delay = r.read_u32()?;
+        self.trap_disable_delay = r.read_u32()?;
+        self.trap_suppressed = r.read_bool()?;
+        self.opcode0_counter = r.read_u32()?;
+
+        self.cycle_num = r.read_u64()?;
+        self.halt_cycles = r.read_u64()?;
+        self.instruction_count = r.read_u64()?;
+        self.int_count = r.read_u64()?;
+        self.iret_count = r.read_u64()?;
+
+        self.dram_refresh_simulation = r.read_bool()?;
+        self.dram_refresh_cycle_period = r.read_u32()?;
+        self.dram_refresh_cycle_num = r.read_u32()?;
+        self.dram_refresh_adjust = r.read_u32()?;
+        self.dram_refresh_tc = r.read_bool()?;
+        self.dram_refresh_retrigger = r.read_bool()?;
+
+        // Return the BIU and EU to an idle state between instructions.
+        self.state = CpuState::Normal;
+        self.address_latch = 0;
+        self.bus_status = BusStatus::Passive;
+        self.bus_status_latch = BusStatus::Passive;
+        self.bus_pending = BusPendingType::None;
+        self.t_cycle = TCycle::Ti;
+        self.ta_cycle = TaCycle::Td;
+        self.pl_status = BusStatus::Passive;
+        self.pl_slot = false;
+        self.queue_op = QueueOp::Idle;
+        self.last_queue_op = QueueOp::Idle;
+        self.dma_state = DmaState::Idle;
+        self.nx = false;
+        self.rni = false;
+        self.in_rep = false;
+        self.rep_init = false;
+        self.in_int = false;
+        self.reported_halt = false;
+        self.is_error = false;
+        self.step_over_target = None;
+        self.step_over_breakpoint = None;
+        self.instruction_history.clear();
+        self.call_stack.clear();
+
+        self.i8288.ale = false;
+        self.i8288.mrdc = false;
+        self.i8288.amwc = false;
+        self.i8288.mwtc = false;
+        self.i8288.iorc = false;
+        self.i8288.aiowc = false;
+        self.i8288.iowc = false;
+
+        if self.halted {
+            // Remain halted until the next interrupt, with prefetching stopped.
+            self.queue.flush();
+            self.halt_not_hold = true;
+            self.instruction_reentrant = true;
+            self.fetch_state = FetchState::Halted;
+            self.bus_status = BusStatus::Halt;
+            self.bus_status_latch = BusStatus::Halt;
+        }
+        else {
+            self.halt_not_hold = false;
+            self.instruction_reentrant = false;
+            self.biu_queue_flush();
+        }
+
+        Ok(())
+    }
+}
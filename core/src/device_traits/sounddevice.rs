@@ -27,22 +27,107 @@
     device_traits::sounddevice.rs
 
     Defines the SoundDevice trait which any sound device (Adlib,SoundBlaster, etc)
-    must implement.
+    must implement. Sound devices are installed, run and reset uniformly by the
+    bus, and each is given its own sound source to be mixed by the frontend.
 */
 
 #[cfg(feature = "opl")]
 use crate::devices::adlib::AdLibCard;
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    devices::{dma::DMAController, sn76489::Sn76489, sound_blaster::SoundBlaster},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
+use crossbeam_channel::Sender;
 use enum_dispatch::enum_dispatch;
 
 pub type AudioSample = f32;
 
 #[enum_dispatch]
 pub enum SoundDispatch {
+    #[cfg(feature = "opl")]
     AdLibCard,
+    SoundBlaster,
+    Sn76489,
 }
 
 #[enum_dispatch(SoundDispatch)]
 pub trait SoundDevice {
-    fn run(&mut self, usec: f64);
+    /// The name of the device, used to label its sound source.
+    fn name(&self) -> &'static str;
+    /// The number of interleaved channels the device produces.
+    fn channels(&self) -> usize;
+    /// Send output samples at the specified sample rate to the sound output.
+    fn set_audio_sender(&mut self, sender: Sender<AudioSample>, sample_rate: u32);
+    /// The IRQ line the device drives, if any.
+    fn irq(&self) -> Option<u8> {
+        None
+    }
+    /// The DMA channel the device uses, if any.
+    fn dma(&self) -> Option<usize> {
+        None
+    }
+    /// Reset the device to its power-on state.
+    fn reset(&mut self);
+    /// Run the device for the specified number of microseconds, producing output samples at the
+    /// output sample rate. Devices that use DMA are passed the primary DMA controller.
+    /// Returns Some(state) when the device's IRQ line changes.
+    fn run(&mut self, dma: &mut DMAController, bus: &mut BusInterface, usec: f64) -> Option<bool>;
+}
+
+impl IoDevice for SoundDispatch {
+    fn read_u8(&mut self, port: u16, delta: DeviceRunTimeUnit) -> u8 {
+        match self {
+            #[cfg(feature = "opl")]
+            SoundDispatch::AdLibCard(adlib) => adlib.read_u8(port, delta),
+            SoundDispatch::SoundBlaster(sb) => sb.read_u8(port, delta),
+            SoundDispatch::Sn76489(sn) => sn.read_u8(port, delta),
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, bus: Option<&mut BusInterface>, delta: DeviceRunTimeUnit) {
+        match self {
+            #[cfg(feature = "opl")]
+            SoundDispatch::AdLibCard(adlib) => adlib.write_u8(port, data, bus, delta),
+            SoundDispatch::SoundBlaster(sb) => sb.write_u8(port, data, bus, delta),
+            SoundDispatch::Sn76489(sn) => sn.write_u8(port, data, bus, delta),
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        match self {
+            #[cfg(feature = "opl")]
+            SoundDispatch::AdLibCard(adlib) => adlib.port_list(),
+            SoundDispatch::SoundBlaster(sb) => sb.port_list(),
+            SoundDispatch::Sn76489(sn) => sn.port_list(),
+        }
+    }
+}
+
+/// The device's name is saved with its state, so that a state is only restored into the same
+/// kind of device.
+impl SaveState for SoundDispatch {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_str(self.name());
+        match self {
+            #[cfg(feature = "opl")]
+            SoundDispatch::AdLibCard(adlib) => adlib.save_state(w),
+            SoundDispatch::SoundBlaster(sb) => sb.save_state(w),
+            SoundDispatch::Sn76489(sn) => sn.save_state(w),
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let name = r.read_str()?;
+        if name != self.name() {
+            return Err(SaveStateError::MachineMismatch(name));
+        }
+        match self {
+            #[cfg(feature = "opl")]
+            SoundDispatch::AdLibCard(adlib) => adlib.load_state(r),
+            SoundDispatch::SoundBlaster(sb) => sb.load_state(r),
+            SoundDispatch::Sn76489(sn) => sn.load_state(r),
+        }
+    }
 }
@@ -70,7 +70,10 @@ use crate::devices::ega::EGACard;
 use crate::devices::vga::VGACard;
 use crate::devices::{cga::CGACard, mda::MDACard, tga::TGACard};
 
-use crate::devices::pic::Pic;
+use crate::{
+    devices::pic::Pic,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 use serde::Deserialize;
 use serde_derive::Serialize;
 
@@ -121,7 +124,19 @@ pub enum VideoCardSubType {
     IbmPCJr,
     Tandy1000,
     CompaqCGA,
+    ColorPlus,
     Hercules,
+    HerculesInColor,
+    Mcga,
+}
+
+/// The broadcast standard of a card's composite output. This determines the frame rate the
+/// attached monitor expects and how composite color is decoded.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum VideoStandard {
+    #[default]
+    Ntsc,
+    Pal,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
@@ -187,6 +202,81 @@ pub struct VideoCardInterface<'a> {
 pub enum VideoOption {
     DebugDraw(bool),
     EnableSnow(bool),
+    LogRegisterWrites(bool),
+}
+
+/// A register write made while the adapter was drawing a frame, along with the raster position
+/// at which it occurred.
+#[derive(Copy, Clone, Debug)]
+pub struct VideoRegisterWrite {
+    pub scanline: u32,
+    pub hdot: u32,
+    pub port: u16,
+    pub index: Option<u8>, // The selected register, for writes to an indexed register file such as the CRTC
+    pub value: u8,
+}
+
+/// The register writes made during a single frame.
+#[derive(Clone, Debug, Default)]
+pub struct VideoRegisterLog {
+    pub frame:  u64,
+    pub writes: Vec<VideoRegisterWrite>,
+}
+
+/// A register write that should pause execution when made to a video card. Each breakpoint may
+/// optionally be restricted to writes of a specific value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VideoBreakpoint {
+    ModeWrite(Option<u8>),     // Break on a write to the mode control register
+    CrtcWrite(u8, Option<u8>), // Break on a write to the specified CRTC register
+}
+
+impl VideoBreakpoint {
+    /// Return true if a write of the specified value to the mode control register triggers this breakpoint.
+    pub fn matches_mode_write(&self, value: u8) -> bool {
+        match *self {
+            VideoBreakpoint::ModeWrite(filter) => filter.map_or(true, |v| v == value),
+            _ => false,
+        }
+    }
+
+    /// Return true if a write of the specified value to the specified CRTC register triggers this breakpoint.
+    pub fn matches_crtc_write(&self, reg: u8, value: u8) -> bool {
+        match *self {
+            VideoBreakpoint::CrtcWrite(bp_reg, filter) => bp_reg == reg && filter.map_or(true, |v| v == value),
+            _ => false,
+        }
+    }
+}
+
+/// The regions of the video field observed during the last complete frame, in field coordinates.
+/// Used to visualize the raster timing of an adapter while debugging.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RasterRegions {
+    pub display:  Option<(u32, u32, u32, u32)>, // Left, top, right and bottom edges of the active display area
+    pub hblank_x: Option<u32>,                  // Horizontal position at which horizontal blanking begins
+    pub vblank_y: Option<u32>,                  // Vertical position at which vertical blanking begins
+}
+
+impl RasterRegions {
+    /// Expand the display area to include a character of the specified width at the given beam position.
+    #[inline]
+    pub fn mark_display(&mut self, x: u32, y: u32, char_w: u32) {
+        self.display = Some(match self.display {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x + char_w), y1.max(y + 1)),
+            None => (x, y, x + char_w, y + 1),
+        });
+    }
+
+    /// Record the start of a horizontal blanking period at the given horizontal beam position.
+    pub fn mark_hblank(&mut self, x: u32) {
+        self.hblank_x = Some(self.hblank_x.map_or(x, |hx| hx.min(x)));
+    }
+
+    /// Record the start of a vertical blanking period at the given vertical beam position.
+    pub fn mark_vblank(&mut self, y: u32) {
+        self.vblank_y = Some(self.vblank_y.map_or(y, |vy| vy.min(y)));
+    }
 }
 
 // This enum determines the rendering method of the given videocard device.
@@ -233,6 +323,7 @@ pub enum DisplayMode {
     Mode5LowResAltPalette,
     Mode6HiResGraphics,
     Mode7MonochromeText,
+    ModeHGCGraphics,
     Mode8LowResGraphics16,
     Mode8TGAMedResGraphics16,
     ModeATGAHighResGraphics,
@@ -245,6 +336,7 @@ pub enum DisplayMode {
     Mode11VGAHiResMono,
     Mode12VGAHiResGraphics,
     Mode13VGALowRes256,
+    ModeXVGAUnchained256,
 }
 
 pub struct CursorInfo {
@@ -262,6 +354,167 @@ pub struct FontInfo {
     pub font_data: &'static [u8],
 }
 
+/// A snapshot of the characters and attributes of a text mode screen.
+#[derive(Clone, Debug, Default)]
+pub struct TextModeScreen {
+    pub columns: usize,
+    pub rows: usize,
+    pub monochrome: bool,     // Attributes are MDA attributes (underline, intensity, reverse video)
+    pub blink_enabled: bool,  // Attribute bit 7 selects blinking instead of a bright background
+    pub cells: Vec<(u8, u8)>, // Character and attribute of each cell, in row-major order
+}
+
+impl TextModeScreen {
+    /// Build a screen from a text buffer of interleaved character and attribute bytes. `start` is
+    /// a word address as generated by the CRTC; each cell address is masked by `wrap` and doubled.
+    pub fn from_text_buffer(
+        mem: &[u8],
+        start: usize,
+        columns: usize,
+        rows: usize,
+        wrap: usize,
+        monochrome: bool,
+        blink_enabled: bool,
+    ) -> Self {
+        let cells = (0..columns * rows)
+            .map(|i| {
+                let addr = ((start + i) & wrap) << 1;
+                (mem[addr], mem[addr + 1])
+            })
+            .collect();
+
+        Self {
+            columns,
+            rows,
+            monochrome,
+            blink_enabled,
+            cells,
+        }
+    }
+
+    /// Return the cells of the specified row.
+    pub fn row(&self, row: usize) -> &[(u8, u8)] {
+        &self.cells[row * self.columns..(row + 1) * self.columns]
+    }
+}
+
+/// A set of 256 glyphs from an adapter's character generator, either from ROM or loaded into
+/// font RAM. Glyphs are stored consecutively, with one byte per glyph row (MSB is leftmost).
+#[derive(Clone, Debug)]
+pub struct CharacterSet {
+    pub name: String,
+    pub w: u32,
+    pub h: u32,
+    pub active: bool, // Whether the set is currently selected for display
+    pub data: Vec<u8>,
+}
+
+impl CharacterSet {
+    /// Build a character set from a font bitmap stored row-major, with 'span' bytes per row, as
+    /// the character ROMs of the CGA and MDA are.
+    pub fn from_row_major(name: &str, w: u32, h: u32, span: usize, font: &[u8]) -> Self {
+        let data = (0..256)
+            .flat_map(|glyph| (0..h as usize).map(move |row| font.get(row * span + glyph).copied().unwrap_or(0)))
+            .collect();
+        Self {
+            name: name.to_string(),
+            w,
+            h,
+            active: true,
+            data,
+        }
+    }
+
+    /// Build a character set from glyphs stored consecutively with 'stride' bytes per glyph,
+    /// as fonts loaded into EGA and VGA font RAM are.
+    pub fn from_glyph_major(name: &str, w: u32, h: u32, stride: usize, font: &[u8]) -> Self {
+        let data = (0..256)
+            .flat_map(|glyph| (0..h as usize).map(move |row| font.get(glyph * stride + row).copied().unwrap_or(0)))
+            .collect();
+        Self {
+            name: name.to_string(),
+            w,
+            h,
+            active: true,
+            data,
+        }
+    }
+
+    /// Return the rows of the specified glyph.
+    pub fn glyph(&self, glyph: u8) -> &[u8] {
+        let h = self.h as usize;
+        &self.data[glyph as usize * h..(glyph as usize + 1) * h]
+    }
+}
+
+/// The timing programmed into an adapter's CRTC. Horizontal values are in character clocks and
+/// vertical values are in scanlines.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CrtcTiming {
+    pub char_clock: f64,    // Character clock frequency in MHz
+    pub nominal_hfreq: f64, // Horizontal frequency expected by the adapter's monitor, in kHz
+    pub h_total: u32,
+    pub h_displayed: u32,
+    pub h_sync_start: u32,
+    pub h_sync_width: u32,
+    pub v_total: u32,
+    pub v_displayed: u32,
+    pub v_sync_start: u32,
+    pub v_sync_width: u32,
+}
+
+impl CrtcTiming {
+    /// Build timing from the first ten registers (R0-R9) of a 6845 register file.
+    pub fn from_6845(regs: &[u8; 10], char_clock: f64, nominal_hfreq: f64) -> Self {
+        let scanlines_per_row = (regs[9] & 0x1F) as u32 + 1;
+        Self {
+            char_clock,
+            nominal_hfreq,
+            h_total: regs[0] as u32 + 1,
+            h_displayed: regs[1] as u32,
+            h_sync_start: regs[2] as u32,
+            // A sync width of 0 programs the maximum width of 16 characters.
+            h_sync_width: match regs[3] & 0x0F {
+                0 => 16,
+                w => w as u32,
+            },
+            v_total: ((regs[4] & 0x7F) as u32 + 1) * scanlines_per_row + (regs[5] & 0x1F) as u32,
+            v_displayed: (regs[6] & 0x7F) as u32 * scanlines_per_row,
+            v_sync_start: (regs[7] & 0x7F) as u32 * scanlines_per_row,
+            // The 6845 has a fixed vertical sync width.
+            v_sync_width: 16,
+        }
+    }
+
+    /// Return the horizontal scan rate in kHz.
+    pub fn hfreq(&self) -> f64 {
+        self.char_clock * 1000.0 / self.h_total.max(1) as f64
+    }
+
+    /// Return the vertical refresh rate in Hz.
+    pub fn vfreq(&self) -> f64 {
+        self.hfreq() * 1000.0 / self.v_total.max(1) as f64
+    }
+}
+
+/// A color as produced by an adapter's palette logic, before conversion to RGB by the frontend.
+#[derive(Copy, Clone, Debug)]
+pub enum PaletteColor {
+    Irgb(u8),      // 4-bit IRGB color, as output by the CGA and compatible adapters
+    Rgb6(u8),      // 6-bit rgbRGB color, as output by the EGA
+    Rgba([u8; 4]), // Direct color, as from the VGA DAC or InColor palette
+}
+
+/// The current state of an adapter's color logic.
+#[derive(Clone, Debug, Default)]
+pub struct PaletteState {
+    pub palette: Vec<(u8, PaletteColor)>, // Palette entries as (register value, resolved color)
+    pub overscan: Option<PaletteColor>,   // Overscan (border) color, if the adapter has one
+    pub blink_enabled: bool,              // Whether attribute bit 7 selects blinking instead of intensity
+    pub blink_state: bool,                // Current phase of the blink cycle
+    pub dac: Vec<[u8; 4]>,                // DAC entries, if the adapter has a DAC
+}
+
 pub enum CGAPalette {
     Monochrome(CGAColor),
     MagentaCyanWhite(CGAColor),
@@ -340,6 +593,7 @@ pub struct DisplayExtents {
     pub row_stride: usize,               // Number of bytes in frame buffer to skip to reach next row
     pub double_scan: bool,               // Whether the display should be double-scanned when RGBA converted
     pub mode_byte: u8,                   // Mode byte. Used by CGA modes only.
+    pub standard: VideoStandard,         // Composite video standard. Used by CGA modes only.
 }
 
 pub trait VideoCard {
@@ -361,8 +615,9 @@ pub trait VideoCard {
     /// Override the clocking mode for the adapter.
     fn set_clocking_mode(&mut self, mode: ClockingMode);
 
-    /// Returns a slice of u8 representing video memory
-    //fn get_vram(&self) -> &[u8];
+    /// Returns a slice of u8 representing video memory, or None if the adapter has no linear
+    /// video memory of its own (planar adapters, or adapters that share system memory).
+    fn get_vram(&self) -> Option<&[u8]>;
 
     /// Return the size (width, height) of the last rendered frame.
     fn get_display_size(&self) -> (u32, u32);
@@ -393,6 +648,25 @@ pub trait VideoCard {
     /// Get the position of the CRT beam (Direct rendering only)
     fn get_beam_pos(&self) -> Option<(u32, u32)>;
 
+    /// Set the position of the light pen in raster coordinates, or None if the light pen is not
+    /// pressed against the screen.
+    fn set_light_pen(&mut self, pos: Option<(u32, u32)>);
+
+    /// Return the register writes made during the last complete frame, or None if register
+    /// write logging is disabled or unsupported by the adapter.
+    fn get_register_log(&self) -> Option<&VideoRegisterLog>;
+
+    /// Set the list of register writes that should trigger a breakpoint, replacing any previous list.
+    fn set_breakpoints(&mut self, bp_list: Vec<VideoBreakpoint>);
+
+    /// Return true if a register write has triggered a breakpoint since the last call, clearing
+    /// the breakpoint flag.
+    fn take_breakpoint_hit(&mut self) -> bool;
+
+    /// Return the display and blanking regions observed during the last complete frame, or None
+    /// if unsupported by the adapter.
+    fn get_raster_regions(&self) -> Option<RasterRegions>;
+
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32;
 
@@ -418,12 +692,21 @@ pub trait VideoCard {
     /// Return a FontInfo struct describing the currently selected font
     fn get_current_font(&self) -> Option<FontInfo>;
 
+    /// Return the character sets available to the adapter's character generator.
+    fn get_character_sets(&self) -> Vec<CharacterSet>;
+
     /// Returns the currently programmed character height
     /// (CRTC Maximum Scanline + 1)
     fn get_character_height(&self) -> u8;
 
     fn get_palette(&self) -> Option<Vec<[u8; 4]>>;
 
+    /// Return the current state of the adapter's palette registers and related color logic.
+    fn get_palette_state(&self) -> Option<PaletteState>;
+
+    /// Return the timing currently programmed into the adapter's CRTC.
+    fn get_crtc_timing(&self) -> Option<CrtcTiming>;
+
     /// Returns a hash map of vectors containing name and value pairs.
     ///
     /// This allows returning multiple categories of related registers.
@@ -465,4 +748,15 @@ pub trait VideoCard {
     /// Return a vector of Strings representing the current text on screen. If the adapter is not in
     /// text mode, an empty vector should be returned.
     fn get_text_mode_strings(&self) -> Vec<String>;
+
+    /// Return the characters and attributes of the current text mode screen, or None if the adapter
+    /// is not in text mode. If `full_buffer` is set, the entire text buffer is returned starting
+    /// from the beginning of video memory, including any text not currently displayed.
+    fn get_text_mode_screen(&self, full_buffer: bool) -> Option<TextModeScreen>;
+
+    /// Write the adapter's state to a save state.
+    fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError>;
+
+    /// Restore the adapter's state from a save state written by save_state().
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError>;
 }
@@ -28,65 +28,294 @@
 
     Implement the AdLib sound card.
 
+    Sound generation is provided by the opl3-rs crate, which runs at the
+    OPL2's native sample rate of 49716Hz. Output is resampled to the sound
+    output's rate by linear interpolation.
+
+    The OPL2's two timers are implemented here rather than in the synthesizer
+    so that they are advanced in step with emulated time. Software commonly
+    detects an AdLib by starting timer 1 and checking the status register for
+    an overflow after 80us.
+
+    The synthesizer's internal state cannot be read back, so the register file
+    is shadowed here. Restoring a save state writes the shadowed registers back
+    to the synthesizer; notes that were sounding restart their envelopes.
+
 */
 
 pub const DEFAULT_ADLIB_BASE: u16 = 0x388;
-pub const SAMPLE_BUF_LEN: usize = 800;
+/// Native sample rate of the OPL2: 14.31818MHz / 4 / 72.
+pub const OPL_NATIVE_RATE: u32 = 49716;
+/// Number of native samples generated at a time.
+pub const SAMPLE_BUF_LEN: usize = 64;
+const DEFAULT_OUTPUT_RATE: u32 = 44100;
+
+const TIMER1_TICK_US: f64 = 80.0;
+const TIMER2_TICK_US: f64 = 320.0;
+
+const REG_TIMER1: u8 = 0x02;
+const REG_TIMER2: u8 = 0x03;
+const REG_TIMER_CONTROL: u8 = 0x04;
+
+const TIMER_CONTROL_IRQ_RESET: u8 = 0x80;
+const TIMER_CONTROL_T1_MASK: u8 = 0x40;
+const TIMER_CONTROL_T2_MASK: u8 = 0x20;
+const TIMER_CONTROL_T2_START: u8 = 0x02;
+const TIMER_CONTROL_T1_START: u8 = 0x01;
+
+const STATUS_IRQ: u8 = 0x80;
+const STATUS_T1_FLAG: u8 = 0x40;
+const STATUS_T2_FLAG: u8 = 0x20;
+/// The low bits of the status register read as 0b110 on an OPL2, and 0 on an OPL3.
+const STATUS_OPL2_ID: u8 = 0x06;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
     device_traits::sounddevice::{AudioSample, SoundDevice},
+    devices::dma::DMAController,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 use crossbeam_channel::Sender;
 use opl3_rs::{Opl3Device, OplRegisterFile};
 
+#[derive(Default)]
+struct OplTimer {
+    tick_us: f64,
+    reload:  u8,
+    counter: u8,
+    running: bool,
+    masked:  bool,
+    accum:   f64,
+}
+
+impl OplTimer {
+    fn new(tick_us: f64) -> Self {
+        Self {
+            tick_us,
+            ..Default::default()
+        }
+    }
+
+    fn start(&mut self) {
+        if !self.running {
+            self.running = true;
+            self.counter = self.reload;
+            self.accum = 0.0;
+        }
+    }
+
+    /// Advance the timer, returning true if the counter overflowed.
+    fn run(&mut self, usec: f64) -> bool {
+        if !self.running {
+            return false;
+        }
+        let mut overflow = false;
+        self.accum += usec;
+        while self.accum >= self.tick_us {
+            self.accum -= self.tick_us;
+            if self.counter == 0xFF {
+                self.counter = self.reload;
+                overflow = true;
+            }
+            else {
+                self.counter += 1;
+            }
+        }
+        overflow && !self.masked
+    }
+}
+
 pub struct AdLibCard {
     pub io_base: u16,
     pub opl3: Opl3Device,
-    pub sender: Sender<AudioSample>,
-    pub in_buf: [i16; 2],
+    pub sender: Option<Sender<AudioSample>>,
     pub out_buf: [i16; SAMPLE_BUF_LEN * 2],
     pub sample_accum: usize,
-    pub usec_accum: f64,
     pub addr: u8,
+    regs: [u8; 0x100],
+    timer1: OplTimer,
+    timer2: OplTimer,
+    status: u8,
+    // Resampler state. The position of the next output sample between the previous and current native
+    // samples, in units of native samples.
+    resample_step: f64,
+    resample_pos: f64,
+    last_frame: [f32; 2],
 }
 
 impl AdLibCard {
-    pub fn new(io_base: u16, sample_rate: u32, sender: Sender<AudioSample>) -> Self {
-        log::debug!("Creating Opl3Device with sample rate {}", sample_rate);
-        let opl3 = Opl3Device::new(sample_rate);
+    pub fn new(io_base: u16) -> Self {
+        log::debug!("Creating Opl3Device at {}Hz", OPL_NATIVE_RATE);
+        let opl3 = Opl3Device::new(OPL_NATIVE_RATE);
         AdLibCard {
             io_base,
             opl3,
-            sender,
-            in_buf: [0; 2],
+            sender: None,
             out_buf: [0; SAMPLE_BUF_LEN * 2],
             sample_accum: 0,
-            usec_accum: 0.0,
             addr: 0,
+            regs: [0; 0x100],
+            timer1: OplTimer::new(TIMER1_TICK_US),
+            timer2: OplTimer::new(TIMER2_TICK_US),
+            status: 0,
+            resample_step: OPL_NATIVE_RATE as f64 / DEFAULT_OUTPUT_RATE as f64,
+            resample_pos: 0.0,
+            last_frame: [0.0; 2],
         }
     }
+
+    fn read_status(&self) -> u8 {
+        let mut status = self.status | STATUS_OPL2_ID;
+        if self.status & (STATUS_T1_FLAG | STATUS_T2_FLAG) != 0 {
+            status |= STATUS_IRQ;
+        }
+        status
+    }
+
+    fn write_register(&mut self, data: u8) {
+        match self.addr {
+            REG_TIMER1 => self.timer1.reload = data,
+            REG_TIMER2 => self.timer2.reload = data,
+            REG_TIMER_CONTROL => {
+                if data & TIMER_CONTROL_IRQ_RESET != 0 {
+                    // The IRQ reset bit clears the flags. All other bits are ignored.
+                    self.status = 0;
+                    return;
+                }
+                self.timer1.masked = data & TIMER_CONTROL_T1_MASK != 0;
+                self.timer2.masked = data & TIMER_CONTROL_T2_MASK != 0;
+                if data & TIMER_CONTROL_T1_START != 0 {
+                    self.timer1.start();
+                }
+                else {
+                    self.timer1.running = false;
+                }
+                if data & TIMER_CONTROL_T2_START != 0 {
+                    self.timer2.start();
+                }
+                else {
+                    self.timer2.running = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Resample a native stereo frame to the output rate.
+    fn resample_frame(&mut self, frame: [f32; 2]) {
+        while self.resample_pos < 1.0 {
+            let t = self.resample_pos as f32;
+            if let Some(sender) = &self.sender {
+                for (last, cur) in self.last_frame.iter().zip(frame.iter()) {
+                    _ = sender.send(last + (cur - last) * t);
+                }
+            }
+            self.resample_pos += self.resample_step;
+        }
+        self.resample_pos -= 1.0;
+        self.last_frame = frame;
+    }
+}
+
+fn sample_to_f32(sample: i16) -> f32 {
+    // The synthesizer idles at -1; treat this as silence to avoid a DC offset.
+    let sample = if sample == -1 { 0 } else { sample };
+    sample as f32 / i16::MAX as f32
 }
 
 impl SoundDevice for AdLibCard {
-    fn run(&mut self, usec: f64) {
+    fn name(&self) -> &'static str {
+        "AdLib Music Synthesizer"
+    }
+
+    fn channels(&self) -> usize {
+        2
+    }
+
+    fn set_audio_sender(&mut self, sender: Sender<AudioSample>, sample_rate: u32) {
+        log::debug!(
+            "Resampling AdLib output from {}Hz to {}Hz",
+            OPL_NATIVE_RATE,
+            sample_rate
+        );
+        self.sender = Some(sender);
+        self.resample_step = OPL_NATIVE_RATE as f64 / sample_rate as f64;
+    }
+
+    /// Silence the synthesizer and stop the timers.
+    fn reset(&mut self) {
+        for reg in 0x01..=0xF5 {
+            _ = self.opl3.write_address(reg, OplRegisterFile::Primary);
+            _ = self.opl3.write_data(0, OplRegisterFile::Primary, false);
+        }
+        self.addr = 0;
+        self.regs = [0; 0x100];
+        self.timer1 = OplTimer::new(TIMER1_TICK_US);
+        self.timer2 = OplTimer::new(TIMER2_TICK_US);
+        self.status = 0;
+    }
+
+    fn run(&mut self, _dma: &mut DMAController, _bus: &mut BusInterface, usec: f64) -> Option<bool> {
+        if self.timer1.run(usec) {
+            self.status |= STATUS_T1_FLAG;
+        }
+        if self.timer2.run(usec) {
+            self.status |= STATUS_T2_FLAG;
+        }
+
         self.sample_accum += self.opl3.run(usec);
-        self.usec_accum += usec;
 
         while self.sample_accum >= SAMPLE_BUF_LEN {
-            //log::debug!("Reached {} samples in {}", self.sample_accum, self.usec_accum);
             self.sample_accum -= SAMPLE_BUF_LEN;
-            self.usec_accum = 0.0;
 
             _ = self.opl3.generate_samples(&mut self.out_buf);
 
-            for s in self.out_buf.chunks_exact(2) {
-                let samp0 = if s[0] == -1 { 0 } else { s[0] };
-                let samp1 = if s[1] == -1 { 0 } else { s[1] };
-                self.sender.send(samp0 as f32 / i16::MAX as f32).unwrap();
-                self.sender.send(samp1 as f32 / i16::MAX as f32).unwrap();
+            for i in 0..SAMPLE_BUF_LEN {
+                let frame = [
+                    sample_to_f32(self.out_buf[i * 2]),
+                    sample_to_f32(self.out_buf[i * 2 + 1]),
+                ];
+                self.resample_frame(frame);
             }
         }
+        // The AdLib's timer interrupt line is not connected.
+        None
+    }
+}
+
+impl SaveState for AdLibCard {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.addr);
+        w.write_bytes(&self.regs);
+        for timer in [&self.timer1, &self.timer2] {
+            w.write_u8(timer.reload);
+            w.write_u8(timer.counter);
+            w.write_bool(timer.running);
+            w.write_bool(timer.masked);
+            w.write_f64(timer.accum);
+        }
+        w.write_u8(self.status);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let addr = r.read_u8()?;
+        r.read_bytes_into(&mut self.regs, "AdLib registers")?;
+        for timer in [&mut self.timer1, &mut self.timer2] {
+            timer.reload = r.read_u8()?;
+            timer.counter = r.read_u8()?;
+            timer.running = r.read_bool()?;
+            timer.masked = r.read_bool()?;
+            timer.accum = r.read_f64()?;
+        }
+        self.status = r.read_u8()?;
+
+        for (reg, data) in self.regs.iter().enumerate().skip(1) {
+            _ = self.opl3.write_address(reg as u8, OplRegisterFile::Primary);
+            _ = self.opl3.write_data(*data, OplRegisterFile::Primary, false);
+        }
+        _ = self.opl3.write_address(addr, OplRegisterFile::Primary);
+        self.addr = addr;
+        Ok(())
     }
 }
 
@@ -94,7 +323,7 @@ impl IoDevice for AdLibCard {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
         //log::debug!("Read from Adlib port {:04X}", port - self.io_base);
         match port - self.io_base {
-            0 => self.opl3.read_status(),
+            0 => self.read_status(),
             1 => 0x1F,
             _ => 0xFF,
         }
@@ -108,6 +337,8 @@ impl IoDevice for AdLibCard {
                 self.addr = data;
             }
             1 => {
+                self.regs[self.addr as usize] = data;
+                self.write_register(data);
                 _ = self.opl3.write_data(data, OplRegisterFile::Primary, false);
             }
             _ => {}
@@ -0,0 +1,718 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::atapi_cdrom.rs
+
+    Implements the packet command set of an ATAPI CD-ROM drive. The drive is
+    attached to an ATA controller, which is responsible for transporting
+    command packets and data; see devices::xtide.
+
+    CD audio is played directly from the image and streamed to the sound
+    output as 44.1KHz stereo samples, as if from the drive's analog output.
+
+*/
+
+use std::path::Path;
+
+use anyhow::Error;
+
+#[cfg(feature = "sound")]
+use crate::device_traits::sounddevice::AudioSample;
+use crate::{
+    cd_image::{lba_to_msf, msf_to_lba, CdImage, CD_DATA_SECTOR_SIZE, CD_LEADOUT_TRACK, CD_RAW_SECTOR_SIZE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+#[cfg(feature = "sound")]
+use crossbeam_channel::Sender;
+
+pub const CDROM_AUDIO_SAMPLE_RATE: u32 = 44100;
+pub const CDROM_AUDIO_CHANNELS: u32 = 2;
+
+const SAMPLES_PER_FRAME: usize = CD_RAW_SECTOR_SIZE / 4;
+// Limit of a single READ command, to bound the size of the transfer buffer.
+const MAX_READ_SECTORS: u32 = 64;
+
+const PKT_TEST_UNIT_READY: u8 = 0x00;
+const PKT_REQUEST_SENSE: u8 = 0x03;
+const PKT_INQUIRY: u8 = 0x12;
+const PKT_START_STOP_UNIT: u8 = 0x1B;
+const PKT_PREVENT_ALLOW_REMOVAL: u8 = 0x1E;
+const PKT_READ_CAPACITY: u8 = 0x25;
+const PKT_READ_10: u8 = 0x28;
+const PKT_SEEK_10: u8 = 0x2B;
+const PKT_READ_SUB_CHANNEL: u8 = 0x42;
+const PKT_READ_TOC: u8 = 0x43;
+const PKT_PLAY_AUDIO_10: u8 = 0x45;
+const PKT_PLAY_AUDIO_MSF: u8 = 0x47;
+const PKT_PAUSE_RESUME: u8 = 0x4B;
+const PKT_STOP_PLAY_SCAN: u8 = 0x4E;
+const PKT_MODE_SELECT_10: u8 = 0x55;
+const PKT_MODE_SENSE_10: u8 = 0x5A;
+const PKT_PLAY_AUDIO_12: u8 = 0xA5;
+const PKT_READ_12: u8 = 0xA8;
+
+pub const SENSE_NONE: u8 = 0x00;
+pub const SENSE_NOT_READY: u8 = 0x02;
+pub const SENSE_MEDIUM_ERROR: u8 = 0x03;
+pub const SENSE_ILLEGAL_REQUEST: u8 = 0x05;
+pub const SENSE_UNIT_ATTENTION: u8 = 0x06;
+
+const ASC_INVALID_COMMAND: u8 = 0x20;
+const ASC_LBA_OUT_OF_RANGE: u8 = 0x21;
+const ASC_INVALID_FIELD: u8 = 0x24;
+const ASC_MEDIUM_CHANGED: u8 = 0x28;
+const ASC_MEDIUM_NOT_PRESENT: u8 = 0x3A;
+const ASC_REMOVAL_PREVENTED: u8 = 0x53;
+const ASC_ILLEGAL_MODE_FOR_TRACK: u8 = 0x64;
+const ASC_UNRECOVERED_READ_ERROR: u8 = 0x11;
+
+const MODE_PAGE_AUDIO_CONTROL: u8 = 0x0E;
+const MODE_PAGE_CAPABILITIES: u8 = 0x2A;
+const MODE_PAGE_ALL: u8 = 0x3F;
+
+/// The outcome of a packet command, to be carried out by the controller.
+#[derive(Debug, PartialEq)]
+pub enum PacketResult {
+    /// The command completed with no data phase.
+    Complete,
+    /// The command completed and has data to transfer to the host.
+    DataIn(Vec<u8>),
+    /// The command expects the specified number of bytes from the host, to be passed to data_out().
+    DataOut(usize),
+    /// The command failed with the specified sense key. Details are returned by REQUEST SENSE.
+    CheckCondition(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum AudioStatus {
+    NoStatus = 0x15,
+    Playing = 0x11,
+    Paused = 0x12,
+    Completed = 0x13,
+    Error = 0x14,
+}
+
+pub struct AtapiCdRom {
+    image: Option<CdImage>,
+    media_changed: bool,
+    locked: bool,
+    eject_requested: bool,
+
+    sense_key: u8,
+    asc: u8,
+
+    audio_status: AudioStatus,
+    audio_lba: u32,
+    audio_end: u32,
+    volume: [u8; 2],
+    sample_accum: f64,
+    frame_buf: Vec<u8>,
+    #[cfg(feature = "sound")]
+    sender: Option<Sender<AudioSample>>,
+}
+
+impl Default for AtapiCdRom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AtapiCdRom {
+    pub fn new() -> Self {
+        Self {
+            image: None,
+            media_changed: false,
+            locked: false,
+            eject_requested: false,
+            sense_key: SENSE_NONE,
+            asc: 0,
+            audio_status: AudioStatus::NoStatus,
+            audio_lba: 0,
+            audio_end: 0,
+            volume: [0xFF, 0xFF],
+            sample_accum: 0.0,
+            frame_buf: vec![0; CD_RAW_SECTOR_SIZE],
+            #[cfg(feature = "sound")]
+            sender: None,
+        }
+    }
+
+    /// Provide a channel for the drive's audio output.
+    #[cfg(feature = "sound")]
+    pub fn set_audio_sender(&mut self, sender: Sender<AudioSample>) {
+        self.sender = Some(sender);
+    }
+
+    pub fn reset(&mut self) {
+        self.stop_audio();
+        self.locked = false;
+        self.volume = [0xFF, 0xFF];
+    }
+
+    /// Open and insert the CD image at the specified path, replacing any current disc.
+    pub fn load_image(&mut self, path: &Path) -> Result<(), Error> {
+        let image = CdImage::open(path)?;
+        log::debug!(
+            "Inserting CD image {:?}: {} tracks, {} frames",
+            path,
+            image.tracks().len(),
+            image.leadout()
+        );
+        self.insert(image);
+        Ok(())
+    }
+
+    pub fn insert(&mut self, image: CdImage) {
+        self.stop_audio();
+        self.image = Some(image);
+        self.media_changed = true;
+    }
+
+    /// Remove the current disc. The host is not consulted; a locked drive is ejected regardless,
+    /// as if with a paperclip.
+    pub fn eject(&mut self) -> Option<CdImage> {
+        self.stop_audio();
+        self.locked = false;
+        self.eject_requested = false;
+        self.media_changed = self.image.is_some();
+        self.image.take()
+    }
+
+    pub fn image_path(&self) -> Option<&Path> {
+        self.image.as_ref().map(|image| image.path())
+    }
+
+    /// Returns true once if the host has ejected the disc with START STOP UNIT, so that the
+    /// front end may update its state.
+    pub fn take_eject_request(&mut self) -> bool {
+        std::mem::take(&mut self.eject_requested)
+    }
+
+    fn stop_audio(&mut self) {
+        if matches!(self.audio_status, AudioStatus::Playing | AudioStatus::Paused) {
+            self.audio_status = AudioStatus::Completed;
+        }
+    }
+
+    fn set_sense(&mut self, sense_key: u8, asc: u8) -> PacketResult {
+        self.sense_key = sense_key;
+        self.asc = asc;
+        PacketResult::CheckCondition(sense_key)
+    }
+
+    /// Check that a disc is present for commands that access the medium.
+    fn check_ready(&mut self) -> Option<PacketResult> {
+        if self.image.is_none() {
+            return Some(self.set_sense(SENSE_NOT_READY, ASC_MEDIUM_NOT_PRESENT));
+        }
+        None
+    }
+
+    /// Build the 256-word IDENTIFY PACKET DEVICE response.
+    pub fn identify(&self) -> Vec<u8> {
+        let mut words = [0u16; 256];
+
+        let put_string = |words: &mut [u16; 256], start: usize, len: usize, s: &str| {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.resize(len * 2, b' ');
+            for i in 0..len {
+                words[start + i] = (bytes[i * 2] as u16) << 8 | bytes[i * 2 + 1] as u16;
+            }
+        };
+
+        // ATAPI, CD-ROM device, removable, 12-byte packets, DRQ within 3ms.
+        words[0] = 0x8580;
+        put_string(&mut words, 10, 10, "MARTYPC00000002");
+        put_string(&mut words, 23, 4, "1.0");
+        put_string(&mut words, 27, 20, "MartyPC ATAPI CD-ROM");
+        words[49] = 0x0200; // LBA supported
+
+        let mut buf = Vec::with_capacity(512);
+        for word in words.iter() {
+            buf.push(*word as u8);
+            buf.push((*word >> 8) as u8);
+        }
+        buf
+    }
+
+    /// Execute a 12-byte command packet.
+    pub fn packet(&mut self, cdb: &[u8]) -> PacketResult {
+        log::trace!("ATAPI: Packet {:02X?}", cdb);
+
+        // Everything but INQUIRY and REQUEST SENSE reports a pending unit attention.
+        if !matches!(cdb[0], PKT_INQUIRY | PKT_REQUEST_SENSE) && self.media_changed {
+            self.media_changed = false;
+            return self.set_sense(SENSE_UNIT_ATTENTION, ASC_MEDIUM_CHANGED);
+        }
+
+        let result = match cdb[0] {
+            PKT_TEST_UNIT_READY => self.check_ready().unwrap_or(PacketResult::Complete),
+            PKT_REQUEST_SENSE => {
+                let mut sense = vec![0; 18];
+                sense[0] = 0x70; // Current error
+                sense[2] = self.sense_key;
+                sense[7] = 10; // Additional sense length
+                sense[12] = self.asc;
+                self.sense_key = SENSE_NONE;
+                self.asc = 0;
+                // REQUEST SENSE must not overwrite the sense data it is returning.
+                return PacketResult::DataIn(truncate(sense, cdb[4] as usize));
+            }
+            PKT_INQUIRY => {
+                let mut inquiry = vec![0; 36];
+                inquiry[0] = 0x05; // CD-ROM device
+                inquiry[1] = 0x80; // Removable medium
+                inquiry[3] = 0x21; // ATAPI version, response format 1
+                inquiry[4] = 31; // Additional length
+                inquiry[8..16].copy_from_slice(b"MARTYPC ");
+                inquiry[16..32].copy_from_slice(b"CD-ROM DRIVE    ");
+                inquiry[32..36].copy_from_slice(b"1.0 ");
+                PacketResult::DataIn(truncate(inquiry, cdb[4] as usize))
+            }
+            PKT_START_STOP_UNIT => {
+                let load_eject = cdb[4] & 0x02 != 0;
+                let start = cdb[4] & 0x01 != 0;
+                if load_eject && !start {
+                    if self.locked {
+                        return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_REMOVAL_PREVENTED);
+                    }
+                    if self.image.is_some() {
+                        self.stop_audio();
+                        self.image = None;
+                        self.eject_requested = true;
+                    }
+                }
+                else if !start {
+                    self.stop_audio();
+                }
+                PacketResult::Complete
+            }
+            PKT_PREVENT_ALLOW_REMOVAL => {
+                self.locked = cdb[4] & 0x01 != 0;
+                PacketResult::Complete
+            }
+            PKT_READ_CAPACITY => match self.check_ready() {
+                Some(result) => result,
+                None => {
+                    let last_lba = self.image.as_ref().unwrap().leadout().saturating_sub(1);
+                    let mut capacity = last_lba.to_be_bytes().to_vec();
+                    capacity.extend_from_slice(&(CD_DATA_SECTOR_SIZE as u32).to_be_bytes());
+                    PacketResult::DataIn(capacity)
+                }
+            },
+            PKT_READ_10 | PKT_READ_12 => {
+                let lba = be_u32(&cdb[2..6]);
+                let count = if cdb[0] == PKT_READ_10 {
+                    be_u16(&cdb[7..9]) as u32
+                }
+                else {
+                    be_u32(&cdb[6..10])
+                };
+                self.read(lba, count)
+            }
+            PKT_SEEK_10 => match self.check_ready() {
+                Some(result) => result,
+                None => {
+                    self.stop_audio();
+                    PacketResult::Complete
+                }
+            },
+            PKT_READ_SUB_CHANNEL => self.read_sub_channel(cdb),
+            PKT_READ_TOC => self.read_toc(cdb),
+            PKT_PLAY_AUDIO_10 => {
+                let lba = be_u32(&cdb[2..6]);
+                let len = be_u16(&cdb[7..9]) as u32;
+                self.play_audio(lba, lba.saturating_add(len))
+            }
+            PKT_PLAY_AUDIO_12 => {
+                let lba = be_u32(&cdb[2..6]);
+                let len = be_u32(&cdb[6..10]);
+                self.play_audio(lba, lba.saturating_add(len))
+            }
+            PKT_PLAY_AUDIO_MSF => {
+                let start = msf_to_lba(cdb[3], cdb[4], cdb[5]).unwrap_or(0);
+                let end = msf_to_lba(cdb[6], cdb[7], cdb[8]).unwrap_or(0);
+                self.play_audio(start, end)
+            }
+            PKT_PAUSE_RESUME => {
+                let resume = cdb[8] & 0x01 != 0;
+                match (self.audio_status, resume) {
+                    (AudioStatus::Playing, false) => {
+                        self.audio_status = AudioStatus::Paused;
+                        PacketResult::Complete
+                    }
+                    (AudioStatus::Paused, true) => {
+                        self.audio_status = AudioStatus::Playing;
+                        PacketResult::Complete
+                    }
+                    (AudioStatus::Playing, true) | (AudioStatus::Paused, false) => PacketResult::Complete,
+                    _ => self.set_sense(SENSE_ILLEGAL_REQUEST, 0x2C), // Command sequence error
+                }
+            }
+            PKT_STOP_PLAY_SCAN => {
+                self.stop_audio();
+                PacketResult::Complete
+            }
+            PKT_MODE_SENSE_10 => self.mode_sense(cdb),
+            PKT_MODE_SELECT_10 => PacketResult::DataOut(be_u16(&cdb[7..9]) as usize),
+            _ => {
+                log::debug!("ATAPI: Unsupported packet command {:02X}", cdb[0]);
+                self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_INVALID_COMMAND)
+            }
+        };
+
+        if !matches!(result, PacketResult::CheckCondition(_)) {
+            self.sense_key = SENSE_NONE;
+            self.asc = 0;
+        }
+        result
+    }
+
+    /// Receive the parameter data of a command that returned PacketResult::DataOut.
+    pub fn data_out(&mut self, data: &[u8]) {
+        // MODE SELECT is the only command with a data-out phase. Only the audio control page
+        // is changeable, which sets the output volume.
+        let mut pos = 8;
+        while pos + 2 <= data.len() {
+            let page = data[pos] & 0x3F;
+            let len = data[pos + 1] as usize;
+            if page == MODE_PAGE_AUDIO_CONTROL && pos + 12 <= data.len() {
+                self.volume = [data[pos + 9], data[pos + 11]];
+                log::debug!("ATAPI: Audio volume set to {:?}", self.volume);
+            }
+            pos += len + 2;
+        }
+    }
+
+    fn read(&mut self, lba: u32, count: u32) -> PacketResult {
+        if let Some(result) = self.check_ready() {
+            return result;
+        }
+        self.stop_audio();
+
+        let image = self.image.as_mut().unwrap();
+        if lba.saturating_add(count) > image.leadout() {
+            return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE);
+        }
+        if count > MAX_READ_SECTORS {
+            log::warn!("ATAPI: READ of {} sectors truncated to {}", count, MAX_READ_SECTORS);
+        }
+
+        let count = std::cmp::min(count, MAX_READ_SECTORS) as usize;
+        let mut data = vec![0; count * CD_DATA_SECTOR_SIZE];
+        for (i, sector) in data.chunks_exact_mut(CD_DATA_SECTOR_SIZE).enumerate() {
+            let sector_lba = lba + i as u32;
+            if image
+                .track_at(sector_lba)
+                .map(|t| t.track_type.is_audio())
+                .unwrap_or(true)
+            {
+                return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_ILLEGAL_MODE_FOR_TRACK);
+            }
+            if let Err(e) = image.read_data_sector(sector, sector_lba) {
+                log::error!("ATAPI: Error reading sector {}: {}", sector_lba, e);
+                return self.set_sense(SENSE_MEDIUM_ERROR, ASC_UNRECOVERED_READ_ERROR);
+            }
+        }
+        PacketResult::DataIn(data)
+    }
+
+    fn play_audio(&mut self, start: u32, end: u32) -> PacketResult {
+        if let Some(result) = self.check_ready() {
+            return result;
+        }
+        let image = self.image.as_ref().unwrap();
+        if start == end {
+            // A zero-length play is a seek, and is not an error.
+            self.stop_audio();
+            return PacketResult::Complete;
+        }
+        if start > end || end > image.leadout() {
+            return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE);
+        }
+        if !image.track_for(start).map(|t| t.track_type.is_audio()).unwrap_or(false) {
+            return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_ILLEGAL_MODE_FOR_TRACK);
+        }
+
+        log::debug!("ATAPI: Playing audio from {} to {}", start, end);
+        self.audio_lba = start;
+        self.audio_end = end;
+        self.sample_accum = 0.0;
+        self.audio_status = AudioStatus::Playing;
+        PacketResult::Complete
+    }
+
+    fn read_toc(&mut self, cdb: &[u8]) -> PacketResult {
+        if let Some(result) = self.check_ready() {
+            return result;
+        }
+        let msf = cdb[1] & 0x02 != 0;
+        let format = match cdb[2] & 0x0F {
+            0 => cdb[9] >> 6,
+            f => f,
+        };
+        let alloc_len = be_u16(&cdb[7..9]) as usize;
+        let image = self.image.as_ref().unwrap();
+        let tracks = image.tracks();
+        let first = tracks.first().unwrap().number;
+        let last = tracks.last().unwrap().number;
+
+        let mut toc = vec![0, 0];
+        match format {
+            0 => {
+                let start_track = cdb[6];
+                if start_track > last && start_track != CD_LEADOUT_TRACK {
+                    return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD);
+                }
+                toc.extend_from_slice(&[first, last]);
+                for track in tracks.iter().filter(|t| t.number >= start_track) {
+                    toc.extend_from_slice(&[0, 0x10 | track.track_type.control(), track.number, 0]);
+                    toc.extend_from_slice(&address_bytes(track.start, msf));
+                }
+                toc.extend_from_slice(&[
+                    0,
+                    0x10 | tracks.last().unwrap().track_type.control(),
+                    CD_LEADOUT_TRACK,
+                    0,
+                ]);
+                toc.extend_from_slice(&address_bytes(image.leadout(), msf));
+            }
+            1 => {
+                // Multi-session information; images have a single session.
+                let track = tracks.first().unwrap();
+                toc.extend_from_slice(&[1, 1]);
+                toc.extend_from_slice(&[0, 0x10 | track.track_type.control(), track.number, 0]);
+                toc.extend_from_slice(&address_bytes(track.start, msf));
+            }
+            _ => return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD),
+        }
+
+        let data_len = (toc.len() - 2) as u16;
+        toc[0..2].copy_from_slice(&data_len.to_be_bytes());
+        PacketResult::DataIn(truncate(toc, alloc_len))
+    }
+
+    fn read_sub_channel(&mut self, cdb: &[u8]) -> PacketResult {
+        if let Some(result) = self.check_ready() {
+            return result;
+        }
+        let msf = cdb[1] & 0x02 != 0;
+        let sub_q = cdb[2] & 0x40 != 0;
+        let format = cdb[3];
+        let alloc_len = be_u16(&cdb[7..9]) as usize;
+
+        let mut data = vec![0, self.audio_status as u8, 0, 0];
+        // The Completed and Error statuses are reported only once.
+        if matches!(self.audio_status, AudioStatus::Completed | AudioStatus::Error) {
+            self.audio_status = AudioStatus::NoStatus;
+        }
+
+        if sub_q {
+            if format != 0x01 {
+                return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD);
+            }
+            let image = self.image.as_ref().unwrap();
+            let track = image.track_for(self.audio_lba).unwrap();
+            let relative = self.audio_lba as i64 - track.start as i64;
+            data.extend_from_slice(&[0x01, 0x10 | track.track_type.control(), track.number, 1]);
+            data.extend_from_slice(&address_bytes(self.audio_lba, msf));
+            if msf {
+                // Relative MSF addresses don't include the lead-in.
+                let frames = relative.unsigned_abs() as u32;
+                data.extend_from_slice(&[
+                    0,
+                    (frames / 4500) as u8,
+                    ((frames / 75) % 60) as u8,
+                    (frames % 75) as u8,
+                ]);
+            }
+            else {
+                data.extend_from_slice(&(relative as i32).to_be_bytes());
+            }
+            data[3] = (data.len() - 4) as u8;
+        }
+        PacketResult::DataIn(truncate(data, alloc_len))
+    }
+
+    fn mode_sense(&mut self, cdb: &[u8]) -> PacketResult {
+        let page_code = cdb[2] & 0x3F;
+        let alloc_len = be_u16(&cdb[7..9]) as usize;
+
+        let medium_type = match &self.image {
+            Some(image) if image.tracks().iter().all(|t| t.track_type.is_audio()) => 0x02,
+            Some(image) if image.tracks().iter().any(|t| t.track_type.is_audio()) => 0x03,
+            Some(_) => 0x01,
+            None => 0x70,
+        };
+        let mut data = vec![0, 0, medium_type, 0, 0, 0, 0, 0];
+
+        if page_code == MODE_PAGE_AUDIO_CONTROL || page_code == MODE_PAGE_ALL {
+            let mut page = vec![0; 16];
+            page[0] = MODE_PAGE_AUDIO_CONTROL;
+            page[1] = 14;
+            page[2] = 0x04; // Immediate
+            page[8] = 0x01; // Port 0 outputs channel 0
+            page[9] = self.volume[0];
+            page[10] = 0x02; // Port 1 outputs channel 1
+            page[11] = self.volume[1];
+            data.extend_from_slice(&page);
+        }
+        if page_code == MODE_PAGE_CAPABILITIES || page_code == MODE_PAGE_ALL {
+            let mut page = vec![0; 20];
+            page[0] = MODE_PAGE_CAPABILITIES;
+            page[1] = 18;
+            page[4] = 0x01; // Audio play
+            page[6] = 0x2B; // Tray loader, eject, lock state, lock
+            if self.locked {
+                page[6] |= 0x02;
+            }
+            page[8..10].copy_from_slice(&352u16.to_be_bytes()); // Max speed in KB/s (2x)
+            page[10..12].copy_from_slice(&256u16.to_be_bytes()); // Volume levels
+            page[12..14].copy_from_slice(&64u16.to_be_bytes()); // Buffer size in KB
+            page[14..16].copy_from_slice(&352u16.to_be_bytes()); // Current speed
+            data.extend_from_slice(&page);
+        }
+        if data.len() == 8 {
+            return self.set_sense(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD);
+        }
+
+        let data_len = (data.len() - 2) as u16;
+        data[0..2].copy_from_slice(&data_len.to_be_bytes());
+        PacketResult::DataIn(truncate(data, alloc_len))
+    }
+
+    /// Run the drive for the specified number of microseconds, advancing audio playback.
+    pub fn run(&mut self, us: f64) {
+        self.sample_accum += us * CDROM_AUDIO_SAMPLE_RATE as f64 / 1_000_000.0;
+
+        while self.sample_accum >= SAMPLES_PER_FRAME as f64 {
+            self.sample_accum -= SAMPLES_PER_FRAME as f64;
+
+            let playing = self.audio_status == AudioStatus::Playing;
+            if playing {
+                let image = self.image.as_mut().unwrap();
+                if let Err(e) = image.read_audio_frame(&mut self.frame_buf, self.audio_lba) {
+                    log::error!("ATAPI: Error reading audio frame {}: {}", self.audio_lba, e);
+                    self.audio_status = AudioStatus::Error;
+                    continue;
+                }
+                self.audio_lba += 1;
+                if self.audio_lba >= self.audio_end {
+                    self.audio_status = AudioStatus::Completed;
+                }
+            }
+
+            #[cfg(feature = "sound")]
+            if let Some(sender) = &self.sender {
+                let scale = [
+                    self.volume[0] as f32 / (255.0 * i16::MAX as f32),
+                    self.volume[1] as f32 / (255.0 * i16::MAX as f32),
+                ];
+                for s in self.frame_buf.chunks_exact(4) {
+                    let (left, right) = if playing {
+                        (
+                            i16::from_le_bytes([s[0], s[1]]) as f32 * scale[0],
+                            i16::from_le_bytes([s[2], s[3]]) as f32 * scale[1],
+                        )
+                    }
+                    else {
+                        (0.0, 0.0)
+                    };
+                    _ = sender.send(left);
+                    _ = sender.send(right);
+                }
+            }
+        }
+    }
+}
+
+/// The identity of the inserted disc is saved rather than its contents. Audio playback resumes
+/// from the saved frame.
+impl SaveState for AtapiCdRom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_option_str(self.image.as_ref().map(|image| image.identity()).as_deref());
+        w.write_bool(self.media_changed);
+        w.write_bool(self.locked);
+        w.write_bool(self.eject_requested);
+        w.write_u8(self.sense_key);
+        w.write_u8(self.asc);
+        w.write_u8(self.audio_status as u8);
+        w.write_u32(self.audio_lba);
+        w.write_u32(self.audio_end);
+        w.write_u8(self.volume[0]);
+        w.write_u8(self.volume[1]);
+        w.write_f64(self.sample_accum);
+        w.write_bytes(&self.frame_buf);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.read_media(self.image.as_ref().map(|image| image.identity()), "CD-ROM")?;
+        self.media_changed = r.read_bool()?;
+        self.locked = r.read_bool()?;
+        self.eject_requested = r.read_bool()?;
+        self.sense_key = r.read_u8()?;
+        self.asc = r.read_u8()?;
+        self.audio_status = match r.read_u8()? {
+            0x11 => AudioStatus::Playing,
+            0x12 => AudioStatus::Paused,
+            0x13 => AudioStatus::Completed,
+            0x14 => AudioStatus::Error,
+            0x15 => AudioStatus::NoStatus,
+            _ => return Err(SaveStateError::InvalidValue("CD-ROM audio status")),
+        };
+        self.audio_lba = r.read_u32()?;
+        self.audio_end = r.read_u32()?;
+        self.volume[0] = r.read_u8()?;
+        self.volume[1] = r.read_u8()?;
+        self.sample_accum = r.read_f64()?;
+        r.read_bytes_into(&mut self.frame_buf, "CD-ROM frame buffer")?;
+        Ok(())
+    }
+}
+
+fn truncate(mut data: Vec<u8>, len: usize) -> Vec<u8> {
+    data.truncate(len);
+    data
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Encode an address as a 4-byte MSF or LBA field.
+fn address_bytes(lba: u32, msf: bool) -> [u8; 4] {
+    if msf {
+        let (m, s, f) = lba_to_msf(lba);
+        [0, m, s, f]
+    }
+    else {
+        lba.to_be_bytes()
+    }
+}
@@ -32,7 +32,10 @@
 
 use anyhow::{anyhow, Error};
 
-use crate::bus::{MemRangeDescriptor, MemoryMappedDevice};
+use crate::{
+    bus::{MemRangeDescriptor, MemoryMappedDevice},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 use marty_common::types::cartridge::CartImage;
 
@@ -69,6 +72,35 @@ impl CartridgeSlot {
     pub fn remove_cart(&mut self, slot: usize) {
         self.carts[slot] = None;
     }
+
+    fn cart_identity(&self, slot: usize) -> Option<String> {
+        self.carts[slot].as_ref().map(|cart| {
+            format!(
+                "{:04X}:{:04X} size:{} md5:{:x}",
+                cart.address_seg,
+                cart.address_mask,
+                cart.image.len(),
+                md5::compute(&cart.image)
+            )
+        })
+    }
+}
+
+/// Cartridges are read-only, so the slots have no state beyond the identity of the inserted
+/// cartridges.
+impl SaveState for CartridgeSlot {
+    fn save_state(&self, w: &mut StateWriter) {
+        for slot in 0..self.carts.len() {
+            w.write_option_str(self.cart_identity(slot).as_deref());
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for slot in 0..self.carts.len() {
+            r.read_media(self.cart_identity(slot), &format!("cartridge slot {}", slot))?;
+        }
+        Ok(())
+    }
 }
 
 impl MemoryMappedDevice for CartridgeSlot {
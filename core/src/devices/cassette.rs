@@ -0,0 +1,486 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::cassette.rs
+
+   Implements the cassette interface of the IBM 5150. The tape is modelled
+   as a list of half-cycle durations with alternating signal levels.
+
+   When the motor relay is closed (PB3 low) the tape advances. During
+   playback the current signal level is presented on PC4. During recording
+   the edges of PIT channel 2's output are timed and appended to the tape.
+
+   Tapes can be loaded from and saved to WAV files (PCM, 8 or 16-bit) or
+   CAS files, which store the raw tape bitstream MSB first, as written by
+   the BIOS: leader, sync bit, sync byte, data blocks with CRC and trailer.
+
+*/
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Error};
+
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// Half-cycle length of a '0' bit in microseconds (1 full cycle is 500us).
+pub const CASSETTE_ZERO_HALF_US: f64 = 250.0;
+/// Half-cycle length of a '1' bit in microseconds (1 full cycle is 1000us).
+pub const CASSETTE_ONE_HALF_US: f64 = 500.0;
+/// Full cycles longer than this are considered '1' bits when decoding.
+const CASSETTE_BIT_THRESHOLD_US: f64 = 750.0;
+/// Full cycles longer than this are considered gaps and are skipped when decoding.
+const CASSETTE_GAP_THRESHOLD_US: f64 = 2000.0;
+/// Half-cycles longer than this while recording are clamped, so that a stalled output doesn't
+/// produce enormous gaps on tape.
+const CASSETTE_MAX_HALF_US: f64 = 1_000_000.0;
+/// Silence inserted before data loaded from a CAS image.
+const CAS_LEAD_IN_US: f64 = 500_000.0;
+
+const WAV_SAVE_RATE: u32 = 44100;
+const WAV_SAVE_LOW: u8 = 0x20;
+const WAV_SAVE_HIGH: u8 = 0xE0;
+/// Zero-crossing hysteresis for WAV decoding, as a fraction of full scale.
+const WAV_HYSTERESIS: f64 = 0.02;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CassetteState {
+    #[default]
+    Stopped,
+    Playing,
+    Recording,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CassetteImageFormat {
+    Wav,
+    Cas,
+}
+
+impl CassetteImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "wav" => Some(CassetteImageFormat::Wav),
+            "cas" => Some(CassetteImageFormat::Cas),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CassetteStatus {
+    pub state: CassetteState,
+    pub motor_on: bool,
+    pub image: Option<PathBuf>,
+    pub position_us: f64,
+    pub length_us: f64,
+    pub modified: bool,
+}
+
+#[derive(Default)]
+pub struct Cassette {
+    state: CassetteState,
+    motor_on: bool,
+    image: Option<PathBuf>,
+    modified: bool,
+    /// Half-cycle durations in microseconds. Even indices are low, odd indices are high.
+    tape: Vec<f64>,
+    /// Index of the current half-cycle.
+    index: usize,
+    /// Time elapsed within the current half-cycle.
+    elapsed: f64,
+    /// Total time of all half-cycles before the current one.
+    position: f64,
+    last_out: bool,
+    record_elapsed: f64,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn status(&self) -> CassetteStatus {
+        CassetteStatus {
+            state: self.state,
+            motor_on: self.motor_on,
+            image: self.image.clone(),
+            position_us: self.position + self.elapsed,
+            length_us: self.tape.iter().sum(),
+            modified: self.modified,
+        }
+    }
+
+    /// Load a WAV or CAS image, replacing the current tape. The tape is rewound and stopped.
+    pub fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let data = fs::read(path)?;
+        let tape = match CassetteImageFormat::from_path(path) {
+            Some(CassetteImageFormat::Wav) => decode_wav(&data)?,
+            Some(CassetteImageFormat::Cas) => decode_cas(&data),
+            None => bail!("Unsupported cassette image format: {}", path.display()),
+        };
+        log::debug!(
+            "Loaded cassette image {}: {} half-cycles, {:.1} seconds",
+            path.display(),
+            tape.len(),
+            tape.iter().sum::<f64>() / 1_000_000.0
+        );
+        self.tape = tape;
+        self.image = Some(path.to_path_buf());
+        self.modified = false;
+        self.state = CassetteState::Stopped;
+        self.rewind();
+        Ok(())
+    }
+
+    /// Save the tape to the specified path. The format is chosen by the file extension.
+    pub fn save(&mut self, path: &Path) -> Result<(), Error> {
+        let data = match CassetteImageFormat::from_path(path) {
+            Some(CassetteImageFormat::Wav) => encode_wav(&self.tape),
+            Some(CassetteImageFormat::Cas) => encode_cas(&self.tape),
+            None => bail!("Unsupported cassette image format: {}", path.display()),
+        };
+        fs::write(path, data)?;
+        self.image = Some(path.to_path_buf());
+        self.modified = false;
+        Ok(())
+    }
+
+    /// Remove the tape, returning the path of the ejected image, if any.
+    pub fn eject(&mut self) -> Option<PathBuf> {
+        self.tape.clear();
+        self.modified = false;
+        self.state = CassetteState::Stopped;
+        self.rewind();
+        self.image.take()
+    }
+
+    /// Insert a blank tape for recording.
+    pub fn new_tape(&mut self) {
+        self.eject();
+    }
+
+    pub fn play(&mut self) {
+        self.state = CassetteState::Playing;
+    }
+
+    /// Begin recording at the current position. Anything on the tape after this point is erased.
+    pub fn record(&mut self) {
+        self.tape.truncate(self.index);
+        self.elapsed = 0.0;
+        self.record_elapsed = 0.0;
+        self.state = CassetteState::Recording;
+    }
+
+    pub fn stop(&mut self) {
+        if self.state == CassetteState::Recording {
+            self.finish_recording();
+        }
+        self.state = CassetteState::Stopped;
+    }
+
+    pub fn rewind(&mut self) {
+        if self.state == CassetteState::Recording {
+            self.finish_recording();
+        }
+        self.index = 0;
+        self.elapsed = 0.0;
+        self.position = 0.0;
+    }
+
+    pub fn reset(&mut self) {
+        self.motor_on = false;
+        if self.state == CassetteState::Recording {
+            self.finish_recording();
+        }
+    }
+
+    fn finish_recording(&mut self) {
+        if self.record_elapsed > 0.0 {
+            self.push_half_cycle(self.record_elapsed);
+        }
+        self.record_elapsed = 0.0;
+    }
+
+    fn push_half_cycle(&mut self, us: f64) {
+        let us = us.min(CASSETTE_MAX_HALF_US);
+        self.tape.push(us);
+        self.position += us;
+        self.index = self.tape.len();
+        self.modified = true;
+    }
+
+    /// Advance the tape by the specified number of microseconds. `motor_on` is the state of the
+    /// motor relay and `data_out` is the level of the cassette data output (PIT channel 2).
+    /// Returns the level of the cassette data input.
+    pub fn run(&mut self, us: f64, motor_on: bool, data_out: bool) -> bool {
+        self.motor_on = motor_on;
+
+        match self.state {
+            CassetteState::Playing if motor_on => {
+                self.elapsed += us;
+                while self.index < self.tape.len() && self.elapsed >= self.tape[self.index] {
+                    self.elapsed -= self.tape[self.index];
+                    self.position += self.tape[self.index];
+                    self.index += 1;
+                }
+                if self.index >= self.tape.len() {
+                    log::debug!("Cassette reached end of tape.");
+                    self.elapsed = 0.0;
+                    self.state = CassetteState::Stopped;
+                }
+            }
+            CassetteState::Recording if motor_on => {
+                self.record_elapsed += us;
+                if data_out != self.last_out {
+                    // Keep the alternating level convention: a half-cycle that would be recorded with
+                    // the wrong polarity is prefixed with a zero-length one.
+                    if (self.tape.len() & 1 == 1) != self.last_out {
+                        self.push_half_cycle(0.0);
+                    }
+                    let elapsed = self.record_elapsed;
+                    self.push_half_cycle(elapsed);
+                    self.record_elapsed = 0.0;
+                }
+            }
+            _ => {}
+        }
+        self.last_out = data_out;
+
+        self.state == CassetteState::Playing && motor_on && self.index & 1 == 1
+    }
+}
+
+/// Unlike disk media, the tape is held in memory and may have been recorded onto, so the tape
+/// itself is part of the state, along with the path of the image it came from.
+impl SaveState for Cassette {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.state {
+            CassetteState::Stopped => 0,
+            CassetteState::Playing => 1,
+            CassetteState::Recording => 2,
+        });
+        w.write_bool(self.motor_on);
+        w.write_option_str(self.image.as_ref().map(|path| path.to_string_lossy()).as_deref());
+        w.write_bool(self.modified);
+        w.write_usize(self.tape.len());
+        for half_cycle in self.tape.iter() {
+            w.write_f64(*half_cycle);
+        }
+        w.write_usize(self.index);
+        w.write_f64(self.elapsed);
+        w.write_f64(self.position);
+        w.write_bool(self.last_out);
+        w.write_f64(self.record_elapsed);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.state = match r.read_u8()? {
+            0 => CassetteState::Stopped,
+            1 => CassetteState::Playing,
+            2 => CassetteState::Recording,
+            _ => return Err(SaveStateError::InvalidValue("cassette state")),
+        };
+        self.motor_on = r.read_bool()?;
+        self.image = r.read_option_str()?.map(PathBuf::from);
+        self.modified = r.read_bool()?;
+        let len = r.read_usize()?;
+        self.tape.clear();
+        for _ in 0..len {
+            self.tape.push(r.read_f64()?);
+        }
+        self.index = r.read_usize()?;
+        self.elapsed = r.read_f64()?;
+        self.position = r.read_f64()?;
+        self.last_out = r.read_bool()?;
+        self.record_elapsed = r.read_f64()?;
+
+        if self.index > self.tape.len() {
+            return Err(SaveStateError::InvalidValue("cassette position"));
+        }
+        Ok(())
+    }
+}
+
+/// Decode a PCM WAV file into half-cycle durations by detecting zero crossings.
+fn decode_wav(data: &[u8]) -> Result<Vec<f64>, Error> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        bail!("Not a RIFF WAVE file.");
+    }
+
+    let mut format = None;
+    let mut samples = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let len = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let body = &data[offset + 8..(offset + 8 + len).min(data.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                if tag != 1 {
+                    bail!("Unsupported WAV encoding: {} (only PCM is supported)", tag);
+                }
+                if bits != 8 && bits != 16 {
+                    bail!("Unsupported WAV sample size: {} bits", bits);
+                }
+                if channels == 0 || rate == 0 {
+                    bail!("Invalid WAV format.");
+                }
+                format = Some((channels, rate, bits));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        offset += 8 + len + (len & 1);
+    }
+
+    let (channels, rate, bits) = format.ok_or(anyhow!("WAV file has no format chunk."))?;
+    let samples = samples.ok_or(anyhow!("WAV file has no data chunk."))?;
+
+    let frame_size = channels * (bits as usize / 8);
+    let sample_us = 1_000_000.0 / rate as f64;
+
+    let mut tape = Vec::new();
+    let mut level = false;
+    let mut run = 0.0;
+    for frame in samples.chunks_exact(frame_size) {
+        // Only the first channel is used.
+        let sample = match bits {
+            8 => (frame[0] as f64 - 128.0) / 128.0,
+            _ => i16::from_le_bytes([frame[0], frame[1]]) as f64 / 32768.0,
+        };
+        let new_level = if level {
+            sample > -WAV_HYSTERESIS
+        }
+        else {
+            sample > WAV_HYSTERESIS
+        };
+        if new_level != level {
+            tape.push(run);
+            run = 0.0;
+            level = new_level;
+        }
+        run += sample_us;
+    }
+    if run > 0.0 {
+        tape.push(run);
+    }
+    Ok(tape)
+}
+
+/// Render the tape as an 8-bit mono square wave.
+fn encode_wav(tape: &[f64]) -> Vec<u8> {
+    let sample_us = 1_000_000.0 / WAV_SAVE_RATE as f64;
+    let mut samples = Vec::new();
+    let mut time = 0.0;
+    let mut next_sample = 0.0;
+    for (i, half) in tape.iter().enumerate() {
+        time += half;
+        let value = if i & 1 == 1 { WAV_SAVE_HIGH } else { WAV_SAVE_LOW };
+        while next_sample < time {
+            samples.push(value);
+            next_sample += sample_us;
+        }
+    }
+
+    let mut wav = Vec::with_capacity(samples.len() + 44);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&WAV_SAVE_RATE.to_le_bytes());
+    wav.extend_from_slice(&WAV_SAVE_RATE.to_le_bytes()); // Byte rate
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Block align
+    wav.extend_from_slice(&8u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&samples);
+    if samples.len() & 1 == 1 {
+        wav.push(0);
+    }
+    wav
+}
+
+/// Expand a CAS bitstream into half-cycles.
+fn decode_cas(data: &[u8]) -> Vec<f64> {
+    let mut tape = Vec::with_capacity(data.len() * 16 + 1);
+    tape.push(CAS_LEAD_IN_US);
+    for byte in data {
+        for bit in (0..8).rev() {
+            let half = if byte & (1 << bit) != 0 {
+                CASSETTE_ONE_HALF_US
+            }
+            else {
+                CASSETTE_ZERO_HALF_US
+            };
+            tape.push(half);
+            tape.push(half);
+        }
+    }
+    tape
+}
+
+/// Classify full cycles of the tape into a CAS bitstream. Gaps between blocks are dropped.
+fn encode_cas(tape: &[f64]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut byte = 0u8;
+    let mut bits = 0;
+
+    // Pair half-cycles into full cycles, re-aligning the pairing after every gap.
+    let mut pending = None;
+    for &half in tape.iter().filter(|&&half| half > 0.0) {
+        if half > CASSETTE_GAP_THRESHOLD_US / 2.0 {
+            pending = None;
+            continue;
+        }
+        let first = match pending.take() {
+            Some(first) => first,
+            None => {
+                pending = Some(half);
+                continue;
+            }
+        };
+        byte = (byte << 1) | (first + half > CASSETTE_BIT_THRESHOLD_US) as u8;
+        bits += 1;
+        if bits == 8 {
+            data.push(byte);
+            byte = 0;
+            bits = 0;
+        }
+    }
+    if bits > 0 {
+        data.push(byte << (8 - bits));
+    }
+    data
+}
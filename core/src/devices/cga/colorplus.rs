@@ -0,0 +1,140 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+    --------------------------------------------------------------------------
+
+    devices::cga::colorplus.rs
+
+    Implementation of the Plantronics ColorPlus extensions to the CGA.
+
+    The ColorPlus is a CGA-compatible card with a second 16K bank of video
+    memory, mapped at BC000 where the CGA would normally mirror its memory.
+    A control register at 3DD enables two extended graphics modes that read
+    one plane from each bank: 320x200 in 16 colors and 640x200 in 4 colors.
+
+*/
+
+use super::{CGA_MEM_APERTURE, CGA_MEM_SIZE};
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub const COLORPLUS_CONTROL_REGISTER: u16 = 0x3DD;
+
+const COLORPLUS_320X200_MODE: u8 = 0b0001_0000;
+const COLORPLUS_640X200_MODE: u8 = 0b0010_0000;
+const COLORPLUS_EITHER_MODE: u8 = COLORPLUS_320X200_MODE | COLORPLUS_640X200_MODE;
+// Swaps the two banks as seen by the CPU, so that B8000 addresses the second bank.
+const COLORPLUS_PLANE_SWAP: u8 = 0b0100_0000;
+
+pub struct ColorPlus {
+    control: u8,
+    mem: Box<[u8; CGA_MEM_SIZE]>,
+}
+
+impl ColorPlus {
+    pub fn new() -> Self {
+        Self {
+            control: 0,
+            mem: vec![0; CGA_MEM_SIZE].into_boxed_slice().try_into().unwrap(),
+        }
+    }
+
+    #[inline]
+    pub fn control(&self) -> u8 {
+        self.control
+    }
+
+    pub fn write_control(&mut self, byte: u8) {
+        log::trace!("Write to ColorPlus control register: {:02X}", byte);
+        self.control = byte;
+    }
+
+    /// Return whether one of the extended graphics modes is enabled.
+    #[inline]
+    pub fn extended_mode(&self) -> bool {
+        self.control & COLORPLUS_EITHER_MODE != 0
+    }
+
+    /// Return whether the 16 color 320x200 mode is enabled. This takes priority over the
+    /// 640x200 mode if both bits are set.
+    #[inline]
+    pub fn mode_16color(&self) -> bool {
+        self.control & COLORPLUS_320X200_MODE != 0
+    }
+
+    /// Resolve an offset into the CGA memory aperture into a bank number and an offset within
+    /// that bank. Memory is mirrored as on a CGA unless an extended mode is enabled.
+    #[inline]
+    pub fn resolve(&self, a_offset: usize, graphics: bool) -> (usize, usize) {
+        if !self.extended_mode() {
+            return (0, a_offset & (CGA_MEM_SIZE - 1));
+        }
+        let mut offset = a_offset & (CGA_MEM_APERTURE - 1);
+        if graphics && (self.control & COLORPLUS_PLANE_SWAP != 0) {
+            offset ^= CGA_MEM_SIZE;
+        }
+        (offset / CGA_MEM_SIZE, offset & (CGA_MEM_SIZE - 1))
+    }
+
+    #[inline]
+    pub fn mem(&self) -> &[u8] {
+        &self.mem[..]
+    }
+
+    #[inline]
+    pub fn mem_mut(&mut self) -> &mut [u8] {
+        &mut self.mem[..]
+    }
+
+    /// Read a big-endian word from the second bank, matching how the CGA fetches a pair of
+    /// graphics bytes per character clock.
+    #[inline]
+    pub fn read_word(&self, addr: usize) -> u16 {
+        (self.mem[addr] as u16) << 8 | self.mem[addr + 1] as u16
+    }
+
+    /// Return the pel value of the specified dot (0-15) within a character clock, given the
+    /// words fetched from each bank. The first bank provides the low order bits.
+    /// In 16 color mode, each pel is two dots wide and the value is a direct RGBI color.
+    /// In 4 color mode, each pel is one dot wide and the value is a palette index.
+    #[inline]
+    pub fn pel(&self, plane0: u16, plane1: u16, dot: u8) -> u8 {
+        if self.mode_16color() {
+            let shift = 14 - (dot & !0x01);
+            (((plane0 >> shift) & 0x03) | ((plane1 >> shift) & 0x03) << 2) as u8
+        }
+        else {
+            let shift = 15 - dot;
+            (((plane0 >> shift) & 0x01) | ((plane1 >> shift) & 0x01) << 1) as u8
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.control);
+        w.write_bytes(&*self.mem);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.control = r.read_u8()?;
+        r.read_bytes_into(&mut *self.mem, "ColorPlus memory")
+    }
+}
@@ -185,6 +185,11 @@ impl CGACard {
     /// Draw a pixel in low resolution graphics mode (320x200)
     /// In this mode, pixels are doubled
     pub fn draw_lowres_gfx_mode_pixel(&mut self) {
+        if self.mode_enable && self.colorplus_active() {
+            self.draw_colorplus_pixel();
+            return;
+        }
+
         let mut new_pixel = self.get_lowres_pixel_color(self.vlc_c9, self.char_col);
 
         if self.rba >= CGA_MAX_CLOCK - 2 {
@@ -203,7 +208,10 @@ impl CGACard {
     /// This routine uses precalculated lookups and masks to generate two u64
     /// values to write to the index frame buffer directly.
     pub fn draw_lowres_gfx_mode_char(&mut self) {
-        if self.mode_enable {
+        if self.mode_enable && self.colorplus_active() {
+            self.draw_colorplus_char();
+        }
+        else if self.mode_enable {
             let lchar_dat = self.get_lowres_gfx_lchar(self.vlc_c9);
             let color0 = lchar_dat.0 .0;
             let color1 = lchar_dat.1 .0;
@@ -223,6 +231,11 @@ impl CGACard {
     /// Draw pixels in high resolution graphics mode. (640x200)
     /// In this mode, two pixels are drawn at the same time.
     pub fn draw_hires_gfx_mode_pixel(&mut self) {
+        if self.mode_enable && self.colorplus_active() {
+            self.draw_colorplus_pixel();
+            return;
+        }
+
         let base_addr = self.get_gfx_addr(self.vlc_c9);
 
         let word = (self.mem[base_addr] as u16) << 8 | self.mem[base_addr + 1] as u16;
@@ -253,6 +266,11 @@ impl CGACard {
 
     /// Draw a single character column in high resolution graphics mode (640x200)
     pub fn draw_hires_gfx_mode_char(&mut self) {
+        if self.mode_enable && self.colorplus_active() {
+            self.draw_colorplus_char();
+            return;
+        }
+
         let base_addr = self.get_gfx_addr(self.vlc_c9);
         let frame_u64: &mut [u64] = bytemuck::cast_slice_mut(&mut *self.buf[self.back_buf]);
 
@@ -268,4 +286,58 @@ impl CGACard {
             frame_u64[(self.rba >> 3) + 1] = 0;
         }
     }
+
+    /// Resolve the color of the specified dot (0-15) of the current character clock in a
+    /// ColorPlus extended graphics mode.
+    fn get_colorplus_dot_color(&self, colorplus: &ColorPlus, plane0: u16, plane1: u16, dot: u8) -> u8 {
+        let pel = colorplus.pel(plane0, plane1, dot);
+        if colorplus.mode_16color() {
+            pel
+        }
+        else if pel == 0 {
+            self.cc_altcolor
+        }
+        else {
+            CGA_PALETTES[self.cc_palette][pel as usize]
+        }
+    }
+
+    /// Fetch the pair of graphics words for the current character clock from both ColorPlus
+    /// banks.
+    fn get_colorplus_planes(&self, colorplus: &ColorPlus) -> (u16, u16) {
+        let base_addr = self.get_gfx_addr(self.vlc_c9);
+        let plane0 = (self.mem[base_addr] as u16) << 8 | self.mem[base_addr + 1] as u16;
+        (plane0, colorplus.read_word(base_addr))
+    }
+
+    /// Draw two dots in a ColorPlus extended graphics mode.
+    pub fn draw_colorplus_pixel(&mut self) {
+        if self.rba >= CGA_MAX_CLOCK - 2 {
+            return;
+        }
+
+        if let Some(colorplus) = &self.colorplus {
+            let (plane0, plane1) = self.get_colorplus_planes(colorplus);
+            let dot = self.char_col * 2;
+            let color0 = self.get_colorplus_dot_color(colorplus, plane0, plane1, dot);
+            let color1 = self.get_colorplus_dot_color(colorplus, plane0, plane1, dot + 1);
+
+            self.buf[self.back_buf][self.rba] = color0;
+            self.buf[self.back_buf][self.rba + 1] = color1;
+        }
+    }
+
+    /// Draw a full character clock (16 dots) in a ColorPlus extended graphics mode.
+    pub fn draw_colorplus_char(&mut self) {
+        let mut dots = [0u8; CGA_LCHAR_CLOCK as usize];
+
+        if let Some(colorplus) = &self.colorplus {
+            let (plane0, plane1) = self.get_colorplus_planes(colorplus);
+            for (i, dot) in dots.iter_mut().enumerate() {
+                *dot = self.get_colorplus_dot_color(colorplus, plane0, plane1, i as u8);
+            }
+        }
+
+        self.buf[self.back_buf][self.rba..self.rba + dots.len()].copy_from_slice(&dots);
+    }
 }
@@ -94,6 +94,9 @@ impl IoDevice for CGACard {
 
         //self.rw_op(ticks, data, port as u32, RwSlotType::Io);
 
+        self.log_register_write(port, data);
+        self.check_breakpoints(port, data);
+
         if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Write is to CRTC register.
             if port & 0x01 == 0 {
@@ -111,6 +114,11 @@ impl IoDevice for CGACard {
                 CGA_COLOR_CONTROL_REGISTER => {
                     self.handle_cc_register_write(data);
                 }
+                COLORPLUS_CONTROL_REGISTER => {
+                    if let Some(colorplus) = &mut self.colorplus {
+                        colorplus.write_control(data);
+                    }
+                }
                 CGA_LIGHTPEN_LATCH_RESET => self.clear_lp_latch(),
                 CGA_LIGHTPEN_LATCH_SET => {
                     log::debug!("wrote latch set register");
@@ -122,7 +130,7 @@ impl IoDevice for CGACard {
     }
 
     fn port_list(&self) -> Vec<(String, u16)> {
-        vec![
+        let mut ports = vec![
             ("CRTC Address".into(), CRTC_REGISTER_SELECT0),
             ("CRTC Data".into(), CRTC_REGISTER0),
             ("CRTC Address".into(), CRTC_REGISTER_SELECT1),
@@ -134,6 +142,11 @@ impl IoDevice for CGACard {
             ("CGA LP Latch Reset".into(), CGA_LIGHTPEN_LATCH_RESET),
             ("CGA LP Latch Set".into(), CGA_LIGHTPEN_LATCH_SET),
             ("CGA Status".into(), CGA_STATUS_REGISTER),
-        ]
+        ];
+
+        if self.colorplus.is_some() {
+            ports.push(("ColorPlus Control".into(), COLORPLUS_CONTROL_REGISTER));
+        }
+        ports
     }
 }
@@ -67,7 +67,13 @@ impl MemoryMappedDevice for CGACard {
             self.catch_up(DeviceRunTimeUnit::SystemTicks(cycles * 3));
         }*/
 
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
+        let (bank, a_offset) = self.resolve_vram_address(address);
+        if bank > 0 {
+            // Second bank of a ColorPlus. Snow is only emulated for the first bank.
+            let byte = self.colorplus.as_ref().map_or(0xFF, |cp| cp.mem()[a_offset]);
+            trace!(self, "READ_U8 (BANK 1): {:04X}:{:02X}", a_offset, byte);
+            return (byte, 0);
+        }
         if a_offset < CGA_MEM_SIZE {
             // Do snow every other hchar
             if self.cycles & 0b1000 == 0 {
@@ -92,19 +98,26 @@ impl MemoryMappedDevice for CGACard {
     }
 
     fn mmio_peek_u8(&self, address: usize, _cpumem: Option<&[u8]>) -> u8 {
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
-
-        self.mem[a_offset]
+        match self.resolve_vram_address(address) {
+            (0, a_offset) => self.mem[a_offset],
+            (_, a_offset) => self.colorplus.as_ref().map_or(0xFF, |cp| cp.mem()[a_offset]),
+        }
     }
 
-    fn mmio_peek_u16(&self, address: usize, _cpumem: Option<&[u8]>) -> u16 {
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
-
-        (self.mem[a_offset] as u16) << 8 | self.mem[a_offset + 1] as u16
+    fn mmio_peek_u16(&self, address: usize, cpumem: Option<&[u8]>) -> u16 {
+        (MemoryMappedDevice::mmio_peek_u8(self, address, cpumem) as u16) << 8
+            | MemoryMappedDevice::mmio_peek_u8(self, address + 1, cpumem) as u16
     }
 
     fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
+        let (bank, a_offset) = self.resolve_vram_address(address);
+        if bank > 0 {
+            if let Some(colorplus) = &mut self.colorplus {
+                colorplus.mem_mut()[a_offset] = byte;
+            }
+            trace!(self, "WRITE_U8 (BANK 1): {:04X}:{:02X}", a_offset, byte);
+            return 0;
+        }
         if a_offset < CGA_MEM_SIZE {
             // Save bus parameters for snow emulation
             self.last_bus_addr = a_offset;
@@ -42,12 +42,15 @@ use std::{collections::HashMap, convert::TryInto, path::Path};
 
 #[macro_use]
 mod io;
+mod colorplus;
 mod draw;
 mod mmio;
+mod savestate;
 mod tablegen;
 mod videocard;
 
 use super::*;
+use colorplus::*;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
@@ -137,12 +140,17 @@ const US_PER_CLOCK: f64 = 1.0 / CGA_CLOCK;
     the CGA. This is accomplished with a HorizontalDisplayed value of 96. (96 * 8 = 768)
     I am assuming this is the highest value we will actually ever encounter and anything
     wider might not sync to a real monitor.
+
+    European clones driving a PAL monitor program the CRTC for 312 scanlines instead,
+    giving 14,318,180Hz / 284,544 clocks or a 50.32Hz refresh rate. The frame buffer is
+    sized for the larger PAL field so that either standard fits.
 */
 
 // Calculate the maximum possible area of buf field (including refresh period)
 const CGA_XRES_MAX: u32 = (CRTC_R0_HORIZONTAL_MAX + 1) * CGA_HCHAR_CLOCK as u32;
 const CGA_YRES_MAX: u32 = CRTC_SCANLINE_MAX;
-pub const CGA_MAX_CLOCK: usize = (CGA_XRES_MAX * CGA_YRES_MAX) as usize; // Should be 238944
+const CGA_YRES_MAX_PAL: u32 = CRTC_SCANLINE_MAX_PAL;
+pub const CGA_MAX_CLOCK: usize = (CGA_XRES_MAX * CGA_YRES_MAX_PAL) as usize; // Should be 284544
 
 // Monitor sync position. The monitor will eventually perform an hsync at a fixed position
 // if hsync signal is late from the CGA card.
@@ -151,6 +159,8 @@ const CGA_MONITOR_HSYNC_WIDTH: u32 = 80;
 const CGA_MONITOR_VSYNC_POS: u32 = 246;
 // Minimum scanline value after which we can perform a vsync. A vsync before this scanline will be ignored.
 const CGA_MONITOR_VSYNC_MIN: u32 = 127;
+// Nominal horizontal scan rate of a CGA monitor, in kHz.
+const CGA_MONITOR_HFREQ: f64 = 15.699;
 
 // For derivation of CGA timings, see https://www.vogons.org/viewtopic.php?t=47052
 // We run the CGA card independent of the CPU frequency.
@@ -212,6 +222,7 @@ const CRTC_VSYNC_HEIGHT: u8 = 16;
 
 const CRTC_R0_HORIZONTAL_MAX: u32 = 113;
 const CRTC_SCANLINE_MAX: u32 = 262;
+const CRTC_SCANLINE_MAX_PAL: u32 = 312;
 
 // The CGA card decodes different numbers of address lines from the CRTC depending on
 // whether it is in text or graphics modes. This causes wrapping at 0x2000 bytes in
@@ -544,6 +555,9 @@ pub struct CGACard {
 
     mem: Box<[u8; CGA_MEM_SIZE]>,
 
+    subtype: VideoCardSubType,
+    colorplus: Option<ColorPlus>,
+
     back_buf: usize,
     front_buf: usize,
     extents: DisplayExtents,
@@ -558,6 +572,16 @@ pub struct CGACard {
 
     lightpen_latch: bool,
     lightpen_addr:  usize,
+    lightpen_pos:   Option<(u32, u32)>,
+
+    reg_log: Option<VideoRegisterLog>,
+    reg_log_last: Option<VideoRegisterLog>,
+
+    breakpoints: Vec<VideoBreakpoint>,
+    breakpoint_hit: bool,
+
+    raster_regions: RasterRegions,
+    raster_regions_last: Option<RasterRegions>,
 
     out_of_sync: bool,
 }
@@ -599,6 +623,7 @@ impl CgaDefault for DisplayExtents {
             row_stride: CGA_XRES_MAX as usize,
             double_scan: true,
             mode_byte: 0,
+            standard: VideoStandard::Ntsc,
         }
     }
 }
@@ -734,6 +759,9 @@ impl Default for CGACard {
 
             mem: vec![0; CGA_MEM_SIZE].into_boxed_slice().try_into().unwrap(),
 
+            subtype: VideoCardSubType::None,
+            colorplus: None,
+
             back_buf:  1,
             front_buf: 0,
             extents:   CgaDefault::default(),
@@ -756,6 +784,16 @@ impl Default for CGACard {
 
             lightpen_latch: false,
             lightpen_addr:  0,
+            lightpen_pos:   None,
+
+            reg_log: None,
+            reg_log_last: None,
+
+            breakpoints: Vec::new(),
+            breakpoint_hit: false,
+
+            raster_regions: Default::default(),
+            raster_regions_last: None,
 
             out_of_sync: false,
         }
@@ -763,9 +801,25 @@ impl Default for CGACard {
 }
 
 impl CGACard {
-    pub fn new(trace_logger: TraceLogger, clock_mode: ClockingMode, _video_frame_debug: bool) -> Self {
+    pub fn new(
+        subtype: VideoCardSubType,
+        standard: VideoStandard,
+        trace_logger: TraceLogger,
+        clock_mode: ClockingMode,
+        _video_frame_debug: bool,
+    ) -> Self {
         let mut cga = Self::default();
 
+        cga.subtype = subtype;
+        if let VideoCardSubType::ColorPlus = subtype {
+            cga.colorplus = Some(ColorPlus::new());
+        }
+        if let VideoStandard::Pal = standard {
+            // Extend the field and debug aperture to cover the full PAL frame.
+            cga.extents.field_h = CGA_YRES_MAX_PAL;
+            cga.extents.apertures[DisplayApertureType::Debug as usize].h = CGA_YRES_MAX_PAL;
+        }
+        cga.extents.standard = standard;
         cga.trace_logger = trace_logger;
         //cga.debug = video_frame_debug;
 
@@ -790,12 +844,32 @@ impl CGACard {
             enable_snow: self.enable_snow,
             frame_count: self.frame_count, // Keep frame count as to not confuse frontend
             trace_logger,
+            reg_log: self.reg_log.as_ref().map(|_| VideoRegisterLog::default()),
+            breakpoints: self.breakpoints.clone(),
             extents: self.extents.clone(),
+            subtype: self.subtype,
+            colorplus: self.colorplus.as_ref().map(|_| ColorPlus::new()),
 
             ..Self::default()
         }
     }
 
+    /// Resolve a CPU address within the CGA aperture to a VRAM bank and an offset within that
+    /// bank. Without the ColorPlus extensions, the 16K of VRAM is mirrored across the aperture.
+    #[inline]
+    fn resolve_vram_address(&self, address: usize) -> (usize, usize) {
+        match &self.colorplus {
+            Some(colorplus) => colorplus.resolve(address - CGA_MEM_ADDRESS, self.mode_graphics),
+            None => (0, (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS),
+        }
+    }
+
+    /// Return whether a ColorPlus extended graphics mode is currently being displayed.
+    #[inline]
+    fn colorplus_active(&self) -> bool {
+        self.mode_graphics && self.colorplus.as_ref().map_or(false, |cp| cp.extended_mode())
+    }
+
     fn rw_op(&mut self, ticks: u32, data: u8, addr: u32, rwtype: RwSlotType) {
         assert!(self.slot_idx < 4);
 
@@ -900,11 +974,84 @@ impl CGACard {
         self.lightpen_latch = true;
     }
 
+    /// Enable or disable logging of register writes.
+    fn set_register_logging(&mut self, state: bool) {
+        self.reg_log = state.then(|| VideoRegisterLog {
+            frame:  self.frame_count,
+            writes: Vec::new(),
+        });
+        self.reg_log_last = None;
+    }
+
+    /// Record a register write along with the current raster position, if logging is enabled.
+    fn log_register_write(&mut self, port: u16, data: u8) {
+        if let Some(log) = &mut self.reg_log {
+            let index = if (port & !io::CRTC_REGISTER_MASK) == io::CRTC_REGISTER_BASE && (port & 0x01 != 0) {
+                Some(self.crtc_register_select_byte)
+            }
+            else {
+                None
+            };
+            log.writes.push(VideoRegisterWrite {
+                scanline: self.beam_y,
+                hdot: self.beam_x,
+                port,
+                index,
+                value: data,
+            });
+        }
+    }
+
+    /// Set the breakpoint flag if a register write matches any of the current video breakpoints.
+    fn check_breakpoints(&mut self, port: u16, data: u8) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+
+        let hit = if port == io::CGA_MODE_CONTROL_REGISTER {
+            self.breakpoints.iter().any(|bp| bp.matches_mode_write(data))
+        }
+        else if (port & !io::CRTC_REGISTER_MASK) == io::CRTC_REGISTER_BASE && (port & 0x01 != 0) {
+            let reg = self.crtc_register_select_byte;
+            self.breakpoints.iter().any(|bp| bp.matches_crtc_write(reg, data))
+        }
+        else {
+            false
+        };
+
+        if hit {
+            log::debug!("Video breakpoint hit: write of {:02X} to port {:03X}", data, port);
+            self.breakpoint_hit = true;
+        }
+    }
+
     fn clear_lp_latch(&mut self) {
         log::debug!("clearing lightpen latch");
         self.lightpen_latch = false;
     }
 
+    /// Trigger the light pen latch if the character just drawn passed under the light pen.
+    /// The pen only sees the beam within the display area.
+    #[inline]
+    fn check_lp_trigger(&mut self) {
+        if let Some((pen_x, pen_y)) = self.lightpen_pos {
+            let char_w = CGA_HCHAR_CLOCK as u32 * self.clock_divisor as u32;
+            if self.in_display_area && self.beam_y == pen_y && pen_x < self.beam_x && pen_x + char_w >= self.beam_x {
+                self.set_lp_latch();
+            }
+        }
+    }
+
+    /// Expand the display area of the current frame's raster regions if the character just drawn
+    /// was within it.
+    #[inline]
+    fn mark_raster_regions(&mut self) {
+        if self.in_display_area {
+            let char_w = CGA_HCHAR_CLOCK as u32 * self.clock_divisor as u32;
+            self.raster_regions.mark_display(self.beam_x.saturating_sub(char_w), self.beam_y, char_w);
+        }
+    }
+
     fn get_cursor_span(&self) -> (u8, u8) {
         (self.crtc_cursor_start_line, self.crtc_cursor_end_line)
     }
@@ -1353,7 +1500,9 @@ impl CGACard {
         }
 
         // This bit is logically reversed, i.e., 0 is switch on
-        //byte |= STATUS_LIGHTPEN_SWITCH_STATUS;
+        if self.lightpen_pos.is_none() {
+            byte |= STATUS_LIGHTPEN_SWITCH_STATUS;
+        }
 
         trace_regs!(self);
         trace!(
@@ -2011,6 +2160,9 @@ impl CGACard {
 
     /// Update the CRTC logic for next character.
     pub fn tick_crtc_char(&mut self) {
+        self.check_lp_trigger();
+        self.mark_raster_regions();
+
         if self.hcc_c0 == 0 {
             self.hborder = false;
             if self.vcc_c4 == 0 {
@@ -2124,6 +2276,7 @@ impl CGACard {
         if self.hcc_c0 == self.crtc_horizontal_sync_pos {
             // We entered horizontal blank
             self.in_crtc_hblank = true;
+            self.raster_regions.mark_hblank(self.beam_x);
             self.hsc_c3l = 0;
         }
 
@@ -2181,6 +2334,7 @@ impl CGACard {
                     trace_regs!(self);
                     trace!(self, "Entering vsync");
                     self.in_crtc_vblank = true;
+                    self.raster_regions.mark_vblank(self.beam_y);
                     self.in_display_area = false;
                 }
 
@@ -2283,6 +2437,17 @@ impl CGACard {
             self.scanline = 0;
             self.frame_count += 1;
 
+            self.raster_regions_last = Some(std::mem::take(&mut self.raster_regions));
+
+            // Start a new register log for the next frame.
+            if let Some(log) = &mut self.reg_log {
+                let new_log = VideoRegisterLog {
+                    frame:  self.frame_count,
+                    writes: Vec::new(),
+                };
+                self.reg_log_last = Some(std::mem::replace(log, new_log));
+            }
+
             // Save the current mode byte, used for composite rendering.
             // The mode could have changed several times per frame, but I am not sure how the composite rendering should
             // really handle that...
@@ -0,0 +1,101 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::cga::savestate.rs
+
+    Implements save state support for the IBM CGA card.
+
+    Video memory and the register file are saved, along with the ColorPlus
+    extension if installed. On restore the registers are written back through
+    the same paths as a port write so that all derived state is recalculated.
+    Raster position is not saved; the card continues scanning from wherever
+    it currently is and resynchronizes on the next frame.
+
+*/
+
+use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+impl CGACard {
+    pub fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        let crtc_regs = [
+            self.crtc_horizontal_total,
+            self.crtc_horizontal_displayed,
+            self.crtc_horizontal_sync_pos,
+            self.crtc_sync_width,
+            self.crtc_vertical_total,
+            self.crtc_vertical_total_adjust,
+            self.crtc_vertical_displayed,
+            self.crtc_vertical_sync_pos,
+            self.crtc_interlace_mode,
+            self.crtc_maximum_scanline_address,
+            self.crtc_cursor_start_line | (self.cursor_attr << 5),
+            self.crtc_cursor_end_line,
+            self.crtc_start_address_ho,
+            self.crtc_start_address_lo,
+            self.crtc_cursor_address_ho,
+            self.crtc_cursor_address_lo,
+        ];
+
+        w.write_bytes(&crtc_regs);
+        w.write_u8(self.crtc_register_select_byte);
+        w.write_u8(self.mode_byte);
+        w.write_u8(self.cc_register);
+        w.write_bytes(&*self.mem);
+        w.write_bool(self.colorplus.is_some());
+        if let Some(colorplus) = &self.colorplus {
+            colorplus.save_state(w);
+        }
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut crtc_regs = [0u8; 16];
+        r.read_bytes_into(&mut crtc_regs, "CGA CRTC registers")?;
+        let register_select = r.read_u8()?;
+        let mode_byte = r.read_u8()?;
+        let cc_register = r.read_u8()?;
+        r.read_bytes_into(&mut *self.mem, "CGA memory")?;
+        match (r.read_bool()?, &mut self.colorplus) {
+            (true, Some(colorplus)) => colorplus.load_state(r)?,
+            (false, None) => {}
+            _ => return Err(SaveStateError::MachineMismatch("ColorPlus".to_string())),
+        }
+
+        for (idx, byte) in crtc_regs.iter().enumerate() {
+            self.handle_crtc_register_select(idx as u8);
+            self.handle_crtc_register_write(*byte);
+        }
+        self.handle_crtc_register_select(register_select);
+
+        // Apply the mode immediately instead of deferring it to the next hsync.
+        self.mode_pending = false;
+        self.mode_byte = mode_byte;
+        self.update_mode();
+        self.handle_cc_register_write(cc_register);
+        Ok(())
+    }
+}
@@ -31,7 +31,11 @@
 */
 
 use super::*;
-use crate::{device_traits::videocard::*, devices::pic::Pic};
+use crate::{
+    device_traits::videocard::*,
+    devices::pic::Pic,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 
 // Helper macro for pushing video card state entries.
 // For CGA, we put the decorator first as there is only one register file an we use it to show the register index.
@@ -78,6 +82,10 @@ impl VideoCard for CGACard {
                 log::debug!("VideoOption::DebugDraw set to: {}", state);
                 self.debug_draw = state;
             }
+            VideoOption::LogRegisterWrites(state) => {
+                log::debug!("VideoOption::LogRegisterWrites set to: {}", state);
+                self.set_register_logging(state);
+            }
         }
     }
 
@@ -136,6 +144,27 @@ impl VideoCard for CGACard {
         Some((self.beam_x, self.beam_y))
     }
 
+    fn set_light_pen(&mut self, pos: Option<(u32, u32)>) {
+        self.lightpen_pos = pos;
+    }
+
+    fn get_register_log(&self) -> Option<&VideoRegisterLog> {
+        self.reg_log_last.as_ref()
+    }
+
+    fn set_breakpoints(&mut self, bp_list: Vec<VideoBreakpoint>) {
+        self.breakpoints = bp_list;
+        self.breakpoint_hit = false;
+    }
+
+    fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::replace(&mut self.breakpoint_hit, false)
+    }
+
+    fn get_raster_regions(&self) -> Option<RasterRegions> {
+        self.raster_regions_last
+    }
+
     /// Tick the CGA the specified number of video clock cycles.
     fn debug_tick(&mut self, ticks: u32, _cpumem: Option<&[u8]>) {
         match self.clock_mode {
@@ -209,9 +238,13 @@ impl VideoCard for CGACard {
         &self.buf[self.front_buf][..]
     }
 
-    /// Get the current display refresh rate of the device. For CGA, this is always 60.
+    /// Get the current display refresh rate of the device. For CGA, this is 60 for NTSC
+    /// and 50 for PAL.
     fn get_refresh_rate(&self) -> u32 {
-        60
+        match self.extents.standard {
+            VideoStandard::Ntsc => 60,
+            VideoStandard::Pal => 50,
+        }
     }
 
     fn is_40_columns(&self) -> bool {
@@ -233,6 +266,10 @@ impl VideoCard for CGACard {
     }
 
     /// Return the 16-bit value computed from the CRTC's pair of Page Address registers.
+    fn get_vram(&self) -> Option<&[u8]> {
+        Some(&self.mem[..])
+    }
+
     fn get_start_address(&self) -> u16 {
         return (self.crtc_start_address_ho as u16) << 8 | self.crtc_start_address_lo as u16;
     }
@@ -287,6 +324,60 @@ impl VideoCard for CGACard {
         None
     }
     
+    fn get_palette_state(&self) -> Option<PaletteState> {
+        let palette = if self.mode_hires_gfx {
+            vec![(0, PaletteColor::Irgb(0)), (1, PaletteColor::Irgb(self.cc_altcolor))]
+        }
+        else if self.mode_graphics {
+            CGA_PALETTES[self.cc_palette]
+                .iter()
+                .enumerate()
+                .map(|(i, &color)| {
+                    // Color 0 is the background color, selected by the color control register.
+                    let color = if i == 0 { self.cc_altcolor } else { color };
+                    (i as u8, PaletteColor::Irgb(color))
+                })
+                .collect()
+        }
+        else {
+            (0..16).map(|i| (i, PaletteColor::Irgb(i))).collect()
+        };
+
+        Some(PaletteState {
+            palette,
+            overscan: Some(PaletteColor::Irgb(self.cc_overscan_color)),
+            blink_enabled: self.mode_blinking,
+            blink_state: self.blink_state,
+            dac: Vec::new(),
+        })
+    }
+
+    fn get_crtc_timing(&self) -> Option<CrtcTiming> {
+        let regs = [
+            self.crtc_horizontal_total,
+            self.crtc_horizontal_displayed,
+            self.crtc_horizontal_sync_pos,
+            self.crtc_sync_width,
+            self.crtc_vertical_total,
+            self.crtc_vertical_total_adjust,
+            self.crtc_vertical_displayed,
+            self.crtc_vertical_sync_pos,
+            self.crtc_interlace_mode,
+            self.crtc_maximum_scanline_address,
+        ];
+        Some(CrtcTiming::from_6845(&regs, CGA_CLOCK / self.char_clock as f64, CGA_MONITOR_HFREQ))
+    }
+
+    fn get_character_sets(&self) -> Vec<CharacterSet> {
+        vec![CharacterSet::from_row_major(
+            "Character ROM",
+            CGA_HCHAR_CLOCK as u32,
+            CRTC_FONT_HEIGHT as u32,
+            CGA_FONT_SPAN,
+            CGA_FONT,
+        )]
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc_maximum_scanline_address + 1
     }
@@ -302,6 +393,9 @@ impl VideoCard for CGACard {
         general_vec.push((String::from("Video Enable:"), VideoCardStateEntry::String(format!("{:?}", self.mode_enable))));
         general_vec.push((String::from("Clock Divisor:"), VideoCardStateEntry::String(format!("{}", self.clock_divisor))));
         general_vec.push((String::from("Frame Count:"), VideoCardStateEntry::String(format!("{}", self.frame_count))));
+        if let Some(colorplus) = &self.colorplus {
+            general_vec.push((String::from("ColorPlus Control:"), VideoCardStateEntry::String(format!("{:08b}", colorplus.control()))));
+        }
         map.insert("General".to_string(), general_vec);
 
         let mut crtc_vec = Vec::new();
@@ -588,4 +682,34 @@ impl VideoCard for CGACard {
 
         strings
     }
+
+    fn get_text_mode_screen(&self, full_buffer: bool) -> Option<TextModeScreen> {
+        let columns = self.crtc_horizontal_displayed as usize;
+        if self.mode_graphics || columns == 0 {
+            return None;
+        }
+
+        let (start, rows) = match full_buffer {
+            true => (0, (CGA_TEXT_MODE_WRAP + 1) / columns),
+            false => (self.crtc_start_address, self.crtc_vertical_displayed as usize),
+        };
+
+        Some(TextModeScreen::from_text_buffer(
+            &self.mem[..],
+            start,
+            columns,
+            rows,
+            CGA_TEXT_MODE_WRAP,
+            false,
+            self.mode_blinking,
+        ))
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
 }
@@ -28,9 +28,17 @@
 
     Implements the Intel 8237 DMA Controller
 
+    An AT-style secondary controller can be created with new_secondary().
+    The secondary controller decodes ports C0-DF on even addresses, performs
+    16-bit word transfers on channels 5-7, and uses channel 4 to cascade the
+    primary controller.
+
 */
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 pub const DMA_CHANNEL_0_ADDR_PORT: u16 = 0x00; // R/W
 pub const DMA_CHANNEL_0_WC_PORT: u16 = 0x01; // R/W
@@ -61,6 +69,17 @@ pub const DMA_CHANNEL_1_PAGE_REGISTER: u16 = 0x83; // R/W
 pub const DMA_CHANNEL_2_PAGE_REGISTER: u16 = 0x81; // R/W
 pub const DMA_CHANNEL_3_PAGE_REGISTER: u16 = 0x82; // R/W
 
+// Secondary (16-bit) controller ports. Registers are on even addresses from the base.
+pub const DMA2_BASE_PORT: u16 = 0xC0;
+pub const DMA2_LAST_PORT: u16 = 0xDF;
+pub const DMA_CHANNEL_4_PAGE_REGISTER: u16 = 0x8F; // R/W (unused, channel 4 is cascade)
+pub const DMA_CHANNEL_5_PAGE_REGISTER: u16 = 0x8B; // R/W
+pub const DMA_CHANNEL_6_PAGE_REGISTER: u16 = 0x89; // R/W
+pub const DMA_CHANNEL_7_PAGE_REGISTER: u16 = 0x8A; // R/W
+
+/// The channel of the secondary controller that the primary controller is cascaded to.
+pub const DMA_CASCADE_CHANNEL: usize = 0;
+
 // Control byte bit fields - not all of these are implemented
 pub const DMA_COMMAND_MEM_TO_MEM: u8 = 0x01;
 pub const DMA_COMMAND_CHANNEL_0_HOLD: u8 = 0x02;
@@ -131,7 +150,6 @@ pub struct DMAChannel {
     transfer_type: TransferType,
     terminal_count: bool,
     terminal_count_reached: bool,
-    request: bool,
     masked: bool,
     page: u8,
 }
@@ -176,10 +194,15 @@ pub struct DMAController {
     temp_reg: u8,
 
     dreq: bool,
+
+    // Secondary controller state
+    secondary: bool,
+    cascade_request: bool,
 }
 
 impl IoDevice for DMAController {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        let port = self.translate_port(port);
         match port {
             DMA_CHANNEL_0_ADDR_PORT => self.handle_addr_port_read(0),
             DMA_CHANNEL_1_ADDR_PORT => self.handle_addr_port_read(1),
@@ -203,6 +226,7 @@ impl IoDevice for DMAController {
     }
 
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        let port = self.translate_port(port);
         match port {
             DMA_CHANNEL_0_ADDR_PORT => {
                 self.handle_addr_port_write(0, data);
@@ -272,7 +296,7 @@ impl IoDevice for DMAController {
     }
 
     fn port_list(&self) -> Vec<(String, u16)> {
-        vec![
+        let ports = vec![
             (String::from("DMA Channel 0 Address"), DMA_CHANNEL_0_ADDR_PORT),
             (String::from("DMA Channel 0 Word Count"), DMA_CHANNEL_0_WC_PORT),
             (String::from("DMA Channel 1 Address"), DMA_CHANNEL_1_ADDR_PORT),
@@ -294,7 +318,32 @@ impl IoDevice for DMAController {
             (String::from("DMA Channel 1 Page Register"), DMA_CHANNEL_1_PAGE_REGISTER),
             (String::from("DMA Channel 2 Page Register"), DMA_CHANNEL_2_PAGE_REGISTER),
             (String::from("DMA Channel 3 Page Register"), DMA_CHANNEL_3_PAGE_REGISTER),
-        ]
+        ];
+
+        if !self.secondary {
+            return ports;
+        }
+
+        // Renumber channels and map ports for the secondary controller.
+        ports
+            .into_iter()
+            .map(|(name, port)| {
+                let name = name
+                    .replace("DMA Channel 3", "DMA Channel 7")
+                    .replace("DMA Channel 2", "DMA Channel 6")
+                    .replace("DMA Channel 1", "DMA Channel 5")
+                    .replace("DMA Channel 0", "DMA Channel 4")
+                    .replace("DMA ", "DMA2 ");
+                let port = match port {
+                    DMA_CHANNEL_0_PAGE_REGISTER => DMA_CHANNEL_4_PAGE_REGISTER,
+                    DMA_CHANNEL_1_PAGE_REGISTER => DMA_CHANNEL_5_PAGE_REGISTER,
+                    DMA_CHANNEL_2_PAGE_REGISTER => DMA_CHANNEL_6_PAGE_REGISTER,
+                    DMA_CHANNEL_3_PAGE_REGISTER => DMA_CHANNEL_7_PAGE_REGISTER,
+                    _ => DMA2_BASE_PORT + (port << 1),
+                };
+                (name, port)
+            })
+            .collect()
     }
 }
 
@@ -302,7 +351,10 @@ impl DMAController {
     pub fn new() -> Self {
         Self {
             enabled: true,
-            mem_to_mem_enabled: true,
+            // Memory-to-memory transfers are disabled until enabled via the command register, as
+            // on a real 8237 after reset. PIT channel 1 raises DREQ0 for DRAM refresh, which would
+            // otherwise start a memory-to-memory transfer before the BIOS programs the controller.
+            mem_to_mem_enabled: false,
             channel_0_hold_enabled: false,
             timing_mode: TimingMode::NormalTiming,
             priority_mode: PriorityMode::Fixed,
@@ -320,9 +372,69 @@ impl DMAController {
             temp_reg: 0,
 
             dreq: false,
+
+            secondary: false,
+            cascade_request: false,
+        }
+    }
+
+    /// Create a secondary, 16-bit DMA controller as found on the IBM AT.
+    pub fn new_secondary() -> Self {
+        let mut dma = Self::new();
+        dma.secondary = true;
+        dma
+    }
+
+    pub fn is_secondary(&self) -> bool {
+        self.secondary
+    }
+
+    /// Translate a port address to the equivalent primary controller port. The secondary controller
+    /// decodes its registers on even addresses starting at C0h.
+    fn translate_port(&self, port: u16) -> u16 {
+        if !self.secondary {
+            return port;
+        }
+        match port {
+            DMA_CHANNEL_4_PAGE_REGISTER => DMA_CHANNEL_0_PAGE_REGISTER,
+            DMA_CHANNEL_5_PAGE_REGISTER => DMA_CHANNEL_1_PAGE_REGISTER,
+            DMA_CHANNEL_6_PAGE_REGISTER => DMA_CHANNEL_2_PAGE_REGISTER,
+            DMA_CHANNEL_7_PAGE_REGISTER => DMA_CHANNEL_3_PAGE_REGISTER,
+            DMA2_BASE_PORT..=DMA2_LAST_PORT => (port - DMA2_BASE_PORT) >> 1,
+            _ => port,
+        }
+    }
+
+    /// Return the state of the HRQ output. HRQ is asserted when any channel has an active request
+    /// that is not masked. Software requests cannot be masked.
+    pub fn hrq(&self) -> bool {
+        self.enabled
+            && self
+                .channels
+                .iter()
+                .enumerate()
+                .any(|(i, chan)| (self.request_reg & (0x01 << i) != 0) && !chan.masked)
+    }
+
+    /// Set the DREQ state of the cascade channel, driven by the HRQ output of the primary controller.
+    pub fn set_cascade_request(&mut self, state: bool) {
+        self.cascade_request = state;
+        if state {
+            self.request_reg |= 0x01 << DMA_CASCADE_CHANNEL;
+        }
+        else {
+            self.request_reg &= !(0x01 << DMA_CASCADE_CHANNEL);
         }
     }
 
+    /// Return whether the cascade channel acknowledges the primary controller's request, ie, the
+    /// cascade channel is programmed for cascade mode and is unmasked. This represents HLDA to the
+    /// primary controller.
+    pub fn cascade_acknowledge(&self) -> bool {
+        let chan = &self.channels[DMA_CASCADE_CHANNEL];
+        self.enabled && self.cascade_request && !chan.masked && matches!(chan.service_mode, ServiceMode::Cascade)
+    }
+
     /// Reset the DMA controller
     pub fn reset(&mut self) {
         // TODO:
@@ -435,7 +547,7 @@ impl DMAController {
             }
 
             // Intel: Bits 4-7 are set whenever their corresponding channel is requesting service.
-            if chan.request {
+            if self.request_reg & (0x01 << i) != 0 {
                 status_byte |= 0x01 << (i + 4);
             }
         }
@@ -475,7 +587,18 @@ impl DMAController {
     }
 
     pub fn handle_write_req_register(&mut self, data: u8) {
-        log::debug!("DMA: Unimplemented write to Write Request Register: {:02X}", data);
+        // Bits 0-1: Channel Number
+        // Bit 2: Request bit state
+        // Intel: Software requests are non-maskable and are the only way to initiate a memory-to-memory
+        // transfer, by setting the request bit for channel 0.
+        let chan = data & 0x03;
+        if data & 0x04 != 0 {
+            log::trace!("DMA: Software request on channel {}", chan);
+            self.request_reg |= 0x01 << chan;
+        }
+        else {
+            self.request_reg &= !(0x01 << chan);
+        }
     }
 
     pub fn handle_channel_mask_register_write(&mut self, data: u8) {
@@ -544,7 +667,8 @@ impl DMAController {
         for chan in &mut self.channels {
             chan.masked = true;
         }
-        self.command_register = 0;
+        self.handle_command_register_write(0);
+        self.request_reg = 0;
         self.status_reg = 0;
         self.temp_reg = 0;
         self.flipflop = false;
@@ -607,6 +731,12 @@ impl DMAController {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
         }
+        if self.secondary {
+            // The secondary controller addresses words. The address register is shifted left one bit
+            // to form A1-A16 and bit 0 of the page register is ignored.
+            return (((self.channels[channel].page & 0xFE) as usize) << 16)
+                + ((self.channels[channel].current_address_reg as usize) << 1);
+        }
         let address: usize =
             ((self.channels[channel].page as usize) << 16) + self.channels[channel].current_address_reg as usize;
         address
@@ -649,6 +779,46 @@ impl DMAController {
         self.channels[channel].terminal_count
     }
 
+    /// Perform the address and word count update for a single transfer cycle on the specified channel.
+    /// If `hold_address` is set, the current address is not modified (used by channel 0 address hold
+    /// during memory-to-memory transfers).
+    /// Returns None if the channel has already reached terminal count and no transfer should occur,
+    /// otherwise Some(true) if this cycle reached terminal count, or Some(false) if it did not.
+    fn transfer_cycle(&mut self, channel: usize, hold_address: bool) -> Option<bool> {
+        let chan = &mut self.channels[channel];
+
+        if chan.current_word_count_reg > 0 {
+            if !hold_address {
+                // Internal address register wraps around
+                chan.current_address_reg = match chan.address_mode {
+                    AddressMode::Increment => chan.current_address_reg.wrapping_add(1),
+                    AddressMode::Decrement => chan.current_address_reg.wrapping_sub(1),
+                };
+            }
+            chan.current_word_count_reg -= 1;
+            Some(false)
+        }
+        else if !chan.terminal_count {
+            // Transfer one more on a 0 count, then set TC
+            if chan.auto_init {
+                // Reload channel if auto-init on
+                chan.current_address_reg = chan.base_address_reg;
+                chan.current_word_count_reg = chan.base_word_count_reg;
+            }
+            else {
+                chan.terminal_count = true;
+                log::trace!("Terminal count reached on DMA channel {:01X}", channel);
+            }
+            // Set the tc status bit regardless of auto-init
+            chan.terminal_count_reached = true;
+            Some(true)
+        }
+        else {
+            // Trying to transfer on a terminal count
+            None
+        }
+    }
+
     pub fn do_dma_read_u8(&mut self, bus: &mut BusInterface, channel: usize) -> u8 {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
@@ -659,107 +829,138 @@ impl DMAController {
         }
 
         let mut data: u8 = 0;
-        let mut _cost = 0;
         let bus_address = self.get_dma_transfer_address(channel);
 
-        match self.channels[channel].address_mode {
-            AddressMode::Increment => {
-                if self.channels[channel].current_word_count_reg > 0 {
-                    (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+        if self.transfer_cycle(channel, false).is_some() {
+            // Don't access memory if in Verify mode
+            if !matches!(self.channels[channel].transfer_type, TransferType::Verify) {
+                (data, _) = bus.read_u8(bus_address, 0).unwrap();
+            }
+            //log::trace!("DMA read {:02X} from address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
+        }
 
-                    if self.channels[channel].current_word_count_reg == 1 {
-                        //log::trace!("car: {} cwc: {} ", self.channels[channel].current_address_reg, self.channels[channel].current_word_count_reg);
-                    }
+        data
+    }
 
-                    // Internal address register wraps around
-                    self.channels[channel].current_address_reg =
-                        self.channels[channel].current_address_reg.wrapping_add(1);
-                    self.channels[channel].current_word_count_reg -= 1;
+    pub fn do_dma_write_u8(&mut self, bus: &mut BusInterface, channel: usize, data: u8) {
+        if channel >= DMA_CHANNEL_COUNT {
+            panic!("Invalid DMA Channel");
+        }
 
-                    //log::trace!("DMA read {:02X} from address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                }
-                else if self.channels[channel].current_word_count_reg == 0 && !self.channels[channel].terminal_count {
-                    // Transfer one more on a 0 count, then set TC
-                    (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+        let bus_address = self.get_dma_transfer_address(channel);
 
-                    //self.channels[channel].current_address_reg += 1;
+        if let Some(tc) = self.transfer_cycle(channel, false) {
+            // Don't transfer anything if in Verify mode
+            if let TransferType::Write = self.channels[channel].transfer_type {
+                bus.write_u8(bus_address, data, 0).unwrap();
+            }
+            //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
+
+            if tc {
+                log::trace!(
+                    "Completed DMA of {} bytes to address {:05X}",
+                    self.channels[channel].base_word_count_reg as u32 + 1,
+                    ((self.channels[channel].page as u32) << 16) + (self.channels[channel].base_address_reg as u32)
+                );
+            }
+        }
+    }
 
-                    //log::trace!("DMA read {:02X} from address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                    if self.channels[channel].auto_init {
-                        // Reload channel if auto-init on
-                        self.channels[channel].current_address_reg = self.channels[channel].base_address_reg;
-                        self.channels[channel].current_word_count_reg = self.channels[channel].base_word_count_reg;
-                    }
-                    else {
-                        self.channels[channel].terminal_count = true;
-                        log::trace!("Terminal count reached on DMA channel {:01X}", channel);
-                    }
-                    // Set the tc status bit regardless of auto-init
-                    self.channels[channel].terminal_count_reached = true;
-                }
-                else {
-                    // Trying to transfer on a terminal count
-                }
+    /// Perform a 16-bit read transfer from memory on a secondary controller channel.
+    pub fn do_dma_read_u16(&mut self, bus: &mut BusInterface, channel: usize) -> u16 {
+        if channel >= DMA_CHANNEL_COUNT {
+            panic!("Invalid DMA Channel");
+        }
+
+        if !self.enabled {
+            return 0;
+        }
+
+        let mut data: u16 = 0;
+        let bus_address = self.get_dma_transfer_address(channel);
+
+        if self.transfer_cycle(channel, false).is_some() {
+            if !matches!(self.channels[channel].transfer_type, TransferType::Verify) {
+                let (lo, _) = bus.read_u8(bus_address, 0).unwrap();
+                let (hi, _) = bus.read_u8(bus_address + 1, 0).unwrap();
+                data = u16::from_le_bytes([lo, hi]);
             }
-            _ => panic!("DMA Decrement address mode unimplemented"),
         }
 
         data
     }
 
-    pub fn do_dma_write_u8(&mut self, bus: &mut BusInterface, channel: usize, data: u8) {
+    /// Perform a 16-bit write transfer to memory on a secondary controller channel.
+    pub fn do_dma_write_u16(&mut self, bus: &mut BusInterface, channel: usize, data: u16) {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
         }
 
         let bus_address = self.get_dma_transfer_address(channel);
 
-        match self.channels[channel].address_mode {
-            AddressMode::Increment => {
-                if self.channels[channel].current_word_count_reg > 0 {
-                    // Don't transfer anything if in Verify mode
-                    if let TransferType::Write = self.channels[channel].transfer_type {
-                        bus.write_u8(bus_address, data, 0).unwrap();
-                    }
-
-                    self.channels[channel].current_address_reg =
-                        self.channels[channel].current_address_reg.wrapping_add(1);
-                    self.channels[channel].current_word_count_reg -= 1;
+        if self.transfer_cycle(channel, false).is_some() {
+            if let TransferType::Write = self.channels[channel].transfer_type {
+                let [lo, hi] = data.to_le_bytes();
+                bus.write_u8(bus_address, lo, 0).unwrap();
+                bus.write_u8(bus_address + 1, hi, 0).unwrap();
+            }
+        }
+    }
 
-                    //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                }
-                else if self.channels[channel].current_word_count_reg == 0 && !self.channels[channel].terminal_count {
-                    // Transfer one more on a 0 count, then set TC
-                    if let TransferType::Write = self.channels[channel].transfer_type {
-                        bus.write_u8(bus_address, data, 0).unwrap();
-                    }
-                    //self.channels[channel].current_address_reg += 1;
+    /// Perform a memory-to-memory transfer. Channel 0 supplies the source address and channel 1 the
+    /// destination address. Each byte is read into the temporary register and then written out.
+    /// The transfer ends when channel 1 reaches terminal count.
+    fn do_mem_to_mem(&mut self, bus: &mut BusInterface) {
+        let hold = self.channel_0_hold_enabled;
+        log::trace!(
+            "DMA: Memory-to-memory transfer of {} bytes from {:05X} to {:05X} (hold: {})",
+            self.channels[1].current_word_count_reg as u32 + 1,
+            self.get_dma_transfer_address(0),
+            self.get_dma_transfer_address(1),
+            hold
+        );
 
-                    //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                    self.channels[channel].terminal_count = true;
-                    log::trace!("Terminal count reached on DMA channel {:01X}", channel);
-                    log::trace!(
-                        "Completed DMA of {} bytes to address {:05X}",
-                        self.channels[channel].base_word_count_reg + 1,
-                        ((self.channels[channel].page as u32) << 16) + (self.channels[channel].base_address_reg as u32)
-                    );
+        loop {
+            let src_address = self.get_dma_transfer_address(0);
+            let dst_address = self.get_dma_transfer_address(1);
 
-                    // TODO: Support auto-init here
+            // Channel 0 may already be at terminal count if its count was shorter than channel 1.
+            // In that case the source address is held and we continue to write the temp register.
+            if self.transfer_cycle(0, hold).is_some() {
+                (self.temp_reg, _) = bus.read_u8(src_address, 0).unwrap();
+            }
 
-                    // Set the tc status bit regardless of auto-init
-                    self.channels[channel].terminal_count_reached = true;
-                }
-                else {
-                    // Trying to transfer on a terminal count
+            match self.transfer_cycle(1, false) {
+                Some(tc) => {
+                    if !matches!(self.channels[1].transfer_type, TransferType::Verify) {
+                        bus.write_u8(dst_address, self.temp_reg, 0).unwrap();
+                    }
+                    if tc {
+                        break;
+                    }
                 }
+                None => break,
             }
-            _ => panic!("DMA Decrement address mode unimplemented"),
         }
+
+        // Transfer is complete, clear the software request for channel 0.
+        self.request_reg &= !0x01;
     }
 
     /// Fake the DMA controller. This should eventually be replaced by a tick procedure that
     /// ticks in line with the CPU.
     pub fn run(&mut self, bus: &mut BusInterface) {
+        if !self.enabled {
+            return;
+        }
+
+        // A request on channel 0 with memory-to-memory enabled starts a memory-to-memory transfer
+        // instead of a normal channel 0 transfer. The secondary controller cannot do memory-to-memory
+        // transfers as its channel 0 is the cascade channel.
+        if self.mem_to_mem_enabled && !self.secondary && (self.request_reg & 0x01 != 0) {
+            self.do_mem_to_mem(bus);
+        }
+
         for i in 0..DMA_CHANNEL_COUNT {
             if self.request_reg & (0x01 << i) != 0 {
                 // We have an active DREQ on this channel, service it
@@ -767,11 +968,16 @@ impl DMAController {
                     ServiceMode::Single => {
                         // We can handle single byte mode
                         match self.channels[i].transfer_type {
-                            TransferType::Read | TransferType::Verify => {
+                            TransferType::Read => {
                                 if i == 0 {
                                     self.do_dma_read_u8(bus, i);
                                 }
                             }
+                            TransferType::Verify => {
+                                // Verify cycles generate addresses and update counts, but do not
+                                // transfer any data.
+                                self.transfer_cycle(i, false);
+                            }
                             TransferType::Write => {
                                 // nothing to do here
                             }
@@ -783,11 +989,283 @@ impl DMAController {
                         // Since this is single byte service, we can now reset the request register bit.
                         self.request_reg &= !(0x01 << i);
                     }
-                    _ => {
-                        //log::warn!("Unhandled DMA service mode: {:?}", self.channels[i].service_mode);
+                    ServiceMode::Block => {
+                        // A block verify runs until terminal count, and then releases the request.
+                        if let TransferType::Verify = self.channels[i].transfer_type {
+                            while let Some(false) = self.transfer_cycle(i, false) {}
+                            self.request_reg &= !(0x01 << i);
+                        }
+                    }
+                    ServiceMode::Demand => {
+                        // A demand verify runs one cycle per request until terminal count.
+                        if let TransferType::Verify = self.channels[i].transfer_type {
+                            if !matches!(self.transfer_cycle(i, false), Some(false)) {
+                                self.request_reg &= !(0x01 << i);
+                            }
+                        }
+                    }
+                    ServiceMode::Cascade => {
+                        // Cascade channels only pass HRQ/HLDA between controllers; the cascaded
+                        // controller performs the transfer. Nothing to do here.
                     }
                 }
             }
         }
     }
 }
+
+impl SaveState for DMAChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.current_address_reg);
+        w.write_u16(self.current_word_count_reg);
+        w.write_u16(self.base_address_reg);
+        w.write_u16(self.base_word_count_reg);
+        w.write_u8(self.mode_reg);
+        w.write_bool(self.auto_init);
+        w.write_u8(match self.service_mode {
+            ServiceMode::Demand => 0,
+            ServiceMode::Single => 1,
+            ServiceMode::Block => 2,
+            ServiceMode::Cascade => 3,
+        });
+        w.write_bool(matches!(self.address_mode, AddressMode::Decrement));
+        w.write_u8(match self.transfer_type {
+            TransferType::Verify => 0,
+            TransferType::Write => 1,
+            TransferType::Read => 2,
+            TransferType::Illegal => 3,
+        });
+        w.write_bool(self.terminal_count);
+        w.write_bool(self.terminal_count_reached);
+        w.write_bool(self.masked);
+        w.write_u8(self.page);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.current_address_reg = r.read_u16()?;
+        self.current_word_count_reg = r.read_u16()?;
+        self.base_address_reg = r.read_u16()?;
+        self.base_word_count_reg = r.read_u16()?;
+        self.mode_reg = r.read_u8()?;
+        self.auto_init = r.read_bool()?;
+        self.service_mode = match r.read_u8()? {
+            0 => ServiceMode::Demand,
+            1 => ServiceMode::Single,
+            2 => ServiceMode::Block,
+            3 => ServiceMode::Cascade,
+            _ => return Err(SaveStateError::InvalidValue("DMA service mode")),
+        };
+        self.address_mode = match r.read_bool()? {
+            true => AddressMode::Decrement,
+            false => AddressMode::Increment,
+        };
+        self.transfer_type = match r.read_u8()? {
+            0 => TransferType::Verify,
+            1 => TransferType::Write,
+            2 => TransferType::Read,
+            3 => TransferType::Illegal,
+            _ => return Err(SaveStateError::InvalidValue("DMA transfer type")),
+        };
+        self.terminal_count = r.read_bool()?;
+        self.terminal_count_reached = r.read_bool()?;
+        self.masked = r.read_bool()?;
+        self.page = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for DMAController {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.enabled);
+        w.write_bool(self.mem_to_mem_enabled);
+        w.write_bool(self.channel_0_hold_enabled);
+        w.write_bool(matches!(self.timing_mode, TimingMode::CompressedTiming));
+        w.write_bool(matches!(self.priority_mode, PriorityMode::Rotating));
+        w.write_bool(self.flipflop);
+        for channel in &self.channels {
+            channel.save_state(w);
+        }
+        w.write_u8(self.command_register);
+        w.write_u8(self.request_reg);
+        w.write_u8(self.status_reg);
+        w.write_u8(self.temp_reg);
+        w.write_bool(self.dreq);
+        w.write_bool(self.cascade_request);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = r.read_bool()?;
+        self.mem_to_mem_enabled = r.read_bool()?;
+        self.channel_0_hold_enabled = r.read_bool()?;
+        self.timing_mode = match r.read_bool()? {
+            true => TimingMode::CompressedTiming,
+            false => TimingMode::NormalTiming,
+        };
+        self.priority_mode = match r.read_bool()? {
+            true => PriorityMode::Rotating,
+            false => PriorityMode::Fixed,
+        };
+        self.flipflop = r.read_bool()?;
+        for channel in self.channels.iter_mut() {
+            channel.load_state(r)?;
+        }
+        self.command_register = r.read_u8()?;
+        self.request_reg = r.read_u8()?;
+        self.status_reg = r.read_u8()?;
+        self.temp_reg = r.read_u8()?;
+        self.dreq = r.read_bool()?;
+        self.cascade_request = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_cycle() {
+        let mut dma = DMAController::new();
+
+        // Increment mode: address advances and count decrements each cycle.
+        dma.channels[2].current_address_reg = 0x1000;
+        dma.channels[2].current_word_count_reg = 1;
+        assert_eq!(dma.transfer_cycle(2, false), Some(false));
+        assert_eq!(dma.channels[2].current_address_reg, 0x1001);
+        assert_eq!(dma.channels[2].current_word_count_reg, 0);
+
+        // One more transfer on a 0 count sets terminal count.
+        assert_eq!(dma.transfer_cycle(2, false), Some(true));
+        assert!(dma.channels[2].terminal_count);
+        assert!(dma.channels[2].terminal_count_reached);
+
+        // No transfer occurs once terminal count has been reached.
+        assert_eq!(dma.transfer_cycle(2, false), None);
+
+        // Decrement mode wraps the internal address register.
+        dma.channels[3].address_mode = AddressMode::Decrement;
+        dma.channels[3].current_address_reg = 0x0000;
+        dma.channels[3].current_word_count_reg = 4;
+        assert_eq!(dma.transfer_cycle(3, false), Some(false));
+        assert_eq!(dma.channels[3].current_address_reg, 0xFFFF);
+        assert_eq!(dma.channels[3].current_word_count_reg, 3);
+
+        // Address hold leaves the address register unchanged.
+        assert_eq!(dma.transfer_cycle(3, true), Some(false));
+        assert_eq!(dma.channels[3].current_address_reg, 0xFFFF);
+        assert_eq!(dma.channels[3].current_word_count_reg, 2);
+
+        // Auto-init reloads the channel instead of setting terminal count.
+        dma.channels[1].auto_init = true;
+        dma.channels[1].base_address_reg = 0x2000;
+        dma.channels[1].base_word_count_reg = 0x10;
+        dma.channels[1].current_address_reg = 0x2010;
+        dma.channels[1].current_word_count_reg = 0;
+        assert_eq!(dma.transfer_cycle(1, false), Some(true));
+        assert_eq!(dma.channels[1].current_address_reg, 0x2000);
+        assert_eq!(dma.channels[1].current_word_count_reg, 0x10);
+        assert!(!dma.channels[1].terminal_count);
+        assert!(dma.channels[1].terminal_count_reached);
+    }
+
+    #[test]
+    fn test_mem_to_mem() {
+        let mut bus = BusInterface::default();
+        let mut dma = DMAController::new();
+
+        for (i, byte) in [0x11, 0x22, 0x33, 0x44].iter().enumerate() {
+            bus.write_u8(0x01000 + i, *byte, 0).unwrap();
+        }
+
+        // Channel 0 is the source, channel 1 the destination. Counts are one less than the number
+        // of bytes to transfer.
+        dma.handle_command_register_write(DMA_COMMAND_MEM_TO_MEM);
+        dma.channels[0].page = 0x00;
+        dma.channels[0].current_address_reg = 0x1000;
+        dma.channels[0].current_word_count_reg = 3;
+        dma.channels[1].page = 0x02;
+        dma.channels[1].current_address_reg = 0x3000;
+        dma.channels[1].current_word_count_reg = 3;
+        dma.channels[1].transfer_type = TransferType::Write;
+        dma.request_reg |= 0x01;
+
+        dma.run(&mut bus);
+
+        for (i, byte) in [0x11, 0x22, 0x33, 0x44].iter().enumerate() {
+            assert_eq!(bus.read_u8(0x23000 + i, 0).unwrap().0, *byte);
+        }
+        assert_eq!(dma.temp_reg, 0x44);
+        // The final transfer on a 0 count does not advance the address.
+        assert_eq!(dma.channels[0].current_address_reg, 0x1003);
+        assert_eq!(dma.channels[1].current_address_reg, 0x3003);
+        assert_eq!(dma.channels[1].current_word_count_reg, 0);
+        assert!(dma.channels[1].terminal_count);
+        assert_eq!(dma.request_reg & 0x01, 0);
+
+        // With channel 0 address hold, a single source byte fills the destination.
+        let mut dma = DMAController::new();
+        dma.handle_command_register_write(DMA_COMMAND_MEM_TO_MEM | DMA_COMMAND_CHANNEL_0_HOLD);
+        dma.channels[0].current_address_reg = 0x1002;
+        dma.channels[0].current_word_count_reg = 2;
+        dma.channels[1].current_address_reg = 0x4000;
+        dma.channels[1].current_word_count_reg = 2;
+        dma.channels[1].transfer_type = TransferType::Write;
+        dma.request_reg |= 0x01;
+
+        dma.run(&mut bus);
+
+        for i in 0..3 {
+            assert_eq!(bus.read_u8(0x04000 + i, 0).unwrap().0, 0x33);
+        }
+        assert_eq!(dma.channels[0].current_address_reg, 0x1002);
+        assert_eq!(dma.channels[0].current_word_count_reg, 0);
+        assert_eq!(dma.channels[1].current_address_reg, 0x4002);
+        assert_eq!(dma.request_reg & 0x01, 0);
+    }
+
+    #[test]
+    fn test_cascade() {
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+        let mut dma1 = DMAController::new();
+        let mut dma2 = DMAController::new_secondary();
+
+        // Program the secondary controller's channel 4 for cascade mode. The mode register is at D6h,
+        // and the mask register at D4h.
+        dma2.write_u8(0xD6, 0xC0, None, delta);
+        dma2.write_u8(0xD4, 0x04, None, delta);
+        assert!(!dma1.hrq());
+
+        // A masked request on the primary controller does not raise HRQ.
+        dma1.handle_channel_mask_register_write(0x06);
+        dma1.request_service(2);
+        assert!(!dma1.hrq());
+        dma2.set_cascade_request(dma1.hrq());
+        assert!(!dma2.cascade_acknowledge());
+
+        // Unmasking the channel raises HRQ, which is passed to the secondary controller's cascade
+        // channel. It is not acknowledged while the cascade channel is masked.
+        dma1.handle_channel_mask_register_write(0x02);
+        assert!(dma1.hrq());
+        dma2.set_cascade_request(dma1.hrq());
+        assert_eq!((dma2.request_reg >> DMA_CASCADE_CHANNEL) & 0x01, 1);
+        assert!(!dma2.cascade_acknowledge());
+
+        // Unmasking the cascade channel acknowledges the request.
+        dma2.write_u8(0xD4, 0x00, None, delta);
+        assert!(dma2.hrq());
+        assert!(dma2.cascade_acknowledge());
+
+        // Dropping HRQ on the primary controller releases the cascade channel.
+        dma1.clear_service(2);
+        assert!(!dma1.hrq());
+        dma2.set_cascade_request(dma1.hrq());
+        assert_eq!((dma2.request_reg >> DMA_CASCADE_CHANNEL) & 0x01, 0);
+        assert!(!dma2.cascade_acknowledge());
+        assert!(!dma2.hrq());
+
+        // A disabled primary controller cannot raise HRQ.
+        dma1.request_service(2);
+        dma1.handle_command_register_write(DMA_COMMAND_DISABLE);
+        assert!(!dma1.hrq());
+    }
+}
@@ -0,0 +1,346 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::eems.rs
+
+    Implementation of an EEMS / AboveBoard-style expanded memory board.
+
+    Unlike a simple EMS board, which only maps pages into a 64K page frame, an
+    EEMS board can also map pages into 16K windows below the page frame,
+    replacing conventional memory from a configurable backfill segment up to
+    640K. Multitasking environments such as DESQview use these windows to swap
+    entire programs in and out of conventional memory.
+
+    The board is programmed through four IO ports:
+
+      base + 0: Window select (R/W). Window n covers physical address n * 16K.
+      base + 1: Page number, low byte (R/W), for the selected window.
+      base + 2: Page number bits 8-9 and enable bit 7 (R/W), for the selected window.
+      base + 3: Board size in 64K units (R).
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemRangeDescriptor, MemoryMappedDevice, NO_IO_BYTE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+pub const EEMS_DEFAULT_IO_BASE: u16 = 0x258;
+pub const EEMS_IO_MASK: u16 = !0x03;
+pub const EEMS_DEFAULT_FRAME_SEG: usize = 0xD000;
+pub const EEMS_FRAME_SIZE: usize = 0x10000;
+pub const EEMS_DEFAULT_SIZE: usize = 0x200000;
+pub const EEMS_MAX_SIZE: usize = 0x800000;
+pub const EEMS_PAGE_SIZE: usize = 0x4000;
+pub const EEMS_PAGE_SHIFT: usize = 14;
+pub const EEMS_WINDOW_CT: usize = 0x100000 >> EEMS_PAGE_SHIFT;
+
+// Backfill windows may not extend past the end of conventional memory.
+pub const EEMS_BACKFILL_END_SEG: usize = 0xA000;
+pub const EEMS_SEG_ALIGN: usize = EEMS_PAGE_SIZE >> 4;
+
+pub const EEMS_PAGE_HI_MASK: u8 = 0x03;
+pub const EEMS_ENABLE_BIT: u8 = 0x80;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EemsWindow {
+    mappable: bool,
+    enabled: bool,
+    page: u16,
+}
+
+pub struct EemsBoard {
+    port_base: u16,
+    frame_addr: usize,
+    page_ct: usize,
+    select: usize,
+    windows: [EemsWindow; EEMS_WINDOW_CT],
+    mem: Vec<u8>,
+    map_changes: Vec<(MemRangeDescriptor, bool)>,
+}
+
+impl EemsBoard {
+    pub fn new(
+        port_base: Option<u16>,
+        frame_seg: Option<usize>,
+        size: Option<usize>,
+        backfill_seg: Option<usize>,
+    ) -> Self {
+        let mut frame_seg = frame_seg.unwrap_or(EEMS_DEFAULT_FRAME_SEG);
+        if frame_seg < EEMS_BACKFILL_END_SEG || frame_seg > 0xE000 || frame_seg % EEMS_SEG_ALIGN != 0 {
+            log::warn!(
+                "Invalid EEMS page frame segment {:04X}, using default of {:04X}",
+                frame_seg,
+                EEMS_DEFAULT_FRAME_SEG
+            );
+            frame_seg = EEMS_DEFAULT_FRAME_SEG;
+        }
+
+        let mut size = size
+            .unwrap_or(EEMS_DEFAULT_SIZE)
+            .clamp(EEMS_PAGE_SIZE * 4, EEMS_MAX_SIZE);
+        if !size.is_power_of_two() {
+            let rounded = size.next_power_of_two() >> 1;
+            log::warn!(
+                "EEMS size {:X} is not a power of two, rounding down to {:X}",
+                size,
+                rounded
+            );
+            size = rounded;
+        }
+
+        let mut windows = [EemsWindow::default(); EEMS_WINDOW_CT];
+
+        // The four page frame windows are always mappable.
+        let frame_window = frame_seg >> 10;
+        for window in windows.iter_mut().skip(frame_window).take(4) {
+            window.mappable = true;
+        }
+
+        if let Some(backfill_seg) = backfill_seg {
+            if backfill_seg < EEMS_BACKFILL_END_SEG && backfill_seg % EEMS_SEG_ALIGN == 0 {
+                for window in windows
+                    .iter_mut()
+                    .take(EEMS_BACKFILL_END_SEG >> 10)
+                    .skip(backfill_seg >> 10)
+                {
+                    window.mappable = true;
+                }
+            }
+            else {
+                log::warn!("Invalid EEMS backfill segment {:04X}, backfill disabled", backfill_seg);
+            }
+        }
+
+        log::debug!(
+            "Creating EEMS board: io_base: {:03X} page frame: {:04X} size: {}K backfill: {:04X?}",
+            port_base.unwrap_or(EEMS_DEFAULT_IO_BASE),
+            frame_seg,
+            size / 1024,
+            backfill_seg
+        );
+
+        EemsBoard {
+            port_base: port_base.unwrap_or(EEMS_DEFAULT_IO_BASE),
+            frame_addr: frame_seg << 4,
+            page_ct: size / EEMS_PAGE_SIZE,
+            select: 0,
+            windows,
+            mem: vec![0xAA; size],
+            map_changes: Vec::new(),
+        }
+    }
+
+    /// Disable all windows. Backfill windows are returned to conventional memory.
+    pub fn reset(&mut self) {
+        for n in 0..EEMS_WINDOW_CT {
+            self.set_window_enabled(n, false);
+            self.windows[n].page = 0;
+        }
+        self.select = 0;
+    }
+
+    /// Return the total amount of expanded memory on the board, in bytes.
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    /// Return the list of mapping changes made since the last call. Each entry is a window's
+    /// memory range and whether it should now be mapped to the board. The page frame is
+    /// always mapped, so only backfill windows generate changes.
+    pub fn take_map_changes(&mut self) -> Vec<(MemRangeDescriptor, bool)> {
+        std::mem::take(&mut self.map_changes)
+    }
+
+    fn is_frame_window(&self, n: usize) -> bool {
+        let frame_window = self.frame_addr >> EEMS_PAGE_SHIFT;
+        n >= frame_window && n < frame_window + 4
+    }
+
+    fn set_window_enabled(&mut self, n: usize, enabled: bool) {
+        if self.windows[n].enabled == enabled {
+            return;
+        }
+        self.windows[n].enabled = enabled;
+
+        if !self.is_frame_window(n) {
+            self.map_changes.push((
+                MemRangeDescriptor::new(n << EEMS_PAGE_SHIFT, EEMS_PAGE_SIZE, false),
+                enabled,
+            ));
+        }
+    }
+
+    #[inline]
+    fn ems_addr(&self, address: usize) -> Option<usize> {
+        let window = &self.windows[(address >> EEMS_PAGE_SHIFT) & (EEMS_WINDOW_CT - 1)];
+        if window.enabled {
+            Some(((window.page as usize) << EEMS_PAGE_SHIFT) + (address & (EEMS_PAGE_SIZE - 1)))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Which windows are mappable is configuration and is not saved. Restoring a state queues mapping
+/// changes for any backfill windows that change state, as a port write would.
+impl SaveState for EemsBoard {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_usize(self.select);
+        for window in self.windows.iter() {
+            w.write_bool(window.enabled);
+            w.write_u16(window.page);
+        }
+        w.write_bytes(&self.mem);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.select = r.read_usize()? & (EEMS_WINDOW_CT - 1);
+        for n in 0..EEMS_WINDOW_CT {
+            let enabled = r.read_bool()?;
+            self.windows[n].page = r.read_u16()? & (self.page_ct as u16 - 1);
+            if enabled && !self.windows[n].mappable {
+                return Err(SaveStateError::MachineMismatch("EEMS backfill".to_string()));
+            }
+            self.set_window_enabled(n, enabled);
+        }
+        r.read_bytes_into(&mut self.mem, "EEMS memory size")?;
+        Ok(())
+    }
+}
+
+impl IoDevice for EemsBoard {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        let window = &self.windows[self.select];
+        match port.wrapping_sub(self.port_base) {
+            0 => self.select as u8,
+            1 => window.page as u8,
+            2 => ((window.page >> 8) as u8 & EEMS_PAGE_HI_MASK) | if window.enabled { EEMS_ENABLE_BIT } else { 0 },
+            3 => (self.mem.len() >> 16) as u8,
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        if (port & EEMS_IO_MASK) != self.port_base {
+            return;
+        }
+        match port & 0x03 {
+            0 => {
+                self.select = data as usize & (EEMS_WINDOW_CT - 1);
+            }
+            1 => {
+                let window = &mut self.windows[self.select];
+                window.page = ((window.page & 0xFF00) | data as u16) & (self.page_ct as u16 - 1);
+            }
+            2 => {
+                let n = self.select;
+                if !self.windows[n].mappable {
+                    log::trace!("EEMS: Ignoring write to unmappable window {}", n);
+                    return;
+                }
+                let window = &mut self.windows[n];
+                window.page =
+                    ((window.page & 0x00FF) | ((data & EEMS_PAGE_HI_MASK) as u16) << 8) & (self.page_ct as u16 - 1);
+                self.set_window_enabled(n, data & EEMS_ENABLE_BIT != 0);
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            ("EEMS Window Select".to_string(), self.port_base),
+            ("EEMS Page Low".to_string(), self.port_base + 1),
+            ("EEMS Page High/Enable".to_string(), self.port_base + 2),
+            ("EEMS Board Size".to_string(), self.port_base + 3),
+        ]
+    }
+}
+
+/// Reads from disabled page frame windows return open bus, and writes are discarded.
+/// Disabled backfill windows are unmapped entirely, so never reach the board.
+impl MemoryMappedDevice for EemsBoard {
+    fn get_read_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn mmio_read_u8(&mut self, address: usize, _cycles: u32, cpumem: Option<&[u8]>) -> (u8, u32) {
+        (self.mmio_peek_u8(address, cpumem), 0)
+    }
+
+    fn mmio_read_u16(&mut self, address: usize, _cycles: u32, cpumem: Option<&[u8]>) -> (u16, u32) {
+        (self.mmio_peek_u16(address, cpumem), 0)
+    }
+
+    fn mmio_peek_u8(&self, address: usize, _cpumem: Option<&[u8]>) -> u8 {
+        match self.ems_addr(address) {
+            Some(ems_addr) => self.mem[ems_addr],
+            None => NO_IO_BYTE,
+        }
+    }
+
+    fn mmio_peek_u16(&self, address: usize, cpumem: Option<&[u8]>) -> u16 {
+        let lo_byte = self.mmio_peek_u8(address, cpumem);
+        let ho_byte = self.mmio_peek_u8(address + 1, cpumem);
+
+        (ho_byte as u16) << 8 | lo_byte as u16
+    }
+
+    fn get_write_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
+        if let Some(ems_addr) = self.ems_addr(address) {
+            self.mem[ems_addr] = byte;
+        }
+        0
+    }
+
+    fn mmio_write_u16(&mut self, address: usize, data: u16, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
+        if let Some(ems_addr) = self.ems_addr(address) {
+            self.mem[ems_addr] = data as u8;
+        }
+        if let Some(ems_addr) = self.ems_addr(address + 1) {
+            self.mem[ems_addr] = (data >> 8) as u8;
+        }
+        0
+    }
+
+    fn get_mapping(&self) -> Vec<MemRangeDescriptor> {
+        let mut mapping = vec![MemRangeDescriptor::new(self.frame_addr, EEMS_FRAME_SIZE, false)];
+
+        for (n, window) in self.windows.iter().enumerate() {
+            if window.enabled && !self.is_frame_window(n) {
+                mapping.push(MemRangeDescriptor::new(n << EEMS_PAGE_SHIFT, EEMS_PAGE_SIZE, false));
+            }
+        }
+
+        mapping
+    }
+}
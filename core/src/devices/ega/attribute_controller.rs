@@ -31,6 +31,7 @@
 */
 
 use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 #[derive(Copy, Clone, Debug)]
 pub enum AttributeRegister {
@@ -212,6 +213,49 @@ impl AttributeController {
     pub fn reset_flipflop(&mut self) {
         self.register_flipflop = AttributeRegisterFlipFlop::Address;
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let mut regs = [0u8; 20];
+        for (reg, entry) in regs.iter_mut().zip(self.palette_registers.iter()) {
+            *reg = entry.six;
+        }
+        regs[0x10] = self.mode_control.into_bytes()[0];
+        regs[0x11] = self.overscan_color.six;
+        regs[0x12] = self.color_plane_enable.into_bytes()[0];
+        regs[0x13] = self.pel_panning;
+        w.write_bytes(&regs);
+        w.write_u8(self.register_selected as u8);
+        w.write_usize(self.palette_index);
+        w.write_bool(matches!(self.register_flipflop, AttributeRegisterFlipFlop::Data));
+        w.write_bool(self.blink_state);
+    }
+
+    /// Restore the attribute registers by writing them back through the attribute register, then
+    /// restore the flipflop.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0u8; 20];
+        r.read_bytes_into(&mut regs, "EGA attribute registers")?;
+        let selected = r.read_u8()?;
+        let palette_index = r.read_usize()?;
+        let flipflop_data = r.read_bool()?;
+        self.blink_state = r.read_bool()?;
+        if selected as usize >= regs.len() || palette_index >= self.palette_registers.len() {
+            return Err(SaveStateError::InvalidValue("EGA attribute register select"));
+        }
+
+        for (idx, byte) in regs.iter().enumerate() {
+            self.reset_flipflop();
+            self.write_attribute_register(idx as u8);
+            self.write_attribute_register(*byte);
+        }
+        self.reset_flipflop();
+        self.write_attribute_register(selected);
+        self.palette_index = palette_index;
+        if !flipflop_data {
+            self.reset_flipflop();
+        }
+        Ok(())
+    }
     /// Handle a write to the Attribute Register 0x3C0.
     ///
     /// Unlike the other register files on the EGA, the Attribute Register doesn't have an
@@ -490,6 +534,31 @@ impl AttributeController {
         glyph_row_base & EGA_COLORS_U64[fg_color] | !glyph_row_base & EGA_COLORS_U64[bg_color]
     }
 
+    pub fn pel_panning(&self) -> u8 {
+        self.pel_panning
+    }
+
+    /// Return the two attribute output lines selected by the Video Status Mux field of the
+    /// Color Plane Enable register, positioned at bits 4 and 5 as they appear in Input Status
+    /// Register 1.
+    pub fn video_status_bits(&self, pel: u8) -> u8 {
+        let (bit5, bit4) = match self.color_plane_enable.video_status_mux() {
+            0 => (2, 0),
+            1 => (5, 4),
+            2 => (3, 1),
+            _ => (7, 6),
+        };
+        ((pel >> bit5) & 0x01) << 5 | ((pel >> bit4) & 0x01) << 4
+    }
+
+    /// Return whether attribute bit 7 selects blinking rather than background intensity.
+    pub fn blink_enabled(&self) -> bool {
+        matches!(
+            self.mode_control.enable_blink_or_intensity(),
+            AttributeBlinkOrIntensity::Blink
+        )
+    }
+
     pub fn palette(&self, pel: u8) -> u8 {
         self.palette_registers[(pel & 0x0F) as usize].six
     }
@@ -31,6 +31,7 @@
 */
 
 use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 pub const EGA_VBLANK_MASK: u16 = 0x001F;
 pub const EGA_VSYNC_MASK: u16 = 0x001F;
@@ -51,6 +52,8 @@ const DEFAULT_OVERFLOW: u8 = 0;
 const DEFAULT_PRESET_ROW_SCAN: u8 = 0;
 const DEFAULT_MAX_SCANLINE: u8 = 13;
 
+const CRTC_REGISTER_CT: usize = 0x19;
+
 const VSYNC_LENGTH: u8 = 16;
 const CURSOR_LINE_MASK: u8 = 0b0001_1111;
 const AC_LATENCY: u8 = 1;
@@ -176,6 +179,9 @@ pub struct EgaCrtc {
     register_select_byte: u8,
     register_selected:    CRTCRegister,
 
+    // Register values as last written, for save states.
+    regs: [u8; CRTC_REGISTER_CT],
+
     crtc_horizontal_total: u8,                          // R(0) Horizontal Total
     crtc_horizontal_display_end: u8,                    // R(1) Horizontal Display End
     crtc_start_horizontal_blank: u8,                    // R(2) Start Horizontal Blank
@@ -247,6 +253,8 @@ impl Default for EgaCrtc {
             register_selected:    CRTCRegister::HorizontalTotal,
             register_select_byte: 0,
 
+            regs: [0; CRTC_REGISTER_CT],
+
             crtc_horizontal_total: DEFAULT_HORIZONTAL_TOTAL,
             crtc_horizontal_display_end: DEFAULT_HORIZONTAL_DISPLAYED,
             crtc_start_horizontal_blank: DEFAULT_HORIZONTAL_SYNC_POS,
@@ -317,6 +325,27 @@ impl EgaCrtc {
         Self::default()
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.regs);
+        w.write_u8(self.register_select_byte);
+    }
+
+    /// Restore the CRTC registers by writing them back through the data register. Returns true
+    /// if the card should recalculate its mode. The CRTC's counters are not restored.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<bool, SaveStateError> {
+        let mut regs = [0u8; CRTC_REGISTER_CT];
+        r.read_bytes_into(&mut regs, "EGA CRTC registers")?;
+        let address = r.read_u8()?;
+
+        let mut recalc = false;
+        for (idx, byte) in regs.iter().enumerate() {
+            self.write_crtc_register_address(idx as u8);
+            recalc |= self.write_crtc_register_data(*byte).0;
+        }
+        self.write_crtc_register_address(address);
+        Ok(recalc)
+    }
+
     pub fn write_crtc_register_address(&mut self, byte: u8) {
         //log::trace!("CGA: CRTC register {:02X} selected", byte);
         self.register_select_byte = byte & 0x1F;
@@ -361,6 +390,7 @@ impl EgaCrtc {
     pub fn write_crtc_register_data(&mut self, byte: u8) -> (bool, bool) {
         //log::debug!("CGA: Write to CRTC register: {:?}: {:02}", self.crtc_register_selected, byte );
         let mut clear_intr = false;
+        self.regs[self.register_select_byte as usize] = byte;
         match self.register_selected {
             CRTCRegister::HorizontalTotal => {
                 // (R0) 8 bit write only
@@ -1078,6 +1108,22 @@ impl EgaCrtc {
         (self.crtc_cursor_start, self.crtc_cursor_end.cursor_end())
     }
 
+    pub fn horizontal_total(&self) -> u8 {
+        self.crtc_horizontal_total
+    }
+
+    pub fn vertical_total(&self) -> u16 {
+        self.crtc_vertical_total
+    }
+
+    pub fn offset(&self) -> u8 {
+        self.crtc_offset
+    }
+
+    pub fn line_compare(&self) -> u16 {
+        self.crtc_line_compare
+    }
+
     pub fn horizontal_display_end(&self) -> u8 {
         self.crtc_horizontal_display_end
     }
@@ -55,6 +55,7 @@ mod graphics_controller;
 mod io;
 mod mmio;
 mod planes;
+mod savestate;
 mod sequencer;
 mod tablegen;
 mod videocard;
@@ -602,6 +603,7 @@ pub struct EGACard {
     ac: AttributeController,
 
     pel_pan_latch: u8,
+    last_pel: u8,
 
     current_font: u8,
 
@@ -733,6 +735,7 @@ impl Default for EGACard {
             ac: AttributeController::new(),
 
             pel_pan_latch: 0,
+            last_pel: 0,
 
             current_font: 0,
             misc_output_register: EMiscellaneousOutputRegister::new(),
@@ -817,6 +820,7 @@ impl EGACard {
             row_stride: EGA16_MAX_RASTER_X as usize,
             double_scan: false,
             mode_byte: 0,
+            standard: VideoStandard::Ntsc,
         }
     }
 
@@ -929,13 +933,9 @@ impl EGACard {
         // controlled by bits 4 & 5 of the Color Plane Enable Register Video Status
         // Mux Field.
         // The EGA BIOS performs a diagnostic that senses these line transitions after
-        // drawing a line of high-intensity white characters to the screen.
-        // Currently, we just fake this whole affair by setting the bits to be on during
-        // the first few scanlines.
-
-        if self.crtc.scanline() < 9 {
-            byte |= 0x30;
-        }
+        // drawing a line of high-intensity white characters to the screen, so we report
+        // the last pel shifted out of the Attribute Controller.
+        byte |= self.ac.video_status_bits(self.last_pel);
 
         byte
     }
@@ -1063,6 +1063,7 @@ impl EGACard {
         if self.rba < (EGA_MAX_CLOCK16 - 8) {
             // Shift the current character span out from the attribute controller and draw it
             let out_span = self.ac.shift_out64();
+            self.last_pel = out_span as u8;
             self.draw_from_ac(out_span);
 
             if self.crtc.status.den | self.crtc.status.den_skew | self.crtc.status.hborder {}
@@ -1181,6 +1182,7 @@ impl EGACard {
         // Only draw if buffer address is in bounds.
         if self.rba < (EGA_MAX_CLOCK16 - 16) {
             let (out_span1, outspan2) = self.ac.shift_out64_halfclock();
+            self.last_pel = out_span1 as u8;
             self.draw_from_ac_halfclock(out_span1, outspan2);
 
             if self.crtc.status.den | self.crtc.status.den_skew {}
@@ -31,7 +31,11 @@
 */
 
 use super::*;
-use crate::{bus::DeviceRunTimeUnit, devices::pic::Pic};
+use crate::{
+    bus::DeviceRunTimeUnit,
+    devices::pic::Pic,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 use std::{collections::HashMap, path::Path};
 
 impl VideoCard for EGACard {
@@ -48,6 +52,9 @@ impl VideoCard for EGACard {
                 log::debug!("VideoOption::DebugDraw set to: {}", state);
                 self.debug_draw = state;
             }
+            VideoOption::LogRegisterWrites(_state) => {
+                log::warn!("VideoOption::LogRegisterWrites not supported for EGA");
+            }
         }
     }
 
@@ -72,25 +79,23 @@ impl VideoCard for EGACard {
     }
 
     fn get_display_size(&self) -> (u32, u32) {
-        /*        // EGA supports multiple fonts.
-
-        let font_w = EGA_FONTS[self.current_font].w;
-        let _font_h = EGA_FONTS[self.current_font].h;
-
-        // Clock divisor effectively doubles the CRTC register values
-        let _clock_divisor = match self.sequencer.clocking_mode.dot_clock() {
-            DotClock::Native => 1,
-            DotClock::HalfClock => 2,
+        // The character clock is either 8 or 9 dots wide, and the half-clock modes effectively
+        // double the horizontal CRTC register values.
+        let char_w = match self.sequencer.clocking_mode.character_clock() {
+            CharacterClock::EightDots => 8,
+            CharacterClock::NineDots => 9,
         };
-
-        //let width = (self.crtc_horizontal_display_end as u32 + 1) * clock_divisor * font_w as u32;
-        let width = (self.crtc_horizontal_display_end as u32 + 1) * font_w as u32;
-        let height = self.crtc_vertical_display_end as u32 + 1;
-        (width, height)*/
-        (320, 200)
+        let width = (self.crtc.horizontal_display_end() as u32 + 1) * char_w * self.get_clock_divisor();
+        let height = self.crtc.vertical_display_end() as u32 + 1;
+        (width, height)
     }
 
     /// Return the 16-bit value computed from the CRTC's pair of Page Address registers.
+    /// Unimplemented
+    fn get_vram(&self) -> Option<&[u8]> {
+        None
+    }
+
     fn get_start_address(&self) -> u16 {
         return self.crtc.start_address();
     }
@@ -105,12 +110,33 @@ impl VideoCard for EGACard {
         Some((self.raster_x, self.raster_y))
     }
 
+    /// Unimplemented
+    fn set_light_pen(&mut self, _pos: Option<(u32, u32)>) {}
+
+    /// Unimplemented
+    fn get_register_log(&self) -> Option<&VideoRegisterLog> {
+        None
+    }
+
+    /// Unimplemented
+    fn set_breakpoints(&mut self, _bp_list: Vec<VideoBreakpoint>) {}
+
+    /// Unimplemented
+    fn take_breakpoint_hit(&mut self) -> bool {
+        false
+    }
+
+    /// Unimplemented
+    fn get_raster_regions(&self) -> Option<RasterRegions> {
+        None
+    }
+
     /// Unimplemented
     fn debug_tick(&mut self, _ticks: u32, _cpumem: Option<&[u8]>) {}
 
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32 {
-        0
+        self.crtc.scanline() as u32
     }
 
     /// Return whether to double scanlines produced by this adapter.
@@ -140,13 +166,18 @@ impl VideoCard for EGACard {
     }
 
     fn get_overscan_color(&self) -> u8 {
-        0
+        self.ac.overscan_color.six
     }
 
-    /// Return the current refresh rate.
-    /// TODO: Handle VGA 70Hz modes.
+    /// Return the current refresh rate, derived from the selected dot clock and the size of the
+    /// monitor's display field.
     fn get_refresh_rate(&self) -> u32 {
-        60
+        let dot_clock = match self.misc_output_register.clock_select() {
+            ClockSelect::Clock14 => 14_318_180.0,
+            ClockSelect::Clock16 => 16_257_000.0,
+            _ => return 60,
+        };
+        (dot_clock / (self.extents.field_w * self.extents.field_h) as f64).round() as u32
     }
 
     fn get_clock_divisor(&self) -> u32 {
@@ -209,6 +240,31 @@ impl VideoCard for EGACard {
         None
     }
 
+    /// Unimplemented
+    fn get_crtc_timing(&self) -> Option<CrtcTiming> {
+        None
+    }
+
+    fn get_character_sets(&self) -> Vec<CharacterSet> {
+        // Fonts are loaded into plane 2 in four 16K blocks, with EGA_CHARACTER_HEIGHT bytes reserved per glyph.
+        let plane = self.get_plane_slice(2);
+        let h = std::cmp::min(self.get_character_height() as usize, EGA_CHARACTER_HEIGHT) as u32;
+        (0..4)
+            .filter_map(|block| {
+                let offset = block * 0x4000;
+                let mut set = CharacterSet::from_glyph_major(
+                    &format!("Font RAM block {}", block),
+                    8,
+                    h,
+                    EGA_CHARACTER_HEIGHT,
+                    plane.get(offset..)?,
+                );
+                set.active = offset == self.sequencer.font_offset_a || offset == self.sequencer.font_offset_b;
+                Some(set)
+            })
+            .collect()
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc.maximum_scanline() + 1
     }
@@ -217,6 +273,21 @@ impl VideoCard for EGACard {
         None
     }
 
+    fn get_palette_state(&self) -> Option<PaletteState> {
+        Some(PaletteState {
+            palette: self
+                .ac
+                .palette_registers
+                .iter()
+                .map(|entry| (entry.six, PaletteColor::Rgb6(entry.six)))
+                .collect(),
+            overscan: Some(PaletteColor::Rgb6(self.ac.overscan_color.six)),
+            blink_enabled: self.ac.blink_enabled(),
+            blink_state: self.blink_state,
+            dac: Vec::new(),
+        })
+    }
+
     #[rustfmt::skip]
     #[allow(dead_code)]
     /// Returns a string representation of all the CRTC Registers.
@@ -370,43 +441,27 @@ impl VideoCard for EGACard {
         &DUMMY_PIXEL
     }
 
-    fn get_pixel_raw(&self, _x: u32, _y: u32) -> u8 {
-        /*        let mut byte = 0;
-
-        let x_byte_offset = (x + self.attribute_pel_panning as u32) / 8;
-        let x_bit_offset = (x + self.attribute_pel_panning as u32) % 8;
-
-        // Get the current width of screen + offset
-        // let span = (self.crtc_horizontal_display_end + 1 + 64) as u32;
-        let span = self.crtc_offset as u32 * 2;
+    fn get_pixel_raw(&self, x: u32, y: u32) -> u8 {
+        let x = x + self.ac.pel_panning() as u32;
+        let x_byte_offset = x / 8;
+        let x_bit_offset = (x % 8) as u8;
 
-        let y_offset = y * span;
+        // The Offset register specifies the width of a logical line in words.
+        let span = self.crtc.offset() as u32 * 2;
 
         // The line compare register resets the CRTC Start Address and line counter to 0 at the
         // specified scanline.
-        // If we are above the value in Line Compare calculate the read offset as normal.
-        let read_offset;
-        if y >= self.crtc_line_compare as u32 {
-            read_offset = (((y - self.crtc_line_compare as u32) * span) + x_byte_offset) as usize;
+        let line_compare = self.crtc.line_compare() as u32;
+        let read_offset = if y >= line_compare {
+            ((y - line_compare) * span + x_byte_offset) as usize
         }
         else {
-            read_offset = (y_offset + x_byte_offset + self.crtc_start_address as u32) as usize;
-        }
+            (y * span + x_byte_offset + self.crtc.start_address() as u32) as usize
+        };
 
         if read_offset < self.sequencer.vram.plane_len() {
-            for i in 0..4 {
-                let read_byte = self.sequencer.vram.read_u8(i, read_offset);
-                let read_bit = match read_byte & (0x01 << (7 - x_bit_offset)) != 0 {
-                    true => 1,
-                    false => 0,
-                };
-
-                //byte |= read_bit << (3 - i);
-                byte |= read_bit << i;
-            }
-            // return self.attribute_palette_registers[byte & 0x0F].into_bytes()[0];
-            return self.attribute_palette_registers[byte & 0x0F].six;
-        }*/
+            return self.ac.palette(self.get_pixel(read_offset, x_bit_offset));
+        }
         0
     }
 
@@ -445,4 +500,17 @@ impl VideoCard for EGACard {
     fn get_text_mode_strings(&self) -> Vec<String> {
         Vec::new()
     }
+
+    /// Unimplemented
+    fn get_text_mode_screen(&self, _full_buffer: bool) -> Option<TextModeScreen> {
+        None
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
 }
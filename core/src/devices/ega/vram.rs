@@ -36,7 +36,10 @@
 
 */
 
-use crate::devices::ega::EGA_GFX_PLANE_SIZE;
+use crate::{
+    devices::ega::EGA_GFX_PLANE_SIZE,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 
 pub struct Vram {
     // Display Planes
@@ -133,4 +136,20 @@ impl Vram {
         self.planes[p][offset] |= data;
         self.deplane(offset);
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for plane in self.planes.iter() {
+            w.write_bytes(plane);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for plane in self.planes.iter_mut() {
+            r.read_bytes_into(plane, "EGA video memory")?;
+        }
+        for offset in 0..self.plane_len() {
+            self.deplane(offset);
+        }
+        Ok(())
+    }
 }
@@ -36,6 +36,7 @@ use crate::{
     devices::{dma, floppy_drive::FloppyDiskDrive},
     machine_config::FloppyDriveConfig,
     machine_types::FdcType,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 use anyhow::{anyhow, Error};
 use std::{collections::VecDeque, default::Default, path::PathBuf};
@@ -58,6 +59,10 @@ pub const PCJR_IO_BASE: u16 = 0x00F0;
 pub const FDC_DIGITAL_OUTPUT_REGISTER: u16 = 0x02;
 pub const FDC_STATUS_REGISTER: u16 = 0x04;
 pub const FDC_DATA_REGISTER: u16 = 0x05;
+pub const FDC_DIGITAL_INPUT_REGISTER: u16 = 0x07;
+
+// Digital Input Register Bit Definitions
+pub const FDC_DIR_DISK_CHANGE: u8 = 0b1000_0000;
 
 // Main Status Register Bit Definitions
 // --------------------------------------------------------------------------------
@@ -313,6 +318,7 @@ impl IoDevice for FloppyController {
             }
             FDC_STATUS_REGISTER => self.handle_status_register_read(),
             FDC_DATA_REGISTER => self.handle_data_register_read(),
+            FDC_DIGITAL_INPUT_REGISTER => self.handle_dir_read(),
             _ => unreachable!("FLOPPY: Bad port #"),
         }
     }
@@ -334,6 +340,10 @@ impl IoDevice for FloppyController {
             FDC_DATA_REGISTER => {
                 self.handle_data_register_write(data);
             }
+            FDC_DIGITAL_INPUT_REGISTER => {
+                // This is the Configuration Control Register on an AT. There is no data rate to select.
+                log::debug!("Write to Configuration Control Register ignored: {:02X}", data);
+            }
             _ => unreachable!("FLOPPY: Bad port #"),
         }
     }
@@ -344,14 +354,21 @@ impl IoDevice for FloppyController {
             FdcType::IbmPCJrNec => PCJR_IO_BASE,
         };
 
-        vec![
+        let mut ports = vec![
             (
                 String::from("FDC Digital Output Register"),
                 base + FDC_DIGITAL_OUTPUT_REGISTER,
             ),
             (String::from("FDC Status Register"), base + FDC_STATUS_REGISTER),
             (String::from("FDC Data Register"), base + FDC_DATA_REGISTER),
-        ]
+        ];
+        if let FdcType::IbmNec = self.fdc_type {
+            ports.push((
+                String::from("FDC Digital Input Register"),
+                base + FDC_DIGITAL_INPUT_REGISTER,
+            ));
+        }
+        ports
     }
 }
 
@@ -506,6 +523,11 @@ impl FloppyController {
         self.drive_ct
     }
 
+    /// Return the motor state of each installed drive, indexed by drive number.
+    pub fn drive_motors(&self) -> Vec<bool> {
+        self.drives[..self.drive_ct].iter().map(|d| d.motor_running()).collect()
+    }
+
     pub fn drive(&self, idx: usize) -> &FloppyDiskDrive {
         if idx >= self.drive_ct {
             panic!("Invalid drive index");
@@ -635,6 +657,17 @@ impl FloppyController {
         self.drives[drive_select].write_protected = write_protected;
     }
 
+    /// Read the Digital Input Register. Bit 7 reports the disk change line of the selected drive.
+    /// The IBM PC/XT controller has no such register and its drives have no disk change line, but
+    /// reporting one as AT-compatible controllers do lets software notice a disk that was swapped
+    /// while the machine was running. The other bits belong to the hard disk controller on an AT.
+    pub fn handle_dir_read(&mut self) -> u8 {
+        match self.drives[self.drive_select].disk_changed() {
+            true => FDC_DIR_DISK_CHANGE,
+            false => 0,
+        }
+    }
+
     pub fn handle_dor_write(&mut self, data: u8) {
         if data & DOR_FDC_RESET == 0 {
             // Reset the FDC when the reset bit is *not* set
@@ -977,7 +1010,7 @@ impl FloppyController {
                 COMMAND_READ_DELETED_DATA => {
                     self.command_deleted = true;
                     log::trace!("Received Read Deleted Data command: {:02}", command);
-                    log::error!("Command unimplemented");
+                    self.set_command(Command::ReadData, 8, FloppyController::command_read_data);
                 }
                 COMMAND_FORMAT_TRACK => {
                     log::trace!("Received Format Track command: {:02}", command);
@@ -1722,9 +1755,10 @@ impl FloppyController {
             log::trace!("DMA destination address: {:05X}", dst_address);
 
             let skip_flag = self.command_skip;
+            let deleted = self.command_deleted;
             match self
                 .selected_drive_mut()
-                .command_read_data(h, chs, xfer_sectors, n, 0, 0, 0, skip_flag)
+                .command_read_data(h, chs, xfer_sectors, n, 0, 0, 0, skip_flag, deleted)
             {
                 Ok(read_result) => {
                     log::trace!("Read sector command accepted, new sid: {}", read_result.new_sid);
@@ -2360,3 +2394,286 @@ impl FloppyController {
         }
     }
 }
+
+fn command_to_u8(command: Command) -> u8 {
+    match command {
+        Command::NoCommand => 0,
+        Command::ReadTrack => 1,
+        Command::WriteData => 2,
+        Command::ReadData => 3,
+        Command::WriteDeletedSector => 4,
+        Command::ReadDeletedSector => 5,
+        Command::FormatTrack => 6,
+        Command::FixDriveData => 7,
+        Command::CheckDriveStatus => 8,
+        Command::CalibrateDrive => 9,
+        Command::SenseIntStatus => 10,
+        Command::ReadSectorID => 11,
+        Command::SeekParkHead => 12,
+        Command::Invalid => 13,
+    }
+}
+
+fn command_from_u8(byte: u8) -> Result<Command, SaveStateError> {
+    Ok(match byte {
+        0 => Command::NoCommand,
+        1 => Command::ReadTrack,
+        2 => Command::WriteData,
+        3 => Command::ReadData,
+        4 => Command::WriteDeletedSector,
+        5 => Command::ReadDeletedSector,
+        6 => Command::FormatTrack,
+        7 => Command::FixDriveData,
+        8 => Command::CheckDriveStatus,
+        9 => Command::CalibrateDrive,
+        10 => Command::SenseIntStatus,
+        11 => Command::ReadSectorID,
+        12 => Command::SeekParkHead,
+        13 => Command::Invalid,
+        _ => return Err(SaveStateError::InvalidValue("FDC command")),
+    })
+}
+
+/// Return the handler that handle_data_register_write() registers for a command, so that a command
+/// whose parameter bytes are still being received can be resumed after a restore.
+fn command_dispatch_fn(command: Command) -> Option<CommandDispatchFn> {
+    match command {
+        Command::ReadTrack => Some(FloppyController::command_read_track),
+        Command::WriteData => Some(FloppyController::command_write_data),
+        Command::ReadData => Some(FloppyController::command_read_data),
+        Command::FormatTrack => Some(FloppyController::command_format_track),
+        Command::FixDriveData => Some(FloppyController::command_fix_drive_data),
+        Command::CheckDriveStatus => Some(FloppyController::command_check_drive_status),
+        Command::CalibrateDrive => Some(FloppyController::command_calibrate_drive),
+        Command::ReadSectorID => Some(FloppyController::command_read_sector_id),
+        Command::SeekParkHead => Some(FloppyController::command_seek_head),
+        _ => None,
+    }
+}
+
+fn write_chs(w: &mut StateWriter, chs: &DiskChs) {
+    w.write_u16(chs.c());
+    w.write_u8(chs.h());
+    w.write_u8(chs.s());
+}
+
+fn read_chs(r: &mut StateReader) -> Result<DiskChs, SaveStateError> {
+    let c = r.read_u16()?;
+    let h = r.read_u8()?;
+    let s = r.read_u8()?;
+    Ok(DiskChs::new(c, h, s))
+}
+
+fn write_error(w: &mut StateWriter, error: DriveError) {
+    w.write_u8(match error {
+        DriveError::NoError => 0,
+        DriveError::NoMedia => 1,
+        DriveError::BadSeek => 2,
+        DriveError::BadRead => 3,
+        DriveError::BadWrite => 4,
+        DriveError::WriteProtect => 5,
+        DriveError::DMAError => 6,
+    });
+}
+
+fn read_error(r: &mut StateReader) -> Result<DriveError, SaveStateError> {
+    Ok(match r.read_u8()? {
+        0 => DriveError::NoError,
+        1 => DriveError::NoMedia,
+        2 => DriveError::BadSeek,
+        3 => DriveError::BadRead,
+        4 => DriveError::BadWrite,
+        5 => DriveError::WriteProtect,
+        6 => DriveError::DMAError,
+        _ => return Err(SaveStateError::InvalidValue("FDC drive error")),
+    })
+}
+
+fn write_operation(w: &mut StateWriter, operation: &Operation) {
+    match operation {
+        Operation::NoOperation => w.write_u8(0),
+        Operation::ReadData(h, chs, n, eot, gap, dtl) | Operation::ReadTrack(h, chs, n, eot, gap, dtl) => {
+            w.write_u8(if matches!(operation, Operation::ReadData(..)) {
+                1
+            }
+            else {
+                2
+            });
+            w.write_u8(*h);
+            write_chs(w, chs);
+            w.write_u8(*n);
+            w.write_u8(*eot);
+            w.write_u8(*gap);
+            w.write_u8(*dtl);
+        }
+        Operation::WriteData(h, chs, n, eot, gap, dtl, deleted) => {
+            w.write_u8(3);
+            w.write_u8(*h);
+            write_chs(w, chs);
+            w.write_u8(*n);
+            w.write_u8(*eot);
+            w.write_u8(*gap);
+            w.write_u8(*dtl);
+            w.write_bool(*deleted);
+        }
+        Operation::FormatTrack(h, n, sc, gap, fill) => {
+            w.write_u8(4);
+            w.write_u8(*h);
+            w.write_u8(*n);
+            w.write_u8(*sc);
+            w.write_u8(*gap);
+            w.write_u8(*fill);
+        }
+    }
+}
+
+fn read_operation(r: &mut StateReader) -> Result<Operation, SaveStateError> {
+    Ok(match r.read_u8()? {
+        0 => Operation::NoOperation,
+        1 => Operation::ReadData(
+            r.read_u8()?,
+            read_chs(r)?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_u8()?,
+        ),
+        2 => Operation::ReadTrack(
+            r.read_u8()?,
+            read_chs(r)?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_u8()?,
+        ),
+        3 => Operation::WriteData(
+            r.read_u8()?,
+            read_chs(r)?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_u8()?,
+            r.read_bool()?,
+        ),
+        4 => Operation::FormatTrack(r.read_u8()?, r.read_u8()?, r.read_u8()?, r.read_u8()?, r.read_u8()?),
+        _ => return Err(SaveStateError::InvalidValue("FDC operation")),
+    })
+}
+
+/// Commands and operations in progress, including partially completed DMA transfers, are saved
+/// along with the state of each drive.
+impl SaveState for FloppyController {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_f64(self.us_accumulator);
+        w.write_f64(self.watchdog_accumulator);
+        w.write_u8(self.status_byte);
+        w.write_bool(self.reset_flag);
+        w.write_u8(self.reset_sense_count);
+        w.write_bool(self.mrq);
+        w.write_u8(self.data_register);
+        w.write_bool(self.dma);
+        w.write_u8(self.dor);
+        w.write_bool(self.busy);
+        w.write_bool(matches!(self.dio, IoMode::ToCpu));
+        w.write_bool(self.reading_command);
+        w.write_u8(command_to_u8(self.command));
+        w.write_u8(command_to_u8(self.last_command));
+        w.write_bool(self.receiving_command);
+        w.write_u32(self.command_byte_n);
+        w.write_bool(self.command_skip);
+        w.write_bool(self.command_deleted);
+        write_operation(w, &self.operation);
+        w.write_bool(self.operation_init);
+        w.write_u8(self.operation_final_sid);
+        w.write_bool(self.send_interrupt);
+        w.write_bool(self.pending_interrupt);
+        w.write_bool(self.end_interrupt);
+        w.write_bool(self.watchdog_enabled);
+        w.write_bool(self.watchdog_trigger_bit);
+        w.write_bool(self.watchdog_triggered);
+        write_error(w, self.last_error);
+        w.write_bytes(&self.last_status_bytes);
+        w.write_bytes(&self.data_register_out.iter().copied().collect::<Vec<u8>>());
+        w.write_bytes(&self.data_register_in.iter().copied().collect::<Vec<u8>>());
+        w.write_bytes(&self.format_buffer.iter().copied().collect::<Vec<u8>>());
+
+        w.write_usize(self.drive_ct);
+        for drive in self.drives.iter().take(self.drive_ct) {
+            drive.save_state(w);
+        }
+        w.write_usize(self.drive_select);
+
+        w.write_bool(self.in_dma);
+        w.write_usize(self.dma_byte_count);
+        w.write_usize(self.dma_bytes_left);
+        w.write_usize(self.pio_byte_count);
+        w.write_usize(self.pio_sector_byte_count);
+        w.write_usize(self.pio_bytes_left);
+        w.write_usize(self.xfer_size_sectors);
+        w.write_usize(self.xfer_size_bytes);
+        w.write_usize(self.xfer_completed_sectors);
+        w.write_bytes(&self.xfer_buffer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.us_accumulator = r.read_f64()?;
+        self.watchdog_accumulator = r.read_f64()?;
+        self.status_byte = r.read_u8()?;
+        self.reset_flag = r.read_bool()?;
+        self.reset_sense_count = r.read_u8()?;
+        self.mrq = r.read_bool()?;
+        self.data_register = r.read_u8()?;
+        self.dma = r.read_bool()?;
+        self.dor = r.read_u8()?;
+        self.busy = r.read_bool()?;
+        self.dio = match r.read_bool()? {
+            true => IoMode::ToCpu,
+            false => IoMode::FromCpu,
+        };
+        self.reading_command = r.read_bool()?;
+        self.command = command_from_u8(r.read_u8()?)?;
+        self.command_fn = command_dispatch_fn(self.command);
+        self.last_command = command_from_u8(r.read_u8()?)?;
+        self.receiving_command = r.read_bool()?;
+        self.command_byte_n = r.read_u32()?;
+        self.command_skip = r.read_bool()?;
+        self.command_deleted = r.read_bool()?;
+        self.operation = read_operation(r)?;
+        self.operation_init = r.read_bool()?;
+        self.operation_final_sid = r.read_u8()?;
+        self.send_interrupt = r.read_bool()?;
+        self.pending_interrupt = r.read_bool()?;
+        self.end_interrupt = r.read_bool()?;
+        self.watchdog_enabled = r.read_bool()?;
+        self.watchdog_trigger_bit = r.read_bool()?;
+        self.watchdog_triggered = r.read_bool()?;
+        self.last_error = read_error(r)?;
+        self.last_status_bytes = r.read_bytes()?.to_vec();
+        self.data_register_out = r.read_bytes()?.iter().copied().collect();
+        self.data_register_in = r.read_bytes()?.iter().copied().collect();
+        self.format_buffer = r.read_bytes()?.iter().copied().collect();
+
+        if r.read_usize()? != self.drive_ct {
+            return Err(SaveStateError::MachineMismatch("floppy drive count".to_string()));
+        }
+        for drive in self.drives.iter_mut().take(self.drive_ct) {
+            drive.load_state(r)?;
+        }
+        self.drive_select = r.read_usize()?;
+        if self.drive_select >= FDC_MAX_DRIVES {
+            return Err(SaveStateError::InvalidValue("FDC drive select"));
+        }
+
+        self.in_dma = r.read_bool()?;
+        self.dma_byte_count = r.read_usize()?;
+        self.dma_bytes_left = r.read_usize()?;
+        self.pio_byte_count = r.read_usize()?;
+        self.pio_sector_byte_count = r.read_usize()?;
+        self.pio_bytes_left = r.read_usize()?;
+        self.xfer_size_sectors = r.read_usize()?;
+        self.xfer_size_bytes = r.read_usize()?;
+        self.xfer_completed_sectors = r.read_usize()?;
+        self.xfer_buffer = r.read_bytes()?.to_vec();
+        Ok(())
+    }
+}
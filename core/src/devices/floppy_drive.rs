@@ -31,6 +31,7 @@
 use crate::{
     device_types::fdc::{FloppyImageType, DRIVE_CAPABILITIES},
     machine_types::FloppyDriveType,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 use anyhow::{anyhow, Error};
 use fluxfox::{
@@ -139,6 +140,9 @@ pub struct FloppyDiskDrive {
     pub(crate) motor_on: bool,
     pub(crate) positioning: bool,
     pub(crate) disk_present: bool,
+    /// The disk change latch. Set when media is inserted or removed, and cleared by a step pulse
+    /// with a disk in the drive.
+    pub(crate) disk_changed: bool,
     pub(crate) write_protected: bool,
     pub(crate) disk_image: Option<DiskImage>,
 
@@ -166,6 +170,7 @@ impl Default for FloppyDiskDrive {
             motor_on: false,
             positioning: false,
             disk_present: false,
+            disk_changed: true,
             write_protected: true,
             disk_image: None,
 
@@ -246,6 +251,7 @@ impl FloppyDiskDrive {
         ));
 
         self.disk_present = true;
+        self.disk_changed = true;
         self.disk_image = Some(image);
         self.write_protected = write_protect;
 
@@ -262,6 +268,7 @@ impl FloppyDiskDrive {
         ));
 
         self.disk_present = true;
+        self.disk_changed = true;
         self.disk_image = Some(image);
         self.write_protected = write_protect;
 
@@ -285,6 +292,7 @@ impl FloppyDiskDrive {
         self.chsn = Default::default();
         self.media_geom = DiskChs::default();
         self.disk_present = false;
+        self.disk_changed = true;
         self.disk_image = None;
     }
 
@@ -413,6 +421,7 @@ impl FloppyDiskDrive {
         _gap3_len: u8,
         _data_len: u8,
         skip_flag: bool,
+        deleted: bool,
     ) -> Result<DriveReadResult, Error> {
         if self.disk_image.is_none() {
             return Err(anyhow!("No media in drive"));
@@ -478,9 +487,12 @@ impl FloppyDiskDrive {
                 read_sector_result.no_dam
             );
 
-            match (skip_flag, read_sector_result.deleted_mark) {
+            // A Read Deleted Data command expects deleted marks, so for it a normal mark is the
+            // control mark - the one that sets the CM bit in ST2 and is skipped by the skip flag.
+            let control_mark = read_sector_result.deleted_mark != deleted;
+            match (skip_flag, control_mark) {
                 (_, false) => {
-                    // Normal mark read, skip flag irrelevant. Read current sector and continue.
+                    // Expected mark read, skip flag irrelevant. Read current sector and continue.
                     if read_sector_result.address_crc_error {
                         self.operation_status.address_crc_error = true;
                         break;
@@ -498,7 +510,7 @@ impl FloppyDiskDrive {
                     continue;
                 }
                 (false, true) => {
-                    // Deleted mark read, and skip flag not set. Read current sector and stop.
+                    // Control mark read, and skip flag not set. Read current sector and stop.
                     self.operation_status.deleted_mark = true;
 
                     if read_sector_result.address_crc_error {
@@ -514,7 +526,7 @@ impl FloppyDiskDrive {
                     break;
                 }
                 (true, true) => {
-                    // Deleted mark read, skip flag true. Skip the current sector and continue.
+                    // Control mark read, skip flag true. Skip the current sector and continue.
                     sid = sid.wrapping_add(1);
                     self.operation_status.deleted_mark = true;
                     if read_sector_result.address_crc_error {
@@ -696,6 +708,10 @@ impl FloppyDiskDrive {
         if !self.is_seek_valid(c) {
             return;
         }
+        // Stepping the head with a disk in the drive resets the disk change latch.
+        if self.disk_present && c != self.cylinder {
+            self.disk_changed = false;
+        }
         self.cylinder = c;
         self.chsn.set_c(c);
     }
@@ -744,6 +760,16 @@ impl FloppyDiskDrive {
         self.disk_present
     }
 
+    /// Returns true if the media has been changed since the head last stepped with a disk present.
+    pub fn disk_changed(&self) -> bool {
+        self.disk_changed
+    }
+
+    /// Returns true if the drive motor is currently spinning.
+    pub fn motor_running(&self) -> bool {
+        self.motor_on
+    }
+
     pub fn image_state(&self) -> Option<FloppyImageState> {
         if let Some(image) = &self.disk_image {
             let sector_map = image.get_sector_map();
@@ -759,3 +785,125 @@ impl FloppyDiskDrive {
         }
     }
 }
+
+/// The mounted disk image is not part of a drive's state. Media presence, write protection and
+/// geometry reflect whatever is mounted when the state is restored.
+impl SaveState for FloppyDiskDrive {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.error_signal);
+        w.write_u16(self.cylinder);
+        w.write_u16(self.chsn.c());
+        w.write_u8(self.chsn.h());
+        w.write_u8(self.chsn.s());
+        w.write_u8(self.chsn.n());
+        w.write_bool(self.motor_on);
+        w.write_bool(self.positioning);
+
+        let status = &self.operation_status;
+        w.write_u8(match status.op_type {
+            FloppyDriveOperation::NoOperation => 0,
+            FloppyDriveOperation::ReadData => 1,
+            FloppyDriveOperation::WriteData => 2,
+        });
+        w.write_bool(status.sector_not_found);
+        w.write_bool(status.address_crc_error);
+        w.write_bool(status.data_crc_error);
+        w.write_bool(status.deleted_mark);
+        w.write_bool(status.no_dam);
+        w.write_bool(status.wrong_cylinder);
+        w.write_bool(status.wrong_head);
+
+        w.write_bytes(self.operation_buf.get_ref());
+        w.write_u64(self.operation_buf.position());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.error_signal = r.read_bool()?;
+        self.cylinder = r.read_u16()?;
+        let c = r.read_u16()?;
+        let h = r.read_u8()?;
+        let s = r.read_u8()?;
+        let n = r.read_u8()?;
+        self.chsn = DiskChsn::new(c, h, s, n);
+        self.motor_on = r.read_bool()? && self.disk_present;
+        self.positioning = r.read_bool()?;
+
+        let op_type = match r.read_u8()? {
+            0 => FloppyDriveOperation::NoOperation,
+            1 => FloppyDriveOperation::ReadData,
+            2 => FloppyDriveOperation::WriteData,
+            _ => return Err(SaveStateError::InvalidValue("floppy drive operation")),
+        };
+        self.operation_status = OperationStatus {
+            op_type,
+            sector_not_found: r.read_bool()?,
+            address_crc_error: r.read_bool()?,
+            data_crc_error: r.read_bool()?,
+            deleted_mark: r.read_bool()?,
+            no_dam: r.read_bool()?,
+            wrong_cylinder: r.read_bool()?,
+            wrong_head: r.read_bool()?,
+        };
+
+        self.operation_buf = Cursor::new(r.read_bytes()?.to_vec());
+        self.operation_buf.set_position(r.read_u64()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_change_latch() {
+        let mut drive = FloppyDiskDrive::new(0, FloppyDriveType::Floppy360K);
+        assert!(drive.disk_changed());
+
+        // Stepping with no disk in the drive leaves the latch set.
+        drive.seek(1);
+        assert!(drive.disk_changed());
+
+        // A seek to the current cylinder doesn't step the head.
+        drive.disk_present = true;
+        drive.seek(1);
+        assert!(drive.disk_changed());
+
+        drive.seek(2);
+        assert!(!drive.disk_changed());
+
+        drive.unload_image();
+        assert!(drive.disk_changed());
+    }
+
+    #[test]
+    fn test_read_deleted_data() {
+        let mut drive = FloppyDiskDrive::new(0, FloppyDriveType::Floppy360K);
+        drive.create_new_image(StandardFormat::PcFloppy360, true).unwrap();
+
+        // Sector 2 gets a deleted data mark, sectors 1 and 3 keep normal ones.
+        let chs = DiskChs::new(0, 0, 2);
+        drive
+            .command_write_data(0, chs, 1, 2, &[0xE5; 512], false, true)
+            .unwrap();
+
+        // Read Data stops after reading the deleted sector.
+        let result = drive
+            .command_read_data(0, DiskChs::new(0, 0, 1), 3, 2, 0, 0, 0, false, false)
+            .unwrap();
+        assert_eq!(result.sectors_read, 2);
+        assert!(result.deleted_mark);
+
+        // Read Deleted Data reads the deleted sector without setting the control mark...
+        let result = drive.command_read_data(0, chs, 1, 2, 0, 0, 0, false, true).unwrap();
+        assert_eq!(result.sectors_read, 1);
+        assert!(!result.deleted_mark);
+
+        // ...and stops after reading a normal sector.
+        let result = drive
+            .command_read_data(0, DiskChs::new(0, 0, 3), 2, 2, 0, 0, 0, false, true)
+            .unwrap();
+        assert_eq!(result.sectors_read, 1);
+        assert!(result.deleted_mark);
+    }
+}
@@ -42,10 +42,18 @@
     used to determine the stick position.  This of course, requires frequent
     and costly polling of the game port to determine the stick position.
 
+    Since the polling loop's count depends on CPU speed, the game port catches
+    up to the current CPU cycle whenever it is accessed, so that the one-shots
+    expire at the correct point within a device update period.
+
 
 */
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE};
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+use serde_derive::Deserialize;
 
 pub const GAMEPORT_DEFAULT_PORT: u16 = 0x201;
 pub const GAMEPORT_DEFAULT_MASK: u16 = 0xFFFF;
@@ -68,11 +76,14 @@ pub const POT_OHMS: f64 = 100_000.0;
 pub const BASE_CHARGE_TIME_US: f64 = 25.2;
 pub const CHARGE_FACTOR: f64 = 0.011;
 
-#[derive(Default)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
 pub enum ControllerLayout {
     #[default]
     TwoJoysticksTwoButtons,
     OneJoystickFourButtons,
+    // A single controller with four axes and four buttons, such as a flight stick with throttle and
+    // rudder. The third and fourth axes are read through the second joystick's X and Y inputs.
+    OneJoystickFourAxes,
 }
 
 #[derive(Default)]
@@ -101,16 +112,22 @@ pub struct GamePort {
     layout:    ControllerLayout,
     sticks:    [Stick; 2],
     buttons:   [bool; 4],
+    timewarp:  f64,
 }
 
 impl GamePort {
-    pub fn new(port_base: Option<u16>) -> Self {
+    pub fn new(port_base: Option<u16>, layout: Option<ControllerLayout>) -> Self {
         GamePort {
             port_base: port_base.unwrap_or(GAMEPORT_DEFAULT_PORT),
+            layout: layout.unwrap_or_default(),
             ..Default::default()
         }
     }
 
+    pub fn layout(&self) -> ControllerLayout {
+        self.layout
+    }
+
     pub fn set_button(&mut self, controller: usize, button: usize, state: bool) {
         match self.layout {
             ControllerLayout::TwoJoysticksTwoButtons => {
@@ -118,7 +135,7 @@ impl GamePort {
                     self.buttons[button + (controller * 2)] = state;
                 }
             }
-            ControllerLayout::OneJoystickFourButtons => {
+            ControllerLayout::OneJoystickFourButtons | ControllerLayout::OneJoystickFourAxes => {
                 if controller == 0 && button < 4 {
                     self.buttons[button] = state;
                 }
@@ -148,13 +165,52 @@ impl GamePort {
                     }
                 }
             }
+            ControllerLayout::OneJoystickFourAxes => {
+                if controller == 0 && stick < 2 {
+                    if let Some(x) = x {
+                        self.sticks[stick].x.pos = x;
+                    }
+                    if let Some(y) = y {
+                        self.sticks[stick].y.pos = y;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set the position of a single axis of a controller, in the range -1.0 to 1.0. Axes are numbered
+    /// X, Y, then, for four-axis controllers, the third and fourth axes.
+    pub fn set_axis(&mut self, controller: usize, axis: usize, pos: f64) {
+        let pos = pos.clamp(-1.0, 1.0);
+        let (stick, axis) = (axis / 2, axis % 2);
+        let (x, y) = match axis {
+            0 => (Some(pos), None),
+            _ => (None, Some(pos)),
+        };
+        self.set_stick_pos(controller, stick, x, y);
+    }
+
+    /// Set all buttons and stick positions at once, as returned by get_state(). Positions are
+    /// set directly, without regard to the controller layout.
+    pub fn set_inputs(&mut self, buttons: [bool; 4], sticks: [(f64, f64); 2]) {
+        self.buttons = buttons;
+        for (stick, (x, y)) in self.sticks.iter_mut().zip(sticks) {
+            stick.x.pos = x;
+            stick.y.pos = y;
         }
     }
 
     pub fn get_controller_count(&self) -> usize {
         match self.layout {
             ControllerLayout::TwoJoysticksTwoButtons => 2,
-            ControllerLayout::OneJoystickFourButtons => 1,
+            ControllerLayout::OneJoystickFourButtons | ControllerLayout::OneJoystickFourAxes => 1,
+        }
+    }
+
+    pub fn get_axis_count(&self) -> usize {
+        match self.layout {
+            ControllerLayout::OneJoystickFourAxes => 4,
+            _ => 2,
         }
     }
 
@@ -208,12 +264,31 @@ impl GamePort {
         }
     }
 
-    pub fn run(&mut self, us: f64) {
+    /// Advance the one-shots to the current CPU cycle within this device update period. `delta` is
+    /// the time elapsed since the last call to run().
+    fn catch_up(&mut self, delta: DeviceRunTimeUnit) {
+        if let DeviceRunTimeUnit::Microseconds(us) = delta {
+            if us > self.timewarp {
+                self.time_axes(us - self.timewarp);
+                self.timewarp = us;
+            }
+        }
+    }
+
+    fn time_axes(&mut self, us: f64) {
         for sticks in self.sticks.iter_mut() {
             time_axis(&mut sticks.x, us);
             time_axis(&mut sticks.y, us);
         }
     }
+
+    pub fn run(&mut self, us: f64) {
+        // Don't count time we already advanced through when catching up on port access.
+        let us = us - std::mem::take(&mut self.timewarp);
+        if us > 0.0 {
+            self.time_axes(us);
+        }
+    }
 }
 
 #[inline]
@@ -235,8 +310,34 @@ pub fn time_axis(axis: &mut Axis, us: f64) {
     }
 }
 
+/// Stick positions and buttons reflect the host controller and are left as they are; only the
+/// state of the one-shot timers is saved.
+impl SaveState for GamePort {
+    fn save_state(&self, w: &mut StateWriter) {
+        for stick in self.sticks.iter() {
+            for axis in [&stick.x, &stick.y] {
+                w.write_f64(axis.time);
+                w.write_bool(axis.timing);
+            }
+        }
+        w.write_f64(self.timewarp);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for stick in self.sticks.iter_mut() {
+            for axis in [&mut stick.x, &mut stick.y] {
+                axis.time = r.read_f64()?;
+                axis.timing = r.read_bool()?;
+            }
+        }
+        self.timewarp = r.read_f64()?;
+        Ok(())
+    }
+}
+
 impl IoDevice for GamePort {
-    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+    fn read_u8(&mut self, port: u16, delta: DeviceRunTimeUnit) -> u8 {
+        self.catch_up(delta);
         if (port & GAMEPORT_DEFAULT_MASK) == self.port_base {
             self.port_read()
         }
@@ -245,8 +346,9 @@ impl IoDevice for GamePort {
         }
     }
 
-    fn write_u8(&mut self, _port: u16, _data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+    fn write_u8(&mut self, _port: u16, _data: u8, _bus: Option<&mut BusInterface>, delta: DeviceRunTimeUnit) {
         // Writing to the game port resets the one-shot counters.
+        self.catch_up(delta);
         self.reset_oneshots();
     }
 
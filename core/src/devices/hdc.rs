@@ -39,6 +39,7 @@ use core::fmt::Display;
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
     devices::dma,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 //use crate::fdc::Operation;
 use crate::{bus::IoDevice, device_types::hdc::HardDiskFormat, vhd::VirtualHardDisk};
@@ -97,6 +98,7 @@ pub enum ControllerError {
     NoError,
     InvalidDevice,
     UnsupportedVHD,
+    NoController,
 }
 impl Error for ControllerError {}
 impl Display for ControllerError {
@@ -106,6 +108,7 @@ impl Display for ControllerError {
             ControllerError::InvalidDevice => {
                 write!(f, "The specified Device ID was out of range [0..1]")
             }
+            ControllerError::NoController => write!(f, "No Hard Disk Controller present!"),
             ControllerError::UnsupportedVHD => {
                 write!(f, "The VHD file did not match the list of supported drive types.")
             }
@@ -150,6 +153,45 @@ pub enum Command {
     WriteLongTrack,
 }
 
+// Lookup tables for restoring enums from a save state, in declaration order.
+const STATES: [State; 7] = [
+    State::Reset,
+    State::WaitingForCommand,
+    State::ReceivingCommand,
+    State::ExecutingCommand,
+    State::HaveCommandResult,
+    State::HaveCommandStatus,
+    State::HaveSenseBytes,
+];
+const OPERATION_ERRORS: [OperationError; 4] = [
+    OperationError::NoError,
+    OperationError::NoReadySignal,
+    OperationError::InvalidCommand,
+    OperationError::IllegalAccess,
+];
+const COMMANDS: [Command; 20] = [
+    Command::None,
+    Command::TestDriveReady,
+    Command::Recalibrate,
+    Command::RequestSense,
+    Command::FormatDrive,
+    Command::ReadyVerify,
+    Command::FormatTrack,
+    Command::FormatBadTrack,
+    Command::Read,
+    Command::Write,
+    Command::Seek,
+    Command::Initialize,
+    Command::ReadEccBurstLength,
+    Command::ReadSectorBuffer,
+    Command::WriteSectorBuffer,
+    Command::RamDiagnostic,
+    Command::DriveDiagnostic,
+    Command::ControllerDiagnostic,
+    Command::ReadLongTrack,
+    Command::WriteLongTrack,
+];
+
 type CommandDispatchFn = fn(&mut HardDiskController, &mut BusInterface) -> Continuation;
 
 impl IoDevice for HardDiskController {
@@ -1507,3 +1549,124 @@ impl HardDiskController {
         }
     }
 }
+
+/// Return the handler set_command() installs for a command, if any.
+fn command_dispatch_fn(command: Command) -> Option<CommandDispatchFn> {
+    let command_fn: CommandDispatchFn = match command {
+        Command::TestDriveReady => HardDiskController::command_test_drive_ready,
+        Command::Recalibrate => HardDiskController::command_recalibrate,
+        Command::RequestSense => HardDiskController::command_sense_status,
+        Command::ReadyVerify => HardDiskController::command_ready_verify,
+        Command::Read => HardDiskController::command_read,
+        Command::Write => HardDiskController::command_write,
+        Command::Seek => HardDiskController::command_seek,
+        Command::Initialize => HardDiskController::command_initialize_dc,
+        Command::ReadSectorBuffer => HardDiskController::command_read_sector_buffer,
+        Command::WriteSectorBuffer => HardDiskController::command_write_sector_buffer,
+        Command::RamDiagnostic => HardDiskController::command_ram_diagnostic,
+        Command::DriveDiagnostic => HardDiskController::command_drive_diagnostic,
+        Command::ControllerDiagnostic => HardDiskController::command_controller_diagnostic,
+        _ => return None,
+    };
+    Some(command_fn)
+}
+
+fn read_enum<T: Copy>(r: &mut StateReader, table: &[T], what: &'static str) -> Result<T, SaveStateError> {
+    table
+        .get(r.read_u8()? as usize)
+        .copied()
+        .ok_or(SaveStateError::InvalidValue(what))
+}
+
+/// The contents of the disk images are not saved, only the identity of each mounted image, which
+/// must match when the state is restored. A command in progress continues from where it was.
+impl SaveState for HardDiskController {
+    fn save_state(&self, w: &mut StateWriter) {
+        for drive in self.drives.iter() {
+            w.write_option_str(drive.vhd.as_ref().map(|vhd| vhd.identity()).as_deref());
+            w.write_u16(drive.cylinder);
+            w.write_u8(drive.head);
+            w.write_u8(drive.sector);
+            w.write_bytes(&drive.sector_buf);
+        }
+        w.write_usize(self.drive_select);
+        w.write_u8(self.state as u8);
+        w.write_u8(self.last_error as u8);
+        w.write_usize(self.last_error_drive);
+        w.write_bool(self.error_flag);
+        w.write_bool(self.receiving_dcb);
+        w.write_u8(self.command as u8);
+        w.write_u8(self.last_command as u8);
+        w.write_u32(self.command_byte_n);
+        w.write_bool(self.command_result_pending);
+        w.write_bytes(&self.data_register_in.iter().copied().collect::<Vec<u8>>());
+        w.write_bytes(&self.data_register_out.iter().copied().collect::<Vec<u8>>());
+
+        let status = &self.operation_status;
+        w.write_usize(status.drive_select);
+        w.write_usize(status.buffer_idx);
+        w.write_u8(status.block_ct);
+        w.write_u8(status.block_n);
+        w.write_usize(status.dma_bytes_left);
+        w.write_usize(status.dma_byte_count);
+
+        w.write_bool(self.dma_enabled);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.send_interrupt);
+        w.write_bool(self.clear_interrupt);
+        w.write_bool(self.interrupt_active);
+        w.write_bool(self.send_dreq);
+        w.write_bool(self.clear_dreq);
+        w.write_bool(self.dreq_active);
+        w.write_f64(self.state_accumulator);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for (n, drive) in self.drives.iter_mut().enumerate() {
+            r.read_media(
+                drive.vhd.as_ref().map(|vhd| vhd.identity()),
+                &format!("hard disk {}", n),
+            )?;
+            drive.cylinder = r.read_u16()?;
+            drive.head = r.read_u8()?;
+            drive.sector = r.read_u8()?;
+            r.read_bytes_into(&mut drive.sector_buf, "HDC sector buffer")?;
+        }
+        self.drive_select = r.read_usize()?;
+        self.state = read_enum(r, &STATES, "HDC state")?;
+        self.last_error = read_enum(r, &OPERATION_ERRORS, "HDC error")?;
+        self.last_error_drive = r.read_usize()?;
+        self.error_flag = r.read_bool()?;
+        self.receiving_dcb = r.read_bool()?;
+        self.command = read_enum(r, &COMMANDS, "HDC command")?;
+        self.command_fn = command_dispatch_fn(self.command);
+        self.last_command = read_enum(r, &COMMANDS, "HDC command")?;
+        self.command_byte_n = r.read_u32()?;
+        self.command_result_pending = r.read_bool()?;
+        self.data_register_in = r.read_bytes()?.iter().copied().collect();
+        self.data_register_out = r.read_bytes()?.iter().copied().collect();
+
+        let status = &mut self.operation_status;
+        status.drive_select = r.read_usize()?;
+        status.buffer_idx = r.read_usize()?;
+        status.block_ct = r.read_u8()?;
+        status.block_n = r.read_u8()?;
+        status.dma_bytes_left = r.read_usize()?;
+        status.dma_byte_count = r.read_usize()?;
+
+        self.dma_enabled = r.read_bool()?;
+        self.irq_enabled = r.read_bool()?;
+        self.send_interrupt = r.read_bool()?;
+        self.clear_interrupt = r.read_bool()?;
+        self.interrupt_active = r.read_bool()?;
+        self.send_dreq = r.read_bool()?;
+        self.clear_dreq = r.read_bool()?;
+        self.dreq_active = r.read_bool()?;
+        self.state_accumulator = r.read_f64()?;
+
+        if self.drive_select >= self.drives.len() {
+            return Err(SaveStateError::InvalidValue("HDC drive select"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::inport_mouse.rs
+
+    Implementation of the Microsoft InPort bus mouse adapter.
+
+    The InPort is accessed through an address register that selects one of
+    several internal registers, which are then read or written through the
+    data register. Software sets the Hold bit in the mode register to latch
+    the button status and accumulated movement counters, reads them, then
+    clears the Hold bit.
+
+    The adapter can interrupt at a fixed rate, either unconditionally or
+    only when there is movement or button data to report. The interrupt line
+    can also be held high or low, which drivers use to detect the IRQ.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+pub const INPORT_DEFAULT_PORT: u16 = 0x23C;
+pub const INPORT_DEFAULT_IRQ: u8 = 2;
+
+// Scale factor for host vs emulated mouse deltas, matching the serial mouse.
+const MOUSE_SCALE_DEFAULT: f64 = 0.25;
+
+const INPORT_SIGNATURE: u8 = 0xDE;
+const INPORT_VERSION: u8 = 0x12;
+
+const ADDRESS_RESET: u8 = 0b1000_0000;
+const ADDRESS_MASK: u8 = 0b0000_0111;
+
+// Internal registers
+const REG_STATUS: u8 = 0;
+const REG_DATA_X: u8 = 1;
+const REG_DATA_Y: u8 = 2;
+const REG_MODE: u8 = 7;
+
+// Status register bits
+const STATUS_RBUTTON: u8 = 0b0000_0001;
+const STATUS_MBUTTON: u8 = 0b0000_0010;
+const STATUS_LBUTTON: u8 = 0b0000_0100;
+const STATUS_RBUTTON_DELTA: u8 = 0b0000_1000;
+const STATUS_MBUTTON_DELTA: u8 = 0b0001_0000;
+const STATUS_LBUTTON_DELTA: u8 = 0b0010_0000;
+const STATUS_MOVEMENT: u8 = 0b0100_0000;
+
+// Mode register bits
+const MODE_RATE_MASK: u8 = 0b0000_0111;
+const MODE_DATA_INT: u8 = 0b0000_1000;
+const MODE_TIMER_INT: u8 = 0b0001_0000;
+const MODE_HOLD: u8 = 0b0010_0000;
+
+const RATE_INTR_LOW: u8 = 0;
+const RATE_INTR_HIGH: u8 = 6;
+
+#[derive(Default)]
+pub struct InPortStringState {
+    pub mode:    String,
+    pub status:  String,
+    pub delta_x: String,
+    pub delta_y: String,
+}
+
+pub struct InPortMouse {
+    port_base: u16,
+    irq: u8,
+    scale: f64,
+    address: u8,
+    mode: u8,
+    test: u8,
+    signature_toggle: bool,
+    l_button: bool,
+    r_button: bool,
+    m_button: bool,
+    l_button_delta: bool,
+    r_button_delta: bool,
+    m_button_delta: bool,
+    accum_x: f64,
+    accum_y: f64,
+    latched_status: u8,
+    latched_x: i8,
+    latched_y: i8,
+    timer: f64,
+    intr: bool,
+}
+
+impl IoDevice for InPortMouse {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port - self.port_base {
+            0 => self.address,
+            1 => self.read_register(),
+            2 => {
+                // The identification register alternates between the signature and the version.
+                self.signature_toggle = !self.signature_toggle;
+                match self.signature_toggle {
+                    true => INPORT_SIGNATURE,
+                    false => INPORT_VERSION,
+                }
+            }
+            3 => self.test,
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port - self.port_base {
+            0 => {
+                if data & ADDRESS_RESET != 0 {
+                    log::debug!("InPort: reset");
+                    self.reset();
+                }
+                else {
+                    self.address = data & ADDRESS_MASK;
+                }
+            }
+            1 => self.write_register(data),
+            3 => self.test = data,
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("InPort Address Register"), self.port_base),
+            (String::from("InPort Data Register"), self.port_base + 1),
+            (String::from("InPort Identification Register"), self.port_base + 2),
+            (String::from("InPort Test Register"), self.port_base + 3),
+        ]
+    }
+}
+
+impl InPortMouse {
+    pub fn new(port_base: Option<u16>, irq: Option<u8>, scale: Option<f64>) -> Self {
+        Self {
+            port_base: port_base.unwrap_or(INPORT_DEFAULT_PORT),
+            irq: irq.unwrap_or(INPORT_DEFAULT_IRQ),
+            scale: scale.unwrap_or(MOUSE_SCALE_DEFAULT),
+            address: 0,
+            mode: 0,
+            test: 0,
+            signature_toggle: false,
+            l_button: false,
+            r_button: false,
+            m_button: false,
+            l_button_delta: false,
+            r_button_delta: false,
+            m_button_delta: false,
+            accum_x: 0.0,
+            accum_y: 0.0,
+            latched_status: 0,
+            latched_x: 0,
+            latched_y: 0,
+            timer: 0.0,
+            intr: false,
+        }
+    }
+
+    /// Reset the InPort chip. Button state reflects the physical mouse and is kept.
+    pub fn reset(&mut self) {
+        self.address = 0;
+        self.mode = 0;
+        self.test = 0;
+        self.signature_toggle = false;
+        self.l_button_delta = false;
+        self.r_button_delta = false;
+        self.m_button_delta = false;
+        self.accum_x = 0.0;
+        self.accum_y = 0.0;
+        self.latched_status = 0;
+        self.latched_x = 0;
+        self.latched_y = 0;
+        self.timer = 0.0;
+    }
+
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    /// Receive a mouse update from the host. Deltas are in host units, with positive Y downwards.
+    pub fn update(
+        &mut self,
+        l_button_pressed: bool,
+        r_button_pressed: bool,
+        m_button_pressed: bool,
+        delta_x: f64,
+        delta_y: f64,
+    ) {
+        if l_button_pressed != self.l_button {
+            self.l_button_delta = true;
+        }
+        if r_button_pressed != self.r_button {
+            self.r_button_delta = true;
+        }
+        if m_button_pressed != self.m_button {
+            self.m_button_delta = true;
+        }
+        self.l_button = l_button_pressed;
+        self.r_button = r_button_pressed;
+        self.m_button = m_button_pressed;
+        self.accum_x += delta_x * self.scale;
+        self.accum_y += delta_y * self.scale;
+    }
+
+    fn have_data(&self) -> bool {
+        self.l_button_delta
+            || self.r_button_delta
+            || self.m_button_delta
+            || self.accum_x.abs() >= 1.0
+            || self.accum_y.abs() >= 1.0
+    }
+
+    fn read_register(&self) -> u8 {
+        match self.address {
+            REG_STATUS => self.latched_status,
+            REG_DATA_X => self.latched_x as u8,
+            REG_DATA_Y => self.latched_y as u8,
+            REG_MODE => self.mode,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, data: u8) {
+        match self.address {
+            REG_MODE => {
+                if data & MODE_HOLD != 0 && self.mode & MODE_HOLD == 0 {
+                    self.latch();
+                }
+                if data & MODE_RATE_MASK != self.mode & MODE_RATE_MASK {
+                    self.timer = 0.0;
+                }
+                self.mode = data;
+            }
+            _ => {
+                log::trace!("InPort: write to unsupported register {}: {:02X}", self.address, data);
+            }
+        }
+    }
+
+    /// Latch the button status and movement counters on a rising edge of the Hold bit, then reset
+    /// the counters.
+    fn latch(&mut self) {
+        let dx = self.accum_x.trunc().clamp(i8::MIN as f64, i8::MAX as f64);
+        let dy = self.accum_y.trunc().clamp(i8::MIN as f64, i8::MAX as f64);
+        self.accum_x -= dx;
+        self.accum_y -= dy;
+
+        let mut status = 0;
+        if self.l_button {
+            status |= STATUS_LBUTTON;
+        }
+        if self.r_button {
+            status |= STATUS_RBUTTON;
+        }
+        if self.m_button {
+            status |= STATUS_MBUTTON;
+        }
+        if self.l_button_delta {
+            status |= STATUS_LBUTTON_DELTA;
+        }
+        if self.m_button_delta {
+            status |= STATUS_MBUTTON_DELTA;
+        }
+        if self.r_button_delta {
+            status |= STATUS_RBUTTON_DELTA;
+        }
+        if dx != 0.0 || dy != 0.0 {
+            status |= STATUS_MOVEMENT;
+        }
+
+        self.latched_status = status;
+        self.latched_x = dx as i8;
+        self.latched_y = dy as i8;
+        self.l_button_delta = false;
+        self.r_button_delta = false;
+        self.m_button_delta = false;
+    }
+
+    /// Return the interrupt period for the current mode, in microseconds.
+    fn interrupt_period(&self) -> Option<f64> {
+        let hz = match self.mode & MODE_RATE_MASK {
+            1 => 30.0,
+            2 => 50.0,
+            3 => 100.0,
+            4 => 200.0,
+            _ => return None,
+        };
+        Some(1_000_000.0 / hz)
+    }
+
+    /// Run the InPort for the specified number of microseconds. Returns the new state of the
+    /// interrupt line, if it changed.
+    pub fn run(&mut self, us: f64) -> Option<bool> {
+        let rate = self.mode & MODE_RATE_MASK;
+        let new_intr = if rate == RATE_INTR_HIGH {
+            true
+        }
+        else if rate == RATE_INTR_LOW || self.mode & MODE_TIMER_INT == 0 {
+            false
+        }
+        else if let Some(period) = self.interrupt_period() {
+            // Timer interrupts are brief pulses. Drop the line on the update after raising it.
+            self.timer += us;
+            if self.timer >= period {
+                self.timer %= period;
+                !self.intr && (self.mode & MODE_DATA_INT == 0 || self.have_data())
+            }
+            else {
+                false
+            }
+        }
+        else {
+            false
+        };
+
+        if new_intr != self.intr {
+            self.intr = new_intr;
+            return Some(new_intr);
+        }
+        None
+    }
+
+    pub fn get_string_state(&self) -> InPortStringState {
+        InPortStringState {
+            mode:    format!("{:08b}", self.mode),
+            status:  format!("{:08b}", self.latched_status),
+            delta_x: format!("{}", self.latched_x),
+            delta_y: format!("{}", self.latched_y),
+        }
+    }
+}
+
+impl SaveState for InPortMouse {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.address);
+        w.write_u8(self.mode);
+        w.write_u8(self.test);
+        w.write_bool(self.signature_toggle);
+        w.write_bool(self.l_button);
+        w.write_bool(self.r_button);
+        w.write_bool(self.m_button);
+        w.write_bool(self.l_button_delta);
+        w.write_bool(self.r_button_delta);
+        w.write_bool(self.m_button_delta);
+        w.write_f64(self.accum_x);
+        w.write_f64(self.accum_y);
+        w.write_u8(self.latched_status);
+        w.write_u8(self.latched_x as u8);
+        w.write_u8(self.latched_y as u8);
+        w.write_f64(self.timer);
+        w.write_bool(self.intr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.address = r.read_u8()?;
+        self.mode = r.read_u8()?;
+        self.test = r.read_u8()?;
+        self.signature_toggle = r.read_bool()?;
+        self.l_button = r.read_bool()?;
+        self.r_button = r.read_bool()?;
+        self.m_button = r.read_bool()?;
+        self.l_button_delta = r.read_bool()?;
+        self.r_button_delta = r.read_bool()?;
+        self.m_button_delta = r.read_bool()?;
+        self.accum_x = r.read_f64()?;
+        self.accum_y = r.read_f64()?;
+        self.latched_status = r.read_u8()?;
+        self.latched_x = r.read_u8()? as i8;
+        self.latched_y = r.read_u8()? as i8;
+        self.timer = r.read_f64()?;
+        self.intr = r.read_bool()?;
+        Ok(())
+    }
+}
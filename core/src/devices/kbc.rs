@@ -0,0 +1,490 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::kbc.rs
+
+    Implementation of the Intel 8042 keyboard controller, as used on the
+    IBM PC/AT and compatibles in place of the XT's PPI keyboard interface.
+
+    The 8042 receives serial data from the keyboard (and, on PS/2 style
+    controllers, an auxiliary device such as a mouse) and presents it to the
+    host through a one-byte output buffer at port 60h, raising IRQ1 (or IRQ12
+    for auxiliary data) when the buffer is full. The host may send commands
+    to the controller via port 64h, or to the keyboard via port 60h.
+
+    When translation is enabled in the controller's command byte, scancode
+    set 2 data from the keyboard is translated to set 1 before it reaches the
+    host, so that software written for the XT continues to work.
+
+    The 8042's output port also controls the A20 gate and the CPU reset line.
+
+    The controller does not own the keyboard device; the bus moves bytes
+    between the two.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    devices::keyboard::{SET1_F7, SET2_BREAK_PREFIX, SET2_F7, SET2_TO_SET1},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+pub const KBC_DATA_PORT: u16 = 0x60;
+pub const KBC_COMMAND_PORT: u16 = 0x64;
+pub const KBC_KEYBOARD_IRQ: u8 = 1;
+pub const KBC_AUX_IRQ: u8 = 12;
+
+// Status register bits
+const STATUS_OBF: u8 = 0b0000_0001; // Output buffer full
+const STATUS_SYS: u8 = 0b0000_0100; // System flag, from the command byte
+const STATUS_A2: u8 = 0b0000_1000; // Last write was to the command port
+const STATUS_INH: u8 = 0b0001_0000; // Keyboard not inhibited (keylock)
+const STATUS_AOBF: u8 = 0b0010_0000; // Output buffer holds auxiliary device data
+
+// Command byte bits
+const CMD_INT: u8 = 0b0000_0001; // Enable keyboard output buffer interrupt
+const CMD_INT2: u8 = 0b0000_0010; // Enable auxiliary output buffer interrupt
+const CMD_SYS: u8 = 0b0000_0100; // System flag
+const CMD_DISABLE_KB: u8 = 0b0001_0000; // Disable keyboard interface
+const CMD_DISABLE_AUX: u8 = 0b0010_0000; // Disable auxiliary interface
+const CMD_XLAT: u8 = 0b0100_0000; // Translate set 2 scancodes to set 1
+
+// Output port bits
+const OUT_RESET_N: u8 = 0b0000_0001; // CPU reset, active low
+const OUT_A20: u8 = 0b0000_0010; // A20 gate
+const OUT_DEFAULT: u8 = 0b1101_1111;
+
+// Input port defaults: Keyboard not inhibited, manufacturing jumper not installed, CGA primary display.
+const INPUT_PORT_DEFAULT: u8 = 0b1010_0000;
+const INPUT_PORT_MDA: u8 = 0b0100_0000;
+
+const SELF_TEST_OK: u8 = 0x55;
+const INTERFACE_TEST_OK: u8 = 0x00;
+
+/// The destination of the next byte written to the data port, if set by a controller command.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DataWrite {
+    Keyboard,
+    RamByte(u8),
+    OutputPort,
+    KeyboardOutputBuffer,
+    AuxOutputBuffer,
+    AuxDevice,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Source {
+    Controller,
+    Keyboard,
+    Aux,
+}
+
+fn write_byte_source(w: &mut StateWriter, (byte, source): (u8, Source)) {
+    w.write_u8(byte);
+    w.write_u8(match source {
+        Source::Controller => 0,
+        Source::Keyboard => 1,
+        Source::Aux => 2,
+    });
+}
+
+fn read_byte_source(r: &mut StateReader) -> Result<(u8, Source), SaveStateError> {
+    let byte = r.read_u8()?;
+    let source = match r.read_u8()? {
+        0 => Source::Controller,
+        1 => Source::Keyboard,
+        2 => Source::Aux,
+        _ => return Err(SaveStateError::InvalidValue("KBC data source")),
+    };
+    Ok((byte, source))
+}
+
+#[derive(Default)]
+pub struct KbcStringState {
+    pub status: String,
+    pub command_byte: String,
+    pub output_port: String,
+    pub output_buffer: String,
+    pub translate: String,
+}
+
+pub struct KeyboardController {
+    ram: [u8; 32], // Byte 0 is the command byte
+    status: u8,
+    input_port: u8,
+    output_port: u8,
+    output_buffer: Option<(u8, Source)>,
+    queue: VecDeque<(u8, Source)>, // Bytes generated by the controller or written by the host, awaiting the output buffer.
+    data_write: DataWrite,
+    break_prefix: bool, // A set 2 break prefix was received while translating.
+    has_aux: bool,
+    kb_tx: Option<u8>,
+    aux_tx: Option<u8>,
+    reset_pending: bool,
+    kb_irq: bool,
+    aux_irq: bool,
+}
+
+impl IoDevice for KeyboardController {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port {
+            KBC_DATA_PORT => self.read_data(),
+            KBC_COMMAND_PORT => self.read_status(),
+            _ => unreachable!("KBC: Bad port #"),
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port {
+            KBC_DATA_PORT => self.write_data(data),
+            KBC_COMMAND_PORT => self.write_command(data),
+            _ => unreachable!("KBC: Bad port #"),
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("KBC Data Port"), KBC_DATA_PORT),
+            (String::from("KBC Command/Status Port"), KBC_COMMAND_PORT),
+        ]
+    }
+}
+
+impl KeyboardController {
+    /// Create a new keyboard controller. 'has_aux' specifies a PS/2 style controller with an
+    /// auxiliary device port. 'mda' sets the display type switch on the input port.
+    pub fn new(has_aux: bool, mda: bool) -> Self {
+        let mut ram = [0; 32];
+        ram[0] = CMD_INT | CMD_XLAT;
+        Self {
+            ram,
+            status: STATUS_INH,
+            input_port: INPUT_PORT_DEFAULT | if mda { INPUT_PORT_MDA } else { 0 },
+            output_port: OUT_DEFAULT,
+            output_buffer: None,
+            queue: VecDeque::new(),
+            data_write: DataWrite::Keyboard,
+            break_prefix: false,
+            has_aux,
+            kb_tx: None,
+            aux_tx: None,
+            reset_pending: false,
+            kb_irq: false,
+            aux_irq: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = KeyboardController::new(self.has_aux, self.input_port & INPUT_PORT_MDA != 0);
+    }
+
+    #[inline]
+    fn command_byte(&self) -> u8 {
+        self.ram[0]
+    }
+
+    /// Return whether the A20 gate is enabled via the output port.
+    pub fn a20_enabled(&self) -> bool {
+        self.output_port & OUT_A20 != 0
+    }
+
+    /// Return whether translation of set 2 scancodes to set 1 is enabled.
+    pub fn translation_enabled(&self) -> bool {
+        self.command_byte() & CMD_XLAT != 0
+    }
+
+    /// Return and clear any byte the host has sent to the keyboard.
+    pub fn take_keyboard_tx(&mut self) -> Option<u8> {
+        self.kb_tx.take()
+    }
+
+    /// Return and clear any byte the host has sent to the auxiliary device.
+    pub fn take_aux_tx(&mut self) -> Option<u8> {
+        self.aux_tx.take()
+    }
+
+    /// Return and clear a pending CPU reset request.
+    pub fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset_pending)
+    }
+
+    /// Return whether the controller can accept a byte from the keyboard. The keyboard holds
+    /// data while the output buffer is full or the keyboard interface is disabled.
+    pub fn keyboard_ready(&self) -> bool {
+        self.output_buffer.is_none() && self.queue.is_empty() && self.command_byte() & CMD_DISABLE_KB == 0
+    }
+
+    /// Return whether the controller can accept a byte from the auxiliary device.
+    pub fn aux_ready(&self) -> bool {
+        self.has_aux
+            && self.output_buffer.is_none()
+            && self.queue.is_empty()
+            && self.command_byte() & CMD_DISABLE_AUX == 0
+    }
+
+    /// Receive a byte from the keyboard, translating it if translation is enabled.
+    pub fn receive_keyboard(&mut self, byte: u8) {
+        if !self.translation_enabled() {
+            self.fill_output(byte, Source::Keyboard);
+            return;
+        }
+
+        if byte == SET2_BREAK_PREFIX {
+            // The break prefix is absorbed and sets the high bit of the next translated byte.
+            self.break_prefix = true;
+            return;
+        }
+
+        let mut translated = match byte {
+            SET2_F7 => SET1_F7,
+            0x00..=0x7F => SET2_TO_SET1[byte as usize],
+            _ => byte,
+        };
+        if std::mem::take(&mut self.break_prefix) {
+            translated |= 0x80;
+        }
+        self.fill_output(translated, Source::Keyboard);
+    }
+
+    /// Receive a byte from the auxiliary device.
+    pub fn receive_aux(&mut self, byte: u8) {
+        self.fill_output(byte, Source::Aux);
+    }
+
+    fn fill_output(&mut self, byte: u8, source: Source) {
+        if self.output_buffer.is_none() && !self.kb_irq && !self.aux_irq {
+            self.output_buffer = Some((byte, source));
+        }
+        else {
+            self.queue.push_back((byte, source));
+        }
+    }
+
+    fn read_status(&self) -> u8 {
+        let mut status = self.status & (STATUS_A2 | STATUS_INH);
+        if self.command_byte() & CMD_SYS != 0 {
+            status |= STATUS_SYS;
+        }
+        match self.output_buffer {
+            Some((_, Source::Aux)) => status |= STATUS_OBF | STATUS_AOBF,
+            Some(_) => status |= STATUS_OBF,
+            None => {}
+        }
+        status
+    }
+
+    fn read_data(&mut self) -> u8 {
+        match self.output_buffer.take() {
+            Some((byte, _)) => byte,
+            None => {
+                // Reading an empty output buffer returns the last byte read. We don't track that,
+                // so return 0.
+                0
+            }
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.status &= !STATUS_A2;
+        let write = std::mem::replace(&mut self.data_write, DataWrite::Keyboard);
+        match write {
+            DataWrite::Keyboard => {
+                // Sending data to the keyboard enables the keyboard interface.
+                self.ram[0] &= !CMD_DISABLE_KB;
+                self.kb_tx = Some(data);
+            }
+            DataWrite::RamByte(idx) => {
+                self.ram[idx as usize] = data;
+                if idx == 0 {
+                    log::debug!("KBC: Command byte set: {:02X}", data);
+                }
+            }
+            DataWrite::OutputPort => self.write_output_port(data),
+            DataWrite::KeyboardOutputBuffer => self.fill_output(data, Source::Keyboard),
+            DataWrite::AuxOutputBuffer => self.fill_output(data, Source::Aux),
+            DataWrite::AuxDevice => {
+                self.ram[0] &= !CMD_DISABLE_AUX;
+                self.aux_tx = Some(data);
+            }
+        }
+    }
+
+    fn write_output_port(&mut self, data: u8) {
+        if data & OUT_A20 != self.output_port & OUT_A20 {
+            log::debug!(
+                "KBC: A20 gate {}",
+                if data & OUT_A20 != 0 { "enabled" } else { "disabled" }
+            );
+        }
+        self.output_port = data;
+        if data & OUT_RESET_N == 0 {
+            log::debug!("KBC: CPU reset requested via output port");
+            self.reset_pending = true;
+            self.output_port |= OUT_RESET_N;
+        }
+    }
+
+    fn write_command(&mut self, cmd: u8) {
+        self.status |= STATUS_A2;
+        self.data_write = DataWrite::Keyboard;
+        log::trace!("KBC: Command: {:02X}", cmd);
+        match cmd {
+            0x20..=0x3F => self.fill_output(self.ram[(cmd & 0x1F) as usize], Source::Controller),
+            0x60..=0x7F => self.data_write = DataWrite::RamByte(cmd & 0x1F),
+            0xA7 if self.has_aux => self.ram[0] |= CMD_DISABLE_AUX,
+            0xA8 if self.has_aux => self.ram[0] &= !CMD_DISABLE_AUX,
+            0xA9 if self.has_aux => self.fill_output(INTERFACE_TEST_OK, Source::Controller),
+            0xAA => {
+                // Controller self test. Also sets the system flag.
+                self.ram[0] |= CMD_SYS;
+                self.fill_output(SELF_TEST_OK, Source::Controller);
+            }
+            0xAB => self.fill_output(INTERFACE_TEST_OK, Source::Controller),
+            0xAD => self.ram[0] |= CMD_DISABLE_KB,
+            0xAE => self.ram[0] &= !CMD_DISABLE_KB,
+            0xC0 => self.fill_output(self.input_port, Source::Controller),
+            0xD0 => self.fill_output(self.output_port, Source::Controller),
+            0xD1 => self.data_write = DataWrite::OutputPort,
+            0xD2 if self.has_aux => self.data_write = DataWrite::KeyboardOutputBuffer,
+            0xD3 if self.has_aux => self.data_write = DataWrite::AuxOutputBuffer,
+            0xD4 if self.has_aux => self.data_write = DataWrite::AuxDevice,
+            0xDD => self.write_output_port(self.output_port & !OUT_A20),
+            0xDF => self.write_output_port(self.output_port | OUT_A20),
+            0xE0 => self.fill_output(0x00, Source::Controller),
+            0xF0..=0xFF => {
+                // Pulse output port bits. A bit is pulsed low if its corresponding command bit is 0.
+                // Only bit 0 (reset) has an effect.
+                if cmd & OUT_RESET_N == 0 {
+                    log::debug!("KBC: CPU reset requested via pulse command");
+                    self.reset_pending = true;
+                }
+            }
+            _ => log::warn!("KBC: Unhandled command: {:02X}", cmd),
+        }
+    }
+
+    /// Run the controller. Returns the new state of the keyboard and auxiliary IRQ lines, if changed.
+    pub fn run(&mut self, _us: f64) -> (Option<bool>, Option<bool>) {
+        // Refill the output buffer from the queue, but only once the IRQ lines have dropped, so that
+        // each byte produces a fresh interrupt edge.
+        if self.output_buffer.is_none() && !self.kb_irq && !self.aux_irq {
+            self.output_buffer = self.queue.pop_front();
+        }
+
+        let cmd = self.command_byte();
+        let (kb_irq, aux_irq) = match self.output_buffer {
+            Some((_, Source::Aux)) => (false, cmd & CMD_INT2 != 0),
+            Some(_) => (cmd & CMD_INT != 0, false),
+            None => (false, false),
+        };
+
+        let kb_change = (kb_irq != self.kb_irq).then_some(kb_irq);
+        let aux_change = (aux_irq != self.aux_irq).then_some(aux_irq);
+        self.kb_irq = kb_irq;
+        self.aux_irq = aux_irq;
+        (kb_change, aux_change)
+    }
+
+    pub fn get_string_state(&self) -> KbcStringState {
+        KbcStringState {
+            status: format!("{:08b}", self.read_status()),
+            command_byte: format!("{:08b}", self.command_byte()),
+            output_port: format!("{:08b}", self.output_port),
+            output_buffer: match self.output_buffer {
+                Some((byte, source)) => format!("{:02X} ({:?})", byte, source),
+                None => "Empty".to_string(),
+            },
+            translate: format!("{}", self.translation_enabled()),
+        }
+    }
+}
+
+/// The auxiliary port and display switch are configuration and are not saved.
+impl SaveState for KeyboardController {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.status);
+        w.write_u8(self.output_port);
+        w.write_bool(self.output_buffer.is_some());
+        if let Some(output) = self.output_buffer {
+            write_byte_source(w, output);
+        }
+        w.write_u32(self.queue.len() as u32);
+        for output in self.queue.iter() {
+            write_byte_source(w, *output);
+        }
+        match self.data_write {
+            DataWrite::Keyboard => w.write_u8(0),
+            DataWrite::RamByte(idx) => {
+                w.write_u8(1);
+                w.write_u8(idx);
+            }
+            DataWrite::OutputPort => w.write_u8(2),
+            DataWrite::KeyboardOutputBuffer => w.write_u8(3),
+            DataWrite::AuxOutputBuffer => w.write_u8(4),
+            DataWrite::AuxDevice => w.write_u8(5),
+        }
+        w.write_bool(self.break_prefix);
+        w.write_option_u8(self.kb_tx);
+        w.write_option_u8(self.aux_tx);
+        w.write_bool(self.reset_pending);
+        w.write_bool(self.kb_irq);
+        w.write_bool(self.aux_irq);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.read_bytes_into(&mut self.ram, "KBC RAM")?;
+        self.status = r.read_u8()?;
+        self.output_port = r.read_u8()?;
+        self.output_buffer = match r.read_bool()? {
+            true => Some(read_byte_source(r)?),
+            false => None,
+        };
+        let queue_len = r.read_u32()?;
+        self.queue.clear();
+        for _ in 0..queue_len {
+            self.queue.push_back(read_byte_source(r)?);
+        }
+        self.data_write = match r.read_u8()? {
+            0 => DataWrite::Keyboard,
+            1 => DataWrite::RamByte(r.read_u8()? & 0x1F),
+            2 => DataWrite::OutputPort,
+            3 => DataWrite::KeyboardOutputBuffer,
+            4 => DataWrite::AuxOutputBuffer,
+            5 => DataWrite::AuxDevice,
+            _ => return Err(SaveStateError::InvalidValue("KBC data write")),
+        };
+        self.break_prefix = r.read_bool()?;
+        self.kb_tx = r.read_option_u8()?;
+        self.aux_tx = r.read_option_u8()?;
+        self.reset_pending = r.read_bool()?;
+        self.kb_irq = r.read_bool()?;
+        self.aux_irq = r.read_bool()?;
+        Ok(())
+    }
+}
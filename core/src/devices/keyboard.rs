@@ -28,6 +28,13 @@
 
     Implementation of various keyboards.
 
+    Keyboards natively generate scancode set 1 (XT) sequences. A keyboard
+    attached to an 8042 keyboard controller may be switched to scancode set 2
+    or 3, in which case set 1 sequences are converted on their way into the
+    keyboard buffer. Such keyboards also accept commands from the host,
+    including setting the typematic delay and rate and the state of the
+    keyboard LEDs.
+
 */
 
 use anyhow::{bail, Result};
@@ -43,7 +50,11 @@ use strum::IntoEnumIterator;
 use serde_derive::Deserialize;
 use toml;
 
-use crate::{keys::MartyKey, machine::KeybufferEntry};
+use crate::{
+    keys::MartyKey,
+    machine::KeybufferEntry,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 // Define the various types of keyboard we can emulate.
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
@@ -108,6 +119,91 @@ pub enum TranslationMode {
     Scancode,
 }
 
+/// The scancode set a keyboard transmits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+    Set3,
+}
+
+/// Scancode set 3 allows the host to select whether keys are typematic, send break codes, or both.
+/// We only emulate the all-keys commands; per-key settings are acknowledged and ignored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Set3KeyMode {
+    TypematicMakeBreak,
+    Typematic,
+    MakeBreak,
+    MakeOnly,
+}
+
+/// The table used by the 8042 to translate scancode set 2 to set 1, indexed by set 2 make code.
+/// Also used in reverse to convert set 1 make codes to set 2.
+pub const SET2_TO_SET1: [u8; 128] = [
+    0xFF, 0x43, 0x41, 0x3F, 0x3D, 0x3B, 0x3C, 0x58, 0x64, 0x44, 0x42, 0x40, 0x3E, 0x0F, 0x29, 0x59, //
+    0x65, 0x38, 0x2A, 0x70, 0x1D, 0x10, 0x02, 0x5A, 0x66, 0x71, 0x2C, 0x1F, 0x1E, 0x11, 0x03, 0x5B, //
+    0x67, 0x2E, 0x2D, 0x20, 0x12, 0x05, 0x04, 0x5C, 0x68, 0x39, 0x2F, 0x21, 0x14, 0x13, 0x06, 0x5D, //
+    0x69, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x5E, 0x6A, 0x72, 0x32, 0x24, 0x16, 0x08, 0x09, 0x5F, //
+    0x6B, 0x33, 0x25, 0x17, 0x18, 0x0B, 0x0A, 0x60, 0x6C, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0C, 0x61, //
+    0x6D, 0x73, 0x28, 0x74, 0x1A, 0x0D, 0x62, 0x6E, 0x3A, 0x36, 0x1C, 0x1B, 0x75, 0x2B, 0x63, 0x76, //
+    0x55, 0x56, 0x77, 0x78, 0x79, 0x7A, 0x0E, 0x7B, 0x7C, 0x4F, 0x7D, 0x4B, 0x47, 0x7E, 0x7F, 0x6F, //
+    0x52, 0x53, 0x50, 0x4C, 0x4D, 0x48, 0x01, 0x45, 0x57, 0x4E, 0x51, 0x4A, 0x37, 0x49, 0x46, 0x54, //
+];
+
+/// F7 is the only set 2 make code above 0x7F.
+pub const SET2_F7: u8 = 0x83;
+pub const SET1_F7: u8 = 0x41;
+pub const SET2_BREAK_PREFIX: u8 = 0xF0;
+
+/// Scancode set 3 make codes, indexed by set 1 make code. Zero entries have no set 3 equivalent.
+/// Set 3 has no prefixed codes, so keys with E0-prefixed set 1 codes are looked up in
+/// SET1_E0_TO_SET3 instead.
+pub const SET1_TO_SET3: [u8; 0x59] = [
+    0x00, 0x08, 0x16, 0x1E, 0x26, 0x25, 0x2E, 0x36, 0x3D, 0x3E, 0x46, 0x45, 0x4E, 0x55, 0x66, 0x0D, //
+    0x15, 0x1D, 0x24, 0x2D, 0x2C, 0x35, 0x3C, 0x43, 0x44, 0x4D, 0x54, 0x5B, 0x5A, 0x11, 0x1C, 0x1B, //
+    0x23, 0x2B, 0x34, 0x33, 0x3B, 0x42, 0x4B, 0x4C, 0x52, 0x0E, 0x12, 0x5C, 0x1A, 0x22, 0x21, 0x2A, //
+    0x32, 0x31, 0x3A, 0x41, 0x49, 0x4A, 0x59, 0x7E, 0x19, 0x29, 0x14, 0x07, 0x0F, 0x17, 0x1F, 0x27, //
+    0x2F, 0x37, 0x3F, 0x47, 0x4F, 0x76, 0x5F, 0x6C, 0x75, 0x7D, 0x84, 0x6B, 0x73, 0x74, 0x7C, 0x69, //
+    0x72, 0x7A, 0x70, 0x71, 0x57, 0x00, 0x13, 0x56, 0x5E, //
+];
+
+/// Set 1 E0-prefixed make codes and their set 3 equivalents.
+pub const SET1_E0_TO_SET3: [(u8, u8); 17] = [
+    (0x1C, 0x79), // Keypad Enter
+    (0x1D, 0x58), // Right Ctrl
+    (0x35, 0x77), // Keypad /
+    (0x37, 0x57), // Print Screen
+    (0x38, 0x39), // Right Alt
+    (0x47, 0x6E), // Home
+    (0x48, 0x63), // Up
+    (0x49, 0x6F), // Page Up
+    (0x4B, 0x61), // Left
+    (0x4D, 0x6A), // Right
+    (0x4F, 0x65), // End
+    (0x50, 0x60), // Down
+    (0x51, 0x6D), // Page Down
+    (0x52, 0x67), // Insert
+    (0x53, 0x64), // Delete
+    (0x5B, 0x8B), // Left Windows
+    (0x5C, 0x8C), // Right Windows
+];
+pub const SET3_PAUSE: u8 = 0x62;
+
+// Keyboard command responses.
+pub const KB_RESPONSE_ACK: u8 = 0xFA;
+pub const KB_RESPONSE_RESEND: u8 = 0xFE;
+pub const KB_RESPONSE_BAT_OK: u8 = 0xAA;
+pub const KB_RESPONSE_ECHO: u8 = 0xEE;
+
+/// Commands that are followed by a parameter byte from the host.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum KeyboardCommandParam {
+    SetLeds,
+    ScancodeSet,
+    Typematic,
+    Set3KeyList, // A list of keys follows, terminated by the next command.
+}
+
 #[derive(Clone, Debug)]
 pub struct KeyState {
     pressed: bool,
@@ -174,12 +270,19 @@ pub struct Keyboard {
     kb_hash: HashMap<MartyKey, KeyState>,
     keys_pressed: Vec<MartyKey>,
     typematic: bool,
-    typematic_delay: f64, // Typematic repeat delay from initial keypress (ms)
-    typematic_rate: f64,  // Typematic repeat rate (ms)
+    typematic_delay: f64,           // Typematic repeat delay from initial keypress (ms)
+    typematic_rate: f64,            // Typematic repeat rate (ms)
+    typematic_defaults: (f64, f64), // Delay and rate restored by the Set Defaults and Reset commands
+    set3_mode: Set3KeyMode,
     kb_buffer_size: usize,
     kb_buffer: Vec<u8>, // Keyboard buffer. Variable length depending on keyboard model.
     kb_buffer_overflow: bool,
     keycode_mappings: Vec<KeycodeMapping>,
+    scancode_set: ScancodeSet,
+    enabled: bool,
+    leds: u8,
+    pending_param: Option<KeyboardCommandParam>,
+    responses: VecDeque<u8>, // Responses to host commands, delivered ahead of scancodes.
 }
 
 impl Default for Keyboard {
@@ -192,10 +295,17 @@ impl Default for Keyboard {
             typematic: true,
             typematic_delay: 500.0,
             typematic_rate: 100.0,
+            typematic_defaults: (500.0, 100.0),
+            set3_mode: Set3KeyMode::TypematicMakeBreak,
             kb_buffer_size: 1,
             kb_buffer: Vec::new(),
             kb_buffer_overflow: false,
             keycode_mappings: Vec::new(),
+            scancode_set: ScancodeSet::Set1,
+            enabled: true,
+            leds: 0,
+            pending_param: None,
+            responses: VecDeque::new(),
         }
     }
 }
@@ -233,6 +343,7 @@ impl Keyboard {
         if let Some(rate) = rate {
             self.typematic_rate = rate;
         }
+        self.typematic_defaults = (self.typematic_delay, self.typematic_rate);
 
         log::debug!(
             "Typematic paramters set: enabled: {}, delay: {:.2}, rate: {:.2}",
@@ -263,6 +374,218 @@ impl Keyboard {
         self.kb_type
     }
 
+    /// Set the scancode set the keyboard transmits. Keyboards attached to an 8042 use set 2.
+    /// AT keyboards buffer their output, so the keyboard buffer is enlarged accordingly.
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.scancode_set = set;
+        if set != ScancodeSet::Set1 {
+            self.kb_buffer_size = 16;
+        }
+    }
+
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.scancode_set
+    }
+
+    /// Return the LED state last set by the host (bit 0: Scroll Lock, bit 1: Num Lock, bit 2: Caps Lock).
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    /// Convert a scancode set 1 sequence into scancode set 2.
+    pub fn set1_to_set2(codes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(codes.len() * 2);
+        for &code in codes {
+            match code {
+                0xE0 | 0xE1 => out.push(code),
+                _ => {
+                    if code & 0x80 != 0 {
+                        out.push(SET2_BREAK_PREFIX);
+                    }
+                    let make = code & 0x7F;
+                    if make == SET1_F7 {
+                        out.push(SET2_F7);
+                    }
+                    else if let Some(idx) = SET2_TO_SET1.iter().position(|&c| c == make) {
+                        out.push(idx as u8);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Convert a scancode set 1 sequence into scancode set 3.
+    /// The fake shift codes set 1 wraps around some keys have no equivalent in set 3 and are dropped.
+    pub fn set1_to_set3(codes: &[u8], mode: Set3KeyMode) -> Vec<u8> {
+        let mut out = Vec::with_capacity(codes.len() * 2);
+        let mut iter = codes.iter().copied();
+        while let Some(code) = iter.next() {
+            let (make, is_break) = match code {
+                0xE0 => match iter.next() {
+                    Some(code) => match SET1_E0_TO_SET3.iter().find(|(set1, _)| *set1 == code & 0x7F) {
+                        Some((_, set3)) => (*set3, code & 0x80 != 0),
+                        None => continue,
+                    },
+                    None => break,
+                },
+                0xE1 => {
+                    // Pause is sent as E1 1D 45 on make and E1 9D C5 on break.
+                    let code = iter.next().unwrap_or(0);
+                    iter.next();
+                    (SET3_PAUSE, code & 0x80 != 0)
+                }
+                _ => match SET1_TO_SET3.get((code & 0x7F) as usize) {
+                    Some(&set3) if set3 != 0 => (set3, code & 0x80 != 0),
+                    _ => continue,
+                },
+            };
+            if is_break {
+                if mode == Set3KeyMode::MakeOnly || mode == Set3KeyMode::Typematic {
+                    continue;
+                }
+                out.push(SET2_BREAK_PREFIX);
+            }
+            out.push(make);
+        }
+        out
+    }
+
+    /// Convert a typematic rate/delay parameter byte to a delay and repeat interval in milliseconds.
+    /// Bits 0-4 select the rate, from 30 to 2 characters per second, and bits 5-6 select a delay of
+    /// 250 to 1000ms.
+    pub fn typematic_from_byte(byte: u8) -> (f64, f64) {
+        let delay = ((byte >> 5) & 0x03) as f64 * 250.0 + 250.0;
+        let a = (byte & 0x07) as f64;
+        let b = ((byte >> 3) & 0x03) as u32;
+        let rate = (8.0 + a) * 2u32.pow(b) as f64 * 4.17;
+        (delay, rate)
+    }
+
+    /// Restore the typematic parameters and key modes selected by the Set Defaults command.
+    fn set_defaults(&mut self) {
+        (self.typematic_delay, self.typematic_rate) = self.typematic_defaults;
+        self.set3_mode = Set3KeyMode::TypematicMakeBreak;
+    }
+
+    /// Receive a command or parameter byte from the host, via a keyboard controller.
+    /// Responses are queued and delivered ahead of any scancodes.
+    pub fn command(&mut self, byte: u8) {
+        if let Some(KeyboardCommandParam::Set3KeyList) = self.pending_param {
+            if byte < 0xED {
+                log::debug!("Keyboard: Ignoring per-key mode for set 3 key: {:02X}", byte);
+                self.responses.push_back(KB_RESPONSE_ACK);
+                return;
+            }
+            // Any command byte terminates the key list.
+            self.pending_param = None;
+        }
+
+        if let Some(param) = self.pending_param.take() {
+            match param {
+                KeyboardCommandParam::SetLeds => {
+                    self.leds = byte & 0x07;
+                }
+                KeyboardCommandParam::ScancodeSet => {
+                    match byte {
+                        0 => {
+                            // Report current scancode set.
+                            self.responses.push_back(KB_RESPONSE_ACK);
+                            self.responses.push_back(match self.scancode_set {
+                                ScancodeSet::Set1 => 1,
+                                ScancodeSet::Set2 => 2,
+                                ScancodeSet::Set3 => 3,
+                            });
+                            return;
+                        }
+                        1 => self.set_scancode_set(ScancodeSet::Set1),
+                        2 => self.set_scancode_set(ScancodeSet::Set2),
+                        3 => self.set_scancode_set(ScancodeSet::Set3),
+                        _ => log::warn!("Keyboard: Unsupported scancode set: {}", byte),
+                    }
+                }
+                KeyboardCommandParam::Typematic => {
+                    (self.typematic_delay, self.typematic_rate) = Keyboard::typematic_from_byte(byte);
+                    log::debug!(
+                        "Keyboard: Typematic rate/delay set: {:02X} (delay: {:.0}ms, rate: {:.1}ms)",
+                        byte,
+                        self.typematic_delay,
+                        self.typematic_rate
+                    );
+                }
+                KeyboardCommandParam::Set3KeyList => {}
+            }
+            self.responses.push_back(KB_RESPONSE_ACK);
+            return;
+        }
+
+        log::debug!("Keyboard: Received command: {:02X}", byte);
+        match byte {
+            0xED => {
+                self.pending_param = Some(KeyboardCommandParam::SetLeds);
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xEE => {
+                self.responses.push_back(KB_RESPONSE_ECHO);
+            }
+            0xF0 => {
+                self.pending_param = Some(KeyboardCommandParam::ScancodeSet);
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xF2 => {
+                // Identify. MF2 keyboards respond with AB 83.
+                self.responses.extend([KB_RESPONSE_ACK, 0xAB, 0x83]);
+            }
+            0xF3 => {
+                self.pending_param = Some(KeyboardCommandParam::Typematic);
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xF4 => {
+                self.enabled = true;
+                self.kb_buffer.clear();
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xF5 | 0xF6 => {
+                // Disable (F5) or set defaults (F6). Both restore defaults.
+                self.enabled = byte == 0xF6;
+                self.set_defaults();
+                self.kb_buffer.clear();
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xF7..=0xFA => {
+                // Set all keys typematic (F7), make/break (F8), make only (F9) or all three (FA).
+                self.set3_mode = match byte {
+                    0xF7 => Set3KeyMode::Typematic,
+                    0xF8 => Set3KeyMode::MakeBreak,
+                    0xF9 => Set3KeyMode::MakeOnly,
+                    _ => Set3KeyMode::TypematicMakeBreak,
+                };
+                log::debug!("Keyboard: Set 3 key mode: {:?}", self.set3_mode);
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xFB..=0xFD => {
+                // Set a list of keys typematic (FB), make/break (FC) or make only (FD).
+                self.pending_param = Some(KeyboardCommandParam::Set3KeyList);
+                self.responses.push_back(KB_RESPONSE_ACK);
+            }
+            0xFE => {
+                // Resend is not emulated as responses are never lost.
+            }
+            0xFF => {
+                // Reset. Perform basic assurance test (BAT).
+                self.enabled = true;
+                self.leds = 0;
+                self.set_defaults();
+                self.kb_buffer.clear();
+                self.responses.extend([KB_RESPONSE_ACK, KB_RESPONSE_BAT_OK]);
+            }
+            _ => {
+                log::warn!("Keyboard: Unhandled command: {:02X}", byte);
+                self.responses.push_back(KB_RESPONSE_RESEND);
+            }
+        }
+    }
+
     pub fn set_type(&mut self, kb_type: KeyboardType) {
         self.kb_type = kb_type;
         // Do any reinitialization here
@@ -656,6 +979,21 @@ impl Keyboard {
 
     /// Send the corresponding scancodes to the keyboard buffer.
     pub fn send_scancodes(&mut self, keys: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+        let converted;
+        let keys = match self.scancode_set {
+            ScancodeSet::Set1 => keys,
+            ScancodeSet::Set2 => {
+                converted = Keyboard::set1_to_set2(keys);
+                &converted
+            }
+            ScancodeSet::Set3 => {
+                converted = Keyboard::set1_to_set3(keys, self.set3_mode);
+                &converted
+            }
+        };
         if keys.len() > 0 {
             if self.kb_buffer_size > 1 {
                 // We have a keyboard buffer
@@ -663,6 +1001,9 @@ impl Keyboard {
                     // KB overflow!
                     self.kb_buffer_overflow = true;
                 }
+                else {
+                    self.kb_buffer.extend_from_slice(keys);
+                }
             }
             else if self.kb_buffer_size == 1 {
                 // No keyboard buffer (kb_buffer_size == 1). Just set one scancode.
@@ -677,10 +1018,26 @@ impl Keyboard {
 
     /// Read out a scancode from the keyboard or None if no key in buffer.
     pub fn recv_scancode(&mut self) -> Option<u8> {
-        if self.kb_buffer_overflow {
-            // Send the keyboard overflow scancode
+        if let Some(response) = self.responses.pop_front() {
+            // Command responses take priority over scancodes.
+            Some(response)
+        }
+        else if self.kb_buffer_overflow {
+            // Send the keyboard overflow scancode. Set 2 keyboards report overflow as 00.
             self.kb_buffer_overflow = false;
-            Some(0xFF)
+            match self.scancode_set {
+                ScancodeSet::Set1 => Some(0xFF),
+                ScancodeSet::Set2 | ScancodeSet::Set3 => Some(0x00),
+            }
+        }
+        else if self.kb_buffer_size > 1 {
+            // Buffered keyboards deliver scancodes in order.
+            if self.kb_buffer.is_empty() {
+                None
+            }
+            else {
+                Some(self.kb_buffer.remove(0))
+            }
         }
         else {
             self.kb_buffer.pop()
@@ -797,7 +1154,7 @@ impl Keyboard {
 
         // Update keys pressed.
         for vkey in &self.keys_pressed {
-            if self.typematic && self.is_typematic_key(*vkey) {
+            if self.typematic && self.set3_typematic() && self.is_typematic_key(*vkey) {
                 if let Some(key_state) = self.kb_hash.get_mut(&vkey) {
                     key_state.pressed_time += ms;
                     if key_state.pressed_time > (self.typematic_delay - self.typematic_rate) {
@@ -831,6 +1188,14 @@ impl Keyboard {
         }
     }
 
+    /// In scancode set 3, the host may disable typematic repeat.
+    fn set3_typematic(&self) -> bool {
+        match self.scancode_set {
+            ScancodeSet::Set3 => matches!(self.set3_mode, Set3KeyMode::TypematicMakeBreak | Set3KeyMode::Typematic),
+            _ => true,
+        }
+    }
+
     /// Return whether key is a typematic key or not. Modifiers and lock keys are not typematic.
     pub fn is_typematic_key(&self, key_code: MartyKey) -> bool {
         match key_code {
@@ -851,3 +1216,56 @@ impl Keyboard {
         }
     }
 }
+
+impl SaveState for Keyboard {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.typematic);
+        w.write_f64(self.typematic_delay);
+        w.write_f64(self.typematic_rate);
+        w.write_u8(match self.set3_mode {
+            Set3KeyMode::TypematicMakeBreak => 0,
+            Set3KeyMode::Typematic => 1,
+            Set3KeyMode::MakeBreak => 2,
+            Set3KeyMode::MakeOnly => 3,
+        });
+        w.write_bytes(&self.kb_buffer);
+        w.write_bool(self.kb_buffer_overflow);
+        w.write_u8(match self.scancode_set {
+            ScancodeSet::Set1 => 1,
+            ScancodeSet::Set2 => 2,
+            ScancodeSet::Set3 => 3,
+        });
+        w.write_bool(self.enabled);
+        w.write_u8(self.leds);
+        w.write_bytes(&self.responses.iter().copied().collect::<Vec<u8>>());
+    }
+
+    /// Host keys held at the time of the save are not restored; all keys are released.
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.typematic = r.read_bool()?;
+        self.typematic_delay = r.read_f64()?;
+        self.typematic_rate = r.read_f64()?;
+        self.set3_mode = match r.read_u8()? {
+            0 => Set3KeyMode::TypematicMakeBreak,
+            1 => Set3KeyMode::Typematic,
+            2 => Set3KeyMode::MakeBreak,
+            3 => Set3KeyMode::MakeOnly,
+            _ => return Err(SaveStateError::InvalidValue("keyboard set 3 mode")),
+        };
+        self.kb_buffer = r.read_bytes()?.to_vec();
+        self.kb_buffer_overflow = r.read_bool()?;
+        self.scancode_set = match r.read_u8()? {
+            1 => ScancodeSet::Set1,
+            2 => ScancodeSet::Set2,
+            3 => ScancodeSet::Set3,
+            _ => return Err(SaveStateError::InvalidValue("keyboard scancode set")),
+        };
+        self.enabled = r.read_bool()?;
+        self.leds = r.read_u8()?;
+        self.responses = r.read_bytes()?.iter().copied().collect();
+        self.pending_param = None;
+        self.clear();
+        self.keys_pressed.clear();
+        Ok(())
+    }
+}
@@ -27,11 +27,18 @@
     devices::lotech_ems.rs
 
     Implementation of the LoTech 2MB EMS Board
+
+    The board provides four write-only page registers at a base of 260h-26Ch that each map a
+    16K page of board memory into a 64K page frame. The LTEMM.EXE driver provides LIM EMS
+    3.2/4.0 services on top of this interface.
     https://texelec.com/product/lo-tech-ems-2-mb/
 
 */
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemRangeDescriptor, MemoryMappedDevice, NO_IO_BYTE};
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemRangeDescriptor, MemoryMappedDevice, NO_IO_BYTE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 pub const LOTECH_DEFAULT_IO_BASE: u16 = 0x260;
 pub const LOTECH_IO_MASK: u16 = !0x03;
@@ -40,9 +47,16 @@ pub const LOTECH_EMS_WINDOW_SIZE: usize = 0x10000;
 pub const LOTECH_EMS_SIZE: usize = 0x200000;
 pub const LOTECH_EMS_PAGE_SIZE: usize = 0x4000;
 
+// The page frame must be 16K aligned and fit below the end of the adapter ROM area.
+pub const LOTECH_MIN_WINDOW_SEG: usize = 0xC000;
+pub const LOTECH_MAX_WINDOW_SEG: usize = 0xE000;
+pub const LOTECH_WINDOW_SEG_ALIGN: usize = LOTECH_EMS_PAGE_SIZE >> 4;
+
 pub const LOTECH_PAGE_MASK: usize = 0b1100_0000_0000_0000;
 pub const LOTECH_BASE_MASK: usize = 0b0011_1111_1111_1111;
 pub const LOTECH_PAGE_SHIFT: usize = 14;
+// Page registers are 7 bits wide, selecting one of 128 16K pages of a full 2MB board.
+pub const LOTECH_PAGE_REG_MASK: u8 = 0x7F;
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct PageRegister {
@@ -52,6 +66,7 @@ pub struct PageRegister {
 pub struct LotechEmsCard {
     port_base: u16,
     window_addr: usize,
+    page_ct: usize,
     pages: [PageRegister; 4],
     mem: Vec<u8>,
 }
@@ -61,6 +76,7 @@ impl Default for LotechEmsCard {
         LotechEmsCard {
             port_base: LOTECH_DEFAULT_IO_BASE,
             window_addr: LOTECH_DEFAULT_EMS_WINDOW_SEG << 4,
+            page_ct: LOTECH_EMS_SIZE / LOTECH_EMS_PAGE_SIZE,
             pages: [PageRegister::default(); 4],
             mem: vec![0xAA; LOTECH_EMS_SIZE],
         }
@@ -68,37 +84,97 @@ impl Default for LotechEmsCard {
 }
 
 impl LotechEmsCard {
-    pub fn new(port_base: Option<u16>, window_seg: Option<usize>) -> Self {
+    pub fn new(port_base: Option<u16>, window_seg: Option<usize>, size: Option<usize>) -> Self {
+        let mut window_seg = window_seg.unwrap_or(LOTECH_DEFAULT_EMS_WINDOW_SEG);
+        if window_seg < LOTECH_MIN_WINDOW_SEG
+            || window_seg > LOTECH_MAX_WINDOW_SEG
+            || window_seg % LOTECH_WINDOW_SEG_ALIGN != 0
+        {
+            log::warn!(
+                "Invalid EMS page frame segment {:04X}, using default of {:04X}",
+                window_seg,
+                LOTECH_DEFAULT_EMS_WINDOW_SEG
+            );
+            window_seg = LOTECH_DEFAULT_EMS_WINDOW_SEG;
+        }
+
+        // Round the backing size to a power-of-two number of pages so that unused page register
+        // bits alias, as they would on a board populated with fewer RAM chips.
+        let mut size = size
+            .unwrap_or(LOTECH_EMS_SIZE)
+            .clamp(LOTECH_EMS_PAGE_SIZE * 4, LOTECH_EMS_SIZE);
+        if !size.is_power_of_two() {
+            let rounded = size.next_power_of_two() >> 1;
+            log::warn!(
+                "EMS size {:X} is not a power of two, rounding down to {:X}",
+                size,
+                rounded
+            );
+            size = rounded;
+        }
+
+        log::debug!(
+            "Creating LoTech EMS board: io_base: {:03X} page frame: {:04X} size: {}K",
+            port_base.unwrap_or(LOTECH_DEFAULT_IO_BASE),
+            window_seg,
+            size / 1024
+        );
+
         LotechEmsCard {
             port_base: port_base.unwrap_or(LOTECH_DEFAULT_IO_BASE),
-            window_addr: window_seg.unwrap_or(LOTECH_DEFAULT_EMS_WINDOW_SEG) << 4,
+            window_addr: window_seg << 4,
+            page_ct: size / LOTECH_EMS_PAGE_SIZE,
+            mem: vec![0xAA; size],
             ..Default::default()
         }
     }
 
     pub fn page_reg_write(&mut self, port_num: u16, data: u8) {
-        self.pages[port_num as usize].page_addr = ((data & 0x7F) as usize) << 14;
+        let page = (data & LOTECH_PAGE_REG_MASK) as usize & (self.page_ct - 1);
+        self.pages[port_num as usize].page_addr = page << LOTECH_PAGE_SHIFT;
+    }
+
+    /// Return the total amount of expanded memory on the board, in bytes.
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    #[inline]
+    fn ems_addr(&self, address: usize) -> usize {
+        let offset = address.wrapping_sub(self.window_addr) & (LOTECH_EMS_WINDOW_SIZE - 1);
+        let page = (offset & LOTECH_PAGE_MASK) >> LOTECH_PAGE_SHIFT;
+        self.pages[page].page_addr + (offset & LOTECH_BASE_MASK)
     }
 }
 
-impl IoDevice for LotechEmsCard {
-    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
-        // Catch up to CPU state.
-        //let _ticks = self.catch_up(delta, false);
-        //self.rw_op(ticks, 0, port as u32, RwSlotType::Io);
+impl SaveState for LotechEmsCard {
+    fn save_state(&self, w: &mut StateWriter) {
+        for page in self.pages.iter() {
+            w.write_usize(page.page_addr);
+        }
+        w.write_bytes(&self.mem);
+    }
 
-        if (port & LOTECH_IO_MASK) == self.port_base {
-            //self.port_read(port)
-            NO_IO_BYTE
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for page in self.pages.iter_mut() {
+            page.page_addr = r.read_usize()?;
         }
-        else {
-            NO_IO_BYTE
+        r.read_bytes_into(&mut self.mem, "EMS memory size")?;
+        if self.pages.iter().any(|page| page.page_addr >= self.mem.len()) {
+            return Err(SaveStateError::InvalidValue("EMS page register"));
         }
+        Ok(())
+    }
+}
+
+impl IoDevice for LotechEmsCard {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        // Page registers are write-only.
+        NO_IO_BYTE
     }
 
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
         if (port & LOTECH_IO_MASK) == self.port_base {
-            // Read is from LPT port.
             let port_num = port & 0x03;
             self.page_reg_write(port_num, data);
         }
@@ -114,39 +190,33 @@ impl IoDevice for LotechEmsCard {
     }
 }
 
-/// Unlike the EGA or VGA the CGA doesn't do any operations on video memory on read/write,
-/// but we handle the mirroring of VRAM this way, and for consistency with other devices
+/// The page frame is divided into four 16K windows, each mapped to a page of board memory by
+/// the corresponding page register.
 impl MemoryMappedDevice for LotechEmsCard {
     fn get_read_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
         0
     }
 
     fn mmio_read_u8(&mut self, address: usize, _cycles: u32, _cpumem: Option<&[u8]>) -> (u8, u32) {
-        let page = (address & LOTECH_PAGE_MASK) >> LOTECH_PAGE_SHIFT;
-        let ems_addr = self.pages[page].page_addr + (address & LOTECH_BASE_MASK);
-
-        (self.mem[ems_addr], 0)
+        (self.mem[self.ems_addr(address)], 0)
     }
 
     fn mmio_read_u16(&mut self, address: usize, _cycles: u32, cpumem: Option<&[u8]>) -> (u16, u32) {
         let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, 0, cpumem);
         let (ho_byte, wait2) = MemoryMappedDevice::mmio_read_u8(self, address + 1, 0, cpumem);
 
-        log::warn!("Unsupported 16 bit read from VRAM");
-        return ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2);
+        ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2)
     }
 
     fn mmio_peek_u8(&self, address: usize, _cpumem: Option<&[u8]>) -> u8 {
-        let page = (address & LOTECH_PAGE_MASK) >> LOTECH_PAGE_SHIFT;
-        let ems_addr = self.pages[page].page_addr + (address & LOTECH_BASE_MASK);
-
-        self.mem[ems_addr]
+        self.mem[self.ems_addr(address)]
     }
 
     fn mmio_peek_u16(&self, address: usize, _cpumem: Option<&[u8]>) -> u16 {
-        let a_offset = (address & LOTECH_PAGE_MASK) >> LOTECH_PAGE_SHIFT;
+        let lo_byte = self.mem[self.ems_addr(address)];
+        let ho_byte = self.mem[self.ems_addr(address + 1)];
 
-        (self.mem[a_offset] as u16) << 8 | self.mem[a_offset + 1] as u16
+        (ho_byte as u16) << 8 | lo_byte as u16
     }
 
     fn get_write_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
@@ -154,16 +224,16 @@ impl MemoryMappedDevice for LotechEmsCard {
     }
 
     fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
-        let page = (address & LOTECH_PAGE_MASK) >> LOTECH_PAGE_SHIFT;
-        let ems_addr = self.pages[page].page_addr + (address & LOTECH_BASE_MASK);
-
+        let ems_addr = self.ems_addr(address);
         self.mem[ems_addr] = byte;
         0
     }
 
-    fn mmio_write_u16(&mut self, _address: usize, _data: u16, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
-        //trace!(self, "16 byte write to VRAM, {:04X} -> {:05X} ", data, address);
-        log::warn!("Unsupported 16 bit write to VRAM");
+    fn mmio_write_u16(&mut self, address: usize, data: u16, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
+        let lo_addr = self.ems_addr(address);
+        self.mem[lo_addr] = data as u8;
+        let ho_addr = self.ems_addr(address + 1);
+        self.mem[ho_addr] = (data >> 8) as u8;
         0
     }
 
@@ -32,7 +32,9 @@
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
-    devices::{lpt_port::ParallelPort},
+    devices::{lpt_dac::ParallelDac, lpt_port::ParallelPort, printer::Printer},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    tracelogger::TraceLogger,
 };
 
 pub const LPT_DEFAULT_IO_BASE: u16 = 0x3BC;
@@ -53,12 +55,42 @@ impl Default for ParallelController {
 }
 
 impl ParallelController {
-    pub fn new(port_base: Option<u16>) -> Self {
+    pub fn new(port_base: Option<u16>, irq: Option<u16>) -> Self {
         ParallelController {
             lpt_port_base: port_base.unwrap_or(LPT_DEFAULT_IO_BASE),
-            ..Default::default()
+            lpt: ParallelPort::new(irq, TraceLogger::None),
         }
     }
+
+    pub fn irq(&self) -> u16 {
+        self.lpt.irq()
+    }
+
+    pub fn attach_printer(&mut self, printer: Printer) {
+        self.lpt.attach_printer(printer);
+    }
+
+    pub fn printer_mut(&mut self) -> Option<&mut Printer> {
+        self.lpt.printer_mut()
+    }
+
+    pub fn attach_dac(&mut self, dac: ParallelDac) {
+        self.lpt.attach_dac(dac);
+    }
+
+    pub fn dac_mut(&mut self) -> Option<&mut ParallelDac> {
+        self.lpt.dac_mut()
+    }
+
+    pub fn reset(&mut self) {
+        self.lpt.reset();
+    }
+
+    /// Run the parallel port. Returns Some(true) if the port's IRQ should be raised, or Some(false)
+    /// if it should be lowered.
+    pub fn run(&mut self, us: f64) -> Option<bool> {
+        self.lpt.run(us)
+    }
 }
 
 impl IoDevice for ParallelController {
@@ -90,3 +122,13 @@ impl IoDevice for ParallelController {
         ]
     }
 }
+
+impl SaveState for ParallelController {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.lpt.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.lpt.load_state(r)
+    }
+}
@@ -0,0 +1,174 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::lpt_dac.rs
+
+   Implements DAC devices that plug into a parallel port.
+
+   The Covox Speech Thing is a resistor ladder across the data lines, so
+   the output level follows the data register directly.
+
+   The Disney Sound Source contains a 16-byte FIFO played back at a fixed
+   7kHz. A byte is clocked into the FIFO on a falling edge of the SELECT IN
+   control bit, and the ACK status bit is set while the FIFO is full.
+
+*/
+use std::collections::VecDeque;
+
+use crossbeam_channel::Sender;
+
+use crate::device_traits::sounddevice::AudioSample;
+
+pub const DSS_SAMPLE_RATE: f64 = 7000.0;
+// The output sample rate used until an audio sender is attached.
+const DEFAULT_OUTPUT_RATE: u32 = 44100;
+const DSS_FIFO_LEN: usize = 16;
+
+const CONTROL_SELECT_IN: u8 = 0b0000_1000;
+const STATUS_DSS_FULL: u8 = 0b0100_0000;
+const STATUS_DSS_IDLE: u8 = 0b0000_0111;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParallelDacModel {
+    Covox,
+    DisneySoundSource,
+}
+
+pub struct ParallelDac {
+    model: ParallelDacModel,
+    level: u8,
+    fifo: VecDeque<u8>,
+    dss_accum: f64,
+
+    sample_rate: f64,
+    sample_accum: f64,
+    // The sum of the output level weighted by time within the current output sample.
+    level_accum: f64,
+    sender: Option<Sender<AudioSample>>,
+}
+
+impl ParallelDac {
+    pub fn new(model: ParallelDacModel) -> Self {
+        Self {
+            model,
+            level: 0x80,
+            fifo: VecDeque::with_capacity(DSS_FIFO_LEN),
+            dss_accum: 0.0,
+            sample_rate: DEFAULT_OUTPUT_RATE as f64,
+            sample_accum: 0.0,
+            level_accum: 0.0,
+            sender: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.model {
+            ParallelDacModel::Covox => "Covox Speech Thing",
+            ParallelDacModel::DisneySoundSource => "Disney Sound Source",
+        }
+    }
+
+    /// Send mono output samples at the specified sample rate to the sound output.
+    pub fn set_audio_sender(&mut self, sender: Sender<AudioSample>, sample_rate: u32) {
+        self.sender = Some(sender);
+        self.sample_rate = sample_rate as f64;
+    }
+
+    pub fn reset(&mut self) {
+        self.level = 0x80;
+        self.fifo.clear();
+        self.dss_accum = 0.0;
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        if self.model == ParallelDacModel::Covox {
+            self.level = data;
+        }
+    }
+
+    pub fn write_control(&mut self, old: u8, new: u8, data: u8) {
+        if self.model == ParallelDacModel::DisneySoundSource
+            && (old & CONTROL_SELECT_IN != 0)
+            && (new & CONTROL_SELECT_IN == 0)
+            && self.fifo.len() < DSS_FIFO_LEN
+        {
+            self.fifo.push_back(data);
+        }
+    }
+
+    /// Return the value of the status register, if this device drives it.
+    pub fn status(&self) -> Option<u8> {
+        match self.model {
+            ParallelDacModel::Covox => None,
+            ParallelDacModel::DisneySoundSource => {
+                if self.fifo.len() >= DSS_FIFO_LEN {
+                    Some(STATUS_DSS_IDLE | STATUS_DSS_FULL)
+                }
+                else {
+                    Some(STATUS_DSS_IDLE)
+                }
+            }
+        }
+    }
+
+    pub fn run(&mut self, mut us: f64) {
+        let us_per_sample = 1_000_000.0 / self.sample_rate;
+        let us_per_dss_sample = 1_000_000.0 / DSS_SAMPLE_RATE;
+
+        while us > 0.0 {
+            // Advance to the next output sample or FIFO clock, whichever comes first.
+            let mut slice = (us_per_sample - self.sample_accum).min(us);
+            if self.model == ParallelDacModel::DisneySoundSource {
+                slice = slice.min(us_per_dss_sample - self.dss_accum);
+            }
+            slice = slice.max(0.0);
+
+            self.level_accum += self.level as f64 * slice;
+            self.sample_accum += slice;
+            us -= slice;
+
+            if self.model == ParallelDacModel::DisneySoundSource {
+                self.dss_accum += slice;
+                if self.dss_accum >= us_per_dss_sample {
+                    self.dss_accum -= us_per_dss_sample;
+                    // The output holds its last level when the FIFO runs dry.
+                    if let Some(sample) = self.fifo.pop_front() {
+                        self.level = sample;
+                    }
+                }
+            }
+
+            if self.sample_accum >= us_per_sample {
+                let level = self.level_accum / self.sample_accum;
+                self.sample_accum = 0.0;
+                self.level_accum = 0.0;
+                if let Some(sender) = &self.sender {
+                    _ = sender.send(((level - 128.0) / 128.0) as AudioSample);
+                }
+            }
+        }
+    }
+}
@@ -30,13 +30,38 @@
     implementation, and must be embedded into a card implementation that can
     decode the proper port address.
 
+    When a printer is attached, the port performs the Centronics handshake:
+    asserting STROBE latches the data byte and makes the printer BUSY for a
+    short time, after which it pulses ACK. If enabled in the control
+    register, the ACK pulse raises the port's IRQ.
+
+    Alternatively, a DAC such as a Covox Speech Thing or Disney Sound Source
+    may be attached in place of the printer.
+
 */
 
-use crate::tracelogger::TraceLogger;
+use crate::{
+    devices::{lpt_dac::ParallelDac, printer::Printer},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    tracelogger::TraceLogger,
+};
 use modular_bitfield::{bitfield, prelude::*};
 
 pub const LPT_DEFAULT_IRQ: u16 = 7;
 
+// Time the printer stays busy after accepting a byte, and the width of the following ACK pulse.
+// These give a transfer rate of around 16KB/s, typical of a buffered printer.
+const LPT_BUSY_US: f64 = 50.0;
+const LPT_ACK_US: f64 = 10.0;
+
+// Status register bits as presented to the CPU. BUSY, ACK and ERROR are inverted.
+const STATUS_NOT_ERROR: u8 = 0b0000_1000;
+const STATUS_SELECT: u8 = 0b0001_0000;
+const STATUS_NOT_ACK: u8 = 0b0100_0000;
+const STATUS_NOT_BUSY: u8 = 0b1000_0000;
+const STATUS_UNUSED: u8 = 0b0000_0111;
+const CONTROL_UNUSED: u8 = 0b1110_0000;
+
 #[bitfield]
 #[derive(Copy, Clone)]
 pub struct ParallelStatus {
@@ -61,13 +86,17 @@ pub struct ParallelControl {
     pub unused2: B3,
 }
 
-#[allow(dead_code)]
 pub struct ParallelPort {
     data: u8,
     status: ParallelStatus,
     control: ParallelControl,
     irq: u16,
     trace_logger: TraceLogger,
+    printer: Option<Printer>,
+    dac: Option<ParallelDac>,
+    busy_us: f64,
+    ack_us: f64,
+    irq_active: bool,
 }
 
 impl Default for ParallelPort {
@@ -78,6 +107,11 @@ impl Default for ParallelPort {
             control: ParallelControl::from_bytes([0]),
             irq: LPT_DEFAULT_IRQ,
             trace_logger: TraceLogger::None,
+            printer: None,
+            dac: None,
+            busy_us: 0.0,
+            ack_us: 0.0,
+            irq_active: false,
         }
     }
 }
@@ -91,6 +125,77 @@ impl ParallelPort {
         }
     }
 
+    pub fn irq(&self) -> u16 {
+        self.irq
+    }
+
+    pub fn attach_printer(&mut self, printer: Printer) {
+        self.printer = Some(printer);
+    }
+
+    pub fn printer_mut(&mut self) -> Option<&mut Printer> {
+        self.printer.as_mut()
+    }
+
+    pub fn attach_dac(&mut self, dac: ParallelDac) {
+        self.dac = Some(dac);
+    }
+
+    pub fn dac_mut(&mut self) -> Option<&mut ParallelDac> {
+        self.dac.as_mut()
+    }
+
+    /// Reset the port. An attached printer ends its current job but remains attached.
+    pub fn reset(&mut self) {
+        if let Some(printer) = &mut self.printer {
+            printer.end_job();
+        }
+        if let Some(dac) = &mut self.dac {
+            dac.reset();
+        }
+        *self = Self {
+            irq: self.irq,
+            trace_logger: std::mem::take(&mut self.trace_logger),
+            printer: self.printer.take(),
+            dac: self.dac.take(),
+            ..Default::default()
+        };
+    }
+
+    /// Run the printer handshake for the specified number of microseconds. Returns Some(true) if
+    /// the port's IRQ should be raised, or Some(false) if it should be lowered.
+    pub fn run(&mut self, us: f64) -> Option<bool> {
+        if let Some(dac) = &mut self.dac {
+            dac.run(us);
+        }
+        let printer = self.printer.as_mut()?;
+        printer.run(us);
+
+        if self.busy_us > 0.0 {
+            self.busy_us -= us;
+            if self.busy_us <= 0.0 {
+                self.busy_us = 0.0;
+                self.ack_us = LPT_ACK_US;
+                if self.control.enable_irq() != 0 {
+                    self.irq_active = true;
+                    return Some(true);
+                }
+            }
+        }
+        else if self.ack_us > 0.0 {
+            self.ack_us -= us;
+            if self.ack_us <= 0.0 {
+                self.ack_us = 0.0;
+            }
+        }
+
+        if self.ack_us == 0.0 && self.irq_active {
+            self.irq_active = false;
+            return Some(false);
+        }
+        None
+    }
+
     pub fn port_write(&mut self, port: u16, data: u8) {
         match port & 0x03 {
             0 => {
@@ -123,6 +228,9 @@ impl ParallelPort {
 
     pub fn data_register_write(&mut self, data: u8) {
         self.data = data;
+        if let Some(dac) = &mut self.dac {
+            dac.write_data(data);
+        }
         self.trace_logger
             .print(format!("LPT: Data register write: {:#02X}", data));
     }
@@ -134,7 +242,27 @@ impl ParallelPort {
     }
 
     pub fn control_register_write(&mut self, data: u8) {
+        let old = self.control;
         self.control = ParallelControl::from_bytes([data]);
+
+        if let Some(dac) = &mut self.dac {
+            dac.write_control(old.into_bytes()[0], data, self.data);
+        }
+
+        if let Some(printer) = &mut self.printer {
+            if old.initialize() != 0 && self.control.initialize() == 0 {
+                // -INIT asserted. The printer resets, ending any job in progress.
+                printer.end_job();
+                self.busy_us = 0.0;
+                self.ack_us = 0.0;
+            }
+            else if old.strobe() == 0 && self.control.strobe() != 0 && self.busy_us == 0.0 && self.ack_us == 0.0 {
+                // STROBE asserted while the printer is ready. Latch the data byte.
+                printer.print_byte(self.data);
+                self.busy_us = LPT_BUSY_US;
+            }
+        }
+
         self.trace_logger
             .print(format!("LPT: Control register write: {:#02X}", data));
     }
@@ -146,16 +274,57 @@ impl ParallelPort {
     }
 
     pub fn status_register_read(&mut self) -> u8 {
-        let byte = self.status.into_bytes()[0];
+        let byte = match &self.printer {
+            Some(_) => {
+                // The printer is online and has paper.
+                let mut byte = STATUS_UNUSED | STATUS_SELECT | STATUS_NOT_ERROR;
+                if self.busy_us == 0.0 {
+                    byte |= STATUS_NOT_BUSY;
+                }
+                if self.ack_us == 0.0 {
+                    byte |= STATUS_NOT_ACK;
+                }
+                byte
+            }
+            None => match self.dac.as_ref().and_then(|dac| dac.status()) {
+                Some(byte) => byte,
+                None => self.status.into_bytes()[0],
+            },
+        };
         self.trace_logger
             .print(format!("LPT: Status register read: {:#02X}", byte));
         byte
     }
 
     pub fn control_register_read(&mut self) -> u8 {
-        let byte = self.control.into_bytes()[0];
+        let mut byte = self.control.into_bytes()[0];
+        if self.printer.is_some() {
+            byte |= CONTROL_UNUSED;
+        }
         self.trace_logger
             .print(format!("LPT: Control register read: {:#02X}", byte));
         byte
     }
 }
+
+/// The state of an attached printer or DAC is not saved.
+impl SaveState for ParallelPort {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.data);
+        w.write_u8(self.status.into_bytes()[0]);
+        w.write_u8(self.control.into_bytes()[0]);
+        w.write_f64(self.busy_us);
+        w.write_f64(self.ack_us);
+        w.write_bool(self.irq_active);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.data = r.read_u8()?;
+        self.status = ParallelStatus::from_bytes([r.read_u8()?]);
+        self.control = ParallelControl::from_bytes([r.read_u8()?]);
+        self.busy_us = r.read_f64()?;
+        self.ack_us = r.read_f64()?;
+        self.irq_active = r.read_bool()?;
+        Ok(())
+    }
+}
@@ -375,6 +375,11 @@ impl Crtc6845 {
         }
     }
 
+    #[inline]
+    pub fn selected_register(&self) -> u8 {
+        self.reg_select as u8
+    }
+
     pub fn read_register(&self) -> u8 {
         match self.reg_select {
             CursorAddressH | CursorAddressL | LightPenPositionH | LightPenPositionL => {
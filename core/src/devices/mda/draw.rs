@@ -173,6 +173,19 @@ impl MDACard {
     /// Draw a single character column in high resolution graphics mode (640x200)
     pub fn draw_hires_gfx_mode_char(&mut self) {
         let base_addr = self.get_gfx_addr(self.crtc.vlc());
+
+        if let Some(incolor) = &self.incolor {
+            let frame = &mut self.buf[self.back_buf][self.rba..self.rba + 16];
+            if self.mode_enable {
+                incolor.draw_gfx_byte(base_addr, &mut frame[0..8]);
+                incolor.draw_gfx_byte(base_addr + 1, &mut frame[8..16]);
+            }
+            else {
+                frame.fill(0);
+            }
+            return;
+        }
+
         let frame_u64: &mut [u64] = bytemuck::cast_slice_mut(&mut *self.buf[self.back_buf]);
 
         if self.mode_enable {
@@ -0,0 +1,323 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::mda::incolor.rs
+
+    Implementation of the Hercules InColor card extensions.
+
+    The InColor is a Hercules-compatible card with four planes of video
+    memory, giving 16 colors at 720x348 in graphics mode. Each plane is
+    64K, and is addressed through the same B000/B800 pages as the HGC.
+    A set of extended CRTC registers control how the CPU reads and writes
+    the planes, which planes are displayed, and a 16 entry palette of
+    6-bit EGA-style colors.
+
+*/
+
+use super::HGC_MEM_SIZE;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub const INCOLOR_PLANES: usize = 4;
+
+// Extended CRTC registers, selected through the normal CRTC address port.
+pub const INCOLOR_REG_XMODE: u8 = 0x14;
+pub const INCOLOR_REG_UNDERLINE: u8 = 0x15;
+pub const INCOLOR_REG_OVERSTRIKE: u8 = 0x16;
+pub const INCOLOR_REG_EXCEPTION: u8 = 0x17;
+pub const INCOLOR_REG_PLANE_MASK: u8 = 0x18;
+pub const INCOLOR_REG_RW_CONTROL: u8 = 0x19;
+pub const INCOLOR_REG_RW_COLOR: u8 = 0x1A;
+pub const INCOLOR_REG_LATCH_PROTECT: u8 = 0x1B;
+pub const INCOLOR_REG_PALETTE: u8 = 0x1C;
+
+// Bits 4-6 of the status register identify the InColor card.
+pub const INCOLOR_STATUS_ID: u8 = 0b0101_0000;
+
+const EXCEPTION_PALETTE_ENABLE: u8 = 0b0001_0000;
+const RW_CONTROL_DONT_CARE: u8 = 0b0000_1111;
+const RW_CONTROL_WRITE_MODE: u8 = 0b0011_0000;
+const RW_CONTROL_POLARITY: u8 = 0b0100_0000;
+
+// In text mode, writes below this offset are made to all planes.
+const TEXT_MODE_LIMIT: usize = 0x4000;
+
+// The palette used when the exception register has not enabled the palette registers.
+const INCOLOR_DEFAULT_PALETTE: [u8; 16] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+];
+
+pub struct InColor {
+    planes: Vec<Box<[u8; HGC_MEM_SIZE]>>,
+    latch: [u8; INCOLOR_PLANES],
+    xmode: u8,
+    underline: u8,
+    overstrike: u8,
+    exception: u8,
+    plane_mask: u8,
+    rw_control: u8,
+    rw_color: u8,
+    latch_protect: u8,
+    palette: [u8; 16],
+    palette_idx: usize,
+}
+
+impl InColor {
+    pub fn new() -> Self {
+        Self {
+            planes: (0..INCOLOR_PLANES)
+                .map(|_| vec![0; HGC_MEM_SIZE].into_boxed_slice().try_into().unwrap())
+                .collect(),
+            latch: [0; INCOLOR_PLANES],
+            xmode: 0,
+            underline: 0,
+            overstrike: 0,
+            exception: 0,
+            // All planes displayed and none write-protected.
+            plane_mask: 0x0F,
+            rw_control: 0,
+            rw_color: 0,
+            latch_protect: 0,
+            palette: INCOLOR_DEFAULT_PALETTE,
+            palette_idx: 0,
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for plane in self.planes.iter() {
+            w.write_bytes(&plane[..]);
+        }
+        w.write_bytes(&self.latch);
+        let regs = [
+            self.xmode,
+            self.underline,
+            self.overstrike,
+            self.exception,
+            self.plane_mask,
+            self.rw_control,
+            self.rw_color,
+            self.latch_protect,
+        ];
+        w.write_bytes(&regs);
+        w.write_bytes(&self.palette);
+        w.write_usize(self.palette_idx);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for plane in self.planes.iter_mut() {
+            r.read_bytes_into(&mut plane[..], "InColor plane")?;
+        }
+        r.read_bytes_into(&mut self.latch, "InColor latches")?;
+        let mut regs = [0u8; 8];
+        r.read_bytes_into(&mut regs, "InColor registers")?;
+        r.read_bytes_into(&mut self.palette, "InColor palette")?;
+        let palette_idx = r.read_usize()?;
+        if palette_idx >= self.palette.len() {
+            return Err(SaveStateError::InvalidValue("InColor palette index"));
+        }
+
+        for (idx, byte) in (INCOLOR_REG_XMODE..=INCOLOR_REG_LATCH_PROTECT).zip(regs.iter()) {
+            self.write_register(idx, *byte);
+        }
+        self.palette_idx = palette_idx;
+        Ok(())
+    }
+
+    /// Returns true if the specified CRTC register index is one of the InColor extended registers.
+    pub fn is_ext_register(idx: u8) -> bool {
+        (INCOLOR_REG_XMODE..=INCOLOR_REG_PALETTE).contains(&idx)
+    }
+
+    pub fn read_register(&mut self, idx: u8) -> u8 {
+        match idx {
+            INCOLOR_REG_XMODE => self.xmode,
+            INCOLOR_REG_UNDERLINE => self.underline,
+            INCOLOR_REG_OVERSTRIKE => self.overstrike,
+            INCOLOR_REG_EXCEPTION => self.exception,
+            INCOLOR_REG_PLANE_MASK => self.plane_mask,
+            INCOLOR_REG_RW_CONTROL => self.rw_control,
+            INCOLOR_REG_RW_COLOR => self.rw_color,
+            INCOLOR_REG_LATCH_PROTECT => self.latch_protect,
+            INCOLOR_REG_PALETTE => {
+                // Reading the palette register resets the palette pointer.
+                self.palette_idx = 0;
+                0xFF
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, idx: u8, data: u8) {
+        log::trace!("InColor register {:02X} write: {:02X}", idx, data);
+        match idx {
+            INCOLOR_REG_XMODE => self.xmode = data,
+            INCOLOR_REG_UNDERLINE => self.underline = data,
+            INCOLOR_REG_OVERSTRIKE => self.overstrike = data,
+            INCOLOR_REG_EXCEPTION => self.exception = data,
+            INCOLOR_REG_PLANE_MASK => self.plane_mask = data,
+            INCOLOR_REG_RW_CONTROL => self.rw_control = data,
+            INCOLOR_REG_RW_COLOR => self.rw_color = data,
+            INCOLOR_REG_LATCH_PROTECT => self.latch_protect = data,
+            INCOLOR_REG_PALETTE => {
+                // Each write stores the next palette entry.
+                self.palette[self.palette_idx] = data & 0x3F;
+                self.palette_idx = (self.palette_idx + 1) & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    /// Read a byte of video memory. All planes are loaded into the latches. In graphics mode, the
+    /// value returned is the result of comparing each pixel against the read/write color, ignoring
+    /// any 'don't care' planes. In text mode, plane 0 is returned.
+    pub fn cpu_read(&mut self, offset: usize, graphics: bool) -> u8 {
+        for (latch, plane) in self.latch.iter_mut().zip(self.planes.iter()) {
+            *latch = plane[offset];
+        }
+
+        if !graphics {
+            return self.latch[0];
+        }
+
+        let dont_care = self.rw_control & RW_CONTROL_DONT_CARE;
+        let mut result = 0;
+        for bit in 0..8 {
+            let mask = 0x80 >> bit;
+            let matched = (0..INCOLOR_PLANES).all(|p| {
+                let p_mask = 1 << p;
+                dont_care & p_mask != 0 || ((self.latch[p] & mask != 0) == (self.rw_color & p_mask != 0))
+            });
+            if matched {
+                result |= mask;
+            }
+        }
+
+        if self.rw_control & RW_CONTROL_POLARITY != 0 {
+            !result
+        }
+        else {
+            result
+        }
+    }
+
+    pub fn peek(&self, offset: usize) -> u8 {
+        self.planes[0][offset]
+    }
+
+    /// Write a byte of video memory. In graphics mode, each plane not write-protected by the plane
+    /// mask register receives a combination of the foreground and background colors of the
+    /// read/write color register and the latches, depending on the write mode:
+    ///  0: 1 bits write the foreground, 0 bits write the background
+    ///  1: 1 bits write the foreground, 0 bits write the latch
+    ///  2: 1 bits write the latch, 0 bits write the background
+    ///  3: 1 bits write the latch, 0 bits write the inverted latch
+    pub fn cpu_write(&mut self, offset: usize, byte: u8, graphics: bool) {
+        if !graphics && offset < TEXT_MODE_LIMIT {
+            for plane in self.planes.iter_mut() {
+                plane[offset] = byte;
+            }
+            return;
+        }
+
+        let write_mode = (self.rw_control & RW_CONTROL_WRITE_MODE) >> 4;
+        for p in 0..INCOLOR_PLANES {
+            if self.plane_mask & (0x10 << p) != 0 {
+                // Plane is write-protected.
+                continue;
+            }
+            let fg = if self.rw_color & (0x01 << p) != 0 { 0xFF } else { 0x00 };
+            let bg = if self.rw_color & (0x10 << p) != 0 { 0xFF } else { 0x00 };
+            let latch = self.latch[p];
+
+            let (set, clear) = match write_mode {
+                0 => (fg, bg),
+                1 => (fg, latch),
+                2 => (latch, bg),
+                _ => (latch, !latch),
+            };
+            self.planes[p][offset] = (byte & set) | (!byte & clear);
+        }
+    }
+
+    /// Return the byte of plane 0 at the specified offset. Text mode reads characters and
+    /// attributes from plane 0.
+    #[inline]
+    pub fn text_byte(&self, offset: usize) -> u8 {
+        self.planes[0][offset]
+    }
+
+    /// Decode the 8 pixels at the specified offset into color indices, applying the display
+    /// plane mask.
+    #[inline]
+    pub fn draw_gfx_byte(&self, offset: usize, out: &mut [u8]) {
+        let display_mask = self.plane_mask & 0x0F;
+        for (bit, pixel) in out.iter_mut().take(8).enumerate() {
+            let mask = 0x80 >> bit;
+            let mut color = 0;
+            for p in 0..INCOLOR_PLANES {
+                if self.planes[p][offset] & mask != 0 {
+                    color |= 1 << p;
+                }
+            }
+            *pixel = color & display_mask;
+        }
+    }
+
+    /// Return the current palette as RGBA colors.
+    pub fn rgba_palette(&self) -> Vec<[u8; 4]> {
+        let palette = if self.exception & EXCEPTION_PALETTE_ENABLE != 0 {
+            &self.palette
+        }
+        else {
+            &INCOLOR_DEFAULT_PALETTE
+        };
+
+        // Palette entries are in EGA format, with the secondary (low intensity) color bits in
+        // bits 3-5 and the primary color bits in bits 0-2.
+        palette
+            .iter()
+            .map(|&c| {
+                let r = ((c >> 2) & 1) * 0xAA + ((c >> 5) & 1) * 0x55;
+                let g = ((c >> 1) & 1) * 0xAA + ((c >> 4) & 1) * 0x55;
+                let b = (c & 1) * 0xAA + ((c >> 3) & 1) * 0x55;
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    /// Return the extended register values for display in the debugger.
+    pub fn register_state(&self) -> Vec<(&'static str, u8)> {
+        vec![
+            ("xMode", self.xmode),
+            ("Underline", self.underline),
+            ("Overstrike", self.overstrike),
+            ("Exception", self.exception),
+            ("Plane Mask", self.plane_mask),
+            ("R/W Control", self.rw_control),
+            ("R/W Color", self.rw_color),
+            ("Latch Protect", self.latch_protect),
+        ]
+    }
+}
@@ -31,7 +31,10 @@
 */
 
 use super::*;
-use crate::bus::{IoDevice, NO_IO_BYTE};
+use crate::{
+    bus::{IoDevice, NO_IO_BYTE},
+    devices::mda::incolor::InColor,
+};
 
 pub const LPT_DEFAULT_IO_BASE: u16 = 0x3BC;
 pub const LPT_PORT_MASK: u16 = !0x003;
@@ -67,7 +70,11 @@ impl IoDevice for MDACard {
 
         if (port & CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Read is from CRTC register.
-            self.crtc.port_read(port)
+            match (&mut self.incolor, self.incolor_reg_select) {
+                // InColor extended registers are read through the CRTC data port.
+                (Some(incolor), Some(idx)) if port & 0x01 != 0 => incolor.read_register(idx),
+                _ => self.crtc.port_read(port),
+            }
         }
         else if (port & LPT_PORT_MASK) == LPT_DEFAULT_IO_BASE {
             // Read is from LPT port.
@@ -102,10 +109,23 @@ impl IoDevice for MDACard {
 
         if (port & CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Write is to CRTC register.
+            if let Some(incolor) = &mut self.incolor {
+                // The InColor extends the CRTC register file with its own registers. Track whether
+                // one of them is selected so that data port accesses can be redirected.
+                if port & 0x01 == 0 {
+                    let idx = data & 0x1F;
+                    self.incolor_reg_select = InColor::is_ext_register(idx).then_some(idx);
+                }
+                else if let Some(idx) = self.incolor_reg_select {
+                    incolor.write_register(idx, data);
+                    return;
+                }
+            }
+            self.check_breakpoints(port, data);
             self.crtc.port_write(port, data);
         }
         else if port == HGC_CONFIG_SWITCH_REGISTER {
-            if let VideoCardSubType::Hercules = self.subtype {
+            if self.is_hercules() {
                 self.handle_hgc_config_switch(data);
             }
         }
@@ -119,6 +139,7 @@ impl IoDevice for MDACard {
             // Must be some other MDA register
             match port {
                 MDA_MODE_CONTROL_REGISTER => {
+                    self.check_breakpoints(port, data);
                     self.handle_mode_register(data);
                 }
                 _ => {}
@@ -152,7 +173,7 @@ impl IoDevice for MDACard {
             );
         }
 
-        if let VideoCardSubType::Hercules = self.subtype {
+        if self.is_hercules() {
             mda_ports.push((String::from("HGC Config Switch"), HGC_CONFIG_SWITCH_REGISTER));
         }
 
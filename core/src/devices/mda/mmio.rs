@@ -31,7 +31,7 @@
 */
 
 use super::*;
-use crate::bus::{MemRangeDescriptor, MemoryMappedDevice};
+use crate::bus::{MemRangeDescriptor, MemoryMappedDevice, NO_IO_BYTE};
 
 /// Unlike the EGA or VGA the CGA doesn't do any operations on video memory on read/write,
 /// but we handle the mirroring of VRAM this way, and for consistency with other devices
@@ -58,6 +58,12 @@ impl MemoryMappedDevice for MDACard {
 
     fn mmio_read_u8(&mut self, address: usize, _cycles: u32, _cpumem: Option<&[u8]>) -> (u8, u32) {
         let a_offset = address & self.mem_mask;
+        if !self.is_offset_mapped(a_offset) {
+            return (NO_IO_BYTE, 0);
+        }
+        if let Some(incolor) = &mut self.incolor {
+            return (incolor.cpu_read(a_offset, self.mode_graphics), 0);
+        }
 
         trace!(self, "READ_U8: {:04X}:{:02X}", a_offset, self.mem[a_offset]);
         (self.mem[a_offset], 0)
@@ -65,18 +71,37 @@ impl MemoryMappedDevice for MDACard {
 
     fn mmio_peek_u8(&self, address: usize, _cpumem: Option<&[u8]>) -> u8 {
         let a_offset = address & self.mem_mask;
+        if !self.is_offset_mapped(a_offset) {
+            return NO_IO_BYTE;
+        }
+        if let Some(incolor) = &self.incolor {
+            return incolor.peek(a_offset);
+        }
 
         self.mem[a_offset]
     }
 
     fn mmio_peek_u16(&self, address: usize, _cpumem: Option<&[u8]>) -> u16 {
         let a_offset = address & self.mem_mask;
+        if !self.is_offset_mapped(a_offset) {
+            return (NO_IO_BYTE as u16) << 8 | NO_IO_BYTE as u16;
+        }
+        if let Some(incolor) = &self.incolor {
+            return (incolor.peek(a_offset) as u16) << 8 | incolor.peek((a_offset + 1) & self.mem_mask) as u16;
+        }
 
         (self.mem[a_offset] as u16) << 8 | self.mem[(a_offset + 1) & self.mem_mask] as u16
     }
 
     fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
         let a_offset = address & self.mem_mask;
+        if !self.is_offset_mapped(a_offset) {
+            return 0;
+        }
+        if let Some(incolor) = &mut self.incolor {
+            incolor.cpu_write(a_offset, byte, self.mode_graphics);
+            return 0;
+        }
 
         self.mem[a_offset] = byte;
         trace!(self, "WRITE_U8: {:04X}:{:02X}", a_offset, byte);
@@ -110,7 +135,7 @@ impl MemoryMappedDevice for MDACard {
                     priority: 0,
                 });
             }
-            VideoCardSubType::Hercules => {
+            VideoCardSubType::Hercules | VideoCardSubType::HerculesInColor => {
                 log::debug!("MDA get_mapping(): Using Hercules memory map");
                 mapping.push(MemRangeDescriptor {
                     address: 0xB0000,
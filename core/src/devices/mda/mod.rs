@@ -41,12 +41,15 @@ use std::{collections::HashMap, convert::TryInto, path::Path};
 mod io;
 mod attr;
 mod draw;
+mod incolor;
 mod mmio;
+mod savestate;
 mod tablegen;
 mod videocard;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
+    devices::mda::incolor::{InColor, INCOLOR_STATUS_ID},
     device_traits::videocard::*,
     tracelogger::TraceLogger,
 };
@@ -150,6 +153,8 @@ const MDA_MONITOR_HSYNC_WIDTH: u32 = 80;
 //const MDA_MONITOR_VSYNC_POS: u32 = 246;
 // Minimum scanline value after which we can perform a vsync. A vsync before this scanline will be ignored.
 const MDA_MONITOR_VSYNC_MIN: u32 = 0;
+// Nominal horizontal scan rate of an MDA monitor, in kHz.
+const MDA_MONITOR_HFREQ: f64 = 18.432;
 
 const MDA_DEFAULT_CURSOR_BLINK_RATE: f64 = 0.0625;
 const MDA_DEFAULT_CURSOR_FRAME_CYCLE: u64 = 8;
@@ -167,7 +172,8 @@ const MDA_FONT: &'static [u8] = include_bytes!("../../../../assets/mda_8by14.bin
 const MDA_FONT_SPAN: usize = 256; // Font bitmap is 2048 bits wide (256 * 8 characters)
 
 const MDA_CHAR_CLOCK: u8 = 9;
-const HGC_CHAR_CLOCK: u8 = 8;
+// In graphics mode, each CRTC character is two bytes of video memory, or 16 pixels.
+const HGC_CHAR_CLOCK: u8 = 16;
 const CRTC_FONT_HEIGHT: u8 = 14;
 const CRTC_VBLANK_HEIGHT: u8 = 16;
 
@@ -313,6 +319,56 @@ const MDA_APERTURES: [DisplayAperture; 4] = [
     },
 ];
 
+// The Hercules graphics mode has different CRTC timings, so the display area falls at a different
+// position within the display field. The standard graphics mode is 720x348.
+const HGC_APERTURE_CROPPED_W: u32 = 720;
+const HGC_APERTURE_CROPPED_H: u32 = 348;
+const HGC_APERTURE_CROPPED_X: u32 = 16;
+const HGC_APERTURE_CROPPED_Y: u32 = 6;
+
+const HGC_APERTURE_NORMAL_W: u32 = 752;
+const HGC_APERTURE_NORMAL_H: u32 = 356;
+const HGC_APERTURE_NORMAL_X: u32 = 0;
+const HGC_APERTURE_NORMAL_Y: u32 = 2;
+
+const HGC_APERTURE_DEBUG_W: u32 = HGC_XRES_MAX;
+const HGC_APERTURE_DEBUG_H: u32 = MDA_YRES_MAX;
+
+const HGC_GFX_APERTURES: [DisplayAperture; 4] = [
+    // 16Mhz CROPPED aperture
+    DisplayAperture {
+        w: HGC_APERTURE_CROPPED_W,
+        h: HGC_APERTURE_CROPPED_H,
+        x: HGC_APERTURE_CROPPED_X,
+        y: HGC_APERTURE_CROPPED_Y,
+        debug: false,
+    },
+    // 16Mhz ACCURATE aperture
+    DisplayAperture {
+        w: HGC_APERTURE_NORMAL_W,
+        h: HGC_APERTURE_NORMAL_H,
+        x: HGC_APERTURE_NORMAL_X,
+        y: HGC_APERTURE_NORMAL_Y,
+        debug: false,
+    },
+    // 16Mhz FULL aperture
+    DisplayAperture {
+        w: HGC_APERTURE_NORMAL_W,
+        h: HGC_APERTURE_NORMAL_H,
+        x: HGC_APERTURE_NORMAL_X,
+        y: HGC_APERTURE_NORMAL_Y,
+        debug: false,
+    },
+    // 16Mhz DEBUG aperture
+    DisplayAperture {
+        w: HGC_APERTURE_DEBUG_W,
+        h: HGC_APERTURE_DEBUG_H,
+        x: 0,
+        y: 0,
+        debug: true,
+    },
+];
+
 const CROPPED_STRING: &str = &formatcp!("Cropped: {}x{}", MDA_APERTURE_CROPPED_W, MDA_APERTURE_CROPPED_H);
 const ACCURATE_STRING: &str = &formatcp!("Accurate: {}x{}", MDA_APERTURE_NORMAL_W, MDA_APERTURE_NORMAL_H);
 const FULL_STRING: &str = &formatcp!("Full: {}x{}", MDA_APERTURE_FULL_W, MDA_APERTURE_FULL_H);
@@ -442,6 +498,9 @@ pub struct MDACard {
 
     crtc: Crtc6845,
 
+    breakpoints: Vec<VideoBreakpoint>,
+    breakpoint_hit: bool,
+
     clock_divisor: u8, // Clock divisor is 1 in high resolution text mode, 2 in all other modes
     clock_mode:    ClockingMode,
     char_clock:    u32,
@@ -509,6 +568,8 @@ pub struct MDACard {
     hgc_config: HercConfigSwitch,
     hgc_page_offset: usize,
     hgc_page_flips: u32,
+    incolor: Option<InColor>,
+    incolor_reg_select: Option<u8>,
 }
 
 #[derive(Debug)]
@@ -548,6 +609,7 @@ impl MdaDefault for DisplayExtents {
             row_stride: MDA_XRES_MAX as usize,
             double_scan: false,
             mode_byte: 0,
+            standard: VideoStandard::Ntsc,
         }
     }
 }
@@ -574,7 +636,7 @@ impl Default for MDACard {
             mode: MdaModeRegister::new(),
             mode_pending: false,
             clock_pending: false,
-            display_mode: DisplayMode::Mode0TextBw40,
+            display_mode: DisplayMode::Mode7MonochromeText,
             mode_enable: true,
             mode_graphics: false,
             mode_bw: false,
@@ -598,6 +660,9 @@ impl Default for MDACard {
 
             crtc: Crtc6845::new(TraceLogger::None),
 
+            breakpoints: Vec::new(),
+            breakpoint_hit: false,
+
             clock_divisor: DEFAULT_CLOCK_DIVISOR,
             clock_mode: ClockingMode::Character,
             char_clock: DEFAULT_CHAR_CLOCK,
@@ -670,6 +735,8 @@ impl Default for MDACard {
             hgc_config: HercConfigSwitch::new(),
             hgc_page_offset: 0,
             hgc_page_flips: 0,
+            incolor: None,
+            incolor_reg_select: None,
         }
     }
 }
@@ -685,9 +752,13 @@ impl MDACard {
         let mut mda = Self::default();
 
         mda.subtype = subtype;
-        if let VideoCardSubType::Hercules = subtype {
+        if mda.is_hercules() {
+            // The upper page is handled by is_offset_mapped(), so always decode the full 64K.
             mda.mem_mask = HGC_MEM_MASK_FULL;
         }
+        if let VideoCardSubType::HerculesInColor = subtype {
+            mda.incolor = Some(InColor::new());
+        }
         mda.trace_logger = trace_logger;
         mda.debug = video_frame_debug;
 
@@ -723,13 +794,40 @@ impl MDACard {
             clock_mode: self.clock_mode,
             frame_count: self.frame_count, // Keep frame count as to not confuse frontend
             trace_logger,
+            breakpoints: self.breakpoints.clone(),
             extents: self.extents.clone(),
+            mem_mask: self.mem_mask,
+            incolor: self.incolor.as_ref().map(|_| InColor::new()),
             hblank_fn,
             lpt,
             ..Self::default()
         }
     }
 
+    /// Set the breakpoint flag if a write to the mode control register or to the selected CRTC
+    /// register matches any of the current video breakpoints.
+    fn check_breakpoints(&mut self, port: u16, data: u8) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+
+        let hit = if port == io::MDA_MODE_CONTROL_REGISTER {
+            self.breakpoints.iter().any(|bp| bp.matches_mode_write(data))
+        }
+        else if port & 0x01 != 0 {
+            let reg = self.crtc.selected_register();
+            self.breakpoints.iter().any(|bp| bp.matches_crtc_write(reg, data))
+        }
+        else {
+            false
+        };
+
+        if hit {
+            log::debug!("Video breakpoint hit: write of {:02X} to port {:03X}", data, port);
+            self.breakpoint_hit = true;
+        }
+    }
+
     /*
     fn rw_op(&mut self, ticks: u32, data: u8, addr: u32, rwtype: RwSlotType) {
         assert!(self.slot_idx < 4);
@@ -851,67 +949,89 @@ impl MDACard {
 
     /// Handle a write to the MDA mode register. Two of the bits are basically useless (0 & 1)
     /// leaving bit 3, which enables or disables video, and Bit 5, which controls blinking.
+    /// On the Hercules, bit 1 selects graphics mode and bit 7 selects the displayed page.
     fn handle_mode_register(&mut self, mode_byte: u8) {
         log::debug!("Write to MDA mode register: {:02X}", mode_byte);
+        self.mode_byte = mode_byte;
+        self.update_mode();
+    }
 
-        let old_bw = self.mode.bw();
+    /// Apply the last value written to the mode register. The Hercules graphics and page select
+    /// bits only take effect while enabled by the configuration switch, so this is also called
+    /// when the configuration switch is written.
+    fn update_mode(&mut self) {
         let old_page = self.mode.page_select();
 
-        self.mode = MdaModeRegister::from_bytes([mode_byte]);
+        self.mode = MdaModeRegister::from_bytes([self.mode_byte]);
 
         // Don't allow these bits to be set unless enabled and we are a HGC
-        if !self.hgc_config.enable_gfx() {
-            self.mode.set_bw(old_bw);
+        let hercules = self.is_hercules();
+        if !hercules || !self.hgc_config.enable_gfx() {
+            self.mode.set_bw(false);
         }
-        if !self.hgc_config.enable_page() {
-            self.mode.set_page_select(old_page);
+        if !hercules || !self.hgc_config.enable_page() {
+            self.mode.set_page_select(PageSelect::PageB000);
         }
 
-        if let VideoCardSubType::Hercules = self.subtype {
-            let current_page = self.mode.page_select();
-
-            if self.hgc_config.enable_page() {
-                self.mem_mask = HGC_MEM_MASK_FULL;
+        self.mode_enable = self.mode.display_enable();
+        self.mode_blinking = self.mode.blinking();
 
-                if current_page != old_page {
-                    self.hgc_page_flips += 1;
-                }
-
-                // Offset VMA base address based on selected page.
-                match current_page {
-                    PageSelect::PageB000 => {
-                        self.hgc_page_offset = 0;
-                    }
-                    PageSelect::PageB800 => {
-                        self.hgc_page_offset = HGC_PAGE_SIZE;
-                    }
-                }
-            }
-            else {
-                self.mem_mask = HGC_MEM_MASK_HALF;
+        if hercules {
+            let current_page = self.mode.page_select();
+            if current_page != old_page {
+                self.hgc_page_flips += 1;
             }
+
+            // Offset VMA base address based on selected page.
+            self.hgc_page_offset = match current_page {
+                PageSelect::PageB000 => 0,
+                PageSelect::PageB800 => HGC_PAGE_SIZE,
+            };
         }
 
         self.mode_graphics = self.mode.bw();
         if self.mode_graphics {
+            self.display_mode = DisplayMode::ModeHGCGraphics;
             self.char_clock = HGC_CHAR_CLOCK as u32;
             self.row_span = HGC_XRES_MAX;
+            self.extents.apertures = HGC_GFX_APERTURES.to_vec();
             self.extents.field_w = HGC_XRES_MAX;
             self.extents.row_stride = HGC_XRES_MAX as usize;
-            //self.clock_divisor = 2;
         }
         else {
+            self.display_mode = DisplayMode::Mode7MonochromeText;
             self.char_clock = MDA_CHAR_CLOCK as u32;
             self.row_span = MDA_XRES_MAX;
+            self.extents.apertures = MDA_APERTURES.to_vec();
             self.extents.field_w = MDA_XRES_MAX;
             self.extents.row_stride = MDA_XRES_MAX as usize;
-            //self.clock_divisor = 1;
         }
     }
 
+    /// Handle a write to the Hercules configuration switch. Bit 0 allows graphics mode to be
+    /// selected, and bit 1 maps the second page of video memory at B8000. Clearing either bit
+    /// returns the card to the corresponding MDA-compatible behavior immediately.
     fn handle_hgc_config_switch(&mut self, data: u8) {
         log::debug!("Write to Hercules configuration switch: {:02X}", data);
         self.hgc_config = HercConfigSwitch::from_bytes([data]);
+        self.update_mode();
+    }
+
+    /// Returns true if this card is a Hercules or Hercules-compatible card.
+    #[inline]
+    fn is_hercules(&self) -> bool {
+        matches!(
+            self.subtype,
+            VideoCardSubType::Hercules | VideoCardSubType::HerculesInColor
+        )
+    }
+
+    /// Returns whether the specified offset into video memory is decoded by the card. A Hercules
+    /// card in 'half' mode does not respond to the second page at B8000, so that a color adapter
+    /// may be installed alongside it.
+    #[inline]
+    fn is_offset_mapped(&self, a_offset: usize) -> bool {
+        a_offset < HGC_PAGE_SIZE || self.hgc_config.enable_page()
     }
 
     /// Handle a read from the MDA status register. This register has bits to indicate whether
@@ -925,12 +1045,13 @@ impl MDACard {
 
         // Bit 3 is set when the horizontal retrace is active.
 
-        let mut byte = 0x70;
-        if let VideoCardSubType::Hercules = self.subtype {
-            byte = 0x00;
-        }
+        let mut byte = match self.subtype {
+            VideoCardSubType::Hercules => 0x00,
+            VideoCardSubType::HerculesInColor => INCOLOR_STATUS_ID,
+            _ => 0x70,
+        };
 
-        if let VideoCardSubType::Hercules = self.subtype {
+        if self.is_hercules() {
             if !self.crtc.den() {
                 byte |= STATUS_HRETRACE;
             }
@@ -943,7 +1064,7 @@ impl MDACard {
             byte |= STATUS_VIDEO;
         }
 
-        if let VideoCardSubType::Hercules = self.subtype {
+        if self.is_hercules() {
             if !self.crtc.vblank() {
                 byte |= STATUS_NO_VRETRACE;
                 // Temporary hack for hercules (Road Runner)
@@ -1010,8 +1131,14 @@ impl MDACard {
     /// Fetch the character and attribute for the specified CRTC address.
     fn fetch_char(&mut self, vma: u16) {
         let addr = (vma as usize & MDA_TEXT_MODE_WRAP) << 1;
-        self.cur_char = self.mem[addr];
-        self.cur_attr = self.mem[addr + 1];
+        if let Some(incolor) = &self.incolor {
+            self.cur_char = incolor.text_byte(addr);
+            self.cur_attr = incolor.text_byte(addr + 1);
+        }
+        else {
+            self.cur_char = self.mem[addr];
+            self.cur_attr = self.mem[addr + 1];
+        }
 
         if self.mode_blinking {
             self.cur_blink = self.cur_attr & 0x80 != 0;
@@ -1019,6 +1146,19 @@ impl MDACard {
         else {
             self.cur_blink = false;
         }
+        if self.incolor.is_some() {
+            // The InColor uses color attributes in text mode, with bit 7 selecting either blink
+            // or a bright background.
+            self.cur_ul = false;
+            self.cur_fg = self.cur_attr & 0x0F;
+            self.cur_bg = if self.mode_blinking {
+                (self.cur_attr >> 4) & 0x07
+            }
+            else {
+                self.cur_attr >> 4
+            };
+            return;
+        }
         // Bits 0-2 determine underline status
         self.cur_ul = self.cur_attr & 0x03 == 1;
         // Look up fg/bg from attribute table as the logic isn't regular.
@@ -1196,8 +1336,8 @@ impl MDACard {
         }
 
         // Update position to next pixel and character column.
-        self.beam_x += 16;
-        self.rba += 16;
+        self.beam_x += self.char_clock;
+        self.rba += self.char_clock as usize;
 
         // If we have reached the right edge of the 'monitor', return the raster position
         // to the left side of the screen.
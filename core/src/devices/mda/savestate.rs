@@ -0,0 +1,80 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::mda::savestate.rs
+
+    Implements save state support for the IBM MDA and Hercules cards.
+
+    Video memory, the CRTC register file, the mode register and the Hercules
+    configuration switch are saved. On restore the registers are written back
+    through the same paths as a port write so that all derived state is
+    recalculated. Raster position is not saved. The InColor card's planes and
+    extended registers are saved after the MDA's memory if installed.
+
+*/
+
+use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+impl MDACard {
+    pub fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        w.write_bytes(&self.crtc.reg[0..16]);
+        w.write_u8(self.mode_byte);
+        w.write_u8(self.hgc_config.into_bytes()[0]);
+        w.write_bytes(&*self.mem);
+        w.write_bool(self.incolor.is_some());
+        if let Some(incolor) = &self.incolor {
+            incolor.save_state(w);
+            w.write_option_u8(self.incolor_reg_select);
+        }
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut crtc_regs = [0u8; 16];
+        r.read_bytes_into(&mut crtc_regs, "MDA CRTC registers")?;
+        let mode_byte = r.read_u8()?;
+        let hgc_config = r.read_u8()?;
+        r.read_bytes_into(&mut *self.mem, "MDA memory")?;
+        match (r.read_bool()?, &mut self.incolor) {
+            (true, Some(incolor)) => {
+                incolor.load_state(r)?;
+                self.incolor_reg_select = r.read_option_u8()?.filter(|idx| InColor::is_ext_register(*idx));
+            }
+            (false, None) => {}
+            _ => return Err(SaveStateError::MachineMismatch("Hercules InColor".to_string())),
+        }
+
+        for (idx, byte) in crtc_regs.iter().enumerate() {
+            self.crtc.port_write(io::CRTC_REGISTER_SELECT0, idx as u8);
+            self.crtc.port_write(io::CRTC_REGISTER0, *byte);
+        }
+
+        self.hgc_config = HercConfigSwitch::from_bytes([hgc_config]);
+        self.handle_mode_register(mode_byte);
+        Ok(())
+    }
+}
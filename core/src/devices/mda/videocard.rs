@@ -30,7 +30,11 @@
 
 */
 use super::*;
-use crate::{device_traits::videocard::*, devices::pic::Pic};
+use crate::{
+    device_traits::videocard::*,
+    devices::pic::Pic,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 
 impl VideoCard for MDACard {
     fn get_sync(&self) -> (bool, bool, bool, bool) {
@@ -51,6 +55,9 @@ impl VideoCard for MDACard {
                 log::debug!("VideoOption::DebugDraw set to: {}", state);
                 self.debug_draw = state;
             }
+            VideoOption::LogRegisterWrites(_state) => {
+                log::warn!("VideoOption::LogRegisterWrites not supported for MDA.");
+            }
         }
     }
 
@@ -83,7 +90,8 @@ impl VideoCard for MDACard {
         // MDA supports a single fixed 8x14 font. The size of the displayed window
         // is always HorizontalDisplayed * (VerticalDisplayed * (MaximumScanlineAddress + 1))
         // (Excepting fancy CRTC tricks that delay vsync)
-        let width = self.crtc.reg[0] as u32 * MDA_CHAR_CLOCK as u32;
+        // In Hercules graphics mode, each character is 16 pixels wide.
+        let width = self.crtc.reg[1] as u32 * self.char_clock;
         let height = self.crtc.reg[6] as u32 * (self.crtc.reg[9] as u32 + 1);
         (width, height)
     }
@@ -105,6 +113,28 @@ impl VideoCard for MDACard {
         Some((self.beam_x, self.beam_y))
     }
 
+    /// Unimplemented
+    fn set_light_pen(&mut self, _pos: Option<(u32, u32)>) {}
+
+    /// Unimplemented
+    fn get_register_log(&self) -> Option<&VideoRegisterLog> {
+        None
+    }
+
+    fn set_breakpoints(&mut self, bp_list: Vec<VideoBreakpoint>) {
+        self.breakpoints = bp_list;
+        self.breakpoint_hit = false;
+    }
+
+    fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::replace(&mut self.breakpoint_hit, false)
+    }
+
+    /// Unimplemented
+    fn get_raster_regions(&self) -> Option<RasterRegions> {
+        None
+    }
+
     /// Tick the MDA the specified number of video clock cycles.
     fn debug_tick(&mut self, ticks: u32, _cpumem: Option<&[u8]>) {
         match self.clock_mode {
@@ -190,6 +220,10 @@ impl VideoCard for MDACard {
     }
 
     /// Return the 16-bit value computed from the CRTC's pair of Page Address registers.
+    fn get_vram(&self) -> Option<&[u8]> {
+        Some(&self.mem[..])
+    }
+
     fn get_start_address(&self) -> u16 {
         return self.crtc.start_address();
     }
@@ -198,7 +232,7 @@ impl VideoCard for MDACard {
         let addr = self.get_cursor_address();
 
         match self.display_mode {
-            DisplayMode::Mode2TextBw80 | DisplayMode::Mode3TextCo80 => CursorInfo {
+            DisplayMode::Mode2TextBw80 | DisplayMode::Mode3TextCo80 | DisplayMode::Mode7MonochromeText => CursorInfo {
                 addr,
                 pos_x: (addr % 80) as u32,
                 pos_y: (addr / 80) as u32,
@@ -232,12 +266,49 @@ impl VideoCard for MDACard {
         })
     }
 
+    fn get_crtc_timing(&self) -> Option<CrtcTiming> {
+        let mut regs = [0; 10];
+        regs.copy_from_slice(&self.crtc.reg[0..10]);
+        Some(CrtcTiming::from_6845(&regs, MDA_CLOCK / self.char_clock as f64, MDA_MONITOR_HFREQ))
+    }
+
+    fn get_character_sets(&self) -> Vec<CharacterSet> {
+        vec![CharacterSet::from_row_major(
+            "Character ROM",
+            MDA_CHAR_CLOCK as u32,
+            CRTC_FONT_HEIGHT as u32,
+            MDA_FONT_SPAN,
+            MDA_FONT,
+        )]
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc.reg[9] + 1
     }
 
     fn get_palette(&self) -> Option<Vec<[u8;4]>> {
-        None
+        // Only the InColor has a palette. Other MDA cards are drawn with fixed monochrome colors.
+        self.incolor.as_ref().map(|incolor| incolor.rgba_palette())
+    }
+
+    fn get_palette_state(&self) -> Option<PaletteState> {
+        let palette = self
+            .get_palette()
+            .map(|pal| {
+                pal.into_iter()
+                    .enumerate()
+                    .map(|(i, rgba)| (i as u8, PaletteColor::Rgba(rgba)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(PaletteState {
+            palette,
+            overscan: None,
+            blink_enabled: self.mode_blinking,
+            blink_state: self.text_blink_state,
+            dac: Vec::new(),
+        })
     }
 
     // /// Return the current palette number, intensity attribute bit, and alt color
@@ -327,13 +398,24 @@ impl VideoCard for MDACard {
         internal_vec.push(("phase:".to_string(), VideoCardStateEntry::String(format!("{}", self.cycles & 0x0F))));
         internal_vec.push(("cursor attr:".to_string(), VideoCardStateEntry::String(format!("{:02b}", self.cursor_attr))));
         
-        if let VideoCardSubType::Hercules = self.subtype {
+        if self.is_hercules() {
             internal_vec.push(("HGC Display Page:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.mode.page_select()))));
             internal_vec.push(("HGC Page Flips:".to_string(), VideoCardStateEntry::String(format!("{}", self.hgc_page_flips))));
+            internal_vec.push(("HGC Allow Graphics:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.hgc_config.enable_gfx()))));
+            internal_vec.push(("HGC Full Mode:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.hgc_config.enable_page()))));
         }
         
         map.insert("Internal".to_string(), internal_vec);
 
+        if let Some(incolor) = &self.incolor {
+            let incolor_vec = incolor
+                .register_state()
+                .into_iter()
+                .map(|(name, val)| (format!("{}:", name), VideoCardStateEntry::String(format!("{:08b}", val))))
+                .collect();
+            map.insert("InColor".to_string(), incolor_vec);
+        }
+
         map
     }
 
@@ -344,8 +426,13 @@ impl VideoCard for MDACard {
         }
         */
 
+        // The Hercules card is clocked by its own 16Mhz crystal.
+        let clock = match self.subtype {
+            VideoCardSubType::Hercules | VideoCardSubType::HerculesInColor => HGC_CLOCK,
+            _ => MDA_CLOCK,
+        };
         let ticks = if let DeviceRunTimeUnit::Microseconds(us) = time {
-            us * MDA_CLOCK
+            us * clock
         }
         else {
             panic!("MDA requires Microseconds time unit.");
@@ -525,4 +612,34 @@ impl VideoCard for MDACard {
 
         strings
     }
+
+    fn get_text_mode_screen(&self, full_buffer: bool) -> Option<TextModeScreen> {
+        let columns = self.crtc.reg[1] as usize;
+        if self.mode_graphics || columns == 0 {
+            return None;
+        }
+
+        let (start, rows) = match full_buffer {
+            true => (0, (MDA_TEXT_MODE_WRAP + 1) / columns),
+            false => (self.crtc.start_address() as usize, self.crtc.reg[6] as usize),
+        };
+
+        Some(TextModeScreen::from_text_buffer(
+            &self.mem[..],
+            start,
+            columns,
+            rows,
+            MDA_TEXT_MODE_WRAP,
+            true,
+            self.mode_blinking,
+        ))
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
 }
@@ -33,27 +33,47 @@
 pub mod a0;
 #[cfg(feature = "opl")]
 pub mod adlib;
+pub mod atapi_cdrom;
 pub mod cartridge_slots;
+pub mod cassette;
 pub mod cga;
 pub mod dipswitch;
 pub mod dma;
+pub mod eems;
 #[cfg(feature = "ega")]
 pub mod ega;
 pub mod fdc;
 pub mod floppy_drive;
 pub mod game_port;
 pub mod hdc;
+pub mod inport_mouse;
+pub mod kbc;
 pub mod keyboard;
 pub mod lotech_ems;
 pub mod lpt_card;
+pub mod lpt_dac;
 pub mod lpt_port;
 pub mod mc6845;
 pub mod mda;
+pub mod modem;
 pub mod mouse;
+pub mod mpu401;
+pub mod net;
 pub mod pic;
 pub mod pit;
+pub mod post_card;
 pub mod ppi;
+pub mod printer;
+pub mod ps2_mouse;
+pub mod rtc;
+pub mod scsi_disk;
+pub mod seagate_st01;
 pub mod serial;
+pub mod serial_bridge;
+pub mod sn76489;
+pub mod sound_blaster;
+pub mod speaker_filter;
 pub mod tga;
 #[cfg(feature = "vga")]
 pub mod vga;
+pub mod xtide;
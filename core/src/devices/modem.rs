@@ -0,0 +1,843 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::modem.rs
+
+   Implements a Hayes-compatible modem attached to a serial port.
+
+   The modem understands a practical subset of the Hayes AT command set.
+   Dialing (ATDT) opens an outgoing TCP connection, either to an entry in
+   the configured phonebook or to a "host[:port]" dial string, so that the
+   guest can reach telnet BBSes. The modem can optionally listen on a TCP
+   port, in which case incoming connections ring the guest and are answered
+   with ATA or automatically after S0 rings.
+
+*/
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    devices::serial::{ModemStatusLines, SerialPortController},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+// Network activity is polled at this interval rather than every device tick.
+const MODEM_POLL_INTERVAL: f64 = 1000.0;
+// Stop reading from the connection while this many bytes are waiting for the guest.
+const MODEM_RX_BACKLOG: usize = 64;
+const MODEM_READ_CHUNK: usize = 256;
+
+// A ring cycle is six seconds, with RI asserted for the first two.
+const MODEM_RING_CYCLE: f64 = 6_000_000.0;
+const MODEM_RING_ON_TIME: f64 = 2_000_000.0;
+
+const MODEM_DEFAULT_PORT: u16 = 23;
+const MODEM_MAX_COMMAND_LEN: usize = 256;
+const MODEM_S_REGISTERS: usize = 32;
+
+const S_AUTO_ANSWER: usize = 0;
+const S_RING_COUNT: usize = 1;
+const S_ESCAPE_CHAR: usize = 2;
+const S_CR_CHAR: usize = 3;
+const S_LF_CHAR: usize = 4;
+const S_BS_CHAR: usize = 5;
+const S_CARRIER_WAIT: usize = 7;
+const S_GUARD_TIME: usize = 12;
+
+const TELNET_IAC: u8 = 0xFF;
+const TELNET_DONT: u8 = 0xFE;
+const TELNET_DO: u8 = 0xFD;
+const TELNET_WONT: u8 = 0xFC;
+const TELNET_WILL: u8 = 0xFB;
+const TELNET_SB: u8 = 0xFA;
+const TELNET_SE: u8 = 0xF0;
+const TELNET_OPT_BINARY: u8 = 0x00;
+const TELNET_OPT_ECHO: u8 = 0x01;
+const TELNET_OPT_SGA: u8 = 0x03;
+
+const MODEM_ID: &str = "MartyPC Hayes Modem";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ResultCode {
+    Ok = 0,
+    Connect = 1,
+    Ring = 2,
+    NoCarrier = 3,
+    Error = 4,
+    Busy = 7,
+    NoAnswer = 8,
+}
+
+impl ResultCode {
+    fn text(&self) -> &'static str {
+        match self {
+            ResultCode::Ok => "OK",
+            ResultCode::Connect => "CONNECT",
+            ResultCode::Ring => "RING",
+            ResultCode::NoCarrier => "NO CARRIER",
+            ResultCode::Error => "ERROR",
+            ResultCode::Busy => "BUSY",
+            ResultCode::NoAnswer => "NO ANSWER",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ModemState {
+    Command,
+    Dialing,
+    Online,
+    // Connected, but the guest has escaped back to command mode.
+    OnlineCommand,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TelnetState {
+    Data,
+    Iac,
+    Option(u8),
+    Sub,
+    SubIac,
+}
+
+pub struct HayesModem {
+    port: usize,
+    state: ModemState,
+    echo: bool,
+    quiet: bool,
+    verbose: bool,
+    dcd_follows_carrier: bool,
+    dtr_mode: u8,
+    s_reg: [u8; MODEM_S_REGISTERS],
+
+    cmd_buf:  Vec<u8>,
+    last_cmd: Vec<u8>,
+    to_guest: VecDeque<u8>,
+
+    phonebook: HashMap<String, String>,
+    telnet: bool,
+    telnet_state: TelnetState,
+
+    listener: Option<TcpListener>,
+    conn: Option<TcpStream>,
+    conn_out: Vec<u8>,
+    dial_result: Option<Receiver<Result<TcpStream, ResultCode>>>,
+    incoming: Option<TcpStream>,
+
+    ring_timer: f64,
+    poll_timer: f64,
+    // Time since the guest last sent a byte while online, for +++ guard time detection.
+    idle_timer: f64,
+    escape_count: u8,
+    dtr: bool,
+    lines: Option<ModemStatusLines>,
+    // The serial port's baud rate, reported as the connection speed.
+    baud: u32,
+}
+
+impl HayesModem {
+    pub fn new(port: usize, listen_port: Option<u16>, phonebook: HashMap<String, String>, telnet: bool) -> Self {
+        let listener = listen_port.and_then(|listen_port| {
+            match TcpListener::bind(("0.0.0.0", listen_port)).and_then(|l| l.set_nonblocking(true).map(|_| l)) {
+                Ok(listener) => {
+                    log::debug!("Modem listening for incoming calls on port {}", listen_port);
+                    Some(listener)
+                }
+                Err(e) => {
+                    log::error!("Modem failed to listen on port {}: {}", listen_port, e);
+                    None
+                }
+            }
+        });
+
+        let phonebook = phonebook
+            .into_iter()
+            .map(|(number, host)| (Self::normalize_number(&number), host))
+            .collect();
+
+        let mut modem = Self {
+            port,
+            state: ModemState::Command,
+            echo: true,
+            quiet: false,
+            verbose: true,
+            dcd_follows_carrier: true,
+            dtr_mode: 2,
+            s_reg: [0; MODEM_S_REGISTERS],
+            cmd_buf: Vec::new(),
+            last_cmd: Vec::new(),
+            to_guest: VecDeque::new(),
+            phonebook,
+            telnet,
+            telnet_state: TelnetState::Data,
+            listener,
+            conn: None,
+            conn_out: Vec::new(),
+            dial_result: None,
+            incoming: None,
+            ring_timer: 0.0,
+            poll_timer: 0.0,
+            idle_timer: 0.0,
+            escape_count: 0,
+            dtr: false,
+            lines: None,
+            baud: 2400,
+        };
+        modem.factory_defaults();
+        modem
+    }
+
+    /// Return the modem to its power-on state, dropping any call in progress.
+    pub fn reset(&mut self) {
+        self.hang_up();
+        self.incoming = None;
+        self.cmd_buf.clear();
+        self.last_cmd.clear();
+        self.to_guest.clear();
+        self.dtr = false;
+        self.lines = None;
+        self.factory_defaults();
+    }
+
+    fn factory_defaults(&mut self) {
+        self.echo = true;
+        self.quiet = false;
+        self.verbose = true;
+        self.dcd_follows_carrier = true;
+        self.dtr_mode = 2;
+        self.s_reg = [0; MODEM_S_REGISTERS];
+        self.s_reg[S_ESCAPE_CHAR] = b'+';
+        self.s_reg[S_CR_CHAR] = b'\r';
+        self.s_reg[S_LF_CHAR] = b'\n';
+        self.s_reg[S_BS_CHAR] = 0x08;
+        self.s_reg[6] = 2;
+        self.s_reg[S_CARRIER_WAIT] = 50;
+        self.s_reg[8] = 2;
+        self.s_reg[9] = 6;
+        self.s_reg[10] = 14;
+        self.s_reg[11] = 95;
+        self.s_reg[S_GUARD_TIME] = 50;
+    }
+
+    /// Phone numbers are matched ignoring the punctuation people tend to dial with.
+    fn normalize_number(number: &str) -> String {
+        number
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '(' | ')'))
+            .collect::<String>()
+            .to_ascii_lowercase()
+    }
+
+    fn is_connected(&self) -> bool {
+        matches!(self.state, ModemState::Online | ModemState::OnlineCommand)
+    }
+
+    fn guard_time(&self) -> f64 {
+        // S12 is specified in fiftieths of a second.
+        self.s_reg[S_GUARD_TIME] as f64 * 20_000.0
+    }
+
+    pub fn run(&mut self, serial: &mut SerialPortController, us: f64) {
+        self.baud = serial.baud_rate(self.port);
+        self.check_dtr(serial.get_dtr(self.port));
+
+        for byte in serial.take_tx_bytes(self.port) {
+            match self.state {
+                ModemState::Command | ModemState::OnlineCommand => self.command_byte(byte),
+                ModemState::Dialing => {
+                    // Any character aborts dialing.
+                    log::debug!("Modem: dial aborted");
+                    self.dial_result = None;
+                    self.state = ModemState::Command;
+                    self.result(ResultCode::NoCarrier);
+                }
+                ModemState::Online => self.online_byte(byte),
+            }
+        }
+
+        // A complete "+++" followed by the guard time returns to command mode.
+        if self.state == ModemState::Online {
+            self.idle_timer += us;
+            if self.escape_count == 3 && self.idle_timer >= self.guard_time() {
+                log::debug!("Modem: escaped to command mode");
+                self.escape_count = 0;
+                self.state = ModemState::OnlineCommand;
+                self.result(ResultCode::Ok);
+            }
+        }
+
+        self.poll_timer += us;
+        if self.poll_timer >= MODEM_POLL_INTERVAL {
+            self.poll_timer = 0.0;
+            self.poll_dial();
+            self.poll_listener();
+            self.poll_connection(serial.rx_queue_len(self.port));
+        }
+
+        self.run_ring(us);
+        self.update_lines(serial);
+
+        while let Some(byte) = self.to_guest.pop_front() {
+            serial.queue_byte(self.port, byte);
+        }
+    }
+
+    fn check_dtr(&mut self, dtr: bool) {
+        if self.dtr && !dtr {
+            match self.dtr_mode {
+                1 if self.state == ModemState::Online => {
+                    self.state = ModemState::OnlineCommand;
+                    self.result(ResultCode::Ok);
+                }
+                2 | 3 if self.is_connected() || self.state == ModemState::Dialing => {
+                    log::debug!("Modem: DTR dropped, hanging up");
+                    self.hang_up();
+                    self.result(ResultCode::NoCarrier);
+                }
+                _ => {}
+            }
+        }
+        self.dtr = dtr;
+    }
+
+    fn update_lines(&mut self, serial: &mut SerialPortController) {
+        let lines = ModemStatusLines {
+            cts: true,
+            dsr: true,
+            ri:  self.incoming.is_some() && self.ring_timer < MODEM_RING_ON_TIME,
+            dcd: !self.dcd_follows_carrier || self.is_connected(),
+        };
+        if self.lines != Some(lines) {
+            serial.set_modem_status_lines(self.port, lines);
+            self.lines = Some(lines);
+        }
+    }
+
+    fn run_ring(&mut self, us: f64) {
+        if self.incoming.is_none() {
+            return;
+        }
+        let ring_start = self.ring_timer == 0.0;
+        self.ring_timer += us;
+        if ring_start {
+            self.s_reg[S_RING_COUNT] = self.s_reg[S_RING_COUNT].saturating_add(1);
+            self.result(ResultCode::Ring);
+            let auto_answer = self.s_reg[S_AUTO_ANSWER];
+            if auto_answer > 0 && self.s_reg[S_RING_COUNT] >= auto_answer {
+                self.answer();
+                return;
+            }
+        }
+        if self.ring_timer >= MODEM_RING_CYCLE {
+            self.ring_timer = 0.0;
+        }
+    }
+
+    fn send_to_guest(&mut self, bytes: &[u8]) {
+        self.to_guest.extend(bytes);
+    }
+
+    fn result(&mut self, code: ResultCode) {
+        self.result_text(code, None);
+    }
+
+    fn result_text(&mut self, code: ResultCode, suffix: Option<String>) {
+        if self.quiet {
+            return;
+        }
+        let cr = self.s_reg[S_CR_CHAR];
+        let lf = self.s_reg[S_LF_CHAR];
+        if self.verbose {
+            let mut text = code.text().to_string();
+            if let Some(suffix) = suffix {
+                text.push(' ');
+                text.push_str(&suffix);
+            }
+            self.send_to_guest(&[cr, lf]);
+            self.send_to_guest(text.as_bytes());
+            self.send_to_guest(&[cr, lf]);
+        }
+        else {
+            self.send_to_guest(format!("{}", code as u8).as_bytes());
+            self.send_to_guest(&[cr]);
+        }
+    }
+
+    fn info_line(&mut self, text: &str) {
+        let cr = self.s_reg[S_CR_CHAR];
+        let lf = self.s_reg[S_LF_CHAR];
+        self.send_to_guest(&[cr, lf]);
+        self.send_to_guest(text.as_bytes());
+        self.send_to_guest(&[cr, lf]);
+    }
+
+    fn command_byte(&mut self, byte: u8) {
+        if self.echo {
+            self.send_to_guest(&[byte]);
+        }
+
+        if byte == self.s_reg[S_CR_CHAR] {
+            let line = std::mem::take(&mut self.cmd_buf);
+            if line.len() >= 2 && line[..2].eq_ignore_ascii_case(b"AT") {
+                self.last_cmd = line[2..].to_vec();
+                self.execute(&line[2..]);
+            }
+        }
+        else if byte == self.s_reg[S_BS_CHAR] {
+            self.cmd_buf.pop();
+        }
+        else if byte == b'/' && self.cmd_buf.len() == 1 && self.cmd_buf[0].eq_ignore_ascii_case(&b'A') {
+            // A/ repeats the last command immediately.
+            self.cmd_buf.clear();
+            let last = self.last_cmd.clone();
+            self.execute(&last);
+        }
+        else if byte.is_ascii_graphic() || byte == b' ' {
+            if self.cmd_buf.len() < MODEM_MAX_COMMAND_LEN {
+                self.cmd_buf.push(byte);
+            }
+        }
+    }
+
+    fn online_byte(&mut self, byte: u8) {
+        let guard_elapsed = self.idle_timer >= self.guard_time();
+        if byte == self.s_reg[S_ESCAPE_CHAR] && (self.escape_count > 0 || guard_elapsed) && self.escape_count < 3 {
+            self.escape_count += 1;
+        }
+        else {
+            self.escape_count = 0;
+        }
+        self.idle_timer = 0.0;
+
+        if self.telnet && byte == TELNET_IAC {
+            self.conn_out.push(TELNET_IAC);
+        }
+        self.conn_out.push(byte);
+    }
+
+    /// Execute the command line following "AT".
+    fn execute(&mut self, cmd: &[u8]) {
+        let cmd: Vec<u8> = cmd.iter().map(|b| b.to_ascii_uppercase()).collect();
+        let mut pos = 0;
+
+        // Parse an optional decimal argument at the current position.
+        fn number(cmd: &[u8], pos: &mut usize) -> Option<u32> {
+            let start = *pos;
+            while *pos < cmd.len() && cmd[*pos].is_ascii_digit() {
+                *pos += 1;
+            }
+            std::str::from_utf8(&cmd[start..*pos]).ok()?.parse().ok()
+        }
+
+        while pos < cmd.len() {
+            let c = cmd[pos];
+            pos += 1;
+            match c {
+                b' ' => {}
+                b'E' => self.echo = number(&cmd, &mut pos).unwrap_or(0) != 0,
+                b'Q' => self.quiet = number(&cmd, &mut pos).unwrap_or(0) != 0,
+                b'V' => self.verbose = number(&cmd, &mut pos).unwrap_or(0) != 0,
+                b'L' | b'M' | b'X' | b'B' => {
+                    // Speaker and result code set selection have no effect.
+                    number(&cmd, &mut pos);
+                }
+                b'Z' => {
+                    self.hang_up();
+                    self.factory_defaults();
+                    number(&cmd, &mut pos);
+                }
+                b'&' => {
+                    let sub = cmd.get(pos).copied();
+                    pos += 1;
+                    let arg = number(&cmd, &mut pos).unwrap_or(0);
+                    match sub {
+                        Some(b'F') => self.factory_defaults(),
+                        Some(b'C') => self.dcd_follows_carrier = arg != 0,
+                        Some(b'D') if arg <= 3 => self.dtr_mode = arg as u8,
+                        Some(b'K') | Some(b'S') | Some(b'W') | Some(b'V') => {}
+                        _ => {
+                            self.result(ResultCode::Error);
+                            return;
+                        }
+                    }
+                }
+                b'S' => {
+                    let reg = number(&cmd, &mut pos).unwrap_or(0) as usize;
+                    if reg >= MODEM_S_REGISTERS {
+                        self.result(ResultCode::Error);
+                        return;
+                    }
+                    match cmd.get(pos) {
+                        Some(b'=') => {
+                            pos += 1;
+                            let value = number(&cmd, &mut pos).unwrap_or(0);
+                            if value > 255 {
+                                self.result(ResultCode::Error);
+                                return;
+                            }
+                            self.s_reg[reg] = value as u8;
+                        }
+                        Some(b'?') => {
+                            pos += 1;
+                            let value = format!("{:03}", self.s_reg[reg]);
+                            self.info_line(&value);
+                        }
+                        _ => {
+                            self.result(ResultCode::Error);
+                            return;
+                        }
+                    }
+                }
+                b'I' => match number(&cmd, &mut pos).unwrap_or(0) {
+                    0 => self.info_line("2400"),
+                    _ => self.info_line(MODEM_ID),
+                },
+                b'H' => {
+                    number(&cmd, &mut pos);
+                    if self.is_connected() || self.incoming.is_some() {
+                        log::debug!("Modem: hanging up");
+                    }
+                    self.hang_up();
+                    self.incoming = None;
+                }
+                b'A' => {
+                    if self.incoming.is_some() {
+                        self.answer();
+                    }
+                    else {
+                        self.result(ResultCode::NoCarrier);
+                    }
+                    return;
+                }
+                b'O' => {
+                    number(&cmd, &mut pos);
+                    if self.state == ModemState::OnlineCommand {
+                        self.go_online(None);
+                    }
+                    else {
+                        self.result(ResultCode::NoCarrier);
+                    }
+                    return;
+                }
+                b'D' => {
+                    // The rest of the line is the dial string.
+                    let mut dial = &cmd[pos..];
+                    if let Some(b'T' | b'P') = dial.first() {
+                        dial = &dial[1..];
+                    }
+                    let dial = String::from_utf8_lossy(dial).trim().to_string();
+                    self.dial(&dial);
+                    return;
+                }
+                _ => {
+                    self.result(ResultCode::Error);
+                    return;
+                }
+            }
+        }
+        self.result(ResultCode::Ok);
+    }
+
+    fn dial(&mut self, dial: &str) {
+        if self.is_connected() {
+            self.result(ResultCode::Error);
+            return;
+        }
+
+        let host = match self.phonebook.get(&Self::normalize_number(dial)) {
+            Some(host) => host.clone(),
+            None => dial.to_ascii_lowercase(),
+        };
+        if host.is_empty() {
+            self.result(ResultCode::Error);
+            return;
+        }
+        let target = if host.contains(':') {
+            host
+        }
+        else {
+            format!("{}:{}", host, MODEM_DEFAULT_PORT)
+        };
+
+        log::debug!("Modem: dialing {}", target);
+        let timeout = Duration::from_secs(self.s_reg[S_CARRIER_WAIT].max(1) as u64);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = match target.to_socket_addrs() {
+                Ok(mut addrs) => match addrs.next() {
+                    Some(addr) => TcpStream::connect_timeout(&addr, timeout).map_err(|e| {
+                        log::debug!("Modem: connection to {} failed: {}", target, e);
+                        match e.kind() {
+                            ErrorKind::ConnectionRefused => ResultCode::Busy,
+                            ErrorKind::TimedOut | ErrorKind::WouldBlock => ResultCode::NoAnswer,
+                            _ => ResultCode::NoCarrier,
+                        }
+                    }),
+                    None => Err(ResultCode::NoCarrier),
+                },
+                Err(e) => {
+                    log::debug!("Modem: couldn't resolve {}: {}", target, e);
+                    Err(ResultCode::NoCarrier)
+                }
+            };
+            _ = tx.send(result);
+        });
+        self.dial_result = Some(rx);
+        self.state = ModemState::Dialing;
+    }
+
+    fn poll_dial(&mut self) {
+        let result = match &self.dial_result {
+            Some(rx) => match rx.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => Err(ResultCode::NoCarrier),
+            },
+            None => return,
+        };
+        self.dial_result = None;
+        match result {
+            Ok(stream) => self.go_online(Some(stream)),
+            Err(code) => {
+                self.state = ModemState::Command;
+                self.result(code);
+            }
+        }
+    }
+
+    fn poll_listener(&mut self) {
+        let stream = match &self.listener {
+            Some(listener) => match listener.accept() {
+                Ok((stream, addr)) => {
+                    log::debug!("Modem: incoming call from {}", addr);
+                    stream
+                }
+                Err(_) => return,
+            },
+            None => return,
+        };
+
+        if self.state == ModemState::Command && self.incoming.is_none() {
+            self.incoming = Some(stream);
+            self.ring_timer = 0.0;
+            self.s_reg[S_RING_COUNT] = 0;
+        }
+        else {
+            // The line is busy.
+            let mut stream = stream;
+            _ = stream.write_all(b"BUSY\r\n");
+        }
+    }
+
+    fn answer(&mut self) {
+        if let Some(stream) = self.incoming.take() {
+            log::debug!("Modem: answering");
+            self.ring_timer = 0.0;
+            self.s_reg[S_RING_COUNT] = 0;
+            self.go_online(Some(stream));
+        }
+    }
+
+    fn go_online(&mut self, stream: Option<TcpStream>) {
+        if let Some(stream) = stream {
+            if let Err(e) = stream.set_nonblocking(true) {
+                log::error!("Modem: couldn't configure connection: {}", e);
+                self.state = ModemState::Command;
+                self.result(ResultCode::NoCarrier);
+                return;
+            }
+            _ = stream.set_nodelay(true);
+            self.conn = Some(stream);
+            self.conn_out.clear();
+            self.telnet_state = TelnetState::Data;
+        }
+        self.state = ModemState::Online;
+        self.escape_count = 0;
+        self.idle_timer = 0.0;
+        self.result_text(ResultCode::Connect, Some(self.baud.to_string()));
+    }
+
+    fn hang_up(&mut self) {
+        self.conn = None;
+        self.conn_out.clear();
+        self.dial_result = None;
+        self.escape_count = 0;
+        self.state = ModemState::Command;
+    }
+
+    fn poll_connection(&mut self, rx_pending: usize) {
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => return,
+        };
+        let mut dropped = false;
+
+        while !self.conn_out.is_empty() {
+            match conn.write(&self.conn_out) {
+                Ok(0) => {
+                    dropped = true;
+                    break;
+                }
+                Ok(n) => {
+                    self.conn_out.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    dropped = true;
+                    break;
+                }
+            }
+        }
+
+        // Received data is only read while online and the guest is keeping up, letting TCP flow
+        // control throttle the remote end.
+        let mut received = Vec::new();
+        if !dropped && self.state == ModemState::Online && self.to_guest.len() + rx_pending < MODEM_RX_BACKLOG {
+            let mut buf = [0u8; MODEM_READ_CHUNK];
+            match conn.read(&mut buf) {
+                Ok(0) => dropped = true,
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => dropped = true,
+            }
+        }
+
+        for byte in received {
+            if self.telnet {
+                self.telnet_byte(byte);
+            }
+            else {
+                self.to_guest.push_back(byte);
+            }
+        }
+
+        if dropped {
+            log::debug!("Modem: remote end hung up");
+            self.hang_up();
+            self.result(ResultCode::NoCarrier);
+        }
+    }
+
+    /// Strip telnet commands from the received data, refusing any options other than the few
+    /// that make for a transparent 8-bit connection.
+    fn telnet_byte(&mut self, byte: u8) {
+        self.telnet_state = match self.telnet_state {
+            TelnetState::Data if byte == TELNET_IAC => TelnetState::Iac,
+            TelnetState::Data => {
+                self.to_guest.push_back(byte);
+                TelnetState::Data
+            }
+            TelnetState::Iac => match byte {
+                TELNET_IAC => {
+                    self.to_guest.push_back(byte);
+                    TelnetState::Data
+                }
+                TELNET_DO | TELNET_DONT | TELNET_WILL | TELNET_WONT => TelnetState::Option(byte),
+                TELNET_SB => TelnetState::Sub,
+                _ => TelnetState::Data,
+            },
+            TelnetState::Option(cmd) => {
+                let reply = match cmd {
+                    TELNET_DO if matches!(byte, TELNET_OPT_BINARY | TELNET_OPT_SGA) => Some(TELNET_WILL),
+                    TELNET_DO => Some(TELNET_WONT),
+                    TELNET_WILL if matches!(byte, TELNET_OPT_BINARY | TELNET_OPT_SGA | TELNET_OPT_ECHO) => {
+                        Some(TELNET_DO)
+                    }
+                    TELNET_WILL => Some(TELNET_DONT),
+                    _ => None,
+                };
+                if let Some(reply) = reply {
+                    self.conn_out.extend_from_slice(&[TELNET_IAC, reply, byte]);
+                }
+                TelnetState::Data
+            }
+            TelnetState::Sub if byte == TELNET_IAC => TelnetState::SubIac,
+            TelnetState::Sub => TelnetState::Sub,
+            TelnetState::SubIac if byte == TELNET_SE => TelnetState::Data,
+            TelnetState::SubIac => TelnetState::Sub,
+        };
+    }
+}
+
+/// Network connections can't be saved. Restoring a state that was taken during a call restores the
+/// modem's settings and reports NO CARRIER, as if the line had dropped.
+impl SaveState for HayesModem {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.state {
+            ModemState::Command => 0,
+            ModemState::Dialing => 1,
+            ModemState::Online => 2,
+            ModemState::OnlineCommand => 3,
+        });
+        w.write_bool(self.echo);
+        w.write_bool(self.quiet);
+        w.write_bool(self.verbose);
+        w.write_bool(self.dcd_follows_carrier);
+        w.write_u8(self.dtr_mode);
+        w.write_bytes(&self.s_reg);
+        w.write_bytes(&self.cmd_buf);
+        w.write_bytes(&self.last_cmd);
+        w.write_bytes(&self.to_guest.iter().copied().collect::<Vec<u8>>());
+        w.write_bool(self.dtr);
+        w.write_u32(self.baud);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let in_call = match r.read_u8()? {
+            0 => false,
+            1..=3 => true,
+            _ => return Err(SaveStateError::InvalidValue("modem state")),
+        };
+        self.echo = r.read_bool()?;
+        self.quiet = r.read_bool()?;
+        self.verbose = r.read_bool()?;
+        self.dcd_follows_carrier = r.read_bool()?;
+        self.dtr_mode = r.read_u8()?;
+        r.read_bytes_into(&mut self.s_reg, "modem S-registers")?;
+        self.cmd_buf = r.read_bytes()?.to_vec();
+        self.last_cmd = r.read_bytes()?.to_vec();
+        self.to_guest = r.read_bytes()?.iter().copied().collect();
+        self.dtr = r.read_bool()?;
+        self.baud = r.read_u32()?;
+
+        self.hang_up();
+        self.incoming = None;
+        self.telnet_state = TelnetState::Data;
+        self.ring_timer = 0.0;
+        self.idle_timer = 0.0;
+        self.lines = None;
+        if in_call {
+            self.result(ResultCode::NoCarrier);
+        }
+        Ok(())
+    }
+}
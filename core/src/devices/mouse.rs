@@ -28,10 +28,18 @@
 
    Implements a Microsoft Serial Mouse
 
+   The mouse can also speak the Logitech 3-button extension to the Microsoft
+   protocol, which adds a fourth byte for the middle button, or the Mouse
+   Systems 5-byte protocol.
+
 */
 use std::collections::VecDeque;
 
-use crate::devices::serial::SerialPortController;
+use crate::{
+    devices::serial::SerialPortController,
+    machine_types::SerialMouseType,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 // Scale factor for real vs emulated mouse deltas. Need to play with
 // this value until it feels right.
@@ -50,9 +58,22 @@ const MOUSE_UPDATE_RBUTTON: u8 = 0b0001_0000;
 const MOUSE_UPDATE_HO_BITS: u8 = 0b1100_0000;
 const MOUSE_UPDATE_LO_BITS: u8 = 0b0011_1111;
 
+// Logitech 3-button mice identify themselves with 'M3' on reset, and send a fourth byte
+// with the state of the middle button.
+const LOGITECH_RESET_ID_BYTE: u8 = 0x33;
+const LOGITECH_MBUTTON: u8 = 0b0010_0000;
+
+// Mouse Systems packets start with a sync byte containing the button state, active low.
+const MSYS_SYNC: u8 = 0b1000_0000;
+const MSYS_LBUTTON: u8 = 0b0000_0100;
+const MSYS_MBUTTON: u8 = 0b0000_0010;
+const MSYS_RBUTTON: u8 = 0b0000_0001;
+
 #[allow(dead_code)]
 pub struct Mouse {
+    mouse_type: SerialMouseType,
     updates: VecDeque<MouseUpdate>,
+    m_button: bool,
     rts: bool,
     rts_low_timer: f64,
     dtr: bool,
@@ -60,13 +81,15 @@ pub struct Mouse {
 }
 
 pub enum MouseUpdate {
-    Update(u8, u8, u8),
+    Update(Vec<u8>),
 }
 
 impl Mouse {
-    pub fn new(port: usize) -> Self {
+    pub fn new(port: usize, mouse_type: SerialMouseType) -> Self {
         Self {
+            mouse_type,
             updates: VecDeque::new(),
+            m_button: false,
             rts: false,
             rts_low_timer: 0.0,
             dtr: false,
@@ -74,7 +97,14 @@ impl Mouse {
         }
     }
 
-    pub fn update(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: f64, delta_y: f64) {
+    pub fn update(
+        &mut self,
+        l_button_pressed: bool,
+        r_button_pressed: bool,
+        m_button_pressed: bool,
+        delta_x: f64,
+        delta_y: f64,
+    ) {
         let mut scaled_x = delta_x * MOUSE_SCALE;
         let mut scaled_y = delta_y * MOUSE_SCALE;
 
@@ -94,6 +124,20 @@ impl Mouse {
         let delta_x_i8 = scaled_x as i8;
         let delta_y_i8 = scaled_y as i8;
 
+        let m_button_released = self.m_button && !m_button_pressed;
+        self.m_button = m_button_pressed;
+
+        if let SerialMouseType::MouseSystems = self.mouse_type {
+            self.update_mouse_systems(
+                l_button_pressed,
+                r_button_pressed,
+                m_button_pressed,
+                delta_x_i8,
+                delta_y_i8,
+            );
+            return;
+        }
+
         let mut byte1 = MOUSE_UPDATE_STARTBIT;
 
         if l_button_pressed {
@@ -120,9 +164,21 @@ impl Mouse {
         // LO 6 bits of Y into byte 3
         let byte3 = (delta_y_i8 as u8) & MOUSE_UPDATE_LO_BITS;
 
+        let mut packet = vec![byte1, byte2, byte3];
+
+        // Logitech mice append the middle button state while it is held, and once more on release.
+        if let SerialMouseType::Logitech = self.mouse_type {
+            if m_button_pressed {
+                packet.push(LOGITECH_MBUTTON);
+            }
+            else if m_button_released {
+                packet.push(0);
+            }
+        }
+
         // Queue update
 
-        self.updates.push_back(MouseUpdate::Update(byte1, byte2, byte3));
+        self.updates.push_back(MouseUpdate::Update(packet));
         /*
         let mut serial = self.serial_ctrl.borrow_mut();
         serial.queue_byte(MOUSE_PORT, byte1);
@@ -130,13 +186,31 @@ impl Mouse {
         serial.queue_byte(MOUSE_PORT, byte3);*/
     }
 
+    /// Queue a Mouse Systems 5-byte packet. The second pair of deltas is movement since the first
+    /// pair was sampled, which is always zero for us. Mouse Systems mice report Y positive upwards.
+    fn update_mouse_systems(&mut self, l_button: bool, r_button: bool, m_button: bool, delta_x: i8, delta_y: i8) {
+        let mut sync = MSYS_SYNC | MSYS_LBUTTON | MSYS_MBUTTON | MSYS_RBUTTON;
+        if l_button {
+            sync &= !MSYS_LBUTTON;
+        }
+        if m_button {
+            sync &= !MSYS_MBUTTON;
+        }
+        if r_button {
+            sync &= !MSYS_RBUTTON;
+        }
+        let delta_y = delta_y.saturating_neg();
+        self.updates
+            .push_back(MouseUpdate::Update(vec![sync, delta_x as u8, delta_y as u8, 0, 0]));
+    }
+
     /// Run the mouse device for the specified number of microseconds
     pub fn run(&mut self, serial: &mut SerialPortController, us: f64) {
         // Send a queued update.
-        if let Some(MouseUpdate::Update(byte1, byte2, byte3)) = self.updates.pop_front() {
-            serial.queue_byte(self.port, byte1);
-            serial.queue_byte(self.port, byte2);
-            serial.queue_byte(self.port, byte3);
+        if let Some(MouseUpdate::Update(packet)) = self.updates.pop_front() {
+            for byte in packet {
+                serial.queue_byte(self.port, byte);
+            }
         }
 
         // Check RTS line for mouse reset
@@ -159,10 +233,43 @@ impl Mouse {
             if self.rts_low_timer > MOUSE_RESET_TIME {
                 // Reset mouse
                 self.rts_low_timer = 0.0;
-                // Send reset ack byte
-                log::trace!("Sending reset byte: {:02X}", MOUSE_RESET_ACK_BYTE);
-                serial.queue_byte(self.port, MOUSE_RESET_ACK_BYTE);
+                // Send reset ack byte. Mouse Systems mice don't respond to RTS.
+                match self.mouse_type {
+                    SerialMouseType::Microsoft => {
+                        log::trace!("Sending reset byte: {:02X}", MOUSE_RESET_ACK_BYTE);
+                        serial.queue_byte(self.port, MOUSE_RESET_ACK_BYTE);
+                    }
+                    SerialMouseType::Logitech => {
+                        log::trace!(
+                            "Sending reset bytes: {:02X} {:02X}",
+                            MOUSE_RESET_ACK_BYTE,
+                            LOGITECH_RESET_ID_BYTE
+                        );
+                        serial.queue_byte(self.port, MOUSE_RESET_ACK_BYTE);
+                        serial.queue_byte(self.port, LOGITECH_RESET_ID_BYTE);
+                    }
+                    SerialMouseType::MouseSystems => {}
+                }
             }
         }
     }
 }
+
+impl SaveState for Mouse {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.m_button);
+        w.write_bool(self.rts);
+        w.write_f64(self.rts_low_timer);
+        w.write_bool(self.dtr);
+    }
+
+    /// Pending movement updates come from the host and are discarded.
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.m_button = r.read_bool()?;
+        self.rts = r.read_bool()?;
+        self.rts_low_timer = r.read_f64()?;
+        self.dtr = r.read_bool()?;
+        self.updates.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,516 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::mpu401.rs
+
+   Implements a Roland MPU-401 MIDI interface.
+
+   UART mode is fully supported: bytes written to the data port are
+   assembled into complete MIDI messages and sent to a host MIDI output
+   port (requires the 'midi' feature).
+
+   MIDI received from a host input port is passed to the guest. In UART
+   mode it is passed through as-is. In intelligent mode it is passed while
+   recording, with the timing bytes the MPU-401 inserts before each message
+   according to the current tempo and timebase.
+
+   Intelligent mode is otherwise supported to the extent most drivers need:
+   commands are acknowledged, the version and revision can be queried, and
+   the 'Send Data' commands (0xD0-0xD7) pass a message through. The playback
+   sequencer and conductor are not implemented.
+
+*/
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+use crossbeam_channel::{Receiver, Sender};
+#[cfg(feature = "midi")]
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+pub const MPU_DEFAULT_IO_BASE: u16 = 0x330;
+pub const MPU_DEFAULT_IRQ: u8 = 2;
+
+const PORT_DATA: u16 = 0;
+const PORT_STATUS_COMMAND: u16 = 1;
+
+// Status bits are active low. Bit 6 (DRR) is always clear, as output is never busy.
+const STATUS_INPUT_EMPTY: u8 = 0x80;
+
+const CMD_RESET: u8 = 0xFF;
+const CMD_UART_MODE: u8 = 0x3F;
+const CMD_VERSION: u8 = 0xAC;
+const CMD_REVISION: u8 = 0xAD;
+
+const CMD_TIMEBASE_48: u8 = 0xC2;
+const CMD_TIMEBASE_192: u8 = 0xC8;
+const CMD_SET_TEMPO: u8 = 0xE0;
+
+const MSG_ACK: u8 = 0xFE;
+const MSG_TIMING_OVERFLOW: u8 = 0xF8;
+const MSG_ALL_END: u8 = 0xFC;
+/// The largest timing value sent before a message. Longer intervals are sent as timing overflows.
+const MAX_TIMING_TICKS: f64 = 240.0;
+const DEFAULT_TEMPO: u8 = 100;
+const DEFAULT_TIMEBASE: u16 = 120;
+const MPU_VERSION: u8 = 0x15;
+const MPU_REVISION: u8 = 0x01;
+
+const INPUT_QUEUE_LEN: usize = 256;
+const MAX_SYSEX_LEN: usize = 4096;
+
+/// Assembles a stream of MIDI bytes into complete messages, expanding running status.
+#[derive(Default)]
+struct MidiParser {
+    status:   u8,
+    message:  Vec<u8>,
+    expected: usize,
+    in_sysex: bool,
+}
+
+impl MidiParser {
+    /// Return the number of data bytes expected after the specified status byte.
+    fn data_len(status: u8) -> usize {
+        match status & 0xF0 {
+            0xC0 | 0xD0 => 1,
+            0x80..=0xE0 => 2,
+            _ => match status {
+                0xF1 | 0xF3 => 1,
+                0xF2 => 2,
+                _ => 0,
+            },
+        }
+    }
+
+    /// Feed a byte, returning a complete message if one was finished.
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if byte >= 0xF8 {
+            // Real-time messages may appear anywhere, even inside other messages.
+            return Some(vec![byte]);
+        }
+
+        if self.in_sysex {
+            if byte & 0x80 == 0 {
+                if self.message.len() < MAX_SYSEX_LEN {
+                    self.message.push(byte);
+                }
+                return None;
+            }
+            // Any status byte ends a system exclusive message. A status byte other than EOX is dropped;
+            // this is rare in practice.
+            self.in_sysex = false;
+            self.message.push(0xF7);
+            return Some(std::mem::take(&mut self.message));
+        }
+
+        if byte & 0x80 != 0 {
+            if byte == 0xF0 {
+                self.in_sysex = true;
+                self.message = vec![byte];
+                return None;
+            }
+            if byte == 0xF7 {
+                // Stray end of exclusive.
+                return None;
+            }
+            self.message.clear();
+            self.message.push(byte);
+            self.expected = Self::data_len(byte);
+            // System common messages cancel running status.
+            self.status = if byte < 0xF0 { byte } else { 0 };
+        }
+        else {
+            if self.message.is_empty() {
+                if self.status == 0 {
+                    // Data byte without a status; ignore it.
+                    return None;
+                }
+                self.message.push(self.status);
+                self.expected = Self::data_len(self.status);
+            }
+            self.message.push(byte);
+        }
+
+        if self.message.len() > self.expected {
+            return Some(std::mem::take(&mut self.message));
+        }
+        None
+    }
+}
+
+pub struct Mpu401 {
+    io_base: u16,
+    irq: u8,
+    uart_mode: bool,
+    input: VecDeque<u8>,
+    // Set while an intelligent mode 'Send Data' message is being received.
+    send_data: bool,
+    parser: MidiParser,
+    #[cfg(feature = "midi")]
+    output: Option<MidiOutputConnection>,
+    #[cfg(feature = "midi")]
+    _midi_in: Option<MidiInputConnection<()>>,
+    #[cfg_attr(not(feature = "midi"), allow(dead_code))]
+    midi_in_tx: Sender<Vec<u8>>,
+    midi_in_rx: Receiver<Vec<u8>>,
+    // Intelligent mode command waiting for its parameter on the data port.
+    param_command: Option<u8>,
+    recording: bool,
+    tempo: u8,
+    timebase: u16,
+    // Time since the last timing byte was sent while recording.
+    record_us: f64,
+    irq_asserted: bool,
+}
+
+impl Mpu401 {
+    pub fn new(io_base: u16, irq: u8) -> Self {
+        let (midi_in_tx, midi_in_rx) = crossbeam_channel::unbounded();
+        Self {
+            io_base,
+            irq,
+            uart_mode: false,
+            input: VecDeque::with_capacity(INPUT_QUEUE_LEN),
+            send_data: false,
+            parser: MidiParser::default(),
+            #[cfg(feature = "midi")]
+            output: None,
+            #[cfg(feature = "midi")]
+            _midi_in: None,
+            midi_in_tx,
+            midi_in_rx,
+            param_command: None,
+            recording: false,
+            tempo: DEFAULT_TEMPO,
+            timebase: DEFAULT_TIMEBASE,
+            record_us: 0.0,
+            irq_asserted: false,
+        }
+    }
+
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    /// Connect to a host MIDI output port. The first port whose name contains `port` is used, or the
+    /// first port available if `port` is None. Returns the name of the connected port.
+    #[cfg(feature = "midi")]
+    pub fn connect_output(&mut self, port: Option<&str>) -> Result<String, anyhow::Error> {
+        let midi_out = MidiOutput::new("MartyPC").map_err(|e| anyhow::anyhow!("{}", e))?;
+        let ports = midi_out.ports();
+        let selected = ports.iter().find(|p| match port {
+            Some(name) => midi_out
+                .port_name(p)
+                .map(|n| n.to_lowercase().contains(&name.to_lowercase()))
+                .unwrap_or(false),
+            None => true,
+        });
+        let selected = match selected {
+            Some(p) => p,
+            None => anyhow::bail!("No matching MIDI output port found."),
+        };
+        let name = midi_out.port_name(selected).unwrap_or_default();
+        let conn = midi_out
+            .connect(selected, "MartyPC MPU-401")
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.output = Some(conn);
+        Ok(name)
+    }
+
+    /// Connect to a host MIDI input port, such as a keyboard. The first port whose name contains `port`
+    /// is used, or the first port available if `port` is None. Returns the name of the connected port.
+    #[cfg(feature = "midi")]
+    pub fn connect_input(&mut self, port: Option<&str>) -> Result<String, anyhow::Error> {
+        let mut midi_in = MidiInput::new("MartyPC").map_err(|e| anyhow::anyhow!("{}", e))?;
+        // Active sensing would flood the guest with messages it doesn't expect.
+        midi_in.ignore(Ignore::ActiveSense);
+        let ports = midi_in.ports();
+        let selected = ports.iter().find(|p| match port {
+            Some(name) => midi_in
+                .port_name(p)
+                .map(|n| n.to_lowercase().contains(&name.to_lowercase()))
+                .unwrap_or(false),
+            None => true,
+        });
+        let selected = match selected {
+            Some(p) => p,
+            None => anyhow::bail!("No matching MIDI input port found."),
+        };
+        let name = midi_in.port_name(selected).unwrap_or_default();
+        let tx = self.midi_in_tx.clone();
+        let conn = midi_in
+            .connect(
+                selected,
+                "MartyPC MPU-401",
+                move |_timestamp, message, _| {
+                    _ = tx.send(message.to_vec());
+                },
+                (),
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        self._midi_in = Some(conn);
+        Ok(name)
+    }
+
+    /// Return the length of a timing tick in microseconds at the current tempo and timebase.
+    fn tick_us(&self) -> f64 {
+        60_000_000.0 / (self.tempo.max(1) as f64 * self.timebase as f64)
+    }
+
+    /// Pass a message received on MIDI IN to the guest.
+    fn receive_message(&mut self, message: &[u8]) {
+        if self.uart_mode {
+            for byte in message {
+                self.queue_input(*byte);
+            }
+        }
+        else if self.recording {
+            // Each message is preceded by the number of ticks since the previous event.
+            let ticks = (self.record_us / self.tick_us()).floor().min(MAX_TIMING_TICKS - 1.0);
+            self.record_us -= ticks * self.tick_us();
+            self.queue_input(ticks as u8);
+            for byte in message {
+                self.queue_input(*byte);
+            }
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if self.recording {
+            self.recording = false;
+            let ticks = (self.record_us / self.tick_us()).floor().min(MAX_TIMING_TICKS - 1.0);
+            self.queue_input(ticks as u8);
+            self.queue_input(MSG_ALL_END);
+        }
+    }
+
+    /// Reset the interface to intelligent mode and silence all notes.
+    pub fn reset(&mut self) {
+        self.uart_mode = false;
+        self.input.clear();
+        self.send_data = false;
+        self.parser = MidiParser::default();
+        self.param_command = None;
+        self.recording = false;
+        self.tempo = DEFAULT_TEMPO;
+        self.timebase = DEFAULT_TIMEBASE;
+        self.record_us = 0.0;
+        self.all_notes_off();
+    }
+
+    fn all_notes_off(&mut self) {
+        for channel in 0..16 {
+            // All Sound Off and Reset All Controllers
+            self.send_message(&[0xB0 | channel, 0x78, 0x00]);
+            self.send_message(&[0xB0 | channel, 0x79, 0x00]);
+        }
+    }
+
+    fn send_message(&mut self, _message: &[u8]) {
+        #[cfg(feature = "midi")]
+        if let Some(output) = &mut self.output {
+            if let Err(e) = output.send(_message) {
+                log::warn!("MPU-401: Failed to send MIDI message: {}", e);
+            }
+        }
+    }
+
+    fn queue_input(&mut self, byte: u8) {
+        if self.input.len() < INPUT_QUEUE_LEN {
+            self.input.push_back(byte);
+        }
+    }
+
+    fn write_data(&mut self, byte: u8) {
+        if let Some(command) = self.param_command.take() {
+            if command == CMD_SET_TEMPO {
+                self.tempo = byte;
+            }
+            return;
+        }
+        if !self.uart_mode && !self.send_data {
+            // Intelligent mode data without a preceding command is ignored.
+            return;
+        }
+        if let Some(message) = self.parser.push(byte) {
+            self.send_message(&message);
+            self.send_data = false;
+        }
+    }
+
+    fn write_command(&mut self, command: u8) {
+        if self.uart_mode {
+            // In UART mode, only reset is recognized. It returns to intelligent mode without an ACK.
+            if command == CMD_RESET {
+                log::debug!("MPU-401: Leaving UART mode");
+                self.reset();
+            }
+            return;
+        }
+
+        match command {
+            CMD_RESET => self.reset(),
+            CMD_UART_MODE => {
+                log::debug!("MPU-401: Entering UART mode");
+                self.uart_mode = true;
+            }
+            // Start and stop commands. Bits 5-4 control MIDI, bits 3-2 playback and bits 1-0 recording.
+            0x00..=0x2F => match command & 0x03 {
+                0x01 => self.stop_recording(),
+                0x02 => {
+                    self.recording = true;
+                    self.record_us = 0.0;
+                }
+                _ => {}
+            },
+            CMD_TIMEBASE_48..=CMD_TIMEBASE_192 => {
+                self.timebase = (command - CMD_TIMEBASE_48 + 2) as u16 * 24;
+            }
+            0xD0..=0xD7 => {
+                self.send_data = true;
+            }
+            // Commands that take a parameter byte on the data port.
+            0xE0..=0xEF => {
+                self.param_command = Some(command);
+            }
+            _ => {}
+        }
+
+        // All commands are acknowledged in intelligent mode.
+        self.queue_input(MSG_ACK);
+
+        match command {
+            CMD_VERSION => self.queue_input(MPU_VERSION),
+            CMD_REVISION => self.queue_input(MPU_REVISION),
+            _ => {}
+        }
+    }
+
+    /// The MPU-401 holds its IRQ line while there is data to be read. Returns Some(state) when the IRQ
+    /// line changes.
+    pub fn run(&mut self, us: f64) -> Option<bool> {
+        if self.recording {
+            self.record_us += us;
+            // Send a timing overflow for every 240 ticks without an event.
+            let overflow_us = MAX_TIMING_TICKS * self.tick_us();
+            while self.record_us >= overflow_us {
+                self.record_us -= overflow_us;
+                self.queue_input(MSG_TIMING_OVERFLOW);
+            }
+        }
+
+        while let Ok(message) = self.midi_in_rx.try_recv() {
+            self.receive_message(&message);
+        }
+
+        let pending = !self.input.is_empty();
+        if pending != self.irq_asserted {
+            self.irq_asserted = pending;
+            return Some(pending);
+        }
+        None
+    }
+}
+
+impl Drop for Mpu401 {
+    fn drop(&mut self) {
+        self.all_notes_off();
+    }
+}
+
+impl IoDevice for Mpu401 {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port - self.io_base {
+            PORT_DATA => self.input.pop_front().unwrap_or(0xFF),
+            PORT_STATUS_COMMAND => {
+                let mut status = 0x3F;
+                if self.input.is_empty() {
+                    status |= STATUS_INPUT_EMPTY;
+                }
+                status
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port - self.io_base {
+            PORT_DATA => self.write_data(data),
+            PORT_STATUS_COMMAND => self.write_command(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("MPU-401 Data"), self.io_base + PORT_DATA),
+            (
+                String::from("MPU-401 Status/Command"),
+                self.io_base + PORT_STATUS_COMMAND,
+            ),
+        ]
+    }
+}
+
+/// Host MIDI connections are not part of the state. A partial message is kept, so a message the
+/// guest was in the middle of sending is completed after a restore.
+impl SaveState for Mpu401 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.uart_mode);
+        w.write_bytes(&self.input.iter().copied().collect::<Vec<u8>>());
+        w.write_bool(self.send_data);
+        w.write_u8(self.parser.status);
+        w.write_bytes(&self.parser.message);
+        w.write_usize(self.parser.expected);
+        w.write_bool(self.parser.in_sysex);
+        w.write_option_u8(self.param_command);
+        w.write_bool(self.recording);
+        w.write_u8(self.tempo);
+        w.write_u16(self.timebase);
+        w.write_f64(self.record_us);
+        w.write_bool(self.irq_asserted);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.uart_mode = r.read_bool()?;
+        self.input = r.read_bytes()?.iter().copied().collect();
+        self.send_data = r.read_bool()?;
+        self.parser.status = r.read_u8()?;
+        self.parser.message = r.read_bytes()?.to_vec();
+        self.parser.expected = r.read_usize()?;
+        self.parser.in_sysex = r.read_bool()?;
+        self.param_command = r.read_option_u8()?;
+        self.recording = r.read_bool()?;
+        self.tempo = r.read_u8()?;
+        self.timebase = r.read_u16()?;
+        self.record_us = r.read_f64()?;
+        self.irq_asserted = r.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::net::mod.rs
+
+    Network adapters and the host networking backends they attach to.
+
+    A network card exchanges raw Ethernet frames with a NetworkBackend. The
+    backend is responsible for getting those frames to and from the outside
+    world, whether by running a user-mode TCP/IP stack on behalf of the guest
+    or by bridging to a host network interface.
+
+*/
+
+pub mod ne2000;
+pub mod pcap;
+pub mod slirp;
+
+pub const ETHERNET_ADDR_LEN: usize = 6;
+pub const ETHERNET_HEADER_LEN: usize = 14;
+pub const ETHERNET_MIN_FRAME: usize = 60;
+pub const ETHERNET_MAX_FRAME: usize = 1514;
+
+pub type MacAddress = [u8; ETHERNET_ADDR_LEN];
+
+pub const BROADCAST_MAC: MacAddress = [0xFF; ETHERNET_ADDR_LEN];
+
+/// A host networking backend. Frames are complete Ethernet frames without the trailing FCS.
+pub trait NetworkBackend {
+    /// Return a short description of the backend for logging and display.
+    fn name(&self) -> String;
+    /// Transmit a frame sent by the guest.
+    fn send(&mut self, frame: &[u8]);
+    /// Return the next frame destined for the guest, if one is available.
+    fn recv(&mut self) -> Option<Vec<u8>>;
+    /// Service the backend. This is called periodically with the elapsed time in microseconds.
+    fn poll(&mut self, us: f64);
+}
+
+/// Parse a MAC address in the form "00:11:22:33:44:55". Dashes are accepted as separators as well.
+pub fn parse_mac(s: &str) -> Option<MacAddress> {
+    let mut mac = [0u8; ETHERNET_ADDR_LEN];
+    let mut parts = s.trim().split(|c| c == ':' || c == '-');
+    for byte in mac.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(mac)
+}
+
+pub fn format_mac(mac: &MacAddress) -> String {
+    mac.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
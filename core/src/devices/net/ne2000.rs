@@ -0,0 +1,874 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::net::ne2000.rs
+
+    Implementation of the Novell NE1000 and NE2000 network adapters.
+
+    Both cards are built around the National DP8390 Network Interface
+    Controller. The host has no direct access to the card's packet buffer
+    RAM; instead it programs the DP8390's remote DMA channel with an address
+    and byte count and then reads or writes the data port. The NE1000 is an
+    8-bit card with 8K of buffer RAM, the NE2000 has 16K. On an 8-bit bus the
+    NE2000 is operated in byte mode, so every access to the data port moves
+    a single byte.
+
+    Received frames are placed into a ring of 256-byte pages between PSTART
+    and PSTOP, each preceded by a four byte header giving the receive status,
+    the next page pointer and the frame length.
+
+    The station address PROM is mapped at address 0 of the remote DMA space.
+
+    Frames pass through a NetworkTap on their way to and from the backend,
+    allowing traffic to be captured to a pcap file or replayed from one.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    devices::net::{
+        format_mac,
+        pcap::NetworkTap,
+        MacAddress,
+        NetworkBackend,
+        BROADCAST_MAC,
+        ETHERNET_ADDR_LEN,
+        ETHERNET_MAX_FRAME,
+        ETHERNET_MIN_FRAME,
+    },
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+pub const NE2000_DEFAULT_PORT: u16 = 0x300;
+pub const NE2000_DEFAULT_IRQ: u8 = 3;
+// Novell's OUI.
+pub const NE2000_DEFAULT_MAC: MacAddress = [0x00, 0x00, 0x1B, 0x12, 0x34, 0x56];
+
+const NE2000_PORT_CT: u16 = 0x20;
+const NE_DATA_PORT: u16 = 0x10;
+const NE_RESET_PORT: u16 = 0x18;
+
+const PAGE_SIZE: usize = 256;
+const PROM_SIZE: usize = 32;
+// How often the backend is serviced.
+const POLL_INTERVAL_US: f64 = 1000.0;
+// Frames waiting for space in the receive ring. Beyond this the oldest frames are dropped.
+const RX_QUEUE_LEN: usize = 64;
+
+// Command register bits
+const CR_STP: u8 = 0b0000_0001;
+const CR_STA: u8 = 0b0000_0010;
+const CR_TXP: u8 = 0b0000_0100;
+const CR_RD_MASK: u8 = 0b0011_1000;
+const CR_RD_READ: u8 = 0b0000_1000;
+const CR_RD_WRITE: u8 = 0b0001_0000;
+const CR_RD_SEND: u8 = 0b0001_1000;
+const CR_RD_ABORT: u8 = 0b0010_0000;
+const CR_PS_MASK: u8 = 0b1100_0000;
+
+// Interrupt status register bits
+const ISR_PRX: u8 = 0b0000_0001;
+const ISR_PTX: u8 = 0b0000_0010;
+const ISR_OVW: u8 = 0b0001_0000;
+const ISR_RDC: u8 = 0b0100_0000;
+const ISR_RST: u8 = 0b1000_0000;
+
+// Receive configuration register bits
+const RCR_AB: u8 = 0b0000_0100;
+const RCR_AM: u8 = 0b0000_1000;
+const RCR_PRO: u8 = 0b0001_0000;
+const RCR_MON: u8 = 0b0010_0000;
+
+// Transmit configuration register bits
+const TCR_LB_MASK: u8 = 0b0000_0110;
+
+// Transmit and receive status bits
+const TSR_PTX: u8 = 0b0000_0001;
+const RSR_PRX: u8 = 0b0000_0001;
+const RSR_PHY: u8 = 0b0010_0000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NicModel {
+    Ne1000,
+    Ne2000,
+}
+
+impl NicModel {
+    fn mem_start(&self) -> usize {
+        match self {
+            NicModel::Ne1000 => 0x2000,
+            NicModel::Ne2000 => 0x4000,
+        }
+    }
+
+    fn mem_size(&self) -> usize {
+        match self {
+            NicModel::Ne1000 => 0x2000,
+            NicModel::Ne2000 => 0x4000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NicStats {
+    pub tx_frames:  usize,
+    pub rx_frames:  usize,
+    pub rx_dropped: usize,
+}
+
+pub struct Ne2000 {
+    model: NicModel,
+    io_base: u16,
+    irq: u8,
+    mac: MacAddress,
+    prom: [u8; PROM_SIZE],
+    mem: Vec<u8>,
+    backend: Box<dyn NetworkBackend>,
+    tap: NetworkTap,
+    rx_queue: VecDeque<Vec<u8>>,
+    poll_accum: f64,
+    intr: bool,
+    stats: NicStats,
+
+    cr: u8,
+    isr: u8,
+    imr: u8,
+    dcr: u8,
+    tcr: u8,
+    tsr: u8,
+    rcr: u8,
+    rsr: u8,
+    pstart: u8,
+    pstop: u8,
+    bnry: u8,
+    curr: u8,
+    tpsr: u8,
+    tbcr: u16,
+    rsar: u16,
+    rbcr: u16,
+    clda: u16,
+    par: MacAddress,
+    mar: [u8; 8],
+    tally: [u8; 3],
+}
+
+impl IoDevice for Ne2000 {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port - self.io_base {
+            reg @ 0x00..=0x0F => self.read_register(reg as u8),
+            0x10..=0x17 => self.remote_read(),
+            _ => {
+                // Reading the reset port resets the card.
+                self.reset();
+                NO_IO_BYTE
+            }
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port - self.io_base {
+            reg @ 0x00..=0x0F => self.write_register(reg as u8, data),
+            0x10..=0x17 => self.remote_write(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        let name = self.model_name();
+        let mut ports = Vec::new();
+        for reg in 0..NE_DATA_PORT {
+            ports.push((format!("{} DP8390 Register {:X}h", name, reg), self.io_base + reg));
+        }
+        for port in NE_DATA_PORT..NE_RESET_PORT {
+            ports.push((format!("{} Data Port", name), self.io_base + port));
+        }
+        for port in NE_RESET_PORT..NE2000_PORT_CT {
+            ports.push((format!("{} Reset Port", name), self.io_base + port));
+        }
+        ports
+    }
+}
+
+impl Ne2000 {
+    pub fn new(
+        model: NicModel,
+        io_base: Option<u16>,
+        irq: Option<u8>,
+        mac: Option<MacAddress>,
+        backend: Box<dyn NetworkBackend>,
+    ) -> Self {
+        let mac = mac.unwrap_or(NE2000_DEFAULT_MAC);
+
+        // The NE2000 PROM is wired to both halves of the data bus, so each byte appears twice.
+        // Bytes 14 and 15 hold the 'WW' signature drivers use to identify a word-wide card.
+        let mut prom = [0u8; PROM_SIZE];
+        match model {
+            NicModel::Ne1000 => {
+                prom[..ETHERNET_ADDR_LEN].copy_from_slice(&mac);
+                prom[14] = 0x57;
+                prom[15] = 0x57;
+            }
+            NicModel::Ne2000 => {
+                for (i, byte) in mac.iter().enumerate() {
+                    prom[i * 2] = *byte;
+                    prom[i * 2 + 1] = *byte;
+                }
+                prom[28..32].fill(0x57);
+            }
+        }
+
+        let mut nic = Self {
+            model,
+            io_base: io_base.unwrap_or(NE2000_DEFAULT_PORT),
+            irq: irq.unwrap_or(NE2000_DEFAULT_IRQ),
+            mac,
+            prom,
+            mem: vec![0; model.mem_size()],
+            backend,
+            tap: NetworkTap::new(),
+            rx_queue: VecDeque::new(),
+            poll_accum: 0.0,
+            intr: false,
+            stats: Default::default(),
+            cr: 0,
+            isr: 0,
+            imr: 0,
+            dcr: 0,
+            tcr: 0,
+            tsr: 0,
+            rcr: 0,
+            rsr: 0,
+            pstart: 0,
+            pstop: 0,
+            bnry: 0,
+            curr: 0,
+            tpsr: 0,
+            tbcr: 0,
+            rsar: 0,
+            rbcr: 0,
+            clda: 0,
+            par: [0; ETHERNET_ADDR_LEN],
+            mar: [0; 8],
+            tally: [0; 3],
+        };
+        nic.reset();
+        log::debug!(
+            "{} using {} backend, MAC {}",
+            nic.model_name(),
+            nic.backend.name(),
+            format_mac(&nic.mac)
+        );
+        nic
+    }
+
+    pub fn reset(&mut self) {
+        log::trace!("Resetting {}...", self.model_name());
+        self.cr = CR_STP | CR_RD_ABORT;
+        self.isr = ISR_RST;
+        self.imr = 0;
+        self.dcr = 0;
+        self.tcr = 0;
+        self.rcr = 0;
+        self.rbcr = 0;
+        self.rx_queue.clear();
+    }
+
+    pub fn model_name(&self) -> &'static str {
+        match self.model {
+            NicModel::Ne1000 => "NE1000",
+            NicModel::Ne2000 => "NE2000",
+        }
+    }
+
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    pub fn stats(&self) -> &NicStats {
+        &self.stats
+    }
+
+    pub fn tap(&self) -> &NetworkTap {
+        &self.tap
+    }
+
+    pub fn tap_mut(&mut self) -> &mut NetworkTap {
+        &mut self.tap
+    }
+
+    /// Service the network backend and update the interrupt line. Returns Some(state) if the
+    /// state of the interrupt line has changed.
+    pub fn run(&mut self, us: f64) -> Option<bool> {
+        self.poll_accum += us;
+        if self.poll_accum >= POLL_INTERVAL_US {
+            self.backend.poll(self.poll_accum);
+            self.poll_accum = 0.0;
+
+            while let Some(frame) = self.backend.recv() {
+                self.queue_frame(frame);
+            }
+        }
+
+        for frame in self.tap.run(us) {
+            self.queue_frame(frame);
+        }
+
+        // Deliver queued frames into the receive ring as space allows.
+        while self.cr & CR_STA != 0 {
+            match self.rx_queue.pop_front() {
+                Some(frame) => {
+                    if self.accept_frame(&frame) && !self.receive(&frame) {
+                        // No room in the ring. Try again once the driver has emptied it.
+                        self.rx_queue.push_front(frame);
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        self.update_intr()
+    }
+
+    fn queue_frame(&mut self, frame: Vec<u8>) {
+        self.tap.capture(&frame);
+        if self.rx_queue.len() >= RX_QUEUE_LEN {
+            self.rx_queue.pop_front();
+            self.stats.rx_dropped += 1;
+        }
+        self.rx_queue.push_back(frame);
+    }
+
+    fn update_intr(&mut self) -> Option<bool> {
+        let intr = self.isr & self.imr & 0x7F != 0;
+        if intr != self.intr {
+            self.intr = intr;
+            return Some(intr);
+        }
+        None
+    }
+
+    fn page(&self) -> u8 {
+        (self.cr & CR_PS_MASK) >> 6
+    }
+
+    fn read_register(&mut self, reg: u8) -> u8 {
+        if reg == 0 {
+            return self.cr;
+        }
+        match (self.page(), reg) {
+            (0, 0x01) => self.clda as u8,
+            (0, 0x02) => (self.clda >> 8) as u8,
+            (0, 0x03) => self.bnry,
+            (0, 0x04) => self.tsr,
+            (0, 0x05) => 0, // NCR: there are no collisions.
+            (0, 0x06) => 0, // FIFO
+            (0, 0x07) => self.isr,
+            (0, 0x08) => self.rsar as u8,
+            (0, 0x09) => (self.rsar >> 8) as u8,
+            (0, 0x0C) => self.rsr,
+            (0, reg @ 0x0D..=0x0F) => {
+                // Tally counters are cleared when read.
+                let i = (reg - 0x0D) as usize;
+                std::mem::take(&mut self.tally[i])
+            }
+            (1, reg @ 0x01..=0x06) => self.par[(reg - 1) as usize],
+            (1, 0x07) => self.curr,
+            (1, reg @ 0x08..=0x0F) => self.mar[(reg - 8) as usize],
+            (2, 0x01) => self.pstart,
+            (2, 0x02) => self.pstop,
+            (2, 0x04) => self.tpsr,
+            (2, 0x0C) => self.rcr,
+            (2, 0x0D) => self.tcr,
+            (2, 0x0E) => self.dcr,
+            (2, 0x0F) => self.imr,
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_register(&mut self, reg: u8, data: u8) {
+        if reg == 0 {
+            self.write_command(data);
+            return;
+        }
+        match (self.page(), reg) {
+            (0, 0x01) => self.pstart = data,
+            (0, 0x02) => self.pstop = data,
+            (0, 0x03) => self.bnry = data,
+            (0, 0x04) => self.tpsr = data,
+            (0, 0x05) => self.tbcr = (self.tbcr & 0xFF00) | data as u16,
+            (0, 0x06) => self.tbcr = (self.tbcr & 0x00FF) | (data as u16) << 8,
+            (0, 0x07) => {
+                // Writing a 1 to a status bit acknowledges it. The reset bit is not affected.
+                self.isr &= !(data & !ISR_RST);
+            }
+            (0, 0x08) => self.rsar = (self.rsar & 0xFF00) | data as u16,
+            (0, 0x09) => self.rsar = (self.rsar & 0x00FF) | (data as u16) << 8,
+            (0, 0x0A) => self.rbcr = (self.rbcr & 0xFF00) | data as u16,
+            (0, 0x0B) => self.rbcr = (self.rbcr & 0x00FF) | (data as u16) << 8,
+            (0, 0x0C) => self.rcr = data,
+            (0, 0x0D) => self.tcr = data,
+            (0, 0x0E) => self.dcr = data,
+            (0, 0x0F) => self.imr = data,
+            (1, reg @ 0x01..=0x06) => self.par[(reg - 1) as usize] = data,
+            (1, 0x07) => self.curr = data,
+            (1, reg @ 0x08..=0x0F) => self.mar[(reg - 8) as usize] = data,
+            _ => {
+                log::trace!(
+                    "{}: Write to unhandled register {:X}h, page {}: {:02X}",
+                    self.model_name(),
+                    reg,
+                    self.page(),
+                    data
+                );
+            }
+        }
+    }
+
+    fn write_command(&mut self, data: u8) {
+        // STP and STA are mutually exclusive; STP takes precedence.
+        if data & CR_STP != 0 {
+            self.cr = (data & !CR_STA) | CR_STP;
+        }
+        else if data & CR_STA != 0 {
+            self.cr = (data & !CR_STP) | CR_STA;
+            // Leaving the stopped state clears the reset status.
+            self.isr &= !ISR_RST;
+        }
+        else {
+            self.cr = (data & !(CR_STA | CR_STP)) | (self.cr & (CR_STA | CR_STP));
+        }
+
+        if data & CR_RD_MASK == CR_RD_SEND {
+            // Send Packet reads a frame out of the ring for the host. Set the remote DMA up
+            // to read the next frame, starting at the boundary pointer.
+            self.rsar = (self.bnry as u16) << 8;
+            let header =
+                self.mem_read(self.rsar as usize + 2) as u16 | (self.mem_read(self.rsar as usize + 3) as u16) << 8;
+            self.rbcr = header;
+        }
+        else if data & CR_RD_MASK == CR_RD_READ || data & CR_RD_MASK == CR_RD_WRITE {
+            if self.rbcr == 0 {
+                self.isr |= ISR_RDC;
+            }
+        }
+
+        if data & CR_TXP != 0 && self.cr & CR_STA != 0 {
+            self.transmit();
+        }
+    }
+
+    fn remote_active(&self) -> bool {
+        matches!(self.cr & CR_RD_MASK, CR_RD_READ | CR_RD_WRITE | CR_RD_SEND)
+    }
+
+    /// Advance the remote DMA address, wrapping at the end of the receive ring.
+    fn remote_advance(&mut self) {
+        self.rsar = self.rsar.wrapping_add(1);
+        if self.pstop > self.pstart && self.rsar == (self.pstop as u16) << 8 {
+            self.rsar = (self.pstart as u16) << 8;
+        }
+        self.rbcr = self.rbcr.wrapping_sub(1);
+        if self.rbcr == 0 {
+            self.cr = (self.cr & !CR_RD_MASK) | CR_RD_ABORT;
+            self.isr |= ISR_RDC;
+        }
+    }
+
+    fn remote_read(&mut self) -> u8 {
+        if !self.remote_active() || self.rbcr == 0 {
+            return NO_IO_BYTE;
+        }
+        let byte = self.mem_read(self.rsar as usize);
+        self.remote_advance();
+        byte
+    }
+
+    fn remote_write(&mut self, data: u8) {
+        if !self.remote_active() || self.rbcr == 0 {
+            return;
+        }
+        self.mem_write(self.rsar as usize, data);
+        self.remote_advance();
+    }
+
+    fn mem_read(&self, addr: usize) -> u8 {
+        let start = self.model.mem_start();
+        if addr < PROM_SIZE {
+            self.prom[addr]
+        }
+        else if addr >= start && addr < start + self.mem.len() {
+            self.mem[addr - start]
+        }
+        else {
+            NO_IO_BYTE
+        }
+    }
+
+    fn mem_write(&mut self, addr: usize, data: u8) {
+        let start = self.model.mem_start();
+        if addr >= start && addr < start + self.mem.len() {
+            self.mem[addr - start] = data;
+        }
+    }
+
+    fn transmit(&mut self) {
+        let start = (self.tpsr as usize) << 8;
+        let len = (self.tbcr as usize).min(ETHERNET_MAX_FRAME);
+        let frame: Vec<u8> = (start..start + len).map(|addr| self.mem_read(addr)).collect();
+
+        log::trace!("{}: Transmitting {} byte frame", self.model_name(), frame.len());
+        self.stats.tx_frames += 1;
+        self.tap.capture(&frame);
+
+        if self.tcr & TCR_LB_MASK != 0 {
+            // In loopback mode the frame is received by the card instead of going out on the wire.
+            if self.accept_frame(&frame) {
+                self.receive(&frame);
+            }
+        }
+        else {
+            self.backend.send(&frame);
+        }
+
+        self.cr &= !CR_TXP;
+        self.tsr = TSR_PTX;
+        self.isr |= ISR_PTX;
+    }
+
+    /// Apply the receive address filter to a frame.
+    fn accept_frame(&self, frame: &[u8]) -> bool {
+        if frame.len() < ETHERNET_ADDR_LEN || self.rcr & RCR_MON != 0 {
+            return false;
+        }
+        if self.rcr & RCR_PRO != 0 {
+            return true;
+        }
+        let dest = &frame[..ETHERNET_ADDR_LEN];
+        if dest == BROADCAST_MAC {
+            return self.rcr & RCR_AB != 0;
+        }
+        if dest[0] & 0x01 != 0 {
+            if self.rcr & RCR_AM == 0 {
+                return false;
+            }
+            let bit = (ethernet_crc(dest) >> 26) as usize;
+            return self.mar[bit >> 3] & (1 << (bit & 7)) != 0;
+        }
+        dest == self.par
+    }
+
+    /// Copy a frame into the receive ring. Returns false if there was insufficient space, in which
+    /// case the frame should be offered again later. If the ring pointers are not set up sensibly,
+    /// or the frame could never fit in the ring, the frame is dropped.
+    fn receive(&mut self, frame: &[u8]) -> bool {
+        // The ring pointers are programmed by the guest, so don't trust them.
+        let pstart = self.pstart as usize;
+        let pstop = self.pstop as usize;
+        let curr = self.curr as usize;
+        let bnry = self.bnry as usize;
+        let in_ring = |page: usize| page >= pstart && page < pstop;
+        if pstop <= pstart || !in_ring(curr) || !in_ring(bnry) {
+            if self.isr & ISR_OVW == 0 {
+                log::warn!(
+                    "{}: Bad receive ring (PSTART: {:02X} PSTOP: {:02X} BNRY: {:02X} CURR: {:02X}), dropping frame",
+                    self.model_name(),
+                    self.pstart,
+                    self.pstop,
+                    self.bnry,
+                    self.curr
+                );
+            }
+            self.drop_frame();
+            return true;
+        }
+
+        // Short frames are padded out to the minimum Ethernet frame size.
+        let frame_len = frame.len().max(ETHERNET_MIN_FRAME);
+        let padding = frame_len - frame.len();
+        let total = frame_len + 4;
+        let pages_needed = (total + PAGE_SIZE - 1) / PAGE_SIZE;
+        let ring_pages = pstop - pstart;
+        if pages_needed >= ring_pages {
+            if self.isr & ISR_OVW == 0 {
+                log::warn!("{}: {} byte frame can't fit in receive ring", self.model_name(), total);
+            }
+            self.drop_frame();
+            return true;
+        }
+
+        // The ring is full if writing the frame would overrun the boundary pointer.
+        let used = (curr + ring_pages - bnry) % ring_pages;
+        let free = ring_pages - used;
+        if pages_needed >= free {
+            if self.isr & ISR_OVW == 0 {
+                log::trace!("{}: Receive ring full", self.model_name());
+            }
+            self.isr |= ISR_OVW;
+            return false;
+        }
+
+        let mut next = curr + pages_needed;
+        if next >= pstop {
+            next -= ring_pages;
+        }
+        let next = next as u8;
+
+        let physical = frame[0] & 0x01 == 0;
+        let status = RSR_PRX | if physical { 0 } else { RSR_PHY };
+        // The byte count includes the header itself.
+        let count = total as u16;
+        let header = [status, next, count as u8, (count >> 8) as u8];
+
+        let mut addr = curr << 8;
+        let ring_end = pstop << 8;
+        for byte in header
+            .iter()
+            .chain(frame.iter())
+            .chain(std::iter::repeat(&0).take(padding))
+        {
+            self.mem_write(addr, *byte);
+            addr += 1;
+            if addr >= ring_end {
+                addr = pstart << 8;
+            }
+        }
+
+        self.curr = next;
+        self.rsr = status;
+        self.isr |= ISR_PRX;
+        self.stats.rx_frames += 1;
+        true
+    }
+
+    fn drop_frame(&mut self) {
+        self.isr |= ISR_OVW;
+        self.stats.rx_dropped += 1;
+    }
+}
+
+/// The state of the network backend, such as open host connections, is not saved. Frames waiting
+/// to be received are.
+impl SaveState for Ne2000 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.mem);
+        w.write_usize(self.rx_queue.len());
+        for frame in self.rx_queue.iter() {
+            w.write_bytes(frame);
+        }
+        w.write_f64(self.poll_accum);
+        w.write_bool(self.intr);
+
+        w.write_u8(self.cr);
+        w.write_u8(self.isr);
+        w.write_u8(self.imr);
+        w.write_u8(self.dcr);
+        w.write_u8(self.tcr);
+        w.write_u8(self.tsr);
+        w.write_u8(self.rcr);
+        w.write_u8(self.rsr);
+        w.write_u8(self.pstart);
+        w.write_u8(self.pstop);
+        w.write_u8(self.bnry);
+        w.write_u8(self.curr);
+        w.write_u8(self.tpsr);
+        w.write_u16(self.tbcr);
+        w.write_u16(self.rsar);
+        w.write_u16(self.rbcr);
+        w.write_u16(self.clda);
+        w.write_bytes(&self.par);
+        w.write_bytes(&self.mar);
+        w.write_bytes(&self.tally);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.read_bytes_into(&mut self.mem, "NIC buffer memory")?;
+        let frames = r.read_usize()?;
+        self.rx_queue.clear();
+        for _ in 0..frames {
+            self.rx_queue.push_back(r.read_bytes()?.to_vec());
+        }
+        self.poll_accum = r.read_f64()?;
+        self.intr = r.read_bool()?;
+
+        self.cr = r.read_u8()?;
+        self.isr = r.read_u8()?;
+        self.imr = r.read_u8()?;
+        self.dcr = r.read_u8()?;
+        self.tcr = r.read_u8()?;
+        self.tsr = r.read_u8()?;
+        self.rcr = r.read_u8()?;
+        self.rsr = r.read_u8()?;
+        self.pstart = r.read_u8()?;
+        self.pstop = r.read_u8()?;
+        self.bnry = r.read_u8()?;
+        self.curr = r.read_u8()?;
+        self.tpsr = r.read_u8()?;
+        self.tbcr = r.read_u16()?;
+        self.rsar = r.read_u16()?;
+        self.rbcr = r.read_u16()?;
+        self.clda = r.read_u16()?;
+        r.read_bytes_into(&mut self.par, "NIC physical address")?;
+        r.read_bytes_into(&mut self.mar, "NIC multicast address")?;
+        r.read_bytes_into(&mut self.tally, "NIC tally counters")?;
+        Ok(())
+    }
+}
+
+/// Calculate the Ethernet CRC-32 of the specified bytes, most significant bit first, as used
+/// by the DP8390 multicast address filter.
+fn ethernet_crc(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        let mut b = *byte;
+        for _ in 0..8 {
+            let carry = ((crc >> 31) as u8 ^ (b & 0x01)) != 0;
+            crc <<= 1;
+            b >>= 1;
+            if carry {
+                crc ^= 0x04C1_1DB7;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullBackend;
+
+    impl NetworkBackend for NullBackend {
+        fn name(&self) -> String {
+            String::from("null")
+        }
+        fn send(&mut self, _frame: &[u8]) {}
+        fn recv(&mut self) -> Option<Vec<u8>> {
+            None
+        }
+        fn poll(&mut self, _us: f64) {}
+    }
+
+    // A six page receive ring in the NE2000's buffer RAM, which starts at page 40h.
+    const RING_START: u8 = 0x46;
+    const RING_STOP: u8 = 0x4C;
+
+    fn ne2000_with_ring(bnry: u8, curr: u8) -> Ne2000 {
+        let mut nic = Ne2000::new(NicModel::Ne2000, None, None, None, Box::new(NullBackend));
+        nic.pstart = RING_START;
+        nic.pstop = RING_STOP;
+        nic.bnry = bnry;
+        nic.curr = curr;
+        nic
+    }
+
+    fn frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_receive_ring_full() {
+        let mut nic = ne2000_with_ring(RING_START, RING_START);
+
+        // Minimum size frames take a single page. One page must always be left free, so only
+        // five of them fit in the ring.
+        for i in 0..5 {
+            assert!(nic.receive(&frame(ETHERNET_MIN_FRAME)));
+            assert_eq!(nic.curr, RING_START + i + 1);
+        }
+        assert_eq!(nic.isr & ISR_OVW, 0);
+        assert!(!nic.receive(&frame(ETHERNET_MIN_FRAME)));
+        assert_eq!(nic.isr & ISR_OVW, ISR_OVW);
+        assert_eq!(nic.curr, RING_STOP - 1);
+        assert_eq!(nic.stats.rx_frames, 5);
+
+        // Once the driver advances the boundary pointer there is room again.
+        nic.isr = 0;
+        nic.bnry = RING_START + 1;
+        assert!(nic.receive(&frame(ETHERNET_MIN_FRAME)));
+        assert_eq!(nic.curr, RING_START);
+    }
+
+    #[test]
+    fn test_receive_ring_wrap() {
+        let mut nic = ne2000_with_ring(RING_STOP - 2, RING_STOP - 1);
+
+        // A 300 byte frame needs two pages, so it wraps from the last page of the ring to the first.
+        let data = frame(300);
+        assert!(nic.receive(&data));
+        assert_eq!(nic.curr, RING_START + 1);
+
+        let header = (RING_STOP as usize - 1) << 8;
+        assert_eq!(nic.mem_read(header), RSR_PRX);
+        assert_eq!(nic.mem_read(header + 1), RING_START + 1);
+        assert_eq!(nic.mem_read(header + 2), (304 & 0xFF) as u8);
+        assert_eq!(nic.mem_read(header + 3), (304 >> 8) as u8);
+        assert_eq!(nic.mem_read(header + 4), data[0]);
+        assert_eq!(nic.mem_read(header + PAGE_SIZE - 1), data[PAGE_SIZE - 5]);
+
+        // The rest of the frame continues at the start of the ring.
+        let wrapped = (RING_START as usize) << 8;
+        assert_eq!(nic.mem_read(wrapped), data[PAGE_SIZE - 4]);
+        assert_eq!(nic.mem_read(wrapped + 299 - (PAGE_SIZE - 4)), data[299]);
+    }
+
+    #[test]
+    fn test_receive_bad_ring() {
+        // PSTOP below PSTART.
+        let mut nic = ne2000_with_ring(RING_START, RING_START);
+        nic.pstart = RING_STOP;
+        nic.pstop = RING_START;
+        assert!(nic.receive(&frame(ETHERNET_MIN_FRAME)));
+        assert_eq!(nic.isr & ISR_OVW, ISR_OVW);
+        assert_eq!(nic.stats.rx_dropped, 1);
+        assert_eq!(nic.stats.rx_frames, 0);
+
+        // Boundary pointer outside the ring.
+        let mut nic = ne2000_with_ring(0x20, RING_START);
+        assert!(nic.receive(&frame(ETHERNET_MIN_FRAME)));
+        assert_eq!(nic.isr & ISR_OVW, ISR_OVW);
+        assert_eq!(nic.curr, RING_START);
+
+        // Current page pointer outside the ring, at the top of the page range.
+        let mut nic = ne2000_with_ring(RING_START, 0xFF);
+        assert!(nic.receive(&frame(ETHERNET_MIN_FRAME)));
+        assert_eq!(nic.curr, 0xFF);
+        assert_eq!(nic.stats.rx_dropped, 1);
+
+        // A ring too small to ever hold the frame.
+        let mut nic = ne2000_with_ring(RING_START, RING_START);
+        nic.pstop = RING_START + 1;
+        assert!(nic.receive(&frame(ETHERNET_MIN_FRAME)));
+        assert_eq!(nic.stats.rx_dropped, 1);
+    }
+}
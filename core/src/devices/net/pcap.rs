@@ -0,0 +1,281 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::net::pcap.rs
+
+    A capture tap for network devices.
+
+    The tap sits between a network card and its backend. Every frame the
+    guest transmits or receives can be written to a file in the classic
+    libpcap format, which can be opened directly in Wireshark or tcpdump.
+    The tap can also replay the frames of a capture file into the guest,
+    preserving the original inter-frame timing in emulated time.
+
+    Capture timestamps are the host time the capture was started, advanced
+    by emulated time. They are therefore consistent with the guest's view of
+    time even if the emulator is paused or running faster than real time.
+
+*/
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::devices::net::{MacAddress, ETHERNET_ADDR_LEN};
+
+const PCAP_MAGIC: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NS: u32 = 0xA1B2_3C4D;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 0xFFFF;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+/// A single frame read from a capture file.
+#[derive(Clone, Debug)]
+pub struct PcapRecord {
+    /// Timestamp in microseconds, relative to the first frame in the file.
+    pub time_us: f64,
+    pub data:    Vec<u8>,
+}
+
+/// Writes frames to a file in libpcap format.
+pub struct PcapWriter {
+    path:   PathBuf,
+    writer: BufWriter<File>,
+    frames: usize,
+}
+
+impl PcapWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Couldn't create capture file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        let mut header = Vec::with_capacity(PCAP_HEADER_LEN);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes()); // thiszone
+        header.extend_from_slice(&0u32.to_le_bytes()); // sigfigs
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+        writer.write_all(&header)?;
+        writer.flush()?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer,
+            frames: 0,
+        })
+    }
+
+    /// Write a frame with the specified timestamp, in microseconds since the Unix epoch.
+    pub fn write_frame(&mut self, frame: &[u8], timestamp_us: u64) -> Result<()> {
+        let len = frame.len().min(PCAP_SNAPLEN as usize);
+        let mut record = Vec::with_capacity(PCAP_RECORD_HEADER_LEN + len);
+        record.extend_from_slice(&((timestamp_us / 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&((timestamp_us % 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&(len as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&frame[..len]);
+        self.writer.write_all(&record)?;
+        // Flush every frame so the capture is usable while the emulator is still running.
+        self.writer.flush()?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+/// Read all Ethernet frames from a libpcap format file. Both byte orders and microsecond or
+/// nanosecond timestamps are supported.
+pub fn read_pcap(path: &Path) -> Result<Vec<PcapRecord>> {
+    let data = std::fs::read(path).with_context(|| format!("Couldn't read capture file {}", path.display()))?;
+    if data.len() < PCAP_HEADER_LEN {
+        bail!("File is too short to be a pcap file.");
+    }
+
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let (big_endian, nanos) = match magic {
+        PCAP_MAGIC => (false, false),
+        PCAP_MAGIC_NS => (false, true),
+        _ => match magic.swap_bytes() {
+            PCAP_MAGIC => (true, false),
+            PCAP_MAGIC_NS => (true, true),
+            _ => bail!("Not a pcap file. (pcapng files are not supported)"),
+        },
+    };
+    let read_u32 = |offset: usize| {
+        let bytes = [data[offset], data[offset + 1], data[offset + 2], data[offset + 3]];
+        match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    };
+
+    let linktype = read_u32(20);
+    if linktype != PCAP_LINKTYPE_ETHERNET {
+        bail!("Unsupported pcap link type: {}", linktype);
+    }
+
+    let mut records = Vec::new();
+    let mut first_us = None;
+    let mut pos = PCAP_HEADER_LEN;
+    while pos + PCAP_RECORD_HEADER_LEN <= data.len() {
+        let secs = read_u32(pos) as f64;
+        let frac = read_u32(pos + 4) as f64;
+        let incl_len = read_u32(pos + 8) as usize;
+        pos += PCAP_RECORD_HEADER_LEN;
+        if pos + incl_len > data.len() {
+            log::warn!("Capture file {} is truncated", path.display());
+            break;
+        }
+
+        let time_us = secs * 1_000_000.0 + if nanos { frac / 1000.0 } else { frac };
+        let first = *first_us.get_or_insert(time_us);
+        records.push(PcapRecord {
+            time_us: (time_us - first).max(0.0),
+            data:    data[pos..pos + incl_len].to_vec(),
+        });
+        pos += incl_len;
+    }
+    Ok(records)
+}
+
+/// Captures and replays the frames passing through a network device.
+pub struct NetworkTap {
+    elapsed_us: f64,
+    epoch_us: u64,
+    capture: Option<PcapWriter>,
+    replay: VecDeque<PcapRecord>,
+    replay_start_us: f64,
+}
+
+impl Default for NetworkTap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkTap {
+    pub fn new() -> Self {
+        Self {
+            elapsed_us: 0.0,
+            epoch_us: 0,
+            capture: None,
+            replay: VecDeque::new(),
+            replay_start_us: 0.0,
+        }
+    }
+
+    /// Begin writing all frames to the specified capture file, replacing any capture in progress.
+    pub fn start_capture(&mut self, path: &Path) -> Result<()> {
+        let writer = PcapWriter::create(path)?;
+        log::debug!("Starting network capture to {}", path.display());
+        self.epoch_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        self.elapsed_us = 0.0;
+        self.capture = Some(writer);
+        Ok(())
+    }
+
+    /// Stop capturing. Returns the path of the capture file and the number of frames written.
+    pub fn stop_capture(&mut self) -> Option<(PathBuf, usize)> {
+        let writer = self.capture.take()?;
+        log::debug!(
+            "Stopped network capture to {} after {} frames",
+            writer.path().display(),
+            writer.frames()
+        );
+        Some((writer.path().to_path_buf(), writer.frames()))
+    }
+
+    pub fn capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Queue the frames of a capture file for delivery to the guest. Frames sent from the
+    /// `exclude` address, usually the guest's own transmissions, are skipped. Returns the number
+    /// of frames queued.
+    pub fn start_replay(&mut self, path: &Path, exclude: Option<MacAddress>) -> Result<usize> {
+        let records = read_pcap(path)?;
+        self.replay = records
+            .into_iter()
+            .filter(|record| match exclude {
+                Some(mac) => record.data.get(ETHERNET_ADDR_LEN..ETHERNET_ADDR_LEN * 2) != Some(&mac[..]),
+                None => true,
+            })
+            .collect();
+        self.replay_start_us = self.elapsed_us;
+        log::debug!("Replaying {} frames from {}", self.replay.len(), path.display());
+        Ok(self.replay.len())
+    }
+
+    pub fn replaying(&self) -> bool {
+        !self.replay.is_empty()
+    }
+
+    /// Record a frame. Capture errors stop the capture rather than interrupting emulation.
+    pub fn capture(&mut self, frame: &[u8]) {
+        if let Some(writer) = &mut self.capture {
+            let timestamp = self.epoch_us + self.elapsed_us as u64;
+            if let Err(e) = writer.write_frame(frame, timestamp) {
+                log::error!("Network capture to {} failed: {}", writer.path().display(), e);
+                self.capture = None;
+            }
+        }
+    }
+
+    /// Advance the tap's clock. Returns any replayed frames that are now due for delivery.
+    pub fn run(&mut self, us: f64) -> Vec<Vec<u8>> {
+        self.elapsed_us += us;
+
+        let mut due = Vec::new();
+        while let Some(record) = self.replay.front() {
+            if self.replay_start_us + record.time_us > self.elapsed_us {
+                break;
+            }
+            due.push(self.replay.pop_front().unwrap().data);
+        }
+        due
+    }
+}
@@ -0,0 +1,1000 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::net::slirp.rs
+
+    A user-mode networking backend in the spirit of SLIRP.
+
+    The guest is placed on a private 10.0.2.0/24 network behind a virtual
+    gateway at 10.0.2.2, with a DNS server at 10.0.2.3. Guest traffic is
+    terminated here and re-originated from ordinary host sockets, so no
+    privileges or host network configuration are required.
+
+    The following services are provided:
+      - ARP replies for the virtual gateway and DNS server.
+      - A DHCP server handing out the address 10.0.2.15.
+      - DNS A record queries, answered with the host's resolver.
+      - ICMP echo replies from the gateway and DNS server.
+      - UDP and TCP connections to the outside world, translated to host
+        sockets. The gateway address reaches the host's loopback interface.
+
+    Host name lookups and TCP connection attempts can block, so they are
+    performed on short-lived worker threads.
+
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use crate::devices::net::{MacAddress, NetworkBackend, BROADCAST_MAC, ETHERNET_HEADER_LEN};
+
+pub const SLIRP_GATEWAY_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+pub const SLIRP_DNS_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 3);
+pub const SLIRP_GUEST_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const SLIRP_NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+const GATEWAY_MAC: MacAddress = [0x52, 0x55, 0x0A, 0x00, 0x02, 0x02];
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+
+const IP_PROTO_ICMP: u8 = 1;
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+const IP_HEADER_LEN: usize = 20;
+const IP_TTL: u8 = 64;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+const UDP_HEADER_LEN: usize = 8;
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_LEASE_SECS: u32 = 86400;
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+const DHCP_INFORM: u8 = 8;
+
+const DNS_PORT: u16 = 53;
+const DNS_TTL: u32 = 300;
+const DNS_RCODE_FORMERR: u16 = 1;
+const DNS_RCODE_NXDOMAIN: u16 = 3;
+
+const TCP_HEADER_LEN: usize = 20;
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_MSS: u16 = 1460;
+// The MSS assumed if the guest doesn't send the option.
+const TCP_DEFAULT_MSS: usize = 536;
+const TCP_MAX_WINDOW: usize = 0xFFFF;
+// Maximum amount of host data buffered per connection waiting to be sent to the guest.
+const TCP_SEND_BUFFER: usize = 0x10000;
+const TCP_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Compute the one's complement sum of the specified bytes, added to an initial sum.
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn checksum_finish(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Calculate a TCP or UDP checksum, including the IPv4 pseudo-header.
+fn transport_checksum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, segment: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.octets());
+    sum = checksum_add(sum, &dst.octets());
+    sum += proto as u32;
+    sum += segment.len() as u32;
+    checksum_finish(checksum_add(sum, segment))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn read_ip(data: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(data[offset], data[offset + 1], data[offset + 2], data[offset + 3])
+}
+
+/// Compare TCP sequence numbers, allowing for wraparound.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// Return true if the address is on the virtual network.
+fn is_virtual(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    octets[0..3] == SLIRP_GATEWAY_IP.octets()[0..3]
+}
+
+/// Translate an address as seen by the guest into the host address to connect to. The gateway
+/// is an alias for the host itself; other addresses on the virtual network don't exist.
+fn host_address(addr: SocketAddrV4) -> Option<SocketAddr> {
+    if *addr.ip() == SLIRP_GATEWAY_IP {
+        Some(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, addr.port())))
+    }
+    else if is_virtual(*addr.ip()) || addr.ip().is_broadcast() || addr.ip().is_multicast() {
+        None
+    }
+    else {
+        Some(SocketAddr::V4(addr))
+    }
+}
+
+/// Translate a host address back into the address the guest should see.
+fn guest_address(addr: SocketAddr) -> Option<SocketAddrV4> {
+    match addr {
+        SocketAddr::V4(v4) if v4.ip().is_loopback() => Some(SocketAddrV4::new(SLIRP_GATEWAY_IP, v4.port())),
+        SocketAddr::V4(v4) => Some(v4),
+        SocketAddr::V6(_) => None,
+    }
+}
+
+/// The virtual link between the gateway and the guest. Frames built here are queued for
+/// delivery to the network card.
+struct Link {
+    guest_mac: Option<MacAddress>,
+    guest_ip: Ipv4Addr,
+    ip_id: u16,
+    rx_queue: VecDeque<Vec<u8>>,
+}
+
+impl Link {
+    fn send_frame(&mut self, dest: MacAddress, ethertype: u16, payload: &[u8]) {
+        let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&dest);
+        frame.extend_from_slice(&GATEWAY_MAC);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.rx_queue.push_back(frame);
+    }
+
+    fn send_ip(&mut self, proto: u8, src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) {
+        let total_len = (IP_HEADER_LEN + payload.len()) as u16;
+        self.ip_id = self.ip_id.wrapping_add(1);
+
+        let mut packet = Vec::with_capacity(total_len as usize);
+        packet.push(0x45);
+        packet.push(0);
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&self.ip_id.to_be_bytes());
+        // Don't fragment.
+        packet.extend_from_slice(&0x4000u16.to_be_bytes());
+        packet.push(IP_TTL);
+        packet.push(proto);
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        let csum = checksum_finish(checksum_add(0, &packet));
+        packet[10..12].copy_from_slice(&csum.to_be_bytes());
+        packet.extend_from_slice(payload);
+
+        let dest = match self.guest_mac {
+            Some(mac) if !dst.is_broadcast() => mac,
+            _ => BROADCAST_MAC,
+        };
+        self.send_frame(dest, ETHERTYPE_IPV4, &packet);
+    }
+
+    fn send_udp(&mut self, src: SocketAddrV4, dst: SocketAddrV4, data: &[u8]) {
+        let len = (UDP_HEADER_LEN + data.len()) as u16;
+        let mut datagram = Vec::with_capacity(len as usize);
+        datagram.extend_from_slice(&src.port().to_be_bytes());
+        datagram.extend_from_slice(&dst.port().to_be_bytes());
+        datagram.extend_from_slice(&len.to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(data);
+        let csum = match transport_checksum(*src.ip(), *dst.ip(), IP_PROTO_UDP, &datagram) {
+            // A computed checksum of zero is transmitted as all ones.
+            0 => 0xFFFF,
+            csum => csum,
+        };
+        datagram[6..8].copy_from_slice(&csum.to_be_bytes());
+        self.send_ip(IP_PROTO_UDP, *src.ip(), *dst.ip(), &datagram);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_tcp(
+        &mut self,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        window: u16,
+        data: &[u8],
+    ) {
+        // Advertise our MSS on SYN segments.
+        let options: &[u8] = if flags & TCP_SYN != 0 {
+            &[2, 4, (TCP_MSS >> 8) as u8, TCP_MSS as u8]
+        }
+        else {
+            &[]
+        };
+        let header_len = TCP_HEADER_LEN + options.len();
+
+        let mut segment = Vec::with_capacity(header_len + data.len());
+        segment.extend_from_slice(&src.port().to_be_bytes());
+        segment.extend_from_slice(&dst.port().to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.push(((header_len / 4) as u8) << 4);
+        segment.push(flags);
+        segment.extend_from_slice(&window.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(options);
+        segment.extend_from_slice(data);
+        let csum = transport_checksum(*src.ip(), *dst.ip(), IP_PROTO_TCP, &segment);
+        segment[16..18].copy_from_slice(&csum.to_be_bytes());
+        self.send_ip(IP_PROTO_TCP, *src.ip(), *dst.ip(), &segment);
+    }
+}
+
+/// A parsed TCP segment received from the guest.
+struct TcpSegment<'a> {
+    seq:    u32,
+    ack:    u32,
+    flags:  u8,
+    window: u16,
+    mss:    Option<usize>,
+    data:   &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    fn parse(segment: &'a [u8]) -> Option<Self> {
+        if segment.len() < TCP_HEADER_LEN {
+            return None;
+        }
+        let data_offset = ((segment[12] >> 4) as usize) * 4;
+        if data_offset < TCP_HEADER_LEN || data_offset > segment.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut options = &segment[TCP_HEADER_LEN..data_offset];
+        while let [kind, rest @ ..] = options {
+            match *kind {
+                0 => break,
+                1 => options = rest,
+                _ => {
+                    let len = *rest.first()? as usize;
+                    if len < 2 || len > options.len() {
+                        break;
+                    }
+                    if *kind == 2 && len == 4 {
+                        mss = Some(read_u16(options, 2) as usize);
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+
+        Some(Self {
+            seq: read_u32(segment, 4),
+            ack: read_u32(segment, 8),
+            flags: segment[13],
+            window: read_u16(segment, 14),
+            mss,
+            data: &segment[data_offset..],
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TcpState {
+    // Waiting for the host connection attempt to complete.
+    Connecting,
+    // SYN-ACK sent to the guest, waiting for its ACK.
+    SynReceived,
+    Established,
+}
+
+/// A TCP connection from the guest, relayed through a host socket.
+struct TcpConnection {
+    state: TcpState,
+    guest: SocketAddrV4,
+    remote: SocketAddrV4,
+    stream: Option<TcpStream>,
+    connect_rx: Option<Receiver<std::io::Result<TcpStream>>>,
+
+    // Host to guest direction. send_buf holds data from snd_una onwards.
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    send_buf: VecDeque<u8>,
+    guest_window: usize,
+    guest_mss: usize,
+    host_eof: bool,
+    fin_sent: bool,
+    last_send: Instant,
+
+    // Guest to host direction.
+    rcv_nxt: u32,
+    to_host: Vec<u8>,
+    guest_fin: bool,
+    host_shutdown: bool,
+}
+
+impl TcpConnection {
+    fn new(guest: SocketAddrV4, remote: SocketAddrV4, host: SocketAddr, syn: &TcpSegment) -> Self {
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(TcpStream::connect_timeout(&host, TCP_CONNECT_TIMEOUT));
+        });
+
+        let iss = rand::random::<u32>();
+        Self {
+            state: TcpState::Connecting,
+            guest,
+            remote,
+            stream: None,
+            connect_rx: Some(rx),
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            send_buf: VecDeque::new(),
+            guest_window: syn.window as usize,
+            guest_mss: syn.mss.unwrap_or(TCP_DEFAULT_MSS).min(TCP_MSS as usize),
+            host_eof: false,
+            fin_sent: false,
+            last_send: Instant::now(),
+            rcv_nxt: syn.seq.wrapping_add(1),
+            to_host: Vec::new(),
+            guest_fin: false,
+            host_shutdown: false,
+        }
+    }
+
+    fn window(&self) -> u16 {
+        TCP_MAX_WINDOW.saturating_sub(self.to_host.len()) as u16
+    }
+
+    fn send(&mut self, link: &mut Link, seq: u32, flags: u8, data: &[u8]) {
+        link.send_tcp(self.remote, self.guest, seq, self.rcv_nxt, flags, self.window(), data);
+    }
+
+    fn send_ack(&mut self, link: &mut Link) {
+        self.send(link, self.snd_nxt, TCP_ACK, &[]);
+    }
+
+    fn send_reset(&mut self, link: &mut Link) {
+        self.send(link, self.snd_nxt, TCP_RST | TCP_ACK, &[]);
+    }
+
+    /// Process a segment from the guest. Returns false if the connection should be removed.
+    fn guest_segment(&mut self, link: &mut Link, seg: &TcpSegment) -> bool {
+        if seg.flags & TCP_RST != 0 {
+            log::trace!("SLIRP: TCP {} -> {} reset by guest", self.guest, self.remote);
+            return false;
+        }
+        if seg.flags & TCP_SYN != 0 {
+            // A retransmitted SYN. Answer it again if we've already responded.
+            if self.state == TcpState::SynReceived {
+                self.send(link, self.iss, TCP_SYN | TCP_ACK, &[]);
+            }
+            return true;
+        }
+        if self.state == TcpState::Connecting {
+            return true;
+        }
+
+        if seg.flags & TCP_ACK != 0 && seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            if self.state == TcpState::SynReceived {
+                // The SYN occupies the first sequence number.
+                self.state = TcpState::Established;
+                self.snd_una = self.snd_una.wrapping_add(1);
+            }
+            let acked = (seg.ack.wrapping_sub(self.snd_una) as usize).min(self.send_buf.len());
+            self.send_buf.drain(..acked);
+            self.snd_una = seg.ack;
+            self.last_send = Instant::now();
+        }
+        if self.state != TcpState::Established {
+            return true;
+        }
+        self.guest_window = seg.window as usize;
+
+        let mut need_ack = false;
+        if !seg.data.is_empty() || seg.flags & TCP_FIN != 0 {
+            if seg.seq == self.rcv_nxt && !self.guest_fin {
+                self.to_host.extend_from_slice(seg.data);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(seg.data.len() as u32);
+                if seg.flags & TCP_FIN != 0 {
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                    self.guest_fin = true;
+                }
+            }
+            // Out of order or duplicate segments are acknowledged with the sequence we expect.
+            need_ack = true;
+        }
+
+        if !self.flush_to_host() {
+            self.send_reset(link);
+            return false;
+        }
+        if need_ack {
+            self.send_ack(link);
+        }
+        true
+    }
+
+    /// Write pending guest data to the host socket. Returns false if the host connection has failed.
+    fn flush_to_host(&mut self) -> bool {
+        if let Some(stream) = &mut self.stream {
+            while !self.to_host.is_empty() {
+                match stream.write(&self.to_host) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        self.to_host.drain(..n);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => break,
+                    Err(e) => {
+                        log::debug!("SLIRP: TCP {} -> {} write error: {}", self.guest, self.remote, e);
+                        return false;
+                    }
+                }
+            }
+            if self.guest_fin && self.to_host.is_empty() && !self.host_shutdown {
+                let _ = stream.shutdown(Shutdown::Write);
+                self.host_shutdown = true;
+            }
+        }
+        true
+    }
+
+    /// Service the host side of the connection. Returns false if the connection should be removed.
+    fn poll(&mut self, link: &mut Link) -> bool {
+        if let Some(rx) = &self.connect_rx {
+            match rx.try_recv() {
+                Ok(Ok(stream)) => {
+                    log::debug!("SLIRP: TCP {} -> {} connected", self.guest, self.remote);
+                    let _ = stream.set_nonblocking(true);
+                    let _ = stream.set_nodelay(true);
+                    self.stream = Some(stream);
+                    self.connect_rx = None;
+                    self.state = TcpState::SynReceived;
+                    self.send(link, self.iss, TCP_SYN | TCP_ACK, &[]);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    self.last_send = Instant::now();
+                }
+                Ok(Err(e)) => {
+                    log::debug!("SLIRP: TCP {} -> {} connect failed: {}", self.guest, self.remote, e);
+                    self.send(link, 0, TCP_RST | TCP_ACK, &[]);
+                    return false;
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return false,
+            }
+            return true;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if self.last_send.elapsed() >= TCP_RETRANSMIT_TIMEOUT {
+                self.send(link, self.iss, TCP_SYN | TCP_ACK, &[]);
+                self.last_send = Instant::now();
+            }
+            return true;
+        }
+
+        if !self.flush_to_host() {
+            self.send_reset(link);
+            return false;
+        }
+
+        // Read more data from the host if we have room to buffer it.
+        if !self.host_eof && self.send_buf.len() < TCP_SEND_BUFFER {
+            if let Some(stream) = &mut self.stream {
+                let mut buf = [0u8; 4096];
+                let max = buf.len().min(TCP_SEND_BUFFER - self.send_buf.len());
+                match stream.read(&mut buf[..max]) {
+                    Ok(0) => self.host_eof = true,
+                    Ok(n) => self.send_buf.extend(&buf[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        log::debug!("SLIRP: TCP {} -> {} error: {}", self.guest, self.remote, e);
+                        self.send_reset(link);
+                        return false;
+                    }
+                }
+            }
+        }
+
+        // Go back and resend everything unacknowledged if the guest has gone quiet.
+        if self.snd_nxt != self.snd_una && self.last_send.elapsed() >= TCP_RETRANSMIT_TIMEOUT {
+            self.snd_nxt = self.snd_una;
+            self.fin_sent = false;
+        }
+
+        // Send as much buffered data as the guest's window allows.
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buf.len().saturating_sub(in_flight);
+            let window = self.guest_window.saturating_sub(in_flight);
+            let len = unsent.min(window).min(self.guest_mss);
+            if len == 0 {
+                break;
+            }
+            let data: Vec<u8> = self.send_buf.range(in_flight..in_flight + len).copied().collect();
+            self.send(link, self.snd_nxt, TCP_PSH | TCP_ACK, &data);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            self.last_send = Instant::now();
+        }
+
+        // Once the host has closed its side and everything has been sent, send our FIN.
+        let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.send_buf.len();
+        if self.host_eof && all_sent && !self.fin_sent {
+            self.send(link, self.snd_nxt, TCP_FIN | TCP_ACK, &[]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.last_send = Instant::now();
+        }
+
+        // The connection is finished once both FINs have been sent and acknowledged.
+        let fin_acked = self.fin_sent && self.snd_una == self.snd_nxt;
+        !(fin_acked && self.guest_fin && self.to_host.is_empty())
+    }
+}
+
+/// A UDP socket relaying datagrams for a single guest port.
+struct UdpBinding {
+    socket:    UdpSocket,
+    last_used: Instant,
+}
+
+pub struct SlirpBackend {
+    link:   Link,
+    udp:    HashMap<u16, UdpBinding>,
+    tcp:    HashMap<(u16, SocketAddrV4), TcpConnection>,
+    dns_tx: Sender<(u16, Vec<u8>)>,
+    dns_rx: Receiver<(u16, Vec<u8>)>,
+}
+
+impl Default for SlirpBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkBackend for SlirpBackend {
+    fn name(&self) -> String {
+        "SLIRP".to_string()
+    }
+
+    fn send(&mut self, frame: &[u8]) {
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return;
+        }
+        let mut src_mac = [0u8; 6];
+        src_mac.copy_from_slice(&frame[6..12]);
+        self.link.guest_mac = Some(src_mac);
+
+        let payload = &frame[ETHERNET_HEADER_LEN..];
+        match read_u16(frame, 12) {
+            ETHERTYPE_ARP => self.handle_arp(payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(payload),
+            _ => {}
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.link.rx_queue.pop_front()
+    }
+
+    fn poll(&mut self, _us: f64) {
+        // Completed DNS lookups
+        while let Ok((guest_port, response)) = self.dns_rx.try_recv() {
+            let guest = SocketAddrV4::new(self.link.guest_ip, guest_port);
+            self.link
+                .send_udp(SocketAddrV4::new(SLIRP_DNS_IP, DNS_PORT), guest, &response);
+        }
+
+        // Incoming UDP datagrams
+        let link = &mut self.link;
+        self.udp.retain(|guest_port, binding| {
+            let mut buf = [0u8; 2048];
+            while let Ok((n, from)) = binding.socket.recv_from(&mut buf) {
+                if let Some(from) = guest_address(from) {
+                    let guest = SocketAddrV4::new(link.guest_ip, *guest_port);
+                    link.send_udp(from, guest, &buf[..n]);
+                    binding.last_used = Instant::now();
+                }
+            }
+            binding.last_used.elapsed() < UDP_IDLE_TIMEOUT
+        });
+
+        // TCP connections
+        self.tcp.retain(|_, conn| conn.poll(link));
+    }
+}
+
+impl SlirpBackend {
+    pub fn new() -> Self {
+        let (dns_tx, dns_rx) = channel();
+        Self {
+            link: Link {
+                guest_mac: None,
+                guest_ip: SLIRP_GUEST_IP,
+                ip_id: 0,
+                rx_queue: VecDeque::new(),
+            },
+            udp: HashMap::new(),
+            tcp: HashMap::new(),
+            dns_tx,
+            dns_rx,
+        }
+    }
+
+    fn handle_arp(&mut self, arp: &[u8]) {
+        // Only Ethernet/IPv4 requests are of interest.
+        if arp.len() < 28 || read_u16(arp, 0) != 1 || read_u16(arp, 2) != ETHERTYPE_IPV4 || read_u16(arp, 6) != 1 {
+            return;
+        }
+        let sender_mac = &arp[8..14];
+        let sender_ip = &arp[14..18];
+        let target_ip = read_ip(arp, 24);
+        if target_ip != SLIRP_GATEWAY_IP && target_ip != SLIRP_DNS_IP {
+            return;
+        }
+
+        let mut reply = Vec::with_capacity(28);
+        reply.extend_from_slice(&arp[0..6]);
+        reply.extend_from_slice(&2u16.to_be_bytes());
+        reply.extend_from_slice(&GATEWAY_MAC);
+        reply.extend_from_slice(&target_ip.octets());
+        reply.extend_from_slice(sender_mac);
+        reply.extend_from_slice(sender_ip);
+
+        let mut dest = [0u8; 6];
+        dest.copy_from_slice(sender_mac);
+        self.link.send_frame(dest, ETHERTYPE_ARP, &reply);
+    }
+
+    fn handle_ipv4(&mut self, packet: &[u8]) {
+        if packet.len() < IP_HEADER_LEN || packet[0] >> 4 != 4 {
+            return;
+        }
+        let header_len = ((packet[0] & 0x0F) as usize) * 4;
+        let total_len = (read_u16(packet, 2) as usize).min(packet.len());
+        if header_len < IP_HEADER_LEN || total_len < header_len {
+            return;
+        }
+        if read_u16(packet, 6) & 0x3FFF != 0 {
+            log::trace!("SLIRP: Dropping IP fragment");
+            return;
+        }
+
+        let proto = packet[9];
+        let src = read_ip(packet, 12);
+        let dst = read_ip(packet, 16);
+        let payload = &packet[header_len..total_len];
+
+        if !src.is_unspecified() {
+            self.link.guest_ip = src;
+        }
+
+        match proto {
+            IP_PROTO_ICMP => self.handle_icmp(src, dst, payload),
+            IP_PROTO_UDP => self.handle_udp(dst, payload),
+            IP_PROTO_TCP => self.handle_tcp(src, dst, payload),
+            _ => log::trace!("SLIRP: Unsupported IP protocol {}", proto),
+        }
+    }
+
+    fn handle_icmp(&mut self, src: Ipv4Addr, dst: Ipv4Addr, icmp: &[u8]) {
+        if icmp.len() < 8 || icmp[0] != ICMP_ECHO_REQUEST {
+            return;
+        }
+        // Pinging external hosts would require raw sockets, so only the virtual hosts answer.
+        if dst != SLIRP_GATEWAY_IP && dst != SLIRP_DNS_IP {
+            log::trace!("SLIRP: Can't forward ICMP echo request to {}", dst);
+            return;
+        }
+        let mut reply = icmp.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        reply[2..4].fill(0);
+        let csum = checksum_finish(checksum_add(0, &reply));
+        reply[2..4].copy_from_slice(&csum.to_be_bytes());
+        self.link.send_ip(IP_PROTO_ICMP, dst, src, &reply);
+    }
+
+    fn handle_udp(&mut self, dst: Ipv4Addr, udp: &[u8]) {
+        if udp.len() < UDP_HEADER_LEN {
+            return;
+        }
+        let src_port = read_u16(udp, 0);
+        let dst_port = read_u16(udp, 2);
+        let len = (read_u16(udp, 4) as usize).clamp(UDP_HEADER_LEN, udp.len());
+        let data = &udp[UDP_HEADER_LEN..len];
+
+        if dst_port == DHCP_SERVER_PORT {
+            self.handle_dhcp(data);
+        }
+        else if dst == SLIRP_DNS_IP && dst_port == DNS_PORT {
+            self.handle_dns(src_port, data);
+        }
+        else if let Some(host) = host_address(SocketAddrV4::new(dst, dst_port)) {
+            let binding = match self.udp.entry(src_port) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    let socket = match UdpSocket::bind("0.0.0.0:0") {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            log::warn!("SLIRP: Failed to create UDP socket: {}", e);
+                            return;
+                        }
+                    };
+                    let _ = socket.set_nonblocking(true);
+                    entry.insert(UdpBinding {
+                        socket,
+                        last_used: Instant::now(),
+                    })
+                }
+            };
+            if let Err(e) = binding.socket.send_to(data, host) {
+                log::debug!("SLIRP: UDP send to {} failed: {}", host, e);
+            }
+            binding.last_used = Instant::now();
+        }
+    }
+
+    fn handle_dhcp(&mut self, request: &[u8]) {
+        if request.len() < 240 || request[0] != 1 || request[236..240] != DHCP_MAGIC_COOKIE {
+            return;
+        }
+
+        let mut msg_type = None;
+        let mut requested_ip = None;
+        let mut options = &request[240..];
+        while let [code, rest @ ..] = options {
+            match *code {
+                0 => options = rest,
+                255 => break,
+                _ => {
+                    let len = match rest.first() {
+                        Some(len) if (*len as usize) < rest.len() => *len as usize,
+                        _ => break,
+                    };
+                    let value = &rest[1..1 + len];
+                    match *code {
+                        53 if len == 1 => msg_type = Some(value[0]),
+                        50 if len == 4 => requested_ip = Some(read_ip(value, 0)),
+                        _ => {}
+                    }
+                    options = &rest[1 + len..];
+                }
+            }
+        }
+
+        let reply_type = match msg_type {
+            Some(DHCP_DISCOVER) => DHCP_OFFER,
+            Some(DHCP_REQUEST) if requested_ip.map_or(true, |ip| ip == SLIRP_GUEST_IP) => DHCP_ACK,
+            Some(DHCP_REQUEST) => DHCP_NAK,
+            Some(DHCP_INFORM) => DHCP_ACK,
+            _ => return,
+        };
+        log::debug!("SLIRP: DHCP request type {:?}, replying with {}", msg_type, reply_type);
+
+        let mut reply = vec![0u8; 240];
+        reply[0] = 2; // BOOTREPLY
+        reply[1] = 1; // Ethernet
+        reply[2] = 6;
+        reply[4..8].copy_from_slice(&request[4..8]); // xid
+        reply[10..12].copy_from_slice(&request[10..12]); // flags
+        if reply_type != DHCP_NAK && msg_type != Some(DHCP_INFORM) {
+            reply[16..20].copy_from_slice(&SLIRP_GUEST_IP.octets());
+        }
+        reply[20..24].copy_from_slice(&SLIRP_GATEWAY_IP.octets());
+        reply[28..44].copy_from_slice(&request[28..44]); // chaddr
+        reply[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+        reply.extend_from_slice(&[53, 1, reply_type]);
+        reply.extend_from_slice(&[54, 4]);
+        reply.extend_from_slice(&SLIRP_GATEWAY_IP.octets());
+        if reply_type != DHCP_NAK {
+            reply.extend_from_slice(&[51, 4]);
+            reply.extend_from_slice(&DHCP_LEASE_SECS.to_be_bytes());
+            reply.extend_from_slice(&[1, 4]);
+            reply.extend_from_slice(&SLIRP_NETMASK.octets());
+            reply.extend_from_slice(&[3, 4]);
+            reply.extend_from_slice(&SLIRP_GATEWAY_IP.octets());
+            reply.extend_from_slice(&[6, 4]);
+            reply.extend_from_slice(&SLIRP_DNS_IP.octets());
+        }
+        reply.push(255);
+        // BOOTP messages are a minimum of 300 bytes.
+        if reply.len() < 300 {
+            reply.resize(300, 0);
+        }
+
+        self.link.send_udp(
+            SocketAddrV4::new(SLIRP_GATEWAY_IP, DHCP_SERVER_PORT),
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT),
+            &reply,
+        );
+    }
+
+    fn handle_dns(&mut self, guest_port: u16, query: &[u8]) {
+        if query.len() < 12 || query[2] & 0x80 != 0 {
+            return;
+        }
+
+        // Parse the question. Only a single uncompressed question is supported.
+        let mut labels = Vec::new();
+        let mut pos = 12;
+        let mut valid = read_u16(query, 4) == 1;
+        while valid {
+            match query.get(pos) {
+                Some(0) => {
+                    pos += 1;
+                    break;
+                }
+                Some(len) if *len < 64 && pos + 1 + (*len as usize) < query.len() => {
+                    let len = *len as usize;
+                    labels.push(String::from_utf8_lossy(&query[pos + 1..pos + 1 + len]).to_string());
+                    pos += 1 + len;
+                }
+                _ => valid = false,
+            }
+        }
+        if pos + 4 > query.len() {
+            valid = false;
+        }
+
+        let mut response = query[..2].to_vec();
+        if !valid {
+            response.extend_from_slice(&(0x8180 | DNS_RCODE_FORMERR).to_be_bytes());
+            response.extend_from_slice(&[0; 8]);
+            self.link.send_udp(
+                SocketAddrV4::new(SLIRP_DNS_IP, DNS_PORT),
+                SocketAddrV4::new(self.link.guest_ip, guest_port),
+                &response,
+            );
+            return;
+        }
+
+        let question = query[12..pos + 4].to_vec();
+        let qtype = read_u16(query, pos);
+        let qclass = read_u16(query, pos + 2);
+        let name = labels.join(".");
+        let tx = self.dns_tx.clone();
+
+        log::debug!("SLIRP: DNS query for {} (type {})", name, qtype);
+        std::thread::spawn(move || {
+            let mut rcode = 0;
+            let mut answers = Vec::new();
+            // Only A records can be answered using the host's resolver.
+            if qtype == 1 && qclass == 1 {
+                match (name.as_str(), 0).to_socket_addrs() {
+                    Ok(addrs) => {
+                        for addr in addrs {
+                            if let SocketAddr::V4(v4) = addr {
+                                if !answers.contains(v4.ip()) {
+                                    answers.push(*v4.ip());
+                                }
+                            }
+                        }
+                    }
+                    Err(_) => rcode = DNS_RCODE_NXDOMAIN,
+                }
+            }
+
+            response.extend_from_slice(&(0x8180 | rcode).to_be_bytes());
+            response.extend_from_slice(&1u16.to_be_bytes());
+            response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+            response.extend_from_slice(&[0; 4]);
+            response.extend_from_slice(&question);
+            for ip in answers {
+                // A pointer to the name in the question
+                response.extend_from_slice(&[0xC0, 0x0C]);
+                response.extend_from_slice(&1u16.to_be_bytes());
+                response.extend_from_slice(&1u16.to_be_bytes());
+                response.extend_from_slice(&DNS_TTL.to_be_bytes());
+                response.extend_from_slice(&4u16.to_be_bytes());
+                response.extend_from_slice(&ip.octets());
+            }
+            let _ = tx.send((guest_port, response));
+        });
+    }
+
+    fn handle_tcp(&mut self, src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+        let seg = match TcpSegment::parse(segment) {
+            Some(seg) => seg,
+            None => return,
+        };
+        let guest = SocketAddrV4::new(src, read_u16(segment, 0));
+        let remote = SocketAddrV4::new(dst, read_u16(segment, 2));
+        let key = (guest.port(), remote);
+
+        if let Some(conn) = self.tcp.get_mut(&key) {
+            if !conn.guest_segment(&mut self.link, &seg) {
+                if let Some(stream) = &conn.stream {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                self.tcp.remove(&key);
+            }
+            return;
+        }
+
+        if seg.flags & TCP_RST != 0 {
+            return;
+        }
+
+        let host = match host_address(remote) {
+            Some(host) if seg.flags & (TCP_SYN | TCP_ACK) == TCP_SYN => host,
+            _ => {
+                // Not a connection we know about, or a destination we can't reach.
+                let (seq, ack, flags) = if seg.flags & TCP_ACK != 0 {
+                    (seg.ack, 0, TCP_RST)
+                }
+                else {
+                    let mut len = seg.data.len() as u32;
+                    if seg.flags & (TCP_SYN | TCP_FIN) != 0 {
+                        len += 1;
+                    }
+                    (0, seg.seq.wrapping_add(len), TCP_RST | TCP_ACK)
+                };
+                self.link.send_tcp(remote, guest, seq, ack, flags, 0, &[]);
+                return;
+            }
+        };
+
+        log::debug!("SLIRP: TCP connect {} -> {} ({})", guest, remote, host);
+        self.tcp.insert(key, TcpConnection::new(guest, remote, host, &seg));
+    }
+}
@@ -28,21 +28,36 @@
 
     Implements the 8259 PIC (Programmable Interrupt Controller)
 
+    A PIC may be operated on its own, or as one of a master/slave pair. In a
+    cascaded configuration, the slave's INT output drives one of the master's
+    IR lines (IR2 on the PC/AT), and the master's ICW3 indicates which of its
+    IR lines have slaves attached. When the master acknowledges an interrupt
+    on a cascade line, the slave is responsible for placing the vector on the
+    bus. This sequencing is performed by the bus, which owns both devices.
+
 */
 
 #![allow(dead_code)]
 
 //use std::io::Read;
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 //pub const PIC_INTERRUPT_OFFSET: u8 = 8;
 
 pub const PIC_COMMAND_PORT: u16 = 0x20;
 pub const PIC_DATA_PORT: u16 = 0x21;
+pub const PIC2_COMMAND_PORT: u16 = 0xA0;
+pub const PIC2_DATA_PORT: u16 = 0xA1;
+
+/// The IR line of the master PIC that the slave PIC's INT output is connected to.
+pub const PIC_CASCADE_IRQ: u8 = 2;
 
 const ICW1_ICW4_NEEDED: u8 = 0b0000_0001; // Bit set if a 4th control world is required (not supported)
-const ICW1_SINGLE_MODE: u8 = 0b0000_0010; // Bit is set if PIC is operating in single mode (only supported configuration)
+const ICW1_SINGLE_MODE: u8 = 0b0000_0010; // Bit is set if PIC is operating in single mode, clear if cascaded (ICW3 follows)
 const ICW1_ADI: u8 = 0b0000_0100; // Bit is set if PIC is using a call address interval of 4, otherwise 8
 const ICW1_LTIM: u8 = 0b0000_1000; // Bit is set if PIC is in Level Triggered Mode
 const ICW1_IS_ICW1: u8 = 0b0001_0000; // Bit determines if input is ICW1
@@ -66,6 +81,7 @@ const SPURIOUS_INTERRUPT: u8 = 7;
 pub enum InitializationState {
     Normal,        // Normal operation, can receive an ICW1 at any point
     ExpectingICW2, // In initialization sequence, expecting ICW2
+    ExpectingICW3, // In initialization sequence, expecting ICW3 (cascade mode only)
     ExpectingICW4, // In initialization sequence, expecting ICW4
 }
 
@@ -120,6 +136,10 @@ pub struct Pic {
     expecting_icw2: bool,
     expecting_icw4: bool, // ICW3 not supported in Single mode operation
     error: bool,          // We encountered an invalid condition or request
+    secondary: bool,      // This PIC is the slave of a cascaded pair and lives at ports A0-A1.
+    cascaded: bool,       // ICW1 specified cascade mode
+    icw3: u8,             // Master: bitfield of IR lines with slaves attached. Slave: slave ID.
+    cascade_ack: bool,    // The last interrupt acknowledged was on a cascade line; a slave must supply the vector.
 
     spurious_irqs: u64,
     interrupt_stats: Vec<InterruptStats>,
@@ -149,6 +169,10 @@ impl Default for Pic {
             expecting_icw2: false,
             expecting_icw4: false,
             error: false,
+            secondary: false,
+            cascaded: false,
+            icw3: 0,
+            cascade_ack: false,
 
             spurious_irqs: 0,
             interrupt_stats: vec![InterruptStats::new(); 8],
@@ -174,17 +198,17 @@ pub struct PicStringState {
 impl IoDevice for Pic {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
         match port {
-            PIC_COMMAND_PORT => self.handle_command_register_read(),
-            PIC_DATA_PORT => self.handle_data_register_read(),
+            PIC_COMMAND_PORT | PIC2_COMMAND_PORT => self.handle_command_register_read(),
+            PIC_DATA_PORT | PIC2_DATA_PORT => self.handle_data_register_read(),
             _ => unreachable!("PIC: Bad port #"),
         }
     }
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
         match port {
-            PIC_COMMAND_PORT => {
+            PIC_COMMAND_PORT | PIC2_COMMAND_PORT => {
                 self.handle_command_register_write(data);
             }
-            PIC_DATA_PORT => {
+            PIC_DATA_PORT | PIC2_DATA_PORT => {
                 self.handle_data_register_write(data);
             }
             _ => unreachable!("PIC: Bad port #"),
@@ -192,10 +216,18 @@ impl IoDevice for Pic {
     }
 
     fn port_list(&self) -> Vec<(String, u16)> {
-        vec![
-            (String::from("PIC Command Port"), PIC_COMMAND_PORT),
-            (String::from("PIC Data Port"), PIC_DATA_PORT),
-        ]
+        if self.secondary {
+            vec![
+                (String::from("PIC2 Command Port"), PIC2_COMMAND_PORT),
+                (String::from("PIC2 Data Port"), PIC2_DATA_PORT),
+            ]
+        }
+        else {
+            vec![
+                (String::from("PIC Command Port"), PIC_COMMAND_PORT),
+                (String::from("PIC Data Port"), PIC_DATA_PORT),
+            ]
+        }
     }
 }
 
@@ -204,8 +236,23 @@ impl Pic {
         Default::default()
     }
 
+    /// Create the slave PIC of a cascaded pair.
+    pub fn new_secondary() -> Self {
+        Self {
+            secondary: true,
+            ..Default::default()
+        }
+    }
+
     pub fn reset(&mut self) {
-        *self = Default::default();
+        *self = Self {
+            secondary: self.secondary,
+            ..Default::default()
+        };
+    }
+
+    pub fn is_secondary(&self) -> bool {
+        self.secondary
     }
 
     pub fn handle_command_register_write(&mut self, byte: u8) {
@@ -223,10 +270,8 @@ impl Pic {
                 log::warn!("PIC: Warning: Received unexpected ICW1: {:02X}", byte);
             }
 
-            if byte & ICW1_SINGLE_MODE == 0 {
-                log::error!("PIC: Error: Chained mode not supported");
-                self.error = true;
-            }
+            self.cascaded = byte & ICW1_SINGLE_MODE == 0;
+            self.icw3 = 0;
 
             if byte & ICW1_ADI != 0 {
                 log::error!("PIC: Error: 4 byte ADI unsupported");
@@ -352,7 +397,7 @@ impl Pic {
     }
 
     pub fn handle_data_register_write(&mut self, byte: u8) {
-        // Handle ICW2 & ICW4 (ICW3 skipped in Single mode)
+        // Handle ICW2, ICW3 & ICW4 (ICW3 skipped in Single mode)
         match self.init_state {
             InitializationState::Normal => {
                 // We aren't expecting any ICWs, so treat this write as a set of the IMR
@@ -364,6 +409,35 @@ impl Pic {
                 // This value should be an ICW2 based on just receiving an ICW1 on control port
                 log::debug!("PIC: Read ICW2: {:02X}", byte);
                 self.int_offset = byte & ICW2_MASK;
+                self.init_state = if self.cascaded {
+                    InitializationState::ExpectingICW3
+                }
+                else {
+                    InitializationState::ExpectingICW4
+                };
+                return;
+            }
+            InitializationState::ExpectingICW3 => {
+                // On the master, each set bit marks an IR line with a slave attached.
+                // On the slave, the low three bits specify the slave's ID (the master IR it is attached to).
+                log::debug!("PIC: Read ICW3: {:02X}", byte);
+                self.icw3 = if self.secondary { byte & 0x07 } else { byte };
+                if !self.cascade_matches_wiring() {
+                    if self.secondary {
+                        log::warn!(
+                            "PIC: Slave ID {} does not match master IR{}, which the slave is wired to",
+                            self.icw3,
+                            PIC_CASCADE_IRQ
+                        );
+                    }
+                    else {
+                        log::warn!(
+                            "PIC: ICW3 {:02X} has no slave on IR{}; the slave's vectors will not be used",
+                            self.icw3,
+                            PIC_CASCADE_IRQ
+                        );
+                    }
+                }
                 self.init_state = InitializationState::ExpectingICW4;
                 return;
             }
@@ -378,10 +452,9 @@ impl Pic {
                 }
                 self.auto_eoi = byte & ICW4_AEOI_MODE != 0;
                 self.buffered = byte & ICW4_BUFFERED != 0;
-                if byte & ICW4_NESTED != 0 {
-                    log::error!("PIC: Error: MCS-80/85 mode unsupported");
-                    self.error = true;
-                }
+                // Special fully nested mode allows a master to accept further requests from a slave that is
+                // already in service, so that higher priority slave interrupts may nest.
+                self.special_nested = byte & ICW4_NESTED != 0;
                 return;
             }
         }
@@ -514,12 +587,13 @@ impl Pic {
             log::warn!("get_interrupt_vector() called when INTR is not asserted");
             return None;
         }
+        self.cascade_ack = false;
 
         // Return the highest priority vector.
         let mut ir_bit: u8 = 0x01;
         for irq in 0..8 {
             let have_request = self.irr & ir_bit != 0;
-            let in_service = self.isr & ir_bit != 0;
+            let in_service = self.isr & ir_bit != 0 && !(self.special_nested && self.is_cascade_line(irq));
             let is_masked = self.imr & ir_bit != 0;
 
             // TODO: Can an interrupt vector be delivered while in service? We assume not for now.
@@ -543,6 +617,12 @@ impl Pic {
                 // Finally, set INTR line low
                 self.intr = false;
 
+                // If this IR line has a slave attached, the slave must supply the vector during the
+                // second INTA pulse instead of us.
+                if self.is_cascade_line(irq) {
+                    self.cascade_ack = true;
+                }
+
                 return Some(irq | self.int_offset);
             }
             ir_bit <<= 1;
@@ -555,6 +635,42 @@ impl Pic {
         Some(SPURIOUS_INTERRUPT)
     }
 
+    /// Return whether the specified IR line has a slave PIC attached, as programmed by ICW3.
+    #[inline]
+    pub fn is_cascade_line(&self, irq: u8) -> bool {
+        !self.secondary && self.cascaded && self.icw3 & (0x01 << irq) != 0
+    }
+
+    /// Return whether ICW3 agrees with how the pair is wired, with the slave's INT output on the
+    /// master's PIC_CASCADE_IRQ line. For the master, the line must be in its cascade mask; for
+    /// the slave, its ID must be that line.
+    pub fn cascade_matches_wiring(&self) -> bool {
+        match self.secondary {
+            true => self.icw3 == PIC_CASCADE_IRQ,
+            false => self.is_cascade_line(PIC_CASCADE_IRQ),
+        }
+    }
+
+    /// Return whether the last interrupt acknowledged was on a cascade line. If so, the vector
+    /// returned by get_interrupt_vector() should be replaced by the slave's vector.
+    #[inline]
+    pub fn cascade_acknowledged(&self) -> bool {
+        self.cascade_ack
+    }
+
+    /// Represents a slave PIC's response to an INTA sequence in which it was selected by the
+    /// master, but has no pending request. The slave will supply its own IR7 vector.
+    pub fn get_spurious_vector(&mut self) -> u8 {
+        self.spurious_irqs += 1;
+        self.int_offset | SPURIOUS_INTERRUPT
+    }
+
+    /// Return the state of the specified IR line.
+    #[inline]
+    pub fn ir_line(&self, interrupt: u8) -> bool {
+        self.ir & (0x01 << interrupt) != 0
+    }
+
     pub fn get_string_state(&self) -> PicStringState {
         let mut state = PicStringState {
             imr: format!("{:08b}", self.imr),
@@ -591,7 +707,7 @@ impl Pic {
         for irq in 0..8 {
             let have_request = self.irr & ir_bit != 0;
             let is_not_masked = self.imr & ir_bit == 0;
-            let is_not_in_service = self.isr & ir_bit == 0;
+            let is_not_in_service = self.isr & ir_bit == 0 || (self.special_nested && self.is_cascade_line(irq));
 
             if have_request && is_not_masked && is_not_in_service {
                 return (true, irq);
@@ -624,3 +740,112 @@ impl Pic {
         }
     }
 }
+
+impl SaveState for Pic {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.init_state {
+            InitializationState::Normal => 0,
+            InitializationState::ExpectingICW2 => 1,
+            InitializationState::ExpectingICW3 => 2,
+            InitializationState::ExpectingICW4 => 3,
+        });
+        w.write_u8(self.int_offset);
+        w.write_u8(self.imr);
+        w.write_u8(self.isr);
+        w.write_u8(self.irr);
+        w.write_u8(self.ir);
+        w.write_bool(matches!(self.read_select, ReadSelect::ISR));
+        w.write_u8(self.irq);
+        w.write_bool(self.intr);
+        w.write_bool(self.buffered);
+        w.write_bool(self.nested);
+        w.write_bool(self.special_nested);
+        w.write_bool(self.polled);
+        w.write_bool(self.auto_eoi);
+        w.write_bool(self.rotate_on_aeoi);
+        w.write_bool(self.trigger_mode == TriggerMode::Level);
+        w.write_bool(self.expecting_icw2);
+        w.write_bool(self.expecting_icw4);
+        w.write_bool(self.error);
+        w.write_bool(self.cascaded);
+        w.write_u8(self.icw3);
+        w.write_bool(self.cascade_ack);
+        w.write_bool(self.intr_scheduled);
+        w.write_u32(self.intr_timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.init_state = match r.read_u8()? {
+            0 => InitializationState::Normal,
+            1 => InitializationState::ExpectingICW2,
+            2 => InitializationState::ExpectingICW3,
+            3 => InitializationState::ExpectingICW4,
+            _ => return Err(SaveStateError::InvalidValue("PIC initialization state")),
+        };
+        self.int_offset = r.read_u8()?;
+        self.imr = r.read_u8()?;
+        self.isr = r.read_u8()?;
+        self.irr = r.read_u8()?;
+        self.ir = r.read_u8()?;
+        self.read_select = match r.read_bool()? {
+            true => ReadSelect::ISR,
+            false => ReadSelect::IRR,
+        };
+        self.irq = r.read_u8()?;
+        self.intr = r.read_bool()?;
+        self.buffered = r.read_bool()?;
+        self.nested = r.read_bool()?;
+        self.special_nested = r.read_bool()?;
+        self.polled = r.read_bool()?;
+        self.auto_eoi = r.read_bool()?;
+        self.rotate_on_aeoi = r.read_bool()?;
+        self.trigger_mode = match r.read_bool()? {
+            true => TriggerMode::Level,
+            false => TriggerMode::Edge,
+        };
+        self.expecting_icw2 = r.read_bool()?;
+        self.expecting_icw4 = r.read_bool()?;
+        self.error = r.read_bool()?;
+        self.cascaded = r.read_bool()?;
+        self.icw3 = r.read_u8()?;
+        self.cascade_ack = r.read_bool()?;
+        self.intr_scheduled = r.read_bool()?;
+        self.intr_timer = r.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Initialize a PIC in cascade mode with the specified ICW3.
+    fn init_cascaded(pic: &mut Pic, icw2: u8, icw3: u8) {
+        pic.handle_command_register_write(ICW1_IS_ICW1 | ICW1_ICW4_NEEDED);
+        pic.handle_data_register_write(icw2);
+        pic.handle_data_register_write(icw3);
+        pic.handle_data_register_write(0x01);
+    }
+
+    #[test]
+    fn test_icw3_cascade_validation() {
+        let mut master = Pic::new();
+        init_cascaded(&mut master, 0x08, 0x01 << PIC_CASCADE_IRQ);
+        assert!(master.cascade_matches_wiring());
+        assert!(master.is_cascade_line(PIC_CASCADE_IRQ));
+
+        let mut slave = Pic::new_secondary();
+        init_cascaded(&mut slave, 0x70, PIC_CASCADE_IRQ);
+        assert!(slave.cascade_matches_wiring());
+
+        // A master with no slave on the cascade line, and a slave with the wrong ID.
+        init_cascaded(&mut master, 0x08, 0x08);
+        assert!(!master.cascade_matches_wiring());
+        init_cascaded(&mut slave, 0x70, 0x03);
+        assert!(!slave.cascade_matches_wiring());
+
+        // The slave ID is only three bits wide.
+        init_cascaded(&mut slave, 0x70, 0xF8 | PIC_CASCADE_IRQ);
+        assert!(slave.cascade_matches_wiring());
+    }
+}
@@ -28,6 +28,14 @@
 
     Implements functionality for the Intel 8253 Programmable Interval Timer.
 
+    The Intel 8254 may also be emulated. The 8254 adds the read-back command,
+    which can latch the count and/or a status byte for any combination of
+    counters at once. The status byte reports the output state, the counter's
+    programmed mode and the 'null count' flag, which indicates that a new count
+    has been written but not yet loaded into the counting element. Since the
+    8253 ignores the read-back command, this is a common way for software to
+    tell the two apart.
+
 */
 
 use log;
@@ -36,9 +44,12 @@ use crossbeam_channel::Sender;
 use std::collections::{BTreeMap, VecDeque};
 
 use modular_bitfield::prelude::*;
+use serde_derive::Deserialize;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    devices::speaker_filter::SpeakerFilter,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::*,
     updatable::*,
 };
@@ -70,6 +81,15 @@ pub const SPEAKER_SAMPLE_RATE: u32 = 47727;
 // of the PIT input clock that would latch the value.
 pub const PIT_WRITE_LATENCY: u32 = 3;
 
+// Read-back command bits (8254 only).
+const READBACK_COUNT_N: u8 = 0b0010_0000; // Bit is clear if the count should be latched
+const READBACK_STATUS_N: u8 = 0b0001_0000; // Bit is clear if the status should be latched
+const READBACK_CHANNEL_MASK: u8 = 0b0000_1110; // Bits 1-3 select counters 0-2
+
+// Status byte bits (8254 only).
+const STATUS_OUTPUT: u8 = 0b1000_0000;
+const STATUS_NULL_COUNT: u8 = 0b0100_0000;
+
 #[derive(Debug, PartialEq)]
 pub enum ChannelMode {
     InterruptOnTerminalCount,
@@ -104,7 +124,7 @@ pub enum ReloadFlag {
     ReloadNextCycle,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, BitfieldSpecifier)]
+#[derive(Debug, Copy, Clone, PartialEq, BitfieldSpecifier, Deserialize)]
 pub enum PitType {
     Model8253,
     Model8254,
@@ -180,6 +200,9 @@ pub struct Channel {
     armed: bool,
     read_state: ReadState,
     count_is_latched: bool,
+    status_is_latched: bool,
+    status_latch: u8,
+    null_count: bool,
     output: Updatable<bool>,
     output_on_reload: bool,
     reload_on_trigger: bool,
@@ -197,6 +220,7 @@ pub struct PitSpeaker {
     pub sample_accum: f32,
     pub sample_ct: u32,
     pub sender: Option<Sender<f32>>,
+    pub filter: Option<SpeakerFilter>,
 }
 
 #[allow(dead_code)]
@@ -298,6 +322,9 @@ impl Default for Channel {
             armed: false,
             read_state: ReadState::NoRead,
             count_is_latched: false,
+            status_is_latched: false,
+            status_latch: 0,
+            null_count: true,
             output: Updatable::Dirty(false, false),
             output_on_reload: false,
             reload_on_trigger: false,
@@ -383,6 +410,10 @@ impl Channel {
         self.bcd_mode = bcd;
         self.dirty = true;
 
+        // Writing a control word sets null count until a count is loaded into the counting element.
+        self.null_count = true;
+        self.status_is_latched = false;
+
         // Setting any mode stops counter.
         self.change_channel_state(ChannelState::WaitingForReload);
         self.read_state = ReadState::NoRead;
@@ -440,6 +471,46 @@ impl Channel {
         self.dirty = true;
     }
 
+    /// Build the status byte returned by the 8254 read-back command.
+    /// Bit 7 is the output state, bit 6 is the null count flag, and bits 0-5 reflect the
+    /// last control word written to the counter.
+    pub fn status_byte(&self) -> u8 {
+        let mode_bits = match *self.mode {
+            ChannelMode::InterruptOnTerminalCount => 0,
+            ChannelMode::HardwareRetriggerableOneShot => 1,
+            ChannelMode::RateGenerator => 2,
+            ChannelMode::SquareWaveGenerator => 3,
+            ChannelMode::SoftwareTriggeredStrobe => 4,
+            ChannelMode::HardwareTriggeredStrobe => 5,
+        };
+        let rw_bits = match *self.rw_mode {
+            RwMode::Lsb => 1,
+            RwMode::Msb => 2,
+            RwMode::LsbMsb => 3,
+        };
+
+        let mut status = (rw_bits << 4) | (mode_bits << 1) | self.bcd_mode as u8;
+        if *self.output {
+            status |= STATUS_OUTPUT;
+        }
+        if self.null_count {
+            status |= STATUS_NULL_COUNT;
+        }
+        status
+    }
+
+    /// Handle a read-back command directed at this channel (8254 only).
+    /// A count or status that is already latched and has not been read is not overwritten.
+    pub fn readback(&mut self, latch_count: bool, latch_status: bool) {
+        if latch_status && !self.status_is_latched {
+            self.status_latch = self.status_byte();
+            self.status_is_latched = true;
+        }
+        if latch_count && !self.count_is_latched {
+            self.latch_count();
+        }
+    }
+
     pub fn set_gate(&mut self, new_state: bool, bus: &mut BusInterface) {
         if (*self.gate == false) && (new_state == true) {
             // Rising edge of input gate.
@@ -507,6 +578,12 @@ impl Channel {
     /// When the timer is not latched, the output latch updates synchronously with the
     /// counting element per tick. When latched, the output latch stops updating.
     pub fn read_byte(&mut self) -> u8 {
+        // A latched status byte is always read first, ahead of any latched count.
+        if self.status_is_latched {
+            self.status_is_latched = false;
+            return self.status_latch;
+        }
+
         match self.read_state {
             ReadState::NoRead => {
                 // No read in progress
@@ -575,6 +652,7 @@ impl Channel {
     pub fn finalize_load(&mut self, defer_reload: bool) {
         // The count register is transferred to the counting element when a complete count is written.
         self.reload_value.update(*self.count_register);
+        self.null_count = true;
 
         let next_reload_state = match defer_reload {
             true => ChannelState::DeferLoadCycle,
@@ -649,7 +727,8 @@ impl Channel {
         else {
             // Wrap BCD counter
             if *self.counting_element == 0 {
-                *self.counting_element = 0x9999;
+                // A count of 0 represents 10000 in BCD mode.
+                self.counting_element.set(0x9999);
             }
             else {
                 // Countdown in BCD...
@@ -708,6 +787,7 @@ impl Channel {
             // Load the current reload value into the counting element, applying the load mask
             //self.counting_element.update(*self.reload_value & self.load_mask);
             self.counting_element.update(*self.reload_value);
+            self.null_count = false;
 
             // Start counting.
             self.change_channel_state(ChannelState::Counting(ReloadFlag::Normal));
@@ -780,6 +860,7 @@ impl Channel {
                             if *self.counting_element == 0 {
                                 self.change_output_state(!*self.output, bus); // Toggle output state
                                 self.counting_element.update(*self.reload_value);
+                                self.null_count = false;
                                 // Reload counting element
                             }
                         }
@@ -799,6 +880,7 @@ impl Channel {
                                         // Output is low. Reload and update output immediately.
                                         self.change_output_state(!*self.output, bus); // Toggle output state
                                         self.counting_element.update(*self.reload_value);
+                                        self.null_count = false;
                                         // Reload counting element
                                     }
                                 }
@@ -824,6 +906,7 @@ impl Channel {
                                     // Counting element is immediately reloaded and output toggled.
                                     self.change_output_state(!*self.output, bus); // Toggle output state
                                     self.counting_element.update(*self.reload_value);
+                                    self.null_count = false;
                                 }
                             }
                         }
@@ -889,6 +972,7 @@ impl ProgrammableIntervalTimer {
                 sample_accum: 0.0,
                 sample_ct: 0,
                 sender: speaker_sender,
+                filter: None,
             },
         }
     }
@@ -906,6 +990,15 @@ impl ProgrammableIntervalTimer {
                 ..Default::default()
             }
         }
+
+        if let Some(filter) = &mut self.speaker.filter {
+            filter.reset();
+        }
+    }
+
+    /// Set the filter chain applied to speaker output samples.
+    pub fn set_speaker_filter(&mut self, filter: SpeakerFilter) {
+        self.speaker.filter = Some(filter);
     }
 
     /// Optionally set the input of a channel to the output of another channel. If set to None,
@@ -963,7 +1056,19 @@ impl ProgrammableIntervalTimer {
                     // Readback command not supported. Do nothing.
                 }
                 PitType::Model8254 => {
-                    // Do readback command here and return.
+                    let latch_count = byte & READBACK_COUNT_N == 0;
+                    let latch_status = byte & READBACK_STATUS_N == 0;
+                    log::trace!(
+                        "PIT: Read-back command: {:02X} count: {} status: {}",
+                        byte,
+                        latch_count,
+                        latch_status
+                    );
+                    for (i, channel) in self.channels.iter_mut().enumerate() {
+                        if byte & READBACK_CHANNEL_MASK & (0x02 << i) != 0 {
+                            channel.readback(latch_count, latch_status);
+                        }
+                    }
                 }
             }
             return;
@@ -1230,7 +1335,10 @@ impl ProgrammableIntervalTimer {
         self.speaker.sample_ct += 1;
 
         if self.speaker.sample_ct == SPEAKER_SAMPLE_RATIO {
-            let sample = self.speaker.sample_accum / SPEAKER_SAMPLE_RATIO as f32;
+            let mut sample = self.speaker.sample_accum / SPEAKER_SAMPLE_RATIO as f32;
+            if let Some(filter) = &mut self.speaker.filter {
+                sample = filter.process(sample);
+            }
             self.speaker.sample_accum = 0.0;
             self.speaker.sample_ct = 0;
             let _ = self.speaker.sender.as_mut().unwrap().send(sample);
@@ -1382,3 +1490,272 @@ impl ProgrammableIntervalTimer {
         state_vec
     }
 }
+
+impl SaveState for Channel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.mode.get() {
+            ChannelMode::InterruptOnTerminalCount => 0,
+            ChannelMode::HardwareRetriggerableOneShot => 1,
+            ChannelMode::RateGenerator => 2,
+            ChannelMode::SquareWaveGenerator => 3,
+            ChannelMode::SoftwareTriggeredStrobe => 4,
+            ChannelMode::HardwareTriggeredStrobe => 5,
+        });
+        w.write_u8(match self.rw_mode.get() {
+            RwMode::Lsb => 0,
+            RwMode::Msb => 1,
+            RwMode::LsbMsb => 2,
+        });
+        w.write_u8(match self.channel_state {
+            ChannelState::WaitingForReload => 0,
+            ChannelState::WaitingForGate => 1,
+            ChannelState::DeferLoadCycle => 2,
+            ChannelState::WaitingForLoadCycle => 3,
+            ChannelState::WaitingForLoadTrigger => 4,
+            ChannelState::Counting(ReloadFlag::Normal) => 5,
+            ChannelState::Counting(ReloadFlag::ReloadNextCycle) => 6,
+        });
+        w.write_u32(self.cycles_in_state);
+        w.write_u16(*self.count_register.get());
+        w.write_bool(self.load_state == LoadState::WaitingForMsb);
+        w.write_bool(self.load_type == LoadType::SubsequentLoad);
+        w.write_u16(self.load_mask);
+        w.write_u16(*self.reload_value.get());
+        w.write_u16(*self.counting_element.get());
+        w.write_bool(self.ce_undefined);
+        w.write_bool(self.armed);
+        w.write_bool(self.read_state == ReadState::ReadLsb);
+        w.write_bool(self.count_is_latched);
+        w.write_bool(self.status_is_latched);
+        w.write_u8(self.status_latch);
+        w.write_bool(self.null_count);
+        w.write_bool(*self.output.get());
+        w.write_bool(self.output_on_reload);
+        w.write_bool(self.reload_on_trigger);
+        w.write_u16(*self.output_latch.get());
+        w.write_bool(self.bcd_mode);
+        w.write_bool(*self.gate.get());
+        w.write_bool(self.incomplete_reload);
+        w.write_bool(self.ticked);
+        w.write_bool(self.defer_reload_flag);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mode = r.read_u8()?;
+        if mode > 5 {
+            return Err(SaveStateError::InvalidValue("PIT channel mode"));
+        }
+        self.mode.set(ChannelMode::from(mode));
+        self.rw_mode.set(match r.read_u8()? {
+            0 => RwMode::Lsb,
+            1 => RwMode::Msb,
+            2 => RwMode::LsbMsb,
+            _ => return Err(SaveStateError::InvalidValue("PIT access mode")),
+        });
+        self.channel_state = match r.read_u8()? {
+            0 => ChannelState::WaitingForReload,
+            1 => ChannelState::WaitingForGate,
+            2 => ChannelState::DeferLoadCycle,
+            3 => ChannelState::WaitingForLoadCycle,
+            4 => ChannelState::WaitingForLoadTrigger,
+            5 => ChannelState::Counting(ReloadFlag::Normal),
+            6 => ChannelState::Counting(ReloadFlag::ReloadNextCycle),
+            _ => return Err(SaveStateError::InvalidValue("PIT channel state")),
+        };
+        self.cycles_in_state = r.read_u32()?;
+        self.count_register.set(r.read_u16()?);
+        self.load_state = match r.read_bool()? {
+            true => LoadState::WaitingForMsb,
+            false => LoadState::WaitingForLsb,
+        };
+        self.load_type = match r.read_bool()? {
+            true => LoadType::SubsequentLoad,
+            false => LoadType::InitialLoad,
+        };
+        self.load_mask = r.read_u16()?;
+        self.reload_value.set(r.read_u16()?);
+        self.counting_element.set(r.read_u16()?);
+        self.ce_undefined = r.read_bool()?;
+        self.armed = r.read_bool()?;
+        self.read_state = match r.read_bool()? {
+            true => ReadState::ReadLsb,
+            false => ReadState::NoRead,
+        };
+        self.count_is_latched = r.read_bool()?;
+        self.status_is_latched = r.read_bool()?;
+        self.status_latch = r.read_u8()?;
+        self.null_count = r.read_bool()?;
+        self.output.set(r.read_bool()?);
+        self.output_on_reload = r.read_bool()?;
+        self.reload_on_trigger = r.read_bool()?;
+        self.output_latch.set(r.read_u16()?);
+        self.bcd_mode = r.read_bool()?;
+        self.gate.set(r.read_bool()?);
+        self.incomplete_reload = r.read_bool()?;
+        self.ticked = r.read_bool()?;
+        self.defer_reload_flag = r.read_bool()?;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl SaveState for ProgrammableIntervalTimer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.pit_cycles);
+        w.write_u32(self.sys_tick_accumulator);
+        w.write_u32(self.sys_ticks_advance);
+        w.write_f64(self.cycle_accumulator);
+        match self.timewarp {
+            DeviceRunTimeUnit::SystemTicks(ticks) => {
+                w.write_u8(0);
+                w.write_f64(ticks as f64);
+            }
+            DeviceRunTimeUnit::Microseconds(us) => {
+                w.write_u8(1);
+                w.write_f64(us);
+            }
+        }
+        w.write_bool(self.defer_reload_flag);
+        for state in self.last_output_state {
+            w.write_bool(state);
+        }
+        for channel in &self.channels {
+            channel.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pit_cycles = r.read_u64()?;
+        self.sys_tick_accumulator = r.read_u32()?;
+        self.sys_ticks_advance = r.read_u32()?;
+        self.cycle_accumulator = r.read_f64()?;
+        self.timewarp = match (r.read_u8()?, r.read_f64()?) {
+            (0, ticks) => DeviceRunTimeUnit::SystemTicks(ticks as u32),
+            (1, us) => DeviceRunTimeUnit::Microseconds(us),
+            _ => return Err(SaveStateError::InvalidValue("PIT time unit")),
+        };
+        self.defer_reload_flag = r.read_bool()?;
+        for state in self.last_output_state.iter_mut() {
+            *state = r.read_bool()?;
+        }
+        for channel in self.channels.iter_mut() {
+            channel.load_state(r)?;
+        }
+        // Discard buffered speaker samples from before the restore.
+        self.speaker_buf.clear();
+        self.speaker.sample_accum = 0.0;
+        self.speaker.sample_ct = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_count(pit: &mut Pit, channel: usize) -> u16 {
+        let lsb = pit.data_read(channel) as u16;
+        let msb = pit.data_read(channel) as u16;
+        (msb << 8) | lsb
+    }
+
+    fn load_count(pit: &mut Pit, channel: usize, count: u16, bus: &mut BusInterface) {
+        pit.data_write(channel, (count & 0xFF) as u8, bus);
+        pit.data_write(channel, (count >> 8) as u8, bus);
+    }
+
+    #[test]
+    fn test_readback() {
+        let mut bus = BusInterface::default();
+        let mut pit = Pit::new(PitType::Model8254, 0.0, 4, None);
+        pit.set_channel_gate(0, true, &mut bus);
+        pit.set_channel_gate(2, true, &mut bus);
+
+        // Channel 0: mode 0, LSB/MSB. Channel 2: mode 2, LSB/MSB.
+        pit.control_register_write(0x30, &mut bus);
+        load_count(&mut pit, 0, 0x1000, &mut bus);
+        pit.control_register_write(0xB4, &mut bus);
+        load_count(&mut pit, 2, 0x1234, &mut bus);
+
+        // Latch status only for channel 2. Null count is set until the count is loaded.
+        pit.control_register_write(0xE8, &mut bus);
+        assert_eq!(pit.data_read(2), STATUS_OUTPUT | STATUS_NULL_COUNT | 0x34);
+
+        // Load both counters, then count 4 cycles.
+        for _ in 0..5 {
+            pit.tick(&mut bus, None);
+        }
+
+        // Latch channel 2 with a counter latch command, then keep counting.
+        pit.control_register_write(0x80, &mut bus);
+        pit.tick(&mut bus, None);
+        pit.tick(&mut bus, None);
+
+        // Read back count and status for channels 0 and 2. Channel 0 latches its current count, while
+        // channel 2's existing latched count is not overwritten.
+        pit.control_register_write(0xCA, &mut bus);
+        assert_eq!(pit.data_read(0), 0x30);
+        assert_eq!(read_count(&mut pit, 0), 0x0FFA);
+        assert_eq!(pit.data_read(2), STATUS_OUTPUT | 0x34);
+        assert_eq!(read_count(&mut pit, 2), 0x1230);
+
+        // Once read, channel 2's output latch follows the counting element again.
+        pit.tick(&mut bus, None);
+        assert_eq!(read_count(&mut pit, 2), 0x122D);
+
+        // A status latched twice is only returned once, ahead of the count.
+        pit.control_register_write(0xE8, &mut bus);
+        pit.control_register_write(0xC8, &mut bus);
+        assert_eq!(pit.data_read(2), STATUS_OUTPUT | 0x34);
+        assert_eq!(read_count(&mut pit, 2), 0x122D);
+    }
+
+    #[test]
+    fn test_readback_8253() {
+        let mut bus = BusInterface::default();
+        let mut pit = Pit::new(PitType::Model8253, 0.0, 4, None);
+        pit.set_channel_gate(2, true, &mut bus);
+
+        pit.control_register_write(0xB4, &mut bus);
+        load_count(&mut pit, 2, 0x1234, &mut bus);
+        for _ in 0..3 {
+            pit.tick(&mut bus, None);
+        }
+
+        // The 8253 ignores the read-back command, so the current count is read.
+        pit.control_register_write(0xC8, &mut bus);
+        assert_eq!(read_count(&mut pit, 2), 0x1232);
+    }
+
+    #[test]
+    fn test_null_count_reload() {
+        let mut bus = BusInterface::default();
+        let mut pit = Pit::new(PitType::Model8254, 0.0, 4, None);
+        pit.set_channel_gate(2, true, &mut bus);
+
+        // Channel 2: mode 2, LSB/MSB.
+        pit.control_register_write(0xB4, &mut bus);
+        load_count(&mut pit, 2, 0x0010, &mut bus);
+        assert!(pit.channels[2].status_byte() & STATUS_NULL_COUNT != 0);
+
+        // Null count clears when the count is transferred to the counting element.
+        pit.tick(&mut bus, None);
+        assert!(pit.channels[2].status_byte() & STATUS_NULL_COUNT == 0);
+
+        // Writing a new count sets null count. In mode 2 the new count is not loaded until the
+        // current count reaches 1.
+        load_count(&mut pit, 2, 0x0020, &mut bus);
+        for _ in 0..15 {
+            pit.tick(&mut bus, None);
+            pit.control_register_write(0xE8, &mut bus);
+            assert!(pit.data_read(2) & STATUS_NULL_COUNT != 0);
+        }
+        assert_eq!(pit.get_channel_count(2).1, 1);
+
+        // The reload clears null count.
+        pit.tick(&mut bus, None);
+        pit.control_register_write(0xE8, &mut bus);
+        assert_eq!(pit.data_read(2) & STATUS_NULL_COUNT, 0);
+        assert_eq!(pit.get_channel_count(2).1, 0x0020);
+    }
+}
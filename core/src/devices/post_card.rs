@@ -0,0 +1,144 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::post_card.rs
+
+    Implementation of a POST diagnostic card.
+
+    Many BIOSes write a progress code to port 80h before each step of the
+    Power-On Self Test. A POST card latches these writes and shows the last
+    code on a pair of seven-segment displays, so that a machine that hangs
+    during POST can be diagnosed by the last code written.
+
+    This device records each code along with the emulated time it was written,
+    keeping a bounded history for display by the front end.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE};
+
+pub const POST_CARD_DEFAULT_PORT: u16 = 0x80;
+pub const POST_CARD_HISTORY_LEN: usize = 256;
+
+/// A single POST code write, timestamped in microseconds of emulated time since
+/// the card was installed.
+#[derive(Copy, Clone, Debug)]
+pub struct PostCodeEntry {
+    pub code:    u8,
+    pub time_us: f64,
+}
+
+#[derive(Clone, Default)]
+pub struct PostCardState {
+    pub port: u16,
+    pub last_code: Option<u8>,
+    pub history: Vec<PostCodeEntry>,
+}
+
+pub struct PostCard {
+    port: u16,
+    elapsed_us: f64,
+    last_code: Option<u8>,
+    history: VecDeque<PostCodeEntry>,
+}
+
+impl IoDevice for PostCard {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        // Most POST cards are write-only. Some latch the code back onto the bus,
+        // but we don't want to rely on that.
+        NO_IO_BYTE
+    }
+
+    fn write_u8(&mut self, _port: u16, data: u8, _bus: Option<&mut BusInterface>, delta: DeviceRunTimeUnit) {
+        // The bus has not yet run us for the cycles elapsed in the current instruction,
+        // so add them to the timestamp.
+        let delta_us = match delta {
+            DeviceRunTimeUnit::Microseconds(us) => us,
+            _ => 0.0,
+        };
+        self.record(data, self.elapsed_us + delta_us);
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![(String::from("POST Code Register"), self.port)]
+    }
+}
+
+impl PostCard {
+    pub fn new(port: Option<u16>) -> Self {
+        Self {
+            port: port.unwrap_or(POST_CARD_DEFAULT_PORT),
+            elapsed_us: 0.0,
+            last_code: None,
+            history: VecDeque::with_capacity(POST_CARD_HISTORY_LEN),
+        }
+    }
+
+    /// Resetting the machine blanks the display. The history is kept so that codes
+    /// from a previous boot attempt can be compared against the current one.
+    pub fn reset(&mut self) {
+        self.last_code = None;
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn last_code(&self) -> Option<u8> {
+        self.last_code
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &PostCodeEntry> {
+        self.history.iter()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn run(&mut self, us: f64) {
+        self.elapsed_us += us;
+    }
+
+    pub fn get_state(&self) -> PostCardState {
+        PostCardState {
+            port: self.port,
+            last_code: self.last_code,
+            history: self.history.iter().copied().collect(),
+        }
+    }
+
+    fn record(&mut self, code: u8, time_us: f64) {
+        log::trace!("POST code: {:02X} at {:.0}us", code, time_us);
+        if self.history.len() == POST_CARD_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(PostCodeEntry { code, time_us });
+        self.last_code = Some(code);
+    }
+}
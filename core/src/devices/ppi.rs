@@ -45,6 +45,7 @@ use crate::{
     device_traits::videocard::VideoType,
     devices::{pic},
     machine_types::MachineType,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
     updatable::Updatable,
 };
@@ -364,11 +365,13 @@ pub struct Ppi {
     dip_sw1: u8,
     dip_sw2: u8,
     timer_in: bool,
+    cassette_in: bool,
     speaker_in: bool,
     jr_kb_in: bool,
     nmi_latch_in: bool,
     kb_serializer: KbSerializer,
     num_floppies: u32,
+    pending_dip_sw: Option<(u8, Option<u8>)>,
 }
 
 impl Default for Ppi {
@@ -400,11 +403,13 @@ impl Default for Ppi {
             dip_sw1: 0,
             dip_sw2: 0,
             timer_in: false,
+            cassette_in: false,
             speaker_in: false,
             jr_kb_in: false,
             nmi_latch_in: false,
             kb_serializer: KbSerializer::default(),
             num_floppies: 0,
+            pending_dip_sw: None,
         }
     }
 }
@@ -439,6 +444,16 @@ pub struct PpiStringState {
 
 pub type PpiDisplayState = BTreeMap<String, Vec<BTreeMap<&'static str, SyntaxToken>>>;
 
+/// The physical state of the motherboard DIP switches. A set bit indicates a switch in the
+/// ON position, with bit 0 corresponding to switch 1. Only the 5150 has a second switch block.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PpiDipSwitchState {
+    pub machine_type: MachineType,
+    pub sw1: u8,
+    pub sw2: Option<u8>,
+    pub pending: Option<(u8, Option<u8>)>,
+}
+
 impl Ppi {
     pub fn new(
         machine_type: MachineType,
@@ -475,13 +490,14 @@ impl Ppi {
             // We have a card that requires an expansion BIOs.
             SW1_HAVE_EXPANSION
         }
-        else if video_types.contains(&VideoType::CGA) {
-            // We have a CGA card.
-            SW1_HAVE_CGA_HIRES
-        }
         else {
-            // MDA or no card.
-            SW1_HAVE_MDA
+            // With both an MDA and CGA installed, the first listed is the primary display.
+            match video_types.iter().find(|vt| matches!(vt, VideoType::CGA | VideoType::MDA)) {
+                // We have a CGA card.
+                Some(VideoType::CGA) => SW1_HAVE_CGA_HIRES,
+                // MDA or no card.
+                _ => SW1_HAVE_MDA,
+            }
         };
 
         Self {
@@ -797,8 +813,7 @@ impl Ppi {
             (self.timer_in as u8) << 4
         }
         else {
-            // TODO: Implement cassette data input
-            0
+            (self.cassette_in as u8) << 4
         };
 
         let speaker_bit = (self.speaker_in as u8) << 4;
@@ -806,7 +821,7 @@ impl Ppi {
 
         match (&self.machine_type, &self.port_c_mode) {
             (MachineType::Ibm5150v64K | MachineType::Ibm5150v256K, PortCMode::Switch2OneToFour) => {
-                // We aren't implementing the cassette on 5150, and we'll never have parity errors
+                // We'll never have parity errors
 
                 (self.dip_sw2 & 0x0F) | cassette_bit | timer_bit
             }
@@ -877,6 +892,39 @@ impl Ppi {
         }
     }
 
+    /// Return the physical state of the motherboard DIP switches, or None if this machine
+    /// has no switches.
+    pub fn dip_switch_state(&self) -> Option<PpiDipSwitchState> {
+        let sw2 = match self.machine_type {
+            MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => Some(!self.dip_sw2),
+            MachineType::Ibm5160 => None,
+            _ => return None,
+        };
+        Some(PpiDipSwitchState {
+            machine_type: self.machine_type,
+            sw1: !self.dip_sw1,
+            sw2,
+            pending: self.pending_dip_sw,
+        })
+    }
+
+    /// Set the physical state of the motherboard DIP switches. Since the BIOS only reads the
+    /// switches during POST, the new settings are not applied until the next reset.
+    pub fn set_dip_switches(&mut self, sw1: u8, sw2: Option<u8>) {
+        log::debug!("Ppi::set_dip_switches(): SW1: {:08b} SW2: {:?}", sw1, sw2);
+        self.pending_dip_sw = Some((sw1, sw2));
+    }
+
+    pub fn apply_pending_dip_switches(&mut self) {
+        if let Some((sw1, sw2)) = self.pending_dip_sw.take() {
+            self.dip_sw1 = !sw1;
+            if let Some(sw2) = sw2 {
+                self.dip_sw2 = !sw2;
+            }
+            log::debug!("Applied DIP switches: SW1: {:08b} SW2: {:08b}", sw1, !self.dip_sw2);
+        }
+    }
+
     pub fn get_display_state(&mut self, clean: bool) -> PpiDisplayState {
         let port_a_value = match self.port_a_mode {
             PortAMode::SwitchBlock1 => self.dip_sw1,
@@ -1015,6 +1063,21 @@ impl Ppi {
         self.timer_in = state;
     }
 
+    /// Return the level of PIT channel 2's output, which drives the cassette data output.
+    pub fn get_pit_output_bit(&self) -> bool {
+        self.timer_in
+    }
+
+    /// Return whether the cassette motor relay is closed.
+    pub fn cassette_motor_on(&self) -> bool {
+        self.port_b_byte & PORTB_CASSETTE_MOTOR_OFF == 0
+    }
+
+    /// Set the level of the cassette data input, read on PC4 while the cassette motor is on.
+    pub fn set_cassette_bit(&mut self, state: bool) {
+        self.cassette_in = state;
+    }
+
     pub fn set_speaker_bit(&mut self, state: bool) {
         self.speaker_in = state;
     }
@@ -1075,3 +1138,118 @@ impl Ppi {
         }
     }
 }
+
+impl SaveState for Ppi {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.control_word.into_bytes()[0]);
+        w.write_u8(match self.group_a_mode {
+            PpiModeA::Mode0Io => 0,
+            PpiModeA::Mode1StrobedIo => 1,
+            PpiModeA::Mode2BiDirectional => 2,
+            PpiModeA::Mode2BiDirectional2 => 3,
+        });
+        w.write_bool(matches!(self.group_b_mode, PpiModeB::Mode1StrobedIo));
+        w.write_bool(matches!(self.port_a_mode, PortAMode::SwitchBlock1));
+        w.write_u8(match self.port_c_mode {
+            PortCMode::Switch2OneToFour => 0,
+            PortCMode::Switch2Five => 1,
+            PortCMode::Switch1OneToFour => 2,
+            PortCMode::Switch1FiveToEight => 3,
+            PortCMode::Tandy1000 => 4,
+        });
+        w.write_bool(self.kb_clock_low);
+        w.write_bool(self.kb_counting_low);
+        w.write_f64(self.kb_low_count);
+        w.write_bool(self.kb_do_reset);
+        w.write_f64(self.kb_count_until_reset_byte);
+        w.write_u8(self.port_a_byte);
+        w.write_u8(self.port_b_byte);
+        w.write_u8(*self.kb_byte);
+        w.write_u8(*self.kb_byte_last);
+        w.write_bool(self.keyboard_clear_scheduled);
+        w.write_bool(self.ksr_cleared);
+        w.write_bool(self.kb_enabled);
+        w.write_u8(self.dip_sw1);
+        w.write_u8(self.dip_sw2);
+        w.write_bool(self.timer_in);
+        w.write_bool(self.cassette_in);
+        w.write_bool(self.speaker_in);
+        w.write_bool(self.jr_kb_in);
+        w.write_bool(self.nmi_latch_in);
+
+        let serializer = &self.kb_serializer;
+        w.write_f64(serializer.us_accum);
+        w.write_option_u8(serializer.data);
+        match serializer.state {
+            KbSerializeState::Idle => w.write_u16(0),
+            KbSerializeState::StartBit => w.write_u16(1),
+            KbSerializeState::DataBit(bit) => w.write_u16(0x100 | bit as u16),
+            KbSerializeState::ParityBit => w.write_u16(2),
+            KbSerializeState::StopBit => w.write_u16(3),
+        }
+        w.write_bool(serializer.firsthalf);
+        w.write_u8(serializer.bit_ct);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.control_word = PpiControlWord::from_bytes([r.read_u8()?]);
+        self.group_a_mode = match r.read_u8()? {
+            0 => PpiModeA::Mode0Io,
+            1 => PpiModeA::Mode1StrobedIo,
+            2 => PpiModeA::Mode2BiDirectional,
+            3 => PpiModeA::Mode2BiDirectional2,
+            _ => return Err(SaveStateError::InvalidValue("PPI group A mode")),
+        };
+        self.group_b_mode = match r.read_bool()? {
+            true => PpiModeB::Mode1StrobedIo,
+            false => PpiModeB::Mode0Io,
+        };
+        self.port_a_mode = match r.read_bool()? {
+            true => PortAMode::SwitchBlock1,
+            false => PortAMode::KeyboardByte,
+        };
+        self.port_c_mode = match r.read_u8()? {
+            0 => PortCMode::Switch2OneToFour,
+            1 => PortCMode::Switch2Five,
+            2 => PortCMode::Switch1OneToFour,
+            3 => PortCMode::Switch1FiveToEight,
+            4 => PortCMode::Tandy1000,
+            _ => return Err(SaveStateError::InvalidValue("PPI port C mode")),
+        };
+        self.kb_clock_low = r.read_bool()?;
+        self.kb_counting_low = r.read_bool()?;
+        self.kb_low_count = r.read_f64()?;
+        self.kb_do_reset = r.read_bool()?;
+        self.kb_count_until_reset_byte = r.read_f64()?;
+        self.port_a_byte = r.read_u8()?;
+        self.port_b_byte = r.read_u8()?;
+        self.kb_byte.set(r.read_u8()?);
+        self.kb_byte_last.set(r.read_u8()?);
+        self.keyboard_clear_scheduled = r.read_bool()?;
+        self.ksr_cleared = r.read_bool()?;
+        self.kb_enabled = r.read_bool()?;
+        self.dip_sw1 = r.read_u8()?;
+        self.dip_sw2 = r.read_u8()?;
+        self.timer_in = r.read_bool()?;
+        self.cassette_in = r.read_bool()?;
+        self.speaker_in = r.read_bool()?;
+        self.jr_kb_in = r.read_bool()?;
+        self.nmi_latch_in = r.read_bool()?;
+
+        let serializer = &mut self.kb_serializer;
+        serializer.us_accum = r.read_f64()?;
+        serializer.data = r.read_option_u8()?;
+        serializer.state = match r.read_u16()? {
+            0 => KbSerializeState::Idle,
+            1 => KbSerializeState::StartBit,
+            2 => KbSerializeState::ParityBit,
+            3 => KbSerializeState::StopBit,
+            bit @ 0x100..=0x1FF => KbSerializeState::DataBit(bit as u8),
+            _ => return Err(SaveStateError::InvalidValue("PPI keyboard serializer state")),
+        };
+        serializer.firsthalf = r.read_bool()?;
+        serializer.bit_ct = r.read_u8()?;
+        self.pending_dip_sw = None;
+        Ok(())
+    }
+}
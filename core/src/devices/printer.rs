@@ -0,0 +1,239 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::printer.rs
+
+   Implements a printer attached to a parallel port. Printed bytes can be
+   captured raw to a file, and each print job can be piped to a host
+   command, such as a spooler.
+
+   A print job ends when the guest initializes the printer, or when no data
+   has been received for the job timeout.
+
+   Output to a print command is buffered and written by a separate thread, so
+   that a slow command can't stall emulation.
+
+*/
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use anyhow::Error;
+
+pub const PRINTER_DEFAULT_JOB_TIMEOUT: f64 = 5.0;
+/// Output to the print command is sent to its writer thread in chunks of up to this size.
+const PRINTER_JOB_CHUNK: usize = 4096;
+
+struct PrinterCapture {
+    path:   PathBuf,
+    writer: BufWriter<File>,
+    bytes:  usize,
+}
+
+enum PrintJob {
+    /// The print command is running. Output is sent to the thread writing to its stdin.
+    Running { sender: Sender<Vec<u8>>, buf: Vec<u8> },
+    /// The print command failed to start or exited early. Output is discarded until the job ends.
+    Failed,
+}
+
+pub struct Printer {
+    capture: Option<PrinterCapture>,
+    command: Option<String>,
+    job: Option<PrintJob>,
+    job_bytes: usize,
+    job_count: usize,
+    idle_us: f64,
+    job_timeout_us: f64,
+}
+
+impl Printer {
+    pub fn new(command: Option<String>, job_timeout: Option<f64>) -> Self {
+        Self {
+            capture: None,
+            command,
+            job: None,
+            job_bytes: 0,
+            job_count: 0,
+            idle_us: 0.0,
+            job_timeout_us: job_timeout.unwrap_or(PRINTER_DEFAULT_JOB_TIMEOUT) * 1_000_000.0,
+        }
+    }
+
+    /// Begin capturing printed bytes to the specified file. Output is appended if the file exists.
+    pub fn start_capture(&mut self, path: &Path) -> Result<(), Error> {
+        self.stop_capture();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        log::debug!("Capturing printer output to {}", path.display());
+        self.capture = Some(PrinterCapture {
+            path:   path.to_path_buf(),
+            writer: BufWriter::new(file),
+            bytes:  0,
+        });
+        Ok(())
+    }
+
+    /// Stop capturing printed bytes, returning the path of the capture file and the number of bytes written.
+    pub fn stop_capture(&mut self) -> Option<(PathBuf, usize)> {
+        self.capture.take().map(|mut capture| {
+            _ = capture.writer.flush();
+            (capture.path, capture.bytes)
+        })
+    }
+
+    pub fn capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Return the number of print jobs completed.
+    pub fn job_count(&self) -> usize {
+        self.job_count
+    }
+
+    /// Accept a byte from the parallel port.
+    pub fn print_byte(&mut self, byte: u8) {
+        self.idle_us = 0.0;
+        self.job_bytes += 1;
+
+        if let Some(capture) = &mut self.capture {
+            if let Err(e) = capture.writer.write_all(&[byte]) {
+                log::error!("Error writing printer capture: {}", e);
+                self.capture = None;
+            }
+            else {
+                capture.bytes += 1;
+            }
+        }
+
+        if self.job.is_none() {
+            if let Some(command) = &self.command {
+                self.job = Some(Self::spawn(command));
+            }
+        }
+        if let Some(PrintJob::Running { buf, .. }) = &mut self.job {
+            buf.push(byte);
+            if buf.len() >= PRINTER_JOB_CHUNK {
+                self.send_job_output();
+            }
+        }
+    }
+
+    /// Send any buffered output to the print command's writer thread.
+    fn send_job_output(&mut self) {
+        if let Some(PrintJob::Running { sender, buf }) = &mut self.job {
+            if !buf.is_empty() && sender.send(std::mem::take(buf)).is_err() {
+                // The writer thread has exited and logged the error.
+                self.job = Some(PrintJob::Failed);
+            }
+        }
+    }
+
+    fn spawn(command: &str) -> PrintJob {
+        #[cfg(windows)]
+        let mut cmd = {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C").arg(command);
+            cmd
+        };
+        #[cfg(not(windows))]
+        let mut cmd = {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command);
+            cmd
+        };
+
+        match cmd.stdin(Stdio::piped()).spawn() {
+            Ok(child) => {
+                log::debug!("Started print command: {}", command);
+                let (sender, receiver) = mpsc::channel();
+                thread::spawn(move || Self::write_job(child, receiver));
+                PrintJob::Running {
+                    sender,
+                    buf: Vec::with_capacity(PRINTER_JOB_CHUNK),
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to start print command '{}': {}", command, e);
+                PrintJob::Failed
+            }
+        }
+    }
+
+    /// Write a job's output to the print command until the job ends, then reap the command.
+    fn write_job(mut child: Child, receiver: mpsc::Receiver<Vec<u8>>) {
+        if let Some(mut stdin) = child.stdin.take() {
+            for chunk in receiver {
+                if stdin.write_all(&chunk).is_err() {
+                    log::error!("Print command exited before the end of the job.");
+                    break;
+                }
+            }
+            // Closing stdin signals the end of the job.
+        }
+        _ = child.wait();
+    }
+
+    fn finish_job(&mut self) {
+        self.send_job_output();
+        // Dropping the sender ends the writer thread once it has written everything sent.
+        self.job = None;
+    }
+
+    /// End the current print job, if any data has been printed.
+    pub fn end_job(&mut self) {
+        if self.job_bytes == 0 {
+            return;
+        }
+        log::debug!("Print job complete: {} bytes", self.job_bytes);
+        if let Some(capture) = &mut self.capture {
+            _ = capture.writer.flush();
+        }
+        self.finish_job();
+        self.job_bytes = 0;
+        self.job_count += 1;
+    }
+
+    pub fn run(&mut self, us: f64) {
+        if self.job_bytes > 0 {
+            self.send_job_output();
+            self.idle_us += us;
+            if self.idle_us >= self.job_timeout_us {
+                self.end_job();
+            }
+        }
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.end_job();
+    }
+}
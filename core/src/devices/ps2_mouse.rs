@@ -0,0 +1,554 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::ps2_mouse.rs
+
+    Implementation of a PS/2 mouse, attached to the auxiliary port of the 8042
+    keyboard controller.
+
+    The mouse reports movement and button state in 3-byte packets. In stream
+    mode, packets are sent at most at the configured sample rate whenever
+    there is something to report. In remote mode, the host must poll with the
+    Read Data command.
+
+    The PS/2 mouse reports Y movement as positive upwards, unlike the serial
+    mice and the host, which report positive downwards.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+// Default scale factor for host vs emulated mouse deltas, matching the serial mouse.
+const MOUSE_SCALE_DEFAULT: f64 = 0.25;
+
+pub const PS2_MOUSE_ID: u8 = 0x00;
+
+const RESPONSE_ACK: u8 = 0xFA;
+const RESPONSE_RESEND: u8 = 0xFE;
+const RESPONSE_ERROR: u8 = 0xFC;
+const RESPONSE_BAT_OK: u8 = 0xAA;
+
+const DEFAULT_SAMPLE_RATE: u8 = 100;
+const DEFAULT_RESOLUTION: u8 = 2; // 4 counts per mm
+
+// Packet byte 0 bits
+const PACKET_LBUTTON: u8 = 0b0000_0001;
+const PACKET_RBUTTON: u8 = 0b0000_0010;
+const PACKET_MBUTTON: u8 = 0b0000_0100;
+const PACKET_ALWAYS_ONE: u8 = 0b0000_1000;
+const PACKET_X_SIGN: u8 = 0b0001_0000;
+const PACKET_Y_SIGN: u8 = 0b0010_0000;
+const PACKET_X_OVERFLOW: u8 = 0b0100_0000;
+const PACKET_Y_OVERFLOW: u8 = 0b1000_0000;
+
+// Status byte bits, returned by Status Request. Note the button order differs from the packet.
+const STATUS_RBUTTON: u8 = 0b0000_0001;
+const STATUS_MBUTTON: u8 = 0b0000_0010;
+const STATUS_LBUTTON: u8 = 0b0000_0100;
+const STATUS_SCALING: u8 = 0b0001_0000;
+const STATUS_ENABLED: u8 = 0b0010_0000;
+const STATUS_REMOTE: u8 = 0b0100_0000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Ps2MouseMode {
+    Stream,
+    Remote,
+    Wrap,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum CommandParam {
+    SampleRate,
+    Resolution,
+}
+
+pub struct Ps2Mouse {
+    scale: f64,
+    mode: Ps2MouseMode,
+    wrap_return: Ps2MouseMode,
+    enabled: bool,
+    sample_rate: u8,
+    resolution: u8,
+    scaling_2to1: bool,
+    l_button: bool,
+    r_button: bool,
+    m_button: bool,
+    buttons_changed: bool,
+    accum_x: f64,
+    accum_y: f64,
+    sample_timer: f64,
+    pending_param: Option<CommandParam>,
+    responses: VecDeque<u8>,
+    last_byte: u8,
+}
+
+impl Ps2Mouse {
+    pub fn new(scale: Option<f64>) -> Self {
+        let mut mouse = Self {
+            scale: scale.unwrap_or(MOUSE_SCALE_DEFAULT),
+            mode: Ps2MouseMode::Stream,
+            wrap_return: Ps2MouseMode::Stream,
+            enabled: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            resolution: DEFAULT_RESOLUTION,
+            scaling_2to1: false,
+            l_button: false,
+            r_button: false,
+            m_button: false,
+            buttons_changed: false,
+            accum_x: 0.0,
+            accum_y: 0.0,
+            sample_timer: 0.0,
+            pending_param: None,
+            responses: VecDeque::new(),
+            last_byte: 0,
+        };
+        mouse.reset();
+        mouse
+    }
+
+    /// Reset the mouse. The mouse sends the BAT completion code and its ID after a reset.
+    pub fn reset(&mut self) {
+        self.set_defaults();
+        self.mode = Ps2MouseMode::Stream;
+        self.pending_param = None;
+        self.responses.clear();
+        self.responses.push_back(RESPONSE_BAT_OK);
+        self.responses.push_back(PS2_MOUSE_ID);
+    }
+
+    fn set_defaults(&mut self) {
+        self.enabled = false;
+        self.sample_rate = DEFAULT_SAMPLE_RATE;
+        self.resolution = DEFAULT_RESOLUTION;
+        self.scaling_2to1 = false;
+        self.clear_movement();
+    }
+
+    fn clear_movement(&mut self) {
+        self.accum_x = 0.0;
+        self.accum_y = 0.0;
+        self.buttons_changed = false;
+        self.sample_timer = 0.0;
+    }
+
+    pub fn mode(&self) -> Ps2MouseMode {
+        self.mode
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn sample_rate(&self) -> u8 {
+        self.sample_rate
+    }
+
+    /// Receive a mouse update from the host. Deltas are in host units, with positive Y downwards.
+    pub fn update(
+        &mut self,
+        l_button_pressed: bool,
+        r_button_pressed: bool,
+        m_button_pressed: bool,
+        delta_x: f64,
+        delta_y: f64,
+    ) {
+        if l_button_pressed != self.l_button || r_button_pressed != self.r_button || m_button_pressed != self.m_button {
+            self.buttons_changed = true;
+        }
+        self.l_button = l_button_pressed;
+        self.r_button = r_button_pressed;
+        self.m_button = m_button_pressed;
+
+        // Resolution 2 (4 counts per mm) is our reference scale.
+        let counts = self.scale * (1 << self.resolution) as f64 / 4.0;
+        self.accum_x += delta_x * counts;
+        self.accum_y -= delta_y * counts;
+    }
+
+    /// Receive a command or parameter byte from the host via the keyboard controller.
+    pub fn command(&mut self, byte: u8) {
+        if let Some(param) = self.pending_param.take() {
+            match param {
+                CommandParam::SampleRate => {
+                    if byte > 0 {
+                        log::debug!("PS/2 mouse: sample rate set to {}", byte);
+                        self.sample_rate = byte;
+                        self.respond(RESPONSE_ACK);
+                    }
+                    else {
+                        self.respond(RESPONSE_ERROR);
+                    }
+                }
+                CommandParam::Resolution => {
+                    if byte <= 3 {
+                        log::debug!("PS/2 mouse: resolution set to {} counts/mm", 1 << byte);
+                        self.resolution = byte;
+                        self.respond(RESPONSE_ACK);
+                    }
+                    else {
+                        self.respond(RESPONSE_ERROR);
+                    }
+                }
+            }
+            return;
+        }
+
+        // In wrap mode, everything but Reset and Reset Wrap Mode is echoed back.
+        if self.mode == Ps2MouseMode::Wrap && byte != 0xEC && byte != 0xFF {
+            self.respond(byte);
+            return;
+        }
+
+        match byte {
+            0xFF => {
+                log::debug!("PS/2 mouse: reset");
+                self.reset();
+                self.responses.push_front(RESPONSE_ACK);
+            }
+            0xFE => {
+                // Resend the last byte sent.
+                let last = self.last_byte;
+                self.respond(last);
+            }
+            0xF6 => {
+                self.set_defaults();
+                self.respond(RESPONSE_ACK);
+            }
+            0xF5 => {
+                self.enabled = false;
+                self.clear_movement();
+                self.respond(RESPONSE_ACK);
+            }
+            0xF4 => {
+                self.enabled = true;
+                self.clear_movement();
+                self.respond(RESPONSE_ACK);
+            }
+            0xF3 => {
+                self.pending_param = Some(CommandParam::SampleRate);
+                self.respond(RESPONSE_ACK);
+            }
+            0xF2 => {
+                self.respond(RESPONSE_ACK);
+                self.respond(PS2_MOUSE_ID);
+            }
+            0xF0 => {
+                self.mode = Ps2MouseMode::Remote;
+                self.clear_movement();
+                self.respond(RESPONSE_ACK);
+            }
+            0xEE => {
+                self.wrap_return = self.mode;
+                self.mode = Ps2MouseMode::Wrap;
+                self.clear_movement();
+                self.respond(RESPONSE_ACK);
+            }
+            0xEC => {
+                self.mode = self.wrap_return;
+                self.clear_movement();
+                self.respond(RESPONSE_ACK);
+            }
+            0xEB => {
+                self.respond(RESPONSE_ACK);
+                self.send_packet();
+            }
+            0xEA => {
+                self.mode = Ps2MouseMode::Stream;
+                self.clear_movement();
+                self.respond(RESPONSE_ACK);
+            }
+            0xE9 => {
+                self.respond(RESPONSE_ACK);
+                let status = self.status_byte();
+                self.respond(status);
+                self.respond(self.resolution);
+                self.respond(self.sample_rate);
+            }
+            0xE8 => {
+                self.pending_param = Some(CommandParam::Resolution);
+                self.respond(RESPONSE_ACK);
+            }
+            0xE7 => {
+                self.scaling_2to1 = true;
+                self.respond(RESPONSE_ACK);
+            }
+            0xE6 => {
+                self.scaling_2to1 = false;
+                self.respond(RESPONSE_ACK);
+            }
+            _ => {
+                log::warn!("PS/2 mouse: unsupported command: {:02X}", byte);
+                self.respond(RESPONSE_RESEND);
+            }
+        }
+    }
+
+    /// Return the next byte the mouse has to send, if any.
+    pub fn recv(&mut self) -> Option<u8> {
+        self.responses.pop_front()
+    }
+
+    fn respond(&mut self, byte: u8) {
+        if byte != RESPONSE_RESEND {
+            self.last_byte = byte;
+        }
+        self.responses.push_back(byte);
+    }
+
+    fn status_byte(&self) -> u8 {
+        let mut status = 0;
+        if self.l_button {
+            status |= STATUS_LBUTTON;
+        }
+        if self.r_button {
+            status |= STATUS_RBUTTON;
+        }
+        if self.m_button {
+            status |= STATUS_MBUTTON;
+        }
+        if self.scaling_2to1 {
+            status |= STATUS_SCALING;
+        }
+        if self.enabled {
+            status |= STATUS_ENABLED;
+        }
+        if self.mode == Ps2MouseMode::Remote {
+            status |= STATUS_REMOTE;
+        }
+        status
+    }
+
+    /// Apply 2:1 scaling to a movement count.
+    fn scale_2to1(count: i32) -> i32 {
+        match count.abs() {
+            0 => 0,
+            1 | 2 => count.signum(),
+            3 => count.signum() * 3,
+            4 => count.signum() * 6,
+            5 => count.signum() * 9,
+            _ => count * 2,
+        }
+    }
+
+    fn have_report(&self) -> bool {
+        self.buttons_changed || self.accum_x.abs() >= 1.0 || self.accum_y.abs() >= 1.0
+    }
+
+    /// Build a movement packet from the accumulated movement and queue it for sending.
+    fn send_packet(&mut self) {
+        let mut dx = self.accum_x.trunc() as i32;
+        let mut dy = self.accum_y.trunc() as i32;
+        self.accum_x -= dx as f64;
+        self.accum_y -= dy as f64;
+        self.buttons_changed = false;
+
+        if self.scaling_2to1 {
+            dx = Self::scale_2to1(dx);
+            dy = Self::scale_2to1(dy);
+        }
+
+        let mut byte0 = PACKET_ALWAYS_ONE;
+        if self.l_button {
+            byte0 |= PACKET_LBUTTON;
+        }
+        if self.r_button {
+            byte0 |= PACKET_RBUTTON;
+        }
+        if self.m_button {
+            byte0 |= PACKET_MBUTTON;
+        }
+        if !(-256..=255).contains(&dx) {
+            byte0 |= PACKET_X_OVERFLOW;
+            dx = dx.clamp(-256, 255);
+        }
+        if !(-256..=255).contains(&dy) {
+            byte0 |= PACKET_Y_OVERFLOW;
+            dy = dy.clamp(-256, 255);
+        }
+        if dx < 0 {
+            byte0 |= PACKET_X_SIGN;
+        }
+        if dy < 0 {
+            byte0 |= PACKET_Y_SIGN;
+        }
+
+        self.respond(byte0);
+        self.respond(dx as u8);
+        self.respond(dy as u8);
+    }
+
+    /// Run the mouse for the specified number of microseconds. In stream mode, a packet is sent once
+    /// per sample period if there has been movement or a button change.
+    pub fn run(&mut self, us: f64) {
+        if self.mode != Ps2MouseMode::Stream || !self.enabled {
+            return;
+        }
+
+        let period = 1_000_000.0 / self.sample_rate as f64;
+        self.sample_timer += us;
+        if self.sample_timer < period {
+            return;
+        }
+        self.sample_timer %= period;
+
+        // Don't send a new packet until the previous one has been taken by the controller.
+        if self.responses.is_empty() && self.have_report() {
+            self.send_packet();
+        }
+    }
+}
+
+impl SaveState for Ps2Mouse {
+    fn save_state(&self, w: &mut StateWriter) {
+        let mode = |mode: Ps2MouseMode| match mode {
+            Ps2MouseMode::Stream => 0,
+            Ps2MouseMode::Remote => 1,
+            Ps2MouseMode::Wrap => 2,
+        };
+        w.write_u8(mode(self.mode));
+        w.write_u8(mode(self.wrap_return));
+        w.write_bool(self.enabled);
+        w.write_u8(self.sample_rate);
+        w.write_u8(self.resolution);
+        w.write_bool(self.scaling_2to1);
+        w.write_bool(self.l_button);
+        w.write_bool(self.r_button);
+        w.write_bool(self.m_button);
+        w.write_bool(self.buttons_changed);
+        w.write_f64(self.accum_x);
+        w.write_f64(self.accum_y);
+        w.write_f64(self.sample_timer);
+        w.write_u8(match self.pending_param {
+            None => 0,
+            Some(CommandParam::SampleRate) => 1,
+            Some(CommandParam::Resolution) => 2,
+        });
+        w.write_bytes(&self.responses.iter().copied().collect::<Vec<u8>>());
+        w.write_u8(self.last_byte);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut read_mode = || match r.read_u8()? {
+            0 => Ok(Ps2MouseMode::Stream),
+            1 => Ok(Ps2MouseMode::Remote),
+            2 => Ok(Ps2MouseMode::Wrap),
+            _ => Err(SaveStateError::InvalidValue("PS/2 mouse mode")),
+        };
+        self.mode = read_mode()?;
+        self.wrap_return = read_mode()?;
+        self.enabled = r.read_bool()?;
+        self.sample_rate = r.read_u8()?;
+        self.resolution = r.read_u8()?;
+        self.scaling_2to1 = r.read_bool()?;
+        self.l_button = r.read_bool()?;
+        self.r_button = r.read_bool()?;
+        self.m_button = r.read_bool()?;
+        self.buttons_changed = r.read_bool()?;
+        self.accum_x = r.read_f64()?;
+        self.accum_y = r.read_f64()?;
+        self.sample_timer = r.read_f64()?;
+        self.pending_param = match r.read_u8()? {
+            0 => None,
+            1 => Some(CommandParam::SampleRate),
+            2 => Some(CommandParam::Resolution),
+            _ => return Err(SaveStateError::InvalidValue("PS/2 mouse parameter")),
+        };
+        self.responses = r.read_bytes()?.iter().copied().collect();
+        self.last_byte = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(mouse: &mut Ps2Mouse) -> Vec<u8> {
+        std::iter::from_fn(|| mouse.recv()).collect()
+    }
+
+    #[test]
+    fn test_ps2_mouse_reset_and_id() {
+        let mut mouse = Ps2Mouse::new(None);
+        assert_eq!(drain(&mut mouse), vec![0xAA, 0x00]);
+        mouse.command(0xFF);
+        assert_eq!(drain(&mut mouse), vec![0xFA, 0xAA, 0x00]);
+        mouse.command(0xF2);
+        assert_eq!(drain(&mut mouse), vec![0xFA, 0x00]);
+        mouse.command(0xF3);
+        mouse.command(40);
+        mouse.command(0xE9);
+        assert_eq!(drain(&mut mouse), vec![0xFA, 0xFA, 0xFA, 0x00, 0x02, 40]);
+    }
+
+    #[test]
+    fn test_ps2_mouse_stream_packet() {
+        let mut mouse = Ps2Mouse::new(Some(1.0));
+        drain(&mut mouse);
+
+        // Nothing is reported while the mouse is disabled.
+        mouse.update(true, false, false, 10.0, 10.0);
+        mouse.run(20_000.0);
+        assert!(drain(&mut mouse).is_empty());
+
+        mouse.command(0xF4);
+        assert_eq!(drain(&mut mouse), vec![0xFA]);
+        mouse.update(true, false, false, -5.0, 300.0);
+        mouse.run(20_000.0);
+        // Host Y is positive down, so a downward move is reported as negative and overflows.
+        assert_eq!(
+            drain(&mut mouse),
+            vec![
+                PACKET_ALWAYS_ONE | PACKET_LBUTTON | PACKET_X_SIGN | PACKET_Y_SIGN | PACKET_Y_OVERFLOW,
+                (-5i32) as u8,
+                (-256i32) as u8
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ps2_mouse_save_state() {
+        let mut mouse = Ps2Mouse::new(None);
+        drain(&mut mouse);
+        mouse.command(0xF0);
+        mouse.command(0xF3);
+
+        let mut w = StateWriter::new();
+        mouse.save_state(&mut w);
+        let data = w.into_bytes();
+
+        let mut restored = Ps2Mouse::new(None);
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.mode(), Ps2MouseMode::Remote);
+        assert_eq!(drain(&mut restored), vec![0xFA, 0xFA]);
+        // The sample rate command is still waiting for its parameter.
+        restored.command(40);
+        assert_eq!(restored.sample_rate(), 40);
+    }
+}
@@ -0,0 +1,673 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::rtc.rs
+
+    Implementation of the Motorola MC146818 Real Time Clock with battery-backed
+    CMOS RAM.
+
+    The MC146818 is accessed through an index register and a data register.
+    On the PC/AT these live at ports 70h and 71h, with bit 7 of the index
+    register doubling as the NMI mask. Various XT clock cards placed the same
+    chip at other addresses.
+
+    The first 14 registers hold the time, date, alarm and control registers.
+    The remaining 50 bytes are general purpose RAM, preserved by the battery.
+    The front end is responsible for persisting the contents of CMOS RAM
+    between sessions and for providing the initial time of day.
+
+    The clock is advanced by emulated time, not host time, so that guest
+    software sees a clock consistent with the speed of the emulated machine.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+pub const RTC_AT_PORT: u16 = 0x70;
+pub const RTC_AT_IRQ: u8 = 8;
+pub const RTC_CMOS_SIZE: usize = 64;
+
+const RTC_INDEX_MASK: u8 = 0x3F;
+const RTC_NMI_MASK_BIT: u8 = 0b1000_0000;
+
+// Time and date registers.
+const REG_SECONDS: usize = 0x00;
+const REG_SECONDS_ALARM: usize = 0x01;
+const REG_MINUTES: usize = 0x02;
+const REG_MINUTES_ALARM: usize = 0x03;
+const REG_HOURS: usize = 0x04;
+const REG_HOURS_ALARM: usize = 0x05;
+const REG_DAY_OF_WEEK: usize = 0x06;
+const REG_DAY_OF_MONTH: usize = 0x07;
+const REG_MONTH: usize = 0x08;
+const REG_YEAR: usize = 0x09;
+const REG_A: usize = 0x0A;
+const REG_B: usize = 0x0B;
+const REG_C: usize = 0x0C;
+const REG_D: usize = 0x0D;
+
+const REGA_UIP: u8 = 0b1000_0000; // Update in progress
+const REGA_DV_MASK: u8 = 0b0111_0000; // Divider select
+const REGA_DV_32K: u8 = 0b0010_0000; // Divider set for a 32.768KHz time base - the only one that runs the clock
+const REGA_RS_MASK: u8 = 0b0000_1111; // Periodic interrupt rate select
+
+const REGB_SET: u8 = 0b1000_0000; // Inhibit updates while the time is being set
+const REGB_PIE: u8 = 0b0100_0000; // Periodic interrupt enable
+const REGB_AIE: u8 = 0b0010_0000; // Alarm interrupt enable
+const REGB_UIE: u8 = 0b0001_0000; // Update-ended interrupt enable
+const REGB_SQWE: u8 = 0b0000_1000; // Square wave enable
+const REGB_DM: u8 = 0b0000_0100; // Data mode: set for binary, clear for BCD
+const REGB_24H: u8 = 0b0000_0010; // Set for 24 hour mode, clear for 12 hour mode
+const REGB_DSE: u8 = 0b0000_0001; // Daylight savings enable (not emulated)
+
+const REGC_IRQF: u8 = 0b1000_0000; // Interrupt request flag
+const REGC_PF: u8 = 0b0100_0000; // Periodic interrupt flag
+const REGC_AF: u8 = 0b0010_0000; // Alarm interrupt flag
+const REGC_UF: u8 = 0b0001_0000; // Update-ended interrupt flag
+
+const REGD_VRT: u8 = 0b1000_0000; // Valid RAM and time
+
+const HOUR_PM_BIT: u8 = 0b1000_0000;
+const ALARM_DONT_CARE: u8 = 0b1100_0000;
+
+// The update cycle takes 1984us, and UIP is asserted 244us before it begins.
+const UPDATE_CYCLE_US: f64 = 1984.0;
+const UIP_LEAD_US: f64 = 244.0;
+const SECOND_US: f64 = 1_000_000.0;
+
+/// The time of day used to initialize the clock, typically the host's local time.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RtcDateTime {
+    pub year: u16,       // Full year, ie, 1987
+    pub month: u8,       // 1-12
+    pub day: u8,         // 1-31
+    pub day_of_week: u8, // 1-7, Sunday = 1
+    pub hour: u8,        // 0-23
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Default)]
+pub struct RtcStringState {
+    pub time: String,
+    pub reg_a: String,
+    pub reg_b: String,
+    pub reg_c: String,
+    pub periodic_rate: String,
+    pub irq: String,
+}
+
+pub struct Rtc {
+    port_base: u16,
+    irq: u8,
+    at_style: bool,
+    index: u8,
+    nmi_masked: bool,
+    cmos: [u8; RTC_CMOS_SIZE],
+    cmos_dirty: bool,
+
+    // The current time is kept in binary, and converted to the format selected by register B on access.
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day_of_week: u8,
+    day: u8,
+    month: u8,
+    year: u8, // 0-99. The century is stored by software in CMOS RAM.
+
+    second_accum: f64,
+    periodic_accum: f64,
+    irq_line: bool,
+}
+
+impl IoDevice for Rtc {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port - self.port_base {
+            0 => {
+                // The index register is write-only on the AT.
+                NO_IO_BYTE
+            }
+            1 => self.read_register(self.index as usize),
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port - self.port_base {
+            0 => {
+                self.index = data & RTC_INDEX_MASK;
+                if self.at_style {
+                    self.nmi_masked = data & RTC_NMI_MASK_BIT != 0;
+                }
+            }
+            1 => self.write_register(self.index as usize, data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("RTC Index Register"), self.port_base),
+            (String::from("RTC Data Register"), self.port_base + 1),
+        ]
+    }
+}
+
+impl Rtc {
+    pub fn new(port_base: Option<u16>, irq: Option<u8>, at_style: bool) -> Self {
+        let mut cmos = [0; RTC_CMOS_SIZE];
+        cmos[REG_A] = REGA_DV_32K | 0x06;
+        cmos[REG_B] = REGB_24H;
+        cmos[REG_D] = REGD_VRT;
+
+        Self {
+            port_base: port_base.unwrap_or(RTC_AT_PORT),
+            irq: irq.unwrap_or(RTC_AT_IRQ),
+            at_style,
+            index: 0,
+            nmi_masked: false,
+            cmos,
+            cmos_dirty: false,
+            second: 0,
+            minute: 0,
+            hour: 0,
+            day_of_week: 1,
+            day: 1,
+            month: 1,
+            year: 80,
+            second_accum: 0.0,
+            periodic_accum: 0.0,
+            irq_line: false,
+        }
+    }
+
+    /// The RESET pin of the MC146818 clears the interrupt enables and flags, but the time and
+    /// CMOS RAM are battery backed and preserved.
+    pub fn reset(&mut self) {
+        self.cmos[REG_B] &= !(REGB_PIE | REGB_AIE | REGB_UIE | REGB_SQWE);
+        self.cmos[REG_C] = 0;
+        self.nmi_masked = false;
+        self.index = 0;
+    }
+
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    /// Return whether NMI has been masked via bit 7 of the index register (AT only).
+    pub fn nmi_masked(&self) -> bool {
+        self.nmi_masked
+    }
+
+    /// Set the current time of day.
+    pub fn set_time(&mut self, dt: &RtcDateTime) {
+        self.year = (dt.year % 100) as u8;
+        self.month = dt.month.clamp(1, 12);
+        self.day = dt.day.clamp(1, 31);
+        self.day_of_week = dt.day_of_week.clamp(1, 7);
+        self.hour = dt.hour % 24;
+        self.minute = dt.minute % 60;
+        self.second = dt.second % 60;
+        self.second_accum = 0.0;
+    }
+
+    /// Load the contents of CMOS RAM, ie, from a host file. The time and control registers are
+    /// not restored, as the clock is set from the host.
+    pub fn load_cmos(&mut self, data: &[u8]) {
+        let len = data.len().min(RTC_CMOS_SIZE);
+        if len > REG_D + 1 {
+            self.cmos[REG_D + 1..len].copy_from_slice(&data[REG_D + 1..len]);
+        }
+        self.cmos_dirty = false;
+    }
+
+    /// Return the contents of CMOS RAM for persistence.
+    pub fn cmos(&self) -> &[u8] {
+        &self.cmos
+    }
+
+    /// Return (and reset) whether CMOS RAM has been written since the last call.
+    pub fn cmos_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.cmos_dirty)
+    }
+
+    #[inline]
+    fn binary_mode(&self) -> bool {
+        self.cmos[REG_B] & REGB_DM != 0
+    }
+
+    #[inline]
+    fn to_bcd(byte: u8) -> u8 {
+        ((byte / 10) << 4) | (byte % 10)
+    }
+
+    #[inline]
+    fn from_bcd(byte: u8) -> u8 {
+        (byte >> 4) * 10 + (byte & 0x0F)
+    }
+
+    /// Convert a binary value into the data format selected by register B.
+    fn encode(&self, value: u8) -> u8 {
+        if self.binary_mode() {
+            value
+        }
+        else {
+            Rtc::to_bcd(value)
+        }
+    }
+
+    /// Convert a value written in the data format selected by register B to binary.
+    fn decode(&self, value: u8) -> u8 {
+        if self.binary_mode() {
+            value
+        }
+        else {
+            Rtc::from_bcd(value)
+        }
+    }
+
+    /// Convert an hour in 24 hour binary format to the format selected by register B.
+    fn encode_hour(&self, hour: u8) -> u8 {
+        if self.cmos[REG_B] & REGB_24H != 0 {
+            self.encode(hour)
+        }
+        else {
+            let pm = hour >= 12;
+            let hour12 = match hour % 12 {
+                0 => 12,
+                h => h,
+            };
+            self.encode(hour12) | if pm { HOUR_PM_BIT } else { 0 }
+        }
+    }
+
+    /// Convert an hour written in the format selected by register B to 24 hour binary format.
+    fn decode_hour(&self, value: u8) -> u8 {
+        if self.cmos[REG_B] & REGB_24H != 0 {
+            self.decode(value) % 24
+        }
+        else {
+            let pm = value & HOUR_PM_BIT != 0;
+            let hour12 = self.decode(value & !HOUR_PM_BIT) % 12;
+            if pm {
+                hour12 + 12
+            }
+            else {
+                hour12
+            }
+        }
+    }
+
+    fn read_register(&mut self, reg: usize) -> u8 {
+        match reg {
+            REG_SECONDS => self.encode(self.second),
+            REG_MINUTES => self.encode(self.minute),
+            REG_HOURS => self.encode_hour(self.hour),
+            REG_DAY_OF_WEEK => self.encode(self.day_of_week),
+            REG_DAY_OF_MONTH => self.encode(self.day),
+            REG_MONTH => self.encode(self.month),
+            REG_YEAR => self.encode(self.year),
+            REG_A => {
+                let mut byte = self.cmos[REG_A] & !REGA_UIP;
+                if self.update_in_progress() {
+                    byte |= REGA_UIP;
+                }
+                byte
+            }
+            REG_C => {
+                // Reading register C clears all interrupt flags.
+                let byte = self.cmos[REG_C];
+                self.cmos[REG_C] = 0;
+                byte
+            }
+            REG_D => {
+                // Reading register D sets VRT. It is only cleared by loss of battery power.
+                self.cmos[REG_D] = REGD_VRT;
+                REGD_VRT
+            }
+            _ => self.cmos[reg],
+        }
+    }
+
+    fn write_register(&mut self, reg: usize, data: u8) {
+        match reg {
+            REG_SECONDS => {
+                self.second = self.decode(data & 0x7F) % 60;
+                self.second_accum = 0.0;
+            }
+            REG_MINUTES => self.minute = self.decode(data) % 60,
+            REG_HOURS => self.hour = self.decode_hour(data),
+            REG_DAY_OF_WEEK => self.day_of_week = self.decode(data).clamp(1, 7),
+            REG_DAY_OF_MONTH => self.day = self.decode(data).clamp(1, 31),
+            REG_MONTH => self.month = self.decode(data).clamp(1, 12),
+            REG_YEAR => self.year = self.decode(data) % 100,
+            REG_A => {
+                // UIP is read-only.
+                let old_rate = self.cmos[REG_A] & REGA_RS_MASK;
+                self.cmos[REG_A] = data & !REGA_UIP;
+                if data & REGA_RS_MASK != old_rate {
+                    self.periodic_accum = 0.0;
+                }
+                self.cmos_dirty = true;
+            }
+            REG_B => {
+                if data & REGB_SET != 0 {
+                    // Setting SET aborts any update cycle and clears UIE.
+                    self.cmos[REG_B] = data & !REGB_UIE;
+                }
+                else {
+                    self.cmos[REG_B] = data;
+                }
+                if data & REGB_DSE != 0 {
+                    log::trace!("RTC: Daylight savings enable set (not emulated)");
+                }
+                self.cmos_dirty = true;
+            }
+            REG_C | REG_D => {
+                // Read-only.
+            }
+            _ => {
+                // Alarm registers and general purpose RAM.
+                if self.cmos[reg] != data {
+                    self.cmos_dirty = true;
+                }
+                self.cmos[reg] = data;
+            }
+        }
+    }
+
+    /// Return whether the oscillator is running. Only the 32.768KHz divider setting runs the clock.
+    #[inline]
+    fn oscillator_running(&self) -> bool {
+        self.cmos[REG_A] & REGA_DV_MASK == REGA_DV_32K
+    }
+
+    #[inline]
+    fn update_in_progress(&self) -> bool {
+        self.oscillator_running()
+            && self.cmos[REG_B] & REGB_SET == 0
+            && self.second_accum >= SECOND_US - UIP_LEAD_US - UPDATE_CYCLE_US
+    }
+
+    /// Return the period of the periodic interrupt in microseconds, or None if disabled.
+    /// Rates 1 and 2 alias to rates 8 and 9 with a 32.768KHz time base.
+    pub fn periodic_period_us(&self) -> Option<f64> {
+        let rs = match self.cmos[REG_A] & REGA_RS_MASK {
+            0 => return None,
+            1 => 8,
+            2 => 9,
+            rs => rs,
+        };
+        let freq = 32768.0 / (1u32 << (rs - 1)) as f64;
+        Some(SECOND_US / freq)
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 => {
+                // The MC146818 treats every year divisible by 4 as a leap year.
+                if self.year % 4 == 0 {
+                    29
+                }
+                else {
+                    28
+                }
+            }
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Advance the clock by one second.
+    fn tick_second(&mut self) {
+        self.second += 1;
+        if self.second < 60 {
+            return;
+        }
+        self.second = 0;
+        self.minute += 1;
+        if self.minute < 60 {
+            return;
+        }
+        self.minute = 0;
+        self.hour += 1;
+        if self.hour < 24 {
+            return;
+        }
+        self.hour = 0;
+        self.day_of_week = self.day_of_week % 7 + 1;
+        self.day += 1;
+        if self.day <= self.days_in_month() {
+            return;
+        }
+        self.day = 1;
+        self.month += 1;
+        if self.month <= 12 {
+            return;
+        }
+        self.month = 1;
+        self.year = (self.year + 1) % 100;
+    }
+
+    /// Compare an alarm register against the current time. An alarm value with both high bits set
+    /// matches any value.
+    fn alarm_matches(&self, alarm: u8, current: u8) -> bool {
+        alarm & ALARM_DONT_CARE == ALARM_DONT_CARE || alarm == current
+    }
+
+    fn check_alarm(&self) -> bool {
+        self.alarm_matches(self.cmos[REG_SECONDS_ALARM], self.encode(self.second))
+            && self.alarm_matches(self.cmos[REG_MINUTES_ALARM], self.encode(self.minute))
+            && self.alarm_matches(self.cmos[REG_HOURS_ALARM], self.encode_hour(self.hour))
+    }
+
+    /// Update the IRQF flag from the interrupt flags and their enables.
+    fn update_irqf(&mut self) {
+        let flags = self.cmos[REG_C];
+        let enables = self.cmos[REG_B];
+        let irq = (flags & REGC_PF != 0 && enables & REGB_PIE != 0)
+            || (flags & REGC_AF != 0 && enables & REGB_AIE != 0)
+            || (flags & REGC_UF != 0 && enables & REGB_UIE != 0);
+        if irq {
+            self.cmos[REG_C] |= REGC_IRQF;
+        }
+    }
+
+    /// Run the RTC for the specified number of microseconds.
+    /// Returns Some(state) if the state of the IRQ line changed.
+    pub fn run(&mut self, us: f64) -> Option<bool> {
+        if self.oscillator_running() {
+            if let Some(period) = self.periodic_period_us() {
+                self.periodic_accum += us;
+                while self.periodic_accum >= period {
+                    self.periodic_accum -= period;
+                    self.cmos[REG_C] |= REGC_PF;
+                }
+            }
+
+            self.second_accum += us;
+            while self.second_accum >= SECOND_US {
+                self.second_accum -= SECOND_US;
+                if self.cmos[REG_B] & REGB_SET == 0 {
+                    self.tick_second();
+                    self.cmos[REG_C] |= REGC_UF;
+                    if self.check_alarm() {
+                        self.cmos[REG_C] |= REGC_AF;
+                    }
+                }
+            }
+            self.update_irqf();
+        }
+
+        let irq_line = self.cmos[REG_C] & REGC_IRQF != 0;
+        if irq_line != self.irq_line {
+            self.irq_line = irq_line;
+            return Some(irq_line);
+        }
+        None
+    }
+
+    pub fn get_string_state(&self) -> RtcStringState {
+        RtcStringState {
+            time: format!(
+                "{:02}-{:02}-{:02} {:02}:{:02}:{:02}",
+                self.year, self.month, self.day, self.hour, self.minute, self.second
+            ),
+            reg_a: format!("{:08b}", self.cmos[REG_A]),
+            reg_b: format!("{:08b}", self.cmos[REG_B]),
+            reg_c: format!("{:08b}", self.cmos[REG_C]),
+            periodic_rate: match self.periodic_period_us() {
+                Some(period) => format!("{:.3}us", period),
+                None => "Disabled".to_string(),
+            },
+            irq: format!("{}", self.irq_line),
+        }
+    }
+}
+
+/// Restoring a state replaces the contents of CMOS RAM, which is then marked dirty so that the
+/// front end persists it.
+impl SaveState for Rtc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.index);
+        w.write_bool(self.nmi_masked);
+        w.write_bytes(&self.cmos);
+        for value in [
+            self.second,
+            self.minute,
+            self.hour,
+            self.day_of_week,
+            self.day,
+            self.month,
+            self.year,
+        ] {
+            w.write_u8(value);
+        }
+        w.write_f64(self.second_accum);
+        w.write_f64(self.periodic_accum);
+        w.write_bool(self.irq_line);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.index = r.read_u8()? & RTC_INDEX_MASK;
+        self.nmi_masked = r.read_bool()?;
+        r.read_bytes_into(&mut self.cmos, "CMOS RAM size")?;
+        self.second = r.read_u8()? % 60;
+        self.minute = r.read_u8()? % 60;
+        self.hour = r.read_u8()? % 24;
+        self.day_of_week = r.read_u8()?.clamp(1, 7);
+        self.day = r.read_u8()?.clamp(1, 31);
+        self.month = r.read_u8()?.clamp(1, 12);
+        self.year = r.read_u8()? % 100;
+        self.second_accum = r.read_f64()?;
+        self.periodic_accum = r.read_f64()?;
+        self.irq_line = r.read_bool()?;
+        self.cmos_dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtc_rollover() {
+        let mut rtc = Rtc::new(None, None, true);
+        rtc.set_time(&RtcDateTime {
+            year: 1999,
+            month: 12,
+            day: 31,
+            day_of_week: 6,
+            hour: 23,
+            minute: 59,
+            second: 59,
+        });
+        rtc.run(SECOND_US);
+
+        assert_eq!(rtc.read_register(REG_YEAR), 0x00);
+        assert_eq!(rtc.read_register(REG_MONTH), 0x01);
+        assert_eq!(rtc.read_register(REG_DAY_OF_MONTH), 0x01);
+        assert_eq!(rtc.read_register(REG_DAY_OF_WEEK), 0x07);
+        assert_eq!(rtc.read_register(REG_HOURS), 0x00);
+        // Update-ended flag should be set, but no IRQ as interrupts are disabled.
+        let reg_c = rtc.read_register(REG_C);
+        assert_ne!(reg_c & REGC_UF, 0);
+        assert_eq!(reg_c & REGC_IRQF, 0);
+    }
+
+    #[test]
+    fn test_rtc_periodic_irq() {
+        let mut rtc = Rtc::new(None, None, true);
+        rtc.write_register(REG_B, REGB_24H | REGB_PIE);
+        // Default rate 6 is 1024Hz.
+        assert_eq!(rtc.run(900.0), None);
+        assert_eq!(rtc.run(100.0), Some(true));
+        assert_eq!(rtc.read_register(REG_C), REGC_IRQF | REGC_PF);
+        assert_eq!(rtc.run(1.0), Some(false));
+    }
+
+    #[test]
+    fn test_rtc_12_hour_mode() {
+        let mut rtc = Rtc::new(None, None, true);
+        rtc.write_register(REG_B, 0);
+        rtc.write_register(REG_HOURS, 0x12 | HOUR_PM_BIT);
+        assert_eq!(rtc.hour, 12);
+        rtc.write_register(REG_HOURS, 0x12);
+        assert_eq!(rtc.hour, 0);
+        rtc.write_register(REG_B, REGB_24H | REGB_DM);
+        rtc.write_register(REG_HOURS, 15);
+        rtc.write_register(REG_B, 0);
+        assert_eq!(rtc.read_register(REG_HOURS), 0x03 | HOUR_PM_BIT);
+    }
+
+    #[test]
+    fn test_rtc_save_state() {
+        let mut rtc = Rtc::new(None, None, true);
+        rtc.write_register(REG_MINUTES, 0x42);
+        rtc.write_register(0x20, 0x5A);
+        rtc.write_u8(RTC_AT_PORT, 0x8E, None, DeviceRunTimeUnit::Microseconds(0.0));
+        rtc.run(SECOND_US / 2.0);
+        let mut w = StateWriter::new();
+        rtc.save_state(&mut w);
+        let data = w.into_bytes();
+
+        let mut restored = Rtc::new(None, None, true);
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.read_register(REG_MINUTES), 0x42);
+        assert_eq!(restored.cmos()[0x20], 0x5A);
+        assert_eq!(restored.index, 0x0E);
+        assert!(restored.nmi_masked());
+        assert!(restored.cmos_dirty());
+
+        // Half a second remains until the next update.
+        restored.run(SECOND_US / 2.0);
+        assert_eq!(restored.read_register(REG_SECONDS), 0x01);
+    }
+}
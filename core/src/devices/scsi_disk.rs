@@ -0,0 +1,349 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::scsi_disk.rs
+
+    Implements the command set of a SCSI direct-access device (hard disk),
+    backed by a VirtualHardDisk. The disk is attached to a SCSI host adapter,
+    which is responsible for the bus phases; see devices::seagate_st01.
+
+*/
+
+use crate::{
+    devices::hdc::SECTOR_SIZE,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    vhd::VirtualHardDisk,
+};
+
+pub const SCSI_STATUS_GOOD: u8 = 0x00;
+pub const SCSI_STATUS_CHECK_CONDITION: u8 = 0x02;
+
+const CMD_TEST_UNIT_READY: u8 = 0x00;
+const CMD_REZERO_UNIT: u8 = 0x01;
+const CMD_REQUEST_SENSE: u8 = 0x03;
+const CMD_FORMAT_UNIT: u8 = 0x04;
+const CMD_READ_6: u8 = 0x08;
+const CMD_WRITE_6: u8 = 0x0A;
+const CMD_SEEK_6: u8 = 0x0B;
+const CMD_INQUIRY: u8 = 0x12;
+const CMD_MODE_SELECT_6: u8 = 0x15;
+const CMD_RESERVE: u8 = 0x16;
+const CMD_RELEASE: u8 = 0x17;
+const CMD_MODE_SENSE_6: u8 = 0x1A;
+const CMD_START_STOP_UNIT: u8 = 0x1B;
+const CMD_SEND_DIAGNOSTIC: u8 = 0x1D;
+const CMD_PREVENT_ALLOW_REMOVAL: u8 = 0x1E;
+const CMD_READ_CAPACITY: u8 = 0x25;
+const CMD_READ_10: u8 = 0x28;
+const CMD_WRITE_10: u8 = 0x2A;
+const CMD_SEEK_10: u8 = 0x2B;
+const CMD_VERIFY_10: u8 = 0x2F;
+const CMD_SYNCHRONIZE_CACHE: u8 = 0x35;
+
+const SENSE_NONE: u8 = 0x00;
+const SENSE_MEDIUM_ERROR: u8 = 0x03;
+const SENSE_ILLEGAL_REQUEST: u8 = 0x05;
+
+const ASC_UNRECOVERED_READ_ERROR: u8 = 0x11;
+const ASC_INVALID_COMMAND: u8 = 0x20;
+const ASC_LBA_OUT_OF_RANGE: u8 = 0x21;
+const ASC_INVALID_FIELD: u8 = 0x24;
+const ASC_WRITE_ERROR: u8 = 0x0C;
+
+const MODE_PAGE_FORMAT: u8 = 0x03;
+const MODE_PAGE_GEOMETRY: u8 = 0x04;
+const MODE_PAGE_ALL: u8 = 0x3F;
+
+/// The outcome of a command, to be carried out by the host adapter.
+#[derive(Debug, PartialEq)]
+pub enum ScsiResult {
+    /// The command is complete with the specified status byte.
+    Status(u8),
+    /// The command has data to transfer to the initiator, after which the status is GOOD.
+    DataIn(Vec<u8>),
+    /// The command expects the specified number of bytes from the initiator, to be passed to data_out().
+    DataOut(usize),
+}
+
+/// Return the length of a command descriptor block given its operation code.
+pub fn cdb_len(opcode: u8) -> usize {
+    match opcode >> 5 {
+        1 | 2 => 10,
+        5 => 12,
+        _ => 6,
+    }
+}
+
+pub struct ScsiDisk {
+    vhd: VirtualHardDisk,
+    sense_key: u8,
+    asc: u8,
+    // Destination of data expected from the initiator by a WRITE command
+    write_lba: Option<u32>,
+}
+
+impl ScsiDisk {
+    pub fn new(vhd: VirtualHardDisk) -> Self {
+        Self {
+            vhd,
+            sense_key: SENSE_NONE,
+            asc: 0,
+            write_lba: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.sense_key = SENSE_NONE;
+        self.asc = 0;
+        self.write_lba = None;
+    }
+
+    /// Return the identity of the disk image, for save states.
+    pub fn identity(&self) -> String {
+        self.vhd.identity()
+    }
+
+    fn check_condition(&mut self, sense_key: u8, asc: u8) -> ScsiResult {
+        self.sense_key = sense_key;
+        self.asc = asc;
+        ScsiResult::Status(SCSI_STATUS_CHECK_CONDITION)
+    }
+
+    fn good(&mut self) -> ScsiResult {
+        self.sense_key = SENSE_NONE;
+        self.asc = 0;
+        ScsiResult::Status(SCSI_STATUS_GOOD)
+    }
+
+    fn in_range(&self, lba: u32, count: u32) -> bool {
+        lba as u64 + count as u64 <= self.vhd.total_sectors() as u64
+    }
+
+    /// Execute a command descriptor block.
+    pub fn command(&mut self, cdb: &[u8]) -> ScsiResult {
+        log::trace!("SCSI disk: Command {:02X?}", cdb);
+        self.write_lba = None;
+
+        match cdb[0] {
+            CMD_TEST_UNIT_READY
+            | CMD_REZERO_UNIT
+            | CMD_FORMAT_UNIT
+            | CMD_RESERVE
+            | CMD_RELEASE
+            | CMD_START_STOP_UNIT
+            | CMD_SEND_DIAGNOSTIC
+            | CMD_PREVENT_ALLOW_REMOVAL
+            | CMD_SYNCHRONIZE_CACHE => self.good(),
+            CMD_REQUEST_SENSE => {
+                let mut sense = vec![0; 18];
+                sense[0] = 0x70; // Current error
+                sense[2] = self.sense_key;
+                sense[7] = 10; // Additional sense length
+                sense[12] = self.asc;
+                self.sense_key = SENSE_NONE;
+                self.asc = 0;
+                // An allocation length of 0 requests 4 bytes of sense data.
+                let len = match cdb[4] {
+                    0 => 4,
+                    n => n as usize,
+                };
+                sense.truncate(len);
+                ScsiResult::DataIn(sense)
+            }
+            CMD_INQUIRY => {
+                let mut inquiry = vec![0; 36];
+                inquiry[0] = 0x00; // Direct-access device
+                inquiry[2] = 0x02; // SCSI-2
+                inquiry[3] = 0x02; // Response data format
+                inquiry[4] = 31; // Additional length
+                inquiry[8..16].copy_from_slice(b"MARTYPC ");
+                inquiry[16..32].copy_from_slice(b"SCSI DISK       ");
+                inquiry[32..36].copy_from_slice(b"1.0 ");
+                inquiry.truncate(cdb[4] as usize);
+                self.good();
+                ScsiResult::DataIn(inquiry)
+            }
+            CMD_READ_6 | CMD_WRITE_6 => {
+                let lba = ((cdb[1] & 0x1F) as u32) << 16 | (cdb[2] as u32) << 8 | cdb[3] as u32;
+                // A transfer length of 0 requests 256 blocks.
+                let count = match cdb[4] {
+                    0 => 256,
+                    n => n as u32,
+                };
+                self.transfer(cdb[0] == CMD_READ_6, lba, count)
+            }
+            CMD_READ_10 | CMD_WRITE_10 => {
+                let lba = u32::from_be_bytes([cdb[2], cdb[3], cdb[4], cdb[5]]);
+                let count = u16::from_be_bytes([cdb[7], cdb[8]]) as u32;
+                self.transfer(cdb[0] == CMD_READ_10, lba, count)
+            }
+            CMD_SEEK_6 => {
+                let lba = ((cdb[1] & 0x1F) as u32) << 16 | (cdb[2] as u32) << 8 | cdb[3] as u32;
+                self.seek(lba)
+            }
+            CMD_SEEK_10 => self.seek(u32::from_be_bytes([cdb[2], cdb[3], cdb[4], cdb[5]])),
+            CMD_VERIFY_10 => {
+                let lba = u32::from_be_bytes([cdb[2], cdb[3], cdb[4], cdb[5]]);
+                let count = u16::from_be_bytes([cdb[7], cdb[8]]) as u32;
+                if self.in_range(lba, count) {
+                    self.good()
+                }
+                else {
+                    self.check_condition(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE)
+                }
+            }
+            CMD_READ_CAPACITY => {
+                let last_lba = self.vhd.total_sectors().saturating_sub(1);
+                let mut capacity = last_lba.to_be_bytes().to_vec();
+                capacity.extend_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+                self.good();
+                ScsiResult::DataIn(capacity)
+            }
+            CMD_MODE_SENSE_6 => self.mode_sense(cdb),
+            CMD_MODE_SELECT_6 => match cdb[4] {
+                0 => self.good(),
+                len => ScsiResult::DataOut(len as usize),
+            },
+            _ => {
+                log::debug!("SCSI disk: Unsupported command {:02X}", cdb[0]);
+                self.check_condition(SENSE_ILLEGAL_REQUEST, ASC_INVALID_COMMAND)
+            }
+        }
+    }
+
+    /// Receive the data of a command that returned ScsiResult::DataOut, returning the status byte.
+    pub fn data_out(&mut self, data: &[u8]) -> u8 {
+        // MODE SELECT parameters are accepted and ignored.
+        let lba = match self.write_lba.take() {
+            Some(lba) => lba,
+            None => return SCSI_STATUS_GOOD,
+        };
+
+        for (i, sector) in data.chunks_exact(SECTOR_SIZE).enumerate() {
+            let sector_lba = lba + i as u32;
+            if let Err(e) = self.vhd.write_sector_lba(sector, sector_lba) {
+                log::error!("SCSI disk: Error writing sector {}: {}", sector_lba, e);
+                self.sense_key = SENSE_MEDIUM_ERROR;
+                self.asc = ASC_WRITE_ERROR;
+                return SCSI_STATUS_CHECK_CONDITION;
+            }
+        }
+        SCSI_STATUS_GOOD
+    }
+
+    fn seek(&mut self, lba: u32) -> ScsiResult {
+        if self.in_range(lba, 1) {
+            self.good()
+        }
+        else {
+            self.check_condition(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE)
+        }
+    }
+
+    fn transfer(&mut self, read: bool, lba: u32, count: u32) -> ScsiResult {
+        if count == 0 {
+            return self.good();
+        }
+        if !self.in_range(lba, count) {
+            return self.check_condition(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE);
+        }
+
+        if !read {
+            self.write_lba = Some(lba);
+            self.good();
+            return ScsiResult::DataOut(count as usize * SECTOR_SIZE);
+        }
+
+        let mut data = vec![0; count as usize * SECTOR_SIZE];
+        for (i, sector) in data.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            let sector_lba = lba + i as u32;
+            if let Err(e) = self.vhd.read_sector_lba(sector, sector_lba) {
+                log::error!("SCSI disk: Error reading sector {}: {}", sector_lba, e);
+                return self.check_condition(SENSE_MEDIUM_ERROR, ASC_UNRECOVERED_READ_ERROR);
+            }
+        }
+        self.good();
+        ScsiResult::DataIn(data)
+    }
+
+    fn mode_sense(&mut self, cdb: &[u8]) -> ScsiResult {
+        let page_code = cdb[2] & 0x3F;
+        let disable_block_descriptors = cdb[1] & 0x08 != 0;
+
+        let mut data = vec![0; 4];
+        if !disable_block_descriptors {
+            let blocks = std::cmp::min(self.vhd.total_sectors(), 0x00FF_FFFF);
+            data[3] = 8; // Block descriptor length
+            data.extend_from_slice(&blocks.to_be_bytes());
+            data.extend_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+        }
+
+        if page_code == MODE_PAGE_FORMAT || page_code == MODE_PAGE_ALL {
+            let mut page = vec![0; 24];
+            page[0] = MODE_PAGE_FORMAT;
+            page[1] = 22;
+            page[10..12].copy_from_slice(&(self.vhd.max_sectors as u16).to_be_bytes());
+            page[12..14].copy_from_slice(&(SECTOR_SIZE as u16).to_be_bytes());
+            data.extend_from_slice(&page);
+        }
+        if page_code == MODE_PAGE_GEOMETRY || page_code == MODE_PAGE_ALL {
+            let cylinders = self.vhd.total_sectors() / (self.vhd.max_heads * self.vhd.max_sectors);
+            let mut page = vec![0; 24];
+            page[0] = MODE_PAGE_GEOMETRY;
+            page[1] = 22;
+            page[2..5].copy_from_slice(&cylinders.to_be_bytes()[1..4]);
+            page[5] = self.vhd.max_heads as u8;
+            data.extend_from_slice(&page);
+        }
+        if page_code != 0 && data.len() == 4 + data[3] as usize {
+            return self.check_condition(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD);
+        }
+
+        data[0] = (data.len() - 1) as u8; // Mode data length
+        data.truncate(cdb[4] as usize);
+        self.good();
+        ScsiResult::DataIn(data)
+    }
+}
+
+/// The disk image is not part of the state; the host adapter records its identity.
+impl SaveState for ScsiDisk {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.sense_key);
+        w.write_u8(self.asc);
+        w.write_bool(self.write_lba.is_some());
+        w.write_u32(self.write_lba.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sense_key = r.read_u8()?;
+        self.asc = r.read_u8()?;
+        let write_pending = r.read_bool()?;
+        let write_lba = r.read_u32()?;
+        self.write_lba = write_pending.then_some(write_lba);
+        Ok(())
+    }
+}
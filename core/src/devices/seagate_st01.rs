@@ -0,0 +1,502 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::seagate_st01.rs
+
+    Implements a Seagate ST-01 SCSI host adapter.
+
+    The ST-01 has no IO ports. Its option ROM, a small scratch RAM, and its
+    control/status and data registers all appear within an 8K memory window,
+    typically at C800:
+
+      0000-17FF  Option ROM
+      1800-19FF  Scratch RAM
+      1A00-1BFF  Control register (write) / Status register (read)
+      1C00-1FFF  Data register
+
+    The data register is mirrored across its range so that blocks can be
+    transferred with REP MOVSB. The ROM is loaded by the ROM manager like any
+    other option ROM; the adapter passes reads of the ROM range through to
+    memory.
+
+    The REQ/ACK handshake is performed automatically by accesses to the data
+    register. The adapter is operated in polled mode; reselection and the
+    interrupt are not supported.
+
+*/
+
+use crate::{
+    bus::{MemRangeDescriptor, MemoryMappedDevice},
+    device_types::hdc::HardDiskFormat,
+    devices::{
+        hdc::ControllerError,
+        scsi_disk::{cdb_len, ScsiDisk, ScsiResult, SCSI_STATUS_GOOD},
+    },
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    vhd::VirtualHardDisk,
+};
+
+pub const ST01_DEFAULT_WINDOW_SEG: u32 = 0xC800;
+pub const ST01_WINDOW_SIZE: usize = 0x2000;
+pub const ST01_MAX_DRIVES: usize = 2;
+// The adapter itself is SCSI ID 7, leaving IDs 0-6 for targets.
+pub const ST01_MAX_TARGETS: usize = 7;
+const HOST_ID_BIT: u8 = 0x80;
+
+const RAM_OFFSET: usize = 0x1800;
+const RAM_SIZE: usize = 0x200;
+const REG_CONTROL_STATUS: usize = 0x1A00;
+const REG_DATA: usize = 0x1C00;
+
+const CONTROL_RST: u8 = 0b0000_0001;
+const CONTROL_SEL: u8 = 0b0000_0010;
+const CONTROL_BSY: u8 = 0b0000_0100;
+const CONTROL_ATN: u8 = 0b0000_1000;
+const CONTROL_ARBITRATE: u8 = 0b0001_0000;
+const CONTROL_DRIVER_ENABLE: u8 = 0b1000_0000;
+
+const STATUS_BSY: u8 = 0b0000_0001;
+const STATUS_MSG: u8 = 0b0000_0010;
+const STATUS_IO: u8 = 0b0000_0100;
+const STATUS_CD: u8 = 0b0000_1000;
+const STATUS_REQ: u8 = 0b0001_0000;
+const STATUS_SEL: u8 = 0b0010_0000;
+const STATUS_ARB_COMPLETE: u8 = 0b1000_0000;
+
+const MSG_COMMAND_COMPLETE: u8 = 0x00;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum BusPhase {
+    Free,
+    // A target has responded to selection and is waiting for SEL to be released.
+    Selected,
+    MessageOut,
+    Command,
+    DataIn,
+    DataOut,
+    Status,
+    MessageIn,
+}
+
+impl BusPhase {
+    /// Return the state of the MSG, C/D and I/O lines signalling this phase.
+    fn signals(&self) -> u8 {
+        match self {
+            BusPhase::Free | BusPhase::Selected => 0,
+            BusPhase::MessageOut => STATUS_MSG | STATUS_CD,
+            BusPhase::Command => STATUS_CD,
+            BusPhase::DataIn => STATUS_IO,
+            BusPhase::DataOut => 0,
+            BusPhase::Status => STATUS_CD | STATUS_IO,
+            BusPhase::MessageIn => STATUS_MSG | STATUS_CD | STATUS_IO,
+        }
+    }
+}
+
+pub struct SeagateSt01 {
+    window_addr: usize,
+    ram: Vec<u8>,
+    targets: [Option<ScsiDisk>; ST01_MAX_TARGETS],
+    supported_formats: Vec<HardDiskFormat>,
+
+    control: u8,
+    data_bus: u8,
+    phase: BusPhase,
+    target: usize,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    status_byte: u8,
+}
+
+impl SeagateSt01 {
+    pub fn new(window_seg: Option<u32>) -> Self {
+        let window_seg = window_seg.unwrap_or(ST01_DEFAULT_WINDOW_SEG);
+        if window_seg & 0x1FF != 0 {
+            log::warn!("ST-01: Memory window {:04X} is not 8K aligned", window_seg);
+        }
+
+        Self {
+            window_addr: ((window_seg & !0x1FF) as usize) << 4,
+            ram: vec![0; RAM_SIZE],
+            targets: Default::default(),
+            supported_formats: vec![
+                HardDiskFormat {
+                    max_cylinders: 615,
+                    max_heads: 4,
+                    max_sectors: 17,
+                    wpc: None,
+                    desc: "20MB".to_string(),
+                },
+                HardDiskFormat {
+                    max_cylinders: 977,
+                    max_heads: 5,
+                    max_sectors: 17,
+                    wpc: None,
+                    desc: "40MB".to_string(),
+                },
+                HardDiskFormat {
+                    max_cylinders: 1024,
+                    max_heads: 12,
+                    max_sectors: 17,
+                    wpc: None,
+                    desc: "100MB".to_string(),
+                },
+            ],
+            control: 0,
+            data_bus: 0,
+            phase: BusPhase::Free,
+            target: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            status_byte: SCSI_STATUS_GOOD,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        log::trace!("Resetting ST-01 SCSI adapter...");
+        self.control = 0;
+        self.bus_reset();
+    }
+
+    fn bus_reset(&mut self) {
+        self.phase = BusPhase::Free;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        for target in self.targets.iter_mut().flatten() {
+            target.reset();
+        }
+    }
+
+    pub fn drive_ct(&self) -> usize {
+        ST01_MAX_DRIVES
+    }
+
+    pub fn get_supported_formats(&self) -> Vec<HardDiskFormat> {
+        self.supported_formats.clone()
+    }
+
+    /// Attach a disk image to the specified drive. Drive numbers correspond to SCSI IDs.
+    pub fn set_vhd(&mut self, device_id: usize, vhd: VirtualHardDisk) -> Result<(), ControllerError> {
+        if device_id >= ST01_MAX_DRIVES {
+            return Err(ControllerError::InvalidDevice);
+        }
+        self.targets[device_id] = Some(ScsiDisk::new(vhd));
+        Ok(())
+    }
+
+    fn read_status(&self) -> u8 {
+        let mut status = self.phase.signals();
+        if self.phase != BusPhase::Free {
+            status |= STATUS_BSY;
+        }
+        if !matches!(self.phase, BusPhase::Free | BusPhase::Selected) {
+            status |= STATUS_REQ;
+        }
+        if self.control & CONTROL_SEL != 0 {
+            status |= STATUS_SEL;
+        }
+        if self.control & CONTROL_ARBITRATE != 0 {
+            // There is no other initiator on the bus, so arbitration is won immediately.
+            status |= STATUS_ARB_COMPLETE;
+        }
+        status
+    }
+
+    fn write_control(&mut self, data: u8) {
+        let prev = self.control;
+        self.control = data;
+
+        if data & CONTROL_RST != 0 {
+            self.bus_reset();
+            return;
+        }
+
+        let sel_asserted = data & CONTROL_SEL != 0 && prev & CONTROL_SEL == 0;
+        let sel_released = data & CONTROL_SEL == 0 && prev & CONTROL_SEL != 0;
+
+        if sel_asserted && self.phase == BusPhase::Free && data & CONTROL_DRIVER_ENABLE != 0 {
+            self.select();
+        }
+        else if sel_released && self.phase == BusPhase::Selected {
+            // The target takes control of the bus. If ATN was raised, the initiator has a message to send.
+            self.enter_phase(if data & CONTROL_ATN != 0 {
+                BusPhase::MessageOut
+            }
+            else {
+                BusPhase::Command
+            });
+        }
+        else if data & CONTROL_SEL == 0 && data & CONTROL_BSY == 0 && self.phase == BusPhase::Selected {
+            // Selection was abandoned.
+            self.phase = BusPhase::Free;
+        }
+    }
+
+    /// Respond to a selection of the target(s) whose ID bits are on the data bus.
+    fn select(&mut self) {
+        let mut ids = self.data_bus;
+        if ids.count_ones() > 1 {
+            ids &= !HOST_ID_BIT;
+        }
+        let target = ids.trailing_zeros() as usize;
+
+        if target < ST01_MAX_TARGETS && self.targets[target].is_some() {
+            log::trace!("ST-01: Selected target {}", target);
+            self.target = target;
+            self.phase = BusPhase::Selected;
+        }
+    }
+
+    fn enter_phase(&mut self, phase: BusPhase) {
+        self.phase = phase;
+        self.buffer_pos = 0;
+        match phase {
+            BusPhase::MessageOut => self.buffer = vec![0; 1],
+            BusPhase::Command => self.buffer.clear(),
+            BusPhase::Status => self.buffer = vec![self.status_byte],
+            BusPhase::MessageIn => self.buffer = vec![MSG_COMMAND_COMPLETE],
+            _ => {}
+        }
+    }
+
+    fn execute_command(&mut self) {
+        let result = match &mut self.targets[self.target] {
+            Some(target) => target.command(&self.buffer),
+            None => return,
+        };
+
+        match result {
+            ScsiResult::Status(status) => {
+                self.status_byte = status;
+                self.enter_phase(BusPhase::Status);
+            }
+            ScsiResult::DataIn(data) => {
+                self.status_byte = SCSI_STATUS_GOOD;
+                if data.is_empty() {
+                    self.enter_phase(BusPhase::Status);
+                }
+                else {
+                    self.buffer = data;
+                    self.enter_phase(BusPhase::DataIn);
+                }
+            }
+            ScsiResult::DataOut(len) => {
+                self.buffer = vec![0; len];
+                self.enter_phase(BusPhase::DataOut);
+            }
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if !matches!(self.phase, BusPhase::DataIn | BusPhase::Status | BusPhase::MessageIn) {
+            return self.data_bus;
+        }
+
+        let byte = self.buffer[self.buffer_pos];
+        self.buffer_pos += 1;
+        if self.buffer_pos >= self.buffer.len() {
+            match self.phase {
+                BusPhase::DataIn => self.enter_phase(BusPhase::Status),
+                BusPhase::Status => self.enter_phase(BusPhase::MessageIn),
+                _ => {
+                    // The target releases the bus after sending COMMAND COMPLETE.
+                    self.phase = BusPhase::Free;
+                    self.buffer.clear();
+                }
+            }
+        }
+        byte
+    }
+
+    fn peek_data(&self) -> u8 {
+        match self.phase {
+            BusPhase::DataIn | BusPhase::Status | BusPhase::MessageIn => self.buffer[self.buffer_pos],
+            _ => self.data_bus,
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.data_bus = data;
+        if self.control & CONTROL_DRIVER_ENABLE == 0 {
+            return;
+        }
+
+        match self.phase {
+            BusPhase::MessageOut => {
+                // IDENTIFY and other messages are accepted and ignored. The target continues to
+                // request messages as long as ATN is asserted.
+                if self.control & CONTROL_ATN == 0 {
+                    self.enter_phase(BusPhase::Command);
+                }
+            }
+            BusPhase::Command => {
+                self.buffer.push(data);
+                if self.buffer.len() >= cdb_len(self.buffer[0]) {
+                    self.execute_command();
+                }
+            }
+            BusPhase::DataOut => {
+                self.buffer[self.buffer_pos] = data;
+                self.buffer_pos += 1;
+                if self.buffer_pos >= self.buffer.len() {
+                    if let Some(target) = &mut self.targets[self.target] {
+                        self.status_byte = target.data_out(&self.buffer);
+                    }
+                    self.enter_phase(BusPhase::Status);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MemoryMappedDevice for SeagateSt01 {
+    fn get_read_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn mmio_read_u8(&mut self, address: usize, _cycles: u32, cpumem: Option<&[u8]>) -> (u8, u32) {
+        let offset = address - self.window_addr;
+        let byte = match offset {
+            REG_DATA.. => self.read_data(),
+            REG_CONTROL_STATUS.. => self.read_status(),
+            RAM_OFFSET.. => self.ram[offset - RAM_OFFSET],
+            _ => cpumem.map(|mem| mem[address]).unwrap_or(0xFF),
+        };
+        (byte, 0)
+    }
+
+    fn mmio_read_u16(&mut self, address: usize, cycles: u32, cpumem: Option<&[u8]>) -> (u16, u32) {
+        let (lo, _) = self.mmio_read_u8(address, cycles, cpumem);
+        let (hi, _) = self.mmio_read_u8(address + 1, cycles, cpumem);
+        ((hi as u16) << 8 | lo as u16, 0)
+    }
+
+    fn mmio_peek_u8(&self, address: usize, cpumem: Option<&[u8]>) -> u8 {
+        let offset = address - self.window_addr;
+        match offset {
+            REG_DATA.. => self.peek_data(),
+            REG_CONTROL_STATUS.. => self.read_status(),
+            RAM_OFFSET.. => self.ram[offset - RAM_OFFSET],
+            _ => cpumem.map(|mem| mem[address]).unwrap_or(0xFF),
+        }
+    }
+
+    fn mmio_peek_u16(&self, address: usize, cpumem: Option<&[u8]>) -> u16 {
+        let lo = self.mmio_peek_u8(address, cpumem);
+        let hi = self.mmio_peek_u8(address + 1, cpumem);
+        (hi as u16) << 8 | lo as u16
+    }
+
+    fn get_write_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn mmio_write_u8(&mut self, address: usize, data: u8, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
+        let offset = address - self.window_addr;
+        match offset {
+            REG_DATA.. => self.write_data(data),
+            REG_CONTROL_STATUS.. => self.write_control(data),
+            RAM_OFFSET.. => self.ram[offset - RAM_OFFSET] = data,
+            _ => {}
+        }
+        0
+    }
+
+    fn mmio_write_u16(&mut self, address: usize, data: u16, cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
+        self.mmio_write_u8(address, data as u8, cycles, None);
+        self.mmio_write_u8(address + 1, (data >> 8) as u8, cycles, None);
+        0
+    }
+
+    fn get_mapping(&self) -> Vec<MemRangeDescriptor> {
+        vec![MemRangeDescriptor::new(self.window_addr, ST01_WINDOW_SIZE, false)]
+    }
+}
+
+/// The identity of each attached disk image is saved rather than its contents.
+impl SaveState for SeagateSt01 {
+    fn save_state(&self, w: &mut StateWriter) {
+        for target in self.targets.iter() {
+            w.write_option_str(target.as_ref().map(|disk| disk.identity()).as_deref());
+            if let Some(disk) = target {
+                disk.save_state(w);
+            }
+        }
+        w.write_bytes(&self.ram);
+        w.write_u8(self.control);
+        w.write_u8(self.data_bus);
+        w.write_u8(match self.phase {
+            BusPhase::Free => 0,
+            BusPhase::Selected => 1,
+            BusPhase::MessageOut => 2,
+            BusPhase::Command => 3,
+            BusPhase::DataIn => 4,
+            BusPhase::DataOut => 5,
+            BusPhase::Status => 6,
+            BusPhase::MessageIn => 7,
+        });
+        w.write_usize(self.target);
+        w.write_bytes(&self.buffer);
+        w.write_usize(self.buffer_pos);
+        w.write_u8(self.status_byte);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for (id, target) in self.targets.iter_mut().enumerate() {
+            r.read_media(
+                target.as_ref().map(|disk| disk.identity()),
+                &format!("SCSI target {}", id),
+            )?;
+            if let Some(disk) = target {
+                disk.load_state(r)?;
+            }
+        }
+        r.read_bytes_into(&mut self.ram, "ST-01 RAM")?;
+        self.control = r.read_u8()?;
+        self.data_bus = r.read_u8()?;
+        self.phase = match r.read_u8()? {
+            0 => BusPhase::Free,
+            1 => BusPhase::Selected,
+            2 => BusPhase::MessageOut,
+            3 => BusPhase::Command,
+            4 => BusPhase::DataIn,
+            5 => BusPhase::DataOut,
+            6 => BusPhase::Status,
+            7 => BusPhase::MessageIn,
+            _ => return Err(SaveStateError::InvalidValue("ST-01 bus phase")),
+        };
+        self.target = r.read_usize()?;
+        self.buffer = r.read_bytes()?.to_vec();
+        self.buffer_pos = r.read_usize()?;
+        self.status_byte = r.read_u8()?;
+
+        if self.target >= ST01_MAX_TARGETS || self.buffer_pos > self.buffer.len() {
+            return Err(SaveStateError::InvalidValue("ST-01 transfer"));
+        }
+        Ok(())
+    }
+}
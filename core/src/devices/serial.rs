@@ -43,7 +43,12 @@ use std::{
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
-    devices::pic,
+    devices::{
+        pic,
+        serial_bridge::{NullModemLines, SerialBridge},
+    },
+    machine_config::SerialBridgeConfig,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
 };
 
@@ -58,6 +63,10 @@ const SERIAL_CLOCK: f64 = 1.8432;
 pub const SERIAL1_IRQ: u8 = 4;
 pub const SERIAL2_IRQ: u8 = 3;
 
+// Stop reading from a socket bridge while this many bytes are waiting to be received, letting the
+// host end's flow control throttle it to the port's baud rate.
+const SOCKET_BRIDGE_RX_LIMIT: usize = 1024;
+
 /* - Ports -
 
     Ports 0x3F8 & 0x3F9 (And their corresponding secondary ports) are multiplexed via
@@ -88,6 +97,13 @@ pub const SERIAL2_MODEM_STATUS: u16 = 0x2FE;
 const WORD_LENGTH_SELECT_MASK: u8 = 0b0000_0011;
 const STOP_BIT_SELECT_BIT: u8 = 0b0000_0100;
 const PARITY_ENABLE_BIT: u8 = 0b0000_1000;
+#[cfg(feature = "serial")]
+const EVEN_PARITY_SELECT_BIT: u8 = 0b0001_0000;
+#[cfg(feature = "serial")]
+const STICK_PARITY_BIT: u8 = 0b0010_0000;
+// The bits of the Line Control Register that define the data format
+#[cfg(feature = "serial")]
+const LINE_FORMAT_MASK: u8 = 0b0011_1111;
 const DIVISOR_LATCH_ACCESS_BIT: u8 = 0b1000_0000;
 
 // Line Status Register constants
@@ -220,6 +236,15 @@ pub enum IntrAction {
 
 pub type SerialPortDisplayState = BTreeMap<&'static str, SyntaxToken>;
 
+/// The state of the modem status input lines of a serial port, as driven by an attached device.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ModemStatusLines {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri:  bool,
+    pub dcd: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct SerialPortDescriptor {
     pub id: usize,
@@ -257,6 +282,10 @@ pub struct SerialPort {
     tx_queue: VecDeque<u8>,
     tx_timer: f64,
     us_per_byte: f64,
+    // A device is attached that consumes transmitted bytes from the tx queue.
+    attached: bool,
+    // Bridge to a host TCP socket or pseudo-terminal
+    socket_bridge: Option<SerialBridge>,
 
     // Serial port bridge
     #[cfg(feature = "serial")]
@@ -265,6 +294,12 @@ pub struct SerialPort {
     bridge_port: Option<Box<dyn serialport::SerialPort>>,
     #[cfg(feature = "serial")]
     bridge_buf: Vec<u8>,
+    // The divisor and line format last applied to the host port
+    #[cfg(feature = "serial")]
+    bridge_format: Option<(u16, u8)>,
+    // The DTR and RTS state last applied to the host port
+    #[cfg(feature = "serial")]
+    bridge_mcr: Option<u8>,
 }
 
 impl Default for SerialPort {
@@ -298,6 +333,8 @@ impl Default for SerialPort {
             tx_queue: VecDeque::new(),
             tx_timer: 0.0,
             us_per_byte: 833.333, // 9600 baud
+            attached: false,
+            socket_bridge: None,
 
             #[cfg(feature = "serial")]
             bridge_port_id: None,
@@ -305,6 +342,10 @@ impl Default for SerialPort {
             bridge_port: None,
             #[cfg(feature = "serial")]
             bridge_buf: vec![0; 1000],
+            #[cfg(feature = "serial")]
+            bridge_format: None,
+            #[cfg(feature = "serial")]
+            bridge_mcr: None,
         }
     }
 }
@@ -324,6 +365,12 @@ impl SerialPort {
             name: self.name.clone(),
             irq: self.irq,
             out2_suppresses_int: self.out2_suppresses_int,
+            attached: self.attached,
+            socket_bridge: self.socket_bridge.take(),
+            #[cfg(feature = "serial")]
+            bridge_port_id: self.bridge_port_id,
+            #[cfg(feature = "serial")]
+            bridge_port: self.bridge_port.take(),
             ..Default::default()
         }
     }
@@ -690,15 +737,42 @@ impl SerialPort {
         self.modem_status_reg = byte;
     }
 
-    fn set_modem_status_connected(&mut self) {
-        if self.modem_status_reg & MODEM_STATUS_CTS == 0 {
-            self.modem_status_reg |= MODEM_STATUS_CTS;
-            self.modem_status_reg |= MODEM_STATUS_DCTS;
+    /// Drive the modem status input lines, setting the corresponding delta bits and raising a modem
+    /// status interrupt if any have changed.
+    fn set_modem_status_lines(&mut self, lines: ModemStatusLines) {
+        let old = self.modem_status_reg;
+        let mut new = old & 0x0F;
+        if lines.cts {
+            new |= MODEM_STATUS_CTS;
+        }
+        if lines.dsr {
+            new |= MODEM_STATUS_DSR;
+        }
+        if lines.ri {
+            new |= MODEM_STATUS_RI;
+        }
+        if lines.dcd {
+            new |= MODEM_STATUS_RLSD;
+        }
+
+        let changed = old ^ new;
+        if changed & MODEM_STATUS_CTS != 0 {
+            new |= MODEM_STATUS_DCTS;
+        }
+        if changed & MODEM_STATUS_DSR != 0 {
+            new |= MODEM_STATUS_DDSR;
+        }
+        if changed & MODEM_STATUS_RI != 0 && !lines.ri {
+            // Only the trailing edge of RI is reported.
+            new |= MODEM_STATUS_TERI;
+        }
+        if changed & MODEM_STATUS_RLSD != 0 {
+            new |= MODEM_STATUS_DRLSD;
         }
 
-        if self.modem_status_reg & MODEM_STATUS_DSR == 0 {
-            self.modem_status_reg |= MODEM_STATUS_DSR;
-            self.modem_status_reg |= MODEM_STATUS_DDSR;
+        self.modem_status_reg = new;
+        if (new & !old) & 0x0F != 0 {
+            self.raise_interrupt_type(INTERRUPT_MODEM_STATUS);
         }
     }
 
@@ -781,7 +855,9 @@ impl SerialPort {
                 log::debug!("Successfully opened host port {}", port_name);
                 self.bridge_port = Some(bridge_port);
                 self.bridge_port_id = Some(port_id);
-                self.set_modem_status_connected();
+                // Apply the current line settings on the next update.
+                self.bridge_format = None;
+                self.bridge_mcr = None;
                 Ok(true)
             }
             Err(e) => {
@@ -791,6 +867,82 @@ impl SerialPort {
         }
     }
 
+    /// Mirror the port's baud rate, data format and modem control outputs to the bridged host port,
+    /// and the host port's modem status inputs back to the emulated port.
+    #[cfg(feature = "serial")]
+    fn sync_bridge_port(&mut self) {
+        let bridge_port = match &mut self.bridge_port {
+            Some(bridge_port) => bridge_port,
+            None => return,
+        };
+
+        let format = (self.divisor, self.line_control_reg & LINE_FORMAT_MASK);
+        if self.bridge_format != Some(format) {
+            let baud = (SERIAL_CLOCK * 1_000_000.0 / 16.0 / self.divisor.max(1) as f64).round() as u32;
+            let data_bits = match self.word_length {
+                5 => serialport::DataBits::Five,
+                6 => serialport::DataBits::Six,
+                7 => serialport::DataBits::Seven,
+                _ => serialport::DataBits::Eight,
+            };
+            let parity = match (self.parity_enable, self.line_control_reg & EVEN_PARITY_SELECT_BIT != 0) {
+                (false, _) => serialport::Parity::None,
+                (true, false) => serialport::Parity::Odd,
+                (true, true) => serialport::Parity::Even,
+            };
+            if self.parity_enable && self.line_control_reg & STICK_PARITY_BIT != 0 {
+                log::warn!("{}: Stick parity is not supported by the host port", self.name);
+            }
+            // Host ports don't offer 1.5 stop bits, which the 8250 uses for 5-bit words.
+            let stop_bits = match self.stop_bits {
+                StopBits::One => serialport::StopBits::One,
+                StopBits::OneAndAHalf | StopBits::Two => serialport::StopBits::Two,
+            };
+
+            log::debug!(
+                "{}: Setting host port to {} baud, {} data bits, {:?} parity, {:?} stop bits",
+                self.name,
+                baud,
+                self.word_length,
+                parity,
+                stop_bits
+            );
+            if let Err(e) = bridge_port
+                .set_baud_rate(baud)
+                .and_then(|_| bridge_port.set_data_bits(data_bits))
+                .and_then(|_| bridge_port.set_parity(parity))
+                .and_then(|_| bridge_port.set_stop_bits(stop_bits))
+            {
+                log::error!("{}: Error configuring host port: {}", self.name, e);
+            }
+            self.bridge_format = Some(format);
+        }
+
+        // In loopback mode the modem control outputs are disconnected from the outside world.
+        if self.loopback {
+            return;
+        }
+
+        let mcr = self.modem_control_reg & (MODEM_CONTROL_DTR | MODEM_CONTROL_RTS);
+        if self.bridge_mcr != Some(mcr) {
+            if let Err(e) = bridge_port
+                .write_data_terminal_ready(mcr & MODEM_CONTROL_DTR != 0)
+                .and_then(|_| bridge_port.write_request_to_send(mcr & MODEM_CONTROL_RTS != 0))
+            {
+                log::error!("{}: Error setting host port control lines: {}", self.name, e);
+            }
+            self.bridge_mcr = Some(mcr);
+        }
+
+        let lines = ModemStatusLines {
+            cts: bridge_port.read_clear_to_send().unwrap_or(false),
+            dsr: bridge_port.read_data_set_ready().unwrap_or(false),
+            ri:  bridge_port.read_ring_indicator().unwrap_or(false),
+            dcd: bridge_port.read_carrier_detect().unwrap_or(false),
+        };
+        self.set_modem_status_lines(lines);
+    }
+
     pub fn get_display_state(&mut self, _clean: bool) -> SerialPortDisplayState {
         let mut state = BTreeMap::<&str, SyntaxToken>::new();
 
@@ -869,6 +1021,85 @@ impl SerialPort {
     }
 }
 
+/// Host serial bridges are not part of the state. Bytes already queued for transmission are
+/// restored and will be sent to whatever is attached at the time of the restore.
+impl SaveState for SerialPort {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.line_control_reg);
+        w.write_u8(self.word_length);
+        w.write_u8(match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::OneAndAHalf => 1,
+            StopBits::Two => 2,
+        });
+        w.write_bool(self.parity_enable);
+        w.write_bool(self.divisor_latch_access);
+        w.write_u16(self.divisor);
+        w.write_u8(self.line_status_reg);
+        w.write_u8(self.interrupts_active);
+        w.write_u8(self.interrupt_enable_reg);
+        w.write_u8(match self.intr_action {
+            IntrAction::None => 0,
+            IntrAction::Raise => 1,
+            IntrAction::Lower => 2,
+        });
+        w.write_u8(self.modem_control_reg);
+        w.write_bool(self.loopback);
+        w.write_u8(self.modem_status_reg);
+        w.write_u8(self.rx_byte);
+        w.write_usize(self.rx_count);
+        w.write_usize(self.rx_overrun_count);
+        w.write_bool(self.rx_was_read);
+        w.write_u8(self.tx_holding_reg);
+        w.write_bool(self.tx_holding_empty);
+        w.write_bytes(&self.rx_queue.iter().copied().collect::<Vec<u8>>());
+        w.write_f64(self.rx_timer);
+        w.write_usize(self.tx_count);
+        w.write_bytes(&self.tx_queue.iter().copied().collect::<Vec<u8>>());
+        w.write_f64(self.tx_timer);
+        w.write_f64(self.us_per_byte);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.line_control_reg = r.read_u8()?;
+        self.word_length = r.read_u8()?;
+        self.stop_bits = match r.read_u8()? {
+            0 => StopBits::One,
+            1 => StopBits::OneAndAHalf,
+            2 => StopBits::Two,
+            _ => return Err(SaveStateError::InvalidValue("serial stop bits")),
+        };
+        self.parity_enable = r.read_bool()?;
+        self.divisor_latch_access = r.read_bool()?;
+        self.divisor = r.read_u16()?;
+        self.line_status_reg = r.read_u8()?;
+        self.interrupts_active = r.read_u8()?;
+        self.interrupt_enable_reg = r.read_u8()?;
+        self.intr_action = match r.read_u8()? {
+            0 => IntrAction::None,
+            1 => IntrAction::Raise,
+            2 => IntrAction::Lower,
+            _ => return Err(SaveStateError::InvalidValue("serial interrupt action")),
+        };
+        self.modem_control_reg = r.read_u8()?;
+        self.loopback = r.read_bool()?;
+        self.modem_status_reg = r.read_u8()?;
+        self.rx_byte = r.read_u8()?;
+        self.rx_count = r.read_usize()?;
+        self.rx_overrun_count = r.read_usize()?;
+        self.rx_was_read = r.read_bool()?;
+        self.tx_holding_reg = r.read_u8()?;
+        self.tx_holding_empty = r.read_bool()?;
+        self.rx_queue = r.read_bytes()?.iter().copied().collect();
+        self.rx_timer = r.read_f64()?;
+        self.tx_count = r.read_usize()?;
+        self.tx_queue = r.read_bytes()?.iter().copied().collect();
+        self.tx_timer = r.read_f64()?;
+        self.us_per_byte = r.read_f64()?;
+        Ok(())
+    }
+}
+
 pub struct SerialPortController {
     port: [SerialPort; 2],
 }
@@ -920,7 +1151,6 @@ impl SerialPortController {
     }
 
     /// Get status of the specified serial port's DTR line
-    #[allow(dead_code)]
     pub fn get_dtr(&self, port: usize) -> bool {
         self.port[port].modem_control_reg & MODEM_CONTROL_DTR != 0
     }
@@ -930,6 +1160,53 @@ impl SerialPortController {
         self.port[port].rx_queue.push_back(byte);
     }
 
+    /// Attach a device to the specified serial port. Bytes transmitted by the port are queued for
+    /// the device to collect with take_tx_bytes().
+    pub fn attach_device(&mut self, port: usize) {
+        self.port[port].attached = true;
+    }
+
+    /// Remove and return the bytes transmitted by the specified port since the last call.
+    pub fn take_tx_bytes(&mut self, port: usize) -> Vec<u8> {
+        self.port[port].tx_queue.drain(..).collect()
+    }
+
+    /// Return the number of bytes waiting to be received by the specified port.
+    pub fn rx_queue_len(&self, port: usize) -> usize {
+        self.port[port].rx_queue.len()
+    }
+
+    /// Set the state of the modem status lines of the specified port.
+    pub fn set_modem_status_lines(&mut self, port: usize, lines: ModemStatusLines) {
+        self.port[port].set_modem_status_lines(lines);
+    }
+
+    /// Return the current baud rate of the specified port.
+    pub fn baud_rate(&self, port: usize) -> u32 {
+        SerialPort::divisor_to_baud(self.port[port].divisor) as u32
+    }
+
+    /// Bridge the specified serial port to a host TCP socket or pseudo-terminal. Returns a
+    /// description of the host end.
+    pub fn bridge_socket(&mut self, port: usize, config: &SerialBridgeConfig) -> anyhow::Result<String> {
+        let bridge = SerialBridge::new(config)?;
+        let description = bridge.description().to_string();
+        log::info!("{} bridged to {}", self.port[port].name, description);
+        self.port[port].socket_bridge = Some(bridge);
+        Ok(description)
+    }
+
+    /// Bridge the specified serial port to the named host serial port, as configured in the machine
+    /// configuration. The host port id is its index in the host's list of available ports.
+    #[cfg(feature = "serial")]
+    pub fn bridge_host_port(&mut self, port: usize, host_port_name: &str) -> anyhow::Result<bool> {
+        let host_port_id = serialport::available_ports()
+            .ok()
+            .and_then(|ports| ports.iter().position(|p| p.port_name == host_port_name))
+            .unwrap_or(usize::MAX);
+        self.bridge_port(port, host_port_name.to_string(), host_port_id)
+    }
+
     /// Bridge the specified serial port
     #[cfg(feature = "serial")]
     pub fn bridge_port(&mut self, port: usize, host_port_name: String, host_port_id: usize) -> anyhow::Result<bool> {
@@ -970,9 +1247,14 @@ impl SerialPortController {
             while port.tx_timer > port.us_per_byte {
                 // Is there a byte waiting to be sent in the tx holding register?
                 if !port.tx_holding_empty {
-                    // If we have bridged this serial port, send the byte to the tx queue
+                    // If we have bridged this serial port or attached a device to it, send the byte to the tx queue
+                    #[allow(unused_mut)]
+                    let mut capture = port.attached || port.socket_bridge.is_some();
                     #[cfg(feature = "serial")]
-                    if let Some(_) = &port.bridge_port {
+                    {
+                        capture |= port.bridge_port.is_some();
+                    }
+                    if capture {
                         //log::trace!("{}: Sending byte: {:02X}", port.name, port.tx_holding_reg);
                         port.tx_queue.push_back(port.tx_holding_reg);
                     }
@@ -1002,8 +1284,50 @@ impl SerialPortController {
     /// The update function is called per-frame, instead of within the emulation loop.
     /// This allows bridging realtime events with virtual device.
     pub fn update(&mut self) {
+        for port in &mut self.port {
+            if let Some(bridge) = &mut port.socket_bridge {
+                // Cross our RTS and DTR over to a null-modem peer's CTS, DSR and DCD.
+                bridge.set_local_lines(NullModemLines {
+                    rts: port.modem_control_reg & MODEM_CONTROL_RTS != 0,
+                    dtr: port.modem_control_reg & MODEM_CONTROL_DTR != 0,
+                });
+                if !port.tx_queue.is_empty() {
+                    let (tx1, tx2) = port.tx_queue.as_slices();
+                    bridge.write(tx1);
+                    bridge.write(tx2);
+                    port.tx_queue.clear();
+                }
+
+                let mut buf = [0u8; SOCKET_BRIDGE_RX_LIMIT];
+                let limit = SOCKET_BRIDGE_RX_LIMIT.saturating_sub(port.rx_queue.len());
+                let ct = bridge.update(&mut buf[..limit]);
+                port.rx_queue.extend(&buf[..ct]);
+
+                let lines = if bridge.is_null_modem() {
+                    let remote = bridge.remote_lines();
+                    ModemStatusLines {
+                        cts: remote.rts,
+                        dsr: remote.dtr,
+                        ri:  false,
+                        dcd: remote.dtr,
+                    }
+                }
+                else {
+                    // Carrier follows the state of the host connection.
+                    ModemStatusLines {
+                        cts: true,
+                        dsr: true,
+                        ri:  false,
+                        dcd: bridge.connected(),
+                    }
+                };
+                port.set_modem_status_lines(lines);
+            }
+        }
+
         #[cfg(feature = "serial")]
         for port in &mut self.port {
+            port.sync_bridge_port();
             match &mut port.bridge_port {
                 Some(bridge_port) => {
                     // Write any pending bytes
@@ -1045,3 +1369,18 @@ impl SerialPortController {
         }
     }
 }
+
+impl SaveState for SerialPortController {
+    fn save_state(&self, w: &mut StateWriter) {
+        for port in self.port.iter() {
+            port.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for port in self.port.iter_mut() {
+            port.load_state(r)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,575 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::serial_bridge.rs
+
+   Connects an emulated serial port to a host TCP socket or pseudo-terminal,
+   so that the guest's serial traffic can be consumed by host tools.
+
+   As a TCP server, the bridge accepts one client at a time; a new client
+   replaces the existing one. As a TCP client, the bridge reconnects to its
+   server at a fixed interval whenever the connection is lost. A
+   pseudo-terminal stays open for the lifetime of the bridge, so host tools
+   may attach and detach from it freely.
+
+   A null-modem bridge links the serial ports of two MartyPC instances over
+   TCP, one listening and one connecting. Besides the data, each end sends
+   the state of its RTS and DTR outputs, which the other end presents on its
+   CTS, and DSR and DCD inputs, as a crossed null-modem cable would. Within
+   the stream, a byte of 0xFF escapes the following byte: a second 0xFF is
+   a data byte, anything else carries the line state.
+
+*/
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{machine_config::SerialBridgeConfig, machine_types::SerialBridgeType};
+
+pub const DEFAULT_RECONNECT_MS: u32 = 2000;
+// Outgoing bytes are buffered up to this limit while the host end isn't reading.
+const MAX_PENDING_TX: usize = 65536;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const NULL_MODEM_ESCAPE: u8 = 0xFF;
+// Line state messages have this prefix in the high bits, with the line bits below.
+const NULL_MODEM_LINES: u8 = 0x80;
+const NULL_MODEM_RTS: u8 = 0x01;
+const NULL_MODEM_DTR: u8 = 0x02;
+
+/// The state of the handshake outputs of the serial port at the other end of a null-modem cable.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct NullModemLines {
+    pub rts: bool,
+    pub dtr: bool,
+}
+
+enum BridgeEndpoint {
+    TcpServer {
+        listener: TcpListener,
+        client:   Option<TcpStream>,
+    },
+    TcpClient {
+        address: String,
+        stream: Option<TcpStream>,
+        connecting: Option<Receiver<std::io::Result<TcpStream>>>,
+        next_attempt: Instant,
+    },
+    #[cfg(unix)]
+    Pty(pty::Pty),
+}
+
+pub struct SerialBridge {
+    endpoint:    BridgeEndpoint,
+    description: String,
+    reconnect:   Duration,
+    tx_pending:  Vec<u8>,
+
+    null_modem: bool,
+    local_lines_sent: Option<NullModemLines>,
+    remote_lines: NullModemLines,
+    rx_escape: bool,
+}
+
+impl SerialBridge {
+    pub fn new(config: &SerialBridgeConfig) -> anyhow::Result<Self> {
+        let reconnect = Duration::from_millis(config.reconnect_ms.unwrap_or(DEFAULT_RECONNECT_MS) as u64);
+
+        let (endpoint, description) = match config.bridge_type {
+            SerialBridgeType::TcpServer => {
+                let (endpoint, address) = Self::tcp_server(config.address.as_deref().unwrap_or("127.0.0.1:2323"))?;
+                (endpoint, format!("TCP server {}", address))
+            }
+            SerialBridgeType::TcpClient => {
+                let (endpoint, address) = Self::tcp_client(config.address.as_deref())?;
+                (endpoint, format!("TCP client {}", address))
+            }
+            SerialBridgeType::NullModem => {
+                if config.listen.unwrap_or(false) {
+                    let (endpoint, address) = Self::tcp_server(config.address.as_deref().unwrap_or("127.0.0.1:2400"))?;
+                    (endpoint, format!("null-modem listening on {}", address))
+                }
+                else {
+                    let (endpoint, address) = Self::tcp_client(config.address.as_deref())?;
+                    (endpoint, format!("null-modem connecting to {}", address))
+                }
+            }
+            #[cfg(unix)]
+            SerialBridgeType::Pty => {
+                let pty = pty::Pty::open(config.address.as_deref())?;
+                let description = format!("pseudo-terminal {}", pty.name());
+                (BridgeEndpoint::Pty(pty), description)
+            }
+            #[cfg(not(unix))]
+            SerialBridgeType::Pty => {
+                anyhow::bail!("Pseudo-terminal serial bridges are not supported on this platform.")
+            }
+            SerialBridgeType::Host => {
+                anyhow::bail!("Host serial port passthrough requires the 'serial' feature.")
+            }
+        };
+
+        Ok(Self {
+            endpoint,
+            description,
+            reconnect,
+            tx_pending: Vec::new(),
+            null_modem: config.bridge_type == SerialBridgeType::NullModem,
+            local_lines_sent: None,
+            remote_lines: NullModemLines::default(),
+            rx_escape: false,
+        })
+    }
+
+    fn tcp_server(address: &str) -> anyhow::Result<(BridgeEndpoint, String)> {
+        let listener =
+            TcpListener::bind(address).map_err(|e| anyhow::anyhow!("Couldn't listen on {}: {}", address, e))?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?.to_string();
+        Ok((BridgeEndpoint::TcpServer { listener, client: None }, address))
+    }
+
+    fn tcp_client(address: Option<&str>) -> anyhow::Result<(BridgeEndpoint, String)> {
+        let address = match address {
+            Some(address) => address.to_string(),
+            None => anyhow::bail!("A TCP client serial bridge requires an address."),
+        };
+        Ok((
+            BridgeEndpoint::TcpClient {
+                address: address.clone(),
+                stream: None,
+                connecting: None,
+                next_attempt: Instant::now(),
+            },
+            address,
+        ))
+    }
+
+    /// Return whether this bridge is a null-modem link, which carries handshake lines.
+    pub fn is_null_modem(&self) -> bool {
+        self.null_modem
+    }
+
+    /// Return the state of the remote port's handshake outputs. These are all inactive while the
+    /// link is down.
+    pub fn remote_lines(&self) -> NullModemLines {
+        if self.connected() {
+            self.remote_lines
+        }
+        else {
+            NullModemLines::default()
+        }
+    }
+
+    /// Send the state of the local port's handshake outputs to the remote port, if it has changed.
+    pub fn set_local_lines(&mut self, lines: NullModemLines) {
+        if !self.null_modem || !self.connected() || self.local_lines_sent == Some(lines) {
+            return;
+        }
+        let mut state = NULL_MODEM_LINES;
+        if lines.rts {
+            state |= NULL_MODEM_RTS;
+        }
+        if lines.dtr {
+            state |= NULL_MODEM_DTR;
+        }
+        self.tx_pending.extend_from_slice(&[NULL_MODEM_ESCAPE, state]);
+        self.local_lines_sent = Some(lines);
+    }
+
+    /// Remove line state messages from bytes received over a null-modem link, returning the number
+    /// of data bytes left at the start of buf.
+    fn decode_null_modem(&mut self, buf: &mut [u8], len: usize) -> usize {
+        let mut out = 0;
+        for i in 0..len {
+            let byte = buf[i];
+            if self.rx_escape {
+                self.rx_escape = false;
+                if byte == NULL_MODEM_ESCAPE {
+                    buf[out] = byte;
+                    out += 1;
+                }
+                else if byte & NULL_MODEM_LINES != 0 {
+                    self.remote_lines = NullModemLines {
+                        rts: byte & NULL_MODEM_RTS != 0,
+                        dtr: byte & NULL_MODEM_DTR != 0,
+                    };
+                }
+            }
+            else if byte == NULL_MODEM_ESCAPE {
+                self.rx_escape = true;
+            }
+            else {
+                buf[out] = byte;
+                out += 1;
+            }
+        }
+        out
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Return whether a host peer is currently connected. A pseudo-terminal is always considered
+    /// connected.
+    pub fn connected(&self) -> bool {
+        match &self.endpoint {
+            BridgeEndpoint::TcpServer { client, .. } => client.is_some(),
+            BridgeEndpoint::TcpClient { stream, .. } => stream.is_some(),
+            #[cfg(unix)]
+            BridgeEndpoint::Pty(_) => true,
+        }
+    }
+
+    /// Queue bytes transmitted by the guest for the host. Bytes sent while nothing is connected
+    /// are discarded, as they would be on a disconnected serial line.
+    pub fn write(&mut self, data: &[u8]) {
+        if !self.connected() || self.tx_pending.len() + data.len() > MAX_PENDING_TX {
+            return;
+        }
+        if self.null_modem {
+            for &byte in data {
+                if byte == NULL_MODEM_ESCAPE {
+                    self.tx_pending.push(NULL_MODEM_ESCAPE);
+                }
+                self.tx_pending.push(byte);
+            }
+        }
+        else {
+            self.tx_pending.extend_from_slice(data);
+        }
+    }
+
+    /// Service the bridge: accept or re-establish connections, flush pending output and read up
+    /// to buf.len() bytes of input. Returns the number of bytes read.
+    pub fn update(&mut self, buf: &mut [u8]) -> usize {
+        self.connect();
+
+        let mut dropped = false;
+        let mut read = 0;
+        let mut pending = std::mem::take(&mut self.tx_pending);
+        if let Some(stream) = self.stream() {
+            while !pending.is_empty() {
+                match stream.write(&pending) {
+                    Ok(0) => {
+                        dropped = true;
+                        break;
+                    }
+                    Ok(n) => {
+                        pending.drain(..n);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => {
+                        dropped = true;
+                        break;
+                    }
+                }
+            }
+
+            if !dropped && !buf.is_empty() {
+                match stream.read(buf) {
+                    Ok(0) => dropped = true,
+                    Ok(n) => read = n,
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                    Err(_) => dropped = true,
+                }
+            }
+        }
+
+        self.tx_pending = pending;
+
+        if dropped {
+            self.disconnect();
+        }
+        if self.null_modem {
+            read = self.decode_null_modem(buf, read);
+        }
+        read
+    }
+
+    fn stream(&mut self) -> Option<&mut dyn ReadWrite> {
+        match &mut self.endpoint {
+            BridgeEndpoint::TcpServer { client, .. } => client.as_mut().map(|s| s as &mut dyn ReadWrite),
+            BridgeEndpoint::TcpClient { stream, .. } => stream.as_mut().map(|s| s as &mut dyn ReadWrite),
+            #[cfg(unix)]
+            BridgeEndpoint::Pty(pty) => Some(pty as &mut dyn ReadWrite),
+        }
+    }
+
+    fn connect(&mut self) {
+        let mut new_connection = false;
+        match &mut self.endpoint {
+            BridgeEndpoint::TcpServer { listener, client } => {
+                if let Ok((stream, addr)) = listener.accept() {
+                    if client.is_some() {
+                        log::debug!("Serial bridge: client {} replaces existing connection", addr);
+                    }
+                    else {
+                        log::debug!("Serial bridge: client {} connected", addr);
+                    }
+                    if stream.set_nonblocking(true).is_ok() {
+                        _ = stream.set_nodelay(true);
+                        *client = Some(stream);
+                        new_connection = true;
+                    }
+                }
+            }
+            BridgeEndpoint::TcpClient {
+                address,
+                stream,
+                connecting,
+                next_attempt,
+            } => {
+                if stream.is_some() {
+                    return;
+                }
+                if let Some(rx) = connecting {
+                    match rx.try_recv() {
+                        Ok(Ok(new_stream)) => {
+                            *connecting = None;
+                            if new_stream.set_nonblocking(true).is_ok() {
+                                log::debug!("Serial bridge: connected to {}", address);
+                                _ = new_stream.set_nodelay(true);
+                                *stream = Some(new_stream);
+                                new_connection = true;
+                            }
+                        }
+                        Ok(Err(e)) => {
+                            log::trace!("Serial bridge: connection to {} failed: {}", address, e);
+                            *connecting = None;
+                        }
+                        Err(TryRecvError::Empty) => {}
+                        Err(TryRecvError::Disconnected) => *connecting = None,
+                    }
+                }
+                else if Instant::now() >= *next_attempt {
+                    // Connect on a separate thread so an unreachable host doesn't stall the frame.
+                    *next_attempt = Instant::now() + self.reconnect;
+                    let (tx, rx) = mpsc::channel();
+                    let target = address.clone();
+                    thread::spawn(move || {
+                        let result = target.to_socket_addrs().and_then(|mut addrs| match addrs.next() {
+                            Some(addr) => TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT),
+                            None => Err(ErrorKind::AddrNotAvailable.into()),
+                        });
+                        _ = tx.send(result);
+                    });
+                    *connecting = Some(rx);
+                }
+            }
+            #[cfg(unix)]
+            BridgeEndpoint::Pty(_) => {}
+        }
+
+        if new_connection {
+            self.reset_link();
+        }
+    }
+
+    /// Clear the state of the link when a new connection is made.
+    fn reset_link(&mut self) {
+        self.tx_pending.clear();
+        self.local_lines_sent = None;
+        self.remote_lines = NullModemLines::default();
+        self.rx_escape = false;
+    }
+
+    fn disconnect(&mut self) {
+        self.tx_pending.clear();
+        match &mut self.endpoint {
+            BridgeEndpoint::TcpServer { client, .. } => {
+                log::debug!("Serial bridge: client disconnected");
+                *client = None;
+            }
+            BridgeEndpoint::TcpClient {
+                address,
+                stream,
+                next_attempt,
+                ..
+            } => {
+                log::debug!(
+                    "Serial bridge: lost connection to {}, reconnecting in {}ms",
+                    address,
+                    self.reconnect.as_millis()
+                );
+                *stream = None;
+                *next_attempt = Instant::now() + self.reconnect;
+            }
+            #[cfg(unix)]
+            BridgeEndpoint::Pty(_) => {}
+        }
+    }
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+#[cfg(unix)]
+mod pty {
+    use std::{
+        ffi::CStr,
+        fs::{File, OpenOptions},
+        io::{Read, Write},
+        os::{
+            raw::{c_char, c_int, c_void},
+            unix::{fs::OpenOptionsExt, io::AsRawFd},
+        },
+        path::PathBuf,
+    };
+
+    #[cfg(target_os = "linux")]
+    const O_NONBLOCK: i32 = 0o4000;
+    #[cfg(target_os = "linux")]
+    const O_NOCTTY: i32 = 0o400;
+    #[cfg(not(target_os = "linux"))]
+    const O_NONBLOCK: i32 = 0x0004;
+    #[cfg(not(target_os = "linux"))]
+    const O_NOCTTY: i32 = 0x20000;
+    const TCSANOW: c_int = 0;
+
+    extern "C" {
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *const c_char;
+        fn tcgetattr(fd: c_int, termios: *mut c_void) -> c_int;
+        fn tcsetattr(fd: c_int, action: c_int, termios: *const c_void) -> c_int;
+        fn cfmakeraw(termios: *mut c_void);
+    }
+
+    /// The master side of a pseudo-terminal. We keep the slave side open as well, so that the
+    /// terminal survives host tools attaching and detaching, and so that it can be put in raw mode
+    /// before anything else opens it.
+    pub struct Pty {
+        master: File,
+        _slave: File,
+        name:   String,
+        link:   Option<PathBuf>,
+    }
+
+    impl Pty {
+        pub fn open(link: Option<&str>) -> anyhow::Result<Self> {
+            let master = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(O_NONBLOCK | O_NOCTTY)
+                .open("/dev/ptmx")?;
+            let fd = master.as_raw_fd();
+
+            // SAFETY: fd is a valid, open pseudo-terminal master, and ptsname() returns a pointer
+            // to a NUL-terminated static buffer that we copy immediately.
+            let name = unsafe {
+                if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+                    anyhow::bail!("Couldn't unlock pseudo-terminal: {}", std::io::Error::last_os_error());
+                }
+                let name = ptsname(fd);
+                if name.is_null() {
+                    anyhow::bail!("Couldn't get pseudo-terminal name");
+                }
+                CStr::from_ptr(name).to_string_lossy().into_owned()
+            };
+
+            let slave = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(O_NOCTTY)
+                .open(&name)?;
+
+            // Put the terminal in raw mode so the line discipline doesn't echo or translate the
+            // guest's traffic. The termios structure is opaque to us; the buffer is larger than
+            // any platform's definition.
+            let mut termios = [0u64; 64];
+            // SAFETY: the slave fd is open and termios is large enough for the platform's struct.
+            unsafe {
+                let termios = termios.as_mut_ptr() as *mut c_void;
+                if tcgetattr(slave.as_raw_fd(), termios) == 0 {
+                    cfmakeraw(termios);
+                    tcsetattr(slave.as_raw_fd(), TCSANOW, termios);
+                }
+            }
+
+            let link = match link {
+                Some(link) => {
+                    let link = PathBuf::from(link);
+                    if link.is_symlink() {
+                        _ = std::fs::remove_file(&link);
+                    }
+                    std::os::unix::fs::symlink(&name, &link)?;
+                    Some(link)
+                }
+                None => None,
+            };
+
+            log::debug!("Opened pseudo-terminal {}", name);
+            Ok(Self {
+                master,
+                _slave: slave,
+                name,
+                link,
+            })
+        }
+
+        pub fn name(&self) -> String {
+            match &self.link {
+                Some(link) => format!("{} -> {}", link.display(), self.name),
+                None => self.name.clone(),
+            }
+        }
+    }
+
+    impl Read for Pty {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.master.read(buf) {
+                // A read of zero would otherwise look like a disconnect.
+                Ok(0) => Err(std::io::ErrorKind::WouldBlock.into()),
+                result => result,
+            }
+        }
+    }
+
+    impl Write for Pty {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.master.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.master.flush()
+        }
+    }
+
+    impl Drop for Pty {
+        fn drop(&mut self) {
+            if let Some(link) = &self.link {
+                _ = std::fs::remove_file(link);
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::sn76489.rs
+
+   Implements the TI SN76489 programmable sound generator, as found in the
+   IBM PCjr and Tandy 1000 (as the compatible SN76496 or NCR 8496), or on
+   a standalone "Tandy sound" card.
+
+   The chip has three square wave tone channels and one noise channel, each
+   with a 4-bit attenuator. It is clocked at 3.579545MHz and divides its
+   clock by 16. Output is box-filtered down to the sound output rate.
+
+*/
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::sounddevice::{AudioSample, SoundDevice},
+    devices::dma::DMAController,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+use crossbeam_channel::Sender;
+
+pub const SN_DEFAULT_IO_BASE: u16 = 0xC0;
+/// The PCjr and Tandy 1000 clock the chip from the 14.31818MHz system crystal divided by 4.
+pub const SN_CLOCK: f64 = 14_318_180.0 / 4.0;
+/// The chip decodes 8 consecutive ports.
+const SN_PORT_COUNT: u16 = 8;
+const SN_CLOCK_DIVISOR: f64 = 16.0;
+const DEFAULT_OUTPUT_RATE: u32 = 44100;
+
+const NOISE_LFSR_RESET: u16 = 0x4000;
+/// Each attenuator step is 2dB. Step 15 is off.
+const VOLUME_TABLE: [f32; 16] = [
+    1.0, 0.794, 0.631, 0.501, 0.398, 0.316, 0.251, 0.200, 0.158, 0.126, 0.100, 0.079, 0.063, 0.050, 0.040, 0.0,
+];
+/// Scale output so that all four channels at full volume do not clip.
+const CHANNEL_SCALE: f32 = 0.25;
+
+#[derive(Copy, Clone, Default)]
+struct ToneChannel {
+    period: u16,
+    counter: u16,
+    output: bool,
+    attenuation: u8,
+}
+
+pub struct Sn76489 {
+    io_base: u16,
+    tones: [ToneChannel; 3],
+    noise_control: u8,
+    noise_counter: u16,
+    noise_toggle: bool,
+    noise_lfsr: u16,
+    noise_attenuation: u8,
+    latched_register: u8,
+
+    chip_us: f64,
+    chip_accum: f64,
+    output_rate: f64,
+    output_accum: f64,
+    level_accum: f32,
+    level_count: u32,
+    sender: Option<Sender<AudioSample>>,
+}
+
+impl Sn76489 {
+    pub fn new(io_base: u16) -> Self {
+        let mut sn = Self {
+            io_base,
+            tones: [ToneChannel::default(); 3],
+            noise_control: 0,
+            noise_counter: 0,
+            noise_toggle: false,
+            noise_lfsr: NOISE_LFSR_RESET,
+            noise_attenuation: 0x0F,
+            latched_register: 0,
+            chip_us: 1_000_000.0 * SN_CLOCK_DIVISOR / SN_CLOCK,
+            chip_accum: 0.0,
+            output_rate: DEFAULT_OUTPUT_RATE as f64,
+            output_accum: 0.0,
+            level_accum: 0.0,
+            level_count: 0,
+            sender: None,
+        };
+        sn.reset();
+        sn
+    }
+
+    fn write_data(&mut self, data: u8) {
+        if data & 0x80 != 0 {
+            // Latch byte: bits 6-5 select the channel, bit 4 selects tone/noise or attenuation.
+            self.latched_register = (data >> 4) & 0x07;
+            self.write_register(data & 0x0F, true);
+        }
+        else {
+            self.write_register(data & 0x3F, false);
+        }
+    }
+
+    fn write_register(&mut self, value: u8, latch: bool) {
+        let channel = (self.latched_register >> 1) as usize;
+        let is_volume = self.latched_register & 0x01 != 0;
+
+        match (channel, is_volume) {
+            (0..=2, false) => {
+                let tone = &mut self.tones[channel];
+                tone.period = if latch {
+                    (tone.period & 0x3F0) | value as u16
+                }
+                else {
+                    (tone.period & 0x00F) | ((value as u16) << 4)
+                };
+            }
+            (0..=2, true) => self.tones[channel].attenuation = value & 0x0F,
+            (_, false) => {
+                // Writing the noise control register resets the shift register.
+                self.noise_control = value & 0x07;
+                self.noise_lfsr = NOISE_LFSR_RESET;
+            }
+            (_, true) => self.noise_attenuation = value & 0x0F,
+        }
+    }
+
+    /// Advance the chip by one divided clock.
+    fn tick(&mut self) {
+        for tone in self.tones.iter_mut() {
+            if tone.counter > 0 {
+                tone.counter -= 1;
+            }
+            if tone.counter == 0 {
+                // A period of 0 behaves as 0x400.
+                tone.counter = if tone.period == 0 { 0x400 } else { tone.period };
+                tone.output = !tone.output;
+            }
+        }
+
+        if self.noise_counter > 0 {
+            self.noise_counter -= 1;
+        }
+        if self.noise_counter == 0 {
+            self.noise_counter = match self.noise_control & 0x03 {
+                0 => 0x10,
+                1 => 0x20,
+                2 => 0x40,
+                // Use the period of tone channel 2.
+                _ => self.tones[2].period.max(1),
+            };
+            // The shift register is clocked on each rising edge of the noise toggle.
+            self.noise_toggle = !self.noise_toggle;
+            if self.noise_toggle {
+                let feedback = if self.noise_control & 0x04 != 0 {
+                    // White noise: taps on bits 0 and 1.
+                    (self.noise_lfsr ^ (self.noise_lfsr >> 1)) & 0x01
+                }
+                else {
+                    // Periodic noise
+                    self.noise_lfsr & 0x01
+                };
+                self.noise_lfsr = (self.noise_lfsr >> 1) | (feedback << 14);
+            }
+        }
+    }
+
+    fn level(&self) -> f32 {
+        let mut level = 0.0;
+        for tone in self.tones.iter() {
+            // Periods of 1 hold the output high, which software uses to play samples via the attenuator.
+            let high = tone.output || tone.period == 1;
+            level += if high { 1.0 } else { -1.0 } * VOLUME_TABLE[tone.attenuation as usize];
+        }
+        let noise_high = self.noise_lfsr & 0x01 != 0;
+        level += if noise_high { 1.0 } else { -1.0 } * VOLUME_TABLE[self.noise_attenuation as usize];
+        level * CHANNEL_SCALE
+    }
+}
+
+impl SoundDevice for Sn76489 {
+    fn name(&self) -> &'static str {
+        "SN76489 Sound Generator"
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    /// Send mono output samples at the specified sample rate to the sound output.
+    fn set_audio_sender(&mut self, sender: Sender<AudioSample>, sample_rate: u32) {
+        self.sender = Some(sender);
+        self.output_rate = sample_rate as f64;
+    }
+
+    /// Silence all channels. The chip powers on making noise; the BIOS is expected to silence it,
+    /// but we start quiet.
+    fn reset(&mut self) {
+        for tone in self.tones.iter_mut() {
+            *tone = ToneChannel {
+                attenuation: 0x0F,
+                ..Default::default()
+            };
+        }
+        self.noise_control = 0;
+        self.noise_counter = 0;
+        self.noise_lfsr = NOISE_LFSR_RESET;
+        self.noise_attenuation = 0x0F;
+        self.latched_register = 0;
+    }
+
+    fn run(&mut self, _dma: &mut DMAController, _bus: &mut BusInterface, us: f64) -> Option<bool> {
+        let us_per_sample = 1_000_000.0 / self.output_rate;
+
+        self.chip_accum += us;
+        while self.chip_accum >= self.chip_us {
+            self.chip_accum -= self.chip_us;
+            self.tick();
+            self.level_accum += self.level();
+            self.level_count += 1;
+
+            self.output_accum += self.chip_us;
+            if self.output_accum >= us_per_sample {
+                self.output_accum -= us_per_sample;
+                let sample = self.level_accum / self.level_count as f32;
+                self.level_accum = 0.0;
+                self.level_count = 0;
+                if let Some(sender) = &self.sender {
+                    _ = sender.send(sample);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Output filtering state is not saved; the next output sample is built from the restored chip.
+impl SaveState for Sn76489 {
+    fn save_state(&self, w: &mut StateWriter) {
+        for tone in self.tones.iter() {
+            w.write_u16(tone.period);
+            w.write_u16(tone.counter);
+            w.write_bool(tone.output);
+            w.write_u8(tone.attenuation);
+        }
+        w.write_u8(self.noise_control);
+        w.write_u16(self.noise_counter);
+        w.write_bool(self.noise_toggle);
+        w.write_u16(self.noise_lfsr);
+        w.write_u8(self.noise_attenuation);
+        w.write_u8(self.latched_register);
+        w.write_f64(self.chip_accum);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for tone in self.tones.iter_mut() {
+            tone.period = r.read_u16()? & 0x3FF;
+            tone.counter = r.read_u16()?;
+            tone.output = r.read_bool()?;
+            tone.attenuation = r.read_u8()? & 0x0F;
+        }
+        self.noise_control = r.read_u8()? & 0x07;
+        self.noise_counter = r.read_u16()?;
+        self.noise_toggle = r.read_bool()?;
+        self.noise_lfsr = r.read_u16()?;
+        self.noise_attenuation = r.read_u8()? & 0x0F;
+        self.latched_register = r.read_u8()? & 0x07;
+        self.chip_accum = r.read_f64()?;
+        self.output_accum = 0.0;
+        self.level_accum = 0.0;
+        self.level_count = 0;
+        Ok(())
+    }
+}
+
+impl IoDevice for Sn76489 {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        // The chip is write-only.
+        0xFF
+    }
+
+    fn write_u8(&mut self, _port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        self.write_data(data);
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        (0..SN_PORT_COUNT)
+            .map(|i| (String::from("SN76489 Sound Generator"), self.io_base + i))
+            .collect()
+    }
+}
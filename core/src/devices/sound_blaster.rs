@@ -0,0 +1,560 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::sound_blaster.rs
+
+   Implements the DSP of a Sound Blaster 2.0 (DSP version 2.01).
+
+   8-bit PCM is played and recorded through the 8237 DMA controller, one
+   byte per sample period as set by the DSP's time constant. The DSP keeps
+   its own transfer length counter, and raises an IRQ each time a block
+   completes. In auto-init mode the counter is reloaded from the block size,
+   while the DMA channel is expected to be programmed for auto-init as well.
+
+   There is no mixer on this feature level. ADPCM transfers are accepted
+   but played as silence. Recording always samples silence.
+
+   The FM synthesizer at base+8 is not mirrored; use the AdLib device at
+   388h for FM music.
+
+*/
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::sounddevice::{AudioSample, SoundDevice},
+    devices::dma::DMAController,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+use crossbeam_channel::Sender;
+
+pub const SB_DEFAULT_IO_BASE: u16 = 0x220;
+pub const SB_DEFAULT_IRQ: u8 = 7;
+pub const SB_DEFAULT_DMA: u8 = 1;
+
+const DEFAULT_OUTPUT_RATE: u32 = 44100;
+
+const DSP_VERSION: [u8; 2] = [0x02, 0x01];
+const DSP_RESET_RESPONSE: u8 = 0xAA;
+const DSP_OUTPUT_QUEUE_LEN: usize = 64;
+const DSP_SILENCE: u8 = 0x80;
+
+const PORT_RESET: u16 = 0x06;
+const PORT_READ_DATA: u16 = 0x0A;
+const PORT_WRITE: u16 = 0x0C;
+const PORT_READ_STATUS: u16 = 0x0E;
+
+const STATUS_READY: u8 = 0x80;
+
+// Increment table for the DSP's 0xE2 DMA identification command. Creative's drivers use this
+// to verify that they are talking to a genuine DSP.
+#[rustfmt::skip]
+const E2_INCR_TABLE: [[i16; 9]; 4] = [
+    [ 0x01, -0x02, -0x04,  0x08, -0x10,  0x20,  0x40, -0x80, -106],
+    [-0x01,  0x02, -0x04,  0x08,  0x10, -0x20,  0x40, -0x80,  165],
+    [-0x01,  0x02,  0x04, -0x08,  0x10, -0x20, -0x40,  0x80, -151],
+    [ 0x01, -0x02,  0x04, -0x08, -0x10,  0x20, -0x40,  0x80,   90],
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DmaMode {
+    None,
+    /// 8-bit PCM output.
+    Dac,
+    /// 8-bit PCM input.
+    Adc,
+    /// ADPCM output, played as silence.
+    Adpcm,
+    /// Output of silence with no DMA, via command 0x80.
+    Silence,
+}
+
+pub struct SoundBlaster {
+    io_base: u16,
+    irq: u8,
+    dma_channel: usize,
+
+    in_reset: bool,
+    command: Option<u8>,
+    args: Vec<u8>,
+    output: VecDeque<u8>,
+    test_reg: u8,
+    speaker_on: bool,
+    time_constant: u8,
+    block_size: u16,
+
+    dma_mode: DmaMode,
+    auto_init: bool,
+    exit_auto_init: bool,
+    dma_paused: bool,
+    // Bytes remaining in the current block.
+    block_remaining: u32,
+    sample_accum: f64,
+    e2_value: u8,
+    e2_count: usize,
+    e2_pending: bool,
+
+    irq_pending:  bool,
+    irq_asserted: bool,
+
+    level: u8,
+    output_rate: f64,
+    output_accum: f64,
+    level_accum: f64,
+    sender: Option<Sender<AudioSample>>,
+}
+
+impl SoundBlaster {
+    pub fn new(io_base: u16, irq: u8, dma_channel: u8) -> Self {
+        Self {
+            io_base,
+            irq,
+            dma_channel: dma_channel as usize,
+            in_reset: false,
+            command: None,
+            args: Vec::with_capacity(2),
+            output: VecDeque::with_capacity(DSP_OUTPUT_QUEUE_LEN),
+            test_reg: 0,
+            speaker_on: false,
+            time_constant: 0,
+            block_size: 0x7FF,
+            dma_mode: DmaMode::None,
+            auto_init: false,
+            exit_auto_init: false,
+            dma_paused: false,
+            block_remaining: 0,
+            sample_accum: 0.0,
+            e2_value: 0xAA,
+            e2_count: 0,
+            e2_pending: false,
+            irq_pending: false,
+            irq_asserted: false,
+            level: DSP_SILENCE,
+            output_rate: DEFAULT_OUTPUT_RATE as f64,
+            output_accum: 0.0,
+            level_accum: 0.0,
+            sender: None,
+        }
+    }
+
+    /// Return the sample rate in Hz programmed by the time constant.
+    pub fn sample_rate(&self) -> f64 {
+        1_000_000.0 / (256 - self.time_constant as u32) as f64
+    }
+
+    fn stop_dma(&mut self) {
+        self.dma_mode = DmaMode::None;
+        self.auto_init = false;
+        self.exit_auto_init = false;
+        self.dma_paused = false;
+        self.block_remaining = 0;
+        self.sample_accum = 0.0;
+    }
+
+    fn start_dma(&mut self, mode: DmaMode, auto_init: bool, length: u32) {
+        log::trace!(
+            "SB DSP: Starting {:?} transfer of {} bytes at {:.0}Hz (auto-init: {})",
+            mode,
+            length,
+            self.sample_rate(),
+            auto_init
+        );
+        self.dma_mode = mode;
+        self.auto_init = auto_init;
+        self.exit_auto_init = false;
+        self.dma_paused = false;
+        self.block_remaining = length;
+        self.sample_accum = 0.0;
+    }
+
+    fn push_output(&mut self, byte: u8) {
+        if self.output.len() < DSP_OUTPUT_QUEUE_LEN {
+            self.output.push_back(byte);
+        }
+    }
+
+    /// Return the number of argument bytes the specified command expects.
+    fn command_args(command: u8) -> usize {
+        match command {
+            0x10 | 0x40 | 0xE0 | 0xE2 | 0xE4 => 1,
+            0x14 | 0x16 | 0x17 | 0x24 | 0x48 | 0x74 | 0x75 | 0x76 | 0x77 | 0x80 => 2,
+            _ => 0,
+        }
+    }
+
+    fn arg_length(&self) -> u32 {
+        u16::from_le_bytes([self.args[0], self.args[1]]) as u32 + 1
+    }
+
+    fn write_command(&mut self, data: u8) {
+        match self.command {
+            None => {
+                self.command = Some(data);
+                self.args.clear();
+            }
+            Some(_) => self.args.push(data),
+        }
+
+        let command = self.command.unwrap();
+        if self.args.len() < Self::command_args(command) {
+            return;
+        }
+        self.command = None;
+
+        match command {
+            // Direct 8-bit output
+            0x10 => self.level = self.args[0],
+            // Single-cycle 8-bit DMA output
+            0x14 => self.start_dma(DmaMode::Dac, false, self.arg_length()),
+            // Single-cycle ADPCM output
+            0x16 | 0x17 | 0x74 | 0x75 | 0x76 | 0x77 => {
+                log::warn!(
+                    "SB DSP: ADPCM command {:02X} is not supported, playing silence.",
+                    command
+                );
+                self.start_dma(DmaMode::Adpcm, false, self.arg_length());
+            }
+            // Auto-init 8-bit DMA output, normal and high-speed
+            0x1C | 0x90 => self.start_dma(DmaMode::Dac, true, self.block_size as u32 + 1),
+            // Auto-init ADPCM output
+            0x1F | 0x7D | 0x7F => {
+                log::warn!(
+                    "SB DSP: ADPCM command {:02X} is not supported, playing silence.",
+                    command
+                );
+                self.start_dma(DmaMode::Adpcm, true, self.block_size as u32 + 1)
+            }
+            // Direct 8-bit input
+            0x20 => self.push_output(DSP_SILENCE),
+            // Single-cycle 8-bit DMA input
+            0x24 => self.start_dma(DmaMode::Adc, false, self.arg_length()),
+            // Auto-init 8-bit DMA input, normal and high-speed
+            0x2C | 0x98 => self.start_dma(DmaMode::Adc, true, self.block_size as u32 + 1),
+            // Set time constant
+            0x40 => {
+                self.time_constant = self.args[0];
+                log::trace!("SB DSP: Sample rate set to {:.0}Hz", self.sample_rate());
+            }
+            // Set block size
+            0x48 => self.block_size = u16::from_le_bytes([self.args[0], self.args[1]]),
+            // Output silence
+            0x80 => self.start_dma(DmaMode::Silence, false, self.arg_length()),
+            // Single-cycle high-speed DMA output and input
+            0x91 => self.start_dma(DmaMode::Dac, false, self.block_size as u32 + 1),
+            0x99 => self.start_dma(DmaMode::Adc, false, self.block_size as u32 + 1),
+            // Halt DMA
+            0xD0 => self.dma_paused = true,
+            // Speaker on/off
+            0xD1 => self.speaker_on = true,
+            0xD3 => self.speaker_on = false,
+            // Continue DMA
+            0xD4 => self.dma_paused = false,
+            // Speaker status
+            0xD8 => self.push_output(if self.speaker_on { 0xFF } else { 0x00 }),
+            // Exit auto-init after the current block
+            0xDA => self.exit_auto_init = true,
+            // DSP identification
+            0xE0 => self.push_output(!self.args[0]),
+            // Get DSP version
+            0xE1 => {
+                self.push_output(DSP_VERSION[0]);
+                self.push_output(DSP_VERSION[1]);
+            }
+            // DMA identification
+            0xE2 => {
+                let table = &E2_INCR_TABLE[self.e2_count % 4];
+                for (i, incr) in table[0..8].iter().enumerate() {
+                    if self.args[0] & (1 << i) != 0 {
+                        self.e2_value = self.e2_value.wrapping_add(*incr as u8);
+                    }
+                }
+                self.e2_value = self.e2_value.wrapping_add(table[8] as u8);
+                self.e2_count += 1;
+                self.e2_pending = true;
+            }
+            // Write and read test register
+            0xE4 => self.test_reg = self.args[0],
+            0xE8 => self.push_output(self.test_reg),
+            // Force IRQ
+            0xF2 => self.irq_pending = true,
+            _ => log::warn!("SB DSP: Unhandled command {:02X}", command),
+        }
+    }
+
+    /// Transfer one sample by DMA, returning true if the transfer happened.
+    fn transfer_sample(&mut self, dma: &mut DMAController, bus: &mut BusInterface) -> bool {
+        match self.dma_mode {
+            DmaMode::Silence => {
+                self.level = DSP_SILENCE;
+                true
+            }
+            _ if !dma.check_dma_ready(self.dma_channel) => false,
+            DmaMode::Dac => {
+                self.level = dma.do_dma_read_u8(bus, self.dma_channel);
+                true
+            }
+            DmaMode::Adpcm => {
+                _ = dma.do_dma_read_u8(bus, self.dma_channel);
+                self.level = DSP_SILENCE;
+                true
+            }
+            DmaMode::Adc => {
+                dma.do_dma_write_u8(bus, self.dma_channel, DSP_SILENCE);
+                true
+            }
+            DmaMode::None => false,
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.irq_pending = true;
+        if self.auto_init && !self.exit_auto_init {
+            self.block_remaining = self.block_size as u32 + 1;
+        }
+        else {
+            self.stop_dma();
+        }
+    }
+
+    fn run_output(&mut self, mut us: f64) {
+        let us_per_sample = 1_000_000.0 / self.output_rate;
+        let level = if self.speaker_on { self.level } else { DSP_SILENCE };
+
+        while us > 0.0 {
+            let slice = (us_per_sample - self.output_accum).min(us).max(0.0);
+            self.level_accum += level as f64 * slice;
+            self.output_accum += slice;
+            us -= slice;
+
+            if self.output_accum >= us_per_sample {
+                let level = self.level_accum / self.output_accum;
+                self.output_accum = 0.0;
+                self.level_accum = 0.0;
+                if let Some(sender) = &self.sender {
+                    _ = sender.send(((level - 128.0) / 128.0) as AudioSample);
+                }
+            }
+        }
+    }
+}
+
+impl SoundDevice for SoundBlaster {
+    fn name(&self) -> &'static str {
+        "Sound Blaster DSP"
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    /// Send mono output samples at the specified sample rate to the sound output.
+    fn set_audio_sender(&mut self, sender: Sender<AudioSample>, sample_rate: u32) {
+        self.sender = Some(sender);
+        self.output_rate = sample_rate as f64;
+    }
+
+    fn irq(&self) -> Option<u8> {
+        Some(self.irq)
+    }
+
+    fn dma(&self) -> Option<usize> {
+        Some(self.dma_channel)
+    }
+
+    /// Reset the DSP to its power-on state.
+    fn reset(&mut self) {
+        self.command = None;
+        self.args.clear();
+        self.output.clear();
+        self.speaker_on = false;
+        self.time_constant = 0;
+        self.block_size = 0x7FF;
+        self.stop_dma();
+        self.e2_value = 0xAA;
+        self.e2_count = 0;
+        self.e2_pending = false;
+        self.irq_pending = false;
+        self.level = DSP_SILENCE;
+    }
+
+    /// Run the DSP for the specified number of microseconds. Samples are transferred by DMA at the
+    /// programmed sample rate. Returns Some(state) when the IRQ line changes.
+    fn run(&mut self, dma: &mut DMAController, bus: &mut BusInterface, us: f64) -> Option<bool> {
+        if self.e2_pending && dma.check_dma_ready(self.dma_channel) {
+            dma.do_dma_write_u8(bus, self.dma_channel, self.e2_value);
+            self.e2_pending = false;
+        }
+
+        let mut remaining = us;
+        if self.dma_mode != DmaMode::None && !self.dma_paused {
+            let us_per_sample = 1_000_000.0 / self.sample_rate();
+            while self.dma_mode != DmaMode::None && self.sample_accum + remaining >= us_per_sample {
+                // Render output up to the sample boundary at the previous level.
+                let slice = us_per_sample - self.sample_accum;
+                self.run_output(slice);
+                remaining -= slice;
+                self.sample_accum = 0.0;
+
+                // A sample is only consumed if the DMA controller serviced the request. Otherwise the
+                // DSP waits, as the real DSP would stall on DREQ.
+                if self.transfer_sample(dma, bus) {
+                    self.block_remaining = self.block_remaining.saturating_sub(1);
+                    if self.block_remaining == 0 {
+                        self.end_block();
+                    }
+                }
+            }
+            self.sample_accum += remaining;
+        }
+        self.run_output(remaining);
+
+        if self.irq_pending != self.irq_asserted {
+            self.irq_asserted = self.irq_pending;
+            return Some(self.irq_asserted);
+        }
+        None
+    }
+}
+
+impl SaveState for SoundBlaster {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.in_reset);
+        w.write_option_u8(self.command);
+        w.write_bytes(&self.args);
+        w.write_bytes(&self.output.iter().copied().collect::<Vec<u8>>());
+        w.write_u8(self.test_reg);
+        w.write_bool(self.speaker_on);
+        w.write_u8(self.time_constant);
+        w.write_u16(self.block_size);
+        w.write_u8(match self.dma_mode {
+            DmaMode::None => 0,
+            DmaMode::Dac => 1,
+            DmaMode::Adc => 2,
+            DmaMode::Adpcm => 3,
+            DmaMode::Silence => 4,
+        });
+        w.write_bool(self.auto_init);
+        w.write_bool(self.exit_auto_init);
+        w.write_bool(self.dma_paused);
+        w.write_u32(self.block_remaining);
+        w.write_f64(self.sample_accum);
+        w.write_u8(self.e2_value);
+        w.write_usize(self.e2_count);
+        w.write_bool(self.e2_pending);
+        w.write_bool(self.irq_pending);
+        w.write_bool(self.irq_asserted);
+        w.write_u8(self.level);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.in_reset = r.read_bool()?;
+        self.command = r.read_option_u8()?;
+        self.args = r.read_bytes()?.to_vec();
+        self.output = r.read_bytes()?.iter().copied().collect();
+        self.test_reg = r.read_u8()?;
+        self.speaker_on = r.read_bool()?;
+        self.time_constant = r.read_u8()?;
+        self.block_size = r.read_u16()?;
+        self.dma_mode = match r.read_u8()? {
+            0 => DmaMode::None,
+            1 => DmaMode::Dac,
+            2 => DmaMode::Adc,
+            3 => DmaMode::Adpcm,
+            4 => DmaMode::Silence,
+            _ => return Err(SaveStateError::InvalidValue("Sound Blaster DMA mode")),
+        };
+        self.auto_init = r.read_bool()?;
+        self.exit_auto_init = r.read_bool()?;
+        self.dma_paused = r.read_bool()?;
+        self.block_remaining = r.read_u32()?;
+        self.sample_accum = r.read_f64()?;
+        self.e2_value = r.read_u8()?;
+        self.e2_count = r.read_usize()?;
+        self.e2_pending = r.read_bool()?;
+        self.irq_pending = r.read_bool()?;
+        self.irq_asserted = r.read_bool()?;
+        self.level = r.read_u8()?;
+        if self.args.len() > 2 || self.output.len() > DSP_OUTPUT_QUEUE_LEN {
+            return Err(SaveStateError::InvalidValue("Sound Blaster DSP queue"));
+        }
+        self.output_accum = 0.0;
+        self.level_accum = 0.0;
+        Ok(())
+    }
+}
+
+impl IoDevice for SoundBlaster {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port - self.io_base {
+            PORT_READ_DATA => self.output.pop_front().unwrap_or(0xFF),
+            PORT_WRITE => {
+                // Bit 7 clear indicates the DSP is ready to accept a command.
+                !STATUS_READY
+            }
+            PORT_READ_STATUS => {
+                // Reading the status port acknowledges the 8-bit DMA interrupt.
+                self.irq_pending = false;
+                if self.output.is_empty() {
+                    !STATUS_READY
+                }
+                else {
+                    0xFF
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port - self.io_base {
+            PORT_RESET => {
+                if data & 0x01 != 0 {
+                    self.in_reset = true;
+                }
+                else if self.in_reset {
+                    self.in_reset = false;
+                    self.reset();
+                    self.push_output(DSP_RESET_RESPONSE);
+                }
+            }
+            PORT_WRITE => {
+                if !self.in_reset {
+                    self.write_command(data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("SB DSP Reset"), self.io_base + PORT_RESET),
+            (String::from("SB DSP Read Data"), self.io_base + PORT_READ_DATA),
+            (String::from("SB DSP Write Command/Status"), self.io_base + PORT_WRITE),
+            (String::from("SB DSP Read Status"), self.io_base + PORT_READ_STATUS),
+        ]
+    }
+}
@@ -0,0 +1,242 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::speaker_filter.rs
+
+    Filter chain for the PC speaker.
+
+    The PIT produces an ideal square wave, but the small paper cone in a PC
+    can't follow it. The cone has mass and is held by a suspension, so it
+    behaves as a resonant second-order low-pass filter, rounding off edges
+    and ringing slightly after each one. The speaker also cannot reproduce
+    low frequencies or DC, and the case and driver circuit attenuate highs.
+
+    The filter chain models these as a high-pass filter, a resonant low-pass
+    'cone' filter and a final one-pole low-pass filter. This also smooths the
+    PWM carrier used by RealSound and similar techniques to play samples,
+    which would otherwise be heard as a harsh whine.
+
+*/
+
+use serde_derive::Deserialize;
+
+/// A selection of filter settings approximating different speakers.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum SpeakerFilterPreset {
+    /// No filtering. The speaker output is the raw PIT output.
+    Raw,
+    /// The 2.25" speaker in the IBM 5150 and 5160.
+    #[default]
+    Ibm5150,
+    /// A smaller speaker with less bass and a stronger resonance, such as in
+    /// portables and the PCjr.
+    Compact,
+    /// A heavily filtered, softer sound.
+    Soft,
+}
+
+/// Cutoff frequencies in Hz for each stage of the filter chain. A stage is
+/// bypassed if its frequency is not set.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SpeakerFilterParams {
+    pub highpass: Option<f32>,
+    pub cone_hz:  Option<f32>,
+    pub cone_q:   f32,
+    pub lowpass:  Option<f32>,
+}
+
+impl SpeakerFilterPreset {
+    pub fn params(&self) -> SpeakerFilterParams {
+        match self {
+            SpeakerFilterPreset::Raw => SpeakerFilterParams::default(),
+            SpeakerFilterPreset::Ibm5150 => SpeakerFilterParams {
+                highpass: Some(150.0),
+                cone_hz:  Some(5500.0),
+                cone_q:   1.4,
+                lowpass:  Some(9000.0),
+            },
+            SpeakerFilterPreset::Compact => SpeakerFilterParams {
+                highpass: Some(300.0),
+                cone_hz:  Some(4000.0),
+                cone_q:   2.0,
+                lowpass:  Some(7000.0),
+            },
+            SpeakerFilterPreset::Soft => SpeakerFilterParams {
+                highpass: Some(80.0),
+                cone_hz:  Some(3000.0),
+                cone_q:   0.707,
+                lowpass:  Some(4000.0),
+            },
+        }
+    }
+}
+
+/// A second-order IIR filter section, using the coefficients from Robert
+/// Bristow-Johnson's Audio EQ Cookbook.
+#[derive(Copy, Clone, Debug, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn lowpass(sample_rate: f32, cutoff: f32, q: f32) -> Self {
+        let (cos_w0, alpha) = Biquad::omega(sample_rate, cutoff, q);
+        Biquad::normalize(
+            (1.0 - cos_w0) / 2.0,
+            1.0 - cos_w0,
+            (1.0 - cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    fn highpass(sample_rate: f32, cutoff: f32, q: f32) -> Self {
+        let (cos_w0, alpha) = Biquad::omega(sample_rate, cutoff, q);
+        Biquad::normalize(
+            (1.0 + cos_w0) / 2.0,
+            -(1.0 + cos_w0),
+            (1.0 + cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    fn omega(sample_rate: f32, cutoff: f32, q: f32) -> (f32, f32) {
+        // Keep the cutoff below Nyquist, or the filter becomes unstable.
+        let cutoff = cutoff.clamp(1.0, sample_rate * 0.45);
+        let w0 = 2.0 * std::f32::consts::PI * cutoff / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q.max(0.1)))
+    }
+
+    fn normalize(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+
+    fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+}
+
+/// A one-pole low-pass filter.
+#[derive(Copy, Clone, Debug, Default)]
+struct OnePole {
+    alpha: f32,
+    y: f32,
+}
+
+impl OnePole {
+    fn lowpass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff.max(1.0));
+        let dt = 1.0 / sample_rate;
+        Self {
+            alpha: dt / (rc + dt),
+            y: 0.0,
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        self.y += self.alpha * (x - self.y);
+        self.y
+    }
+}
+
+pub struct SpeakerFilter {
+    highpass: Option<Biquad>,
+    cone: Option<Biquad>,
+    lowpass: Option<OnePole>,
+}
+
+impl SpeakerFilter {
+    pub fn new(params: SpeakerFilterParams, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            highpass: params
+                .highpass
+                .map(|hz| Biquad::highpass(sample_rate, hz, std::f32::consts::FRAC_1_SQRT_2)),
+            cone: params.cone_hz.map(|hz| Biquad::lowpass(sample_rate, hz, params.cone_q)),
+            lowpass: params.lowpass.map(|hz| OnePole::lowpass(sample_rate, hz)),
+        }
+    }
+
+    /// Filter one speaker sample. The input is the speaker level from 0.0 to 1.0.
+    #[inline]
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let mut sample = sample;
+        if let Some(highpass) = &mut self.highpass {
+            sample = highpass.process(sample);
+        }
+        if let Some(cone) = &mut self.cone {
+            sample = cone.process(sample);
+        }
+        if let Some(lowpass) = &mut self.lowpass {
+            sample = lowpass.process(sample);
+        }
+        sample
+    }
+
+    pub fn reset(&mut self) {
+        if let Some(highpass) = &mut self.highpass {
+            highpass.reset();
+        }
+        if let Some(cone) = &mut self.cone {
+            cone.reset();
+        }
+        if let Some(lowpass) = &mut self.lowpass {
+            lowpass.y = 0.0;
+        }
+    }
+}
@@ -98,6 +98,9 @@ impl IoDevice for TGACard {
 
         //self.rw_op(ticks, data, port as u32, RwSlotType::Io);
 
+        self.log_register_write(port, data);
+        self.check_breakpoints(port, data);
+
         if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Write is to CRTC register.
             if port & 0x01 == 0 {
@@ -39,6 +39,10 @@ use crate::{
 /// but we handle the mirroring of VRAM this way, and for consistency with other devices
 impl MemoryMappedDevice for TGACard {
     fn get_read_wait(&mut self, _address: usize, cycles: u32) -> u32 {
+        if let VideoCardSubType::IbmPCJr = self.subtype {
+            // The PCjr aperture maps onto shared system RAM, so is subject to the gate array's timing.
+            return self.get_shared_mem_wait(cycles);
+        }
         // Look up wait states given the last ticked clock cycle + elapsed cycles
         // passed in.
         let phase = (self.cycles + cycles as u64 + 1) as usize & (0x0F as usize);
@@ -49,6 +53,9 @@ impl MemoryMappedDevice for TGACard {
     }
 
     fn get_write_wait(&mut self, _address: usize, cycles: u32) -> u32 {
+        if let VideoCardSubType::IbmPCJr = self.subtype {
+            return self.get_shared_mem_wait(cycles);
+        }
         // Look up wait states given the last ticked clock cycle + elapsed cycles
         // passed in.
         let phase = (self.cycles + cycles as u64 + 1) as usize & (0x0F as usize);
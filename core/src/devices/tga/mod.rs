@@ -56,6 +56,7 @@ use std::{collections::HashMap, convert::TryInto, path::Path};
 mod io;
 mod draw;
 mod mmio;
+mod savestate;
 mod tablegen;
 mod videocard;
 
@@ -140,6 +141,13 @@ static DUMMY_PIXEL: [u8; 4] = [0, 0, 0, 0];
 static WAIT_TABLE: [u32; 16] = [14, 13, 12, 11, 10, 9, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15];
 // in cpu cycles: 5,5,4,4,4,3,8,8,8,7,7,7,6,6,6,5
 
+// The PCjr's video gate array shares the first 128K of system RAM with the CPU. Memory cycles
+// are interleaved with video fetches, and the CPU is granted a slot once every 8 system ticks.
+// Precalculated waits in system ticks for each of the 16 phases of the gate array's clock.
+static JR_WAIT_TABLE: [u32; 16] = [8, 7, 6, 5, 4, 3, 2, 1, 8, 7, 6, 5, 4, 3, 2, 1];
+/// Amount of system RAM shared with the PCjr's video gate array.
+pub const TGA_JR_SHARED_MEM_SIZE: usize = 0x20000;
+
 pub const TGA_MEM_ADDRESS: usize = 0xB8000;
 pub const TGA_MEM_APERTURE: usize = 0x8000; // 32Kb aperture.
 pub const TGA_MEM_SIZE: usize = 0x8000; // 32Kb vram
@@ -205,6 +213,8 @@ const CGA_MONITOR_HSYNC_WIDTH: u32 = 80;
 const CGA_MONITOR_VSYNC_POS: u32 = 246;
 // Minimum scanline value after which we can perform a vsync. A vsync before this scanline will be ignored.
 const CGA_MONITOR_VSYNC_MIN: u32 = 127;
+// Nominal horizontal scan rate of a CGA monitor, in kHz.
+const CGA_MONITOR_HFREQ: f64 = 15.699;
 
 // For derivation of CGA timings, see https://www.vogons.org/viewtopic.php?t=47052
 // We run the CGA card independent of the CPU frequency.
@@ -678,6 +688,16 @@ pub struct TGACard {
 
     lightpen_latch: bool,
     lightpen_addr:  usize,
+    lightpen_pos:   Option<(u32, u32)>,
+
+    reg_log: Option<VideoRegisterLog>,
+    reg_log_last: Option<VideoRegisterLog>,
+
+    breakpoints: Vec<VideoBreakpoint>,
+    breakpoint_hit: bool,
+
+    raster_regions: RasterRegions,
+    raster_regions_last: Option<RasterRegions>,
 
     // TGA stuff
     do_vsync: bool,
@@ -738,6 +758,7 @@ impl TgaDefault for DisplayExtents {
             row_stride: CGA_XRES_MAX as usize,
             double_scan: true,
             mode_byte: 0,
+            standard: VideoStandard::Ntsc,
         }
     }
 }
@@ -897,6 +918,16 @@ impl Default for TGACard {
 
             lightpen_latch: false,
             lightpen_addr:  0,
+            lightpen_pos:   None,
+
+            reg_log: None,
+            reg_log_last: None,
+
+            breakpoints: Vec::new(),
+            breakpoint_hit: false,
+
+            raster_regions: Default::default(),
+            raster_regions_last: None,
 
             // TGA stuff
             do_vsync: false,
@@ -959,12 +990,22 @@ impl TGACard {
             enable_snow: self.enable_snow,
             frame_count: self.frame_count, // Keep frame count as to not confuse frontend
             trace_logger,
+            reg_log: self.reg_log.as_ref().map(|_| VideoRegisterLog::default()),
+            breakpoints: self.breakpoints.clone(),
             extents: self.extents.clone(),
 
             ..Self::default()
         }
     }
 
+    /// Return the number of system ticks the CPU must wait for a memory slot when accessing
+    /// the PCjr's shared video RAM, given the elapsed cycles since the card was last ticked.
+    #[inline]
+    pub fn get_shared_mem_wait(&self, cycles: u32) -> u32 {
+        let phase = (self.cycles + cycles as u64 + 1) as usize & 0x0F;
+        JR_WAIT_TABLE[phase]
+    }
+
     #[inline(always)]
     pub fn set_a0(&mut self, byte: u8) {
         self.a0 = byte & 0x0F;
@@ -1080,11 +1121,84 @@ impl TGACard {
         self.lightpen_latch = true;
     }
 
+    /// Enable or disable logging of register writes.
+    fn set_register_logging(&mut self, state: bool) {
+        self.reg_log = state.then(|| VideoRegisterLog {
+            frame:  self.frame_count,
+            writes: Vec::new(),
+        });
+        self.reg_log_last = None;
+    }
+
+    /// Record a register write along with the current raster position, if logging is enabled.
+    fn log_register_write(&mut self, port: u16, data: u8) {
+        if let Some(log) = &mut self.reg_log {
+            let index = if (port & !io::CRTC_REGISTER_MASK) == io::CRTC_REGISTER_BASE && (port & 0x01 != 0) {
+                Some(self.crtc_register_select_byte)
+            }
+            else {
+                None
+            };
+            log.writes.push(VideoRegisterWrite {
+                scanline: self.beam_y,
+                hdot: self.beam_x,
+                port,
+                index,
+                value: data,
+            });
+        }
+    }
+
+    /// Set the breakpoint flag if a register write matches any of the current video breakpoints.
+    fn check_breakpoints(&mut self, port: u16, data: u8) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+
+        let hit = if port == io::CGA_MODE_CONTROL_REGISTER {
+            self.breakpoints.iter().any(|bp| bp.matches_mode_write(data))
+        }
+        else if (port & !io::CRTC_REGISTER_MASK) == io::CRTC_REGISTER_BASE && (port & 0x01 != 0) {
+            let reg = self.crtc_register_select_byte;
+            self.breakpoints.iter().any(|bp| bp.matches_crtc_write(reg, data))
+        }
+        else {
+            false
+        };
+
+        if hit {
+            log::debug!("Video breakpoint hit: write of {:02X} to port {:03X}", data, port);
+            self.breakpoint_hit = true;
+        }
+    }
+
     fn clear_lp_latch(&mut self) {
         log::debug!("clearing lightpen latch");
         self.lightpen_latch = false;
     }
 
+    /// Trigger the light pen latch if the character just drawn passed under the light pen.
+    /// The pen only sees the beam within the display area.
+    #[inline]
+    fn check_lp_trigger(&mut self) {
+        if let Some((pen_x, pen_y)) = self.lightpen_pos {
+            let char_w = TGA_HCHAR_CLOCK as u32 * self.clock_divisor as u32;
+            if self.in_display_area && self.beam_y == pen_y && pen_x < self.beam_x && pen_x + char_w >= self.beam_x {
+                self.set_lp_latch();
+            }
+        }
+    }
+
+    /// Expand the display area of the current frame's raster regions if the character just drawn
+    /// was within it.
+    #[inline]
+    fn mark_raster_regions(&mut self) {
+        if self.in_display_area {
+            let char_w = TGA_HCHAR_CLOCK as u32 * self.clock_divisor as u32;
+            self.raster_regions.mark_display(self.beam_x.saturating_sub(char_w), self.beam_y, char_w);
+        }
+    }
+
     fn get_cursor_span(&self) -> (u8, u8) {
         (self.crtc_cursor_start_line, self.crtc_cursor_end_line)
     }
@@ -1460,13 +1574,23 @@ impl TGACard {
                 // in which case overscan must be black (0).
                 self.cc_overscan_color = if self.mode_hires_gfx { 0 } else { self.border_color };
 
+                // The Tandy's 640x200 4-color mode is selected by the 2bpp hires bit in the video
+                // array mode control register in combination with the high-res text clock. It is
+                // drawn with the same 2bpp hchar path as the PCjr's equivalent mode.
+                let tandy_hires_4color =
+                    self.mode_graphics && self.mode_hires_txt && !self.mode_4bpp && self.t_mode_control.twobpp_hires();
+                if tandy_hires_4color {
+                    self.mode_hires_gfx = true;
+                }
+
                 // Reinterpret the CC register based on new mode.
                 self.update_palette();
 
                 // Attempt to update clock.
                 self.update_clock();
 
-                let mut vmode_byte = self.mode_byte;
+                // The blinking bit shares a position with our virtual 4bpp bit, so mask it off.
+                let mut vmode_byte = self.mode_byte & !MODE_BLINKING;
                 if self.mode_4bpp {
                     // Replaces blinking bit
                     vmode_byte |= VMODE_4BPP;
@@ -1476,6 +1600,8 @@ impl TGACard {
                 // the CGA card outputs video at a given moment. This can be toggled on
                 // and off during a single frame, such as done in VileR's fontcmp.com
                 self.display_mode = match vmode_byte & CGA_MODE_ENABLE_MASK {
+                    _ if tandy_hires_4color => DisplayMode::ModeATGAHighResGraphics,
+                    0b10_0011 => DisplayMode::Mode8TGAMedResGraphics16,
                     0b00_0100 => DisplayMode::Mode0TextBw40,
                     0b00_0000 => DisplayMode::Mode1TextCo40,
                     0b00_0101 => DisplayMode::Mode2TextBw80,
@@ -1584,6 +1710,8 @@ impl TGACard {
                 }
             };
 
+            // The mode size may have changed since the page register was last written.
+            self.update_page_offsets();
             self.clock_pending = false;
         }
     }
@@ -1654,7 +1782,9 @@ impl TGACard {
         }
 
         // This bit is logically reversed, i.e., 0 is switch on
-        //byte |= STATUS_LIGHTPEN_SWITCH_STATUS;
+        if self.lightpen_pos.is_none() {
+            byte |= STATUS_LIGHTPEN_SWITCH_STATUS;
+        }
 
         // Video MUX bits for TGA/PCJr.
         // The PCJr POST tests the mux bit by drawing a line of full-block characters to the top row
@@ -2494,6 +2624,9 @@ impl TGACard {
 
     /// Update the CRTC logic for next character.
     pub fn tick_crtc_char(&mut self) {
+        self.check_lp_trigger();
+        self.mark_raster_regions();
+
         if self.hcc_c0 == 0 {
             self.hborder = false;
             if self.vcc_c4 == 0 {
@@ -2622,6 +2755,7 @@ impl TGACard {
         if self.hcc_c0 == self.crtc_horizontal_sync_pos {
             // We entered horizontal blank
             self.in_crtc_hblank = true;
+            self.raster_regions.mark_hblank(self.beam_x);
             self.hsc_c3l = 0;
         }
 
@@ -2675,6 +2809,7 @@ impl TGACard {
                     trace_regs!(self);
                     trace!(self, "Entering vsync");
                     self.in_crtc_vblank = true;
+                    self.raster_regions.mark_vblank(self.beam_y);
                     self.in_display_area = false;
                 }
 
@@ -2776,6 +2911,17 @@ impl TGACard {
             self.scanline = 0;
             self.frame_count += 1;
 
+            self.raster_regions_last = Some(std::mem::take(&mut self.raster_regions));
+
+            // Start a new register log for the next frame.
+            if let Some(log) = &mut self.reg_log {
+                let new_log = VideoRegisterLog {
+                    frame:  self.frame_count,
+                    writes: Vec::new(),
+                };
+                self.reg_log_last = Some(std::mem::replace(log, new_log));
+            }
+
             // Save the current mode byte, used for composite rendering.
             // The mode could have changed several times per frame, but I am not sure how the composite rendering should
             // really handle that...
@@ -2851,19 +2997,24 @@ impl TGACard {
     pub fn page_register_write(&mut self, data: u8) {
         self.page_register = TPageRegister::from_bytes([data]);
         log::debug!("TGA Page Register: {:?}", self.page_register);
+        self.update_page_offsets();
+    }
+
+    /// Recalculate the CPU and CRT page offsets from the page register and current mode size.
+    fn update_page_offsets(&mut self) {
         match self.mode_size {
             VideoModeSize::Mode16k => {
-                // Select 16K page for CPU
+                // Select 16K pages for CPU and CRT
                 self.cpu_page_offset = self.page_register.cpu_page() as usize * 0x4000;
+                self.crt_page_offset = self.page_register.crt_page() as usize * 0x4000;
             }
             VideoModeSize::Mode32k => {
-                // Select 32K page for CPU} // Select 32K page for CPU
-                // 32K page is chosen by ignoring bit 0 of the CPU page register
+                // Select 32K pages for CPU and CRT.
+                // 32K page is chosen by ignoring bit 0 of the page register fields
                 self.cpu_page_offset = (self.page_register.cpu_page() & 0x0E) as usize * 0x4000;
+                self.crt_page_offset = (self.page_register.crt_page() & 0x0E) as usize * 0x4000;
             }
         }
-
-        self.crt_page_offset = self.page_register.crt_page() as usize * 0x4000; // Select 16K page for TGA
         log::debug!(
             "New page offsets: CPU {:05X} TGA {:05X}",
             self.cpu_page_offset,
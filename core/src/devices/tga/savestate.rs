@@ -0,0 +1,129 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::tga::savestate.rs
+
+    Implements save state support for the Tandy 1000 and PCjr video gate array.
+
+    The TGA has no memory of its own; video memory is system RAM and is saved
+    with the bus. The CRTC register file, the mode and color control registers,
+    the video array registers and the page register are saved. On restore the
+    registers are written back through the same paths as a port write so that
+    all derived state is recalculated. Raster position is not saved.
+
+*/
+
+use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+const VIDEO_ARRAY_CT: usize = 0x20;
+
+impl TGACard {
+    pub fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        let crtc_regs = [
+            self.crtc_horizontal_total,
+            self.crtc_horizontal_displayed,
+            self.crtc_horizontal_sync_pos,
+            self.crtc_sync_width,
+            self.crtc_vertical_total,
+            self.crtc_vertical_total_adjust,
+            self.crtc_vertical_displayed,
+            self.crtc_vertical_sync_pos,
+            self.crtc_interlace_mode,
+            self.crtc_maximum_scanline_address,
+            self.crtc_cursor_start_line | (self.cursor_attr << 5),
+            self.crtc_cursor_end_line,
+            self.crtc_start_address_ho,
+            self.crtc_start_address_lo,
+            self.crtc_cursor_address_ho,
+            self.crtc_cursor_address_lo,
+        ];
+
+        // Video array registers, by index. Register 3 is the mode control register specific
+        // to each subtype.
+        let mut video_array = [0u8; VIDEO_ARRAY_CT];
+        video_array[0x00] = self.jr_mode_control.into_bytes()[0];
+        video_array[0x01] = self.palette_mask;
+        video_array[0x02] = self.border_color;
+        video_array[0x03] = match self.subtype {
+            VideoCardSubType::IbmPCJr => self.jr_mode_control2.into_bytes()[0],
+            _ => self.t_mode_control.into_bytes()[0],
+        };
+        video_array[0x10..].copy_from_slice(&self.palette_registers);
+
+        w.write_bytes(&crtc_regs);
+        w.write_u8(self.crtc_register_select_byte);
+        w.write_u8(self.mode_byte);
+        w.write_u8(self.cc_register);
+        w.write_bytes(&video_array);
+        w.write_usize(self.video_array_address);
+        w.write_bool(self.address_flipflop);
+        w.write_u8(self.page_register.into_bytes()[0]);
+        w.write_bool(self.intr);
+        w.write_bool(self.last_intr);
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut crtc_regs = [0u8; 16];
+        r.read_bytes_into(&mut crtc_regs, "TGA CRTC registers")?;
+        let register_select = r.read_u8()?;
+        let mode_byte = r.read_u8()?;
+        let cc_register = r.read_u8()?;
+        let mut video_array = [0u8; VIDEO_ARRAY_CT];
+        r.read_bytes_into(&mut video_array, "TGA video array registers")?;
+        let video_array_address = r.read_usize()?;
+        let address_flipflop = r.read_bool()?;
+        let page_register = r.read_u8()?;
+        self.intr = r.read_bool()?;
+        self.last_intr = r.read_bool()?;
+        if video_array_address >= VIDEO_ARRAY_CT {
+            return Err(SaveStateError::InvalidValue("TGA video array address"));
+        }
+
+        for (idx, byte) in crtc_regs.iter().enumerate() {
+            self.handle_crtc_register_select(idx as u8);
+            self.handle_crtc_register_write(*byte);
+        }
+        self.handle_crtc_register_select(register_select);
+        self.recalc_extents();
+
+        for (idx, byte) in video_array.iter().enumerate() {
+            self.video_array_select(idx as u8);
+            self.video_array_write(*byte);
+        }
+        self.video_array_select(video_array_address as u8);
+        self.address_flipflop = address_flipflop;
+        self.page_register_write(page_register);
+
+        // Apply the mode immediately instead of deferring it to the next hsync.
+        self.mode_pending = false;
+        self.mode_byte = mode_byte;
+        self.update_mode();
+        self.handle_cc_register_write(cc_register);
+        Ok(())
+    }
+}
@@ -35,6 +35,7 @@ use crate::{
     devices::{
         pic::Pic,
     },
+    savestate::{SaveStateError, StateReader, StateWriter},
 };
 
 // Helper macro for pushing video card state entries.
@@ -82,6 +83,10 @@ impl VideoCard for TGACard {
                 log::debug!("VideoOption::DebugDraw set to: {}", state);
                 self.debug_draw = state;
             }
+            VideoOption::LogRegisterWrites(state) => {
+                log::debug!("VideoOption::LogRegisterWrites set to: {}", state);
+                self.set_register_logging(state);
+            }
         }
     }
 
@@ -141,6 +146,27 @@ impl VideoCard for TGACard {
         Some((self.beam_x, self.beam_y))
     }
 
+    fn set_light_pen(&mut self, pos: Option<(u32, u32)>) {
+        self.lightpen_pos = pos;
+    }
+
+    fn get_register_log(&self) -> Option<&VideoRegisterLog> {
+        self.reg_log_last.as_ref()
+    }
+
+    fn set_breakpoints(&mut self, bp_list: Vec<VideoBreakpoint>) {
+        self.breakpoints = bp_list;
+        self.breakpoint_hit = false;
+    }
+
+    fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::replace(&mut self.breakpoint_hit, false)
+    }
+
+    fn get_raster_regions(&self) -> Option<RasterRegions> {
+        self.raster_regions_last
+    }
+
     /// Tick the CGA the specified number of video clock cycles.
     fn debug_tick(&mut self, ticks: u32, cpumem: Option<&[u8]>) {
         match self.clock_mode {
@@ -239,6 +265,11 @@ impl VideoCard for TGACard {
     }
 
     /// Return the 16-bit value computed from the CRTC's pair of Page Address registers.
+    /// The TGA has no dedicated video memory; it displays a page of system memory.
+    fn get_vram(&self) -> Option<&[u8]> {
+        None
+    }
+
     fn get_start_address(&self) -> u16 {
         return (self.crtc_start_address_ho as u16) << 8 | self.crtc_start_address_lo as u16;
     }
@@ -289,6 +320,32 @@ impl VideoCard for TGACard {
         })
     }
 
+    fn get_crtc_timing(&self) -> Option<CrtcTiming> {
+        let regs = [
+            self.crtc_horizontal_total,
+            self.crtc_horizontal_displayed,
+            self.crtc_horizontal_sync_pos,
+            self.crtc_sync_width,
+            self.crtc_vertical_total,
+            self.crtc_vertical_total_adjust,
+            self.crtc_vertical_displayed,
+            self.crtc_vertical_sync_pos,
+            self.crtc_interlace_mode,
+            self.crtc_maximum_scanline_address,
+        ];
+        Some(CrtcTiming::from_6845(&regs, CGA_CLOCK / self.char_clock as f64, CGA_MONITOR_HFREQ))
+    }
+
+    fn get_character_sets(&self) -> Vec<CharacterSet> {
+        vec![CharacterSet::from_row_major(
+            "Character ROM",
+            TGA_HCHAR_CLOCK as u32,
+            CRTC_FONT_HEIGHT as u32,
+            TGA_FONT_SPAN,
+            TGA_FONT,
+        )]
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc_maximum_scanline_address + 1
     }
@@ -297,6 +354,20 @@ impl VideoCard for TGACard {
         None
     }
 
+    fn get_palette_state(&self) -> Option<PaletteState> {
+        Some(PaletteState {
+            palette: self
+                .palette_registers
+                .iter()
+                .map(|&reg| (reg, PaletteColor::Irgb(reg & 0x0F)))
+                .collect(),
+            overscan: Some(PaletteColor::Irgb(self.cc_overscan_color)),
+            blink_enabled: self.mode_blinking,
+            blink_state: self.blink_state,
+            dac: Vec::new(),
+        })
+    }
+
     #[rustfmt::skip]
     fn get_videocard_string_state(&self) -> HashMap<String, Vec<(String, VideoCardStateEntry)>> {
         let mut map = HashMap::new();
@@ -615,4 +686,17 @@ impl VideoCard for TGACard {
         strings*/
         Vec::new()
     }
+
+    /// Unimplemented
+    fn get_text_mode_screen(&self, _full_buffer: bool) -> Option<TextModeScreen> {
+        None
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
 }
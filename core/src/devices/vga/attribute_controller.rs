@@ -31,9 +31,10 @@
 */
 
 use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
-pub const DAC_STATE_READ: u8 = 0;
-pub const DAC_STATE_WRITE: u8 = 0x03;
+pub const DAC_STATE_READ: u8 = 0x03;
+pub const DAC_STATE_WRITE: u8 = 0;
 
 #[derive(Copy, Clone, Debug)]
 pub enum AttributeRegister {
@@ -234,8 +235,8 @@ impl Default for AttributeController {
             color_pel_write_address_color: 0,
             color_pel_read_address: 0,
             color_pel_read_address_color: 0,
-            color_pel_mask: 0,
-            color_dac_state: 0,
+            color_pel_mask: 0xFF,
+            color_dac_state: DAC_STATE_WRITE,
         }
     }
 }
@@ -249,6 +250,80 @@ impl AttributeController {
         self.register_flipflop = AttributeRegisterFlipFlop::Address;
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let mut regs = [0u8; 20];
+        for (reg, entry) in regs.iter_mut().zip(self.palette_registers.iter()) {
+            *reg = entry.six;
+        }
+        regs[0x10] = self.mode_control.into_bytes()[0];
+        regs[0x11] = self.overscan_color.six;
+        regs[0x12] = self.color_plane_enable.into_bytes()[0];
+        regs[0x13] = self.pel_panning;
+        w.write_bytes(&regs);
+        w.write_u8(self.register_selected as u8);
+        w.write_usize(self.palette_index);
+        w.write_bool(matches!(self.register_flipflop, AttributeRegisterFlipFlop::Data));
+        w.write_bool(self.blink_state);
+
+        let dac = self.color_registers.iter().flatten().copied().collect::<Vec<u8>>();
+        w.write_bytes(&dac);
+        w.write_u8(self.color_pel_write_address);
+        w.write_u8(self.color_pel_write_address_color);
+        w.write_u8(self.color_pel_read_address);
+        w.write_u8(self.color_pel_read_address_color);
+        w.write_u8(self.color_pel_mask);
+        w.write_u8(self.color_dac_state);
+    }
+
+    /// Restore the attribute registers by writing them back through the attribute register, then
+    /// restore the flipflop. The DAC palette is written back through the PEL data register.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0u8; 20];
+        r.read_bytes_into(&mut regs, "VGA attribute registers")?;
+        let selected = r.read_u8()?;
+        let palette_index = r.read_usize()?;
+        let flipflop_data = r.read_bool()?;
+        self.blink_state = r.read_bool()?;
+        let mut dac = [0u8; 256 * 3];
+        r.read_bytes_into(&mut dac, "VGA DAC registers")?;
+        let pel_write_address = r.read_u8()?;
+        let pel_write_address_color = r.read_u8()?;
+        let pel_read_address = r.read_u8()?;
+        let pel_read_address_color = r.read_u8()?;
+        let pel_mask = r.read_u8()?;
+        let dac_state = r.read_u8()?;
+        if pel_write_address_color > 2 || pel_read_address_color > 2 {
+            return Err(SaveStateError::InvalidValue("VGA DAC color index"));
+        }
+        if selected as usize >= regs.len() || palette_index >= self.palette_registers.len() {
+            return Err(SaveStateError::InvalidValue("VGA attribute register select"));
+        }
+
+        for (idx, byte) in regs.iter().enumerate() {
+            self.reset_flipflop();
+            self.write_attribute_register(idx as u8);
+            self.write_attribute_register(*byte);
+        }
+        self.reset_flipflop();
+        self.write_attribute_register(selected);
+        self.palette_index = palette_index;
+        if !flipflop_data {
+            self.reset_flipflop();
+        }
+
+        self.write_pel_address_write_mode(0);
+        for byte in dac.iter() {
+            self.write_pel_data(*byte);
+        }
+        self.color_pel_write_address = pel_write_address;
+        self.color_pel_write_address_color = pel_write_address_color;
+        self.color_pel_read_address = pel_read_address;
+        self.color_pel_read_address_color = pel_read_address_color;
+        self.color_pel_mask = pel_mask;
+        self.color_dac_state = dac_state;
+        Ok(())
+    }
+
     /// On the VGA, the Attribute Controller registers are readable.
     /// Titles like QBasic will rely on this to properly set their text modes, or else they
     /// will inadvertently set a graphics mode.
@@ -509,7 +584,8 @@ impl AttributeController {
         let mut out_data0 = 0;
         let mut out_data1 = 0;
 
-        let out_data = ((self.shift_reg << (std::cmp::min(self.pel_panning, 0x07) * 8)) >> 64) as u64;
+        // In 256 color modes, each pel spans two dots, so the panning value is in units of half pels.
+        let out_data = ((self.shift_reg << ((std::cmp::min(self.pel_panning, 0x07) >> 1) * 8)) >> 64) as u64;
 
         out_data0 |= (out_data & 0xFF00000000000000) >> 56; // -> 0x00000000000000FF
         out_data0 |= (out_data & 0xFF00000000000000) >> 48; // -> 0x000000000000FF00
@@ -583,6 +659,14 @@ impl AttributeController {
         glyph_row_base & EGA_COLORS_U64[fg_color] | !glyph_row_base & EGA_COLORS_U64[bg_color]
     }
 
+    /// Return whether attribute bit 7 selects blinking rather than background intensity.
+    pub fn blink_enabled(&self) -> bool {
+        matches!(
+            self.mode_control.enable_blink_or_intensity(),
+            AttributeBlinkOrIntensity::Blink
+        )
+    }
+
     pub fn palette(&self, pel: u8) -> u8 {
         self.palette_registers[(pel & 0x0F) as usize].six
     }
@@ -595,12 +679,20 @@ impl AttributeController {
         self.color_pel_write_address
     }
 
+    /// Writing the PEL write address resets the DAC's RGB sequence to red and puts the DAC into
+    /// write mode.
     pub fn write_pel_address_write_mode(&mut self, data: u8) {
         self.color_pel_write_address = data;
+        self.color_pel_write_address_color = 0;
+        self.color_dac_state = DAC_STATE_WRITE;
     }
 
+    /// Writing the PEL read address resets the DAC's RGB sequence to red and puts the DAC into
+    /// read mode.
     pub fn write_pel_address_read_mode(&mut self, data: u8) {
         self.color_pel_read_address = data;
+        self.color_pel_read_address_color = 0;
+        self.color_dac_state = DAC_STATE_READ;
     }
 
     pub fn read_color_dac_state(&self) -> u8 {
@@ -638,7 +730,6 @@ impl AttributeController {
             self.color_pel_read_address = self.color_pel_read_address.wrapping_add(1);
         }
 
-        self.color_dac_state = DAC_STATE_READ;
         byte
     }
 
@@ -646,7 +737,8 @@ impl AttributeController {
         let color = self.color_pel_write_address as usize;
         let rgb_idx = self.color_pel_write_address_color as usize;
 
-        self.color_registers[color][rgb_idx] = byte;
+        // The DAC color registers are 6 bits wide.
+        self.color_registers[color][rgb_idx] = byte & 0x3F;
 
         // Automatically increment to next color register, cycling through
         // Red, Green and Blue registers per Read Index
@@ -678,8 +770,14 @@ impl AttributeController {
             // Done with all colors, so go to next palette entry
             self.color_pel_write_address = self.color_pel_write_address.wrapping_add(1);
         }
+    }
 
-        self.color_dac_state = DAC_STATE_WRITE;
+    /// Return the DAC palette as RGBA entries, as seen through the PEL mask. Each color index
+    /// is ANDed with the PEL mask before the color register lookup.
+    pub fn dac_palette(&self) -> Vec<[u8; 4]> {
+        (0..256)
+            .map(|i| self.color_registers_rgba[i & self.color_pel_mask as usize])
+            .collect()
     }
 
     #[rustfmt::skip]
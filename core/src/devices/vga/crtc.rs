@@ -31,6 +31,7 @@
 */
 
 use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 pub const EGA_VBLANK_MASK: u16 = 0x001F;
 pub const EGA_VSYNC_MASK: u16 = 0x001F;
@@ -51,6 +52,8 @@ const DEFAULT_OVERFLOW: u8 = 0;
 const DEFAULT_PRESET_ROW_SCAN: u8 = 0;
 const DEFAULT_MAX_SCANLINE: u8 = 13;
 
+const CRTC_REGISTER_CT: usize = 0x19;
+
 const VSYNC_LENGTH: u8 = 16;
 const CURSOR_LINE_MASK: u8 = 0b0001_1111;
 const AC_LATENCY: u8 = 1;
@@ -231,6 +234,9 @@ pub struct VgaCrtc {
     register_select_byte: u8,
     register_selected:    CRTCRegister,
 
+    // Register values as last written, for save states.
+    regs: [u8; CRTC_REGISTER_CT],
+
     crtc_horizontal_total: u8,                          // R(0) Horizontal Total
     crtc_horizontal_display_end: u8,                    // R(1) Horizontal Display End
     crtc_start_horizontal_blank: u8,                    // R(2) Start Horizontal Blank
@@ -285,6 +291,7 @@ pub struct VgaCrtc {
     den_skew_front: bool, // Display enable skew control for front porch
     den_skew_back: bool,  // Display enable skew control for back porch
     dsc: u8,              // Display enable skew counter
+    scan_doubled: bool,   // Set on the second scanline of a double-scanned row
 
     pub status: CrtcStatus,
     blink_state: bool,
@@ -302,6 +309,8 @@ impl Default for VgaCrtc {
             register_selected:    CRTCRegister::HorizontalTotal,
             register_select_byte: 0,
 
+            regs: [0; CRTC_REGISTER_CT],
+
             crtc_horizontal_total: DEFAULT_HORIZONTAL_TOTAL,
             crtc_horizontal_display_end: DEFAULT_HORIZONTAL_DISPLAYED,
             crtc_start_horizontal_blank: DEFAULT_HORIZONTAL_SYNC_POS,
@@ -355,6 +364,7 @@ impl Default for VgaCrtc {
             den_skew_front: false,
             den_skew_back: false,
             dsc: 0,
+            scan_doubled: false,
 
             status: CrtcStatus::default(),
             blink_state: false,
@@ -372,6 +382,31 @@ impl VgaCrtc {
         Self::default()
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.regs);
+        w.write_u8(self.register_select_byte);
+    }
+
+    /// Restore the CRTC registers by writing them back through the data register. Register
+    /// protection is lifted while R0-R7 are replayed and restored with R11. Returns true if the
+    /// card should recalculate its mode. The CRTC's counters are not restored.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<bool, SaveStateError> {
+        let mut regs = [0u8; CRTC_REGISTER_CT];
+        r.read_bytes_into(&mut regs, "VGA CRTC registers")?;
+        let address = r.read_u8()?;
+
+        self.write_crtc_register_address(0x11);
+        self.write_crtc_register_data(regs[0x11] & 0x7F);
+
+        let mut recalc = false;
+        for (idx, byte) in regs.iter().enumerate() {
+            self.write_crtc_register_address(idx as u8);
+            recalc |= self.write_crtc_register_data(*byte).0;
+        }
+        self.write_crtc_register_address(address);
+        Ok(recalc)
+    }
+
     pub fn read_crtc_register_address(&self) -> u8 {
         self.register_select_byte
     }
@@ -536,6 +571,9 @@ impl VgaCrtc {
     pub fn write_crtc_register_data(&mut self, byte: u8) -> (bool, bool) {
         //log::trace!("VGA: Write to CRTC register: {:?}: {:02}", self.crtc_register_selected, byte );
         let mut clear_intr = false;
+        if self.register_select_byte > 0x07 || self.crtc_vertical_retrace_end.protect_regs() == 0 {
+            self.regs[self.register_select_byte as usize] = byte;
+        }
         match self.register_selected {
             CRTCRegister::HorizontalTotal => {
                 // (R0) 8 bit write only
@@ -612,7 +650,11 @@ impl VgaCrtc {
             }
             CRTCRegister::MaximumScanLine => {
                 // (R9)
+                // Bit 5: Start Vertical Blank Bit 9
+                // Bit 6: Line Compare Bit 9
+                // Bit 7: Double scan (2T4)
                 self.crtc_maximum_scanline = CMaximumScanline::from_bytes([byte]);
+                self.set_crtc_overflow_bits();
             }
             CRTCRegister::CursorStartLine => {
                 // R(A)
@@ -732,6 +774,7 @@ impl VgaCrtc {
         // Bit 1: Vertical Display Enable End
         self.crtc_vertical_display_end &= 0x00FF;
         self.crtc_vertical_display_end |= (self.crtc_overflow.vertical_display_enable_end_bit_8() as u16) << 8;
+        self.crtc_vertical_display_end |= (self.crtc_overflow.vertical_display_enable_bit_9() as u16) << 9;
         // Bit 2: Vertical Retrace Start
         self.crtc_vertical_retrace_start &= 0x00FF;
         self.crtc_vertical_retrace_start |= (self.crtc_overflow.vertical_retrace_start_bit_8() as u16) << 8;
@@ -739,9 +782,11 @@ impl VgaCrtc {
         // Bit 3: Start Vertical Blank
         self.crtc_start_vertical_blank &= 0x00FF;
         self.crtc_start_vertical_blank |= (self.crtc_overflow.start_vertical_blank_bit_8() as u16) << 8;
+        self.crtc_start_vertical_blank |= (self.crtc_maximum_scanline.vbs() as u16) << 9;
         // Bit 4: Line Compare
         self.crtc_line_compare &= 0x00FF;
         self.crtc_line_compare |= (self.crtc_overflow.line_compare_bit_8() as u16) << 8;
+        self.crtc_line_compare |= (self.crtc_maximum_scanline.lc() as u16) << 9;
 
         // In IBM's documentation, bit 5 is specified to be "Bit 8" of register 0x0A,
         // but they call it the "Cursor Location" register in the same paragraph, which is
@@ -1013,7 +1058,18 @@ impl VgaCrtc {
             // Reset Horizontal Character Counter and increment character row counter
             self.hcc = 0;
             //self.status.hborder = false;
-            self.vlc += 1;
+
+            // When double scanning is enabled, each row scan is output twice before the row
+            // scan counter advances. This is how the 200-line modes are displayed at 400 lines.
+            if self.crtc_maximum_scanline.two_t4() != 0 {
+                self.scan_doubled = !self.scan_doubled;
+                if !self.scan_doubled {
+                    self.vlc += 1;
+                }
+            }
+            else {
+                self.vlc += 1;
+            }
             // Return video memory address to starting position for next character row
             self.vma = self.vma_sl;
 
@@ -1049,6 +1105,7 @@ impl VgaCrtc {
                 self.vma_sl = 0;
                 self.vma = 0;
                 self.vlc = 0;
+                self.scan_doubled = false;
             }
 
             if self.slc == self.crtc_vertical_retrace_start {
@@ -1084,6 +1141,7 @@ impl VgaCrtc {
                 self.hcc = 0;
                 self.vcc = 0;
                 self.vlc = self.crtc_preset_row_scan;
+                self.scan_doubled = false;
 
                 self.frame += 1;
                 // Toggle blink state. This is toggled every 8 frames by default.
@@ -1261,6 +1319,23 @@ impl VgaCrtc {
         (self.crtc_cursor_start, self.crtc_cursor_end.cursor_end())
     }
 
+    pub fn horizontal_total(&self) -> u8 {
+        self.crtc_horizontal_total
+    }
+
+    pub fn vertical_total(&self) -> u16 {
+        self.crtc_vertical_total
+    }
+
+    pub fn line_compare(&self) -> u16 {
+        self.crtc_line_compare
+    }
+
+    #[inline]
+    pub fn double_scan(&self) -> bool {
+        self.crtc_maximum_scanline.two_t4() != 0
+    }
+
     pub fn horizontal_display_end(&self) -> u8 {
         self.crtc_horizontal_display_end
     }
@@ -32,6 +32,7 @@
 */
 
 use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 #[derive(Copy, Clone, Debug)]
 pub enum GraphicsRegister {
@@ -205,6 +206,36 @@ impl GraphicsController {
         *self = GraphicsController::default();
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&[
+            self.graphics_set_reset,
+            self.graphics_enable_set_reset,
+            self.graphics_color_compare,
+            self.graphics_data_rotate.into_bytes()[0],
+            self.graphics_read_map_select,
+            self.graphics_mode.into_bytes()[0],
+            self.graphics_micellaneous.into_bytes()[0],
+            self.graphics_color_dont_care,
+            self.graphics_bitmask,
+        ]);
+        w.write_u8(self.graphics_register_select_byte);
+        w.write_bytes(&self.latches);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0u8; 9];
+        r.read_bytes_into(&mut regs, "VGA graphics controller registers")?;
+        let address = r.read_u8()?;
+        r.read_bytes_into(&mut self.latches, "VGA graphics controller latches")?;
+
+        for (idx, byte) in regs.iter().enumerate() {
+            self.write_address(idx as u8);
+            self.write_data(*byte);
+        }
+        self.write_address(address);
+        Ok(())
+    }
+
     #[inline]
     pub fn chain(&self) -> bool {
         self.graphics_micellaneous.chain_odd_even()
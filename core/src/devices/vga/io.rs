@@ -34,6 +34,9 @@ use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
 
 impl IoDevice for VGACard {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        if self.mcga.is_some() {
+            return self.mcga_read_u8(port);
+        }
         match port {
             INPUT_STATUS_REGISTER_0 => self.read_input_status_register_0(),
             INPUT_STATUS_REGISTER_1 => {
@@ -84,6 +87,10 @@ impl IoDevice for VGACard {
     }
 
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        if self.mcga.is_some() {
+            self.mcga_write_u8(port, data);
+            return;
+        }
         match port {
             MISC_OUTPUT_REGISTER => {
                 self.write_external_misc_output_register(data);
@@ -104,7 +111,10 @@ impl IoDevice for VGACard {
                 }
             }
             VGA_GRAPHICS_ADDRESS => self.gc.write_address(data),
-            VGA_GRAPHICS_DATA => self.gc.write_data(data),
+            VGA_GRAPHICS_DATA => {
+                self.gc.write_data(data);
+                self.recalculate_mode();
+            }
             VGA_SEQUENCER_ADDRESS_REGISTER => self.sequencer.write_address(data),
             VGA_SEQUENCER_DATA_REGISTER => {
                 self.sequencer.write_data(data);
@@ -126,7 +136,10 @@ impl IoDevice for VGACard {
     }
 
     fn port_list(&self) -> Vec<(String, u16)> {
-        if self.dip_sw.get_physical_state() == EGA_DIP_SWITCH_MDA {
+        if self.mcga.is_some() {
+            self.mcga_port_list()
+        }
+        else if self.dip_sw.get_physical_state() == EGA_DIP_SWITCH_MDA {
             vec![
                 (String::from("EGA Attribute Register"), VGA_ATTRIBUTE_REGISTER),
                 (String::from("EGA Attribute Register"), VGA_ATTRIBUTE_REGISTER_ALT),
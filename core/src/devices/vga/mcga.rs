@@ -0,0 +1,468 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::vga::mcga.rs
+
+    Implementation of the IBM MCGA (Multi-Color Graphics Array) as a
+    personality of the VGA device.
+
+    The MCGA is not register compatible with the EGA. It presents a CGA-style
+    mode control and color select register, a 6845-style CRTC extended with a
+    mode control register at index 10h, and a VGA-style DAC. There is no
+    Sequencer, Graphics Controller or Attribute Controller visible to software.
+
+    We emulate the MCGA by hiding the VGA register file and programming it
+    internally with a canned set of values for each of the MCGA's modes, which
+    are selected by the MCGA's own registers. The start address and cursor
+    registers are shared between the 6845 and the VGA CRTC and are passed
+    through directly.
+
+    The MCGA's character generator is loaded by the PS/2 BIOS in a way we do
+    not currently emulate. Instead, a built-in font is loaded into the glyph
+    plane whenever a text mode is selected.
+
+*/
+
+use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub const MCGA_MODE_CONTROL_REGISTER: u16 = 0x3D8;
+pub const MCGA_COLOR_SELECT_REGISTER: u16 = 0x3D9;
+
+const MCGA_FONT: &[u8] = include_bytes!("../../../../assets/ega_8by14.bin");
+const MCGA_FONT_HEIGHT: usize = 14;
+
+// CGA-compatible mode control register bits.
+const MODE_HIRES_TEXT: u8 = 0b0000_0001;
+const MODE_GRAPHICS: u8 = 0b0000_0010;
+const MODE_HIRES_GRAPHICS: u8 = 0b0001_0000;
+const MODE_BLINKING: u8 = 0b0010_0000;
+
+// Color select register bits.
+const CS_BRIGHT_BIT: u8 = 0b0001_0000;
+const CS_PALETTE_BIT: u8 = 0b0010_0000;
+
+// MCGA-specific CRTC registers.
+const MCGA_CRTC_MODE_CONTROL: usize = 0x10;
+const MCGA_CRTC_REGISTER_COUNT: usize = 0x15;
+
+// Bits of the CRTC Mode Control register.
+const MCGA_MODE_256_COLOR: u8 = 0b0000_0001;
+const MCGA_MODE_640X480: u8 = 0b0000_0010;
+
+// Default DAC values for the 16 CGA colors, in 6-bit RGB.
+const MCGA_DEFAULT_DAC: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x2A],
+    [0x00, 0x2A, 0x00],
+    [0x00, 0x2A, 0x2A],
+    [0x2A, 0x00, 0x00],
+    [0x2A, 0x00, 0x2A],
+    [0x2A, 0x15, 0x00],
+    [0x2A, 0x2A, 0x2A],
+    [0x15, 0x15, 0x15],
+    [0x15, 0x15, 0x3F],
+    [0x15, 0x3F, 0x15],
+    [0x15, 0x3F, 0x3F],
+    [0x3F, 0x15, 0x15],
+    [0x3F, 0x15, 0x3F],
+    [0x3F, 0x3F, 0x15],
+    [0x3F, 0x3F, 0x3F],
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum McgaMode {
+    Text40,
+    Text80,
+    LowResGraphics,
+    HiResGraphics,
+    Graphics640x480,
+    Graphics256,
+}
+
+/// Internal VGA register values used to implement an MCGA mode. Attribute Controller
+/// palette registers are derived from the color select register and are not included.
+struct McgaModeParams {
+    misc: u8,
+    seq:  [u8; 5],
+    crtc: [u8; 25],
+    ac:   [u8; 4],
+    gc:   [u8; 9],
+}
+
+#[rustfmt::skip]
+const MCGA_MODE_PARAMS: [McgaModeParams; 6] = [
+    // Text40: 40x25 text, 320x400
+    McgaModeParams {
+        misc: 0x63,
+        seq:  [0x03, 0x09, 0x03, 0x00, 0x02],
+        crtc: [0x2D, 0x27, 0x28, 0x90, 0x2B, 0xA0, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00,
+               0x9C, 0x8E, 0x8F, 0x14, 0x1F, 0x96, 0xB9, 0xA3, 0xFF],
+        ac:   [0x0C, 0x00, 0x0F, 0x00],
+        gc:   [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    },
+    // Text80: 80x25 text, 640x400
+    McgaModeParams {
+        misc: 0x63,
+        seq:  [0x03, 0x01, 0x03, 0x00, 0x02],
+        crtc: [0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00,
+               0x9C, 0x8E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF],
+        ac:   [0x0C, 0x00, 0x0F, 0x00],
+        gc:   [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    },
+    // LowResGraphics: 320x200 in 4 colors, double scanned
+    McgaModeParams {
+        misc: 0x63,
+        seq:  [0x03, 0x09, 0x03, 0x00, 0x02],
+        crtc: [0x2D, 0x27, 0x28, 0x90, 0x2B, 0x80, 0xBF, 0x1F, 0x00, 0xC1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+               0x9C, 0x8E, 0x8F, 0x14, 0x00, 0x96, 0xB9, 0xA2, 0xFF],
+        ac:   [0x01, 0x00, 0x03, 0x00],
+        gc:   [0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x0F, 0x00, 0xFF],
+    },
+    // HiResGraphics: 640x200 in 2 colors, double scanned
+    McgaModeParams {
+        misc: 0x63,
+        seq:  [0x03, 0x01, 0x01, 0x00, 0x06],
+        crtc: [0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0xC1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+               0x9C, 0x8E, 0x8F, 0x28, 0x00, 0x96, 0xB9, 0xC2, 0xFF],
+        ac:   [0x01, 0x00, 0x01, 0x00],
+        gc:   [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0D, 0x00, 0xFF],
+    },
+    // Graphics640x480: 640x480 in 2 colors
+    McgaModeParams {
+        misc: 0xE3,
+        seq:  [0x03, 0x01, 0x0F, 0x00, 0x06],
+        crtc: [0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0x0B, 0x3E, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+               0xEA, 0x8C, 0xDF, 0x28, 0x00, 0xE7, 0x04, 0xE3, 0xFF],
+        ac:   [0x01, 0x00, 0x01, 0x00],
+        gc:   [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x0F, 0xFF],
+    },
+    // Graphics256: 320x200 in 256 colors, double scanned
+    McgaModeParams {
+        misc: 0x63,
+        seq:  [0x03, 0x01, 0x0F, 0x00, 0x0E],
+        crtc: [0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+               0x9C, 0x8E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF],
+        ac:   [0x41, 0x00, 0x0F, 0x00],
+        gc:   [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    },
+];
+
+/// The software-visible register state of the MCGA.
+pub struct Mcga {
+    mode_byte: u8,
+    color_select: u8,
+    crtc_address: usize,
+    crtc_regs: [u8; MCGA_CRTC_REGISTER_COUNT],
+    mode: Option<McgaMode>,
+}
+
+impl Mcga {
+    pub fn new() -> Self {
+        Self {
+            mode_byte: MODE_HIRES_TEXT | MODE_BLINKING,
+            color_select: 0,
+            crtc_address: 0,
+            crtc_regs: [0; MCGA_CRTC_REGISTER_COUNT],
+            mode: None,
+        }
+    }
+
+    pub fn mode_byte(&self) -> u8 {
+        self.mode_byte
+    }
+
+    pub fn color_select(&self) -> u8 {
+        self.color_select
+    }
+
+    pub fn mode(&self) -> Option<McgaMode> {
+        self.mode
+    }
+
+    pub fn crtc_mode_control(&self) -> u8 {
+        self.crtc_regs[MCGA_CRTC_MODE_CONTROL]
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.mode_byte);
+        w.write_u8(self.color_select);
+        w.write_u8(self.crtc_address as u8);
+        w.write_bytes(&self.crtc_regs);
+    }
+
+    /// Restore the MCGA's registers. The internal VGA registers are restored separately, so the
+    /// selected mode is only recalculated here rather than reprogrammed.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mode_byte = r.read_u8()?;
+        self.color_select = r.read_u8()?;
+        self.crtc_address = (r.read_u8()? & 0x1F) as usize;
+        r.read_bytes_into(&mut self.crtc_regs, "MCGA CRTC registers")?;
+        self.mode = Some(self.select_mode());
+        Ok(())
+    }
+
+    /// Resolve the mode selected by the mode control registers. The CRTC Mode Control
+    /// register's extended modes take priority over the CGA-compatible mode control register.
+    fn select_mode(&self) -> McgaMode {
+        let mcga_mode = self.crtc_regs[MCGA_CRTC_MODE_CONTROL];
+        if mcga_mode & MCGA_MODE_256_COLOR != 0 {
+            McgaMode::Graphics256
+        }
+        else if mcga_mode & MCGA_MODE_640X480 != 0 {
+            McgaMode::Graphics640x480
+        }
+        else if self.mode_byte & MODE_GRAPHICS != 0 {
+            match self.mode_byte & MODE_HIRES_GRAPHICS != 0 {
+                true => McgaMode::HiResGraphics,
+                false => McgaMode::LowResGraphics,
+            }
+        }
+        else {
+            match self.mode_byte & MODE_HIRES_TEXT != 0 {
+                true => McgaMode::Text80,
+                false => McgaMode::Text40,
+            }
+        }
+    }
+
+    /// Return the Attribute Controller palette that maps pixel values onto the first 16
+    /// DAC entries for the specified mode, and the overscan color.
+    fn palette(&self, mode: McgaMode) -> ([u8; 16], u8) {
+        let mut palette = [0; 16];
+        let color = self.color_select & 0x0F;
+        match mode {
+            McgaMode::Text40 | McgaMode::Text80 | McgaMode::Graphics256 => {
+                for (i, entry) in palette.iter_mut().enumerate() {
+                    *entry = i as u8;
+                }
+            }
+            McgaMode::LowResGraphics => {
+                let bright = if self.color_select & CS_BRIGHT_BIT != 0 {
+                    0x08
+                }
+                else {
+                    0
+                };
+                let colors = match self.color_select & CS_PALETTE_BIT != 0 {
+                    true => [3, 5, 7],
+                    false => [2, 4, 6],
+                };
+                palette[0] = color;
+                for (i, c) in colors.iter().enumerate() {
+                    palette[i + 1] = c | bright;
+                }
+            }
+            McgaMode::HiResGraphics | McgaMode::Graphics640x480 => {
+                palette[1] = color;
+            }
+        }
+
+        let overscan = match mode {
+            McgaMode::Text40 | McgaMode::Text80 | McgaMode::LowResGraphics => color,
+            _ => 0,
+        };
+        (palette, overscan)
+    }
+}
+
+impl VGACard {
+    /// Initialize the VGA's internal state for the MCGA's power-on mode.
+    pub(crate) fn mcga_init(&mut self) {
+        for (i, rgb) in MCGA_DEFAULT_DAC.iter().enumerate() {
+            self.ac.write_pel_address_write_mode(i as u8);
+            for c in rgb {
+                self.ac.write_pel_data(*c);
+            }
+        }
+        self.mcga_update_mode();
+    }
+
+    pub(crate) fn mcga_read_u8(&mut self, port: u16) -> u8 {
+        match port {
+            VGA_CRTC_REGISTER_ADDRESS => self.mcga.as_ref().map_or(0xFF, |m| m.crtc_address as u8),
+            VGA_CRTC_REGISTER => match &self.mcga {
+                Some(mcga) if mcga.crtc_address < MCGA_CRTC_REGISTER_COUNT => mcga.crtc_regs[mcga.crtc_address],
+                _ => 0xFF,
+            },
+            INPUT_STATUS_REGISTER_1 => self.read_input_status_register_1(),
+            PEL_ADDRESS_WRITE_MODE => self.ac.read_pel_address_write_mode(),
+            PEL_DATA => self.ac.read_pel_data(),
+            DAC_STATE_REGISTER => self.ac.read_color_dac_state(),
+            PEL_MASK => self.ac.read_pel_mask(),
+            _ => 0xFF,
+        }
+    }
+
+    pub(crate) fn mcga_write_u8(&mut self, port: u16, data: u8) {
+        match port {
+            VGA_CRTC_REGISTER_ADDRESS => {
+                if let Some(mcga) = &mut self.mcga {
+                    mcga.crtc_address = (data & 0x1F) as usize;
+                }
+            }
+            VGA_CRTC_REGISTER => self.mcga_write_crtc(data),
+            MCGA_MODE_CONTROL_REGISTER => {
+                if let Some(mcga) = &mut self.mcga {
+                    mcga.mode_byte = data;
+                }
+                self.mcga_update_mode();
+            }
+            MCGA_COLOR_SELECT_REGISTER => {
+                if let Some(mcga) = &mut self.mcga {
+                    mcga.color_select = data;
+                }
+                self.mcga_update_palette();
+            }
+            PEL_ADDRESS_WRITE_MODE => self.ac.write_pel_address_write_mode(data),
+            PEL_ADDRESS_READ_MODE => self.ac.write_pel_address_read_mode(data),
+            PEL_DATA => self.ac.write_pel_data(data),
+            PEL_MASK => self.ac.write_pel_mask(data),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn mcga_port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("MCGA CRTC Address"), VGA_CRTC_REGISTER_ADDRESS),
+            (String::from("MCGA CRTC Data"), VGA_CRTC_REGISTER),
+            (String::from("MCGA Mode Control Register"), MCGA_MODE_CONTROL_REGISTER),
+            (String::from("MCGA Color Select Register"), MCGA_COLOR_SELECT_REGISTER),
+            (String::from("MCGA Status Register"), INPUT_STATUS_REGISTER_1),
+            (String::from("MCGA Pel Address Read"), PEL_ADDRESS_READ_MODE),
+            (String::from("MCGA Pel Address Write"), PEL_ADDRESS_WRITE_MODE),
+            (String::from("MCGA Pel Data"), PEL_DATA),
+            (String::from("MCGA Pel Mask"), PEL_MASK),
+        ]
+    }
+
+    fn mcga_write_crtc(&mut self, data: u8) {
+        let address = match &mut self.mcga {
+            Some(mcga) if mcga.crtc_address < MCGA_CRTC_REGISTER_COUNT => {
+                mcga.crtc_regs[mcga.crtc_address] = data;
+                mcga.crtc_address
+            }
+            _ => return,
+        };
+
+        match address {
+            0x0A..=0x0F => {
+                // Cursor and start address registers are shared with the VGA CRTC.
+                self.crtc.write_crtc_register_address(address as u8);
+                let (_, clear_intr) = self.crtc.write_crtc_register_data(data);
+                if clear_intr {
+                    self.intr = false;
+                }
+            }
+            MCGA_CRTC_MODE_CONTROL => self.mcga_update_mode(),
+            _ => {
+                // Timing registers are latched, but the VGA is driven by the canned mode parameters.
+            }
+        }
+    }
+
+    /// Reprogram the internal VGA registers if the MCGA's selected mode has changed.
+    fn mcga_update_mode(&mut self) {
+        let mcga = match &mut self.mcga {
+            Some(mcga) => mcga,
+            None => return,
+        };
+        let mode = mcga.select_mode();
+        let mode_changed = mcga.mode != Some(mode);
+        mcga.mode = Some(mode);
+        let blink = mcga.mode_byte & MODE_BLINKING != 0;
+
+        if mode_changed {
+            log::debug!("MCGA mode selected: {:?}", mode);
+            let params = &MCGA_MODE_PARAMS[mode as usize];
+
+            self.write_external_misc_output_register(params.misc);
+            for (i, byte) in params.seq.iter().enumerate() {
+                self.sequencer.write_address(i as u8);
+                self.sequencer.write_data(*byte);
+            }
+            // Clear the protect bit so the horizontal timing registers can be written.
+            self.crtc.write_crtc_register_address(0x11);
+            self.crtc.write_crtc_register_data(0x00);
+            for (i, byte) in params.crtc.iter().enumerate() {
+                if (0x0A..=0x0F).contains(&i) {
+                    // Cursor and start address are owned by the MCGA's CRTC.
+                    continue;
+                }
+                self.crtc.write_crtc_register_address(i as u8);
+                self.crtc.write_crtc_register_data(*byte);
+            }
+            for (i, byte) in params.gc.iter().enumerate() {
+                self.gc.write_address(i as u8);
+                self.gc.write_data(*byte);
+            }
+
+            if matches!(mode, McgaMode::Text40 | McgaMode::Text80) {
+                self.mcga_load_font();
+            }
+        }
+
+        // Attribute mode control: set or clear the blink enable bit in text modes.
+        let mut ac_mode = MCGA_MODE_PARAMS[mode as usize].ac[0];
+        if matches!(mode, McgaMode::Text40 | McgaMode::Text80) && !blink {
+            ac_mode &= !0x08;
+        }
+        self.ac.reset_flipflop();
+        self.ac.write_attribute_register(0x10);
+        self.ac.write_attribute_register(ac_mode);
+        for (i, byte) in MCGA_MODE_PARAMS[mode as usize].ac.iter().enumerate().skip(2) {
+            self.ac.write_attribute_register(0x10 + i as u8);
+            self.ac.write_attribute_register(*byte);
+        }
+        self.mcga_update_palette();
+        self.recalculate_mode();
+    }
+
+    /// Update the Attribute Controller palette from the color select register.
+    fn mcga_update_palette(&mut self) {
+        let (palette, overscan) = match self.mcga.as_ref().and_then(|m| m.mode.map(|mode| m.palette(mode))) {
+            Some(palette) => palette,
+            None => return,
+        };
+        self.ac.reset_flipflop();
+        for (i, entry) in palette.iter().enumerate() {
+            self.ac.write_attribute_register(i as u8);
+            self.ac.write_attribute_register(*entry);
+        }
+        self.ac.write_attribute_register(0x11);
+        self.ac.write_attribute_register(overscan);
+    }
+
+    /// Load the built-in font into the glyph plane.
+    fn mcga_load_font(&mut self) {
+        for glyph in 0..256 {
+            for row in 0..MCGA_FONT_HEIGHT {
+                let address = self.sequencer.get_glyph_address(glyph as u8, 0, row as u8);
+                self.sequencer.vram.write_u8(2, address, MCGA_FONT[row * 256 + glyph]);
+            }
+        }
+    }
+}
@@ -51,14 +51,17 @@ mod crtc;
 mod draw;
 mod graphics_controller;
 mod io;
+mod mcga;
 mod mmio;
 mod planes;
+mod savestate;
 mod sequencer;
 mod tablegen;
 mod videocard;
 mod vram;
 
 use attribute_controller::*;
+use mcga::*;
 
 use crate::devices::vga::crtc::{VgaCrtc, WordOrByteMode};
 
@@ -630,6 +633,8 @@ pub struct VGACard {
     feature_bits: u8,
 
     gc_debug: [u8; 8],
+
+    mcga: Option<Mcga>,
 }
 
 #[bitfield]
@@ -765,6 +770,8 @@ impl Default for VGACard {
             feature_bits: 0,
 
             gc_debug: [0; 8],
+
+            mcga: None,
         }
     }
 }
@@ -790,7 +797,13 @@ impl Default for DisplayExtents {
 }*/
 
 impl VGACard {
-    pub fn new(trace_logger: TraceLogger, clock_mode: ClockingMode, video_frame_debug: bool, dip: Option<u8>) -> Self {
+    pub fn new(
+        subtype: VideoCardSubType,
+        trace_logger: TraceLogger,
+        clock_mode: ClockingMode,
+        video_frame_debug: bool,
+        dip: Option<u8>,
+    ) -> Self {
         let mut ega = Self::default();
 
         // If a dip was provided, set the dip switches, otherwise leave them default.
@@ -809,6 +822,11 @@ impl VGACard {
         else {
             ega.clock_mode = clock_mode;
         }
+
+        if let VideoCardSubType::Mcga = subtype {
+            ega.mcga = Some(Mcga::new());
+            ega.mcga_init();
+        }
         ega
     }
 
@@ -820,6 +838,7 @@ impl VGACard {
             row_stride: VGA25_MAX_RASTER_X as usize,
             double_scan: false,
             mode_byte: 0,
+            standard: VideoStandard::Ntsc,
         }
     }
 
@@ -834,8 +853,13 @@ impl VGACard {
             clock_mode: self.clock_mode,
             frame: self.frame,
             trace_logger,
+            mcga: self.mcga.as_ref().map(|_| Mcga::new()),
             ..Self::default()
         };
+
+        if self.mcga.is_some() {
+            self.mcga_init();
+        }
     }
 
     fn get_cursor_span(&self) -> (u8, u8) {
@@ -923,7 +947,9 @@ impl VGACard {
         if !self.crtc.status.den {
             byte |= 0x01;
         }
-        if self.crtc.status.vblank {
+        // Unlike the EGA, the VGA reports the vertical retrace period itself in bit 3, which
+        // software commonly polls to synchronize with the display.
+        if self.crtc.status.vsync {
             byte |= 0x08;
         }
 
@@ -977,17 +1003,23 @@ impl VGACard {
                         OddEvenModeComplement::Sequential => DisplayMode::Mode6HiResGraphics,
                     },
                     MemoryMap::A0000_64K | MemoryMap::A0000_128k => {
-                        if self.sequencer.memory_mode.chain_four() == 1
-                            && matches!(self.ac.pixel_clock(), PixelClockSelect::EveryOtherCycle)
-                        {
-                            DisplayMode::Mode13VGALowRes256
+                        if matches!(self.ac.pixel_clock(), PixelClockSelect::EveryOtherCycle) {
+                            // 256 color modes. With chain-4 disabled, this is the unchained
+                            // 'Mode X' layout, with consecutive pixels in consecutive planes.
+                            match self.sequencer.memory_mode.chain_four() {
+                                1 => DisplayMode::Mode13VGALowRes256,
+                                _ => DisplayMode::ModeXVGAUnchained256,
+                            }
                         }
                         else {
+                            // Double-scanned modes program twice the number of displayed lines.
+                            let lines = (self.crtc.vertical_display_end() as u32 + 1) >> self.crtc.double_scan() as u32;
                             match (self.crtc.horizontal_display_end(), self.ac.display_type()) {
                                 (00..=39, AttributeDisplayType::Color) => DisplayMode::ModeDEGALowResGraphics,
-                                (79, AttributeDisplayType::Color) => match self.crtc.vertical_display_end() {
-                                    0..=199 => DisplayMode::ModeEEGAMedResGraphics,
-                                    _ => DisplayMode::Mode10EGAHiResGraphics,
+                                (79, AttributeDisplayType::Color) => match lines {
+                                    0..=200 => DisplayMode::ModeEEGAMedResGraphics,
+                                    201..=350 => DisplayMode::Mode10EGAHiResGraphics,
+                                    _ => DisplayMode::Mode12VGAHiResGraphics,
                                 },
                                 _ => {
                                     log::warn!("Unsupported graphics mode.");
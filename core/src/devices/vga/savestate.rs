@@ -0,0 +1,83 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::vga::savestate.rs
+
+    Implements save state support for the IBM VGA card and the MCGA.
+
+    Video memory, the latches, the DAC and the register files of the
+    sequencer, graphics controller, attribute controller and CRTC are saved
+    along with the Miscellaneous Output register. On restore the registers are
+    written back through the same paths as a port write so that all derived
+    state is recalculated. Raster position is not saved.
+
+    When the card is an MCGA, the MCGA's own registers are saved after the
+    internal VGA register file. Since the internal registers already reflect
+    the selected MCGA mode, the mode is not reprogrammed on restore.
+
+*/
+
+use super::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+impl VGACard {
+    pub fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        w.write_u8(self.misc_output_register.into_bytes()[0]);
+        w.write_u8(self.feature_bits);
+        self.sequencer.save_state(w);
+        self.gc.save_state(w);
+        self.ac.save_state(w);
+        self.crtc.save_state(w);
+        w.write_bool(self.intr);
+        w.write_bool(self.last_intr);
+        w.write_bool(self.mcga.is_some());
+        if let Some(mcga) = &self.mcga {
+            mcga.save_state(w);
+        }
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let misc_output = r.read_u8()?;
+        self.feature_bits = r.read_u8()?;
+        self.sequencer.load_state(r)?;
+        self.gc.load_state(r)?;
+        self.ac.load_state(r)?;
+        self.crtc.load_state(r)?;
+        self.intr = r.read_bool()?;
+        self.last_intr = r.read_bool()?;
+        let has_mcga = r.read_bool()?;
+        match (&mut self.mcga, has_mcga) {
+            (Some(mcga), true) => mcga.load_state(r)?,
+            (None, false) => {}
+            _ => return Err(SaveStateError::MachineMismatch("MCGA".to_string())),
+        }
+
+        self.write_external_misc_output_register(misc_output);
+        self.recalculate_mode();
+        Ok(())
+    }
+}
@@ -33,6 +33,7 @@
 use crate::{
     device_traits::videocard::VideoCardStateEntry,
     devices::vga::{tablegen::BIT_EXTEND_TABLE64, vram::Vram, VGA_CHARACTER_HEIGHT},
+    savestate::{SaveStateError, StateReader, StateWriter},
 };
 use modular_bitfield::{bitfield, prelude::*, BitfieldSpecifier};
 
@@ -237,6 +238,34 @@ impl Sequencer {
         }
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&[
+            self.reset,
+            self.clocking_mode.into_bytes()[0],
+            self.map_mask,
+            self.character_map_select.into_bytes()[0],
+            self.memory_mode.into_bytes()[0],
+        ]);
+        w.write_u8(self.address_byte);
+        self.vram.save_state(w);
+    }
+
+    /// Restore the sequencer's registers by writing them back through the data register, and the
+    /// contents of video memory.
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0u8; 5];
+        r.read_bytes_into(&mut regs, "VGA sequencer registers")?;
+        let address = r.read_u8()?;
+        self.vram.load_state(r)?;
+
+        for (idx, byte) in regs.iter().enumerate() {
+            self.write_address(idx as u8);
+            self.write_data(*byte);
+        }
+        self.write_address(address);
+        Ok(())
+    }
+
     pub fn update_character_maps(&mut self) {
         // Character font selection is only enabled if the two generator selections differ.
         self.font_select_enabled = self.character_map_select.generator_a() != self.character_map_select.generator_b();
@@ -31,7 +31,11 @@
 */
 
 use super::*;
-use crate::{bus::DeviceRunTimeUnit, devices::pic::Pic};
+use crate::{
+    bus::DeviceRunTimeUnit,
+    devices::pic::Pic,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 use std::{collections::HashMap, path::Path};
 
 impl VideoCard for VGACard {
@@ -48,6 +52,9 @@ impl VideoCard for VGACard {
                 log::debug!("VideoOption::DebugDraw set to: {}", state);
                 self.debug_draw = state;
             }
+            VideoOption::LogRegisterWrites(_state) => {
+                log::warn!("VideoOption::LogRegisterWrites not supported for VGA");
+            }
         }
     }
 
@@ -91,6 +98,11 @@ impl VideoCard for VGACard {
     }
 
     /// Return the 16-bit value computed from the CRTC's pair of Page Address registers.
+    /// Unimplemented
+    fn get_vram(&self) -> Option<&[u8]> {
+        None
+    }
+
     fn get_start_address(&self) -> u16 {
         return self.crtc.start_address();
     }
@@ -105,12 +117,33 @@ impl VideoCard for VGACard {
         Some((self.raster_x, self.raster_y))
     }
 
+    /// Unimplemented
+    fn set_light_pen(&mut self, _pos: Option<(u32, u32)>) {}
+
+    /// Unimplemented
+    fn get_register_log(&self) -> Option<&VideoRegisterLog> {
+        None
+    }
+
+    /// Unimplemented
+    fn set_breakpoints(&mut self, _bp_list: Vec<VideoBreakpoint>) {}
+
+    /// Unimplemented
+    fn take_breakpoint_hit(&mut self) -> bool {
+        false
+    }
+
+    /// Unimplemented
+    fn get_raster_regions(&self) -> Option<RasterRegions> {
+        None
+    }
+
     /// Unimplemented
     fn debug_tick(&mut self, _ticks: u32, _cpumem: Option<&[u8]>) {}
 
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32 {
-        0
+        self.crtc.scanline() as u32
     }
 
     /// Return whether to double scanlines produced by this adapter.
@@ -140,13 +173,27 @@ impl VideoCard for VGACard {
     }
 
     fn get_overscan_color(&self) -> u8 {
-        0
+        self.ac.overscan_color.six
     }
 
-    /// Return the current refresh rate.
-    /// TODO: Handle VGA 70Hz modes.
+    /// Return the current refresh rate, calculated from the selected dot clock and the CRTC's
+    /// horizontal and vertical totals. This distinguishes the 70Hz 400-line modes from the 60Hz
+    /// 480-line modes.
     fn get_refresh_rate(&self) -> u32 {
-        60
+        let dot_clock = match self.misc_output_register.clock_select() {
+            ClockSelect::Clock25 => VGA_CLOCK25,
+            ClockSelect::Clock28 => VGA_CLOCK28,
+            _ => return 60,
+        };
+        let char_w = match self.sequencer.clocking_mode.character_clock() {
+            CharacterClock::EightDots => 8,
+            CharacterClock::NineDots => 9,
+        };
+        // Horizontal Total is programmed as the character count minus 5, and Vertical Total
+        // as the scanline count minus 2.
+        let dots_per_line = (self.crtc.horizontal_total() as u32 + 5) * char_w * self.get_clock_divisor();
+        let lines = self.crtc.vertical_total() as u32 + 2;
+        ((dot_clock * 1_000_000.0) / (dots_per_line * lines) as f64).round() as u32
     }
 
     fn get_clock_divisor(&self) -> u32 {
@@ -209,12 +256,54 @@ impl VideoCard for VGACard {
         None
     }
 
+    /// Unimplemented
+    fn get_crtc_timing(&self) -> Option<CrtcTiming> {
+        None
+    }
+
+    fn get_character_sets(&self) -> Vec<CharacterSet> {
+        // Fonts are loaded into plane 2 in four 16K blocks, with VGA_CHARACTER_HEIGHT bytes reserved per glyph.
+        let plane = self.get_plane_slice(2);
+        let h = std::cmp::min(self.get_character_height() as usize, VGA_CHARACTER_HEIGHT) as u32;
+        (0..4)
+            .filter_map(|block| {
+                let offset = block * 0x4000;
+                let mut set = CharacterSet::from_glyph_major(
+                    &format!("Font RAM block {}", block),
+                    8,
+                    h,
+                    VGA_CHARACTER_HEIGHT,
+                    plane.get(offset..)?,
+                );
+                set.active = offset == self.sequencer.font_offset_a || offset == self.sequencer.font_offset_b;
+                Some(set)
+            })
+            .collect()
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc.maximum_scanline() + 1
     }
 
     fn get_palette(&self) -> Option<Vec<[u8;4]>> {
-        Some(self.ac.color_registers_rgba.to_vec())
+        Some(self.ac.dac_palette())
+    }
+
+    fn get_palette_state(&self) -> Option<PaletteState> {
+        // Palette registers select a DAC entry rather than a color directly.
+        let dac = self.ac.dac_palette();
+        Some(PaletteState {
+            palette: self
+                .ac
+                .palette_registers
+                .iter()
+                .map(|entry| (entry.six, PaletteColor::Rgba(dac[entry.six as usize])))
+                .collect(),
+            overscan: Some(PaletteColor::Rgba(dac[self.ac.overscan_color.six as usize])),
+            blink_enabled: self.ac.blink_enabled(),
+            blink_state: self.blink_state,
+            dac,
+        })
     }
 
     #[rustfmt::skip]
@@ -247,6 +336,15 @@ impl VideoCard for VGACard {
         external_vec.push(("Misc Output [vrp]".to_string(), VideoCardStateEntry::String(format!("{:?}", self.misc_output_register.vertical_retrace_polarity()))));
         map.insert("External".to_string(), external_vec);
 
+        if let Some(mcga) = &self.mcga {
+            let mut mcga_vec = Vec::new();
+            mcga_vec.push(("Mode:".to_string(), VideoCardStateEntry::String(format!("{:?}", mcga.mode()))));
+            mcga_vec.push(("Mode Control:".to_string(), VideoCardStateEntry::String(format!("{:08b}", mcga.mode_byte()))));
+            mcga_vec.push(("Color Select:".to_string(), VideoCardStateEntry::String(format!("{:08b}", mcga.color_select()))));
+            mcga_vec.push(("CRTC Mode Control:".to_string(), VideoCardStateEntry::String(format!("{:08b}", mcga.crtc_mode_control()))));
+            map.insert("MCGA".to_string(), mcga_vec);
+        }
+
         map.insert("Sequencer".to_string(), self.sequencer.get_state());
         map.insert("Graphics".to_string(), self.gc.get_state());
         map.insert("Graphics Stats".to_string(), self.gc.get_stats());
@@ -494,4 +592,17 @@ impl VideoCard for VGACard {
     fn get_text_mode_strings(&self) -> Vec<String> {
         Vec::new()
     }
+
+    /// Unimplemented
+    fn get_text_mode_screen(&self, _full_buffer: bool) -> Option<TextModeScreen> {
+        None
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        self.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.load_state(r)
+    }
 }
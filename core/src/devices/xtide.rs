@@ -0,0 +1,1030 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::xtide.rs
+
+    Implements an XT-IDE (8-bit ATA) controller.
+
+    The controller uses the XT-IDE rev 1 port layout, where the ATA command
+    block registers are at base + 0-7, the high byte latch of the 16-bit data
+    register is at base + 8, and the alternate status / device control
+    register is at base + 0Eh. The XUB option ROM should be configured for an
+    "XTIDE rev 1" controller at the same base address. No IRQ is used; the XUB
+    operates the controller in polled mode.
+
+    SET FEATURES 01h (8-bit data transfers, as used by XT-CF style adapters)
+    is also supported, in which case data is transferred a byte at a time
+    through base + 0.
+
+    Either device may instead be an ATAPI CD-ROM drive, in which case the
+    controller implements the PACKET protocol and passes command packets to
+    devices::atapi_cdrom. A DOS ATAPI driver that supports the XT-IDE port
+    layout is required to use it.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    device_types::hdc::HardDiskFormat,
+    devices::{
+        atapi_cdrom::{AtapiCdRom, PacketResult},
+        hdc::{ControllerError, SECTOR_SIZE},
+    },
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    vhd::VirtualHardDisk,
+};
+
+pub const XTIDE_DEFAULT_IO_BASE: u16 = 0x300;
+pub const XTIDE_PORT_CT: u16 = 16;
+pub const XTIDE_MAX_DRIVES: usize = 2;
+pub const XTIDE_MAX_MULTIPLE: u8 = 16;
+
+// Register offsets from the base address
+const REG_DATA: u16 = 0x00;
+const REG_ERROR_FEATURES: u16 = 0x01;
+const REG_SECTOR_COUNT: u16 = 0x02;
+const REG_SECTOR_NUMBER: u16 = 0x03;
+const REG_CYLINDER_LOW: u16 = 0x04;
+const REG_CYLINDER_HIGH: u16 = 0x05;
+const REG_DRIVE_HEAD: u16 = 0x06;
+const REG_STATUS_COMMAND: u16 = 0x07;
+const REG_DATA_HIGH: u16 = 0x08;
+const REG_ALT_STATUS_CONTROL: u16 = 0x0E;
+
+const STATUS_BSY: u8 = 0b1000_0000;
+const STATUS_DRDY: u8 = 0b0100_0000;
+const STATUS_DSC: u8 = 0b0001_0000;
+const STATUS_DRQ: u8 = 0b0000_1000;
+const STATUS_ERR: u8 = 0b0000_0001;
+
+const ERROR_ABRT: u8 = 0b0000_0100;
+const ERROR_IDNF: u8 = 0b0001_0000;
+const ERROR_UNC: u8 = 0b0100_0000;
+const DIAG_PASSED: u8 = 0x01;
+
+const CONTROL_SRST: u8 = 0b0000_0100;
+
+const DRIVE_HEAD_LBA: u8 = 0b0100_0000;
+const DRIVE_HEAD_DEV: u8 = 0b0001_0000;
+const DRIVE_HEAD_FIXED: u8 = 0b1010_0000;
+
+// Interrupt reason bits, reported in the sector count register during a packet command
+const REASON_COD: u8 = 0b0000_0001;
+const REASON_IO: u8 = 0b0000_0010;
+
+// ATAPI devices report this signature in the cylinder registers after a reset
+const ATAPI_SIGNATURE_LOW: u8 = 0x14;
+const ATAPI_SIGNATURE_HIGH: u8 = 0xEB;
+const ATAPI_PACKET_LEN: usize = 12;
+const ATAPI_MAX_BYTE_COUNT: u16 = 0xFFFE;
+
+const CMD_DEVICE_RESET: u8 = 0x08;
+const CMD_RECALIBRATE: u8 = 0x10;
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_NR: u8 = 0x21;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_NR: u8 = 0x31;
+const CMD_VERIFY_SECTORS: u8 = 0x40;
+const CMD_VERIFY_SECTORS_NR: u8 = 0x41;
+const CMD_SEEK: u8 = 0x70;
+const CMD_EXECUTE_DIAGNOSTIC: u8 = 0x90;
+const CMD_INITIALIZE_PARAMETERS: u8 = 0x91;
+const CMD_PACKET: u8 = 0xA0;
+const CMD_IDENTIFY_PACKET: u8 = 0xA1;
+const CMD_READ_MULTIPLE: u8 = 0xC4;
+const CMD_WRITE_MULTIPLE: u8 = 0xC5;
+const CMD_SET_MULTIPLE: u8 = 0xC6;
+const CMD_CHECK_POWER_MODE: u8 = 0xE5;
+const CMD_IDENTIFY: u8 = 0xEC;
+const CMD_SET_FEATURES: u8 = 0xEF;
+
+const FEATURE_ENABLE_8BIT: u8 = 0x01;
+const FEATURE_DISABLE_8BIT: u8 = 0x81;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Transfer {
+    None,
+    Read,
+    Write,
+    // Receiving a command packet
+    Packet,
+    // Sending packet command data to the host
+    PacketIn,
+    // Receiving packet command data from the host
+    PacketOut,
+}
+
+#[derive(Default)]
+pub struct AtaDrive {
+    vhd: Option<VirtualHardDisk>,
+    // Current translation geometry, set by INITIALIZE DEVICE PARAMETERS
+    heads: u8,
+    sectors: u8,
+    multiple: u8,
+}
+
+impl AtaDrive {
+    fn total_sectors(&self) -> u32 {
+        self.vhd.as_ref().map(|vhd| vhd.total_sectors()).unwrap_or(0)
+    }
+
+    /// Reset the translation geometry to the default geometry of the image.
+    fn reset_geometry(&mut self) {
+        if let Some(vhd) = &self.vhd {
+            self.heads = vhd.max_heads as u8;
+            self.sectors = vhd.max_sectors as u8;
+        }
+    }
+
+    fn cylinders(&self) -> u16 {
+        match self.heads as u32 * self.sectors as u32 {
+            0 => 0,
+            track_size => std::cmp::min(self.total_sectors() / track_size, u16::MAX as u32) as u16,
+        }
+    }
+}
+
+pub struct XtIdeController {
+    io_base: u16,
+    drives: [AtaDrive; XTIDE_MAX_DRIVES],
+    cdrom: Option<AtapiCdRom>,
+    cdrom_device: usize,
+    supported_formats: Vec<HardDiskFormat>,
+
+    features: u8,
+    sector_count: u8,
+    sector_number: u8,
+    cylinder_low: u8,
+    cylinder_high: u8,
+    drive_head: u8,
+    status: u8,
+    error: u8,
+    device_control: u8,
+    eight_bit: bool,
+
+    transfer: Transfer,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    data_latch: u8,
+    sectors_left: u32,
+    block_sectors: u32,
+    packet_data: Vec<u8>,
+    packet_pos: usize,
+}
+
+impl XtIdeController {
+    /// Create a new controller. If `cdrom_device` is specified, an ATAPI CD-ROM drive is attached
+    /// as that device instead of a hard disk.
+    pub fn new(io_base: Option<u16>, cdrom_device: Option<usize>) -> Self {
+        let cdrom_device = cdrom_device.filter(|device| {
+            if *device >= XTIDE_MAX_DRIVES {
+                log::error!("XT-IDE: Invalid CD-ROM device {}", device);
+            }
+            *device < XTIDE_MAX_DRIVES
+        });
+
+        let mut controller = Self {
+            io_base: io_base.unwrap_or(XTIDE_DEFAULT_IO_BASE),
+            drives: Default::default(),
+            cdrom: cdrom_device.map(|_| AtapiCdRom::new()),
+            cdrom_device: cdrom_device.unwrap_or(0),
+            supported_formats: vec![
+                HardDiskFormat {
+                    max_cylinders: 615,
+                    max_heads: 4,
+                    max_sectors: 17,
+                    wpc: None,
+                    desc: "20MB".to_string(),
+                },
+                HardDiskFormat {
+                    max_cylinders: 977,
+                    max_heads: 5,
+                    max_sectors: 17,
+                    wpc: None,
+                    desc: "40MB".to_string(),
+                },
+                HardDiskFormat {
+                    max_cylinders: 1024,
+                    max_heads: 16,
+                    max_sectors: 63,
+                    wpc: None,
+                    desc: "504MB".to_string(),
+                },
+            ],
+            features: 0,
+            sector_count: 0,
+            sector_number: 0,
+            cylinder_low: 0,
+            cylinder_high: 0,
+            drive_head: DRIVE_HEAD_FIXED,
+            status: 0,
+            error: 0,
+            device_control: 0,
+            eight_bit: false,
+            transfer: Transfer::None,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            data_latch: 0,
+            sectors_left: 0,
+            block_sectors: 1,
+            packet_data: Vec::new(),
+            packet_pos: 0,
+        };
+        controller.reset();
+        controller
+    }
+
+    pub fn reset(&mut self) {
+        log::trace!("Resetting XT-IDE controller...");
+
+        // Registers take their diagnostic signature values after a reset.
+        self.sector_count = 1;
+        self.sector_number = 1;
+        self.cylinder_low = 0;
+        self.cylinder_high = 0;
+        self.drive_head = DRIVE_HEAD_FIXED;
+        self.error = DIAG_PASSED;
+        self.eight_bit = false;
+        self.end_transfer();
+
+        for drive in self.drives.iter_mut() {
+            drive.reset_geometry();
+            drive.multiple = 0;
+        }
+        if let Some(cdrom) = &mut self.cdrom {
+            cdrom.reset();
+            if self.cdrom_device == 0 {
+                self.set_atapi_signature();
+            }
+        }
+        self.status = STATUS_DRDY | STATUS_DSC;
+    }
+
+    /// Run the controller for the specified number of microseconds. Only the CD-ROM drive has
+    /// anything to do, playing audio.
+    pub fn run(&mut self, us: f64) {
+        if let Some(cdrom) = &mut self.cdrom {
+            cdrom.run(us);
+        }
+    }
+
+    pub fn cdrom(&self) -> Option<&AtapiCdRom> {
+        self.cdrom.as_ref()
+    }
+
+    pub fn cdrom_mut(&mut self) -> Option<&mut AtapiCdRom> {
+        self.cdrom.as_mut()
+    }
+
+    pub fn drive_ct(&self) -> usize {
+        XTIDE_MAX_DRIVES
+    }
+
+    pub fn get_supported_formats(&self) -> Vec<HardDiskFormat> {
+        self.supported_formats.clone()
+    }
+
+    /// Attach a disk image to the specified drive. Unlike the Xebec controller, any geometry is accepted.
+    pub fn set_vhd(&mut self, device_id: usize, vhd: VirtualHardDisk) -> Result<(), ControllerError> {
+        if device_id >= XTIDE_MAX_DRIVES || (self.cdrom.is_some() && device_id == self.cdrom_device) {
+            return Err(ControllerError::InvalidDevice);
+        }
+
+        let drive = &mut self.drives[device_id];
+        drive.vhd = Some(vhd);
+        drive.reset_geometry();
+        drive.multiple = 0;
+        Ok(())
+    }
+
+    fn selected(&self) -> usize {
+        ((self.drive_head & DRIVE_HEAD_DEV) != 0) as usize
+    }
+
+    fn atapi_selected(&self) -> bool {
+        self.cdrom.is_some() && self.selected() == self.cdrom_device
+    }
+
+    fn drive_present(&self) -> bool {
+        self.atapi_selected() || self.drives[self.selected()].vhd.is_some()
+    }
+
+    fn set_atapi_signature(&mut self) {
+        self.sector_count = 1;
+        self.sector_number = 1;
+        self.cylinder_low = ATAPI_SIGNATURE_LOW;
+        self.cylinder_high = ATAPI_SIGNATURE_HIGH;
+    }
+
+    fn read_status(&self) -> u8 {
+        if self.drive_present() {
+            self.status
+        }
+        else {
+            0
+        }
+    }
+
+    fn end_transfer(&mut self) {
+        self.transfer = Transfer::None;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.sectors_left = 0;
+        self.packet_data.clear();
+        self.packet_pos = 0;
+        self.status &= !STATUS_DRQ;
+    }
+
+    fn abort(&mut self, error: u8) {
+        self.end_transfer();
+        self.error = error;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_ERR;
+    }
+
+    fn complete(&mut self) {
+        self.end_transfer();
+        self.error = 0;
+        self.status = STATUS_DRDY | STATUS_DSC;
+    }
+
+    /// Resolve the address in the command block registers to an LBA on the selected drive.
+    fn current_lba(&self) -> Option<u32> {
+        let drive = &self.drives[self.selected()];
+        let lba = if self.drive_head & DRIVE_HEAD_LBA != 0 {
+            ((self.drive_head & 0x0F) as u32) << 24
+                | (self.cylinder_high as u32) << 16
+                | (self.cylinder_low as u32) << 8
+                | self.sector_number as u32
+        }
+        else {
+            let cylinder = (self.cylinder_high as u32) << 8 | self.cylinder_low as u32;
+            let head = (self.drive_head & 0x0F) as u32;
+            let sector = self.sector_number as u32;
+            if sector == 0 || sector > drive.sectors as u32 || head >= drive.heads as u32 {
+                return None;
+            }
+            (cylinder * drive.heads as u32 + head) * drive.sectors as u32 + (sector - 1)
+        };
+
+        if lba < drive.total_sectors() {
+            Some(lba)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Advance the command block registers to the next sector, as a drive does during a multi-sector transfer.
+    fn advance_address(&mut self) {
+        if self.drive_head & DRIVE_HEAD_LBA != 0 {
+            let lba = (((self.drive_head & 0x0F) as u32) << 24
+                | (self.cylinder_high as u32) << 16
+                | (self.cylinder_low as u32) << 8
+                | self.sector_number as u32)
+                .wrapping_add(1);
+            self.sector_number = lba as u8;
+            self.cylinder_low = (lba >> 8) as u8;
+            self.cylinder_high = (lba >> 16) as u8;
+            self.drive_head = (self.drive_head & 0xF0) | ((lba >> 24) as u8 & 0x0F);
+        }
+        else {
+            let drive = &self.drives[self.selected()];
+            if self.sector_number < drive.sectors {
+                self.sector_number += 1;
+            }
+            else {
+                self.sector_number = 1;
+                let head = (self.drive_head & 0x0F) + 1;
+                if head < drive.heads {
+                    self.drive_head = (self.drive_head & 0xF0) | head;
+                }
+                else {
+                    self.drive_head &= 0xF0;
+                    let cylinder = ((self.cylinder_high as u16) << 8 | self.cylinder_low as u16).wrapping_add(1);
+                    self.cylinder_low = cylinder as u8;
+                    self.cylinder_high = (cylinder >> 8) as u8;
+                }
+            }
+        }
+        self.sector_count = self.sector_count.wrapping_sub(1);
+    }
+
+    /// Return the number of sectors requested by the sector count register, where 0 means 256.
+    fn requested_sectors(&self) -> u32 {
+        match self.sector_count {
+            0 => 256,
+            n => n as u32,
+        }
+    }
+
+    /// Read the next block of sectors into the buffer. Returns false if the transfer was aborted.
+    fn fill_read_buffer(&mut self) -> bool {
+        let block = std::cmp::min(self.block_sectors, self.sectors_left);
+        self.buffer.clear();
+        self.buffer_pos = 0;
+
+        for _ in 0..block {
+            let lba = match self.current_lba() {
+                Some(lba) => lba,
+                None => {
+                    self.abort(ERROR_IDNF);
+                    return false;
+                }
+            };
+
+            let mut sector_buf = vec![0; SECTOR_SIZE];
+            let drive = self.selected();
+            if let Some(vhd) = &mut self.drives[drive].vhd {
+                if let Err(e) = vhd.read_sector_lba(&mut sector_buf, lba) {
+                    log::error!("XT-IDE: Error reading sector {}: {}", lba, e);
+                    self.abort(ERROR_UNC);
+                    return false;
+                }
+            }
+            self.buffer.extend_from_slice(&sector_buf);
+            self.advance_address();
+            self.sectors_left -= 1;
+        }
+
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+        true
+    }
+
+    /// Write the buffer out to the drive. Returns false if the transfer was aborted.
+    fn flush_write_buffer(&mut self) -> bool {
+        let buffer = std::mem::take(&mut self.buffer);
+
+        for sector_buf in buffer.chunks(SECTOR_SIZE) {
+            let lba = match self.current_lba() {
+                Some(lba) => lba,
+                None => {
+                    self.abort(ERROR_IDNF);
+                    return false;
+                }
+            };
+
+            let drive = self.selected();
+            if let Some(vhd) = &mut self.drives[drive].vhd {
+                if let Err(e) = vhd.write_sector_lba(sector_buf, lba) {
+                    log::error!("XT-IDE: Error writing sector {}: {}", lba, e);
+                    self.abort(ERROR_UNC);
+                    return false;
+                }
+            }
+            self.advance_address();
+            self.sectors_left -= 1;
+        }
+        true
+    }
+
+    /// Prepare the buffer to receive the next block of sectors from the host.
+    fn begin_write_block(&mut self) {
+        let block = std::cmp::min(self.block_sectors, self.sectors_left) as usize;
+        self.buffer = vec![0; block * SECTOR_SIZE];
+        self.buffer_pos = 0;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+    }
+
+    fn start_read(&mut self, block_sectors: u32) {
+        self.transfer = Transfer::Read;
+        self.sectors_left = self.requested_sectors();
+        self.block_sectors = block_sectors;
+        self.fill_read_buffer();
+    }
+
+    fn start_write(&mut self, block_sectors: u32) {
+        self.transfer = Transfer::Write;
+        self.sectors_left = self.requested_sectors();
+        self.block_sectors = block_sectors;
+        self.begin_write_block();
+    }
+
+    /// Called when the host has consumed or filled the entire buffer.
+    fn buffer_complete(&mut self) {
+        match self.transfer {
+            Transfer::Read => {
+                if self.sectors_left > 0 {
+                    self.fill_read_buffer();
+                }
+                else {
+                    self.complete();
+                }
+            }
+            Transfer::Write => {
+                if self.flush_write_buffer() {
+                    if self.sectors_left > 0 {
+                        self.begin_write_block();
+                    }
+                    else {
+                        self.complete();
+                    }
+                }
+            }
+            Transfer::Packet => {
+                self.execute_packet();
+            }
+            Transfer::PacketIn => {
+                if self.packet_pos < self.packet_data.len() {
+                    self.send_packet_chunk();
+                }
+                else {
+                    self.complete_packet();
+                }
+            }
+            Transfer::PacketOut => {
+                let data = std::mem::take(&mut self.buffer);
+                if let Some(cdrom) = &mut self.cdrom {
+                    cdrom.data_out(&data);
+                }
+                self.complete_packet();
+            }
+            Transfer::None => {}
+        }
+    }
+
+    fn data_read(&mut self) -> u8 {
+        if !matches!(self.transfer, Transfer::Read | Transfer::PacketIn) || self.buffer_pos >= self.buffer.len() {
+            return NO_IO_BYTE;
+        }
+
+        let byte = self.buffer[self.buffer_pos];
+        if self.eight_bit {
+            self.buffer_pos += 1;
+        }
+        else {
+            // Latch the high byte of the word for a following read of the high data register.
+            self.data_latch = self.buffer[self.buffer_pos + 1];
+            self.buffer_pos += 2;
+        }
+
+        if self.buffer_pos >= self.buffer.len() {
+            self.buffer_complete();
+        }
+        byte
+    }
+
+    fn data_write(&mut self, byte: u8) {
+        if !matches!(self.transfer, Transfer::Write | Transfer::Packet | Transfer::PacketOut)
+            || self.buffer_pos >= self.buffer.len()
+        {
+            return;
+        }
+
+        self.buffer[self.buffer_pos] = byte;
+        if self.eight_bit {
+            self.buffer_pos += 1;
+        }
+        else {
+            // The high byte was written to the latch first.
+            self.buffer[self.buffer_pos + 1] = self.data_latch;
+            self.buffer_pos += 2;
+        }
+
+        if self.buffer_pos >= self.buffer.len() {
+            self.buffer_complete();
+        }
+    }
+
+    /// Build the 256-word IDENTIFY DEVICE response for the selected drive.
+    fn identify(&self) -> Vec<u8> {
+        let drive = &self.drives[self.selected()];
+        let vhd = drive.vhd.as_ref().unwrap();
+        let mut words = [0u16; 256];
+
+        let put_string = |words: &mut [u16; 256], start: usize, len: usize, s: &str| {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.resize(len * 2, b' ');
+            for i in 0..len {
+                // ATA strings store the first character of each pair in the high byte.
+                words[start + i] = (bytes[i * 2] as u16) << 8 | bytes[i * 2 + 1] as u16;
+            }
+        };
+
+        let total = vhd.total_sectors();
+        let cylinders = std::cmp::min(total / (vhd.max_heads * vhd.max_sectors), 16383);
+
+        words[0] = 0x0040; // Fixed disk
+        words[1] = cylinders as u16;
+        words[3] = vhd.max_heads as u16;
+        words[6] = vhd.max_sectors as u16;
+        put_string(&mut words, 10, 10, "MARTYPC00000001");
+        put_string(&mut words, 23, 4, "1.0");
+        put_string(&mut words, 27, 20, "MartyPC XT-IDE Disk");
+        words[47] = 0x8000 | XTIDE_MAX_MULTIPLE as u16;
+        words[49] = 0x0200; // LBA supported
+        words[53] = 0x0001; // Words 54-58 are valid
+
+        let current_sectors = drive.cylinders() as u32 * drive.heads as u32 * drive.sectors as u32;
+        words[54] = drive.cylinders();
+        words[55] = drive.heads as u16;
+        words[56] = drive.sectors as u16;
+        words[57] = current_sectors as u16;
+        words[58] = (current_sectors >> 16) as u16;
+        if drive.multiple > 0 {
+            words[59] = 0x0100 | drive.multiple as u16;
+        }
+        words[60] = total as u16;
+        words[61] = (total >> 16) as u16;
+
+        let mut buf = Vec::with_capacity(SECTOR_SIZE);
+        for word in words.iter() {
+            buf.push(*word as u8);
+            buf.push((*word >> 8) as u8);
+        }
+        buf
+    }
+
+    /// Pass a received command packet to the CD-ROM drive and begin the resulting data phase.
+    fn execute_packet(&mut self) {
+        let cdb = std::mem::take(&mut self.buffer);
+        let result = match &mut self.cdrom {
+            Some(cdrom) => cdrom.packet(&cdb),
+            None => return,
+        };
+
+        match result {
+            PacketResult::Complete => self.complete_packet(),
+            PacketResult::DataIn(data) if data.is_empty() => self.complete_packet(),
+            PacketResult::DataIn(data) => {
+                self.packet_data = data;
+                self.packet_pos = 0;
+                self.send_packet_chunk();
+            }
+            PacketResult::DataOut(0) => self.complete_packet(),
+            PacketResult::DataOut(len) => {
+                self.transfer = Transfer::PacketOut;
+                self.buffer = vec![0; (len + 1) & !1];
+                self.buffer_pos = 0;
+                self.cylinder_low = len as u8;
+                self.cylinder_high = (len >> 8) as u8;
+                self.sector_count = 0;
+                self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+            }
+            PacketResult::CheckCondition(sense_key) => {
+                self.abort((sense_key << 4) | ERROR_ABRT);
+                self.sector_count = REASON_IO | REASON_COD;
+            }
+        }
+    }
+
+    /// Present the next chunk of packet data to the host, limited by the byte count the host
+    /// specified in the cylinder registers.
+    fn send_packet_chunk(&mut self) {
+        let limit = match (self.cylinder_high as u16) << 8 | self.cylinder_low as u16 {
+            0 | 0xFFFF => ATAPI_MAX_BYTE_COUNT,
+            limit => limit & !1,
+        };
+        let remaining = self.packet_data.len() - self.packet_pos;
+        let chunk = std::cmp::min(limit as usize, remaining);
+
+        self.buffer = self.packet_data[self.packet_pos..self.packet_pos + chunk].to_vec();
+        if !self.eight_bit && chunk & 1 != 0 {
+            // Word transfers of an odd length are padded.
+            self.buffer.push(0);
+        }
+        self.buffer_pos = 0;
+        self.packet_pos += chunk;
+
+        self.transfer = Transfer::PacketIn;
+        self.cylinder_low = chunk as u8;
+        self.cylinder_high = (chunk >> 8) as u8;
+        self.sector_count = REASON_IO;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+    }
+
+    fn complete_packet(&mut self) {
+        self.complete();
+        self.sector_count = REASON_IO | REASON_COD;
+    }
+
+    fn atapi_command(&mut self, command: u8) {
+        match command {
+            CMD_PACKET => {
+                self.transfer = Transfer::Packet;
+                self.buffer = vec![0; ATAPI_PACKET_LEN];
+                self.buffer_pos = 0;
+                self.error = 0;
+                self.sector_count = REASON_COD;
+                self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+            }
+            CMD_IDENTIFY_PACKET => {
+                self.buffer = self.cdrom.as_ref().unwrap().identify();
+                self.buffer_pos = 0;
+                self.sectors_left = 0;
+                self.transfer = Transfer::Read;
+                self.error = 0;
+                self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+            }
+            CMD_IDENTIFY => {
+                // Packet devices abort IDENTIFY DEVICE and present their signature, so that the host
+                // knows to use IDENTIFY PACKET DEVICE instead.
+                self.abort(ERROR_ABRT);
+                self.set_atapi_signature();
+            }
+            CMD_DEVICE_RESET => {
+                self.complete();
+                self.error = DIAG_PASSED;
+                self.set_atapi_signature();
+            }
+            CMD_EXECUTE_DIAGNOSTIC => {
+                self.reset();
+            }
+            CMD_SET_FEATURES => {
+                match self.features {
+                    FEATURE_ENABLE_8BIT => self.eight_bit = true,
+                    FEATURE_DISABLE_8BIT => self.eight_bit = false,
+                    _ => {}
+                }
+                self.complete();
+            }
+            CMD_CHECK_POWER_MODE => {
+                self.sector_count = 0xFF;
+                self.complete();
+            }
+            0xE0..=0xE4 | 0xE6 | 0xE7 => {
+                self.complete();
+            }
+            _ => {
+                log::debug!("XT-IDE: Unsupported command {:02X} for ATAPI device", command);
+                self.abort(ERROR_ABRT);
+            }
+        }
+    }
+
+    fn command(&mut self, command: u8) {
+        if !self.drive_present() {
+            // Commands to an absent device are ignored.
+            return;
+        }
+        log::trace!("XT-IDE: Command {:02X} to drive {}", command, self.selected());
+        self.end_transfer();
+
+        if self.atapi_selected() {
+            self.atapi_command(command);
+            return;
+        }
+
+        match command {
+            CMD_IDENTIFY => {
+                self.buffer = self.identify();
+                self.buffer_pos = 0;
+                self.sectors_left = 0;
+                self.transfer = Transfer::Read;
+                self.error = 0;
+                self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+            }
+            CMD_READ_SECTORS | CMD_READ_SECTORS_NR => {
+                self.start_read(1);
+            }
+            CMD_WRITE_SECTORS | CMD_WRITE_SECTORS_NR => {
+                self.start_write(1);
+            }
+            CMD_READ_MULTIPLE | CMD_WRITE_MULTIPLE => {
+                let multiple = self.drives[self.selected()].multiple as u32;
+                if multiple == 0 {
+                    self.abort(ERROR_ABRT);
+                }
+                else if command == CMD_READ_MULTIPLE {
+                    self.start_read(multiple);
+                }
+                else {
+                    self.start_write(multiple);
+                }
+            }
+            CMD_VERIFY_SECTORS | CMD_VERIFY_SECTORS_NR => {
+                for _ in 0..self.requested_sectors() {
+                    if self.current_lba().is_none() {
+                        self.abort(ERROR_IDNF);
+                        return;
+                    }
+                    self.advance_address();
+                }
+                self.complete();
+            }
+            CMD_INITIALIZE_PARAMETERS => {
+                let drive = self.selected();
+                self.drives[drive].sectors = self.sector_count;
+                self.drives[drive].heads = (self.drive_head & 0x0F) + 1;
+                self.complete();
+            }
+            CMD_SET_MULTIPLE => {
+                let count = self.sector_count;
+                if count > XTIDE_MAX_MULTIPLE || (count != 0 && !count.is_power_of_two()) {
+                    self.abort(ERROR_ABRT);
+                }
+                else {
+                    let drive = self.selected();
+                    self.drives[drive].multiple = count;
+                    self.complete();
+                }
+            }
+            CMD_SET_FEATURES => {
+                match self.features {
+                    FEATURE_ENABLE_8BIT => self.eight_bit = true,
+                    FEATURE_DISABLE_8BIT => self.eight_bit = false,
+                    _ => {}
+                }
+                self.complete();
+            }
+            CMD_EXECUTE_DIAGNOSTIC => {
+                self.reset();
+            }
+            CMD_CHECK_POWER_MODE => {
+                self.sector_count = 0xFF;
+                self.complete();
+            }
+            0xE0..=0xE4 | 0xE6 | 0xE7 => {
+                // Power management and cache flush commands have nothing to do.
+                self.complete();
+            }
+            _ if command & 0xF0 == CMD_RECALIBRATE || command & 0xF0 == CMD_SEEK => {
+                self.complete();
+            }
+            _ => {
+                log::debug!("XT-IDE: Unsupported command {:02X}", command);
+                self.abort(ERROR_ABRT);
+            }
+        }
+    }
+}
+
+impl IoDevice for XtIdeController {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.io_base) {
+            REG_DATA => self.data_read(),
+            REG_ERROR_FEATURES => self.error,
+            REG_SECTOR_COUNT => self.sector_count,
+            REG_SECTOR_NUMBER => self.sector_number,
+            REG_CYLINDER_LOW => self.cylinder_low,
+            REG_CYLINDER_HIGH => self.cylinder_high,
+            REG_DRIVE_HEAD => self.drive_head,
+            REG_STATUS_COMMAND | REG_ALT_STATUS_CONTROL => self.read_status(),
+            REG_DATA_HIGH => self.data_latch,
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.io_base) {
+            REG_DATA => self.data_write(data),
+            REG_ERROR_FEATURES => self.features = data,
+            REG_SECTOR_COUNT => self.sector_count = data,
+            REG_SECTOR_NUMBER => self.sector_number = data,
+            REG_CYLINDER_LOW => self.cylinder_low = data,
+            REG_CYLINDER_HIGH => self.cylinder_high = data,
+            REG_DRIVE_HEAD => self.drive_head = data | DRIVE_HEAD_FIXED,
+            REG_STATUS_COMMAND => self.command(data),
+            REG_DATA_HIGH => self.data_latch = data,
+            REG_ALT_STATUS_CONTROL => {
+                // Reset is performed on the falling edge of SRST.
+                if self.device_control & CONTROL_SRST != 0 && data & CONTROL_SRST == 0 {
+                    self.reset();
+                }
+                else if data & CONTROL_SRST != 0 {
+                    self.status = STATUS_BSY;
+                }
+                self.device_control = data;
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            (String::from("XT-IDE Data"), self.io_base + REG_DATA),
+            (String::from("XT-IDE Error/Features"), self.io_base + REG_ERROR_FEATURES),
+            (String::from("XT-IDE Sector Count"), self.io_base + REG_SECTOR_COUNT),
+            (String::from("XT-IDE Sector Number"), self.io_base + REG_SECTOR_NUMBER),
+            (String::from("XT-IDE Cylinder Low"), self.io_base + REG_CYLINDER_LOW),
+            (String::from("XT-IDE Cylinder High"), self.io_base + REG_CYLINDER_HIGH),
+            (String::from("XT-IDE Drive/Head"), self.io_base + REG_DRIVE_HEAD),
+            (String::from("XT-IDE Status/Command"), self.io_base + REG_STATUS_COMMAND),
+            (String::from("XT-IDE Data High"), self.io_base + REG_DATA_HIGH),
+            (
+                String::from("XT-IDE Alt Status/Control"),
+                self.io_base + REG_ALT_STATUS_CONTROL,
+            ),
+        ]
+    }
+}
+
+/// The identity of each mounted disk image is saved rather than its contents.
+impl SaveState for XtIdeController {
+    fn save_state(&self, w: &mut StateWriter) {
+        for drive in self.drives.iter() {
+            w.write_option_str(drive.vhd.as_ref().map(|vhd| vhd.identity()).as_deref());
+            w.write_u8(drive.heads);
+            w.write_u8(drive.sectors);
+            w.write_u8(drive.multiple);
+        }
+        w.write_bool(self.cdrom.is_some());
+        if let Some(cdrom) = &self.cdrom {
+            w.write_usize(self.cdrom_device);
+            cdrom.save_state(w);
+        }
+
+        w.write_u8(self.features);
+        w.write_u8(self.sector_count);
+        w.write_u8(self.sector_number);
+        w.write_u8(self.cylinder_low);
+        w.write_u8(self.cylinder_high);
+        w.write_u8(self.drive_head);
+        w.write_u8(self.status);
+        w.write_u8(self.error);
+        w.write_u8(self.device_control);
+        w.write_bool(self.eight_bit);
+
+        w.write_u8(match self.transfer {
+            Transfer::None => 0,
+            Transfer::Read => 1,
+            Transfer::Write => 2,
+            Transfer::Packet => 3,
+            Transfer::PacketIn => 4,
+            Transfer::PacketOut => 5,
+        });
+        w.write_bytes(&self.buffer);
+        w.write_usize(self.buffer_pos);
+        w.write_u8(self.data_latch);
+        w.write_u32(self.sectors_left);
+        w.write_u32(self.block_sectors);
+        w.write_bytes(&self.packet_data);
+        w.write_usize(self.packet_pos);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for (n, drive) in self.drives.iter_mut().enumerate() {
+            r.read_media(
+                drive.vhd.as_ref().map(|vhd| vhd.identity()),
+                &format!("XT-IDE device {}", n),
+            )?;
+            drive.heads = r.read_u8()?;
+            drive.sectors = r.read_u8()?;
+            drive.multiple = r.read_u8()?;
+        }
+        if r.read_bool()? != self.cdrom.is_some() {
+            return Err(SaveStateError::MachineMismatch("XT-IDE CD-ROM".to_string()));
+        }
+        if let Some(cdrom) = &mut self.cdrom {
+            if r.read_usize()? != self.cdrom_device {
+                return Err(SaveStateError::MachineMismatch("XT-IDE CD-ROM device".to_string()));
+            }
+            cdrom.load_state(r)?;
+        }
+
+        self.features = r.read_u8()?;
+        self.sector_count = r.read_u8()?;
+        self.sector_number = r.read_u8()?;
+        self.cylinder_low = r.read_u8()?;
+        self.cylinder_high = r.read_u8()?;
+        self.drive_head = r.read_u8()?;
+        self.status = r.read_u8()?;
+        self.error = r.read_u8()?;
+        self.device_control = r.read_u8()?;
+        self.eight_bit = r.read_bool()?;
+
+        self.transfer = match r.read_u8()? {
+            0 => Transfer::None,
+            1 => Transfer::Read,
+            2 => Transfer::Write,
+            3 => Transfer::Packet,
+            4 => Transfer::PacketIn,
+            5 => Transfer::PacketOut,
+            _ => return Err(SaveStateError::InvalidValue("XT-IDE transfer")),
+        };
+        self.buffer = r.read_bytes()?.to_vec();
+        self.buffer_pos = r.read_usize()?;
+        self.data_latch = r.read_u8()?;
+        self.sectors_left = r.read_u32()?;
+        self.block_sectors = r.read_u32()?;
+        self.packet_data = r.read_bytes()?.to_vec();
+        self.packet_pos = r.read_usize()?;
+
+        if self.buffer_pos > self.buffer.len() || self.packet_pos > self.packet_data.len() {
+            return Err(SaveStateError::InvalidValue("XT-IDE buffer position"));
+        }
+        Ok(())
+    }
+}
@@ -39,10 +39,10 @@
     }
 */
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum_macros::{EnumIter, EnumString};
 
-#[derive(Copy, Clone, Debug, EnumIter, EnumString, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, EnumIter, EnumString, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum MartyKey {
     None,
     Backquote,
@@ -34,10 +34,13 @@
 
 extern crate core;
 
+pub mod analysis;
 pub mod breakpoints;
 pub mod bus;
 pub mod bytebuf;
 pub mod bytequeue;
+pub mod cd_image;
+pub mod control_flow;
 pub mod coreconfig;
 pub mod cpu_808x;
 pub mod cpu_common;
@@ -51,6 +54,7 @@ pub mod keys;
 pub mod machine;
 pub mod machine_config;
 pub mod memerror;
+pub mod savestate;
 #[cfg(feature = "sound")]
 pub mod sound;
 pub mod syntax_token;
@@ -39,7 +39,7 @@ use std::{
     collections::{HashMap, BTreeMap, VecDeque},
     fs::File,
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use log;
@@ -52,28 +52,45 @@ use crate::sound::{SoundOutputConfig, SoundOutput, SoundSourceDescriptor};
 use crate::{
     breakpoints::BreakPointType,
     bus::{BusInterface, ClockFactor, DeviceEvent, MEM_CP_BIT},
+    analysis::{AnalysisCollector, AnalysisFormat},
+    control_flow::{ControlFlowEdgeKind, ControlFlowFormat, ControlFlowNode, ControlFlowRecorder},
     coreconfig::CoreConfig,
     cpu_808x::{Intel808x},
     cpu_common::{Cpu, CpuOption, CpuError, TraceMode},
-    device_traits::videocard::{VideoCard, VideoCardId, VideoCardInterface, VideoCardState, VideoOption},
+    device_traits::videocard::{
+        VideoCard,
+        VideoCardId,
+        VideoCardInterface,
+        VideoCardState,
+        VideoBreakpoint,
+        VideoOption,
+        VideoRegisterLog,
+    },
+    device_types::hdc::HardDiskFormat,
     devices::{
         dma::DMAControllerStringState,
         fdc::FloppyController,
-        hdc::HardDiskController,
+        hdc::{ControllerError, HardDiskController},
         keyboard::KeyboardModifiers,
         mouse::Mouse,
         pic::PicStringState,
         pit::{PitDisplayState},
-        ppi::{PpiDisplayState, PpiStringState},
+        post_card::PostCardState,
+        ppi::{PpiDipSwitchState, PpiDisplayState, PpiStringState},
         cartridge_slots::CartridgeSlot,
+        cassette::Cassette,
         serial::SerialPortDisplayState,
+        seagate_st01::SeagateSt01,
+        xtide::XtIdeController,
     },
     keys::MartyKey,
-    machine_config::{get_machine_descriptor, MachineConfiguration, MachineDescriptor},
+    machine_config::{get_machine_descriptor, DmaType, MachineConfiguration, MachineDescriptor},
     machine_types::{OnHaltBehavior, MachineType},
+    savestate::{self, SaveState, SaveStateError, StateReader, StateWriter},
     tracelogger::TraceLogger,
+    vhd::VirtualHardDisk,
 };
-use crate::cpu_common::{CpuAddress, CpuDispatch, Disassembly, format_instruction_bytes, ServiceEvent, StepResult};
+use crate::cpu_common::{CpuAddress, CpuDispatch, Disassembly, format_instruction_bytes, Mnemonic, OperandType, Register16, Segment, ServiceEvent, StepResult};
 use crate::cpu_common::builder::CpuBuilder;
 use crate::devices::fdc::FdcDebugState;
 use crate::devices::floppy_drive::FloppyImageState;
@@ -99,6 +116,27 @@ pub struct KeybufferEntry {
     pub translate: bool,
 }
 
+/// An input sent to the machine by the host. While input logging is enabled, every input is
+/// logged so that a session can be recorded and later replayed with apply_input().
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MachineInput {
+    KeyPress(MartyKey, KeyboardModifiers),
+    KeyRelease(MartyKey),
+    CtrlAltDel,
+    Mouse {
+        l_button: bool,
+        r_button: bool,
+        m_button: bool,
+        delta_x: f64,
+        delta_y: f64,
+    },
+    GamePort {
+        buttons: [bool; 4],
+        sticks: [(f64, f64); 2],
+    },
+    Turbo(bool),
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum MachineEvent {
     CheckpointHit(usize, u32),
@@ -124,6 +162,8 @@ impl MachineState {
 #[derive(Copy, Clone, Debug)]
 pub enum MachineOption {
     RecordListing(bool),
+    RecordControlFlow(bool),
+    RecordAnalysis(bool),
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -294,6 +334,8 @@ pub struct MachineRomManifest {
 #[derive(Default, Debug)]
 pub struct MachineOptions {
     pub record_listing: bool,
+    pub record_control_flow: bool,
+    pub record_analysis: bool,
 }
 
 #[derive(Default)]
@@ -358,6 +400,8 @@ pub struct MachineBuilder<'a> {
     #[cfg(feature = "sound")]
     sound_override: Option<bool>,
     keyboard_layout_file: Option<PathBuf>,
+    control_flow_file: Option<PathBuf>,
+    analysis_file: Option<PathBuf>,
 }
 
 impl<'a> MachineBuilder<'a> {
@@ -428,6 +472,16 @@ impl<'a> MachineBuilder<'a> {
         self
     }
 
+    pub fn with_control_flow_file(mut self, control_flow_file: Option<PathBuf>) -> Self {
+        self.control_flow_file = control_flow_file;
+        self
+    }
+
+    pub fn with_analysis_file(mut self, analysis_file: Option<PathBuf>) -> Self {
+        self.analysis_file = analysis_file;
+        self
+    }
+
     pub fn build(mut self) -> Result<Machine, Error> {
         let core_config = self.core_config.ok_or(anyhow!("No core configuration specified"))?;
         let machine_config = self
@@ -458,6 +512,8 @@ impl<'a> MachineBuilder<'a> {
             rom_manifest,
             self.keyboard_layout_file,
             self.listing_file,
+            self.control_flow_file,
+            self.analysis_file,
         ))
     }
 }
@@ -479,6 +535,9 @@ pub struct Machine {
     sound_sources: Vec<SoundSourceDescriptor>,
     debug_snd_file: Option<File>,
     kb_buf: VecDeque<KeybufferEntry>,
+    input_log: Option<Vec<MachineInput>>,
+    input_log_game_port: Option<MachineInput>,
+    input_locked: bool,
     error: bool,
     error_str: Option<String>,
     turbo_bit: bool,
@@ -493,9 +552,14 @@ pub struct Machine {
     events: Vec<MachineEvent>,
     reload_pending: bool,
     halt_behavior: OnHaltBehavior,
+    video_breakpoints: bool,
     disassembly: Disassembly,
     disassembly_listing: BTreeMap<CpuAddress, DisassemblyListingEntry>,
     disassembly_listing_file: Option<PathBuf>,
+    control_flow: ControlFlowRecorder,
+    control_flow_file: Option<PathBuf>,
+    analysis: AnalysisCollector,
+    analysis_file: Option<PathBuf>,
 }
 
 impl Machine {
@@ -511,6 +575,8 @@ impl Machine {
         rom_manifest: MachineRomManifest,
         keyboard_layout_file: Option<PathBuf>,
         disassembly_listing_file: Option<PathBuf>,
+        control_flow_file: Option<PathBuf>,
+        analysis_file: Option<PathBuf>,
         //rom_manager: RomManager,
     ) -> Machine {
 
@@ -597,6 +663,12 @@ impl Machine {
 
         cpu.set_option(CpuOption::TraceLoggingEnabled(core_config.get_cpu_trace_on()));
 
+        // Let the CPU's DMA scheduler know whether DMA requests pass through a cascaded controller.
+        cpu.set_option(CpuOption::DmaCascade(matches!(
+            machine_config.dma_type.or(machine_desc.dma_type),
+            Some(DmaType::Chained)
+        )));
+
         // Set bus options from core configuration now that CPU has created the bus
         cpu.bus_mut().set_options(core_config.get_title_hacks());
 
@@ -711,6 +783,9 @@ impl Machine {
             //cpu.set_reset_vector(CpuAddress::Segmented(rom_entry_point.0, rom_entry_point.1));
         }
 
+        // Populate custom memory regions. ROM mirrors need the ROMs to be installed first.
+        cpu.bus_mut().apply_memory_regions();
+
         // Set CPU clock divisor/multiplier
         let cpu_factor = if core_config.get_machine_turbo() {
             machine_desc.cpu_turbo_factor
@@ -744,6 +819,9 @@ impl Machine {
             sound_sources,
             debug_snd_file: None,
             kb_buf: VecDeque::new(),
+            input_log: None,
+            input_log_game_port: None,
+            input_locked: false,
             error: false,
             error_str: None,
             turbo_bit: false,
@@ -758,9 +836,14 @@ impl Machine {
             events: Vec::new(),
             reload_pending: false,
             halt_behavior: core_config.get_halt_behavior(),
+            video_breakpoints: false,
             disassembly: Disassembly::default(),
             disassembly_listing: BTreeMap::new(),
             disassembly_listing_file,
+            control_flow: ControlFlowRecorder::new(),
+            control_flow_file,
+            analysis: AnalysisCollector::new(),
+            analysis_file,
         }
     }
 
@@ -792,12 +875,59 @@ impl Machine {
                     }
                 }
             }
+            MachineOption::RecordControlFlow(state) => {
+                let old_state = self.options.record_control_flow;
+                self.options.record_control_flow = state;
+                match state {
+                    true => {
+                        if !old_state {
+                            log::debug!("Control flow recording: ON");
+                            self.control_flow.clear();
+                        }
+                    }
+                    false => {
+                        if old_state {
+                            log::debug!("Control flow recording: OFF");
+                            log::debug!("Dumping control flow graph to: {:?}", self.control_flow_file);
+                        }
+
+                        self.dump_control_flow();
+                        self.control_flow.clear();
+                    }
+                }
+            }
+            MachineOption::RecordAnalysis(state) => {
+                let old_state = self.options.record_analysis;
+                self.options.record_analysis = state;
+                match state {
+                    true => {
+                        if !old_state {
+                            log::debug!("Analysis recording: ON");
+                            self.analysis.clear();
+                            for cp in self.rom_manifest.checkpoints.iter() {
+                                self.analysis.add_symbol(cp.addr, &cp.desc);
+                            }
+                        }
+                    }
+                    false => {
+                        if old_state {
+                            log::debug!("Analysis recording: OFF");
+                            log::debug!("Dumping analysis export to: {:?}", self.analysis_file);
+                        }
+
+                        self.dump_analysis();
+                        self.analysis.clear();
+                    }
+                }
+            }
         }
     }
 
     pub fn get_option(&self, opt: MachineOption) -> MachineOption {
         match opt {
             MachineOption::RecordListing(_) => MachineOption::RecordListing(self.options.record_listing),
+            MachineOption::RecordControlFlow(_) => MachineOption::RecordControlFlow(self.options.record_control_flow),
+            MachineOption::RecordAnalysis(_) => MachineOption::RecordAnalysis(self.options.record_analysis),
         }
     }
 
@@ -826,6 +956,7 @@ impl Machine {
                 }
             }
         }
+        self.cpu.bus_mut().apply_memory_regions();
 
         self.rom_manifest = rom_manifest;
         // Allow machine to run again
@@ -887,6 +1018,15 @@ impl Machine {
         Ok(())
     }
 
+    /// Transfer execution to the specified address without resetting the rest of the machine.
+    /// The CPU is reset to flush its prefetch queue, so all other registers must be set after
+    /// calling this. The reset vector is restored afterwards.
+    pub fn set_entry_point(&mut self, segment: u16, offset: u16) {
+        self.cpu.set_reset_vector(CpuAddress::Segmented(segment, offset));
+        self.cpu.reset();
+        self.cpu.set_reset_vector(CpuAddress::Segmented(0xFFFF, 0x0000));
+    }
+
     pub fn bus(&self) -> &BusInterface {
         self.cpu.bus()
     }
@@ -932,6 +1072,10 @@ impl Machine {
         &self.cpu
     }
 
+    pub fn cpu_mut(&mut self) -> &mut CpuDispatch {
+        &mut self.cpu
+    }
+
     pub fn config(&self) -> &MachineConfiguration {
         &self.machine_config
     }
@@ -980,6 +1124,10 @@ impl Machine {
     /// We must be careful not to update this between step() and run_devices() or devices'
     /// advance_ticks may overflow device update ticks.
     pub fn set_turbo_mode(&mut self, state: bool) {
+        self.host_input(MachineInput::Turbo(state));
+    }
+
+    fn apply_turbo_mode(&mut self, state: bool) {
         self.turbo_button = state;
         if state {
             self.next_cpu_factor = self.machine_desc.cpu_turbo_factor;
@@ -993,6 +1141,107 @@ impl Machine {
         );
     }
 
+    /// Returns true if the CPU is running at its turbo clock factor, either because the turbo
+    /// button is pressed or because software has enabled turbo via the PPI.
+    pub fn turbo_active(&self) -> bool {
+        self.turbo_button || self.turbo_bit
+    }
+
+    /// Identifies the machine a save state was taken from. A state can only be restored into a
+    /// machine with the same description and set of devices.
+    fn state_description(&self) -> String {
+        format!("{:?} {:?}", self.machine_type, self.cpu.get_type())
+    }
+
+    /// Capture the complete state of the machine. This must be called between calls to run().
+    /// Mounted media is not part of the state; devices record its identity instead.
+    pub fn save_state(&self) -> Result<Vec<u8>, SaveStateError> {
+        let mut w = StateWriter::new();
+        savestate::write_header(&mut w, &self.state_description());
+        w.write_section(b"MACH", |w| {
+            w.write_u64(self.cpu_cycles);
+            w.write_u64(self.cpu_instructions);
+            w.write_u64(self.system_ticks);
+            w.write_bool(self.turbo_bit);
+            w.write_bool(self.turbo_button);
+        });
+        w.write_section(b"CPU ", |w| self.cpu.save_state(w));
+        self.cpu.bus().save_state(&mut w)?;
+        Ok(w.into_bytes())
+    }
+
+    /// Restore a state captured by save_state(). If the state cannot be fully restored, the
+    /// machine is returned to the state it was in before the call.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut r = StateReader::new(data);
+        let machine = savestate::read_header(&mut r)?;
+        if machine != self.state_description() {
+            return Err(SaveStateError::MachineMismatch(machine));
+        }
+
+        let backup = self.save_state()?;
+        if let Err(e) = self.load_state_sections(&mut r) {
+            let mut r = StateReader::new(&backup);
+            let rollback = savestate::read_header(&mut r).and_then(|_| self.load_state_sections(&mut r));
+            if let Err(rollback_err) = rollback {
+                log::error!("Failed to roll back machine state after failed load: {}", rollback_err);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn load_state_sections(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.read_section(b"MACH", |r| {
+            self.cpu_cycles = r.read_u64()?;
+            self.cpu_instructions = r.read_u64()?;
+            self.system_ticks = r.read_u64()?;
+            self.turbo_bit = r.read_bool()?;
+            self.turbo_button = r.read_bool()?;
+            Ok(())
+        })?;
+        r.read_section(b"CPU ", |r| self.cpu.load_state(r))?;
+        self.cpu.bus_mut().load_state(r)?;
+        if !r.is_empty() {
+            return Err(SaveStateError::InvalidValue("trailing data"));
+        }
+
+        self.next_cpu_factor = match self.turbo_active() {
+            true => self.machine_desc.cpu_turbo_factor,
+            false => self.machine_desc.cpu_factor,
+        };
+        self.kb_buf.clear();
+        Ok(())
+    }
+
+    /// Capture the state of the RTC, if the machine has one. The RTC's time and CMOS RAM are
+    /// battery backed and survive a reset, so they are not otherwise reproducible from a reset.
+    pub fn rtc_state(&self) -> Option<Vec<u8>> {
+        let rtc = self.cpu.bus().rtc().as_ref()?;
+        let mut w = StateWriter::new();
+        rtc.save_state(&mut w);
+        Some(w.into_bytes())
+    }
+
+    /// Restore the state of the RTC captured by rtc_state().
+    pub fn load_rtc_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        match self.cpu.bus_mut().rtc_mut() {
+            Some(rtc) => rtc.load_state(&mut StateReader::new(data)),
+            None => Err(SaveStateError::MachineMismatch("RTC".to_string())),
+        }
+    }
+
+    /// Return the motor state of each floppy drive, or an empty vector if there is no floppy
+    /// controller.
+    pub fn floppy_activity(&self) -> Vec<bool> {
+        self.cpu
+            .bus()
+            .fdc()
+            .as_ref()
+            .map(|fdc| fdc.drive_motors())
+            .unwrap_or_default()
+    }
+
     pub fn fdc(&mut self) -> &mut Option<FloppyController> {
         self.cpu.bus_mut().fdc_mut()
     }
@@ -1001,8 +1250,122 @@ impl Machine {
         self.cpu.bus_mut().hdc_mut()
     }
 
+    pub fn xtide(&mut self) -> &mut Option<XtIdeController> {
+        self.cpu.bus_mut().xtide_mut()
+    }
+
+    pub fn st01(&mut self) -> &mut Option<SeagateSt01> {
+        self.cpu.bus_mut().st01_mut()
+    }
+
+    /// Attach a disk image to the specified drive of the installed hard disk controller.
+    pub fn mount_vhd(&mut self, drive: usize, vhd: VirtualHardDisk) -> Result<(), ControllerError> {
+        if let Some(hdc) = self.hdc() {
+            hdc.set_vhd(drive, vhd)
+        }
+        else if let Some(xtide) = self.xtide() {
+            xtide.set_vhd(drive, vhd)
+        }
+        else if let Some(st01) = self.st01() {
+            st01.set_vhd(drive, vhd)
+        }
+        else {
+            Err(ControllerError::NoController)
+        }
+    }
+
+    /// Return the drive formats supported by the installed hard disk controller, if any.
+    pub fn hdc_supported_formats(&mut self) -> Option<Vec<HardDiskFormat>> {
+        if let Some(hdc) = self.hdc() {
+            Some(hdc.get_supported_formats())
+        }
+        else if let Some(xtide) = self.xtide() {
+            Some(xtide.get_supported_formats())
+        }
+        else {
+            self.st01().as_ref().map(|st01| st01.get_supported_formats())
+        }
+    }
+
+    /// Return true if the machine has a CD-ROM drive.
+    pub fn cdrom_present(&mut self) -> bool {
+        self.xtide().as_ref().map(|xtide| xtide.cdrom().is_some()).unwrap_or(false)
+    }
+
+    /// Insert the CD image at the specified path into the CD-ROM drive.
+    pub fn load_cd_image(&mut self, path: &Path) -> Result<(), Error> {
+        match self.xtide().as_mut().and_then(|xtide| xtide.cdrom_mut()) {
+            Some(cdrom) => cdrom.load_image(path),
+            None => Err(anyhow!("No CD-ROM drive present.")),
+        }
+    }
+
+    /// Remove the disc from the CD-ROM drive, returning the path of the ejected image.
+    pub fn eject_cd_image(&mut self) -> Option<PathBuf> {
+        self.xtide()
+            .as_mut()
+            .and_then(|xtide| xtide.cdrom_mut())
+            .and_then(|cdrom| cdrom.eject())
+            .map(|image| image.path().to_path_buf())
+    }
+
+    /// Returns true once after the guest has ejected the disc from the CD-ROM drive.
+    pub fn cd_ejected_by_guest(&mut self) -> bool {
+        self.xtide()
+            .as_mut()
+            .and_then(|xtide| xtide.cdrom_mut())
+            .map(|cdrom| cdrom.take_eject_request())
+            .unwrap_or(false)
+    }
+
+    /// Begin capturing the output of the printer attached to the parallel port to the specified file.
+    pub fn start_printer_capture(&mut self, path: &Path) -> Result<(), Error> {
+        match self.cpu.bus_mut().parallel_mut().as_mut().and_then(|lpt| lpt.printer_mut()) {
+            Some(printer) => printer.start_capture(path),
+            None => Err(anyhow!("No printer attached.")),
+        }
+    }
+
+    /// Stop capturing printer output, returning the path of the capture file and the number of bytes written.
+    pub fn stop_printer_capture(&mut self) -> Option<(PathBuf, usize)> {
+        self.cpu
+            .bus_mut()
+            .parallel_mut()
+            .as_mut()
+            .and_then(|lpt| lpt.printer_mut())
+            .and_then(|printer| printer.stop_capture())
+    }
+
+    /// Begin capturing all frames sent and received by the network card to the specified pcap file.
+    pub fn start_network_capture(&mut self, path: &Path) -> Result<(), Error> {
+        match self.cpu.bus_mut().nic_mut() {
+            Some(nic) => nic.tap_mut().start_capture(path),
+            None => Err(anyhow!("No network card present.")),
+        }
+    }
+
+    /// Stop capturing network traffic, returning the path of the capture file and the number of frames written.
+    pub fn stop_network_capture(&mut self) -> Option<(PathBuf, usize)> {
+        self.cpu.bus_mut().nic_mut().as_mut().and_then(|nic| nic.tap_mut().stop_capture())
+    }
+
+    /// Replay the frames in the specified pcap file into the guest. Frames the guest itself sent are skipped.
+    pub fn replay_network_capture(&mut self, path: &Path) -> Result<usize, Error> {
+        match self.cpu.bus_mut().nic_mut() {
+            Some(nic) => {
+                let mac = nic.mac();
+                nic.tap_mut().start_replay(path, Some(mac))
+            }
+            None => Err(anyhow!("No network card present.")),
+        }
+    }
+
     pub fn cart_slot(&mut self) -> &mut Option<CartridgeSlot> { self.cpu.bus_mut().cart_slot_mut() }
 
+    pub fn cassette(&mut self) -> &mut Option<Cassette> {
+        self.cpu.bus_mut().cassette_mut()
+    }
+
     pub fn cpu_cycles(&self) -> u64 {
         self.cpu_cycles
     }
@@ -1070,6 +1433,20 @@ impl Machine {
         self.cpu.bus_mut().ppi_mut().as_mut().map(|ppi| ppi.get_display_state(true))
     }
 
+    pub fn ppi_dip_switch_state(&mut self) -> Option<PpiDipSwitchState> {
+        self.cpu.bus_mut().ppi_mut().as_ref().and_then(|ppi| ppi.dip_switch_state())
+    }
+
+    pub fn set_ppi_dip_switches(&mut self, sw1: u8, sw2: Option<u8>) {
+        if let Some(ppi) = self.cpu.bus_mut().ppi_mut() {
+            ppi.set_dip_switches(sw1, sw2);
+        }
+    }
+
+    pub fn post_card_state(&mut self) -> Option<PostCardState> {
+        self.cpu.bus_mut().post_card_mut().as_ref().map(|post_card| post_card.get_state())
+    }
+
     pub fn set_nmi(&mut self, state: bool) {
         self.cpu.set_nmi(state);
     }
@@ -1103,34 +1480,143 @@ impl Machine {
             .map(|video_card| video_card.get_videocard_string_state())
     }
 
+    /// Return the register writes made during the last complete frame by the primary video card,
+    /// if register write logging is enabled.
+    pub fn videocard_register_log(&mut self) -> Option<VideoRegisterLog> {
+        self.cpu
+            .bus_mut()
+            .primary_video_mut()
+            .and_then(|video_card| video_card.get_register_log().cloned())
+    }
+
     pub fn get_error_str(&self) -> &Option<String> {
         &self.error_str
     }
 
+    /// Return the state of the keyboard LEDs, if the keyboard has any. Only keyboards attached
+    /// to an 8042 keyboard controller have LEDs under host control.
+    /// Bit 0 is Scroll Lock, bit 1 is Num Lock and bit 2 is Caps Lock.
+    pub fn keyboard_leds(&mut self) -> Option<u8> {
+        let bus = self.cpu.bus_mut();
+        if bus.kbc_mut().is_none() {
+            return None;
+        }
+        bus.keyboard_mut().map(|keyboard| keyboard.leds())
+    }
+
+    /// Start or stop logging the inputs sent to the machine. Stopping discards any inputs not yet
+    /// taken with take_input_log().
+    pub fn set_input_logging(&mut self, state: bool) {
+        self.input_log = state.then(Vec::new);
+        self.input_log_game_port = self.game_port_input();
+    }
+
+    /// Take the inputs sent to the machine since the last call. Changes to the game port's
+    /// buttons and sticks are logged here, as the host sets them on the game port directly.
+    pub fn take_input_log(&mut self) -> Vec<MachineInput> {
+        let game_port = self.game_port_input();
+        match &mut self.input_log {
+            Some(log) => {
+                if game_port != self.input_log_game_port {
+                    if let Some(input) = game_port {
+                        log.push(input);
+                    }
+                    self.input_log_game_port = game_port;
+                }
+                std::mem::take(log)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Ignore inputs from the host, such as while a recorded session is replayed. Inputs sent
+    /// with apply_input() are not affected.
+    pub fn lock_input(&mut self, state: bool) {
+        self.input_locked = state;
+    }
+
+    fn game_port_input(&mut self) -> Option<MachineInput> {
+        self.cpu.bus_mut().game_port_mut().as_ref().map(|game_port| {
+            let state = game_port.get_state();
+            MachineInput::GamePort {
+                buttons: state.buttons,
+                sticks: state.sticks,
+            }
+        })
+    }
+
+    fn host_input(&mut self, input: MachineInput) {
+        if self.input_locked {
+            return;
+        }
+        if let Some(log) = &mut self.input_log {
+            log.push(input);
+        }
+        self.apply_input(&input);
+    }
+
+    /// Send an input to the machine, bypassing the input lock and log.
+    pub fn apply_input(&mut self, input: &MachineInput) {
+        match *input {
+            MachineInput::KeyPress(keycode, modifiers) => {
+                self.kb_buf.push_back(KeybufferEntry {
+                    keycode,
+                    pressed: true,
+                    modifiers,
+                    translate: true,
+                });
+            }
+            MachineInput::KeyRelease(keycode) => {
+                // HO Bit set converts a scancode into its 'release' code
+                self.kb_buf.push_back(KeybufferEntry {
+                    keycode,
+                    pressed: false,
+                    modifiers: KeyboardModifiers::default(),
+                    translate: true,
+                });
+            }
+            MachineInput::CtrlAltDel => self.apply_ctrl_alt_del(),
+            MachineInput::Mouse {
+                l_button,
+                r_button,
+                m_button,
+                delta_x,
+                delta_y,
+            } => {
+                self.cpu
+                    .bus_mut()
+                    .mouse_update(l_button, r_button, m_button, delta_x, delta_y);
+            }
+            MachineInput::GamePort { buttons, sticks } => {
+                if let Some(game_port) = self.cpu.bus_mut().game_port_mut() {
+                    game_port.set_inputs(buttons, sticks);
+                }
+                // Don't log a replayed game port change as a new input.
+                if self.input_log.is_some() {
+                    self.input_log_game_port = Some(*input);
+                }
+            }
+            MachineInput::Turbo(state) => self.apply_turbo_mode(state),
+        }
+    }
+
     /// Enter a keypress keycode into the emulator keyboard buffer.
     pub fn key_press(&mut self, keycode: MartyKey, modifiers: KeyboardModifiers) {
-        self.kb_buf.push_back(KeybufferEntry {
-            keycode,
-            pressed: true,
-            modifiers,
-            translate: true,
-        });
+        self.host_input(MachineInput::KeyPress(keycode, modifiers));
     }
 
     /// Enter a key release keycode into the emulator keyboard buffer.
     pub fn key_release(&mut self, keycode: MartyKey) {
-        // HO Bit set converts a scancode into its 'release' code
-        self.kb_buf.push_back(KeybufferEntry {
-            keycode,
-            pressed: false,
-            modifiers: KeyboardModifiers::default(),
-            translate: true,
-        });
+        self.host_input(MachineInput::KeyRelease(keycode));
     }
 
-    #[rustfmt::skip]
     /// Simulate the user pressing control-alt-delete.
     pub fn emit_ctrl_alt_del(&mut self) {
+        self.host_input(MachineInput::CtrlAltDel);
+    }
+
+    #[rustfmt::skip]
+    fn apply_ctrl_alt_del(&mut self) {
         let reboot_keycodes = [
             MartyKey::ControlLeft,
             MartyKey::AltLeft,
@@ -1162,6 +1648,28 @@ impl Machine {
         self.cpu.bus_mut().mouse_mut()
     }
 
+    pub fn have_mouse(&self) -> bool {
+        self.cpu.bus().have_mouse()
+    }
+
+    /// Send a host mouse update to all installed mouse devices.
+    pub fn mouse_update(
+        &mut self,
+        l_button_pressed: bool,
+        r_button_pressed: bool,
+        m_button_pressed: bool,
+        delta_x: f64,
+        delta_y: f64,
+    ) {
+        self.host_input(MachineInput::Mouse {
+            l_button: l_button_pressed,
+            r_button: r_button_pressed,
+            m_button: m_button_pressed,
+            delta_x,
+            delta_y,
+        });
+    }
+
     #[cfg(feature = "serial")]
     pub fn bridge_serial_port(&mut self, port_num: usize, host_port_name: String, host_port_id: usize) -> Result<(), Error> {
         if let Some(spc) = self.cpu.bus_mut().serial_mut() {
@@ -1176,7 +1684,22 @@ impl Machine {
         Ok(())
     }
 
+    /// Set the list of active breakpoints. Video register breakpoints are passed on to the
+    /// primary video card; the rest are handled by the CPU.
     pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
+        let video_bps: Vec<VideoBreakpoint> = bp_list
+            .iter()
+            .filter_map(|bp| match bp {
+                BreakPointType::VideoRegister(video_bp) => Some(*video_bp),
+                _ => None,
+            })
+            .collect();
+
+        self.video_breakpoints = !video_bps.is_empty();
+        if let Some(video_card) = self.cpu.bus_mut().primary_video_mut() {
+            video_card.set_breakpoints(video_bps);
+        }
+
         self.cpu.set_breakpoints(bp_list)
     }
 
@@ -1204,9 +1727,11 @@ impl Machine {
             // Clear patch installation status
             //self.rom_manager.reset_patches();
         }
+        self.cpu.bus_mut().apply_memory_regions();
 
         // Reset all installed devices.
         self.cpu.bus_mut().reset_devices();
+        self.kb_buf.clear();
         self.events.push(MachineEvent::Reset);
     }
 
@@ -1418,6 +1943,7 @@ impl Machine {
             }
 
             let mut step_over_target = None;
+            let instr_ct = self.cpu.get_instruction_ct();
 
             match self.cpu.step(skip_breakpoint) {
                 Ok((step_result, step_cycles)) => match step_result {
@@ -1499,18 +2025,32 @@ impl Machine {
             self.cpu.set_intr(intr);
 
             // Give step_finish a mutable reference to a Disassembly struct if we are recording the
-            // disassembly listing.
-            let listing_entry = if self.options.record_listing {
+            // disassembly listing, control flow graph or analysis export.
+            let listing_entry = if self.options.record_listing
+                || self.options.record_control_flow
+                || self.options.record_analysis
+            {
                 Some(&mut self.disassembly)
             } else {
                 None
             };
 
             // Finish instruction after running devices (RNI)
-            if let Err(err) = self.cpu.step_finish(listing_entry) {
-                self.error = true;
-                self.error_str = Some(format!("{}", err));
-                log::error!("CPU Error: {}\n{}", err, self.cpu.dump_instruction_history_string());
+            match self.cpu.step_finish(listing_entry) {
+                Ok(finish_result) => {
+                    if self.options.record_control_flow || self.options.record_analysis {
+                        let executed = self.cpu.get_instruction_ct() != instr_ct;
+                        self.record_control_flow(&finish_result, executed);
+                        if executed && self.options.record_analysis {
+                            self.record_analysis();
+                        }
+                    }
+                }
+                Err(err) => {
+                    self.error = true;
+                    self.error_str = Some(format!("{}", err));
+                    log::error!("CPU Error: {}\n{}", err, self.cpu.dump_instruction_history_string());
+                }
             }
 
             if self.options.record_listing {
@@ -1541,6 +2081,19 @@ impl Machine {
                     }
                 }
             }
+
+            // Stop after the instruction that wrote to a video register matching a video breakpoint.
+            if self.video_breakpoints {
+                let hit = self
+                    .cpu
+                    .bus_mut()
+                    .primary_video_mut()
+                    .map_or(false, |video_card| video_card.take_breakpoint_hit());
+                if hit {
+                    exec_control.state = ExecutionState::BreakpointHit;
+                    break;
+                }
+            }
         }
 
         //log::debug!("cycles_elapsed: {}", cycles_elapsed);
@@ -1611,6 +2164,12 @@ impl Machine {
                     // Stop refresh
                     self.cpu.set_option(CpuOption::ScheduleDramRefresh(false, 0, 0, false));
                 }
+                DeviceEvent::CpuReset => {
+                    // The keyboard controller pulsed the CPU reset line. Only the CPU is reset; memory and device
+                    // state are preserved, which is how AT software returns from protected mode.
+                    log::debug!("CPU reset requested by device.");
+                    self.cpu.reset();
+                }
                 _ => {}
             }
         }
@@ -1622,7 +2181,7 @@ impl Machine {
         }*/
 
         // Query interrupt line after device processing.
-        let intr = self.cpu.bus_mut().pic_mut().as_ref().unwrap().query_interrupt_line();
+        let intr = self.cpu.bus().query_interrupt_line();
 
         self.system_ticks += sys_ticks as u64;
         (intr, sys_ticks)
@@ -1692,6 +2251,15 @@ impl Machine {
             _ => {}
         }
 
+        // Deliver any notifications posted to the bus.
+        device_events.extend(
+            self.cpu
+                .bus_mut()
+                .take_notifications()
+                .into_iter()
+                .map(DeviceEvent::Notification),
+        );
+
         device_events
     }
 
@@ -1821,4 +2389,139 @@ impl Machine {
             _ = writer.write_all(line.as_bytes());
         }
     }
+
+    /// Record any control flow edge produced by the last executed instruction. `executed` should be false
+    /// if the CPU did not complete an instruction (ie, it is halted or in the middle of a REP).
+    fn record_control_flow(&mut self, finish_result: &StepResult, executed: bool) {
+        let cs = self.cpu.get_register16(Register16::CS);
+        let ip = self.cpu.get_ip();
+        let from = ControlFlowNode::new(self.disassembly.cs, self.disassembly.ip);
+
+        // If step_finish() entered an interrupt, the current CS:IP is the entry point of the ISR.
+        if let StepResult::Call(_) = finish_result {
+            let vector = self.find_vector_for_handler(cs, ip);
+            self.record_interrupt_edge(from, ControlFlowNode::new(cs, ip), vector, true);
+            return;
+        }
+
+        if !executed {
+            return;
+        }
+
+        let next_ip = self.disassembly.ip.wrapping_add(self.disassembly.i.size as u16);
+        let taken = (cs != self.disassembly.cs) || (ip != next_ip);
+        let to = ControlFlowNode::new(cs, ip);
+
+        match ControlFlowEdgeKind::from_mnemonic(self.disassembly.i.mnemonic, taken) {
+            Some(ControlFlowEdgeKind::Interrupt) => {
+                let vector = match (self.disassembly.i.mnemonic, self.disassembly.i.operand1_type) {
+                    (Mnemonic::INT, OperandType::Immediate8(v)) => Some(v),
+                    (Mnemonic::INT3, _) => Some(3),
+                    (Mnemonic::INTO, _) => Some(4),
+                    _ => None,
+                };
+                self.record_interrupt_edge(from, to, vector, false);
+            }
+            Some(kind) => {
+                if self.options.record_control_flow {
+                    self.control_flow.record(from, to, kind);
+                }
+                if self.options.record_analysis {
+                    self.analysis.record_edge(from, to, kind);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn record_interrupt_edge(&mut self, from: ControlFlowNode, to: ControlFlowNode, vector: Option<u8>, hw: bool) {
+        if self.options.record_control_flow {
+            self.control_flow.record_interrupt(from, to, vector, hw);
+        }
+        if self.options.record_analysis {
+            self.analysis.record_interrupt(from, to, vector, hw);
+        }
+    }
+
+    /// Record the code and data boundaries of the last executed instruction for analysis export.
+    fn record_analysis(&mut self) {
+        let data_seg = match self.disassembly.i.segment_override {
+            Some(Segment::ES) => self.cpu.get_register16(Register16::ES),
+            Some(Segment::CS) => self.cpu.get_register16(Register16::CS),
+            Some(Segment::SS) => self.cpu.get_register16(Register16::SS),
+            _ => self.cpu.get_register16(Register16::DS),
+        };
+        self.analysis
+            .record_instruction(self.disassembly.cs, self.disassembly.ip, &self.disassembly.i, data_seg);
+    }
+
+    /// Scan the interrupt vector table for a vector pointing to the specified handler address.
+    fn find_vector_for_handler(&self, cs: u16, ip: u16) -> Option<u8> {
+        let bus = self.cpu.bus();
+        (0..256usize).find_map(|v| {
+            let ivt = bus.peek_range(v * 4, 4).ok()?;
+            let v_ip = u16::from_le_bytes([ivt[0], ivt[1]]);
+            let v_cs = u16::from_le_bytes([ivt[2], ivt[3]]);
+            (v_cs == cs && v_ip == ip).then_some(v as u8)
+        })
+    }
+
+    pub fn control_flow(&self) -> &ControlFlowRecorder {
+        &self.control_flow
+    }
+
+    pub fn dump_control_flow(&mut self) {
+        // Resolve filename option
+        let filename = match &self.control_flow_file {
+            Some(f) => f,
+            None => {
+                log::error!("No control flow graph file specified.");
+                return;
+            }
+        };
+
+        let format = ControlFlowFormat::from_path(filename);
+        match self.control_flow.export(filename, format) {
+            Ok(_) => {
+                log::debug!(
+                    "Wrote control flow graph ({} edges) to: {}",
+                    self.control_flow.edge_ct(),
+                    filename.display()
+                );
+            }
+            Err(e) => {
+                log::error!("Failed to write control flow graph '{}': {}", filename.display(), e);
+            }
+        }
+    }
+
+    pub fn analysis(&self) -> &AnalysisCollector {
+        &self.analysis
+    }
+
+    pub fn dump_analysis(&mut self) {
+        // Resolve filename option
+        let filename = match &self.analysis_file {
+            Some(f) => f,
+            None => {
+                log::error!("No analysis export file specified.");
+                return;
+            }
+        };
+
+        let format = AnalysisFormat::from_path(filename);
+        match self.analysis.export(filename, format) {
+            Ok(_) => {
+                log::debug!(
+                    "Wrote analysis export ({} instructions, {} data references) to: {}",
+                    self.analysis.code_ct(),
+                    self.analysis.data_ct(),
+                    filename.display()
+                );
+            }
+            Err(e) => {
+                log::error!("Failed to write analysis export '{}': {}", filename.display(), e);
+            }
+        }
+    }
 }
@@ -31,12 +31,19 @@
 */
 
 use crate::machine_types::{
+    BusMouseType,
     EmsType,
     FdcType,
     FloppyDriveType,
     HardDiskControllerType,
     HardDriveFormat,
     MachineType,
+    MemoryRegionType,
+    NetworkBackendType,
+    NetworkCardType,
+    ParallelDeviceType,
+    RtcType,
+    SerialBridgeType,
     SerialControllerType,
     SerialMouseType,
     SoundType,
@@ -49,11 +56,19 @@ use crate::{
     bus::ClockFactor,
     cpu_common::CpuType,
     device_traits::videocard::VideoType,
-    devices::{keyboard::KeyboardType, pit::PitType},
+    devices::{
+        game_port::ControllerLayout,
+        keyboard::KeyboardType,
+        pit::PitType,
+        speaker_filter::{SpeakerFilterParams, SpeakerFilterPreset},
+    },
     tracelogger::TraceLogger,
 };
 
-use crate::{device_traits::videocard::VideoCardSubType, devices::a0::A0Type};
+use crate::{
+    device_traits::videocard::{VideoCardSubType, VideoStandard},
+    devices::a0::A0Type,
+};
 use serde_derive::Deserialize;
 
 // Clock derivation from reenigne
@@ -91,20 +106,20 @@ pub struct DeviceSpec {
     hotplug: bool,          // Whether device can be added/removed while machine is running.
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize)]
 pub enum KbControllerType {
     Ppi,
     PCJr,
     At,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize)]
 pub enum PicType {
     Single,
     Chained,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize)]
 pub enum DmaType {
     Single,
     Chained,
@@ -126,6 +141,19 @@ pub struct CpuConfig {
 #[derive(Clone, Debug, Deserialize)]
 pub struct MemoryConfig {
     pub conventional: ConventionalMemoryConfig,
+    #[serde(default, rename = "region")]
+    pub regions: Vec<MemoryRegionConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MemoryRegionConfig {
+    #[serde(rename = "type")]
+    pub region_type: MemoryRegionType,
+    pub address: u32,
+    pub size: u32,
+    /// Address of the range to copy for a RomMirror region.
+    pub source: Option<u32>,
+    pub wait_states: Option<u32>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -133,7 +161,9 @@ pub struct EmsMemoryConfig {
     pub ems_type: EmsType,
     pub window: u32,
     pub io_base: u16,
-    pub size: usize,
+    pub size: Option<usize>,
+    /// Lowest segment of conventional memory that can be replaced by EEMS windows.
+    pub backfill: Option<u32>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -160,9 +190,112 @@ pub struct SerialMouseConfig {
     pub port: u32,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct BusMouseConfig {
+    #[serde(rename = "type")]
+    pub mouse_type: BusMouseType,
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+    pub scale: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Ps2MouseConfig {
+    pub scale: Option<f64>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct GamePortConfig {
     pub io_base: u16,
+    pub layout: Option<ControllerLayout>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RtcConfig {
+    #[serde(rename = "type")]
+    pub rtc_type: RtcType,
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+    pub cmos_file: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PostCardConfig {
+    pub io_base: Option<u16>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ParallelPortConfig {
+    /// The port base of a parallel card. Ignored if the machine has an onboard parallel port.
+    pub io_base: Option<u16>,
+    pub irq: Option<u16>,
+    /// The device attached to the port.
+    #[serde(default)]
+    pub device: ParallelDeviceType,
+    /// Capture printed output to this file in the 'print' resource directory.
+    pub capture: Option<String>,
+    /// Pipe each print job to this host command, such as "lpr".
+    pub command: Option<String>,
+    /// Seconds without data after which a print job is considered complete.
+    pub job_timeout: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SpeakerFilterConfig {
+    /// The filter preset to start from.
+    #[serde(default)]
+    pub preset: SpeakerFilterPreset,
+    /// Overrides the preset's high-pass cutoff frequency, in Hz.
+    pub highpass: Option<f32>,
+    /// Overrides the preset's low-pass cutoff frequency, in Hz.
+    pub lowpass: Option<f32>,
+}
+
+impl SpeakerFilterConfig {
+    pub fn params(&self) -> SpeakerFilterParams {
+        let mut params = self.preset.params();
+        if self.highpass.is_some() {
+            params.highpass = self.highpass;
+        }
+        if self.lowpass.is_some() {
+            params.lowpass = self.lowpass;
+        }
+        params
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CassetteConfig {
+    /// A WAV or CAS tape image to insert into the deck at startup.
+    pub image: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModemConfig {
+    /// The serial port the modem is attached to (0 = COM1).
+    pub port: u32,
+    /// Listen on this TCP port for incoming calls, which ring the guest.
+    pub listen_port: Option<u16>,
+    /// Map dialed numbers to "host[:port]" addresses.
+    pub phonebook: Option<HashMap<String, String>>,
+    /// Negotiate telnet options with the remote end. Defaults to true.
+    pub telnet: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NetworkCardConfig {
+    #[serde(rename = "type")]
+    pub nic_type: NetworkCardType,
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+    /// The station address, in the form "00:00:1B:12:34:56".
+    pub mac: Option<String>,
+    #[serde(default)]
+    pub backend: NetworkBackendType,
+    /// Capture all network traffic to this pcap file in the 'capture' resource directory.
+    pub capture: Option<String>,
+    /// Replay the frames of this pcap file in the 'capture' resource directory into the guest.
+    pub replay: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -172,6 +305,17 @@ pub struct VideoCardConfig {
     #[serde(rename = "subtype")]
     pub video_subtype: Option<VideoCardSubType>,
     pub dip_switch:    Option<u8>,
+    /// Designate this card as the primary display when multiple cards are installed. The
+    /// primary card is the one selected by the system's DIP switches at boot.
+    #[serde(default)]
+    pub primary:       bool,
+    /// The composite video standard of the card, either Ntsc or Pal. PAL cards run at 50Hz.
+    #[serde(default)]
+    pub standard:      VideoStandard,
+    /// The name of a post-processing shader used by displays attached to this card, unless the
+    /// display's scaler preset specifies its own.
+    #[serde(default)]
+    pub crt_shader:    Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -179,6 +323,14 @@ pub struct SoundDeviceConfig {
     #[serde(rename = "type")]
     pub sound_type: SoundType,
     pub io_base:    u16,
+    pub irq:        Option<u8>,
+    pub dma:        Option<u8>,
+    /// For MIDI interfaces, the host MIDI output port to connect to. The first port whose name
+    /// contains this string is used. If not specified, the first available port is used.
+    pub midi_output: Option<String>,
+    /// For MIDI interfaces, the host MIDI input port to receive from, matched the same way. MIDI input
+    /// is disabled if not specified.
+    pub midi_input: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -187,6 +339,19 @@ pub struct SerialPortConfig {
     pub irq: u32,
     #[serde(default = "_default_true")]
     pub out2_suppresses_int: bool,
+    pub bridge: Option<SerialBridgeConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SerialBridgeConfig {
+    #[serde(rename = "type")]
+    pub bridge_type: SerialBridgeType,
+    /// The address to listen on or connect to, or the symlink to create for a pseudo-terminal.
+    pub address: Option<String>,
+    /// The interval between reconnection attempts of a TCP client bridge.
+    pub reconnect_ms: Option<u32>,
+    /// For a null-modem bridge, listen on the address instead of connecting to it.
+    pub listen: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -214,7 +379,12 @@ pub struct FloppyDriveConfig {
 pub struct HardDriveControllerConfig {
     #[serde(rename = "type")]
     pub hdc_type: HardDiskControllerType,
+    pub io_base:  Option<u16>,
     pub drive:    Option<Vec<HardDriveConfig>>,
+    /// Attach an ATAPI CD-ROM drive as the specified device (0 or 1). Only supported by the XT-IDE.
+    pub cdrom:    Option<usize>,
+    /// Segment of the memory window of a memory-mapped controller such as the ST-01.
+    pub window:   Option<u32>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -244,17 +414,30 @@ pub struct MediaConfig {
 #[derive(Clone, Debug)]
 pub struct MachineConfiguration {
     pub speaker: bool,
+    pub speaker_filter: Option<SpeakerFilterConfig>,
     pub ppi_turbo: Option<bool>,
     pub machine_type: MachineType,
+    pub pit_type: Option<PitType>, // Overrides the machine type's PIT model if present.
+    pub pic_type: Option<PicType>, // Overrides the machine type's PIC configuration if present.
+    pub dma_type: Option<DmaType>, // Overrides the machine type's DMA configuration if present.
+    pub kb_controller: Option<KbControllerType>, // Overrides the machine type's keyboard controller if present.
     pub cpu: Option<CpuConfig>,
     pub memory: MemoryConfig,
     pub ems: Option<EmsMemoryConfig>,
     pub keyboard: Option<KeyboardConfig>,
     pub serial_mouse: Option<SerialMouseConfig>,
+    pub modem: Option<ModemConfig>,
+    pub cassette: Option<CassetteConfig>,
+    pub bus_mouse: Option<BusMouseConfig>,
+    pub ps2_mouse: Option<Ps2MouseConfig>,
     pub video: Vec<VideoCardConfig>,
     pub sound: Vec<SoundDeviceConfig>,
     pub serial: Vec<SerialControllerConfig>,
+    pub parallel: Option<ParallelPortConfig>,
     pub game_port: Option<GamePortConfig>,
+    pub rtc: Option<RtcConfig>,
+    pub post_card: Option<PostCardConfig>,
+    pub network: Option<NetworkCardConfig>,
     pub fdc: Option<FloppyControllerConfig>,
     pub hdc: Option<HardDriveControllerConfig>,
     pub media: Option<MediaConfig>,
@@ -67,6 +67,9 @@ impl FromStr for MachineType {
 #[derive(Copy, Clone, Debug, Deserialize, Hash, Eq, PartialEq)]
 pub enum SoundType {
     AdLib,
+    SoundBlaster,
+    Sn76489,
+    Mpu401,
 }
 
 impl FromStr for SoundType {
@@ -77,6 +80,9 @@ impl FromStr for SoundType {
     {
         match s.to_lowercase().as_str() {
             "adlib" => Ok(SoundType::AdLib),
+            "soundblaster" => Ok(SoundType::SoundBlaster),
+            "sn76489" => Ok(SoundType::Sn76489),
+            "mpu401" => Ok(SoundType::Mpu401),
             _ => Err("Bad value for SoundType".to_string()),
         }
     }
@@ -217,6 +223,10 @@ pub enum FdcType {
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum HardDiskControllerType {
     IbmXebec,
+    /// An XT-IDE (8-bit ATA) controller, for use with the XUB option ROM.
+    XtIde,
+    /// A Seagate ST-01 memory-mapped SCSI host adapter.
+    SeagateSt01,
 }
 
 impl FromStr for HardDiskControllerType {
@@ -227,6 +237,8 @@ impl FromStr for HardDiskControllerType {
     {
         match s.to_lowercase().as_str() {
             "ibmxebec" => Ok(HardDiskControllerType::IbmXebec),
+            "xtide" => Ok(HardDiskControllerType::XtIde),
+            "seagatest01" => Ok(HardDiskControllerType::SeagateSt01),
             _ => Err("Bad value for HardDiskControllerType".to_string()),
         }
     }
@@ -246,9 +258,75 @@ pub enum SerialControllerType {
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum SerialMouseType {
     Microsoft,
+    Logitech,
+    MouseSystems,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum BusMouseType {
+    InPort,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum MemoryRegionType {
+    /// Read/write RAM, such as an upper memory block.
+    Ram,
+    /// A read-only copy of another range of memory, usually a ROM.
+    RomMirror,
+    /// An unpopulated range. Reads return the open bus byte and writes are ignored.
+    Hole,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum EmsType {
     LoTech2MB,
+    /// An EEMS / AboveBoard-style board that can also map pages below the page frame.
+    Eems,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum NetworkCardType {
+    Ne1000,
+    Ne2000,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum NetworkBackendType {
+    /// User-mode networking. The guest is given a private network with NAT to the host's connection.
+    #[default]
+    Slirp,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum ParallelDeviceType {
+    /// A printer, whose output can be captured or sent to a host command.
+    #[default]
+    Printer,
+    /// A Covox Speech Thing resistor ladder DAC.
+    Covox,
+    /// A Disney Sound Source, a DAC with a 16-byte FIFO played at 7kHz.
+    DisneySoundSource,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum SerialBridgeType {
+    /// Listen for a TCP connection on the configured address.
+    TcpServer,
+    /// Connect to a TCP server at the configured address, reconnecting if the connection is lost.
+    TcpClient,
+    /// Open a pseudo-terminal. The configured address, if any, is created as a symlink to it.
+    Pty,
+    /// Link to the serial port of another MartyPC instance over TCP, emulating a null-modem cable.
+    NullModem,
+    /// Pass through to the physical host serial port named by the address, such as COM1 or
+    /// /dev/ttyUSB0. Requires the 'serial' feature.
+    Host,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum RtcType {
+    /// An AT-style MC146818 at ports 70h-71h, on IRQ8.
+    At,
+    /// An XT clock card built around an MC146818, with index and data registers at a configurable base.
+    XtClockCard,
 }
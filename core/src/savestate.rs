@@ -0,0 +1,427 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    savestate.rs
+
+    Implements the machine save state format. A save state is a header
+    followed by a sequence of tagged sections, one for the CPU, the bus and
+    each installed device. Each component serializes itself with the
+    SaveState trait.
+
+    The contents of mounted media such as hard disk images are not part of a
+    save state. Devices record the identity of their media instead, and a
+    state can only be restored with the same media mounted.
+
+*/
+
+use std::{error::Error, fmt, fmt::Display};
+
+/// Magic bytes that begin every save state.
+pub const SAVESTATE_MAGIC: &[u8; 8] = b"MARTYSAV";
+/// Current save state format version. Increment whenever the layout of any section changes;
+/// states with a different version are rejected rather than misinterpreted.
+pub const SAVESTATE_VERSION: u32 = 1;
+
+/// Four-character tag identifying a section of a save state.
+pub type SectionTag = [u8; 4];
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    UnexpectedEnd,
+    BadMagic,
+    UnsupportedVersion(u32),
+    SectionMismatch(SectionTag, SectionTag),
+    SectionLength(SectionTag),
+    InvalidValue(&'static str),
+    MachineMismatch(String),
+    MediaMismatch(String),
+}
+impl Error for SaveStateError {}
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::UnexpectedEnd => write!(f, "Save state data ended unexpectedly."),
+            SaveStateError::BadMagic => write!(f, "Not a MartyPC save state."),
+            SaveStateError::UnsupportedVersion(v) => write!(
+                f,
+                "Save state format version {} is not supported (expected {}).",
+                v, SAVESTATE_VERSION
+            ),
+            SaveStateError::SectionMismatch(expected, found) => write!(
+                f,
+                "Expected save state section '{}', found '{}'.",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(found)
+            ),
+            SaveStateError::SectionLength(tag) => write!(
+                f,
+                "Save state section '{}' has an unexpected length.",
+                String::from_utf8_lossy(tag)
+            ),
+            SaveStateError::InvalidValue(what) => write!(f, "Invalid value in save state: {}", what),
+            SaveStateError::MachineMismatch(what) => {
+                write!(f, "Save state was taken on a different machine configuration: {}", what)
+            }
+            SaveStateError::MediaMismatch(what) => {
+                write!(f, "Save state was taken with different media mounted: {}", what)
+            }
+        }
+    }
+}
+
+/// Implemented by every component that contributes state to a save state.
+pub trait SaveState {
+    /// Serialize the component's state.
+    fn save_state(&self, w: &mut StateWriter);
+    /// Restore the component's state. Configuration that is not part of the state (such as the
+    /// device type or installed ROMs) is assumed to match the component that saved it.
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+/// Writes save state data in little-endian order.
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// usize values are always stored as 64 bits so that states are portable between hosts.
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a length-prefixed byte slice.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_option_u8(&mut self, value: Option<u8>) {
+        self.write_bool(value.is_some());
+        self.write_u8(value.unwrap_or(0));
+    }
+
+    pub fn write_option_str(&mut self, value: Option<&str>) {
+        self.write_bool(value.is_some());
+        self.write_str(value.unwrap_or_default());
+    }
+
+    /// Write a tagged section. The section length is recorded so that a reader can verify it
+    /// consumed exactly what was written.
+    pub fn write_section<F: FnOnce(&mut StateWriter)>(&mut self, tag: &SectionTag, f: F) {
+        self.buf.extend_from_slice(tag);
+        let len_pos = self.buf.len();
+        self.write_u32(0);
+        f(self);
+        let len = (self.buf.len() - len_pos - 4) as u32;
+        self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads save state data produced by a StateWriter.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() - self.pos < len {
+            return Err(SaveStateError::UnexpectedEnd);
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("bool")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, SaveStateError> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, SaveStateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Read a length-prefixed byte slice into a buffer of exactly the same length.
+    pub fn read_bytes_into(&mut self, dst: &mut [u8], what: &'static str) -> Result<(), SaveStateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dst.len() {
+            return Err(SaveStateError::InvalidValue(what));
+        }
+        dst.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn read_str(&mut self) -> Result<String, SaveStateError> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| SaveStateError::InvalidValue("string"))
+    }
+
+    pub fn read_option_u8(&mut self) -> Result<Option<u8>, SaveStateError> {
+        let some = self.read_bool()?;
+        let value = self.read_u8()?;
+        Ok(if some { Some(value) } else { None })
+    }
+
+    pub fn read_option_str(&mut self) -> Result<Option<String>, SaveStateError> {
+        let some = self.read_bool()?;
+        let value = self.read_str()?;
+        Ok(if some { Some(value) } else { None })
+    }
+
+    /// Read the identity of the media that was mounted when the state was saved, and check that
+    /// it matches the media mounted now. `what` describes the drive for the error message.
+    pub fn read_media(&mut self, mounted: Option<String>, what: &str) -> Result<(), SaveStateError> {
+        if self.read_option_str()? != mounted {
+            return Err(SaveStateError::MediaMismatch(what.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Read the next section, which must have the specified tag, and run `f` over its contents.
+    /// `f` must consume the entire section.
+    pub fn read_section<T, F>(&mut self, tag: &SectionTag, f: F) -> Result<T, SaveStateError>
+    where
+        F: FnOnce(&mut StateReader<'a>) -> Result<T, SaveStateError>,
+    {
+        let mut found = [0; 4];
+        found.copy_from_slice(self.take(4)?);
+        if &found != tag {
+            return Err(SaveStateError::SectionMismatch(*tag, found));
+        }
+        let len = self.read_u32()? as usize;
+        let mut section = StateReader::new(self.take(len)?);
+        let result = f(&mut section)?;
+        if !section.is_empty() {
+            return Err(SaveStateError::SectionLength(*tag));
+        }
+        Ok(result)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+/// Write the save state header.
+pub fn write_header(w: &mut StateWriter, machine: &str) {
+    w.buf.extend_from_slice(SAVESTATE_MAGIC);
+    w.write_u32(SAVESTATE_VERSION);
+    w.write_str(machine);
+}
+
+/// Read and validate the save state header, returning the machine description it was saved with.
+pub fn read_header(r: &mut StateReader) -> Result<String, SaveStateError> {
+    if r.take(SAVESTATE_MAGIC.len())? != SAVESTATE_MAGIC {
+        return Err(SaveStateError::BadMagic);
+    }
+    let version = r.read_u32()?;
+    if version != SAVESTATE_VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    r.read_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut w = StateWriter::new();
+        write_header(&mut w, "IbmPc");
+        w.write_section(b"TEST", |w| {
+            w.write_u8(0x12);
+            w.write_bool(true);
+            w.write_u16(0x3456);
+            w.write_u32(0x789A_BCDE);
+            w.write_u64(u64::MAX - 1);
+            w.write_usize(0x10000);
+            w.write_f64(1.5);
+            w.write_bytes(&[1, 2, 3]);
+            w.write_str("marty");
+            w.write_option_u8(Some(7));
+            w.write_option_u8(None);
+            w.write_option_str(Some("disk"));
+            w.write_option_str(None);
+        });
+        let data = w.into_bytes();
+
+        let mut r = StateReader::new(&data);
+        assert_eq!(read_header(&mut r).unwrap(), "IbmPc");
+        r.read_section(b"TEST", |r| {
+            assert_eq!(r.read_u8()?, 0x12);
+            assert!(r.read_bool()?);
+            assert_eq!(r.read_u16()?, 0x3456);
+            assert_eq!(r.read_u32()?, 0x789A_BCDE);
+            assert_eq!(r.read_u64()?, u64::MAX - 1);
+            assert_eq!(r.read_usize()?, 0x10000);
+            assert_eq!(r.read_f64()?, 1.5);
+            let mut bytes = [0; 3];
+            r.read_bytes_into(&mut bytes, "bytes")?;
+            assert_eq!(bytes, [1, 2, 3]);
+            assert_eq!(r.read_str()?, "marty");
+            assert_eq!(r.read_option_u8()?, Some(7));
+            assert_eq!(r.read_option_u8()?, None);
+            assert!(matches!(
+                r.read_media(Some("other".to_string()), "drive 0"),
+                Err(SaveStateError::MediaMismatch(_))
+            ));
+            r.read_media(None, "drive 1")?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn test_section_checks() {
+        let mut w = StateWriter::new();
+        w.write_section(b"AAAA", |w| w.write_u16(1));
+        let data = w.into_bytes();
+
+        // Wrong tag
+        let mut r = StateReader::new(&data);
+        assert!(matches!(
+            r.read_section(b"BBBB", |_| Ok(())),
+            Err(SaveStateError::SectionMismatch(..))
+        ));
+
+        // Section not fully consumed
+        let mut r = StateReader::new(&data);
+        assert!(matches!(
+            r.read_section(b"AAAA", |r| r.read_u8()),
+            Err(SaveStateError::SectionLength(..))
+        ));
+
+        // Reading past the end of the section
+        let mut r = StateReader::new(&data);
+        assert!(matches!(
+            r.read_section(b"AAAA", |r| r.read_u32()),
+            Err(SaveStateError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut r = StateReader::new(b"NOTMARTY");
+        assert!(matches!(read_header(&mut r), Err(SaveStateError::BadMagic)));
+
+        let mut w = StateWriter::new();
+        w.buf.extend_from_slice(SAVESTATE_MAGIC);
+        w.write_u32(SAVESTATE_VERSION + 1);
+        let data = w.into_bytes();
+        let mut r = StateReader::new(&data);
+        assert!(matches!(
+            read_header(&mut r),
+            Err(SaveStateError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -56,6 +56,12 @@ pub const VHD_FEATURE_RESERVED: u32 = 0x02;
 pub const VHD_CHECKSUM_OFFSET: usize = 64;
 pub const VHD_DISK_TYPE: u32 = 0x02;
 
+// Geometry reported for raw images, which have no footer to describe it.
+pub const RAW_HEADS: u32 = 16;
+pub const RAW_SECTORS: u32 = 63;
+pub const RAW_SMALL_HEADS: u32 = 4;
+pub const RAW_SMALL_SECTORS: u32 = 17;
+
 #[derive(Debug)]
 pub enum VirtualHardDiskError {
     FileExists,
@@ -94,6 +100,7 @@ pub struct VirtualHardDisk {
     footer:   VHDFileFooter,
 
     size: u64,
+    footer_len: u64,
     checksum: u32,
 
     pub max_cylinders: u32,
@@ -304,12 +311,18 @@ impl VirtualHardDisk {
         // Read in the entire footer
         vhd_file.read_exact(&mut trailer_buf)?;
 
+        // A file without a footer cookie is treated as a raw sector image.
+        if &trailer_buf[0..8] != "conectix".as_bytes() {
+            return VirtualHardDisk::from_raw_file(vhd_file);
+        }
+
         let footer = VHDFileFooter::parse_vhd_footer(&mut trailer_buf)?;
 
         Ok(VirtualHardDisk {
             vhd_file,
 
             size: metadata.len(),
+            footer_len: VHD_FOOTER_LEN as u64,
             checksum: 0,
 
             max_cylinders: footer.geometry.c as u32,
@@ -324,6 +337,75 @@ impl VirtualHardDisk {
         })
     }
 
+    /// Open a raw sector image with no VHD footer. Since a raw image carries no geometry, a geometry is
+    /// derived from the image size. Controllers that address sectors by LBA are not affected by it.
+    pub fn from_raw_file(vhd_file: File) -> Result<VirtualHardDisk, anyhow::Error> {
+        let metadata = vhd_file.metadata().context("Failed to read raw image metadata")?;
+        if metadata.len() == 0 || metadata.len() % VHD_SECTOR_SIZE as u64 != 0 {
+            bail!(VirtualHardDiskError::InvalidLength);
+        }
+
+        let total_sectors = (metadata.len() / VHD_SECTOR_SIZE as u64) as u32;
+        let (heads, sectors) = if total_sectors >= RAW_HEADS * RAW_SECTORS {
+            (RAW_HEADS, RAW_SECTORS)
+        }
+        else {
+            (RAW_SMALL_HEADS, RAW_SMALL_SECTORS)
+        };
+        let cylinders = std::cmp::max(1, std::cmp::min(total_sectors / (heads * sectors), u16::MAX as u32));
+
+        let footer = VHDFileFooter::new(cylinders as u16, heads as u8, sectors as u8, Uuid::nil());
+
+        log::debug!(
+            "Opened raw disk image of {} sectors, using geometry c:{} h:{} s:{}",
+            total_sectors,
+            cylinders,
+            heads,
+            sectors
+        );
+
+        Ok(VirtualHardDisk {
+            vhd_file,
+
+            size: metadata.len(),
+            footer_len: 0,
+            checksum: 0,
+
+            max_cylinders: cylinders,
+            max_heads: heads,
+            max_sectors: sectors,
+
+            cur_cylinder: 0,
+            cur_head: 0,
+            cur_sector: 0,
+
+            footer,
+        })
+    }
+
+    /// Return a description that identifies the disk image: its UUID, which is nil for raw images,
+    /// its geometry and its size.
+    pub fn identity(&self) -> String {
+        format!(
+            "{} c:{} h:{} s:{} sectors:{}",
+            self.footer.uuid,
+            self.max_cylinders,
+            self.max_heads,
+            self.max_sectors,
+            self.total_sectors()
+        )
+    }
+
+    /// Return true if this disk is a raw image without a VHD footer.
+    pub fn is_raw(&self) -> bool {
+        self.footer_len == 0
+    }
+
+    /// Return the total number of sectors in the image.
+    pub fn total_sectors(&self) -> u32 {
+        ((self.size - self.footer_len) / VHD_SECTOR_SIZE as u64) as u32
+    }
+
     /// Return a byte offset given a CHS (Cylinder, Head, Sector) address
     ///
     /// Hard drive sectors are allowed to start at 0
@@ -337,9 +419,17 @@ impl VirtualHardDisk {
 
     pub fn read_sector(&mut self, buf: &mut [u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
         let read_offset = self.get_chs_offset(cylinder, head, sector);
+        self.read_sector_at(buf, read_offset)
+    }
 
+    /// Read a sector given an LBA (Logical Block Address).
+    pub fn read_sector_lba(&mut self, buf: &mut [u8], lba: u32) -> Result<(), anyhow::Error> {
+        self.read_sector_at(buf, lba as usize * SECTOR_SIZE)
+    }
+
+    fn read_sector_at(&mut self, buf: &mut [u8], read_offset: usize) -> Result<(), anyhow::Error> {
         let metadata = self.vhd_file.metadata().context("Couldn't get VHD file metadata")?;
-        if read_offset as u64 > metadata.len() - VHD_FOOTER_LEN as u64 - VHD_SECTOR_SIZE as u64 {
+        if read_offset as u64 + VHD_SECTOR_SIZE as u64 > metadata.len() - self.footer_len {
             // Read requested past last sector in file
             bail!(VirtualHardDiskError::InvalidSeek);
         }
@@ -353,9 +443,17 @@ impl VirtualHardDisk {
 
     pub fn write_sector(&mut self, buf: &[u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
         let write_offset = self.get_chs_offset(cylinder, head, sector);
+        self.write_sector_at(buf, write_offset)
+    }
+
+    /// Write a sector given an LBA (Logical Block Address).
+    pub fn write_sector_lba(&mut self, buf: &[u8], lba: u32) -> Result<(), anyhow::Error> {
+        self.write_sector_at(buf, lba as usize * SECTOR_SIZE)
+    }
 
+    fn write_sector_at(&mut self, buf: &[u8], write_offset: usize) -> Result<(), anyhow::Error> {
         let metadata = self.vhd_file.metadata().context("Couldn't get VHD file metadata")?;
-        if write_offset as u64 > metadata.len() - VHD_FOOTER_LEN as u64 - VHD_SECTOR_SIZE as u64 {
+        if write_offset as u64 + VHD_SECTOR_SIZE as u64 > metadata.len() - self.footer_len {
             // Write requested past last sector in file
             bail!(VirtualHardDiskError::InvalidSeek);
         }
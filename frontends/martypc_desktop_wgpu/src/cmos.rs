@@ -0,0 +1,95 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cmos.rs
+
+    Sets the machine's RTC from the host clock, and restores and saves the
+    contents of its battery-backed CMOS RAM to a host file. Shared by the
+    GUI and headless front ends.
+*/
+
+use std::path::PathBuf;
+
+use chrono::{Datelike, Local, Timelike};
+use frontend_common::resource_manager::ResourceManager;
+use marty_core::{devices::rtc::RtcDateTime, machine::Machine};
+
+/// Resolve the path to the CMOS file for the machine's RTC, if one is configured.
+fn cmos_path(machine: &Machine, rm: &ResourceManager) -> Option<PathBuf> {
+    let cmos_file = machine.config().rtc.as_ref()?.cmos_file.clone()?;
+    let mut path = rm.get_resource_path("cmos")?;
+    path.push(cmos_file);
+    Some(path)
+}
+
+/// Set the RTC to the host's local time and restore the contents of CMOS RAM, if the machine
+/// has an RTC.
+pub fn init_rtc(machine: &mut Machine, rm: &ResourceManager) {
+    let cmos_path = cmos_path(machine, rm);
+    let rtc = match machine.bus_mut().rtc_mut().as_mut() {
+        Some(rtc) => rtc,
+        None => return,
+    };
+
+    let now = Local::now();
+    rtc.set_time(&RtcDateTime {
+        year: now.year() as u16,
+        month: now.month() as u8,
+        day: now.day() as u8,
+        day_of_week: now.weekday().number_from_sunday() as u8,
+        hour: now.hour() as u8,
+        minute: now.minute() as u8,
+        second: now.second() as u8,
+    });
+
+    if let Some(path) = cmos_path {
+        match std::fs::read(&path) {
+            Ok(data) => {
+                log::debug!("Loaded CMOS contents from {}", path.display());
+                rtc.load_cmos(&data);
+            }
+            Err(e) => log::warn!("Couldn't read CMOS file {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Save the contents of CMOS RAM to the host file configured for the machine's RTC, if they
+/// have changed.
+pub fn save_cmos(machine: &mut Machine, rm: &ResourceManager) {
+    let path = match cmos_path(machine, rm) {
+        Some(path) => path,
+        None => return,
+    };
+    if let Some(rtc) = machine.bus_mut().rtc_mut().as_mut() {
+        if !rtc.cmos_dirty() {
+            return;
+        }
+        match std::fs::write(&path, rtc.cmos()) {
+            Ok(_) => log::debug!("Saved CMOS contents to {}", path.display()),
+            Err(e) => log::error!("Failed to save CMOS file {}: {}", path.display(), e),
+        }
+    }
+}
@@ -30,41 +30,88 @@
 */
 
 use crate::JoystickData;
+use chrono::Local;
 use display_manager_wgpu::DisplayManager;
-use std::{cell::RefCell, ffi::OsString, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    ffi::OsString,
+    io::Cursor,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    cmos,
     event_loop::thread_events,
+    gamepad::GamepadInput,
     input::HotkeyManager,
+    render_thread::RenderThread,
     sound_player::SoundInterface,
+    video_recorder::{capture_dimensions, VideoRecorder},
     Counter,
     KeyboardData,
     MouseData,
 };
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use config_toml_bpaf::ConfigFileParams;
 use display_manager_wgpu::WgpuDisplayManager;
 use frontend_common::{
+    automation::server::AutomationServer,
     cartridge_manager::CartridgeManager,
-    display_scaler::SCALER_MODES,
-    floppy_manager::FloppyManager,
+    cassette_manager::CassetteManager,
+    cdrom_manager::CdRomManager,
+    constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME},
+    debug_console::{server::ConsoleServer, ConsoleBreakpoint, DebugConsole},
+    display_scaler::{ScalerMode, SCALER_MODES},
+    floppy_manager::{disk_set::DiskSet, write_back::MountedImage, FloppyManager},
+    keyboard_macro::{KeyboardMacro, MacroPlayer, MacroRecorder},
+    media_library::{MediaFavorites, MediaKind, MediaLibraryEntry},
+    movie::{movie_slice, Movie, MovieAnchor, MovieInput, MoviePlayer, MovieRecorder},
+    paste::{text_to_macro, PasteOptions},
+    quick_load::{at_dos_prompt, is_program_file, quick_load, ProgramFormat},
     resource_manager::ResourceManager,
+    rewind_buffer::RewindBuffer,
     rom_manager::RomManager,
+    savestate_manager::{config_hash, SaveStateManager, SaveStateMetadata},
+    script::ScriptEngine,
+    text_export::{export_text_screen, text_screen_to_string, TextExportFormat, TextSelection},
     timestep_manager::PerfSnapshot,
-    vhd_manager::VhdManager,
+    types::{
+        display::DisplaySettings,
+        floppy::{FloppyImageSource, FloppyWriteBack},
+        hotkeys::{find_conflicts, HotkeyBindings},
+        session::{FloppyMediaSource, FloppySession, HddSession, MachineSession, SessionState, WindowGeometry},
+        sound::MixerConfig,
+    },
+    vhd_manager::{host_volume::HostVolume, VhdManager},
 };
 use marty_core::{
+    breakpoints::BreakPointType,
     cpu_common::{Cpu, CpuOption},
+    device_traits::videocard::{VideoBreakpoint, VideoCardId},
+    devices::{hdc::ControllerError, keyboard::KeyboardModifiers},
+    file_util,
     machine::{ExecutionControl, Machine, MachineEvent, MachineState},
     vhd::VirtualHardDisk,
 };
-use marty_egui::{state::GuiState, GuiBoolean, GuiWindow};
-use videocard_renderer::AspectCorrectionMode;
+use fluxfox::DiskImage;
+use marty_egui::{
+    modal::DropTarget,
+    state::{FloppyDriveSelection, GuiState},
+    status_bar::StatusBarItems,
+    GuiBoolean,
+    GuiWindow,
+};
+use videocard_renderer::{AspectCorrectionMode, ScreenshotOptions};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 
 /// Define flags to be used by emulator.
 pub struct EmuFlags {
     pub render_gui: bool,
     pub debug_keyboard: bool,
+    pub low_latency: bool,
 }
 
 /// Define the main Emulator struct for this frontend.
@@ -82,16 +129,38 @@ pub struct Emulator {
     pub exec_control: Rc<RefCell<ExecutionControl>>,
     pub mouse_data: MouseData,
     pub joy_data: JoystickData,
+    pub gamepad: Option<GamepadInput>,
     pub kb_data: KeyboardData,
+    pub state_slot: usize, // The save state slot used by the SaveState and LoadState hotkeys
+    pub state_refresh_at: Option<Instant>, // When to rescan save state slots, once a thumbnail is written
+    pub rewind: Option<RewindBuffer>,
+    pub rewind_at: Instant, // When to capture the next rewind snapshot
+    pub movie_recorder: Option<MovieRecorder>,
+    pub movie_player: Option<MoviePlayer>,
     pub stat_counter: Counter,
     pub gui: GuiState,
     pub floppy_manager: FloppyManager,
     pub vhd_manager: VhdManager,
+    pub host_volumes: BTreeMap<usize, HostVolume>, // Hard disks built from host directories, by drive
+    pub floppy_mounts: BTreeMap<usize, MountedImage>, // Floppy images loaded from files, by drive
+    pub floppy_sets: BTreeMap<usize, (DiskSet, usize)>, // Disk set of each drive's disk, and its index
+    pub floppy_media: BTreeMap<usize, FloppySession>, // Where each drive's disk came from, for the session
     pub cart_manager: CartridgeManager,
+    pub cdrom_manager: CdRomManager,
+    pub cassette_manager: CassetteManager,
     pub flags: EmuFlags,
     pub perf: PerfSnapshot,
     pub hkm: HotkeyManager,
+    pub console: DebugConsole,
+    pub console_server: Option<ConsoleServer>,
+    pub automation: Option<AutomationServer>,
+    pub script: ScriptEngine,
+    pub script_break_cycle: Option<u64>, // The cycle of the last breakpoint handled by a script
+    pub clipboard: Option<arboard::Clipboard>, // Opened on first use, then kept to own copied text
+    pub quick_load_pending: Option<PathBuf>,   // Program to quick-load once DOS reaches a prompt
     pub si: Option<SoundInterface>,
+    pub video_recorder: Option<(VideoCardId, VideoRecorder)>,
+    pub render_thread: Option<RenderThread>,
     pub receiver: crossbeam_channel::Receiver<thread_events::FrontendThreadEvent>,
     pub sender: crossbeam_channel::Sender<thread_events::FrontendThreadEvent>,
 }
@@ -117,6 +186,22 @@ impl Emulator {
 
         self.flags.debug_keyboard = self.config.emulator.input.debug_keyboard;
 
+        // Set up the on-screen display.
+        self.gui.osd().set_enabled(self.config.gui.osd.enabled);
+        self.gui.osd().set_position(self.config.gui.osd.position);
+        self.gui.osd().set_duration(Duration::from_millis(self.config.gui.osd.duration_ms));
+
+        // Set up the status bar.
+        let status_bar = &self.config.gui.status_bar;
+        self.gui.status_bar().set_enabled(status_bar.enabled);
+        self.gui.status_bar().set_items(StatusBarItems {
+            cpu_mhz: status_bar.cpu_mhz,
+            fps: status_bar.fps,
+            audio: status_bar.audio,
+            disk: status_bar.disk,
+            speed: status_bar.speed,
+        });
+
         // Do PIT phase offset option
         self.machine
             .pit_adjust(self.config.machine.pit_phase.unwrap_or(0) & 0x03);
@@ -323,45 +408,108 @@ impl Emulator {
         let mut config_drive_idx: usize = 0;
         for vhd_name in vhd_names.into_iter().filter_map(|x| x) {
             let vhd_os_name: OsString = vhd_name.into();
-            match self.vhd_manager.load_vhd_file_by_name(config_drive_idx, &vhd_os_name) {
-                Ok((vhd_file, vhd_idx)) => match VirtualHardDisk::from_file(vhd_file) {
-                    Ok(vhd) => {
-                        if let Some(hdc) = self.machine.hdc() {
-                            match hdc.set_vhd(config_drive_idx, vhd) {
-                                Ok(_) => {
-                                    log::info!(
-                                        "VHD image {:?} successfully loaded into virtual drive: {}",
-                                        vhd_os_name,
-                                        config_drive_idx
-                                    );
-
-                                    if let Some(selection) = self.vhd_manager.get_vhd_path(vhd_idx) {
-                                        self.gui
-                                            .set_hdd_selection(config_drive_idx, Some(vhd_idx), Some(selection));
-                                    }
-                                }
-                                Err(err) => {
-                                    log::error!("Error mounting VHD: {}", err);
-                                }
-                            }
-                        }
-                        else {
-                            log::error!("Couldn't load VHD: No Hard Disk Controller present!");
-                        }
-                    }
-                    Err(err) => {
-                        log::error!("Error loading VHD: {}", err);
-                    }
-                },
-                Err(err) => {
-                    log::error!("Failed to load VHD image {:?}: {}", vhd_os_name, err);
-                }
+            if let Err(err) = self.mount_vhd_by_name(config_drive_idx, &vhd_os_name) {
+                log::error!("Failed to load VHD image {:?}: {}", vhd_os_name, err);
             }
             config_drive_idx += 1;
         }
+
+        // Host directories are mounted last, replacing any image in the same drive.
+        for entry in self.config.emulator.media.host_dir.iter().flatten() {
+            let (drive, path, write_back) = (entry.drive, entry.path.clone(), entry.write_back);
+            if let Err(err) = self.mount_hdd_dir(drive, &path, write_back) {
+                log::error!(
+                    "Failed to mount directory {} in hard disk {}: {}",
+                    path.display(),
+                    drive,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Mount the VHD image with the specified file name, from the 'hdd' resource, in the specified
+    /// hard disk.
+    pub fn mount_vhd_by_name(&mut self, drive: usize, name: &OsString) -> Result<(), Error> {
+        let (vhd_file, vhd_idx) = self
+            .vhd_manager
+            .load_vhd_file_by_name(drive, name)
+            .map_err(|e| anyhow!("{}", e))?;
+        let vhd = VirtualHardDisk::from_file(vhd_file).map_err(|e| anyhow!("Error loading VHD: {}", e))?;
+        match self.machine.mount_vhd(drive, vhd) {
+            Ok(_) => {}
+            Err(ControllerError::NoController) => return Err(anyhow!("No Hard Disk Controller present!")),
+            Err(err) => return Err(anyhow!("Error mounting VHD: {}", err)),
+        }
+
+        log::info!("VHD image {:?} successfully loaded into virtual drive: {}", name, drive);
+        if let Some(selection) = self.vhd_manager.get_vhd_path(vhd_idx) {
+            self.gui.set_hdd_selection(drive, Some(vhd_idx), Some(selection));
+        }
         Ok(())
     }
 
+    /// Mount a host directory in the specified hard disk as a FAT16 volume, built to a format the
+    /// hard disk controller supports. The volume is built from the directory's current contents,
+    /// so mounting it again picks up changes made on the host. With write back, the files the
+    /// guest creates or changes are copied to the directory by sync_host_volumes().
+    pub fn mount_hdd_dir(&mut self, drive: usize, path: &Path, write_back: bool) -> Result<(), Error> {
+        let formats = self
+            .machine
+            .hdc_supported_formats()
+            .ok_or(anyhow!("No Hard Disk Controller present!"))?;
+        let (volume, vhd) = HostVolume::new(path, &formats, write_back)?;
+        self.machine
+            .mount_vhd(drive, vhd)
+            .map_err(|e| anyhow!("Error mounting VHD: {}", e))?;
+        self.vhd_manager.release_vhd(drive);
+        // Replacing a previous volume removes its image, now that the controller has released it.
+        self.host_volumes.insert(drive, volume);
+
+        log::info!(
+            "Mounted directory {} in hard disk {} (write back: {})",
+            path.display(),
+            drive,
+            write_back
+        );
+        self.gui.set_hdd_host_dir(drive, path.to_path_buf(), write_back);
+        Ok(())
+    }
+
+    /// Copy the files the guest created or changed to the host directories of hard disks mounted
+    /// with write back. This should only be done while the guest isn't running.
+    pub fn sync_host_volumes(&mut self) {
+        for (drive, volume) in self.host_volumes.iter().filter(|(_, volume)| volume.write_back()) {
+            match volume.sync_to_host() {
+                Ok(0) => {}
+                Ok(written) => {
+                    log::info!(
+                        "Wrote {} files from hard disk {} to {}",
+                        written,
+                        drive,
+                        volume.dir().display()
+                    );
+                    self.gui
+                        .osd()
+                        .post(format!("Wrote {} files to {}", written, volume.dir().display()));
+                }
+                Err(err) => {
+                    log::error!(
+                        "Failed to write hard disk {} back to {}: {}",
+                        drive,
+                        volume.dir().display(),
+                        err
+                    );
+                    self.gui
+                        .toasts()
+                        .error(format!("Failed to write back to {}: {}", volume.dir().display(), err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
+    }
+
     pub fn post_dm_build_init(&mut self) {
         // Set all DisplayTargets to hardware aspect correction
         self.dm.for_each_target(|dtc, _idx| {
@@ -410,9 +558,16 @@ impl Emulator {
             }
         }
         self.gui.set_floppy_drives(drive_types);
+        for i in 0..drive_ct {
+            self.gui
+                .set_floppy_write_back(i, self.config.emulator.media.floppy_write_back);
+        }
 
         // Set default floppy path. This is used to set the default path for Save As dialogs.
         self.gui.set_paths(self.rm.get_resource_path("floppy").unwrap());
+        if let Some(path) = self.rm.get_resource_path("movie") {
+            self.gui.set_movie_path(path);
+        }
 
         // Set hard drives.
         self.gui.set_hdds(self.machine.bus().hdd_ct());
@@ -420,6 +575,14 @@ impl Emulator {
         // Set cartridge slots
         self.gui.set_cart_slots(self.machine.bus().cart_ct());
 
+        // Set CD-ROM drive
+        let cdrom_present = self.machine.cdrom_present();
+        self.gui.set_cdrom(cdrom_present);
+
+        // Set cassette deck
+        let cassette_status = self.machine.cassette().as_ref().map(|cassette| cassette.status());
+        self.gui.set_cassette(cassette_status);
+
         // Set autofloppy paths
         self.gui
             .set_autofloppy_paths(self.floppy_manager.get_autofloppy_paths());
@@ -428,6 +591,1783 @@ impl Emulator {
         self.gui.initialize();
     }
 
+    /// Apply the combined set of breakpoints from the breakpoint window, the debug console and
+    /// scripts to the machine.
+    pub fn apply_breakpoints(&mut self) {
+        // Get breakpoints from GUI
+        let bp_set = self.gui.get_breakpoints();
+
+        let mut breakpoints = Vec::new();
+
+        // Push exec breakpoint to list if valid expression
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.breakpoint) {
+            let flat_addr = u32::from(addr);
+            if flat_addr > 0 && flat_addr < 0x100000 {
+                breakpoints.push(BreakPointType::ExecuteFlat(flat_addr));
+            }
+        };
+
+        // Push mem breakpoint to list if valid expression
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.mem_breakpoint) {
+            let flat_addr = u32::from(addr);
+            if flat_addr > 0 && flat_addr < 0x100000 {
+                breakpoints.push(BreakPointType::MemAccessFlat(flat_addr));
+            }
+        }
+
+        // Push int breakpoint to list
+        if let Ok(iv) = u32::from_str_radix(bp_set.int_breakpoint, 10) {
+            if iv < 256 {
+                breakpoints.push(BreakPointType::Interrupt(iv as u8));
+            }
+        }
+
+        // Push io breakpoint to list
+        if let Ok(addr) = u32::from_str_radix(bp_set.io_breakpoint, 16) {
+            let port = addr as u16;
+            log::debug!("Adding I/O breakpoint: {:04X}", port);
+            breakpoints.push(BreakPointType::IoAccess(port));
+        }
+
+        // Push video mode register breakpoint to list. '*' matches any value written.
+        let mode_bp = bp_set.mode_breakpoint.trim();
+        if mode_bp == "*" {
+            breakpoints.push(BreakPointType::VideoRegister(VideoBreakpoint::ModeWrite(None)));
+        }
+        else if let Ok(value) = u8::from_str_radix(mode_bp, 16) {
+            breakpoints.push(BreakPointType::VideoRegister(VideoBreakpoint::ModeWrite(Some(value))));
+        }
+
+        // Push CRTC register breakpoints to list. Each entry is a register number, optionally
+        // followed by '=' and the value to match.
+        for entry in bp_set.crtc_breakpoint.split(',') {
+            let mut parts = entry.splitn(2, '=');
+            let reg = match u8::from_str_radix(parts.next().unwrap_or("").trim(), 16) {
+                Ok(reg) => reg,
+                Err(_) => continue,
+            };
+            let value = match parts.next() {
+                Some(value_str) => match u8::from_str_radix(value_str.trim(), 16) {
+                    Ok(value) => Some(value),
+                    Err(_) => continue,
+                },
+                None => None,
+            };
+            log::debug!("Adding CRTC breakpoint: R{:02X} value: {:?}", reg, value);
+            breakpoints.push(BreakPointType::VideoRegister(VideoBreakpoint::CrtcWrite(reg, value)));
+        }
+
+        // Push stopwatches to list
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.sw_start) {
+            let start_flat_addr = u32::from(addr);
+            if start_flat_addr > 0 && start_flat_addr < 0x100000 {
+                if let Some(addr) = self.machine.cpu().eval_address(bp_set.sw_stop) {
+                    let stop_flat_addr = u32::from(addr);
+                    if stop_flat_addr > 0 && stop_flat_addr < 0x100000 {
+                        breakpoints.push(BreakPointType::StartWatch(start_flat_addr));
+                        breakpoints.push(BreakPointType::StopWatch(stop_flat_addr));
+                        self.machine.set_stopwatch(0, start_flat_addr, stop_flat_addr);
+                    }
+                }
+            }
+        }
+
+        // Push breakpoints set from the debug console
+        for bp in self.console.breakpoints() {
+            match bp {
+                ConsoleBreakpoint::Execute(expr) => {
+                    if let Some(addr) = self.machine.cpu().eval_address(expr) {
+                        breakpoints.push(BreakPointType::ExecuteFlat(u32::from(addr) & 0xFFFFF));
+                    }
+                }
+                ConsoleBreakpoint::MemAccess(expr) => {
+                    if let Some(addr) = self.machine.cpu().eval_address(expr) {
+                        breakpoints.push(BreakPointType::MemAccessFlat(u32::from(addr) & 0xFFFFF));
+                    }
+                }
+                ConsoleBreakpoint::Interrupt(iv) => breakpoints.push(BreakPointType::Interrupt(*iv)),
+                ConsoleBreakpoint::IoAccess(port) => breakpoints.push(BreakPointType::IoAccess(*port)),
+            }
+        }
+
+        // Push breakpoints registered by scripts
+        for expr in self.script.breakpoints() {
+            if let Some(addr) = self.machine.cpu().eval_address(&expr) {
+                breakpoints.push(BreakPointType::ExecuteFlat(u32::from(addr) & 0xFFFFF));
+            }
+        }
+
+        self.machine.set_breakpoints(breakpoints);
+    }
+
+    /// Set the RTC clock and restore CMOS contents, if the machine has an RTC.
+    pub fn init_rtc(&mut self) {
+        cmos::init_rtc(&mut self.machine, &self.rm);
+    }
+
+    /// Persist battery-backed CMOS contents, if they have changed.
+    pub fn save_cmos(&mut self) {
+        cmos::save_cmos(&mut self.machine, &self.rm);
+    }
+
+    /// Resolve the path to the saved audio mixer settings.
+    fn mixer_path(&self) -> Option<PathBuf> {
+        let mut path = self.rm.get_resource_path("audio")?;
+        path.push("mixer.toml");
+        Some(path)
+    }
+
+    /// Restore saved mixer settings for the machine's sound sources.
+    pub fn init_mixer(&mut self) {
+        let path = match self.mixer_path() {
+            Some(path) if path.exists() => path,
+            _ => return,
+        };
+        if let Some(si) = self.si.as_mut() {
+            match MixerConfig::load(&path) {
+                Ok(config) => {
+                    log::debug!("Loaded mixer settings from {}", path.display());
+                    si.apply_mixer_config(&config);
+                }
+                Err(e) => log::warn!("Couldn't read mixer settings file {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Save the current mixer settings. Saved settings for sound sources not present in this
+    /// machine are kept.
+    pub fn save_mixer(&mut self) -> Result<PathBuf, Error> {
+        let path = self
+            .mixer_path()
+            .ok_or(anyhow!("No 'audio' resource path defined."))?;
+        let si = self.si.as_ref().ok_or(anyhow!("Sound is disabled."))?;
+
+        let mut config = MixerConfig::load(&path).unwrap_or_default();
+        si.update_mixer_config(&mut config);
+        config.save(&path)?;
+        Ok(path)
+    }
+
+    /// Resolve the path to the hotkey bindings saved from the hotkey editor.
+    fn hotkey_bindings_path(&self) -> Option<PathBuf> {
+        let mut path = self.rm.get_resource_path("input")?;
+        path.push("hotkeys.toml");
+        Some(path)
+    }
+
+    /// Apply any saved hotkey bindings over those from the configuration file, and warn about
+    /// conflicting bindings.
+    pub fn init_hotkeys(&mut self) {
+        if let Some(path) = self.hotkey_bindings_path().filter(|path| path.exists()) {
+            match HotkeyBindings::load(&path) {
+                Ok(bindings) => {
+                    for entry in bindings.hotkey {
+                        self.hkm
+                            .add_hotkey(entry.event, entry.keys, entry.scope, entry.capture_disable);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Couldn't read hotkey bindings file {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        let entries = self.hkm.entries();
+        for (a, b) in find_conflicts(&entries) {
+            log::warn!("Hotkeys {:?} and {:?} are bound to the same keys.", a, b);
+            self.gui
+                .toasts()
+                .warning(format!("Hotkeys {:?} and {:?} are bound to the same keys.", a, b))
+                .set_duration(Some(LONG_NOTIFICATION_TIME));
+        }
+        self.gui.hotkey_editor.set_bindings(entries);
+    }
+
+    /// Save the current hotkey bindings so they override the configuration file in future sessions.
+    pub fn save_hotkeys(&mut self) -> Result<PathBuf, Error> {
+        let path = self
+            .hotkey_bindings_path()
+            .ok_or(anyhow!("No 'input' resource path defined."))?;
+        let bindings = HotkeyBindings {
+            hotkey: self.hkm.entries(),
+        };
+        bindings.save(&path)?;
+        Ok(path)
+    }
+
+    /// Discard saved hotkey bindings and restore the bindings from the configuration file.
+    pub fn reset_hotkeys(&mut self) -> Result<(), Error> {
+        self.hkm = HotkeyManager::new();
+        self.hkm.add_hotkeys(self.config.emulator.input.hotkeys.clone());
+        self.gui.hotkey_editor.set_bindings(self.hkm.entries());
+        if let Some(path) = self.hotkey_bindings_path().filter(|path| path.exists()) {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve the path of a keyboard macro file. Relative paths are looked up in the 'macro'
+    /// resource directory.
+    fn macro_file_path(&self, file: &Path) -> Option<PathBuf> {
+        if file.is_absolute() {
+            return Some(file.to_path_buf());
+        }
+        let mut path = self.rm.get_resource_path("macro")?;
+        path.push(file);
+        Some(path)
+    }
+
+    /// Resolve the configured keyboard macro, and start playing it if it should be played at boot.
+    pub fn init_macro(&mut self) {
+        self.kb_data.macro_path = self
+            .config
+            .emulator
+            .input
+            .macro_file
+            .clone()
+            .and_then(|file| self.macro_file_path(&file));
+
+        if self.config.emulator.input.macro_at_boot {
+            self.play_macro();
+        }
+    }
+
+    /// Start recording keys sent to the guest as a keyboard macro, or stop the recording in
+    /// progress and save it. A newly recorded macro becomes the one played by the PlayMacro hotkey.
+    pub fn toggle_macro_recording(&mut self) {
+        match self.kb_data.macro_recorder.take() {
+            Some(recorder) => {
+                let keyboard_macro = recorder.finish();
+                let result = self
+                    .rm
+                    .get_resource_path("macro")
+                    .ok_or(anyhow!("No 'macro' resource path defined."))
+                    .and_then(|path| {
+                        let filename = file_util::find_unique_filename(&path, "macro", "txt");
+                        keyboard_macro.save(&filename)?;
+                        Ok(filename)
+                    });
+
+                match result {
+                    Ok(path) => {
+                        log::info!(
+                            "Saved keyboard macro of {} events to {}",
+                            keyboard_macro.events.len(),
+                            path.display()
+                        );
+                        self.gui.osd().post(format!(
+                            "Macro saved to {}",
+                            path.file_name().unwrap_or_default().to_string_lossy()
+                        ));
+                        self.kb_data.macro_path = Some(path);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to save keyboard macro: {}", e);
+                        self.gui
+                            .toasts()
+                            .error(format!("Failed to save keyboard macro: {}", e))
+                            .set_duration(Some(LONG_NOTIFICATION_TIME));
+                    }
+                }
+            }
+            None => {
+                self.kb_data.macro_player = None;
+                self.kb_data.macro_recorder = Some(MacroRecorder::new());
+                self.gui.osd().post("Recording keyboard macro...");
+            }
+        }
+    }
+
+    /// Start playing the current keyboard macro from the beginning.
+    pub fn play_macro(&mut self) {
+        let path = match &self.kb_data.macro_path {
+            Some(path) => path.clone(),
+            None => {
+                self.gui
+                    .toasts()
+                    .error("No keyboard macro to play. Record one, or set 'macro_file'.".to_string())
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                return;
+            }
+        };
+
+        match KeyboardMacro::load(&path) {
+            Ok(keyboard_macro) => {
+                log::debug!("Playing keyboard macro {}", path.display());
+                self.kb_data.macro_player = Some(MacroPlayer::new(keyboard_macro));
+                self.kb_data.macro_tick = Instant::now();
+                self.gui.osd().post(format!(
+                    "Playing macro {}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                ));
+            }
+            Err(e) => {
+                log::error!("Failed to load keyboard macro {}: {}", path.display(), e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to load keyboard macro: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Type the text on the host clipboard into the guest. The text is converted into a keyboard
+    /// macro and played back through the same path as a recorded macro, replacing any macro
+    /// currently playing.
+    pub fn paste_clipboard(&mut self) {
+        let text = match self.clipboard().and_then(|clipboard| clipboard.get_text()) {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("Failed to read clipboard: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to read clipboard: {}", e))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                return;
+            }
+        };
+
+        let mut options = PasteOptions::default();
+        if let Some(ms) = self.config.emulator.input.paste_key_delay {
+            options.key_delay = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.config.emulator.input.paste_line_delay {
+            options.line_delay = Duration::from_millis(ms);
+        }
+
+        let (keyboard_macro, skipped) = text_to_macro(&text, &options);
+        if skipped > 0 {
+            log::warn!("Paste: skipped {} characters with no CP437 equivalent", skipped);
+            self.gui
+                .toasts()
+                .warning(format!("Skipped {} characters that can't be typed", skipped))
+                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+        }
+        if keyboard_macro.events.is_empty() {
+            return;
+        }
+
+        let chars = text.chars().count() - skipped;
+        log::debug!("Pasting {} characters ({} key events)", chars, keyboard_macro.events.len());
+        self.kb_data.macro_player = Some(MacroPlayer::new(keyboard_macro));
+        self.kb_data.macro_tick = Instant::now();
+        self.gui.osd().post(format!("Pasting {} characters", chars));
+    }
+
+    /// Return the host clipboard, opening it if necessary. On X11 the contents of the clipboard
+    /// are served by the application that set them, so the handle is kept for the lifetime of
+    /// the emulator rather than dropped after each copy.
+    fn clipboard(&mut self) -> Result<&mut arboard::Clipboard, arboard::Error> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new()?);
+        }
+        Ok(self.clipboard.as_mut().unwrap())
+    }
+
+    /// Copy the text displayed by the specified video card, or by the primary video card if None,
+    /// to the host clipboard. If a selection is given, only that region of the screen is copied.
+    /// Returns the number of characters copied.
+    pub fn copy_text_screen(
+        &mut self,
+        card_idx: Option<usize>,
+        selection: Option<TextSelection>,
+    ) -> Result<usize, Error> {
+        let bus = self.machine.bus();
+        let vid = match card_idx {
+            Some(idx) => bus.enumerate_videocards().into_iter().find(|vid| vid.idx == idx),
+            None => bus.primary_video_id(),
+        }
+        .ok_or(anyhow!("No video card present."))?;
+
+        let screen = bus
+            .video(&vid)
+            .and_then(|video| video.get_text_mode_screen(false))
+            .ok_or(anyhow!("Video card is not in a supported text mode."))?;
+
+        let text = text_screen_to_string(&screen, selection);
+        let chars = text.chars().filter(|c| *c != '\n').count();
+        self.clipboard()
+            .and_then(|clipboard| clipboard.set_text(text))
+            .map_err(|e| anyhow!("Failed to write clipboard: {}", e))?;
+        Ok(chars)
+    }
+
+    /// Copy the text screen to the host clipboard, reporting the result with a notification.
+    pub fn copy_screen_to_clipboard(&mut self, card_idx: Option<usize>) {
+        match self.copy_text_screen(card_idx, None) {
+            Ok(chars) => {
+                self.gui.osd().post(format!("Copied {} characters", chars));
+            }
+            Err(e) => {
+                log::error!("Failed to copy text screen: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("{}", e))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Send any keys from the playing keyboard macro that are due to the machine. Playback only
+    /// advances while the machine is running.
+    pub fn update_macro(&mut self) {
+        let now = Instant::now();
+        let delta = now.duration_since(self.kb_data.macro_tick);
+        self.kb_data.macro_tick = now;
+
+        if !matches!(self.machine.get_state(), MachineState::On) {
+            return;
+        }
+
+        if let Some(player) = &mut self.kb_data.macro_player {
+            for (key, pressed) in player.advance(delta) {
+                match pressed {
+                    true => self.machine.key_press(key, KeyboardModifiers::default()),
+                    false => self.machine.key_release(key),
+                }
+            }
+            if player.is_finished() {
+                log::debug!("Keyboard macro finished.");
+                self.kb_data.macro_player = None;
+            }
+        }
+    }
+
+    /// Create a SaveStateManager for the current machine configuration. Slots are kept separately
+    /// for each machine configuration, as a state can only be restored into the machine that saved it.
+    fn state_manager(&self) -> Result<SaveStateManager, Error> {
+        let path = self
+            .rm
+            .get_resource_path("savestate")
+            .ok_or(anyhow!("No 'savestate' resource path defined."))?;
+        Ok(SaveStateManager::new(path, &self.config.machine.config_name))
+    }
+
+    /// Hash the current machine configuration. Media is excluded, as it is compared separately.
+    fn state_config_hash(&self) -> String {
+        let mut config = self.machine.config().clone();
+        config.media = None;
+        config_hash(&format!("{:?}", config))
+    }
+
+    /// Save the state of the machine to the specified slot, replacing any state already there.
+    /// The slot becomes the one used by the SaveState and LoadState hotkeys.
+    pub fn save_state_slot(&mut self, slot: usize) {
+        self.state_slot = slot;
+        if matches!(self.machine.get_state(), MachineState::Off) {
+            self.gui.osd().post("Machine is off, nothing to save");
+            return;
+        }
+
+        let result = self.state_manager().and_then(|manager| {
+            let path = manager.state_path(slot);
+            let state = self.machine.save_state()?;
+            std::fs::write(&path, &state)?;
+
+            let metadata = SaveStateMetadata {
+                timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                config_name: self.config.machine.config_name.clone(),
+                config_hash: self.state_config_hash(),
+                media: self.gui.mounted_media(),
+            };
+            metadata.save(&manager.metadata_path(slot))?;
+
+            // A state is still usable without its thumbnail, so don't fail the save over it.
+            let thumbnail = manager.thumbnail_path(slot);
+            let opts = ScreenshotOptions {
+                raw: false,
+                metadata: Vec::new(),
+            };
+            if let Err(e) = self.dm.save_screenshot_to(0, &thumbnail, opts) {
+                log::warn!("Failed to request save state thumbnail: {}", e);
+            }
+            Ok((path, state.len()))
+        });
+
+        match result {
+            Ok((path, len)) => {
+                log::info!("Saved machine state ({} bytes) to {}", len, path.display());
+                self.gui.osd().post(format!("State saved to slot {}", slot));
+                // The thumbnail is written when the next frame is rendered.
+                self.state_refresh_at = Some(Instant::now() + Duration::from_millis(500));
+            }
+            Err(e) => {
+                log::error!("Failed to save machine state: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to save state: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Restore the state of the machine from the specified slot. The machine is left unchanged
+    /// if the state cannot be loaded. Mounted media is not part of a save state, so the same
+    /// disks should be inserted as when the state was saved.
+    pub fn load_state_slot(&mut self, slot: usize) {
+        self.state_slot = slot;
+        if matches!(self.machine.get_state(), MachineState::Off) {
+            self.gui.osd().post("Power on the machine to load a state");
+            return;
+        }
+
+        let result = self.state_manager().and_then(|manager| {
+            let path = manager.state_path(slot);
+            if !path.exists() {
+                return Err(anyhow!("Slot {} is empty.", slot));
+            }
+            let state = std::fs::read(&path)?;
+            self.machine.load_state(&state)?;
+            Ok((path, manager.load_metadata(slot)))
+        });
+
+        match result {
+            Ok((path, metadata)) => {
+                // A movie can't continue past a jump in the machine's state.
+                self.stop_movie();
+                log::info!("Loaded machine state from {}", path.display());
+                self.gui.osd().post(format!("State loaded from slot {}", slot));
+
+                let warnings = match metadata {
+                    Some(metadata) => metadata.mismatches(&self.state_config_hash(), &self.gui.mounted_media()),
+                    None => Vec::new(),
+                };
+                for warning in warnings {
+                    log::warn!("Save state slot {}: {}", slot, warning);
+                    self.gui
+                        .toasts()
+                        .warning(warning)
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to load machine state: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to load state: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Delete the state, metadata and thumbnail saved in the specified slot.
+    pub fn delete_state_slot(&mut self, slot: usize) {
+        match self.state_manager().and_then(|manager| manager.delete(slot)) {
+            Ok(()) => {
+                self.gui.osd().post(format!("Deleted save state slot {}", slot));
+            }
+            Err(e) => {
+                log::error!("Failed to delete save state: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to delete state: {}", e))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        }
+        self.refresh_save_states();
+    }
+
+    /// Rescan the save state slots and update the save state browser.
+    pub fn refresh_save_states(&mut self) {
+        self.state_refresh_at = None;
+        match self.state_manager() {
+            Ok(manager) => {
+                let slots = manager.scan(&self.state_config_hash(), &self.gui.mounted_media());
+                self.gui.savestate_browser.set_slots(slots);
+            }
+            Err(e) => {
+                log::error!("Failed to scan save states: {}", e);
+            }
+        }
+    }
+
+    /// Resolve the path to the list of favorite images in the media library.
+    fn media_favorites_path(&self) -> Option<PathBuf> {
+        let mut path = self.rm.get_resource_path("media_library")?;
+        path.push("favorites.toml");
+        Some(path)
+    }
+
+    fn load_media_favorites(&self) -> MediaFavorites {
+        match self.media_favorites_path().filter(|path| path.exists()) {
+            Some(path) => MediaFavorites::load(&path).unwrap_or_else(|e| {
+                log::warn!("Couldn't read media favorites file {}: {}", path.display(), e);
+                MediaFavorites::default()
+            }),
+            None => MediaFavorites::default(),
+        }
+    }
+
+    /// Inspect the floppy and hard disk images found by the last scan of the media directories,
+    /// and update the media library browser.
+    pub fn refresh_media_library(&mut self) {
+        let favorites = self.load_media_favorites();
+
+        let floppies = (0..self.floppy_manager.get_floppy_names().len())
+            .filter_map(|idx| Some((MediaKind::Floppy, idx, self.floppy_manager.get_floppy_path(idx)?)));
+        let hdds = (0..self.vhd_manager.get_vhd_names().len())
+            .filter_map(|idx| Some((MediaKind::HardDisk, idx, self.vhd_manager.get_vhd_path(idx)?)));
+
+        let entries = floppies
+            .chain(hdds)
+            .map(|(kind, idx, path)| {
+                let mut entry = MediaLibraryEntry::from_path(kind, idx, &path);
+                entry.favorite = favorites.contains(&path);
+                entry
+            })
+            .collect::<Vec<_>>();
+        log::debug!("Indexed {} images for the media library", entries.len());
+        self.gui.media_library.set_entries(entries);
+    }
+
+    /// Add an image to or remove it from the media library's favorites.
+    pub fn set_media_favorite(&mut self, path: &Path, favorite: bool) -> Result<(), Error> {
+        let favorites_path = self
+            .media_favorites_path()
+            .ok_or(anyhow!("No 'media_library' resource path defined."))?;
+        let mut favorites = self.load_media_favorites();
+        favorites.set(path, favorite);
+        favorites.save(&favorites_path)?;
+        self.gui.media_library.set_favorite(path, favorite);
+        Ok(())
+    }
+
+    /// Perform a rescan of the save state slots scheduled by save_state_slot().
+    pub fn update_save_states(&mut self) {
+        if let Some(at) = self.state_refresh_at {
+            if Instant::now() >= at {
+                self.refresh_save_states();
+            }
+        }
+    }
+
+    /// Capture a rewind snapshot if rewind is enabled and the snapshot interval has elapsed.
+    /// Rewind is disabled if the machine can't be saved.
+    pub fn update_rewind(&mut self) {
+        if self.rewind.is_none()
+            || !matches!(self.machine.get_state(), MachineState::On)
+            || Instant::now() < self.rewind_at
+        {
+            return;
+        }
+        self.rewind_at = Instant::now() + Duration::from_millis(self.config.emulator.rewind.interval_ms);
+
+        match self.machine.save_state() {
+            Ok(state) => {
+                if let Some(rewind) = &mut self.rewind {
+                    rewind.push(state);
+                }
+            }
+            Err(e) => {
+                log::warn!("Disabling rewind: {}", e);
+                self.gui
+                    .toasts()
+                    .warning(format!("Rewind is unavailable: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+                self.rewind = None;
+            }
+        }
+    }
+
+    /// Restore the most recent rewind snapshot, removing it from the rewind buffer. Each call
+    /// steps further back in time, so holding the Rewind hotkey scrubs backwards.
+    pub fn rewind(&mut self) {
+        let state = match self.rewind.as_mut().map(|rewind| rewind.pop()) {
+            Some(Some(state)) => state,
+            Some(None) => {
+                self.gui.osd().post("Nothing to rewind");
+                return;
+            }
+            None => {
+                self.gui.osd().post("Rewind is disabled");
+                return;
+            }
+        };
+
+        match self.machine.load_state(&state) {
+            Ok(()) => {
+                self.stop_movie();
+                // Give the machine a full interval before the next snapshot, so repeated steps
+                // keep going back rather than capturing the state just restored.
+                self.rewind_at = Instant::now() + Duration::from_millis(self.config.emulator.rewind.interval_ms);
+                let remaining = self.rewind.as_ref().map_or(0, |rewind| rewind.len());
+                self.gui.osd().post(format!("Rewind ({} left)", remaining));
+            }
+            Err(e) => {
+                log::error!("Failed to restore rewind snapshot: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to rewind: {}", e))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Run the machine for the specified number of cycles. While a movie is recorded or played,
+    /// the machine is run in movie slices, and inputs are recorded or replayed between slices.
+    pub fn run_machine(&mut self, cycles: u32) {
+        let anchor_cycle = match (&self.movie_recorder, &self.movie_player) {
+            (Some(recorder), _) => recorder.anchor_cycle(),
+            (None, Some(player)) => player.anchor_cycle(),
+            (None, None) => {
+                self.machine.run(cycles, &mut self.exec_control.borrow_mut());
+                return;
+            }
+        };
+
+        let target = self.machine.cpu_cycles() + cycles as u64;
+        while self.machine.cpu_cycles() < target {
+            self.update_movie();
+            let start = self.machine.cpu_cycles();
+            self.machine
+                .run(movie_slice(start - anchor_cycle), &mut self.exec_control.borrow_mut());
+            if self.machine.cpu_cycles() == start {
+                // The machine is paused or stopped at a breakpoint.
+                break;
+            }
+        }
+    }
+
+    /// Record the inputs sent to the machine since the last slice, or replay the inputs due.
+    fn update_movie(&mut self) {
+        let cycle = self.machine.cpu_cycles();
+        if let Some(recorder) = &mut self.movie_recorder {
+            for input in self.machine.take_input_log() {
+                recorder.record(cycle, MovieInput::Machine(input));
+            }
+        }
+
+        let inputs = match &mut self.movie_player {
+            Some(player) => player.advance(cycle),
+            None => return,
+        };
+        for input in inputs {
+            match input {
+                MovieInput::Machine(input) => self.machine.apply_input(&input),
+                MovieInput::LoadFloppy(drive, path) => {
+                    if let Err(e) = self.load_floppy_immediate(drive, &path) {
+                        log::error!("Movie: failed to load floppy image {}: {}", path.display(), e);
+                    }
+                }
+                MovieInput::EjectFloppy(drive) => self.eject_floppy(drive),
+            }
+        }
+
+        if self.movie_player.as_ref().map_or(false, |player| player.is_finished()) {
+            self.stop_movie();
+        }
+    }
+
+    /// Record an input that isn't sent through the machine, such as a disk change, to the movie
+    /// being recorded.
+    pub fn record_movie_input(&mut self, input: MovieInput) {
+        if let Some(recorder) = &mut self.movie_recorder {
+            recorder.record(self.machine.cpu_cycles(), input);
+        }
+    }
+
+    /// Start recording a movie. If `from_state` is set, the movie starts from the current state
+    /// of the machine; otherwise the machine is reset first.
+    pub fn record_movie(&mut self, from_state: bool) {
+        if !matches!(self.machine.get_state(), MachineState::On) {
+            self.gui.osd().post("Power on the machine to record a movie");
+            return;
+        }
+        self.stop_movie();
+
+        let anchor = match from_state {
+            true => match self.machine.save_state() {
+                Ok(state) => MovieAnchor::State(state),
+                Err(e) => {
+                    log::error!("Failed to save movie anchor state: {}", e);
+                    self.gui
+                        .toasts()
+                        .error(format!("Can't record from the current state: {}", e))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                    return;
+                }
+            },
+            false => {
+                self.machine.reset();
+                MovieAnchor::Reset(self.machine.rtc_state())
+            }
+        };
+
+        self.machine.set_input_logging(true);
+        self.movie_recorder = Some(MovieRecorder::new(
+            self.state_config_hash(),
+            anchor,
+            self.machine.cpu_cycles(),
+        ));
+        self.gui.osd().post("Recording movie...");
+    }
+
+    /// Start playing the movie at the specified path. The machine is first reset or restored to
+    /// the movie's anchor, and host input is ignored until playback ends.
+    pub fn play_movie(&mut self, path: &Path) {
+        if !matches!(self.machine.get_state(), MachineState::On) {
+            self.gui.osd().post("Power on the machine to play a movie");
+            return;
+        }
+        self.stop_movie();
+
+        let result = Movie::load(path).and_then(|movie| {
+            match &movie.anchor {
+                MovieAnchor::Reset(rtc) => {
+                    self.machine.reset();
+                    match (rtc, self.machine.rtc_state().is_some()) {
+                        (Some(rtc), true) => self.machine.load_rtc_state(rtc)?,
+                        (None, false) => {}
+                        _ => return Err(anyhow!("The movie's RTC doesn't match the machine")),
+                    }
+                }
+                MovieAnchor::State(state) => self.machine.load_state(state)?,
+            }
+            Ok(movie)
+        });
+
+        match result {
+            Ok(movie) => {
+                if movie.config_hash != self.state_config_hash() {
+                    self.gui
+                        .toasts()
+                        .warning("This movie was recorded with a different machine configuration.".to_string())
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+                log::info!(
+                    "Playing movie {} with {} inputs",
+                    path.display(),
+                    movie.events.len()
+                );
+                self.machine.lock_input(true);
+                self.movie_player = Some(MoviePlayer::new(movie, self.machine.cpu_cycles()));
+                self.gui.osd().post(format!(
+                    "Playing movie {}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                ));
+            }
+            Err(e) => {
+                log::error!("Failed to play movie {}: {}", path.display(), e);
+                self.gui
+                    .toasts()
+                    .error(format!("Failed to play movie: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Stop the movie being recorded or played. A recorded movie is saved to the 'movie'
+    /// resource directory.
+    pub fn stop_movie(&mut self) {
+        if let Some(player) = self.movie_player.take() {
+            self.machine.lock_input(false);
+            match player.is_desynced() {
+                true => {
+                    log::warn!("Movie playback desynchronized.");
+                    self.gui
+                        .toasts()
+                        .warning("Movie playback lost sync with the recording.".to_string())
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+                false => self.gui.osd().post("Movie finished"),
+            }
+        }
+
+        if let Some(mut recorder) = self.movie_recorder.take() {
+            let cycle = self.machine.cpu_cycles();
+            for input in self.machine.take_input_log() {
+                recorder.record(cycle, MovieInput::Machine(input));
+            }
+            self.machine.set_input_logging(false);
+
+            let movie = recorder.finish();
+            let result = self
+                .rm
+                .get_resource_path("movie")
+                .ok_or(anyhow!("No 'movie' resource path defined."))
+                .and_then(|path| {
+                    let filename = file_util::find_unique_filename(&path, "movie", "txt");
+                    movie.save(&filename)?;
+                    Ok(filename)
+                });
+
+            match result {
+                Ok(path) => {
+                    log::info!("Saved movie of {} inputs to {}", movie.events.len(), path.display());
+                    self.gui.osd().post(format!(
+                        "Movie saved to {}",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ));
+                }
+                Err(e) => {
+                    log::error!("Failed to save movie: {}", e);
+                    self.gui
+                        .toasts()
+                        .error(format!("Failed to save movie: {}", e))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
+    }
+
+    /// Load a floppy image for movie playback or a script. Unlike loading from the GUI, the image
+    /// is loaded immediately, so that the disk change happens on the cycle it was requested at.
+    pub fn load_floppy_immediate(&mut self, drive: usize, path: &Path) -> Result<(), Error> {
+        // The image isn't tracked for write back, so that playing a movie never changes the
+        // image files it uses.
+        self.release_floppy(drive);
+        let (data, path) = match self.floppy_manager.load_floppy_by_path(path.to_path_buf(), &self.rm) {
+            Ok(FloppyImageSource::DiskImage(data, path)) => (data, path),
+            Ok(_) => return Err(anyhow!("Only disk images can be loaded immediately.")),
+            Err(e) => return Err(anyhow!("{}", e)),
+        };
+        let image = DiskImage::load(&mut Cursor::new(data), Some(path.clone()), None, None)?;
+
+        let write_protect = self.config.emulator.media.write_protect_default;
+        let fdc = self.machine.fdc().as_mut().ok_or(anyhow!("No floppy controller."))?;
+        let image = fdc.attach_image(drive, image, Some(path.clone()), write_protect)?;
+        let (source_format, compatible_formats) = (image.source_format(), image.compatible_formats(true));
+        self.find_disk_set(drive, &path);
+        self.remember_floppy(drive, FloppyMediaSource::Image, &path, None);
+        self.gui.set_floppy_selection(
+            drive,
+            None,
+            FloppyDriveSelection::Image(path),
+            source_format,
+            compatible_formats,
+            Some(write_protect),
+        );
+        Ok(())
+    }
+
+    /// Remember where the disk in the specified drive came from, so that it can be mounted again in
+    /// the next session.
+    pub fn remember_floppy(&mut self, drive: usize, source: FloppyMediaSource, path: &Path, image: Option<&str>) {
+        self.floppy_media.insert(
+            drive,
+            FloppySession {
+                drive,
+                source,
+                path: path.to_path_buf(),
+                image: image.map(|image| image.to_string()),
+                // Saved with the session, as it can be changed at any time.
+                write_protect: false,
+            },
+        );
+    }
+
+    /// Find the disk set of the image file just loaded into the specified drive, so that the other
+    /// disks in the set can be swapped in.
+    pub fn find_disk_set(&mut self, drive: usize, path: &Path) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let set = self.floppy_manager.find_disk_set(path);
+        if let Some((set, idx)) = set.and_then(|set| set.position(&name).map(|idx| (set, idx))) {
+            log::debug!("Floppy image {} is disk {} of a set of {}", name, idx + 1, set.len());
+            self.floppy_sets.insert(drive, (set, idx));
+        }
+    }
+
+    /// Swap the disk in the specified drive for another disk of its set: the disk at the specified
+    /// index, or the next disk, wrapping around after the last. The drive's disk change line is set
+    /// as for any other disk change. Returns the index of the new disk and the size of the set.
+    pub fn swap_floppy(&mut self, drive: usize, idx: Option<usize>) -> Result<(usize, usize), Error> {
+        let (set, current) = self
+            .floppy_sets
+            .get(&drive)
+            .cloned()
+            .ok_or(anyhow!("The disk in drive {} isn't part of a disk set.", drive))?;
+        let idx = match idx {
+            Some(idx) if idx < set.len() => idx,
+            Some(idx) => return Err(anyhow!("Invalid disk {}: the set has {} disks.", idx, set.len())),
+            None => (current + 1) % set.len(),
+        };
+
+        match &set {
+            DiskSet::Files(files) => {
+                let path = files[idx].clone();
+                self.load_floppy_immediate(drive, &path)?;
+                // Unlike a movie's disk changes, a swap is treated as a load from the menu.
+                if self.movie_player.is_none() {
+                    self.track_floppy(drive, &path);
+                }
+                self.record_movie_input(MovieInput::LoadFloppy(drive, path));
+            }
+            DiskSet::Archive(archive, names) => {
+                self.mount_zip(drive, archive, Some(&names[idx]))?;
+            }
+        }
+
+        let letter = (b'A' + drive as u8) as char;
+        log::info!("Swapped disk {} of {} into drive {}:", idx + 1, set.len(), letter);
+        self.gui.osd().post(format!(
+            "{}: disk {} of {}: {}",
+            letter,
+            idx + 1,
+            set.len(),
+            set.name(idx)
+        ));
+        Ok((idx, set.len()))
+    }
+
+    /// Mount a host directory in the specified drive as a write-protected FAT12 floppy, built to
+    /// the largest format the drive supports. The image is built from the directory's current
+    /// contents, so mounting the same directory again picks up changes made on the host. Changes
+    /// made by the guest are not written back to the directory.
+    pub fn mount_floppy_dir(&mut self, drive: usize, path: &Path) -> Result<(), Error> {
+        self.release_floppy(drive);
+        let fdc = self.machine.fdc().as_mut().ok_or(anyhow!("No floppy controller."))?;
+        if drive >= fdc.drive_ct() {
+            return Err(anyhow!("Invalid floppy drive: {}", drive));
+        }
+        let image_type = fdc.drive(drive).get_largest_supported_image_format();
+
+        let data = self
+            .floppy_manager
+            .build_floppy_image_from_host_dir(path, Some(image_type))?;
+        let image = fdc.load_image_from(drive, data, None, true)?;
+        let (source_format, compatible_formats) = (image.source_format(), image.compatible_formats(true));
+
+        // Patch the floppy image with the correct BPB for the selected format type.
+        fdc.patch_image_bpb(drive, Some(image_type))?;
+
+        log::info!("Mounted directory {} in floppy drive {}", path.display(), drive);
+        self.remember_floppy(drive, FloppyMediaSource::Directory, path, None);
+        self.gui.set_floppy_selection(
+            drive,
+            None,
+            FloppyDriveSelection::Directory(path.to_path_buf()),
+            source_format,
+            compatible_formats,
+            Some(true),
+        );
+        self.gui.set_floppy_write_protected(drive, true);
+        Ok(())
+    }
+
+    /// Mount a zip archive in the specified drive, write-protected. With an image name, that disk
+    /// image inside the archive is mounted. Otherwise, a FAT12 floppy of the largest format the
+    /// drive supports is built from the files in the archive. Either way, the drive's menu then
+    /// lists the archive's disk images so that another can be selected.
+    pub fn mount_zip(&mut self, drive: usize, path: &Path, image_name: Option<&str>) -> Result<(), Error> {
+        let archive = self.rm.read_resource_from_path(path)?;
+        let archive_images = self.floppy_manager.zip_disk_images(&archive)?;
+        let disk_set = DiskSet::Archive(path.to_path_buf(), archive_images.clone());
+
+        self.release_floppy(drive);
+        let fdc = self.machine.fdc().as_mut().ok_or(anyhow!("No floppy controller."))?;
+        if drive >= fdc.drive_ct() {
+            return Err(anyhow!("Invalid floppy drive: {}", drive));
+        }
+        let archive_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        let (compatible_formats, selection_name) = match image_name {
+            Some(image_name) => {
+                let data = self.floppy_manager.read_zip_entry(&archive, image_name)?;
+                let image = DiskImage::load(&mut Cursor::new(data), Some(PathBuf::from(image_name)), None, None)?;
+                // The image can't be saved back into the archive, so it has no path.
+                let image = fdc.attach_image(drive, image, None, true)?;
+                (
+                    image.compatible_formats(true),
+                    format!("{}/{}", archive_name, image_name),
+                )
+            }
+            None => {
+                let image_type = fdc.drive(drive).get_largest_supported_image_format();
+                let data = self
+                    .floppy_manager
+                    .build_autofloppy_image_from_zip(archive, Some(image_type), &self.rm)?;
+                let compatible_formats = fdc.load_image_from(drive, data, None, true)?.compatible_formats(true);
+                // Patch the floppy image with the correct BPB for the selected format type.
+                fdc.patch_image_bpb(drive, Some(image_type))?;
+                (compatible_formats, archive_name)
+            }
+        };
+
+        log::info!("Mounted zip archive {} in floppy drive {}", path.display(), drive);
+        // The disk has no source format, so that it can't be saved over the archive.
+        self.gui.set_floppy_selection(
+            drive,
+            None,
+            FloppyDriveSelection::ZipArchive(selection_name.into()),
+            None,
+            compatible_formats,
+            Some(true),
+        );
+        self.gui.set_floppy_archive(drive, path.to_path_buf(), archive_images);
+        self.gui.set_floppy_write_protected(drive, true);
+        if let Some(idx) = image_name.and_then(|name| disk_set.position(name)) {
+            self.floppy_sets.insert(drive, (disk_set, idx));
+        }
+        self.remember_floppy(drive, FloppyMediaSource::ZipArchive, path, image_name);
+        Ok(())
+    }
+
+    /// Offer to insert a file dropped onto the emulator window. The kind of media is decided by the
+    /// file's extension, and a chooser asks which drive or slot to insert it into.
+    pub fn handle_dropped_file(&mut self, path: &Path) {
+        let target = if self.floppy_manager.is_floppy_image(path) {
+            self.machine.fdc().is_some().then_some(DropTarget::Floppy)
+        }
+        else if self.cart_manager.is_cart_image(path) {
+            self.machine.cart_slot().is_some().then_some(DropTarget::Cartridge)
+        }
+        else if is_program_file(path) {
+            Some(DropTarget::Program)
+        }
+        else {
+            None
+        };
+
+        match target {
+            Some(target) => {
+                log::debug!("File dropped: {} as {:?}", path.display(), target);
+                let write_protect = self.config.emulator.media.write_protect_default;
+                self.gui.show_drop_chooser(path.to_path_buf(), target, write_protect);
+            }
+            None => {
+                log::warn!("Can't insert dropped file: {}", path.display());
+                self.gui
+                    .toasts()
+                    .error(format!(
+                        "Can't insert {}",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Track the image just loaded into the specified drive from a file, so that the guest's
+    /// changes can be written back to it according to the drive's write back mode.
+    pub fn track_floppy(&mut self, drive: usize, path: &Path) {
+        let mode = self.gui.floppy_write_back(drive);
+        let backup = self.config.emulator.media.floppy_backup;
+        let mount = match self.machine.fdc() {
+            Some(fdc) => match fdc.get_image(drive) {
+                (Some(image), write_ct) => MountedImage::new(path, image, write_ct, mode, backup),
+                _ => None,
+            },
+            None => None,
+        };
+
+        match mount {
+            Some(mount) => {
+                log::debug!("Tracking floppy image {} in drive {}", path.display(), drive);
+                self.floppy_mounts.insert(drive, mount);
+            }
+            None => {
+                self.floppy_mounts.remove(&drive);
+            }
+        }
+    }
+
+    /// Stop tracking the image in the specified drive before it is ejected or replaced. The
+    /// guest's changes are written back, discarded, or reported as lost according to the drive's
+    /// write back mode, and the drive's disk set and source are forgotten.
+    pub fn release_floppy(&mut self, drive: usize) {
+        self.floppy_sets.remove(&drive);
+        self.floppy_media.remove(&drive);
+        let mut mount = match self.floppy_mounts.remove(&drive) {
+            Some(mount) => mount,
+            None => return,
+        };
+        self.gui.set_floppy_modified(drive, false, false);
+
+        let fdc = match self.machine.fdc() {
+            Some(fdc) => fdc,
+            None => return,
+        };
+        let (image, write_ct) = fdc.get_image_mut(drive);
+        let image = match image {
+            Some(image) if mount.is_modified(write_ct) => image,
+            _ => return,
+        };
+
+        let name = mount
+            .path()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        match mount.mode() {
+            FloppyWriteBack::Auto => match mount.flush(image, write_ct) {
+                Ok(_) => {
+                    log::info!("Wrote floppy image {} back to its file", name);
+                    self.gui.osd().post(format!("Floppy saved: {}", name));
+                }
+                Err(err) => {
+                    log::error!("Failed to write back floppy image {}: {}", name, err);
+                    self.gui
+                        .toasts()
+                        .error(format!("Failed to save {}: {}", name, err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            },
+            FloppyWriteBack::Discard => {
+                log::info!("Discarded changes to floppy image {}", name);
+                self.gui.osd().post(format!("Floppy changes discarded: {}", name));
+            }
+            FloppyWriteBack::Manual => {
+                log::warn!("Unsaved changes to floppy image {} were lost", name);
+                self.gui
+                    .toasts()
+                    .warning(format!("Changes to {} were not saved", name))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Release the images in every floppy drive, before exiting.
+    pub fn release_floppies(&mut self) {
+        let drives: Vec<usize> = self.floppy_mounts.keys().copied().collect();
+        for drive in drives {
+            self.release_floppy(drive);
+        }
+    }
+
+    /// Write back floppy images in automatic mode once the guest has stopped writing to them, and
+    /// show each drive's unsaved changes in the GUI.
+    pub fn update_floppy_write_back(&mut self) {
+        let fdc = match self.machine.fdc() {
+            Some(fdc) => fdc,
+            None => return,
+        };
+
+        for (drive, mount) in self.floppy_mounts.iter_mut() {
+            let (image, write_ct) = fdc.get_image_mut(*drive);
+            let image = match image {
+                Some(image) => image,
+                None => continue,
+            };
+
+            if mount.flush_due(write_ct) {
+                if let Err(err) = mount.flush(image, write_ct) {
+                    // Fall back to manual mode, rather than retrying on every frame.
+                    log::error!("Failed to write back floppy image in drive {}: {}", drive, err);
+                    mount.set_mode(FloppyWriteBack::Manual);
+                    self.gui.set_floppy_write_back(*drive, FloppyWriteBack::Manual);
+                    self.gui
+                        .toasts()
+                        .error(format!("Automatic write back disabled for drive {}: {}", drive, err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+            self.gui
+                .set_floppy_modified(*drive, mount.is_modified(write_ct), mount.backup_path().is_some());
+        }
+    }
+
+    /// Set the write back mode of the specified drive, and of the image in it.
+    pub fn set_floppy_write_back(&mut self, drive: usize, mode: FloppyWriteBack) {
+        self.gui.set_floppy_write_back(drive, mode);
+        if let Some(mount) = self.floppy_mounts.get_mut(&drive) {
+            mount.set_mode(mode);
+        }
+    }
+
+    /// Restore the backup of the image in the specified drive over the image file, then reload it.
+    /// This undoes every change written back since the image was loaded.
+    pub fn restore_floppy_backup(&mut self, drive: usize) -> Result<PathBuf, Error> {
+        let mount = self
+            .floppy_mounts
+            .get_mut(&drive)
+            .ok_or(anyhow!("No floppy image file is loaded in drive {}", drive))?;
+        mount.restore_backup()?;
+        let path = mount.path().to_path_buf();
+
+        // The image in the drive no longer matches its file, so stop tracking it without writing
+        // it back. The caller reloads the restored file.
+        self.floppy_mounts.remove(&drive);
+        Ok(path)
+    }
+
+    /// Eject the floppy image in the specified drive.
+    pub fn eject_floppy(&mut self, drive: usize) {
+        self.release_floppy(drive);
+        if let Some(fdc) = self.machine.fdc() {
+            fdc.unload_image(drive);
+            self.gui.set_floppy_selection(
+                drive,
+                None,
+                FloppyDriveSelection::None,
+                None,
+                Vec::new(),
+                Some(false),
+            );
+            self.gui.osd().post("Floppy ejected!");
+        }
+    }
+
+    /// Resolve the path to the saved display settings.
+    fn display_settings_path(&self) -> Option<PathBuf> {
+        let mut path = self.rm.get_resource_path("display")?;
+        path.push("display.toml");
+        Some(path)
+    }
+
+    /// Restore the scaler mode of each display target saved for the current machine.
+    pub fn init_display_settings(&mut self) {
+        let path = match self.display_settings_path() {
+            Some(path) if path.exists() => path,
+            _ => return,
+        };
+        let settings = match DisplaySettings::load(&path) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::warn!("Couldn't read display settings file {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        if let Some(machine) = settings.get(&self.config.machine.config_name) {
+            for display in machine.display.iter() {
+                log::debug!(
+                    "Restoring scaler mode {:?} for display target {}",
+                    display.scaler_mode,
+                    display.idx
+                );
+                if let Err(e) = self.dm.set_scaler_mode(display.idx, display.scaler_mode) {
+                    log::warn!("Couldn't restore scaler mode for display target {}: {}", display.idx, e);
+                }
+            }
+        }
+    }
+
+    /// Save the scaler mode of a display target for the current machine. Saved settings for other
+    /// machines are kept.
+    pub fn save_scaler_mode(&mut self, dt_idx: usize, mode: ScalerMode) -> Result<PathBuf, Error> {
+        let path = self
+            .display_settings_path()
+            .ok_or(anyhow!("No 'display' resource path defined."))?;
+
+        let mut settings = DisplaySettings::load(&path).unwrap_or_default();
+        settings.set_scaler_mode(&self.config.machine.config_name, dt_idx, mode);
+        settings.save(&path)?;
+        Ok(path)
+    }
+
+    /// Resolve the path to the saved session state.
+    fn session_path(&self) -> Option<PathBuf> {
+        let mut path = self.rm.get_resource_path("session")?;
+        path.push("session.toml");
+        Some(path)
+    }
+
+    /// Restore the media, main window geometry and GUI windows saved at the end of the last session
+    /// with the current machine. Hard disks mounted from the configuration take precedence over
+    /// saved ones.
+    pub fn init_session(&mut self) {
+        if !self.config.emulator.remember_session {
+            return;
+        }
+        let path = match self.session_path() {
+            Some(path) if path.exists() => path,
+            _ => return,
+        };
+        let session = match SessionState::load(&path) {
+            Ok(state) => match state.get(&self.config.machine.config_name) {
+                Some(session) => session.clone(),
+                None => return,
+            },
+            Err(e) => {
+                log::warn!("Couldn't read session file {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        for hdd in session.hdd.iter() {
+            if self.vhd_manager.is_drive_loaded(hdd.drive) || self.host_volumes.contains_key(&hdd.drive) {
+                continue;
+            }
+            if let Err(e) = self.mount_vhd_by_name(hdd.drive, &OsString::from(&hdd.filename)) {
+                log::warn!(
+                    "Couldn't restore VHD {} in hard disk {}: {}",
+                    hdd.filename,
+                    hdd.drive,
+                    e
+                );
+            }
+        }
+
+        for floppy in session.floppy.iter() {
+            if let Err(e) = self.restore_floppy(floppy) {
+                log::warn!(
+                    "Couldn't restore {} in floppy drive {}: {}",
+                    floppy.path.display(),
+                    floppy.drive,
+                    e
+                );
+            }
+        }
+
+        if let Some(geometry) = session.window {
+            self.restore_window_geometry(&geometry);
+        }
+        self.gui.restore_workspace_layout(&session.gui_window);
+    }
+
+    fn restore_floppy(&mut self, floppy: &FloppySession) -> Result<(), Error> {
+        match floppy.source {
+            FloppyMediaSource::Image => {
+                self.load_floppy_immediate(floppy.drive, &floppy.path)?;
+                if let Some(fdc) = self.machine.fdc() {
+                    fdc.write_protect(floppy.drive, floppy.write_protect);
+                }
+                self.gui.set_floppy_write_protected(floppy.drive, floppy.write_protect);
+                self.track_floppy(floppy.drive, &floppy.path);
+            }
+            FloppyMediaSource::ZipArchive => self.mount_zip(floppy.drive, &floppy.path, floppy.image.as_deref())?,
+            FloppyMediaSource::Directory => self.mount_floppy_dir(floppy.drive, &floppy.path)?,
+        }
+        log::info!("Restored {} in floppy drive {}", floppy.path.display(), floppy.drive);
+        Ok(())
+    }
+
+    fn restore_window_geometry(&mut self, geometry: &WindowGeometry) {
+        if let Some(window) = self.dm.get_main_window() {
+            if let Some([x, y]) = geometry.position {
+                window.set_outer_position(PhysicalPosition::new(x, y));
+            }
+            let _ = window.request_inner_size(PhysicalSize::new(geometry.size[0], geometry.size[1]));
+            if geometry.maximized {
+                window.set_maximized(true);
+            }
+        }
+    }
+
+    /// Return the geometry of the main window, or None if it is fullscreen, as the fullscreen size
+    /// isn't the one to restore.
+    fn main_window_geometry(&self) -> Option<WindowGeometry> {
+        let window = self.dm.get_main_window()?;
+        if window.fullscreen().is_some() {
+            return None;
+        }
+        let size = window.inner_size();
+        Some(WindowGeometry {
+            position: window.outer_position().ok().map(|pos| [pos.x, pos.y]),
+            size: [size.width, size.height],
+            maximized: window.is_maximized(),
+        })
+    }
+
+    /// Save the media mounted, the main window geometry and the open GUI windows, to be restored
+    /// in the next session with the current machine. Sessions saved for other machines are kept.
+    pub fn save_session(&mut self) {
+        if !self.config.emulator.remember_session {
+            return;
+        }
+        let path = match self.session_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let mut state = SessionState::load(&path).unwrap_or_default();
+        let name = self.config.machine.config_name.clone();
+
+        let floppy = self
+            .floppy_media
+            .values()
+            .map(|floppy| FloppySession {
+                write_protect: self.gui.floppy_write_protected(floppy.drive),
+                ..floppy.clone()
+            })
+            .collect();
+        let hdd = (0..self.machine.bus().hdd_ct())
+            .filter_map(|drive| {
+                let filename = self.vhd_manager.drive_vhd_path(drive)?.file_name()?;
+                Some(HddSession {
+                    drive,
+                    filename: filename.to_string_lossy().to_string(),
+                })
+            })
+            .collect();
+        // Keep the last saved geometry if the window is fullscreen.
+        let window = self
+            .main_window_geometry()
+            .or_else(|| state.get(&name).and_then(|session| session.window));
+
+        state.set(MachineSession {
+            name,
+            window,
+            floppy,
+            hdd,
+            gui_window: self.gui.workspace_layout(),
+        });
+        match state.save(&path) {
+            Ok(_) => log::debug!("Saved session to {}", path.display()),
+            Err(e) => log::error!("Couldn't save session to {}: {}", path.display(), e),
+        }
+    }
+
+    /// Start capturing sound output to a new WAV file, or stop the capture in progress. If `stems`
+    /// is set, each sound source is also written to its own file.
+    pub fn toggle_audio_capture(&mut self, stems: bool) {
+        let si = match self.si.as_mut() {
+            Some(si) => si,
+            None => {
+                self.gui
+                    .toasts()
+                    .error("Sound is disabled.".to_string())
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                return;
+            }
+        };
+
+        let result = if si.is_capturing() {
+            si.stop_capture().map(|paths| {
+                log::info!("Stopped audio capture. Wrote {} file(s).", paths.len());
+                "Audio capture saved.".to_string()
+            })
+        }
+        else {
+            self.rm
+                .get_available_filename("audio_capture", "capture", Some("wav"))
+                .and_then(|path| si.start_capture(&path, stems))
+                .map(|paths| {
+                    log::info!("Started audio capture: {:?}", paths);
+                    "Audio capture started.".to_string()
+                })
+        };
+
+        match result {
+            Ok(msg) => {
+                self.gui.osd().post(msg);
+            }
+            Err(e) => {
+                log::error!("Audio capture failed: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Audio capture failed: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    /// Start recording the primary video card to a new file in the configured video capture
+    /// format, or stop the recording in progress.
+    pub fn toggle_video_capture(&mut self) {
+        let result = match self.video_recorder.take() {
+            Some((_, recorder)) => {
+                let path = recorder.path().to_path_buf();
+                recorder.stop().map(|frames| {
+                    log::info!("Stopped video capture. Wrote {} frames to {}", frames, path.display());
+                    "Video capture saved.".to_string()
+                })
+            }
+            None => self
+                .start_video_capture()
+                .map(|format| format!("{} video capture started.", format)),
+        };
+        self.gui.set_video_capture_active(self.video_recorder.is_some());
+
+        match result {
+            Ok(msg) => {
+                self.gui.osd().post(msg);
+            }
+            Err(e) => {
+                log::error!("Video capture failed: {}", e);
+                self.gui
+                    .toasts()
+                    .error(format!("Video capture failed: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
+    fn start_video_capture(&mut self) -> Result<String, Error> {
+        let vid = self
+            .machine
+            .bus()
+            .primary_video_id()
+            .ok_or(anyhow!("No video card present."))?;
+        let rate = self
+            .machine
+            .bus()
+            .video(&vid)
+            .map(|video| video.get_refresh_rate())
+            .ok_or(anyhow!("No video card present."))?;
+        let dims = self
+            .dm
+            .get_primary_renderer()
+            .map(capture_dimensions)
+            .ok_or(anyhow!("No renderer for the primary video card."))?;
+
+        let config = &self.config.emulator.video_capture;
+        let path = self
+            .rm
+            .get_available_filename("video_capture", "video_capture", config.format.extension())?;
+        let recorder = VideoRecorder::start(config.format, &path, dims, rate as f64, config.ffmpeg_path.as_deref())?;
+
+        log::info!("Started video capture: {}", path.display());
+        let format = recorder.format().to_string();
+        self.video_recorder = Some((vid, recorder));
+        Ok(format)
+    }
+
+    /// Stop the recording in progress after a frame could not be submitted, reporting the error.
+    pub fn abort_video_capture(&mut self, err: Error) {
+        if let Some((_, recorder)) = self.video_recorder.take() {
+            // The writer's own error, if any, is more useful than the submit failure.
+            let err = recorder.stop().err().unwrap_or(err);
+            log::error!("Video capture stopped: {}", err);
+            self.gui
+                .toasts()
+                .error(format!("Video capture stopped: {}", err))
+                .set_duration(Some(LONG_NOTIFICATION_TIME));
+        }
+        self.gui.set_video_capture_active(false);
+    }
+
+    /// Request a screenshot of the specified display target, embedding machine and video mode
+    /// metadata. If `raw` is set, the video card's raw framebuffer is saved alongside it.
+    /// Returns the path the screenshot will be written to on the next rendered frame.
+    pub fn take_screenshot(&mut self, dt_idx: usize, raw: bool) -> Result<PathBuf, Error> {
+        let path = self
+            .rm
+            .get_resource_path("screenshot")
+            .ok_or(anyhow!("No screenshot directory configured."))?;
+
+        let mut metadata = vec![
+            ("Software".to_string(), format!("MartyPC {}", env!("CARGO_PKG_VERSION"))),
+            ("Machine".to_string(), self.config.machine.config_name.clone()),
+            (
+                "Machine Type".to_string(),
+                format!("{:?}", self.machine.config().machine_type),
+            ),
+        ];
+
+        let vid = self
+            .dm
+            .get_display_info(&self.machine)
+            .get(dt_idx)
+            .and_then(|info| info.vid);
+        if let Some(video) = vid.and_then(|vid| self.machine.bus().video(&vid)) {
+            metadata.push(("Display Mode".to_string(), format!("{:?}", video.get_display_mode())));
+            metadata.push(("Frame".to_string(), video.get_frame_count().to_string()));
+        }
+
+        self.dm
+            .save_screenshot(dt_idx, path, ScreenshotOptions { raw, metadata })
+    }
+
+    /// Resolve a file name in the network capture directory.
+    pub fn capture_path(&self, file: &str) -> Option<PathBuf> {
+        let mut path = self.rm.get_resource_path("capture")?;
+        path.push(file);
+        Some(path)
+    }
+
+    /// Export the text displayed by the specified video card, or by the primary video card if
+    /// None, to a new file in the screenshot directory. Returns the path of the file written.
+    pub fn export_text_screen(
+        &mut self,
+        card_idx: Option<usize>,
+        format: TextExportFormat,
+        full_buffer: bool,
+    ) -> Result<PathBuf, Error> {
+        let bus = self.machine.bus();
+        let vid = match card_idx {
+            Some(idx) => bus.enumerate_videocards().into_iter().find(|vid| vid.idx == idx),
+            None => bus.primary_video_id(),
+        }
+        .ok_or(anyhow!("No video card present."))?;
+
+        let screen = bus
+            .video(&vid)
+            .and_then(|video| video.get_text_mode_screen(full_buffer))
+            .ok_or(anyhow!("Video card is not in a supported text mode."))?;
+
+        let path = self
+            .rm
+            .get_resource_path("screenshot")
+            .ok_or(anyhow!("No screenshot directory configured."))?;
+        let filename = file_util::find_unique_filename(&path, "textscreen", format.extension());
+        std::fs::write(&filename, export_text_screen(&screen, format))?;
+        Ok(filename)
+    }
+
+    /// Load a DOS program from the host into memory and start it. The guest should be sitting at a
+    /// DOS prompt, as the program replaces whatever the CPU was doing.
+    pub fn quick_load_program(&mut self, path: &Path, args: &str) -> Result<ProgramFormat, Error> {
+        let segment = self.config.emulator.quick_load.segment;
+        let format = quick_load(&mut self.machine, path, args, segment)?;
+        log::info!("Quick-loaded {:?} program {}", format, path.display());
+        self.gui.osd().post(format!(
+            "Running {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        Ok(format)
+    }
+
+    /// Start the program from the quick_load config once the primary display shows a DOS prompt.
+    pub fn update_quick_load(&mut self) {
+        let path = match &self.quick_load_pending {
+            Some(path) if matches!(self.machine.get_state(), MachineState::On) => path.clone(),
+            _ => return,
+        };
+
+        let bus = self.machine.bus();
+        let at_prompt = bus
+            .primary_video_id()
+            .and_then(|vid| bus.video(&vid))
+            .and_then(|video| video.get_text_mode_screen(false))
+            .is_some_and(|screen| {
+                let text = text_screen_to_string(&screen, None);
+                at_dos_prompt(&text, self.config.emulator.quick_load.prompt.as_deref())
+            });
+        if !at_prompt {
+            return;
+        }
+
+        self.quick_load_pending = None;
+        let args = self.config.emulator.quick_load.args.clone().unwrap_or_default();
+        if let Err(e) = self.quick_load_program(&path, &args) {
+            log::error!("Failed to quick-load {}: {}", path.display(), e);
+            self.gui
+                .toasts()
+                .error(format!("Failed to quick-load program: {}", e))
+                .set_duration(Some(LONG_NOTIFICATION_TIME));
+        }
+    }
+
+    /// Start capturing and replaying network traffic, if configured for the machine's network card.
+    pub fn init_network_tap(&mut self) {
+        let nic_config = match self.machine.config().network.clone() {
+            Some(nic_config) => nic_config,
+            None => return,
+        };
+
+        if let Some(path) = nic_config.capture.and_then(|file| self.capture_path(&file)) {
+            if let Err(e) = self.machine.start_network_capture(&path) {
+                log::error!("Failed to start network capture: {}", e);
+            }
+        }
+        if let Some(path) = nic_config.replay.and_then(|file| self.capture_path(&file)) {
+            if let Err(e) = self.machine.replay_network_capture(&path) {
+                log::error!("Failed to replay network capture: {}", e);
+            }
+        }
+    }
+
+    /// Start capturing printer output, if configured for the machine's parallel port.
+    pub fn init_printer(&mut self) {
+        let capture = self.machine.config().parallel.as_ref().and_then(|lpt| lpt.capture.clone());
+        if let Some(file) = capture {
+            let path = match self.rm.get_resource_path("print") {
+                Some(mut path) => {
+                    path.push(file);
+                    path
+                }
+                None => {
+                    log::error!("No 'print' resource path defined; can't capture printer output.");
+                    return;
+                }
+            };
+            if let Err(e) = self.machine.start_printer_capture(&path) {
+                log::error!("Failed to start printer capture: {}", e);
+            }
+        }
+    }
+
     pub fn start(&mut self) {
         //self.machine.play_sound_buffer();
     }
@@ -0,0 +1,112 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    event_loop/automation.rs
+
+    Service the JSON-RPC automation server. Machine requests are handled by
+    frontend_common; this module handles the requests that need the rest of
+    the emulator, such as mounting media.
+*/
+
+use std::path::Path;
+
+use frontend_common::automation::{service, RpcError, RpcRequest, RpcResult};
+use serde_json::{json, Value};
+
+use crate::{event_loop::console::run_console_command, Emulator};
+
+/// Process requests received from automation clients. Called once per frame.
+pub fn process_automation(emu: &mut Emulator) {
+    let mut server = match emu.automation.take() {
+        Some(server) => server,
+        None => return,
+    };
+
+    let requests = service(&mut server, &mut emu.machine, &mut emu.exec_control.borrow_mut());
+    for request in requests {
+        let result = handle_request(emu, &request);
+        server.respond(&request, result);
+    }
+
+    emu.automation = Some(server);
+}
+
+fn handle_request(emu: &mut Emulator, request: &RpcRequest) -> RpcResult {
+    match request.method.as_str() {
+        "mount_floppy" => {
+            let drive = request.u64_param("drive")? as usize;
+            let path = request.str_param("path")?;
+            emu.load_floppy_immediate(drive, Path::new(path))
+                .map_err(|e| RpcError::failed(format!("Failed to load floppy: {}", e)))?;
+            Ok(Value::Null)
+        }
+        "eject_floppy" => {
+            let drive = request.u64_param("drive")? as usize;
+            let drive_ct = emu.machine.fdc().as_ref().map_or(0, |fdc| fdc.drive_ct());
+            if drive >= drive_ct {
+                return Err(RpcError::invalid_params(format!("Invalid drive: {}", drive)));
+            }
+            emu.eject_floppy(drive);
+            Ok(Value::Null)
+        }
+        "swap_floppy" => {
+            let drive = request.u64_param("drive")? as usize;
+            let disk = match request.param("disk") {
+                Some(_) => Some(request.u64_param("disk")? as usize),
+                None => None,
+            };
+            let (disk, count) = emu
+                .swap_floppy(drive, disk)
+                .map_err(|e| RpcError::failed(format!("Failed to swap floppy: {}", e)))?;
+            Ok(json!({ "disk": disk, "count": count }))
+        }
+        "get_disk_set" => {
+            let drive = request.u64_param("drive")? as usize;
+            Ok(match emu.floppy_sets.get(&drive) {
+                Some((set, disk)) => {
+                    let disks: Vec<String> = (0..set.len()).map(|idx| set.name(idx)).collect();
+                    json!({ "disk": disk, "disks": disks })
+                }
+                None => Value::Null,
+            })
+        }
+        "screenshot" => {
+            let display = match request.param("display") {
+                Some(_) => request.u64_param("display")? as usize,
+                None => 0,
+            };
+            let path = emu
+                .take_screenshot(display, false)
+                .map_err(|e| RpcError::failed(format!("Failed to take screenshot: {}", e)))?;
+            Ok(json!({ "path": path }))
+        }
+        "console" => {
+            let command = request.str_param("command")?;
+            Ok(json!(run_console_command(emu, command)))
+        }
+        method => Err(RpcError::method_not_found(method)),
+    }
+}
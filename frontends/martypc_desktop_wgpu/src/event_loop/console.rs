@@ -0,0 +1,292 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    event_loop/console.rs
+
+    Execute debugger console commands received from the GUI, from command
+    scripts, or from the console automation socket.
+*/
+
+use std::path::PathBuf;
+
+use frontend_common::{
+    debug_console::{ConsoleBreakpoint, ConsoleCommand, ConsoleRegister, CONSOLE_HELP},
+    script::SCRIPT_HELP,
+};
+use marty_core::{
+    cpu_common::{Cpu, Register16},
+    machine::{ExecutionOperation, ExecutionState},
+};
+
+use crate::{
+    event_loop::script::{clear_scripts, eval_script, run_script_file},
+    Emulator,
+};
+
+/// The maximum number of bytes the 'dump' command will display in the console.
+const MAX_DISPLAY_DUMP: usize = 0x1000;
+
+/// Process queued script commands and commands received over the console socket.
+/// Called once per frame.
+pub fn process_console(emu: &mut Emulator) {
+    while let Some(line) = emu.console.next_pending() {
+        let output = run_console_command(emu, &line);
+        emu.gui.debug_console.push_output(&output);
+    }
+
+    let lines = match emu.console_server.as_mut() {
+        Some(server) => server.poll(),
+        None => return,
+    };
+    for (client, line) in lines {
+        let output = run_console_command(emu, &line);
+        if let Some(server) = emu.console_server.as_mut() {
+            server.respond(client, &output);
+        }
+    }
+}
+
+/// Execute a single console command line and return its output.
+pub fn run_console_command(emu: &mut Emulator, line: &str) -> Vec<String> {
+    let command = match ConsoleCommand::parse(line) {
+        Ok(Some(command)) => command,
+        Ok(None) => return Vec::new(),
+        Err(e) => return vec![e],
+    };
+
+    emu.console.push_history(line);
+    emu.gui
+        .debug_console
+        .set_history(emu.console.history().iter().cloned().collect());
+
+    match command {
+        ConsoleCommand::Help => CONSOLE_HELP.lines().map(|l| l.to_string()).collect(),
+        ConsoleCommand::ScriptHelp => SCRIPT_HELP.lines().map(|l| l.to_string()).collect(),
+        ConsoleCommand::Breakpoint(bp) => {
+            if let ConsoleBreakpoint::Execute(addr) | ConsoleBreakpoint::MemAccess(addr) = &bp {
+                if emu.machine.cpu().eval_address(addr).is_none() {
+                    return vec![format!("Invalid address: {}", addr)];
+                }
+            }
+            if !emu.console.add_breakpoint(bp) {
+                return vec!["Breakpoint already set.".to_string()];
+            }
+            emu.apply_breakpoints();
+            vec![format!("Breakpoint {} set.", emu.console.breakpoints().len() - 1)]
+        }
+        ConsoleCommand::ListBreakpoints => {
+            if emu.console.breakpoints().is_empty() {
+                return vec!["No breakpoints set.".to_string()];
+            }
+            emu.console
+                .breakpoints()
+                .iter()
+                .enumerate()
+                .map(|(i, bp)| match bp {
+                    ConsoleBreakpoint::Execute(addr) => format!("{}: exec {}", i, addr),
+                    ConsoleBreakpoint::MemAccess(addr) => format!("{}: mem {}", i, addr),
+                    ConsoleBreakpoint::Interrupt(vector) => format!("{}: int {:02X}h", i, vector),
+                    ConsoleBreakpoint::IoAccess(port) => format!("{}: io {:04X}h", i, port),
+                })
+                .collect()
+        }
+        ConsoleCommand::ClearBreakpoints(idx) => {
+            if !emu.console.clear_breakpoints(idx) {
+                return vec!["Invalid breakpoint number.".to_string()];
+            }
+            emu.apply_breakpoints();
+            vec!["Breakpoints cleared.".to_string()]
+        }
+        ConsoleCommand::Dump(addr, len, file) => dump(emu, &addr, len, file),
+        ConsoleCommand::ShowRegisters => show_registers(emu),
+        ConsoleCommand::SetRegisters(regs) => {
+            let cpu = emu.machine.cpu_mut();
+            for (reg, val) in regs {
+                match reg {
+                    ConsoleRegister::Reg16(reg) => cpu.set_register16(reg, val),
+                    ConsoleRegister::Reg8(reg) => cpu.set_register8(reg, val as u8),
+                    ConsoleRegister::Flags => cpu.set_flags(val),
+                }
+            }
+            show_registers(emu)
+        }
+        ConsoleCommand::Step(n) => {
+            let mut exec_control = emu.exec_control.borrow_mut();
+            if !exec_control.get_state().can_step() {
+                return vec!["Machine must be paused to step.".to_string()];
+            }
+            let mut stepped = 0;
+            while stepped < n {
+                exec_control.set_op(ExecutionOperation::Step);
+                emu.machine.run(1, &mut exec_control);
+                stepped += 1;
+                if let ExecutionState::BreakpointHit = exec_control.get_state() {
+                    break;
+                }
+            }
+            drop(exec_control);
+            let mut output = vec![format!("Stepped {} instruction(s).", stepped)];
+            output.extend(show_registers(emu));
+            output
+        }
+        ConsoleCommand::Run => {
+            emu.exec_control.borrow_mut().set_op(ExecutionOperation::Run);
+            vec!["Running.".to_string()]
+        }
+        ConsoleCommand::Pause => {
+            emu.exec_control.borrow_mut().set_op(ExecutionOperation::Pause);
+            vec!["Paused.".to_string()]
+        }
+        ConsoleCommand::Exec(file, args) => match emu.quick_load_program(&PathBuf::from(&file), &args) {
+            Ok(format) => vec![format!("Running {:?} program {}", format, file)],
+            Err(e) => vec![format!("Failed to load program: {}", e)],
+        },
+        ConsoleCommand::Reset => {
+            emu.exec_control.borrow_mut().set_op(ExecutionOperation::Reset);
+            vec!["Reset.".to_string()]
+        }
+        ConsoleCommand::Script(file) => match emu.console.queue_script(&PathBuf::from(&file)) {
+            Ok(ct) => vec![format!("Queued {} line(s) from {}", ct, file)],
+            Err(e) => vec![format!("Failed to read script '{}': {}", file, e)],
+        },
+        ConsoleCommand::RunScript(Some(file)) => run_script_file(emu, &PathBuf::from(&file)),
+        ConsoleCommand::RunScript(None) => {
+            clear_scripts(emu);
+            vec!["Scripts cleared.".to_string()]
+        }
+        ConsoleCommand::Eval(code) => eval_script(emu, &code),
+        ConsoleCommand::NetCapture(Some(file)) => {
+            let path = match emu.capture_path(&file) {
+                Some(path) => path,
+                None => return vec!["No capture directory configured.".to_string()],
+            };
+            match emu.machine.start_network_capture(&path) {
+                Ok(_) => vec![format!("Capturing network traffic to {}", path.display())],
+                Err(e) => vec![format!("Failed to start capture: {}", e)],
+            }
+        }
+        ConsoleCommand::NetCapture(None) => match emu.machine.stop_network_capture() {
+            Some((path, frames)) => vec![format!("Wrote {} frames to {}", frames, path.display())],
+            None => vec!["No capture in progress.".to_string()],
+        },
+        ConsoleCommand::NetReplay(file) => {
+            let path = match emu.capture_path(&file) {
+                Some(path) => path,
+                None => return vec!["No capture directory configured.".to_string()],
+            };
+            match emu.machine.replay_network_capture(&path) {
+                Ok(frames) => vec![format!("Replaying {} frames from {}", frames, path.display())],
+                Err(e) => vec![format!("Failed to replay capture: {}", e)],
+            }
+        }
+        ConsoleCommand::TextExport(format, full_buffer) => match emu.export_text_screen(None, format, full_buffer) {
+            Ok(path) => vec![format!("Wrote text screen to {}", path.display())],
+            Err(e) => vec![format!("Failed to export text screen: {}", e)],
+        },
+        ConsoleCommand::CopyText(selection) => match emu.copy_text_screen(None, selection) {
+            Ok(chars) => vec![format!("Copied {} characters to the clipboard", chars)],
+            Err(e) => vec![format!("Failed to copy text screen: {}", e)],
+        },
+        ConsoleCommand::Screenshot(dt_idx, raw) => match emu.take_screenshot(dt_idx, raw) {
+            Ok(path) => vec![format!("Saving screenshot to {}", path.display())],
+            Err(e) => vec![format!("Failed to take screenshot: {}", e)],
+        },
+    }
+}
+
+fn show_registers(emu: &mut Emulator) -> Vec<String> {
+    let ip = emu.machine.cpu_mut().get_ip();
+    let cpu = emu.machine.cpu();
+    let r = |reg| cpu.get_register16(reg);
+    vec![
+        format!(
+            "AX={:04X} BX={:04X} CX={:04X} DX={:04X} SP={:04X} BP={:04X} SI={:04X} DI={:04X}",
+            r(Register16::AX),
+            r(Register16::BX),
+            r(Register16::CX),
+            r(Register16::DX),
+            r(Register16::SP),
+            r(Register16::BP),
+            r(Register16::SI),
+            r(Register16::DI)
+        ),
+        format!(
+            "DS={:04X} ES={:04X} SS={:04X} CS={:04X} IP={:04X} FLAGS={:04X}",
+            r(Register16::DS),
+            r(Register16::ES),
+            r(Register16::SS),
+            r(Register16::CS),
+            ip,
+            cpu.get_flags()
+        ),
+    ]
+}
+
+fn dump(emu: &mut Emulator, addr: &str, len: usize, file: Option<String>) -> Vec<String> {
+    let start = match emu.machine.cpu().eval_address(addr) {
+        Some(addr) => u32::from(addr) & 0xFFFFF,
+        None => return vec![format!("Invalid address: {}", addr)],
+    };
+    if len == 0 {
+        return Vec::new();
+    }
+    let len = len.min(0x100000 - start as usize);
+
+    if let Some(file) = file {
+        let mut path = match emu.rm.get_resource_path("dump") {
+            Some(path) => path,
+            None => return vec!["No dump directory configured.".to_string()],
+        };
+        path.push(&file);
+        emu.machine
+            .bus()
+            .dump_mem_range(start, start + len as u32 - 1, &path);
+        return vec![format!("Wrote {} bytes at {:05X} to {}", len, start, path.display())];
+    }
+
+    let display_len = len.min(MAX_DISPLAY_DUMP);
+    let bytes = match emu.machine.bus().peek_range(start as usize, display_len) {
+        Ok(bytes) => bytes,
+        Err(_) => return vec![format!("Failed to read memory at {:05X}", start)],
+    };
+
+    let mut output: Vec<String> = bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex = chunk.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            format!("{:05X}  {:<47}  {}", start as usize + i * 16, hex, ascii)
+        })
+        .collect();
+    if display_len < len {
+        output.push(format!("({} bytes not shown, specify a file to dump more)", len - display_len));
+    }
+    output
+}
@@ -29,19 +29,24 @@
     Process received egui events.
 */
 
-use crate::{event_loop::thread_events::FrontendThreadEvent, Emulator};
+use crate::{
+    event_loop::{console, thread_events::FrontendThreadEvent},
+    Emulator,
+};
 use display_manager_wgpu::DisplayManager;
 use fluxfox::{DiskImage, LoadingStatus};
 use frontend_common::{
     constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME, SHORT_NOTIFICATION_TIME},
-    types::floppy::FloppyImageSource,
+    floppy_manager::{convert, create_formatted_image_file, sanitize_label},
+    movie::MovieInput,
+    types::{floppy::FloppyImageSource, session::FloppyMediaSource},
+    vhd_manager::fat_disk,
 };
 use marty_core::{
-    breakpoints::BreakPointType,
     cpu_common,
     cpu_common::{Cpu, CpuOption, Register16},
-    device_traits::videocard::ClockingMode,
-    device_types::fdc::FloppyImageType,
+    device_traits::videocard::{ClockingMode, VideoOption},
+    devices::hdc::ControllerError,
     machine::{MachineOption, MachineState},
     vhd,
     vhd::VirtualHardDisk,
@@ -55,9 +60,10 @@ use marty_egui::{
     GuiEvent,
     GuiVariable,
     GuiVariableContext,
+    GuiWindow,
     InputFieldChangeSource,
 };
-use std::{ffi::OsString, io::Cursor, mem::discriminant, path::PathBuf, sync::Arc, time::Duration};
+use std::{io::Cursor, mem::discriminant, path::PathBuf, sync::Arc, time::Duration};
 use videocard_renderer::AspectCorrectionMode;
 use winit::event_loop::EventLoopWindowTarget;
 
@@ -115,6 +121,9 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                         if let Err(_e) = emu.dm.set_scaler_mode(*d_idx, *new_mode) {
                             log::error!("Failed to set scaler mode for display target!");
                         }
+                        else if let Err(e) = emu.save_scaler_mode(*d_idx, *new_mode) {
+                            log::warn!("Couldn't save scaler mode: {}", e);
+                        }
                     }
                     GuiEnum::DisplayScalerPreset(new_preset) => {
                         log::debug!("Got scaler preset update event: {:?}", new_preset);
@@ -173,31 +182,24 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
 
             match emu.vhd_manager.load_vhd_file(*drive_idx, *image_idx) {
                 Ok(vhd_file) => match VirtualHardDisk::from_file(vhd_file) {
-                    Ok(vhd) => {
-                        if let Some(hdc) = emu.machine.hdc() {
-                            match hdc.set_vhd(*drive_idx, vhd) {
-                                Ok(_) => {
-                                    let vhd_name = emu.vhd_manager.get_vhd_name(*image_idx).unwrap();
-                                    log::info!(
-                                        "VHD image {:?} successfully loaded into virtual drive: {}",
-                                        vhd_name,
-                                        *drive_idx
-                                    );
-
-                                    emu.gui
-                                        .toasts()
-                                        .info(format!("VHD loaded: {:?}", vhd_name))
-                                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                                }
-                                Err(err) => {
-                                    error_str = Some(format!("Error mounting VHD: {}", err));
-                                }
-                            }
+                    Ok(vhd) => match emu.machine.mount_vhd(*drive_idx, vhd) {
+                        Ok(_) => {
+                            let vhd_name = emu.vhd_manager.get_vhd_name(*image_idx).unwrap();
+                            log::info!(
+                                "VHD image {:?} successfully loaded into virtual drive: {}",
+                                vhd_name,
+                                *drive_idx
+                            );
+
+                            emu.gui.osd().post(format!("VHD loaded: {:?}", vhd_name));
                         }
-                        else {
+                        Err(ControllerError::NoController) => {
                             error_str = Some("No Hard Disk Controller present!".to_string());
                         }
-                    }
+                        Err(err) => {
+                            error_str = Some(format!("Error mounting VHD: {}", err));
+                        }
+                    },
                     Err(err) => {
                         error_str = Some(format!("Error loading VHD: {}", err));
                     }
@@ -216,18 +218,51 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     .set_duration(Some(LONG_NOTIFICATION_TIME));
             }
         }
-        GuiEvent::CreateVHD(filename, fmt) => {
-            log::info!("Got CreateVHD event: {:?}, {:?}", filename, fmt);
+        GuiEvent::LoadZipFloppy(drive_idx, path, image_name) => {
+            match emu.mount_zip(*drive_idx, path, image_name.as_deref()) {
+                Ok(_) => {
+                    emu.gui.osd().post(format!(
+                        "Floppy loaded: {}",
+                        image_name.as_deref().unwrap_or("archive contents")
+                    ));
+                }
+                Err(err) => {
+                    log::error!("Failed to mount zip archive: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Zip archive mount failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::MountHddDirectory(drive_idx, path, write_back) => {
+            if let Err(err) = emu.mount_hdd_dir(*drive_idx, path, *write_back) {
+                log::error!("Failed to mount directory: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to mount directory: {}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+            else {
+                emu.gui.osd().post(format!("Directory mounted: {}", path.display()));
+            }
+        }
+        GuiEvent::CreateVHD(filename, fmt, label) => {
+            log::info!("Got CreateVHD event: {:?}, {:?}, label: {:?}", filename, fmt, label);
 
             let mut vhd_path = emu.rm.get_resource_path("hdd").unwrap();
             vhd_path.push(filename);
 
-            match vhd::create_vhd(
-                vhd_path.into_os_string(),
-                fmt.max_cylinders,
-                fmt.max_heads,
-                fmt.max_sectors,
-            ) {
+            let result = match label {
+                Some(label) => fat_disk::create_formatted_vhd(&vhd_path, fmt, &sanitize_label(label), None),
+                None => vhd::create_vhd(
+                    vhd_path.into_os_string(),
+                    fmt.max_cylinders,
+                    fmt.max_heads,
+                    fmt.max_sectors,
+                ),
+            };
+            match result {
                 Ok(_) => {
                     // We don't actually do anything with the newly created file
                     // But show a toast notification.
@@ -240,6 +275,12 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     if let Err(e) = emu.vhd_manager.scan_resource(&emu.rm) {
                         log::error!("Error scanning hdd directory: {}", e);
                     };
+                    if let Ok(hdd_tree) = emu.vhd_manager.make_tree(&emu.rm) {
+                        emu.gui.set_hdd_tree(hdd_tree);
+                    }
+                    if emu.gui.is_window_open(GuiWindow::MediaLibrary) {
+                        emu.refresh_media_library();
+                    }
                 }
                 Err(err) => {
                     log::error!("Error creating VHD: {}", err);
@@ -250,6 +291,93 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 }
             }
         }
+        GuiEvent::CreateFloppyImage(filename, format, label) => {
+            log::info!(
+                "Got CreateFloppyImage event: {:?}, {:?}, label: {}",
+                filename,
+                format,
+                label
+            );
+
+            let mut floppy_path = emu.rm.get_resource_path("floppy").unwrap();
+            floppy_path.push(filename);
+
+            match create_formatted_image_file(&floppy_path, &sanitize_label(label), *format) {
+                Ok(_) => {
+                    emu.gui
+                        .toasts()
+                        .info(format!("Created floppy image: {}", filename.to_string_lossy()))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+
+                    // Rescan resource paths to show new file in list
+                    if let Err(e) = emu.floppy_manager.scan_resource(&emu.rm) {
+                        log::error!("Error scanning floppy directory: {}", e);
+                    }
+                    if let Ok(floppy_tree) = emu.floppy_manager.make_tree(&emu.rm) {
+                        emu.gui.set_floppy_tree(floppy_tree);
+                    }
+                    if emu.gui.is_window_open(GuiWindow::MediaLibrary) {
+                        emu.refresh_media_library();
+                    }
+                }
+                Err(err) => {
+                    log::error!("Error creating floppy image: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("{}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::InspectFloppyImage(path) => match convert::load_image(path) {
+            Ok(image) => {
+                emu.gui.floppy_converter.set_source(
+                    path.clone(),
+                    image.source_format(),
+                    image.compatible_formats(true),
+                );
+            }
+            Err(err) => {
+                log::error!("Error loading floppy image to convert: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("{}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::ConvertFloppyImage(src, format, dest) => {
+            log::info!("Converting floppy image {:?} to {:?} as {:?}", src, dest, format);
+
+            match convert::convert_image(src, Some(*format), dest) {
+                Ok(_) => {
+                    emu.gui
+                        .toasts()
+                        .info(format!(
+                            "Converted floppy image: {}",
+                            dest.file_name().unwrap_or_default().to_string_lossy()
+                        ))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+
+                    // The output may be in the floppy directory, so rescan to show it.
+                    if let Err(e) = emu.floppy_manager.scan_resource(&emu.rm) {
+                        log::error!("Error scanning floppy directory: {}", e);
+                    }
+                    if let Ok(floppy_tree) = emu.floppy_manager.make_tree(&emu.rm) {
+                        emu.gui.set_floppy_tree(floppy_tree);
+                    }
+                    if emu.gui.is_window_open(GuiWindow::MediaLibrary) {
+                        emu.refresh_media_library();
+                    }
+                }
+                Err(err) => {
+                    log::error!("Error converting floppy image: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("{}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
         GuiEvent::RescanMediaFolders => {
             if let Err(e) = emu.floppy_manager.scan_resource(&emu.rm) {
                 log::error!("Error scanning floppy directory: {}", e);
@@ -263,6 +391,12 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             if let Err(e) = emu.cart_manager.scan_resource(&emu.rm) {
                 log::error!("Error scanning cartridge directory: {}", e);
             }
+            if let Err(e) = emu.cdrom_manager.scan_resource(&emu.rm) {
+                log::error!("Error scanning cdrom directory: {}", e);
+            }
+            if let Err(e) = emu.cassette_manager.scan_resource(&emu.rm) {
+                log::error!("Error scanning cassette directory: {}", e);
+            }
             // Update Floppy Disk Image tree
             if let Ok(floppy_tree) = emu.floppy_manager.make_tree(&emu.rm) {
                 emu.gui.set_floppy_tree(floppy_tree);
@@ -276,6 +410,18 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             if let Ok(cart_tree) = emu.cart_manager.make_tree(&emu.rm) {
                 emu.gui.set_cart_tree(cart_tree);
             }
+            // Update CD-ROM Image tree
+            if let Ok(cdrom_tree) = emu.cdrom_manager.make_tree(&emu.rm) {
+                emu.gui.set_cdrom_tree(cdrom_tree);
+            }
+            // Update Cassette Image tree
+            if let Ok(cassette_tree) = emu.cassette_manager.make_tree(&emu.rm) {
+                emu.gui.set_cassette_tree(cassette_tree);
+            }
+            // The media library refers to images by their index, which a rescan may change.
+            if emu.gui.is_window_open(GuiWindow::MediaLibrary) {
+                emu.refresh_media_library();
+            }
         }
         GuiEvent::InsertCartridge(slot_select, item_idx) => {
             log::debug!("Insert Cart image: {:?} into drive: {}", item_idx, slot_select);
@@ -293,10 +439,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                                 emu.gui
                                     .set_cart_selection(*slot_select, Some(*item_idx), Some(name.clone().into()));
 
-                                emu.gui
-                                    .toasts()
-                                    .info(format!("Cartridge inserted: {:?}", name.clone()))
-                                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                                emu.gui.osd().post(format!("Cartridge inserted: {:?}", name.clone()));
 
                                 // Inserting a cartridge reboots the machine due to a switch in the cartridge slot.
                                 reboot = true;
@@ -324,6 +467,37 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 emu.machine.change_state(MachineState::Rebooting);
             }
         }
+        GuiEvent::InsertDroppedCartridge(slot_select, path) => {
+            log::info!("Loading cart image: {} into slot: {}", path.display(), slot_select);
+
+            let mut reboot = false;
+            if let Some(cart_slot) = emu.machine.cart_slot() {
+                let result = emu
+                    .cart_manager
+                    .load_cart_data_by_path(path, &emu.rm)
+                    .and_then(|cart_image| cart_slot.insert_cart(*slot_select, cart_image));
+                match result {
+                    Ok(()) => {
+                        let name = path.file_name().unwrap_or_default();
+                        emu.gui.set_cart_selection(*slot_select, None, Some(name.into()));
+                        emu.gui.osd().post(format!("Cartridge inserted: {:?}", name));
+                        // Inserting a cartridge reboots the machine due to a switch in the cartridge slot.
+                        reboot = true;
+                    }
+                    Err(err) => {
+                        log::error!("Cart image failed to load into slot {}: {}", slot_select, err);
+                        emu.gui
+                            .toasts()
+                            .error(format!("Cartridge load failed: {}", err))
+                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                    }
+                }
+            }
+
+            if reboot {
+                emu.machine.change_state(MachineState::Rebooting);
+            }
+        }
         GuiEvent::RemoveCartridge(slot_select) => {
             log::info!("Removing cartridge from slot: {}", slot_select);
 
@@ -331,10 +505,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             if let Some(cart_slot) = emu.machine.cart_slot() {
                 cart_slot.remove_cart(*slot_select);
                 emu.gui.set_cart_selection(*slot_select, None, None);
-                emu.gui
-                    .toasts()
-                    .info("Cartridge removed!".to_string())
-                    .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                emu.gui.osd().post("Cartridge removed!");
 
                 reboot = true;
             }
@@ -342,9 +513,127 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 emu.machine.change_state(MachineState::Rebooting);
             }
         }
+        GuiEvent::LoadCdImage(item_idx) => {
+            if let (Some(name), Some(path)) = (
+                emu.cdrom_manager.get_image_name(*item_idx),
+                emu.cdrom_manager.get_image_path(*item_idx),
+            ) {
+                log::info!("Loading CD image: {:?}", name);
+
+                match emu.machine.load_cd_image(&path) {
+                    Ok(()) => {
+                        emu.gui.set_cdrom_selection(Some(*item_idx), Some(name.clone().into()));
+                        emu.gui.osd().post(format!("CD inserted: {:?}", name));
+                    }
+                    Err(err) => {
+                        log::error!("CD image failed to load: {}", err);
+                        emu.gui
+                            .toasts()
+                            .error(format!("CD image load failed: {}", err))
+                            .set_duration(Some(LONG_NOTIFICATION_TIME));
+                    }
+                }
+            }
+        }
+        GuiEvent::EjectCdImage => {
+            if let Some(path) = emu.machine.eject_cd_image() {
+                log::info!("Ejected CD image: {:?}", path);
+            }
+            emu.gui.set_cdrom_selection(None, None);
+            emu.gui.osd().post("CD ejected!");
+        }
+        GuiEvent::LoadCassette(item_idx) => {
+            if let (Some(name), Some(path), Some(cassette)) = (
+                emu.cassette_manager.get_image_name(*item_idx),
+                emu.cassette_manager.get_image_path(*item_idx),
+                emu.machine.cassette().as_mut(),
+            ) {
+                log::info!("Loading cassette image: {:?}", name);
+
+                match cassette.load(&path) {
+                    Ok(()) => {
+                        emu.gui.osd().post(format!("Tape inserted: {:?}", name));
+                    }
+                    Err(err) => {
+                        log::error!("Cassette image failed to load: {}", err);
+                        emu.gui
+                            .toasts()
+                            .error(format!("Cassette image load failed: {}", err))
+                            .set_duration(Some(LONG_NOTIFICATION_TIME));
+                    }
+                }
+            }
+        }
+        GuiEvent::NewCassette => {
+            if let Some(cassette) = emu.machine.cassette().as_mut() {
+                cassette.new_tape();
+            }
+        }
+        GuiEvent::SaveCassette => {
+            // Save over the inserted image, or to a new file in the cassette directory for a blank tape.
+            let save_path = emu
+                .machine
+                .cassette()
+                .as_ref()
+                .and_then(|cassette| cassette.status().image)
+                .or_else(|| emu.cassette_manager.get_save_path(&emu.rm));
+
+            if let (Some(path), Some(cassette)) = (save_path, emu.machine.cassette().as_mut()) {
+                match cassette.save(&path) {
+                    Ok(()) => {
+                        log::info!("Saved cassette image: {}", path.display());
+                        emu.gui
+                            .toasts()
+                            .info(format!("Tape saved: {}", path.display()))
+                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                        // Rescan so a new recording shows up in the Insert Tape menu.
+                        if emu.cassette_manager.scan_resource(&emu.rm).is_ok() {
+                            if let Ok(cassette_tree) = emu.cassette_manager.make_tree(&emu.rm) {
+                                emu.gui.set_cassette_tree(cassette_tree);
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("Failed to save cassette image: {}", err);
+                        emu.gui
+                            .toasts()
+                            .error(format!("Tape save failed: {}", err))
+                            .set_duration(Some(LONG_NOTIFICATION_TIME));
+                    }
+                }
+            }
+        }
+        GuiEvent::EjectCassette => {
+            if let Some(cassette) = emu.machine.cassette().as_mut() {
+                if let Some(path) = cassette.eject() {
+                    log::info!("Ejected cassette image: {:?}", path);
+                }
+            }
+            emu.gui.osd().post("Tape ejected!");
+        }
+        GuiEvent::CassettePlay => {
+            if let Some(cassette) = emu.machine.cassette().as_mut() {
+                cassette.play();
+            }
+        }
+        GuiEvent::CassetteRecord => {
+            if let Some(cassette) = emu.machine.cassette().as_mut() {
+                cassette.record();
+            }
+        }
+        GuiEvent::CassetteStop => {
+            if let Some(cassette) = emu.machine.cassette().as_mut() {
+                cassette.stop();
+            }
+        }
+        GuiEvent::CassetteRewind => {
+            if let Some(cassette) = emu.machine.cassette().as_mut() {
+                cassette.rewind();
+            }
+        }
         GuiEvent::LoadQuickFloppy(drive_select, item_idx) => {
             log::debug!("Load floppy quick image: {:?} into drive: {}", item_idx, drive_select);
-            handle_load_floppy(emu, *drive_select, FileSelectionContext::Index(*item_idx));
+            handle_load_floppy(emu, *drive_select, FileSelectionContext::Index(*item_idx), None);
         }
         GuiEvent::LoadFloppyAs(drive_select, path) => {
             log::debug!(
@@ -352,7 +641,21 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 path.to_string_lossy(),
                 drive_select
             );
-            handle_load_floppy(emu, *drive_select, FileSelectionContext::Path(path.clone()));
+            handle_load_floppy(emu, *drive_select, FileSelectionContext::Path(path.clone()), None);
+        }
+        GuiEvent::InsertDroppedFloppy(drive_select, path, write_protect) => {
+            log::debug!(
+                "Insert dropped floppy image: {} into drive: {}",
+                path.display(),
+                drive_select
+            );
+            // The new disk sets the drive's disk change line, so the guest sees a media change.
+            handle_load_floppy(
+                emu,
+                *drive_select,
+                FileSelectionContext::Path(path.clone()),
+                Some(*write_protect),
+            );
         }
         GuiEvent::LoadAutoFloppy(drive_select, path) => {
             log::debug!(
@@ -361,69 +664,12 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 drive_select
             );
 
-            // Query the indicated floppy drive for the largest supported image format.
-            // An autofloppy will always be built to the largest supported capacity.
-            let mut image_type = None;
-            if let Some(fdc) = emu.machine.fdc() {
-                image_type = Some(fdc.drive(*drive_select).get_largest_supported_image_format());
-            }
-
-            match emu
-                .floppy_manager
-                .build_autofloppy_image_from_dir(path, image_type, &emu.rm)
-            {
-                Ok(vec) => {
-                    if let Some(fdc) = emu.machine.fdc() {
-                        let mut load_success = false;
-                        match fdc.load_image_from(*drive_select, vec, None, true) {
-                            Ok(image) => {
-                                log::info!("Floppy image successfully loaded into virtual drive.");
-                                load_success = true;
-
-                                emu.gui.set_floppy_selection(
-                                    *drive_select,
-                                    None,
-                                    FloppyDriveSelection::Directory(path.clone()),
-                                    image.source_format(),
-                                    image.compatible_formats(true),
-                                    Some(true),
-                                );
-
-                                emu.gui.set_floppy_write_protected(*drive_select, true);
-                            }
-                            Err(err) => {
-                                log::warn!("Floppy image failed to load: {}", err);
-                            }
-                        }
-
-                        let mut patch_success = false;
-                        // Patch the floppy image with the correct BPB for the selected format type.
-                        match fdc.patch_image_bpb(*drive_select, image_type) {
-                            Ok(()) => {
-                                log::info!("Floppy image patched with correct BPB.");
-                                patch_success = true;
-                            }
-                            Err(err) => {
-                                log::warn!("Failed to patch floppy image with correct BPB: {}", err);
-                            }
-                        }
-
-                        if load_success & patch_success {
-                            emu.gui
-                                .toasts()
-                                .info("Floppy image successfully mounted!".to_string())
-                                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                        }
-                        else {
-                            emu.gui
-                                .toasts()
-                                .error("Failed to mount floppy image!".to_string())
-                                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                        }
-                    }
+            match emu.mount_floppy_dir(*drive_select, path) {
+                Ok(()) => {
+                    emu.gui.osd().post("Floppy image successfully mounted!");
                 }
                 Err(err) => {
-                    log::error!("Failed to build autofloppy image. Error: {}", err);
+                    log::error!("Failed to mount directory. Error: {}", err);
                     emu.gui
                         .toasts()
                         .error(format!("Directory mount failed: {}", err))
@@ -465,8 +711,10 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 filepath,
             );
 
+            let mut saved_write_ct = None;
             if let Some(fdc) = emu.machine.fdc() {
                 let floppy = fdc.get_image_mut(*drive_select);
+                let write_ct = floppy.1;
                 if let Some(floppy_image) = floppy.0 {
                     match fluxfox::ImageWriter::new()
                         .with_format(*format)
@@ -475,6 +723,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     {
                         Ok(_) => {
                             log::info!("Floppy image successfully saved: {:?}", filepath);
+                            saved_write_ct = Some(write_ct);
 
                             emu.gui.set_floppy_selection(
                                 *drive_select,
@@ -501,24 +750,20 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     }
                 }
             }
+
+            // The drive now holds the saved file, so write back further changes to it.
+            if let Some(write_ct) = saved_write_ct {
+                match emu.floppy_mounts.get_mut(drive_select) {
+                    Some(mount) if mount.path() == filepath.as_path() => mount.set_saved(write_ct),
+                    _ => emu.track_floppy(*drive_select, filepath),
+                }
+                emu.remember_floppy(*drive_select, FloppyMediaSource::Image, filepath, None);
+            }
         }
         GuiEvent::EjectFloppy(drive_select) => {
             log::info!("Ejecting floppy in drive: {}", drive_select);
-            if let Some(fdc) = emu.machine.fdc() {
-                fdc.unload_image(*drive_select);
-                emu.gui.set_floppy_selection(
-                    *drive_select,
-                    None,
-                    FloppyDriveSelection::None,
-                    None,
-                    Vec::new(),
-                    Some(false),
-                );
-                emu.gui
-                    .toasts()
-                    .info("Floppy ejected!".to_string())
-                    .set_duration(Some(SHORT_NOTIFICATION_TIME));
-            }
+            emu.eject_floppy(*drive_select);
+            emu.record_movie_input(MovieInput::EjectFloppy(*drive_select));
         }
         GuiEvent::CreateNewFloppy(drive_select, format, formatted) => {
             log::info!(
@@ -527,6 +772,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 format,
                 formatted
             );
+            emu.release_floppy(*drive_select);
             if let Some(fdc) = emu.machine.fdc() {
                 fdc.unload_image(*drive_select);
                 emu.gui.set_floppy_selection(
@@ -578,6 +824,34 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 fdc.write_protect(*drive_select, *state);
             }
         }
+        GuiEvent::SetFloppyWriteBack(drive_select, mode) => {
+            log::info!("Setting floppy write back mode for drive {}: {:?}", drive_select, mode);
+            emu.set_floppy_write_back(*drive_select, *mode);
+        }
+        GuiEvent::RestoreFloppyBackup(drive_select) => match emu.restore_floppy_backup(*drive_select) {
+            Ok(path) => {
+                log::info!("Restored backup of floppy image {}", path.display());
+                emu.gui
+                    .toasts()
+                    .info(format!("Restored backup of {:?}", path.file_name().unwrap_or_default()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+
+                let write_protect = emu.gui.floppy_write_protected(*drive_select);
+                handle_load_floppy(
+                    emu,
+                    *drive_select,
+                    FileSelectionContext::Path(path),
+                    Some(write_protect),
+                );
+            }
+            Err(err) => {
+                log::error!("Failed to restore floppy backup: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to restore backup: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
         GuiEvent::BridgeSerialPort(guest_port_id, host_port_name, host_port_id) => {
             log::info!("Bridging serial port: {}, id: {}", host_port_name, host_port_id);
             if let Err(err) = emu
@@ -654,57 +928,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 });
         }
         GuiEvent::EditBreakpoint => {
-            // Get breakpoints from GUI
-            let bp_set = emu.gui.get_breakpoints();
-
-            let mut breakpoints = Vec::new();
-
-            // Push exec breakpoint to list if valid expression
-            if let Some(addr) = emu.machine.cpu().eval_address(bp_set.breakpoint) {
-                let flat_addr = u32::from(addr);
-                if flat_addr > 0 && flat_addr < 0x100000 {
-                    breakpoints.push(BreakPointType::ExecuteFlat(flat_addr));
-                }
-            };
-
-            // Push mem breakpoint to list if valid expression
-            if let Some(addr) = emu.machine.cpu().eval_address(bp_set.mem_breakpoint) {
-                let flat_addr = u32::from(addr);
-                if flat_addr > 0 && flat_addr < 0x100000 {
-                    breakpoints.push(BreakPointType::MemAccessFlat(flat_addr));
-                }
-            }
-
-            // Push int breakpoint to list
-            if let Ok(iv) = u32::from_str_radix(bp_set.int_breakpoint, 10) {
-                if iv < 256 {
-                    breakpoints.push(BreakPointType::Interrupt(iv as u8));
-                }
-            }
-
-            // Push io breakpoint to list
-            if let Ok(addr) = u32::from_str_radix(bp_set.io_breakpoint, 16) {
-                let port = addr as u16;
-                log::debug!("Adding I/O breakpoint: {:04X}", port);
-                breakpoints.push(BreakPointType::IoAccess(port));
-            }
-
-            // Push stopwatches to list
-            if let Some(addr) = emu.machine.cpu().eval_address(bp_set.sw_start) {
-                let start_flat_addr = u32::from(addr);
-                if start_flat_addr > 0 && start_flat_addr < 0x100000 {
-                    if let Some(addr) = emu.machine.cpu().eval_address(bp_set.sw_stop) {
-                        let stop_flat_addr = u32::from(addr);
-                        if stop_flat_addr > 0 && stop_flat_addr < 0x100000 {
-                            breakpoints.push(BreakPointType::StartWatch(start_flat_addr));
-                            breakpoints.push(BreakPointType::StopWatch(stop_flat_addr));
-                            emu.machine.set_stopwatch(0, start_flat_addr, stop_flat_addr);
-                        }
-                    }
-                }
-            }
-
-            emu.machine.set_breakpoints(breakpoints);
+            emu.apply_breakpoints();
         }
         GuiEvent::MemoryUpdate => {
             // The address bar for the memory viewer was updated. We need to
@@ -777,11 +1001,13 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 }
             }
             emu.machine.change_state(*state);
+            if let MachineState::Off = state {
+                // The guest is stopped, so its changes to host directory volumes can be written back.
+                emu.sync_host_volumes();
+            }
         }
         GuiEvent::TakeScreenshot(dt_idx) => {
-            let screenshot_path = emu.rm.get_resource_path("screenshot").unwrap();
-
-            if let Err(err) = emu.dm.save_screenshot(*dt_idx, screenshot_path) {
+            if let Err(err) = emu.take_screenshot(*dt_idx, emu.config.emulator.screenshot_raw) {
                 log::error!("Failed to save screenshot: {}", err);
                 emu.gui
                     .toasts()
@@ -789,23 +1015,83 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     .set_duration(Some(LONG_NOTIFICATION_TIME));
             }
         }
+        GuiEvent::ExportTextScreen(dt_idx, format, full_buffer) => {
+            match emu.export_text_screen(Some(*dt_idx), *format, *full_buffer) {
+                Ok(path) => {
+                    emu.gui
+                        .toasts()
+                        .info(format!("Saved text screen to {}", path.display()))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::error!("Failed to export text screen: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("{}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::CopyTextScreen(dt_idx) => {
+            emu.copy_screen_to_clipboard(Some(*dt_idx));
+        }
         GuiEvent::ToggleFullscreen(dt_idx) => {
-            if let Some(window) = emu.dm.get_window(*dt_idx) {
-                match window.fullscreen() {
-                    Some(_) => {
-                        log::debug!("ToggleFullscreen: Resetting fullscreen state.");
-                        window.set_fullscreen(None);
-                    }
-                    None => {
-                        log::debug!("ToggleFullscreen: Entering fullscreen state.");
-                        window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
-                    }
+            if let Some(wid) = emu.dm.get_window(*dt_idx).map(|window| window.id()) {
+                if let Err(err) = emu.dm.toggle_fullscreen(wid) {
+                    log::error!("ToggleFullscreen: {}", err);
                 }
             }
         }
         GuiEvent::CtrlAltDel => {
             emu.machine.emit_ctrl_alt_del();
         }
+        GuiEvent::PasteClipboard => {
+            emu.paste_clipboard();
+        }
+        GuiEvent::SaveState(slot) => {
+            emu.save_state_slot(*slot);
+        }
+        GuiEvent::LoadState(slot) => {
+            emu.load_state_slot(*slot);
+        }
+        GuiEvent::DeleteState(slot) => {
+            emu.delete_state_slot(*slot);
+        }
+        GuiEvent::RefreshSaveStates => {
+            emu.refresh_save_states();
+        }
+        GuiEvent::RefreshMediaLibrary => {
+            emu.refresh_media_library();
+        }
+        GuiEvent::SetMediaFavorite(path, favorite) => {
+            if let Err(err) = emu.set_media_favorite(path, *favorite) {
+                log::error!("Failed to save media favorites: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to save favorites: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                // Undo the change the library browser made.
+                emu.gui.media_library.set_favorite(path, !*favorite);
+            }
+        }
+        GuiEvent::RecordMovie(from_state) => {
+            emu.record_movie(*from_state);
+        }
+        GuiEvent::StopMovie => {
+            emu.stop_movie();
+        }
+        GuiEvent::PlayMovie(path) => {
+            emu.play_movie(path);
+        }
+        GuiEvent::QuickLoad(path) => {
+            if let Err(err) = emu.quick_load_program(path, "") {
+                log::error!("Failed to quick-load {}: {}", path.display(), err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to quick-load program: {}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
         GuiEvent::CompositeAdjust(dt_idx, params) => {
             //log::warn!("got composite params: {:?}", params);
             emu.dm.with_renderer(*dt_idx, |renderer| {
@@ -826,38 +1112,134 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
         GuiEvent::ResetIOStats => {
             emu.machine.bus_mut().reset_io_stats();
         }
+        GuiEvent::SetPpiDipSwitches(sw1, sw2) => {
+            emu.machine.set_ppi_dip_switches(*sw1, *sw2);
+        }
+        GuiEvent::SetSoundVolume(s_idx, volume) => {
+            if let Some(si) = emu.si.as_mut() {
+                si.set_volume(*s_idx, *volume);
+            }
+        }
+        GuiEvent::SetSoundPan(s_idx, pan) => {
+            if let Some(si) = emu.si.as_mut() {
+                si.set_pan(*s_idx, *pan);
+            }
+        }
+        GuiEvent::SetSoundMuted(s_idx, muted) => {
+            if let Some(si) = emu.si.as_mut() {
+                si.set_muted(*s_idx, *muted);
+            }
+        }
+        GuiEvent::ToggleAudioCapture(stems) => {
+            emu.toggle_audio_capture(*stems);
+        }
+        GuiEvent::ToggleVideoCapture => {
+            emu.toggle_video_capture();
+        }
+        GuiEvent::SetAudioResampler(quality) => {
+            if let Some(si) = emu.si.as_mut() {
+                si.set_resampler(*quality);
+            }
+        }
+        GuiEvent::SetAudioMaxLatency(max_latency_ms) => {
+            if let Some(si) = emu.si.as_mut() {
+                si.set_max_latency(*max_latency_ms);
+            }
+        }
+        GuiEvent::SaveMixerSettings => match emu.save_mixer() {
+            Ok(path) => {
+                log::info!("Saved mixer settings to {}", path.display());
+                emu.gui
+                    .toasts()
+                    .info("Mixer settings saved.".to_string())
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(e) => {
+                log::error!("Failed to save mixer settings: {}", e);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to save mixer settings: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::SetHotkey(event, keys) => {
+            log::debug!("Binding hotkey {:?} to {:?}", event, keys);
+            emu.hkm.set_keys(*event, keys.clone());
+            emu.gui.hotkey_editor.set_bindings(emu.hkm.entries());
+            if let Err(e) = emu.save_hotkeys() {
+                log::error!("Failed to save hotkey bindings: {}", e);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to save hotkey bindings: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+        GuiEvent::ResetHotkeys => {
+            if let Err(e) = emu.reset_hotkeys() {
+                log::error!("Failed to reset hotkey bindings: {}", e);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to reset hotkey bindings: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+        GuiEvent::ClearPostCodes => {
+            if let Some(post_card) = emu.machine.bus_mut().post_card_mut() {
+                post_card.clear_history();
+            }
+        }
+        GuiEvent::SetRegisterLogging(state) => {
+            emu.machine.set_video_option(VideoOption::LogRegisterWrites(state));
+        }
         GuiEvent::StartRecordingDisassembly => {
             emu.machine.set_option(MachineOption::RecordListing(true));
         }
         GuiEvent::StopRecordingDisassembly => {
             emu.machine.set_option(MachineOption::RecordListing(false));
         }
+        GuiEvent::StartRecordingControlFlow => {
+            emu.machine.set_option(MachineOption::RecordControlFlow(true));
+        }
+        GuiEvent::StopRecordingControlFlow => {
+            emu.machine.set_option(MachineOption::RecordControlFlow(false));
+        }
+        GuiEvent::StartRecordingAnalysis => {
+            emu.machine.set_option(MachineOption::RecordAnalysis(true));
+        }
+        GuiEvent::StopRecordingAnalysis => {
+            emu.machine.set_option(MachineOption::RecordAnalysis(false));
+        }
+        GuiEvent::ConsoleCommand(command) => {
+            let output = console::run_console_command(emu, command);
+            emu.gui.debug_console.push_output(&output);
+        }
         _ => {
             log::warn!("Unhandled GUI event: {:?}", discriminant(gui_event));
         }
     }
 }
 
-/// handle_load_floppy(emu, *drive_select, path);
-pub fn handle_load_floppy(emu: &mut Emulator, drive_select: usize, context: FileSelectionContext) {
-    if let Some(fdc) = emu.machine.fdc() {
+/// Load a floppy image into the specified drive. The disk is write-protected as specified, or by
+/// the configured default. Disks mounted from zip archives are always write-protected.
+pub fn handle_load_floppy(
+    emu: &mut Emulator,
+    drive_select: usize,
+    context: FileSelectionContext,
+    write_protect: Option<bool>,
+) {
+    let write_protect = write_protect.unwrap_or(emu.config.emulator.media.write_protect_default);
+    if emu.machine.fdc().is_some() {
         let mut floppy_result = None;
-        let mut floppy_name = None;
         match context.clone() {
             FileSelectionContext::Index(item_idx) => {
                 let name = emu.floppy_manager.get_floppy_name(item_idx);
 
                 if let Some(name) = name {
-                    floppy_name = Some(name.clone());
                     log::info!("Loading floppy image: {:?} into drive: {}", name, drive_select);
                     floppy_result = Some(emu.floppy_manager.load_floppy_by_idx(item_idx, &emu.rm));
                 };
             }
             FileSelectionContext::Path(path) => {
-                if let Some(file_name) = path.file_name() {
-                    floppy_name = Some(file_name.to_os_string());
-                }
-
                 log::info!("Loading floppy image: {:?} into drive: {}", path, drive_select);
                 floppy_result = Some(emu.floppy_manager.load_floppy_by_path(path, &emu.rm));
             }
@@ -866,50 +1248,22 @@ pub fn handle_load_floppy(emu: &mut Emulator, drive_select: usize, context: File
         if let Some(floppy_result) = floppy_result {
             match floppy_result {
                 Ok(FloppyImageSource::ZipArchive(zip_vec, path)) => {
-                    let mut image_type = None;
-                    image_type = Some(fdc.drive(drive_select).get_largest_supported_image_format());
-                    match emu.floppy_manager.build_autofloppy_image_from_zip(
-                        zip_vec,
-                        Some(FloppyImageType::Image360K),
-                        &emu.rm,
-                    ) {
-                        Ok(vec) => {
-                            if let Some(fdc) = emu.machine.fdc() {
-                                match fdc.load_image_from(drive_select, vec, None, true) {
-                                    Ok(image) => {
-                                        log::info!("Floppy image successfully loaded into virtual drive.");
-
-                                        let compat_formats = image.compatible_formats(true);
-
-                                        let name = floppy_name.unwrap_or_else(|| OsString::from("Unknown"));
-
-                                        emu.gui.set_floppy_selection(
-                                            drive_select,
-                                            None,
-                                            FloppyDriveSelection::ZipArchive(name.into()),
-                                            image.source_format(),
-                                            compat_formats,
-                                            None,
-                                        );
-
-                                        emu.gui.set_floppy_write_protected(drive_select, true);
-
-                                        emu.gui
-                                            .toasts()
-                                            .info("Directory successfully mounted!".to_string())
-                                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                                    }
-                                    Err(err) => {
-                                        log::warn!("Floppy image failed to load: {}", err);
-                                    }
-                                }
-                            }
+                    // Mount the first disk image in the archive if there is one, otherwise build a
+                    // floppy from the archive's contents.
+                    let image_name = emu
+                        .floppy_manager
+                        .zip_disk_images(&zip_vec)
+                        .ok()
+                        .and_then(|images| images.into_iter().next());
+                    match emu.mount_zip(drive_select, &path, image_name.as_deref()) {
+                        Ok(_) => {
+                            emu.gui.osd().post("Zip archive successfully mounted!");
                         }
                         Err(err) => {
-                            log::error!("Failed to build autofloppy image. Error: {}", err);
+                            log::error!("Failed to mount zip archive. Error: {}", err);
                             emu.gui
                                 .toasts()
-                                .error(format!("Directory mount failed: {}", err))
+                                .error(format!("Zip archive mount failed: {}", err))
                                 .set_duration(Some(NORMAL_NOTIFICATION_TIME));
                         }
                     }
@@ -945,6 +1299,7 @@ pub fn handle_load_floppy(emu: &mut Emulator, drive_select: usize, context: File
                                     image: disk_image,
                                     item: context,
                                     path: Some(floppy_path),
+                                    write_protect,
                                 });
                             }
                             Err(err) => {
@@ -40,7 +40,7 @@ use marty_core::{
     syntax_token::SyntaxToken,
     util,
 };
-use marty_egui::GuiWindow;
+use marty_egui::{status_bar::StatusBarMetrics, GuiWindow};
 
 use frontend_common::timestep_manager::TimestepManager;
 use marty_core::cpu_common::{CpuAddress, TraceMode};
@@ -70,13 +70,50 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
     // -- Update machine state
     emu.gui.set_machine_state(emu.machine.get_state());
 
+    // -- Update keyboard LEDs
+    emu.gui.set_keyboard_leds(emu.machine.keyboard_leds());
+
+    // -- Update status bar
+    let (audio_latency_ms, audio_underruns) = match emu.si.as_ref() {
+        Some(si) => {
+            let stats = si.get_stats();
+            let latency = stats
+                .iter()
+                .filter(|s| !s.muted)
+                .map(|s| s.latency_ms)
+                .fold(0.0f32, f32::max);
+            (Some(latency), stats.iter().map(|s| s.underruns).sum())
+        }
+        None => (None, 0),
+    };
+    emu.gui.status_bar().update(StatusBarMetrics {
+        cpu_mhz: emu.perf.cpu_cycles as f64 / 1_000_000.0,
+        target_mhz: emu.machine.get_cpu_mhz(),
+        host_fps: emu.perf.wm_fps,
+        emu_fps: emu.perf.emu_frames,
+        audio_latency_ms,
+        audio_underruns,
+        floppy_activity: emu.machine.floppy_activity(),
+        turbo: emu.machine.turbo_active(),
+        frame_skip: tm.current_frame_skip(),
+    });
+
     // -- Update serial ports
     emu.gui.set_serial_ports(emu.machine.bus().enumerate_serial_ports());
 
+    // -- Update CD-ROM drive if the guest ejected the disc
+    if emu.machine.cd_ejected_by_guest() {
+        emu.gui.set_cdrom_selection(None, None);
+    }
+
+    // -- Update cassette deck status
+    let cassette_status = emu.machine.cassette().as_ref().map(|cassette| cassette.status());
+    emu.gui.set_cassette(cassette_status);
+
     // -- Update VHD Creator window
     if emu.gui.is_window_open(GuiWindow::VHDCreator) {
-        if let Some(hdc) = emu.machine.hdc() {
-            emu.gui.vhd_creator.set_formats(hdc.get_supported_formats());
+        if let Some(formats) = emu.machine.hdc_supported_formats() {
+            emu.gui.vhd_creator.set_formats(formats);
         }
         else {
             log::error!("Couldn't query available formats: No Hard Disk Controller present!");
@@ -116,6 +153,15 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
         emu.gui.perf_viewer.update(dti, sound_stats, &emu.perf, frame_history)
     }
 
+    // Update audio mixer
+    if emu.gui.is_window_open(GuiWindow::AudioMixer) {
+        if let Some(si) = emu.si.as_ref() {
+            emu.gui.audio_mixer.update_sources(si.get_stats());
+            emu.gui.audio_mixer.update_output(si.output_stats());
+            emu.gui.audio_mixer.set_capturing(si.is_capturing());
+        }
+    }
+
     // -- Update memory viewer window if open
     if emu.gui.is_window_open(GuiWindow::MemoryViewer) {
         let (mem_dump_addr_str, _source) = emu.gui.memory_viewer.get_address();
@@ -228,6 +274,19 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
         }
     }
 
+    // -- Update DIP switch editor window
+    if emu.gui.is_window_open(GuiWindow::DipSwitchEditor) {
+        let dip_state = emu.machine.ppi_dip_switch_state();
+        emu.gui.dip_switch_editor.update_state(dip_state);
+    }
+
+    // -- Update POST card viewer window
+    if emu.gui.is_window_open(GuiWindow::PostCardViewer) {
+        if let Some(post_card_state) = emu.machine.post_card_state() {
+            emu.gui.post_card_viewer.update_state(post_card_state);
+        }
+    }
+
     // -- Update DMA viewer window
     if emu.gui.is_window_open(GuiWindow::DmaViewer) {
         let dma_state = emu.machine.dma_state();
@@ -263,6 +322,54 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
         }
     }
 
+    // -- Update Video Register Log
+    if emu.gui.is_window_open(GuiWindow::VideoRegisterLog) {
+        if let Some(register_log) = emu.machine.videocard_register_log() {
+            emu.gui.video_register_log.update_state(register_log);
+        }
+    }
+
+    // -- Update VRAM Viewer
+    if emu.gui.is_window_open(GuiWindow::VramViewer) {
+        if let Some(videocard) = emu.machine.primary_videocard() {
+            emu.gui
+                .vram_viewer
+                .update(videocard.get_vram(), videocard.get_start_address());
+        }
+    }
+
+    // -- Update Character Set Viewer
+    if emu.gui.is_window_open(GuiWindow::CharacterSetViewer) {
+        if let Some(path) = emu.rm.get_resource_path("dump") {
+            emu.gui.character_set_viewer.set_dump_path(path);
+        }
+
+        let sets = emu
+            .machine
+            .primary_videocard()
+            .map(|videocard| videocard.get_character_sets())
+            .unwrap_or_default();
+        emu.gui.character_set_viewer.update(sets);
+    }
+
+    // -- Update Palette Viewer
+    if emu.gui.is_window_open(GuiWindow::PaletteViewer) {
+        let state = emu
+            .machine
+            .primary_videocard()
+            .and_then(|videocard| videocard.get_palette_state());
+        emu.gui.palette_viewer.update_state(state);
+    }
+
+    // -- Update CRTC Timing Viewer
+    if emu.gui.is_window_open(GuiWindow::CrtcTimingViewer) {
+        let timing = emu
+            .machine
+            .primary_videocard()
+            .and_then(|videocard| videocard.get_crtc_timing());
+        emu.gui.crtc_timing_viewer.update_state(timing);
+    }
+
     // -- Update Instruction Trace window
     if emu.gui.is_window_open(GuiWindow::InstructionHistoryViewer) {
         let trace = emu.machine.cpu().dump_instruction_history_tokens();
@@ -32,13 +32,18 @@
 
 use winit::{
     event::{ElementState, KeyEvent, Modifiers, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
+    keyboard::{Key, KeyCode, PhysicalKey},
     window::WindowId,
 };
 
 use display_manager_wgpu::DisplayManager;
-use frontend_common::{constants::LONG_NOTIFICATION_TIME, types::joykeys::JoyKeyInput, HotkeyEvent};
+use frontend_common::{
+    constants::LONG_NOTIFICATION_TIME,
+    types::{joykeys::JoyKeyInput, keyboard_layout::KeyAction},
+    HotkeyEvent,
+};
 use marty_core::machine::{ExecutionOperation, MachineState};
+use marty_egui::GuiBoolean;
 
 use crate::{input::TranslateKey, Emulator};
 
@@ -61,6 +66,7 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
     // Destructure the KeyEvent.
     let KeyEvent {
         physical_key,
+        logical_key,
         state,
         repeat,
         ..
@@ -81,6 +87,14 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
 
     match (physical_key, gui_has_focus) {
         (PhysicalKey::Code(keycode), gui_focus) => {
+            // If the hotkey editor is recording a new binding, it gets the key and nothing else does.
+            if emu
+                .gui
+                .record_hotkey_key(keycode.to_internal(), matches!(state, ElementState::Pressed))
+            {
+                return true;
+            }
+
             // An egui widget doesn't have focus, so send an event to the emulated machine
 
             process_hotkeys(
@@ -124,7 +138,14 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
                     if !repeat {
                         match state {
                             ElementState::Pressed => {
-                                emu.machine.key_press(keycode.to_internal(), emu.kb_data.modifiers);
+                                // The logical key gives the character produced by the host layout, for
+                                // translation into the guest layout.
+                                let character = match logical_key {
+                                    Key::Character(s) if s.chars().count() == 1 => s.chars().next(),
+                                    _ => None,
+                                };
+                                let actions = emu.kb_data.translator.key_down(keycode.to_internal(), character);
+                                send_key_actions(emu, &actions);
                                 if emu.flags.debug_keyboard {
                                     println!("Window: {:?} Key pressed: {:?}", window_id, keycode);
                                     //log::debug!("Key pressed, keycode: {:?}: xt: {:02X}", keycode, keycode);
@@ -132,7 +153,8 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
                                 return true;
                             }
                             ElementState::Released => {
-                                emu.machine.key_release(keycode.to_internal());
+                                let actions = emu.kb_data.translator.key_up(keycode.to_internal());
+                                send_key_actions(emu, &actions);
                                 if emu.flags.debug_keyboard {
                                     println!("Window: {:?} Key released: {:?}", window_id, keycode);
                                 }
@@ -152,6 +174,22 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
     return false;
 }
 
+/// Send translated key presses and releases to the emulated machine.
+fn send_key_actions(emu: &mut Emulator, actions: &[KeyAction]) {
+    for action in actions {
+        match action {
+            KeyAction::Press(key) => emu.machine.key_press(*key, emu.kb_data.modifiers),
+            KeyAction::Release(key) => emu.machine.key_release(*key),
+        }
+        if let Some(recorder) = &mut emu.kb_data.macro_recorder {
+            match action {
+                KeyAction::Press(key) => recorder.record(*key, true),
+                KeyAction::Release(key) => recorder.record(*key, false),
+            }
+        }
+    }
+}
+
 #[allow(unreachable_patterns)]
 pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, window_id: WindowId, gui_focus: bool) {
     let mut event_opt = None;
@@ -170,6 +208,14 @@ pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, wind
                 log::debug!("ToggleGui hotkey triggered. Toggling GUI visibility.");
                 emu.flags.render_gui = !emu.flags.render_gui;
             }
+            HotkeyEvent::ToggleAudioCapture => {
+                log::debug!("ToggleAudioCapture hotkey triggered.");
+                emu.toggle_audio_capture(emu.config.emulator.audio.capture_stems);
+            }
+            HotkeyEvent::ToggleVideoCapture => {
+                log::debug!("ToggleVideoCapture hotkey triggered.");
+                emu.toggle_video_capture();
+            }
             HotkeyEvent::CaptureMouse => {
                 // Get the window for this event.
                 let event_window = emu
@@ -216,37 +262,90 @@ pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, wind
             HotkeyEvent::CtrlAltDel => {
                 log::debug!("CtrlAltDel hotkey triggered. Sending Ctrl-Alt-Del to machine.");
                 emu.machine.emit_ctrl_alt_del();
+                emu.gui.osd().post("Ctrl-Alt-Del");
             }
             HotkeyEvent::Reboot => {
                 log::debug!("Reboot hotkey triggered. Restarting machine.");
                 emu.machine.change_state(MachineState::Rebooting);
+                emu.gui.osd().post("Rebooting...");
+            }
+            HotkeyEvent::Pause => match emu.machine.get_state() {
+                MachineState::On => {
+                    log::debug!("Pause hotkey triggered. Pausing machine.");
+                    emu.machine.change_state(MachineState::Paused);
+                    emu.gui.osd().post("Paused");
+                }
+                MachineState::Paused => {
+                    log::debug!("Pause hotkey triggered. Resuming machine.");
+                    emu.machine.change_state(MachineState::Resuming);
+                    emu.gui.osd().post("Resumed");
+                }
+                _ => {}
+            },
+            HotkeyEvent::ToggleTurbo => {
+                let state = !emu.gui.get_option(GuiBoolean::TurboButton).unwrap_or(false);
+                log::debug!("ToggleTurbo hotkey triggered. Setting turbo button to {}.", state);
+                emu.machine.set_turbo_mode(state);
+                emu.gui.set_option(GuiBoolean::TurboButton, state);
+                emu.gui.osd().post(match state {
+                    true => "Turbo mode enabled!",
+                    false => "Turbo mode disabled!",
+                });
+            }
+            HotkeyEvent::MountFloppy => {
+                log::debug!("MountFloppy hotkey triggered. Opening floppy image dialog.");
+                emu.gui.browse_floppy(0);
+            }
+            HotkeyEvent::NextDiskA | HotkeyEvent::NextDiskB => {
+                let drive = match hotkey {
+                    HotkeyEvent::NextDiskA => 0,
+                    _ => 1,
+                };
+                log::debug!("{:?} hotkey triggered. Swapping disk in drive {}.", hotkey, drive);
+                if let Err(err) = emu.swap_floppy(drive, None) {
+                    log::warn!("Failed to swap disk: {}", err);
+                    emu.gui.osd().post(format!("{}", err));
+                }
+            }
+            HotkeyEvent::ToggleMacroRecording => {
+                log::debug!("ToggleMacroRecording hotkey triggered.");
+                emu.toggle_macro_recording();
+            }
+            HotkeyEvent::PlayMacro => {
+                log::debug!("PlayMacro hotkey triggered.");
+                emu.play_macro();
+            }
+            HotkeyEvent::Paste => {
+                log::debug!("Paste hotkey triggered.");
+                emu.paste_clipboard();
+            }
+            HotkeyEvent::CopyScreen => {
+                log::debug!("CopyScreen hotkey triggered.");
+                emu.copy_screen_to_clipboard(None);
+            }
+            HotkeyEvent::SaveState => {
+                log::debug!("SaveState hotkey triggered.");
+                emu.save_state_slot(emu.state_slot);
+            }
+            HotkeyEvent::LoadState => {
+                log::debug!("LoadState hotkey triggered.");
+                emu.load_state_slot(emu.state_slot);
+            }
+            HotkeyEvent::Rewind => {
+                log::debug!("Rewind hotkey triggered.");
+                emu.rewind();
             }
             HotkeyEvent::ToggleFullscreen => {
                 log::debug!("ToggleFullscreen hotkey triggered.");
-                // Get the window for this event.
-                let event_window = emu
-                    .dm
-                    .get_window_by_id(window_id)
-                    .expect(&format!("Couldn't resolve window id {:?} to window.", window_id));
-
-                match event_window.fullscreen() {
-                    Some(_) => {
-                        log::debug!("ToggleFullscreen: Resetting fullscreen state.");
-                        event_window.set_fullscreen(None);
-                    }
-                    None => {
-                        log::debug!("ToggleFullscreen: Entering fullscreen state.");
-                        event_window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
-                    }
+                if let Err(err) = emu.dm.toggle_fullscreen(window_id) {
+                    log::error!("ToggleFullscreen: {}", err);
                 }
             }
             HotkeyEvent::Screenshot => {
                 log::debug!("Screenshot hotkey triggered. Capturing screenshot.");
 
-                let screenshot_path = emu.rm.get_resource_path("screenshot").unwrap();
-
                 // Take as screenshot of the primary display target.
-                if let Err(err) = emu.dm.save_screenshot(0, screenshot_path) {
+                if let Err(err) = emu.take_screenshot(0, emu.config.emulator.screenshot_raw) {
                     log::error!("Failed to save screenshot: {}", err);
                     emu.gui
                         .toasts()
@@ -263,6 +362,10 @@ pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, wind
             HotkeyEvent::JoyToggle => {
                 log::debug!("JoyToggle hotkey triggered. Toggling joystick keyboard emulation.");
                 emu.joy_data.enabled = !emu.joy_data.enabled;
+                emu.gui.osd().post(match emu.joy_data.enabled {
+                    true => "Keyboard joystick enabled",
+                    false => "Keyboard joystick disabled",
+                });
             }
             _ => {
                 log::debug!("Unhandled Hotkey triggered: {:?}", hotkey);
@@ -281,7 +384,8 @@ pub fn process_joykeys(
     _window_id: WindowId,
     _gui_focus: bool,
 ) -> bool {
-    if !emu.joy_data.enabled {
+    // Game port input comes from the movie during playback.
+    if !emu.joy_data.enabled || emu.movie_player.is_some() {
         return false;
     }
     let martykey = keycode.to_internal();
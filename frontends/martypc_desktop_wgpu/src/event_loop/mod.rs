@@ -31,10 +31,13 @@
     functionality domains for readability as it was originally very long.
 */
 
+mod automation;
+pub(crate) mod console;
 mod egui_events;
 mod egui_update;
 mod keyboard;
 mod render_frame;
+pub(crate) mod script;
 pub(crate) mod thread_events;
 mod update;
 mod winit_events;
@@ -29,20 +29,27 @@
     Handle rendering of video targets at the end of event processing.
 */
 
-use crate::Emulator;
+use crate::{video_recorder::capture_dimensions, Emulator};
 use display_backend_pixels::DisplayBackend;
 use display_manager_wgpu::DisplayManager;
 use marty_core::{device_traits::videocard::BufferSelect, machine::ExecutionState};
 use marty_egui::GuiBoolean;
 
-pub fn render_frame(emu: &mut Emulator) {
+/// Render and present all display targets. If 'beam_race' is set and the machine is running,
+/// each card is drawn as far as its raster beam has scanned out the current frame, over the
+/// previous frame. Otherwise, if the render thread is enabled, frames are drawn there and the
+/// most recently completed frame is presented.
+pub fn render_frame(emu: &mut Emulator, beam_race: bool) {
     // First, run each renderer to resolve all videocard views.
     // Every renderer will have an associated card and backend.
+    let mut capture_error = None;
     emu.dm.for_each_renderer(|renderer, vid, backend_buf| {
         if let Some(videocard) = emu.machine.bus_mut().video_mut(&vid) {
             // Check if the emulator is paused - if paused, optionally select the back buffer
             // so we can watch the raster beam draw
             let mut beam_pos = None;
+            let mut regions = None;
+            let mut race_y = None;
             match emu.exec_control.borrow_mut().get_state() {
                 ExecutionState::Paused | ExecutionState::BreakpointHit | ExecutionState::Halted => {
                     if emu.gui.get_option(GuiBoolean::ShowBackBuffer).unwrap_or(false) {
@@ -50,6 +57,9 @@ pub fn render_frame(emu: &mut Emulator) {
                         if emu.gui.get_option(GuiBoolean::ShowRasterPosition).unwrap_or(false) {
                             beam_pos = videocard.get_beam_pos();
                         }
+                        if emu.gui.get_option(GuiBoolean::ShowRasterRegions).unwrap_or(false) {
+                            regions = videocard.get_raster_regions();
+                        }
                     }
                     else {
                         renderer.select_buffer(BufferSelect::Front);
@@ -57,6 +67,9 @@ pub fn render_frame(emu: &mut Emulator) {
                 }
                 _ => {
                     renderer.select_buffer(BufferSelect::Front);
+                    if beam_race {
+                        race_y = videocard.get_beam_pos().map(|(_, y)| y);
+                    }
                 }
             }
 
@@ -70,16 +83,57 @@ pub fn render_frame(emu: &mut Emulator) {
             }
 
             //log::debug!("Drawing renderer for vid: {:?}", vid);
-            renderer.draw(
-                videocard.get_buf(renderer.get_selected_buffer()),
-                backend_buf,
-                extents,
-                beam_pos,
-                videocard.get_palette(),
-            )
+            let frame_count = match (race_y, emu.render_thread.as_mut()) {
+                (Some(beam_y), _) => {
+                    renderer.draw_beam_raced(
+                        videocard.get_buf(BufferSelect::Front),
+                        videocard.get_buf(BufferSelect::Back),
+                        beam_y,
+                        backend_buf,
+                        extents,
+                        videocard.get_palette(),
+                    );
+                    Some(videocard.get_frame_count())
+                }
+                (None, Some(render_thread)) => {
+                    // Queue this frame for the render thread and present the last one it finished.
+                    if let Err(e) =
+                        render_thread.submit(vid, renderer, &**videocard, beam_pos, regions, backend_buf.len())
+                    {
+                        log::error!("Failed to start render thread: {}", e);
+                    }
+                    render_thread.present(vid, renderer, backend_buf)
+                }
+                (None, None) => {
+                    renderer.draw(
+                        videocard.get_buf(renderer.get_selected_buffer()),
+                        backend_buf,
+                        extents,
+                        beam_pos,
+                        regions,
+                        videocard.get_palette(),
+                    );
+                    Some(videocard.get_frame_count())
+                }
+            };
+
+            // Record the frame if this card is being captured. The recorder tracks the card's
+            // frame counter, so frames are captured at the guest's rate, not ours.
+            if let (Some((capture_vid, recorder)), Some(frame_count)) = (emu.video_recorder.as_mut(), frame_count) {
+                if *capture_vid == vid {
+                    let dims = capture_dimensions(renderer);
+                    if let Err(e) = recorder.submit_frame(frame_count, backend_buf, dims) {
+                        capture_error = Some(e);
+                    }
+                }
+            }
         }
     });
 
+    if let Some(e) = capture_error {
+        emu.abort_video_capture(e);
+    }
+
     // Prepare guis for rendering.
     emu.dm.for_each_gui(|gui, window| gui.prepare(window, &mut emu.gui));
 
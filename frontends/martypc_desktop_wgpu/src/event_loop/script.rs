@@ -0,0 +1,232 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    event_loop/script.rs
+
+    Run Rhai scripts against the machine, call their breakpoint and frame
+    callbacks, and perform the actions they request.
+*/
+
+use std::path::Path;
+
+use frontend_common::{
+    debug_console::ConsoleRegister,
+    script::{ScriptAction, ScriptMachineState},
+};
+use marty_core::{
+    cpu_common::{Cpu, CpuOption, Register16},
+    devices::keyboard::KeyboardModifiers,
+    machine::{ExecutionOperation, ExecutionState},
+};
+
+use crate::{event_loop::console::run_console_command, Emulator};
+
+/// Call script callbacks for the current frame, and perform any actions they request.
+/// Called once per frame.
+pub fn process_scripts(emu: &mut Emulator) {
+    let state = emu.exec_control.borrow().get_state();
+    let mut output = Vec::new();
+
+    match state {
+        ExecutionState::BreakpointHit => {
+            let cycles = emu.machine.cpu_cycles();
+            if emu.script_break_cycle != Some(cycles) {
+                emu.script_break_cycle = Some(cycles);
+                output.extend(call_breakpoints(emu));
+            }
+        }
+        ExecutionState::Running if emu.script.has_frame_callbacks() => {
+            let machine_state = script_state(emu);
+            for e in emu.script.call_frame(machine_state) {
+                output.push(format!("Frame callback failed and was removed: {}", e));
+            }
+            output.extend(finish_script(emu));
+        }
+        _ => {}
+    }
+
+    if !output.is_empty() {
+        emu.gui.debug_console.push_output(&output);
+    }
+}
+
+/// Run a script file and return its output.
+pub fn run_script_file(emu: &mut Emulator, path: &Path) -> Vec<String> {
+    let machine_state = script_state(emu);
+    let mut output = match emu.script.load(path, machine_state) {
+        Ok(_) => vec![format!("Loaded script {}", path.display())],
+        Err(e) => vec![format!("Script '{}' failed: {}", path.display(), e)],
+    };
+    output.extend(finish_script(emu));
+    output
+}
+
+/// Evaluate a line of script and return its output, including its result.
+pub fn eval_script(emu: &mut Emulator, code: &str) -> Vec<String> {
+    let machine_state = script_state(emu);
+    let result = emu.script.eval(code, machine_state);
+    let mut output = finish_script(emu);
+    match result {
+        Ok(Some(value)) => output.push(value),
+        Ok(None) => {}
+        Err(e) => output.push(e.to_string()),
+    }
+    output
+}
+
+/// Unload all scripts and remove their breakpoints.
+pub fn clear_scripts(emu: &mut Emulator) {
+    emu.script.clear();
+    emu.apply_breakpoints();
+}
+
+/// Call the callbacks of the script breakpoints at the current CS:IP. Execution resumes
+/// afterwards, unless a callback paused the machine.
+fn call_breakpoints(emu: &mut Emulator) -> Vec<String> {
+    let cs = emu.machine.cpu().get_register16(Register16::CS);
+    let ip = emu.machine.cpu_mut().get_ip();
+    let flat_addr = ((cs as u32) << 4).wrapping_add(ip as u32) & 0xFFFFF;
+
+    let hits: Vec<usize> = emu
+        .script
+        .breakpoints()
+        .iter()
+        .enumerate()
+        .filter(|(_, expr)| {
+            emu.machine
+                .cpu()
+                .eval_address(expr)
+                .is_some_and(|addr| u32::from(addr) & 0xFFFFF == flat_addr)
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    if hits.is_empty() {
+        // Not our breakpoint. Leave it for the user.
+        return Vec::new();
+    }
+
+    let mut output = Vec::new();
+    for idx in hits {
+        let machine_state = script_state(emu);
+        if let Err(e) = emu.script.call_breakpoint(idx, machine_state) {
+            output.push(format!("Breakpoint callback failed: {}", e));
+        }
+    }
+
+    let mut actions = emu.script.take_actions();
+    if !actions.iter().any(|action| matches!(action, ScriptAction::Pause)) {
+        actions.push(ScriptAction::Run);
+    }
+    output.extend(perform_actions(emu, actions));
+    output.extend(emu.script.take_output());
+    output
+}
+
+/// Perform the actions requested by the last script call and collect its output.
+fn finish_script(emu: &mut Emulator) -> Vec<String> {
+    // Callbacks may have been registered, so the breakpoint set may have changed.
+    emu.apply_breakpoints();
+    let actions = emu.script.take_actions();
+    let mut output = emu.script.take_output();
+    output.extend(perform_actions(emu, actions));
+    output
+}
+
+fn perform_actions(emu: &mut Emulator, actions: Vec<ScriptAction>) -> Vec<String> {
+    let mut output = Vec::new();
+    for action in actions {
+        match action {
+            ScriptAction::Reset => emu.exec_control.borrow_mut().set_op(ExecutionOperation::Reset),
+            ScriptAction::Pause => emu.exec_control.borrow_mut().set_op(ExecutionOperation::Pause),
+            ScriptAction::Run => emu.exec_control.borrow_mut().set_op(ExecutionOperation::Run),
+            ScriptAction::KeyPress(key) => emu.machine.key_press(key, KeyboardModifiers::default()),
+            ScriptAction::KeyRelease(key) => emu.machine.key_release(key),
+            ScriptAction::MountFloppy(drive, path) => {
+                if let Err(e) = emu.load_floppy_immediate(drive, Path::new(&path)) {
+                    output.push(format!("Failed to load floppy '{}': {}", path, e));
+                }
+            }
+            ScriptAction::EjectFloppy(drive) => emu.eject_floppy(drive),
+            ScriptAction::WriteByte(addr, value) => {
+                if emu.machine.bus_mut().write_u8(addr as usize, value, 0).is_err() {
+                    output.push(format!("Failed to write memory at {:05X}", addr));
+                }
+            }
+            ScriptAction::SetRegister(reg, value) => {
+                let cpu = emu.machine.cpu_mut();
+                match reg {
+                    ConsoleRegister::Reg16(reg) => cpu.set_register16(reg, value),
+                    ConsoleRegister::Reg8(reg) => cpu.set_register8(reg, value as u8),
+                    ConsoleRegister::Flags => cpu.set_flags(value),
+                }
+            }
+            ScriptAction::Screenshot(dt_idx) => match emu.take_screenshot(dt_idx, false) {
+                Ok(path) => output.push(format!("Saving screenshot to {}", path.display())),
+                Err(e) => output.push(format!("Failed to take screenshot: {}", e)),
+            },
+            ScriptAction::TraceLogging(state) => emu.machine.set_cpu_option(CpuOption::TraceLoggingEnabled(state)),
+            ScriptAction::Console(line) => output.extend(run_console_command(emu, &line)),
+        }
+    }
+    output
+}
+
+/// Take a snapshot of the machine state for a script to read.
+fn script_state(emu: &mut Emulator) -> ScriptMachineState {
+    let ip = emu.machine.cpu_mut().get_ip();
+    let cpu = emu.machine.cpu();
+    let mut registers: Vec<(&'static str, u16)> = [
+        ("ax", Register16::AX),
+        ("bx", Register16::BX),
+        ("cx", Register16::CX),
+        ("dx", Register16::DX),
+        ("sp", Register16::SP),
+        ("bp", Register16::BP),
+        ("si", Register16::SI),
+        ("di", Register16::DI),
+        ("cs", Register16::CS),
+        ("ds", Register16::DS),
+        ("es", Register16::ES),
+        ("ss", Register16::SS),
+    ]
+    .into_iter()
+    .map(|(name, reg)| (name, cpu.get_register16(reg)))
+    .collect();
+    registers.push(("ip", ip));
+    registers.push(("flags", cpu.get_flags()));
+
+    let memory = match emu.machine.bus().peek_range(0, 0x100000) {
+        Ok(memory) => memory.to_vec(),
+        Err(_) => Vec::new(),
+    };
+
+    ScriptMachineState {
+        registers,
+        memory,
+        cycles: emu.machine.cpu_cycles(),
+    }
+}
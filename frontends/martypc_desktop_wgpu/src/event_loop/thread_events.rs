@@ -33,7 +33,7 @@ use crate::{
     event_loop::{egui_events::FileSelectionContext, thread_events::FrontendThreadEvent::FloppyImageLoadProgress},
 };
 use fluxfox::DiskImage;
-use frontend_common::constants::NORMAL_NOTIFICATION_TIME;
+use frontend_common::{constants::NORMAL_NOTIFICATION_TIME, movie::MovieInput, types::session::FloppyMediaSource};
 use marty_egui::{modal::ModalContext, state::FloppyDriveSelection};
 use std::path::PathBuf;
 
@@ -46,6 +46,7 @@ pub enum FrontendThreadEvent {
         item: FileSelectionContext,
         image: DiskImage,
         path: Option<PathBuf>,
+        write_protect: bool,
     },
     FloppyImageSaveError(String),
     FloppyImageSaveComplete(PathBuf),
@@ -78,14 +79,11 @@ pub fn handle_thread_event(emu: &mut Emulator) {
                 item,
                 image,
                 path,
+                write_protect,
             } => {
+                emu.release_floppy(drive_select);
                 if let Some(fdc) = emu.machine.fdc() {
-                    match fdc.attach_image(
-                        drive_select,
-                        image,
-                        path.clone(),
-                        emu.config.emulator.media.write_protect_default,
-                    ) {
+                    match fdc.attach_image(drive_select, image, path.clone(), write_protect) {
                         Ok(image) => {
                             let item_idx = if let FileSelectionContext::Index(idx) = item {
                                 Some(idx)
@@ -101,13 +99,17 @@ pub fn handle_thread_event(emu: &mut Emulator) {
                                 FloppyDriveSelection::Image(path.clone().unwrap_or_default().into()),
                                 image.source_format(),
                                 image.compatible_formats(true),
-                                Some(emu.config.emulator.media.write_protect_default),
+                                Some(write_protect),
                             );
+                            emu.gui.set_floppy_write_protected(drive_select, write_protect);
 
-                            emu.gui
-                                .toasts()
-                                .info(format!("Floppy loaded: {:?}", path.clone()))
-                                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                            emu.gui.osd().post(format!("Floppy loaded: {:?}", path.clone()));
+                            if let Some(path) = path {
+                                emu.track_floppy(drive_select, &path);
+                                emu.find_disk_set(drive_select, &path);
+                                emu.remember_floppy(drive_select, FloppyMediaSource::Image, &path, None);
+                                emu.record_movie_input(MovieInput::LoadFloppy(drive_select, path));
+                            }
 
                             emu.gui.modal.close();
                         }
@@ -29,19 +29,26 @@
     Process an event loop update
 */
 
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use winit::event_loop::EventLoopWindowTarget;
 
 use display_manager_wgpu::DisplayManager;
 use frontend_common::{
-    constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME, SHORT_NOTIFICATION_TIME},
+    automation::AutomationEvent,
+    constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME},
     timestep_manager::{MachinePerfStats, TimestepManager},
 };
 use marty_core::{bus::DeviceEvent, machine::MachineEvent};
 use videocard_renderer::RendererEvent;
 
 use crate::{
-    event_loop::{egui_update::update_egui, render_frame::render_frame},
+    event_loop::{
+        automation::process_automation,
+        console::process_console,
+        egui_update::update_egui,
+        render_frame::render_frame,
+        script::process_scripts,
+    },
     Emulator,
 };
 
@@ -60,18 +67,40 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
         },
         |emuc, cycles| {
             // Per emu update freq
-            emuc.machine.run(cycles, &mut emuc.exec_control.borrow_mut());
+            emuc.run_machine(cycles);
+
+            // In low latency mode each update is a slice of a frame, and we present after every
+            // slice. Recording video needs whole frames, so don't race the beam while recording.
+            if emuc.flags.low_latency {
+                resize_cards(emuc);
+                let beam_race = emuc.video_recorder.is_none();
+                render_frame(emuc, beam_race);
+            }
         },
         |emuc, tmc, &perf| {
             emuc.perf = perf;
 
             // Per frame freq
-            if let Some(mouse) = emuc.machine.mouse_mut() {
+            // Game port input comes from the movie during playback.
+            if emuc.movie_player.is_none() {
+                if let Some(gamepad) = &mut emuc.gamepad {
+                    gamepad.poll(&mut emuc.machine);
+                }
+            }
+
+            emuc.update_macro();
+            emuc.update_save_states();
+            emuc.update_rewind();
+            emuc.update_floppy_write_back();
+            emuc.update_quick_load();
+
+            if emuc.machine.have_mouse() {
                 // Send any pending mouse update to machine if mouse is captured
                 if emuc.mouse_data.is_captured && emuc.mouse_data.have_update {
-                    mouse.update(
+                    emuc.machine.mouse_update(
                         emuc.mouse_data.l_button_was_pressed,
                         emuc.mouse_data.r_button_was_pressed,
+                        emuc.mouse_data.m_button_was_pressed,
                         emuc.mouse_data.frame_delta_x,
                         emuc.mouse_data.frame_delta_y,
                     );
@@ -91,9 +120,20 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                         emuc.mouse_data.r_button_was_pressed
                     };
 
-                    if emuc.mouse_data.l_button_was_released || emuc.mouse_data.r_button_was_released {
+                    let m_release_state = if emuc.mouse_data.m_button_was_released {
+                        false
+                    }
+                    else {
+                        emuc.mouse_data.m_button_was_pressed
+                    };
+
+                    if emuc.mouse_data.l_button_was_released
+                        || emuc.mouse_data.r_button_was_released
+                        || emuc.mouse_data.m_button_was_released
+                    {
                         // Send release event
-                        mouse.update(l_release_state, r_release_state, 0.0, 0.0);
+                        emuc.machine
+                            .mouse_update(l_release_state, r_release_state, m_release_state, 0.0, 0.0);
                     }
 
                     // Reset mouse for next frame
@@ -103,6 +143,11 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
 
             // Drain machine events
             while let Some(event) = emuc.machine.get_event() {
+                if let Some(server) = emuc.automation.as_mut() {
+                    if let Some(automation_event) = AutomationEvent::from_machine_event(&event) {
+                        server.notify(&automation_event);
+                    }
+                }
                 match event {
                     MachineEvent::CheckpointHit(checkpoint, pri) => {
                         log::info!(
@@ -130,10 +175,11 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                     }
                     MachineEvent::Reset => {
                         // Send notification
-                        emuc.gui
-                            .toasts()
-                            .info("Machine reset!".to_string())
-                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                        emuc.gui.osd().post("Machine reset!");
+
+                        // Persist CMOS contents when the machine is rebooted or powered off, so that
+                        // setup changes survive a later crash of the emulator.
+                        emuc.save_cmos();
 
                         if emuc.config.machine.reload_roms {
                             // Reload ROMs from the saved list of ROM sets.
@@ -172,6 +218,15 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                 }
             }
 
+            // Run queued and remote debug console commands
+            process_console(emuc);
+
+            // Handle requests from automation clients
+            process_automation(emuc);
+
+            // Call script breakpoint and frame callbacks
+            process_scripts(emuc);
+
             // Do per-frame updates (Serial port emulation)
             let events = emuc.machine.frame_update();
             for event in events {
@@ -179,18 +234,15 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                     DeviceEvent::TurboToggled(state) => {
                         // Send notification
                         if state {
-                            emuc.gui
-                                .toasts()
-                                .info("Turbo mode enabled!".to_string())
-                                .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                            emuc.gui.osd().post("Turbo mode enabled!");
                         }
                         else {
-                            emuc.gui
-                                .toasts()
-                                .info("Turbo mode disabled!".to_string())
-                                .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                            emuc.gui.osd().post("Turbo mode disabled!");
                         }
                     }
+                    DeviceEvent::Notification(message) => {
+                        emuc.gui.osd().post(message);
+                    }
                     _ => {}
                 }
             }
@@ -201,15 +253,7 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
             }
 
             let render_start = Instant::now();
-
-            // Check if any videocard has resized and handle it
-            emuc.machine.for_each_videocard(|vci| {
-                let extents = vci.card.get_display_extents();
-                // Resize the card.
-                if let Err(_) = emuc.dm.on_card_resized(&vci.id, &extents) {
-                    log::error!("Error resizing videocard");
-                }
-            });
+            resize_cards(emuc);
             emuc.stat_counter.render_time = Instant::now() - render_start;
 
             // Update egui data
@@ -220,18 +264,18 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                 sound.run();
             }
 
-            // Render the current frame for all window display targets.
-            render_frame(emuc);
+            // Render the current frame for all window display targets, unless we're skipping it.
+            // In low latency mode this has already been done for each slice of the frame.
+            if !emuc.flags.low_latency && !tmc.skip_frame() {
+                render_frame(emuc, false);
+            }
 
             // Handle renderer events
             emuc.dm.for_each_renderer(|renderer, _vid, _backend_buf| {
                 while let Some(event) = renderer.get_event() {
                     match event {
                         RendererEvent::ScreenshotSaved => {
-                            emuc.gui
-                                .toasts()
-                                .info("Screenshot saved!".to_string())
-                                .set_duration(Some(Duration::from_secs(5)));
+                            emuc.gui.osd().post("Screenshot saved!");
                         }
                     }
                 }
@@ -239,3 +283,14 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
         },
     );
 }
+
+/// Check if any videocard has resized and handle it.
+fn resize_cards(emu: &mut Emulator) {
+    emu.machine.for_each_videocard(|vci| {
+        let extents = vci.card.get_display_extents();
+        // Resize the card.
+        if let Err(_) = emu.dm.on_card_resized(&vci.id, &extents) {
+            log::error!("Error resizing videocard");
+        }
+    });
+}
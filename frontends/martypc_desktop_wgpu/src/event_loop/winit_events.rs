@@ -33,7 +33,7 @@ use std::time::Instant;
 use winit::{
     event::{DeviceEvent, ElementState, Event, StartCause, WindowEvent},
     event_loop::EventLoopWindowTarget,
-    window::WindowLevel,
+    window::{WindowId, WindowLevel},
 };
 
 use crate::{
@@ -47,6 +47,28 @@ use crate::{
 use display_manager_wgpu::DisplayManager;
 use frontend_common::timestep_manager::TimestepManager;
 
+/// Update the light pen of the video card shown in the specified window. When the mouse is not
+/// captured, holding the left mouse button presses the light pen against the screen at the
+/// cursor position.
+fn update_light_pen(emu: &mut Emulator, window_id: WindowId) {
+    let cursor_pos = if emu.mouse_data.light_pen_down {
+        emu.mouse_data.cursor_pos
+    }
+    else {
+        None
+    };
+
+    let machine = &mut emu.machine;
+    emu.dm.with_target_by_wid(window_id, |dt| {
+        if let Some(card_id) = dt.get_card_id() {
+            if let Some(mut video) = machine.bus_mut().video_mut(&card_id) {
+                let pen_pos = cursor_pos.and_then(|(x, y)| dt.surface_to_beam(x, y, video.get_display_extents()));
+                video.set_light_pen(pen_pos);
+            }
+        }
+    });
+}
+
 pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<()>, elwt: &EventLoopWindowTarget<()>) {
     match event {
         Event::NewEvents(StartCause::Init) => {
@@ -58,6 +80,15 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
             emu.gui.init_display_info(dti);
         }
 
+        Event::LoopExiting => {
+            // Persist battery-backed CMOS contents.
+            emu.save_cmos();
+            emu.sync_host_volumes();
+            // Save the session before the floppies are released, which forgets their sources.
+            emu.save_session();
+            emu.release_floppies();
+        }
+
         Event::DeviceEvent { event, .. } => {
             match event {
                 DeviceEvent::MouseMotion { delta: (x, y) } => {
@@ -102,6 +133,16 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                             emu.mouse_data.r_button_was_released = true;
                             emu.mouse_data.have_update = true;
                         }
+                        (MouseButton::Middle, ElementState::Pressed) => {
+                            emu.mouse_data.m_button_was_pressed = true;
+                            emu.mouse_data.m_button_is_pressed = true;
+                            emu.mouse_data.have_update = true;
+                        }
+                        (MouseButton::Middle, ElementState::Released) => {
+                            emu.mouse_data.m_button_is_pressed = false;
+                            emu.mouse_data.m_button_was_released = true;
+                            emu.mouse_data.have_update = true;
+                        }
                         _ => {}
                     }
                     //log::debug!("Mouse button: {:?} state: {:?}", button, state);
@@ -162,6 +203,9 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                     elwt.exit();
                     return;
                 }
+                WindowEvent::DroppedFile(ref path) => {
+                    emu.handle_dropped_file(path);
+                }
                 WindowEvent::ModifiersChanged(modifiers) => {
                     handle_modifiers(emu, window_id, &event, &modifiers);
                     pass_to_egui = true;
@@ -174,9 +218,35 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                 WindowEvent::RedrawRequested => {
                     process_update(emu, tm, elwt);
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    emu.mouse_data.cursor_pos = Some((position.x, position.y));
+                    if emu.mouse_data.light_pen_down {
+                        update_light_pen(emu, window_id);
+                    }
+                    pass_to_egui = true;
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    emu.mouse_data.cursor_pos = None;
+                    if emu.mouse_data.light_pen_down {
+                        update_light_pen(emu, window_id);
+                    }
+                    pass_to_egui = true;
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: winit::event::MouseButton::Left,
+                    ..
+                } => {
+                    if !emu.mouse_data.is_captured {
+                        emu.mouse_data.light_pen_down = state == ElementState::Pressed;
+                        update_light_pen(emu, window_id);
+                    }
+                    pass_to_egui = true;
+                }
                 WindowEvent::Focused(state) => match state {
                     true => {
                         log::debug!("Window {:?} gained focus", window_id);
+                        emu.dm.on_window_focused(window_id, true);
                         emu.dm.for_each_target(|dtc, _| {
                             if dtc.window_opts.as_ref().is_some_and(|opts| opts.always_on_top) {
                                 dtc.window.as_ref().map(|window| {
@@ -188,6 +258,7 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                     }
                     false => {
                         log::debug!("Window {:?} lost focus", window_id);
+                        emu.dm.on_window_focused(window_id, false);
                         emu.dm.for_each_window(|window, on_top| {
                             if on_top {
                                 window.set_window_level(WindowLevel::Normal);
@@ -0,0 +1,194 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    gamepad.rs
+
+    Maps host gamepads onto the emulated game port and keyboard, using a
+    gamepad profile from the emulator configuration.
+*/
+
+use std::collections::HashSet;
+
+use frontend_common::{
+    types::gamepad::{GamepadAxis, GamepadButton},
+    GamepadProfile,
+};
+use gilrs::{Axis, Button, Event, EventType, Gilrs};
+use marty_core::{devices::keyboard::KeyboardModifiers, keys::MartyKey, machine::Machine};
+
+const DEFAULT_DEADZONE: f64 = 0.1;
+
+pub struct GamepadInput {
+    gilrs: Gilrs,
+    profile: GamepadProfile,
+    keys_held: HashSet<MartyKey>,
+}
+
+impl GamepadInput {
+    pub fn new(profile: GamepadProfile) -> Option<Self> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                log::error!("Failed to initialize gamepad input: {}", e);
+                return None;
+            }
+        };
+
+        for (_id, gamepad) in gilrs.gamepads() {
+            log::info!("Found gamepad: {}", gamepad.name());
+        }
+        log::debug!("Using gamepad profile: {}", profile.name);
+
+        Some(Self {
+            gilrs,
+            profile,
+            keys_held: HashSet::new(),
+        })
+    }
+
+    pub fn profile_name(&self) -> &str {
+        &self.profile.name
+    }
+
+    /// Process all pending gamepad events.
+    pub fn poll(&mut self, machine: &mut Machine) {
+        while let Some(Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::AxisChanged(axis, value, _) => {
+                    if let Some(axis) = axis_from_gilrs(axis) {
+                        self.handle_axis(machine, axis, value as f64);
+                    }
+                }
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = button_from_gilrs(button) {
+                        self.handle_button(machine, button, true);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = button_from_gilrs(button) {
+                        self.handle_button(machine, button, false);
+                    }
+                }
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {}", self.gilrs.gamepad(id).name());
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", self.gilrs.gamepad(id).name());
+                    self.release_all(machine);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_axis(&mut self, machine: &mut Machine, axis: GamepadAxis, value: f64) {
+        // Apply the deadzone, then rescale so the live range still covers -1.0 to 1.0.
+        let deadzone = self.profile.deadzone.unwrap_or(DEFAULT_DEADZONE).clamp(0.0, 0.99);
+        let pos = if value.abs() < deadzone {
+            0.0
+        }
+        else {
+            value.signum() * (value.abs() - deadzone) / (1.0 - deadzone)
+        };
+
+        if let Some(gameport) = machine.bus_mut().game_port_mut() {
+            for mapping in self.profile.axes.iter().filter(|m| m.axis == axis) {
+                let pos = if mapping.invert { -pos } else { pos };
+                gameport.set_axis(mapping.controller, mapping.joy_axis, pos);
+            }
+        }
+    }
+
+    fn handle_button(&mut self, machine: &mut Machine, button: GamepadButton, pressed: bool) {
+        for mapping in self.profile.buttons.iter().filter(|m| m.button == button) {
+            if let Some(joy_button) = mapping.joy_button {
+                if let Some(gameport) = machine.bus_mut().game_port_mut() {
+                    gameport.set_button(mapping.controller, joy_button, pressed);
+                }
+            }
+            if let Some(key) = mapping.key {
+                // Track held keys so we don't send unbalanced presses or releases.
+                if pressed && self.keys_held.insert(key) {
+                    machine.key_press(key, KeyboardModifiers::default());
+                }
+                else if !pressed && self.keys_held.remove(&key) {
+                    machine.key_release(key);
+                }
+            }
+        }
+    }
+
+    /// Center all mapped axes and release all mapped buttons and keys.
+    fn release_all(&mut self, machine: &mut Machine) {
+        if let Some(gameport) = machine.bus_mut().game_port_mut() {
+            for mapping in self.profile.axes.iter() {
+                gameport.set_axis(mapping.controller, mapping.joy_axis, 0.0);
+            }
+            for mapping in self.profile.buttons.iter() {
+                if let Some(joy_button) = mapping.joy_button {
+                    gameport.set_button(mapping.controller, joy_button, false);
+                }
+            }
+        }
+        for key in self.keys_held.drain() {
+            machine.key_release(key);
+        }
+    }
+}
+
+fn axis_from_gilrs(axis: Axis) -> Option<GamepadAxis> {
+    match axis {
+        Axis::LeftStickX => Some(GamepadAxis::LeftStickX),
+        Axis::LeftStickY => Some(GamepadAxis::LeftStickY),
+        Axis::RightStickX => Some(GamepadAxis::RightStickX),
+        Axis::RightStickY => Some(GamepadAxis::RightStickY),
+        Axis::LeftZ => Some(GamepadAxis::LeftZ),
+        Axis::RightZ => Some(GamepadAxis::RightZ),
+        _ => None,
+    }
+}
+
+fn button_from_gilrs(button: Button) -> Option<GamepadButton> {
+    match button {
+        Button::South => Some(GamepadButton::South),
+        Button::East => Some(GamepadButton::East),
+        Button::North => Some(GamepadButton::North),
+        Button::West => Some(GamepadButton::West),
+        Button::LeftTrigger => Some(GamepadButton::LeftTrigger),
+        Button::LeftTrigger2 => Some(GamepadButton::LeftTrigger2),
+        Button::RightTrigger => Some(GamepadButton::RightTrigger),
+        Button::RightTrigger2 => Some(GamepadButton::RightTrigger2),
+        Button::Select => Some(GamepadButton::Select),
+        Button::Start => Some(GamepadButton::Start),
+        Button::LeftThumb => Some(GamepadButton::LeftThumb),
+        Button::RightThumb => Some(GamepadButton::RightThumb),
+        Button::DPadUp => Some(GamepadButton::DPadUp),
+        Button::DPadDown => Some(GamepadButton::DPadDown),
+        Button::DPadLeft => Some(GamepadButton::DPadLeft),
+        Button::DPadRight => Some(GamepadButton::DPadRight),
+        _ => None,
+    }
+}
@@ -49,6 +49,7 @@ pub enum MouseButton {
 }
 
 pub struct HotkeyState {
+    pub keys: Vec<MartyKey>,
     pub keyset: HashSet<MartyKey>,
     pub pressed: HashSet<MartyKey>,
     pub scope: HotkeyScope,
@@ -59,6 +60,7 @@ pub struct HotkeyState {
 impl Default for HotkeyState {
     fn default() -> Self {
         HotkeyState {
+            keys: Vec::new(),
             keyset: HashSet::new(),
             pressed: HashSet::new(),
             scope: HotkeyScope::Any,
@@ -105,6 +107,7 @@ impl HotkeyManager {
             hotkey,
             HotkeyState {
                 keyset: HashSet::from_iter(keyvec.iter().cloned()),
+                keys: keyvec,
                 pressed: HashSet::new(),
                 scope,
                 capture_disable,
@@ -113,6 +116,29 @@ impl HotkeyManager {
         );
     }
 
+    /// Rebind a hotkey to a new key combination, keeping its scope. An empty key list unbinds it.
+    pub fn set_keys(&mut self, hotkey: HotkeyEvent, keyvec: Vec<MartyKey>) {
+        let (scope, capture_disable) = self
+            .hotkeys
+            .get(&hotkey)
+            .map_or((HotkeyScope::Any, false), |state| (state.scope, state.capture_disable));
+        self.add_hotkey(hotkey, keyvec, scope, capture_disable);
+    }
+
+    /// Return the current binding of every hotkey event, in declaration order.
+    pub fn entries(&self) -> Vec<HotkeyConfigEntry> {
+        HotkeyEvent::iter()
+            .filter_map(|event| {
+                self.hotkeys.get(&event).map(|state| HotkeyConfigEntry {
+                    event,
+                    keys: state.keys.clone(),
+                    capture_disable: state.capture_disable,
+                    scope: state.scope,
+                })
+            })
+            .collect()
+    }
+
     pub fn keydown(&mut self, key: MartyKey, gui_focus: bool, input_captured: bool) -> Option<Vec<HotkeyEvent>> {
         let mut events = Vec::new();
         for (hotkey, state) in self.hotkeys.iter_mut() {
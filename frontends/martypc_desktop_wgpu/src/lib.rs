@@ -36,26 +36,31 @@
 #![allow(clippy::too_many_arguments)]
 #![forbid(unsafe_code)]
 
+mod cmos;
 mod cpu_test;
 mod emulator;
 mod event_loop;
+mod gamepad;
 mod input;
+mod render_thread;
 mod run_benchmark;
+mod run_convert;
 mod run_headless;
 mod sound_player;
+mod video_recorder;
 
 #[cfg(feature = "arduino_validator")]
 mod run_fuzzer;
 
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::PathBuf,
     rc::Rc,
     time::{Duration, Instant},
 };
 
-use crate::run_benchmark::run_benchmark;
+use crate::{run_benchmark::run_benchmark, run_convert::run_convert, run_headless::run_headless};
 
 #[cfg(feature = "arduino_validator")]
 use crate::{cpu_test::gen_tests::run_gentests, cpu_test::process_tests::run_processtests, run_fuzzer::run_fuzzer};
@@ -76,10 +81,21 @@ use marty_core::{
 use display_manager_wgpu::{DisplayBackend, DisplayManager, DisplayManagerGuiOptions, WgpuDisplayManagerBuilder};
 use frontend_common::{
     cartridge_manager::CartridgeManager,
+    automation::server::AutomationServer,
+    cassette_manager::CassetteManager,
+    cdrom_manager::CdRomManager,
+    debug_console::{server::ConsoleServer, DebugConsole},
     floppy_manager::FloppyManager,
+    keyboard_macro::{MacroPlayer, MacroRecorder},
     resource_manager::ResourceManager,
-    timestep_manager::TimestepManager,
-    types::joykeys::JoyKeyInput,
+    rewind_buffer::RewindBuffer,
+    script::ScriptEngine,
+    timestep_manager::{FramePacing, TimestepManager, DEFAULT_FRAME_SLICES},
+    types::{
+        joykeys::JoyKeyInput,
+        keyboard_layout::{KeyTranslationMode, KeyTranslator},
+        sound::AudioOutputOptions,
+    },
     vhd_manager::VhdManager,
     JoyKeyEntry,
 };
@@ -88,8 +104,10 @@ use marty_egui::state::GuiState;
 
 use crate::{
     emulator::{EmuFlags, Emulator},
-    event_loop::{handle_event, thread_events::handle_thread_event},
+    event_loop::{handle_event, script::run_script_file, thread_events::handle_thread_event},
+    gamepad::GamepadInput,
     input::HotkeyManager,
+    render_thread::RenderThread,
     sound_player::SoundInterface,
 };
 
@@ -182,8 +200,14 @@ pub struct MouseData {
     pub r_button_was_pressed: bool,
     pub r_button_was_released: bool,
     pub r_button_is_pressed: bool,
+    pub m_button_was_pressed: bool,
+    pub m_button_was_released: bool,
+    pub m_button_is_pressed: bool,
     pub frame_delta_x: f64,
     pub frame_delta_y: f64,
+    // Host cursor position within a display window, used to drive the light pen.
+    pub cursor_pos: Option<(f64, f64)>,
+    pub light_pen_down: bool,
 }
 
 impl MouseData {
@@ -200,8 +224,13 @@ impl MouseData {
             r_button_was_pressed: false,
             r_button_was_released: false,
             r_button_is_pressed: false,
+            m_button_was_pressed: false,
+            m_button_was_released: false,
+            m_button_is_pressed: false,
             frame_delta_x: 0.0,
             frame_delta_y: 0.0,
+            cursor_pos: None,
+            light_pen_down: false,
         }
     }
     pub fn reset(&mut self) {
@@ -211,9 +240,13 @@ impl MouseData {
         if !self.r_button_is_pressed {
             self.r_button_was_pressed = false;
         }
+        if !self.m_button_is_pressed {
+            self.m_button_was_pressed = false;
+        }
 
         self.l_button_was_released = false;
         self.r_button_was_released = false;
+        self.m_button_was_released = false;
 
         self.frame_delta_x = 0.0;
         self.frame_delta_y = 0.0;
@@ -268,14 +301,24 @@ impl JoystickData {
 }
 
 pub struct KeyboardData {
-    pub modifiers:    KeyboardModifiers,
+    pub modifiers: KeyboardModifiers,
     pub ctrl_pressed: bool,
+    pub translator: KeyTranslator,
+    pub macro_recorder: Option<MacroRecorder>,
+    pub macro_player: Option<MacroPlayer>,
+    pub macro_tick: Instant,
+    pub macro_path: Option<PathBuf>, // The macro played by the PlayMacro hotkey
 }
 impl KeyboardData {
-    fn new() -> Self {
+    fn new(translator: KeyTranslator) -> Self {
         Self {
-            modifiers:    KeyboardModifiers::default(),
+            modifiers: KeyboardModifiers::default(),
             ctrl_pressed: false,
+            translator,
+            macro_recorder: None,
+            macro_player: None,
+            macro_tick: Instant::now(),
+            macro_path: None,
         }
     }
 }
@@ -319,6 +362,11 @@ pub fn run() {
         },
     };
 
+    // If a floppy image conversion was requested, do it now. No machine is needed.
+    if let Some(input) = &config.emulator.convert_floppy {
+        return run_convert(input, config.emulator.convert_output.as_deref());
+    }
+
     // Now that we have our configuration, we can instantiate a ResourceManager.
     let mut resource_manager = ResourceManager::from_config(config.emulator.basedir.clone(), &config.emulator.paths)
         .unwrap_or_else(|e| {
@@ -554,6 +602,24 @@ pub fn run() {
         std::process::exit(1);
     }
 
+    // Instantiate the CD-ROM image manager
+    let mut cdrom_manager = CdRomManager::new();
+
+    // Scan the "cdrom" resource
+    if let Err(e) = cdrom_manager.scan_resource(&resource_manager) {
+        eprintln!("Failed to read cdrom path: {:?}", e);
+        std::process::exit(1);
+    }
+
+    // Instantiate the cassette image manager
+    let mut cassette_manager = CassetteManager::new();
+
+    // Scan the "cassette" resource
+    if let Err(e) = cassette_manager.scan_resource(&resource_manager) {
+        eprintln!("Failed to read cassette path: {:?}", e);
+        std::process::exit(1);
+    }
+
     // Enumerate host serial ports
     let serial_ports = serialport::available_ports().unwrap_or_else(|e| {
         log::warn!("Didn't find any serial ports: {:?}", e);
@@ -605,7 +671,14 @@ pub fn run() {
 
     // If headless mode was specified, run the emulator in headless mode now
     if config.emulator.headless {
-        //return run_headless::run_headless(&config, rom_manager, floppy_manager);
+        return run_headless(
+            &config,
+            machine_config_file,
+            rom_manifest,
+            resource_manager,
+            floppy_manager,
+            vhd_manager,
+        );
     }
 
     // ----------------------------------------------------------------------------
@@ -625,9 +698,6 @@ pub fn run() {
 
     let stat_counter = Counter::new();
 
-    // KB modifiers
-    let kb_data = KeyboardData::new();
-
     // Mouse event struct
     let mouse_data = MouseData::new(config.emulator.input.reverse_mouse_buttons);
     log::debug!(
@@ -637,7 +707,13 @@ pub fn run() {
 
     let mut sound_config = Default::default();
     let mut sound_player = if config.emulator.audio.enabled {
-        let mut sound_player = SoundInterface::new(config.emulator.audio.enabled);
+        let audio_options = AudioOutputOptions {
+            sample_rate: config.emulator.audio.sample_rate,
+            buffer_size: config.emulator.audio.buffer_size,
+            max_latency_ms: config.emulator.audio.max_latency_ms,
+            resampler: config.emulator.audio.resampler,
+        };
+        let mut sound_player = SoundInterface::new(config.emulator.audio.enabled, audio_options);
 
         match sound_player.open_device() {
             Ok(_) => {
@@ -711,11 +787,31 @@ pub fn run() {
         }
     }
 
-    if let Some(mut kb_layout_resource_path) = resource_manager.get_resource_path("keyboard_layout") {
-        kb_layout_resource_path.push(format!("keyboard_{}.toml", kb_string));
-        kb_layout_file_path = Some(kb_layout_resource_path);
+    // Only positional translation uses the keyboard layout file. The other modes translate host keys
+    // as-is, or by character into the guest layout.
+    let kb_translation = config.machine.input.keyboard_translation.unwrap_or_default();
+    if kb_translation == KeyTranslationMode::Positional {
+        if let Some(mut kb_layout_resource_path) = resource_manager.get_resource_path("keyboard_layout") {
+            kb_layout_resource_path.push(format!("keyboard_{}.toml", kb_string));
+            kb_layout_file_path = Some(kb_layout_resource_path);
+        }
     }
 
+    // KB modifiers and key translation. The guest layout defaults to the keyboard layout.
+    let guest_kb_layout = config
+        .machine
+        .input
+        .guest_keyboard_layout
+        .clone()
+        .unwrap_or_else(|| kb_string.clone());
+    let kb_translator = KeyTranslator::new(kb_translation, &guest_kb_layout);
+    log::debug!(
+        "Using {:?} key translation with guest layout: {}",
+        kb_translator.mode(),
+        kb_translator.layout_name()
+    );
+    let kb_data = KeyboardData::new(kb_translator);
+
     let mut disassembly_file_path = None;
     if let Some(disassembly_file) = config.machine.disassembly_file.as_ref() {
         disassembly_file_path = Some(trace_file_base.join(disassembly_file));
@@ -725,6 +821,24 @@ pub fn run() {
         );
     }
 
+    let mut control_flow_file_path = None;
+    if let Some(control_flow_file) = config.machine.control_flow_file.as_ref() {
+        control_flow_file_path = Some(trace_file_base.join(control_flow_file));
+        log::info!(
+            "Using control flow graph file: {:?}",
+            control_flow_file_path.clone().unwrap_or(PathBuf::from("None"))
+        );
+    }
+
+    let mut analysis_file_path = None;
+    if let Some(analysis_file) = config.machine.analysis_file.as_ref() {
+        analysis_file_path = Some(trace_file_base.join(analysis_file));
+        log::info!(
+            "Using analysis export file: {:?}",
+            analysis_file_path.clone().unwrap_or(PathBuf::from("None"))
+        );
+    }
+
     let machine_builder = MachineBuilder::new()
         .with_core_config(Box::new(&config))
         .with_machine_config(&machine_config)
@@ -733,7 +847,9 @@ pub fn run() {
         .with_trace_log(trace_file_path)
         .with_sound_config(sound_config)
         .with_keyboard_layout(kb_layout_file_path)
-        .with_listing_file(disassembly_file_path);
+        .with_listing_file(disassembly_file_path)
+        .with_control_flow_file(control_flow_file_path)
+        .with_analysis_file(analysis_file_path);
 
     let machine = machine_builder.build().unwrap_or_else(|e| {
         log::error!("Failed to build machine: {:?}", e);
@@ -772,6 +888,26 @@ pub fn run() {
     timestep_manager.set_cpu_mhz(machine.get_cpu_mhz());
     timestep_manager.set_emu_update_rate(highest_rate);
     timestep_manager.set_emu_render_rate(highest_rate);
+    let low_latency = config.emulator.backend.frame_pacing == FramePacing::LowLatency;
+    if low_latency {
+        let slices = config.emulator.backend.frame_slices.unwrap_or(DEFAULT_FRAME_SLICES);
+        log::info!("Using low latency frame pacing with {} slices per frame.", slices);
+        timestep_manager.set_update_slices(slices);
+    }
+    else {
+        timestep_manager.set_frame_skip(
+            config.emulator.backend.frame_skip,
+            config.emulator.backend.auto_frame_skip,
+        );
+    }
+    // Low latency mode draws each slice as the beam races, which needs the card's live buffers.
+    let render_thread = if config.emulator.backend.render_thread && !low_latency {
+        log::info!("Drawing frames on a dedicated render thread.");
+        Some(RenderThread::new())
+    }
+    else {
+        None
+    };
 
     let gui_options = DisplayManagerGuiOptions {
         enabled: !config.gui.disabled,
@@ -782,11 +918,54 @@ pub fn run() {
         debug_drawing: false,
     };
 
+    // Resolve the default post-processing shader for each video card, then load every shader
+    // referenced by a card or scaler preset from the shader resource directory.
+    let mut card_crt_shaders = HashMap::new();
+    for card in cardlist.iter() {
+        if let Some(shader) = machine_config.video.get(card.idx).and_then(|vc| vc.crt_shader.clone()) {
+            card_crt_shaders.insert(*card, shader);
+        }
+    }
+
+    let mut crt_shaders = HashMap::new();
+    let shader_names: Vec<String> = config
+        .emulator
+        .scaler_preset
+        .iter()
+        .filter_map(|preset| preset.crt_shader.clone())
+        .chain(card_crt_shaders.values().cloned())
+        .collect();
+
+    if !shader_names.is_empty() {
+        if let Some(shader_path) = resource_manager.get_resource_path("shader") {
+            for name in shader_names {
+                if crt_shaders.contains_key(&name) {
+                    continue;
+                }
+                let path = shader_path.join(format!("{}.wgsl", name));
+                match std::fs::read_to_string(&path) {
+                    Ok(source) => {
+                        log::debug!("Loaded post-processing shader: {}", path.display());
+                        crt_shaders.insert(name, source);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to load post-processing shader {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        else {
+            log::warn!("No 'shader' resource path is defined. Post-processing shaders will not be loaded.");
+        }
+    }
+
     // Create displays.
     let mut display_manager = WgpuDisplayManagerBuilder::build(
         &config,
         cardlist,
         &config.emulator.scaler_preset,
+        crt_shaders,
+        card_crt_shaders,
         None,
         Some(MARTY_ICON),
         &gui_options,
@@ -796,12 +975,36 @@ pub fn run() {
         std::process::exit(1);
     });
 
+    // With multiple video cards installed, the primary card may not be the first.
+    if let Some(primary_id) = machine.bus().primary_video_id() {
+        display_manager.set_primary_card(primary_id);
+    }
+
     // Create joystick data
     let joy_data = JoystickData::new(
         config.emulator.input.joystick_keys.clone(),
         config.emulator.input.keyboard_joystick,
     );
 
+    // Create gamepad input, if enabled. Use the named profile, or the first profile defined.
+    let gamepad = if config.emulator.input.gamepad {
+        let profiles = &config.emulator.input.gamepad_profiles;
+        let profile = match &config.emulator.input.gamepad_profile {
+            Some(name) => profiles.iter().find(|p| p.name == *name),
+            None => profiles.first(),
+        };
+        match profile {
+            Some(profile) => GamepadInput::new(profile.clone()),
+            None => {
+                log::error!("Gamepad input enabled, but no matching gamepad profile was found.");
+                None
+            }
+        }
+    }
+    else {
+        None
+    };
+
     // Create GUI state
     let render_egui = true;
     let gui = GuiState::new(exec_control.clone());
@@ -815,6 +1018,50 @@ pub fn run() {
 
     let (sender, receiver) = crossbeam_channel::unbounded();
 
+    // Start the debug console server, if a port was specified
+    let console_server = config
+        .emulator
+        .debugger
+        .console_port
+        .and_then(|port| match ConsoleServer::bind(port) {
+            Ok(server) => {
+                log::info!("Debug console listening on port {}", port);
+                Some(server)
+            }
+            Err(e) => {
+                log::error!("Failed to start debug console on port {}: {}", port, e);
+                None
+            }
+        });
+
+    // Start the automation server, if a port or socket is configured
+    let debugger = &config.emulator.debugger;
+    let automation = if debugger.automation_port.is_some() || debugger.automation_socket.is_some() {
+        match AutomationServer::bind(debugger.automation_port, debugger.automation_socket.as_deref()) {
+            Ok(server) => {
+                log::info!("Automation server started.");
+                Some(server)
+            }
+            Err(e) => {
+                log::error!("Failed to start automation server: {}", e);
+                None
+            }
+        }
+    }
+    else {
+        None
+    };
+
+    // Create the rewind buffer, if rewind is enabled
+    let rewind = config
+        .emulator
+        .rewind
+        .enabled
+        .then(|| RewindBuffer::new(config.emulator.rewind.length));
+
+    // A quick-load program is started once the machine reaches a DOS prompt
+    let quick_load_pending = config.emulator.quick_load.program.clone();
+
     // Put everything we want to handle in event loop into an Emulator struct
     let mut emu = Emulator {
         rm: resource_manager,
@@ -827,19 +1074,42 @@ pub fn run() {
         exec_control,
         mouse_data,
         kb_data,
+        state_slot: 1,
+        state_refresh_at: None,
+        rewind,
+        rewind_at: Instant::now(),
+        movie_recorder: None,
+        movie_player: None,
         joy_data,
+        gamepad,
         stat_counter,
         gui,
         floppy_manager,
         vhd_manager,
+        host_volumes: BTreeMap::new(),
+        floppy_mounts: BTreeMap::new(),
+        floppy_sets: BTreeMap::new(),
+        floppy_media: BTreeMap::new(),
         cart_manager,
+        cdrom_manager,
+        cassette_manager,
         perf: Default::default(),
         flags: EmuFlags {
             render_gui: render_egui,
             debug_keyboard: false,
+            low_latency,
         },
         hkm: hotkey_manager,
+        console: DebugConsole::new(),
+        console_server,
+        automation,
+        script: ScriptEngine::new(),
+        script_break_cycle: None,
+        clipboard: None,
+        quick_load_pending,
         si: sound_player,
+        video_recorder: None,
+        render_thread,
         sender,
         receiver,
     };
@@ -888,6 +1158,47 @@ pub fn run() {
         std::process::exit(1);
     }
 
+    // Set the RTC clock and restore CMOS contents, if present
+    emu.init_rtc();
+
+    // Restore saved mixer settings
+    emu.init_mixer();
+
+    // Restore saved display settings
+    emu.init_display_settings();
+
+    // Restore the media, window geometry and GUI windows of the last session
+    emu.init_session();
+
+    // Apply hotkey bindings saved from the hotkey editor
+    emu.init_hotkeys();
+
+    // Resolve the configured keyboard macro, and start playing it if requested
+    emu.init_macro();
+
+    // Start network capture or replay, if configured
+    emu.init_network_tap();
+
+    // Start capturing printer output, if configured
+    emu.init_printer();
+
+    // Queue the debug console startup script, if specified
+    if let Some(script) = emu.config.emulator.debugger.console_script.clone() {
+        match emu.console.queue_script(&script) {
+            Ok(ct) => log::info!("Queued {} debug console command(s) from {}", ct, script.display()),
+            Err(e) => log::error!("Failed to read debug console script '{}': {}", script.display(), e),
+        }
+    }
+
+    // Run the startup Rhai script, if specified
+    if let Some(script) = emu.config.emulator.debugger.script.clone() {
+        let output = run_script_file(&mut emu, &script);
+        for line in &output {
+            log::info!("{}", line);
+        }
+        emu.gui.debug_console.push_output(&output);
+    }
+
     // Start emulator
     emu.start();
 
@@ -0,0 +1,330 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    render_thread.rs
+
+    Draw video frames on a dedicated thread.
+
+    Converting a video card's indexed framebuffer to RGBA (composite decoding,
+    software aspect correction, and so on) can take a significant part of each
+    frame. The render thread takes that work off the emulation thread: each
+    frame, the card's buffer is copied into a triple-buffered queue and the
+    render thread draws it with its own copy of the card's VideoRenderer. The
+    emulation thread presents the most recently completed frame and never
+    waits for the render thread; if the render thread falls behind, stale
+    frames are dropped.
+
+    The render thread's renderer is replaced with a fresh fork() of the display
+    target's renderer whenever the latter's state generation changes, so
+    settings changed through the GUI take effect on the next frame.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::Error;
+use marty_core::device_traits::videocard::{DisplayExtents, RasterRegions, VideoCard, VideoCardId};
+use videocard_renderer::{RendererEvent, VideoRenderer};
+
+/// The shared slot of a triple buffer.
+struct Exchange<T> {
+    slot:   Box<T>,
+    fresh:  bool,
+    closed: bool,
+}
+
+type SharedExchange<T> = Arc<(Mutex<Exchange<T>>, Condvar)>;
+
+/// The writing side of a triple buffer. Values are built in the back slot and then published,
+/// swapping them into the shared slot.
+struct Producer<T> {
+    back:   Box<T>,
+    shared: SharedExchange<T>,
+}
+
+/// The reading side of a triple buffer. Taking a value swaps the most recently published value
+/// into the front slot.
+struct Consumer<T> {
+    front:  Box<T>,
+    shared: SharedExchange<T>,
+}
+
+/// Create a triple buffer. Neither side ever waits for the other to finish with a slot;
+/// publishing a value that replaces one that was never taken discards the older value.
+fn triple_buffer<T: Default>() -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new((
+        Mutex::new(Exchange {
+            slot:   Box::default(),
+            fresh:  false,
+            closed: false,
+        }),
+        Condvar::new(),
+    ));
+    (
+        Producer {
+            back:   Box::default(),
+            shared: shared.clone(),
+        },
+        Consumer {
+            front: Box::default(),
+            shared,
+        },
+    )
+}
+
+impl<T> Producer<T> {
+    fn back(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    /// Publish the back slot. If the value being replaced was never taken, 'carry' is called with
+    /// the stale value and the new value first, so anything that must not be lost can be moved over.
+    fn publish(&mut self, carry: impl FnOnce(&mut T, &mut T)) {
+        let (lock, cvar) = &*self.shared;
+        let mut exchange = lock.lock().unwrap();
+        if exchange.fresh {
+            carry(&mut exchange.slot, &mut self.back);
+        }
+        std::mem::swap(&mut exchange.slot, &mut self.back);
+        exchange.fresh = true;
+        cvar.notify_one();
+    }
+
+    /// Close the buffer, waking a consumer waiting on it.
+    fn close(&mut self) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_one();
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Take the most recently published value, if there is one we have not already taken.
+    fn take(&mut self) -> Option<&mut T> {
+        let mut exchange = self.shared.0.lock().unwrap();
+        if !exchange.fresh {
+            return None;
+        }
+        std::mem::swap(&mut exchange.slot, &mut self.front);
+        exchange.fresh = false;
+        drop(exchange);
+        Some(&mut self.front)
+    }
+
+    /// Wait for a value to be published and take it. Returns None once the buffer is closed.
+    fn wait(&mut self) -> Option<&mut T> {
+        let (lock, cvar) = &*self.shared;
+        let mut exchange = cvar
+            .wait_while(lock.lock().unwrap(), |ex| !ex.fresh && !ex.closed)
+            .unwrap();
+        if exchange.closed {
+            return None;
+        }
+        std::mem::swap(&mut exchange.slot, &mut self.front);
+        exchange.fresh = false;
+        drop(exchange);
+        Some(&mut self.front)
+    }
+}
+
+/// A frame queued for drawing.
+#[derive(Default)]
+struct FrameInput {
+    card_buf: Vec<u8>,
+    extents: Option<DisplayExtents>,
+    palette: Option<Vec<[u8; 4]>>,
+    beam_pos: Option<(u32, u32)>,
+    regions: Option<RasterRegions>,
+    frame_count: u64,
+    output_len: usize,
+    // A replacement renderer, sent when the display target's renderer has changed.
+    renderer: Option<Box<VideoRenderer>>,
+}
+
+/// A frame that has been drawn.
+#[derive(Default)]
+struct FrameOutput {
+    buf: Vec<u8>,
+    frame_count: u64,
+    render_time: Duration,
+    events: Vec<RendererEvent>,
+}
+
+/// The render thread for a single display target.
+struct RenderWorker {
+    generation: Option<u64>,
+    input: Producer<FrameInput>,
+    output: Consumer<FrameOutput>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RenderWorker {
+    fn spawn(vid: VideoCardId) -> Result<Self, Error> {
+        let (input, input_rx) = triple_buffer();
+        let (output_tx, output) = triple_buffer();
+
+        let thread = std::thread::Builder::new()
+            .name(format!("render-{}", vid.idx))
+            .spawn(move || RenderWorker::run(input_rx, output_tx))?;
+
+        Ok(Self {
+            generation: None,
+            input,
+            output,
+            thread: Some(thread),
+        })
+    }
+
+    fn run(mut input: Consumer<FrameInput>, mut output: Producer<FrameOutput>) {
+        let mut renderer: Option<Box<VideoRenderer>> = None;
+
+        while let Some(frame) = input.wait() {
+            if let Some(new_renderer) = frame.renderer.take() {
+                renderer = Some(new_renderer);
+            }
+            let (renderer, extents) = match (renderer.as_mut(), frame.extents.as_ref()) {
+                (Some(renderer), Some(extents)) => (renderer, extents),
+                _ => continue,
+            };
+
+            let out = output.back();
+            out.buf.resize(frame.output_len, 0);
+            renderer.draw(
+                &frame.card_buf,
+                &mut out.buf,
+                extents,
+                frame.beam_pos,
+                frame.regions,
+                frame.palette.take(),
+            );
+            out.frame_count = frame.frame_count;
+            out.render_time = renderer.get_last_render_time();
+            out.events.clear();
+            while let Some(event) = renderer.get_event() {
+                out.events.push(event);
+            }
+
+            // Events from a frame that was never presented still need to be delivered.
+            output.publish(|stale, new| {
+                let mut events = std::mem::take(&mut stale.events);
+                events.append(&mut new.events);
+                new.events = events;
+            });
+        }
+    }
+}
+
+impl Drop for RenderWorker {
+    fn drop(&mut self) {
+        self.input.close();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Render thread panicked.");
+            }
+        }
+    }
+}
+
+/// Draws frames for each video card's display target on its own thread.
+#[derive(Default)]
+pub struct RenderThread {
+    workers: HashMap<VideoCardId, RenderWorker>,
+}
+
+impl RenderThread {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Queue the current frame of the specified video card to be drawn with 'renderer'.
+    /// The buffer selected by the renderer is copied, so the card is free to continue drawing
+    /// as soon as this returns. The output will be 'output_len' bytes.
+    pub fn submit(
+        &mut self,
+        vid: VideoCardId,
+        renderer: &mut VideoRenderer,
+        videocard: &dyn VideoCard,
+        beam_pos: Option<(u32, u32)>,
+        regions: Option<RasterRegions>,
+        output_len: usize,
+    ) -> Result<(), Error> {
+        let worker = match self.workers.get_mut(&vid) {
+            Some(worker) => worker,
+            None => {
+                log::debug!("Starting render thread for card {:?}", vid);
+                self.workers.entry(vid).or_insert(RenderWorker::spawn(vid)?)
+            }
+        };
+
+        let frame = worker.input.back();
+        frame.card_buf.clear();
+        frame
+            .card_buf
+            .extend_from_slice(videocard.get_buf(renderer.get_selected_buffer()));
+        frame.extents = Some(videocard.get_display_extents().clone());
+        frame.palette = videocard.get_palette();
+        frame.beam_pos = beam_pos;
+        frame.regions = regions;
+        frame.frame_count = videocard.get_frame_count();
+        frame.output_len = output_len;
+        frame.renderer = None;
+        if worker.generation != Some(renderer.generation()) {
+            frame.renderer = Some(Box::new(renderer.fork()));
+            worker.generation = Some(renderer.generation());
+        }
+
+        // Don't lose a renderer update if the render thread never saw the frame carrying it.
+        worker.input.publish(|stale, new| {
+            if new.renderer.is_none() {
+                new.renderer = stale.renderer.take();
+            }
+        });
+        Ok(())
+    }
+
+    /// Copy the most recently drawn frame for the specified video card into 'output_buf', passing
+    /// the render time and any events the render thread's renderer produced on to 'renderer'.
+    /// Returns the card's frame count for the frame copied, or None if no new frame was available.
+    pub fn present(&mut self, vid: VideoCardId, renderer: &mut VideoRenderer, output_buf: &mut [u8]) -> Option<u64> {
+        let frame = self.workers.get_mut(&vid)?.output.take()?;
+
+        renderer.set_last_render_time(frame.render_time);
+        for event in frame.events.drain(..) {
+            renderer.send_event(event);
+        }
+
+        // The frame may have been drawn before the display target was last resized.
+        if frame.buf.len() != output_buf.len() {
+            return None;
+        }
+        output_buf.copy_from_slice(&frame.buf);
+        Some(frame.frame_count)
+    }
+}
@@ -0,0 +1,54 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    run_convert.rs - Implement the main procedure for floppy image conversion.
+
+    Converts the image given with --convert-floppy to the file given with
+    --convert-output, choosing the format from the output file's extension,
+    then exits without starting a machine.
+
+*/
+
+use std::path::Path;
+
+use frontend_common::floppy_manager::convert::convert_image;
+
+pub fn run_convert(input: &Path, output: Option<&Path>) {
+    let output = output.unwrap_or_else(|| {
+        eprintln!("No output file specified. Provide the path to write with --convert-output.");
+        std::process::exit(1);
+    });
+
+    match convert_image(input, None, output) {
+        Ok(format) => {
+            println!("Converted {} to {} ({:?})", input.display(), output.display(), format);
+        }
+        Err(e) => {
+            eprintln!("Conversion failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
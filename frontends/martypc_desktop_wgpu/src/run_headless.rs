@@ -26,112 +26,587 @@
 
     run_headless.rs - Implement the main procedure for headless mode.
 
+    A headless run boots the configured machine without any windows and runs it
+    until a trigger or run limit is reached, then writes any requested output
+    and exits with a code describing how the run ended.
+
 */
 
+use std::{
+    ffi::OsString,
+    io::Cursor,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
 use config_toml_bpaf::ConfigFileParams;
-use frontend_common::floppy_manager::FloppyManager;
+use fluxfox::DiskImage;
+use frontend_common::{
+    automation::{server::AutomationServer, service, AutomationEvent, RpcError, RpcRequest, RpcResult},
+    floppy_manager::FloppyManager,
+    machine_manager::MachineConfigFileEntry,
+    quick_load::{at_dos_prompt, quick_load},
+    resource_manager::ResourceManager,
+    text_export::{export_text_screen, TextExportFormat},
+    types::{floppy::FloppyImageSource, sound::AudioOutputOptions},
+    vhd_manager::{host_volume::HostVolume, VhdManager},
+};
+use marty_core::{
+    breakpoints::BreakPointType,
+    cpu_common::CpuOption,
+    device_traits::videocard::BufferSelect,
+    machine::{
+        ExecutionControl,
+        ExecutionOperation,
+        ExecutionState,
+        Machine,
+        MachineBuilder,
+        MachineEvent,
+        MachineRomManifest,
+    },
+    vhd::VirtualHardDisk,
+};
+use serde_json::Value;
+use videocard_renderer::{RendererEvent, ScreenshotOptions, VideoRenderer};
 
-#[allow(dead_code)]
-pub fn run_headless(_config: &ConfigFileParams, _floppy_manager: FloppyManager) {
+use crate::{cmos, sound_player::SoundInterface, FPS_TARGET};
 
-    /*
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_LIMIT: i32 = 2;
+const EXIT_HALTED: i32 = 3;
 
-    // Init sound
-    // The cpal sound library uses generics to initialize depending on the SampleFormat type.
-    // On Windows at least a sample type of f32 is typical, but just in case...
-    let sample_fmt = SoundPlayer::get_sample_format();
-    let sp = match sample_fmt {
-        cpal::SampleFormat::F32 => SoundPlayer::new::<f32>(),
-        cpal::SampleFormat::I16 => SoundPlayer::new::<i16>(),
-        cpal::SampleFormat::U16 => SoundPlayer::new::<u16>(),
-    };
+/// The reason a headless run ended.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum HeadlessEnd {
+    Trigger,
+    Limit,
+    Halted,
+    /// An automation client requested the run end.
+    Quit,
+}
 
-    // Look up the machine description given the machine type in the configuration file
-    let machine_desc_opt = MACHINE_DESCS.get(&config.machine.model);
-    if let Some(machine_desc) = machine_desc_opt {
-        log::debug!(
-            "Given machine type {:?} got machine description: {:?}",
-            config.machine.model,
-            machine_desc
-        );
+impl HeadlessEnd {
+    /// Reaching the run limit is only a failure if we were waiting for a trigger.
+    fn exit_code(&self, have_trigger: bool) -> i32 {
+        match self {
+            HeadlessEnd::Trigger => EXIT_SUCCESS,
+            HeadlessEnd::Limit if have_trigger => EXIT_LIMIT,
+            HeadlessEnd::Limit => EXIT_SUCCESS,
+            HeadlessEnd::Halted => EXIT_HALTED,
+            HeadlessEnd::Quit => EXIT_SUCCESS,
+        }
+    }
+}
+
+pub fn run_headless(
+    config: &ConfigFileParams,
+    machine_config_file: &MachineConfigFileEntry,
+    rom_manifest: MachineRomManifest,
+    rm: ResourceManager,
+    fm: FloppyManager,
+    mut vhdm: VhdManager,
+) {
+    let opts = &config.emulator.headless_run;
+    let machine_config = machine_config_file.to_machine_config();
+
+    // Sound is only sent to the host if requested. Otherwise, it is produced and discarded.
+    let mut sound_config = Default::default();
+    let mut sound_player = if config.emulator.audio.enabled && !opts.null_audio {
+        let sound_player = open_sound(config).unwrap_or_else(|e| {
+            eprintln!("Failed to open audio device: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        });
+        sound_config = sound_player.config();
+        Some(sound_player)
     }
     else {
-        log::error!("Couldn't get machine description for {:?}", config.machine.model);
+        None
+    };
 
-        eprintln!(
-            "Couldn't get machine description for machine type {:?}. \
-             Check that you have a valid machine type specified in configuration file.",
-            config.machine.model
-        );
-        std::process::exit(1);
-    }
-
-    let (video_type, _clock_mode, _video_debug) = {
-        let mut video_type: Option<VideoType> = None;
-        let mut clock_mode: Option<ClockingMode> = None;
-        let video_cards = config.get_video_cards();
-        if video_cards.len() > 0 {
-            clock_mode = video_cards[0].clocking_mode;
-            video_type = Some(video_cards[0].video_type); // Videotype is not optional
-        }
-        (
-            video_type.unwrap_or(VideoType::CGA),
-            clock_mode.unwrap_or_default(),
-            video_cards[0].debug.unwrap_or(false),
-        )
+    let trace_file_path = config
+        .machine
+        .cpu
+        .trace_file
+        .as_ref()
+        .and_then(|trace_file| Some(rm.get_resource_path("trace")?.join(trace_file)));
+
+    let machine_builder = MachineBuilder::new()
+        .with_core_config(Box::new(config))
+        .with_machine_config(&machine_config)
+        .with_roms(rom_manifest)
+        .with_trace_mode(config.machine.cpu.trace_mode.unwrap_or_default())
+        .with_trace_log(trace_file_path)
+        .with_sound_config(sound_config);
+
+    let mut machine = machine_builder.build().unwrap_or_else(|e| {
+        eprintln!("Failed to build machine: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    });
+
+    if let Some(si) = sound_player.as_mut() {
+        for source in machine.get_sound_sources().iter() {
+            if let Err(e) = si.add_source(source) {
+                eprintln!("Failed to add sound source: {}", e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        // PC Speaker is always first sound source. Set its volume to 25%.
+        si.set_volume(0, 0.25);
+    }
+
+    let host_volumes = match prepare_machine(&mut machine, config, &rm, &fm, &mut vhdm) {
+        Ok(host_volumes) => host_volumes,
+        Err(e) => {
+            eprintln!("Failed to start machine: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
     };
 
-    // Instantiate the main Machine data struct
-    // Machine coordinates all the parts of the emulated computer
-    let mut machine = Machine::new(
-        config,
-        config.machine.model,
-        *machine_desc_opt.unwrap(),
-        config.machine.cpu.trace_mode.unwrap_or_default(),
-        video_type,
-        sp,
-        rom_manager,
-    );
+    // Set the RTC clock and restore CMOS contents, if present
+    cmos::init_rtc(&mut machine, &rm);
 
-    // Load program binary if one was specified in config options
-    if let Some(prog_bin) = &config.emulator.run_bin {
-        if let Some(prog_seg) = config.emulator.run_bin_seg {
-            if let Some(prog_ofs) = config.emulator.run_bin_ofs {
-                let prog_vec = match std::fs::read(prog_bin.clone()) {
-                    Ok(vec) => vec,
-                    Err(e) => {
-                        eprintln!("Error opening filename {:?}: {}", prog_bin, e);
-                        std::process::exit(1);
-                    }
-                };
-
-                if let Err(_) = machine.load_program(&prog_vec, prog_seg, prog_ofs) {
-                    eprintln!(
-                        "Error loading program into memory at {:04X}:{:04X}.",
-                        prog_seg, prog_ofs
-                    );
-                    std::process::exit(1);
-                };
+    // The run limit is whichever of the cycle count or emulated time is reached first.
+    let cpu_hz = machine.get_cpu_mhz() * 1_000_000.0;
+    let limit = [opts.cycles, opts.timeout.map(|secs| (cpu_hz * secs as f64) as u64)]
+        .into_iter()
+        .flatten()
+        .min();
+
+    let have_trigger = opts.trigger_address.is_some() || opts.trigger_text.is_some();
+    if let Some(address) = opts.trigger_address {
+        machine.set_breakpoints(vec![BreakPointType::ExecuteFlat(address)]);
+    }
+
+    match limit {
+        Some(limit) => println!("Running headless for up to {} cycles", limit),
+        None => println!("Running headless with no run limit"),
+    }
+
+    let debugger = &config.emulator.debugger;
+    let mut automation = if debugger.automation_port.is_some() || debugger.automation_socket.is_some() {
+        match AutomationServer::bind(debugger.automation_port, debugger.automation_socket.as_deref()) {
+            Ok(server) => {
+                println!("Automation server started");
+                Some(server)
             }
-            else {
-                eprintln!("Must specifiy program load offset.");
-                std::process::exit(1);
+            Err(e) => {
+                eprintln!("Failed to start automation server: {}", e);
+                std::process::exit(EXIT_FAILURE);
             }
         }
-        else {
-            eprintln!("Must specifiy program load segment.");
-            std::process::exit(1);
-        }
     }
+    else {
+        None
+    };
 
     let mut exec_control = ExecutionControl::new();
     exec_control.set_state(ExecutionState::Running);
 
-    loop {
-        // This should really return a Result
-        machine.run(1000, &mut exec_control);
+    // A quick-load program is started once the machine reaches a DOS prompt.
+    let quick_load_opts = &config.emulator.quick_load;
+    let mut quick_load_program = quick_load_opts.program.clone();
+
+    // Run in batches of about one frame, so that the screen can be checked for trigger text.
+    let batch = (cpu_hz / FPS_TARGET) as u64;
+    let run_start = Instant::now();
+    let start_cycles = machine.cpu_cycles();
+
+    let end = 'run: loop {
+        if let Some(server) = automation.as_mut() {
+            for request in service(server, &mut machine, &mut exec_control) {
+                if request.method == "quit" {
+                    server.respond(&request, Ok(Value::Null));
+                    break 'run HeadlessEnd::Quit;
+                }
+                let result = handle_automation_request(&mut machine, config, &rm, &fm, &request);
+                server.respond(&request, result);
+            }
+
+            // Wait while an automation client has the machine paused.
+            if let ExecutionState::Paused = exec_control.get_state() {
+                if let ExecutionOperation::None = exec_control.peek_op() {
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            }
+        }
+
+        let cycles_run = machine.cpu_cycles() - start_cycles;
+        let cycles = match limit {
+            Some(limit) if cycles_run >= limit => break HeadlessEnd::Limit,
+            Some(limit) => batch.min(limit - cycles_run),
+            None => batch,
+        };
+        machine.run(cycles as u32, &mut exec_control);
+
+        match exec_control.get_state() {
+            ExecutionState::BreakpointHit => break HeadlessEnd::Trigger,
+            ExecutionState::Halted => break HeadlessEnd::Halted,
+            _ => {}
+        }
+
+        let mut halted = false;
+        while let Some(event) = machine.get_event() {
+            if let Some(server) = automation.as_mut() {
+                if let Some(automation_event) = AutomationEvent::from_machine_event(&event) {
+                    server.notify(&automation_event);
+                }
+            }
+            match event {
+                MachineEvent::CheckpointHit(_, _) => {}
+                MachineEvent::Halted => halted = true,
+                MachineEvent::Reset => {
+                    log::debug!("Machine reset");
+                    cmos::save_cmos(&mut machine, &rm);
+                }
+            }
+        }
+        if halted {
+            break HeadlessEnd::Halted;
+        }
+
+        // Run per-frame device updates. Nothing is listening for their notifications.
+        _ = machine.frame_update();
+
+        // Trigger text isn't checked until the quick-load program has started, as the prompt
+        // it waits for could match.
+        if let Some(text) = opts.trigger_text.as_ref().filter(|_| quick_load_program.is_none()) {
+            if screen_text(&machine).map_or(false, |screen| screen.contains(text.as_str())) {
+                break HeadlessEnd::Trigger;
+            }
+        }
+
+        if let Some(program) = quick_load_program.as_ref() {
+            if screen_text(&machine).is_some_and(|screen| at_dos_prompt(&screen, quick_load_opts.prompt.as_deref())) {
+                let args = quick_load_opts.args.as_deref().unwrap_or_default();
+                if let Err(e) = quick_load(&mut machine, program, args, quick_load_opts.segment) {
+                    eprintln!("Failed to quick-load program: {}", e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+                println!("Quick-loaded {}", program.display());
+                quick_load_program = None;
+            }
+        }
+
+        match sound_player.as_mut() {
+            Some(si) => {
+                si.run();
+                // Pace the run to real time so that sound plays at the right speed.
+                let emulated = Duration::from_secs_f64((machine.cpu_cycles() - start_cycles) as f64 / cpu_hz);
+                if let Some(ahead) = emulated.checked_sub(run_start.elapsed()) {
+                    std::thread::sleep(ahead);
+                }
+            }
+            None => {
+                for source in machine.get_sound_sources() {
+                    _ = source.receiver.try_iter().count();
+                }
+            }
+        }
+    };
+
+    let cycles_run = machine.cpu_cycles() - start_cycles;
+    println!(
+        "Headless run ended ({:?}) after {} cycles ({:.3} emulated seconds) in {:.3} seconds.",
+        end,
+        cycles_run,
+        cycles_run as f64 / cpu_hz,
+        run_start.elapsed().as_secs_f64()
+    );
+
+    machine.flush_trace_logs();
+    cmos::save_cmos(&mut machine, &rm);
+
+    let mut exit_code = end.exit_code(have_trigger);
+    if let Err(e) = write_output(&machine, config, &rm) {
+        eprintln!("Failed to write headless output: {}", e);
+        exit_code = EXIT_FAILURE;
+    }
+    for volume in host_volumes.iter().filter(|volume| volume.write_back()) {
+        if let Err(e) = volume.sync_to_host() {
+            eprintln!("Failed to write back to {}: {}", volume.dir().display(), e);
+            exit_code = EXIT_FAILURE;
+        }
+    }
+    // Exiting doesn't run destructors, so drop the volumes to remove their images.
+    drop(host_volumes);
+
+    std::process::exit(exit_code);
+}
+
+fn open_sound(config: &ConfigFileParams) -> Result<SoundInterface, Error> {
+    let audio_options = AudioOutputOptions {
+        sample_rate: config.emulator.audio.sample_rate,
+        buffer_size: config.emulator.audio.buffer_size,
+        max_latency_ms: config.emulator.audio.max_latency_ms,
+        resampler: config.emulator.audio.resampler,
+    };
+    let mut sound_player = SoundInterface::new(true, audio_options);
+    sound_player.open_device()?;
+    sound_player.open_stream()?;
+    log::info!("Opened audio device: {}", sound_player.device_name());
+    Ok(sound_player)
+}
+
+/// Apply the configured CPU options, and load media and any program binary. Returns the hard disks
+/// built from host directories, which must be kept until the end of the run.
+fn prepare_machine(
+    machine: &mut Machine,
+    config: &ConfigFileParams,
+    rm: &ResourceManager,
+    fm: &FloppyManager,
+    vhdm: &mut VhdManager,
+) -> Result<Vec<HostVolume>, Error> {
+    machine.pit_adjust(config.machine.pit_phase.unwrap_or(0) & 0x03);
+
+    let cpu = &config.machine.cpu;
+    machine.set_cpu_option(CpuOption::OffRailsDetection(cpu.off_rails_detection.unwrap_or(false)));
+    machine.set_cpu_option(CpuOption::EnableServiceInterrupt(
+        cpu.service_interrupt.unwrap_or(false),
+    ));
+    machine.set_cpu_option(CpuOption::EnableWaitStates(cpu.wait_states.unwrap_or(true)));
+    machine.set_cpu_option(CpuOption::InstructionHistory(cpu.instruction_history.unwrap_or(false)));
+    machine.set_cpu_option(CpuOption::TraceLoggingEnabled(cpu.trace_on));
+
+    mount_vhds(machine, config, vhdm)?;
+    let host_volumes = mount_host_dirs(machine, config)?;
+
+    for (drive, path) in config.emulator.headless_run.floppy.iter().enumerate() {
+        load_floppy(machine, config, rm, fm, drive, path)?;
+    }
+
+    if let Some(prog_bin) = config.emulator.run_bin.as_ref() {
+        let emu = &config.emulator;
+        let (seg, ofs, vreset_seg, vreset_ofs) =
+            match (emu.run_bin_seg, emu.run_bin_ofs, emu.vreset_bin_seg, emu.vreset_bin_ofs) {
+                (Some(seg), Some(ofs), Some(vreset_seg), Some(vreset_ofs)) => (seg, ofs, vreset_seg, vreset_ofs),
+                _ => return Err(anyhow!("Must specify program load and start segment and offset.")),
+            };
+        let program = std::fs::read(prog_bin).map_err(|e| anyhow!("Error opening filename {:?}: {}", prog_bin, e))?;
+        machine
+            .load_program(&program, seg, ofs, vreset_seg, vreset_ofs)
+            .map_err(|_| anyhow!("Error loading program into memory at {:04X}:{:04X}.", seg, ofs))?;
+    }
+
+    Ok(host_volumes)
+}
+
+fn load_floppy(
+    machine: &mut Machine,
+    config: &ConfigFileParams,
+    rm: &ResourceManager,
+    fm: &FloppyManager,
+    drive: usize,
+    path: &Path,
+) -> Result<(), Error> {
+    let (data, path) = match fm.load_floppy_by_path(path.to_path_buf(), rm) {
+        Ok(FloppyImageSource::DiskImage(data, path)) => (data, path),
+        Ok(FloppyImageSource::ZipArchive(archive, path)) => return load_zip(machine, rm, fm, drive, archive, &path),
+        Ok(_) => return Err(anyhow!("{}: only disk images can be loaded", path.display())),
+        Err(e) => return Err(anyhow!("{}: {}", path.display(), e)),
+    };
+    let image = DiskImage::load(&mut Cursor::new(data), Some(path.clone()), None, None)?;
+    let write_protect = config.emulator.media.write_protect_default;
+    let fdc = machine.fdc().as_mut().ok_or(anyhow!("No floppy controller."))?;
+    fdc.attach_image(drive, image, Some(path.clone()), write_protect)?;
+    log::info!("Loaded floppy image {} into drive {}", path.display(), drive);
+    Ok(())
+}
+
+/// Load the first disk image in a zip archive, or a floppy built from the archive's contents if it
+/// has none, as the GUI frontend does. The disk is write-protected.
+fn load_zip(
+    machine: &mut Machine,
+    rm: &ResourceManager,
+    fm: &FloppyManager,
+    drive: usize,
+    archive: Vec<u8>,
+    path: &Path,
+) -> Result<(), Error> {
+    let fdc = machine.fdc().as_mut().ok_or(anyhow!("No floppy controller."))?;
+    if drive >= fdc.drive_ct() {
+        return Err(anyhow!("Invalid floppy drive: {}", drive));
+    }
+    match fm.zip_disk_images(&archive)?.first() {
+        Some(image_name) => {
+            let data = fm.read_zip_entry(&archive, image_name)?;
+            let image = DiskImage::load(&mut Cursor::new(data), Some(PathBuf::from(image_name)), None, None)?;
+            fdc.attach_image(drive, image, None, true)?;
+            log::info!("Loaded {} from {} into drive {}", image_name, path.display(), drive);
+        }
+        None => {
+            let image_type = fdc.drive(drive).get_largest_supported_image_format();
+            let data = fm.build_autofloppy_image_from_zip(archive, Some(image_type), rm)?;
+            fdc.load_image_from(drive, data, None, true)?;
+            fdc.patch_image_bpb(drive, Some(image_type))?;
+            log::info!("Built floppy from {} in drive {}", path.display(), drive);
+        }
+    }
+    Ok(())
+}
+
+/// Handle the automation requests that need more than the machine. The 'quit' method ends the
+/// run, and is answered by the caller.
+fn handle_automation_request(
+    machine: &mut Machine,
+    config: &ConfigFileParams,
+    rm: &ResourceManager,
+    fm: &FloppyManager,
+    request: &RpcRequest,
+) -> RpcResult {
+    match request.method.as_str() {
+        "mount_floppy" => {
+            let drive = request.u64_param("drive")? as usize;
+            let path = request.str_param("path")?;
+            load_floppy(machine, config, rm, fm, drive, Path::new(path))
+                .map_err(|e| RpcError::failed(format!("Failed to load floppy: {}", e)))?;
+            Ok(Value::Null)
+        }
+        "eject_floppy" => {
+            let drive = request.u64_param("drive")? as usize;
+            match machine.fdc().as_mut() {
+                Some(fdc) if drive < fdc.drive_ct() => fdc.unload_image(drive),
+                _ => return Err(RpcError::invalid_params(format!("Invalid drive: {}", drive))),
+            }
+            Ok(Value::Null)
+        }
+        method => Err(RpcError::method_not_found(method)),
+    }
+}
+
+/// Mount VHD images from the machine configuration, overridden by position by any images in the
+/// main configuration, as the GUI frontend does.
+fn mount_vhds(machine: &mut Machine, config: &ConfigFileParams, vhdm: &mut VhdManager) -> Result<(), Error> {
+    let mut vhd_names: Vec<Option<String>> = machine
+        .config()
+        .hdc
+        .as_ref()
+        .and_then(|controller| controller.drive.as_ref())
+        .map(|drives| drives.iter().map(|drive| drive.vhd.clone()).collect())
+        .unwrap_or_default();
+
+    for (drive_i, vhd) in config.emulator.media.vhd.iter().flatten().enumerate() {
+        match vhd_names.get_mut(drive_i) {
+            Some(name) => *name = Some(vhd.filename.clone()),
+            None => vhd_names.push(Some(vhd.filename.clone())),
+        }
+    }
+
+    for (drive, vhd_name) in vhd_names.into_iter().flatten().enumerate() {
+        let vhd_os_name: OsString = vhd_name.into();
+        let (vhd_file, _) = vhdm
+            .load_vhd_file_by_name(drive, &vhd_os_name)
+            .map_err(|e| anyhow!("Failed to load VHD image {:?}: {}", vhd_os_name, e))?;
+        let vhd = VirtualHardDisk::from_file(vhd_file).map_err(|e| anyhow!("Error loading VHD: {}", e))?;
+        machine
+            .mount_vhd(drive, vhd)
+            .map_err(|e| anyhow!("Error mounting VHD: {}", e))?;
+        log::info!("VHD image {:?} loaded into virtual drive: {}", vhd_os_name, drive);
+    }
+    Ok(())
+}
+
+/// Mount the host directories in the main configuration as hard disks, replacing any image in the
+/// same drive.
+fn mount_host_dirs(machine: &mut Machine, config: &ConfigFileParams) -> Result<Vec<HostVolume>, Error> {
+    let mut host_volumes = Vec::new();
+    for entry in config.emulator.media.host_dir.iter().flatten() {
+        let formats = machine
+            .hdc_supported_formats()
+            .ok_or(anyhow!("No Hard Disk Controller present!"))?;
+        let (volume, vhd) = HostVolume::new(&entry.path, &formats, entry.write_back)
+            .map_err(|e| anyhow!("{}: {}", entry.path.display(), e))?;
+        machine
+            .mount_vhd(entry.drive, vhd)
+            .map_err(|e| anyhow!("Error mounting VHD: {}", e))?;
+        log::info!(
+            "Mounted directory {} in hard disk {}",
+            entry.path.display(),
+            entry.drive
+        );
+        host_volumes.push(volume);
+    }
+    Ok(host_volumes)
+}
+
+/// Return the text shown by the primary video card, if it is in a text mode.
+fn screen_text(machine: &Machine) -> Option<String> {
+    let bus = machine.bus();
+    let screen = bus.video(&bus.primary_video_id()?)?.get_text_mode_screen(false)?;
+    Some(String::from_utf8_lossy(&export_text_screen(&screen, TextExportFormat::Text)).into_owned())
+}
+
+/// Write the screenshot and screen text requested for the end of the run.
+fn write_output(machine: &Machine, config: &ConfigFileParams, rm: &ResourceManager) -> Result<(), Error> {
+    let opts = &config.emulator.headless_run;
+    if opts.screenshot.is_none() && opts.text_screen.is_none() {
+        return Ok(());
     }
-    */
 
-    //std::process::exit(0);
+    let screenshot_dir = rm
+        .get_resource_path("screenshot")
+        .ok_or(anyhow!("No screenshot directory configured."))?;
+
+    if let Some(file) = opts.screenshot.as_ref() {
+        let path = screenshot_dir.join(file);
+        save_screenshot(machine, config, &path)?;
+        println!("Saved screenshot: {}", path.display());
+    }
+
+    if let Some(file) = opts.text_screen.as_ref() {
+        let path = screenshot_dir.join(file);
+        let text = screen_text(machine).ok_or(anyhow!("Video card is not in a supported text mode."))?;
+        std::fs::write(&path, text)?;
+        println!("Saved screen text: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Render the last complete frame of the primary video card to a PNG file. There is no display
+/// target to take scaler settings from, so the default cropped aperture is used with no aspect
+/// correction.
+fn save_screenshot(machine: &Machine, config: &ConfigFileParams, path: &Path) -> Result<(), Error> {
+    let bus = machine.bus();
+    let vid = bus.primary_video_id().ok_or(anyhow!("No video card present."))?;
+    let video = bus.video(&vid).ok_or(anyhow!("No video card present."))?;
+    let extents = video.get_display_extents();
+
+    let mut renderer = VideoRenderer::new(vid.vtype);
+    renderer.set_line_double(extents.double_scan);
+    let aperture = &extents.apertures[renderer.get_params().aperture as usize];
+    let h = if extents.double_scan {
+        aperture.h * 2
+    }
+    else {
+        aperture.h
+    };
+    renderer.resize((aperture.w, h).into());
+    renderer.cga_direct_mode_update(extents.mode_byte);
+    renderer.set_mode_byte(extents.mode_byte);
+
+    let metadata = vec![
+        ("Software".to_string(), format!("MartyPC {}", env!("CARGO_PKG_VERSION"))),
+        ("Machine".to_string(), config.machine.config_name.clone()),
+        ("Display Mode".to_string(), format!("{:?}", video.get_display_mode())),
+        ("Frame".to_string(), video.get_frame_count().to_string()),
+    ];
+    renderer.request_screenshot(path, ScreenshotOptions { raw: false, metadata });
+
+    let backend = renderer.get_params().backend;
+    let mut frame: Vec<u8> = vec![0; (backend.w * backend.h * 4) as usize];
+    renderer.draw(
+        video.get_buf(BufferSelect::Front),
+        &mut frame,
+        extents,
+        None,
+        None,
+        video.get_palette(),
+    );
+
+    match renderer.get_event() {
+        Some(RendererEvent::ScreenshotSaved) => Ok(()),
+        _ => Err(anyhow!("Failed to write screenshot: {}", path.display())),
+    }
 }
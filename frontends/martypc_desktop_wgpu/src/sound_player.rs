@@ -30,17 +30,35 @@
 
 */
 
-const MAX_BUFFER_SIZE: u32 = 100;
+const DEFAULT_BUFFER_SIZE: u32 = 100;
+// Sources are mixed to stereo so that they can be panned.
+const MIXER_CHANNELS: u16 = 2;
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{anyhow, Error};
 use crossbeam_channel::Receiver;
-use frontend_common::types::sound::SoundSourceStats;
+use frontend_common::{
+    audio_capture::{AudioCapture, CaptureSourceInfo},
+    audio_resampler::Resampler,
+    types::sound::{
+        AudioOutputOptions,
+        AudioOutputStats,
+        MixerChannelConfig,
+        MixerConfig,
+        ResamplerQuality,
+        SoundSourceStats,
+    },
+};
 use marty_core::{
     device_traits::sounddevice::AudioSample,
     sound::{SoundOutputConfig, SoundSourceDescriptor},
 };
 use rodio::{
-    cpal::{traits::HostTrait, SupportedBufferSize},
+    cpal::{traits::HostTrait, SampleRate, SupportedBufferSize},
     DeviceTrait,
     Sink,
     SupportedStreamConfig,
@@ -53,7 +71,37 @@ pub struct SoundSource {
     pub receiver: Receiver<AudioSample>,
     pub sample_ct: u64,
     pub volume: f32,
+    pub pan: f32,
+    pub muted: bool,
     pub sink: Sink,
+    resampler: Resampler,
+    // Length in frames of each buffer appended to the sink that has not yet finished playing.
+    queued: VecDeque<usize>,
+    producing: bool,
+    underruns: u64,
+    dropped: u64,
+}
+
+impl SoundSource {
+    fn update_sink_volume(&mut self) {
+        self.sink.set_volume(if self.muted { 0.0 } else { self.volume });
+    }
+
+    /// Return the left and right gains for the current pan position. The center position leaves
+    /// both channels at full volume.
+    fn pan_gains(&self) -> (f32, f32) {
+        ((1.0 - self.pan).min(1.0), (1.0 + self.pan).min(1.0))
+    }
+
+    /// Return the number of frames queued in the sink. Buffers the sink has finished playing are
+    /// removed from the queue.
+    fn queued_frames(&mut self) -> usize {
+        let pending = self.sink.len();
+        while self.queued.len() > pending {
+            self.queued.pop_front();
+        }
+        self.queued.iter().sum()
+    }
 }
 
 pub struct SoundInterface {
@@ -62,10 +110,13 @@ pub struct SoundInterface {
     sample_rate: u32,
     sample_format: String, // We don't really need this, so I am not converting it to an enum.
     channels: usize,
+    buffer_size: u32,
+    options: AudioOutputOptions,
     device: Option<rodio::cpal::Device>,
     stream: Option<rodio::OutputStream>,
     stream_handle: Option<rodio::OutputStreamHandle>,
     sources: Vec<SoundSource>,
+    capture: Option<AudioCapture>,
 }
 
 impl Default for SoundInterface {
@@ -76,18 +127,22 @@ impl Default for SoundInterface {
             sample_rate: 0,
             sample_format: String::new(),
             channels: 0,
+            buffer_size: 0,
+            options: Default::default(),
             device: None,
             stream: None,
             stream_handle: None,
             sources: Vec::new(),
+            capture: None,
         }
     }
 }
 
 impl SoundInterface {
-    pub fn new(enabled: bool) -> SoundInterface {
+    pub fn new(enabled: bool, options: AudioOutputOptions) -> SoundInterface {
         SoundInterface {
             enabled,
+            options,
             ..Default::default()
         }
     }
@@ -101,16 +156,10 @@ impl SoundInterface {
         let device_name = audio_device.name()?;
         let default_config = audio_device.default_output_config()?;
 
+        let requested_size = self.options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
         let new_max = match default_config.buffer_size() {
-            SupportedBufferSize::Range { min, .. } => {
-                if *min > MAX_BUFFER_SIZE {
-                    *min
-                }
-                else {
-                    MAX_BUFFER_SIZE
-                }
-            }
-            _ => MAX_BUFFER_SIZE,
+            SupportedBufferSize::Range { min, max } => requested_size.clamp(*min, (*max).max(*min)),
+            _ => requested_size,
         };
         log::debug!(
             "Device buffer size: {:?} Overriding max buffer size to: {}",
@@ -118,9 +167,28 @@ impl SoundInterface {
             new_max
         );
 
+        let mut sample_rate = default_config.sample_rate();
+        if let Some(requested_rate) = self.options.sample_rate {
+            let supported = audio_device.supported_output_configs()?.any(|c| {
+                c.channels() == default_config.channels()
+                    && c.sample_format() == default_config.sample_format()
+                    && (c.min_sample_rate().0..=c.max_sample_rate().0).contains(&requested_rate)
+            });
+            if supported {
+                sample_rate = SampleRate(requested_rate);
+            }
+            else {
+                log::warn!(
+                    "Audio device does not support a sample rate of {}Hz, using {}Hz",
+                    requested_rate,
+                    sample_rate.0
+                );
+            }
+        }
+
         let config = SupportedStreamConfig::new(
             default_config.channels(),
-            default_config.sample_rate(),
+            sample_rate,
             SupportedBufferSize::Range { min: 0, max: new_max },
             default_config.sample_format(),
         );
@@ -138,10 +206,13 @@ impl SoundInterface {
                 sample_rate,
                 sample_format,
                 channels,
+                buffer_size: new_max,
+                options: self.options.clone(),
                 device: Some(audio_device),
                 stream: Some(stream),
                 stream_handle: Some(stream_handle),
                 sources: Vec::new(),
+                capture: None,
             }
         };
 
@@ -151,6 +222,12 @@ impl SoundInterface {
     pub fn add_source(&mut self, source: &SoundSourceDescriptor) -> Result<(), Error> {
         let stream_handle = self.stream_handle.as_ref().unwrap();
         let sink = Sink::try_new(stream_handle)?;
+        let resampler = Resampler::new(
+            self.options.resampler,
+            source.sample_rate,
+            self.sample_rate,
+            MIXER_CHANNELS as usize,
+        );
 
         self.sources.push(SoundSource {
             name: source.name.clone(),
@@ -160,21 +237,123 @@ impl SoundInterface {
             sample_ct: 0,
             sink,
             volume: 1.0,
+            pan: 0.0,
+            muted: false,
+            resampler,
+            queued: VecDeque::new(),
+            producing: false,
+            underruns: 0,
+            dropped: 0,
         });
 
         Ok(())
     }
 
     pub fn run(&mut self) {
-        for source in self.sources.iter_mut() {
+        let max_latency_frames = self
+            .options
+            .max_latency_ms
+            .map(|ms| (self.sample_rate as u64 * ms as u64 / 1000) as usize);
+
+        for (s_idx, source) in self.sources.iter_mut().enumerate() {
             let samples_in = source.receiver.try_iter().collect::<Vec<f32>>();
             //log::debug!("received {} samples from channel {}", samples_in.len(), source.name);
             source.sample_ct += (samples_in.len() / source.channels as usize) as u64;
-            let sink_buffer = rodio::buffer::SamplesBuffer::new(source.channels, source.sample_rate, samples_in);
+
+            // Mix the source down to stereo, applying the pan position. Mono sources are copied to
+            // both channels, and the first two channels of a multichannel source are used.
+            let (left, right) = source.pan_gains();
+            let channels = source.channels.max(1) as usize;
+            let mut samples_mixed = Vec::with_capacity(samples_in.len() / channels * MIXER_CHANNELS as usize);
+            for frame in samples_in.chunks_exact(channels) {
+                let (l, r) = if channels == 1 {
+                    (frame[0], frame[0])
+                }
+                else {
+                    (frame[0], frame[1])
+                };
+                samples_mixed.push(l * left);
+                samples_mixed.push(r * right);
+            }
+
+            // Convert to the output sample rate ourselves so that the sink plays the buffer as-is.
+            let mut samples_out = Vec::with_capacity(samples_mixed.len() + samples_mixed.len() / 8);
+            source.resampler.process(&samples_mixed, &mut samples_out);
+
+            if let Some(capture) = &mut self.capture {
+                // The sink applies the source's volume, so apply it to the captured mix here.
+                let gain = if source.muted { 0.0 } else { source.volume };
+                let captured = samples_out.iter().map(|s| s * gain).collect::<Vec<f32>>();
+                if let Err(e) = capture.push_source(s_idx, &samples_in, &captured) {
+                    log::error!("Audio capture failed: {}", e);
+                    self.capture = None;
+                }
+            }
+
+            let frames_out = samples_out.len() / MIXER_CHANNELS as usize;
+            if frames_out == 0 {
+                source.producing = false;
+                continue;
+            }
+
+            let queued_frames = source.queued_frames();
+            if queued_frames == 0 && source.producing {
+                // The sink ran dry while the source was still producing samples.
+                source.underruns += 1;
+            }
+            source.producing = true;
+
+            if let Some(max_frames) = max_latency_frames {
+                if queued_frames > max_frames {
+                    // Playback has fallen too far behind; drop this block to catch up.
+                    source.dropped += 1;
+                    continue;
+                }
+            }
+
+            let sink_buffer = rodio::buffer::SamplesBuffer::new(MIXER_CHANNELS, self.sample_rate, samples_out);
             source.sink.append(sink_buffer);
+            source.queued.push_back(frames_out);
+        }
+
+        if let Some(capture) = &mut self.capture {
+            if let Err(e) = capture.mix() {
+                log::error!("Audio capture failed: {}", e);
+                self.capture = None;
+            }
         }
     }
 
+    /// Start capturing sound output to the WAV file at `path`, optionally writing a separate file
+    /// for each sound source. Returns the paths of the files being written.
+    pub fn start_capture(&mut self, path: &Path, stems: bool) -> Result<Vec<PathBuf>, Error> {
+        let sources = self
+            .sources
+            .iter()
+            .map(|s| CaptureSourceInfo {
+                name: s.name.clone(),
+                sample_rate: s.sample_rate,
+                channels: s.channels,
+            })
+            .collect::<Vec<_>>();
+        let capture = AudioCapture::new(path, self.sample_rate, &sources, stems)?;
+        let paths = capture.paths();
+        self.capture = Some(capture);
+        Ok(paths)
+    }
+
+    /// Stop capturing sound output. Returns the paths of the files written.
+    pub fn stop_capture(&mut self) -> Result<Vec<PathBuf>, Error> {
+        match self.capture.take() {
+            Some(capture) => capture.finish(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
     pub fn open_stream(&mut self) -> Result<(), Error> {
         if self.device.is_none() {
             return Err(anyhow!("No audio device open."));
@@ -189,11 +368,66 @@ impl SoundInterface {
         self.device_name.clone()
     }
 
+    /// Set the resampler quality used for all sources.
+    pub fn set_resampler(&mut self, quality: ResamplerQuality) {
+        self.options.resampler = quality;
+        for source in self.sources.iter_mut() {
+            if source.resampler.quality() != quality {
+                source.resampler =
+                    Resampler::new(quality, source.sample_rate, self.sample_rate, MIXER_CHANNELS as usize);
+            }
+        }
+    }
+
+    /// Set the maximum audio queued per source before samples are dropped, or None for no limit.
+    pub fn set_max_latency(&mut self, max_latency_ms: Option<u32>) {
+        self.options.max_latency_ms = max_latency_ms;
+    }
+
     pub fn set_volume(&mut self, s_idx: usize, volume: f32) {
         if s_idx < self.sources.len() {
             let source = &mut self.sources[s_idx];
             source.volume = volume;
-            source.sink.set_volume(source.volume);
+            source.update_sink_volume();
+        }
+    }
+
+    pub fn set_pan(&mut self, s_idx: usize, pan: f32) {
+        if s_idx < self.sources.len() {
+            self.sources[s_idx].pan = pan.clamp(-1.0, 1.0);
+        }
+    }
+
+    pub fn set_muted(&mut self, s_idx: usize, muted: bool) {
+        if s_idx < self.sources.len() {
+            let source = &mut self.sources[s_idx];
+            source.muted = muted;
+            source.update_sink_volume();
+        }
+    }
+
+    /// Apply saved mixer settings to any sources with a matching name.
+    pub fn apply_mixer_config(&mut self, config: &MixerConfig) {
+        for source in self.sources.iter_mut() {
+            if let Some(channel) = config.get(&source.name) {
+                source.volume = channel.volume;
+                source.pan = channel.pan.clamp(-1.0, 1.0);
+                source.muted = channel.muted;
+                source.update_sink_volume();
+            }
+        }
+    }
+
+    /// Update the provided mixer settings with the settings of each source. Settings for sources
+    /// not present in this machine are kept.
+    pub fn update_mixer_config(&self, config: &mut MixerConfig) {
+        for source in self.sources.iter() {
+            config.set(MixerChannelConfig {
+                name:   source.name.clone(),
+                volume: source.volume,
+                pan:    source.pan,
+                muted:  source.muted,
+            });
         }
     }
 
@@ -202,7 +436,18 @@ impl SoundInterface {
             enabled: self.enabled,
             sample_rate: self.sample_rate,
             channels: self.channels,
-            buffer_size: 1024,
+            buffer_size: self.buffer_size as usize,
+        }
+    }
+
+    pub fn output_stats(&self) -> AudioOutputStats {
+        AudioOutputStats {
+            device_name: self.device_name.clone(),
+            sample_rate: self.sample_rate,
+            sample_format: self.sample_format.clone(),
+            buffer_size: self.buffer_size,
+            max_latency_ms: self.options.max_latency_ms,
+            resampler: self.options.resampler,
         }
     }
 
@@ -215,6 +460,11 @@ impl SoundInterface {
                 channels: s.channels,
                 sample_ct: s.sample_ct,
                 volume: s.volume,
+                pan: s.pan,
+                muted: s.muted,
+                latency_ms: s.queued.iter().sum::<usize>() as f32 * 1000.0 / self.sample_rate.max(1) as f32,
+                underruns: s.underruns,
+                dropped: s.dropped,
             })
             .collect()
     }
@@ -0,0 +1,265 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    video_recorder.rs
+
+    Record emulated video frames to a video file, animated GIF or PNG sequence.
+
+    Frames are submitted along with the video card's frame counter, so that the
+    recording advances at the guest's refresh rate regardless of how often the
+    host presents. Encoding happens on a background thread; MP4, FFV1 and GIF
+    output is produced by piping raw RGBA frames to an external ffmpeg process.
+
+*/
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::JoinHandle,
+};
+
+use anyhow::{anyhow, bail, Error};
+use frontend_common::types::video_capture::VideoCaptureFormat;
+use marty_common::VideoDimensions;
+use videocard_renderer::{AspectCorrectionMode, VideoRenderer};
+
+/// Number of frames that may be queued for the writer thread before submitting blocks.
+const FRAME_QUEUE_LEN: usize = 30;
+/// Upper limit on how many times a frame is repeated to fill in guest frames we never saw.
+const MAX_FRAME_REPEAT: u64 = 60;
+
+pub struct VideoRecorder {
+    format: VideoCaptureFormat,
+    path: PathBuf,
+    dims: VideoDimensions,
+    last_frame: Option<u64>,
+    frames_written: u64,
+    sender: Option<SyncSender<Vec<u8>>>,
+    writer: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl VideoRecorder {
+    /// Start a new recording at `path`. For `PngSequence`, `path` is a directory that will be
+    /// created. The recording size is fixed to `dims`; frames of other sizes are cropped or
+    /// padded to fit.
+    pub fn start(
+        format: VideoCaptureFormat,
+        path: &Path,
+        dims: VideoDimensions,
+        rate: f64,
+        ffmpeg: Option<&Path>,
+    ) -> Result<Self, Error> {
+        if dims.w == 0 || dims.h == 0 {
+            bail!("Invalid video dimensions: {}x{}", dims.w, dims.h);
+        }
+
+        let (sender, receiver) = sync_channel::<Vec<u8>>(FRAME_QUEUE_LEN);
+
+        let writer = match format {
+            VideoCaptureFormat::PngSequence => {
+                std::fs::create_dir_all(path)?;
+                let dir = path.to_path_buf();
+                std::thread::spawn(move || write_png_sequence(receiver, &dir, dims))
+            }
+            _ => {
+                let child = spawn_ffmpeg(format, path, dims, rate, ffmpeg)?;
+                std::thread::spawn(move || write_ffmpeg(receiver, child))
+            }
+        };
+
+        log::debug!(
+            "Started {} recording of {}x{} at {:.2}Hz: {}",
+            format,
+            dims.w,
+            dims.h,
+            rate,
+            path.display()
+        );
+
+        Ok(Self {
+            format,
+            path: path.to_path_buf(),
+            dims,
+            last_frame: None,
+            frames_written: 0,
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn format(&self) -> VideoCaptureFormat {
+        self.format
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Submit the currently displayed frame. `frame_count` is the video card's frame counter;
+    /// a frame already recorded is ignored, and if the counter skipped ahead the frame is
+    /// repeated so the recording stays in step with the guest.
+    pub fn submit_frame(&mut self, frame_count: u64, buf: &[u8], dims: VideoDimensions) -> Result<(), Error> {
+        let repeat = match self.last_frame {
+            Some(last) if frame_count == last => return Ok(()),
+            Some(last) if frame_count > last => (frame_count - last).min(MAX_FRAME_REPEAT),
+            _ => 1,
+        };
+        self.last_frame = Some(frame_count);
+
+        let sender = self.sender.as_ref().ok_or(anyhow!("Recording has been stopped."))?;
+        let frame = fit_frame(buf, dims, self.dims);
+        for _ in 0..repeat {
+            sender
+                .send(frame.clone())
+                .map_err(|_| anyhow!("Video writer stopped unexpectedly."))?;
+        }
+        self.frames_written += repeat;
+        Ok(())
+    }
+
+    /// Finish the recording, waiting for all queued frames to be written. Returns the number of
+    /// frames recorded.
+    pub fn stop(mut self) -> Result<u64, Error> {
+        self.finish()?;
+        Ok(self.frames_written)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        // Dropping the sender ends the writer thread's receive loop.
+        self.sender = None;
+        match self.writer.take() {
+            Some(writer) => writer.join().map_err(|_| anyhow!("Video writer thread panicked."))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for VideoRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Error finishing video recording: {}", e);
+        }
+    }
+}
+
+/// Copy an RGBA frame into a buffer of the recording size, cropping or padding with black.
+fn fit_frame(buf: &[u8], src: VideoDimensions, dst: VideoDimensions) -> Vec<u8> {
+    let src_len = (src.w * src.h * 4) as usize;
+    if src == dst && buf.len() >= src_len {
+        return buf[..src_len].to_vec();
+    }
+
+    let mut frame = [0u8, 0, 0, 0xFF].repeat((dst.w * dst.h) as usize);
+    let copy_w = (src.w.min(dst.w) * 4) as usize;
+    for y in 0..src.h.min(dst.h) as usize {
+        let src_start = y * (src.w * 4) as usize;
+        let dst_start = y * (dst.w * 4) as usize;
+        if src_start + copy_w > buf.len() {
+            break;
+        }
+        frame[dst_start..dst_start + copy_w].copy_from_slice(&buf[src_start..src_start + copy_w]);
+    }
+    frame
+}
+
+fn spawn_ffmpeg(
+    format: VideoCaptureFormat,
+    path: &Path,
+    dims: VideoDimensions,
+    rate: f64,
+    ffmpeg: Option<&Path>,
+) -> Result<Child, Error> {
+    let mut cmd = Command::new(ffmpeg.unwrap_or(Path::new("ffmpeg")));
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(["-s", &format!("{}x{}", dims.w, dims.h)])
+        .args(["-r", &format!("{:.4}", rate)])
+        .args(["-i", "-"]);
+
+    match format {
+        VideoCaptureFormat::Mp4 => {
+            // yuv420p requires even dimensions, so pad by a pixel if needed.
+            cmd.args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+                .args(["-c:v", "libx264", "-preset", "veryfast", "-crf", "18"])
+                .args(["-pix_fmt", "yuv420p"]);
+        }
+        VideoCaptureFormat::Ffv1 => {
+            cmd.args(["-c:v", "ffv1", "-level", "3", "-pix_fmt", "bgr0"]);
+        }
+        VideoCaptureFormat::Gif => {
+            cmd.args([
+                "-vf",
+                "split[a][b];[a]palettegen=stats_mode=full[p];[b][p]paletteuse=dither=none",
+            ]);
+        }
+        VideoCaptureFormat::PngSequence => unreachable!("PNG sequences are not encoded by ffmpeg"),
+    }
+
+    cmd.arg(path).stdin(Stdio::piped()).stdout(Stdio::null());
+
+    cmd.spawn()
+        .map_err(|e| anyhow!("Couldn't launch ffmpeg ({}). Is it installed?", e))
+}
+
+fn write_ffmpeg(receiver: Receiver<Vec<u8>>, mut child: Child) -> Result<(), Error> {
+    let mut stdin = child.stdin.take().ok_or(anyhow!("ffmpeg has no input pipe."))?;
+
+    let mut write_result = Ok(());
+    for frame in receiver {
+        if let Err(e) = stdin.write_all(&frame) {
+            write_result = Err(anyhow!("Error writing to ffmpeg: {}", e));
+            break;
+        }
+    }
+
+    // Closing stdin signals end of stream, letting ffmpeg finalize the file.
+    drop(stdin);
+    let status = child.wait()?;
+    write_result?;
+    if !status.success() {
+        bail!("ffmpeg exited with {}", status);
+    }
+    Ok(())
+}
+
+fn write_png_sequence(receiver: Receiver<Vec<u8>>, dir: &Path, dims: VideoDimensions) -> Result<(), Error> {
+    for (i, frame) in receiver.into_iter().enumerate() {
+        let path = dir.join(format!("frame_{:06}.png", i));
+        image::save_buffer(&path, &frame, dims.w, dims.h, image::ColorType::Rgba8)?;
+    }
+    Ok(())
+}
+
+/// Return the dimensions of the image a renderer draws into its backend buffer.
+pub fn capture_dimensions(renderer: &mut VideoRenderer) -> VideoDimensions {
+    match renderer.get_params().aspect_correction {
+        // Software aspect correction stretches the image into the backend buffer.
+        AspectCorrectionMode::Software => renderer.get_display_dimensions(),
+        _ => renderer.get_buf_dimensions(),
+    }
+}
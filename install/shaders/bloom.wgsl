@@ -0,0 +1,38 @@
+// MartyPC post-processing shader: bloom
+//
+// Adds a glow around bright areas of the image by blending in a wide blur of
+// the pixels that exceed a brightness threshold.
+//
+// Post-processing shaders only define fs_main(). The bindings r_tex_color,
+// r_tex_sampler and post_params are declared by MartyPC before this file is
+// compiled.
+
+const THRESHOLD: f32 = 0.5;
+const STRENGTH: f32 = 0.6;
+const RADIUS: f32 = 3.0;
+
+fn bright_pass(uv: vec2<f32>) -> vec3<f32> {
+    let color = textureSampleLevel(r_tex_color, r_tex_sampler, uv, 0.0).rgb;
+    let luma = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+    return color * smoothstep(THRESHOLD, 1.0, luma);
+}
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    let base = textureSampleLevel(r_tex_color, r_tex_sampler, tex_coord, 0.0).rgb;
+    let texel = RADIUS / post_params.screen_size;
+
+    var glow = vec3<f32>(0.0);
+    var weight_sum = 0.0;
+    for (var y = -2; y <= 2; y = y + 1) {
+        for (var x = -2; x <= 2; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y));
+            let weight = exp(-dot(offset, offset) / 4.0);
+            glow = glow + bright_pass(tex_coord + offset * texel) * weight;
+            weight_sum = weight_sum + weight;
+        }
+    }
+    glow = glow / weight_sum;
+
+    return vec4<f32>(clamp(base + glow * STRENGTH, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
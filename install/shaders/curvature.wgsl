@@ -0,0 +1,30 @@
+// MartyPC post-processing shader: curvature
+//
+// Bends the whole output surface like the face of a CRT tube and darkens the
+// edges with a soft vignette.
+//
+// Post-processing shaders only define fs_main(). The bindings r_tex_color,
+// r_tex_sampler and post_params are declared by MartyPC before this file is
+// compiled.
+
+const CURVATURE: vec2<f32> = vec2<f32>(4.5, 4.0);
+const VIGNETTE: f32 = 0.25;
+
+fn warp(uv: vec2<f32>) -> vec2<f32> {
+    var centered = uv * 2.0 - 1.0;
+    let offset = abs(centered.yx) / CURVATURE;
+    centered = centered + centered * offset * offset;
+    return centered * 0.5 + 0.5;
+}
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    let uv = warp(tex_coord);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let color = textureSampleLevel(r_tex_color, r_tex_sampler, uv, 0.0);
+    let edge = uv * (1.0 - uv.yx);
+    let vignette = clamp(pow(edge.x * edge.y * 16.0, VIGNETTE), 0.0, 1.0);
+    return vec4<f32>(color.rgb * vignette, 1.0);
+}
@@ -0,0 +1,30 @@
+// MartyPC post-processing shader: mask
+//
+// Overlays an aperture grille style phosphor mask. Each group of three
+// surface pixels is tinted red, green and blue in turn, then the result is
+// brightened to compensate for the light lost to the mask.
+//
+// Post-processing shaders only define fs_main(). The bindings r_tex_color,
+// r_tex_sampler and post_params are declared by MartyPC before this file is
+// compiled.
+
+const MASK_DARK: f32 = 0.6;
+const MASK_BOOST: f32 = 1.35;
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(r_tex_color, r_tex_sampler, tex_coord, 0.0).rgb;
+    let column = u32(floor(tex_coord.x * post_params.screen_size.x)) % 3u;
+
+    var mask = vec3<f32>(MASK_DARK, MASK_DARK, MASK_DARK);
+    if (column == 0u) {
+        mask.r = 1.0;
+    }
+    else if (column == 1u) {
+        mask.g = 1.0;
+    }
+    else {
+        mask.b = 1.0;
+    }
+    return vec4<f32>(clamp(color * mask * MASK_BOOST, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
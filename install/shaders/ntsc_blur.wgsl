@@ -0,0 +1,48 @@
+// MartyPC post-processing shader: ntsc_blur
+//
+// Approximates the limited bandwidth of an NTSC signal. Luma is kept fairly
+// sharp while chroma is smeared horizontally across several emulated pixels,
+// producing the soft color fringing of a composite or RF connection.
+//
+// Post-processing shaders only define fs_main(). The bindings r_tex_color,
+// r_tex_sampler and post_params are declared by MartyPC before this file is
+// compiled.
+
+const LUMA_TAPS: i32 = 1;
+const CHROMA_TAPS: i32 = 4;
+
+fn rgb_to_yiq(c: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(c, vec3<f32>(0.299, 0.587, 0.114)),
+        dot(c, vec3<f32>(0.596, -0.274, -0.322)),
+        dot(c, vec3<f32>(0.211, -0.523, 0.312)),
+    );
+}
+
+fn yiq_to_rgb(c: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(c, vec3<f32>(1.0, 0.956, 0.621)),
+        dot(c, vec3<f32>(1.0, -0.272, -0.647)),
+        dot(c, vec3<f32>(1.0, -1.106, 1.703)),
+    );
+}
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    // Step by roughly one emulated pixel, relative to the output surface.
+    let pixel_step = vec2<f32>(1.0 / post_params.texture_size.x, 0.0);
+
+    var luma = 0.0;
+    for (var i = -LUMA_TAPS; i <= LUMA_TAPS; i = i + 1) {
+        luma = luma + rgb_to_yiq(textureSampleLevel(r_tex_color, r_tex_sampler, tex_coord + pixel_step * f32(i), 0.0).rgb).x;
+    }
+    luma = luma / f32(LUMA_TAPS * 2 + 1);
+
+    var chroma = vec2<f32>(0.0);
+    for (var i = -CHROMA_TAPS; i <= CHROMA_TAPS; i = i + 1) {
+        chroma = chroma + rgb_to_yiq(textureSampleLevel(r_tex_color, r_tex_sampler, tex_coord + pixel_step * f32(i), 0.0).rgb).yz;
+    }
+    chroma = chroma / f32(CHROMA_TAPS * 2 + 1);
+
+    return vec4<f32>(clamp(yiq_to_rgb(vec3<f32>(luma, chroma)), vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
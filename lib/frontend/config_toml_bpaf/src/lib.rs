@@ -40,9 +40,19 @@ use std::{
 use frontend_common::{
     display_scaler::ScalerPreset,
     resource_manager::PathConfigItem,
+    timestep_manager::FramePacing,
+    types::{
+        display::FullscreenMode,
+        floppy::FloppyWriteBack,
+        gui::OsdPosition,
+        sound::ResamplerQuality,
+        video_capture::VideoCaptureFormat,
+    },
     BenchmarkEndCondition,
+    GamepadProfile,
     HotkeyConfigEntry,
     JoyKeyEntry,
+    KeyTranslationMode,
     MartyGuiTheme,
 };
 use marty_common::VideoDimensions;
@@ -61,6 +71,18 @@ const fn _default_true() -> bool {
 const fn _default_false() -> bool {
     false
 }
+const fn _default_osd_duration() -> u64 {
+    2000
+}
+const fn _default_rewind_interval() -> u64 {
+    1000
+}
+const fn _default_rewind_length() -> usize {
+    60
+}
+const fn _default_quick_load_segment() -> u16 {
+    0x2000
+}
 
 mod coreconfig;
 
@@ -101,18 +123,114 @@ pub struct VhdConfigEntry {
     pub filename: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct HostDirConfigEntry {
+    pub drive: usize,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub write_back: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Media {
     pub raw_sector_image_extensions: Option<Vec<String>>,
     #[serde(default)]
     pub write_protect_default: bool,
+    #[serde(default)]
+    pub floppy_write_back: FloppyWriteBack,
+    #[serde(default = "_default_true")]
+    pub floppy_backup: bool,
     pub vhd: Option<Vec<VhdConfigEntry>>,
+    pub host_dir: Option<Vec<HostDirConfigEntry>>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Audio {
     #[serde(default = "_default_true")]
     pub enabled: bool,
+    #[serde(default)]
+    pub capture_stems: bool,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub max_latency_ms: Option<u32>,
+    #[serde(default)]
+    pub resampler: ResamplerQuality,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct VideoCapture {
+    #[serde(default)]
+    pub format: VideoCaptureFormat,
+    pub ffmpeg_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rewind {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "_default_rewind_interval")]
+    pub interval_ms: u64,
+    #[serde(default = "_default_rewind_length")]
+    pub length: usize,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: _default_rewind_interval(),
+            length: _default_rewind_length(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuickLoad {
+    pub program: Option<PathBuf>,
+    pub args: Option<String>,
+    pub prompt: Option<String>,
+    #[serde(default = "_default_quick_load_segment")]
+    pub segment: u16,
+}
+
+impl Default for QuickLoad {
+    fn default() -> Self {
+        Self {
+            program: None,
+            args: None,
+            prompt: None,
+            segment: _default_quick_load_segment(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeadlessRun {
+    pub cycles: Option<u64>,
+    pub timeout: Option<u32>,
+    pub trigger_address: Option<u32>,
+    pub trigger_text: Option<String>,
+    #[serde(default)]
+    pub floppy: Vec<PathBuf>,
+    pub screenshot: Option<PathBuf>,
+    pub text_screen: Option<PathBuf>,
+    #[serde(default = "_default_true")]
+    pub null_audio: bool,
+}
+
+impl Default for HeadlessRun {
+    fn default() -> Self {
+        Self {
+            cycles: None,
+            timeout: None,
+            trigger_address: None,
+            trigger_text: None,
+            floppy: Vec::new(),
+            screenshot: None,
+            text_screen: None,
+            null_audio: true,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -120,6 +238,11 @@ pub struct Debugger {
     pub checkpoint_notify_level: Option<u32>,
     #[serde(default)]
     pub breakpoint_notify: bool,
+    pub console_port: Option<u16>,
+    pub console_script: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    pub automation_port: Option<u16>,
+    pub automation_socket: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -128,6 +251,15 @@ pub struct Backend {
     pub vsync: bool,
     #[serde(default)]
     pub macos_stripe_fix: bool,
+    #[serde(default)]
+    pub frame_pacing: FramePacing,
+    pub frame_slices: Option<u32>,
+    #[serde(default)]
+    pub frame_skip: u32,
+    #[serde(default)]
+    pub auto_frame_skip: bool,
+    #[serde(default = "_default_true")]
+    pub render_thread: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -156,9 +288,19 @@ pub struct Emulator {
     pub debug_mode: bool,
     #[serde(default = "_default_true")]
     pub debug_warn: bool,
+    #[serde(default)]
+    pub screenshot_raw: bool,
+    #[serde(default = "_default_true")]
+    pub remember_session: bool,
     pub media: Media,
     pub debugger: Debugger,
     pub audio: Audio,
+    #[serde(default)]
+    pub video_capture: VideoCapture,
+    #[serde(default)]
+    pub rewind: Rewind,
+    #[serde(default)]
+    pub quick_load: QuickLoad,
     pub run_bin: Option<String>,
     pub run_bin_seg: Option<u16>,
     pub run_bin_ofs: Option<u16>,
@@ -176,9 +318,17 @@ pub struct Emulator {
     pub pit_output_int_trigger: bool,
 
     pub window: Vec<WindowDefinition>,
+    #[serde(default = "_default_true")]
+    pub window_per_card: bool,
     pub scaler_preset: Vec<ScalerPreset>,
     pub input: EmulatorInput,
     pub benchmark: Benchmark,
+    #[serde(default)]
+    pub headless_run: HeadlessRun,
+    #[serde(default)]
+    pub convert_floppy: Option<PathBuf>,
+    #[serde(default)]
+    pub convert_output: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -188,6 +338,59 @@ pub struct Gui {
     pub theme: Option<MartyGuiTheme>,
     pub menu_theme: Option<MartyGuiTheme>,
     pub zoom: Option<f32>,
+    #[serde(default)]
+    pub osd: Osd,
+    #[serde(default)]
+    pub status_bar: StatusBar,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Osd {
+    #[serde(default = "_default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub position: OsdPosition,
+    #[serde(default = "_default_osd_duration")]
+    pub duration_ms: u64,
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            position: Default::default(),
+            duration_ms: _default_osd_duration(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusBar {
+    #[serde(default = "_default_true")]
+    pub enabled: bool,
+    #[serde(default = "_default_true")]
+    pub cpu_mhz: bool,
+    #[serde(default = "_default_true")]
+    pub fps: bool,
+    #[serde(default = "_default_true")]
+    pub audio: bool,
+    #[serde(default = "_default_true")]
+    pub disk: bool,
+    #[serde(default = "_default_true")]
+    pub speed: bool,
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cpu_mhz: true,
+            fps: true,
+            audio: true,
+            disk: true,
+            speed: true,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -258,6 +461,8 @@ pub struct Cpu {
 #[derive(Debug, Deserialize)]
 pub struct MachineInput {
     pub keyboard_layout: Option<String>,
+    pub keyboard_translation: Option<KeyTranslationMode>,
+    pub guest_keyboard_layout: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -282,6 +487,8 @@ pub struct Machine {
     pub input: MachineInput,
     pub disassembly_recording: Option<bool>,
     pub disassembly_file: Option<PathBuf>,
+    pub control_flow_file: Option<PathBuf>,
+    pub analysis_file: Option<PathBuf>,
     pub terminal_port: Option<u16>,
 }
 
@@ -294,7 +501,17 @@ pub struct EmulatorInput {
     #[serde(default)]
     pub keyboard_joystick: bool,
     #[serde(default)]
+    pub gamepad: bool,
+    pub gamepad_profile: Option<String>,
+    #[serde(default)]
+    pub gamepad_profiles: Vec<GamepadProfile>,
+    #[serde(default)]
     pub debug_keyboard: bool,
+    pub macro_file: Option<PathBuf>,
+    #[serde(default)]
+    pub macro_at_boot: bool,
+    pub paste_key_delay: Option<u64>,
+    pub paste_line_delay: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -310,6 +527,10 @@ pub struct WindowDefinition {
     #[serde(default)]
     pub always_on_top: bool,
     pub scaler_preset: Option<String>,
+    #[serde(default)]
+    pub fullscreen_mode: FullscreenMode,
+    pub fullscreen_size: Option<VideoDimensions>,
+    pub fullscreen_refresh: Option<f32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -336,9 +557,39 @@ pub struct CmdLineArgs {
     #[bpaf(long, switch)]
     pub noaudio: bool,
 
+    #[bpaf(long, switch)]
+    pub no_session: bool,
+
     // Emulator options
     #[bpaf(long, switch)]
     pub headless: bool,
+    #[bpaf(long)]
+    pub headless_cycles: Option<u64>,
+    #[bpaf(long)]
+    pub headless_timeout: Option<u32>,
+    #[bpaf(long)]
+    pub headless_trigger_text: Option<String>,
+    #[bpaf(long)]
+    pub headless_floppy: Option<PathBuf>,
+    #[bpaf(long)]
+    pub headless_screenshot: Option<PathBuf>,
+    #[bpaf(long)]
+    pub headless_text_screen: Option<PathBuf>,
+
+    #[bpaf(long)]
+    pub automation_port: Option<u16>,
+    #[bpaf(long)]
+    pub automation_socket: Option<PathBuf>,
+
+    #[bpaf(long)]
+    pub quick_load: Option<PathBuf>,
+    #[bpaf(long)]
+    pub quick_load_args: Option<String>,
+
+    #[bpaf(long)]
+    pub convert_floppy: Option<PathBuf>,
+    #[bpaf(long)]
+    pub convert_output: Option<PathBuf>,
 
     #[bpaf(long, switch)]
     pub fuzzer: bool,
@@ -431,11 +682,52 @@ impl ConfigFileParams {
 
         self.emulator.benchmark_mode |= shell_args.benchmark_mode;
         self.emulator.headless |= shell_args.headless;
+        if let Some(cycles) = shell_args.headless_cycles {
+            self.emulator.headless_run.cycles = Some(cycles);
+        }
+        if let Some(timeout) = shell_args.headless_timeout {
+            self.emulator.headless_run.timeout = Some(timeout);
+        }
+        if let Some(trigger_text) = shell_args.headless_trigger_text {
+            self.emulator.headless_run.trigger_text = Some(trigger_text);
+        }
+        if let Some(floppy) = shell_args.headless_floppy {
+            // A floppy given on the command line always goes in the first drive.
+            match self.emulator.headless_run.floppy.first_mut() {
+                Some(first) => *first = floppy,
+                None => self.emulator.headless_run.floppy.push(floppy),
+            }
+        }
+        if let Some(screenshot) = shell_args.headless_screenshot {
+            self.emulator.headless_run.screenshot = Some(screenshot);
+        }
+        if let Some(text_screen) = shell_args.headless_text_screen {
+            self.emulator.headless_run.text_screen = Some(text_screen);
+        }
+        if let Some(port) = shell_args.automation_port {
+            self.emulator.debugger.automation_port = Some(port);
+        }
+        if let Some(socket) = shell_args.automation_socket {
+            self.emulator.debugger.automation_socket = Some(socket);
+        }
+        if let Some(program) = shell_args.quick_load {
+            self.emulator.quick_load.program = Some(program);
+        }
+        if let Some(args) = shell_args.quick_load_args {
+            self.emulator.quick_load.args = Some(args);
+        }
+        if let Some(input) = shell_args.convert_floppy {
+            self.emulator.convert_floppy = Some(input);
+        }
+        if let Some(output) = shell_args.convert_output {
+            self.emulator.convert_output = Some(output);
+        }
         self.emulator.fuzzer |= shell_args.fuzzer;
         self.emulator.auto_poweron |= shell_args.auto_poweron;
         self.emulator.warpspeed |= shell_args.warpspeed;
         self.emulator.title_hacks |= shell_args.title_hacks;
         self.emulator.audio.enabled &= !shell_args.noaudio;
+        self.emulator.remember_session &= !shell_args.no_session;
 
         //self.emulator.scaler_aspect_correction |= shell_args.scaler_aspect_correction;
         self.emulator.debug_mode |= shell_args.debug_mode;
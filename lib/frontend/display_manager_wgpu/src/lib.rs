@@ -38,7 +38,11 @@
    - A file (for screenshots)
 */
 
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 pub use display_backend_pixels::{
     BufferDimensions,
@@ -56,7 +60,8 @@ use anyhow::{anyhow, Context, Error};
 use winit::{
     dpi::PhysicalSize,
     event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
-    window::{Icon, Window, WindowBuilder, WindowButtons, WindowId, WindowLevel},
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Icon, Window, WindowBuilder, WindowButtons, WindowId, WindowLevel},
 };
 
 pub use frontend_common::{
@@ -75,7 +80,7 @@ use marty_common::VideoDimensions;
 use config_toml_bpaf::{ConfigFileParams, WindowDefinition};
 use frontend_common::{
     display_scaler::{PhosphorType, ScalerFilter, ScalerOption, ScalerParams, ScalerPreset},
-    types::display_target_margins::DisplayTargetMargins,
+    types::{display::FullscreenMode, display_target_margins::DisplayTargetMargins},
 };
 
 use marty_core::{
@@ -85,7 +90,7 @@ use marty_core::{
 };
 use marty_egui::context::GuiRenderContext;
 use marty_pixels_scaler::{DisplayScaler, MartyScaler, ScalerMode};
-use videocard_renderer::{AspectCorrectionMode, AspectRatio, VideoRenderer};
+use videocard_renderer::{AspectCorrectionMode, AspectRatio, ScreenshotOptions, VideoRenderer};
 
 const EGUI_MENU_BAR: u32 = 24;
 
@@ -159,6 +164,7 @@ pub struct DisplayTargetContext<T> {
         Option<Box<dyn DisplayScaler<Pixels, NativeTextureView = TextureView, NativeEncoder = CommandEncoder>>>, // The scaler pipeline
     pub(crate) scaler_params: Option<ScalerParams>,
     pub(crate) card_scale: Option<f32>, // If Some, the card resolution is scaled by this factor
+    pub(crate) exclusive_mode: Option<VideoMode>, // Video mode to restore when an exclusive fullscreen window regains focus
 }
 
 pub struct WgpuDisplayManagerBuilder {}
@@ -178,6 +184,8 @@ pub struct WgpuDisplayManager {
     card_id_map: HashMap<VideoCardId, Vec<usize>>, // Card id maps to a Vec<usize> as a single card can have multiple targets.
     primary_idx: Option<usize>,
     scaler_presets: HashMap<String, ScalerPreset>,
+    crt_shaders: HashMap<String, String>, // Post-processing shader sources by name
+    card_crt_shaders: HashMap<VideoCardId, String>, // Default post-processing shader names by card
 }
 
 impl Default for WgpuDisplayManager {
@@ -190,6 +198,8 @@ impl Default for WgpuDisplayManager {
             card_id_map: HashMap::new(),
             primary_idx: None,
             scaler_presets: HashMap::new(),
+            crt_shaders: HashMap::new(),
+            card_crt_shaders: HashMap::new(),
         }
     }
 }
@@ -210,6 +220,29 @@ impl WgpuDisplayManager {
     pub fn take_event_loop(&mut self) -> EventLoop<()> {
         self.event_loop.take().unwrap()
     }
+
+    /// Add a post-processing shader by name. The source should contain a WGSL fs_main() entry
+    /// point.
+    pub fn add_crt_shader(&mut self, name: String, source: String) {
+        if self.crt_shaders.insert(name.clone(), source).is_some() {
+            log::warn!("Post-processing shader {} was overwritten", name);
+        }
+    }
+
+    /// Resolve the post-processing shader source for a display target. A shader named by the
+    /// scaler preset takes precedence over the default shader for the target's video card.
+    fn resolve_crt_shader(&self, preset: &ScalerPreset, card_id: Option<VideoCardId>) -> Option<String> {
+        let name = preset
+            .crt_shader
+            .as_ref()
+            .or_else(|| card_id.and_then(|id| self.card_crt_shaders.get(&id)))?;
+
+        let source = self.crt_shaders.get(name).cloned();
+        if source.is_none() {
+            log::warn!("Post-processing shader {} is not loaded", name);
+        }
+        source
+    }
 }
 
 impl WgpuDisplayManager {
@@ -226,6 +259,33 @@ impl WgpuDisplayManager {
                 |m| m.scale_factor() as f32,
             )
     }
+
+    /// Select the video mode of the monitor that best matches the requested resolution and
+    /// refresh rate. Unspecified values default to the monitor's current desktop mode. Among
+    /// matching modes, the one with the highest bit depth is preferred.
+    fn select_video_mode(
+        monitor: &MonitorHandle,
+        size: Option<DisplayTargetDimensions>,
+        refresh: Option<f32>,
+    ) -> Option<VideoMode> {
+        let desktop_size = monitor.size();
+        let (w, h) = size.map_or((desktop_size.width, desktop_size.height), |size| (size.w, size.h));
+        let refresh_mhz = refresh
+            .map(|hz| (hz * 1000.0) as u32)
+            .or_else(|| monitor.refresh_rate_millihertz());
+
+        let refresh_delta = |mode: &VideoMode| match refresh_mhz {
+            Some(target) => mode.refresh_rate_millihertz().abs_diff(target),
+            None => 0,
+        };
+
+        // Allow some slack on a requested refresh rate so that e.g. 60Hz matches a 59.94Hz mode.
+        monitor
+            .video_modes()
+            .filter(|mode| mode.size() == PhysicalSize::new(w, h))
+            .filter(|mode| refresh.is_none() || refresh_delta(mode) <= 500)
+            .min_by_key(|mode| (refresh_delta(mode), u16::MAX - mode.bit_depth()))
+    }
 }
 pub trait DefaultResolver {
     fn resolve_with_defaults(&self) -> Self;
@@ -255,6 +315,7 @@ impl DefaultResolver for WindowDefinition {
 /// - the user configuration file
 /// - a list of video cards from the emulator core
 /// - a list of scaler preset definitions
+/// - a map of post-processing shader sources, and the default shader name for each card
 /// - a path to an icon (TODO: support different icons per window?)
 /// - a struct of GUI options for the immediate-mode gui a window may contain
 impl WgpuDisplayManagerBuilder {
@@ -262,6 +323,8 @@ impl WgpuDisplayManagerBuilder {
         config: &ConfigFileParams,
         cards: Vec<VideoCardId>,
         scaler_presets: &Vec<ScalerPreset>,
+        crt_shaders: HashMap<String, String>,
+        card_crt_shaders: HashMap<VideoCardId, String>,
         icon_path: Option<PathBuf>,
         icon_buf: Option<&[u8]>,
         gui_options: &DisplayManagerGuiOptions,
@@ -314,6 +377,13 @@ impl WgpuDisplayManagerBuilder {
             dm.add_scaler_preset(preset.clone());
         }
 
+        // Install post-processing shaders
+        for (name, source) in crt_shaders.into_iter() {
+            log::debug!("Installing post-processing shader: {}", &name);
+            dm.add_crt_shader(name, source);
+        }
+        dm.card_crt_shaders = card_crt_shaders;
+
         // Only create windows if the config specifies any!
         if config.emulator.window.len() > 0 {
             // Create the main window.
@@ -334,6 +404,47 @@ impl WgpuDisplayManagerBuilder {
                         .expect("FATAL: Failed to create a window target");
                 }
             }
+
+            // Open a window for each video card that no window displays, so that every adapter of a
+            // multi-head machine can be seen. Each gets its own display target, so scaling and shader
+            // settings are independent.
+            if config.emulator.window_per_card {
+                for card_idx in 0..cards.len() {
+                    let displayed =
+                        config.emulator.window.iter().enumerate().any(|(i, window_def)| {
+                            (i == 0 || window_def.enabled) && window_def.card_id == Some(card_idx)
+                        });
+
+                    if !displayed {
+                        log::debug!("Opening window for undisplayed card {}", card_idx);
+                        let window_def = WindowDefinition {
+                            enabled: true,
+                            name: format!("Display {}", card_idx),
+                            size: Some(VideoDimensions {
+                                w: DEFAULT_RESOLUTION_W,
+                                h: DEFAULT_RESOLUTION_H,
+                            }),
+                            resizable: true,
+                            card_id: Some(card_idx),
+                            card_scale: Some(1.0),
+                            always_on_top: false,
+                            scaler_preset: None,
+                            fullscreen_mode: Default::default(),
+                            fullscreen_size: None,
+                            fullscreen_refresh: None,
+                        };
+                        Self::create_target_from_window_def(
+                            &mut dm,
+                            false,
+                            &window_def,
+                            &cards,
+                            gui_options,
+                            icon.clone(),
+                        )
+                        .expect("FATAL: Failed to create a window target");
+                    }
+                }
+            }
         }
 
         Ok(dm)
@@ -392,6 +503,10 @@ impl WgpuDisplayManagerBuilder {
         // If this is Some, it locks the window resolution to some scale factor of card resolution
         window_opts.card_scale = window_def.card_scale;
 
+        window_opts.fullscreen_mode = window_def.fullscreen_mode;
+        window_opts.fullscreen_size = window_def.fullscreen_size.map(|size| size.into());
+        window_opts.fullscreen_refresh = window_def.fullscreen_refresh;
+
         let preset_name = window_def.scaler_preset.clone().unwrap_or("default".to_string());
 
         // Construct window title.
@@ -431,6 +546,16 @@ impl DisplayTargetContext<PixelsBackend> {
         self.card_id
     }
 
+    /// Resolve a position on this target's window surface to a raster position of the video
+    /// card, or None if the position is outside the display or covered by the GUI.
+    pub fn surface_to_beam(&self, x: f64, y: f64, extents: &DisplayExtents) -> Option<(u32, u32)> {
+        if self.gui_ctx.as_ref().is_some_and(|gui_ctx| gui_ctx.wants_pointer()) {
+            return None;
+        }
+        let (u, v) = self.scaler.as_ref()?.surface_to_texture(x as f32, y as f32)?;
+        self.renderer.as_ref()?.texture_to_beam(extents, u, v)
+    }
+
     pub fn set_scale_factor(&mut self, factor: f64) {
         if let Some(gui_ctx) = &mut self.gui_ctx {
             gui_ctx.scale_factor(factor);
@@ -503,6 +628,19 @@ impl DisplayTargetContext<PixelsBackend> {
         }
     }
 
+    /// Install a post-processing shader from WGSL source, or remove it with None.
+    pub fn set_crt_shader(&mut self, source: Option<String>) {
+        // We must have a backend and scaler to continue...
+        if !self.backend.is_some() || !self.scaler.is_some() {
+            return;
+        }
+        self.scaler.as_mut().unwrap().set_option(
+            self.backend.as_mut().unwrap().get_backend_raw().unwrap(),
+            ScalerOption::PostShader(source),
+            true,
+        );
+    }
+
     pub fn apply_scaler_params(&mut self, params: &ScalerParams) {
         // We must have a backend and scaler to continue...
         if !self.backend.is_some() || !self.scaler.is_some() {
@@ -529,6 +667,7 @@ impl DisplayTargetContext<PixelsBackend> {
         });
 
         scaler_update.push(ScalerOption::Filtering(params.filter));
+        scaler_update.push(ScalerOption::Persistence(params.crt_phosphor_persistence));
 
         if let Some(renderer) = &self.renderer {
             let rparams = renderer.get_params();
@@ -547,7 +686,7 @@ impl DisplayTargetContext<PixelsBackend> {
             scaler_update.push(ScalerOption::Scanlines {
                 enabled: Some(params.crt_scanlines),
                 lines: Some(lines),
-                intensity: Some(0.3),
+                intensity: Some(params.crt_scanline_intensity),
             });
         }
         else {
@@ -814,6 +953,7 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
                 };
 
                 dtc.apply_scaler_preset(&scaler_preset);
+                dtc.set_crt_shader(self.resolve_crt_shader(&scaler_preset, card_id));
 
                 self.targets.push(dtc);
 
@@ -970,6 +1110,15 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
         self.primary_idx.and_then(|idx| self.targets[idx].renderer.as_mut())
     }
 
+    fn set_primary_card(&mut self, id: VideoCardId) {
+        if let Some(dt_idx) = self.card_id_map.get(&id).and_then(|targets| targets.first()) {
+            self.primary_idx = Some(*dt_idx);
+        }
+        else {
+            log::warn!("Primary video card {:?} has no display target.", id);
+        }
+    }
+
     /// Reflect a potential update to a videocard's output resolution. This can be called once
     /// per frame regardless of whether we anticipate the card resolution actually changed.
     /// This method needs to resize the resolution of the backend, renderer and scaler associated
@@ -1271,6 +1420,81 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
         Ok(())
     }
 
+    fn toggle_fullscreen(&mut self, wid: WindowId) -> Result<bool, Error> {
+        let idx = *self.window_id_map.get(&wid).context("Failed to look up window")?;
+        let dt = &mut self.targets[idx];
+        let window = dt.window.as_ref().context("Display target has no window")?;
+
+        dt.exclusive_mode = None;
+        if window.fullscreen().is_some() {
+            log::debug!("toggle_fullscreen(): dt{}: leaving fullscreen", idx);
+            window.set_fullscreen(None);
+            return Ok(false);
+        }
+
+        let (mode, size, refresh) = dt
+            .window_opts
+            .as_ref()
+            .map_or((FullscreenMode::Borderless, None, None), |opts| {
+                (opts.fullscreen_mode, opts.fullscreen_size, opts.fullscreen_refresh)
+            });
+
+        let fullscreen = match mode {
+            FullscreenMode::Borderless => Fullscreen::Borderless(None),
+            FullscreenMode::Exclusive => {
+                let video_mode = window
+                    .current_monitor()
+                    .and_then(|monitor| Self::select_video_mode(&monitor, size, refresh));
+                match video_mode {
+                    Some(video_mode) => {
+                        log::debug!(
+                            "toggle_fullscreen(): dt{}: entering exclusive fullscreen: {}",
+                            idx,
+                            video_mode
+                        );
+                        dt.exclusive_mode = Some(video_mode.clone());
+                        Fullscreen::Exclusive(video_mode)
+                    }
+                    None => {
+                        log::warn!(
+                            "No video mode matches the requested fullscreen mode ({:?} @ {:?}Hz). Using borderless fullscreen.",
+                            size,
+                            refresh
+                        );
+                        if let Some(monitor) = window.current_monitor() {
+                            for video_mode in monitor.video_modes() {
+                                log::debug!("Available video mode: {}", video_mode);
+                            }
+                        }
+                        Fullscreen::Borderless(None)
+                    }
+                }
+            }
+        };
+
+        window.set_fullscreen(Some(fullscreen));
+        Ok(true)
+    }
+
+    fn on_window_focused(&mut self, wid: WindowId, focused: bool) {
+        if let Some(idx) = self.window_id_map.get(&wid) {
+            let dt = &mut self.targets[*idx];
+            if let (Some(window), Some(video_mode)) = (&dt.window, &dt.exclusive_mode) {
+                if focused && window.fullscreen().is_none() {
+                    log::debug!("on_window_focused(): dt{}: restoring exclusive fullscreen", *idx);
+                    window.set_minimized(false);
+                    window.set_fullscreen(Some(Fullscreen::Exclusive(video_mode.clone())));
+                }
+                else if !focused && window.fullscreen().is_some() {
+                    // Hand the display back to the desktop while we're in the background.
+                    log::debug!("on_window_focused(): dt{}: suspending exclusive fullscreen", *idx);
+                    window.set_fullscreen(None);
+                    window.set_minimized(true);
+                }
+            }
+        }
+    }
+
     /// Execute a closure that is passed the VideoCardId for each VideoCard registered in the
     /// DisplayManager.
     fn for_each_card<F>(&mut self, mut f: F)
@@ -1414,7 +1638,9 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
     fn apply_scaler_preset(&mut self, dt_idx: usize, name: String) -> Result<(), Error> {
         if dt_idx < self.targets.len() {
             let preset = self.get_scaler_preset(name).unwrap().clone();
+            let crt_shader = self.resolve_crt_shader(&preset, self.targets[dt_idx].card_id);
             self.targets[dt_idx].apply_scaler_preset(&preset);
+            self.targets[dt_idx].set_crt_shader(crt_shader);
         }
         else {
             return Err(anyhow!("Display target out of range!"));
@@ -1540,15 +1766,23 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
         Ok(())
     }
 
-    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf) -> Result<(), Error> {
+    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf, opts: ScreenshotOptions) -> Result<PathBuf, Error> {
         if dt_idx >= self.targets.len() {
             return Err(anyhow!("Display target out of range!"));
         }
 
         let filename = file_util::find_unique_filename(&path, "screenshot", "png");
+        self.save_screenshot_to(dt_idx, &filename, opts)?;
+        Ok(filename)
+    }
+
+    fn save_screenshot_to(&mut self, dt_idx: usize, filename: &Path, opts: ScreenshotOptions) -> Result<(), Error> {
+        if dt_idx >= self.targets.len() {
+            return Err(anyhow!("Display target out of range!"));
+        }
 
         if let Some(renderer) = &mut self.targets[dt_idx].renderer {
-            renderer.request_screenshot(&filename);
+            renderer.request_screenshot(filename, opts);
         }
         else {
             return Err(anyhow!("No renderer for display target!"));
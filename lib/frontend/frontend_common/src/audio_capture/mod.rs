@@ -0,0 +1,260 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::audio_capture::mod.rs
+
+   Capture sound output to WAV files.
+
+   The mixed output of all sound sources is written to a single stereo file
+   at the output sample rate, with each source's mixer settings applied.
+   Optionally, each source is also written to its own 'stem' file at its
+   native sample rate and channel count, before any mixer settings are
+   applied, for a clean capture of each device.
+
+*/
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Error;
+
+// If a source stops producing samples while others continue, the mix is padded with silence for
+// that source once this many seconds of audio are waiting to be mixed.
+const MIX_STALL_SECONDS: f64 = 0.25;
+const WAV_HEADER_LEN: u32 = 44;
+
+/// A streaming 16-bit PCM WAV file writer. The header is updated with the final length when the
+/// writer is finished or dropped.
+pub struct WavWriter {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    channels: u16,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let block_align = channels * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(WAV_HEADER_LEN - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: Some(writer),
+            channels,
+            data_len: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Write interleaved samples in the range -1.0 to 1.0.
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), Error> {
+        if let Some(writer) = &mut self.writer {
+            for sample in samples {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                writer.write_all(&sample.to_le_bytes())?;
+            }
+            self.data_len = self.data_len.saturating_add(samples.len() as u32 * 2);
+        }
+        Ok(())
+    }
+
+    /// Update the header with the length of the sample data and close the file.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.seek(SeekFrom::Start(4))?;
+            writer.write_all(&(WAV_HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+            writer.seek(SeekFrom::Start(40))?;
+            writer.write_all(&self.data_len.to_le_bytes())?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Failed to finish WAV file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+struct CaptureSource {
+    stem:  Option<WavWriter>,
+    queue: VecDeque<[f32; 2]>,
+}
+
+/// Describes a sound source to be captured.
+pub struct CaptureSourceInfo {
+    pub name: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+pub struct AudioCapture {
+    mix: WavWriter,
+    sample_rate: u32,
+    sources: Vec<CaptureSource>,
+}
+
+impl AudioCapture {
+    /// Begin a capture. The mix is written to `path`. If `stems` is set, each source is also
+    /// written to a file named after the mix file and the source.
+    pub fn new(path: &Path, sample_rate: u32, sources: &[CaptureSourceInfo], stems: bool) -> Result<Self, Error> {
+        let mix = WavWriter::create(path, sample_rate, 2)?;
+
+        let mut capture_sources = Vec::with_capacity(sources.len());
+        for (i, source) in sources.iter().enumerate() {
+            let stem = if stems {
+                let stem_path = AudioCapture::stem_path(path, i, &source.name);
+                Some(WavWriter::create(&stem_path, source.sample_rate, source.channels)?)
+            }
+            else {
+                None
+            };
+            capture_sources.push(CaptureSource {
+                stem,
+                queue: VecDeque::new(),
+            });
+        }
+
+        Ok(Self {
+            mix,
+            sample_rate,
+            sources: capture_sources,
+        })
+    }
+
+    fn stem_path(path: &Path, idx: usize, name: &str) -> PathBuf {
+        let stem_name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                }
+                else {
+                    '_'
+                }
+            })
+            .collect();
+        let base = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_{}_{}.wav", base, idx, stem_name))
+    }
+
+    /// Return the paths of all files being written.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.mix.path().to_path_buf()];
+        for source in self.sources.iter() {
+            if let Some(stem) = &source.stem {
+                paths.push(stem.path().to_path_buf());
+            }
+        }
+        paths
+    }
+
+    /// Capture samples from a source. `raw` holds the source's interleaved samples as produced,
+    /// and `mixed` holds the same samples as stereo at the output sample rate, with the source's
+    /// mixer settings applied.
+    pub fn push_source(&mut self, idx: usize, raw: &[f32], mixed: &[f32]) -> Result<(), Error> {
+        let source = match self.sources.get_mut(idx) {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+        if let Some(stem) = &mut source.stem {
+            stem.write_samples(raw)?;
+        }
+        source
+            .queue
+            .extend(mixed.chunks_exact(2).map(|frame| [frame[0], frame[1]]));
+        Ok(())
+    }
+
+    /// Sum the samples available from all sources and write them to the mix.
+    pub fn mix(&mut self) -> Result<(), Error> {
+        let stall_len = (self.sample_rate as f64 * MIX_STALL_SECONDS) as usize;
+        let longest = self.sources.iter().map(|s| s.queue.len()).max().unwrap_or(0);
+        let frames = if longest > stall_len {
+            longest
+        }
+        else {
+            self.sources.iter().map(|s| s.queue.len()).min().unwrap_or(0)
+        };
+
+        let mut out = vec![0.0; frames * 2];
+        for source in self.sources.iter_mut() {
+            let available = frames.min(source.queue.len());
+            for (i, frame) in source.queue.drain(..available).enumerate() {
+                out[i * 2] += frame[0];
+                out[i * 2 + 1] += frame[1];
+            }
+        }
+        self.mix.write_samples(&out)
+    }
+
+    /// Write any remaining samples and close all files.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>, Error> {
+        let paths = self.paths();
+        let longest = self.sources.iter().map(|s| s.queue.len()).max().unwrap_or(0);
+        let mut out = vec![0.0; longest * 2];
+        for source in self.sources.iter_mut() {
+            for (i, frame) in source.queue.drain(..).enumerate() {
+                out[i * 2] += frame[0];
+                out[i * 2 + 1] += frame[1];
+            }
+        }
+        self.mix.write_samples(&out)?;
+        self.mix.finish()?;
+        for source in self.sources.iter_mut() {
+            if let Some(stem) = &mut source.stem {
+                stem.finish()?;
+            }
+        }
+        Ok(paths)
+    }
+}
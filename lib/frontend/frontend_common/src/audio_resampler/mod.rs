@@ -0,0 +1,161 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::audio_resampler::mod.rs
+
+   A streaming sample rate converter for sound sources.
+
+   Sound devices produce samples at their own native rates, which are
+   converted to the output device's rate before being mixed. Two qualities
+   are offered: linear interpolation, which is cheap but lets some aliasing
+   through, and a Lanczos-windowed sinc interpolator, which is cleaner at
+   the cost of more work per sample.
+
+*/
+
+use crate::types::sound::ResamplerQuality;
+
+// Number of input frames on each side of the interpolation point used by the sinc kernel.
+const SINC_HALF_WIDTH: usize = 8;
+
+/// A streaming resampler for interleaved samples. Input may be supplied in blocks of any size;
+/// enough history is kept between calls for interpolation to continue seamlessly.
+pub struct Resampler {
+    quality: ResamplerQuality,
+    channels: usize,
+    step: f64,
+    cutoff: f64,
+    pos: f64,
+    buffer: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(quality: ResamplerQuality, in_rate: u32, out_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let step = in_rate as f64 / out_rate.max(1) as f64;
+        let history = Resampler::history_len(quality);
+        Self {
+            quality,
+            channels,
+            step,
+            // When downsampling, lower the cutoff to the output Nyquist frequency.
+            cutoff: (1.0 / step).min(1.0),
+            pos: history as f64,
+            buffer: vec![0.0; history * channels],
+        }
+    }
+
+    /// The number of frames before the interpolation point the kernel reads.
+    fn history_len(quality: ResamplerQuality) -> usize {
+        match quality {
+            ResamplerQuality::Linear => 0,
+            ResamplerQuality::Sinc => SINC_HALF_WIDTH - 1,
+        }
+    }
+
+    /// The number of frames after the interpolation point the kernel reads.
+    fn lookahead_len(quality: ResamplerQuality) -> usize {
+        match quality {
+            ResamplerQuality::Linear => 1,
+            ResamplerQuality::Sinc => SINC_HALF_WIDTH,
+        }
+    }
+
+    pub fn quality(&self) -> ResamplerQuality {
+        self.quality
+    }
+
+    /// Resample `input`, appending the converted samples to `output`. Samples are passed through
+    /// unchanged if the input and output rates are equal.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if self.step == 1.0 {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        let ch = self.channels;
+        self.buffer.extend_from_slice(input);
+        let frames = self.buffer.len() / ch;
+        let history = Resampler::history_len(self.quality);
+        let lookahead = Resampler::lookahead_len(self.quality);
+
+        let mut weights = [0.0f64; SINC_HALF_WIDTH * 2];
+        while (self.pos as usize) + lookahead < frames {
+            let i = self.pos as usize;
+            let t = self.pos - i as f64;
+            match self.quality {
+                ResamplerQuality::Linear => {
+                    for c in 0..ch {
+                        let a = self.buffer[i * ch + c];
+                        let b = self.buffer[(i + 1) * ch + c];
+                        output.push(a + (b - a) * t as f32);
+                    }
+                }
+                ResamplerQuality::Sinc => {
+                    let first = i - history;
+                    let mut total = 0.0;
+                    for (k, w) in weights.iter_mut().enumerate() {
+                        *w = self.sinc_weight((first + k) as f64 - self.pos);
+                        total += *w;
+                    }
+                    // Normalize the kernel so that DC passes at unity gain regardless of phase.
+                    let norm = if total.abs() > f64::EPSILON { 1.0 / total } else { 1.0 };
+                    for c in 0..ch {
+                        let mut acc = 0.0;
+                        for (k, w) in weights.iter().enumerate() {
+                            acc += self.buffer[(first + k) * ch + c] as f64 * w;
+                        }
+                        output.push((acc * norm) as f32);
+                    }
+                }
+            }
+            self.pos += self.step;
+        }
+
+        // Discard frames that no longer fall within the kernel.
+        let consumed = (self.pos as usize).saturating_sub(history).min(frames);
+        self.buffer.drain(..consumed * ch);
+        self.pos -= consumed as f64;
+    }
+
+    fn sinc_weight(&self, x: f64) -> f64 {
+        let window = x / SINC_HALF_WIDTH as f64;
+        if window.abs() >= 1.0 {
+            return 0.0;
+        }
+        self.cutoff * sinc(self.cutoff * x) * sinc(window)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    }
+    else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
@@ -0,0 +1,592 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::automation::mod.rs
+
+    A JSON-RPC 2.0 automation API, allowing external tools and test harnesses
+    to drive the emulator over a local socket.
+
+    Requests and responses are JSON objects, one per line. Methods that only
+    need the machine are executed here, so that every front end implements
+    them the same way. Requests for anything else, such as mounting media,
+    are returned to the front end to handle.
+
+    Clients may subscribe to events, which are sent as JSON-RPC notifications
+    with the method "event".
+*/
+
+pub mod server;
+
+use server::AutomationServer;
+
+use marty_core::{
+    cpu_common::{Cpu, Register16},
+    device_traits::videocard::VideoCardStateEntry,
+    machine::{ExecutionControl, ExecutionOperation, ExecutionState, Machine, MachineEvent},
+};
+use serde_json::{json, Map, Value};
+
+use crate::debug_console::{parse_register, ConsoleRegister};
+
+/// The maximum number of bytes that may be read or written in a single request.
+pub const MAX_MEMORY_TRANSFER: usize = 0x10000;
+
+/// The maximum number of instructions that may be stepped in a single request.
+pub const MAX_STEP_COUNT: u64 = 100_000;
+
+/// The names of the events a client may subscribe to.
+pub const EVENT_NAMES: [&str; 4] = ["state", "breakpoint", "halted", "reset"];
+
+const ADDRESS_SPACE: usize = 0x100000;
+
+// Standard JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// A well-formed request that could not be carried out.
+pub const REQUEST_FAILED: i64 = -32000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcError {
+    pub code:    i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self::new(REQUEST_FAILED, message)
+    }
+}
+
+pub type RpcResult = Result<Value, RpcError>;
+
+/// A request received from an automation client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcRequest {
+    /// The id of the client connection the request was received on.
+    pub client: usize,
+    /// The request id. Requests without an id are notifications and receive no response.
+    pub id: Option<Value>,
+    pub method: String,
+    pub params: Value,
+}
+
+impl RpcRequest {
+    /// Parse a single line of JSON into a request. On failure, returns the error to send back
+    /// along with the request id, if one could be read.
+    pub fn parse(client: usize, line: &str) -> Result<Self, (Option<Value>, RpcError)> {
+        let value: Value = serde_json::from_str(line).map_err(|e| (None, RpcError::new(PARSE_ERROR, e.to_string())))?;
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => return Err((None, RpcError::new(INVALID_REQUEST, "Request must be an object"))),
+        };
+        let id = object.remove("id");
+        if object.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err((id, RpcError::new(INVALID_REQUEST, "Expected jsonrpc version 2.0")));
+        }
+        let method = match object.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return Err((id, RpcError::new(INVALID_REQUEST, "Missing method"))),
+        };
+        let params = match object.remove("params") {
+            None | Some(Value::Null) => Value::Object(Map::new()),
+            Some(params @ Value::Object(_)) => params,
+            Some(_) => return Err((id, RpcError::invalid_params("Params must be an object"))),
+        };
+        Ok(Self {
+            client,
+            id,
+            method,
+            params,
+        })
+    }
+
+    pub fn param(&self, name: &str) -> Option<&Value> {
+        self.params.get(name)
+    }
+
+    pub fn u64_param(&self, name: &str) -> Result<u64, RpcError> {
+        self.param(name)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| RpcError::invalid_params(format!("Expected an integer '{}'", name)))
+    }
+
+    pub fn str_param(&self, name: &str) -> Result<&str, RpcError> {
+        self.param(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::invalid_params(format!("Expected a string '{}'", name)))
+    }
+}
+
+/// Build the response to a request.
+pub fn response(id: &Value, result: RpcResult) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": e.code, "message": e.message }
+        }),
+    }
+}
+
+/// An event that may be sent to subscribed clients.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AutomationEvent {
+    /// The execution state changed. Holds the name of the new state.
+    State(&'static str),
+    /// Execution stopped at a breakpoint at the specified CS:IP.
+    Breakpoint(u16, u16),
+    Halted,
+    Reset,
+}
+
+impl AutomationEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AutomationEvent::State(_) => "state",
+            AutomationEvent::Breakpoint(..) => "breakpoint",
+            AutomationEvent::Halted => "halted",
+            AutomationEvent::Reset => "reset",
+        }
+    }
+
+    /// Convert a machine event into an automation event, if clients can subscribe to it.
+    pub fn from_machine_event(event: &MachineEvent) -> Option<Self> {
+        match event {
+            MachineEvent::Halted => Some(AutomationEvent::Halted),
+            MachineEvent::Reset => Some(AutomationEvent::Reset),
+            MachineEvent::CheckpointHit(..) => None,
+        }
+    }
+
+    /// Build the notification sent to clients for this event.
+    pub fn notification(&self) -> Value {
+        let mut params = json!({ "event": self.name() });
+        match self {
+            AutomationEvent::State(state) => params["state"] = json!(state),
+            AutomationEvent::Breakpoint(cs, ip) => {
+                params["cs"] = json!(cs);
+                params["ip"] = json!(ip);
+                params["address"] = json!(flat_address(*cs, *ip));
+            }
+            _ => {}
+        }
+        json!({ "jsonrpc": "2.0", "method": "event", "params": params })
+    }
+}
+
+pub fn state_name(state: ExecutionState) -> &'static str {
+    match state {
+        ExecutionState::Paused => "paused",
+        ExecutionState::BreakpointHit => "breakpoint",
+        ExecutionState::StepOverHit => "stepover",
+        ExecutionState::Running => "running",
+        ExecutionState::Halted => "halted",
+    }
+}
+
+fn flat_address(segment: u16, offset: u16) -> u32 {
+    (((segment as u32) << 4) + offset as u32) & 0xFFFFF
+}
+
+/// Encode bytes as a string of hex digits.
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode a string of hex digits. Whitespace between bytes is ignored.
+pub fn from_hex(s: &str) -> Result<Vec<u8>, RpcError> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.len() & 1 != 0 {
+        return Err(RpcError::invalid_params("Hex data must have an even number of digits"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| RpcError::invalid_params("Invalid hex data"))
+        })
+        .collect()
+}
+
+/// Read an address parameter. Addresses may be given as a flat address, or as a string
+/// address expression such as "f000:e05b" or "cs:ip".
+fn address_param(machine: &Machine, request: &RpcRequest) -> Result<usize, RpcError> {
+    let address = match request.param("address") {
+        Some(Value::Number(n)) => n.as_u64().map(|n| n as usize),
+        Some(Value::String(expr)) => machine.cpu().eval_address(expr).map(|addr| u32::from(addr) as usize),
+        _ => return Err(RpcError::invalid_params("Expected an 'address'")),
+    };
+    match address {
+        Some(address) if address < ADDRESS_SPACE => Ok(address),
+        _ => Err(RpcError::invalid_params("Invalid address")),
+    }
+}
+
+fn registers(machine: &mut Machine) -> Value {
+    let ip = machine.cpu_mut().get_ip();
+    let cpu = machine.cpu();
+    let r = |reg| json!(cpu.get_register16(reg));
+    json!({
+        "ax": r(Register16::AX),
+        "bx": r(Register16::BX),
+        "cx": r(Register16::CX),
+        "dx": r(Register16::DX),
+        "sp": r(Register16::SP),
+        "bp": r(Register16::BP),
+        "si": r(Register16::SI),
+        "di": r(Register16::DI),
+        "cs": r(Register16::CS),
+        "ds": r(Register16::DS),
+        "es": r(Register16::ES),
+        "ss": r(Register16::SS),
+        "ip": ip,
+        "flags": cpu.get_flags(),
+    })
+}
+
+fn set_registers(machine: &mut Machine, request: &RpcRequest) -> RpcResult {
+    let params = request.params.as_object().cloned().unwrap_or_default();
+    // Validate every register before setting any, so a bad request changes nothing.
+    let mut regs = Vec::new();
+    for (name, value) in params.iter() {
+        let reg = parse_register(&name.to_ascii_lowercase()).map_err(RpcError::invalid_params)?;
+        let max = match reg {
+            ConsoleRegister::Reg8(_) => 0xFF,
+            _ => 0xFFFF,
+        };
+        match value.as_u64() {
+            Some(value) if value <= max => regs.push((reg, value as u16)),
+            _ => return Err(RpcError::invalid_params(format!("Invalid value for {}", name))),
+        }
+    }
+
+    let cpu = machine.cpu_mut();
+    for (reg, value) in regs {
+        match reg {
+            ConsoleRegister::Reg16(reg) => cpu.set_register16(reg, value),
+            ConsoleRegister::Reg8(reg) => cpu.set_register8(reg, value as u8),
+            ConsoleRegister::Flags => cpu.set_flags(value),
+        }
+    }
+    Ok(registers(machine))
+}
+
+fn read_memory(machine: &Machine, request: &RpcRequest) -> RpcResult {
+    let address = address_param(machine, request)?;
+    let length = request.u64_param("length")? as usize;
+    if length > MAX_MEMORY_TRANSFER || address + length > ADDRESS_SPACE {
+        return Err(RpcError::invalid_params("Invalid length"));
+    }
+    let data = machine
+        .bus()
+        .peek_range(address, length)
+        .map_err(|_| RpcError::failed(format!("Failed to read memory at {:05X}", address)))?;
+    Ok(json!({ "address": address, "data": to_hex(data) }))
+}
+
+fn write_memory(machine: &mut Machine, request: &RpcRequest) -> RpcResult {
+    let address = address_param(machine, request)?;
+    let data = from_hex(request.str_param("data")?)?;
+    if data.len() > MAX_MEMORY_TRANSFER || address + data.len() > ADDRESS_SPACE {
+        return Err(RpcError::invalid_params("Data too long"));
+    }
+    for (i, byte) in data.iter().enumerate() {
+        machine
+            .bus_mut()
+            .write_u8(address + i, *byte, 0)
+            .map_err(|_| RpcError::failed(format!("Failed to write memory at {:05X}", address + i)))?;
+    }
+    Ok(json!({ "address": address, "length": data.len() }))
+}
+
+fn step(machine: &mut Machine, exec_control: &mut ExecutionControl, request: &RpcRequest) -> RpcResult {
+    let count = match request.param("count") {
+        Some(_) => request.u64_param("count")?,
+        None => 1,
+    };
+    if count > MAX_STEP_COUNT {
+        return Err(RpcError::invalid_params(format!(
+            "Can't step more than {} instructions",
+            MAX_STEP_COUNT
+        )));
+    }
+    if !exec_control.get_state().can_step() {
+        return Err(RpcError::failed("Machine must be paused to step"));
+    }
+    let mut stepped = 0;
+    while stepped < count {
+        exec_control.set_op(ExecutionOperation::Step);
+        machine.run(1, exec_control);
+        stepped += 1;
+        if let ExecutionState::BreakpointHit = exec_control.get_state() {
+            break;
+        }
+    }
+    Ok(json!({ "stepped": stepped, "registers": registers(machine) }))
+}
+
+fn device_state(machine: &mut Machine, request: &RpcRequest) -> RpcResult {
+    let device = request.str_param("device")?;
+    let state = match device {
+        "pic" => {
+            let pic = machine.pic_state();
+            json!({
+                "imr": pic.imr,
+                "isr": pic.isr,
+                "irr": pic.irr,
+                "ir": pic.ir,
+                "intr": pic.intr,
+                "autoeoi": pic.autoeoi,
+                "trigger_mode": pic.trigger_mode,
+                "spurious_irqs": pic.spurious_irqs,
+            })
+        }
+        "pit" => json!(machine
+            .pit_state()
+            .iter()
+            .map(|channel| channel
+                .iter()
+                .map(|(k, v)| (k.to_string(), json!(v.to_string())))
+                .collect::<Map<_, _>>())
+            .collect::<Vec<_>>()),
+        "dma" => {
+            let dma = machine.dma_state();
+            let channels: Vec<Value> = dma
+                .dma_channel_state
+                .iter()
+                .map(|ch| {
+                    json!({
+                        "current_address": ch.current_address_reg,
+                        "current_word_count": ch.current_word_count_reg,
+                        "base_address": ch.base_address_reg,
+                        "base_word_count": ch.base_word_count_reg,
+                        "service_mode": ch.service_mode,
+                        "address_mode": ch.address_mode,
+                        "transfer_type": ch.transfer_type,
+                        "auto_init": ch.auto_init,
+                        "terminal_count": ch.terminal_count,
+                        "terminal_count_reached": ch.terminal_count_reached,
+                        "masked": ch.masked,
+                        "page": ch.page,
+                    })
+                })
+                .collect();
+            json!({
+                "enabled": dma.enabled,
+                "flipflop": dma.flipflop,
+                "dreq": dma.dreq,
+                "channels": channels,
+            })
+        }
+        "serial" => json!(machine
+            .serial_state()
+            .iter()
+            .map(|port| port
+                .iter()
+                .map(|(k, v)| (k.to_string(), json!(v.to_string())))
+                .collect::<Map<_, _>>())
+            .collect::<Vec<_>>()),
+        "video" => {
+            let state = machine
+                .videocard_state()
+                .ok_or_else(|| RpcError::failed("No video card present"))?;
+            let groups: Map<String, Value> = state
+                .into_iter()
+                .map(|(group, entries)| {
+                    let entries: Map<String, Value> = entries
+                        .into_iter()
+                        .map(|(name, entry)| {
+                            let value = match entry {
+                                VideoCardStateEntry::Value8(v) => json!(v),
+                                VideoCardStateEntry::Value16(v) => json!(v),
+                                VideoCardStateEntry::Value32(v) => json!(v),
+                                VideoCardStateEntry::String(s) => json!(s),
+                                VideoCardStateEntry::Color(s, ..) => json!(s),
+                            };
+                            (name, value)
+                        })
+                        .collect();
+                    (group, Value::Object(entries))
+                })
+                .collect();
+            Value::Object(groups)
+        }
+        _ => return Err(RpcError::invalid_params(format!("Unknown device: {}", device))),
+    };
+    Ok(state)
+}
+
+/// Service the automation server. Notifies subscribers of execution state changes, executes
+/// requests that only need the machine, and returns the remaining requests for the front end to
+/// handle. The front end must respond to each of them.
+pub fn service(
+    server: &mut AutomationServer,
+    machine: &mut Machine,
+    exec_control: &mut ExecutionControl,
+) -> Vec<RpcRequest> {
+    let state = exec_control.get_state();
+    if server.update_state(state_name(state)) {
+        server.notify(&AutomationEvent::State(state_name(state)));
+        if let ExecutionState::BreakpointHit = state {
+            let cs = machine.cpu().get_register16(Register16::CS);
+            let ip = machine.cpu_mut().get_ip();
+            server.notify(&AutomationEvent::Breakpoint(cs, ip));
+        }
+    }
+
+    let mut unhandled = Vec::new();
+    for request in server.poll() {
+        match handle_machine_request(machine, exec_control, &request) {
+            Some(result) => server.respond(&request, result),
+            None => unhandled.push(request),
+        }
+    }
+    unhandled
+}
+
+/// Execute a request that only needs the machine. Returns None if the method is not one handled
+/// here, in which case the front end should handle it.
+pub fn handle_machine_request(
+    machine: &mut Machine,
+    exec_control: &mut ExecutionControl,
+    request: &RpcRequest,
+) -> Option<RpcResult> {
+    let result = match request.method.as_str() {
+        "get_state" => Ok(json!({
+            "state": state_name(exec_control.get_state()),
+            "cycles": machine.cpu_cycles(),
+            "instructions": machine.cpu_instructions(),
+            "cpu_mhz": machine.get_cpu_mhz(),
+        })),
+        "pause" => {
+            exec_control.set_op(ExecutionOperation::Pause);
+            Ok(Value::Null)
+        }
+        "resume" => {
+            exec_control.set_op(ExecutionOperation::Run);
+            Ok(Value::Null)
+        }
+        "reset" => {
+            exec_control.set_op(ExecutionOperation::Reset);
+            Ok(Value::Null)
+        }
+        "step" => step(machine, exec_control, request),
+        "get_registers" => Ok(registers(machine)),
+        "set_registers" => set_registers(machine, request),
+        "read_memory" => read_memory(machine, request),
+        "write_memory" => write_memory(machine, request),
+        "get_device_state" => device_state(machine, request),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = RpcRequest::parse(
+            3,
+            r#"{"jsonrpc": "2.0", "id": 7, "method": "read_memory", "params": {"address": "f000:e05b", "length": 16}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.client, 3);
+        assert_eq!(request.id, Some(json!(7)));
+        assert_eq!(request.method, "read_memory");
+        assert_eq!(request.str_param("address").unwrap(), "f000:e05b");
+        assert_eq!(request.u64_param("length").unwrap(), 16);
+        assert_eq!(request.u64_param("address").unwrap_err().code, INVALID_PARAMS);
+
+        // Params are optional, and notifications have no id.
+        let request = RpcRequest::parse(0, r#"{"jsonrpc": "2.0", "method": "pause"}"#).unwrap();
+        assert_eq!(request.id, None);
+        assert_eq!(request.params, json!({}));
+
+        let (id, e) = RpcRequest::parse(0, "{ nope").unwrap_err();
+        assert_eq!((id, e.code), (None, PARSE_ERROR));
+        let (id, e) = RpcRequest::parse(0, r#"{"jsonrpc": "1.0", "id": "a", "method": "pause"}"#).unwrap_err();
+        assert_eq!((id, e.code), (Some(json!("a")), INVALID_REQUEST));
+        let (_, e) =
+            RpcRequest::parse(0, r#"{"jsonrpc": "2.0", "id": 1, "method": "step", "params": [1]}"#).unwrap_err();
+        assert_eq!(e.code, INVALID_PARAMS);
+        let (_, e) = RpcRequest::parse(0, "[]").unwrap_err();
+        assert_eq!(e.code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn test_response() {
+        assert_eq!(
+            response(&json!(1), Ok(json!({ "stepped": 1 }))),
+            json!({ "jsonrpc": "2.0", "id": 1, "result": { "stepped": 1 } })
+        );
+        assert_eq!(
+            response(&json!(2), Err(RpcError::method_not_found("frobnicate"))),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "error": { "code": METHOD_NOT_FOUND, "message": "Method not found: frobnicate" }
+            })
+        );
+        assert_eq!(
+            AutomationEvent::Breakpoint(0xF000, 0xE05B).notification(),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "event",
+                "params": { "event": "breakpoint", "cs": 0xF000, "ip": 0xE05B, "address": 0xFE05B }
+            })
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xAB, 0x7F]), "00ab7f");
+        assert_eq!(from_hex("00 AB 7f").unwrap(), vec![0x00, 0xAB, 0x7F]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
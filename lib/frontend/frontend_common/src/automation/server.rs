@@ -0,0 +1,461 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::automation::server.rs
+
+    The automation server's transport. Clients connect over TCP on the
+    loopback interface, or over a Unix domain socket where supported, and
+    exchange newline-delimited JSON-RPC messages.
+
+    Like the debug console server, the automation server is non-blocking and
+    is intended to be polled once per frame from the front end's main loop.
+*/
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+
+use anyhow::{anyhow, Error};
+use serde_json::{json, Value};
+
+use crate::automation::{response, AutomationEvent, RpcError, RpcRequest, RpcResult, EVENT_NAMES};
+
+/// A client that lets this much output back up without reading it is disconnected.
+const MAX_PENDING_OUTPUT: usize = 16 * 1024 * 1024;
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Stream {
+    fn set_nonblocking(&self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(true),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(true),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+}
+
+struct AutomationClient {
+    id: usize,
+    stream: Stream,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    events: Vec<&'static str>,
+}
+
+impl AutomationClient {
+    /// Queue a message for the client. The stream is non-blocking, so a large message may take
+    /// several calls to flush() to be sent.
+    fn send(&mut self, message: &Value) {
+        self.out.extend_from_slice(message.to_string().as_bytes());
+        self.out.push(b'\n');
+        self.flush();
+    }
+
+    /// Write as much queued output as the stream accepts. Returns false if the client should be
+    /// disconnected.
+    fn flush(&mut self) -> bool {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.out.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::warn!("Failed to write to automation client {}: {}", self.id, e);
+                    return false;
+                }
+            }
+        }
+        if self.out.len() > MAX_PENDING_OUTPUT {
+            log::warn!("Automation client {} is not reading its output, disconnecting", self.id);
+            return false;
+        }
+        true
+    }
+
+    /// Subscribe to or unsubscribe from the events named in the request.
+    fn subscribe(&mut self, request: &RpcRequest, subscribe: bool) -> RpcResult {
+        let names = match request.param("events") {
+            Some(Value::Array(names)) => names.clone(),
+            // With no event list, subscribe to or unsubscribe from everything.
+            None => EVENT_NAMES.iter().map(|name| json!(name)).collect(),
+            _ => return Err(RpcError::invalid_params("Expected an array of 'events'")),
+        };
+        let mut events = Vec::new();
+        for name in names {
+            match EVENT_NAMES.iter().find(|event| Some(**event) == name.as_str()) {
+                Some(event) => events.push(*event),
+                None => return Err(RpcError::invalid_params(format!("Unknown event: {}", name))),
+            }
+        }
+        for event in events {
+            self.events.retain(|e| *e != event);
+            if subscribe {
+                self.events.push(event);
+            }
+        }
+        Ok(json!(self.events))
+    }
+}
+
+pub struct AutomationServer {
+    listeners:  Vec<Listener>,
+    clients:    Vec<AutomationClient>,
+    next_id:    usize,
+    last_state: Option<&'static str>,
+}
+
+impl AutomationServer {
+    /// Listen on the specified TCP port on the loopback interface, on a Unix domain socket at
+    /// the specified path, or both.
+    pub fn bind(port: Option<u16>, socket: Option<&Path>) -> Result<Self, Error> {
+        let mut listeners = Vec::new();
+        if let Some(port) = port {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            listener.set_nonblocking(true)?;
+            listeners.push(Listener::Tcp(listener));
+        }
+        if let Some(path) = socket {
+            listeners.push(Self::bind_socket(path)?);
+        }
+        if listeners.is_empty() {
+            return Err(anyhow!("No port or socket specified."));
+        }
+        Ok(Self {
+            listeners,
+            clients: Vec::new(),
+            next_id: 0,
+            last_state: None,
+        })
+    }
+
+    #[cfg(unix)]
+    fn bind_socket(path: &Path) -> Result<Listener, Error> {
+        // A socket left behind by a previous session would prevent binding. Anything else at the
+        // path is most likely a mistake in the configuration, and is left alone.
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => return Err(anyhow!("{} exists and is not a socket.", path.display())),
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Unix(listener, path.to_path_buf()))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_socket(_path: &Path) -> Result<Listener, Error> {
+        Err(anyhow!("Unix domain sockets are not supported on this platform."))
+    }
+
+    /// Accept new connections and return any requests received. Malformed requests and
+    /// subscription requests are answered here and are not returned.
+    pub fn poll(&mut self) -> Vec<RpcRequest> {
+        for listener in &self.listeners {
+            loop {
+                match listener.accept() {
+                    Ok(stream) => {
+                        if stream.set_nonblocking().is_err() {
+                            continue;
+                        }
+                        log::debug!("Automation client {} connected", self.next_id);
+                        self.clients.push(AutomationClient {
+                            id: self.next_id,
+                            stream,
+                            buf: Vec::new(),
+                            out: Vec::new(),
+                            events: Vec::new(),
+                        });
+                        self.next_id += 1;
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        log::error!("Automation server accept error: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut requests = Vec::new();
+        let mut read_buf = [0u8; 4096];
+        self.clients.retain_mut(|client| {
+            if !client.flush() {
+                return false;
+            }
+            let mut connected = true;
+            loop {
+                match client.stream.read(&mut read_buf) {
+                    Ok(0) => {
+                        log::debug!("Automation client {} disconnected", client.id);
+                        connected = false;
+                        break;
+                    }
+                    Ok(n) => client.buf.extend_from_slice(&read_buf[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => return false,
+                }
+            }
+            while let Some(pos) = client.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.buf.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (id, result) = match RpcRequest::parse(client.id, &line) {
+                    Ok(request) if request.method == "subscribe" => {
+                        (request.id.clone(), client.subscribe(&request, true))
+                    }
+                    Ok(request) if request.method == "unsubscribe" => {
+                        (request.id.clone(), client.subscribe(&request, false))
+                    }
+                    Ok(request) => {
+                        requests.push(request);
+                        continue;
+                    }
+                    Err((id, e)) => (Some(id.unwrap_or(Value::Null)), Err(e)),
+                };
+                if let Some(id) = id {
+                    client.send(&response(&id, result));
+                }
+            }
+            // Requests received before a disconnect are still executed.
+            connected
+        });
+        requests
+    }
+
+    /// Send the result of a request to the client that sent it. Notifications receive no response.
+    pub fn respond(&mut self, request: &RpcRequest, result: RpcResult) {
+        let id = match &request.id {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == request.client) {
+            client.send(&response(id, result));
+        }
+    }
+
+    /// Send an event to the clients subscribed to it.
+    pub fn notify(&mut self, event: &AutomationEvent) {
+        let name = event.name();
+        let notification = event.notification();
+        for client in self.clients.iter_mut().filter(|c| c.events.contains(&name)) {
+            client.send(&notification);
+        }
+    }
+
+    /// Record the current execution state. Returns true if it changed since the last call.
+    pub fn update_state(&mut self, state: &'static str) -> bool {
+        let changed = self.last_state.is_some_and(|last| last != state);
+        self.last_state = Some(state);
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        time::Duration,
+    };
+
+    fn poll_until(server: &mut AutomationServer, n: usize) -> Vec<RpcRequest> {
+        let mut requests = Vec::new();
+        for _ in 0..100 {
+            requests.extend(server.poll());
+            if requests.len() >= n {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        requests
+    }
+
+    #[test]
+    fn test_tcp_session() {
+        let mut server = match AutomationServer::bind(Some(0), None) {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+        let port = match &server.listeners[0] {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().port(),
+            #[cfg(unix)]
+            _ => unreachable!(),
+        };
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut read_message = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        };
+
+        stream
+            .write_all(
+                b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"subscribe\",\"params\":{\"events\":[\"halted\"]}}\n",
+            )
+            .unwrap();
+        stream.write_all(b"garbage\n").unwrap();
+        stream
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"get_state\"}\n")
+            .unwrap();
+
+        let requests = poll_until(&mut server, 1);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "get_state");
+
+        assert_eq!(read_message()["result"], json!(["halted"]));
+        assert_eq!(read_message()["error"]["code"], json!(crate::automation::PARSE_ERROR));
+
+        server.respond(&requests[0], Ok(json!({ "state": "running" })));
+        assert_eq!(
+            read_message(),
+            json!({ "jsonrpc": "2.0", "id": 2, "result": { "state": "running" } })
+        );
+
+        // Only subscribed events are sent.
+        server.notify(&AutomationEvent::Reset);
+        server.notify(&AutomationEvent::Halted);
+        assert_eq!(read_message()["params"]["event"], json!("halted"));
+    }
+
+    #[test]
+    fn test_large_response() {
+        let mut server = match AutomationServer::bind(Some(0), None) {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+        let port = match &server.listeners[0] {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().port(),
+            #[cfg(unix)]
+            _ => unreachable!(),
+        };
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"read_memory\"}\n")
+            .unwrap();
+        let requests = poll_until(&mut server, 1);
+        assert_eq!(requests.len(), 1);
+
+        // Larger than the socket buffers, so the reply can only be sent over several polls.
+        let data = "0f".repeat(256 * 1024);
+        server.respond(&requests[0], Ok(json!({ "data": data })));
+
+        let reader = std::thread::spawn(move || {
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        });
+        while !reader.is_finished() {
+            server.poll();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(reader.join().unwrap()["result"]["data"], json!(data));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_path_not_socket() {
+        let path = std::env::temp_dir().join(format!("martypc_automation_{}.txt", std::process::id()));
+        std::fs::write(&path, b"not a socket").unwrap();
+        assert!(AutomationServer::bind(None, Some(&path)).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_update_state() {
+        let mut server = match AutomationServer::bind(Some(0), None) {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+        assert!(!server.update_state("running"));
+        assert!(!server.update_state("running"));
+        assert!(server.update_state("paused"));
+    }
+}
@@ -112,6 +112,12 @@ impl CartridgeManager {
         }
     }
 
+    /// Return true if the specified file has one of the cartridge image extensions.
+    pub fn is_cart_image(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.extensions.contains(&ext.to_ascii_lowercase()))
+    }
+
     pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
         // Clear and rebuild image lists.
         self.image_vec.clear();
@@ -233,13 +239,15 @@ impl CartridgeManager {
     }
 
     pub fn load_cart_data(&self, idx: usize, rm: &ResourceManager) -> Result<CartImage, Error> {
-        let cart_vec;
-
         if idx >= self.image_vec.len() {
             return Err(anyhow!(CartridgeError::ImageNotFound));
         }
         let floppy_path = self.image_vec[idx].path.clone();
-        cart_vec = match rm.read_resource_from_path(&floppy_path) {
+        self.load_cart_data_by_path(&floppy_path, rm)
+    }
+
+    pub fn load_cart_data_by_path(&self, path: &Path, rm: &ResourceManager) -> Result<CartImage, Error> {
+        let cart_vec = match rm.read_resource_from_path(path) {
             Ok(vec) => vec,
             Err(_e) => {
                 return Err(anyhow!(CartridgeError::FileReadError));
@@ -0,0 +1,100 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::cassette_manager.rs
+
+    Discover cassette tape images in the 'cassette' resource and provide an
+    interface for enumerating them. Tapes are loaded by the core cassette
+    deck directly, as the deck may also write recordings back to disk.
+*/
+
+use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::Error;
+
+#[derive(Clone, Debug)]
+pub struct CassetteImageFile {
+    name: OsString,
+    path: PathBuf,
+}
+
+pub struct CassetteManager {
+    files: Vec<ResourceItem>,
+    image_vec: Vec<CassetteImageFile>,
+    extensions: Vec<OsString>,
+}
+
+impl CassetteManager {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            image_vec: Vec::new(),
+            extensions: vec![OsString::from("wav"), OsString::from("cas")],
+        }
+    }
+
+    pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
+        // Clear and rebuild image lists.
+        self.image_vec.clear();
+
+        // Retrieve all items from the cassette resource paths.
+        let tape_items = rm.enumerate_items("cassette", None, true, true, Some(self.extensions.clone()))?;
+
+        // Index mapping between 'files' vec and 'image_vec' should be maintained.
+        for item in tape_items.iter() {
+            self.image_vec.push(CassetteImageFile {
+                name: item.full_path.file_name().unwrap().to_os_string(),
+                path: item.full_path.clone(),
+            });
+        }
+
+        self.files = tape_items;
+
+        Ok(true)
+    }
+
+    pub fn make_tree(&mut self, rm: &ResourceManager) -> Result<PathTreeNode, Error> {
+        let tree = rm.items_to_tree("cassette", &self.files)?;
+        Ok(tree)
+    }
+
+    pub fn get_image_name(&self, idx: usize) -> Option<OsString> {
+        self.image_vec.get(idx).map(|image| image.name.clone())
+    }
+
+    pub fn get_image_path(&self, idx: usize) -> Option<PathBuf> {
+        self.image_vec.get(idx).map(|image| image.path.clone())
+    }
+
+    /// Return a path in the first cassette resource directory for saving a new recording.
+    pub fn get_save_path(&self, rm: &ResourceManager) -> Option<PathBuf> {
+        let dir = rm.get_resource_path("cassette")?;
+        (0..1000)
+            .map(|i| dir.join(format!("recording{:03}.cas", i)))
+            .find(|path| !path.exists())
+    }
+}
@@ -0,0 +1,104 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::cdrom_manager.rs
+
+    Discover CD-ROM images in the 'cdrom' resource and provide an interface
+    for enumerating them.
+
+    Like Vhd images, CD images are opened by the core directly, as they are
+    typically too large to read into memory and .CUE sheets refer to other
+    files by relative path. The CD-ROM manager only enumerates file paths.
+*/
+
+use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::Error;
+
+#[derive(Clone, Debug)]
+pub struct CdImageFile {
+    name: OsString,
+    path: PathBuf,
+}
+
+pub struct CdRomManager {
+    files: Vec<ResourceItem>,
+    image_vec: Vec<CdImageFile>,
+    extensions: Vec<OsString>,
+}
+
+impl CdRomManager {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            image_vec: Vec::new(),
+            extensions: vec![OsString::from("iso"), OsString::from("cue")],
+        }
+    }
+
+    pub fn set_extensions(&mut self, extensions: Option<Vec<String>>) {
+        if let Some(extensions) = extensions {
+            self.extensions = extensions
+                .iter()
+                .map(|ext| OsString::from(ext.to_lowercase()))
+                .collect();
+        }
+    }
+
+    pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
+        // Clear and rebuild image lists.
+        self.image_vec.clear();
+
+        // Retrieve all items from the cdrom resource paths.
+        let cd_items = rm.enumerate_items("cdrom", None, true, true, Some(self.extensions.clone()))?;
+
+        // Index mapping between 'files' vec and 'image_vec' should be maintained.
+        for item in cd_items.iter() {
+            self.image_vec.push(CdImageFile {
+                name: item.full_path.file_name().unwrap().to_os_string(),
+                path: item.full_path.clone(),
+            });
+        }
+
+        self.files = cd_items;
+
+        Ok(true)
+    }
+
+    pub fn make_tree(&mut self, rm: &ResourceManager) -> Result<PathTreeNode, Error> {
+        let tree = rm.items_to_tree("cdrom", &self.files)?;
+        Ok(tree)
+    }
+
+    pub fn get_image_name(&self, idx: usize) -> Option<OsString> {
+        self.image_vec.get(idx).map(|image| image.name.clone())
+    }
+
+    pub fn get_image_path(&self, idx: usize) -> Option<PathBuf> {
+        self.image_vec.get(idx).map(|image| image.path.clone())
+    }
+}
@@ -0,0 +1,511 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::debug_console::mod.rs
+
+    Command parsing and state for the debugger command console.
+
+    The console accepts text commands from the GUI, from command script files,
+    and from the automation socket. Commands are parsed here into a
+    ConsoleCommand; it is up to the front end to execute them against the
+    machine, since only the front end owns the execution control state.
+*/
+
+pub mod server;
+
+use std::{collections::VecDeque, path::Path};
+
+use crate::text_export::{TextExportFormat, TextSelection};
+use anyhow::{anyhow, Error};
+use marty_core::cpu_common::{Register16, Register8};
+
+pub const HISTORY_MAX: usize = 100;
+/// The deepest that scripts can run other scripts, which stops a script that runs itself.
+pub const SCRIPT_DEPTH_MAX: usize = 16;
+
+pub const CONSOLE_HELP: &str = "\
+Commands:
+  help                      Show this help
+  help rhai                 List the functions available to Rhai scripts
+  bp <addr>                 Set an execution breakpoint at <addr> (ie, f000:e05b)
+  bpm <addr>                Set a memory access breakpoint at <addr>
+  bpi <int>                 Set a breakpoint on interrupt number <int> (hex)
+  bpio <port>               Set a breakpoint on I/O port <port> (hex)
+  bl                        List console breakpoints
+  bc [n]                    Clear breakpoint n, or all breakpoints
+  dump <addr> <len> [file]  Display <len> bytes at <addr>, or write them to [file]
+  textexport <fmt> [all]    Export the text screen as txt, ansi or html. 'all'
+                            includes text in video memory that is not displayed
+  copy [col row cols rows]  Copy the text screen, or the specified region of it,
+                            to the host clipboard
+  screenshot [n] [raw]      Save a screenshot of display n (default 0). 'raw'
+                            also saves the video card's raw framebuffer
+  r                         Display registers
+  r <reg>=<val> ...         Set one or more registers (hex values). CS and IP
+                            can't be set
+  step [n]                  Step n instructions (default 1)
+  g                         Run
+  pause                     Pause execution
+  reset                     Reset the machine
+  exec <file> [args]        Load a DOS .COM or .EXE file into memory and run it
+  script <file>             Execute commands from a file
+  rhai <file>               Run a Rhai script
+  rhai clear                Unload all Rhai scripts and their callbacks
+  eval <code>               Evaluate a line of Rhai script
+  netcap <file>             Capture network traffic to a pcap file
+  netcap stop               Stop capturing network traffic
+  netreplay <file>          Replay the frames in a pcap file into the guest";
+
+/// A breakpoint set from the console. These are kept separately from breakpoints defined
+/// in the breakpoint window, and both sets are merged when breakpoints are applied.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsoleBreakpoint {
+    Execute(String),
+    MemAccess(String),
+    Interrupt(u8),
+    IoAccess(u16),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConsoleRegister {
+    Reg16(Register16),
+    Reg8(Register8),
+    Flags,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsoleCommand {
+    Help,
+    ScriptHelp,
+    Breakpoint(ConsoleBreakpoint),
+    ListBreakpoints,
+    ClearBreakpoints(Option<usize>),
+    Dump(String, usize, Option<String>),
+    ShowRegisters,
+    SetRegisters(Vec<(ConsoleRegister, u16)>),
+    Step(u32),
+    Run,
+    Pause,
+    Reset,
+    /// Quick-load a DOS program with the specified command tail.
+    Exec(String, String),
+    Script(String),
+    /// Run a Rhai script file, or unload all scripts if None.
+    RunScript(Option<String>),
+    /// Evaluate a line of Rhai script.
+    Eval(String),
+    /// Start capturing network traffic to the specified file, or stop capturing if None.
+    NetCapture(Option<String>),
+    NetReplay(String),
+    /// Export the text mode screen in the specified format, optionally including off-screen text.
+    TextExport(TextExportFormat, bool),
+    /// Copy the text mode screen, or a region of it, to the host clipboard.
+    CopyText(Option<TextSelection>),
+    /// Save a screenshot of the specified display target, optionally with the raw framebuffer.
+    Screenshot(usize, bool),
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    let s = s.trim_start_matches("0x").trim_end_matches(['h', 'H']);
+    u32::from_str_radix(s, 16).map_err(|_| format!("Invalid hex value: {}", s))
+}
+
+fn parse_display(s: &str) -> Result<usize, String> {
+    s.parse::<usize>().map_err(|_| format!("Invalid display number: {}", s))
+}
+
+fn parse_cells(s: &str) -> Result<usize, String> {
+    s.parse::<usize>().map_err(|_| format!("Invalid cell count: {}", s))
+}
+
+/// Parse the name of a register that can be set. CS and IP are refused: changing them under the
+/// CPU would leave its prefetch queue holding code from the old address.
+pub(crate) fn parse_register(s: &str) -> Result<ConsoleRegister, String> {
+    let reg = match s.to_ascii_lowercase().as_str() {
+        "ax" => ConsoleRegister::Reg16(Register16::AX),
+        "bx" => ConsoleRegister::Reg16(Register16::BX),
+        "cx" => ConsoleRegister::Reg16(Register16::CX),
+        "dx" => ConsoleRegister::Reg16(Register16::DX),
+        "sp" => ConsoleRegister::Reg16(Register16::SP),
+        "bp" => ConsoleRegister::Reg16(Register16::BP),
+        "si" => ConsoleRegister::Reg16(Register16::SI),
+        "di" => ConsoleRegister::Reg16(Register16::DI),
+        "ds" => ConsoleRegister::Reg16(Register16::DS),
+        "es" => ConsoleRegister::Reg16(Register16::ES),
+        "ss" => ConsoleRegister::Reg16(Register16::SS),
+        "al" => ConsoleRegister::Reg8(Register8::AL),
+        "bl" => ConsoleRegister::Reg8(Register8::BL),
+        "cl" => ConsoleRegister::Reg8(Register8::CL),
+        "dl" => ConsoleRegister::Reg8(Register8::DL),
+        "ah" => ConsoleRegister::Reg8(Register8::AH),
+        "bh" => ConsoleRegister::Reg8(Register8::BH),
+        "ch" => ConsoleRegister::Reg8(Register8::CH),
+        "dh" => ConsoleRegister::Reg8(Register8::DH),
+        "flags" | "fl" => ConsoleRegister::Flags,
+        "cs" | "ip" | "pc" => return Err(format!("Can't set {} without flushing the prefetch queue", s)),
+        _ => return Err(format!("Unknown register: {}", s)),
+    };
+    Ok(reg)
+}
+
+impl ConsoleCommand {
+    /// Parse a single command line. Returns Ok(None) for blank lines and comments.
+    pub fn parse(line: &str) -> Result<Option<ConsoleCommand>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            return Ok(None);
+        }
+
+        let mut args = line.split_whitespace();
+        let cmd = args.next().unwrap_or_default().to_ascii_lowercase();
+
+        // Script code is passed through as-is, whitespace and all.
+        if cmd == "eval" {
+            let code = line[cmd.len()..].trim();
+            if code.is_empty() {
+                return Err("Expected script code after 'eval'".to_string());
+            }
+            return Ok(Some(ConsoleCommand::Eval(code.to_string())));
+        }
+
+        let args: Vec<&str> = args.collect();
+
+        let command = match (cmd.as_str(), args.as_slice()) {
+            ("help" | "?", ["rhai"]) => ConsoleCommand::ScriptHelp,
+            ("help" | "?", _) => ConsoleCommand::Help,
+            ("bp", [addr]) => ConsoleCommand::Breakpoint(ConsoleBreakpoint::Execute(addr.to_string())),
+            ("bpm", [addr]) => ConsoleCommand::Breakpoint(ConsoleBreakpoint::MemAccess(addr.to_string())),
+            ("bpi", [vector]) => {
+                let vector = parse_hex(vector)?;
+                if vector > 0xFF {
+                    return Err(format!("Invalid interrupt number: {:X}", vector));
+                }
+                ConsoleCommand::Breakpoint(ConsoleBreakpoint::Interrupt(vector as u8))
+            }
+            ("bpio", [port]) => {
+                let port = parse_hex(port)?;
+                if port > 0xFFFF {
+                    return Err(format!("Invalid port: {:X}", port));
+                }
+                ConsoleCommand::Breakpoint(ConsoleBreakpoint::IoAccess(port as u16))
+            }
+            ("bl", []) => ConsoleCommand::ListBreakpoints,
+            ("bc", []) => ConsoleCommand::ClearBreakpoints(None),
+            ("bc", [n]) => ConsoleCommand::ClearBreakpoints(Some(
+                n.parse::<usize>().map_err(|_| format!("Invalid breakpoint number: {}", n))?,
+            )),
+            ("dump" | "d", [addr, len]) => ConsoleCommand::Dump(addr.to_string(), parse_hex(len)? as usize, None),
+            ("dump" | "d", [addr, len, file]) => {
+                ConsoleCommand::Dump(addr.to_string(), parse_hex(len)? as usize, Some(file.to_string()))
+            }
+            ("r", []) => ConsoleCommand::ShowRegisters,
+            ("r", assignments) => {
+                let mut regs = Vec::new();
+                for assignment in assignments {
+                    let (reg_str, val_str) = assignment
+                        .split_once('=')
+                        .ok_or(format!("Expected <reg>=<value>, got: {}", assignment))?;
+                    let reg = parse_register(reg_str)?;
+                    let val = parse_hex(val_str)?;
+                    let max = match reg {
+                        ConsoleRegister::Reg8(_) => 0xFF,
+                        _ => 0xFFFF,
+                    };
+                    if val > max {
+                        return Err(format!("Value out of range for {}: {:X}", reg_str, val));
+                    }
+                    regs.push((reg, val as u16));
+                }
+                ConsoleCommand::SetRegisters(regs)
+            }
+            ("step" | "t", []) => ConsoleCommand::Step(1),
+            ("step" | "t", [n]) => {
+                ConsoleCommand::Step(n.parse::<u32>().map_err(|_| format!("Invalid step count: {}", n))?)
+            }
+            ("g" | "run", []) => ConsoleCommand::Run,
+            ("pause", []) => ConsoleCommand::Pause,
+            ("reset", []) => ConsoleCommand::Reset,
+            ("exec", [file, tail @ ..]) => ConsoleCommand::Exec(file.to_string(), tail.join(" ")),
+            ("script", [file]) => ConsoleCommand::Script(file.to_string()),
+            ("rhai", ["clear"]) => ConsoleCommand::RunScript(None),
+            ("rhai", [file]) => ConsoleCommand::RunScript(Some(file.to_string())),
+            ("netcap", ["stop"]) => ConsoleCommand::NetCapture(None),
+            ("netcap", [file]) => ConsoleCommand::NetCapture(Some(file.to_string())),
+            ("netreplay", [file]) => ConsoleCommand::NetReplay(file.to_string()),
+            ("textexport", [format]) => ConsoleCommand::TextExport(format.parse()?, false),
+            ("textexport", [format, "all"]) => ConsoleCommand::TextExport(format.parse()?, true),
+            ("copy", []) => ConsoleCommand::CopyText(None),
+            ("copy", [column, row, columns, rows]) => ConsoleCommand::CopyText(Some(TextSelection {
+                column: parse_cells(column)?,
+                row: parse_cells(row)?,
+                columns: parse_cells(columns)?,
+                rows: parse_cells(rows)?,
+            })),
+            ("screenshot", []) => ConsoleCommand::Screenshot(0, false),
+            ("screenshot", ["raw"]) => ConsoleCommand::Screenshot(0, true),
+            ("screenshot", [n]) => ConsoleCommand::Screenshot(parse_display(n)?, false),
+            ("screenshot", [n, "raw"]) => ConsoleCommand::Screenshot(parse_display(n)?, true),
+            _ => return Err(format!("Invalid command or arguments: '{}'. Type 'help' for help.", line)),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+/// Holds the console's command history, output scrollback and breakpoint list.
+#[derive(Default)]
+pub struct DebugConsole {
+    history: VecDeque<String>,
+    breakpoints: Vec<ConsoleBreakpoint>,
+    // Queued script lines, with the nesting depth of the script each came from.
+    pending: VecDeque<(String, usize)>,
+    // The nesting depth of the script line being executed, or 0 outside of a script.
+    script_depth: usize,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a command line to the history, ignoring consecutive duplicates.
+    pub fn push_history(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.history.back().is_some_and(|last| last == line) {
+            return;
+        }
+        if self.history.len() >= HISTORY_MAX {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.to_string());
+    }
+
+    pub fn history(&self) -> &VecDeque<String> {
+        &self.history
+    }
+
+    pub fn breakpoints(&self) -> &[ConsoleBreakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, bp: ConsoleBreakpoint) -> bool {
+        if self.breakpoints.contains(&bp) {
+            return false;
+        }
+        self.breakpoints.push(bp);
+        true
+    }
+
+    pub fn clear_breakpoints(&mut self, idx: Option<usize>) -> bool {
+        match idx {
+            Some(idx) if idx < self.breakpoints.len() => {
+                self.breakpoints.remove(idx);
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.breakpoints.clear();
+                true
+            }
+        }
+    }
+
+    /// Queue the lines of a command script for execution. A script run from another script is
+    /// queued ahead of the rest of that script's lines, so it executes in place.
+    pub fn queue_script(&mut self, path: &Path) -> Result<usize, Error> {
+        let depth = self.script_depth + 1;
+        if depth > SCRIPT_DEPTH_MAX {
+            return Err(anyhow!(
+                "scripts nested more than {} deep. Does a script run itself?",
+                SCRIPT_DEPTH_MAX
+            ));
+        }
+        let script = std::fs::read_to_string(path)?;
+        let lines: Vec<_> = script.lines().map(|line| (line.to_string(), depth)).collect();
+        let ct = lines.len();
+        match self.script_depth {
+            0 => self.pending.extend(lines),
+            _ => {
+                for line in lines.into_iter().rev() {
+                    self.pending.push_front(line);
+                }
+            }
+        }
+        Ok(ct)
+    }
+
+    /// Return the next queued script line, if any. Scripts it runs are nested inside its script.
+    pub fn next_pending(&mut self) -> Option<String> {
+        match self.pending.pop_front() {
+            Some((line, depth)) => {
+                self.script_depth = depth;
+                Some(line)
+            }
+            None => {
+                self.script_depth = 0;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(ConsoleCommand::parse("  ").unwrap(), None);
+        assert_eq!(ConsoleCommand::parse("# comment").unwrap(), None);
+        assert_eq!(
+            ConsoleCommand::parse("bp f000:e05b").unwrap(),
+            Some(ConsoleCommand::Breakpoint(ConsoleBreakpoint::Execute("f000:e05b".into())))
+        );
+        assert_eq!(
+            ConsoleCommand::parse("bpi 21").unwrap(),
+            Some(ConsoleCommand::Breakpoint(ConsoleBreakpoint::Interrupt(0x21)))
+        );
+        assert_eq!(
+            ConsoleCommand::parse("dump 0000:0400 100 bda.bin").unwrap(),
+            Some(ConsoleCommand::Dump("0000:0400".into(), 0x100, Some("bda.bin".into())))
+        );
+        assert_eq!(
+            ConsoleCommand::parse("r ax=5 bl=ff").unwrap(),
+            Some(ConsoleCommand::SetRegisters(vec![
+                (ConsoleRegister::Reg16(Register16::AX), 5),
+                (ConsoleRegister::Reg8(Register8::BL), 0xFF)
+            ]))
+        );
+        assert_eq!(
+            ConsoleCommand::parse("r cs=f000"),
+            Err("Can't set cs without flushing the prefetch queue".to_string())
+        );
+        assert_eq!(ConsoleCommand::parse("r zz=1"), Err("Unknown register: zz".to_string()));
+        assert_eq!(ConsoleCommand::parse("step 100").unwrap(), Some(ConsoleCommand::Step(100)));
+        assert_eq!(
+            ConsoleCommand::parse("netcap dhcp.pcap").unwrap(),
+            Some(ConsoleCommand::NetCapture(Some("dhcp.pcap".into())))
+        );
+        assert_eq!(ConsoleCommand::parse("netcap stop").unwrap(), Some(ConsoleCommand::NetCapture(None)));
+        assert_eq!(
+            ConsoleCommand::parse("textexport ansi all").unwrap(),
+            Some(ConsoleCommand::TextExport(TextExportFormat::Ansi, true))
+        );
+        assert!(ConsoleCommand::parse("textexport pdf").is_err());
+        assert_eq!(ConsoleCommand::parse("screenshot").unwrap(), Some(ConsoleCommand::Screenshot(0, false)));
+        assert_eq!(
+            ConsoleCommand::parse("screenshot 1 raw").unwrap(),
+            Some(ConsoleCommand::Screenshot(1, true))
+        );
+        assert!(ConsoleCommand::parse("screenshot two").is_err());
+        assert_eq!(ConsoleCommand::parse("copy").unwrap(), Some(ConsoleCommand::CopyText(None)));
+        assert_eq!(
+            ConsoleCommand::parse("copy 0 24 80 1").unwrap(),
+            Some(ConsoleCommand::CopyText(Some(TextSelection {
+                column: 0,
+                row: 24,
+                columns: 80,
+                rows: 1,
+            })))
+        );
+        assert!(ConsoleCommand::parse("copy 0 24").is_err());
+        assert_eq!(
+            ConsoleCommand::parse("exec test.com /a  /b").unwrap(),
+            Some(ConsoleCommand::Exec("test.com".into(), "/a /b".into()))
+        );
+        assert!(ConsoleCommand::parse("exec").is_err());
+        assert_eq!(
+            ConsoleCommand::parse("rhai boot.rhai").unwrap(),
+            Some(ConsoleCommand::RunScript(Some("boot.rhai".into())))
+        );
+        assert_eq!(ConsoleCommand::parse("help rhai").unwrap(), Some(ConsoleCommand::ScriptHelp));
+        assert_eq!(ConsoleCommand::parse("rhai clear").unwrap(), Some(ConsoleCommand::RunScript(None)));
+        assert_eq!(
+            ConsoleCommand::parse("EVAL  poke(0x400, 1);  peek(0x400)").unwrap(),
+            Some(ConsoleCommand::Eval("poke(0x400, 1);  peek(0x400)".into()))
+        );
+        assert!(ConsoleCommand::parse("eval").is_err());
+        assert!(ConsoleCommand::parse("r al=100").is_err());
+        assert!(ConsoleCommand::parse("r ip=100").is_err());
+        assert!(ConsoleCommand::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut console = DebugConsole::new();
+        console.push_history("step");
+        console.push_history("step");
+        console.push_history("r");
+        assert_eq!(console.history().len(), 2);
+        for i in 0..HISTORY_MAX * 2 {
+            console.push_history(&format!("step {}", i));
+        }
+        assert_eq!(console.history().len(), HISTORY_MAX);
+    }
+
+    /// Run queued script lines as the front end does, queueing the scripts they run.
+    fn run_pending(console: &mut DebugConsole) -> (Vec<String>, Vec<String>) {
+        let (mut lines, mut errors) = (Vec::new(), Vec::new());
+        while let Some(line) = console.next_pending() {
+            if let Ok(Some(ConsoleCommand::Script(file))) = ConsoleCommand::parse(&line) {
+                if let Err(e) = console.queue_script(Path::new(&file)) {
+                    errors.push(e.to_string());
+                }
+            }
+            lines.push(line);
+        }
+        (lines, errors)
+    }
+
+    #[test]
+    fn test_nested_scripts() {
+        let dir = std::env::temp_dir().join(format!("martypc_console_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let outer = dir.join("outer.txt");
+        let inner = dir.join("inner.txt");
+        std::fs::write(&outer, format!("step\nscript {}\nr", inner.display())).unwrap();
+        std::fs::write(&inner, "dump 0 10\nbl").unwrap();
+
+        // A script run from a script executes in place.
+        let mut console = DebugConsole::new();
+        console.queue_script(&outer).unwrap();
+        let (lines, errors) = run_pending(&mut console);
+        assert_eq!(lines[0], "step");
+        assert_eq!(lines[2..], ["dump 0 10", "bl", "r"]);
+        assert!(errors.is_empty());
+
+        // A script that runs itself stops at the nesting limit.
+        std::fs::write(&inner, format!("script {}", inner.display())).unwrap();
+        console.queue_script(&inner).unwrap();
+        let (lines, errors) = run_pending(&mut console);
+        assert_eq!(lines.len(), SCRIPT_DEPTH_MAX);
+        assert_eq!(errors.len(), 1);
+
+        // Once the queue is empty, scripts can be queued again.
+        assert!(console.queue_script(&outer).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::debug_console::server.rs
+
+    A minimal line-based TCP server for the debugger console, allowing
+    external tools to drive the debugger. Each line received is a console
+    command; the command's output is written back to the client that sent it,
+    followed by a single '.' line to mark the end of the response.
+
+    The server is non-blocking and is intended to be polled once per frame
+    from the front end's main loop.
+*/
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use anyhow::Error;
+
+/// A client that lets this much output back up without reading it is disconnected.
+const MAX_PENDING_OUTPUT: usize = 16 * 1024 * 1024;
+
+struct ConsoleClient {
+    id: usize,
+    stream: TcpStream,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl ConsoleClient {
+    /// Write as much queued output as the stream accepts. Returns false if the client should be
+    /// disconnected.
+    fn flush(&mut self) -> bool {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.out.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::warn!("Failed to write to debug console client {}: {}", self.id, e);
+                    return false;
+                }
+            }
+        }
+        if self.out.len() > MAX_PENDING_OUTPUT {
+            log::warn!(
+                "Debug console client {} is not reading its output, disconnecting",
+                self.id
+            );
+            return false;
+        }
+        true
+    }
+}
+
+pub struct ConsoleServer {
+    listener: TcpListener,
+    clients: Vec<ConsoleClient>,
+    next_id: usize,
+}
+
+impl ConsoleServer {
+    /// Bind the console server to the specified port on the loopback interface.
+    pub fn bind(port: u16) -> Result<Self, Error> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            clients: Vec::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Accept new connections and return any complete command lines received, tagged with
+    /// the id of the client that sent them.
+    pub fn poll(&mut self) -> Vec<(usize, String)> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    log::debug!("Debug console client connected from {}", addr);
+                    self.clients.push(ConsoleClient {
+                        id: self.next_id,
+                        stream,
+                        buf: Vec::new(),
+                        out: Vec::new(),
+                    });
+                    self.next_id += 1;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("Debug console accept error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut read_buf = [0u8; 1024];
+        self.clients.retain_mut(|client| {
+            if !client.flush() {
+                return false;
+            }
+            loop {
+                match client.stream.read(&mut read_buf) {
+                    Ok(0) => {
+                        log::debug!("Debug console client {} disconnected", client.id);
+                        return false;
+                    }
+                    Ok(n) => client.buf.extend_from_slice(&read_buf[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => return false,
+                }
+            }
+            while let Some(pos) = client.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.buf.drain(..=pos).collect();
+                lines.push((client.id, String::from_utf8_lossy(&line).trim().to_string()));
+            }
+            true
+        });
+        lines
+    }
+
+    /// Send a command's output to the specified client. Output the client's stream can't accept
+    /// immediately is sent by subsequent calls to poll().
+    pub fn respond(&mut self, id: usize, output: &[String]) {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+            for line in output {
+                client.out.extend_from_slice(line.as_bytes());
+                client.out.push(b'\n');
+            }
+            client.out.extend_from_slice(b".\n");
+            client.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_large_response() {
+        let mut server = match ConsoleServer::bind(0) {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+        let port = server.listener.local_addr().unwrap().port();
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"dump 0000:0000\n").unwrap();
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines.extend(server.poll());
+            if !lines.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].1, "dump 0000:0000");
+
+        // Larger than the socket buffers, so the response can only be sent over several polls.
+        let output: Vec<String> = (0..20000)
+            .map(|i| format!("{:05X}: {}", i * 16, "00 ".repeat(16)))
+            .collect();
+        server.respond(lines[0].0, &output);
+
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                if line == "." {
+                    break;
+                }
+                received.push(line);
+            }
+            received
+        });
+        while !reader.is_finished() {
+            server.poll();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(reader.join().unwrap(), output);
+    }
+}
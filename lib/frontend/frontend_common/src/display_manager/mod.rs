@@ -35,7 +35,7 @@ use anyhow::Error;
 use marty_core::machine::Machine;
 use std::{
     fmt::{Display, Formatter},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use web_time::Duration;
 
@@ -43,11 +43,11 @@ pub use crate::types::display_target_dimensions::DisplayTargetDimensions;
 
 use crate::{
     display_scaler::{ScalerMode, ScalerParams, ScalerPreset},
-    types::display_target_margins::DisplayTargetMargins,
+    types::{display::FullscreenMode, display_target_margins::DisplayTargetMargins},
     MartyGuiTheme,
 };
 use marty_core::device_traits::videocard::{DisplayApertureType, DisplayExtents, VideoCardId, VideoType};
-use videocard_renderer::{RendererConfigParams, VideoRenderer};
+use videocard_renderer::{RendererConfigParams, ScreenshotOptions, VideoRenderer};
 
 #[derive(Copy, Clone)]
 pub enum DisplayTargetType {
@@ -113,6 +113,11 @@ pub struct DisplayManagerWindowOptions {
     pub always_on_top: bool,
     pub is_on_top: bool,
     pub card_scale: Option<f32>,
+    pub fullscreen_mode: FullscreenMode,
+    /// Video mode resolution to request in exclusive fullscreen. None keeps the desktop resolution.
+    pub fullscreen_size: Option<DisplayTargetDimensions>,
+    /// Refresh rate in Hz to request in exclusive fullscreen. None keeps the desktop refresh rate.
+    pub fullscreen_refresh: Option<f32>,
 }
 
 impl Default for DisplayManagerWindowOptions {
@@ -127,6 +132,9 @@ impl Default for DisplayManagerWindowOptions {
             always_on_top: false,
             is_on_top: false,
             card_scale: None,
+            fullscreen_mode: Default::default(),
+            fullscreen_size: None,
+            fullscreen_refresh: None,
         }
     }
 }
@@ -201,6 +209,10 @@ pub trait DisplayManager<B, G, Wi, W> {
     /// is present, returns None.
     fn get_primary_renderer(&mut self) -> Option<&mut VideoRenderer>;
 
+    /// Designate the specified video card as the primary card. The first display target
+    /// associated with the card will be used as the primary display target.
+    fn set_primary_card(&mut self, id: VideoCardId);
+
     /// Reflect a change to a videocard's output resolution, so that associated
     /// resources can be resized as well.
     fn on_card_resized(&mut self, vid: &VideoCardId, extents: &DisplayExtents) -> Result<(), Error>;
@@ -215,6 +227,15 @@ pub trait DisplayManager<B, G, Wi, W> {
     /// Reflect pending window resize events, resizing associated resources as needed.
     fn resize_windows(&mut self) -> Result<(), Error>;
 
+    /// Toggle the specified window between windowed and fullscreen, using the fullscreen mode
+    /// configured for the window. Returns true if the window is now fullscreen.
+    fn toggle_fullscreen(&mut self, wid: Wi) -> Result<bool, Error>;
+
+    /// Reflect a change in window focus. A window in exclusive fullscreen gives up the display
+    /// when it loses focus, so the user can switch to other applications, and takes it back when
+    /// focus returns.
+    fn on_window_focused(&mut self, wid: Wi, focused: bool);
+
     /// Execute a closure that is passed the VideoCardId for each VideoCard registered in the
     /// DisplayManager.
     fn for_each_card<F>(&mut self, f: F)
@@ -305,7 +326,11 @@ pub trait DisplayManager<B, G, Wi, W> {
     fn set_scaler_mode(&mut self, dt_idx: usize, mode: ScalerMode) -> Result<(), Error>;
 
     /// Save a screenshot of the specified display target to the specified path.
-    /// A unique filename will be generated assuming the path is a directory.
+    /// A unique filename will be generated assuming the path is a directory, and returned.
     /// No operational error is returned as screenshot operation may be deferred.
-    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf) -> Result<(), Error>;
+    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf, opts: ScreenshotOptions) -> Result<PathBuf, Error>;
+
+    /// Save a screenshot of the specified display target to the exact filename specified,
+    /// replacing any existing file. The screenshot may not be written until the next frame.
+    fn save_screenshot_to(&mut self, dt_idx: usize, filename: &Path, opts: ScreenshotOptions) -> Result<(), Error>;
 }
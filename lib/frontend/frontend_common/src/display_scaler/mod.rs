@@ -31,11 +31,19 @@
 */
 
 use crate::color::MartyColor;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 use videocard_renderer::RendererConfigParams;
 pub use wgpu::Color;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+/// Fixed displays the video output at its native resolution.
+/// Integer scales each axis by a whole number, so every pixel is the same size, choosing factors
+/// that come closest to the display aspect ratio. This is the sharpest mode, but the aspect ratio
+/// may be approximate.
+/// Fit scales the video output proportionally to the largest size that fits the window, keeping
+/// the display aspect ratio (4:3 when aspect correction is on).
+/// Stretch fills the entire window, ignoring the aspect ratio.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub enum ScalerMode {
     Null,
     Fixed,
@@ -44,6 +52,18 @@ pub enum ScalerMode {
     Stretch,
 }
 
+impl Display for ScalerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScalerMode::Null => write!(f, "Null"),
+            ScalerMode::Fixed => write!(f, "Fixed"),
+            ScalerMode::Integer => write!(f, "Integer (Sharp Pixels)"),
+            ScalerMode::Fit => write!(f, "Aspect-Correct Fit"),
+            ScalerMode::Stretch => write!(f, "Stretch"),
+        }
+    }
+}
+
 // This array is intented to represent modes to be displayed to the user. Since Null is an
 // internal mode, we don't include it.
 pub const SCALER_MODES: [ScalerMode; 4] = [
@@ -85,7 +105,9 @@ pub enum ScalerOption {
     Mono { enabled: bool, r: f32, g: f32, b: f32, a: f32 },
     Geometry { h_curvature: f32, v_curvature: f32, corner_radius: f32 },
     Scanlines { enabled: Option<bool>, lines: Option<u32>, intensity: Option<f32> },
+    Persistence(f32), // Fraction of the previous frame's brightness retained each frame
     Effect(ScalerEffect),
+    PostShader(Option<String>), // WGSL source for a post-processing shader, or None to remove it
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
@@ -96,6 +118,12 @@ pub enum PhosphorType {
     Amber,
 }
 
+pub const DEFAULT_SCANLINE_INTENSITY: f32 = 0.3;
+
+const fn _default_scanline_intensity() -> f32 {
+    DEFAULT_SCANLINE_INTENSITY
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ScalerPreset {
     pub name: String,
@@ -107,8 +135,19 @@ pub struct ScalerPreset {
     pub crt_barrel_distortion: f32,
     pub crt_corner_radius: f32,
     pub crt_scanlines: bool,
+    /// How much scanlines darken the gaps between lines, from 0.0 (invisible) to 1.0 (black).
+    #[serde(default = "_default_scanline_intensity")]
+    pub crt_scanline_intensity: f32,
+    /// Fraction of the previous frame's brightness that remains visible, from 0.0 (no
+    /// persistence) to just below 1.0 (very long decay).
+    #[serde(default)]
+    pub crt_phosphor_persistence: f32,
     pub crt_phosphor_type: PhosphorType,
     pub gamma: f32,
+    /// Name of a post-processing shader to load from the shader resource directory. If not
+    /// specified, the default shader for the machine's video card is used, if any.
+    #[serde(default)]
+    pub crt_shader: Option<String>,
     // Options for associated renderer
     pub renderer: RendererConfigParams,
 }
@@ -120,6 +159,8 @@ pub struct ScalerParams {
     pub crt_barrel_distortion: f32,
     pub crt_corner_radius: f32,
     pub crt_scanlines: bool,
+    pub crt_scanline_intensity: f32,
+    pub crt_phosphor_persistence: f32,
     pub crt_phosphor_type: PhosphorType,
     pub gamma: f32,
 }
@@ -131,6 +172,8 @@ impl From<ScalerPreset> for ScalerParams {
             crt_effect: value.crt_effect,
             crt_barrel_distortion: value.crt_barrel_distortion,
             crt_scanlines: value.crt_scanlines,
+            crt_scanline_intensity: value.crt_scanline_intensity,
+            crt_phosphor_persistence: value.crt_phosphor_persistence,
             crt_phosphor_type: value.crt_phosphor_type,
             crt_corner_radius: value.crt_corner_radius,
             gamma: value.gamma,
@@ -146,6 +189,8 @@ impl Default for ScalerParams {
             crt_barrel_distortion: 0.0,
             crt_corner_radius: 0.0,
             crt_scanlines: false,
+            crt_scanline_intensity: DEFAULT_SCANLINE_INTENSITY,
+            crt_phosphor_persistence: 0.0,
             crt_phosphor_type: PhosphorType::Color,
             gamma: 1.0,
         }
@@ -192,4 +237,7 @@ pub trait DisplayScaler<B>: Send + Sync {
     fn set_fill_color(&mut self, fill: MartyColor);
     fn set_option(&mut self, pixels: &B, opt: ScalerOption, update: bool) -> bool;
     fn set_options(&mut self, pixels: &B, opts: Vec<ScalerOption>);
+    /// Map a position on the destination surface to a normalized (0.0-1.0) position on the source
+    /// texture. Returns None if the position lies outside the scaled image.
+    fn surface_to_texture(&self, x: f32, y: f32) -> Option<(f32, f32)>;
 }
@@ -0,0 +1,101 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::floppy_manager::convert.rs
+
+    Convert floppy images between the formats fluxfox can read and write,
+    without a running machine.
+
+    An image can only be written in the formats fluxfox reports as compatible
+    with it, so that a conversion keeps as much of the image's track and
+    sector metadata as the target format can represent.
+*/
+
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use fluxfox::{DiskImage, DiskImageFileFormat, ImageWriter};
+
+/// Load the floppy image at the specified path.
+pub fn load_image(path: &Path) -> Result<DiskImage, Error> {
+    let data = fs::read(path).map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))?;
+    DiskImage::load(&mut Cursor::new(data), Some(path.to_path_buf()), None, None)
+        .map_err(|e| anyhow!("Couldn't load floppy image {}: {}", path.display(), e))
+}
+
+/// Return the format compatible with the image whose extensions include the extension of the
+/// specified path.
+pub fn format_for_path(image: &DiskImage, path: &Path) -> Result<DiskImageFileFormat, Error> {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let formats = image.compatible_formats(true);
+
+    formats
+        .iter()
+        .find(|(_, extensions)| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+        .map(|(format, _)| *format)
+        .ok_or_else(|| {
+            let compatible: Vec<String> = formats
+                .iter()
+                .filter_map(|(_, extensions)| extensions.first())
+                .map(|e| format!(".{}", e))
+                .collect();
+            anyhow!(
+                "This image can't be written as .{}. Compatible formats: {}",
+                ext,
+                compatible.join(", ")
+            )
+        })
+}
+
+/// Convert the floppy image at `src` to a new file at `dest`. If no format is given, it is chosen
+/// from the extension of `dest`. An existing file is never overwritten. Returns the format written.
+pub fn convert_image(
+    src: &Path,
+    format: Option<DiskImageFileFormat>,
+    dest: &Path,
+) -> Result<DiskImageFileFormat, Error> {
+    if dest.exists() {
+        return Err(anyhow!("Output file already exists: {}", dest.display()));
+    }
+
+    let mut image = load_image(src)?;
+    let format = match format {
+        Some(format) => format,
+        None => format_for_path(&image, dest)?,
+    };
+
+    log::debug!("Converting {} to {:?} image {}", src.display(), format, dest.display());
+    ImageWriter::new()
+        .with_format(format)
+        .with_path(PathBuf::from(dest))
+        .write(&mut image)
+        .map_err(|e| anyhow!("Couldn't write {}: {}", dest.display(), e))?;
+    Ok(format)
+}
@@ -0,0 +1,197 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::floppy_manager::disk_set.rs
+
+    Find the disk sets of multi-disk software, so that the next disk can be
+    inserted without going through a menu.
+
+    A disk set is either the floppy images in a directory whose names differ
+    only by a disk number ('GAME1.IMG', 'GAME2.IMG', or 'Game (Disk 1 of
+    3).img' and its siblings), or the disk images in a zip archive.
+*/
+
+use std::path::{Path, PathBuf};
+
+/// A set of disks belonging to the same software, in disk order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiskSet {
+    /// Image files in one directory.
+    Files(Vec<PathBuf>),
+    /// Disk images in the zip archive at the specified path, by name.
+    Archive(PathBuf, Vec<String>),
+}
+
+impl DiskSet {
+    pub fn len(&self) -> usize {
+        match self {
+            DiskSet::Files(files) => files.len(),
+            DiskSet::Archive(_, names) => names.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the display name of the disk at the specified index.
+    pub fn name(&self, idx: usize) -> String {
+        match self {
+            DiskSet::Files(files) => files
+                .get(idx)
+                .and_then(|path| path.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            DiskSet::Archive(_, names) => names.get(idx).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Return the index of the disk with the specified file name, or name in the archive.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        match self {
+            DiskSet::Files(files) => files
+                .iter()
+                .position(|path| path.file_name().is_some_and(|file_name| file_name == name)),
+            DiskSet::Archive(_, names) => names.iter().position(|n| n == name),
+        }
+    }
+}
+
+/// Find the files among the candidates that are named like the specified file apart from a disk
+/// number, sorted by that number. Each run of digits in the file's name is tried as the disk number
+/// in turn, and the first that matches another candidate is used, so that 'Disk 1 of 3' numbers by
+/// the 1. Returns an empty list if no other candidate matches.
+pub fn numbered_set(path: &Path, candidates: &[PathBuf]) -> Vec<PathBuf> {
+    let (stem, ext) = match split_name(path) {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+
+    for (start, end) in digit_runs(&stem) {
+        let (prefix, suffix) = (&stem[..start], &stem[end..]);
+        let mut set: Vec<(u32, &PathBuf)> = candidates
+            .iter()
+            .filter_map(|candidate| {
+                let (c_stem, c_ext) = split_name(candidate)?;
+                if c_ext != ext {
+                    return None;
+                }
+                let number = c_stem.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                Some((number.parse().ok()?, candidate))
+            })
+            .collect();
+
+        if set.len() > 1 {
+            set.sort();
+            return set.into_iter().map(|(_, path)| path.clone()).collect();
+        }
+    }
+    Vec::new()
+}
+
+/// Split a file name into its stem and extension, lowercased so that sets match regardless of case.
+fn split_name(path: &Path) -> Option<(String, String)> {
+    let stem = path.file_stem()?.to_str()?.to_ascii_lowercase();
+    let ext = path
+        .extension()
+        .map_or(Some(""), |ext| ext.to_str())?
+        .to_ascii_lowercase();
+    Some((stem, ext))
+}
+
+/// Return the byte ranges of each run of ASCII digits in a string.
+fn digit_runs(s: &str) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, b) in s.bytes().enumerate() {
+        match (b.is_ascii_digit(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(run_start)) => {
+                runs.push((run_start, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(run_start) = start {
+        runs.push((run_start, s.len()));
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| PathBuf::from("floppy").join(name)).collect()
+    }
+
+    #[test]
+    fn test_numbered_set() {
+        let candidates = paths(&[
+            "GAME2.IMG",
+            "game10.img",
+            "GAME1.IMG",
+            "game1.ima",
+            "gamex.img",
+            "Other (Disk 2 of 3).img",
+            "Other (Disk 1 of 3).img",
+            "Other (Disk 3 of 3).img",
+            "single.img",
+        ]);
+
+        assert_eq!(
+            numbered_set(Path::new("floppy/GAME2.IMG"), &candidates),
+            paths(&["GAME1.IMG", "GAME2.IMG", "game10.img"])
+        );
+        assert_eq!(
+            numbered_set(Path::new("floppy/Other (Disk 3 of 3).img"), &candidates),
+            paths(&[
+                "Other (Disk 1 of 3).img",
+                "Other (Disk 2 of 3).img",
+                "Other (Disk 3 of 3).img"
+            ])
+        );
+        assert!(numbered_set(Path::new("floppy/single.img"), &candidates).is_empty());
+        assert!(numbered_set(Path::new("floppy/game1.ima"), &candidates).is_empty());
+    }
+
+    #[test]
+    fn test_disk_set_position() {
+        let set = DiskSet::Files(paths(&["DISK1.IMG", "DISK2.IMG"]));
+        assert_eq!(set.position("DISK2.IMG"), Some(1));
+        assert_eq!(set.name(0), "DISK1.IMG");
+        assert_eq!(set.position("DISK3.IMG"), None);
+
+        let set = DiskSet::Archive(PathBuf::from("game.zip"), vec!["A.IMG".into(), "B.IMG".into()]);
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.position("B.IMG"), Some(1));
+    }
+}
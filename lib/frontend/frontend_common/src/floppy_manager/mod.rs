@@ -31,6 +31,10 @@
 
 */
 
+pub mod convert;
+pub mod disk_set;
+pub mod write_back;
+
 use crate::{
     resource_manager::{
         tree::{NodeType, TreeNode},
@@ -42,6 +46,7 @@ use crate::{
     types::floppy::{FloppyImageSource, RelativeDirectory},
 };
 use anyhow::Error;
+use disk_set::DiskSet;
 use fatfs::{Dir, OemCpConverter, TimeProvider};
 use marty_core::device_types::fdc::FloppyImageType;
 use std::{
@@ -124,6 +129,12 @@ impl FloppyManager {
         }
     }
 
+    /// Return true if the specified file has one of the floppy image extensions, including zip.
+    pub fn is_floppy_image(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.extensions.contains(&ext.to_ascii_lowercase()))
+    }
+
     pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
         // Clear and rebuild image lists.
         self.image_vec.clear();
@@ -401,26 +412,27 @@ impl FloppyManager {
             }
         }
 
-        /*        // If we found IO.SYS, write it first.
-        if let Some(io_sys_path) = io_sys {
-            let io_sys_vec = rm.read_resource_from_path(&io_sys_path)?;
-            let filename_only = io_sys_path.file_name().unwrap().to_str().unwrap();
-            let mut io_sys_file = vfat12.root_dir().create_file(filename_only)?;
-            log::debug!("Installing IO SYS: {}", filename_only);
-            io_sys_file.write_all(&io_sys_vec)?;
-            io_sys_file.flush().unwrap();
+        // If we found IO.SYS and MSDOS.SYS, write them first, in that order.
+        for sys_path in [io_sys, dos_sys].iter().flatten() {
+            use fatfs::Write;
+
+            let zip_path = zip_entry_name(sys_path).ok_or(FloppyError::ImageBuildError)?;
+            let mut sys_vec = Vec::new();
+            zip.by_name(&zip_path)?.read_to_end(&mut sys_vec)?;
+
+            log::debug!("Installing system file: {}", zip_path);
+            let mut sys_file = vfat12
+                .root_dir()
+                .create_file(&zip_path)
+                .map_err(|_| anyhow::anyhow!("Failed to create file: {}", zip_path))?;
+            sys_file
+                .write_all(&sys_vec)
+                .map_err(|_| anyhow::anyhow!("Failed to write file: {}", zip_path))?;
+            sys_file
+                .flush()
+                .map_err(|_| anyhow::anyhow!("Failed to flush file: {}", zip_path))?;
         }
 
-        // If we found MSDOS.SYS, write it second.
-        if let Some(dos_sys_path) = dos_sys {
-            let dos_sys_vec = rm.read_resource_from_path(&dos_sys_path)?;
-            let filename_only = dos_sys_path.file_name().unwrap().to_str().unwrap();
-            let mut dos_sys_file = vfat12.root_dir().create_file(filename_only)?;
-            log::debug!("Installing DOS SYS: {}", filename_only);
-            dos_sys_file.write_all(&dos_sys_vec)?;
-            dos_sys_file.flush().unwrap();
-        }*/
-
         // Build tree from the rest of the files
         let file_tree = rm.items_to_tree_raw(&zip_items)?;
 
@@ -435,19 +447,7 @@ impl FloppyManager {
             if let Some(src_root_node) = src_root_node_opt {
                 if let Err(err) =
                     build_autofloppy_dir(&src_root_node, dst_root_dir, rm, &files_visited, &mut |path: &Path| {
-                        // This callback strips the first directory entry back off the tree (.\\)
-                        // and converts backslashes to forward slashes.
-
-                        let mut buf = path.to_path_buf();
-                        if let Some(first_component) = buf.components().next() {
-                            if first_component.as_os_str() == "." {
-                                buf = buf.components().skip(1).collect();
-                            }
-                        }
-
-                        if let Some(path_str) = buf.to_str() {
-                            let zip_path = path_str.replace("\\", "/");
-
+                        if let Some(zip_path) = zip_entry_name(path) {
                             log::debug!("Reading file from zip: {}", zip_path);
                             let mut file = zip.by_name(&zip_path)?;
                             let mut file_vec = Vec::new();
@@ -606,27 +606,9 @@ impl FloppyManager {
 
         // Did we find a boot sector file? if so, load it now
         if let Some(bootsector_path) = bootsector_opt {
-            let mut bootsector_vec = rm.read_resource_from_path(&bootsector_path)?;
-
-            if bootsector_vec.len() > 0 {
-                if bootsector_vec.len() < 512 {
-                    bootsector_vec.extend(vec![0u8; 512 - bootsector_vec.len()]);
-                    // Add the boot sector marker
-                    bootsector_vec[510] = 0x55;
-                    bootsector_vec[511] = 0xAA;
-                }
-                else if bootsector_vec.len() > 512 {
-                    bootsector_vec.truncate(512);
-                }
-
-                log::debug!(
-                    "Installing bootsector: {} of len: {} into autofloppy image...",
-                    bootsector_path.display(),
-                    bootsector_vec.len()
-                );
-                // TODO: Eventually we would prefer to use fluxfox to write the first sector logically
-                buf[..512].copy_from_slice(&bootsector_vec);
-            }
+            let bootsector_vec = rm.read_resource_from_path(&bootsector_path)?;
+            log::debug!("Installing bootsector: {}", bootsector_path.display());
+            install_bootsector(&mut buf, bootsector_vec);
         }
 
         let mut file = File::create("fat_dump.img").map_err(|_| FloppyError::ImageBuildError)?;
@@ -635,6 +617,55 @@ impl FloppyManager {
         Ok(buf.clone())
     }
 
+    /// Build a FAT12 floppy image holding the contents of any host directory, including its
+    /// subdirectories. The image is rebuilt from the directory as it is on each call, so
+    /// remounting the directory picks up changes made on the host. As with autofloppy
+    /// directories, DOS system files are written first so that the image can boot, and a
+    /// 'bootsector.bin' file is installed as the boot sector rather than copied.
+    pub fn build_floppy_image_from_host_dir(
+        &self,
+        path: &Path,
+        format: Option<FloppyImageType>,
+    ) -> Result<Vec<u8>, Error> {
+        let format = format.unwrap_or(FloppyImageType::Image360K);
+        if !path.is_dir() {
+            return Err(FloppyError::DirNotFound.into());
+        }
+
+        let dir_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let formatted_image = create_formatted_image(&sanitize_label(&dir_name), format)?;
+        let mut floppy_buf = Cursor::new(formatted_image);
+
+        let mut entries = read_host_dir(path)?;
+        let mut bootsector = None;
+        if let Some(pos) = entries.iter().position(|entry| {
+            entry.is_file()
+                && entry
+                    .file_name()
+                    .is_some_and(|n| n.eq_ignore_ascii_case("bootsector.bin"))
+        }) {
+            bootsector = Some(fs::read(entries.remove(pos))?);
+        }
+        // A stable sort keeps the rest of the directory in name order.
+        entries.sort_by_key(|entry| system_file_order(entry));
+
+        // Block scope to avoid borrowing floppy_buf forever
+        {
+            let vfat12 = fatfs::FileSystem::new(&mut floppy_buf, fatfs::FsOptions::new())
+                .map_err(|e| anyhow::anyhow!("Error creating FAT filesystem: {:?}", e))?;
+            write_host_dir(&entries, &vfat12.root_dir())?;
+            vfat12.unmount()?;
+        }
+
+        let mut buf = floppy_buf.into_inner();
+        if let Some(bootsector) = bootsector {
+            install_bootsector(&mut buf, bootsector);
+        }
+
+        log::debug!("Built {:?} floppy image from host directory {}", format, path.display());
+        Ok(buf)
+    }
+
     /*
     pub fn load_floppy_data(&self, name: &OsString) -> Result<Vec<u8>, FloppyError> {
         let mut floppy_vec = Vec::new();
@@ -721,17 +752,81 @@ impl FloppyManager {
 
         Ok(ArchiveType::Mountable)
     }
+
+    /// List the disk images inside a zip archive by their names in the archive, in name order.
+    /// Any file with a floppy image extension other than .zip is considered a disk image.
+    pub fn zip_disk_images(&self, archive: &[u8]) -> Result<Vec<String>, Error> {
+        let zip = ZipArchive::new(Cursor::new(archive))?;
+
+        let mut images: Vec<String> = zip
+            .file_names()
+            .filter(|name| {
+                Path::new(name).extension().is_some_and(|ext| {
+                    let ext = ext.to_ascii_lowercase();
+                    ext != "zip" && self.extensions.contains(&ext)
+                })
+            })
+            .map(|name| name.to_string())
+            .collect();
+        images.sort_by_key(|name| name.to_ascii_uppercase());
+        Ok(images)
+    }
+
+    /// Find the disk set the specified image file belongs to, from the other floppy images in its
+    /// directory. Returns None if the file has no siblings numbered like it.
+    pub fn find_disk_set(&self, path: &Path) -> Option<DiskSet> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let candidates: Vec<PathBuf> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && self.is_floppy_image(path)
+                    && !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+            })
+            .collect();
+
+        let files = disk_set::numbered_set(path, &candidates);
+        (!files.is_empty()).then_some(DiskSet::Files(files))
+    }
+
+    /// Read the file of the specified name out of a zip archive.
+    pub fn read_zip_entry(&self, archive: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let mut zip = ZipArchive::new(Cursor::new(archive))?;
+        let mut file = zip.by_name(name)?;
+        let mut file_vec = Vec::new();
+        file.read_to_end(&mut file_vec)?;
+        Ok(file_vec)
+    }
 }
 
-fn create_formatted_image(label: &str, format: FloppyImageType) -> Result<Vec<u8>, Error> {
+/// Convert a path in a tree built from zip entry names back to the entry name. The tree adds a
+/// leading '.' component and uses backslashes.
+fn zip_entry_name(path: &Path) -> Option<String> {
+    let mut buf = path.to_path_buf();
+    if let Some(first_component) = buf.components().next() {
+        if first_component.as_os_str() == "." {
+            buf = buf.components().skip(1).collect();
+        }
+    }
+    buf.to_str().map(|path_str| path_str.replace('\\', "/"))
+}
+
+/// Create a raw sector image of a FAT12 floppy of the specified format, with the parameters DOS's
+/// FORMAT command would use.
+pub fn create_formatted_image(label: &str, format: FloppyImageType) -> Result<Vec<u8>, Error> {
     let (bps, bpc, mrde, spt, heads, media_byte, image_size) = match format {
+        FloppyImageType::Image160K => (512, 512, 0x40, 8, 1, 0xFE, 163_840),
+        FloppyImageType::Image180K => (512, 512, 0x40, 9, 1, 0xFC, 184_320),
+        FloppyImageType::Image320K => (512, 2 * 512, 0x70, 8, 2, 0xFF, 327_680),
         FloppyImageType::Image360K => (512, 2 * 512, 0x70, 9, 2, 0xFD, 368_640),
         FloppyImageType::Image720K => (512, 2 * 512, 0x70, 9, 2, 0xF9, 737_280),
         FloppyImageType::Image12M => (512, 2 * 512, 0xE0, 15, 2, 0xF9, 1_228_800),
         FloppyImageType::Image144M => (512, 2 * 512, 0xE0, 18, 2, 0xF0, 1_474_560),
-        _ => {
-            return Err(anyhow::anyhow!("Unsupported floppy image format: {:?}", format));
-        }
     };
 
     log::debug!("Formatting an {:?} format floppy with label: {}", format, label);
@@ -756,8 +851,26 @@ fn create_formatted_image(label: &str, format: FloppyImageType) -> Result<Vec<u8
     Ok(floppy_buf.into_inner().into_inner())
 }
 
-fn create_drive_label(input: &str) -> [u8; 11] {
+/// Write a blank floppy image of the specified format to a new file. An existing file is never
+/// overwritten.
+pub fn create_formatted_image_file(path: &Path, label: &str, format: FloppyImageType) -> Result<(), Error> {
+    let image = create_formatted_image(label, format)?;
+    let mut file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Couldn't create {}: {}", path.display(), e))?;
+    file.write_all(&image)?;
+    Ok(())
+}
+
+pub(crate) fn create_drive_label(input: &str) -> [u8; 11] {
     let max_length = 11;
+    // DOS labels a volume formatted without a label "NO NAME".
+    let input = match input.trim() {
+        "" => "NO NAME",
+        trimmed => trimmed,
+    };
     let trimmed = if input.len() > max_length {
         input[..max_length].to_string().to_ascii_uppercase()
     }
@@ -772,6 +885,103 @@ fn create_drive_label(input: &str) -> [u8; 11] {
         .unwrap()
 }
 
+/// Copy a boot sector into the first sector of a floppy image. Boot sectors shorter than 512
+/// bytes are padded and given the boot signature; longer ones are truncated.
+fn install_bootsector(image: &mut [u8], mut bootsector: Vec<u8>) {
+    if bootsector.is_empty() {
+        return;
+    }
+    if bootsector.len() < 512 {
+        bootsector.resize(512, 0);
+        // Add the boot sector marker
+        bootsector[510] = 0x55;
+        bootsector[511] = 0xAA;
+    }
+    else {
+        bootsector.truncate(512);
+    }
+    // TODO: Eventually we would prefer to use fluxfox to write the first sector logically
+    image[..512].copy_from_slice(&bootsector);
+}
+
+/// Make a volume label from a host directory or user supplied name, replacing characters DOS
+/// won't accept.
+pub fn sanitize_label(name: &str) -> String {
+    name.chars()
+        .take(11)
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// DOS system files must be the first files in the root directory for the disk to boot.
+fn system_file_order(path: &Path) -> usize {
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_ascii_uppercase();
+    match name.as_str() {
+        "IO.SYS" | "IBMBIO.COM" | "KERNEL.SYS" => 0,
+        "MSDOS.SYS" | "IBMDOS.COM" => 1,
+        _ => 2,
+    }
+}
+
+/// List the files and subdirectories of a host directory in name order, skipping hidden entries.
+pub(crate) fn read_host_dir(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        entries.push(entry.path());
+    }
+    entries.sort_by_key(|entry| entry.file_name().unwrap_or_default().to_ascii_uppercase());
+    Ok(entries)
+}
+
+/// Copy host files and directories into a FAT directory, recursing into subdirectories. Names
+/// that aren't valid 8.3 names are given a generated short name by the FAT library.
+pub(crate) fn write_host_dir<IO: fatfs::Read + fatfs::Write + fatfs::Seek, TP, OCC>(
+    entries: &[PathBuf],
+    dir: &Dir<IO, TP, OCC>,
+) -> Result<(), Error>
+where
+    OCC: OemCpConverter,
+    TP: TimeProvider,
+{
+    use fatfs::Write;
+
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if entry.is_dir() {
+            let new_dir = dir
+                .create_dir(&name)
+                .map_err(|_| anyhow::anyhow!("Failed to create directory: {}", entry.display()))?;
+            write_host_dir(&read_host_dir(entry)?, &new_dir)?;
+        }
+        else if entry.is_file() {
+            let data = fs::read(entry)?;
+            let mut file = dir
+                .create_file(&name)
+                .map_err(|_| anyhow::anyhow!("Failed to create file: {}", entry.display()))?;
+            file.write_all(&data).map_err(|_| {
+                anyhow::anyhow!(
+                    "Failed to write {}. The directory may not fit on the disk.",
+                    entry.display()
+                )
+            })?;
+            file.flush()
+                .map_err(|_| anyhow::anyhow!("Failed to flush file: {}", entry.display()))?;
+        }
+    }
+    Ok(())
+}
+
 pub fn build_autofloppy_dir<IO: fatfs::Read + fatfs::Write + fatfs::Seek, TP, OCC>(
     dir_node: &TreeNode,
     fs: Dir<IO, TP, OCC>,
@@ -0,0 +1,165 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::floppy_manager::write_back.rs
+
+    Track floppy images mounted from files, so that the guest's changes can be
+    written back to the image file.
+
+    The first time a mounted image is written back, the image file is copied
+    to a backup beside it ('disk.img' to 'disk.img.bak'), so that everything
+    written during the mount can be undone by restoring the backup. A backup
+    left by an earlier mount is replaced.
+*/
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use fluxfox::{DiskImage, DiskImageFileFormat, ImageWriter};
+use web_time::{Duration, Instant};
+
+use crate::types::floppy::FloppyWriteBack;
+
+/// In automatic mode, an image is written back once the guest has stopped writing to it for this
+/// long, so that a write back doesn't land in the middle of a save spanning several sectors.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+pub struct MountedImage {
+    path: PathBuf,
+    format: DiskImageFileFormat,
+    mode: FloppyWriteBack,
+    backup: bool,
+    backup_path: Option<PathBuf>,
+    saved_write_ct: u64,
+    last_write_ct: u64,
+    last_write_at: Instant,
+}
+
+impl MountedImage {
+    /// Track an image mounted from the specified file, given its current write count. Returns
+    /// None if the image can't be written back in the format it was loaded from.
+    pub fn new(
+        path: &Path,
+        image: &DiskImage,
+        write_ct: u64,
+        mode: FloppyWriteBack,
+        backup: bool,
+    ) -> Option<MountedImage> {
+        let format = image.source_format()?;
+        let writable = image.compatible_formats(true).iter().any(|(fmt, _)| *fmt == format);
+        if !writable || !path.is_file() {
+            return None;
+        }
+
+        Some(MountedImage {
+            path: path.to_path_buf(),
+            format,
+            mode,
+            backup,
+            backup_path: None,
+            saved_write_ct: write_ct,
+            last_write_ct: write_ct,
+            last_write_at: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mode(&self) -> FloppyWriteBack {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FloppyWriteBack) {
+        self.mode = mode;
+    }
+
+    /// The backup made by the first write back of this mount, if any.
+    pub fn backup_path(&self) -> Option<&Path> {
+        self.backup_path.as_deref()
+    }
+
+    /// Return true if the guest has written to the image since it was mounted or last saved.
+    pub fn is_modified(&self, write_ct: u64) -> bool {
+        write_ct != self.saved_write_ct
+    }
+
+    /// Return true if the image should be written back now. This is only the case in automatic
+    /// mode, once the guest has stopped writing to the image.
+    pub fn flush_due(&mut self, write_ct: u64) -> bool {
+        if write_ct != self.last_write_ct {
+            self.last_write_ct = write_ct;
+            self.last_write_at = Instant::now();
+            return false;
+        }
+        self.mode == FloppyWriteBack::Auto && self.is_modified(write_ct) && self.last_write_at.elapsed() >= SETTLE_TIME
+    }
+
+    /// Write the image back to its file, backing up the file first if this is the first write back
+    /// of the mount and backups are enabled.
+    pub fn flush(&mut self, image: &mut DiskImage, write_ct: u64) -> Result<(), Error> {
+        if self.backup && self.backup_path.is_none() {
+            let backup_path = backup_path_for(&self.path);
+            fs::copy(&self.path, &backup_path)
+                .map_err(|e| anyhow!("Couldn't back up {}: {}", self.path.display(), e))?;
+            self.backup_path = Some(backup_path);
+        }
+
+        ImageWriter::new()
+            .with_format(self.format)
+            .with_path(self.path.clone())
+            .write(image)
+            .map_err(|e| anyhow!("Couldn't write {}: {}", self.path.display(), e))?;
+        self.saved_write_ct = write_ct;
+        Ok(())
+    }
+
+    /// Mark the image as matching its file, after it was saved from the drive's menu.
+    pub fn set_saved(&mut self, write_ct: u64) {
+        self.saved_write_ct = write_ct;
+    }
+
+    /// Copy the backup over the image file, undoing every write back since the image was mounted.
+    /// The image must be reloaded from the file afterward.
+    pub fn restore_backup(&mut self) -> Result<(), Error> {
+        let backup_path = self
+            .backup_path
+            .take()
+            .ok_or(anyhow!("No backup was made of {}", self.path.display()))?;
+        fs::copy(&backup_path, &self.path).map_err(|e| anyhow!("Couldn't restore {}: {}", backup_path.display(), e))?;
+        Ok(())
+    }
+}
+
+fn backup_path_for(path: &Path) -> PathBuf {
+    let mut backup_path = path.as_os_str().to_os_string();
+    backup_path.push(".bak");
+    PathBuf::from(backup_path)
+}
@@ -0,0 +1,287 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+    frontend_common::keyboard_macro::mod.rs
+
+    Record and play back sequences of guest keystrokes with their timing.
+
+    Macros are stored as plain text, one key event per line:
+
+        <delay in milliseconds since the previous event> <down|up> <key>
+
+    Key names are the MartyKey names used in keyboard layout files. Blank
+    lines and lines starting with '#' are ignored.
+*/
+
+use std::{path::Path, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use marty_core::keys::MartyKey;
+use web_time::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MacroEvent {
+    /// Time since the previous event.
+    pub delay: Duration,
+    pub key: MartyKey,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyboardMacro {
+    pub events: Vec<MacroEvent>,
+}
+
+impl KeyboardMacro {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut events = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(anyhow!("Line {}: expected '<delay> <down|up> <key>'", line_no + 1));
+            }
+            let delay = fields[0]
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Line {}: invalid delay '{}'", line_no + 1, fields[0]))?;
+            let pressed = match fields[1].to_ascii_lowercase().as_str() {
+                "down" => true,
+                "up" => false,
+                _ => {
+                    return Err(anyhow!(
+                        "Line {}: expected 'down' or 'up', got '{}'",
+                        line_no + 1,
+                        fields[1]
+                    ))
+                }
+            };
+            let key = MartyKey::from_str(fields[2])
+                .map_err(|_| anyhow!("Line {}: unknown key '{}'", line_no + 1, fields[2]))?;
+            events.push(MacroEvent {
+                delay: Duration::from_millis(delay),
+                key,
+                pressed,
+            });
+        }
+        Ok(Self { events })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# MartyPC keyboard macro\n# <delay ms> <down|up> <key>\n");
+        for event in self.events.iter() {
+            text.push_str(&format!(
+                "{} {} {:?}\n",
+                event.delay.as_millis(),
+                if event.pressed { "down" } else { "up" },
+                event.key
+            ));
+        }
+        text
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    /// Remove presses that are never released and releases that were never pressed. These come
+    /// from the keys of the hotkeys used to start and stop recording.
+    fn trim_unbalanced(&mut self) {
+        let mut keep = vec![true; self.events.len()];
+        for (i, event) in self.events.iter().enumerate() {
+            if event.pressed {
+                keep[i] = self.events[i + 1..].iter().any(|e| e.key == event.key && !e.pressed);
+            }
+            else {
+                keep[i] = self.events[..i].iter().any(|e| e.key == event.key && e.pressed);
+            }
+        }
+
+        // Fold the delay of each removed event into the next kept one so timing is preserved.
+        let mut carry = Duration::ZERO;
+        let mut events = Vec::with_capacity(self.events.len());
+        for (event, keep) in self.events.iter().zip(keep) {
+            if keep {
+                events.push(MacroEvent {
+                    delay: event.delay + carry,
+                    ..*event
+                });
+                carry = Duration::ZERO;
+            }
+            else {
+                carry += event.delay;
+            }
+        }
+        self.events = events;
+    }
+}
+
+/// Records key events sent to the guest.
+pub struct MacroRecorder {
+    events: Vec<MacroEvent>,
+    last:   Instant,
+}
+
+impl MacroRecorder {
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            last:   Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, key: MartyKey, pressed: bool) {
+        let now = Instant::now();
+        self.events.push(MacroEvent {
+            delay: now.duration_since(self.last),
+            key,
+            pressed,
+        });
+        self.last = now;
+    }
+
+    pub fn finish(self) -> KeyboardMacro {
+        let mut keyboard_macro = KeyboardMacro { events: self.events };
+        keyboard_macro.trim_unbalanced();
+        keyboard_macro
+    }
+}
+
+/// Plays back a macro as time is advanced.
+pub struct MacroPlayer {
+    keyboard_macro: KeyboardMacro,
+    next: usize,
+    elapsed: Duration,
+}
+
+impl MacroPlayer {
+    pub fn new(keyboard_macro: KeyboardMacro) -> Self {
+        Self {
+            keyboard_macro,
+            next: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Advance playback by the given time and return the key events that are now due, in order.
+    pub fn advance(&mut self, delta: Duration) -> Vec<(MartyKey, bool)> {
+        self.elapsed += delta;
+        let mut due = Vec::new();
+        while let Some(event) = self.keyboard_macro.events.get(self.next) {
+            if event.delay > self.elapsed {
+                break;
+            }
+            self.elapsed -= event.delay;
+            due.push((event.key, event.pressed));
+            self.next += 1;
+        }
+        due
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.keyboard_macro.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(delay: u64, key: MartyKey, pressed: bool) -> MacroEvent {
+        MacroEvent {
+            delay: Duration::from_millis(delay),
+            key,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let text = "# comment\n\n0 down KeyA\n120 up KeyA\n  35 DOWN Enter  \n";
+        let keyboard_macro = KeyboardMacro::parse(text).unwrap();
+        assert_eq!(
+            keyboard_macro.events,
+            vec![
+                event(0, MartyKey::KeyA, true),
+                event(120, MartyKey::KeyA, false),
+                event(35, MartyKey::Enter, true),
+            ]
+        );
+        assert_eq!(KeyboardMacro::parse(&keyboard_macro.to_text()).unwrap(), keyboard_macro);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(KeyboardMacro::parse("10 down").is_err());
+        assert!(KeyboardMacro::parse("x down KeyA").is_err());
+        assert!(KeyboardMacro::parse("10 press KeyA").is_err());
+        assert!(KeyboardMacro::parse("10 down NotAKey").is_err());
+    }
+
+    #[test]
+    fn test_trim_unbalanced() {
+        let mut keyboard_macro = KeyboardMacro {
+            events: vec![
+                event(0, MartyKey::F3, false),
+                event(100, MartyKey::KeyA, true),
+                event(50, MartyKey::KeyA, false),
+                event(200, MartyKey::ControlLeft, true),
+                event(10, MartyKey::F3, true),
+            ],
+        };
+        keyboard_macro.trim_unbalanced();
+        assert_eq!(
+            keyboard_macro.events,
+            vec![event(100, MartyKey::KeyA, true), event(50, MartyKey::KeyA, false)]
+        );
+    }
+
+    #[test]
+    fn test_player() {
+        let keyboard_macro = KeyboardMacro {
+            events: vec![
+                event(100, MartyKey::KeyA, true),
+                event(0, MartyKey::KeyA, false),
+                event(50, MartyKey::KeyB, true),
+            ],
+        };
+        let mut player = MacroPlayer::new(keyboard_macro);
+        assert!(player.advance(Duration::from_millis(99)).is_empty());
+        assert_eq!(
+            player.advance(Duration::from_millis(1)),
+            vec![(MartyKey::KeyA, true), (MartyKey::KeyA, false)]
+        );
+        assert!(!player.is_finished());
+        assert_eq!(player.advance(Duration::from_millis(60)), vec![(MartyKey::KeyB, true)]);
+        assert!(player.is_finished());
+    }
+}
@@ -33,16 +33,31 @@
 
 use serde_derive::Deserialize;
 
+pub mod audio_capture;
+pub mod audio_resampler;
+pub mod automation;
 pub mod cartridge_manager;
+pub mod cassette_manager;
+pub mod cdrom_manager;
 pub mod color;
 pub mod constants;
+pub mod debug_console;
 pub mod display_manager;
 #[cfg(feature = "use_wgpu")]
 pub mod display_scaler;
 pub mod floppy_manager;
+pub mod keyboard_macro;
 pub mod machine_manager;
+pub mod media_library;
+pub mod movie;
+pub mod paste;
+pub mod quick_load;
 pub mod resource_manager;
+pub mod rewind_buffer;
 pub mod rom_manager;
+pub mod savestate_manager;
+pub mod script;
+pub mod text_export;
 pub mod timestep_manager;
 pub mod types;
 pub mod vhd_manager;
@@ -53,6 +68,8 @@ pub type HotkeyEvent = types::hotkeys::HotkeyEvent;
 pub type HotkeyScope = types::hotkeys::HotkeyScope;
 pub type HotkeyConfigEntry = types::hotkeys::HotkeyConfigEntry;
 pub type JoyKeyEntry = types::joykeys::JoyKeyEntry;
+pub type GamepadProfile = types::gamepad::GamepadProfile;
+pub type KeyTranslationMode = types::keyboard_layout::KeyTranslationMode;
 pub type RelativeDirectory = types::floppy::RelativeDirectory;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
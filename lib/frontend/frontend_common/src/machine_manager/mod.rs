@@ -33,19 +33,32 @@ use crate::resource_manager::ResourceManager;
 use anyhow::Error;
 use marty_core::{
     device_traits::videocard::VideoType,
+    devices::pit::PitType,
     machine_config::{
+        BusMouseConfig,
+        CassetteConfig,
         CpuConfig,
+        DmaType,
         EmsMemoryConfig,
         FloppyControllerConfig,
         GamePortConfig,
         HardDriveControllerConfig,
+        KbControllerType,
         KeyboardConfig,
         MachineConfiguration,
         MediaConfig,
         MemoryConfig,
+        ModemConfig,
+        NetworkCardConfig,
+        ParallelPortConfig,
+        PicType,
+        PostCardConfig,
+        Ps2MouseConfig,
+        RtcConfig,
         SerialControllerConfig,
         SerialMouseConfig,
         SoundDeviceConfig,
+        SpeakerFilterConfig,
         VideoCardConfig,
     },
     machine_types::{HardDiskControllerType, MachineType},
@@ -76,37 +89,59 @@ pub struct MachineConfigFileEntry {
     machine_type: MachineType,
     rom_set: String,
     overlays: Option<Vec<String>>,
+    pit_type: Option<PitType>,
+    pic_type: Option<PicType>,
+    dma_type: Option<DmaType>,
+    kb_controller: Option<KbControllerType>,
     cpu: Option<CpuConfig>,
     memory: MemoryConfig,
     ems: Option<EmsMemoryConfig>,
     #[serde(default)]
     speaker: bool,
+    speaker_filter: Option<SpeakerFilterConfig>,
     ppi_turbo: Option<bool>, // This bool is an option so that it is three state - missing means no turbo feature, true means ppi high = turbo, false means ppi low = turbo.
     fdc: Option<FloppyControllerConfig>,
     hdc: Option<HardDriveControllerConfig>,
     serial: Option<Vec<SerialControllerConfig>>,
+    parallel: Option<ParallelPortConfig>,
     video: Option<Vec<VideoCardConfig>>,
     sound: Option<Vec<SoundDeviceConfig>>,
     keyboard: Option<KeyboardConfig>,
     serial_mouse: Option<SerialMouseConfig>,
+    modem: Option<ModemConfig>,
+    cassette: Option<CassetteConfig>,
+    bus_mouse: Option<BusMouseConfig>,
+    ps2_mouse: Option<Ps2MouseConfig>,
     game_port: Option<GamePortConfig>,
+    rtc: Option<RtcConfig>,
+    post_card: Option<PostCardConfig>,
+    network: Option<NetworkCardConfig>,
     media: Option<MediaConfig>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct MachineConfigFileOverlayEntry {
     name: String,
+    speaker_filter: Option<SpeakerFilterConfig>,
     cpu: Option<CpuConfig>,
     memory: Option<MemoryConfig>,
     ems: Option<EmsMemoryConfig>,
     fdc: Option<FloppyControllerConfig>,
     hdc: Option<HardDriveControllerConfig>,
     serial: Option<Vec<SerialControllerConfig>>,
+    parallel: Option<ParallelPortConfig>,
     video: Option<Vec<VideoCardConfig>>,
     sound: Option<Vec<SoundDeviceConfig>>,
     keyboard: Option<KeyboardConfig>,
     serial_mouse: Option<SerialMouseConfig>,
+    modem: Option<ModemConfig>,
+    cassette: Option<CassetteConfig>,
+    bus_mouse: Option<BusMouseConfig>,
+    ps2_mouse: Option<Ps2MouseConfig>,
     game_port: Option<GamePortConfig>,
+    rtc: Option<RtcConfig>,
+    post_card: Option<PostCardConfig>,
+    network: Option<NetworkCardConfig>,
     media: Option<MediaConfig>,
 }
 
@@ -319,6 +354,22 @@ impl MachineConfigFileEntry {
                         req_vec.push(String::from("ibm_xebec"));
                     }
                 }
+                HardDiskControllerType::XtIde => {
+                    if req_set.insert(String::from("expansion")) {
+                        req_vec.push(String::from("expansion"));
+                    }
+                    if req_set.insert(String::from("xtide_bios")) {
+                        req_vec.push(String::from("xtide_bios"));
+                    }
+                }
+                HardDiskControllerType::SeagateSt01 => {
+                    if req_set.insert(String::from("expansion")) {
+                        req_vec.push(String::from("expansion"));
+                    }
+                    if req_set.insert(String::from("st01_bios")) {
+                        req_vec.push(String::from("st01_bios"));
+                    }
+                }
             }
         }
 
@@ -359,6 +410,10 @@ impl MachineConfigFileEntry {
     /// Apply a Machine Config Overlay to this configuration. Every option that is Some within the overlay is
     /// copied into this configuration.
     pub fn apply_overlay(&mut self, overlay: MachineConfigFileOverlayEntry) {
+        if let Some(speaker_filter) = overlay.speaker_filter {
+            log::debug!("Applying speaker filter overlay: {:?}", speaker_filter);
+            self.speaker_filter = Some(speaker_filter);
+        }
         if let Some(cpu) = overlay.cpu {
             log::debug!("Applying CPU overlay: {:?}", cpu);
             self.cpu = Some(cpu);
@@ -383,6 +438,10 @@ impl MachineConfigFileEntry {
             log::debug!("Applying serial overlay: {:?}", serial);
             self.serial = Some(serial);
         }
+        if let Some(parallel) = overlay.parallel {
+            log::debug!("Applying parallel port overlay: {:?}", parallel);
+            self.parallel = Some(parallel);
+        }
         if let Some(video) = overlay.video {
             log::debug!("Applying video overlay: {:?}", video);
             self.video = Some(video);
@@ -399,28 +458,69 @@ impl MachineConfigFileEntry {
             log::debug!("Applying serial mouse overlay: {:?}", serial_mouse);
             self.serial_mouse = Some(serial_mouse);
         }
+        if let Some(modem) = overlay.modem {
+            log::debug!("Applying modem overlay: {:?}", modem);
+            self.modem = Some(modem);
+        }
+        if let Some(cassette) = overlay.cassette {
+            log::debug!("Applying cassette overlay: {:?}", cassette);
+            self.cassette = Some(cassette);
+        }
+        if let Some(bus_mouse) = overlay.bus_mouse {
+            log::debug!("Applying bus mouse overlay: {:?}", bus_mouse);
+            self.bus_mouse = Some(bus_mouse);
+        }
+        if let Some(ps2_mouse) = overlay.ps2_mouse {
+            log::debug!("Applying PS/2 mouse overlay: {:?}", ps2_mouse);
+            self.ps2_mouse = Some(ps2_mouse);
+        }
         if let Some(game_port) = overlay.game_port {
             log::debug!("Applying game port overlay: {:?}", game_port);
             self.game_port = Some(game_port);
         }
+        if let Some(rtc) = overlay.rtc {
+            log::debug!("Applying RTC overlay: {:?}", rtc);
+            self.rtc = Some(rtc);
+        }
+        if let Some(post_card) = overlay.post_card {
+            log::debug!("Applying POST card overlay: {:?}", post_card);
+            self.post_card = Some(post_card);
+        }
+        if let Some(network) = overlay.network {
+            log::debug!("Applying network card overlay: {:?}", network);
+            self.network = Some(network);
+        }
     }
 
     pub fn to_machine_config(&self) -> MachineConfiguration {
         MachineConfiguration {
             speaker: self.speaker,
+            speaker_filter: self.speaker_filter.clone(),
             ppi_turbo: self.ppi_turbo,
             machine_type: self.machine_type,
+            pit_type: self.pit_type,
+            pic_type: self.pic_type,
+            dma_type: self.dma_type,
+            kb_controller: self.kb_controller,
             cpu: self.cpu.clone(),
             memory: self.memory.clone(),
             ems: self.ems.clone(),
             fdc: self.fdc.clone(),
             hdc: self.hdc.clone(),
             serial: self.serial.clone().unwrap_or_default(),
+            parallel: self.parallel.clone(),
             video: self.video.clone().unwrap_or_default(),
             sound: self.sound.clone().unwrap_or_default(),
             keyboard: self.keyboard.clone(),
             serial_mouse: self.serial_mouse.clone(),
+            modem: self.modem.clone(),
+            cassette: self.cassette.clone(),
+            bus_mouse: self.bus_mouse.clone(),
+            ps2_mouse: self.ps2_mouse.clone(),
             game_port: self.game_port.clone(),
+            rtc: self.rtc.clone(),
+            post_card: self.post_card.clone(),
+            network: self.network.clone(),
             media: self.media.clone(),
         }
     }
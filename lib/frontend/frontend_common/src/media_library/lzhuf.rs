@@ -0,0 +1,290 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::media_library::lzhuf.rs
+
+    A decoder for the LZHUF compression used by TeleDisk's "advanced"
+    compression mode. This is Okumura and Yoshizaki's LZHUF: LZSS with a 4K
+    window, whose literals and match lengths are coded with an adaptive
+    Huffman tree, and the upper 6 bits of match positions with a fixed code.
+
+    TeleDisk compresses everything following the 12-byte image header, and
+    stores no length for the decompressed data, so decoding stops at the end
+    of the input or at a caller-provided limit. The bits padding out the last
+    byte of the input may decode to a spurious final symbol.
+*/
+
+/// Size of the ring buffer.
+const N: usize = 4096;
+/// Upper limit for a match length.
+const F: usize = 60;
+/// A match must be longer than this to be coded as a match.
+const THRESHOLD: usize = 2;
+/// Number of symbols: 256 literals, and the match lengths THRESHOLD + 1 to F.
+const N_CHAR: usize = 256 - THRESHOLD + F;
+/// Size of the Huffman tree.
+const T: usize = N_CHAR * 2 - 1;
+/// Index of the root of the Huffman tree.
+const R: usize = T - 1;
+/// The tree is rebuilt when the root's frequency reaches this value.
+const MAX_FREQ: u16 = 0x8000;
+
+/// Bit lengths of the fixed codes for the upper 6 bits of a match position.
+const POSITION_CODE_LENGTHS: [(u8, usize); 6] = [(3, 1), (4, 3), (5, 8), (6, 12), (7, 24), (8, 16)];
+
+struct LzhufDecoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bit_buf: u16,
+    bit_len: u8,
+    freq: [u16; T + 1],
+    // Parent of each node. The leaves, T to T + N_CHAR - 1, hold the parents of the symbols.
+    prnt: [usize; T + N_CHAR],
+    // Child of each node. A child of T or greater is the leaf for symbol (child - T).
+    son: [usize; T],
+    // Decode the upper 6 bits of a match position, and the length of their code, from the next byte.
+    d_code: [u8; 256],
+    d_len: [u8; 256],
+}
+
+impl<'a> LzhufDecoder<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        let mut decoder = Self {
+            input,
+            pos: 0,
+            bit_buf: 0,
+            bit_len: 0,
+            freq: [0; T + 1],
+            prnt: [0; T + N_CHAR],
+            son: [0; T],
+            d_code: [0; 256],
+            d_len: [0; 256],
+        };
+
+        // The position codes are assigned in order of length, so each fills a run of byte values.
+        let mut byte = 0;
+        let mut code = 0;
+        for (len, count) in POSITION_CODE_LENGTHS {
+            for _ in 0..count {
+                for _ in 0..(1 << (8 - len)) {
+                    decoder.d_code[byte] = code;
+                    decoder.d_len[byte] = len;
+                    byte += 1;
+                }
+                code += 1;
+            }
+        }
+
+        decoder.start_huff();
+        decoder
+    }
+
+    /// Build the initial Huffman tree, with every symbol at a frequency of 1.
+    fn start_huff(&mut self) {
+        for i in 0..N_CHAR {
+            self.freq[i] = 1;
+            self.son[i] = i + T;
+            self.prnt[i + T] = i;
+        }
+        let mut i = 0;
+        for j in N_CHAR..=R {
+            self.freq[j] = self.freq[i] + self.freq[i + 1];
+            self.son[j] = i;
+            self.prnt[i] = j;
+            self.prnt[i + 1] = j;
+            i += 2;
+        }
+        self.freq[T] = 0xFFFF;
+        self.prnt[R] = 0;
+    }
+
+    /// Rebuild the tree with halved frequencies.
+    fn reconst(&mut self) {
+        // Collect the leaves in the first half of the table, halving their frequencies.
+        let mut j = 0;
+        for i in 0..T {
+            if self.son[i] >= T {
+                self.freq[j] = self.freq[i].div_ceil(2);
+                self.son[j] = self.son[i];
+                j += 1;
+            }
+        }
+
+        // Connect the nodes, keeping the table sorted by frequency.
+        let mut i = 0;
+        for j in N_CHAR..T {
+            let f = self.freq[i] + self.freq[i + 1];
+            let mut k = j - 1;
+            while f < self.freq[k] {
+                k -= 1;
+            }
+            k += 1;
+            self.freq.copy_within(k..j, k + 1);
+            self.freq[k] = f;
+            self.son.copy_within(k..j, k + 1);
+            self.son[k] = i;
+            i += 2;
+        }
+
+        for i in 0..T {
+            let k = self.son[i];
+            self.prnt[k] = i;
+            if k < T {
+                self.prnt[k + 1] = i;
+            }
+        }
+    }
+
+    /// Increment the frequency of a symbol, and swap nodes to keep the tree ordered by frequency.
+    fn update(&mut self, symbol: usize) {
+        if self.freq[R] == MAX_FREQ {
+            self.reconst();
+        }
+        let mut c = self.prnt[symbol + T];
+        loop {
+            self.freq[c] += 1;
+            let k = self.freq[c];
+
+            // If the order is disturbed, exchange the node with the last node of lower frequency.
+            let mut l = c + 1;
+            if k > self.freq[l] {
+                while k > self.freq[l + 1] {
+                    l += 1;
+                }
+                self.freq[c] = self.freq[l];
+                self.freq[l] = k;
+
+                let i = self.son[c];
+                self.prnt[i] = l;
+                if i < T {
+                    self.prnt[i + 1] = l;
+                }
+                let j = self.son[l];
+                self.son[l] = i;
+                self.prnt[j] = c;
+                if j < T {
+                    self.prnt[j + 1] = c;
+                }
+                self.son[c] = j;
+                c = l;
+            }
+
+            c = self.prnt[c];
+            if c == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Return whether more bits have been consumed than the input holds.
+    fn exhausted(&self) -> bool {
+        self.pos * 8 - self.bit_len as usize > self.input.len() * 8
+    }
+
+    /// Fill the bit buffer so that it holds more than 8 bits. Past the end of the input, zeroes
+    /// are read.
+    fn fill(&mut self) {
+        while self.bit_len <= 8 {
+            let byte = self.input.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.bit_buf |= (byte as u16) << (8 - self.bit_len);
+            self.bit_len += 8;
+        }
+    }
+
+    fn get_bit(&mut self) -> usize {
+        self.fill();
+        let bit = self.bit_buf >> 15;
+        self.bit_buf <<= 1;
+        self.bit_len -= 1;
+        bit as usize
+    }
+
+    fn get_byte(&mut self) -> usize {
+        self.fill();
+        let byte = self.bit_buf >> 8;
+        self.bit_buf <<= 8;
+        self.bit_len -= 8;
+        byte as usize
+    }
+
+    fn decode_char(&mut self) -> usize {
+        // Walk the tree from the root to a leaf.
+        let mut c = self.son[R];
+        while c < T {
+            c += self.get_bit();
+            c = self.son[c];
+        }
+        let symbol = c - T;
+        self.update(symbol);
+        symbol
+    }
+
+    fn decode_position(&mut self) -> usize {
+        // The upper 6 bits are coded by the fixed code, and the lower 6 bits follow verbatim.
+        let mut i = self.get_byte();
+        let upper = (self.d_code[i] as usize) << 6;
+        for _ in 0..self.d_len[i] - 2 {
+            i = (i << 1) + self.get_bit();
+        }
+        upper | (i & 0x3F)
+    }
+}
+
+/// Decompress LZHUF compressed data, until the end of the input or until `limit` bytes have been
+/// produced.
+pub fn decompress(input: &[u8], limit: usize) -> Vec<u8> {
+    let mut decoder = LzhufDecoder::new(input);
+    let mut text_buf = [b' '; N];
+    let mut r = N - F;
+    let mut output = Vec::new();
+
+    while output.len() < limit {
+        let c = decoder.decode_char();
+        if decoder.exhausted() {
+            break;
+        }
+        if c < 256 {
+            output.push(c as u8);
+            text_buf[r] = c as u8;
+            r = (r + 1) & (N - 1);
+        }
+        else {
+            let start = (r + N - decoder.decode_position() - 1) & (N - 1);
+            if decoder.exhausted() {
+                break;
+            }
+            for k in 0..(c - 255 + THRESHOLD) {
+                let byte = text_buf[(start + k) & (N - 1)];
+                output.push(byte);
+                text_buf[r] = byte;
+                r = (r + 1) & (N - 1);
+            }
+        }
+    }
+    output.truncate(limit);
+    output
+}
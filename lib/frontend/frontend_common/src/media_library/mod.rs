@@ -0,0 +1,545 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+    frontend_common::media_library::mod.rs
+
+    Describes the disk images found in the 'floppy' and 'hdd' resource
+    directories, for the media library browser.
+
+    Each image is inspected for a DOS boot sector, from which the filesystem,
+    volume label and boot information are read. Raw sector images are read
+    directly, and VHDs through the first partition in their master boot
+    record. Other image formats would need to be decoded in full, so they are
+    listed by name and size only - except for the comment that TeleDisk and
+    ImageDisk images carry ahead of their track data, which is read for display
+    and search.
+
+    Favorite images are kept in a small file in the 'media_library' resource
+    directory, so they persist between sessions.
+*/
+
+mod lzhuf;
+
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Error;
+use serde_derive::{Deserialize, Serialize};
+
+const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+const DIR_ATTR_VOLUME_LABEL: u8 = 0x08;
+const DIR_ATTR_LONG_NAME: u8 = 0x0F;
+const PARTITION_TABLE_OFFSET: usize = 0x1BE;
+const PARTITION_ENTRY_SIZE: usize = 16;
+/// FAT12 volumes have fewer than this many clusters.
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+const TD0_HEADER_SIZE: usize = 12;
+const TD0_COMMENT_HEADER_SIZE: usize = 10;
+/// Set in the stepping byte of a TD0 header when a comment block follows it.
+const TD0_COMMENT_FLAG: u8 = 0x80;
+const IMD_COMMENT_END: u8 = 0x1A;
+/// The largest TD0 comment block. A TD0 comment's length is a 16-bit value.
+const TD0_COMMENT_MAX: usize = TD0_COMMENT_HEADER_SIZE + u16::MAX as usize;
+/// The most of an image file read to find its comment. This allows for a compressed comment block
+/// being larger than the comment itself.
+const COMMENT_READ_LIMIT: u64 = (TD0_HEADER_SIZE + 2 * TD0_COMMENT_MAX) as u64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Floppy,
+    HardDisk,
+}
+
+/// What could be learned about a volume from its boot sector and root directory.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BootSectorInfo {
+    /// The OEM name in the boot sector, usually naming the system that formatted the volume.
+    pub oem_name: String,
+    /// "FAT12", "FAT16" or "FAT32".
+    pub filesystem: String,
+    pub volume_label: Option<String>,
+    pub media_descriptor: u8,
+    pub total_sectors: u32,
+    /// The sector ends with the 55AA boot signature.
+    pub bootable: bool,
+}
+
+/// A disk image in the media library.
+#[derive(Clone, Debug)]
+pub struct MediaLibraryEntry {
+    pub kind: MediaKind,
+    /// The index of the image in the floppy or VHD manager, used to mount it.
+    pub idx: usize,
+    pub path: PathBuf,
+    pub name: String,
+    /// The image format, from the file extension.
+    pub format: String,
+    pub size: u64,
+    /// The file is read-only on the host, so the image is write-protected.
+    pub read_only: bool,
+    pub boot_sector: Option<BootSectorInfo>,
+    /// The comment stored in a TeleDisk or ImageDisk image.
+    pub comment: Option<String>,
+    pub favorite: bool,
+}
+
+impl MediaLibraryEntry {
+    /// Describe the disk image at the specified path. An image that can't be read is still listed,
+    /// with what the file system reports about it.
+    pub fn from_path(kind: MediaKind, idx: usize, path: &Path) -> Self {
+        let metadata = path.metadata().ok();
+        let format = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_uppercase())
+            .unwrap_or_default();
+
+        let boot_sector = File::open(path).ok().and_then(|mut file| match kind {
+            MediaKind::Floppy => read_volume(&mut file, 0),
+            MediaKind::HardDisk => read_partitioned_volume(&mut file),
+        });
+        let comment = match kind {
+            MediaKind::Floppy => read_image_comment(path),
+            MediaKind::HardDisk => None,
+        };
+
+        Self {
+            kind,
+            idx,
+            path: path.to_path_buf(),
+            name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            format,
+            size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+            read_only: metadata
+                .as_ref()
+                .is_some_and(|metadata| metadata.permissions().readonly()),
+            boot_sector,
+            comment,
+            favorite: false,
+        }
+    }
+
+    /// Return true if every word of the search text appears, ignoring case, in the image's name,
+    /// format, filesystem, volume label, OEM name or comment.
+    pub fn matches(&self, search: &str) -> bool {
+        let mut text = format!("{} {}", self.name, self.format);
+        if let Some(boot_sector) = &self.boot_sector {
+            text.push_str(&format!(
+                " {} {} {}",
+                boot_sector.filesystem,
+                boot_sector.volume_label.as_deref().unwrap_or_default(),
+                boot_sector.oem_name
+            ));
+        }
+        if let Some(comment) = &self.comment {
+            text.push(' ');
+            text.push_str(comment);
+        }
+        let text = text.to_lowercase();
+        search
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+}
+
+/// The favorite images in the media library, persisted between sessions.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MediaFavorites {
+    #[serde(default)]
+    pub favorite: BTreeSet<PathBuf>,
+}
+
+impl MediaFavorites {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.favorite.contains(path)
+    }
+
+    pub fn set(&mut self, path: &Path, favorite: bool) {
+        match favorite {
+            true => self.favorite.insert(path.to_path_buf()),
+            false => self.favorite.remove(path),
+        };
+    }
+}
+
+fn word(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn dword(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Convert a space-padded name from a boot sector or directory entry to a string, or None if it's
+/// blank or DOS's placeholder for an unnamed volume.
+fn padded_name(bytes: &[u8]) -> Option<String> {
+    let name = String::from_utf8_lossy(bytes).trim_end().to_string();
+    match name.is_empty() || name == "NO NAME" {
+        true => None,
+        false => Some(name),
+    }
+}
+
+/// Parse a DOS boot sector. Returns None if the sector doesn't hold a plausible BIOS Parameter
+/// Block, as on DOS 1.x disks or disks that aren't FAT formatted.
+pub fn parse_boot_sector(sector: &[u8]) -> Option<BootSectorInfo> {
+    if sector.len() < SECTOR_SIZE || !matches!(sector[0], 0xEB | 0xE9) {
+        return None;
+    }
+
+    let bytes_per_sector = word(sector, 0x0B) as u32;
+    let sectors_per_cluster = sector[0x0D] as u32;
+    let reserved_sectors = word(sector, 0x0E) as u32;
+    let fat_ct = sector[0x10] as u32;
+    let root_entries = word(sector, 0x11) as u32;
+    let media_descriptor = sector[0x15];
+    let sectors_per_fat = word(sector, 0x16) as u32;
+    let total_sectors = match word(sector, 0x13) {
+        0 => dword(sector, 0x20),
+        sectors => sectors as u32,
+    };
+
+    if !(128..=4096).contains(&bytes_per_sector)
+        || !bytes_per_sector.is_power_of_two()
+        || !sectors_per_cluster.is_power_of_two()
+        || reserved_sectors == 0
+        || !(1..=2).contains(&fat_ct)
+        || media_descriptor < 0xF0
+        || total_sectors == 0
+    {
+        return None;
+    }
+
+    // FAT32 volumes have no sectors per FAT in the original BPB, and a longer extended BPB.
+    let (filesystem, ext_offset) = if sectors_per_fat == 0 {
+        ("FAT32".to_string(), 0x40)
+    }
+    else {
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u32).div_ceil(bytes_per_sector);
+        let data_sectors = total_sectors.saturating_sub(reserved_sectors + fat_ct * sectors_per_fat + root_sectors);
+        match data_sectors / sectors_per_cluster < FAT12_MAX_CLUSTERS {
+            true => ("FAT12".to_string(), 0x24),
+            false => ("FAT16".to_string(), 0x24),
+        }
+    };
+
+    // The extended boot signature, from DOS 4.0 on, marks an extended BPB holding a volume label.
+    let volume_label = match sector[ext_offset + 2] {
+        0x29 => padded_name(&sector[ext_offset + 7..ext_offset + 18]),
+        _ => None,
+    };
+
+    Some(BootSectorInfo {
+        oem_name: String::from_utf8_lossy(&sector[0x03..0x0B]).trim_end().to_string(),
+        filesystem,
+        volume_label,
+        media_descriptor,
+        total_sectors,
+        bootable: sector[510..512] == [0x55, 0xAA],
+    })
+}
+
+/// Find the volume label in a FAT root directory. DOS's LABEL command writes it here, and older
+/// versions of DOS keep it nowhere else.
+pub fn find_root_dir_label(root_dir: &[u8]) -> Option<String> {
+    for entry in root_dir.chunks_exact(DIR_ENTRY_SIZE) {
+        match entry[0] {
+            // The end of the directory.
+            0x00 => break,
+            // A deleted entry.
+            0xE5 => continue,
+            _ => {}
+        }
+        let attributes = entry[0x0B];
+        if attributes & DIR_ATTR_VOLUME_LABEL != 0 && attributes != DIR_ATTR_LONG_NAME {
+            return padded_name(&entry[0..11]);
+        }
+    }
+    None
+}
+
+/// Read the boot sector of the volume starting at the specified offset in the image, and the root
+/// directory's volume label, which takes precedence over the boot sector's.
+fn read_volume(file: &mut File, offset: u64) -> Option<BootSectorInfo> {
+    let mut sector = vec![0u8; SECTOR_SIZE];
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    file.read_exact(&mut sector).ok()?;
+    let mut info = parse_boot_sector(&sector)?;
+
+    // FAT32 keeps its root directory in a cluster chain, so only the boot sector label is used.
+    if info.filesystem != "FAT32" {
+        let bytes_per_sector = word(&sector, 0x0B) as u64;
+        let root_offset =
+            offset + (word(&sector, 0x0E) as u64 + sector[0x10] as u64 * word(&sector, 0x16) as u64) * bytes_per_sector;
+        let mut root_dir = vec![0u8; word(&sector, 0x11) as usize * DIR_ENTRY_SIZE];
+        if file.seek(SeekFrom::Start(root_offset)).is_ok() && file.read_exact(&mut root_dir).is_ok() {
+            if let Some(label) = find_root_dir_label(&root_dir) {
+                info.volume_label = Some(label);
+            }
+        }
+    }
+    Some(info)
+}
+
+/// Parse the comment at the start of a TeleDisk (TD0) or ImageDisk (IMD) image, identified by its
+/// signature. Returns None for other images and images without a comment.
+pub fn parse_image_comment(data: &[u8]) -> Option<String> {
+    let text = if data.starts_with(b"TD") || data.starts_with(b"td") {
+        if data.len() < TD0_HEADER_SIZE || data[7] & TD0_COMMENT_FLAG == 0 {
+            return None;
+        }
+        // A "td" signature marks "advanced" compression, which compresses everything following the
+        // header, including the comment block.
+        let block = match data[0] {
+            b't' => Cow::Owned(lzhuf::decompress(&data[TD0_HEADER_SIZE..], TD0_COMMENT_MAX)),
+            _ => Cow::Borrowed(&data[TD0_HEADER_SIZE..]),
+        };
+        if block.len() < TD0_COMMENT_HEADER_SIZE {
+            return None;
+        }
+        // The comment header holds a CRC, the length of the text, and the date it was written.
+        let len = word(&block, 2) as usize;
+        let text = block.get(TD0_COMMENT_HEADER_SIZE..TD0_COMMENT_HEADER_SIZE + len)?;
+        // Lines of the comment are NUL terminated.
+        text.split(|&b| b == 0)
+            .map(|line| String::from_utf8_lossy(line).trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+    else if data.starts_with(b"IMD ") {
+        // The comment follows the signature line, which holds the version and creation date.
+        let end = data.iter().position(|&b| b == IMD_COMMENT_END)?;
+        let start = data[..end].iter().position(|&b| b == b'\n')? + 1;
+        String::from_utf8_lossy(&data[start..end]).replace("\r\n", "\n")
+    }
+    else {
+        return None;
+    };
+
+    let text = text.trim();
+    match text.is_empty() {
+        true => None,
+        false => Some(text.to_string()),
+    }
+}
+
+/// Read the comment of the TD0 or IMD image at the specified path.
+fn read_image_comment(path: &Path) -> Option<String> {
+    let mut data = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(COMMENT_READ_LIMIT)
+        .read_to_end(&mut data)
+        .ok()?;
+    parse_image_comment(&data)
+}
+
+/// Read the volume in the first partition of a hard disk image with a master boot record.
+fn read_partitioned_volume(file: &mut File) -> Option<BootSectorInfo> {
+    let mut mbr = vec![0u8; SECTOR_SIZE];
+    file.read_exact(&mut mbr).ok()?;
+    if mbr[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+    mbr[PARTITION_TABLE_OFFSET..PARTITION_TABLE_OFFSET + 4 * PARTITION_ENTRY_SIZE]
+        .chunks_exact(PARTITION_ENTRY_SIZE)
+        .find(|entry| entry[4] != 0)
+        .and_then(|entry| read_volume(file, dword(entry, 8) as u64 * SECTOR_SIZE as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the boot sector of a DOS 3.3 formatted 360K floppy.
+    fn boot_sector_360k() -> Vec<u8> {
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        sector[0..3].copy_from_slice(&[0xEB, 0x34, 0x90]);
+        sector[0x03..0x0B].copy_from_slice(b"IBM  3.3");
+        sector[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        sector[0x0D] = 2;
+        sector[0x0E..0x10].copy_from_slice(&1u16.to_le_bytes());
+        sector[0x10] = 2;
+        sector[0x11..0x13].copy_from_slice(&112u16.to_le_bytes());
+        sector[0x13..0x15].copy_from_slice(&720u16.to_le_bytes());
+        sector[0x15] = 0xFD;
+        sector[0x16..0x18].copy_from_slice(&2u16.to_le_bytes());
+        sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+        sector
+    }
+
+    #[test]
+    fn test_parse_boot_sector() {
+        let mut sector = boot_sector_360k();
+        let info = parse_boot_sector(&sector).unwrap();
+        assert_eq!(info.oem_name, "IBM  3.3");
+        assert_eq!(info.filesystem, "FAT12");
+        assert_eq!(info.volume_label, None);
+        assert_eq!((info.media_descriptor, info.total_sectors), (0xFD, 720));
+        assert!(info.bootable);
+
+        // A DOS 4.0 extended BPB.
+        sector[0x26] = 0x29;
+        sector[0x2B..0x36].copy_from_slice(b"GAMES      ");
+        assert_eq!(
+            parse_boot_sector(&sector).unwrap().volume_label.as_deref(),
+            Some("GAMES")
+        );
+        sector[0x2B..0x36].copy_from_slice(b"NO NAME    ");
+        assert_eq!(parse_boot_sector(&sector).unwrap().volume_label, None);
+
+        // A 32MB hard disk partition has too many clusters for FAT12.
+        sector[0x13..0x15].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        sector[0x15] = 0xF8;
+        sector[0x16..0x18].copy_from_slice(&64u16.to_le_bytes());
+        assert_eq!(parse_boot_sector(&sector).unwrap().filesystem, "FAT16");
+
+        // DOS 1.x disks have no BPB.
+        sector[0x0B..0x0D].copy_from_slice(&0u16.to_le_bytes());
+        assert!(parse_boot_sector(&sector).is_none());
+        assert!(parse_boot_sector(&[0u8; SECTOR_SIZE]).is_none());
+    }
+
+    #[test]
+    fn test_find_root_dir_label() {
+        let mut root_dir = vec![0u8; DIR_ENTRY_SIZE * 4];
+        root_dir[0..11].copy_from_slice(b"COMMAND COM");
+        root_dir[0x0B] = 0x20;
+        // A deleted label, then a long file name entry, which also has the volume label bit set.
+        root_dir[32..43].copy_from_slice(b"\xE5LD LABEL  ");
+        root_dir[32 + 0x0B] = DIR_ATTR_VOLUME_LABEL;
+        root_dir[64..75].copy_from_slice(b"Along name ");
+        root_dir[64 + 0x0B] = DIR_ATTR_LONG_NAME;
+        assert_eq!(find_root_dir_label(&root_dir), None);
+
+        root_dir[96..107].copy_from_slice(b"DISK 1     ");
+        root_dir[96 + 0x0B] = DIR_ATTR_VOLUME_LABEL;
+        assert_eq!(find_root_dir_label(&root_dir).as_deref(), Some("DISK 1"));
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut entry = MediaLibraryEntry {
+            kind: MediaKind::Floppy,
+            idx: 0,
+            path: PathBuf::from("Games/KQ1.img"),
+            name: "KQ1.img".to_string(),
+            format: "IMG".to_string(),
+            size: 368640,
+            read_only: false,
+            boot_sector: None,
+            comment: None,
+            favorite: false,
+        };
+        assert!(entry.matches(""));
+        assert!(entry.matches("kq1"));
+        assert!(!entry.matches("kq1 fat12"));
+
+        entry.boot_sector = parse_boot_sector(&boot_sector_360k());
+        assert!(entry.matches("kq1 fat12"));
+        assert!(entry.matches("IBM"));
+
+        entry.comment = Some("King's Quest\nDisk 1 of 2".to_string());
+        assert!(entry.matches("kq1 disk"));
+    }
+
+    #[test]
+    fn test_parse_td0_comment() {
+        let text = b"Norton Utilities 4.5\0Disk 2\0";
+        let mut data = vec![0u8; TD0_HEADER_SIZE + TD0_COMMENT_HEADER_SIZE];
+        data[0..2].copy_from_slice(b"TD");
+        data[7] = TD0_COMMENT_FLAG;
+        data[14..16].copy_from_slice(&(text.len() as u16).to_le_bytes());
+        data.extend_from_slice(text);
+        // The first track header.
+        data.extend_from_slice(&[9, 0, 0, 0xA5]);
+        assert_eq!(
+            parse_image_comment(&data).as_deref(),
+            Some("Norton Utilities 4.5\nDisk 2")
+        );
+
+        // A truncated comment.
+        assert_eq!(parse_image_comment(&data[..30]), None);
+
+        // Without the comment flag, the track data follows the header.
+        data[7] = 0;
+        assert_eq!(parse_image_comment(&data), None);
+    }
+
+    #[test]
+    fn test_parse_td0_compressed_comment() {
+        // An image using advanced compression. The comment block and first track header of the
+        // image above are compressed following the header.
+        let mut data = vec![0u8; TD0_HEADER_SIZE];
+        data[0..2].copy_from_slice(b"td");
+        data[7] = TD0_COMMENT_FLAG;
+        data.extend_from_slice(&[
+            0xC6, 0x62, 0xEA, 0x30, 0xC7, 0x80, 0x3B, 0x5F, 0xBF, 0xF0, 0x05, 0xFF, 0xEB, 0x59, 0xE1, 0xBD, 0xFA, 0xFE,
+            0x2E, 0x6D, 0xD9, 0xF8, 0xFF, 0xEE, 0xF8, 0x1B, 0xAE, 0x0E, 0x1E, 0x85, 0xAB, 0x2F, 0xBD, 0x5D, 0xF5, 0x39,
+            0x5A, 0x69, 0x31,
+        ]);
+        assert_eq!(
+            parse_image_comment(&data).as_deref(),
+            Some("Norton Utilities 4.5\nDisk 2")
+        );
+
+        // A truncated comment.
+        assert_eq!(parse_image_comment(&data[..TD0_HEADER_SIZE + 12]), None);
+
+        // Without the comment flag, the compressed data is track data.
+        data[7] = 0;
+        assert_eq!(parse_image_comment(&data), None);
+    }
+
+    #[test]
+    fn test_parse_imd_comment() {
+        let mut data = b"IMD 1.18: 12/03/2021 10:15:00\r\nCP/M 2.2 boot disk\r\nKaypro II\r\n\x1A".to_vec();
+        data.extend_from_slice(&[0, 0, 0, 10, 2]);
+        assert_eq!(
+            parse_image_comment(&data).as_deref(),
+            Some("CP/M 2.2 boot disk\nKaypro II")
+        );
+
+        // An empty comment, and a header missing its terminator.
+        assert_eq!(parse_image_comment(b"IMD 1.18: 12/03/2021 10:15:00\r\n\x1A"), None);
+        assert_eq!(parse_image_comment(b"IMD 1.18: 12/03/2021 10:15:00\r\nCP/M"), None);
+        assert_eq!(parse_image_comment(&[0u8; 64]), None);
+    }
+}
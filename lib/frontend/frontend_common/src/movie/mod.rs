@@ -0,0 +1,552 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+    frontend_common::movie::mod.rs
+
+    Record and replay every input sent to the machine, timed by CPU cycle.
+
+    A movie begins at an anchor - either a machine reset or a save state -
+    and each input is stamped with the number of CPU cycles elapsed since
+    the anchor. While a movie is recorded or played, the machine is run in
+    slices that end at multiples of MOVIE_QUANTUM cycles from the anchor.
+    As the machine is deterministic, slices end on the same cycles during
+    playback as during recording, so every input can be delivered at
+    exactly the cycle it was originally sent.
+
+    Movies are stored as plain text, one input per line:
+
+        <cycle> keydown <key> <modifiers>
+        <cycle> keyup <key>
+        <cycle> ctrlaltdel
+        <cycle> mouse <lrm buttons> <delta x> <delta y>
+        <cycle> gameport <buttons> <x0> <y0> <x1> <y1>
+        <cycle> turbo <on|off>
+        <cycle> floppy <drive> <path>
+        <cycle> eject <drive>
+
+    preceded by 'config <hash>' and 'anchor <reset|state>' lines. A state
+    anchor is stored next to the movie, with the extension 'mss'.
+
+    A reset does not reset the battery-backed clock of a machine with an RTC,
+    and the guest reads the time and CMOS RAM while it boots. So that it boots
+    the same way during playback, the state of the RTC at the reset is stored
+    in hex on the anchor line, as 'anchor reset <state>'.
+*/
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Error};
+use marty_core::{devices::keyboard::KeyboardModifiers, keys::MartyKey, machine::MachineInput};
+
+/// Movie slices end on multiples of this many cycles from the anchor.
+pub const MOVIE_QUANTUM: u64 = 1000;
+
+/// The state of the machine at the start of a movie.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieAnchor {
+    /// The movie starts from a machine reset. Holds the state of the machine's RTC at the reset,
+    /// if it has one.
+    Reset(Option<Vec<u8>>),
+    /// The movie starts from a save state.
+    State(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieInput {
+    Machine(MachineInput),
+    LoadFloppy(usize, PathBuf),
+    EjectFloppy(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovieEvent {
+    /// CPU cycles elapsed since the anchor.
+    pub cycle: u64,
+    pub input: MovieInput,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    /// Hash of the machine configuration the movie was recorded with.
+    pub config_hash: String,
+    pub anchor: MovieAnchor,
+    pub events: Vec<MovieEvent>,
+}
+
+fn parse_bits<const N: usize>(field: &str) -> Option<[bool; N]> {
+    let mut bits = [false; N];
+    if field.len() != N {
+        return None;
+    }
+    for (bit, c) in bits.iter_mut().zip(field.chars()) {
+        *bit = match c {
+            '0' => false,
+            '1' => true,
+            _ => return None,
+        };
+    }
+    Some(bits)
+}
+
+fn bits_to_string(bits: &[bool]) -> String {
+    bits.iter()
+        .map(|&bit| {
+            if bit {
+                '1'
+            }
+            else {
+                '0'
+            }
+        })
+        .collect()
+}
+
+fn parse_hex(field: &str) -> Option<Vec<u8>> {
+    if field.len() % 2 != 0 {
+        return None;
+    }
+    (0..field.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(field.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_modifiers(field: &str) -> KeyboardModifiers {
+    KeyboardModifiers {
+        control: field.contains('c'),
+        alt: field.contains('a'),
+        shift: field.contains('s'),
+        meta: field.contains('m'),
+    }
+}
+
+fn modifiers_to_string(modifiers: &KeyboardModifiers) -> String {
+    let text: String = [
+        (modifiers.control, 'c'),
+        (modifiers.alt, 'a'),
+        (modifiers.shift, 's'),
+        (modifiers.meta, 'm'),
+    ]
+    .iter()
+    .filter_map(|&(set, c)| set.then_some(c))
+    .collect();
+    match text.is_empty() {
+        true => "-".to_string(),
+        false => text,
+    }
+}
+
+impl MovieInput {
+    fn parse(fields: &[&str], rest: &str) -> Result<Self, Error> {
+        let float = |i: usize| -> Result<f64, Error> {
+            fields
+                .get(i)
+                .and_then(|f| f.parse::<f64>().ok())
+                .ok_or(anyhow!("expected a number"))
+        };
+        let key = |i: usize| -> Result<MartyKey, Error> {
+            let name = fields.get(i).ok_or(anyhow!("expected a key"))?;
+            MartyKey::from_str(name).map_err(|_| anyhow!("unknown key '{}'", name))
+        };
+        let drive = || -> Result<usize, Error> {
+            fields
+                .get(1)
+                .and_then(|f| f.parse::<usize>().ok())
+                .ok_or(anyhow!("expected a drive number"))
+        };
+
+        let input = match fields[0] {
+            "keydown" => MovieInput::Machine(MachineInput::KeyPress(
+                key(1)?,
+                parse_modifiers(fields.get(2).copied().unwrap_or("-")),
+            )),
+            "keyup" => MovieInput::Machine(MachineInput::KeyRelease(key(1)?)),
+            "ctrlaltdel" => MovieInput::Machine(MachineInput::CtrlAltDel),
+            "mouse" => {
+                let [l_button, r_button, m_button] = fields
+                    .get(1)
+                    .and_then(|f| parse_bits::<3>(f))
+                    .ok_or(anyhow!("expected mouse buttons"))?;
+                MovieInput::Machine(MachineInput::Mouse {
+                    l_button,
+                    r_button,
+                    m_button,
+                    delta_x: float(2)?,
+                    delta_y: float(3)?,
+                })
+            }
+            "gameport" => MovieInput::Machine(MachineInput::GamePort {
+                buttons: fields
+                    .get(1)
+                    .and_then(|f| parse_bits::<4>(f))
+                    .ok_or(anyhow!("expected game port buttons"))?,
+                sticks:  [(float(2)?, float(3)?), (float(4)?, float(5)?)],
+            }),
+            "turbo" => match fields.get(1).copied() {
+                Some("on") => MovieInput::Machine(MachineInput::Turbo(true)),
+                Some("off") => MovieInput::Machine(MachineInput::Turbo(false)),
+                _ => return Err(anyhow!("expected 'on' or 'off'")),
+            },
+            "floppy" => {
+                let drive = drive()?;
+                // The path is the remainder of the line, as it may contain spaces.
+                let path = rest.trim_start()[fields[1].len()..].trim();
+                if path.is_empty() {
+                    return Err(anyhow!("expected a path"));
+                }
+                MovieInput::LoadFloppy(drive, PathBuf::from(path))
+            }
+            "eject" => MovieInput::EjectFloppy(drive()?),
+            other => return Err(anyhow!("unknown input '{}'", other)),
+        };
+        Ok(input)
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            MovieInput::Machine(input) => match input {
+                MachineInput::KeyPress(key, modifiers) => {
+                    format!("keydown {:?} {}", key, modifiers_to_string(modifiers))
+                }
+                MachineInput::KeyRelease(key) => format!("keyup {:?}", key),
+                MachineInput::CtrlAltDel => "ctrlaltdel".to_string(),
+                MachineInput::Mouse {
+                    l_button,
+                    r_button,
+                    m_button,
+                    delta_x,
+                    delta_y,
+                } => format!(
+                    "mouse {} {} {}",
+                    bits_to_string(&[*l_button, *r_button, *m_button]),
+                    delta_x,
+                    delta_y
+                ),
+                MachineInput::GamePort { buttons, sticks } => format!(
+                    "gameport {} {} {} {} {}",
+                    bits_to_string(buttons),
+                    sticks[0].0,
+                    sticks[0].1,
+                    sticks[1].0,
+                    sticks[1].1
+                ),
+                MachineInput::Turbo(state) => format!("turbo {}", if *state { "on" } else { "off" }),
+            },
+            MovieInput::LoadFloppy(drive, path) => format!("floppy {} {}", drive, path.display()),
+            MovieInput::EjectFloppy(drive) => format!("eject {}", drive),
+        }
+    }
+}
+
+impl Movie {
+    /// Parse the text of a movie. A state anchor is returned empty, as its state is stored
+    /// separately; see load().
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut config_hash = None;
+        let mut anchor = None;
+        let mut events = Vec::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[0] {
+                "config" => {
+                    config_hash = fields.get(1).map(|hash| hash.to_string());
+                }
+                "anchor" => {
+                    anchor = match fields.get(1).copied() {
+                        Some("reset") => {
+                            let rtc = match fields.get(2) {
+                                Some(hex) => {
+                                    Some(parse_hex(hex).ok_or(anyhow!("Line {}: invalid RTC state", line_no + 1))?)
+                                }
+                                None => None,
+                            };
+                            Some(MovieAnchor::Reset(rtc))
+                        }
+                        Some("state") => Some(MovieAnchor::State(Vec::new())),
+                        _ => return Err(anyhow!("Line {}: expected 'anchor <reset|state>'", line_no + 1)),
+                    };
+                }
+                cycle => {
+                    let cycle = cycle
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("Line {}: invalid cycle '{}'", line_no + 1, cycle))?;
+                    if fields.len() < 2 {
+                        return Err(anyhow!("Line {}: expected an input", line_no + 1));
+                    }
+                    if events.last().map_or(false, |last: &MovieEvent| last.cycle > cycle) {
+                        return Err(anyhow!("Line {}: inputs are out of order", line_no + 1));
+                    }
+                    let rest = &line[fields[0].len()..].trim_start()[fields[1].len()..];
+                    let input =
+                        MovieInput::parse(&fields[1..], rest).map_err(|e| anyhow!("Line {}: {}", line_no + 1, e))?;
+                    events.push(MovieEvent { cycle, input });
+                }
+            }
+        }
+
+        Ok(Self {
+            config_hash: config_hash.ok_or(anyhow!("Missing 'config' line"))?,
+            anchor: anchor.ok_or(anyhow!("Missing 'anchor' line"))?,
+            events,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# MartyPC movie\n");
+        text.push_str(&format!("config {}\n", self.config_hash));
+        match &self.anchor {
+            MovieAnchor::Reset(None) => text.push_str("anchor reset\n"),
+            MovieAnchor::Reset(Some(rtc)) => text.push_str(&format!("anchor reset {}\n", to_hex(rtc))),
+            MovieAnchor::State(_) => text.push_str("anchor state\n"),
+        }
+        for event in self.events.iter() {
+            text.push_str(&format!("{} {}\n", event.cycle, event.input.to_text()));
+        }
+        text
+    }
+
+    /// Load a movie and, for a movie anchored by a save state, the state stored next to it.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut movie = Self::parse(&std::fs::read_to_string(path)?)?;
+        if let MovieAnchor::State(state) = &mut movie.anchor {
+            *state = std::fs::read(path.with_extension("mss"))?;
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_text())?;
+        if let MovieAnchor::State(state) = &self.anchor {
+            std::fs::write(path.with_extension("mss"), state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the number of cycles to run the machine for so that it stops at the next multiple of
+/// MOVIE_QUANTUM cycles after the anchor.
+pub fn movie_slice(cycles_since_anchor: u64) -> u32 {
+    (MOVIE_QUANTUM - cycles_since_anchor % MOVIE_QUANTUM) as u32
+}
+
+/// Records the inputs sent to the machine.
+pub struct MovieRecorder {
+    movie: Movie,
+    anchor_cycle: u64,
+}
+
+impl MovieRecorder {
+    /// Start recording. `anchor_cycle` is the machine's CPU cycle count at the anchor.
+    pub fn new(config_hash: String, anchor: MovieAnchor, anchor_cycle: u64) -> Self {
+        Self {
+            movie: Movie {
+                config_hash,
+                anchor,
+                events: Vec::new(),
+            },
+            anchor_cycle,
+        }
+    }
+
+    pub fn anchor_cycle(&self) -> u64 {
+        self.anchor_cycle
+    }
+
+    /// Record an input sent when the machine's CPU cycle count is `cycle`.
+    pub fn record(&mut self, cycle: u64, input: MovieInput) {
+        self.movie.events.push(MovieEvent {
+            cycle: cycle.saturating_sub(self.anchor_cycle),
+            input,
+        });
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Plays back the inputs of a movie as the machine runs.
+pub struct MoviePlayer {
+    events: Vec<MovieEvent>,
+    next: usize,
+    anchor_cycle: u64,
+    desynced: bool,
+}
+
+impl MoviePlayer {
+    /// Start playback. The machine must already be at the movie's anchor, with a CPU cycle
+    /// count of `anchor_cycle`.
+    pub fn new(movie: Movie, anchor_cycle: u64) -> Self {
+        Self {
+            events: movie.events,
+            next: 0,
+            anchor_cycle,
+            desynced: false,
+        }
+    }
+
+    pub fn anchor_cycle(&self) -> u64 {
+        self.anchor_cycle
+    }
+
+    /// Return the inputs due at the machine's current CPU cycle count, in order. Inputs whose
+    /// cycle has already passed are returned too, but mark the playback as desynchronized.
+    pub fn advance(&mut self, cycle: u64) -> Vec<MovieInput> {
+        let elapsed = cycle.saturating_sub(self.anchor_cycle);
+        let mut due = Vec::new();
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > elapsed {
+                break;
+            }
+            if event.cycle < elapsed {
+                self.desynced = true;
+            }
+            due.push(event.input.clone());
+            self.next += 1;
+        }
+        due
+    }
+
+    /// Whether an input was delivered later than the cycle it was recorded at.
+    pub fn is_desynced(&self) -> bool {
+        self.desynced
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(cycle: u64, input: MovieInput) -> MovieEvent {
+        MovieEvent { cycle, input }
+    }
+
+    fn sample_movie() -> Movie {
+        Movie {
+            config_hash: "0123456789abcdef".to_string(),
+            anchor: MovieAnchor::Reset(None),
+            events: vec![
+                event(
+                    0,
+                    MovieInput::Machine(MachineInput::KeyPress(
+                        MartyKey::KeyA,
+                        KeyboardModifiers {
+                            shift: true,
+                            ..Default::default()
+                        },
+                    )),
+                ),
+                event(1000, MovieInput::Machine(MachineInput::KeyRelease(MartyKey::KeyA))),
+                event(
+                    2003,
+                    MovieInput::Machine(MachineInput::Mouse {
+                        l_button: true,
+                        r_button: false,
+                        m_button: false,
+                        delta_x:  -1.5,
+                        delta_y:  0.1,
+                    }),
+                ),
+                event(
+                    2003,
+                    MovieInput::Machine(MachineInput::GamePort {
+                        buttons: [false, true, false, false],
+                        sticks:  [(0.25, -1.0), (0.0, 1.0 / 3.0)],
+                    }),
+                ),
+                event(4000, MovieInput::Machine(MachineInput::Turbo(true))),
+                event(5000, MovieInput::LoadFloppy(1, PathBuf::from("disks/my game.img"))),
+                event(6000, MovieInput::EjectFloppy(1)),
+                event(7000, MovieInput::Machine(MachineInput::CtrlAltDel)),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let movie = sample_movie();
+        assert_eq!(Movie::parse(&movie.to_text()).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_rtc_anchor() {
+        let mut movie = sample_movie();
+        movie.anchor = MovieAnchor::Reset(Some(vec![0x00, 0x26, 0xFF, 0x80]));
+        let text = movie.to_text();
+        assert!(text.contains("anchor reset 0026ff80\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+
+        assert!(Movie::parse("config 1234\nanchor reset 0026f\n").is_err());
+        assert!(Movie::parse("config 1234\nanchor reset 00zz\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Movie::parse("anchor reset\n").is_err());
+        assert!(Movie::parse("config 1234\nanchor later\n").is_err());
+        assert!(Movie::parse("config 1234\nanchor reset\n10 keydown NotAKey -\n").is_err());
+        assert!(Movie::parse("config 1234\nanchor reset\n10 mouse 12 0 0\n").is_err());
+        assert!(Movie::parse("config 1234\nanchor reset\n20 eject 0\n10 eject 0\n").is_err());
+        assert!(Movie::parse("config 1234\nanchor reset\n10 floppy 0\n").is_err());
+    }
+
+    #[test]
+    fn test_movie_slice() {
+        assert_eq!(movie_slice(0), 1000);
+        assert_eq!(movie_slice(999), 1);
+        assert_eq!(movie_slice(1003), 997);
+    }
+
+    #[test]
+    fn test_player() {
+        let mut player = MoviePlayer::new(sample_movie(), 500);
+        assert_eq!(player.advance(500).len(), 1);
+        assert!(player.advance(1499).is_empty());
+        assert_eq!(player.advance(1500).len(), 1);
+        assert_eq!(player.advance(2503).len(), 2);
+        assert!(!player.is_desynced());
+        assert_eq!(player.advance(4600).len(), 1);
+        assert!(player.is_desynced());
+        assert!(!player.is_finished());
+        assert_eq!(player.advance(10000).len(), 3);
+        assert!(player.is_finished());
+    }
+}
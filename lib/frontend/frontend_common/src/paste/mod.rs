@@ -0,0 +1,288 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::paste::mod.rs
+
+    Convert host text into guest keystrokes, so that text from the host
+    clipboard can be typed into the guest.
+
+    Keystrokes are generated for a US keyboard layout. Characters outside of
+    printable ASCII are translated to CP437 and typed with the BIOS Alt+keypad
+    method, so that box drawing and accented characters come through as well.
+    The result is a keyboard macro, played back like any other.
+*/
+
+use std::str::FromStr;
+
+use marty_core::keys::MartyKey;
+use web_time::Duration;
+
+use crate::{
+    keyboard_macro::{KeyboardMacro, MacroEvent},
+    text_export::CP437_TO_UNICODE,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PasteOptions {
+    /// The time between key events. Each character is held down for this long.
+    pub key_delay:  Duration,
+    /// Additional time to wait after each Enter, to give the guest time to process the line.
+    pub line_delay: Duration,
+}
+
+impl Default for PasteOptions {
+    fn default() -> Self {
+        Self {
+            key_delay:  Duration::from_millis(20),
+            line_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The keystrokes for a single character.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Keystroke {
+    Key(MartyKey),
+    Shifted(MartyKey),
+    /// Type a CP437 code with the Alt+keypad method.
+    AltCode(u8),
+}
+
+/// Return the key that types an ASCII character on a US keyboard.
+fn ascii_keystroke(c: char) -> Option<Keystroke> {
+    let unshifted = |key| Some(Keystroke::Key(key));
+    let shifted = |key| Some(Keystroke::Shifted(key));
+
+    if c.is_ascii_alphabetic() {
+        let key = named_key("Key", c.to_ascii_uppercase());
+        return match c.is_ascii_uppercase() {
+            true => shifted(key),
+            false => unshifted(key),
+        };
+    }
+    if c.is_ascii_digit() {
+        return unshifted(named_key("Digit", c));
+    }
+
+    match c {
+        ' ' => unshifted(MartyKey::Space),
+        '\t' => unshifted(MartyKey::Tab),
+        '`' => unshifted(MartyKey::Backquote),
+        '~' => shifted(MartyKey::Backquote),
+        '!' => shifted(MartyKey::Digit1),
+        '@' => shifted(MartyKey::Digit2),
+        '#' => shifted(MartyKey::Digit3),
+        '$' => shifted(MartyKey::Digit4),
+        '%' => shifted(MartyKey::Digit5),
+        '^' => shifted(MartyKey::Digit6),
+        '&' => shifted(MartyKey::Digit7),
+        '*' => shifted(MartyKey::Digit8),
+        '(' => shifted(MartyKey::Digit9),
+        ')' => shifted(MartyKey::Digit0),
+        '-' => unshifted(MartyKey::Minus),
+        '_' => shifted(MartyKey::Minus),
+        '=' => unshifted(MartyKey::Equal),
+        '+' => shifted(MartyKey::Equal),
+        '[' => unshifted(MartyKey::BracketLeft),
+        '{' => shifted(MartyKey::BracketLeft),
+        ']' => unshifted(MartyKey::BracketRight),
+        '}' => shifted(MartyKey::BracketRight),
+        '\\' => unshifted(MartyKey::Backslash),
+        '|' => shifted(MartyKey::Backslash),
+        ';' => unshifted(MartyKey::Semicolon),
+        ':' => shifted(MartyKey::Semicolon),
+        '\'' => unshifted(MartyKey::Quote),
+        '"' => shifted(MartyKey::Quote),
+        ',' => unshifted(MartyKey::Comma),
+        '<' => shifted(MartyKey::Comma),
+        '.' => unshifted(MartyKey::Period),
+        '>' => shifted(MartyKey::Period),
+        '/' => unshifted(MartyKey::Slash),
+        '?' => shifted(MartyKey::Slash),
+        _ => None,
+    }
+}
+
+/// Return the key named by a prefix and a letter or digit, ie "Numpad" and '1'.
+fn named_key(prefix: &str, c: char) -> MartyKey {
+    MartyKey::from_str(&format!("{}{}", prefix, c)).unwrap_or(MartyKey::None)
+}
+
+/// Return the keystrokes that type the specified character, or None if it has no CP437 equivalent.
+fn keystroke(c: char) -> Option<Keystroke> {
+    if let Some(keystroke) = ascii_keystroke(c) {
+        return Some(keystroke);
+    }
+    // Code 0 can't be typed, and 255 is a non-breaking space that would match ' '.
+    CP437_TO_UNICODE[1..255]
+        .iter()
+        .position(|&glyph| glyph == c)
+        .map(|i| Keystroke::AltCode(i as u8 + 1))
+}
+
+struct MacroBuilder {
+    events: Vec<MacroEvent>,
+    delay:  Duration,
+    // Additional delay before the next event.
+    wait:   Duration,
+}
+
+impl MacroBuilder {
+    fn push(&mut self, key: MartyKey, pressed: bool) {
+        self.events.push(MacroEvent {
+            delay: self.delay + std::mem::take(&mut self.wait),
+            key,
+            pressed,
+        });
+    }
+
+    fn tap(&mut self, key: MartyKey) {
+        self.push(key, true);
+        self.push(key, false);
+    }
+}
+
+/// Convert text into a keyboard macro that types it. Line endings of any style are typed as
+/// Enter. Returns the macro and the number of characters that could not be typed and were skipped.
+pub fn text_to_macro(text: &str, options: &PasteOptions) -> (KeyboardMacro, usize) {
+    let mut builder = MacroBuilder {
+        events: Vec::new(),
+        delay:  options.key_delay,
+        wait:   Duration::ZERO,
+    };
+    let mut skipped = 0;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' | '\n' => {
+                // Treat CR LF as a single line ending.
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                builder.tap(MartyKey::Enter);
+                builder.wait = options.line_delay;
+                continue;
+            }
+            _ => {}
+        }
+
+        match keystroke(c) {
+            Some(Keystroke::Key(key)) => builder.tap(key),
+            Some(Keystroke::Shifted(key)) => {
+                builder.push(MartyKey::ShiftLeft, true);
+                builder.tap(key);
+                builder.push(MartyKey::ShiftLeft, false);
+            }
+            Some(Keystroke::AltCode(code)) => {
+                builder.push(MartyKey::AltLeft, true);
+                for digit in code.to_string().bytes() {
+                    builder.tap(named_key("Numpad", digit as char));
+                }
+                builder.push(MartyKey::AltLeft, false);
+            }
+            None => skipped += 1,
+        }
+    }
+
+    (KeyboardMacro { events: builder.events }, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keyboard_macro: &KeyboardMacro) -> Vec<(MartyKey, bool)> {
+        keyboard_macro.events.iter().map(|e| (e.key, e.pressed)).collect()
+    }
+
+    #[test]
+    fn test_ascii() {
+        let (keyboard_macro, skipped) = text_to_macro("a B?", &PasteOptions::default());
+        assert_eq!(skipped, 0);
+        assert_eq!(
+            keys(&keyboard_macro),
+            vec![
+                (MartyKey::KeyA, true),
+                (MartyKey::KeyA, false),
+                (MartyKey::Space, true),
+                (MartyKey::Space, false),
+                (MartyKey::ShiftLeft, true),
+                (MartyKey::KeyB, true),
+                (MartyKey::KeyB, false),
+                (MartyKey::ShiftLeft, false),
+                (MartyKey::ShiftLeft, true),
+                (MartyKey::Slash, true),
+                (MartyKey::Slash, false),
+                (MartyKey::ShiftLeft, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cp437_and_skipped() {
+        // é is CP437 0x82 (130), typed as Alt+1,3,0. The emoji has no CP437 equivalent.
+        let (keyboard_macro, skipped) = text_to_macro("é😀", &PasteOptions::default());
+        assert_eq!(skipped, 1);
+        assert_eq!(
+            keys(&keyboard_macro),
+            vec![
+                (MartyKey::AltLeft, true),
+                (MartyKey::Numpad1, true),
+                (MartyKey::Numpad1, false),
+                (MartyKey::Numpad3, true),
+                (MartyKey::Numpad3, false),
+                (MartyKey::Numpad0, true),
+                (MartyKey::Numpad0, false),
+                (MartyKey::AltLeft, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let options = PasteOptions {
+            key_delay:  Duration::from_millis(10),
+            line_delay: Duration::from_millis(500),
+        };
+        let (keyboard_macro, _) = text_to_macro("1\r\n2\n", &options);
+        assert_eq!(
+            keys(&keyboard_macro),
+            vec![
+                (MartyKey::Digit1, true),
+                (MartyKey::Digit1, false),
+                (MartyKey::Enter, true),
+                (MartyKey::Enter, false),
+                (MartyKey::Digit2, true),
+                (MartyKey::Digit2, false),
+                (MartyKey::Enter, true),
+                (MartyKey::Enter, false),
+            ]
+        );
+        let delays: Vec<u128> = keyboard_macro.events.iter().map(|e| e.delay.as_millis()).collect();
+        assert_eq!(delays, vec![10, 10, 10, 10, 510, 10, 10, 10]);
+    }
+}
@@ -0,0 +1,687 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::quick_load::mod.rs
+
+    Load a DOS .COM or .EXE program from the host directly into guest memory.
+
+    The program is loaded into a free block of DOS memory, which is split into
+    an environment block and a program block with memory control blocks (MCBs)
+    owned by the program's PSP, as DOS's EXEC function would allocate them. A
+    short stub in the environment block then asks DOS for the current PSP,
+    which becomes the program's parent, makes the program's PSP current, and
+    jumps to the program. The program can then allocate memory and terminate
+    as usual, and DOS frees its memory and returns to the shell.
+
+    Unlike EXEC, the program always receives the rest of the free block, and
+    DOS never opens the program file.
+*/
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Error};
+use marty_core::{
+    cpu_common::{Cpu, Register16},
+    machine::Machine,
+};
+
+/// The size of the Program Segment Prefix, in bytes.
+pub const PSP_SIZE: usize = 0x100;
+/// The size of the environment, which holds no variables and the program name.
+pub const ENV_SIZE: usize = 0x40;
+/// The number of paragraphs in the environment block: the environment, followed by the start stub.
+pub const ENV_PARAGRAPHS: u16 = (ENV_SIZE + STUB_SIZE).div_ceil(16) as u16;
+/// The largest .COM file DOS will load: a segment less the PSP and the initial stack word.
+pub const COM_MAX_SIZE: usize = 0x10000 - PSP_SIZE - 2;
+
+const MZ_HEADER_SIZE: usize = 0x1C;
+const STUB_SIZE: usize = 24;
+const MCB_SIZE: usize = 16;
+const MCB_MID: u8 = b'M';
+const MCB_LAST: u8 = b'Z';
+/// The segment the search for the first MCB starts at, past the interrupt vectors and BIOS data.
+const MCB_SEARCH_START: u16 = 0x0050;
+/// The most blocks followed in an MCB chain.
+const MCB_CHAIN_MAX: usize = 4096;
+const BIOS_MEMORY_SIZE: usize = 0x413;
+const CPU_FLAG_INT_ENABLE: u16 = 0x0200;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProgramFormat {
+    Com,
+    Exe,
+}
+
+#[derive(Clone, Debug)]
+struct MzHeader {
+    min_alloc: u16,
+    ss: u16,
+    sp: u16,
+    ip: u16,
+    cs: u16,
+    relocations: Vec<(u16, u16)>, // Offset, segment
+}
+
+/// A DOS memory control block, the paragraph in front of each block of memory that DOS manages.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MemoryBlock {
+    /// The segment of the MCB. The block itself starts at the following segment.
+    pub segment: u16,
+    /// Whether this is the last block in the chain, marked 'Z' rather than 'M'.
+    pub last: bool,
+    /// The PSP segment of the block's owner, or 0 if the block is free.
+    pub owner: u16,
+    /// The size of the block in paragraphs, not counting the MCB.
+    pub paragraphs: u16,
+}
+
+impl MemoryBlock {
+    fn parse(memory: &[u8], segment: u16) -> Option<Self> {
+        let mcb = memory.get(segment as usize * 16..segment as usize * 16 + MCB_SIZE)?;
+        if mcb[0] != MCB_MID && mcb[0] != MCB_LAST {
+            return None;
+        }
+        Some(Self {
+            segment,
+            last: mcb[0] == MCB_LAST,
+            owner: word(mcb, 1),
+            paragraphs: word(mcb, 3),
+        })
+    }
+
+    /// Return the first segment past the end of the block.
+    pub fn end(&self) -> u32 {
+        self.segment as u32 + 1 + self.paragraphs as u32
+    }
+
+    /// Build the MCB, with a name as DOS 4 and later store for a program's blocks.
+    fn to_bytes(self, name: &str) -> [u8; MCB_SIZE] {
+        let mut mcb = [0u8; MCB_SIZE];
+        mcb[0] = if self.last { MCB_LAST } else { MCB_MID };
+        mcb[1..3].copy_from_slice(&self.owner.to_le_bytes());
+        mcb[3..5].copy_from_slice(&self.paragraphs.to_le_bytes());
+        for (dst, src) in mcb[8..].iter_mut().zip(name.bytes()) {
+            *dst = src;
+        }
+        mcb
+    }
+}
+
+/// Guest state for a program placed in memory. `memory` is written at `base_segment:0000`, and
+/// holds the environment block, the program block with its PSP and program image, and their MCBs.
+#[derive(Clone, Debug)]
+pub struct ProgramLoad {
+    pub base_segment: u16,
+    pub env_segment: u16,
+    pub psp_segment: u16,
+    pub memory: Vec<u8>,
+    /// The free block left in front of `base_segment`, if any, whose MCB must be shrunk to end there.
+    pub free_block: Option<MemoryBlock>,
+    pub cs: u16,
+    pub ip: u16,
+    pub ss: u16,
+    pub sp: u16,
+}
+
+/// Parameters describing the guest that a program is loaded into.
+#[derive(Clone, Debug)]
+pub struct LoadParams {
+    /// The segment to place the environment block's MCB at. The program block follows it.
+    pub base_segment: u16,
+    /// The free block of DOS memory holding `base_segment`, which the program is loaded into.
+    pub free_block: MemoryBlock,
+    /// The program name stored at the end of the environment block, ie "C:\TEST.COM".
+    pub name: String,
+    /// The command tail, without the leading space.
+    pub args: String,
+    /// The INT 22h, 23h and 24h vectors, copied into the PSP.
+    pub vectors: [u8; 12],
+}
+
+#[derive(Clone, Debug)]
+pub struct DosProgram {
+    format: ProgramFormat,
+    image:  Vec<u8>,
+    header: Option<MzHeader>,
+}
+
+fn word(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+impl DosProgram {
+    /// Parse a program file. Files beginning with an 'MZ' signature are loaded as .EXE files,
+    /// anything else as a .COM file, which is also how DOS decides.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 2 || !(data.starts_with(b"MZ") || data.starts_with(b"ZM")) {
+            if data.is_empty() {
+                bail!("Program file is empty.");
+            }
+            if data.len() > COM_MAX_SIZE {
+                bail!("Program is too large for a .COM file: {} bytes", data.len());
+            }
+            return Ok(Self {
+                format: ProgramFormat::Com,
+                image:  data.to_vec(),
+                header: None,
+            });
+        }
+
+        if data.len() < MZ_HEADER_SIZE {
+            bail!("Truncated .EXE header.");
+        }
+        let last_page_bytes = word(data, 0x02) as usize;
+        let pages = word(data, 0x04) as usize;
+        let reloc_count = word(data, 0x06) as usize;
+        let header_paragraphs = word(data, 0x08);
+        let reloc_offset = word(data, 0x18) as usize;
+
+        let mut file_size = pages * 512;
+        if last_page_bytes != 0 {
+            file_size = file_size.saturating_sub(512 - last_page_bytes);
+        }
+        let header_size = header_paragraphs as usize * 16;
+        if file_size > data.len() || header_size > file_size {
+            bail!("Invalid .EXE header: image extends past the end of the file.");
+        }
+        if reloc_offset + reloc_count * 4 > data.len() {
+            bail!("Invalid .EXE header: relocation table extends past the end of the file.");
+        }
+
+        let relocations = (0..reloc_count)
+            .map(|i| {
+                let entry = reloc_offset + i * 4;
+                (word(data, entry), word(data, entry + 2))
+            })
+            .collect();
+
+        Ok(Self {
+            format: ProgramFormat::Exe,
+            image:  data[header_size..file_size].to_vec(),
+            header: Some(MzHeader {
+                min_alloc: word(data, 0x0A),
+                ss: word(data, 0x0E),
+                sp: word(data, 0x10),
+                ip: word(data, 0x14),
+                cs: word(data, 0x16),
+                relocations,
+            }),
+        })
+    }
+
+    pub fn format(&self) -> ProgramFormat {
+        self.format
+    }
+
+    /// Place the program in memory as described by `params`, returning the memory contents and
+    /// initial registers.
+    pub fn load(&self, params: &LoadParams) -> Result<ProgramLoad, Error> {
+        let free = params.free_block;
+        let base_segment = params.base_segment;
+        if base_segment < free.segment || base_segment as u32 >= free.end() {
+            bail!(
+                "Segment {:04X} is not in the free memory block at {:04X}.",
+                base_segment,
+                free.segment
+            );
+        }
+        let block_end = free.end();
+        let psp_segment = base_segment as u32 + ENV_PARAGRAPHS as u32 + 2;
+        let image_segment = psp_segment + (PSP_SIZE / 16) as u32;
+        if block_end <= image_segment {
+            bail!("No memory above segment {:04X}.", base_segment);
+        }
+        let available = (block_end - psp_segment) as usize * 16;
+        let env_segment = base_segment + 1;
+        let psp_segment = psp_segment as u16;
+        let image_segment = image_segment as u16;
+
+        let mut memory = build_psp(psp_segment, env_segment, block_end as u16, params).to_vec();
+
+        let image_paragraphs = self.image.len().div_ceil(16);
+        let (cs, ip, ss, sp) = match &self.header {
+            None => {
+                // A .COM program gets the whole segment, or what there is of it, with a zero word
+                // on the stack so that a RET jumps to the INT 20h at the start of the PSP.
+                let segment_size = available.min(0x10000);
+                if PSP_SIZE + self.image.len() + 2 > segment_size {
+                    bail!("Not enough memory to load program.");
+                }
+                let sp = (segment_size - 2) as u16;
+                memory.extend(&self.image);
+                memory.resize(segment_size, 0);
+                (psp_segment, 0x100, psp_segment, sp)
+            }
+            Some(header) => {
+                let needed = PSP_SIZE + image_paragraphs * 16 + header.min_alloc as usize * 16;
+                if needed > available {
+                    bail!("Not enough memory to load program: {} bytes needed", needed);
+                }
+                let mut image = self.image.clone();
+                for &(offset, segment) in &header.relocations {
+                    let address = segment as usize * 16 + offset as usize;
+                    if address + 1 >= image.len() {
+                        return Err(anyhow!(
+                            "Relocation {:04X}:{:04X} is outside of the program image.",
+                            segment,
+                            offset
+                        ));
+                    }
+                    let value = word(&image, address).wrapping_add(image_segment);
+                    image[address..address + 2].copy_from_slice(&value.to_le_bytes());
+                }
+                memory.extend(image);
+                // Clear the uninitialized data area, as some programs assume it is zeroed.
+                memory.resize((image_paragraphs + header.min_alloc as usize) * 16 + PSP_SIZE, 0);
+                (
+                    header.cs.wrapping_add(image_segment),
+                    header.ip,
+                    header.ss.wrapping_add(image_segment),
+                    header.sp,
+                )
+            }
+        };
+
+        // Both blocks are owned by the PSP, so that DOS frees them when the program terminates.
+        let env_block = MemoryBlock {
+            segment: base_segment,
+            last: false,
+            owner: psp_segment,
+            paragraphs: ENV_PARAGRAPHS,
+        };
+        let program_block = MemoryBlock {
+            segment: env_segment + ENV_PARAGRAPHS,
+            last: free.last,
+            owner: psp_segment,
+            paragraphs: (block_end - psp_segment as u32) as u16,
+        };
+        let mut blocks = env_block.to_bytes("").to_vec();
+        blocks.extend(build_environment(&params.name, &start_stub(psp_segment, cs, ip))?);
+        blocks.extend(program_block.to_bytes(&program_name(&params.name)));
+        blocks.extend(memory);
+
+        Ok(ProgramLoad {
+            base_segment,
+            env_segment,
+            psp_segment,
+            memory: blocks,
+            free_block: (base_segment > free.segment).then(|| MemoryBlock {
+                segment: free.segment,
+                last: false,
+                owner: 0,
+                paragraphs: base_segment - free.segment - 1,
+            }),
+            cs,
+            ip,
+            ss,
+            sp,
+        })
+    }
+}
+
+/// Build an environment block holding no variables and the program name, followed by the start stub.
+fn build_environment(name: &str, stub: &[u8; STUB_SIZE]) -> Result<Vec<u8>, Error> {
+    let mut env = vec![0u8, 0, 1, 0];
+    env.extend(name.bytes().filter(u8::is_ascii));
+    env.push(0);
+    if env.len() > ENV_SIZE {
+        bail!("Program name is too long: {}", name);
+    }
+    env.resize(ENV_SIZE, 0);
+    env.extend(stub);
+    env.resize(ENV_PARAGRAPHS as usize * 16, 0);
+    Ok(env)
+}
+
+/// Build the code the program is entered through, which runs with DS pointing to the PSP. It
+/// stores the current PSP, that of the shell, as the program's parent, makes the program's PSP
+/// current, and jumps to the program's entry point with AX and BX cleared.
+fn start_stub(psp_segment: u16, cs: u16, ip: u16) -> [u8; STUB_SIZE] {
+    let mut stub = [0u8; STUB_SIZE];
+    let [psp_lo, psp_hi] = psp_segment.to_le_bytes();
+    let [ip_lo, ip_hi] = ip.to_le_bytes();
+    let [cs_lo, cs_hi] = cs.to_le_bytes();
+    stub.copy_from_slice(&[
+        0xB4, 0x51, // MOV AH, 51h
+        0xCD, 0x21, // INT 21h
+        0x89, 0x1E, 0x16, 0x00, // MOV [0016h], BX
+        0xBB, psp_lo, psp_hi, // MOV BX, psp_segment
+        0xB4, 0x50, // MOV AH, 50h
+        0xCD, 0x21, // INT 21h
+        0x31, 0xC0, // XOR AX, AX
+        0x31, 0xDB, // XOR BX, BX
+        0xEA, ip_lo, ip_hi, cs_lo, cs_hi, // JMP FAR cs:ip
+    ]);
+    stub
+}
+
+/// Return the name DOS stores in a program's MCB: the file name without its extension.
+fn program_name(name: &str) -> String {
+    let file_name = name.rsplit(['\\', '/', ':']).next().unwrap_or_default();
+    let stem = file_name.split('.').next().unwrap_or_default();
+    stem.chars().take(8).collect()
+}
+
+/// Find the DOS memory chain in `memory` and return the free block containing `segment`. The
+/// first MCB is found by looking for a chain of blocks that runs to the end of `memory`.
+pub fn find_free_block(memory: &[u8], segment: u16) -> Result<MemoryBlock, Error> {
+    let memory_top = (memory.len() / 16) as u32;
+    let chain = (MCB_SEARCH_START as u32..memory_top)
+        .find_map(|start| memory_chain(memory, start as u16))
+        .ok_or_else(|| anyhow!("No DOS memory control blocks found."))?;
+
+    chain
+        .into_iter()
+        .find(|block| block.segment <= segment && (segment as u32) < block.end())
+        .filter(|block| block.owner == 0)
+        .ok_or_else(|| anyhow!("Segment {:04X} is not in free DOS memory.", segment))
+}
+
+/// Follow the chain of MCBs from `start`, returning the blocks if it ends at the top of memory,
+/// or at the MCB just below it that links to upper memory blocks.
+fn memory_chain(memory: &[u8], start: u16) -> Option<Vec<MemoryBlock>> {
+    let memory_top = (memory.len() / 16) as u32;
+    let mut blocks = Vec::new();
+    let mut segment = start as u32;
+    while segment < memory_top && blocks.len() < MCB_CHAIN_MAX {
+        let block = MemoryBlock::parse(memory, segment as u16)?;
+        blocks.push(block);
+        if block.end() + 1 >= memory_top {
+            return (blocks.len() >= 3 && block.end() <= memory_top).then_some(blocks);
+        }
+        if block.last {
+            return None;
+        }
+        segment = block.end();
+    }
+    None
+}
+
+fn build_psp(psp_segment: u16, env_segment: u16, memory_top: u16, params: &LoadParams) -> [u8; PSP_SIZE] {
+    let mut psp = [0u8; PSP_SIZE];
+
+    // INT 20h, for programs that terminate by jumping to offset 0.
+    psp[0x00..0x02].copy_from_slice(&[0xCD, 0x20]);
+    psp[0x02..0x04].copy_from_slice(&memory_top.to_le_bytes());
+    psp[0x0A..0x16].copy_from_slice(&params.vectors);
+    // The parent PSP at 16h is filled in by the start stub.
+    // Job file table: stdin, stdout and stderr on CON, stdaux on AUX, stdprn on PRN.
+    psp[0x18..0x2C].fill(0xFF);
+    psp[0x18..0x1D].copy_from_slice(&[0x01, 0x01, 0x01, 0x00, 0x02]);
+    psp[0x2C..0x2E].copy_from_slice(&env_segment.to_le_bytes());
+    psp[0x32..0x34].copy_from_slice(&20u16.to_le_bytes());
+    psp[0x34..0x36].copy_from_slice(&0x18u16.to_le_bytes());
+    psp[0x36..0x38].copy_from_slice(&psp_segment.to_le_bytes());
+    // INT 21h / RETF dispatcher.
+    psp[0x50..0x53].copy_from_slice(&[0xCD, 0x21, 0xCB]);
+    // Two empty FCBs.
+    psp[0x5D..0x68].fill(b' ');
+    psp[0x6D..0x78].fill(b' ');
+
+    // The command tail, which holds at most 126 characters and a terminating CR.
+    let tail: Vec<u8> = match params.args.is_empty() {
+        true => Vec::new(),
+        false => std::iter::once(b' ')
+            .chain(params.args.bytes().filter(u8::is_ascii))
+            .take(126)
+            .collect(),
+    };
+    psp[0x80] = tail.len() as u8;
+    psp[0x81..0x81 + tail.len()].copy_from_slice(&tail);
+    psp[0x81 + tail.len()] = 0x0D;
+    psp
+}
+
+/// Build load parameters from the machine's current state. The top of memory is taken from the
+/// BIOS data area, the free block from DOS's memory chain and the termination vectors from the
+/// interrupt vector table.
+pub fn load_params(machine: &Machine, base_segment: u16, path: &Path, args: &str) -> Result<LoadParams, Error> {
+    let bus = machine.bus();
+    let memory_kb = bus
+        .peek_range(BIOS_MEMORY_SIZE, 2)
+        .map(|bytes| word(bytes, 0))
+        .unwrap_or(0);
+    let memory_top = match memory_kb {
+        1..=640 => memory_kb * 64,
+        _ => 0xA000,
+    };
+
+    let memory = bus
+        .peek_range(0, memory_top as usize * 16)
+        .map_err(|_| anyhow!("Failed to read conventional memory."))?;
+    let free_block = find_free_block(memory, base_segment)?;
+
+    let mut vectors = [0u8; 12];
+    if let Ok(bytes) = bus.peek_range(0x22 * 4, vectors.len()) {
+        vectors.copy_from_slice(bytes);
+    }
+
+    Ok(LoadParams {
+        base_segment,
+        free_block,
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_uppercase())
+            .unwrap_or_default(),
+        args: args.to_string(),
+        vectors,
+    })
+}
+
+/// Write a loaded program into the machine's memory and start executing it through the start
+/// stub, with interrupts enabled and DS and ES pointing to the PSP.
+pub fn start_program(machine: &mut Machine, load: &ProgramLoad) -> Result<(), Error> {
+    let bus = machine.bus_mut();
+    bus.copy_from(&load.memory, load.base_segment as usize * 16, 0, false)
+        .map_err(|_| anyhow!("Program does not fit in memory."))?;
+    if let Some(free_block) = &load.free_block {
+        bus.copy_from(&free_block.to_bytes(""), free_block.segment as usize * 16, 0, false)
+            .map_err(|_| anyhow!("Failed to write memory control block."))?;
+    }
+
+    machine.set_entry_point(load.env_segment, ENV_SIZE as u16);
+    let cpu = machine.cpu_mut();
+    cpu.set_register16(Register16::SS, load.ss);
+    cpu.set_register16(Register16::SP, load.sp);
+    cpu.set_register16(Register16::DS, load.psp_segment);
+    cpu.set_register16(Register16::ES, load.psp_segment);
+    cpu.set_flags(CPU_FLAG_INT_ENABLE);
+    Ok(())
+}
+
+/// Read a program file from the host and start it in the machine, loading it at `segment`.
+pub fn quick_load(machine: &mut Machine, path: &Path, args: &str, segment: u16) -> Result<ProgramFormat, Error> {
+    let data = std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let program = DosProgram::parse(&data)?;
+    let load = program.load(&load_params(machine, segment, path, args)?)?;
+    start_program(machine, &load)?;
+    log::debug!(
+        "Quick-loaded {} at {:04X}:0000, entry point {:04X}:{:04X}",
+        path.display(),
+        load.psp_segment,
+        load.cs,
+        load.ip
+    );
+    Ok(program.format())
+}
+
+/// Return whether the file has a .COM or .EXE extension.
+pub fn is_program_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("com") || ext.eq_ignore_ascii_case("exe"))
+}
+
+/// Return whether the text screen appears to be at a DOS prompt: the last line with any text on
+/// it ends with '>', as in "A>" or "C:\>". If `prompt` is given, the screen must contain it instead.
+pub fn at_dos_prompt(screen_text: &str, prompt: Option<&str>) -> bool {
+    match prompt {
+        Some(prompt) => screen_text.contains(prompt),
+        None => screen_text
+            .lines()
+            .map(str::trim_end)
+            .rfind(|line| !line.is_empty())
+            .is_some_and(|line| line.ends_with('>')),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_block(segment: u16, end: u16) -> MemoryBlock {
+        MemoryBlock {
+            segment,
+            last: true,
+            owner: 0,
+            paragraphs: end - segment - 1,
+        }
+    }
+
+    fn params(args: &str) -> LoadParams {
+        LoadParams {
+            base_segment: 0x2000,
+            free_block: free_block(0x0400, 0xA000),
+            name: "C:\\TEST.COM".to_string(),
+            args: args.to_string(),
+            vectors: [0x11; 12],
+        }
+    }
+
+    #[test]
+    fn test_load_com() {
+        let program = DosProgram::parse(&[0xB4, 0x4C, 0xCD, 0x21]).unwrap();
+        assert_eq!(program.format(), ProgramFormat::Com);
+
+        let load = program.load(&params("/x foo")).unwrap();
+        assert_eq!((load.env_segment, load.psp_segment), (0x2001, 0x2008));
+        assert_eq!((load.cs, load.ip, load.ss, load.sp), (0x2008, 0x100, 0x2008, 0xFFFE));
+        assert_eq!(load.memory.len(), 0x80 + 0x10000);
+
+        // The free block is shrunk to end at the environment block's MCB.
+        assert_eq!(
+            load.free_block,
+            Some(MemoryBlock {
+                segment: 0x0400,
+                last: false,
+                owner: 0,
+                paragraphs: 0x1BFF,
+            })
+        );
+        assert_eq!(&load.memory[..5], b"M\x08\x20\x06\x00");
+        assert_eq!(&load.memory[0x70..0x75], b"Z\x08\x20\xF8\x7F");
+        assert_eq!(&load.memory[0x78..0x80], b"TEST\0\0\0\0");
+
+        let env = &load.memory[0x10..0x70];
+        assert_eq!(&env[..15], b"\0\0\x01\0C:\\TEST.COM");
+        assert_eq!(&env[0x40..0x44], &[0xB4, 0x51, 0xCD, 0x21]);
+        assert_eq!(&env[0x48..0x4B], &[0xBB, 0x08, 0x20]);
+        assert_eq!(&env[0x53..0x58], &[0xEA, 0x00, 0x01, 0x08, 0x20]);
+        let psp = &load.memory[0x80..0x180];
+        assert_eq!(&psp[..4], &[0xCD, 0x20, 0x00, 0xA0]);
+        assert_eq!(word(psp, 0x2C), 0x2001);
+        assert_eq!(&psp[0x80..0x89], b"\x07 /x foo\r");
+        assert_eq!(&load.memory[0x180..0x184], &[0xB4, 0x4C, 0xCD, 0x21]);
+
+        // Without 64K available, the stack goes at the top of the block.
+        let mut small = params("");
+        small.free_block = free_block(0x0400, 0x2100);
+        let load = program.load(&small).unwrap();
+        assert_eq!(load.sp, 0x0F80 - 2);
+        assert_eq!(load.memory[0x80 + 0x80..0x80 + 0x82], [0x00, 0x0D]);
+
+        // Loading at the free block's MCB replaces it.
+        let mut at_mcb = params("");
+        at_mcb.base_segment = 0x0400;
+        let load = program.load(&at_mcb).unwrap();
+        assert_eq!((load.psp_segment, load.free_block), (0x0408, None));
+
+        at_mcb.base_segment = 0x0300;
+        assert!(program.load(&at_mcb).is_err());
+    }
+
+    #[test]
+    fn test_load_exe() {
+        // A two paragraph header with one relocation, and a 4 byte image.
+        let mut exe = vec![0u8; 0x24];
+        exe[0x00..0x02].copy_from_slice(b"MZ");
+        exe[0x02..0x04].copy_from_slice(&0x24u16.to_le_bytes());
+        exe[0x04..0x06].copy_from_slice(&1u16.to_le_bytes());
+        exe[0x06..0x08].copy_from_slice(&1u16.to_le_bytes());
+        exe[0x08..0x0A].copy_from_slice(&2u16.to_le_bytes());
+        exe[0x0A..0x0C].copy_from_slice(&1u16.to_le_bytes());
+        exe[0x0E..0x10].copy_from_slice(&0x0001u16.to_le_bytes());
+        exe[0x10..0x12].copy_from_slice(&0x0100u16.to_le_bytes());
+        exe[0x14..0x16].copy_from_slice(&0x0002u16.to_le_bytes());
+        exe[0x16..0x18].copy_from_slice(&0x0000u16.to_le_bytes());
+        exe[0x18..0x1A].copy_from_slice(&0x1Cu16.to_le_bytes());
+        exe[0x1C..0x20].copy_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        exe[0x20..0x24].copy_from_slice(&[0x34, 0x12, 0x90, 0x90]);
+
+        let program = DosProgram::parse(&exe).unwrap();
+        assert_eq!(program.format(), ProgramFormat::Exe);
+
+        let load = program.load(&params("")).unwrap();
+        // The image starts after the environment block, the program's MCB and the PSP, at segment 2018h.
+        assert_eq!((load.cs, load.ip, load.ss, load.sp), (0x2018, 0x0002, 0x2019, 0x0100));
+        assert_eq!(&load.memory[0x180..0x184], &[0x4C, 0x32, 0x90, 0x90]);
+        assert_eq!(load.memory.len(), 0x180 + 0x10 + 0x10);
+
+        exe[0x04..0x06].copy_from_slice(&2u16.to_le_bytes());
+        assert!(DosProgram::parse(&exe).is_err());
+    }
+
+    #[test]
+    fn test_find_free_block() {
+        // DOS's block, the shell's block and a free block running to the top of memory.
+        let mut memory = vec![0u8; 0xA000 * 16];
+        memory[0x1000..0x1005].copy_from_slice(b"M\x08\x00\xFF\x00");
+        memory[0x2000..0x2005].copy_from_slice(b"M\x01\x02\xFF\x01");
+        memory[0x4000..0x4005].copy_from_slice(b"Z\x00\x00\xFF\x9B");
+
+        assert_eq!(find_free_block(&memory, 0x2000).unwrap(), free_block(0x0400, 0xA000));
+        assert!(find_free_block(&memory, 0x0300).is_err());
+
+        // A chain that doesn't reach the top of memory is not DOS's.
+        memory[0x4003..0x4005].copy_from_slice(&0x1000u16.to_le_bytes());
+        assert!(find_free_block(&memory, 0x0800).is_err());
+    }
+
+    #[test]
+    fn test_at_dos_prompt() {
+        assert!(at_dos_prompt("Microsoft MS-DOS\n\nA>\n\n", None));
+        assert!(at_dos_prompt("C:\\>   ", None));
+        assert!(!at_dos_prompt("Enter new date (mm-dd-yy):", None));
+        assert!(at_dos_prompt("Enter new date", Some("new date")));
+    }
+
+    #[test]
+    fn test_is_program_file() {
+        assert!(is_program_file(Path::new("GAME.EXE")));
+        assert!(is_program_file(Path::new("dir/command.com")));
+        assert!(!is_program_file(Path::new("disk.img")));
+        assert!(!is_program_file(Path::new("exe")));
+    }
+}
@@ -0,0 +1,244 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+    frontend_common::rewind_buffer::mod.rs
+
+    Keeps a history of machine states in memory for rewinding.
+
+    Only the most recent state is kept in full. Each older state is stored
+    as a delta that transforms the state after it back into it, so stepping
+    backwards is a matter of applying the newest delta and discarding it.
+    Deltas are the XOR of two states with runs of unchanged bytes removed.
+    As most of a machine's memory is unchanged from one snapshot to the
+    next, a delta is usually a small fraction of the size of a full state.
+*/
+
+use std::collections::VecDeque;
+
+/// Runs of unchanged bytes shorter than this are stored in a delta rather than ending a run
+/// of changed bytes, as each run costs a few bytes of overhead.
+const MIN_UNCHANGED_RUN: usize = 8;
+
+/// A set of changes that transforms one machine state into another.
+pub struct StateDelta {
+    len:  usize,
+    data: Vec<u8>,
+}
+
+impl StateDelta {
+    /// Create a delta that transforms `from` into `to`.
+    ///
+    /// The delta is a sequence of runs, each encoded as the number of unchanged bytes, the
+    /// number of changed bytes, and the changed bytes XORed with the originals.
+    pub fn new(from: &[u8], to: &[u8]) -> Self {
+        let xor = |i: usize| to[i] ^ from.get(i).copied().unwrap_or(0);
+        let mut data = Vec::new();
+        let mut i = 0;
+
+        while i < to.len() {
+            let unchanged_start = i;
+            while i < to.len() && xor(i) == 0 {
+                i += 1;
+            }
+
+            let changed_start = i;
+            let mut changed_end = i;
+            while i < to.len() {
+                if xor(i) != 0 {
+                    i += 1;
+                    changed_end = i;
+                }
+                else if i - changed_end >= MIN_UNCHANGED_RUN {
+                    break;
+                }
+                else {
+                    i += 1;
+                }
+            }
+            // Any trailing unchanged bytes begin the next run.
+            i = changed_end;
+
+            write_varint(&mut data, changed_start - unchanged_start);
+            write_varint(&mut data, changed_end - changed_start);
+            data.extend((changed_start..changed_end).map(xor));
+        }
+
+        Self { len: to.len(), data }
+    }
+
+    /// Apply the delta to the state it was created from, returning the state it was created to.
+    pub fn apply(&self, from: &[u8]) -> Vec<u8> {
+        let mut state: Vec<u8> = (0..self.len).map(|i| from.get(i).copied().unwrap_or(0)).collect();
+        let mut pos = 0;
+        let mut cursor = 0;
+
+        while cursor < self.data.len() {
+            pos += read_varint(&self.data, &mut cursor);
+            let changed = read_varint(&self.data, &mut cursor);
+            for byte in &self.data[cursor..cursor + changed] {
+                state[pos] ^= byte;
+                pos += 1;
+            }
+            cursor += changed;
+        }
+        state
+    }
+
+    /// The size of the delta in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], cursor: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*cursor];
+        *cursor += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// A bounded history of machine states, newest last.
+pub struct RewindBuffer {
+    capacity: usize,
+    newest:   Option<Vec<u8>>,
+    // deltas.back() transforms `newest` into the state captured before it.
+    deltas:   VecDeque<StateDelta>,
+}
+
+impl RewindBuffer {
+    /// Create a buffer holding up to `capacity` states.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            newest:   None,
+            deltas:   VecDeque::new(),
+        }
+    }
+
+    /// Add a state to the buffer, discarding the oldest state if the buffer is full.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(newest) = self.newest.take() {
+            self.deltas.push_back(StateDelta::new(&state, &newest));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    /// Remove and return the newest state in the buffer. Returns None if the buffer is empty.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.newest = self.deltas.pop_back().map(|delta| delta.apply(&newest));
+        Some(newest)
+    }
+
+    /// The number of states in the buffer.
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// The memory used by the buffer's states, in bytes.
+    pub fn size(&self) -> usize {
+        self.newest.as_ref().map_or(0, |state| state.len()) + self.deltas.iter().map(|d| d.size()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let from: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut to = from.clone();
+        to[0] = 0xFF;
+        to[10] ^= 0x55;
+        to[12] ^= 0x55;
+        to[500..520].fill(0);
+        to.extend_from_slice(&[1, 2, 3]);
+
+        let delta = StateDelta::new(&from, &to);
+        assert_eq!(delta.apply(&from), to);
+        assert!(delta.size() < 64);
+
+        // A delta to a shorter state truncates.
+        let delta = StateDelta::new(&to, &from);
+        assert_eq!(delta.apply(&to), from);
+    }
+
+    #[test]
+    fn test_identical_states() {
+        let state = vec![0x42u8; 4096];
+        let delta = StateDelta::new(&state, &state);
+        assert_eq!(delta.apply(&state), state);
+        assert!(delta.size() <= 4);
+    }
+
+    #[test]
+    fn test_rewind_buffer() {
+        let states: Vec<Vec<u8>> = (0..5u8).map(|n| vec![n; 256]).collect();
+        let mut buffer = RewindBuffer::new(3);
+        for state in states.iter() {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 3);
+
+        assert_eq!(buffer.pop(), Some(states[4].clone()));
+        assert_eq!(buffer.pop(), Some(states[3].clone()));
+        buffer.push(states[0].clone());
+        assert_eq!(buffer.pop(), Some(states[0].clone()));
+        assert_eq!(buffer.pop(), Some(states[2].clone()));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+    }
+}
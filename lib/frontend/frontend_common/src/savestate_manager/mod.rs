@@ -0,0 +1,258 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+    frontend_common::savestate_manager::mod.rs
+
+    Keeps track of save state slots and the metadata stored alongside them.
+
+    Each slot is stored as up to three files in the save state directory,
+    named after the machine configuration:
+
+        <config>_slot<n>.mss   The machine state
+        <config>_slot<n>.toml  Metadata describing the state
+        <config>_slot<n>.png   A screenshot taken when the state was saved
+
+    The metadata records the machine configuration and the media mounted at
+    the time of the save, so that a mismatch can be reported before the
+    state is loaded.
+*/
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Error;
+use serde_derive::{Deserialize, Serialize};
+
+/// The number of save state slots kept for each machine configuration.
+pub const SAVE_STATE_SLOTS: usize = 8;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SaveStateMetadata {
+    /// Local time the state was saved, for display.
+    pub timestamp: String,
+    /// Name of the machine configuration the state was saved from.
+    pub config_name: String,
+    /// Hash of the machine configuration, as returned by config_hash().
+    pub config_hash: String,
+    /// A description of each mounted media item, such as "Floppy 0: dos33.img".
+    #[serde(default)]
+    pub media: Vec<String>,
+}
+
+impl SaveStateMetadata {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Describe each difference between the machine this state was saved from and the current
+    /// machine. An empty vector means the state can be loaded without surprises.
+    pub fn mismatches(&self, config_hash: &str, media: &[String]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.config_hash != config_hash {
+            warnings.push("The machine configuration has changed since this state was saved.".to_string());
+        }
+        for item in self.media.iter().filter(|item| !media.contains(item)) {
+            warnings.push(format!("Not currently mounted: {}", item));
+        }
+        for item in media.iter().filter(|item| !self.media.contains(item)) {
+            warnings.push(format!("Mounted, but not when saved: {}", item));
+        }
+        warnings
+    }
+}
+
+/// A summary of a save state slot for display in a save state browser.
+#[derive(Clone, Debug, Default)]
+pub struct SaveStateSlotInfo {
+    pub slot: usize,
+    /// Whether a state has been saved to this slot.
+    pub occupied: bool,
+    /// Size of the state file in bytes.
+    pub size: u64,
+    /// The slot's metadata, if present and readable.
+    pub metadata: Option<SaveStateMetadata>,
+    /// The slot's screenshot, as PNG data.
+    pub thumbnail: Option<Arc<[u8]>>,
+    /// Differences between the machine the state was saved from and the current machine.
+    pub warnings: Vec<String>,
+}
+
+/// Return a stable hash of a machine configuration description as a hex string. The FNV-1a hash
+/// is used as, unlike the standard library's hasher, its output does not change between builds.
+pub fn config_hash(desc: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in desc.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+pub struct SaveStateManager {
+    dir: PathBuf,
+    config_name: String,
+}
+
+impl SaveStateManager {
+    pub fn new(dir: PathBuf, config_name: &str) -> Self {
+        Self {
+            dir,
+            config_name: config_name.to_string(),
+        }
+    }
+
+    fn slot_path(&self, slot: usize, extension: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}_slot{}.{}", self.config_name, slot, extension))
+    }
+
+    pub fn state_path(&self, slot: usize) -> PathBuf {
+        self.slot_path(slot, "mss")
+    }
+
+    pub fn metadata_path(&self, slot: usize) -> PathBuf {
+        self.slot_path(slot, "toml")
+    }
+
+    pub fn thumbnail_path(&self, slot: usize) -> PathBuf {
+        self.slot_path(slot, "png")
+    }
+
+    /// Read the metadata for a slot. Returns None if the slot has no metadata or it can't be read.
+    pub fn load_metadata(&self, slot: usize) -> Option<SaveStateMetadata> {
+        let path = self.metadata_path(slot);
+        if !path.exists() {
+            return None;
+        }
+        match SaveStateMetadata::load(&path) {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                log::warn!("Failed to read save state metadata {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Summarize every slot, comparing each occupied slot against the current configuration hash
+    /// and mounted media.
+    pub fn scan(&self, config_hash: &str, media: &[String]) -> Vec<SaveStateSlotInfo> {
+        (1..=SAVE_STATE_SLOTS)
+            .map(|slot| {
+                let size = match std::fs::metadata(self.state_path(slot)) {
+                    Ok(file_meta) => file_meta.len(),
+                    Err(_) => {
+                        return SaveStateSlotInfo {
+                            slot,
+                            ..Default::default()
+                        }
+                    }
+                };
+                let metadata = self.load_metadata(slot);
+                let warnings = match &metadata {
+                    Some(metadata) => metadata.mismatches(config_hash, media),
+                    None => vec!["No metadata was saved with this state.".to_string()],
+                };
+                SaveStateSlotInfo {
+                    slot,
+                    occupied: true,
+                    size,
+                    metadata,
+                    thumbnail: std::fs::read(self.thumbnail_path(slot)).ok().map(Arc::from),
+                    warnings,
+                }
+            })
+            .collect()
+    }
+
+    /// Delete the state, metadata and screenshot for a slot.
+    pub fn delete(&self, slot: usize) -> Result<(), Error> {
+        for path in [
+            self.state_path(slot),
+            self.metadata_path(slot),
+            self.thumbnail_path(slot),
+        ] {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_hash() {
+        // FNV-1a test vectors
+        assert_eq!(config_hash(""), "cbf29ce484222325");
+        assert_eq!(config_hash("a"), "af63dc4c8601ec8c");
+        assert_ne!(config_hash("Ibm5160 CGA"), config_hash("Ibm5160 MDA"));
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let metadata = SaveStateMetadata {
+            timestamp: "2024-01-01 00:00:00".to_string(),
+            config_name: "ibm5160".to_string(),
+            config_hash: config_hash("config"),
+            media: vec!["Floppy 0: dos.img".to_string()],
+        };
+
+        let media = vec!["Floppy 0: dos.img".to_string()];
+        assert!(metadata.mismatches(&config_hash("config"), &media).is_empty());
+        assert_eq!(metadata.mismatches(&config_hash("other"), &media).len(), 1);
+
+        let media = vec!["Floppy 0: game.img".to_string()];
+        assert_eq!(
+            metadata.mismatches(&config_hash("config"), &media),
+            vec![
+                "Not currently mounted: Floppy 0: dos.img".to_string(),
+                "Mounted, but not when saved: Floppy 0: game.img".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let metadata = SaveStateMetadata {
+            timestamp: "2024-01-01 00:00:00".to_string(),
+            config_name: "ibm5160".to_string(),
+            config_hash: config_hash("config"),
+            media: vec!["Floppy 0: dos.img".to_string(), "Hard disk 0: hdd.vhd".to_string()],
+        };
+        let toml_str = toml::to_string(&metadata).unwrap();
+        assert_eq!(toml::from_str::<SaveStateMetadata>(&toml_str).unwrap(), metadata);
+    }
+}
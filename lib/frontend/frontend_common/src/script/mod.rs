@@ -0,0 +1,495 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::script::mod.rs
+
+    An embedded Rhai scripting engine for automating the emulator.
+
+    A script runs against a snapshot of the machine's registers and memory,
+    taken when the script is called. Scripts control the machine by queueing
+    actions, which the front end performs when the script returns, just as it
+    executes console commands. Writes to memory and registers are applied to
+    the snapshot as well, so a script reads back what it has written.
+
+    Scripts may register functions to be called when an execution breakpoint
+    is hit, or once every frame. These remain active until scripts are
+    cleared.
+*/
+
+use std::{cell::RefCell, path::Path, rc::Rc, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use marty_core::keys::MartyKey;
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST, INT};
+
+use crate::debug_console::{parse_register, ConsoleRegister};
+
+/// The maximum number of operations a single call into a script may perform, so that a runaway
+/// loop can't hang the emulator.
+pub const SCRIPT_MAX_OPERATIONS: u64 = 50_000_000;
+
+pub const SCRIPT_HELP: &str = "\
+Script functions:
+  reset() pause() run()         Control execution
+  peek(addr) peekw(addr)        Read a byte or word at flat address <addr>
+  poke(addr, byte)              Write a byte at flat address <addr>
+  reg(name) set_reg(name, val)  Read or write a register, ie reg(\"ax\")
+  cycles()                      CPU cycles executed since power-on
+  key_down(key) key_up(key)     Press or release a key, ie key_down(\"KeyA\")
+  key_tap(key)                  Press and release a key
+  mount_floppy(drive, path)     Load a floppy image into <drive>
+  eject_floppy(drive)           Eject the floppy in <drive>
+  screenshot([n])               Save a screenshot of display n (default 0)
+  trace(on)                     Enable or disable CPU trace logging
+  console(cmd)                  Run a debug console command
+  on_breakpoint(addr, fn)       Call <fn> when execution reaches <addr>
+  on_frame(fn)                  Call <fn> once every frame
+  print(text)                   Write <text> to the console";
+
+/// The machine state a script can read while it runs.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptMachineState {
+    /// Register names and values. Names are lower case, ie "ax" or "flags".
+    pub registers: Vec<(&'static str, u16)>,
+    /// The contents of the machine's address space. Regions mapped to devices, such as video
+    /// memory, hold what the bus stores and may not reflect the device's own memory.
+    pub memory:    Vec<u8>,
+    pub cycles:    u64,
+}
+
+/// An action requested by a script, to be performed by the front end.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptAction {
+    Reset,
+    Pause,
+    Run,
+    KeyPress(MartyKey),
+    KeyRelease(MartyKey),
+    MountFloppy(usize, String),
+    EjectFloppy(usize),
+    WriteByte(u32, u8),
+    SetRegister(ConsoleRegister, u16),
+    Screenshot(usize),
+    TraceLogging(bool),
+    Console(String),
+}
+
+/// A script function registered as a callback, and the index of the script that defined it.
+#[derive(Clone)]
+struct ScriptCallback {
+    script: usize,
+    func:   FnPtr,
+}
+
+#[derive(Default)]
+struct ScriptContext {
+    state: ScriptMachineState,
+    actions: Vec<ScriptAction>,
+    output: Vec<String>,
+    breakpoints: Vec<(String, ScriptCallback)>,
+    frame_callbacks: Vec<ScriptCallback>,
+    // The index of the script being run. Callbacks registered now belong to it.
+    current: usize,
+}
+
+type SharedContext = Rc<RefCell<ScriptContext>>;
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl ScriptContext {
+    fn check_address(&self, addr: INT, len: usize) -> ScriptResult<usize> {
+        match usize::try_from(addr) {
+            Ok(addr) if addr + len <= self.state.memory.len() => Ok(addr),
+            _ => Err(format!("Address out of range: {:X}", addr).into()),
+        }
+    }
+
+    fn register(&self, name: &str) -> ScriptResult<u16> {
+        let name = name.to_ascii_lowercase();
+        self.state
+            .registers
+            .iter()
+            .find(|(reg, _)| *reg == name)
+            .map(|(_, val)| *val)
+            .ok_or_else(|| format!("Invalid register: {}", name).into())
+    }
+}
+
+fn parse_key(name: &str) -> ScriptResult<MartyKey> {
+    MartyKey::from_str(name).map_err(|_| format!("Unknown key: {}", name).into())
+}
+
+fn parse_drive(drive: INT) -> ScriptResult<usize> {
+    usize::try_from(drive).map_err(|_| format!("Invalid drive: {}", drive).into())
+}
+
+fn queue(engine: &mut Engine, ctx: &SharedContext, name: &str, action: ScriptAction) {
+    let ctx = ctx.clone();
+    engine.register_fn(name, move || ctx.borrow_mut().actions.push(action.clone()));
+}
+
+/// Register the functions scripts use to inspect and control the machine.
+fn register_api(engine: &mut Engine, ctx: &SharedContext) {
+    queue(engine, ctx, "reset", ScriptAction::Reset);
+    queue(engine, ctx, "pause", ScriptAction::Pause);
+    queue(engine, ctx, "run", ScriptAction::Run);
+    queue(engine, ctx, "screenshot", ScriptAction::Screenshot(0));
+
+    let c = ctx.clone();
+    engine.register_fn("peek", move |addr: INT| -> ScriptResult<INT> {
+        let ctx = c.borrow();
+        let addr = ctx.check_address(addr, 1)?;
+        Ok(ctx.state.memory[addr] as INT)
+    });
+    let c = ctx.clone();
+    engine.register_fn("peekw", move |addr: INT| -> ScriptResult<INT> {
+        let ctx = c.borrow();
+        let addr = ctx.check_address(addr, 2)?;
+        Ok(u16::from_le_bytes([ctx.state.memory[addr], ctx.state.memory[addr + 1]]) as INT)
+    });
+    let c = ctx.clone();
+    engine.register_fn("poke", move |addr: INT, value: INT| -> ScriptResult<()> {
+        let mut ctx = c.borrow_mut();
+        let addr = ctx.check_address(addr, 1)?;
+        ctx.state.memory[addr] = value as u8;
+        ctx.actions.push(ScriptAction::WriteByte(addr as u32, value as u8));
+        Ok(())
+    });
+
+    let c = ctx.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<INT> {
+        c.borrow().register(name).map(|val| val as INT)
+    });
+    let c = ctx.clone();
+    engine.register_fn("set_reg", move |name: &str, value: INT| -> ScriptResult<()> {
+        let reg = parse_register(name)?;
+        let value = match reg {
+            ConsoleRegister::Reg8(_) => value as u8 as u16,
+            _ => value as u16,
+        };
+        let mut ctx = c.borrow_mut();
+        // Keep the snapshot current. Byte registers are only visible through their word register.
+        let name = name.to_ascii_lowercase();
+        if let Some((_, val)) = ctx.state.registers.iter_mut().find(|(reg, _)| *reg == name) {
+            *val = value;
+        }
+        ctx.actions.push(ScriptAction::SetRegister(reg, value));
+        Ok(())
+    });
+    let c = ctx.clone();
+    engine.register_fn("cycles", move || c.borrow().state.cycles as INT);
+
+    let c = ctx.clone();
+    engine.register_fn("key_down", move |key: &str| -> ScriptResult<()> {
+        c.borrow_mut().actions.push(ScriptAction::KeyPress(parse_key(key)?));
+        Ok(())
+    });
+    let c = ctx.clone();
+    engine.register_fn("key_up", move |key: &str| -> ScriptResult<()> {
+        c.borrow_mut().actions.push(ScriptAction::KeyRelease(parse_key(key)?));
+        Ok(())
+    });
+    let c = ctx.clone();
+    engine.register_fn("key_tap", move |key: &str| -> ScriptResult<()> {
+        let key = parse_key(key)?;
+        let mut ctx = c.borrow_mut();
+        ctx.actions.push(ScriptAction::KeyPress(key));
+        ctx.actions.push(ScriptAction::KeyRelease(key));
+        Ok(())
+    });
+
+    let c = ctx.clone();
+    engine.register_fn("mount_floppy", move |drive: INT, path: &str| -> ScriptResult<()> {
+        let drive = parse_drive(drive)?;
+        c.borrow_mut()
+            .actions
+            .push(ScriptAction::MountFloppy(drive, path.to_string()));
+        Ok(())
+    });
+    let c = ctx.clone();
+    engine.register_fn("eject_floppy", move |drive: INT| -> ScriptResult<()> {
+        let drive = parse_drive(drive)?;
+        c.borrow_mut().actions.push(ScriptAction::EjectFloppy(drive));
+        Ok(())
+    });
+    let c = ctx.clone();
+    engine.register_fn("screenshot", move |display: INT| -> ScriptResult<()> {
+        let display = usize::try_from(display).map_err(|_| format!("Invalid display: {}", display))?;
+        c.borrow_mut().actions.push(ScriptAction::Screenshot(display));
+        Ok(())
+    });
+    let c = ctx.clone();
+    engine.register_fn("trace", move |state: bool| {
+        c.borrow_mut().actions.push(ScriptAction::TraceLogging(state))
+    });
+    let c = ctx.clone();
+    engine.register_fn("console", move |line: &str| {
+        c.borrow_mut().actions.push(ScriptAction::Console(line.to_string()))
+    });
+
+    let c = ctx.clone();
+    engine.register_fn("on_breakpoint", move |addr: &str, func: FnPtr| {
+        let mut ctx = c.borrow_mut();
+        let script = ctx.current;
+        ctx.breakpoints
+            .push((addr.to_string(), ScriptCallback { script, func }));
+    });
+    let c = ctx.clone();
+    engine.register_fn("on_frame", move |func: FnPtr| {
+        let mut ctx = c.borrow_mut();
+        let script = ctx.current;
+        ctx.frame_callbacks.push(ScriptCallback { script, func });
+    });
+}
+
+pub struct ScriptEngine {
+    engine: Engine,
+    ctx: SharedContext,
+    scripts: Vec<AST>,
+}
+
+impl Default for ScriptEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptEngine {
+    pub fn new() -> Self {
+        let ctx = SharedContext::default();
+        let mut engine = Engine::new();
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+
+        let c = ctx.clone();
+        engine.on_print(move |text| c.borrow_mut().output.push(text.to_string()));
+        let c = ctx.clone();
+        engine.on_debug(move |text, _, _| c.borrow_mut().output.push(text.to_string()));
+
+        register_api(&mut engine, &ctx);
+
+        Self {
+            engine,
+            ctx,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Run a script file. Any callbacks it registers stay active until the engine is cleared.
+    pub fn load(&mut self, path: &Path, state: ScriptMachineState) -> Result<(), Error> {
+        let source = std::fs::read_to_string(path)?;
+        self.eval(&source, state).map(|_| ())
+    }
+
+    /// Run a snippet of script, returning its result as text if it has one.
+    pub fn eval(&mut self, source: &str, state: ScriptMachineState) -> Result<Option<String>, Error> {
+        let ast = self.engine.compile(source).map_err(|e| anyhow!("{}", e))?;
+
+        self.begin(state);
+        self.ctx.borrow_mut().current = self.scripts.len();
+        let result = self.engine.eval_ast::<Dynamic>(&ast);
+        self.end();
+
+        // Keep the script even if it failed, as it may have registered callbacks first.
+        self.scripts.push(ast);
+        let value = result.map_err(|e| anyhow!("{}", e))?;
+        Ok((!value.is_unit()).then(|| value.to_string()))
+    }
+
+    /// Return the address expressions of the breakpoints registered by scripts.
+    pub fn breakpoints(&self) -> Vec<String> {
+        self.ctx
+            .borrow()
+            .breakpoints
+            .iter()
+            .map(|(addr, _)| addr.clone())
+            .collect()
+    }
+
+    pub fn has_frame_callbacks(&self) -> bool {
+        !self.ctx.borrow().frame_callbacks.is_empty()
+    }
+
+    /// Call the function registered for the breakpoint at the specified index into breakpoints().
+    pub fn call_breakpoint(&mut self, idx: usize, state: ScriptMachineState) -> Result<(), Error> {
+        let callback = match self.ctx.borrow().breakpoints.get(idx) {
+            Some((_, callback)) => callback.clone(),
+            None => return Err(anyhow!("No script breakpoint #{}", idx)),
+        };
+        self.begin(state);
+        let result = self.call(&callback);
+        self.end();
+        result
+    }
+
+    /// Call every function registered to run each frame. A function that fails is removed, so
+    /// that it doesn't report the same error every frame.
+    pub fn call_frame(&mut self, state: ScriptMachineState) -> Vec<Error> {
+        let callbacks = self.ctx.borrow().frame_callbacks.clone();
+        let mut errors = Vec::new();
+        let mut failed = Vec::new();
+
+        self.begin(state);
+        for (i, callback) in callbacks.iter().enumerate() {
+            if let Err(e) = self.call(callback) {
+                errors.push(e);
+                failed.push(i);
+            }
+        }
+        self.end();
+
+        let mut ctx = self.ctx.borrow_mut();
+        for i in failed.into_iter().rev() {
+            ctx.frame_callbacks.remove(i);
+        }
+        errors
+    }
+
+    /// Take the actions scripts have requested, in the order they were requested.
+    pub fn take_actions(&mut self) -> Vec<ScriptAction> {
+        std::mem::take(&mut self.ctx.borrow_mut().actions)
+    }
+
+    /// Take the text scripts have printed.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.ctx.borrow_mut().output)
+    }
+
+    /// Unload all scripts and remove their callbacks.
+    pub fn clear(&mut self) {
+        let mut ctx = self.ctx.borrow_mut();
+        ctx.breakpoints.clear();
+        ctx.frame_callbacks.clear();
+        self.scripts.clear();
+    }
+
+    fn call(&self, callback: &ScriptCallback) -> Result<(), Error> {
+        callback
+            .func
+            .call::<Dynamic>(&self.engine, &self.scripts[callback.script], ())
+            .map(|_| ())
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    fn begin(&mut self, state: ScriptMachineState) {
+        self.ctx.borrow_mut().state = state;
+    }
+
+    fn end(&mut self) {
+        // Release the memory snapshot until the next call.
+        self.ctx.borrow_mut().state = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> ScriptMachineState {
+        let mut memory = vec![0; 0x100];
+        memory[0x10] = 0x34;
+        memory[0x11] = 0x12;
+        ScriptMachineState {
+            registers: vec![("ax", 0x1234), ("ip", 0x0100)],
+            memory,
+            cycles: 1000,
+        }
+    }
+
+    #[test]
+    fn test_state_access() {
+        let mut engine = ScriptEngine::new();
+        assert_eq!(engine.eval("peekw(0x10)", state()).unwrap(), Some("4660".to_string()));
+        assert_eq!(
+            engine.eval("reg(\"IP\") + cycles()", state()).unwrap(),
+            Some("1256".to_string())
+        );
+        assert_eq!(
+            engine.eval("poke(0x20, 0x1FF); peek(0x20)", state()).unwrap(),
+            Some("255".to_string())
+        );
+        assert_eq!(engine.take_actions(), vec![ScriptAction::WriteByte(0x20, 0xFF)]);
+        assert!(engine.eval("peek(0x100)", state()).is_err());
+        assert!(engine.eval("reg(\"xx\")", state()).is_err());
+    }
+
+    #[test]
+    fn test_actions() {
+        let mut engine = ScriptEngine::new();
+        let result = engine
+            .eval(
+                "reset(); key_tap(\"KeyA\"); mount_floppy(1, \"dos.img\"); screenshot(); print(\"done\");",
+                state(),
+            )
+            .unwrap();
+        assert_eq!(result, None);
+        assert_eq!(
+            engine.take_actions(),
+            vec![
+                ScriptAction::Reset,
+                ScriptAction::KeyPress(MartyKey::KeyA),
+                ScriptAction::KeyRelease(MartyKey::KeyA),
+                ScriptAction::MountFloppy(1, "dos.img".to_string()),
+                ScriptAction::Screenshot(0),
+            ]
+        );
+        assert_eq!(engine.take_output(), vec!["done".to_string()]);
+        assert!(engine.eval("key_down(\"NoSuchKey\")", state()).is_err());
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let mut engine = ScriptEngine::new();
+        engine
+            .eval(
+                "let hits = 0; \
+                 on_breakpoint(\"f000:e05b\", || { hits += 1; print(`hit ${hits} ${reg(\"ip\")}`); }); \
+                 fn every_frame() { if peek(0x10) == 0x34 { pause(); } } \
+                 on_frame(Fn(\"every_frame\"));",
+                state(),
+            )
+            .unwrap();
+        assert_eq!(engine.breakpoints(), vec!["f000:e05b".to_string()]);
+        assert!(engine.has_frame_callbacks());
+
+        engine.call_breakpoint(0, state()).unwrap();
+        engine.call_breakpoint(0, state()).unwrap();
+        assert_eq!(
+            engine.take_output(),
+            vec!["hit 1 256".to_string(), "hit 2 256".to_string()]
+        );
+
+        assert!(engine.call_frame(state()).is_empty());
+        assert_eq!(engine.take_actions(), vec![ScriptAction::Pause]);
+
+        // A frame callback that fails is removed.
+        engine.eval("on_frame(|| peek(-1));", state()).unwrap();
+        assert_eq!(engine.call_frame(state()).len(), 1);
+        assert_eq!(engine.call_frame(state()).len(), 0);
+
+        engine.clear();
+        assert!(engine.breakpoints().is_empty());
+        assert!(!engine.has_frame_callbacks());
+    }
+}
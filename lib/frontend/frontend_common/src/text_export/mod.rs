@@ -0,0 +1,379 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::text_export::mod.rs
+
+    Export the contents of a text mode screen as plain CP437 text, as ANSI
+    with colors, or as HTML.
+*/
+
+use std::str::FromStr;
+
+use crate::color::cga::CGAColor;
+use marty_core::device_traits::videocard::TextModeScreen;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextExportFormat {
+    Text,
+    Ansi,
+    Html,
+}
+
+impl TextExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TextExportFormat::Text => "txt",
+            TextExportFormat::Ansi => "ans",
+            TextExportFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for TextExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "txt" | "text" => Ok(TextExportFormat::Text),
+            "ans" | "ansi" => Ok(TextExportFormat::Ansi),
+            "htm" | "html" => Ok(TextExportFormat::Html),
+            _ => Err(format!("Invalid text export format: {}", s)),
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub(crate) const CP437_TO_UNICODE: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', ' ',];
+
+// ANSI color numbers for each of the eight IBM colors. IBM orders colors as RGB bits, with blue in
+// bit 0, while ANSI puts red in bit 0.
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// The colors and effects of a single text mode cell, with colors as IBM color indices.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct CellStyle {
+    fg: u8,
+    bg: u8,
+    blink: bool,
+    underline: bool,
+}
+
+impl CellStyle {
+    fn from_attr(screen: &TextModeScreen, attr: u8) -> Self {
+        let blink = screen.blink_enabled && (attr & 0x80 != 0);
+        let bg_bright = !screen.blink_enabled && (attr & 0x80 != 0);
+
+        if screen.monochrome {
+            // The MDA only recognizes a few combinations of foreground and background.
+            let intense = attr & 0x08 != 0;
+            let bg = if bg_bright { 0x0F } else { 0x07 };
+            match attr & 0x77 {
+                0x00 => CellStyle {
+                    fg: 0,
+                    bg: 0,
+                    blink,
+                    underline: false,
+                },
+                0x70 => CellStyle {
+                    fg: 0,
+                    bg,
+                    blink,
+                    underline: false,
+                },
+                _ => CellStyle {
+                    fg: if intense { 0x0F } else { 0x07 },
+                    bg: 0,
+                    blink,
+                    underline: attr & 0x07 == 0x01,
+                },
+            }
+        }
+        else {
+            CellStyle {
+                fg: attr & 0x0F,
+                bg: if screen.blink_enabled {
+                    (attr >> 4) & 0x07
+                }
+                else {
+                    attr >> 4
+                },
+                blink,
+                underline: false,
+            }
+        }
+    }
+
+    /// Return an ANSI SGR sequence that selects this style. A bright background is selected with
+    /// the blink attribute, as ANSI art viewers supporting 'iCE colors' expect.
+    fn to_sgr(&self) -> String {
+        let mut sgr = String::from("\x1b[0");
+        if self.fg & 0x08 != 0 {
+            sgr.push_str(";1");
+        }
+        if self.underline {
+            sgr.push_str(";4");
+        }
+        if self.blink || (self.bg & 0x08 != 0) {
+            sgr.push_str(";5");
+        }
+        sgr.push_str(&format!(
+            ";{};{}m",
+            30 + ANSI_COLORS[(self.fg & 0x07) as usize],
+            40 + ANSI_COLORS[(self.bg & 0x07) as usize]
+        ));
+        sgr
+    }
+
+    fn to_css(&self) -> String {
+        let css_color = |idx: u8| {
+            let rgba = CGAColor::decode_attr(idx).0.to_rgba();
+            format!("#{:02X}{:02X}{:02X}", rgba[0], rgba[1], rgba[2])
+        };
+        let mut css = format!("color:{};background-color:{}", css_color(self.fg), css_color(self.bg));
+        if self.underline {
+            css.push_str(";text-decoration:underline");
+        }
+        css
+    }
+}
+
+/// Return true if the specified CP437 byte would be interpreted as a control character when written
+/// to a text file as-is, instead of being displayed as a glyph.
+fn is_control_byte(byte: u8) -> bool {
+    matches!(byte, 0x00 | 0x07..=0x0D | 0x1A | 0x1B | 0xFF)
+}
+
+/// Export the specified screen in the specified format. Text and ANSI output is CP437 encoded,
+/// HTML output is UTF-8.
+pub fn export_text_screen(screen: &TextModeScreen, format: TextExportFormat) -> Vec<u8> {
+    match format {
+        TextExportFormat::Text => export_text(screen),
+        TextExportFormat::Ansi => export_ansi(screen),
+        TextExportFormat::Html => export_html(screen),
+    }
+}
+
+/// A rectangular region of a text mode screen, in character cells.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextSelection {
+    pub column: usize,
+    pub row: usize,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+/// Decode the specified screen, or a selection of it, to a String for the host clipboard.
+/// Characters are translated from CP437 to Unicode, trailing spaces are trimmed from each line,
+/// and lines are separated by '\n'. A selection is clipped to the bounds of the screen.
+pub fn text_screen_to_string(screen: &TextModeScreen, selection: Option<TextSelection>) -> String {
+    let sel = selection.unwrap_or(TextSelection {
+        column: 0,
+        row: 0,
+        columns: screen.columns,
+        rows: screen.rows,
+    });
+    let col_end = sel.column.saturating_add(sel.columns).min(screen.columns);
+    let row_end = sel.row.saturating_add(sel.rows).min(screen.rows);
+
+    let mut lines = Vec::new();
+    for row in sel.row.min(row_end)..row_end {
+        let cells = &screen.row(row)[sel.column.min(col_end)..col_end];
+        let line: String = cells
+            .iter()
+            .map(|&(ch, _)| match ch {
+                0x00 | 0xFF => ' ',
+                _ => CP437_TO_UNICODE[ch as usize],
+            })
+            .collect();
+        lines.push(line.trim_end_matches(' ').to_string());
+    }
+    lines.join("\n")
+}
+
+fn export_text(screen: &TextModeScreen) -> Vec<u8> {
+    let mut out = Vec::new();
+    for row in 0..screen.rows {
+        let mut line: Vec<u8> = screen
+            .row(row)
+            .iter()
+            .map(|&(ch, _)| {
+                if is_control_byte(ch) {
+                    b' '
+                }
+                else {
+                    ch
+                }
+            })
+            .collect();
+        while line.last() == Some(&b' ') {
+            line.pop();
+        }
+        out.extend(line);
+        out.extend(b"\r\n");
+    }
+    out
+}
+
+fn export_ansi(screen: &TextModeScreen) -> Vec<u8> {
+    let mut out = Vec::new();
+    for row in 0..screen.rows {
+        let mut last_style = None;
+        for &(ch, attr) in screen.row(row) {
+            let style = CellStyle::from_attr(screen, attr);
+            if last_style != Some(style) {
+                out.extend(style.to_sgr().as_bytes());
+                last_style = Some(style);
+            }
+            out.push(if is_control_byte(ch) { b' ' } else { ch });
+        }
+        out.extend(b"\x1b[0m\r\n");
+    }
+    out
+}
+
+fn export_html(screen: &TextModeScreen) -> Vec<u8> {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>\n\
+        pre { background-color: #000000; line-height: 1.0; }\n\
+        .blink { animation: blink 1s step-end infinite; }\n\
+        @keyframes blink { 50% { color: transparent; } }\n\
+        </style>\n</head>\n<body>\n<pre>",
+    );
+
+    for row in 0..screen.rows {
+        let mut last_style = None;
+        for &(ch, attr) in screen.row(row) {
+            let style = CellStyle::from_attr(screen, attr);
+            if last_style != Some(style) {
+                if last_style.is_some() {
+                    html.push_str("</span>");
+                }
+                let class = if style.blink { " class=\"blink\"" } else { "" };
+                html.push_str(&format!("<span{} style=\"{}\">", class, style.to_css()));
+                last_style = Some(style);
+            }
+            match CP437_TO_UNICODE[ch as usize] {
+                '&' => html.push_str("&amp;"),
+                '<' => html.push_str("&lt;"),
+                '>' => html.push_str("&gt;"),
+                c => html.push(c),
+            }
+        }
+        if last_style.is_some() {
+            html.push_str("</span>");
+        }
+        html.push('\n');
+    }
+
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_screen(monochrome: bool) -> TextModeScreen {
+        TextModeScreen {
+            columns: 4,
+            rows: 2,
+            monochrome,
+            blink_enabled: true,
+            cells: vec![
+                (b'A', 0x07),
+                (b'<', 0x1F),
+                (0x00, 0x07),
+                (0x00, 0x07),
+                (0xDB, 0x8C),
+                (0x0D, 0x70),
+                (b'z', 0x01),
+                (0xFF, 0x07),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_export_text() {
+        let out = export_text_screen(&test_screen(false), TextExportFormat::Text);
+        assert_eq!(out, b"A<\r\n\xDB z\r\n");
+    }
+
+    #[test]
+    fn test_screen_to_string() {
+        let screen = test_screen(false);
+        assert_eq!(text_screen_to_string(&screen, None), "A<\n█♪z");
+
+        let selection = TextSelection {
+            column: 1,
+            row: 1,
+            columns: 10,
+            rows: 10,
+        };
+        assert_eq!(text_screen_to_string(&screen, Some(selection)), "♪z");
+
+        let selection = TextSelection {
+            column: 5,
+            row: 0,
+            columns: 2,
+            rows: 1,
+        };
+        assert_eq!(text_screen_to_string(&screen, Some(selection)), "");
+    }
+
+    #[test]
+    fn test_export_styles() {
+        let screen = test_screen(false);
+        let ansi = String::from_utf8_lossy(&export_text_screen(&screen, TextExportFormat::Ansi)).into_owned();
+        assert!(ansi.starts_with("\x1b[0;37;40mA\x1b[0;1;37;44m<"));
+        // Blinking bright red on black.
+        assert!(ansi.contains("\x1b[0;1;5;31;40m"));
+
+        let html = String::from_utf8(export_text_screen(&screen, TextExportFormat::Html)).unwrap();
+        assert!(html.contains("&lt;"));
+        assert!(html.contains("<span class=\"blink\""));
+        assert!(html.contains('█'));
+
+        // On the MDA, attribute 01h is underlined and 70h is reverse video.
+        let mono = test_screen(true);
+        assert_eq!(CellStyle::from_attr(&mono, 0x01).underline, true);
+        assert_eq!(CellStyle::from_attr(&mono, 0x70).bg, 0x07);
+    }
+}
@@ -31,6 +31,7 @@
 */
 
 use marty_common::types::history_buffer::HistoryBuffer;
+use serde_derive::Deserialize;
 use std::{default::Default, thread};
 use web_time::{Duration, Instant};
 
@@ -38,6 +39,24 @@ const UPS_CAP: u64 = 1000; // Maximum number of window manager updates per secon
 const UPS_MIN_DURATION: Duration = Duration::from_millis(1000 / UPS_CAP as u64); // Minimum duration between window manager updates
 const DEFAULT_EMU_FPS_TARGET: u32 = 60; // Default rendering FPS for the emulator
 const FRAME_HISTORY_LEN: usize = 60; // Number of frames of history to keep
+pub const DEFAULT_FRAME_SLICES: u32 = 4; // Default number of slices per frame in low latency pacing mode
+pub const MAX_FRAME_SLICES: u32 = 16; // Maximum number of slices per frame in low latency pacing mode
+pub const MAX_FRAME_SKIP: u32 = 5; // Maximum number of consecutive frames to skip rendering
+const AUTO_SKIP_RAISE_LOAD: f64 = 0.90; // Skip more frames when load exceeds this fraction of a frame
+const AUTO_SKIP_LOWER_LOAD: f64 = 0.70; // Skip fewer frames if load would stay under this fraction of a frame
+const AUTO_SKIP_SMOOTHING: f64 = 0.1; // Weight of each new sample in the averaged frame timings
+
+/// How emulated frames are paced and presented to the host.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum FramePacing {
+    /// Run a whole emulated frame per update and present each completed frame.
+    #[default]
+    Standard,
+    /// Divide each emulated frame into slices, presenting after every slice. The front end
+    /// shows the part of the frame the guest has scanned out so far over the previous frame,
+    /// racing the beam to reduce input-to-photon latency.
+    LowLatency,
+}
 
 #[derive(Copy, Clone, Default)]
 pub struct FrameEntry {
@@ -185,6 +204,8 @@ pub struct TimestepManager {
     second_rate: HertzEvent,
     emu_render_rate: HertzEvent, // Desired rendering FPS for the emulator. This can change depending on the video card.
     emu_update_rate: HertzEvent, // Desired update rate for the emulator.
+    base_update_rate: u32,       // Update rate for the emulator before dividing frames into slices.
+    update_slices: u32,          // Number of emulator updates per frame. Greater than 1 in low latency mode.
     gui_render_rate: HertzEvent, // Desired rendering FPS for the GUI. Should be at least emu_render_rate.
     gui_update_rate: HertzEvent, // Desired update rate for the GUI. May be less than emu_fps_target.
     last_instant: Instant,
@@ -201,6 +222,14 @@ pub struct TimestepManager {
     perf_stats: PerfStats,
     total_running_time: Duration,
     frame_due: bool,
+
+    frame_skip: u32,         // Number of frames to skip rendering between rendered frames
+    auto_frame_skip: bool,   // Whether to raise frame_skip automatically when the host can't keep up
+    current_frame_skip: u32, // Frame skip in effect, including any automatic adjustment
+    frames_skipped: u32,     // Number of frames skipped since the last rendered frame
+    skip_frame: bool,        // Whether rendering of the current frame should be skipped
+    avg_emu_time: f64,       // Averaged time spent in the emulator core per frame, in seconds
+    avg_render_time: f64,    // Averaged time spent rendering a frame, in seconds
 }
 
 impl Default for TimestepManager {
@@ -210,6 +239,8 @@ impl Default for TimestepManager {
             second_rate: HertzEvent::new(1),
             emu_render_rate: HertzEvent::new(DEFAULT_EMU_FPS_TARGET),
             emu_update_rate: HertzEvent::new(DEFAULT_EMU_FPS_TARGET),
+            base_update_rate: DEFAULT_EMU_FPS_TARGET,
+            update_slices: 1,
             gui_render_rate: HertzEvent::new(DEFAULT_EMU_FPS_TARGET),
             gui_update_rate: HertzEvent::new(DEFAULT_EMU_FPS_TARGET),
             last_instant: Instant::now(),
@@ -227,6 +258,14 @@ impl Default for TimestepManager {
             perf_stats: PerfStats::default(),
 
             frame_due: false,
+
+            frame_skip: 0,
+            auto_frame_skip: false,
+            current_frame_skip: 0,
+            frames_skipped: 0,
+            skip_frame: false,
+            avg_emu_time: 0.0,
+            avg_render_time: 0.0,
         }
     }
 }
@@ -294,9 +333,25 @@ impl TimestepManager {
 
         // Handle emu frame render
         if self.emu_render_rate.tick(elapsed) {
+            // Decide whether this frame should be rendered. The render callback is still called on
+            // skipped frames so that per-frame updates run; it should check skip_frame().
+            self.skip_frame = self.frames_skipped < self.current_frame_skip;
+            if self.skip_frame {
+                self.frames_skipped += 1;
+            }
+            else {
+                self.frames_skipped = 0;
+            }
+
             let snapshot = self.perf_stats.snapshot(self.cpu_cycle_update_target);
+            let render_start = Instant::now();
             emu_render_callback(emu, &self, &snapshot);
-            self.perf_stats.wm_fps.tick();
+            if !self.skip_frame {
+                self.perf_stats.wm_fps.tick();
+                if self.auto_frame_skip {
+                    self.adjust_frame_skip(render_start.elapsed());
+                }
+            }
             self.perf_stats.frame_time = self.last_frame_instant.elapsed();
 
             self.frame_history.push(FrameEntry {
@@ -361,7 +416,16 @@ impl TimestepManager {
     }
 
     pub fn set_emu_update_rate(&mut self, fps: u32) {
-        self.emu_update_rate.set(fps);
+        self.base_update_rate = fps;
+        self.emu_update_rate.set(fps * self.update_slices);
+    }
+
+    /// Divide each emulator update into the specified number of slices, each run as a separate
+    /// update. The CPU cycle target is adjusted so that the emulated clock rate is unchanged.
+    pub fn set_update_slices(&mut self, slices: u32) {
+        self.update_slices = slices.clamp(1, MAX_FRAME_SLICES);
+        self.emu_update_rate.set(self.base_update_rate * self.update_slices);
+        self.set_cpu_mhz(self.cpu_mhz);
     }
 
     pub fn set_gui_render_rate(&mut self, fps: u32) {
@@ -372,6 +436,49 @@ impl TimestepManager {
         self.gui_update_rate.set(fps);
     }
 
+    /// Set the number of frames to skip rendering between rendered frames. If 'auto' is set, more
+    /// frames are skipped as needed, up to MAX_FRAME_SKIP, when rendering can't keep up with
+    /// the emulated frame rate. Emulation of skipped frames continues at full speed.
+    pub fn set_frame_skip(&mut self, skip: u32, auto: bool) {
+        self.frame_skip = skip.min(MAX_FRAME_SKIP);
+        self.auto_frame_skip = auto;
+        self.current_frame_skip = self.frame_skip;
+        self.frames_skipped = 0;
+    }
+
+    /// Returns true if rendering of the current frame should be skipped.
+    pub fn skip_frame(&self) -> bool {
+        self.skip_frame
+    }
+
+    /// Returns the number of frames currently being skipped between rendered frames.
+    pub fn current_frame_skip(&self) -> u32 {
+        self.current_frame_skip
+    }
+
+    /// Adjust the automatic frame skip, given the time taken to render the last frame.
+    fn adjust_frame_skip(&mut self, render_time: Duration) {
+        // Emulator updates may be divided into slices; scale to the time for a whole frame.
+        let emu_time = self.perf_stats.emu_frame_time.as_secs_f64() * self.update_slices as f64;
+        self.avg_emu_time += (emu_time - self.avg_emu_time) * AUTO_SKIP_SMOOTHING;
+        self.avg_render_time += (render_time.as_secs_f64() - self.avg_render_time) * AUTO_SKIP_SMOOTHING;
+
+        // The cost of rendering is spread over the rendered frame and the frames skipped after it.
+        let load = |skip: u32| self.avg_emu_time + self.avg_render_time / (skip + 1) as f64;
+        let target = self.frame_target.as_secs_f64();
+
+        if load(self.current_frame_skip) > target * AUTO_SKIP_RAISE_LOAD && self.current_frame_skip < MAX_FRAME_SKIP {
+            self.current_frame_skip += 1;
+            log::debug!("Host can't keep up, raising frame skip to {}", self.current_frame_skip);
+        }
+        else if self.current_frame_skip > self.frame_skip
+            && load(self.current_frame_skip - 1) < target * AUTO_SKIP_LOWER_LOAD
+        {
+            self.current_frame_skip -= 1;
+            log::debug!("Lowering frame skip to {}", self.current_frame_skip);
+        }
+    }
+
     pub fn get_perf_stats(&self) -> (&PerfStats, Vec<FrameEntry>) {
         (&self.perf_stats, self.frame_history.as_vec())
     }
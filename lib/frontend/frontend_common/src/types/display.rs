@@ -0,0 +1,106 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::display.rs
+
+   Define frontend types for display settings
+
+*/
+
+use std::path::Path;
+
+use anyhow::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::display_scaler::ScalerMode;
+
+/// How a window is made fullscreen.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum FullscreenMode {
+    /// A borderless window covering the monitor at its current desktop resolution.
+    #[default]
+    Borderless,
+    /// Take exclusive control of the monitor, optionally switching its video mode.
+    Exclusive,
+}
+
+/// Saved settings for a single display target, identified by its index.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisplayTargetSettings {
+    pub idx: usize,
+    pub scaler_mode: ScalerMode,
+}
+
+/// Saved display settings for a single machine configuration, identified by its name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MachineDisplaySettings {
+    pub name:    String,
+    #[serde(default)]
+    pub display: Vec<DisplayTargetSettings>,
+}
+
+/// Display settings for all machine configurations, persisted between sessions.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DisplaySettings {
+    #[serde(default)]
+    pub machine: Vec<MachineDisplaySettings>,
+}
+
+impl DisplaySettings {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, machine: &str) -> Option<&MachineDisplaySettings> {
+        self.machine.iter().find(|m| m.name == machine)
+    }
+
+    /// Update the scaler mode of a display target for a machine, adding entries as needed.
+    pub fn set_scaler_mode(&mut self, machine: &str, idx: usize, scaler_mode: ScalerMode) {
+        let machine_idx = match self.machine.iter().position(|m| m.name == machine) {
+            Some(machine_idx) => machine_idx,
+            None => {
+                self.machine.push(MachineDisplaySettings {
+                    name:    machine.to_string(),
+                    display: Vec::new(),
+                });
+                self.machine.len() - 1
+            }
+        };
+
+        let displays = &mut self.machine[machine_idx].display;
+        match displays.iter_mut().find(|d| d.idx == idx) {
+            Some(existing) => existing.scaler_mode = scaler_mode,
+            None => displays.push(DisplayTargetSettings { idx, scaler_mode }),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use serde_derive::Deserialize;
 use std::{ffi::OsString, path::PathBuf};
 
 #[derive(Clone)]
@@ -12,3 +13,15 @@ pub enum FloppyImageSource {
     ZipArchive(Vec<u8>, PathBuf),
     KryoFluxSet(Vec<u8>, PathBuf),
 }
+
+/// What happens to the guest's changes to a floppy image mounted from a file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum FloppyWriteBack {
+    /// Changes are kept in memory until the image is saved from the drive's menu.
+    #[default]
+    Manual,
+    /// Changes are written back to the image file shortly after the guest writes them.
+    Auto,
+    /// Changes are thrown away when the image is ejected. The image file is never written.
+    Discard,
+}
@@ -0,0 +1,98 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::gamepad.rs
+
+   Define frontend types for mapping host gamepads to emulated devices.
+
+*/
+
+use marty_core::keys::MartyKey;
+use serde_derive::Deserialize;
+
+/// A host gamepad axis, independent of the input library used to read it.
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Eq, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftZ,
+    RightZ,
+}
+
+/// A host gamepad button, using position-based names.
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Eq, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Map a host axis onto an axis of an emulated game port controller.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadAxisMapping {
+    pub axis: GamepadAxis,
+    #[serde(default)]
+    pub controller: usize,
+    pub joy_axis: usize,
+    #[serde(default)]
+    pub invert: bool,
+}
+
+/// Map a host button onto a game port button, a keyboard key, or both.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadButtonMapping {
+    pub button: GamepadButton,
+    #[serde(default)]
+    pub controller: usize,
+    pub joy_button: Option<usize>,
+    pub key: Option<MartyKey>,
+}
+
+/// A named set of gamepad mappings, typically one per game.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadProfile {
+    pub name: String,
+    pub deadzone: Option<f64>,
+    #[serde(default)]
+    pub axes: Vec<GamepadAxisMapping>,
+    #[serde(default)]
+    pub buttons: Vec<GamepadButtonMapping>,
+}
@@ -42,3 +42,13 @@ pub enum MartyGuiTheme {
     Purple,
     Cobalt,
 }
+
+/// The corner of the display in which on-screen display messages appear.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum OsdPosition {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
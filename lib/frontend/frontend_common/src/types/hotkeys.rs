@@ -30,16 +30,33 @@
 
 */
 
+use std::path::Path;
+
+use anyhow::Error;
 use marty_core::keys::MartyKey;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, EnumIter, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, EnumIter, Serialize, Deserialize)]
 pub enum HotkeyEvent {
     CaptureMouse,
     CtrlAltDel,
     Reboot,
+    Pause,
+    ToggleTurbo,
+    MountFloppy,
+    NextDiskA,
+    NextDiskB,
+    ToggleMacroRecording,
+    PlayMacro,
+    Paste,
+    CopyScreen,
+    SaveState,
+    LoadState,
+    Rewind,
     Screenshot,
+    ToggleAudioCapture,
+    ToggleVideoCapture,
     ToggleGui,
     ToggleFullscreen,
     DebugStep,
@@ -53,7 +70,7 @@ pub enum HotkeyEvent {
     JoyDown,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum HotkeyScope {
     Any,
     Gui,
@@ -61,10 +78,104 @@ pub enum HotkeyScope {
     Captured,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+impl HotkeyScope {
+    /// Returns true if a hotkey in this scope and a hotkey in the other scope can be triggered by
+    /// the same keypress.
+    pub fn overlaps(&self, other: HotkeyScope) -> bool {
+        match (*self, other) {
+            (HotkeyScope::Any, _) | (_, HotkeyScope::Any) => true,
+            (HotkeyScope::Gui, HotkeyScope::Machine) | (HotkeyScope::Machine, HotkeyScope::Gui) => false,
+            (HotkeyScope::Gui, HotkeyScope::Captured) | (HotkeyScope::Captured, HotkeyScope::Gui) => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HotkeyConfigEntry {
     pub event: HotkeyEvent,
     pub keys: Vec<MartyKey>,
     pub capture_disable: bool,
     pub scope: HotkeyScope,
 }
+
+/// Hotkey bindings changed in the hotkey editor, persisted between sessions. These override the
+/// bindings for the same events given in the main configuration file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HotkeyBindings {
+    #[serde(default)]
+    pub hotkey: Vec<HotkeyConfigEntry>,
+}
+
+impl HotkeyBindings {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Find pairs of hotkeys bound to the same key combination in overlapping scopes. Such hotkeys
+/// fire together, which is rarely intended.
+pub fn find_conflicts(entries: &[HotkeyConfigEntry]) -> Vec<(HotkeyEvent, HotkeyEvent)> {
+    let same_keys = |a: &[MartyKey], b: &[MartyKey]| a.len() == b.len() && a.iter().all(|k| b.contains(k));
+
+    let mut conflicts = Vec::new();
+    for (i, a) in entries.iter().enumerate() {
+        if a.keys.is_empty() {
+            continue;
+        }
+        for b in entries[i + 1..].iter() {
+            if a.event != b.event && a.scope.overlaps(b.scope) && same_keys(&a.keys, &b.keys) {
+                conflicts.push((a.event, b.event));
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(event: HotkeyEvent, keys: &[MartyKey], scope: HotkeyScope) -> HotkeyConfigEntry {
+        HotkeyConfigEntry {
+            event,
+            keys: keys.to_vec(),
+            capture_disable: false,
+            scope,
+        }
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let entries = vec![
+            entry(
+                HotkeyEvent::Screenshot,
+                &[MartyKey::ControlLeft, MartyKey::F5],
+                HotkeyScope::Any,
+            ),
+            entry(
+                HotkeyEvent::Pause,
+                &[MartyKey::F5, MartyKey::ControlLeft],
+                HotkeyScope::Machine,
+            ),
+            entry(HotkeyEvent::DebugStep, &[MartyKey::F11], HotkeyScope::Gui),
+            entry(HotkeyEvent::ToggleTurbo, &[MartyKey::F11], HotkeyScope::Machine),
+            entry(
+                HotkeyEvent::Reboot,
+                &[MartyKey::ControlLeft, MartyKey::F12],
+                HotkeyScope::Any,
+            ),
+            entry(HotkeyEvent::MountFloppy, &[], HotkeyScope::Any),
+            entry(HotkeyEvent::ToggleGui, &[], HotkeyScope::Any),
+        ];
+
+        let conflicts = find_conflicts(&entries);
+        assert_eq!(conflicts, vec![(HotkeyEvent::Screenshot, HotkeyEvent::Pause)]);
+    }
+}
@@ -0,0 +1,311 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::keyboard_layout.rs
+
+   Translate host key presses for the guest, taking the host and guest
+   keyboard layouts into account.
+
+   By default, host keys are translated by position, so a user with a host
+   layout that differs from the guest's will get the characters printed on
+   the guest's keyboard, not their own. In character mode, keys that produce
+   a character on the host are instead translated into the key press (and
+   any modifiers) that produce the same character on the selected guest
+   layout.
+
+   Guest layouts are defined on the positions of a 102-key keyboard. The
+   83-key keyboard has no key left of Z, so characters only available on
+   that key cannot be typed on an XT.
+
+*/
+
+use std::collections::HashMap;
+
+use marty_core::keys::MartyKey;
+use serde_derive::Deserialize;
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum KeyTranslationMode {
+    /// Translate keys by position, applying any fixups from the keyboard layout file.
+    #[default]
+    Positional,
+    /// Translate keys by the character they produce on the host layout.
+    Character,
+    /// Translate keys by position only. The keyboard layout file is not used.
+    Raw,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyAction {
+    Press(MartyKey),
+    Release(MartyKey),
+}
+
+#[rustfmt::skip]
+const LAYOUT_ROWS: [&[MartyKey]; 4] = [
+    &[
+        MartyKey::Backquote, MartyKey::Digit1, MartyKey::Digit2, MartyKey::Digit3, MartyKey::Digit4,
+        MartyKey::Digit5, MartyKey::Digit6, MartyKey::Digit7, MartyKey::Digit8, MartyKey::Digit9,
+        MartyKey::Digit0, MartyKey::Minus, MartyKey::Equal,
+    ],
+    &[
+        MartyKey::KeyQ, MartyKey::KeyW, MartyKey::KeyE, MartyKey::KeyR, MartyKey::KeyT, MartyKey::KeyY,
+        MartyKey::KeyU, MartyKey::KeyI, MartyKey::KeyO, MartyKey::KeyP, MartyKey::BracketLeft,
+        MartyKey::BracketRight, MartyKey::Backslash,
+    ],
+    &[
+        MartyKey::KeyA, MartyKey::KeyS, MartyKey::KeyD, MartyKey::KeyF, MartyKey::KeyG, MartyKey::KeyH,
+        MartyKey::KeyJ, MartyKey::KeyK, MartyKey::KeyL, MartyKey::Semicolon, MartyKey::Quote,
+    ],
+    &[
+        MartyKey::IntlBackslash, MartyKey::KeyZ, MartyKey::KeyX, MartyKey::KeyC, MartyKey::KeyV,
+        MartyKey::KeyB, MartyKey::KeyN, MartyKey::KeyM, MartyKey::Comma, MartyKey::Period, MartyKey::Slash,
+    ],
+];
+
+/// A guest layout, given as the characters produced by each key in LAYOUT_ROWS when pressed
+/// alone, with Shift, and with AltGr. A space indicates that no character is produced.
+/// Dead keys are omitted, as they don't produce a character by themselves.
+struct GuestLayoutDef {
+    name: &'static str,
+    rows: [[&'static str; 3]; 4],
+}
+
+#[rustfmt::skip]
+const GUEST_LAYOUTS: [GuestLayoutDef; 5] = [
+    GuestLayoutDef {
+        name: "US",
+        rows: [
+            ["`1234567890-=", "~!@#$%^&*()_+", ""],
+            ["qwertyuiop[]\\", "QWERTYUIOP{}|", ""],
+            ["asdfghjkl;'", "ASDFGHJKL:\"", ""],
+            [" zxcvbnm,./", " ZXCVBNM<>?", ""],
+        ],
+    },
+    GuestLayoutDef {
+        name: "UK",
+        rows: [
+            ["`1234567890-=", "¬!\"£$%^&*()_+", "¦"],
+            ["qwertyuiop[]#", "QWERTYUIOP{}~", ""],
+            ["asdfghjkl;'", "ASDFGHJKL:@", ""],
+            ["\\zxcvbnm,./", "|ZXCVBNM<>?", ""],
+        ],
+    },
+    GuestLayoutDef {
+        name: "DE",
+        rows: [
+            [" 1234567890ß ", "°!\"§$%&/()=? ", "  ²³   {[]}\\ "],
+            ["qwertzuiopü+#", "QWERTZUIOPÜ*'", "@          ~ "],
+            ["asdfghjklöä", "ASDFGHJKLÖÄ", ""],
+            ["<yxcvbnm,.-", ">YXCVBNM;:_", "|      µ"],
+        ],
+    },
+    GuestLayoutDef {
+        name: "FR",
+        rows: [
+            ["²&é\"'(-è_çà)=", " 1234567890°+", "  ~#{[|`\\^@]}"],
+            ["azertyuiop $*", "AZERTYUIOP £µ", ""],
+            ["qsdfghjklmù", "QSDFGHJKLM%", ""],
+            ["<wxcvbn,;:!", ">WXCVBN?./§", ""],
+        ],
+    },
+    GuestLayoutDef {
+        name: "IT",
+        rows: [
+            ["\\1234567890'ì", "|!\"£$%&/()=?^", ""],
+            ["qwertyuiopè+ù", "QWERTYUIOPé*§", "          []"],
+            ["asdfghjklòà", "ASDFGHJKLç°", "         @#"],
+            ["<zxcvbnm,.-", ">ZXCVBNM;:_", ""],
+        ],
+    },
+];
+
+/// The guest key and modifiers required to produce a character.
+#[derive(Copy, Clone, Debug)]
+struct GuestKey {
+    key:   MartyKey,
+    shift: bool,
+    altgr: bool,
+}
+
+pub struct KeyTranslator {
+    mode: KeyTranslationMode,
+    layout_name: &'static str,
+    char_map: HashMap<char, GuestKey>,
+    held_modifiers: Vec<MartyKey>,
+    pressed: HashMap<MartyKey, MartyKey>, // Host key -> guest key pressed on its behalf
+}
+
+impl KeyTranslator {
+    /// Create a new translator. If the named guest layout isn't known, the US layout is used.
+    pub fn new(mode: KeyTranslationMode, guest_layout: &str) -> Self {
+        let layout = GUEST_LAYOUTS
+            .iter()
+            .find(|l| l.name.eq_ignore_ascii_case(guest_layout))
+            .unwrap_or_else(|| {
+                log::warn!("Unknown guest keyboard layout '{}', using US", guest_layout);
+                &GUEST_LAYOUTS[0]
+            });
+
+        // Prefer the unmodified key, then Shift, then AltGr, when a character appears more than once.
+        let mut char_map = HashMap::new();
+        for level in 0..3 {
+            for (row, keys) in LAYOUT_ROWS.iter().enumerate() {
+                for (c, key) in layout.rows[row][level].chars().zip(keys.iter()) {
+                    if c != ' ' {
+                        char_map.entry(c).or_insert(GuestKey {
+                            key:   *key,
+                            shift: level == 1,
+                            altgr: level == 2,
+                        });
+                    }
+                }
+            }
+        }
+
+        Self {
+            mode,
+            layout_name: layout.name,
+            char_map,
+            held_modifiers: Vec::new(),
+            pressed: HashMap::new(),
+        }
+    }
+
+    pub fn guest_layouts() -> Vec<&'static str> {
+        GUEST_LAYOUTS.iter().map(|l| l.name).collect()
+    }
+
+    pub fn mode(&self) -> KeyTranslationMode {
+        self.mode
+    }
+
+    pub fn layout_name(&self) -> &'static str {
+        self.layout_name
+    }
+
+    /// Translate a host key press. `character` is the character the key produces on the host
+    /// layout, if any, not accounting for Control.
+    pub fn key_down(&mut self, key: MartyKey, character: Option<char>) -> Vec<KeyAction> {
+        if Self::is_modifier(key) {
+            if !self.held_modifiers.contains(&key) {
+                self.held_modifiers.push(key);
+            }
+            return vec![KeyAction::Press(key)];
+        }
+
+        let guest_key = match (self.mode, character) {
+            (KeyTranslationMode::Character, Some(c)) if Self::is_layout_key(key) => self.char_map.get(&c).copied(),
+            _ => None,
+        };
+
+        match guest_key {
+            Some(guest_key) => {
+                self.pressed.insert(key, guest_key.key);
+                self.press_with_modifiers(guest_key)
+            }
+            None => {
+                self.pressed.insert(key, key);
+                vec![KeyAction::Press(key)]
+            }
+        }
+    }
+
+    /// Translate a host key release, releasing whichever guest key was pressed for it.
+    pub fn key_up(&mut self, key: MartyKey) -> Vec<KeyAction> {
+        if Self::is_modifier(key) {
+            self.held_modifiers.retain(|k| *k != key);
+            return vec![KeyAction::Release(key)];
+        }
+        vec![KeyAction::Release(self.pressed.remove(&key).unwrap_or(key))]
+    }
+
+    /// Press a guest key, adjusting the modifiers the guest sees to match those the guest
+    /// layout requires, and then restoring them to match the host.
+    fn press_with_modifiers(&self, guest_key: GuestKey) -> Vec<KeyAction> {
+        let mut actions = Vec::new();
+        let mut released = Vec::new();
+        let mut pressed = Vec::new();
+
+        let held = |keys: &[MartyKey]| -> Vec<MartyKey> {
+            self.held_modifiers
+                .iter()
+                .copied()
+                .filter(|k| keys.contains(k))
+                .collect()
+        };
+        let shift_keys = held(&[MartyKey::ShiftLeft, MartyKey::ShiftRight]);
+        let ctrl_keys = held(&[MartyKey::ControlLeft, MartyKey::ControlRight]);
+        let alt_keys = held(&[MartyKey::AltLeft, MartyKey::AltRight]);
+
+        if guest_key.shift {
+            if shift_keys.is_empty() {
+                pressed.push(MartyKey::ShiftLeft);
+            }
+        }
+        else {
+            released.extend(shift_keys);
+        }
+
+        if guest_key.altgr {
+            // The 83-key keyboard has no AltGr, so use Ctrl-Alt, which KEYB also accepts.
+            if ctrl_keys.is_empty() {
+                pressed.push(MartyKey::ControlLeft);
+            }
+            if alt_keys.is_empty() {
+                pressed.push(MartyKey::AltLeft);
+            }
+        }
+        else if alt_keys.contains(&MartyKey::AltRight) {
+            // The host used AltGr to produce this character. Some hosts report AltGr as Ctrl-AltGr.
+            released.push(MartyKey::AltRight);
+            released.extend(ctrl_keys);
+        }
+
+        actions.extend(released.iter().map(|k| KeyAction::Release(*k)));
+        actions.extend(pressed.iter().map(|k| KeyAction::Press(*k)));
+        actions.push(KeyAction::Press(guest_key.key));
+        actions.extend(pressed.iter().rev().map(|k| KeyAction::Release(*k)));
+        actions.extend(released.iter().map(|k| KeyAction::Press(*k)));
+        actions
+    }
+
+    fn is_modifier(key: MartyKey) -> bool {
+        matches!(
+            key,
+            MartyKey::ShiftLeft
+                | MartyKey::ShiftRight
+                | MartyKey::ControlLeft
+                | MartyKey::ControlRight
+                | MartyKey::AltLeft
+                | MartyKey::AltRight
+        )
+    }
+
+    fn is_layout_key(key: MartyKey) -> bool {
+        LAYOUT_ROWS.iter().any(|row| row.contains(&key))
+    }
+}
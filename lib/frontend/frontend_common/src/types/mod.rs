@@ -30,10 +30,15 @@
 
 */
 
+pub mod display;
 pub mod display_target_dimensions;
 pub mod display_target_margins;
 pub mod floppy;
+pub mod gamepad;
 pub mod gui;
 pub mod hotkeys;
 pub mod joykeys;
+pub mod keyboard_layout;
+pub mod session;
 pub mod sound;
+pub mod video_capture;
@@ -0,0 +1,190 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::session.rs
+
+   Define frontend types for session state: the media mounted and the
+   windows open when the emulator exits, restored on the next launch.
+
+*/
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use serde_derive::{Deserialize, Serialize};
+
+/// Where the disk in a floppy drive came from.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FloppyMediaSource {
+    Image,
+    ZipArchive,
+    Directory,
+}
+
+/// A floppy disk mounted in a drive.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FloppySession {
+    pub drive: usize,
+    pub source: FloppyMediaSource,
+    pub path: PathBuf,
+    /// The disk image mounted from a zip archive. If None, the disk was built from the files in
+    /// the archive.
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub write_protect: bool,
+}
+
+/// A VHD image mounted in a hard disk, by its file name in the 'hdd' resource.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HddSession {
+    pub drive:    usize,
+    pub filename: String,
+}
+
+/// The position and size of the main window, in physical pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    /// The position of the window's top left corner. Not every platform reports it.
+    #[serde(default)]
+    pub position: Option<[i32; 2]>,
+    pub size: [u32; 2],
+    #[serde(default)]
+    pub maximized: bool,
+}
+
+/// A GUI window left open, by the name of its GuiWindow variant.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GuiWindowSession {
+    pub window: String,
+    pub pos:    [f32; 2],
+    pub width:  f32,
+}
+
+/// Session state for a single machine configuration, identified by its name.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MachineSession {
+    pub name: String,
+    #[serde(default)]
+    pub window: Option<WindowGeometry>,
+    #[serde(default)]
+    pub floppy: Vec<FloppySession>,
+    #[serde(default)]
+    pub hdd: Vec<HddSession>,
+    #[serde(default)]
+    pub gui_window: Vec<GuiWindowSession>,
+}
+
+/// Session state for all machine configurations, persisted between sessions.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    #[serde(default)]
+    pub machine: Vec<MachineSession>,
+}
+
+impl SessionState {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, machine: &str) -> Option<&MachineSession> {
+        self.machine.iter().find(|m| m.name == machine)
+    }
+
+    /// Replace the session state of a machine, adding it if needed.
+    pub fn set(&mut self, session: MachineSession) {
+        match self.machine.iter_mut().find(|m| m.name == session.name) {
+            Some(existing) => *existing = session,
+            None => self.machine.push(session),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut state = SessionState::default();
+        state.set(MachineSession {
+            name: "ibm5160".to_string(),
+            window: Some(WindowGeometry {
+                position: None,
+                size: [1024, 768],
+                maximized: false,
+            }),
+            floppy: vec![
+                FloppySession {
+                    drive: 0,
+                    source: FloppyMediaSource::Image,
+                    path: PathBuf::from("floppy/dos.img"),
+                    image: None,
+                    write_protect: true,
+                },
+                FloppySession {
+                    drive: 1,
+                    source: FloppyMediaSource::ZipArchive,
+                    path: PathBuf::from("floppy/game.zip"),
+                    image: Some("DISK1.IMG".to_string()),
+                    write_protect: true,
+                },
+            ],
+            hdd: vec![HddSession {
+                drive:    0,
+                filename: "hdd.vhd".to_string(),
+            }],
+            gui_window: vec![GuiWindowSession {
+                window: "MemoryViewer".to_string(),
+                pos:    [10.0, 40.0],
+                width:  400.0,
+            }],
+        });
+        state.set(MachineSession {
+            name: "ibm5150_64k".to_string(),
+            ..Default::default()
+        });
+
+        let toml_str = toml::to_string(&state).unwrap();
+        let loaded: SessionState = toml::from_str(&toml_str).unwrap();
+        assert_eq!(loaded.machine, state.machine);
+
+        // Setting a machine's session replaces what was saved for it.
+        let mut state = loaded;
+        state.set(MachineSession {
+            name: "ibm5160".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(state.machine.len(), 2);
+        assert!(state.get("ibm5160").unwrap().floppy.is_empty());
+    }
+}
@@ -30,6 +30,55 @@
 
 */
 
+use std::path::Path;
+
+use anyhow::Error;
+use serde_derive::{Deserialize, Serialize};
+
+/// The interpolation used when converting a sound source to the output sample rate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ResamplerQuality {
+    #[default]
+    Linear,
+    Sinc,
+}
+
+impl ResamplerQuality {
+    pub const ALL: [ResamplerQuality; 2] = [ResamplerQuality::Linear, ResamplerQuality::Sinc];
+}
+
+impl std::fmt::Display for ResamplerQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResamplerQuality::Linear => write!(f, "Linear"),
+            ResamplerQuality::Sinc => write!(f, "Sinc"),
+        }
+    }
+}
+
+/// Options for opening the audio output device.
+#[derive(Clone, Debug, Default)]
+pub struct AudioOutputOptions {
+    /// Requested device sample rate. The device default is used if not set or unsupported.
+    pub sample_rate: Option<u32>,
+    /// Requested device buffer size, in frames.
+    pub buffer_size: Option<u32>,
+    /// Maximum audio queued per source before new samples are dropped to catch up.
+    pub max_latency_ms: Option<u32>,
+    pub resampler: ResamplerQuality,
+}
+
+/// Statistics for the audio output device.
+#[derive(Clone, Debug, Default)]
+pub struct AudioOutputStats {
+    pub device_name: String,
+    pub sample_rate: u32,
+    pub sample_format: String,
+    pub buffer_size: u32,
+    pub max_latency_ms: Option<u32>,
+    pub resampler: ResamplerQuality,
+}
+
 #[derive(Clone, Debug)]
 pub struct SoundSourceStats {
     pub name: String,
@@ -37,4 +86,55 @@ pub struct SoundSourceStats {
     pub channels: u16,
     pub sample_ct: u64,
     pub volume: f32,
+    pub pan: f32,
+    pub muted: bool,
+    /// Audio currently queued for playback, in milliseconds.
+    pub latency_ms: f32,
+    /// Number of times playback ran out of samples while the source was producing.
+    pub underruns: u64,
+    /// Number of sample blocks dropped to keep latency under the configured maximum.
+    pub dropped: u64,
+}
+
+/// Mixer settings for a single sound source, identified by the source's name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MixerChannelConfig {
+    pub name:   String,
+    pub volume: f32,
+    /// Stereo position from -1.0 (left) to 1.0 (right).
+    #[serde(default)]
+    pub pan:    f32,
+    #[serde(default)]
+    pub muted:  bool,
+}
+
+/// Mixer settings for all sound sources, persisted between sessions.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MixerConfig {
+    #[serde(default)]
+    pub channel: Vec<MixerChannelConfig>,
+}
+
+impl MixerConfig {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MixerChannelConfig> {
+        self.channel.iter().find(|c| c.name == name)
+    }
+
+    /// Update the settings for a channel, adding it if not present.
+    pub fn set(&mut self, channel: MixerChannelConfig) {
+        match self.channel.iter_mut().find(|c| c.name == channel.name) {
+            Some(existing) => *existing = channel,
+            None => self.channel.push(channel),
+        }
+    }
 }
@@ -0,0 +1,75 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::video_capture.rs
+
+   Define frontend types for video capture.
+
+*/
+
+use serde_derive::{Deserialize, Serialize};
+
+/// The container and codec used when recording emulated video output.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum VideoCaptureFormat {
+    /// H.264 video in an MP4 container, encoded by ffmpeg.
+    #[default]
+    Mp4,
+    /// Lossless FFV1 video in a Matroska container, encoded by ffmpeg.
+    Ffv1,
+    /// Animated GIF with an optimized palette, encoded by ffmpeg.
+    Gif,
+    /// A directory of numbered PNG files, one per emulated frame.
+    PngSequence,
+}
+
+impl VideoCaptureFormat {
+    /// Returns the file extension for this format, or None if the format is written to a directory.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            VideoCaptureFormat::Mp4 => Some("mp4"),
+            VideoCaptureFormat::Ffv1 => Some("mkv"),
+            VideoCaptureFormat::Gif => Some("gif"),
+            VideoCaptureFormat::PngSequence => None,
+        }
+    }
+
+    /// Returns true if this format is encoded by an external ffmpeg process.
+    pub fn uses_ffmpeg(&self) -> bool {
+        !matches!(self, VideoCaptureFormat::PngSequence)
+    }
+}
+
+impl std::fmt::Display for VideoCaptureFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoCaptureFormat::Mp4 => write!(f, "MP4"),
+            VideoCaptureFormat::Ffv1 => write!(f, "FFV1"),
+            VideoCaptureFormat::Gif => write!(f, "GIF"),
+            VideoCaptureFormat::PngSequence => write!(f, "PNG Sequence"),
+        }
+    }
+}
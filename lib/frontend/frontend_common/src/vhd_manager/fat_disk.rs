@@ -0,0 +1,151 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    frontend_common::vhd_manager::fat_disk.rs
+
+    Build formatted hard disk images: a master boot record holding a single
+    FAT partition that fills the disk, as FDISK and FORMAT would leave it.
+
+    Partitions small enough for FAT12 are formatted that way, so that DOS
+    2.x can use them. Larger partitions are formatted FAT16.
+*/
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{Cursor, Write},
+    path::Path,
+};
+
+use anyhow::Error;
+use marty_core::{
+    device_types::hdc::HardDiskFormat,
+    vhd::{create_vhd, VHD_SECTOR_SIZE},
+};
+
+use crate::floppy_manager::{create_drive_label, read_host_dir, write_host_dir};
+
+/// FDISK formats partitions of fewer sectors than this FAT12.
+const MAX_FAT12_PARTITION_SECTORS: usize = 32680;
+/// DOS versions before 4.0 can't use a FAT16 partition of more than 65535 sectors.
+pub const MAX_SMALL_PARTITION_SECTORS: usize = 0xFFFF;
+const PARTITION_TYPE_FAT12: u8 = 0x01;
+const PARTITION_TYPE_FAT16: u8 = 0x04;
+const PARTITION_TYPE_FAT16B: u8 = 0x06;
+const PARTITION_TABLE_OFFSET: usize = 0x1BE;
+const BPB_HIDDEN_SECTORS_OFFSET: usize = 0x1C;
+
+/// Return the byte offset and length of the partition on a disk of the specified format. The
+/// partition starts on the second track, as FDISK would place it.
+pub fn partition_range(format: &HardDiskFormat) -> (usize, usize) {
+    let offset = format.max_sectors as usize * VHD_SECTOR_SIZE;
+    (offset, format.get_size() - offset)
+}
+
+/// Create a VHD at the specified path, holding a single formatted partition. If a host directory
+/// is given, its files are copied onto the partition. Returns the VHD file, opened for reading and
+/// writing.
+pub fn create_formatted_vhd(
+    path: &Path,
+    format: &HardDiskFormat,
+    label: &str,
+    host_dir: Option<&Path>,
+) -> Result<File, Error> {
+    let image = build_fat_disk(format, label, host_dir)?;
+    create_vhd(
+        OsString::from(path),
+        format.max_cylinders,
+        format.max_heads,
+        format.max_sectors,
+    )?;
+
+    let mut vhd_file = File::options().read(true).write(true).open(path)?;
+    // Only the sectors are written. The footer written by create_vhd() is left in place.
+    vhd_file.write_all(&image)?;
+    vhd_file.flush()?;
+    Ok(vhd_file)
+}
+
+/// Build the sectors of a disk of the specified format: a master boot record holding the partition
+/// table, followed by the formatted partition.
+pub fn build_fat_disk(format: &HardDiskFormat, label: &str, host_dir: Option<&Path>) -> Result<Vec<u8>, Error> {
+    let (partition_offset, partition_len) = partition_range(format);
+    let partition_sectors = partition_len / VHD_SECTOR_SIZE;
+    let (fat_type, partition_type) = match partition_sectors {
+        sectors if sectors < MAX_FAT12_PARTITION_SECTORS => (fatfs::FatType::Fat12, PARTITION_TYPE_FAT12),
+        sectors if sectors <= MAX_SMALL_PARTITION_SECTORS => (fatfs::FatType::Fat16, PARTITION_TYPE_FAT16),
+        _ => (fatfs::FatType::Fat16, PARTITION_TYPE_FAT16B),
+    };
+
+    let mut partition_buf = fatfs::StdIoWrapper::new(Cursor::new(vec![0u8; partition_len]));
+    fatfs::format_volume(
+        &mut partition_buf,
+        fatfs::FormatVolumeOptions::new()
+            .fat_type(fat_type)
+            .volume_label(create_drive_label(label))
+            .bytes_per_sector(VHD_SECTOR_SIZE as u16)
+            .sectors_per_track(format.max_sectors as u16)
+            .heads(format.max_heads as u16)
+            .media(0xF8)
+            .drive_num(0x80),
+    )?;
+    let mut partition_buf = partition_buf.into_inner();
+
+    if let Some(host_dir) = host_dir {
+        let vfat = fatfs::FileSystem::new(&mut partition_buf, fatfs::FsOptions::new())
+            .map_err(|e| anyhow::anyhow!("Error creating FAT filesystem: {:?}", e))?;
+        write_host_dir(&read_host_dir(host_dir)?, &vfat.root_dir())?;
+        vfat.unmount()?;
+    }
+
+    let mut partition = partition_buf.into_inner();
+    // DOS locates the partition's first sector by the hidden sector count in the boot sector.
+    let hidden_sectors = (partition_offset / VHD_SECTOR_SIZE) as u32;
+    partition[BPB_HIDDEN_SECTORS_OFFSET..BPB_HIDDEN_SECTORS_OFFSET + 4].copy_from_slice(&hidden_sectors.to_le_bytes());
+
+    let mut image = vec![0u8; partition_offset];
+    // The disk isn't bootable. If the BIOS tries to boot it anyway, the boot code hands off to
+    // INT 18h, which starts ROM BASIC or reports a boot failure.
+    image[0..2].copy_from_slice(&[0xCD, 0x18]);
+
+    let last_cylinder = format.max_cylinders - 1;
+    let entry = &mut image[PARTITION_TABLE_OFFSET..PARTITION_TABLE_OFFSET + 16];
+    // Active flag, then the CHS address of the first sector: cylinder 0, head 1, sector 1.
+    entry[0..4].copy_from_slice(&[0x80, 1, 1, 0]);
+    entry[4] = partition_type;
+    // The CHS address of the last sector, with cylinder bits 8-9 in the top of the sector byte.
+    entry[5] = format.max_heads - 1;
+    entry[6] = format.max_sectors | ((last_cylinder >> 2) as u8 & 0xC0);
+    entry[7] = last_cylinder as u8;
+    entry[8..12].copy_from_slice(&hidden_sectors.to_le_bytes());
+    entry[12..16].copy_from_slice(&(partition_sectors as u32).to_le_bytes());
+    image[510] = 0x55;
+    image[511] = 0xAA;
+
+    image.extend_from_slice(&partition);
+    Ok(image)
+}
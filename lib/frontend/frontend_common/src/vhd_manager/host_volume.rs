@@ -0,0 +1,262 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::vhd_manager::host_volume.rs
+
+    Synthesize a hard disk holding a single FAT partition from a host
+    directory, so that files edited on the host can be run in the guest.
+
+    The disk is built into a temporary VHD, since the core reads and writes
+    VHD files directly. In write-back mode, the partition can be read back
+    and the files the guest created or changed are copied to the host
+    directory. Files deleted by the guest are left on the host.
+*/
+
+use std::{
+    fs,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, Error};
+use fatfs::{Dir, OemCpConverter, TimeProvider};
+use marty_core::{
+    device_types::hdc::HardDiskFormat,
+    vhd::{VirtualHardDisk, VHD_SECTOR_SIZE},
+};
+
+use crate::{
+    floppy_manager::sanitize_label,
+    vhd_manager::fat_disk::{create_formatted_vhd, partition_range, MAX_SMALL_PARTITION_SECTORS},
+};
+
+// Each mount gets its own image file, so that a remount never has to replace an image that is
+// still open.
+static IMAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct HostVolume {
+    dir: PathBuf,
+    image_path: PathBuf,
+    write_back: bool,
+    partition_offset: usize,
+    partition_len: usize,
+}
+
+impl HostVolume {
+    /// Build a hard disk from the specified host directory, using the largest of the controller's
+    /// supported formats that DOS 3.x can use. The returned disk is ready to be mounted.
+    pub fn new(
+        dir: &Path,
+        formats: &[HardDiskFormat],
+        write_back: bool,
+    ) -> Result<(HostVolume, VirtualHardDisk), Error> {
+        if !dir.is_dir() {
+            return Err(anyhow!("Directory not found: {}", dir.display()));
+        }
+        let format = select_format(formats).ok_or(anyhow!("No hard disk formats are supported."))?;
+
+        let image_path = std::env::temp_dir().join(format!(
+            "martypc_{}_volume{}.vhd",
+            std::process::id(),
+            IMAGE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let dir_name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let image_file = create_formatted_vhd(&image_path, format, &sanitize_label(&dir_name), Some(dir))?;
+
+        let (partition_offset, partition_len) = partition_range(format);
+        let volume = HostVolume {
+            dir: dir.to_path_buf(),
+            image_path,
+            write_back,
+            partition_offset,
+            partition_len,
+        };
+
+        let vhd = VirtualHardDisk::from_file(image_file)?;
+        log::debug!(
+            "Built {} hard disk from host directory {} in {}",
+            format.desc,
+            dir.display(),
+            volume.image_path.display()
+        );
+        Ok((volume, vhd))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn write_back(&self) -> bool {
+        self.write_back
+    }
+
+    /// Copy files that the guest created or changed back to the host directory. Returns the number
+    /// of files written. The guest should not be running, so that the volume is consistent.
+    pub fn sync_to_host(&self) -> Result<usize, Error> {
+        let image = fs::read(&self.image_path)?;
+        let partition = image
+            .get(self.partition_offset..self.partition_offset + self.partition_len)
+            .ok_or(anyhow!("Hard disk image was truncated: {}", self.image_path.display()))?;
+        let mut partition_buf = Cursor::new(partition.to_vec());
+
+        let vfat16 = fatfs::FileSystem::new(&mut partition_buf, fatfs::FsOptions::new())
+            .map_err(|e| anyhow!("Error reading FAT filesystem: {:?}", e))?;
+        let written = sync_host_dir(&vfat16.root_dir(), &self.dir)?;
+
+        log::debug!("Wrote {} files back to host directory {}", written, self.dir.display());
+        Ok(written)
+    }
+}
+
+impl Drop for HostVolume {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.image_path) {
+            log::warn!("Couldn't remove hard disk image {}: {}", self.image_path.display(), e);
+        }
+    }
+}
+
+/// Pick the largest format whose partition older versions of DOS can use, or the smallest format
+/// if there is none.
+fn select_format(formats: &[HardDiskFormat]) -> Option<&HardDiskFormat> {
+    formats
+        .iter()
+        .filter(|format| format.get_size() / VHD_SECTOR_SIZE <= MAX_SMALL_PARTITION_SECTORS)
+        .max_by_key(|format| format.get_size())
+        .or_else(|| formats.iter().min_by_key(|format| format.get_size()))
+}
+
+/// Return the host path for a guest file name. An existing host entry is matched regardless of
+/// case, since DOS upper-cases the names of the files it creates.
+///
+/// The names in the FAT image are under the guest's control. Returns None if the name is anything
+/// other than a single plain path component, so that it can't refer outside of the host directory.
+fn host_entry_path(host_dir: &Path, name: &str) -> Option<PathBuf> {
+    // Separators and drive prefixes from other platforms are not path syntax on this one, but
+    // are rejected all the same.
+    if name.contains(|c| matches!(c, '/' | '\\' | ':' | '\0')) {
+        return None;
+    }
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {}
+        _ => return None,
+    }
+
+    let path = fs::read_dir(host_dir)
+        .ok()
+        .and_then(|entries| {
+            entries
+                .flatten()
+                .find(|entry| entry.file_name().to_string_lossy().eq_ignore_ascii_case(name))
+        })
+        .map(|entry| entry.path())
+        .unwrap_or_else(|| host_dir.join(name));
+    Some(path)
+}
+
+/// Copy the files in a FAT directory to a host directory, recursing into subdirectories. Files
+/// whose contents already match the host are not written.
+fn sync_host_dir<IO: fatfs::Read + fatfs::Write + fatfs::Seek, TP, OCC>(
+    dir: &Dir<IO, TP, OCC>,
+    host_dir: &Path,
+) -> Result<usize, Error>
+where
+    OCC: OemCpConverter,
+    TP: TimeProvider,
+{
+    use fatfs::Read;
+
+    let mut written = 0;
+    for entry in dir.iter() {
+        let entry = entry.map_err(|e| anyhow!("Error reading directory: {:?}", e))?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+
+        let host_path = match host_entry_path(host_dir, &name) {
+            Some(path) => path,
+            None => {
+                log::error!("Not writing back guest file with invalid name: {:?}", name);
+                continue;
+            }
+        };
+        if entry.is_dir() {
+            if !host_path.is_dir() {
+                fs::create_dir(&host_path)?;
+            }
+            written += sync_host_dir(&entry.to_dir(), &host_path)?;
+        }
+        else if entry.is_file() {
+            let mut data = vec![0u8; entry.len() as usize];
+            entry
+                .to_file()
+                .read_exact(&mut data)
+                .map_err(|_| anyhow!("Failed to read file: {}", name))?;
+            if fs::read(&host_path).map_or(true, |host_data| host_data != data) {
+                fs::write(&host_path, &data)?;
+                written += 1;
+            }
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_entry_path() {
+        let dir = std::env::temp_dir().join(format!("martypc_host_volume_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Readme.txt"), b"").unwrap();
+
+        // Existing entries are matched regardless of case, new ones are created in the directory.
+        assert_eq!(host_entry_path(&dir, "README.TXT"), Some(dir.join("Readme.txt")));
+        assert_eq!(host_entry_path(&dir, "NEW.TXT"), Some(dir.join("NEW.TXT")));
+        assert_eq!(host_entry_path(&dir, "..."), Some(dir.join("...")));
+
+        // Names that could refer outside of the directory are refused.
+        for name in [
+            "",
+            ".",
+            "..",
+            "../EVIL.TXT",
+            "..\\EVIL.TXT",
+            "SUB/EVIL.TXT",
+            "/etc/passwd",
+            "\\EVIL.TXT",
+            "C:EVIL.TXT",
+            "C:\\EVIL.TXT",
+        ] {
+            assert_eq!(host_entry_path(&dir, name), None, "{:?}", name);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
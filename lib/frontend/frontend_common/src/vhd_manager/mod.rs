@@ -38,6 +38,9 @@
     to know whether it is operating on an in-memory image or file.
 */
 
+pub mod fat_disk;
+pub mod host_volume;
+
 const DRIVE_MAX: usize = 4;
 
 use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
@@ -194,6 +197,11 @@ impl VhdManager {
         false
     }
 
+    /// Return the path of the VHD image associated with the specified drive, if any.
+    pub fn drive_vhd_path(&self, drive: usize) -> Option<&PathBuf> {
+        self.drives_loaded.get(&drive)
+    }
+
     pub fn is_drive_loaded(&self, drive: usize) -> bool {
         if let Some(_entry) = self.drives_loaded.get(&drive) {
             return true;
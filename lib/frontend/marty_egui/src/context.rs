@@ -172,6 +172,12 @@ impl GuiRenderContext {
         }
     }
 
+    /// Returns whether egui is using the pointer, either because it is over a GUI window or a
+    /// widget is being dragged.
+    pub fn wants_pointer(&self) -> bool {
+        self.egui_ctx.wants_pointer_input() || self.egui_ctx.is_pointer_over_area()
+    }
+
     /// Handle input events from the window manager.
     pub fn handle_event(&mut self, window: &Window, event: &winit::event::WindowEvent) {
         #[cfg(not(target_arch = "wasm32"))]
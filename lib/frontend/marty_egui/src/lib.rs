@@ -46,6 +46,8 @@ use std::{
 use frontend_common::{
     display_manager::DisplayInfo,
     display_scaler::{ScalerMode, ScalerParams},
+    text_export::TextExportFormat,
+    types::{floppy::FloppyWriteBack, hotkeys::HotkeyEvent, sound::ResamplerQuality},
 };
 
 mod color;
@@ -57,7 +59,9 @@ mod glyphs;
 mod layouts;
 mod menu;
 pub mod modal;
+pub mod osd;
 pub mod state;
+pub mod status_bar;
 mod themes;
 mod token_listview;
 mod ui;
@@ -67,8 +71,9 @@ mod workspace;
 
 use marty_core::{
     device_traits::videocard::DisplayApertureType,
-    device_types::hdc::HardDiskFormat,
+    device_types::{fdc::FloppyImageType, hdc::HardDiskFormat},
     devices::pic::PicStringState,
+    keys::MartyKey,
     machine::MachineState,
 };
 
@@ -81,6 +86,7 @@ pub enum GuiWindow {
     About,
     CpuControl,
     PerfViewer,
+    AudioMixer,
     MemoryViewer,
     CompositeAdjust,
     ScalerAdjust,
@@ -95,15 +101,28 @@ pub enum GuiWindow {
     SerialViewer,
     PicViewer,
     PpiViewer,
+    DipSwitchEditor,
+    PostCardViewer,
     DmaViewer,
     VideoCardViewer,
+    VideoRegisterLog,
+    VramViewer,
+    CharacterSetViewer,
+    PaletteViewer,
+    CrtcTimingViewer,
     DataVisualizer,
     CallStack,
     VHDCreator,
+    FloppyCreator,
+    FloppyConverter,
     CycleTraceViewer,
     TextModeViewer,
     FdcViewer,
     FloppyViewer,
+    DebugConsole,
+    HotkeyEditor,
+    SaveStateBrowser,
+    MediaLibrary,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -127,6 +146,7 @@ pub enum GuiBoolean {
     TurboButton,
     ShowBackBuffer,
     ShowRasterPosition,
+    ShowRasterRegions,
 }
 
 // Enums are hashed with a tuple of GuiEnumContext and their base discriminant.
@@ -172,16 +192,24 @@ type GuiEnumMap = HashMap<(GuiVariableContext, Discriminant<GuiEnum>), GuiEnum>;
 pub enum GuiEvent {
     LoadVHD(usize, usize),
     DetachVHD(usize),
-    CreateVHD(OsString, HardDiskFormat),
+    MountHddDirectory(usize, PathBuf, bool), // Drive index, directory, write back guest changes
+    CreateVHD(OsString, HardDiskFormat, Option<String>), // Filename, geometry, volume label or None to leave unformatted
     LoadQuickFloppy(usize, usize),
     LoadFloppyAs(usize, PathBuf),
     LoadAutoFloppy(usize, PathBuf),
-    SaveFloppy(usize, usize),                          // Drive index, disk index
+    LoadZipFloppy(usize, PathBuf, Option<String>), // Drive index, archive, image in the archive or None to build one
+    SaveFloppy(usize, usize),                      // Drive index, disk index
     SaveFloppyAs(usize, DiskImageFileFormat, PathBuf), // Drive image, format, requested path
     EjectFloppy(usize),
     CreateNewFloppy(usize, StandardFormat, bool),
+    CreateFloppyImage(OsString, FloppyImageType, String), // Filename, format, volume label
+    InspectFloppyImage(PathBuf),                          // Source image to convert
+    ConvertFloppyImage(PathBuf, DiskImageFileFormat, PathBuf), // Source image, target format, output path
     QueryCompatibleFloppyFormats(usize),
     SetFloppyWriteProtect(usize, bool),
+    SetFloppyWriteBack(usize, FloppyWriteBack),
+    RestoreFloppyBackup(usize),
+    InsertDroppedFloppy(usize, PathBuf, bool), // Drive index, image, write protect
     BridgeSerialPort(usize, String, usize),
     DumpVRAM,
     DumpCS,
@@ -197,18 +225,60 @@ pub enum GuiEvent {
     TickDevice(DeviceSelection, u32),
     MachineStateChange(MachineState),
     TakeScreenshot(usize),
+    ExportTextScreen(usize, TextExportFormat, bool), // Display index, format, include off-screen text
+    CopyTextScreen(usize),                           // Display index
+    SaveState(usize),                                // Save state slot
+    LoadState(usize),
+    DeleteState(usize),
+    RefreshSaveStates,
+    RefreshMediaLibrary,
+    SetMediaFavorite(PathBuf, bool),
+    RecordMovie(bool), // Record from the current state, rather than from a reset
+    StopMovie,
+    PlayMovie(PathBuf),
+    QuickLoad(PathBuf),
     ToggleFullscreen(usize),
     Exit,
     SetNMI(bool),
     TriggerParity,
     RescanMediaFolders,
     CtrlAltDel,
+    PasteClipboard,
     ZoomChanged(f32),
     ResetIOStats,
     StartRecordingDisassembly,
     StopRecordingDisassembly,
+    StartRecordingControlFlow,
+    StopRecordingControlFlow,
+    StartRecordingAnalysis,
+    StopRecordingAnalysis,
     InsertCartridge(usize, usize),
+    InsertDroppedCartridge(usize, PathBuf), // Slot index, image
     RemoveCartridge(usize),
+    LoadCdImage(usize),
+    EjectCdImage,
+    LoadCassette(usize),
+    NewCassette,
+    SaveCassette,
+    EjectCassette,
+    CassettePlay,
+    CassetteRecord,
+    CassetteStop,
+    CassetteRewind,
+    ConsoleCommand(String),
+    ClearPostCodes,
+    SetRegisterLogging(bool),
+    SetPpiDipSwitches(u8, Option<u8>),
+    SetSoundVolume(usize, f32),
+    SetSoundPan(usize, f32),
+    SetSoundMuted(usize, bool),
+    SaveMixerSettings,
+    ToggleAudioCapture(bool),
+    ToggleVideoCapture,
+    SetAudioResampler(ResamplerQuality),
+    SetAudioMaxLatency(Option<u32>),
+    SetHotkey(HotkeyEvent, Vec<MartyKey>),
+    ResetHotkeys,
 }
 
 pub enum DeviceSelection {
@@ -288,6 +358,46 @@ lazy_static! {
                 resizable: false,
             },
         ),
+        (
+            GuiWindow::AudioMixer,
+            WorkspaceWindowDef {
+                id: GuiWindow::AudioMixer,
+                title: "Audio Mixer",
+                menu: "Audio Mixer",
+                width: 500.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::HotkeyEditor,
+            WorkspaceWindowDef {
+                id: GuiWindow::HotkeyEditor,
+                title: "Hotkeys",
+                menu: "Hotkeys",
+                width: 500.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::SaveStateBrowser,
+            WorkspaceWindowDef {
+                id: GuiWindow::SaveStateBrowser,
+                title: "Save States",
+                menu: "Save States",
+                width: 450.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::MediaLibrary,
+            WorkspaceWindowDef {
+                id: GuiWindow::MediaLibrary,
+                title: "Media Library",
+                menu: "Media Library",
+                width: 800.0,
+                resizable: true,
+            },
+        ),
         (
             GuiWindow::CpuControl,
             WorkspaceWindowDef {
@@ -368,6 +478,16 @@ lazy_static! {
                 resizable: true,
             },
         ),
+        (
+            GuiWindow::DebugConsole,
+            WorkspaceWindowDef {
+                id: GuiWindow::DebugConsole,
+                title: "Debug Console",
+                menu: "Console",
+                width: 600.0,
+                resizable: true,
+            },
+        ),
         (
             GuiWindow::IvtViewer,
             WorkspaceWindowDef {
@@ -458,6 +578,26 @@ lazy_static! {
                 resizable: false,
             },
         ),
+        (
+            GuiWindow::DipSwitchEditor,
+            WorkspaceWindowDef {
+                id: GuiWindow::DipSwitchEditor,
+                title: "DIP Switch Editor",
+                menu: "DIP Switches",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::PostCardViewer,
+            WorkspaceWindowDef {
+                id: GuiWindow::PostCardViewer,
+                title: "POST Card Viewer",
+                menu: "POST Card",
+                width: 300.0,
+                resizable: false,
+            },
+        ),
         (
             GuiWindow::DmaViewer,
             WorkspaceWindowDef {
@@ -478,6 +618,56 @@ lazy_static! {
                 resizable: false,
             },
         ),
+        (
+            GuiWindow::VideoRegisterLog,
+            WorkspaceWindowDef {
+                id: GuiWindow::VideoRegisterLog,
+                title: "Video Register Log",
+                menu: "Video Register Log",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::VramViewer,
+            WorkspaceWindowDef {
+                id: GuiWindow::VramViewer,
+                title: "VRAM Viewer",
+                menu: "VRAM Viewer",
+                width: 400.0,
+                resizable: true,
+            },
+        ),
+        (
+            GuiWindow::CharacterSetViewer,
+            WorkspaceWindowDef {
+                id: GuiWindow::CharacterSetViewer,
+                title: "Character Set Viewer",
+                menu: "Character Set Viewer",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::PaletteViewer,
+            WorkspaceWindowDef {
+                id: GuiWindow::PaletteViewer,
+                title: "Palette Viewer",
+                menu: "Palette Viewer",
+                width: 300.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::CrtcTimingViewer,
+            WorkspaceWindowDef {
+                id: GuiWindow::CrtcTimingViewer,
+                title: "CRTC Timing Viewer",
+                menu: "CRTC Timing Viewer",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
         (
             GuiWindow::VHDCreator,
             WorkspaceWindowDef {
@@ -488,6 +678,26 @@ lazy_static! {
                 resizable: false,
             },
         ),
+        (
+            GuiWindow::FloppyCreator,
+            WorkspaceWindowDef {
+                id: GuiWindow::FloppyCreator,
+                title: "Floppy Image Creator",
+                menu: "Create Floppy Image",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::FloppyConverter,
+            WorkspaceWindowDef {
+                id: GuiWindow::FloppyConverter,
+                title: "Floppy Image Converter",
+                menu: "Convert Floppy Image",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
         (
             GuiWindow::DataVisualizer,
             WorkspaceWindowDef {
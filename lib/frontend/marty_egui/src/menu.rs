@@ -30,10 +30,23 @@
 
 */
 
-use crate::{state::GuiState, GuiBoolean, GuiEnum, GuiEvent, GuiVariable, GuiVariableContext, GuiWindow};
+use crate::{
+    state::{FloppyDriveSelection, GuiState},
+    GuiBoolean,
+    GuiEnum,
+    GuiEvent,
+    GuiVariable,
+    GuiVariableContext,
+    GuiWindow,
+};
 use egui_file::FileDialog;
 //use egui_file_dialog::FileDialog;
 
+use frontend_common::{
+    savestate_manager::SAVE_STATE_SLOTS,
+    text_export::TextExportFormat,
+    types::floppy::FloppyWriteBack,
+};
 use marty_core::{device_traits::videocard::VideoType, devices::serial::SerialPortDescriptor};
 
 use crate::modal::ModalContext;
@@ -51,6 +64,16 @@ impl GuiState {
                         ui.close_menu();
                     }
 
+                    if ui.button("🔊 Audio Mixer...").clicked() {
+                        *self.window_flag(GuiWindow::AudioMixer) = true;
+                        ui.close_menu();
+                    }
+
+                    if ui.button("⌨ Hotkeys...").clicked() {
+                        *self.window_flag(GuiWindow::HotkeyEditor) = true;
+                        ui.close_menu();
+                    }
+
                     if ui.button("❓ About...").clicked() {
                         *self.window_flag(GuiWindow::About) = true;
                         ui.close_menu();
@@ -174,12 +197,70 @@ impl GuiState {
                     }
                 });
 
+                ui.add_enabled_ui(is_on, |ui| {
+                    if ui.button("📋 Paste Clipboard").clicked() {
+                        self.event_queue.send(GuiEvent::PasteClipboard);
+                        ui.close_menu();
+                    }
+                });
+
+                ui.add_enabled_ui(is_on, |ui| {
+                    if ui.button("▶ Quick Load Program...").clicked() {
+                        self.browse_program();
+                        ui.close_menu();
+                    }
+                });
+
                 ui.add_enabled_ui(is_on, |ui| {
                     if ui.button("🔌 Power off").clicked() {
                         self.event_queue.send(GuiEvent::MachineStateChange(MachineState::Off));
                         ui.close_menu();
                     }
                 });
+
+                ui.separator();
+
+                ui.add_enabled_ui(is_on, |ui| {
+                    ui.menu_button("💾 Save State", |ui| {
+                        for slot in 1..=SAVE_STATE_SLOTS {
+                            if ui.button(format!("Slot {}", slot)).clicked() {
+                                self.event_queue.send(GuiEvent::SaveState(slot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.menu_button("🗁 Load State", |ui| {
+                        for slot in 1..=SAVE_STATE_SLOTS {
+                            if ui.button(format!("Slot {}", slot)).clicked() {
+                                self.event_queue.send(GuiEvent::LoadState(slot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    if ui.button("🗄 Save States...").clicked() {
+                        *self.window_flag(GuiWindow::SaveStateBrowser) = true;
+                        self.event_queue.send(GuiEvent::RefreshSaveStates);
+                        ui.close_menu();
+                    }
+                    ui.menu_button("🎥 Movie", |ui| {
+                        if ui.button("⏺ Record from Reset").clicked() {
+                            self.event_queue.send(GuiEvent::RecordMovie(false));
+                            ui.close_menu();
+                        }
+                        if ui.button("⏺ Record from Current State").clicked() {
+                            self.event_queue.send(GuiEvent::RecordMovie(true));
+                            ui.close_menu();
+                        }
+                        if ui.button("▶ Play...").clicked() {
+                            self.browse_movie();
+                            ui.close_menu();
+                        }
+                        if ui.button("⏹ Stop").clicked() {
+                            self.event_queue.send(GuiEvent::StopMovie);
+                            ui.close_menu();
+                        }
+                    });
+                });
             });
 
             let _media_response = ui.menu_button("Media", |ui| {
@@ -190,6 +271,11 @@ impl GuiState {
                 if ui.button("⟲ Rescan Media Folders").clicked() {
                     self.event_queue.send(GuiEvent::RescanMediaFolders);
                 }
+                if ui.button("📚 Media Library...").clicked() {
+                    *self.window_flag(GuiWindow::MediaLibrary) = true;
+                    self.event_queue.send(GuiEvent::RefreshMediaLibrary);
+                    ui.close_menu();
+                }
 
                 for i in 0..self.floppy_drives.len() {
                     self.draw_floppy_menu(ui, i);
@@ -203,10 +289,28 @@ impl GuiState {
                     self.draw_cart_menu(ui, i);
                 }
 
+                if self.cdrom.is_some() {
+                    self.draw_cdrom_menu(ui);
+                }
+
+                if self.cassette.is_some() {
+                    self.draw_cassette_menu(ui);
+                }
+
                 if ui.button("🖹 Create new VHD...").clicked() {
                     *self.window_flag(GuiWindow::VHDCreator) = true;
                     ui.close_menu();
                 };
+
+                if ui.button("🖹 Create new floppy image...").clicked() {
+                    *self.window_flag(GuiWindow::FloppyCreator) = true;
+                    ui.close_menu();
+                };
+
+                if ui.button("🔁 Convert floppy image...").clicked() {
+                    *self.window_flag(GuiWindow::FloppyConverter) = true;
+                    ui.close_menu();
+                };
             });
 
             ui.menu_button("Display", |ui| {
@@ -297,6 +401,7 @@ impl GuiState {
                     self.workspace_window_open_button(ui, GuiWindow::InstructionHistoryViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::CycleTraceViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::CallStack, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::DebugConsole, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::DisassemblyViewer, true, true);
 
                     ui.menu_button("Disassembly Listing", |ui| {
@@ -309,6 +414,28 @@ impl GuiState {
                             ui.close_menu();
                         }
                     });
+
+                    ui.menu_button("Control Flow Graph", |ui| {
+                        if ui.button("⏺ Start Recording").clicked() {
+                            self.event_queue.send(GuiEvent::StartRecordingControlFlow);
+                            ui.close_menu();
+                        }
+                        if ui.button("⏹ Stop Recording and Export").clicked() {
+                            self.event_queue.send(GuiEvent::StopRecordingControlFlow);
+                            ui.close_menu();
+                        }
+                    });
+
+                    ui.menu_button("Analysis Export", |ui| {
+                        if ui.button("⏺ Start Recording").clicked() {
+                            self.event_queue.send(GuiEvent::StartRecordingAnalysis);
+                            ui.close_menu();
+                        }
+                        if ui.button("⏹ Stop Recording and Export").clicked() {
+                            self.event_queue.send(GuiEvent::StopRecordingAnalysis);
+                            ui.close_menu();
+                        }
+                    });
                 });
 
                 ui.menu_button("Memory", |ui| {
@@ -342,10 +469,17 @@ impl GuiState {
                     self.workspace_window_open_button(ui, GuiWindow::PicViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::PitViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::PpiViewer, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::DipSwitchEditor, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::PostCardViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::DmaViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::SerialViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::FdcViewer, true, true);
                     self.workspace_window_open_button(ui, GuiWindow::VideoCardViewer, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::VideoRegisterLog, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::VramViewer, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::CharacterSetViewer, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::PaletteViewer, true, true);
+                    self.workspace_window_open_button(ui, GuiWindow::CrtcTimingViewer, true, true);
 
                     /*
                     if ui
@@ -393,6 +527,22 @@ impl GuiState {
                     ));
                 }
 
+                if ui
+                    .checkbox(
+                        &mut self.get_option_mut(GuiBoolean::ShowRasterRegions),
+                        "Show Raster Regions",
+                    )
+                    .on_hover_text("Outline the display area and blanking periods while paused")
+                    .clicked()
+                {
+                    let new_opt = self.get_option(GuiBoolean::ShowRasterRegions).unwrap();
+
+                    self.event_queue.send(GuiEvent::VariableChanged(
+                        GuiVariableContext::Global,
+                        GuiVariable::Bool(GuiBoolean::ShowRasterRegions, new_opt),
+                    ));
+                }
+
                 if ui.button("Flush Trace Logs").clicked() {
                     self.event_queue.send(GuiEvent::FlushLogs);
                     ui.close_menu();
@@ -433,6 +583,52 @@ impl GuiState {
                     );
                 };
 
+                if ui.button("📁 Mount Host Directory...").clicked() {
+                    self.modal.open(
+                        ModalContext::OpenFloppyDirectory(drive_idx),
+                        self.default_floppy_path.clone(),
+                    );
+                    ui.close_menu();
+                }
+
+                if let FloppyDriveSelection::Directory(path) = &self.floppy_drives[drive_idx].selected_path {
+                    if ui
+                        .button(format!(
+                            "⟲ Remount {}",
+                            path.file_name().unwrap_or_default().to_string_lossy()
+                        ))
+                        .on_hover_text("Rebuild the disk from the directory to pick up changes made on the host")
+                        .clicked()
+                    {
+                        self.event_queue.send(GuiEvent::LoadAutoFloppy(drive_idx, path.clone()));
+                        ui.close_menu();
+                    }
+                }
+
+                if let Some(archive_path) = self.floppy_drives[drive_idx].archive_path.clone() {
+                    ui.menu_button("🗜 Archive Contents", |ui| {
+                        for image_name in self.floppy_drives[drive_idx].archive_images.iter() {
+                            if ui.button(format!("💾 {}", image_name)).clicked() {
+                                self.event_queue.send(GuiEvent::LoadZipFloppy(
+                                    drive_idx,
+                                    archive_path.clone(),
+                                    Some(image_name.clone()),
+                                ));
+                                ui.close_menu();
+                            }
+                        }
+                        if ui
+                            .button("🗐 Build Floppy from Archive Contents")
+                            .on_hover_text("Build a floppy holding the files in the archive")
+                            .clicked()
+                        {
+                            self.event_queue
+                                .send(GuiEvent::LoadZipFloppy(drive_idx, archive_path.clone(), None));
+                            ui.close_menu();
+                        }
+                    });
+                }
+
                 if !self.autofloppy_paths.is_empty() {
                     ui.menu_button("🗐 Create from Directory", |ui| {
                         for path in self.autofloppy_paths.iter() {
@@ -535,6 +731,46 @@ impl GuiState {
                         self.floppy_drives[drive_idx].write_protected,
                     ));
                 }
+
+                // Write back options only apply to images loaded from a file that can be saved
+                // in the format they were loaded from.
+                if matches!(
+                    self.floppy_drives[drive_idx].selected_path,
+                    FloppyDriveSelection::Image(_)
+                ) && self.floppy_drives[drive_idx].is_writeable()
+                {
+                    ui.separator();
+                    ui.menu_button("Write Back", |ui| {
+                        let mut mode = self.floppy_drives[drive_idx].write_back;
+                        for (option, label) in [
+                            (FloppyWriteBack::Manual, "Manual: Save from this menu"),
+                            (FloppyWriteBack::Auto, "Automatic: Write changes to the image file"),
+                            (FloppyWriteBack::Discard, "Discard changes on eject"),
+                        ] {
+                            if ui.radio_value(&mut mode, option, label).clicked() {
+                                self.floppy_drives[drive_idx].write_back = mode;
+                                self.event_queue.send(GuiEvent::SetFloppyWriteBack(drive_idx, mode));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+
+                    if self.floppy_drives[drive_idx].modified {
+                        let warn_color = ui.visuals().warn_fg_color;
+                        ui.label(egui::RichText::new("● Image has unsaved changes").color(warn_color));
+                    }
+
+                    if self.floppy_drives[drive_idx].has_backup {
+                        if ui
+                            .button("↶ Restore Backup")
+                            .on_hover_text("Undo all changes written to the image file since it was loaded.")
+                            .clicked()
+                        {
+                            self.event_queue.send(GuiEvent::RestoreFloppyBackup(drive_idx));
+                            ui.close_menu();
+                        }
+                    }
+                }
             })
             .response;
         ui.end_row();
@@ -561,6 +797,39 @@ impl GuiState {
                     });
                 });
 
+                if ui.button("📁 Mount Host Directory...").clicked() {
+                    self.modal.open(
+                        ModalContext::OpenHddDirectory(drive_idx, self.hdds[drive_idx].write_back),
+                        None,
+                    );
+                    ui.close_menu();
+                }
+
+                if self.hdds[drive_idx].host_dir {
+                    if let Some(path) = self.hdds[drive_idx].selected_path.clone() {
+                        if ui
+                            .button(format!(
+                                "⟲ Remount {}",
+                                path.file_name().unwrap_or_default().to_string_lossy()
+                            ))
+                            .on_hover_text("Rebuild the disk from the directory to pick up changes made on the host")
+                            .clicked()
+                        {
+                            self.event_queue.send(GuiEvent::MountHddDirectory(
+                                drive_idx,
+                                path,
+                                self.hdds[drive_idx].write_back,
+                            ));
+                            ui.close_menu();
+                        }
+                    }
+                }
+
+                ui.checkbox(&mut self.hdds[drive_idx].write_back, "Write Back to Host Directory")
+                    .on_hover_text(
+                        "Copy files the guest creates or changes to the directory when the machine is turned off",
+                    );
+
                 let (have_vhd, detatch_string) = match &self.hdds[drive_idx].filename() {
                     Some(name) => (true, format!("Detach image: {}", name)),
                     None => (false, "Detach: <No Disk>".to_string()),
@@ -598,6 +867,99 @@ impl GuiState {
         });
     }
 
+    pub fn draw_cdrom_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("💿 CD-ROM Drive", |ui| {
+            ui.menu_button("Insert Disc", |ui| {
+                self.cdrom_tree_menu.draw(ui, 0, true, &mut |image_idx| {
+                    self.event_queue.send(GuiEvent::LoadCdImage(image_idx));
+                });
+            });
+
+            let (have_disc, eject_string) = match self.cdrom.as_ref().and_then(|cdrom| cdrom.filename()) {
+                Some(name) => (true, format!("Eject: {}", name)),
+                None => (false, "Eject: <No Disc>".to_string()),
+            };
+
+            ui.add_enabled_ui(have_disc, |ui| {
+                if ui.button(eject_string).clicked() {
+                    self.event_queue.send(GuiEvent::EjectCdImage);
+                }
+            });
+        });
+    }
+
+    pub fn draw_cassette_menu(&mut self, ui: &mut egui::Ui) {
+        let status = match &self.cassette {
+            Some(status) => status.clone(),
+            None => return,
+        };
+
+        ui.menu_button("📼 Cassette Deck", |ui| {
+            let tape_name = status
+                .image
+                .as_ref()
+                .and_then(|path| path.file_name())
+                .map(|name| name.to_string_lossy().to_string());
+
+            ui.label(format!(
+                "{}{}",
+                tape_name.as_deref().unwrap_or("<New Tape>"),
+                if status.modified { " (modified)" } else { "" }
+            ));
+            ui.label(format!(
+                "{:?} {:.1}s / {:.1}s{}",
+                status.state,
+                status.position_us / 1_000_000.0,
+                status.length_us / 1_000_000.0,
+                if status.motor_on { " [Motor]" } else { "" }
+            ));
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("▶ Play").clicked() {
+                    self.event_queue.send(GuiEvent::CassettePlay);
+                }
+                if ui.button("⏺ Record").clicked() {
+                    self.event_queue.send(GuiEvent::CassetteRecord);
+                }
+                if ui.button("⏹ Stop").clicked() {
+                    self.event_queue.send(GuiEvent::CassetteStop);
+                }
+                if ui.button("⏮ Rewind").clicked() {
+                    self.event_queue.send(GuiEvent::CassetteRewind);
+                }
+            });
+            ui.separator();
+
+            ui.menu_button("Insert Tape", |ui| {
+                self.tape_tree_menu.draw(ui, 0, true, &mut |image_idx| {
+                    self.event_queue.send(GuiEvent::LoadCassette(image_idx));
+                });
+            });
+
+            if ui.button("Insert Blank Tape").clicked() {
+                self.event_queue.send(GuiEvent::NewCassette);
+                ui.close_menu();
+            }
+
+            ui.add_enabled_ui(status.length_us > 0.0, |ui| {
+                if ui.button("Save Tape").clicked() {
+                    self.event_queue.send(GuiEvent::SaveCassette);
+                    ui.close_menu();
+                }
+            });
+
+            let eject_string = match &tape_name {
+                Some(name) => format!("Eject: {}", name),
+                None => "Eject".to_string(),
+            };
+            if ui.button(eject_string).clicked() {
+                self.event_queue.send(GuiEvent::EjectCassette);
+                ui.close_menu();
+            }
+        });
+    }
+
     pub fn draw_display_menu(&mut self, ui: &mut egui::Ui, display_idx: usize) {
         let ctx = GuiVariableContext::Display(display_idx);
 
@@ -608,7 +970,7 @@ impl GuiState {
                 {
                     let checked = *enum_mut == GuiEnum::DisplayScalerMode(*mode);
 
-                    if ui.add(egui::RadioButton::new(checked, format!("{:?}", mode))).clicked() {
+                    if ui.add(egui::RadioButton::new(checked, mode.to_string())).clicked() {
                         *enum_mut = GuiEnum::DisplayScalerMode(*mode);
                         self.event_queue.send(GuiEvent::VariableChanged(
                             GuiVariableContext::Display(display_idx),
@@ -739,9 +1101,67 @@ impl GuiState {
             self.event_queue.send(GuiEvent::TakeScreenshot(display_idx));
             ui.close_menu();
         };
+
+        let capture_label = match self.video_capture_active {
+            true => "⏹ Stop Video Recording",
+            false => "⏺ Start Video Recording",
+        };
+        if ui.button(capture_label).clicked() {
+            self.event_queue.send(GuiEvent::ToggleVideoCapture);
+            ui.close_menu();
+        };
+
+        if ui.button("📋 Copy Screen Text").clicked() {
+            self.event_queue.send(GuiEvent::CopyTextScreen(display_idx));
+            ui.close_menu();
+        };
+
+        ui.menu_button("🖹 Export Text Screen", |ui| {
+            ui.checkbox(&mut self.text_export_full_buffer, "Include Off-screen Text");
+            ui.separator();
+            for (format, label) in [
+                (TextExportFormat::Text, "Plain Text (.txt)"),
+                (TextExportFormat::Ansi, "ANSI (.ans)"),
+                (TextExportFormat::Html, "HTML (.html)"),
+            ] {
+                if ui.button(label).clicked() {
+                    self.event_queue.send(GuiEvent::ExportTextScreen(
+                        display_idx,
+                        format,
+                        self.text_export_full_buffer,
+                    ));
+                    ui.close_menu();
+                }
+            }
+        });
     }
 
-    pub fn draw_status_widgets(&mut self, _ui: &mut egui::Ui) {
+    pub fn draw_status_widgets(&mut self, ui: &mut egui::Ui) {
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            // Draw keyboard LEDs, if the keyboard has them.
+            if let Some(leds) = self.keyboard_leds {
+                for (bit, name) in [(0, "Scroll"), (2, "Caps"), (1, "Num")] {
+                    let color = match leds & (1 << bit) != 0 {
+                        true => egui::Color32::from_rgb(0x40, 0xFF, 0x40),
+                        false => egui::Color32::DARK_GRAY,
+                    };
+                    ui.label(egui::RichText::new(name).small().color(color));
+                }
+            }
+
+            // Show a recording indicator while video is being captured.
+            if self.video_capture_active {
+                ui.label(
+                    egui::RichText::new("⏺ REC")
+                        .small()
+                        .strong()
+                        .color(egui::Color32::from_rgb(0xFF, 0x30, 0x30)),
+                );
+            }
+
+            self.status_bar.draw(ui);
+        });
+
         // Can we put stuff on the right hand side of the menu bar?
         // ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
         //     ui.label("💾");
@@ -36,7 +36,30 @@ use fluxfox::DiskImageFileFormat;
 pub enum ModalContext {
     SaveFloppyImage(usize, DiskImageFileFormat, Vec<String>), // Index of the floppy drive, list of extensions
     OpenFloppyImage(usize, Vec<String>),                      // Index of the floppy drive, list of extensions
+    OpenFloppyDirectory(usize),                               // Index of the floppy drive
+    OpenHddDirectory(usize, bool),                            // Index of the hard disk, write back
     ProgressBar(String, f32),                                 // Progress bar with message and progress
+    OpenMovie,
+    OpenProgram,
+    OpenConversionSource,
+    DroppedFile(PathBuf, DropTarget, usize, bool), // Dropped file, media type, number of drives or slots, write protect
+}
+
+/// The kind of media a file dropped onto the emulator window was recognized as.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DropTarget {
+    Floppy,
+    Cartridge,
+    Program,
+}
+
+/// A small window asking where to insert a file dropped onto the emulator window.
+pub struct DropChooser {
+    pub path: PathBuf,
+    pub target: DropTarget,
+    pub slot_ct: usize,
+    pub slot_idx: usize,
+    pub write_protect: bool,
 }
 
 pub struct ProgressWindow {
@@ -48,6 +71,7 @@ pub enum ModalDialog {
     Save(FileDialog),
     Open(FileDialog),
     ProgressBar(ProgressWindow),
+    DropChooser(DropChooser),
 }
 
 #[derive(Default)]
@@ -213,6 +237,52 @@ impl ModalState {
                 dialog.open();
                 self.dialog = Some(ModalDialog::Open(dialog));
             }
+            ModalContext::OpenFloppyDirectory(_) | ModalContext::OpenHddDirectory(..) => {
+                let mut dialog = FileDialog::select_folder(initial_path)
+                    .title("Mount Host Directory")
+                    .default_pos(egui::Pos2::new(20.0, 40.0));
+                dialog.open();
+                self.dialog = Some(ModalDialog::Open(dialog));
+            }
+            ModalContext::OpenMovie => {
+                let show_filter: egui_file::Filter<PathBuf> =
+                    Box::new(|path| path.extension().map_or(false, |ext| ext == "txt"));
+                let mut dialog = FileDialog::open_file(initial_path)
+                    .title("Play Movie")
+                    .default_pos(egui::Pos2::new(20.0, 40.0))
+                    .show_files_filter(show_filter);
+                dialog.open();
+                self.dialog = Some(ModalDialog::Open(dialog));
+            }
+            ModalContext::OpenProgram => {
+                let show_filter: egui_file::Filter<PathBuf> = Box::new(|path| {
+                    path.extension().map_or(false, |ext| {
+                        ext.eq_ignore_ascii_case("com") || ext.eq_ignore_ascii_case("exe")
+                    })
+                });
+                let mut dialog = FileDialog::open_file(initial_path)
+                    .title("Quick Load Program")
+                    .default_pos(egui::Pos2::new(20.0, 40.0))
+                    .show_files_filter(show_filter);
+                dialog.open();
+                self.dialog = Some(ModalDialog::Open(dialog));
+            }
+            ModalContext::OpenConversionSource => {
+                let mut dialog = FileDialog::open_file(initial_path)
+                    .title("Select Floppy Image to Convert")
+                    .default_pos(egui::Pos2::new(20.0, 40.0));
+                dialog.open();
+                self.dialog = Some(ModalDialog::Open(dialog));
+            }
+            ModalContext::DroppedFile(path, target, slot_ct, write_protect) => {
+                self.dialog = Some(ModalDialog::DropChooser(DropChooser {
+                    path: path.clone(),
+                    target: *target,
+                    slot_ct: *slot_ct,
+                    slot_idx: 0,
+                    write_protect: *write_protect,
+                }));
+            }
             ModalContext::ProgressBar(title, progress) => {
                 self.dialog = Some(ModalDialog::ProgressBar(ProgressWindow {
                     title:    title.clone(),
@@ -270,6 +340,11 @@ impl ModalState {
                         );
                     });
             }
+            Some(ModalDialog::DropChooser(chooser)) => {
+                if chooser.show(ctx, events) {
+                    self.close();
+                }
+            }
             None => {}
         }
     }
@@ -293,8 +368,33 @@ impl ModalState {
                         event_queue.send(GuiEvent::LoadFloppyAs(*drive_idx, path.clone()));
                     }
                 }
-                ModalContext::ProgressBar(_, _) => {
-                    // Nothing to do to resolve a ProgressBar
+                ModalContext::OpenFloppyDirectory(drive_idx) => {
+                    if let Some(path) = &self.selected_path {
+                        event_queue.send(GuiEvent::LoadAutoFloppy(*drive_idx, path.clone()));
+                    }
+                }
+                ModalContext::OpenHddDirectory(drive_idx, write_back) => {
+                    if let Some(path) = &self.selected_path {
+                        event_queue.send(GuiEvent::MountHddDirectory(*drive_idx, path.clone(), *write_back));
+                    }
+                }
+                ModalContext::OpenMovie => {
+                    if let Some(path) = &self.selected_path {
+                        event_queue.send(GuiEvent::PlayMovie(path.clone()));
+                    }
+                }
+                ModalContext::OpenProgram => {
+                    if let Some(path) = &self.selected_path {
+                        event_queue.send(GuiEvent::QuickLoad(path.clone()));
+                    }
+                }
+                ModalContext::OpenConversionSource => {
+                    if let Some(path) = &self.selected_path {
+                        event_queue.send(GuiEvent::InspectFloppyImage(path.clone()));
+                    }
+                }
+                ModalContext::ProgressBar(_, _) | ModalContext::DroppedFile(..) => {
+                    // Nothing to do to resolve a ProgressBar. The drop chooser sends its own events.
                 }
             }
         }
@@ -304,3 +404,75 @@ impl ModalState {
         self.extensions.clear();
     }
 }
+
+impl DropChooser {
+    /// Show the chooser, sending the event to insert the file when confirmed. Returns true once the
+    /// chooser has been resolved, either way.
+    pub fn show(&mut self, ctx: &egui::Context, events: &mut GuiEventQueue) -> bool {
+        let mut resolved = false;
+        let file_name = self.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        egui::Window::new("Insert Dropped File")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                ui.label(egui::RichText::new(&file_name).strong());
+                ui.separator();
+
+                match self.target {
+                    DropTarget::Floppy | DropTarget::Cartridge => {
+                        let target = self.target;
+                        let slot_name = |idx: usize| match target {
+                            DropTarget::Floppy => match idx {
+                                0 => "Floppy Drive 0 (A:)".to_string(),
+                                1 => "Floppy Drive 1 (B:)".to_string(),
+                                _ => format!("Floppy Drive {}", idx),
+                            },
+                            _ => format!("Cartridge Slot {}", idx),
+                        };
+                        egui::ComboBox::from_id_source("drop-chooser-slot")
+                            .selected_text(slot_name(self.slot_idx))
+                            .show_ui(ui, |ui| {
+                                for idx in 0..self.slot_ct {
+                                    ui.selectable_value(&mut self.slot_idx, idx, slot_name(idx));
+                                }
+                            });
+                        if self.target == DropTarget::Floppy {
+                            ui.checkbox(&mut self.write_protect, "Write Protect");
+                        }
+                        else {
+                            ui.label("Inserting a cartridge reboots the machine.");
+                        }
+                    }
+                    DropTarget::Program => {
+                        ui.label("Quick load this program?");
+                    }
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let action = match self.target {
+                        DropTarget::Program => "Run",
+                        _ => "Insert",
+                    };
+                    if ui.add_enabled(self.slot_ct > 0, egui::Button::new(action)).clicked() {
+                        let path = self.path.clone();
+                        events.send(match self.target {
+                            DropTarget::Floppy => {
+                                GuiEvent::InsertDroppedFloppy(self.slot_idx, path, self.write_protect)
+                            }
+                            DropTarget::Cartridge => GuiEvent::InsertDroppedCartridge(self.slot_idx, path),
+                            DropTarget::Program => GuiEvent::QuickLoad(path),
+                        });
+                        resolved = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        resolved = true;
+                    }
+                });
+            });
+
+        resolved
+    }
+}
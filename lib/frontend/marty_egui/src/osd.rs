@@ -0,0 +1,143 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    egui::osd.rs
+
+    Implement the on-screen display, which shows brief status messages over
+    the emulated display.
+
+*/
+
+use std::collections::VecDeque;
+
+use egui::{Align2, Color32, Context, Id, Order, RichText};
+use frontend_common::types::gui::OsdPosition;
+use web_time::{Duration, Instant};
+
+/// Maximum number of messages shown at once. Posting more drops the oldest.
+const OSD_MAX_MESSAGES: usize = 5;
+/// Messages fade out over this period at the end of their duration.
+const OSD_FADE_TIME: Duration = Duration::from_millis(400);
+/// Distance of messages from the edge of the display.
+const OSD_MARGIN: f32 = 8.0;
+const OSD_TEXT_SIZE: f32 = 16.0;
+
+struct OsdMessage {
+    text:   String,
+    posted: Instant,
+}
+
+pub struct Osd {
+    enabled:  bool,
+    position: OsdPosition,
+    duration: Duration,
+    messages: VecDeque<OsdMessage>,
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Self {
+            enabled:  true,
+            position: Default::default(),
+            duration: Duration::from_millis(2000),
+            messages: VecDeque::new(),
+        }
+    }
+}
+
+impl Osd {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.messages.clear();
+        }
+    }
+
+    pub fn set_position(&mut self, position: OsdPosition) {
+        self.position = position;
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+
+    /// Post a message to the on-screen display.
+    pub fn post(&mut self, text: impl Into<String>) {
+        if !self.enabled {
+            return;
+        }
+        if self.messages.len() == OSD_MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(OsdMessage {
+            text:   text.into(),
+            posted: Instant::now(),
+        });
+    }
+
+    /// Draw any current messages in the area of the window not taken up by panels.
+    pub(crate) fn show(&mut self, ctx: &Context) {
+        let now = Instant::now();
+        let duration = self.duration;
+        self.messages.retain(|msg| now.duration_since(msg.posted) < duration);
+        if self.messages.is_empty() {
+            return;
+        }
+
+        let rect = ctx.available_rect().shrink(OSD_MARGIN);
+        let (pivot, pos) = match self.position {
+            OsdPosition::TopLeft => (Align2::LEFT_TOP, rect.left_top()),
+            OsdPosition::TopRight => (Align2::RIGHT_TOP, rect.right_top()),
+            OsdPosition::BottomLeft => (Align2::LEFT_BOTTOM, rect.left_bottom()),
+            OsdPosition::BottomRight => (Align2::RIGHT_BOTTOM, rect.right_bottom()),
+        };
+
+        egui::Area::new(Id::new("osd"))
+            .order(Order::Foreground)
+            .interactable(false)
+            .pivot(pivot)
+            .fixed_pos(pos)
+            .show(ctx, |ui| {
+                for msg in self.messages.iter() {
+                    let remaining = duration.saturating_sub(now.duration_since(msg.posted));
+                    let opacity = (remaining.as_secs_f32() / OSD_FADE_TIME.as_secs_f32()).min(1.0);
+                    ui.scope(|ui| {
+                        ui.set_opacity(opacity);
+                        egui::Frame::none()
+                            .fill(Color32::from_black_alpha(192))
+                            .rounding(4.0)
+                            .inner_margin(egui::Margin::symmetric(8.0, 4.0))
+                            .show(ui, |ui| {
+                                ui.label(RichText::new(&msg.text).size(OSD_TEXT_SIZE).color(Color32::WHITE));
+                            });
+                    });
+                }
+            });
+
+        // Keep repainting so messages fade out and expire even if nothing else is happening.
+        ctx.request_repaint();
+    }
+}
@@ -30,34 +30,50 @@
 */
 
 use crate::{
-    modal::ModalState,
+    modal::{DropTarget, ModalContext, ModalState},
+    osd::Osd,
+    status_bar::StatusBar,
     widgets::file_tree_menu::FileTreeMenu,
     windows::{
         about::AboutDialog,
+        audio_mixer::AudioMixerControl,
         call_stack_viewer::CallStackViewer,
+        character_set_viewer::CharacterSetViewerControl,
         composite_adjust::CompositeAdjustControl,
         cpu_control::{BreakpointSet, CpuControl},
         cpu_state_viewer::CpuViewerControl,
+        crtc_timing_viewer::CrtcTimingViewerControl,
         cycle_trace_viewer::CycleTraceViewerControl,
         data_visualizer::DataVisualizerControl,
+        debug_console::DebugConsoleControl,
         delay_adjust::DelayAdjustControl,
         device_control::DeviceControl,
+        dip_switch_editor::DipSwitchEditor,
         disassembly_viewer::DisassemblyControl,
         dma_viewer::DmaViewerControl,
         fdc_viewer::FdcViewerControl,
+        floppy_converter::FloppyConverter,
+        floppy_creator::FloppyCreator,
         floppy_viewer::FloppyViewerControl,
+        hotkey_editor::HotkeyEditorControl,
         instruction_history_viewer::InstructionHistoryControl,
         io_stats_viewer::IoStatsViewerControl,
         ivt_viewer::IvtViewerControl,
+        media_library::MediaLibraryControl,
         memory_viewer::MemoryViewerControl,
+        palette_viewer::PaletteViewerControl,
         performance_viewer::PerformanceViewerControl,
         pic_viewer::PicViewerControl,
         pit_viewer::PitViewerControl,
+        post_card_viewer::PostCardViewerControl,
         ppi_viewer::PpiViewerControl,
+        savestate_browser::SaveStateBrowserControl,
         scaler_adjust::ScalerAdjustControl,
         serial_viewer::SerialViewerControl,
         text_mode_viewer::TextModeViewer,
         vhd_creator::VhdCreator,
+        video_register_log::VideoRegisterLogControl,
+        vram_viewer::VramViewerControl,
     },
     GuiBoolean,
     GuiEnum,
@@ -77,11 +93,13 @@ use frontend_common::{
     display_manager::DisplayInfo,
     display_scaler::{ScalerMode, ScalerPreset},
     resource_manager::PathTreeNode,
+    types::floppy::FloppyWriteBack,
     RelativeDirectory,
 };
 use marty_core::{
     device_traits::videocard::{DisplayApertureDesc, VideoCardState, VideoCardStateEntry},
-    devices::{pit::PitDisplayState, serial::SerialPortDescriptor},
+    devices::{cassette::CassetteStatus, pit::PitDisplayState, serial::SerialPortDescriptor},
+    keys::MartyKey,
     machine::{ExecutionControl, MachineState},
     machine_types::FloppyDriveType,
 };
@@ -116,6 +134,11 @@ pub struct GuiFloppyDriveInfo {
     pub(crate) supported_formats: Vec<(DiskImageFileFormat, Vec<String>)>,
     pub(crate) source_format: Option<DiskImageFileFormat>,
     pub(crate) source_writeback: bool,
+    pub(crate) archive_path: Option<PathBuf>, // The zip archive the disk was mounted from
+    pub(crate) archive_images: Vec<String>,   // Disk images in the zip archive, by name
+    pub(crate) write_back: FloppyWriteBack,
+    pub(crate) modified: bool,   // The guest changed the image since it was loaded or saved
+    pub(crate) has_backup: bool, // The image file was backed up before being written back
     write_ct: u64,
 }
 
@@ -175,6 +198,8 @@ pub struct GuiHddInfo {
     pub(crate) selected_idx: Option<usize>,
     pub(crate) selected_path: Option<PathBuf>,
     pub(crate) write_protected: bool,
+    pub(crate) host_dir: bool,   // The selected path is a host directory rather than an image
+    pub(crate) write_back: bool, // Write guest changes back when mounting a host directory
 }
 
 impl GuiHddInfo {
@@ -201,6 +226,19 @@ impl GuiCartInfo {
     }
 }
 
+pub struct GuiCdRomInfo {
+    pub(crate) selected_idx:  Option<usize>,
+    pub(crate) selected_path: Option<PathBuf>,
+}
+
+impl GuiCdRomInfo {
+    pub fn filename(&self) -> Option<String> {
+        self.selected_path
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string())
+    }
+}
+
 pub struct GuiAutofloppyPath {
     pub(crate) full_path: PathBuf,
     pub(crate) name: OsString,
@@ -234,9 +272,12 @@ pub struct GuiState {
     pub(crate) event_queue: GuiEventQueue,
 
     pub(crate) toasts: Toasts,
+    pub(crate) osd: Osd,
+    pub(crate) status_bar: StatusBar,
     media_tray: MediaTrayState,
 
     pub(crate) default_floppy_path: Option<PathBuf>,
+    pub(crate) default_movie_path: Option<PathBuf>,
 
     /// Only show the associated window when true.
     pub(crate) window_open_flags: HashMap<GuiWindow, bool>,
@@ -248,6 +289,9 @@ pub struct GuiState {
     pub(crate) option_enums: GuiEnumMap,
 
     pub(crate) machine_state: MachineState,
+    pub(crate) keyboard_leds: Option<u8>,
+    pub(crate) video_capture_active: bool,
+    pub(crate) text_export_full_buffer: bool,
 
     video_mem: ColorImage,
     pub(crate) perf_stats: PerformanceStats,
@@ -261,6 +305,8 @@ pub struct GuiState {
     pub(crate) floppy_drives: Vec<GuiFloppyDriveInfo>,
     pub(crate) hdds: Vec<GuiHddInfo>,
     pub(crate) carts: Vec<GuiCartInfo>,
+    pub(crate) cdrom: Option<GuiCdRomInfo>,
+    pub(crate) cassette: Option<CassetteStatus>,
     pub(crate) autofloppy_paths: Vec<GuiAutofloppyPath>,
 
     // VHD Images
@@ -283,13 +329,24 @@ pub struct GuiState {
     pub memory_viewer: MemoryViewerControl,
     pub data_visualizer: DataVisualizerControl,
 
-    pub perf_viewer:  PerformanceViewerControl,
-    pub delay_adjust: DelayAdjustControl,
+    pub perf_viewer:   PerformanceViewerControl,
+    pub delay_adjust:  DelayAdjustControl,
+    pub audio_mixer:   AudioMixerControl,
+    pub hotkey_editor: HotkeyEditorControl,
+    pub savestate_browser: SaveStateBrowserControl,
+    pub media_library: MediaLibraryControl,
 
-    pub pit_viewer:    PitViewerControl,
+    pub pit_viewer: PitViewerControl,
     pub serial_viewer: SerialViewerControl,
-    pub pic_viewer:    PicViewerControl,
-    pub ppi_viewer:    PpiViewerControl,
+    pub pic_viewer: PicViewerControl,
+    pub ppi_viewer: PpiViewerControl,
+    pub post_card_viewer: PostCardViewerControl,
+    pub dip_switch_editor: DipSwitchEditor,
+    pub video_register_log: VideoRegisterLogControl,
+    pub vram_viewer: VramViewerControl,
+    pub character_set_viewer: CharacterSetViewerControl,
+    pub palette_viewer: PaletteViewerControl,
+    pub crtc_timing_viewer: CrtcTimingViewerControl,
 
     pub videocard_state: VideoCardState,
     pub display_info:    Vec<DisplayInfo>,
@@ -303,14 +360,19 @@ pub struct GuiState {
     pub io_stats_viewer: IoStatsViewerControl,
     pub device_control: DeviceControl,
     pub vhd_creator: VhdCreator,
+    pub floppy_creator: FloppyCreator,
+    pub floppy_converter: FloppyConverter,
     pub text_mode_viewer: TextModeViewer,
     pub fdc_viewer: FdcViewerControl,
     pub floppy_viewer: FloppyViewerControl,
     pub call_stack_viewer: CallStackViewer,
+    pub debug_console: DebugConsoleControl,
 
     pub floppy_tree_menu: FileTreeMenu,
     pub hdd_tree_menu:    FileTreeMenu,
     pub cart_tree_menu:   FileTreeMenu,
+    pub cdrom_tree_menu:  FileTreeMenu,
+    pub tape_tree_menu:   FileTreeMenu,
 
     //pub(crate) global_zoom: f32,
     pub modal: ModalState,
@@ -340,6 +402,7 @@ impl GuiState {
             (GuiBoolean::TurboButton, false),
             (GuiBoolean::ShowBackBuffer, false),
             (GuiBoolean::ShowRasterPosition, true),
+            (GuiBoolean::ShowRasterRegions, false),
             //(GuiBoolean::EnableSnow, true),
         ]
         .into();
@@ -349,9 +412,12 @@ impl GuiState {
         Self {
             event_queue: GuiEventQueue::new(),
             toasts: Toasts::new().with_anchor(Anchor::BottomRight),
+            osd: Default::default(),
+            status_bar: Default::default(),
             media_tray: Default::default(),
 
             default_floppy_path: None,
+            default_movie_path: None,
 
             window_open_flags,
             window_state,
@@ -362,6 +428,9 @@ impl GuiState {
             option_enums,
 
             machine_state: MachineState::Off,
+            keyboard_leds: None,
+            video_capture_active: false,
+            text_export_full_buffer: false,
             video_mem: ColorImage::new([320, 200], egui::Color32::BLACK),
 
             perf_stats: Default::default(),
@@ -373,6 +442,8 @@ impl GuiState {
             floppy_drives: Vec::new(),
             hdds: Vec::new(),
             carts: Vec::new(),
+            cdrom: None,
+            cassette: None,
             vhd_names: Vec::new(),
             autofloppy_paths: Vec::new(),
 
@@ -394,13 +465,24 @@ impl GuiState {
 
             perf_viewer: PerformanceViewerControl::new(),
             delay_adjust: DelayAdjustControl::new(),
+            audio_mixer: AudioMixerControl::new(),
+            hotkey_editor: HotkeyEditorControl::new(),
+            savestate_browser: SaveStateBrowserControl::new(),
+            media_library: MediaLibraryControl::new(),
             pit_viewer: PitViewerControl::new(),
             serial_viewer: SerialViewerControl::new(),
             pic_viewer: PicViewerControl::new(),
             ppi_viewer: PpiViewerControl::new(),
+            post_card_viewer: PostCardViewerControl::new(),
+            dip_switch_editor: DipSwitchEditor::new(),
 
             videocard_state: Default::default(),
             display_info: Vec::new(),
+            video_register_log: VideoRegisterLogControl::new(),
+            vram_viewer: VramViewerControl::new(),
+            character_set_viewer: CharacterSetViewerControl::new(),
+            palette_viewer: PaletteViewerControl::new(),
+            crtc_timing_viewer: CrtcTimingViewerControl::new(),
             disassembly_viewer: DisassemblyControl::new(),
             dma_viewer: DmaViewerControl::new(),
             trace_viewer: InstructionHistoryControl::new(),
@@ -410,14 +492,19 @@ impl GuiState {
             io_stats_viewer: IoStatsViewerControl::new(),
             device_control: DeviceControl::new(),
             vhd_creator: VhdCreator::new(),
+            floppy_creator: FloppyCreator::new(),
+            floppy_converter: FloppyConverter::new(),
             text_mode_viewer: TextModeViewer::new(),
             fdc_viewer: FdcViewerControl::new(),
             floppy_viewer: FloppyViewerControl::new(),
             call_stack_viewer: CallStackViewer::new(),
+            debug_console: DebugConsoleControl::new(),
 
             floppy_tree_menu: FileTreeMenu::new().with_file_icon("💾"),
             hdd_tree_menu: FileTreeMenu::new().with_file_icon("🖴"),
             cart_tree_menu: FileTreeMenu::new(),
+            cdrom_tree_menu: FileTreeMenu::new(),
+            tape_tree_menu: FileTreeMenu::new().with_file_icon("📼"),
             //global_zoom: 1.0,
             modal: ModalState::new(),
         }
@@ -437,6 +524,65 @@ impl GuiState {
         &mut self.toasts
     }
 
+    pub fn osd(&mut self) -> &mut Osd {
+        &mut self.osd
+    }
+
+    pub fn status_bar(&mut self) -> &mut StatusBar {
+        &mut self.status_bar
+    }
+
+    /// Pass a host key event to the hotkey editor. Returns true if the editor is recording a key
+    /// combination and consumed the key.
+    pub fn record_hotkey_key(&mut self, key: MartyKey, pressed: bool) -> bool {
+        if !self.hotkey_editor.is_recording() {
+            return false;
+        }
+        self.hotkey_editor.record_key(key, pressed, &mut self.event_queue);
+        true
+    }
+
+    /// Open the file dialog to browse for a floppy image to load into the specified drive.
+    pub fn set_movie_path(&mut self, path: PathBuf) {
+        self.default_movie_path = Some(path);
+    }
+
+    pub fn browse_movie(&mut self) {
+        if !self.modal.is_open() {
+            self.modal.open(ModalContext::OpenMovie, self.default_movie_path.clone());
+        }
+    }
+
+    /// Open the file dialog to browse for a DOS program to quick-load.
+    pub fn browse_program(&mut self) {
+        if !self.modal.is_open() {
+            self.modal.open(ModalContext::OpenProgram, None);
+        }
+    }
+
+    /// Open the chooser asking where to insert a file dropped onto the emulator window.
+    pub fn show_drop_chooser(&mut self, path: PathBuf, target: DropTarget, write_protect: bool) {
+        if self.modal.is_open() {
+            return;
+        }
+        let slot_ct = match target {
+            DropTarget::Floppy => self.floppy_drives.len(),
+            DropTarget::Cartridge => self.carts.len(),
+            DropTarget::Program => 1,
+        };
+        self.modal
+            .open(ModalContext::DroppedFile(path, target, slot_ct, write_protect), None);
+    }
+
+    pub fn browse_floppy(&mut self, drive_idx: usize) {
+        if drive_idx < self.floppy_drives.len() && !self.modal.is_open() {
+            self.modal.open(
+                ModalContext::OpenFloppyImage(drive_idx, Vec::new()),
+                self.default_floppy_path.clone(),
+            );
+        }
+    }
+
     pub fn get_event(&mut self) -> Option<GuiEvent> {
         self.event_queue.pop()
     }
@@ -509,6 +655,14 @@ impl GuiState {
         self.machine_state = state;
     }
 
+    pub fn set_keyboard_leds(&mut self, leds: Option<u8>) {
+        self.keyboard_leds = leds;
+    }
+
+    pub fn set_video_capture_active(&mut self, active: bool) {
+        self.video_capture_active = active;
+    }
+
     pub fn set_floppy_drives(&mut self, drives: Vec<FloppyDriveType>) {
         self.floppy_drives.clear();
 
@@ -524,15 +678,46 @@ impl GuiState {
                 supported_formats: Vec::new(),
                 source_format: None,
                 source_writeback: false,
+                archive_path: None,
+                archive_images: Vec::new(),
+                write_back: FloppyWriteBack::default(),
+                modified: false,
+                has_backup: false,
                 write_ct: 0,
             });
         }
     }
 
+    /// Set the zip archive the disk in the specified drive was mounted from, and the disk images
+    /// it contains, so the drive's menu can offer the other images.
+    pub fn set_floppy_archive(&mut self, drive: usize, path: PathBuf, images: Vec<String>) {
+        self.floppy_drives[drive].archive_path = Some(path);
+        self.floppy_drives[drive].archive_images = images;
+    }
+
+    pub fn set_floppy_write_back(&mut self, drive: usize, mode: FloppyWriteBack) {
+        self.floppy_drives[drive].write_back = mode;
+    }
+
+    pub fn floppy_write_back(&self, drive: usize) -> FloppyWriteBack {
+        self.floppy_drives[drive].write_back
+    }
+
+    /// Show whether the image in the specified drive has changes that weren't written to its file,
+    /// and whether a backup of the file can be restored.
+    pub fn set_floppy_modified(&mut self, drive: usize, modified: bool, has_backup: bool) {
+        self.floppy_drives[drive].modified = modified;
+        self.floppy_drives[drive].has_backup = has_backup;
+    }
+
     pub fn set_floppy_write_protected(&mut self, drive: usize, state: bool) {
         self.floppy_drives[drive].write_protect(state);
     }
 
+    pub fn floppy_write_protected(&self, drive: usize) -> bool {
+        self.floppy_drives[drive].write_protected
+    }
+
     pub fn set_floppy_tree(&mut self, tree: PathTreeNode) {
         self.floppy_tree_menu.set_root(tree);
     }
@@ -565,6 +750,10 @@ impl GuiState {
             self.floppy_viewer.clear_visualization(drive);
         }
         self.floppy_drives[drive].selected_path = name;
+        self.floppy_drives[drive].archive_path = None;
+        self.floppy_drives[drive].archive_images.clear();
+        self.floppy_drives[drive].modified = false;
+        self.floppy_drives[drive].has_backup = false;
 
         if let Some(read_only) = read_only {
             self.floppy_drives[drive].read_only = read_only;
@@ -606,6 +795,8 @@ impl GuiState {
                 selected_idx: None,
                 selected_path: None,
                 write_protected: true,
+                host_dir: false,
+                write_back: false,
             });
         }
     }
@@ -617,6 +808,15 @@ impl GuiState {
     pub fn set_hdd_selection(&mut self, drive: usize, idx: Option<usize>, name: Option<PathBuf>) {
         self.hdds[drive].selected_idx = idx;
         self.hdds[drive].selected_path = name;
+        self.hdds[drive].host_dir = false;
+    }
+
+    /// Show a host directory as the selection for the specified hard disk.
+    pub fn set_hdd_host_dir(&mut self, drive: usize, path: PathBuf, write_back: bool) {
+        self.hdds[drive].selected_idx = None;
+        self.hdds[drive].selected_path = Some(path);
+        self.hdds[drive].host_dir = true;
+        self.hdds[drive].write_back = write_back;
     }
 
     pub fn set_cart_slots(&mut self, slotct: usize) {
@@ -639,6 +839,58 @@ impl GuiState {
         self.cart_tree_menu.set_root(tree);
     }
 
+    /// Show or hide the CD-ROM drive media menu.
+    pub fn set_cdrom(&mut self, present: bool) {
+        self.cdrom = present.then(|| GuiCdRomInfo {
+            selected_idx:  None,
+            selected_path: None,
+        });
+    }
+
+    pub fn set_cdrom_selection(&mut self, idx: Option<usize>, name: Option<PathBuf>) {
+        if let Some(cdrom) = &mut self.cdrom {
+            cdrom.selected_idx = idx;
+            cdrom.selected_path = name;
+        }
+    }
+
+    pub fn set_cdrom_tree(&mut self, tree: PathTreeNode) {
+        self.cdrom_tree_menu.set_root(tree);
+    }
+
+    /// Return a description of each piece of media currently selected in the media menus.
+    pub fn mounted_media(&self) -> Vec<String> {
+        let mut media = Vec::new();
+        for drive in self.floppy_drives.iter() {
+            if let Some(name) = drive.filename() {
+                media.push(format!("Floppy {}: {}", drive.idx, name));
+            }
+        }
+        for hdd in self.hdds.iter() {
+            if let Some(name) = hdd.filename() {
+                media.push(format!("Hard disk {}: {}", hdd.idx, name));
+            }
+        }
+        for cart in self.carts.iter() {
+            if let Some(name) = cart.filename() {
+                media.push(format!("Cartridge {}: {}", cart.idx, name));
+            }
+        }
+        if let Some(name) = self.cdrom.as_ref().and_then(|cdrom| cdrom.filename()) {
+            media.push(format!("CD-ROM: {}", name));
+        }
+        media
+    }
+
+    /// Update the cassette deck status. The cassette menu is hidden if there is no deck.
+    pub fn set_cassette(&mut self, status: Option<CassetteStatus>) {
+        self.cassette = status;
+    }
+
+    pub fn set_cassette_tree(&mut self, tree: PathTreeNode) {
+        self.tape_tree_menu.set_root(tree);
+    }
+
     /// Set display apertures for the specified display. Should be called in a loop for each display
     /// target.
     pub fn set_display_apertures(&mut self, display: usize, apertures: Vec<DisplayApertureDesc>) {
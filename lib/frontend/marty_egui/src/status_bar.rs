@@ -0,0 +1,178 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+    egui::status_bar.rs
+
+    Implement the status bar, which shows live performance and machine
+    metrics on the right side of the menu bar.
+
+*/
+
+use egui::{Color32, RichText, Ui};
+use web_time::{Duration, Instant};
+
+/// How long the audio indicator stays red after the audio buffer runs dry.
+const AUDIO_STARVED_HOLD: Duration = Duration::from_millis(1000);
+
+const ACTIVE_COLOR: Color32 = Color32::from_rgb(0x40, 0xFF, 0x40);
+const WARNING_COLOR: Color32 = Color32::from_rgb(0xFF, 0x40, 0x40);
+
+/// Selects which items are shown in the status bar.
+#[derive(Copy, Clone)]
+pub struct StatusBarItems {
+    pub cpu_mhz: bool,
+    pub fps: bool,
+    pub audio: bool,
+    pub disk: bool,
+    pub speed: bool,
+}
+
+impl Default for StatusBarItems {
+    fn default() -> Self {
+        Self {
+            cpu_mhz: true,
+            fps: true,
+            audio: true,
+            disk: true,
+            speed: true,
+        }
+    }
+}
+
+/// A snapshot of the metrics shown in the status bar, updated by the frontend once per frame.
+#[derive(Clone, Default)]
+pub struct StatusBarMetrics {
+    /// Effective emulated CPU speed over the last second, in MHz.
+    pub cpu_mhz: f64,
+    /// The CPU speed the machine is configured to run at, in MHz.
+    pub target_mhz: f64,
+    pub host_fps: u32,
+    pub emu_fps: u32,
+    /// The highest latency of any unmuted sound source, or None if there is no audio output.
+    pub audio_latency_ms: Option<f32>,
+    /// Total underruns across all sound sources.
+    pub audio_underruns: u64,
+    /// Motor state of each floppy drive.
+    pub floppy_activity: Vec<bool>,
+    pub turbo: bool,
+    pub frame_skip: u32,
+}
+
+pub struct StatusBar {
+    enabled: bool,
+    items: StatusBarItems,
+    metrics: StatusBarMetrics,
+    audio_starved_at: Option<Instant>,
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            items: Default::default(),
+            metrics: Default::default(),
+            audio_starved_at: None,
+        }
+    }
+}
+
+impl StatusBar {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_items(&mut self, items: StatusBarItems) {
+        self.items = items;
+    }
+
+    pub fn update(&mut self, metrics: StatusBarMetrics) {
+        if metrics.audio_underruns > self.metrics.audio_underruns {
+            self.audio_starved_at = Some(Instant::now());
+        }
+        self.metrics = metrics;
+    }
+
+    /// Draw the status bar items. Must be called from within a right-to-left layout, so items
+    /// are added in reverse order.
+    pub(crate) fn draw(&self, ui: &mut Ui) {
+        if !self.enabled {
+            return;
+        }
+        let m = &self.metrics;
+
+        if self.items.speed {
+            if m.frame_skip > 0 {
+                ui.label(RichText::new(format!("Skip {}", m.frame_skip)).small());
+            }
+            match m.turbo {
+                true => ui.label(RichText::new("Turbo").small().color(ACTIVE_COLOR)),
+                false => ui.label(RichText::new("Normal").small()),
+            };
+            ui.separator();
+        }
+
+        if self.items.disk && !m.floppy_activity.is_empty() {
+            for (idx, active) in m.floppy_activity.iter().enumerate().rev() {
+                let color = match active {
+                    true => ACTIVE_COLOR,
+                    false => Color32::DARK_GRAY,
+                };
+                let letter = (b'A' + idx as u8) as char;
+                ui.label(RichText::new(format!("💾{}", letter)).small().color(color));
+            }
+            ui.separator();
+        }
+
+        if self.items.audio {
+            if let Some(latency) = m.audio_latency_ms {
+                let starved = self
+                    .audio_starved_at
+                    .map_or(false, |t| t.elapsed() < AUDIO_STARVED_HOLD);
+                let mut text = RichText::new(format!("🔊 {:.0} ms", latency)).small();
+                if starved {
+                    text = text.color(WARNING_COLOR);
+                }
+                ui.label(text)
+                    .on_hover_text(format!("Audio buffer latency. Underruns: {}", m.audio_underruns));
+                ui.separator();
+            }
+        }
+
+        if self.items.fps {
+            ui.label(RichText::new(format!("{} / {} FPS", m.host_fps, m.emu_fps)).small())
+                .on_hover_text("Host display FPS / emulated FPS");
+            ui.separator();
+        }
+
+        if self.items.cpu_mhz {
+            let text = match m.target_mhz > 0.0 {
+                true => format!("{:.2} MHz ({:.0}%)", m.cpu_mhz, m.cpu_mhz / m.target_mhz * 100.0),
+                false => format!("{:.2} MHz", m.cpu_mhz),
+            };
+            ui.label(RichText::new(text).small());
+            ui.separator();
+        }
+    }
+}
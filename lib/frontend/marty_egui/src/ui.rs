@@ -45,7 +45,10 @@ impl GuiState {
     pub fn ui(&mut self, ctx: &Context) {
         // Init things that need the context
         self.toasts.show(ctx);
+        self.osd.show(ctx);
         self.data_visualizer.init(ctx.clone());
+        self.vram_viewer.init(ctx.clone());
+        self.character_set_viewer.init(ctx.clone());
         self.floppy_viewer.init(ctx.clone());
 
         // Do file dialogs
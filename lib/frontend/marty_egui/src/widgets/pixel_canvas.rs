@@ -126,6 +126,14 @@ impl PixelCanvasDepth {
     }
 }
 
+/// The order in which the pixels of a 1bpp byte are drawn, left to right.
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum PixelCanvasBitOrder {
+    #[default]
+    LsbFirst,
+    MsbFirst,
+}
+
 pub struct PixelCanvasPalette {
     name:   String,
     depth:  PixelCanvasDepth,
@@ -138,6 +146,7 @@ pub struct PixelCanvas {
     view_dimensions: (u32, u32),
     zoom: f32,
     bpp: PixelCanvasDepth,
+    bit_order: PixelCanvasBitOrder,
     device_palette: PixelCanvasPalette,
     use_device_palette: bool,
     current_palette: PixelCanvasPalette,
@@ -159,6 +168,7 @@ impl Default for PixelCanvas {
             view_dimensions: (DEFAULT_WIDTH, DEFAULT_HEIGHT),
             zoom: 1.0,
             bpp: PixelCanvasDepth::OneBpp,
+            bit_order: PixelCanvasBitOrder::LsbFirst,
             device_palette: PixelCanvasPalette {
                 name:   "Default".to_string(),
                 depth:  PixelCanvasDepth::OneBpp,
@@ -354,6 +364,11 @@ impl PixelCanvas {
         self.data_unpacked = false;
     }
 
+    pub fn set_bit_order(&mut self, bit_order: PixelCanvasBitOrder) {
+        self.bit_order = bit_order;
+        self.data_unpacked = false;
+    }
+
     pub fn set_zoom(&mut self, zoom: f32) {
         self.zoom = zoom;
     }
@@ -425,7 +440,10 @@ impl PixelCanvas {
             PixelCanvasDepth::OneBpp => {
                 for i in 0..self.view_dimensions.0 * self.view_dimensions.1 {
                     let byte = self.data_buf[(i / 8) as usize];
-                    let bit = 1 << (i % 8);
+                    let bit = match self.bit_order {
+                        PixelCanvasBitOrder::LsbFirst => 1 << (i % 8),
+                        PixelCanvasBitOrder::MsbFirst => 0x80 >> (i % 8),
+                    };
                     self.backing_buf[i as usize] = if byte & bit != 0 {
                         Color32::WHITE
                     }
@@ -0,0 +1,230 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::audio_mixer.rs
+
+    Implements a mixer control for the machine's sound sources.
+
+    Each sound source has its own volume, pan and mute controls. Settings
+    can be saved so that they are restored the next time a machine with the
+    same sound sources is started.
+
+    Sound output can also be captured to WAV from here, optionally with a
+    separate file for each source.
+
+    The output section shows the audio device settings and playback
+    statistics for each source, and allows the resampler quality and
+    maximum latency to be adjusted while running.
+
+*/
+
+use crate::*;
+use frontend_common::types::sound::{AudioOutputStats, ResamplerQuality, SoundSourceStats};
+
+const DEFAULT_MAX_LATENCY_MS: u32 = 150;
+
+pub struct AudioMixerControl {
+    sources: Vec<SoundSourceStats>,
+    output: AudioOutputStats,
+    capturing: bool,
+    capture_stems: bool,
+}
+
+impl AudioMixerControl {
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            output: Default::default(),
+            capturing: false,
+            capture_stems: false,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        if self.sources.is_empty() {
+            ui.label("No sound sources.");
+            return;
+        }
+
+        egui::Grid::new("audio_mixer")
+            .striped(true)
+            .min_col_width(60.0)
+            .show(ui, |ui| {
+                ui.label(egui::RichText::new("Source").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Mute").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Volume").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Pan").text_style(egui::TextStyle::Monospace));
+                ui.end_row();
+
+                for (i, source) in self.sources.iter_mut().enumerate() {
+                    ui.label(&source.name);
+                    if ui.checkbox(&mut source.muted, "").changed() {
+                        events.send(GuiEvent::SetSoundMuted(i, source.muted));
+                    }
+
+                    let mut volume_pct = source.volume * 100.0;
+                    if ui
+                        .add_enabled(
+                            !source.muted,
+                            egui::Slider::new(&mut volume_pct, 0.0..=100.0)
+                                .suffix("%")
+                                .fixed_decimals(0),
+                        )
+                        .changed()
+                    {
+                        source.volume = volume_pct / 100.0;
+                        events.send(GuiEvent::SetSoundVolume(i, source.volume));
+                    }
+
+                    let pan_response = ui
+                        .add(egui::Slider::new(&mut source.pan, -1.0..=1.0).fixed_decimals(2))
+                        .on_hover_text("Left (-1.0) to right (1.0). Double-click to center.");
+                    if pan_response.double_clicked() {
+                        source.pan = 0.0;
+                        events.send(GuiEvent::SetSoundPan(i, source.pan));
+                    }
+                    else if pan_response.changed() {
+                        events.send(GuiEvent::SetSoundPan(i, source.pan));
+                    }
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui
+                .button("Save")
+                .on_hover_text("Save mixer settings for future sessions")
+                .clicked()
+            {
+                events.send(GuiEvent::SaveMixerSettings);
+            }
+
+            ui.separator();
+            let capture_label = if self.capturing {
+                "⏹ Stop Capture"
+            }
+            else {
+                "⏺ Capture WAV"
+            };
+            if ui.button(capture_label).clicked() {
+                events.send(GuiEvent::ToggleAudioCapture(self.capture_stems));
+            }
+            ui.add_enabled(
+                !self.capturing,
+                egui::Checkbox::new(&mut self.capture_stems, "Separate file per source"),
+            );
+        });
+
+        ui.separator();
+        egui::CollapsingHeader::new("Output")
+            .default_open(false)
+            .show(ui, |ui| {
+                self.draw_output(ui, events);
+            });
+    }
+
+    fn draw_output(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        egui::Grid::new("audio_output").striped(false).show(ui, |ui| {
+            ui.label("Device:");
+            ui.label(&self.output.device_name);
+            ui.end_row();
+
+            ui.label("Sample Rate:");
+            ui.label(format!("{}Hz ({})", self.output.sample_rate, self.output.sample_format));
+            ui.end_row();
+
+            ui.label("Buffer Size:");
+            ui.label(format!("{} frames", self.output.buffer_size))
+                .on_hover_text("Sample rate and buffer size are set in the configuration file.");
+            ui.end_row();
+
+            ui.label("Resampler:");
+            let mut resampler = self.output.resampler;
+            egui::ComboBox::from_id_source("audio_resampler")
+                .selected_text(resampler.to_string())
+                .show_ui(ui, |ui| {
+                    for quality in ResamplerQuality::ALL {
+                        ui.selectable_value(&mut resampler, quality, quality.to_string());
+                    }
+                });
+            if resampler != self.output.resampler {
+                self.output.resampler = resampler;
+                events.send(GuiEvent::SetAudioResampler(resampler));
+            }
+            ui.end_row();
+
+            ui.label("Max Latency:");
+            ui.horizontal(|ui| {
+                let mut limited = self.output.max_latency_ms.is_some();
+                let mut latency_ms = self.output.max_latency_ms.unwrap_or(DEFAULT_MAX_LATENCY_MS);
+                let mut changed = ui.checkbox(&mut limited, "").changed();
+                changed |= ui
+                    .add_enabled(limited, egui::Slider::new(&mut latency_ms, 20..=1000).suffix("ms"))
+                    .changed();
+                if changed {
+                    self.output.max_latency_ms = limited.then_some(latency_ms);
+                    events.send(GuiEvent::SetAudioMaxLatency(self.output.max_latency_ms));
+                }
+            });
+            ui.end_row();
+        });
+
+        ui.separator();
+        egui::Grid::new("audio_output_stats")
+            .striped(true)
+            .min_col_width(60.0)
+            .show(ui, |ui| {
+                ui.label(egui::RichText::new("Source").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Rate").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Latency").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Underruns").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Dropped").text_style(egui::TextStyle::Monospace));
+                ui.end_row();
+
+                for source in self.sources.iter() {
+                    ui.label(&source.name);
+                    ui.label(format!("{}Hz", source.sample_rate));
+                    ui.label(format!("{:.1}ms", source.latency_ms));
+                    ui.label(format!("{}", source.underruns));
+                    ui.label(format!("{}", source.dropped));
+                    ui.end_row();
+                }
+            });
+    }
+
+    pub fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+    }
+
+    pub fn update_sources(&mut self, sources: Vec<SoundSourceStats>) {
+        self.sources = sources;
+    }
+
+    pub fn update_output(&mut self, output: AudioOutputStats) {
+        self.output = output;
+    }
+}
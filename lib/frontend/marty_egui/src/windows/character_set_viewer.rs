@@ -0,0 +1,201 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+
+    egui::character_set_viewer.rs
+
+    Implements a viewer for the character sets of the video card's
+    character generator, from ROM or, for the EGA and VGA, loaded into
+    font RAM. Glyphs are displayed in a 16x16 grid and can be exported
+    to PNG.
+
+*/
+
+use crate::{
+    widgets::pixel_canvas::{PixelCanvas, PixelCanvasDepth},
+    GuiEventQueue,
+};
+use marty_common::find_unique_filename;
+use marty_core::device_traits::videocard::CharacterSet;
+use std::path::PathBuf;
+
+pub const GRID_COLS: u32 = 16;
+pub const GRID_ROWS: u32 = 16;
+pub const DEFAULT_ZOOM: f32 = 2.0;
+
+const GLYPH_FG: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const GLYPH_BG: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+const GRID_COLOR: [u8; 4] = [0x40, 0x40, 0x40, 0xFF];
+
+pub struct CharacterSetViewerControl {
+    sets: Vec<CharacterSet>,
+    selected: usize,
+    show_grid: bool,
+    canvas: Option<PixelCanvas>,
+    dims: (u32, u32),
+    dirty: bool,
+    dump_path: Option<PathBuf>,
+}
+
+impl CharacterSetViewerControl {
+    pub fn new() -> Self {
+        Self {
+            sets: Vec::new(),
+            selected: 0,
+            show_grid: true,
+            canvas: None,
+            dims: (0, 0),
+            dirty: false,
+            dump_path: None,
+        }
+    }
+
+    pub fn init(&mut self, ctx: egui::Context) {
+        if self.canvas.is_none() {
+            let mut canvas = PixelCanvas::new((GRID_COLS * 8, GRID_ROWS * 8), ctx);
+            canvas.set_bpp(PixelCanvasDepth::Rgba);
+            canvas.set_zoom(DEFAULT_ZOOM);
+            self.canvas = Some(canvas);
+        }
+    }
+
+    pub fn set_dump_path(&mut self, path: PathBuf) {
+        self.dump_path = Some(path);
+    }
+
+    pub fn update(&mut self, sets: Vec<CharacterSet>) {
+        let changed = sets.len() != self.sets.len()
+            || sets
+                .iter()
+                .zip(self.sets.iter())
+                .any(|(new, old)| new.data != old.data || new.active != old.active || new.w != old.w);
+        if changed {
+            self.sets = sets;
+            self.dirty = true;
+        }
+    }
+
+    /// Render the selected character set as a grid of glyphs into an RGBA buffer.
+    fn render(&self, set: &CharacterSet) -> ((u32, u32), Vec<u8>) {
+        let gap = if self.show_grid { 1 } else { 0 };
+        let cell_w = set.w + gap;
+        let cell_h = set.h + gap;
+        let dims = (GRID_COLS * cell_w + gap, GRID_ROWS * cell_h + gap);
+        let mut buf = vec![0; (dims.0 * dims.1 * 4) as usize];
+
+        for y in 0..dims.1 {
+            for x in 0..dims.0 {
+                let color = match ((x % cell_w).checked_sub(gap), (y % cell_h).checked_sub(gap)) {
+                    (Some(gx), Some(gy)) if x / cell_w < GRID_COLS && y / cell_h < GRID_ROWS => {
+                        let glyph = ((y / cell_h) * GRID_COLS + (x / cell_w)) as u8;
+                        let row = set.glyph(glyph)[gy as usize];
+                        let lit = if gx < 8 {
+                            row & (0x80 >> gx) != 0
+                        }
+                        else {
+                            // The ninth column of 9-dot fonts repeats the eighth for line drawing characters.
+                            (0xC0..=0xDF).contains(&glyph) && (row & 0x01 != 0)
+                        };
+                        if lit {
+                            GLYPH_FG
+                        }
+                        else {
+                            GLYPH_BG
+                        }
+                    }
+                    _ => GRID_COLOR,
+                };
+                let o = ((y * dims.0 + x) * 4) as usize;
+                buf[o..o + 4].copy_from_slice(&color);
+            }
+        }
+        (dims, buf)
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut GuiEventQueue) {
+        if self.sets.is_empty() {
+            ui.label("The current video card has no character generator.");
+            return;
+        }
+        self.selected = std::cmp::min(self.selected, self.sets.len() - 1);
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("character_set_combo")
+                .selected_text(&self.sets[self.selected].name)
+                .show_ui(ui, |ui| {
+                    for (i, set) in self.sets.iter().enumerate() {
+                        let label = if set.active {
+                            format!("{} (active)", set.name)
+                        }
+                        else {
+                            set.name.clone()
+                        };
+                        if ui.selectable_value(&mut self.selected, i, label).clicked() {
+                            self.dirty = true;
+                        }
+                    }
+                });
+
+            if ui.checkbox(&mut self.show_grid, "Grid").changed() {
+                self.dirty = true;
+            }
+
+            if ui
+                .button("Save PNG")
+                .on_hover_text("Save the character set to file.")
+                .clicked()
+            {
+                if let (Some(canvas), Some(dump_path)) = (self.canvas.as_mut(), &self.dump_path) {
+                    let filename = find_unique_filename(dump_path, "charset", "png");
+                    match canvas.save_buffer(&filename) {
+                        Ok(_) => log::info!("Saved character set to file: {}", filename.display()),
+                        Err(e) => log::error!("Error saving character set to file: {}", e),
+                    }
+                }
+            }
+        });
+
+        let set = &self.sets[self.selected];
+        ui.label(format!("{}x{} glyphs", set.w, set.h));
+
+        if self.dirty {
+            let (dims, buf) = self.render(set);
+            if let Some(canvas) = &mut self.canvas {
+                if dims != self.dims {
+                    canvas.resize(dims, None);
+                    self.dims = dims;
+                }
+                canvas.update_data(&buf, None);
+            }
+            self.dirty = false;
+        }
+
+        if let Some(canvas) = &mut self.canvas {
+            ui.separator();
+            canvas.draw(ui);
+        }
+    }
+}
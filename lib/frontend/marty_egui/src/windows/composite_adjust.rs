@@ -109,6 +109,15 @@ impl CompositeAdjustControl {
                 }
                 ui.end_row();
 
+                ui.label(egui::RichText::new("Sharpness:").text_style(egui::TextStyle::Monospace));
+                if ui
+                    .add(egui::Slider::new(&mut self.params[self.dt_idx].sharpness, -1.0..=1.0))
+                    .changed()
+                {
+                    update = true;
+                }
+                ui.end_row();
+
                 ui.label(egui::RichText::new("Phase Offset:").text_style(egui::TextStyle::Monospace));
                 if ui
                     .add(egui::Slider::new(&mut self.temp_phase[self.dt_idx], 0.0..=270.0).step_by(90.0))
@@ -43,6 +43,8 @@ pub struct BreakpointSet<'a> {
     pub mem_breakpoint: &'a str,
     pub int_breakpoint: &'a str,
     pub io_breakpoint: &'a str,
+    pub mode_breakpoint: &'a str,
+    pub crtc_breakpoint: &'a str,
     pub sw_start: &'a str,
     pub sw_stop: &'a str,
 }
@@ -53,6 +55,8 @@ pub struct CpuControl {
     mem_breakpoint: String,
     int_breakpoint: String,
     io_breakpoint: String,
+    mode_breakpoint: String,
+    crtc_breakpoint: String,
     sw_start: String,
     sw_stop: String,
     sw_measurements: String,
@@ -71,6 +75,8 @@ impl CpuControl {
             mem_breakpoint: String::new(),
             int_breakpoint: String::new(),
             io_breakpoint: String::new(),
+            mode_breakpoint: String::new(),
+            crtc_breakpoint: String::new(),
             sw_start: String::new(),
             sw_stop: String::new(),
             sw_measurements: String::new(),
@@ -255,6 +261,32 @@ impl CpuControl {
                     events.send(GuiEvent::EditBreakpoint);
                 }
                 ui.end_row();
+
+                ui.label("Video Mode Breakpoint: ");
+                if ui
+                    .text_edit_singleline(&mut self.mode_breakpoint)
+                    .on_hover_text(
+                        "Break on a write to the video mode register.\nEnter * for any value, or a hex value.",
+                    )
+                    .changed()
+                {
+                    events.send(GuiEvent::EditBreakpoint);
+                }
+                ui.end_row();
+
+                ui.label("CRTC Breakpoint: ");
+                if ui
+                    .text_edit_singleline(&mut self.crtc_breakpoint)
+                    .on_hover_text(
+                        "Break on a write to the specified CRTC registers.\n\
+                        Enter a comma-separated list of hex register numbers, each\n\
+                        optionally followed by =value, e.g. 04,09=01",
+                    )
+                    .changed()
+                {
+                    events.send(GuiEvent::EditBreakpoint);
+                }
+                ui.end_row();
             });
 
         egui::CollapsingHeader::new("StopWatch")
@@ -318,6 +350,8 @@ impl CpuControl {
             mem_breakpoint: &self.mem_breakpoint,
             int_breakpoint: &self.int_breakpoint,
             io_breakpoint: &self.io_breakpoint,
+            mode_breakpoint: &self.mode_breakpoint,
+            crtc_breakpoint: &self.crtc_breakpoint,
             sw_start: &self.sw_start,
             sw_stop: &self.sw_stop,
         }
@@ -0,0 +1,212 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+
+    egui::crtc_timing_viewer.rs
+
+    Implements a viewer that renders the timing programmed into the video
+    card's CRTC as a timing diagram, along with the resulting scan rates.
+    Values a real monitor would be unlikely to sync to are flagged.
+
+*/
+
+use crate::GuiEventQueue;
+use egui::{Color32, Rect, Sense, Vec2};
+use marty_core::device_traits::videocard::CrtcTiming;
+
+pub const DIAGRAM_WIDTH: f32 = 360.0;
+pub const DIAGRAM_HEIGHT: f32 = 20.0;
+
+// Permitted deviation from the monitor's nominal horizontal scan rate.
+pub const HFREQ_TOLERANCE: f64 = 0.03;
+pub const VFREQ_MIN: f64 = 45.0;
+pub const VFREQ_MAX: f64 = 65.0;
+
+const DISPLAY_COLOR: Color32 = Color32::from_rgb(0x30, 0x90, 0x30);
+const BLANK_COLOR: Color32 = Color32::from_rgb(0x50, 0x50, 0x50);
+const SYNC_COLOR: Color32 = Color32::from_rgb(0xC0, 0x30, 0x30);
+
+pub struct CrtcTimingViewerControl {
+    timing: Option<CrtcTiming>,
+}
+
+impl CrtcTimingViewerControl {
+    pub fn new() -> Self {
+        Self { timing: None }
+    }
+
+    pub fn update_state(&mut self, timing: Option<CrtcTiming>) {
+        self.timing = timing;
+    }
+
+    /// Draw a bar representing one axis of the CRTC's timing. Sync that extends past the total is
+    /// drawn wrapped around to the start of the bar, as the counters would.
+    fn draw_axis(ui: &mut egui::Ui, total: u32, displayed: u32, sync_start: u32, sync_width: u32) {
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(DIAGRAM_WIDTH, DIAGRAM_HEIGHT), Sense::hover());
+        let total = total.max(1) as f32;
+        let span = |start: f32, end: f32| {
+            Rect::from_min_max(
+                egui::pos2(rect.left() + rect.width() * (start / total).min(1.0), rect.top()),
+                egui::pos2(rect.left() + rect.width() * (end / total).min(1.0), rect.bottom()),
+            )
+        };
+
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0.0, BLANK_COLOR);
+        painter.rect_filled(span(0.0, displayed as f32), 0.0, DISPLAY_COLOR);
+
+        let sync_end = (sync_start + sync_width) as f32;
+        painter.rect_filled(span(sync_start as f32, sync_end), 0.0, SYNC_COLOR);
+        if sync_end > total {
+            painter.rect_filled(span(0.0, sync_end - total), 0.0, SYNC_COLOR);
+        }
+    }
+
+    /// Return a list of problems with the specified timing.
+    fn check_timing(timing: &CrtcTiming) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        for (axis, total, displayed, sync_start, sync_width) in [
+            (
+                "Horizontal",
+                timing.h_total,
+                timing.h_displayed,
+                timing.h_sync_start,
+                timing.h_sync_width,
+            ),
+            (
+                "Vertical",
+                timing.v_total,
+                timing.v_displayed,
+                timing.v_sync_start,
+                timing.v_sync_width,
+            ),
+        ] {
+            if displayed > total {
+                warnings.push(format!(
+                    "{} displayed exceeds total; the display area is truncated.",
+                    axis
+                ));
+            }
+            if sync_start >= total {
+                warnings.push(format!("{} sync position is beyond total; no sync is generated.", axis));
+            }
+            else if sync_start + sync_width > total {
+                warnings.push(format!("{} sync extends past total.", axis));
+            }
+            if sync_start < displayed {
+                warnings.push(format!("{} sync begins within the display area.", axis));
+            }
+        }
+
+        let hfreq = timing.hfreq();
+        if (hfreq - timing.nominal_hfreq).abs() > timing.nominal_hfreq * HFREQ_TOLERANCE {
+            warnings.push(format!(
+                "Horizontal rate of {:.3} kHz is out of spec (nominal {:.3} kHz).",
+                hfreq, timing.nominal_hfreq
+            ));
+        }
+        let vfreq = timing.vfreq();
+        if !(VFREQ_MIN..=VFREQ_MAX).contains(&vfreq) {
+            warnings.push(format!(
+                "Vertical rate of {:.2} Hz is out of spec ({}-{} Hz).",
+                vfreq, VFREQ_MIN, VFREQ_MAX
+            ));
+        }
+
+        warnings
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut GuiEventQueue) {
+        let timing = match &self.timing {
+            Some(timing) => timing,
+            None => {
+                ui.label("The current video card does not report CRTC timing.");
+                return;
+            }
+        };
+
+        ui.label(egui::RichText::new("Horizontal (character clocks)").strong());
+        CrtcTimingViewerControl::draw_axis(
+            ui,
+            timing.h_total,
+            timing.h_displayed,
+            timing.h_sync_start,
+            timing.h_sync_width,
+        );
+        ui.label(
+            egui::RichText::new(format!(
+                "total: {} displayed: {} sync: {}-{}",
+                timing.h_total,
+                timing.h_displayed,
+                timing.h_sync_start,
+                timing.h_sync_start + timing.h_sync_width
+            ))
+            .monospace(),
+        );
+
+        ui.add_space(4.0);
+        ui.label(egui::RichText::new("Vertical (scanlines)").strong());
+        CrtcTimingViewerControl::draw_axis(
+            ui,
+            timing.v_total,
+            timing.v_displayed,
+            timing.v_sync_start,
+            timing.v_sync_width,
+        );
+        ui.label(
+            egui::RichText::new(format!(
+                "total: {} displayed: {} sync: {}-{}",
+                timing.v_total,
+                timing.v_displayed,
+                timing.v_sync_start,
+                timing.v_sync_start + timing.v_sync_width
+            ))
+            .monospace(),
+        );
+
+        ui.separator();
+        egui::Grid::new("crtc_timing_rates").striped(true).show(ui, |ui| {
+            ui.label("Character clock:");
+            ui.label(egui::RichText::new(format!("{:.6} MHz", timing.char_clock)).monospace());
+            ui.end_row();
+            ui.label("Horizontal rate:");
+            ui.label(egui::RichText::new(format!("{:.3} kHz", timing.hfreq())).monospace());
+            ui.end_row();
+            ui.label("Vertical rate:");
+            ui.label(egui::RichText::new(format!("{:.3} Hz", timing.vfreq())).monospace());
+            ui.end_row();
+        });
+
+        let warnings = CrtcTimingViewerControl::check_timing(timing);
+        if !warnings.is_empty() {
+            ui.separator();
+            for warning in warnings {
+                ui.colored_label(Color32::from_rgb(0xFF, 0xA0, 0x00), warning);
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::debug_console.rs
+
+    Implements a text command console for the debugger.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::{GuiEvent, GuiEventQueue};
+
+const MAX_OUTPUT_LINES: usize = 1000;
+
+pub struct DebugConsoleControl {
+    output: VecDeque<String>,
+    input: String,
+    history: Vec<String>,
+    history_idx: Option<usize>,
+}
+
+impl DebugConsoleControl {
+    pub fn new() -> Self {
+        Self {
+            output: VecDeque::new(),
+            input: String::new(),
+            history: Vec::new(),
+            history_idx: None,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        let text_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .max_height(text_height * 24.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in self.output.iter() {
+                    ui.label(egui::RichText::new(line).monospace());
+                }
+            });
+
+        ui.separator();
+
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut self.input)
+                .font(egui::TextStyle::Monospace)
+                .desired_width(f32::INFINITY)
+                .hint_text("Type 'help' for a list of commands"),
+        );
+
+        if response.has_focus() {
+            if ui.input(|i| i.key_pressed(egui::Key::ArrowUp)) {
+                self.recall_history(true);
+            }
+            if ui.input(|i| i.key_pressed(egui::Key::ArrowDown)) {
+                self.recall_history(false);
+            }
+        }
+
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            let command = std::mem::take(&mut self.input);
+            self.history_idx = None;
+            self.push_output(&[format!("> {}", command)]);
+            events.send(GuiEvent::ConsoleCommand(command));
+            response.request_focus();
+        }
+    }
+
+    fn recall_history(&mut self, back: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        let idx = match (self.history_idx, back) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(idx), true) => Some(idx.saturating_sub(1)),
+            (Some(idx), false) if idx + 1 < self.history.len() => Some(idx + 1),
+            (Some(_), false) => None,
+        };
+        self.history_idx = idx;
+        self.input = idx.map(|i| self.history[i].clone()).unwrap_or_default();
+    }
+
+    pub fn push_output(&mut self, lines: &[String]) {
+        for line in lines {
+            if self.output.len() >= MAX_OUTPUT_LINES {
+                self.output.pop_front();
+            }
+            self.output.push_back(line.clone());
+        }
+    }
+
+    pub fn set_history(&mut self, history: Vec<String>) {
+        self.history = history;
+    }
+}
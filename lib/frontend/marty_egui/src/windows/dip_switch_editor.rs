@@ -0,0 +1,249 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::dip_switch_editor.rs
+
+    Implements an editor for the motherboard DIP switches read through the
+    8255 PPI on the IBM 5150 and 5160.
+
+    Switches can be toggled individually, or set by the function they
+    control. Since the BIOS only reads the switches during POST, edits are
+    applied to the machine on the next reset.
+
+    Switch information from
+    http://www.minuszerodegrees.net/5150/misc/5150_motherboard_switch_settings.htm
+
+*/
+
+use crate::{layouts::MartyLayout, *};
+use marty_core::devices::ppi::PpiDipSwitchState;
+
+const VIDEO_TYPES: [&str; 4] = ["EGA/VGA", "CGA 40 Column", "CGA 80 Column", "MDA"];
+
+pub struct DipSwitchEditor {
+    state: Option<PpiDipSwitchState>,
+    sw1: u8,
+    sw2: Option<u8>,
+    modified: bool,
+}
+
+impl DipSwitchEditor {
+    pub fn new() -> Self {
+        Self {
+            state: None,
+            sw1: 0,
+            sw2: None,
+            modified: false,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        let state = match self.state {
+            Some(state) => state,
+            None => {
+                ui.label("This machine has no motherboard DIP switches.");
+                return;
+            }
+        };
+
+        ui.label(format!("Machine type: {:?}", state.machine_type));
+        ui.separator();
+
+        ui.label(egui::RichText::new("Switch Block 1").strong());
+        self.modified |= Self::draw_switch_block(ui, &mut self.sw1);
+        if let Some(sw2) = &mut self.sw2 {
+            ui.label(egui::RichText::new("Switch Block 2").strong());
+            self.modified |= Self::draw_switch_block(ui, sw2);
+        }
+
+        ui.separator();
+
+        MartyLayout::new(layouts::Layout::KeyValue, "dip-switch-grid").show(ui, |ui| {
+            MartyLayout::kv_row(ui, "Floppy Drives", None, |ui| {
+                let mut drives = Self::floppy_drives(self.sw1);
+                egui::ComboBox::from_id_source("dip-floppy-drives")
+                    .selected_text(format!("{}", drives))
+                    .show_ui(ui, |ui| {
+                        for i in 0..=4 {
+                            if ui.selectable_value(&mut drives, i, format!("{}", i)).clicked() {
+                                Self::set_floppy_drives(&mut self.sw1, drives);
+                                self.modified = true;
+                            }
+                        }
+                    });
+            });
+            MartyLayout::kv_row(ui, "8087 FPU", None, |ui| {
+                // Switch 2 OFF indicates an 8087 is installed.
+                let mut have_fpu = self.sw1 & 0b0000_0010 == 0;
+                if ui.checkbox(&mut have_fpu, "Installed").changed() {
+                    self.sw1 ^= 0b0000_0010;
+                    self.modified = true;
+                }
+            });
+            MartyLayout::kv_row(ui, "Memory Banks", None, |ui| {
+                let mut banks = Self::memory_banks(self.sw1);
+                egui::ComboBox::from_id_source("dip-memory-banks")
+                    .selected_text(format!("{}", banks))
+                    .show_ui(ui, |ui| {
+                        for i in 1..=4 {
+                            if ui.selectable_value(&mut banks, i, format!("{}", i)).clicked() {
+                                Self::set_memory_banks(&mut self.sw1, banks);
+                                self.modified = true;
+                            }
+                        }
+                    });
+            });
+            if let Some(sw2) = &mut self.sw2 {
+                MartyLayout::kv_row(ui, "Total Memory", None, |ui| {
+                    let mut increments = Self::memory_increments(*sw2);
+                    egui::ComboBox::from_id_source("dip-total-memory")
+                        .selected_text(format!("{}K", 64 + increments * 32))
+                        .show_ui(ui, |ui| {
+                            for i in 0..=18 {
+                                if ui
+                                    .selectable_value(&mut increments, i, format!("{}K", 64 + i * 32))
+                                    .clicked()
+                                {
+                                    Self::set_memory_increments(sw2, increments);
+                                    self.modified = true;
+                                }
+                            }
+                        });
+                });
+            }
+            MartyLayout::kv_row(ui, "Video Type", None, |ui| {
+                let mut video = Self::video_type(self.sw1);
+                egui::ComboBox::from_id_source("dip-video-type")
+                    .selected_text(VIDEO_TYPES[video as usize])
+                    .show_ui(ui, |ui| {
+                        for (i, name) in VIDEO_TYPES.iter().enumerate() {
+                            if ui.selectable_value(&mut video, i as u8, *name).clicked() {
+                                Self::set_video_type(&mut self.sw1, video);
+                                self.modified = true;
+                            }
+                        }
+                    });
+            });
+        });
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.modified, egui::Button::new("Apply"))
+                .on_hover_text("Apply switch settings at the next reset")
+                .clicked()
+            {
+                events.send(GuiEvent::SetPpiDipSwitches(self.sw1, self.sw2));
+                self.modified = false;
+            }
+            if ui.add_enabled(self.modified, egui::Button::new("Revert")).clicked() {
+                self.sync(&state);
+            }
+        });
+
+        if state.pending.is_some() {
+            ui.label(egui::RichText::new("Changes will take effect on the next reset.").color(egui::Color32::YELLOW));
+        }
+    }
+
+    /// Draw a row of eight switches. Returns true if any switch was toggled.
+    fn draw_switch_block(ui: &mut egui::Ui, block: &mut u8) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            for i in 0..8 {
+                let mut on = *block & (1 << i) != 0;
+                if ui.checkbox(&mut on, format!("{}", i + 1)).changed() {
+                    *block ^= 1 << i;
+                    changed = true;
+                }
+            }
+        });
+        changed
+    }
+
+    fn floppy_drives(sw1: u8) -> u8 {
+        // Switch 1 ON indicates no floppy drives are installed.
+        if sw1 & 0b0000_0001 != 0 {
+            0
+        }
+        else {
+            // Switches 7 and 8 encode the drive count, minus one, with OFF as a set bit.
+            ((!sw1 >> 6) & 0b11) + 1
+        }
+    }
+
+    fn set_floppy_drives(sw1: &mut u8, drives: u8) {
+        if drives == 0 {
+            *sw1 |= 0b1100_0001;
+        }
+        else {
+            *sw1 = (*sw1 & 0b0011_1110) | ((!(drives - 1) & 0b11) << 6);
+        }
+    }
+
+    fn memory_banks(sw1: u8) -> u8 {
+        ((!sw1 >> 2) & 0b11) + 1
+    }
+
+    fn set_memory_banks(sw1: &mut u8, banks: u8) {
+        *sw1 = (*sw1 & 0b1111_0011) | ((!(banks - 1) & 0b11) << 2);
+    }
+
+    fn video_type(sw1: u8) -> u8 {
+        // Both ON selects EGA/VGA, both OFF selects MDA.
+        (!sw1 >> 4) & 0b11
+    }
+
+    fn set_video_type(sw1: &mut u8, video: u8) {
+        *sw1 = (*sw1 & 0b1100_1111) | ((!video & 0b11) << 4);
+    }
+
+    fn memory_increments(sw2: u8) -> u8 {
+        // Switches 1-5 encode the amount of memory above 64K in 32K increments, with OFF as a set bit.
+        !sw2 & 0b0001_1111
+    }
+
+    fn set_memory_increments(sw2: &mut u8, increments: u8) {
+        *sw2 = (*sw2 & 0b1110_0000) | (!increments & 0b0001_1111);
+    }
+
+    fn sync(&mut self, state: &PpiDipSwitchState) {
+        let (sw1, sw2) = state.pending.unwrap_or((state.sw1, state.sw2));
+        self.sw1 = sw1;
+        self.sw2 = sw2;
+        self.modified = false;
+    }
+
+    pub fn update_state(&mut self, state: Option<PpiDipSwitchState>) {
+        if let Some(new_state) = &state {
+            if !self.modified {
+                self.sync(new_state);
+            }
+        }
+        self.state = state;
+    }
+}
@@ -0,0 +1,177 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+    egui::floppy_converter.rs
+
+    Implements a tool to convert a floppy image file to another format.
+
+    Once a source image is selected, the emulator loads it and reports the
+    formats it can be written in. The converted image is written next to the
+    source image.
+
+*/
+
+use crate::{layouts::MartyLayout, widgets::big_icon::IconType, *};
+use fluxfox::DiskImageFileFormat;
+use std::path::PathBuf;
+
+pub struct FloppyConverter {
+    source: Option<PathBuf>,
+    source_format: Option<DiskImageFileFormat>,
+    formats: Vec<(DiskImageFileFormat, Vec<String>)>,
+    selected_format_idx: usize,
+    output_name: String,
+}
+
+impl FloppyConverter {
+    pub fn new() -> Self {
+        Self {
+            source: None,
+            source_format: None,
+            formats: Vec::new(),
+            selected_format_idx: 0,
+            output_name: String::new(),
+        }
+    }
+
+    /// Set the source image, with its format and the formats it can be converted to.
+    pub fn set_source(
+        &mut self,
+        path: PathBuf,
+        source_format: Option<DiskImageFileFormat>,
+        formats: Vec<(DiskImageFileFormat, Vec<String>)>,
+    ) {
+        self.source = Some(path);
+        self.source_format = source_format;
+        self.formats = formats.into_iter().filter(|(_, exts)| !exts.is_empty()).collect();
+        self.selected_format_idx = 0;
+        self.update_output_name();
+    }
+
+    /// Draw the converter. Returns true if the user asked to browse for a source image.
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) -> bool {
+        let mut browse = false;
+
+        ui.horizontal(|ui| {
+            IconType::Floppy.draw(ui, None);
+            ui.label(
+                egui::RichText::new(
+                    "Convert a floppy image to another format.\n\
+                        Only formats that can hold the image are offered.",
+                )
+                .font(egui::FontId::proportional(15.0)),
+            );
+        });
+
+        ui.separator();
+
+        MartyLayout::new(layouts::Layout::KeyValue, "floppy-converter-grid").show(ui, |ui| {
+            MartyLayout::kv_row(ui, "Source Image", None, |ui| {
+                match &self.source {
+                    Some(path) => ui.label(path.file_name().unwrap_or_default().to_string_lossy()),
+                    None => ui.label("<No Image>"),
+                };
+                if ui.button("Browse...").clicked() {
+                    browse = true;
+                }
+            });
+
+            if self.source.is_none() {
+                return;
+            }
+
+            MartyLayout::kv_row(ui, "Source Format", None, |ui| {
+                match self.source_format {
+                    Some(format) => ui.label(format!("{:?}", format)),
+                    None => ui.label("Unknown"),
+                };
+            });
+
+            if !self.formats.is_empty() {
+                let mut format_changed = false;
+                MartyLayout::kv_row(ui, "Target Format", None, |ui| {
+                    egui::ComboBox::from_id_source("floppy-converter-formats")
+                        .selected_text(Self::format_label(&self.formats[self.selected_format_idx]))
+                        .show_ui(ui, |ui| {
+                            for (i, format) in self.formats.iter().enumerate() {
+                                format_changed |= ui
+                                    .selectable_value(&mut self.selected_format_idx, i, Self::format_label(format))
+                                    .changed();
+                            }
+                        });
+                });
+                if format_changed {
+                    self.update_output_name();
+                }
+                MartyLayout::kv_row(ui, "Output Filename", Some(300.0), |ui| {
+                    ui.text_edit_singleline(&mut self.output_name);
+                });
+            }
+        });
+
+        if self.source.is_some() && self.formats.is_empty() {
+            ui.horizontal(|ui| {
+                IconType::Warning.draw(ui, None);
+                ui.label("This image can't be written in any format.");
+            });
+        }
+
+        ui.vertical_centered(|ui| {
+            let enabled = self.source.is_some() && !self.formats.is_empty() && !self.output_name.is_empty();
+            if ui.add_enabled(enabled, egui::Button::new("Convert!")).clicked() {
+                if let Some(source) = &self.source {
+                    events.send(GuiEvent::ConvertFloppyImage(
+                        source.clone(),
+                        self.formats[self.selected_format_idx].0,
+                        source.with_file_name(&self.output_name),
+                    ));
+                }
+            }
+        });
+
+        browse
+    }
+
+    fn format_label(format: &(DiskImageFileFormat, Vec<String>)) -> String {
+        format!("{:?} (.{})", format.0, format.1[0])
+    }
+
+    /// Name the output after the source image, with the extension of the selected format.
+    fn update_output_name(&mut self) {
+        if let (Some(source), Some((_, exts))) = (&self.source, self.formats.get(self.selected_format_idx)) {
+            let mut output = PathBuf::from(source.file_name().unwrap_or_default());
+            output.set_extension(&exts[0]);
+            if output.extension() == source.extension() {
+                // Converting to the same extension would overwrite the source image.
+                output.set_file_name(format!(
+                    "{}_converted.{}",
+                    output.file_stem().unwrap_or_default().to_string_lossy(),
+                    exts[0]
+                ));
+            }
+            self.output_name = output.to_string_lossy().to_string();
+        }
+    }
+}
@@ -0,0 +1,127 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+    egui::floppy_creator.rs
+
+    Implements a dialog to create blank, formatted floppy images in the
+    floppy media directory.
+
+*/
+
+use crate::{layouts::MartyLayout, widgets::big_icon::IconType, *};
+use marty_core::device_types::fdc::FloppyImageType;
+use std::{ffi::OsString, path::PathBuf};
+
+const FLOPPY_FORMATS: [(FloppyImageType, &str); 7] = [
+    (FloppyImageType::Image160K, "160K (5.25\" single-sided, 8 sectors)"),
+    (FloppyImageType::Image180K, "180K (5.25\" single-sided, 9 sectors)"),
+    (FloppyImageType::Image320K, "320K (5.25\" double-sided, 8 sectors)"),
+    (FloppyImageType::Image360K, "360K (5.25\" double-density)"),
+    (FloppyImageType::Image720K, "720K (3.5\" double-density)"),
+    (FloppyImageType::Image12M, "1.2M (5.25\" high-density)"),
+    (FloppyImageType::Image144M, "1.44M (3.5\" high-density)"),
+];
+
+pub struct FloppyCreator {
+    selected_format_idx: usize,
+    requested_name: String,
+    volume_label: String,
+}
+
+impl FloppyCreator {
+    pub fn new() -> Self {
+        Self {
+            selected_format_idx: 3,
+            requested_name: String::new(),
+            volume_label: String::new(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        ui.horizontal(|ui| {
+            IconType::Floppy.draw(ui, None);
+            ui.label(
+                egui::RichText::new(
+                    "Create a blank, DOS formatted floppy image.\n\
+                        Enter a filename and click Create.",
+                )
+                .font(egui::FontId::proportional(15.0)),
+            );
+        });
+
+        ui.separator();
+
+        let resolved_name = Self::ensure_img_extension(&self.requested_name);
+        MartyLayout::new(layouts::Layout::KeyValue, "floppy-creator-grid").show(ui, |ui| {
+            MartyLayout::kv_row(ui, "Format", None, |ui| {
+                egui::ComboBox::from_id_source("floppy-creator-formats")
+                    .selected_text(FLOPPY_FORMATS[self.selected_format_idx].1)
+                    .show_ui(ui, |ui| {
+                        for (i, (_, desc)) in FLOPPY_FORMATS.iter().enumerate() {
+                            ui.selectable_value(&mut self.selected_format_idx, i, *desc);
+                        }
+                    });
+            });
+            MartyLayout::kv_row(ui, "Image Name", Some(300.0), |ui| {
+                ui.text_edit_singleline(&mut self.requested_name);
+            });
+            MartyLayout::kv_row(ui, "Volume Label", Some(300.0), |ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.volume_label).char_limit(11));
+            });
+            MartyLayout::kv_row(ui, "Filename", None, |ui| {
+                ui.label(resolved_name.display().to_string());
+            });
+        });
+
+        let (button_text, enabled) = self.get_button_state();
+
+        ui.vertical_centered(|ui| {
+            if ui.add_enabled(enabled, egui::Button::new(button_text)).clicked() {
+                events.send(GuiEvent::CreateFloppyImage(
+                    OsString::from(&resolved_name),
+                    FLOPPY_FORMATS[self.selected_format_idx].0,
+                    self.volume_label.clone(),
+                ))
+            };
+        });
+    }
+
+    fn get_button_state(&self) -> (String, bool) {
+        if self.requested_name.is_empty() {
+            ("Enter an image name".to_string(), false)
+        }
+        else {
+            ("Create Floppy!".to_string(), true)
+        }
+    }
+
+    fn ensure_img_extension(name: &str) -> PathBuf {
+        let mut path = PathBuf::from(name);
+        if path.extension().is_none() {
+            path.set_extension("img");
+        }
+        path
+    }
+}
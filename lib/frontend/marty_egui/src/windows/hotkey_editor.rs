@@ -0,0 +1,182 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+    egui::hotkey_editor.rs
+
+    Implements an editor for the emulator's hotkey bindings.
+
+    Each hotkey event is listed with its current key combination. Clicking
+    'Set' records the next key combination pressed; the binding is made
+    when all keys are released. Bindings that share a key combination in
+    overlapping scopes are highlighted as conflicts.
+
+*/
+
+use std::collections::HashMap;
+
+use crate::*;
+use frontend_common::types::hotkeys::{find_conflicts, HotkeyConfigEntry, HotkeyEvent};
+use marty_core::keys::MartyKey;
+
+pub struct HotkeyEditorControl {
+    bindings: Vec<HotkeyConfigEntry>,
+    conflicts: HashMap<HotkeyEvent, Vec<HotkeyEvent>>,
+    recording: Option<HotkeyEvent>,
+    held: Vec<MartyKey>,
+    combo: Vec<MartyKey>,
+}
+
+impl HotkeyEditorControl {
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+            conflicts: HashMap::new(),
+            recording: None,
+            held: Vec::new(),
+            combo: Vec::new(),
+        }
+    }
+
+    pub fn set_bindings(&mut self, bindings: Vec<HotkeyConfigEntry>) {
+        self.conflicts.clear();
+        for (a, b) in find_conflicts(&bindings) {
+            self.conflicts.entry(a).or_default().push(b);
+            self.conflicts.entry(b).or_default().push(a);
+        }
+        self.bindings = bindings;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Feed a host key press or release to the editor while recording a key combination.
+    /// The combination is complete once every key that was pressed has been released.
+    pub fn record_key(&mut self, key: MartyKey, pressed: bool, events: &mut GuiEventQueue) {
+        let event = match self.recording {
+            Some(event) => event,
+            None => return,
+        };
+
+        if pressed {
+            if !self.combo.contains(&key) {
+                self.combo.push(key);
+            }
+            if !self.held.contains(&key) {
+                self.held.push(key);
+            }
+        }
+        else {
+            self.held.retain(|k| *k != key);
+            if self.held.is_empty() && !self.combo.is_empty() {
+                events.send(GuiEvent::SetHotkey(event, std::mem::take(&mut self.combo)));
+                self.recording = None;
+            }
+        }
+    }
+
+    fn format_keys(keys: &[MartyKey]) -> String {
+        if keys.is_empty() {
+            return "(none)".to_string();
+        }
+        keys.iter().map(|k| format!("{:?}", k)).collect::<Vec<_>>().join(" + ")
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        egui::Grid::new("hotkey_editor")
+            .striped(true)
+            .min_col_width(60.0)
+            .show(ui, |ui| {
+                ui.label(egui::RichText::new("Event").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Keys").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Scope").text_style(egui::TextStyle::Monospace));
+                ui.end_row();
+
+                for binding in self.bindings.iter() {
+                    ui.label(format!("{:?}", binding.event));
+
+                    if self.recording == Some(binding.event) {
+                        let text = match self.combo.is_empty() {
+                            true => "Press keys...".to_string(),
+                            false => Self::format_keys(&self.combo),
+                        };
+                        ui.label(egui::RichText::new(text).color(egui::Color32::YELLOW));
+                    }
+                    else {
+                        let mut text = egui::RichText::new(Self::format_keys(&binding.keys));
+                        match self.conflicts.get(&binding.event) {
+                            Some(others) => {
+                                text = text.color(egui::Color32::RED);
+                                ui.label(text).on_hover_text(format!("Conflicts with: {:?}", others));
+                            }
+                            None => {
+                                ui.label(text);
+                            }
+                        }
+                    }
+
+                    ui.label(format!("{:?}", binding.scope));
+
+                    ui.horizontal(|ui| {
+                        if self.recording == Some(binding.event) {
+                            if ui.button("Cancel").clicked() {
+                                self.recording = None;
+                                self.held.clear();
+                                self.combo.clear();
+                            }
+                        }
+                        else {
+                            if ui.button("Set").clicked() {
+                                self.recording = Some(binding.event);
+                                self.held.clear();
+                                self.combo.clear();
+                            }
+                            if ui
+                                .add_enabled(!binding.keys.is_empty(), egui::Button::new("Clear"))
+                                .clicked()
+                            {
+                                events.send(GuiEvent::SetHotkey(binding.event, Vec::new()));
+                            }
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui
+                .button("Reset to Defaults")
+                .on_hover_text("Discard changes made here and use the bindings from the configuration file")
+                .clicked()
+            {
+                events.send(GuiEvent::ResetHotkeys);
+            }
+            if !self.conflicts.is_empty() {
+                ui.label(egui::RichText::new("⚠ Conflicting bindings").color(egui::Color32::RED));
+            }
+        });
+    }
+}
@@ -0,0 +1,223 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+    egui::media_library.rs
+
+    Implements a browser for the floppy and hard disk images in the media
+    directories.
+
+    Each image is listed with its format, size, and what could be read from
+    its boot sector: the filesystem, volume label and OEM name. The comment of
+    a TeleDisk or ImageDisk image is shown when hovering over its name. Images
+    can be searched, marked as favorites, and mounted in any drive.
+
+*/
+
+use crate::*;
+use frontend_common::media_library::{MediaKind, MediaLibraryEntry};
+use std::path::Path;
+
+const COLUMN_HEADINGS: [&str; 9] = [
+    "",
+    "Name",
+    "Format",
+    "Size",
+    "Filesystem",
+    "Label",
+    "Boot Sector",
+    "",
+    "",
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum KindFilter {
+    All,
+    Floppy,
+    HardDisk,
+}
+
+pub struct MediaLibraryControl {
+    entries: Vec<MediaLibraryEntry>,
+    search: String,
+    kind_filter: KindFilter,
+    favorites_only: bool,
+}
+
+impl MediaLibraryControl {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            search: String::new(),
+            kind_filter: KindFilter::All,
+            favorites_only: false,
+        }
+    }
+
+    pub fn set_entries(&mut self, entries: Vec<MediaLibraryEntry>) {
+        self.entries = entries;
+    }
+
+    pub fn set_favorite(&mut self, path: &Path, favorite: bool) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.path == path) {
+            entry.favorite = favorite;
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, floppy_drive_ct: usize, hdd_ct: usize, events: &mut GuiEventQueue) {
+        ui.horizontal(|ui| {
+            if ui.button("⟲ Refresh").clicked() {
+                events.send(GuiEvent::RefreshMediaLibrary);
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.search).hint_text("🔍 Search"));
+            egui::ComboBox::from_id_source("media-library-kind")
+                .selected_text(match self.kind_filter {
+                    KindFilter::All => "All Media",
+                    KindFilter::Floppy => "Floppy Disks",
+                    KindFilter::HardDisk => "Hard Disks",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.kind_filter, KindFilter::All, "All Media");
+                    ui.selectable_value(&mut self.kind_filter, KindFilter::Floppy, "Floppy Disks");
+                    ui.selectable_value(&mut self.kind_filter, KindFilter::HardDisk, "Hard Disks");
+                });
+            ui.checkbox(&mut self.favorites_only, "★ Favorites");
+        });
+        ui.separator();
+
+        let Self {
+            entries,
+            search,
+            kind_filter,
+            favorites_only,
+        } = self;
+        let shown = entries
+            .iter_mut()
+            .filter(|entry| match kind_filter {
+                KindFilter::All => true,
+                KindFilter::Floppy => entry.kind == MediaKind::Floppy,
+                KindFilter::HardDisk => entry.kind == MediaKind::HardDisk,
+            })
+            .filter(|entry| !*favorites_only || entry.favorite)
+            .filter(|entry| entry.matches(search))
+            .collect::<Vec<_>>();
+        ui.label(format!("{} of {} images", shown.len(), entries.len()));
+
+        egui::ScrollArea::vertical().max_height(500.0).show(ui, |ui| {
+            egui::Grid::new("media-library-grid")
+                .striped(true)
+                .num_columns(COLUMN_HEADINGS.len())
+                .show(ui, |ui| {
+                    for heading in COLUMN_HEADINGS {
+                        ui.label(egui::RichText::new(heading).strong());
+                    }
+                    ui.end_row();
+
+                    for entry in shown {
+                        Self::draw_entry(ui, entry, floppy_drive_ct, hdd_ct, events);
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    fn draw_entry(
+        ui: &mut egui::Ui,
+        entry: &mut MediaLibraryEntry,
+        floppy_drive_ct: usize,
+        hdd_ct: usize,
+        events: &mut GuiEventQueue,
+    ) {
+        let star = match entry.favorite {
+            true => "★",
+            false => "☆",
+        };
+        if ui
+            .selectable_label(entry.favorite, star)
+            .on_hover_text("Favorite")
+            .clicked()
+        {
+            entry.favorite = !entry.favorite;
+            events.send(GuiEvent::SetMediaFavorite(entry.path.clone(), entry.favorite));
+        }
+
+        let hover_text = match &entry.comment {
+            Some(comment) => format!("{}\n\n{}", entry.path.display(), comment),
+            None => entry.path.display().to_string(),
+        };
+        ui.label(entry.name.as_str()).on_hover_text(hover_text);
+        ui.label(entry.format.as_str());
+        ui.label(format!("{}K", entry.size / 1024));
+
+        match &entry.boot_sector {
+            Some(boot_sector) => {
+                ui.label(boot_sector.filesystem.as_str());
+                ui.label(boot_sector.volume_label.as_deref().unwrap_or_default());
+                let boot = match boot_sector.bootable {
+                    true => format!("{} ⚡", boot_sector.oem_name),
+                    false => boot_sector.oem_name.clone(),
+                };
+                ui.label(boot).on_hover_text(format!(
+                    "OEM name: {}\nMedia descriptor: {:02X}h\nTotal sectors: {}\nBoot signature: {}",
+                    boot_sector.oem_name,
+                    boot_sector.media_descriptor,
+                    boot_sector.total_sectors,
+                    if boot_sector.bootable { "Present" } else { "Missing" }
+                ));
+            }
+            None => {
+                for _ in 0..3 {
+                    ui.label("-");
+                }
+            }
+        }
+
+        match entry.read_only {
+            true => ui.label("🔒").on_hover_text("The image file is read-only."),
+            false => ui.label(""),
+        };
+
+        let drive_ct = match entry.kind {
+            MediaKind::Floppy => floppy_drive_ct,
+            MediaKind::HardDisk => hdd_ct,
+        };
+        ui.add_enabled_ui(drive_ct > 0, |ui| {
+            ui.menu_button("Mount", |ui| {
+                for drive in 0..drive_ct {
+                    let (name, event) = match entry.kind {
+                        MediaKind::Floppy => (
+                            format!("💾 Floppy Drive {}", drive),
+                            GuiEvent::LoadQuickFloppy(drive, entry.idx),
+                        ),
+                        MediaKind::HardDisk => (format!("🖴 Hard Disk {}", drive), GuiEvent::LoadVHD(drive, entry.idx)),
+                    };
+                    if ui.button(name).clicked() {
+                        events.send(event);
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+    }
+}
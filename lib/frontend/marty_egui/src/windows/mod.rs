@@ -34,25 +34,39 @@ pub mod cpu_control;
 pub mod disassembly_viewer;
 // Bring in submodules
 pub mod about;
+pub mod audio_mixer;
 pub mod call_stack_viewer;
+pub mod character_set_viewer;
 pub mod cpu_state_viewer;
+pub mod crtc_timing_viewer;
 pub mod cycle_trace_viewer;
 pub mod data_visualizer;
+pub mod debug_console;
 pub mod delay_adjust;
 pub mod device_control;
+pub mod dip_switch_editor;
 pub mod dma_viewer;
 pub mod fdc_viewer;
+pub mod floppy_converter;
+pub mod floppy_creator;
 pub mod floppy_viewer;
+pub mod hotkey_editor;
 pub mod instruction_history_viewer;
 pub mod io_stats_viewer;
 pub mod ivt_viewer;
+pub mod media_library;
 pub mod memory_viewer;
+pub mod palette_viewer;
 pub mod performance_viewer;
 pub mod pic_viewer;
 pub mod pit_viewer;
 pub mod ppi_viewer;
+pub mod post_card_viewer;
+pub mod savestate_browser;
 pub mod scaler_adjust;
 pub mod serial_viewer;
 pub mod text_mode_viewer;
 pub mod vhd_creator;
+pub mod video_register_log;
 pub mod videocard_viewer;
+pub mod vram_viewer;
@@ -0,0 +1,148 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+
+    egui::palette_viewer.rs
+
+    Implements a viewer for the video card's palette registers, overscan
+    color, blink state, and DAC entries, displayed as color swatches.
+
+*/
+
+use crate::GuiEventQueue;
+use egui::{Color32, Sense, Vec2};
+use frontend_common::color::cga::CGAColor;
+use marty_core::device_traits::videocard::{PaletteColor, PaletteState};
+
+pub const SWATCH_SIZE: f32 = 20.0;
+pub const DAC_SWATCH_SIZE: f32 = 12.0;
+pub const DAC_COLUMNS: usize = 16;
+
+pub struct PaletteViewerControl {
+    state: Option<PaletteState>,
+}
+
+impl PaletteViewerControl {
+    pub fn new() -> Self {
+        Self { state: None }
+    }
+
+    pub fn update_state(&mut self, state: Option<PaletteState>) {
+        self.state = state;
+    }
+
+    /// Convert a palette color to RGB for display.
+    fn to_color32(color: PaletteColor) -> Color32 {
+        match color {
+            PaletteColor::Irgb(irgb) => {
+                let rgba = CGAColor::decode_attr(irgb & 0x0F).0.to_rgba();
+                Color32::from_rgb(rgba[0], rgba[1], rgba[2])
+            }
+            PaletteColor::Rgb6(rgb6) => {
+                // Each primary has a high (0xAA) bit in rgbRGB bits 0-2 and a low (0x55) bit in bits 3-5.
+                let channel = |bit: u8| ((rgb6 >> bit) & 1) * 0xAA + ((rgb6 >> (bit + 3)) & 1) * 0x55;
+                Color32::from_rgb(channel(2), channel(1), channel(0))
+            }
+            PaletteColor::Rgba(rgba) => Color32::from_rgb(rgba[0], rgba[1], rgba[2]),
+        }
+    }
+
+    fn swatch(ui: &mut egui::Ui, color: Color32, size: f32) -> egui::Response {
+        let (rect, response) = ui.allocate_exact_size(Vec2::splat(size), Sense::hover());
+        ui.painter().rect_filled(rect, 0.0, color);
+        ui.painter()
+            .rect_stroke(rect, 0.0, egui::Stroke::new(1.0, ui.visuals().weak_text_color()));
+        response
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut GuiEventQueue) {
+        let state = match &self.state {
+            Some(state) => state,
+            None => {
+                ui.label("The current video card does not report palette state.");
+                return;
+            }
+        };
+
+        egui::Grid::new("palette_viewer_status").striped(true).show(ui, |ui| {
+            ui.label("Overscan:");
+            match state.overscan {
+                Some(color) => {
+                    PaletteViewerControl::swatch(ui, PaletteViewerControl::to_color32(color), SWATCH_SIZE);
+                }
+                None => {
+                    ui.label("n/a");
+                }
+            }
+            ui.end_row();
+
+            ui.label("Attribute bit 7:");
+            ui.label(if state.blink_enabled { "Blink" } else { "Intensity" });
+            ui.end_row();
+
+            ui.label("Blink state:");
+            ui.label(if state.blink_state { "On" } else { "Off" });
+            ui.end_row();
+        });
+
+        if !state.palette.is_empty() {
+            ui.separator();
+            ui.label("Palette registers:");
+            egui::Grid::new("palette_viewer_registers")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (i, (reg, color)) in state.palette.iter().enumerate() {
+                        ui.label(egui::RichText::new(format!("{:2}", i)).monospace());
+                        ui.label(egui::RichText::new(format!("{:02X}", reg)).monospace());
+                        PaletteViewerControl::swatch(ui, PaletteViewerControl::to_color32(*color), SWATCH_SIZE);
+                        if i % 4 == 3 {
+                            ui.end_row();
+                        }
+                    }
+                });
+        }
+
+        if !state.dac.is_empty() {
+            ui.separator();
+            ui.label("DAC:");
+            ui.spacing_mut().item_spacing = Vec2::splat(1.0);
+            for (row, entries) in state.dac.chunks(DAC_COLUMNS).enumerate() {
+                ui.horizontal(|ui| {
+                    for (col, rgba) in entries.iter().enumerate() {
+                        PaletteViewerControl::swatch(ui, Color32::from_rgb(rgba[0], rgba[1], rgba[2]), DAC_SWATCH_SIZE)
+                            .on_hover_text(format!(
+                                "{:02X}: #{:02X}{:02X}{:02X}",
+                                row * DAC_COLUMNS + col,
+                                rgba[0],
+                                rgba[1],
+                                rgba[2]
+                            ));
+                    }
+                });
+            }
+        }
+    }
+}
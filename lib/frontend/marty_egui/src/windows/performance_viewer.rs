@@ -125,6 +125,15 @@ impl PerformanceViewerControl {
                                 ui.label("Sample Count: ");
                                 ui.label(egui::RichText::new(format!("{}", ss.sample_ct)));
                                 ui.end_row();
+                                ui.label("Latency: ");
+                                ui.label(egui::RichText::new(format!("{:.1}ms", ss.latency_ms)));
+                                ui.end_row();
+                                ui.label("Underruns: ");
+                                ui.label(egui::RichText::new(format!("{}", ss.underruns)));
+                                ui.end_row();
+                                ui.label("Dropped Blocks: ");
+                                ui.label(egui::RichText::new(format!("{}", ss.dropped)));
+                                ui.end_row();
                             })
                         });
                     ui.end_row();
@@ -0,0 +1,113 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::post_card_viewer.rs
+
+    Implements a viewer control for the POST diagnostic card.
+
+    This viewer displays the last POST code written, as a POST card's
+    seven-segment display would, along with a history of previous codes
+    and the time at which they were written.
+
+*/
+
+use crate::*;
+use marty_core::devices::post_card::PostCardState;
+
+pub struct PostCardViewerControl {
+    state: PostCardState,
+}
+
+impl PostCardViewerControl {
+    pub fn new() -> Self {
+        Self {
+            state: Default::default(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        ui.horizontal(|ui| {
+            let code_str = match self.state.last_code {
+                Some(code) => format!("{:02X}", code),
+                None => String::from("--"),
+            };
+            ui.label(
+                egui::RichText::new(code_str)
+                    .monospace()
+                    .size(48.0)
+                    .color(egui::Color32::from_rgb(0xFF, 0x30, 0x30))
+                    .background_color(egui::Color32::BLACK),
+            );
+            ui.vertical(|ui| {
+                ui.label(egui::RichText::new(format!("Port: {:04X}h", self.state.port)).monospace());
+                ui.label(egui::RichText::new(format!("Codes: {}", self.state.history.len())).monospace());
+                if ui.button("Clear").on_hover_text("Clear POST code history").clicked() {
+                    events.send(GuiEvent::ClearPostCodes);
+                }
+            });
+        });
+
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("post_card_view")
+                    .striped(true)
+                    .min_col_width(80.0)
+                    .show(ui, |ui| {
+                        ui.label(egui::RichText::new("Code").text_style(egui::TextStyle::Monospace));
+                        ui.label(egui::RichText::new("Time (ms)").text_style(egui::TextStyle::Monospace));
+                        ui.label(egui::RichText::new("Delta (ms)").text_style(egui::TextStyle::Monospace));
+                        ui.end_row();
+
+                        let mut last_time = None;
+                        for entry in self.state.history.iter() {
+                            let delta = entry.time_us - last_time.unwrap_or(entry.time_us);
+                            last_time = Some(entry.time_us);
+                            ui.label(
+                                egui::RichText::new(format!("{:02X}", entry.code))
+                                    .text_style(egui::TextStyle::Monospace),
+                            );
+                            ui.label(
+                                egui::RichText::new(format!("{:.3}", entry.time_us / 1000.0))
+                                    .text_style(egui::TextStyle::Monospace),
+                            );
+                            ui.label(
+                                egui::RichText::new(format!("{:.3}", delta / 1000.0))
+                                    .text_style(egui::TextStyle::Monospace),
+                            );
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    pub fn update_state(&mut self, state: PostCardState) {
+        self.state = state;
+    }
+}
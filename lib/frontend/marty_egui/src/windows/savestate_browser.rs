@@ -0,0 +1,182 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+    egui::savestate_browser.rs
+
+    Implements a browser for the save state slots of the current machine.
+
+    Each slot is shown with the screenshot taken when it was saved, the time
+    it was saved, the machine configuration and the media that was mounted.
+    Slots saved from a different configuration or with different media are
+    flagged, and must be confirmed before loading.
+
+*/
+
+use crate::*;
+use frontend_common::savestate_manager::SaveStateSlotInfo;
+
+const THUMBNAIL_SIZE: egui::Vec2 = egui::vec2(160.0, 120.0);
+
+#[derive(Copy, Clone, PartialEq)]
+enum SlotAction {
+    Save,
+    Load,
+    Delete,
+}
+
+pub struct SaveStateBrowserControl {
+    slots:   Vec<SaveStateSlotInfo>,
+    confirm: Option<(usize, SlotAction)>,
+}
+
+impl SaveStateBrowserControl {
+    pub fn new() -> Self {
+        Self {
+            slots:   Vec::new(),
+            confirm: None,
+        }
+    }
+
+    pub fn set_slots(&mut self, slots: Vec<SaveStateSlotInfo>) {
+        self.slots = slots;
+        self.confirm = None;
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        if ui.button("⟲ Refresh").clicked() {
+            events.send(GuiEvent::RefreshSaveStates);
+        }
+        ui.separator();
+
+        let Self { slots, confirm } = self;
+        egui::ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            for info in slots.iter() {
+                ui.horizontal(|ui| {
+                    Self::draw_thumbnail(ui, info);
+                    ui.vertical(|ui| {
+                        Self::draw_details(ui, info);
+                        Self::draw_actions(ui, info, confirm, events);
+                    });
+                });
+                ui.separator();
+            }
+        });
+    }
+
+    fn draw_thumbnail(ui: &mut egui::Ui, info: &SaveStateSlotInfo) {
+        match (&info.thumbnail, &info.metadata) {
+            (Some(png), Some(metadata)) => {
+                // Include the save time in the URI so that a new screenshot isn't hidden by the
+                // image cache.
+                let uri = format!(
+                    "bytes://savestate_slot{}_{}.png",
+                    info.slot,
+                    metadata.timestamp.replace([' ', ':', '-'], "")
+                );
+                ui.add(egui::Image::from_bytes(uri, png.clone()).fit_to_exact_size(THUMBNAIL_SIZE));
+            }
+            _ => {
+                let (rect, _) = ui.allocate_exact_size(THUMBNAIL_SIZE, egui::Sense::hover());
+                ui.painter().rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+            }
+        }
+    }
+
+    fn draw_details(ui: &mut egui::Ui, info: &SaveStateSlotInfo) {
+        ui.label(egui::RichText::new(format!("Slot {}", info.slot)).strong());
+        if !info.occupied {
+            ui.label("Empty");
+            return;
+        }
+
+        if let Some(metadata) = &info.metadata {
+            ui.label(format!("Saved: {}", metadata.timestamp));
+            ui.label(format!("Machine: {} ({})", metadata.config_name, metadata.config_hash));
+            if metadata.media.is_empty() {
+                ui.label("Media: None");
+            }
+            for item in metadata.media.iter() {
+                ui.label(format!("Media: {}", item));
+            }
+        }
+        ui.label(format!("Size: {}K", info.size / 1024));
+
+        for warning in info.warnings.iter() {
+            ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}", warning));
+        }
+    }
+
+    fn draw_actions(
+        ui: &mut egui::Ui,
+        info: &SaveStateSlotInfo,
+        confirm: &mut Option<(usize, SlotAction)>,
+        events: &mut GuiEventQueue,
+    ) {
+        let slot = info.slot;
+        let send = |action: SlotAction, events: &mut GuiEventQueue| match action {
+            SlotAction::Save => events.send(GuiEvent::SaveState(slot)),
+            SlotAction::Load => events.send(GuiEvent::LoadState(slot)),
+            SlotAction::Delete => events.send(GuiEvent::DeleteState(slot)),
+        };
+
+        ui.horizontal(|ui| {
+            if let Some((confirm_slot, action)) = *confirm {
+                if confirm_slot == slot {
+                    let label = match action {
+                        SlotAction::Save => "Overwrite",
+                        SlotAction::Load => "Load anyway",
+                        SlotAction::Delete => "Delete",
+                    };
+                    if ui.button(label).clicked() {
+                        send(action, events);
+                        *confirm = None;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        *confirm = None;
+                    }
+                    return;
+                }
+            }
+
+            if ui.button("💾 Save").clicked() {
+                match info.occupied {
+                    true => *confirm = Some((slot, SlotAction::Save)),
+                    false => send(SlotAction::Save, events),
+                }
+            }
+            ui.add_enabled_ui(info.occupied, |ui| {
+                if ui.button("🗁 Load").clicked() {
+                    match info.warnings.is_empty() {
+                        true => send(SlotAction::Load, events),
+                        false => *confirm = Some((slot, SlotAction::Load)),
+                    }
+                }
+                if ui.button("🗑 Delete").clicked() {
+                    *confirm = Some((slot, SlotAction::Delete));
+                }
+            });
+        });
+    }
+}
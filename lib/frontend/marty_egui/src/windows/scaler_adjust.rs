@@ -144,6 +144,31 @@ impl ScalerAdjustControl {
                 }
                 ui.end_row();
 
+                ui.label(egui::RichText::new("Scanline Intensity:").text_style(egui::TextStyle::Monospace));
+                if ui
+                    .add_enabled(
+                        self.params[self.dt_idx].crt_scanlines,
+                        egui::Slider::new(&mut self.params[self.dt_idx].crt_scanline_intensity, 0.0..=1.0),
+                    )
+                    .changed()
+                {
+                    update = true;
+                }
+                ui.end_row();
+
+                ui.label(egui::RichText::new("Phosphor Persistence:").text_style(egui::TextStyle::Monospace));
+                if ui
+                    .add(egui::Slider::new(
+                        &mut self.params[self.dt_idx].crt_phosphor_persistence,
+                        0.0..=0.95,
+                    ))
+                    .on_hover_text("Fraction of each frame's brightness that lingers into the next frame.")
+                    .changed()
+                {
+                    update = true;
+                }
+                ui.end_row();
+
                 ui.label(egui::RichText::new("Barrel Distortion:").text_style(egui::TextStyle::Monospace));
                 if ui
                     .add(egui::Slider::new(
@@ -36,6 +36,12 @@ use std::{ffi::OsString, path::PathBuf};
 pub struct VhdCreator {
     vhd_formats: Vec<HardDiskFormat>,
     selected_format_idx: usize,
+    custom_geometry: bool,
+    custom_cylinders: u16,
+    custom_heads: u8,
+    custom_sectors: u8,
+    format_disk: bool,
+    volume_label: String,
     vhd_requested_name: String,
     vhd_resolved_name: Option<PathBuf>,
 }
@@ -45,6 +51,12 @@ impl VhdCreator {
         Self {
             vhd_formats: Vec::new(),
             selected_format_idx: 0,
+            custom_geometry: false,
+            custom_cylinders: 615,
+            custom_heads: 4,
+            custom_sectors: 17,
+            format_disk: true,
+            volume_label: String::new(),
             vhd_requested_name: String::new(),
             vhd_resolved_name: None,
         }
@@ -56,8 +68,8 @@ impl VhdCreator {
             ui.label(
                 egui::RichText::new(
                     "Create a VHD (Virtual Hard Drive)\n\
-                        Currently only one disk geometry is supported.\n\
-                        Enter a filename and click Create.",
+                        Choose a geometry, or specify one for a controller\n\
+                        that accepts any. Enter a filename and click Create.",
                 )
                 .font(egui::FontId::proportional(15.0)),
             );
@@ -68,14 +80,52 @@ impl VhdCreator {
         if !self.vhd_formats.is_empty() {
             MartyLayout::new(layouts::Layout::KeyValue, "vhd-grid").show(ui, |ui| {
                 MartyLayout::kv_row(ui, "Disk Geometry", None, |ui| {
+                    let selected_text = if self.custom_geometry {
+                        "Custom".to_string()
+                    }
+                    else {
+                        self.vhd_formats[self.selected_format_idx].desc.clone()
+                    };
                     egui::ComboBox::from_id_source("vhd-formats")
-                        .selected_text(format!("{}", self.vhd_formats[self.selected_format_idx].desc))
+                        .selected_text(selected_text)
                         .show_ui(ui, |ui| {
-                            for (i, fmt) in self.vhd_formats.iter_mut().enumerate() {
-                                ui.selectable_value(&mut self.selected_format_idx, i, fmt.desc.to_string());
+                            for (i, fmt) in self.vhd_formats.iter().enumerate() {
+                                if ui
+                                    .selectable_label(!self.custom_geometry && self.selected_format_idx == i, &fmt.desc)
+                                    .clicked()
+                                {
+                                    self.selected_format_idx = i;
+                                    self.custom_geometry = false;
+                                }
                             }
+                            ui.selectable_value(&mut self.custom_geometry, true, "Custom");
                         });
                 });
+                if self.custom_geometry {
+                    MartyLayout::kv_row(ui, "Cylinders", None, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.custom_cylinders).clamp_range(1..=1024));
+                    });
+                    MartyLayout::kv_row(ui, "Heads", None, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.custom_heads).clamp_range(1..=16));
+                    });
+                    MartyLayout::kv_row(ui, "Sectors per Track", None, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.custom_sectors).clamp_range(1..=63));
+                    });
+                }
+                MartyLayout::kv_row(ui, "Size", None, |ui| {
+                    ui.label(format!(
+                        "{:.1} MB",
+                        self.selected_format().get_size() as f64 / (1024.0 * 1024.0)
+                    ));
+                });
+                MartyLayout::kv_row(ui, "Format (FAT)", None, |ui| {
+                    ui.checkbox(&mut self.format_disk, "Partition and format");
+                });
+                if self.format_disk {
+                    MartyLayout::kv_row(ui, "Volume Label", Some(300.0), |ui| {
+                        ui.add(egui::TextEdit::singleline(&mut self.volume_label).char_limit(11));
+                    });
+                }
                 MartyLayout::kv_row(ui, "VHD Name", Some(300.0), |ui| {
                     ui.text_edit_singleline(&mut self.vhd_requested_name);
                 });
@@ -85,6 +135,13 @@ impl VhdCreator {
                     self.vhd_resolved_name = Some(resolved_name);
                 });
             });
+
+            if self.custom_geometry && !self.is_supported(&self.selected_format()) {
+                ui.horizontal(|ui| {
+                    IconType::Warning.draw(ui, None);
+                    ui.label("The current hard disk controller does not support this geometry.");
+                });
+            }
         }
         else {
             ui.vertical_centered(|ui| {
@@ -98,7 +155,8 @@ impl VhdCreator {
             if ui.add_enabled(enabled, egui::Button::new(button_text)).clicked() {
                 events.send(GuiEvent::CreateVHD(
                     OsString::from(&self.vhd_resolved_name.clone().unwrap_or_default()),
-                    self.vhd_formats[self.selected_format_idx].clone(),
+                    self.selected_format(),
+                    self.format_disk.then(|| self.volume_label.clone()),
                 ))
             };
         });
@@ -113,6 +171,33 @@ impl VhdCreator {
         }
     }
 
+    /// Return the selected format, or a format of the custom geometry.
+    fn selected_format(&self) -> HardDiskFormat {
+        if self.custom_geometry {
+            HardDiskFormat {
+                max_cylinders: self.custom_cylinders,
+                max_heads: self.custom_heads,
+                max_sectors: self.custom_sectors,
+                wpc: None,
+                desc: format!(
+                    "{} cylinders, {} heads, {} sectors",
+                    self.custom_cylinders, self.custom_heads, self.custom_sectors
+                ),
+            }
+        }
+        else {
+            self.vhd_formats[self.selected_format_idx].clone()
+        }
+    }
+
+    fn is_supported(&self, format: &HardDiskFormat) -> bool {
+        self.vhd_formats.iter().any(|fmt| {
+            fmt.max_cylinders == format.max_cylinders
+                && fmt.max_heads == format.max_heads
+                && fmt.max_sectors == format.max_sectors
+        })
+    }
+
     fn ensure_vhd_extension(vhd_name: &str) -> PathBuf {
         let mut path = PathBuf::from(vhd_name);
         if !path.ends_with(".vhd") {
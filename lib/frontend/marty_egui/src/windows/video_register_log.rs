@@ -0,0 +1,101 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::video_register_log.rs
+
+    Implements a viewer for video card register writes made during a frame.
+
+    Each write is listed with the scanline and hdot at which it occurred,
+    which is useful for analyzing effects that reprogram the video card
+    mid-frame.
+
+*/
+
+use crate::*;
+use marty_core::device_traits::videocard::VideoRegisterLog;
+
+pub struct VideoRegisterLogControl {
+    enabled: bool,
+    freeze: bool,
+    log: VideoRegisterLog,
+}
+
+impl VideoRegisterLogControl {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            freeze: false,
+            log: Default::default(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.enabled, "Log register writes").changed() {
+                events.send(GuiEvent::SetRegisterLogging(self.enabled));
+            }
+            ui.checkbox(&mut self.freeze, "Freeze");
+        });
+        ui.label(
+            egui::RichText::new(format!("Frame: {} Writes: {}", self.log.frame, self.log.writes.len())).monospace(),
+        );
+
+        ui.separator();
+
+        egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+            egui::Grid::new("video_register_log")
+                .striped(true)
+                .min_col_width(60.0)
+                .show(ui, |ui| {
+                    ui.label(egui::RichText::new("Scanline").text_style(egui::TextStyle::Monospace));
+                    ui.label(egui::RichText::new("Hdot").text_style(egui::TextStyle::Monospace));
+                    ui.label(egui::RichText::new("Port").text_style(egui::TextStyle::Monospace));
+                    ui.label(egui::RichText::new("Register").text_style(egui::TextStyle::Monospace));
+                    ui.label(egui::RichText::new("Value").text_style(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    for write in self.log.writes.iter() {
+                        let reg_str = match write.index {
+                            Some(index) => format!("R{}", index),
+                            None => String::from("-"),
+                        };
+                        ui.label(egui::RichText::new(format!("{}", write.scanline)).monospace());
+                        ui.label(egui::RichText::new(format!("{}", write.hdot)).monospace());
+                        ui.label(egui::RichText::new(format!("{:04X}h", write.port)).monospace());
+                        ui.label(egui::RichText::new(reg_str).monospace());
+                        ui.label(egui::RichText::new(format!("{:02X}", write.value)).monospace());
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    pub fn update_state(&mut self, log: VideoRegisterLog) {
+        if !self.freeze {
+            self.log = log;
+        }
+    }
+}
@@ -0,0 +1,304 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+
+    egui::vram_viewer.rs
+
+    Implements a viewer that decodes video memory as text or graphics
+    using user-supplied parameters, independent of how the CRTC is
+    currently programmed. This allows viewing off-screen pages and
+    buffers that are not being displayed.
+
+    Rows may be interleaved across several banks, as the CGA does in
+    graphics modes (two banks) and the Hercules does (four banks).
+
+*/
+
+use crate::{
+    glyphs::FontInfo,
+    widgets::pixel_canvas::{PixelCanvas, PixelCanvasBitOrder, PixelCanvasDepth},
+    GuiEventQueue,
+};
+use std::fmt::Display;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+pub const MAX_PITCH: usize = 256;
+pub const MAX_ROWS: usize = 512;
+pub const DEFAULT_BANK_SIZE: usize = 0x2000;
+
+pub const ZOOM_LUT: [f32; 3] = [1.0, 2.0, 4.0];
+pub const ZOOM_STR_LUT: [&str; 3] = ["1x", "2x", "4x"];
+
+#[derive(EnumIter, Copy, Clone, PartialEq, Eq, Default)]
+pub enum VramPreset {
+    Text40x25,
+    #[default]
+    Text80x25,
+    Cga320x200,
+    Cga640x200,
+    Hercules720x348,
+}
+
+impl Display for VramPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VramPreset::Text40x25 => write!(f, "40x25 Text"),
+            VramPreset::Text80x25 => write!(f, "80x25 Text"),
+            VramPreset::Cga320x200 => write!(f, "320x200 CGA 2bpp"),
+            VramPreset::Cga640x200 => write!(f, "640x200 CGA 1bpp"),
+            VramPreset::Hercules720x348 => write!(f, "720x348 Hercules"),
+        }
+    }
+}
+
+impl VramPreset {
+    /// Return the (depth, pitch, rows, banks) parameters for this preset.
+    pub fn params(&self) -> (PixelCanvasDepth, usize, usize, usize) {
+        match self {
+            VramPreset::Text40x25 => (PixelCanvasDepth::Text, 80, 25, 1),
+            VramPreset::Text80x25 => (PixelCanvasDepth::Text, 160, 25, 1),
+            VramPreset::Cga320x200 => (PixelCanvasDepth::TwoBpp, 80, 200, 2),
+            VramPreset::Cga640x200 => (PixelCanvasDepth::OneBpp, 80, 200, 2),
+            VramPreset::Hercules720x348 => (PixelCanvasDepth::OneBpp, 90, 348, 4),
+        }
+    }
+}
+
+pub struct VramViewerControl {
+    bpp: PixelCanvasDepth,
+    start: usize,
+    pitch: usize,
+    rows: usize,
+    banks: usize,
+    bank_size: usize,
+    zoom_idx: usize,
+    crtc_start: u16,
+    vram_len: Option<usize>,
+    freeze: bool,
+    active_preset: VramPreset,
+    canvas: Option<PixelCanvas>,
+    font: FontInfo,
+    row_buf: Vec<u8>,
+}
+
+impl VramViewerControl {
+    pub fn new() -> Self {
+        let active_preset = VramPreset::default();
+        let (bpp, pitch, rows, banks) = active_preset.params();
+        Self {
+            bpp,
+            start: 0,
+            pitch,
+            rows,
+            banks,
+            bank_size: DEFAULT_BANK_SIZE,
+            zoom_idx: 1,
+            crtc_start: 0,
+            vram_len: None,
+            freeze: false,
+            active_preset,
+            canvas: None,
+            font: FontInfo::default(),
+            row_buf: Vec::new(),
+        }
+    }
+
+    pub fn init(&mut self, ctx: egui::Context) {
+        if self.canvas.is_none() {
+            let mut canvas = PixelCanvas::new(self.dimensions(), ctx);
+            canvas.set_bpp(self.bpp);
+            // Video memory holds the leftmost pixel of each byte in its most significant bits.
+            canvas.set_bit_order(PixelCanvasBitOrder::MsbFirst);
+            canvas.set_zoom(ZOOM_LUT[self.zoom_idx]);
+            self.canvas = Some(canvas);
+        }
+    }
+
+    /// Calculate the canvas dimensions in pixels for the current parameters.
+    fn dimensions(&self) -> (u32, u32) {
+        match self.bpp {
+            PixelCanvasDepth::Text => ((self.pitch / 2) as u32 * 8, self.rows as u32 * self.font.max_scanline),
+            _ => ((self.pitch * 8 / self.bpp.bits()) as u32, self.rows as u32),
+        }
+    }
+
+    fn resize(&mut self) {
+        let dims = self.dimensions();
+        if let Some(canvas) = &mut self.canvas {
+            canvas.set_bpp(self.bpp);
+            canvas.resize(dims, Some(&self.font));
+        }
+    }
+
+    /// Decode the provided video memory using the current parameters. Each row is read from
+    /// its bank at the start address plus the row's offset, wrapping at the end of video memory.
+    pub fn update(&mut self, vram: Option<&[u8]>, crtc_start: u16) {
+        self.crtc_start = crtc_start;
+        self.vram_len = vram.map(|vram| vram.len());
+
+        let vram = match vram {
+            Some(vram) if !vram.is_empty() && !self.freeze => vram,
+            _ => return,
+        };
+
+        if let Some(canvas) = &mut self.canvas {
+            let banks = self.banks.max(1);
+            self.row_buf.clear();
+            for row in 0..self.rows {
+                let row_start = self.start + (row % banks) * self.bank_size + (row / banks) * self.pitch;
+                self.row_buf
+                    .extend((0..self.pitch).map(|i| vram[(row_start + i) % vram.len()]));
+            }
+
+            let data_len = canvas.get_required_data_size(Some(&self.font));
+            self.row_buf.resize(data_len, 0);
+            canvas.update_data(&self.row_buf, Some(&self.font));
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut GuiEventQueue) {
+        let mut resize = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Preset:");
+            egui::ComboBox::from_id_source("vram_preset_combo")
+                .selected_text(self.active_preset.to_string())
+                .show_ui(ui, |ui| {
+                    for preset in VramPreset::iter() {
+                        if ui
+                            .selectable_value(&mut self.active_preset, preset, preset.to_string())
+                            .clicked()
+                        {
+                            (self.bpp, self.pitch, self.rows, self.banks) = preset.params();
+                            resize = true;
+                        }
+                    }
+                });
+
+            egui::ComboBox::from_id_source("vram_mode_combo")
+                .selected_text(match self.bpp {
+                    PixelCanvasDepth::Text => "text",
+                    PixelCanvasDepth::TwoBpp => "2bpp",
+                    _ => "1bpp",
+                })
+                .show_ui(ui, |ui| {
+                    for (bpp, bpp_str) in [
+                        (PixelCanvasDepth::Text, "text"),
+                        (PixelCanvasDepth::OneBpp, "1bpp"),
+                        (PixelCanvasDepth::TwoBpp, "2bpp"),
+                    ] {
+                        if ui.selectable_value(&mut self.bpp, bpp, bpp_str).clicked() {
+                            resize = true;
+                        }
+                    }
+                });
+
+            egui::ComboBox::from_id_source("vram_zoom_combo")
+                .selected_text(ZOOM_STR_LUT[self.zoom_idx])
+                .show_ui(ui, |ui| {
+                    for i in 0..ZOOM_LUT.len() {
+                        if ui.selectable_value(&mut self.zoom_idx, i, ZOOM_STR_LUT[i]).clicked() {
+                            if let Some(canvas) = &mut self.canvas {
+                                canvas.set_zoom(ZOOM_LUT[self.zoom_idx]);
+                            }
+                        }
+                    }
+                });
+
+            ui.checkbox(&mut self.freeze, "Freeze");
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.start)
+                    .clamp_range(0..=0xFFFF)
+                    .hexadecimal(4, false, true)
+                    .prefix("start:")
+                    .speed(0.5)
+                    .update_while_editing(false),
+            );
+            if ui
+                .button("CRTC")
+                .on_hover_text("Use the start address currently programmed into the CRTC.")
+                .clicked()
+            {
+                // The CRTC addresses video memory in words.
+                self.start = self.crtc_start as usize * 2;
+            }
+
+            resize |= ui
+                .add(
+                    egui::DragValue::new(&mut self.pitch)
+                        .clamp_range(2..=MAX_PITCH)
+                        .prefix("pitch:")
+                        .suffix(" bytes")
+                        .speed(0.25)
+                        .update_while_editing(false),
+                )
+                .changed();
+            resize |= ui
+                .add(
+                    egui::DragValue::new(&mut self.rows)
+                        .clamp_range(1..=MAX_ROWS)
+                        .prefix("rows:")
+                        .speed(0.25)
+                        .update_while_editing(false),
+                )
+                .changed();
+            ui.add(
+                egui::DragValue::new(&mut self.banks)
+                    .clamp_range(1..=4)
+                    .prefix("banks:")
+                    .speed(0.1),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.bank_size)
+                    .clamp_range(0..=0x8000)
+                    .hexadecimal(4, false, true)
+                    .prefix("bank size:")
+                    .speed(0.5)
+                    .update_while_editing(false),
+            );
+        });
+
+        match self.vram_len {
+            Some(len) => ui.label(format!("Video memory: {} KB", len / 1024)),
+            None => ui.label("The current video card has no directly viewable video memory."),
+        };
+
+        if resize {
+            self.resize();
+        }
+
+        if let Some(canvas) = &mut self.canvas {
+            ui.separator();
+            egui::ScrollArea::both().max_height(600.0).show(ui, |ui| {
+                canvas.draw(ui);
+            });
+        }
+    }
+}
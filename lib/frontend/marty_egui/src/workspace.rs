@@ -34,11 +34,13 @@
 
 #![allow(dead_code)]
 
-use crate::{state::GuiState, GuiWindow, WORKSPACE_WINDOWS};
+use crate::{modal::ModalContext, state::GuiState, GuiWindow, WORKSPACE_WINDOWS};
 use anyhow::Error;
 use egui::{Context, Ui};
+use frontend_common::types::session::GuiWindowSession;
 use marty_core::device_traits::videocard::VideoCardId;
 use std::collections::HashMap;
+use strum::IntoEnumIterator;
 
 pub struct GuiWorkspaceConfig {}
 
@@ -118,6 +120,43 @@ impl GuiState {
         *self.window_flag(window) = state;
     }
 
+    /// Return the open windows with their positions and widths, so that they can be reopened where
+    /// they were in the next session.
+    pub fn workspace_layout(&self) -> Vec<GuiWindowSession> {
+        self.window_state
+            .iter()
+            .filter(|(_, state)| state.open)
+            .map(|(window, state)| GuiWindowSession {
+                window: format!("{:?}", window),
+                pos:    [state.pos.x, state.pos.y],
+                width:  state.size.x,
+            })
+            .collect()
+    }
+
+    /// Open the windows of a saved layout at their saved positions. Resizable windows also get
+    /// their saved widths. Windows that no longer exist are skipped.
+    pub fn restore_workspace_layout(&mut self, layout: &[GuiWindowSession]) {
+        for saved in layout {
+            let window = GuiWindow::iter().find(|window| format!("{:?}", window) == saved.window);
+            let resizable = window
+                .as_ref()
+                .and_then(|window| WORKSPACE_WINDOWS.get(window))
+                .is_some_and(|def| def.resizable);
+
+            match window.and_then(|window| self.window_state.get_mut(&window)) {
+                Some(state) => {
+                    state.open = true;
+                    state.initial_pos = Some(egui::pos2(saved.pos[0], saved.pos[1]));
+                    if resizable && saved.width > 0.0 {
+                        state.initial_size = Some(egui::vec2(saved.width, 0.0));
+                    }
+                }
+                None => log::warn!("Skipping unknown window in saved layout: {}", saved.window),
+            }
+        }
+    }
+
     pub fn draw_workspace(&mut self, ctx: &Context) {
         for (win_enum, win_state) in self.window_state.iter_mut() {
             // Get definition of this window from the constant definitions
@@ -131,6 +170,10 @@ impl GuiState {
 
             win = win.default_width(win_def.width);
 
+            if let Some(pos) = win_state.initial_pos {
+                win = win.default_pos(pos);
+            }
+
             if let Some(egui::Vec2 { x, .. }) = win_state.initial_size {
                 win = win.default_width(x);
             }
@@ -145,6 +188,19 @@ impl GuiState {
                 GuiWindow::PerfViewer => {
                     self.perf_viewer.draw(ui, &mut self.event_queue);
                 }
+                GuiWindow::AudioMixer => {
+                    self.audio_mixer.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::HotkeyEditor => {
+                    self.hotkey_editor.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::SaveStateBrowser => {
+                    self.savestate_browser.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::MediaLibrary => {
+                    self.media_library
+                        .draw(ui, self.floppy_drives.len(), self.hdds.len(), &mut self.event_queue);
+                }
                 GuiWindow::MemoryViewer => {
                     self.memory_viewer.draw(ui, &mut self.event_queue);
                 }
@@ -187,21 +243,54 @@ impl GuiState {
                 GuiWindow::PpiViewer => {
                     self.ppi_viewer.draw(ui, &mut self.event_queue);
                 }
+                GuiWindow::DipSwitchEditor => {
+                    self.dip_switch_editor.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::PostCardViewer => {
+                    self.post_card_viewer.draw(ui, &mut self.event_queue);
+                }
                 GuiWindow::DmaViewer => {
                     self.dma_viewer.draw(ui, &mut self.event_queue);
                 }
                 GuiWindow::VideoCardViewer => {
                     GuiState::draw_video_card_panel(ui, &self.videocard_state);
                 }
+                GuiWindow::VideoRegisterLog => {
+                    self.video_register_log.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::VramViewer => {
+                    self.vram_viewer.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::CharacterSetViewer => {
+                    self.character_set_viewer.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::PaletteViewer => {
+                    self.palette_viewer.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::CrtcTimingViewer => {
+                    self.crtc_timing_viewer.draw(ui, &mut self.event_queue);
+                }
                 GuiWindow::DataVisualizer => {
                     self.data_visualizer.draw(ui, &mut self.event_queue);
                 }
                 GuiWindow::CallStack => {
                     self.call_stack_viewer.draw(ui, &mut self.event_queue);
                 }
+                GuiWindow::DebugConsole => {
+                    self.debug_console.draw(ui, &mut self.event_queue);
+                }
                 GuiWindow::VHDCreator => {
                     self.vhd_creator.draw(ui, &mut self.event_queue);
                 }
+                GuiWindow::FloppyCreator => {
+                    self.floppy_creator.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::FloppyConverter => {
+                    if self.floppy_converter.draw(ui, &mut self.event_queue) && !self.modal.is_open() {
+                        self.modal
+                            .open(ModalContext::OpenConversionSource, self.default_floppy_path.clone());
+                    }
+                }
                 GuiWindow::CycleTraceViewer => {
                     self.cycle_trace_viewer.draw(ui, &mut self.event_queue);
                 }
@@ -220,6 +309,7 @@ impl GuiState {
                 Some(inner_response) => {
                     let win_pos = inner_response.response.rect.min;
                     win_state.pos = win_pos;
+                    win_state.size = inner_response.response.rect.size();
                 }
                 None => {
                     //log::warn!("Window {:?} returned None from show()", win_enum);
@@ -40,12 +40,19 @@ use bytemuck::{Pod, Zeroable};
 // Reexport trait items
 pub use frontend_common::{
     color::MartyColor,
-    display_scaler::{DisplayScaler, ScalerEffect, ScalerFilter, ScalerMode, ScalerOption},
+    display_scaler::{DisplayScaler, ScalerEffect, ScalerFilter, ScalerMode, ScalerOption, DEFAULT_SCANLINE_INTENSITY},
 };
 
 use ultraviolet::Mat4;
 use wgpu::{util::DeviceExt, TextureDescriptor};
 
+/// The maximum relative aspect ratio error accepted by Integer scaling before preferring more
+/// accurate factors over a larger image.
+const INTEGER_ASPECT_TOLERANCE: f32 = 0.05;
+
+/// The maximum phosphor persistence decay factor. A factor of 1.0 would never fade.
+const MAX_PERSISTENCE: f32 = 0.95;
+
 /// A logical texture size for a window surface.
 #[derive(Debug)]
 pub struct SurfaceSize {
@@ -64,6 +71,10 @@ struct CrtParamUniform {
     brightness: f32,
     contrast: f32,
     mono: u32,
+    scanline_intensity: f32,
+    pad0: u32,
+    pad1: u32,
+    pad2: u32,
     mono_color: [f32; 4],
 }
 
@@ -94,6 +105,10 @@ impl Default for CrtParamUniform {
             brightness: 1.0,
             contrast: 1.0,
             mono: 0,
+            scanline_intensity: DEFAULT_SCANLINE_INTENSITY,
+            pad0: 0,
+            pad1: 0,
+            pad2: 0,
             mono_color: [1.0, 1.0, 1.0, 1.0],
         }
     }
@@ -114,7 +129,6 @@ struct ScalerOptionsUniform {
     fill_color: [f32; 4],
 }
 
-#[allow(dead_code)]
 fn create_texture_view(pixels: &pixels::Pixels, width: u32, height: u32) -> wgpu::TextureView {
     let device = pixels.device();
     let texture_descriptor = TextureDescriptor {
@@ -169,6 +183,472 @@ fn create_bind_group(
     })
 }
 
+/// Uniform provided to post-processing shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct PostShaderUniform {
+    screen_size:  [f32; 2],
+    texture_size: [f32; 2],
+}
+
+/// Validate a user-provided post-processing shader, including the prelude, before handing it to
+/// wgpu. wgpu treats shader compilation errors as fatal, so a typo in a user's shader file would
+/// otherwise bring down the emulator.
+fn validate_post_shader(source: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| e.emit_to_string(source))?;
+
+    if !module
+        .entry_points
+        .iter()
+        .any(|ep| ep.name == "fs_main" && ep.stage == naga::ShaderStage::Fragment)
+    {
+        return Err("shader does not define a fragment entry point named fs_main".to_string());
+    }
+    Ok(())
+}
+
+/// An optional second render pass that runs a user-provided WGSL fragment shader over the scaled
+/// output. When active, the scaler renders into an intermediate texture the size of the surface,
+/// which is then sampled by the post-processing shader.
+struct PostShaderStage {
+    bind_group_layout: wgpu::BindGroupLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    texture_view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PostShaderStage {
+    fn new(
+        pixels: &pixels::Pixels,
+        source: &str,
+        sampler: &wgpu::Sampler,
+        screen_size: (u32, u32),
+        texture_size: (u32, u32),
+    ) -> Result<Self, String> {
+        let device = pixels.device();
+
+        // Prepend the prelude, which declares our bindings and the vertex stage.
+        let full_source = format!("{}\n{}", include_str!("./shaders/post.wgsl"), source);
+        validate_post_shader(&full_source)?;
+
+        // A shader that validates can still fail to link against our pipeline layout or vertex
+        // stage. Capture validation errors rather than letting wgpu treat them as fatal.
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label:  Some("marty_scaler_post_shader"),
+            source: wgpu::ShaderSource::Wgsl(full_source.into()),
+        });
+
+        let uniform = PostShaderStage::make_uniform(screen_size, texture_size);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label:    Some("marty_scaler_post_uniform_buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage:    wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label:   Some("marty_scaler_post_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type:    wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled:   false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PostShaderUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("marty_scaler_post_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut primitive = wgpu::PrimitiveState::default();
+        primitive.cull_mode = None;
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("marty_scaler_post_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                compilation_options: Default::default(),
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive,
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                compilation_options: Default::default(),
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: pixels.render_texture_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            return Err(e.to_string());
+        }
+
+        let texture_view = create_texture_view(pixels, screen_size.0.max(1), screen_size.1.max(1));
+        let bind_group =
+            PostShaderStage::create_bind_group(device, &bind_group_layout, &texture_view, sampler, &uniform_buffer);
+
+        Ok(Self {
+            bind_group_layout,
+            render_pipeline,
+            uniform_buffer,
+            texture_view,
+            bind_group,
+        })
+    }
+
+    fn make_uniform(screen_size: (u32, u32), texture_size: (u32, u32)) -> PostShaderUniform {
+        PostShaderUniform {
+            screen_size:  [screen_size.0 as f32, screen_size.1 as f32],
+            texture_size: [texture_size.0 as f32, texture_size.1 as f32],
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        texture_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label:   Some("marty_scaler_post_bind_group"),
+            layout:  bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding:  0,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding:  1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding:  2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Recreate the intermediate texture to match a new surface size and update the uniform.
+    fn resize(
+        &mut self,
+        pixels: &pixels::Pixels,
+        sampler: &wgpu::Sampler,
+        screen_size: (u32, u32),
+        texture_size: (u32, u32),
+    ) {
+        self.texture_view = create_texture_view(pixels, screen_size.0.max(1), screen_size.1.max(1));
+        self.bind_group = PostShaderStage::create_bind_group(
+            pixels.device(),
+            &self.bind_group_layout,
+            &self.texture_view,
+            sampler,
+            &self.uniform_buffer,
+        );
+        let uniform = PostShaderStage::make_uniform(screen_size, texture_size);
+        pixels
+            .queue()
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, render_target: &wgpu::TextureView) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("marty_scaler post-processing pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: render_target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load:  wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+/// Uniform provided to the persistence shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct PersistenceUniform {
+    decay: f32,
+    pad0:  f32,
+    pad1:  f32,
+    pad2:  f32,
+}
+
+/// An optional render pass that emulates phosphor persistence. Each frame the pixel buffer is
+/// blended with a decayed copy of the previous result into an accumulation texture the size of
+/// the pixel buffer. The scaler then samples the accumulation texture instead of the pixel buffer.
+struct PersistenceStage {
+    bind_group_layout: wgpu::BindGroupLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    accum_texture: wgpu::Texture,
+    accum_view: wgpu::TextureView,
+    history_texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PersistenceStage {
+    fn new(pixels: &pixels::Pixels, sampler: &wgpu::Sampler, decay: f32) -> Self {
+        let device = pixels.device();
+        let module = device.create_shader_module(wgpu::include_wgsl!("./shaders/persistence.wgsl"));
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label:    Some("marty_scaler_persistence_uniform_buffer"),
+            contents: bytemuck::bytes_of(&PersistenceStage::make_uniform(decay)),
+            usage:    wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type:    wgpu::TextureSampleType::Float { filterable: true },
+                multisampled:   false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label:   Some("marty_scaler_persistence_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PersistenceUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("marty_scaler_persistence_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut primitive = wgpu::PrimitiveState::default();
+        primitive.cull_mode = None;
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("marty_scaler_persistence_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                compilation_options: Default::default(),
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive,
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                compilation_options: Default::default(),
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: pixels.texture().format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        let (accum_texture, history_texture) = PersistenceStage::create_textures(pixels);
+        let accum_view = accum_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group =
+            PersistenceStage::create_bind_group(pixels, &bind_group_layout, &history_texture, sampler, &uniform_buffer);
+
+        Self {
+            bind_group_layout,
+            render_pipeline,
+            uniform_buffer,
+            accum_texture,
+            accum_view,
+            history_texture,
+            bind_group,
+        }
+    }
+
+    fn make_uniform(decay: f32) -> PersistenceUniform {
+        PersistenceUniform {
+            decay,
+            pad0: 0.0,
+            pad1: 0.0,
+            pad2: 0.0,
+        }
+    }
+
+    /// Create the accumulation and history textures, matching the pixel buffer texture.
+    fn create_textures(pixels: &pixels::Pixels) -> (wgpu::Texture, wgpu::Texture) {
+        let device = pixels.device();
+        let source = pixels.texture();
+        let mut descriptor = TextureDescriptor {
+            label: Some("marty_scaler_persistence_accum_texture"),
+            size: source.size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: source.format(),
+            view_formats: &[],
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+        };
+        let accum_texture = device.create_texture(&descriptor);
+
+        descriptor.label = Some("marty_scaler_persistence_history_texture");
+        descriptor.usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        let history_texture = device.create_texture(&descriptor);
+
+        (accum_texture, history_texture)
+    }
+
+    fn create_bind_group(
+        pixels: &pixels::Pixels,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        history_texture: &wgpu::Texture,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let current_view = pixels.texture().create_view(&wgpu::TextureViewDescriptor::default());
+        let history_view = history_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        pixels.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label:   Some("marty_scaler_persistence_bind_group"),
+            layout:  bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding:  0,
+                    resource: wgpu::BindingResource::TextureView(&current_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding:  1,
+                    resource: wgpu::BindingResource::TextureView(&history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding:  2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding:  3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn set_decay(&mut self, pixels: &pixels::Pixels, decay: f32) {
+        pixels.queue().write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&PersistenceStage::make_uniform(decay)),
+        );
+    }
+
+    /// Recreate the accumulation and history textures after the pixel buffer was resized. Any
+    /// accumulated persistence is discarded.
+    fn resize(&mut self, pixels: &pixels::Pixels, sampler: &wgpu::Sampler) {
+        let (accum_texture, history_texture) = PersistenceStage::create_textures(pixels);
+        self.accum_view = accum_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.accum_texture = accum_texture;
+        self.bind_group = PersistenceStage::create_bind_group(
+            pixels,
+            &self.bind_group_layout,
+            &history_texture,
+            sampler,
+            &self.uniform_buffer,
+        );
+        self.history_texture = history_texture;
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("marty_scaler persistence pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.accum_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load:  wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+        drop(rpass);
+
+        // Keep this frame's result as the history for the next frame.
+        encoder.copy_texture_to_texture(
+            self.accum_texture.as_image_copy(),
+            self.history_texture.as_image_copy(),
+            self.accum_texture.size(),
+        );
+    }
+}
+
 /// The default renderer that scales your frame to the screen size.
 pub struct MartyScaler {
     mode: ScalerMode,
@@ -202,6 +682,7 @@ pub struct MartyScaler {
 
     scanlines: u32,
     do_scanlines: bool,
+    scanline_intensity: f32,
     h_curvature: f32,
     v_curvature: f32,
     corner_radius: f32,
@@ -211,6 +692,8 @@ pub struct MartyScaler {
     effect: ScalerEffect,
     #[allow(dead_code)]
     crt_params: CrtParamUniform,
+    post_stage: Option<PostShaderStage>,
+    persistence_stage: Option<PersistenceStage>,
 }
 
 impl MartyScaler {
@@ -472,6 +955,7 @@ impl MartyScaler {
 
             scanlines: 0,
             do_scanlines: false,
+            scanline_intensity: DEFAULT_SCANLINE_INTENSITY,
             h_curvature: 0.0,
             v_curvature: 0.0,
             corner_radius: 0.0,
@@ -483,6 +967,83 @@ impl MartyScaler {
                 a: 1.0,
             },
             crt_params: Default::default(),
+            post_stage: None,
+            persistence_stage: None,
+        }
+    }
+
+    /// Recreate the bind groups for the scaling pass. The scaler samples the accumulation
+    /// texture of the persistence stage if it is active, otherwise the pixel buffer texture.
+    fn rebuild_bind_groups(&mut self, pixels: &pixels::Pixels) {
+        let source_view = match &self.persistence_stage {
+            Some(stage) => &stage.accum_view,
+            None => &self.texture_view,
+        };
+        self.nearest_bind_group = create_bind_group(
+            pixels.device(),
+            &self.bind_group_layout,
+            source_view,
+            &self.nearest_sampler,
+            &self.transform_uniform_buffer,
+            &self.params_uniform_buffer,
+        );
+        self.bilinear_bind_group = create_bind_group(
+            pixels.device(),
+            &self.bind_group_layout,
+            source_view,
+            &self.bilinear_sampler,
+            &self.transform_uniform_buffer,
+            &self.params_uniform_buffer,
+        );
+    }
+
+    /// Set the phosphor persistence decay factor. A factor of 0 removes the persistence stage.
+    fn set_persistence(&mut self, pixels: &pixels::Pixels, decay: f32) {
+        let decay = decay.clamp(0.0, MAX_PERSISTENCE);
+        if decay > 0.0 {
+            if let Some(stage) = &mut self.persistence_stage {
+                stage.set_decay(pixels, decay);
+            }
+            else {
+                self.persistence_stage = Some(PersistenceStage::new(pixels, &self.nearest_sampler, decay));
+                self.rebuild_bind_groups(pixels);
+            }
+        }
+        else if self.persistence_stage.take().is_some() {
+            self.rebuild_bind_groups(pixels);
+        }
+    }
+
+    /// Install or remove the post-processing shader stage. An invalid shader is logged and
+    /// leaves the current post-processing stage, if any, in place.
+    fn set_post_shader(&mut self, pixels: &pixels::Pixels, source: Option<String>) {
+        let source = match source {
+            Some(source) => source,
+            None => {
+                self.post_stage = None;
+                return;
+            }
+        };
+        match PostShaderStage::new(
+            pixels,
+            &source,
+            &self.bilinear_sampler,
+            (self.screen_width, self.screen_height),
+            (self.texture_width, self.texture_height),
+        ) {
+            Ok(stage) => self.post_stage = Some(stage),
+            Err(e) => log::error!("Failed to load post-processing shader: {}", e),
+        }
+    }
+
+    fn resize_post_stage(&mut self, pixels: &pixels::Pixels) {
+        if let Some(stage) = &mut self.post_stage {
+            stage.resize(
+                pixels,
+                &self.bilinear_sampler,
+                (self.screen_width, self.screen_height),
+                (self.texture_width, self.texture_height),
+            );
         }
     }
 
@@ -556,6 +1117,10 @@ impl MartyScaler {
             brightness: self.brightness,
             contrast: self.contrast,
             mono: self.mono as u32,
+            scanline_intensity: self.scanline_intensity,
+            pad0: 0,
+            pad1: 0,
+            pad2: 0,
             mono_color: MartyColor::from(self.mono_color).into(),
         };
 
@@ -601,13 +1166,22 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
         &self.texture_view
     }
 
-    /// Draw the pixel buffer to the marty_render target.
+    /// Draw the pixel buffer to the marty_render target. If a post-processing shader is
+    /// installed, the scaled image is drawn to an intermediate texture first.
     fn render(&self, encoder: &mut wgpu::CommandEncoder, render_target: &wgpu::TextureView) {
         //println!("render_target: {:?}", render_target);
+        if let Some(stage) = &self.persistence_stage {
+            stage.render(encoder);
+        }
+
+        let scale_target = match &self.post_stage {
+            Some(stage) => &stage.texture_view,
+            None => render_target,
+        };
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("marty_renderer marty_render pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: render_target,
+                view: scale_target,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load:  wgpu::LoadOp::Clear(MartyColor::from(self.fill_color).to_wgpu_color_linear()),
@@ -640,6 +1214,11 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
 
         //rpass.draw(0..3, 0..1);
         rpass.draw(0..3, 0..1);
+        drop(rpass);
+
+        if let Some(stage) = &self.post_stage {
+            stage.render(encoder, render_target);
+        }
     }
 
     fn resize(
@@ -654,23 +1233,10 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
     ) {
         //self.texture_view = create_texture_view(pixels, self.texture_width, self.texture_height);
         self.texture_view = pixels.texture().create_view(&wgpu::TextureViewDescriptor::default());
-        self.nearest_bind_group = create_bind_group(
-            pixels.device(),
-            &self.bind_group_layout,
-            &self.texture_view,
-            &self.nearest_sampler,
-            &self.transform_uniform_buffer,
-            &self.params_uniform_buffer,
-        );
-
-        self.bilinear_bind_group = create_bind_group(
-            pixels.device(),
-            &self.bind_group_layout,
-            &self.texture_view,
-            &self.bilinear_sampler,
-            &self.transform_uniform_buffer,
-            &self.params_uniform_buffer,
-        );
+        if let Some(stage) = &mut self.persistence_stage {
+            stage.resize(pixels, &self.nearest_sampler);
+        }
+        self.rebuild_bind_groups(pixels);
 
         //println!("screen_margin_y: {}", self.screen_margin_y);
         let matrix = ScalingMatrix::new(
@@ -692,28 +1258,16 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
         pixels
             .queue()
             .write_buffer(&self.transform_uniform_buffer, 0, transform_bytes);
+        self.resize_post_stage(pixels);
     }
 
     fn resize_surface(&mut self, pixels: &pixels::Pixels, screen_width: u32, screen_height: u32) {
         //self.texture_view = create_texture_view(pixels, self.screen_width, self.screen_height);
         self.texture_view = pixels.texture().create_view(&wgpu::TextureViewDescriptor::default());
-        self.nearest_bind_group = create_bind_group(
-            pixels.device(),
-            &self.bind_group_layout,
-            &self.texture_view,
-            &self.nearest_sampler,
-            &self.transform_uniform_buffer,
-            &self.params_uniform_buffer,
-        );
-
-        self.bilinear_bind_group = create_bind_group(
-            pixels.device(),
-            &self.bind_group_layout,
-            &self.texture_view,
-            &self.bilinear_sampler,
-            &self.transform_uniform_buffer,
-            &self.params_uniform_buffer,
-        );
+        if let Some(stage) = &mut self.persistence_stage {
+            stage.resize(pixels, &self.nearest_sampler);
+        }
+        self.rebuild_bind_groups(pixels);
 
         self.screen_width = screen_width;
         self.screen_height = screen_height;
@@ -729,6 +1283,7 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
         pixels
             .queue()
             .write_buffer(&self.transform_uniform_buffer, 0, transform_bytes);
+        self.resize_post_stage(pixels);
     }
 
     fn set_mode(&mut self, pixels: &pixels::Pixels, new_mode: ScalerMode) {
@@ -740,6 +1295,38 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
         self.mode
     }
 
+    fn surface_to_texture(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        if self.screen_width == 0 || self.screen_height == 0 {
+            return None;
+        }
+
+        let matrix = ScalingMatrix::new(
+            self.mode,
+            (self.texture_width as f32, self.texture_height as f32),
+            (self.target_width as f32, self.target_height as f32),
+            (self.screen_width as f32, self.screen_height as f32),
+            self.screen_margin_y as f32,
+        );
+        let scale_x = matrix.transform.cols[0].x;
+        let scale_y = matrix.transform.cols[1].y;
+        let offset_x = matrix.transform.cols[3].x;
+        let offset_y = matrix.transform.cols[3].y;
+
+        // Convert surface pixels to normalized device coordinates, then invert the scaling
+        // transform and map to texture coordinates as the vertex shader does.
+        let ndc_x = (x / self.screen_width as f32) * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y / self.screen_height as f32) * 2.0;
+        let u = ((ndc_x - offset_x) / scale_x) * 0.5 + 0.5;
+        let v = 0.5 - ((ndc_y - offset_y) / scale_y) * 0.5;
+
+        if (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v) {
+            Some((u, v))
+        }
+        else {
+            None
+        }
+    }
+
     fn set_margins(&mut self, l: u32, r: u32, t: u32, b: u32) {
         self.margin_l = l;
         self.margin_r = r;
@@ -814,13 +1401,20 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
             ScalerOption::Scanlines {
                 enabled,
                 lines,
-                intensity: _i,
+                intensity,
             } => {
                 self.scanlines = lines.unwrap_or(self.scanlines);
                 self.do_scanlines = enabled.unwrap_or(self.do_scanlines);
+                self.scanline_intensity = intensity.unwrap_or(self.scanline_intensity);
                 update_uniform = true;
             }
+            ScalerOption::Persistence(decay) => {
+                self.set_persistence(pixels, decay);
+            }
             ScalerOption::Effect(_) => {}
+            ScalerOption::PostShader(source) => {
+                self.set_post_shader(pixels, source);
+            }
         }
 
         if update && update_uniform {
@@ -962,21 +1556,21 @@ impl ScalingMatrix {
     ) -> Self {
         let margin_ndc = margin_y / (screen_size.1 / 2.0);
 
-        let (texture_width, _texture_height) = texture_size;
-        let target_height = target_size.1;
+        let (texture_width, texture_height) = texture_size;
+        let (target_width, target_height) = target_size;
         let (screen_width, screen_height) = screen_size;
-
-        let max_height_factor = ((screen_height - margin_y) / screen_height).max(1.0);
         let adjusted_screen_h = screen_height - margin_y;
 
-        let width_ratio = (screen_width / texture_width).max(1.0);
-        let height_ratio = (adjusted_screen_h / target_height).max(max_height_factor);
-
-        // Get smallest scale size
-        let scale = width_ratio.clamp(1.0, height_ratio).floor();
+        // Scale the texture itself, not the aspect-corrected target, so that every texel covers a
+        // whole number of screen pixels. The target only supplies the aspect ratio to aim for.
+        let (scale_x, scale_y) = ScalingMatrix::integer_factors(
+            (texture_width, texture_height),
+            target_width / target_height,
+            (screen_width, adjusted_screen_h),
+        );
 
-        let scaled_width = texture_width * scale;
-        let scaled_height = target_height * scale;
+        let scaled_width = texture_width * scale_x;
+        let scaled_height = texture_height * scale_y;
 
         // Create a transformation matrix
         let sw = scaled_width / screen_width;
@@ -1012,6 +1606,38 @@ impl ScalingMatrix {
         }
     }
 
+    /// Choose independent integer scale factors for each axis that best approximate the target
+    /// aspect ratio within the available area. The largest factors within INTEGER_ASPECT_TOLERANCE
+    /// of the target aspect are used; if no factors are close enough, the most accurate are used.
+    fn integer_factors(texture_size: (f32, f32), target_aspect: f32, available: (f32, f32)) -> (f32, f32) {
+        let (texture_width, texture_height) = texture_size;
+        let max_x = ((available.0 / texture_width).floor() as u32).max(1);
+        let max_y = ((available.1 / texture_height).floor() as u32).max(1);
+
+        let mut best = (1, 1);
+        let mut best_error = f32::MAX;
+        let mut best_in_tolerance = false;
+
+        for scale_y in 1..=max_y {
+            let ideal_x = target_aspect * texture_height * scale_y as f32 / texture_width;
+            let scale_x = (ideal_x.round() as u32).clamp(1, max_x);
+
+            let aspect = (texture_width * scale_x as f32) / (texture_height * scale_y as f32);
+            let error = (aspect - target_aspect).abs() / target_aspect;
+            let in_tolerance = error <= INTEGER_ASPECT_TOLERANCE;
+
+            // Larger factors win as long as they are within tolerance, or if nothing within
+            // tolerance has been found yet and they are at least as accurate.
+            if in_tolerance || (!best_in_tolerance && error <= best_error) {
+                best = (scale_x, scale_y);
+                best_error = error;
+                best_in_tolerance = in_tolerance;
+            }
+        }
+
+        (best.0 as f32, best.1 as f32)
+    }
+
     /// Create a transformation matrix that stretches the texture across the entire surface,
     /// ignoring aspect ratio.
     fn stretch_matrix(
@@ -0,0 +1,69 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    marty_pixels_scaler::shaders::persistence.wgsl
+
+    Blend the current frame with an accumulation of previous frames to emulate
+    the decay of CRT phosphors. Runs at the resolution of the source texture,
+    before scaling.
+*/
+
+struct PersistenceParams {
+    // Fraction of the accumulated brightness retained from the previous frame.
+    decay: f32,
+    pad0: f32,
+    pad1: f32,
+    pad2: f32,
+};
+
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0) @binding(0) var r_tex_current: texture_2d<f32>;
+@group(0) @binding(1) var r_tex_history: texture_2d<f32>;
+@group(0) @binding(2) var r_tex_sampler: sampler;
+@group(0) @binding(3) var<uniform> persistence: PersistenceParams;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.tex_coord = uv;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    let current = textureSample(r_tex_current, r_tex_sampler, tex_coord);
+    let history = textureSample(r_tex_history, r_tex_sampler, tex_coord);
+
+    // A lit phosphor is as bright as the current frame drives it, or the decayed glow of what it
+    // showed before, whichever is brighter.
+    return vec4<f32>(max(current.rgb, history.rgb * persistence.decay), 1.0);
+}
@@ -55,6 +55,10 @@ struct CrtParamUniform {
     brightness: f32,
     contrast: f32,
     mono: u32,
+    scanline_intensity: f32,
+    pad0: u32,
+    pad1: u32,
+    pad2: u32,
     mono_color: vec4<f32>,
 };
 
@@ -209,7 +213,7 @@ fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         let mono = scaler_opts.crt_params.mono;
 
         if (scanlines > 0u) {
-            color = do_scanlines(color, curved_tex_coord.y, scanlines, 0.3);
+            color = do_scanlines(color, curved_tex_coord.y, scanlines, scaler_opts.crt_params.scanline_intensity);
         }
 
         if (mono != 0u) {
@@ -0,0 +1,94 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    benches::simd_bench.rs
+
+    Benchmarks comparing the SIMD rendering kernels to their scalar
+    equivalents, over one full CGA field.
+
+*/
+
+use rand::Rng;
+
+use videocard_renderer::simd::{self, NtscCoefficients};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const FIELD_W: usize = 912;
+const FIELD_H: usize = 262;
+
+pub fn rgbi_bench(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let palette: Vec<u32> = (0..16).map(|_| rng.gen()).collect();
+    let src: Vec<u8> = (0..FIELD_W * FIELD_H).map(|_| rng.gen_range(0..16)).collect();
+    let mut out = vec![0u32; FIELD_W * FIELD_H];
+
+    let mut group = c.benchmark_group("rgbi_to_rgba");
+    group.bench_function("simd", |b| {
+        b.iter(|| {
+            for (src, out) in src.chunks_exact(FIELD_W).zip(out.chunks_exact_mut(FIELD_W)) {
+                simd::rgbi_to_rgba_line(black_box(src), &palette, out);
+            }
+        });
+    });
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for (src, out) in src.chunks_exact(FIELD_W).zip(out.chunks_exact_mut(FIELD_W)) {
+                simd::rgbi_to_rgba_line_scalar(black_box(src), &palette, out);
+            }
+        });
+    });
+    group.finish();
+}
+
+pub fn ntsc_bench(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let k = NtscCoefficients::new(128, 3000, 1800, -800, -1900, -3500, 4600);
+    let luma: Vec<i32> = (0..FIELD_W + 2).map(|_| rng.gen_range(-4096..4096)).collect();
+    let a: Vec<i32> = (0..FIELD_W).map(|_| rng.gen_range(-1024..1024)).collect();
+    let b: Vec<i32> = (0..FIELD_W).map(|_| rng.gen_range(-1024..1024)).collect();
+    let mut out = vec![0u32; FIELD_W];
+
+    let mut group = c.benchmark_group("decode_ntsc");
+    group.bench_function("simd", |bench| {
+        bench.iter(|| {
+            for _ in 0..FIELD_H {
+                simd::decode_ntsc_line(black_box(&luma), &a, &b, &k, &mut out);
+            }
+        });
+    });
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| {
+            for _ in 0..FIELD_H {
+                simd::decode_ntsc_line_scalar(black_box(&luma), &a, &b, &k, &mut out);
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(simd_benches, rgbi_bench, ntsc_bench);
+criterion_main!(simd_benches);
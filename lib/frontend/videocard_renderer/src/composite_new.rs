@@ -13,7 +13,11 @@
 
 */
 
-use super::CompositeParams;
+use super::{
+    simd::{self, NtscCoefficients},
+    CompositeParams,
+};
+use marty_core::device_traits::videocard::VideoStandard;
 
 #[rustfmt::skip]
 const CHROMA_MULTIPLEXER: [u8; 256] = [
@@ -41,6 +45,10 @@ const TAU: f64 = 6.28318531;
 
 const SCALER_MAXWIDTH: usize = 2048;
 
+// Number of hdots averaged to band-limit PAL chroma. At 14.318Mhz, 8 samples gives
+// roughly the 1.3Mhz chroma bandwidth of a PAL receiver.
+const PAL_CHROMA_TAPS: usize = 8;
+
 macro_rules! new_cga {
     ($c:expr, $i:expr, $r:expr, $g:expr, $b:expr) => {
         (($c as f64) / 0.72) * 0.29
@@ -74,22 +82,36 @@ macro_rules! composite_convert {
 }
 */
 
+#[derive(Clone)]
 pub struct ReCompositeBuffers {
-    temp:  [i32; SCALER_MAXWIDTH + 10],
+    temp: [i32; SCALER_MAXWIDTH + 10],
     atemp: [i32; SCALER_MAXWIDTH + 2],
     btemp: [i32; SCALER_MAXWIDTH + 2],
+    // PAL delay line, holding the chroma of the previous scanline.
+    delay_u: [i32; SCALER_MAXWIDTH],
+    delay_v: [i32; SCALER_MAXWIDTH],
+    delay_valid: bool,
 }
 
 impl ReCompositeBuffers {
     pub fn new() -> Self {
         Self {
-            temp:  [0; SCALER_MAXWIDTH + 10],
+            temp: [0; SCALER_MAXWIDTH + 10],
             atemp: [0; SCALER_MAXWIDTH + 2],
             btemp: [0; SCALER_MAXWIDTH + 2],
+            delay_u: [0; SCALER_MAXWIDTH],
+            delay_v: [0; SCALER_MAXWIDTH],
+            delay_valid: false,
         }
     }
+
+    /// Clear the PAL delay line. Should be called at the start of each field.
+    pub fn reset_delay_line(&mut self) {
+        self.delay_valid = false;
+    }
 }
 
+#[derive(Clone)]
 pub struct ReCompositeContext {
     brightness: f64,
     contrast: f64,
@@ -115,8 +137,15 @@ pub struct ReCompositeContext {
     video_sharpness:    i32,
     tandy_mode_control: u32,
 
-    cgamode: u8,
-    new_cga: bool,
+    // PAL encoder output levels for each RGBI color, in the same fixed point scale as the
+    // NTSC decoder output.
+    pal_y: [i32; 16],
+    pal_u: [i32; 16],
+    pal_v: [i32; 16],
+
+    cgamode:  u8,
+    new_cga:  bool,
+    standard: VideoStandard,
 }
 
 impl ReCompositeContext {
@@ -145,8 +174,13 @@ impl ReCompositeContext {
             video_sharpness: 0,
             tandy_mode_control: 0,
 
-            cgamode: 0,
-            new_cga: false,
+            pal_y: [0; 16],
+            pal_u: [0; 16],
+            pal_v: [0; 16],
+
+            cgamode:  0,
+            new_cga:  false,
+            standard: VideoStandard::Ntsc,
         }
     }
 
@@ -256,9 +290,53 @@ impl ReCompositeContext {
 
         self.video_sharpness = (self.sharpness * 256.0 / 100.0) as i32;
 
+        self.recalculate_pal(cgamode);
+
         self.cgamode = cgamode;
     }
 
+    /// Calculate the YUV levels produced by the on-board encoder of a PAL CGA clone.
+    /// Unlike NTSC, the PAL subcarrier is not locked to the pixel clock, so no artifact
+    /// colors are produced and each RGBI color maps to a fixed luma and chroma level.
+    /// Hue is not adjustable, as the PAL delay line cancels out phase errors.
+    fn recalculate_pal(&mut self, cgamode: u8) {
+        const SCALE: f64 = 255.0 * 8192.0;
+
+        let contrast = self.contrast / 100.0;
+        let brightness = self.brightness / 100.0;
+        let saturation = if (cgamode & 4) != 0 {
+            // B&W mode (no colorburst)
+            0.0
+        }
+        else {
+            self.saturation / 100.0
+        };
+
+        for color in 0..16 {
+            let i = if (color & 8) != 0 { 1.0 / 3.0 } else { 0.0 };
+            let r = if (color & 4) != 0 { 2.0 / 3.0 } else { 0.0 } + i;
+            let g = if (color & 2) != 0 { 2.0 / 3.0 } else { 0.0 } + i;
+            let b = if (color & 1) != 0 { 2.0 / 3.0 } else { 0.0 } + i;
+
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            let u = 0.492 * (b - y);
+            let v = 0.877 * (r - y);
+
+            self.pal_y[color] = ((y * contrast + brightness) * SCALE) as i32;
+            self.pal_u[color] = (u * contrast * saturation * SCALE) as i32;
+            self.pal_v[color] = (v * contrast * saturation * SCALE) as i32;
+        }
+    }
+
+    /// Set the video standard to decode. Composite parameters are recalculated if the
+    /// standard has changed.
+    pub fn set_standard(&mut self, standard: VideoStandard) {
+        if self.standard != standard {
+            self.standard = standard;
+            self.recalculate(self.cgamode);
+        }
+    }
+
     /// Set adjustment parameters.
     /// Arguments are scaled as required by algorithm.
     pub fn adjust(&mut self, p: &CompositeParams) {
@@ -266,6 +344,7 @@ impl ReCompositeContext {
         self.hue_offset = p.hue;
         self.saturation = p.sat * 100.0;
         self.brightness = (p.luma - 1.0) * 10.0;
+        self.sharpness = p.sharpness * 100.0;
 
         self.new_cga = p.new_cga;
     }
@@ -278,6 +357,11 @@ impl ReCompositeContext {
         in_line: &[u8],
         out_line: &mut [u32],
     ) {
+        if let VideoStandard::Pal = self.standard {
+            self.composite_process_pal(w, buffers, in_line, out_line);
+            return;
+        }
+
         let blocks = (w / 4) as usize;
 
         let mut o_index = 0;
@@ -330,8 +414,8 @@ impl ReCompositeContext {
             // Do full color multiplexer decoding
 
             let mut i_index = 4;
-            let mut ap_index = 1;
-            let mut bp_index = 1;
+            let ap_index = 1;
+            let bp_index = 1;
 
             for x in 0..(w + 2) {
                 buffers.atemp[ap_index + x - 1] = buffers.temp[i_index - 4]
@@ -344,98 +428,89 @@ impl ReCompositeContext {
                 i_index += 1;
             }
 
-            i_index = 5;
-            buffers.temp[i_index - 1] = (buffers.temp[i_index - 1] << 3) - buffers.atemp[ap_index - 1];
-            buffers.temp[i_index] = (buffers.temp[i_index] << 3) - buffers.atemp[ap_index];
-            let mut srgb_index = 0;
-
-            let mut a;
-            let mut b;
-            let mut c;
-            let mut d;
-            let mut y;
-            let mut rr;
-            let mut gg;
-            let mut bb;
-
-            for _ in 0..blocks {
-                // COMPOSITE_CONVERT(a, b)
-                buffers.temp[i_index + 1] = (buffers.temp[i_index + 1] << 3) - buffers.atemp[ap_index + 1];
-                a = buffers.atemp[ap_index];
-                b = buffers.btemp[bp_index];
-                c = buffers.temp[i_index] + buffers.temp[i_index];
-                d = buffers.temp[i_index - 1] + buffers.temp[i_index + 1];
-                y = ((c + d) << 8) + self.video_sharpness * (c - d);
-                rr = y + (self.video_ri * a) + (self.video_rq * b);
-                gg = y + (self.video_gi * a) + (self.video_gq * b);
-                bb = y + (self.video_bi * a) + (self.video_bq * b);
-                i_index += 1;
-                ap_index += 1;
-                bp_index += 1;
-
-                out_line[srgb_index] = (0xFF << 24 | (byte_clamp(bb) as u32) << 16)
-                    | ((byte_clamp(gg) as u32) << 8)
-                    | (byte_clamp(rr) as u32);
-                srgb_index += 1;
+            // Prescale luma and remove the chroma carrier. Each sample depends only on the
+            // filtered chroma, so this can be done in one pass ahead of decoding.
+            let n = blocks * 4;
+            for k in 4..(n + 6) {
+                buffers.temp[k] = (buffers.temp[k] << 3) - buffers.atemp[k - 4];
+            }
 
-                // COMPOSITE_CONVERT(-b, a)
-                buffers.temp[i_index + 1] = (buffers.temp[i_index + 1] << 3) - buffers.atemp[ap_index + 1];
-                a = buffers.atemp[ap_index];
-                b = buffers.btemp[bp_index];
-                c = buffers.temp[i_index] + buffers.temp[i_index];
-                d = buffers.temp[i_index - 1] + buffers.temp[i_index + 1];
-                y = ((c + d) << 8) + self.video_sharpness * (c - d);
-                rr = y + self.video_ri * -b + self.video_rq * a;
-                gg = y + self.video_gi * -b + self.video_gq * a;
-                bb = y + self.video_bi * -b + self.video_bq * a;
-                i_index += 1;
-                ap_index += 1;
-                bp_index += 1;
+            let coefficients = NtscCoefficients::new(
+                self.video_sharpness,
+                self.video_ri,
+                self.video_rq,
+                self.video_gi,
+                self.video_gq,
+                self.video_bi,
+                self.video_bq,
+            );
+            simd::decode_ntsc_line(
+                &buffers.temp[4..],
+                &buffers.atemp[ap_index..],
+                &buffers.btemp[bp_index..],
+                &coefficients,
+                &mut out_line[..n],
+            );
+        }
+    }
 
-                out_line[srgb_index] = (0xFF << 24 | (byte_clamp(bb) as u32) << 16)
-                    | ((byte_clamp(gg) as u32) << 8)
-                    | (byte_clamp(rr) as u32);
-                srgb_index += 1;
+    /// Decode one scanline as a PAL receiver would. Chroma is band-limited horizontally and
+    /// averaged with the previous scanline by the PAL delay line.
+    fn composite_process_pal(
+        &mut self,
+        w: usize,
+        buffers: &mut ReCompositeBuffers,
+        in_line: &[u8],
+        out_line: &mut [u32],
+    ) {
+        let w = w.min(SCALER_MAXWIDTH);
+
+        // Luma, with the same sharpness filter as the NTSC decoder.
+        for x in 0..w {
+            let c = self.pal_y[(in_line[x] & 0x0f) as usize];
+            let l = self.pal_y[(in_line[x.saturating_sub(1)] & 0x0f) as usize];
+            let r = self.pal_y[(in_line[(x + 1).min(w - 1)] & 0x0f) as usize];
+            buffers.temp[x] = c + ((self.video_sharpness * ((c << 1) - l - r)) >> 8);
+        }
 
-                // COMPOSITE_CONVERT(-a, -b)
-                buffers.temp[i_index + 1] = (buffers.temp[i_index + 1] << 3) - buffers.atemp[ap_index + 1];
-                a = buffers.atemp[ap_index];
-                b = buffers.btemp[bp_index];
-                c = buffers.temp[i_index] + buffers.temp[i_index];
-                d = buffers.temp[i_index - 1] + buffers.temp[i_index + 1];
-                y = ((c + d) << 8) + self.video_sharpness * (c - d);
-                rr = y + self.video_ri * -a + self.video_rq * -b;
-                gg = y + self.video_gi * -a + self.video_gq * -b;
-                bb = y + self.video_bi * -a + self.video_bq * -b;
-                i_index += 1;
-                ap_index += 1;
-                bp_index += 1;
+        // Band-limit chroma with a centered box filter.
+        let mut sum_u = 0;
+        let mut sum_v = 0;
+        let half = PAL_CHROMA_TAPS / 2;
+        for x in 0..(w + half) {
+            if x < w {
+                sum_u += self.pal_u[(in_line[x] & 0x0f) as usize];
+                sum_v += self.pal_v[(in_line[x] & 0x0f) as usize];
+            }
+            if x >= PAL_CHROMA_TAPS {
+                sum_u -= self.pal_u[(in_line[x - PAL_CHROMA_TAPS] & 0x0f) as usize];
+                sum_v -= self.pal_v[(in_line[x - PAL_CHROMA_TAPS] & 0x0f) as usize];
+            }
+            if x >= half {
+                buffers.atemp[x - half] = sum_u / PAL_CHROMA_TAPS as i32;
+                buffers.btemp[x - half] = sum_v / PAL_CHROMA_TAPS as i32;
+            }
+        }
 
-                out_line[srgb_index] = (0xFF << 24 | (byte_clamp(bb) as u32) << 16)
-                    | ((byte_clamp(gg) as u32) << 8)
-                    | (byte_clamp(rr) as u32);
-                srgb_index += 1;
+        for x in 0..w {
+            let mut u = buffers.atemp[x];
+            let mut v = buffers.btemp[x];
+            if buffers.delay_valid {
+                u = (u + buffers.delay_u[x]) >> 1;
+                v = (v + buffers.delay_v[x]) >> 1;
+            }
+            buffers.delay_u[x] = buffers.atemp[x];
+            buffers.delay_v[x] = buffers.btemp[x];
 
-                // COMPOSITE_CONVERT(b, -a)
-                buffers.temp[i_index + 1] = (buffers.temp[i_index + 1] << 3) - buffers.atemp[ap_index + 1];
-                a = buffers.atemp[ap_index];
-                b = buffers.btemp[bp_index];
-                c = buffers.temp[i_index] + buffers.temp[i_index];
-                d = buffers.temp[i_index - 1] + buffers.temp[i_index + 1];
-                y = ((c + d) << 8) + self.video_sharpness * (c - d);
-                rr = y + self.video_ri * b + self.video_rq * -a;
-                gg = y + self.video_gi * b + self.video_gq * -a;
-                bb = y + self.video_bi * b + self.video_bq * -a;
-                i_index += 1;
-                ap_index += 1;
-                bp_index += 1;
+            let y = buffers.temp[x];
+            let rr = y + ((v as f64) * 1.140) as i32;
+            let gg = y - ((u as f64) * 0.395 + (v as f64) * 0.581) as i32;
+            let bb = y + ((u as f64) * 2.032) as i32;
 
-                out_line[srgb_index] = (0xFF << 24 | (byte_clamp(bb) as u32) << 16)
-                    | ((byte_clamp(gg) as u32) << 8)
-                    | (byte_clamp(rr) as u32);
-                srgb_index += 1;
-            }
+            out_line[x] =
+                (0xFF << 24 | (byte_clamp(bb) as u32) << 16) | ((byte_clamp(gg) as u32) << 8) | (byte_clamp(rr) as u32);
         }
+        buffers.delay_valid = true;
     }
 }
 
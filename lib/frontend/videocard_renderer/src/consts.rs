@@ -65,6 +65,7 @@ pub const VGA_HIRES_GFX_W: u32 = 640;
 pub const VGA_HIRES_GFX_H: u32 = 480;
 
 pub const XOR_COLOR: u8 = 0x80;
+pub const XOR_DASH_LEN: u32 = 4;
 
 pub const MDA_RGBA_COLORS: &[[u8; 4]; 16] = &[
     [0x00, 0x00, 0x00, 0xFF], // 0 - Black
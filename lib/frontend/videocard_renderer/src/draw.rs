@@ -29,7 +29,7 @@
     Drawing routines for VideoRenderer
 */
 
-use crate::{consts::*, resize::*};
+use crate::{consts::*, resize::*, screenshot, simd};
 use marty_core::devices::cga;
 use web_time::Instant;
 
@@ -38,17 +38,20 @@ use super::*;
 impl VideoRenderer {
     pub fn clear(&mut self) {
         self.buf.fill(0);
+        self.touch();
     }
 
     /// Draw the direct (indexed) framebuffer created by a Videocard to the specified output buffer, given
     /// the specified display extents. This base method will call the appropriate drawing routine based on
-    /// video card type. Optionally, the raster beam position can be visualized if 'beam_pos' is specified.
+    /// video card type. Optionally, the raster beam position can be visualized if 'beam_pos' is specified,
+    /// and the display area and blanking boundaries if 'regions' is specified.
     pub fn draw(
         &mut self,
         input_buf: &[u8],
         output_buf: &mut [u8],
         extents: &DisplayExtents,
         beam_pos: Option<(u32, u32)>,
+        regions: Option<RasterRegions>,
         palette: Option<Vec<[u8; 4]>>,
     ) {
         let render_start = Instant::now();
@@ -76,6 +79,7 @@ impl VideoRenderer {
                     self.params.render.w,
                     self.params.render.h,
                     input_buf,
+                    palette,
                     self.params.aperture,
                     extents,
                 );
@@ -127,10 +131,24 @@ impl VideoRenderer {
             ),
         }
 
-        // Draw raster beam position if provided
-        if let Some(beam) = beam_pos {
-            let beam_x = beam.0 - extents.apertures[self.params.aperture as usize].x;
-            let mut beam_y = beam.1 - &extents.apertures[self.params.aperture as usize].y;
+        let aperture = &extents.apertures[self.params.aperture as usize];
+
+        // Draw display area and blanking boundaries if provided
+        if let Some(regions) = regions {
+            VideoRenderer::draw_raster_regions(
+                first_pass_buf,
+                self.params.render.w,
+                self.params.render.h,
+                aperture,
+                self.params.line_double,
+                &regions,
+            );
+        }
+
+        // Draw raster beam position if provided, unless it is outside the current aperture.
+        if let Some((beam_x, mut beam_y)) =
+            beam_pos.and_then(|(x, y)| Some((x.checked_sub(aperture.x)?, y.checked_sub(aperture.y)?)))
+        {
             if self.params.line_double {
                 beam_y *= 2
             };
@@ -160,12 +178,21 @@ impl VideoRenderer {
             if let Some(path) = self.screenshot_path.as_ref() {
                 // We may not have been given a path, in which case the caller can retrieve the
                 // internal screenshot buffer, ie, for use in a GUI.
-                match image::save_buffer(
-                    path.clone(),
+                let mut metadata = self.screenshot_opts.metadata.clone();
+                metadata.extend([
+                    ("Video Type".to_string(), format!("{:?}", self.video_type)),
+                    ("Mode Byte".to_string(), format!("{:02X}", extents.mode_byte)),
+                    ("Aperture".to_string(), format!("{:?}", self.params.aperture)),
+                    ("Composite".to_string(), self.composite_enabled.to_string()),
+                ]);
+
+                match screenshot::write_png(
+                    path,
                     first_pass_buf,
                     self.params.render.w,
                     self.params.render.h,
-                    image::ColorType::Rgba8,
+                    png::ColorType::Rgba,
+                    &metadata,
                 ) {
                     Ok(_) => {
                         screenshot_taken = true;
@@ -175,6 +202,16 @@ impl VideoRenderer {
                         log::error!("Error writing screenshot: {}: {}", path.display(), e)
                     }
                 }
+
+                if self.screenshot_opts.raw {
+                    let raw_path = screenshot::raw_screenshot_path(path);
+                    match screenshot::write_raw_frame(&raw_path, input_buf, extents, &metadata) {
+                        Ok(_) => log::info!("Saved raw framebuffer: {}", raw_path.display()),
+                        Err(e) => {
+                            log::error!("Error writing raw framebuffer: {}: {}", raw_path.display(), e)
+                        }
+                    }
+                }
             }
 
             if !do_software_aspect {
@@ -186,6 +223,7 @@ impl VideoRenderer {
             }
 
             self.screenshot_path = None;
+            self.screenshot_opts = Default::default();
             self.screenshot_requested = false;
         }
 
@@ -213,6 +251,32 @@ impl VideoRenderer {
         //log::debug!("render time: {}", self.last_render_time.as_secs_f64());
     }
 
+    /// Draw a partially scanned-out frame for beam-raced presentation. Rows above 'beam_y' have
+    /// already been drawn this frame and are taken from the back buffer; the rest of the frame
+    /// is taken from the last complete frame in the front buffer, as a CRT would still be
+    /// showing it.
+    pub fn draw_beam_raced(
+        &mut self,
+        front_buf: &[u8],
+        back_buf: &[u8],
+        beam_y: u32,
+        output_buf: &mut [u8],
+        extents: &DisplayExtents,
+        palette: Option<Vec<[u8; 4]>>,
+    ) {
+        let len = front_buf.len().min(back_buf.len());
+        let split = (beam_y as usize * extents.row_stride).min(len);
+
+        // Take the composition buffer out of self so we can pass it to draw().
+        let mut composed = std::mem::take(&mut self.beam_buf);
+        composed.clear();
+        composed.extend_from_slice(&back_buf[..split]);
+        composed.extend_from_slice(&front_buf[split..len]);
+
+        self.draw(&composed, output_buf, extents, None, None, palette);
+        self.beam_buf = composed;
+    }
+
     pub fn draw_horizontal_xor_line_2x(&mut self, frame: &mut [u8], w: u32, span: u32, h: u32, y: u32) {
         if y > (h - 1) {
             return;
@@ -300,6 +364,84 @@ impl VideoRenderer {
         }
     }
 
+    /// Draw a dashed horizontal line from x0 to x1 (exclusive) by inverting alternating runs of pixels.
+    pub fn draw_horizontal_dashed_xor_line(frame: &mut [u8], w: u32, h: u32, y: u32, x0: u32, x1: u32) {
+        if y > (h - 1) {
+            return;
+        }
+
+        let frame_row0_offset = (y * (w * 4)) as usize;
+
+        for x in (x0..x1.min(w)).filter(|x| (x / XOR_DASH_LEN) & 1 == 0) {
+            let fo0 = frame_row0_offset + (x * 4) as usize;
+
+            frame[fo0] ^= XOR_COLOR;
+            frame[fo0 + 1] ^= XOR_COLOR;
+            frame[fo0 + 2] ^= XOR_COLOR;
+        }
+    }
+
+    /// Draw a dashed vertical line from y0 to y1 (exclusive) by inverting alternating runs of pixels.
+    pub fn draw_vertical_dashed_xor_line(frame: &mut [u8], w: u32, h: u32, x: u32, y0: u32, y1: u32) {
+        if x > (w - 1) {
+            return;
+        }
+
+        let frame_x0_offset = (x * 4) as usize;
+
+        for y in (y0..y1.min(h)).filter(|y| (y / XOR_DASH_LEN) & 1 == 0) {
+            let fo0 = frame_x0_offset + (y * (w * 4)) as usize;
+
+            frame[fo0] ^= XOR_COLOR;
+            frame[fo0 + 1] ^= XOR_COLOR;
+            frame[fo0 + 2] ^= XOR_COLOR;
+        }
+    }
+
+    /// Outline the active display area and mark the start of the horizontal and vertical blanking
+    /// periods with dashed lines. The area between the display outline and the blanking lines is
+    /// overscan. Regions are specified in field coordinates and translated into the given aperture.
+    pub fn draw_raster_regions(
+        frame: &mut [u8],
+        w: u32,
+        h: u32,
+        aperture: &DisplayAperture,
+        line_double: bool,
+        regions: &RasterRegions,
+    ) {
+        let y_scale = if line_double { 2 } else { 1 };
+        let to_x = |x: u32| x.checked_sub(aperture.x);
+        let to_y = |y: u32| y.checked_sub(aperture.y).map(|y| y * y_scale);
+
+        if let Some((x0, y0, x1, y1)) = regions.display {
+            let (left, right) = (x0.saturating_sub(aperture.x), x1.saturating_sub(aperture.x));
+            let (top, bottom) = (
+                y0.saturating_sub(aperture.y) * y_scale,
+                y1.saturating_sub(aperture.y) * y_scale,
+            );
+
+            if let Some(y) = to_y(y0) {
+                VideoRenderer::draw_horizontal_dashed_xor_line(frame, w, h, y, left, right);
+            }
+            if let Some(y) = to_y(y1) {
+                VideoRenderer::draw_horizontal_dashed_xor_line(frame, w, h, y, left, right);
+            }
+            if let Some(x) = to_x(x0) {
+                VideoRenderer::draw_vertical_dashed_xor_line(frame, w, h, x, top, bottom);
+            }
+            if let Some(x) = to_x(x1) {
+                VideoRenderer::draw_vertical_dashed_xor_line(frame, w, h, x, top, bottom);
+            }
+        }
+
+        if let Some(x) = regions.hblank_x.and_then(to_x) {
+            VideoRenderer::draw_vertical_dashed_xor_line(frame, w, h, x, 0, h);
+        }
+        if let Some(y) = regions.vblank_y.and_then(to_y) {
+            VideoRenderer::draw_horizontal_dashed_xor_line(frame, w, h, y, 0, w);
+        }
+    }
+
     /// Set the alpha component of each pixel in a the specified buffer.
     pub fn set_alpha(frame: &mut [u8], w: u32, h: u32, a: u8) {
         //log::warn!("set_alpha: h: {}", h);
@@ -348,16 +490,13 @@ impl VideoRenderer {
             let frame_row0_offset = ((y * 2) * w) as usize;
             let frame_row1_offset = (((y * 2) * w) + w) as usize;
 
-            for x in 0..max_x {
-                let fo0 = frame_row0_offset + x as usize;
-                let fo1 = frame_row1_offset + x as usize;
-
-                let dbo = dbuf_row_offset + (x + horiz_adjust) as usize;
-
-                // TODO: Would it be better for cache concurrency to do one line at a time?
-                frame_u32[fo0] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
-                frame_u32[fo1] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
-            }
+            let dbo = dbuf_row_offset + horiz_adjust as usize;
+            simd::rgbi_to_rgba_line(
+                &dbuf[dbo..dbo + max_x as usize],
+                &CGA_RGBA_COLORS_U32[0],
+                &mut frame_u32[frame_row0_offset..frame_row0_offset + max_x as usize],
+            );
+            frame_u32.copy_within(frame_row0_offset..frame_row0_offset + max_x as usize, frame_row1_offset);
         }
     }
 
@@ -497,6 +636,9 @@ impl VideoRenderer {
             0
         };
 
+        ctx.set_standard(extents.standard);
+        bufs.reset_delay_line();
+
         // Convert to composite line by line
         for y in 0..(h / 2) {
             //let s_o (= ((y * w) ) as usize;
@@ -528,6 +670,7 @@ impl VideoRenderer {
             //log::debug!("mode changed: new:{:02X} old:{:02X} recalculating composite parameters...", mode, self.last_cga_mode);
             self.composite_ctx.recalculate(mode);
             self.last_cga_mode = mode;
+            self.touch();
         }
     }
 
@@ -539,6 +682,7 @@ impl VideoRenderer {
         self.composite_ctx.recalculate(self.last_cga_mode);
 
         self.composite_params = *composite_params;
+        self.touch();
     }
 
     /// Draw the MDA card in Direct Mode.
@@ -547,11 +691,15 @@ impl VideoRenderer {
     ///
     /// This version uses bytemuck to convert the framebuffer 32 bits at a time, which
     /// is much faster (benchmarked)
+    ///
+    /// A palette is only provided by the Hercules InColor, in which case the framebuffer holds
+    /// 4-bit color indices into it.
     pub fn draw_mda_direct_u32(
         frame: &mut [u8],
         w: u32,
         h: u32,
         dbuf: &[u8],
+        palette: Option<Vec<[u8; 4]>>,
         aperture_type: DisplayApertureType,
         extents: &DisplayExtents,
     ) {
@@ -580,6 +728,17 @@ impl VideoRenderer {
         let max_x = std::cmp::min(w, aperture.w);
         let frame_u32: &mut [u32] = bytemuck::cast_slice_mut(frame);
 
+        let (colors, index_mask) = match palette {
+            Some(palette) => {
+                let mut colors = [0u32; 16];
+                for (color, entry) in colors.iter_mut().zip(palette.iter()) {
+                    *color = u32::from_le_bytes(*entry);
+                }
+                (colors, 0x0F)
+            }
+            None => (*MDA_RGBA_COLORS_U32, index_mask),
+        };
+
         for y in 0..max_y {
             let dbuf_row_offset = (y + vert_adjust) as usize * extents.row_stride;
             let frame_row0_offset = (y * w) as usize;
@@ -587,7 +746,7 @@ impl VideoRenderer {
             for x in 0..max_x {
                 let fo0 = frame_row0_offset + x as usize;
                 let dbo = dbuf_row_offset + (x + horiz_adjust) as usize;
-                frame_u32[fo0] = MDA_RGBA_COLORS_U32[(dbuf[dbo] & index_mask) as usize];
+                frame_u32[fo0] = colors[(dbuf[dbo] & index_mask) as usize];
             }
         }
     }
@@ -646,16 +805,13 @@ impl VideoRenderer {
                         let frame_row0_offset = ((y * 2) * w) as usize;
                         let frame_row1_offset = (((y * 2) * w) + w) as usize;
 
-                        for x in 0..max_x {
-                            let fo0 = frame_row0_offset + x as usize;
-                            let fo1 = frame_row1_offset + x as usize;
-
-                            let dbo = dbuf_row_offset + (x + horiz_adjust) as usize;
-
-                            // TODO: Would it be better for cache concurrency to do one line at a time?
-                            frame_u32[fo0] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
-                            frame_u32[fo1] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
-                        }
+                        let dbo = dbuf_row_offset + horiz_adjust as usize;
+                        simd::rgbi_to_rgba_line(
+                            &dbuf[dbo..dbo + max_x as usize],
+                            &CGA_RGBA_COLORS_U32[0],
+                            &mut frame_u32[frame_row0_offset..frame_row0_offset + max_x as usize],
+                        );
+                        frame_u32.copy_within(frame_row0_offset..frame_row0_offset + max_x as usize, frame_row1_offset);
                     }
                 }
                 else {
@@ -663,12 +819,12 @@ impl VideoRenderer {
                         let dbuf_row_offset = (y + vert_adjust) as usize * extents.row_stride;
                         let frame_row_offset = (y * w) as usize;
 
-                        for x in 0..max_x {
-                            let fo = frame_row_offset + x as usize;
-                            let dbo = dbuf_row_offset + (x + horiz_adjust) as usize;
-
-                            frame_u32[fo] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
-                        }
+                        let dbo = dbuf_row_offset + horiz_adjust as usize;
+                        simd::rgbi_to_rgba_line(
+                            &dbuf[dbo..dbo + max_x as usize],
+                            &CGA_RGBA_COLORS_U32[0],
+                            &mut frame_u32[frame_row_offset..frame_row_offset + max_x as usize],
+                        );
                     }
                 }
             }
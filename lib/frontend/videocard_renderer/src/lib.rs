@@ -54,22 +54,26 @@ use marty_core::device_traits::videocard::{
     BufferSelect,
     CGAColor,
     CGAPalette,
+    DisplayAperture,
     DisplayApertureType,
     DisplayExtents,
     DisplayMode,
+    RasterRegions,
     RenderBpp,
     VideoType,
 };
 use serde::Deserialize;
 
 // Re-export submodules
-pub use self::{color::*, composite::*, consts::*, resize::*};
+pub use self::{color::*, composite::*, consts::*, resize::*, screenshot::ScreenshotOptions};
 
 pub mod color;
 pub mod composite;
 pub mod consts;
 pub mod draw;
 pub mod resize;
+pub mod screenshot;
+pub mod simd;
 // Reenigne composite
 pub mod composite_new;
 
@@ -154,6 +158,7 @@ pub struct CompositeParams {
     pub hue: f64,
     pub sat: f64,
     pub luma: f64,
+    pub sharpness: f64,
     pub new_cga: bool,
 }
 
@@ -165,6 +170,7 @@ impl Default for CompositeParams {
             hue: 0.0,
             sat: 1.0,
             luma: 1.0,
+            sharpness: 0.0,
             new_cga: false,
         }
     }
@@ -191,6 +197,7 @@ pub struct DebugRenderParams {
     pub draw_scanline_color: Option<RenderColor>,
 }
 
+#[derive(Clone)]
 pub struct VideoRenderer {
     video_type: VideoType,
     mode: DisplayMode,
@@ -221,11 +228,17 @@ pub struct VideoRenderer {
     buffer_select: BufferSelect,
 
     screenshot_buf: Vec<u8>,
+    beam_buf: Vec<u8>,
     screenshot_path: Option<std::path::PathBuf>,
+    screenshot_opts: ScreenshotOptions,
     screenshot_requested: bool,
 
     last_render_time: Duration,
     event_queue: VecDeque<RendererEvent>,
+
+    // Incremented whenever state affecting drawing changes, so copies made with fork() can be
+    // refreshed.
+    generation: u64,
 }
 
 impl VideoRenderer {
@@ -270,11 +283,15 @@ impl VideoRenderer {
             buffer_select: BufferSelect::Front,
 
             screenshot_buf: Vec::new(),
+            beam_buf: Vec::new(),
             screenshot_path: None,
+            screenshot_opts: Default::default(),
             screenshot_requested: false,
 
             last_render_time: Duration::from_secs(0),
             event_queue: VecDeque::new(),
+
+            generation: 0,
         }
     }
 
@@ -290,8 +307,36 @@ impl VideoRenderer {
         self.last_render_time
     }
 
+    /// Set the last render time, for a renderer whose frames are drawn by a copy made with fork().
+    pub fn set_last_render_time(&mut self, time: Duration) {
+        self.last_render_time = time;
+    }
+
+    /// Return the current state generation. This changes whenever a setting that affects drawing
+    /// changes, indicating that any copy made with fork() is out of date.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Mark the drawing state as changed.
+    fn touch(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Create a copy of this renderer that can draw frames elsewhere, such as on a render thread.
+    /// A pending screenshot request is handed to the copy, which will take it on its next draw.
+    /// Events are not copied, so the caller is responsible for forwarding any the copy produces.
+    pub fn fork(&mut self) -> VideoRenderer {
+        let mut copy = self.clone();
+        copy.event_queue.clear();
+        self.screenshot_requested = false;
+        self.screenshot_path = None;
+        copy
+    }
+
     pub fn set_config_params(&mut self, cfg: &RendererConfigParams) {
         self.composite_enabled = cfg.composite;
+        self.touch();
 
         if cfg.aspect_correction {
             self.set_aspect_ratio(cfg.aspect_ratio, Some(AspectCorrectionMode::Hardware));
@@ -327,6 +372,7 @@ impl VideoRenderer {
     pub fn set_composite(&mut self, state: bool) {
         log::debug!("Setting composite rendering to {}", state);
         self.composite_enabled = state;
+        self.touch();
     }
 
     pub fn get_composite(&mut self) -> bool {
@@ -337,19 +383,32 @@ impl VideoRenderer {
         log::debug!("Setting renderer aperture to {:?}", aperture);
         self.params.aperture = aperture;
         self.aperture_dirty = true;
+        self.touch();
+    }
+
+    /// Convert a normalized (0.0-1.0) position within the rendered image to a raster position
+    /// within the video field, accounting for the selected display aperture.
+    pub fn texture_to_beam(&self, extents: &DisplayExtents, u: f32, v: f32) -> Option<(u32, u32)> {
+        let aperture = extents.apertures.get(self.params.aperture as usize)?;
+        let x = aperture.x + (u * aperture.w as f32) as u32;
+        let y = aperture.y + (v * aperture.h as f32) as u32;
+        Some((x, y))
     }
 
     pub fn set_debug(&mut self, state: bool) {
         self.params.debug_aperture = state;
+        self.touch();
     }
 
     pub fn set_line_double(&mut self, state: bool) {
         self.params.line_double = state;
+        self.touch();
     }
 
     /// Resizes the internal rendering buffer to the specified dimensions, before aspect correction.
     pub fn resize(&mut self, new_dims: VideoDimensions) {
         self.initialized = true;
+        self.touch();
 
         let mut new_aspect_corrected_dims = self.params.render;
         if let Some(_) = self.aspect_ratio {
@@ -458,6 +517,7 @@ impl VideoRenderer {
             self.params.aspect_correction = mode;
             self.aspect_dirty = true;
         }
+        self.touch();
     }
 
     /// Given the specified resolution and desired aspect ratio, return an aspect corrected resolution
@@ -480,6 +540,7 @@ impl VideoRenderer {
 
     pub fn set_mode_byte(&mut self, byte: u8) {
         self.mode_byte = byte;
+        self.touch();
     }
 
     pub fn screenshot_with_backend(&mut self, _path: &Path) {
@@ -533,14 +594,16 @@ impl VideoRenderer {
     /// Request a screenshot be taken on next render pass. The screenshot will be saved to the specified path.
     /// This is deferred to the next rendering pass for simplicity so we don't have to retrieve backend
     /// or card buffers when requesting a screenshot.
-    pub fn request_screenshot(&mut self, path: &Path) {
+    pub fn request_screenshot(&mut self, path: &Path, opts: ScreenshotOptions) {
         self.screenshot_buf = vec![
             0;
             (self.params.backend.w as usize * self.params.backend.h as usize * std::mem::size_of::<u32>())
                 as usize
         ];
         self.screenshot_path = Some(path.to_path_buf());
+        self.screenshot_opts = opts;
         self.screenshot_requested = true;
+        self.touch();
     }
 
     pub fn render_screenshot(&self, frame: &[u8], path: &Path) {
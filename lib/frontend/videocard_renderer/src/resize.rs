@@ -35,6 +35,7 @@ use std::num::NonZeroU32;
 use fast_image_resize as fr;
 use fr::{FilterType, Image, PixelType, ResizeAlg, Resizer};
 
+#[derive(Clone)]
 pub struct ResampleParam {
     w: u8,
     iw: u8,
@@ -104,6 +105,20 @@ impl ResampleContext {
     }
 }
 
+impl Clone for ResampleContext {
+    // The resizer only holds scratch buffers, so the copy can start with a fresh one.
+    fn clone(&self) -> Self {
+        Self {
+            resizer: Some(Resizer::new(ResizeAlg::SuperSampling(FilterType::Bilinear, 4))),
+            src_w:   self.src_w,
+            src_h:   self.src_h,
+            dst_w:   self.dst_w,
+            dst_h:   self.dst_h,
+            params:  self.params.clone(),
+        }
+    }
+}
+
 /// Performs a linear resize of the specified src into dst.
 ///
 /// Since we are only doing this for aspect correction, we don't need a bi-linear filter
@@ -0,0 +1,106 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    videocard_renderer::screenshot.rs
+
+    Write screenshots as PNG files with embedded metadata, optionally along
+    with the video card's raw framebuffer.
+*/
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use marty_core::device_traits::videocard::DisplayExtents;
+
+/// Options for a screenshot request.
+#[derive(Clone, Debug, Default)]
+pub struct ScreenshotOptions {
+    /// Also save the video card's raw framebuffer (palette indices, before any palette lookup or
+    /// composite conversion) as a grayscale PNG next to the rendered image.
+    pub raw: bool,
+    /// Key/value pairs to embed as text chunks in the saved PNG files.
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Return the path the raw framebuffer is saved to for a screenshot saved at `path`.
+pub fn raw_screenshot_path(path: &Path) -> std::path::PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_raw.png", stem))
+}
+
+/// Write an 8-bit per channel image to a PNG file, embedding each metadata pair as a tEXt chunk.
+pub fn write_png(
+    path: &Path,
+    data: &[u8],
+    w: u32,
+    h: u32,
+    color: png::ColorType,
+    metadata: &[(String, String)],
+) -> Result<(), png::EncodingError> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w, h);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (key, value) in metadata {
+        encoder.add_text_chunk(key.clone(), value.clone())?;
+    }
+
+    let samples = match color {
+        png::ColorType::Rgba => 4,
+        png::ColorType::Rgb => 3,
+        png::ColorType::GrayscaleAlpha => 2,
+        _ => 1,
+    };
+    let len = (w * h) as usize * samples;
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data[..len.min(data.len())])?;
+    writer.finish()
+}
+
+/// Write the raw framebuffer of a video card, one byte per pixel, covering the full video field
+/// described by `extents`.
+pub fn write_raw_frame(
+    path: &Path,
+    input_buf: &[u8],
+    extents: &DisplayExtents,
+    metadata: &[(String, String)],
+) -> Result<(), png::EncodingError> {
+    let w = extents.field_w as usize;
+    let stride = extents.row_stride.max(w);
+    let h = (extents.field_h as usize).min(input_buf.len() / stride.max(1));
+    if w == 0 || h == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty video field").into());
+    }
+
+    let mut frame = vec![0u8; w * h];
+    for (y, row) in frame.chunks_exact_mut(w).enumerate() {
+        row.copy_from_slice(&input_buf[y * stride..y * stride + w]);
+    }
+
+    let mut metadata = metadata.to_vec();
+    metadata.push(("Raw Format".to_string(), "8-bit palette index per pixel".to_string()));
+    metadata.push(("Row Stride".to_string(), extents.row_stride.to_string()));
+    write_png(path, &frame, w as u32, h as u32, png::ColorType::Grayscale, &metadata)
+}
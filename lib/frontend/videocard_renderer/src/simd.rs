@@ -0,0 +1,292 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    videocard_renderer::simd.rs
+
+    SIMD kernels for the hottest rendering inner loops: NTSC composite
+    decoding and RGBI palette conversion.
+
+    Each kernel has an AVX2 implementation, selected at runtime when the host
+    CPU supports it, and a portable scalar implementation that handles other
+    hosts (including WebAssembly) and any pixels left over after the last
+    full vector. Both produce identical output.
+*/
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Number of 32-bit lanes processed per vector.
+const LANES: usize = 8;
+
+// Signs applied to the decoded a and b chroma components for each of the four color
+// carrier phases. See NtscCoefficients.
+const PHASE_SIGN_A: [i32; 4] = [1, -1, -1, 1];
+const PHASE_SIGN_B: [i32; 4] = [1, 1, -1, -1];
+
+/// Coefficients for decoding one line of NTSC composite video to RGB.
+///
+/// The chroma demodulator produces a and b components whose meaning rotates with the
+/// color carrier phase of each pixel: (I, Q) is (a, b), (-b, a), (-a, -b), (b, -a) for
+/// phases 0 through 3. Taking a on even pixels and b on odd pixels (and vice versa),
+/// the rotation reduces to a sign per phase, which is folded into these per-lane
+/// coefficients so every lane can be decoded with the same operations.
+#[derive(Copy, Clone, Default)]
+pub struct NtscCoefficients {
+    pub sharpness: i32,
+    pub ra: [i32; LANES],
+    pub rb: [i32; LANES],
+    pub ga: [i32; LANES],
+    pub gb: [i32; LANES],
+    pub ba: [i32; LANES],
+    pub bb: [i32; LANES],
+}
+
+impl NtscCoefficients {
+    pub fn new(sharpness: i32, ri: i32, rq: i32, gi: i32, gq: i32, bi: i32, bq: i32) -> Self {
+        let a_lanes = |c: i32| std::array::from_fn(|lane| c * PHASE_SIGN_A[lane & 3]);
+        let b_lanes = |c: i32| std::array::from_fn(|lane| c * PHASE_SIGN_B[lane & 3]);
+        Self {
+            sharpness,
+            ra: a_lanes(ri),
+            rb: b_lanes(rq),
+            ga: a_lanes(gi),
+            gb: b_lanes(gq),
+            ba: a_lanes(bi),
+            bb: b_lanes(bq),
+        }
+    }
+}
+
+#[inline(always)]
+fn pack_rgba(rr: i32, gg: i32, bb: i32) -> u32 {
+    let clamp = |v: i32| (v >> 13).clamp(0, 255) as u32;
+    0xFF00_0000 | clamp(bb) << 16 | clamp(gg) << 8 | clamp(rr)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+fn have_avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+/// Decode a line of NTSC composite video.
+///
+/// `luma` holds the composite signal for each output pixel with one sample of context on
+/// either side, so `luma[x + 1]` is the sample for output pixel `x`. `a` and `b` hold the
+/// demodulated chroma for each output pixel. The first output pixel must be at carrier
+/// phase 0.
+pub fn decode_ntsc_line(luma: &[i32], a: &[i32], b: &[i32], k: &NtscCoefficients, out: &mut [u32]) {
+    let n = out.len();
+    assert!(luma.len() >= n + 2 && a.len() >= n && b.len() >= n);
+
+    #[allow(unused_mut)]
+    let mut done = 0;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if have_avx2() {
+        // SAFETY: AVX2 support was checked above, and slice lengths were checked on entry.
+        done = unsafe { decode_ntsc_avx2(luma, a, b, k, out) };
+    }
+    decode_ntsc_scalar(luma, a, b, k, out, done);
+}
+
+/// Decode a line of NTSC composite video without SIMD. Equivalent to decode_ntsc_line(), for
+/// comparison with it.
+pub fn decode_ntsc_line_scalar(luma: &[i32], a: &[i32], b: &[i32], k: &NtscCoefficients, out: &mut [u32]) {
+    assert!(luma.len() >= out.len() + 2 && a.len() >= out.len() && b.len() >= out.len());
+    decode_ntsc_scalar(luma, a, b, k, out, 0);
+}
+
+fn decode_ntsc_scalar(luma: &[i32], a: &[i32], b: &[i32], k: &NtscCoefficients, out: &mut [u32], start: usize) {
+    for x in start..out.len() {
+        let c = luma[x + 1] + luma[x + 1];
+        let d = luma[x] + luma[x + 2];
+        let y = ((c + d) << 8) + k.sharpness * (c - d);
+
+        let (sa, sb) = if x & 1 == 0 { (a[x], b[x]) } else { (b[x], a[x]) };
+        let lane = x & (LANES - 1);
+        let rr = y + k.ra[lane] * sa + k.rb[lane] * sb;
+        let gg = y + k.ga[lane] * sa + k.gb[lane] * sb;
+        let bb = y + k.ba[lane] * sa + k.bb[lane] * sb;
+        out[x] = pack_rgba(rr, gg, bb);
+    }
+}
+
+/// Decode as many whole vectors of pixels as possible, returning the number of pixels decoded.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn decode_ntsc_avx2(luma: &[i32], a: &[i32], b: &[i32], k: &NtscCoefficients, out: &mut [u32]) -> usize {
+    let n = out.len() & !(LANES - 1);
+
+    let load = |s: &[i32], i: usize| _mm256_loadu_si256(s.as_ptr().add(i) as *const __m256i);
+    let coef = |c: &[i32; LANES]| _mm256_loadu_si256(c.as_ptr() as *const __m256i);
+
+    let sharpness = _mm256_set1_epi32(k.sharpness);
+    let (ra, rb) = (coef(&k.ra), coef(&k.rb));
+    let (ga, gb) = (coef(&k.ga), coef(&k.gb));
+    let (ba, bb) = (coef(&k.ba), coef(&k.bb));
+    let odd_lanes = _mm256_setr_epi32(0, -1, 0, -1, 0, -1, 0, -1);
+    let alpha = _mm256_set1_epi32(0xFF00_0000u32 as i32);
+    let zero = _mm256_setzero_si256();
+    let max = _mm256_set1_epi32(255);
+    let clamp = |v: __m256i| _mm256_min_epi32(_mm256_max_epi32(_mm256_srai_epi32::<13>(v), zero), max);
+
+    let mut x = 0;
+    while x < n {
+        let l = load(luma, x);
+        let m = load(luma, x + 1);
+        let r = load(luma, x + 2);
+        let c = _mm256_add_epi32(m, m);
+        let d = _mm256_add_epi32(l, r);
+        let y = _mm256_add_epi32(
+            _mm256_slli_epi32::<8>(_mm256_add_epi32(c, d)),
+            _mm256_mullo_epi32(sharpness, _mm256_sub_epi32(c, d)),
+        );
+
+        let va = load(a, x);
+        let vb = load(b, x);
+        let sa = _mm256_blendv_epi8(va, vb, odd_lanes);
+        let sb = _mm256_blendv_epi8(vb, va, odd_lanes);
+
+        let chroma = |ka: __m256i, kb: __m256i| {
+            _mm256_add_epi32(
+                y,
+                _mm256_add_epi32(_mm256_mullo_epi32(ka, sa), _mm256_mullo_epi32(kb, sb)),
+            )
+        };
+        let rr = clamp(chroma(ra, rb));
+        let gg = clamp(chroma(ga, gb));
+        let bb = clamp(chroma(ba, bb));
+
+        let rgba = _mm256_or_si256(
+            _mm256_or_si256(alpha, _mm256_slli_epi32::<16>(bb)),
+            _mm256_or_si256(_mm256_slli_epi32::<8>(gg), rr),
+        );
+        _mm256_storeu_si256(out.as_mut_ptr().add(x) as *mut __m256i, rgba);
+        x += LANES;
+    }
+    n
+}
+
+/// Convert a line of 4-bit RGBI color indices to RGBA using the first 16 entries of the
+/// specified palette. The upper four bits of each index are ignored.
+pub fn rgbi_to_rgba_line(src: &[u8], palette: &[u32], out: &mut [u32]) {
+    assert!(palette.len() >= 16);
+    let n = out.len().min(src.len());
+
+    #[allow(unused_mut)]
+    let mut done = 0;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if have_avx2() {
+        // SAFETY: AVX2 support was checked above, the palette length was checked on entry, and
+        // we only process min(src, out) pixels.
+        done = unsafe { rgbi_to_rgba_avx2(&src[..n], palette, &mut out[..n]) };
+    }
+    rgbi_to_rgba_scalar(&src[done..n], palette, &mut out[done..n]);
+}
+
+/// Convert a line of RGBI color indices to RGBA without SIMD. Equivalent to rgbi_to_rgba_line(),
+/// for comparison with it.
+pub fn rgbi_to_rgba_line_scalar(src: &[u8], palette: &[u32], out: &mut [u32]) {
+    assert!(palette.len() >= 16);
+    rgbi_to_rgba_scalar(src, palette, out);
+}
+
+fn rgbi_to_rgba_scalar(src: &[u8], palette: &[u32], out: &mut [u32]) {
+    for (dst, &idx) in out.iter_mut().zip(src) {
+        *dst = palette[(idx & 0x0F) as usize];
+    }
+}
+
+/// Convert as many whole vectors of pixels as possible, returning the number of pixels converted.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rgbi_to_rgba_avx2(src: &[u8], palette: &[u32], out: &mut [u32]) -> usize {
+    let n = out.len() & !(LANES - 1);
+
+    // Each half of the palette fits in one vector. Permutes select within a half by the low
+    // three bits of the index, and bit 3 selects between the halves.
+    let pal_lo = _mm256_loadu_si256(palette.as_ptr() as *const __m256i);
+    let pal_hi = _mm256_loadu_si256(palette.as_ptr().add(LANES) as *const __m256i);
+    let index_mask = _mm256_set1_epi32(0x0F);
+    let seven = _mm256_set1_epi32(7);
+
+    let mut x = 0;
+    while x < n {
+        let bytes = _mm_loadl_epi64(src.as_ptr().add(x) as *const __m128i);
+        let idx = _mm256_and_si256(_mm256_cvtepu8_epi32(bytes), index_mask);
+        let lo = _mm256_permutevar8x32_epi32(pal_lo, idx);
+        let hi = _mm256_permutevar8x32_epi32(pal_hi, idx);
+        let rgba = _mm256_blendv_epi8(lo, hi, _mm256_cmpgt_epi32(idx, seven));
+        _mm256_storeu_si256(out.as_mut_ptr().add(x) as *mut __m256i, rgba);
+        x += LANES;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    // Line widths around the vector width, plus common CGA and EGA widths.
+    const WIDTHS: [usize; 14] = [0, 1, 5, 7, 8, 9, 15, 16, 17, 31, 320, 639, 640, 913];
+
+    #[test]
+    fn test_rgbi_matches_scalar() {
+        let mut rng = rand::thread_rng();
+        let palette: Vec<u32> = (0..16).map(|_| rng.gen()).collect();
+        for width in WIDTHS {
+            // Random bytes, so EGA indices with the upper bits set are covered too.
+            let src: Vec<u8> = (0..width).map(|_| rng.gen()).collect();
+            let mut simd_out = vec![0u32; width];
+            let mut scalar_out = vec![0u32; width];
+            rgbi_to_rgba_line(&src, &palette, &mut simd_out);
+            rgbi_to_rgba_line_scalar(&src, &palette, &mut scalar_out);
+            assert_eq!(simd_out, scalar_out, "width {}", width);
+        }
+    }
+
+    #[test]
+    fn test_ntsc_matches_scalar() {
+        let mut rng = rand::thread_rng();
+        for width in WIDTHS {
+            let sharpness = rng.gen_range(-256..256);
+            let mut coef = || rng.gen_range(-4096..4096);
+            let k = NtscCoefficients::new(sharpness, coef(), coef(), coef(), coef(), coef(), coef());
+            // Ranges wide enough for the results to saturate in both directions.
+            let luma: Vec<i32> = (0..width + 2).map(|_| rng.gen_range(-4096..4096)).collect();
+            let a: Vec<i32> = (0..width).map(|_| rng.gen_range(-1024..1024)).collect();
+            let b: Vec<i32> = (0..width).map(|_| rng.gen_range(-1024..1024)).collect();
+            let mut simd_out = vec![0u32; width];
+            let mut scalar_out = vec![0u32; width];
+            decode_ntsc_line(&luma, &a, &b, &k, &mut simd_out);
+            decode_ntsc_line_scalar(&luma, &a, &b, &k, &mut scalar_out);
+            assert_eq!(simd_out, scalar_out, "width {}", width);
+        }
+    }
+}